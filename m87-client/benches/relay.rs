@@ -0,0 +1,39 @@
+//! Relay load benchmark against a test server, using the CLI's login and config:
+//!
+//! ```sh
+//! M87_BENCH_AGENTS=50 M87_BENCH_CLIENTS=200 cargo bench -p m87-client --bench relay
+//! ```
+//!
+//! Also reads `M87_BENCH_SERVER`, `M87_BENCH_OWNER`, `M87_BENCH_STREAMS` and
+//! `M87_BENCH_PAYLOAD_KB`. See `m87 admin bench` for the same run from the CLI.
+
+#[cfg(feature = "runtime")]
+fn env_or(key: &str, default: usize) -> usize {
+    std::env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(feature = "runtime")]
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    use m87_client::bench::{BenchOptions, print_report, run};
+
+    m87_client::util::tls::set_tls_provider();
+    let opts = BenchOptions {
+        server_url: std::env::var("M87_BENCH_SERVER").ok(),
+        owner: std::env::var("M87_BENCH_OWNER").ok(),
+        agents: env_or("M87_BENCH_AGENTS", 10),
+        clients: env_or("M87_BENCH_CLIENTS", 10),
+        streams: env_or("M87_BENCH_STREAMS", 4),
+        payload_bytes: env_or("M87_BENCH_PAYLOAD_KB", 1024) * 1024,
+    };
+    print_report(&run(&opts).await?);
+    Ok(())
+}
+
+#[cfg(not(feature = "runtime"))]
+fn main() {
+    eprintln!("The relay benchmark needs the runtime feature (Linux)");
+}
@@ -8,13 +8,13 @@ fn main() {
         let target_os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
 
         match target_os.as_str() {
-            "linux" => {
-                // Linux gets both m87 runtime and command line capabilities
+            "linux" | "macos" => {
+                // Linux and macOS get both m87 runtime and command line capabilities
                 println!("cargo:rustc-cfg=feature=\"runtime\"");
                 println!("cargo:rustc-cfg=feature=\"cli\"");
             }
-            "macos" | "windows" => {
-                // macOS and Windows get command line only capabilities
+            "windows" => {
+                // Windows gets command line only capabilities
                 println!("cargo:rustc-cfg=feature=\"cli\"");
             }
             _ => {
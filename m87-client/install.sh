@@ -9,12 +9,18 @@ set -e
 #   curl -fsSL https://github.com/make87/m87/releases/latest/download/install-client.sh | sh
 #   curl -fsSL get.make87.com | sh
 #
+# Commands printed by `m87 install-command` also register the runtime:
+#   curl -fsSL get.make87.com | M87_OWNER=<org> M87_ENROLLMENT_TOKEN=<token> sh
+#
 # What it does:
 #   - Detects OS (Linux, macOS) and architecture (x86_64/aarch64)
 #   - Downloads specific version of m87 binary from GitHub releases
 #   - Verifies SHA256 checksum
 #   - Installs to ~/.local/bin/m87
 #   - Prints PATH instructions if needed
+#   - With M87_ENROLLMENT_TOKEN: enables the runtime service, registered under
+#     M87_OWNER (org id or email) with M87_RUNTIME_SERVER_URL if set, without
+#     waiting for approval
 #
 # Supported platforms:
 #   - Linux x86_64 (AMD64)
@@ -228,6 +234,33 @@ check_path() {
     echo "  echo 'set -gx PATH $HOME/.local/bin \$PATH' >> ~/.config/fish/config.fish && source ~/.config/fish/config.fish"
 }
 
+# Enable the runtime service with the enrollment token of an install command
+enroll_runtime() {
+    if [ "$OS" != "linux" ]; then
+        error "The m87 runtime only runs on Linux; the device was not registered"
+        exit 1
+    fi
+    if [ -z "$M87_OWNER" ]; then
+        error "M87_OWNER must be set together with M87_ENROLLMENT_TOKEN"
+        exit 1
+    fi
+
+    case "$M87_OWNER" in
+        *@*) owner_flag="--email" ;;
+        *) owner_flag="--org-id" ;;
+    esac
+
+    info "Registering this device under $M87_OWNER..."
+    if [ -n "$M87_RUNTIME_SERVER_URL" ]; then
+        "$INSTALL_DIR/$BINARY_NAME" runtime enable --now "$owner_flag" "$M87_OWNER" \
+            --enrollment-token "$M87_ENROLLMENT_TOKEN" --server-url "$M87_RUNTIME_SERVER_URL"
+    else
+        "$INSTALL_DIR/$BINARY_NAME" runtime enable --now "$owner_flag" "$M87_OWNER" \
+            --enrollment-token "$M87_ENROLLMENT_TOKEN"
+    fi
+    success "Runtime enabled; the device joins without approval"
+}
+
 # Main installation flow
 main() {
     echo ""
@@ -303,6 +336,18 @@ main() {
         warning "$BINARY_NAME not found in PATH. You may need to restart your shell."
     fi
 
+    # Step 9: Register the runtime (install commands only)
+    if [ -n "$M87_ENROLLMENT_TOKEN" ]; then
+        enroll_runtime
+        echo ""
+        success "make87 has been installed and this device registered!"
+        echo ""
+        echo "Check on it with:"
+        echo "  $BINARY_NAME runtime status     # State of the runtime service"
+        echo ""
+        return 0
+    fi
+
     # Success message
     echo ""
     success "make87 has been installed successfully!"
@@ -310,12 +355,10 @@ main() {
     echo "Get started with:"
     echo "  $BINARY_NAME --help               # Show all commands"
     echo "  $BINARY_NAME login                # Authenticate with make87"
-    if [ "$OS" = "linux" ]; then
-        echo ""
-        echo "Runtime mode (Linux only):"
-        echo "  $BINARY_NAME runtime login          # Authenticate runtime"
-        echo "  $BINARY_NAME runtime enable --now   # Install as system service"
-    fi
+    echo ""
+    echo "Runtime mode:"
+    echo "  $BINARY_NAME runtime login          # Authenticate runtime"
+    echo "  $BINARY_NAME runtime enable --now   # Install as system service"
     echo ""
 }
 
@@ -0,0 +1,86 @@
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use m87_shared::{
+    access::{AccessGrant, CreateAccessGrantBody},
+    roles::Role,
+};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    devices::resolve_device_cached,
+    server,
+    util::servers_parallel::{fanout_servers, find_on_servers, is_not_found},
+};
+
+/// Grants on all servers, newest first.
+pub async fn list(all: bool) -> Result<Vec<AccessGrant>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move { server::list_access_grants(&server_url, &token, trust, all).await }
+    })
+    .await?;
+
+    let mut grants: Vec<AccessGrant> = results.into_iter().map(|(_, g)| g).collect();
+    // RFC 3339 timestamps of one format sort by time
+    grants.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(grants)
+}
+
+/// Give `email` `role` on `device` for `ttl`.
+pub async fn grant(
+    email: &str,
+    device: &str,
+    role: Role,
+    ttl: Duration,
+    reason: Option<String>,
+) -> Result<AccessGrant> {
+    let resolved = resolve_device_cached(device).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::create_access_grant(
+        &resolved.url,
+        &token,
+        trust,
+        &CreateAccessGrantBody {
+            email: email.to_string(),
+            device_id: resolved.id,
+            role,
+            ttl_secs: ttl.as_secs(),
+            reason,
+        },
+    )
+    .await
+}
+
+/// End grant `id` on whichever server holds it.
+pub async fn revoke(id: &str) -> Result<AccessGrant> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let found = find_on_servers(config.manager_server_urls, 4, |server_url| {
+        let token = token.clone();
+        async move {
+            match server::revoke_access_grant(&server_url, &token, trust, id).await {
+                Ok(grant) => Ok(Some(grant)),
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await?;
+
+    match found {
+        Some((_, grant)) => Ok(grant),
+        None => bail!("no access grant {}", id),
+    }
+}
@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use m87_shared::db_stats::CollectionStats;
+
+use crate::{auth::AuthManager, config::Config, server, util::servers_parallel::Fanout};
+
+/// Collection stats of every configured server, or of `server_url`. Servers
+/// the token is no admin of are skipped with a warning, unless all are.
+pub async fn db_stats(server_url: Option<String>) -> Result<Vec<(String, Vec<CollectionStats>)>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+    let servers = match server_url {
+        Some(url) => vec![url],
+        None => config.manager_server_urls,
+    };
+
+    // stats of a large database take a while to collect
+    let mut outcomes = Fanout::new(4)
+        .timeout(Duration::from_secs(60))
+        .run(servers, |server_url| {
+            let token = token.clone();
+            async move { server::get_db_stats(&server_url, &token, trust).await }
+        })
+        .await;
+    if outcomes.ok.is_empty()
+        && let Some((_, e)) = outcomes.failed.pop()
+    {
+        return Err(e);
+    }
+    outcomes.warn_partial("servers");
+    Ok(outcomes.ok)
+}
@@ -0,0 +1,55 @@
+use anyhow::{Result, bail};
+use m87_shared::approval::{ApprovalRequest, ApprovalStatus, DecideApprovalBody};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    server,
+    util::servers_parallel::{fanout_servers, find_on_servers, is_not_found},
+};
+
+/// Approval requests on all servers, newest first.
+pub async fn list(status: Option<ApprovalStatus>, limit: u32) -> Result<Vec<ApprovalRequest>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move { server::list_approvals(&server_url, &token, trust, status, limit).await }
+    })
+    .await?;
+
+    let mut requests: Vec<ApprovalRequest> = results.into_iter().map(|(_, r)| r).collect();
+    // RFC 3339 timestamps of one format sort by time
+    requests.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    requests.truncate(limit as usize);
+    Ok(requests)
+}
+
+/// Approve or reject request `id` on whichever server holds it.
+pub async fn decide(id: &str, approve: bool, comment: Option<String>) -> Result<ApprovalRequest> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+    let body = DecideApprovalBody { approve, comment };
+
+    let found = find_on_servers(config.manager_server_urls, 4, |server_url| {
+        let token = token.clone();
+        let body = body.clone();
+        async move {
+            match server::decide_approval(&server_url, &token, trust, id, &body).await {
+                Ok(request) => Ok(Some(request)),
+                // ids are unique across servers, the others do not know it
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await?;
+
+    match found {
+        Some((_, request)) => Ok(request),
+        None => bail!("no approval request {}", id),
+    }
+}
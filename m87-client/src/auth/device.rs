@@ -12,6 +12,7 @@ pub struct DeviceAuthRequestHandler {
     pub device_info: DeviceSystemInfo,
     pub device_id: String,
     pub owner_scope: String,
+    pub enrollment_token: Option<String>,
     pub request_id: Option<String>,
     pub trust_invalid_server_cert: bool,
 }
@@ -22,6 +23,7 @@ impl DeviceAuthRequestHandler {
             device_info: self.device_info.clone(),
             owner_scope: self.owner_scope.clone(),
             device_id: self.device_id.clone(),
+            enrollment_token: self.enrollment_token.clone(),
         };
         let request_id =
             server::set_auth_request(&self.api_url, body, self.trust_invalid_server_cert).await?;
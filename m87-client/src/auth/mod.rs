@@ -1,4 +1,4 @@
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 #[cfg(feature = "runtime")]
 use m87_shared::device::DeviceSystemInfo;
 use std::fs::{self, File, OpenOptions};
@@ -8,6 +8,7 @@ use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
 #[cfg(feature = "runtime")]
 use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tracing::info;
 
 #[cfg(feature = "runtime")]
@@ -16,8 +17,9 @@ mod oauth;
 
 use serde::{Deserialize, Serialize};
 
-use crate::config::Config;
+use crate::config::{Config, config_dir_override, for_instance};
 use crate::server;
+use crate::util::lan;
 use crate::util::servers_parallel::{fanout_servers, find_on_servers};
 
 pub const OWNER_REFERENCE_ENV_VAR: &str = "OWNER_REFERENCE";
@@ -27,6 +29,9 @@ pub const API_KEY_ENV_VAR: &str = "M87_API_KEY";
 pub struct APIConfig {
     pub credentials: Option<Credentials>,
     pub device_credentials: Option<APIKey>,
+    /// Device id the device credentials were issued for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -34,16 +39,40 @@ pub struct APIKey {
     api_key: String,
 }
 
+/// Token of a password login against a server in local auth mode. It cannot
+/// be refreshed; the user logs in again once it expired.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LocalToken {
+    access_token: String,
+    expires_at: u64,
+    server_url: String,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub enum Credentials {
     APIKey(APIKey),
     OAuth2Token(oauth::OAuth2Token),
+    Local(LocalToken),
 }
 
 impl Credentials {
     pub async fn get_token(&mut self) -> Result<String> {
         match self {
             Credentials::APIKey(credentials) => Ok(credentials.api_key.clone()),
+            Credentials::Local(token) => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                if now >= token.expires_at {
+                    bail!(
+                        "Login to {} expired, run `m87 login --server {} --email <email>` again",
+                        token.server_url,
+                        token.server_url
+                    );
+                }
+                Ok(token.access_token.clone())
+            }
             Credentials::OAuth2Token(credentials) => {
                 let config = Config::load()?;
                 credentials
@@ -109,9 +138,13 @@ impl APIConfig {
     }
 
     pub fn default_credentials_path() -> Result<PathBuf> {
-        let config_dir = dirs::config_dir().context("Failed to get config directory")?;
-        let path = config_dir.join("m87").join("credentials.json");
-        Ok(path)
+        let dir = match config_dir_override() {
+            Some(dir) => dir,
+            None => dirs::config_dir()
+                .context("Failed to get config directory")?
+                .join("m87"),
+        };
+        Ok(for_instance(dir).join("credentials.json"))
     }
 
     pub fn save_cli_credentials(credentials: Credentials) -> Result<()> {
@@ -121,9 +154,10 @@ impl APIConfig {
         Ok(())
     }
 
-    pub fn save_device_credentials(key: String) -> Result<()> {
+    pub fn save_device_credentials(key: String, device_id: &str) -> Result<()> {
         let mut config = Self::load_or_create()?;
         config.device_credentials = Some(APIKey { api_key: key });
+        config.device_id = Some(device_id.to_string());
         config.save()?;
         Ok(())
     }
@@ -138,6 +172,7 @@ impl APIConfig {
     pub fn delete_device_credentials() -> Result<()> {
         let mut config = Self::load_or_create()?;
         config.device_credentials = None;
+        config.device_id = None;
         config.save()?;
         Ok(())
     }
@@ -193,7 +228,7 @@ impl AuthManager {
         match std::env::var(API_KEY_ENV_VAR) {
             Ok(api_key) => {
                 if api_key.len() != 0 {
-                    APIConfig::save_device_credentials(api_key)?;
+                    APIConfig::save_device_credentials(api_key, &auth_handler.device_id)?;
                 }
             }
             _ => {}
@@ -204,7 +239,7 @@ impl AuthManager {
         }
 
         let api_key = auth_handler.handle_headless_auth(timeout).await?;
-        APIConfig::save_device_credentials(api_key)?;
+        APIConfig::save_device_credentials(api_key, &auth_handler.device_id)?;
         info!("Logged device in successfully");
         Ok(())
     }
@@ -220,6 +255,14 @@ impl AuthManager {
     }
 
     pub async fn get_cli_token() -> Result<String> {
+        // Direct LAN sessions authenticate against the device itself
+        if let Some(target) = lan::direct_target() {
+            return Ok(target.device_key.clone());
+        }
+        // Replayed calls never reach a server
+        if server::vcr::replaying() {
+            return Ok(server::vcr::REPLAY_TOKEN.to_string());
+        }
         APIConfig::load_or_create()?
             .credentials
             .ok_or_else(|| anyhow!("cli credentials not found"))?
@@ -241,6 +284,11 @@ impl AuthManager {
     pub fn has_device_credentials() -> Result<bool> {
         Ok(APIConfig::load_or_create()?.device_credentials.is_some())
     }
+
+    /// Device id the stored device credentials belong to, if known.
+    pub fn registered_device_id() -> Result<Option<String>> {
+        Ok(APIConfig::load_or_create()?.device_id)
+    }
 }
 
 // m87 command line: OAuth2 login for device management
@@ -257,6 +305,45 @@ pub async fn login_cli() -> Result<()> {
     Ok(())
 }
 
+// m87 command line: Password login against a self-hosted server in local auth
+// mode; the server becomes the only one devices are managed on
+pub async fn login_password(server_url: &str, email: &str) -> Result<()> {
+    let server_url = server_url.trim_end_matches('/').to_string();
+    let mut config = Config::load()?;
+    let password = read_password()?;
+    let res = server::local_login(
+        &server_url,
+        &server::LocalLoginBody {
+            email: email.to_string(),
+            password,
+        },
+        config.trust_invalid_server_cert,
+    )
+    .await?;
+    APIConfig::save_cli_credentials(Credentials::Local(LocalToken {
+        access_token: res.access_token,
+        expires_at: res.expires_at,
+        server_url: server_url.clone(),
+    }))?;
+    config.manager_server_urls = vec![server_url];
+    config.save()?;
+    Ok(())
+}
+
+/// Password from `M87_PASSWORD`, or prompted for without echo.
+fn read_password() -> Result<String> {
+    if let Ok(password) = std::env::var("M87_PASSWORD") {
+        return Ok(password);
+    }
+    use std::io::Write;
+    let mut stderr = std::io::stderr();
+    write!(stderr, "Password: ")?;
+    stderr.flush()?;
+    let password = crate::tui::term::read_hidden_line()?;
+    writeln!(stderr)?;
+    password.ok_or_else(|| anyhow!("No password entered"))
+}
+
 async fn update_server_urls() -> Result<()> {
     let mut config = Config::load()?;
     if config.manager_server_urls.is_empty() {
@@ -274,13 +361,39 @@ pub async fn register_device(
     owner_scope: Option<String>,
     device_system_info: DeviceSystemInfo,
 ) -> Result<()> {
-    if AuthManager::has_device_credentials()? {
+    let mut config = Config::load()?;
+    let registered = AuthManager::has_device_credentials()?;
+
+    // Credentials are scoped to the id the device registered with; otherwise
+    // the machine-id file is authoritative. Installs that predate the file
+    // seed it with the id from config.json.
+    let registered_id = if registered {
+        AuthManager::registered_device_id()?
+    } else {
+        None
+    };
+    let machine_id = Config::read_machine_id();
+    let device_id = registered_id
+        .or_else(|| machine_id.clone())
+        .unwrap_or_else(|| config.device_id.clone());
+    if machine_id.as_ref() != Some(&device_id) {
+        Config::write_machine_id(&device_id)?;
+    }
+    if device_id != config.device_id {
+        tracing::warn!(
+            "Device id {} in config differs from registered id {}, keeping the registered id",
+            config.device_id,
+            device_id
+        );
+        config.device_id = device_id;
+        config.save()?;
+    }
+
+    if registered {
         info!("Already registered");
         return Ok(());
     }
 
-    let mut config = Config::load()?;
-
     // resolve CLI owner, config owner, or env owner
     let mut owner_scope = owner_scope
         .or_else(|| {
@@ -324,17 +437,14 @@ pub async fn register_device(
     let owner_scope = owner_scope
         .ok_or_else(|| anyhow::anyhow!("No owner reference provided after registration"))?;
 
-    //if @ is in owner_scope prefix with user: otherwise with org:
-    let owner_scope = if owner_scope.contains('@') {
-        format!("user:{}", owner_scope)
-    } else {
-        format!("org:{}", owner_scope)
-    };
+    let owner_scope = owner_scope_of(&owner_scope);
+    let enrollment_token = config.enrollment_token.clone();
     let mut report_handler = device::DeviceAuthRequestHandler {
         api_url,
         device_info: device_system_info,
         device_id: config.device_id,
         owner_scope,
+        enrollment_token: enrollment_token.clone(),
         request_id: None,
         trust_invalid_server_cert: config.trust_invalid_server_cert,
     };
@@ -350,9 +460,25 @@ pub async fn register_device(
         }
         return Err(err);
     }
+    if enrollment_token.is_some() {
+        // spent or used up; later registrations wait for approval again
+        let mut config = Config::load()?;
+        config.enrollment_token = None;
+        config.save()?;
+    }
     Ok(())
 }
 
+/// Scope a device registers into: `user:<email>` for an email, `org:<id>` otherwise.
+#[cfg(feature = "runtime")]
+pub fn owner_scope_of(owner_reference: &str) -> String {
+    if owner_reference.contains('@') {
+        format!("user:{}", owner_reference)
+    } else {
+        format!("org:{}", owner_reference)
+    }
+}
+
 pub async fn status() -> Result<()> {
     let _ = AuthManager::get_cli_token().await?;
     info!("Logged in");
@@ -361,6 +487,9 @@ pub async fn status() -> Result<()> {
 
 // m87 command line: Logout
 pub async fn logout_cli() -> Result<()> {
+    // warm connections still carry the old login
+    #[cfg(unix)]
+    crate::streams::warm::stop_all()?;
     AuthManager::delete_cli_credentials().await
 }
 
@@ -10,6 +10,7 @@ use std::fmt::Debug;
 use std::io::Write;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::util::proxy;
 use crate::util::shutdown::SHUTDOWN;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -45,7 +46,7 @@ impl OAuth2Token {
     pub async fn refresh(&mut self, issuer_url: &str, client_id: &str) -> Result<()> {
         let client = OAuth2Token::get_client(issuer_url, client_id).await?;
 
-        let http_client = reqwest::ClientBuilder::new()
+        let http_client = proxy::apply(reqwest::ClientBuilder::new())?
             // Following redirects opens the client up to SSRF vulnerabilities.
             .redirect(reqwest::redirect::Policy::none())
             .build()?;
@@ -96,7 +97,7 @@ impl OAuth2Token {
         let client_id = ClientId::new(client_id.to_string());
         let issuer_url = IssuerUrl::new(issuer_url.to_string())?;
 
-        let http_client = reqwest::ClientBuilder::new()
+        let http_client = proxy::apply(reqwest::ClientBuilder::new())?
             // Following redirects opens the client up to SSRF vulnerabilities.
             .redirect(reqwest::redirect::Policy::none())
             .build()?;
@@ -125,7 +126,7 @@ impl OAuth2Token {
     ) -> Result<OAuth2Token> {
         let client = OAuth2Token::get_client(issuer_url, client_id).await?;
 
-        let http_client = reqwest::ClientBuilder::new()
+        let http_client = proxy::apply(reqwest::ClientBuilder::new())?
             .redirect(reqwest::redirect::Policy::none())
             .build()?;
 
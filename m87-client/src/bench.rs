@@ -0,0 +1,354 @@
+//! `m87 admin bench`: load test the relay of a server. Registers simulated
+//! agents, connects clients to them and measures tunnel setup, stream
+//! throughput and server memory. The agents echo every stream they accept and
+//! are deleted again afterwards, so point it at a test server.
+
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use futures::{StreamExt, stream};
+use make87_sdk::streams::open_stream;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+use crate::server;
+use crate::sim::{self, SETUP_CONCURRENCY, SimAgent, Target};
+use crate::streams::quic::connect_quic_only;
+use crate::streams::stream_type::StreamClass;
+
+/// Header type of bench streams. Only simulated agents accept it; the relay
+/// treats it like any unknown type and requires the admin role.
+const BENCH_STREAM: &str = "Bench";
+
+pub struct BenchOptions {
+    /// Server to test; defaults to the configured one
+    pub server_url: Option<String>,
+    /// Email or organization id the simulated agents register under
+    pub owner: Option<String>,
+    pub agents: usize,
+    pub clients: usize,
+    /// Streams each client opens in parallel
+    pub streams: usize,
+    /// Bytes echoed through each stream
+    pub payload_bytes: usize,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Percentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Percentiles {
+    fn of(mut samples: Vec<Duration>) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        samples.sort();
+        let at = |q: f64| {
+            let i = ((samples.len() - 1) as f64 * q).round() as usize;
+            samples[i].as_secs_f64() * 1000.0
+        };
+        Self {
+            p50_ms: at(0.5),
+            p95_ms: at(0.95),
+            max_ms: at(1.0),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub agents: usize,
+    pub tunnels_per_sec: f64,
+    pub tunnel_setup: Percentiles,
+    pub streams: usize,
+    pub failed_streams: usize,
+    /// Time from opening a stream to the first echoed byte
+    pub stream_first_byte: Percentiles,
+    /// Payload echoed through the relay, per direction
+    pub throughput_mbit_s: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_rss_before: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_rss_loaded: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bench_rss: Option<u64>,
+}
+
+struct Connected {
+    agent: SimAgent,
+    _endpoint: quinn::Endpoint,
+    conn: quinn::Connection,
+    setup: Duration,
+}
+
+pub async fn run(opts: &BenchOptions) -> Result<BenchReport> {
+    if opts.agents == 0 {
+        bail!("At least one agent is needed");
+    }
+    let target = Target::resolve(opts.server_url.clone(), opts.owner.clone()).await?;
+    let (api_url, host, token, trust) =
+        (&target.api_url, &target.host, &target.token, target.trust);
+
+    let server_rss_before = relay_rss(api_url, token, trust).await;
+
+    info!("Registering {} simulated agents", opts.agents);
+    let registered: Vec<Result<SimAgent>> = stream::iter(0..opts.agents)
+        .map(|_| sim::register(&target, "m87-bench"))
+        .buffer_unordered(SETUP_CONCURRENCY)
+        .collect()
+        .await;
+    let mut agents = Vec::new();
+    for agent in registered {
+        match agent {
+            Ok(agent) => agents.push(agent),
+            Err(e) => warn!("Failed to register a simulated agent: {:#}", e),
+        }
+    }
+
+    info!("Connecting {} control tunnels", agents.len());
+    let started = Instant::now();
+    let connected: Vec<(String, Result<Connected>)> = stream::iter(agents)
+        .map(|agent| async {
+            let device_id = agent.device_id.clone();
+            (device_id, connect_agent(agent, host, trust).await)
+        })
+        .buffer_unordered(SETUP_CONCURRENCY)
+        .collect()
+        .await;
+    let tunnel_secs = started.elapsed().as_secs_f64();
+
+    let mut tunnels = Vec::new();
+    let mut device_ids = Vec::new();
+    for (device_id, res) in connected {
+        match res {
+            Ok(c) => tunnels.push(c),
+            Err(e) => warn!("Failed to connect simulated agent {}: {:#}", device_id, e),
+        }
+        device_ids.push(device_id);
+    }
+
+    let report = if tunnels.is_empty() {
+        Err(anyhow::anyhow!("No simulated agent could connect"))
+    } else {
+        Ok(run_clients(opts, &tunnels, host, token, trust).await)
+    };
+    let server_rss_loaded = relay_rss(api_url, token, trust).await;
+
+    for tunnel in &tunnels {
+        tunnel.conn.close(0u32.into(), b"bench finished");
+    }
+    for device_id in &device_ids {
+        if let Err(e) = server::delete_device(api_url, token, trust, device_id).await {
+            warn!("Failed to delete simulated agent {}: {:#}", device_id, e);
+        }
+    }
+
+    let (streams, failed_streams, first_byte, echoed, stream_secs) = report?;
+    Ok(BenchReport {
+        agents: tunnels.len(),
+        tunnels_per_sec: tunnels.len() as f64 / tunnel_secs.max(f64::EPSILON),
+        tunnel_setup: Percentiles::of(tunnels.iter().map(|t| t.setup).collect()),
+        streams,
+        failed_streams,
+        stream_first_byte: Percentiles::of(first_byte),
+        throughput_mbit_s: echoed as f64 * 8.0 / 1_000_000.0 / stream_secs.max(f64::EPSILON),
+        server_rss_before,
+        server_rss_loaded,
+        bench_rss: m87_shared::relay::resident_memory_bytes(),
+    })
+}
+
+/// Server memory, if the token may read the relay stats.
+async fn relay_rss(api_url: &str, token: &str, trust: bool) -> Option<u64> {
+    match server::get_relay_stats(api_url, token, trust).await {
+        Ok(stats) => stats.rss_bytes,
+        Err(e) => {
+            debug!("Relay stats unavailable: {:#}", e);
+            None
+        }
+    }
+}
+
+async fn connect_agent(agent: SimAgent, host: &str, trust: bool) -> Result<Connected> {
+    let started = Instant::now();
+    let (endpoint, conn) = sim::connect(&agent, host, trust).await?;
+    let setup = started.elapsed();
+    tokio::spawn(serve_echo(conn.clone()));
+    Ok(Connected {
+        agent,
+        _endpoint: endpoint,
+        conn,
+        setup,
+    })
+}
+
+/// Echo every stream the relay opens until the tunnel closes.
+async fn serve_echo(conn: quinn::Connection) {
+    while let Ok((mut send, mut recv)) = conn.accept_bi().await {
+        tokio::spawn(async move {
+            let mut len = [0u8; 4];
+            if recv.read_exact(&mut len).await.is_err() {
+                return;
+            }
+            let mut header = vec![0u8; u32::from_be_bytes(len) as usize];
+            if recv.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            let _ = tokio::io::copy(&mut recv, &mut send).await;
+            let _ = send.finish();
+        });
+    }
+}
+
+type ClientResults = (usize, usize, Vec<Duration>, u64, f64);
+
+/// Connect the clients round-robin to the agents and echo the payload through
+/// every stream. Returns streams, failures, first-byte latencies, echoed bytes
+/// and the elapsed seconds.
+async fn run_clients(
+    opts: &BenchOptions,
+    tunnels: &[Connected],
+    host: &str,
+    token: &str,
+    trust: bool,
+) -> ClientResults {
+    let payload = vec![0x87u8; opts.payload_bytes];
+    info!(
+        "Running {} clients with {} streams of {} bytes each",
+        opts.clients, opts.streams, opts.payload_bytes
+    );
+
+    let started = Instant::now();
+    let outcomes: Vec<Vec<Result<(Duration, u64)>>> = stream::iter(0..opts.clients)
+        .map(|i| {
+            let short_id = &tunnels[i % tunnels.len()].agent.short_id;
+            run_client(host, token, short_id, trust, opts.streams, &payload)
+        })
+        .buffer_unordered(opts.clients.max(1))
+        .collect()
+        .await;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    let mut first_byte = Vec::new();
+    let mut echoed = 0u64;
+    let mut failed = 0;
+    let mut streams = 0;
+    for outcome in outcomes.into_iter().flatten() {
+        streams += 1;
+        match outcome {
+            Ok((latency, bytes)) => {
+                first_byte.push(latency);
+                echoed += bytes;
+            }
+            Err(e) => {
+                debug!("Bench stream failed: {:#}", e);
+                failed += 1;
+            }
+        }
+    }
+    (streams, failed, first_byte, echoed, elapsed)
+}
+
+async fn run_client(
+    host: &str,
+    token: &str,
+    short_id: &str,
+    trust: bool,
+    streams: usize,
+    payload: &[u8],
+) -> Vec<Result<(Duration, u64)>> {
+    let conn = match connect_quic_only(host, token, short_id, trust, StreamClass::Bulk).await {
+        Ok((_endpoint, conn)) => conn,
+        Err(e) => {
+            return (0..streams)
+                .map(|_| Err(anyhow::anyhow!("{:#}", e)))
+                .collect();
+        }
+    };
+    let results =
+        futures::future::join_all((0..streams).map(|_| echo_stream(&conn, token, payload))).await;
+    conn.close(0u32.into(), b"bench finished");
+    results
+}
+
+/// Send `payload` through an echo stream and read it back.
+async fn echo_stream(
+    conn: &quinn::Connection,
+    token: &str,
+    payload: &[u8],
+) -> Result<(Duration, u64)> {
+    let started = Instant::now();
+    let header = serde_json::json!({ "type": BENCH_STREAM, "token": token });
+    let io = open_stream(conn, &header).await?;
+    let (mut reader, mut writer) = tokio::io::split(io);
+
+    let send = async {
+        writer.write_all(payload).await?;
+        writer.shutdown().await
+    };
+    let receive = async {
+        let mut first_byte = None;
+        let mut total = 0u64;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            first_byte.get_or_insert_with(|| started.elapsed());
+            total += n as u64;
+        }
+        Ok::<_, std::io::Error>((first_byte, total))
+    };
+    let (_, (first_byte, total)) = tokio::try_join!(send, receive)?;
+
+    if total != payload.len() as u64 {
+        bail!("echoed {} of {} bytes", total, payload.len());
+    }
+    Ok((first_byte.unwrap_or_else(|| started.elapsed()), total))
+}
+
+pub fn print_report(report: &BenchReport) {
+    let mb = |b: Option<u64>| match b {
+        Some(b) => format!("{:.1} MB", b as f64 / 1_000_000.0),
+        None => "-".to_string(),
+    };
+    let pct = |p: &Percentiles| {
+        format!(
+            "p50 {:.1} ms, p95 {:.1} ms, max {:.1} ms",
+            p.p50_ms, p.p95_ms, p.max_ms
+        )
+    };
+    println!("Agents connected:   {}", report.agents);
+    println!("Tunnel setup rate:  {:.1}/s", report.tunnels_per_sec);
+    println!("Tunnel setup:       {}", pct(&report.tunnel_setup));
+    println!(
+        "Streams:            {} ({} failed)",
+        report.streams, report.failed_streams
+    );
+    println!("Stream first byte:  {}", pct(&report.stream_first_byte));
+    println!("Throughput:         {:.1} Mbit/s", report.throughput_mbit_s);
+    println!(
+        "Server memory:      {} idle, {} loaded",
+        mb(report.server_rss_before),
+        mb(report.server_rss_loaded)
+    );
+    println!("Bench memory:       {}", mb(report.bench_rss));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let samples = (1..=100).map(Duration::from_millis).collect();
+        let p = Percentiles::of(samples);
+        assert_eq!((p.p50_ms, p.p95_ms, p.max_ms), (51.0, 95.0, 100.0));
+        assert_eq!(Percentiles::of(Vec::new()).max_ms, 0.0);
+    }
+}
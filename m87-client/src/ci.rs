@@ -0,0 +1,465 @@
+//! `m87 ci`: deployment helpers for CI pipelines (GitHub Actions first, GitLab CI works too).
+//!
+//! In a pipeline the CLI is not logged in. Set `M87_TOKEN` (an API key) and
+//! `M87_SERVER_URL` instead; the token is masked in the job log.
+
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use m87_shared::deploy_spec::{
+    CreateDeployRevisionBody, DeploymentRevision, DeploymentStatusSnapshot, Outcome, Provenance,
+    SnapshotQuery, StepState, UpdateDeployRevisionBody,
+};
+use make87_sdk::Make87Client;
+
+use crate::auth::AuthManager;
+use crate::config::Config;
+use crate::device::deploy::{SpecFile, SpecType, load_spec_file};
+use crate::devices::resolve_device_cached;
+use crate::util::wait::{WaitOutcome, poll_until};
+
+pub const TOKEN_ENV_VARS: [&str; 2] = ["M87_TOKEN", "M87_API_KEY"];
+pub const SERVER_URL_ENV_VAR: &str = "M87_SERVER_URL";
+
+/// Where a pipeline run comes from, read from the CI provider's environment.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CiContext {
+    pub commit_sha: Option<String>,
+    pub branch: Option<String>,
+    pub repository: Option<String>,
+    pub pipeline_url: Option<String>,
+    pub author: Option<String>,
+}
+
+impl CiContext {
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let get = |key: &str| lookup(key).filter(|v| !v.is_empty());
+        let first = |keys: &[&str]| keys.iter().find_map(|k| get(k));
+
+        let github_run_url = match (
+            get("GITHUB_SERVER_URL"),
+            get("GITHUB_REPOSITORY"),
+            get("GITHUB_RUN_ID"),
+        ) {
+            (Some(server), Some(repo), Some(run)) => {
+                Some(format!("{}/{}/actions/runs/{}", server, repo, run))
+            }
+            _ => None,
+        };
+
+        Self {
+            commit_sha: first(&["GITHUB_SHA", "CI_COMMIT_SHA"]),
+            // GITHUB_HEAD_REF is only set for pull requests and names the source branch
+            branch: first(&["GITHUB_HEAD_REF", "GITHUB_REF_NAME", "CI_COMMIT_REF_NAME"]),
+            repository: first(&["GITHUB_REPOSITORY", "CI_PROJECT_PATH"]),
+            pipeline_url: github_run_url.or_else(|| get("CI_PIPELINE_URL")),
+            author: first(&["GITHUB_ACTOR", "GITLAB_USER_LOGIN"]),
+        }
+    }
+
+    pub fn provenance(&self) -> Provenance {
+        Provenance {
+            git_sha: self.commit_sha.clone(),
+            git_ref: self.branch.clone(),
+            repository: self.repository.clone(),
+            pipeline_url: self.pipeline_url.clone(),
+            author: self.author.clone(),
+        }
+    }
+}
+
+fn is_github_actions() -> bool {
+    std::env::var("GITHUB_ACTIONS").is_ok_and(|v| v == "true")
+}
+
+/// Keep a secret out of the job log.
+fn mask_secret(secret: &str) {
+    if is_github_actions() && !secret.is_empty() {
+        println!("::add-mask::{}", secret);
+    }
+}
+
+/// Publish a step output (`steps.<id>.outputs.<key>`) when running in GitHub Actions.
+fn set_output(key: &str, value: &str) -> Result<()> {
+    let Ok(path) = std::env::var("GITHUB_OUTPUT") else {
+        return Ok(());
+    };
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("failed to open {}", path))?;
+    writeln!(f, "{}={}", key, value)?;
+    Ok(())
+}
+
+struct CiTarget {
+    client: Make87Client,
+    device_id: String,
+}
+
+/// Resolve the device either from the CI environment (`M87_TOKEN` + `M87_SERVER_URL`)
+/// or from the logged-in CLI config.
+async fn resolve_target(device: &str) -> Result<CiTarget> {
+    let trust = Config::load()
+        .map(|c| c.trust_invalid_server_cert)
+        .unwrap_or(false);
+    let env_token = TOKEN_ENV_VARS
+        .iter()
+        .find_map(|k| std::env::var(k).ok().filter(|v| !v.is_empty()));
+
+    let Some(token) = env_token else {
+        let resolved = resolve_device_cached(device).await?;
+        let token = AuthManager::get_cli_token().await?;
+        mask_secret(&token);
+        return Ok(CiTarget {
+            client: Make87Client::with_options(resolved.url, token, trust)?,
+            device_id: resolved.id,
+        });
+    };
+    mask_secret(&token);
+
+    let server_url = std::env::var(SERVER_URL_ENV_VAR)
+        .map_err(|_| anyhow!("{} must be set together with M87_TOKEN", SERVER_URL_ENV_VAR))?;
+    let client = Make87Client::with_options(server_url, token, trust)?;
+    let found = client
+        .list_devices()
+        .await?
+        .into_iter()
+        .find(|d| d.name == device || d.short_id == device || d.id == device)
+        .ok_or_else(|| anyhow!("Device '{}' not found", device))?;
+
+    Ok(CiTarget {
+        client,
+        device_id: found.id,
+    })
+}
+
+async fn active_revision(target: &CiTarget) -> Result<Option<DeploymentRevision>> {
+    match target
+        .client
+        .get_active_deployment_id(&target.device_id)
+        .await?
+    {
+        Some(id) => Ok(Some(
+            target.client.get_deployment(&target.device_id, &id).await?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Build a new revision from `file` on top of the active deployment, annotate it
+/// with the pipeline context and activate it. Returns the new revision id.
+pub async fn deploy(
+    device: &str,
+    file: &Path,
+    spec_type: SpecType,
+    name: Option<&str>,
+) -> Result<String> {
+    let target = resolve_target(device).await?;
+    let ctx = CiContext::from_env();
+
+    let mut revision = match load_spec_file(file, spec_type, name).await? {
+        SpecFile::Deployment(rev) => *rev,
+        SpecFile::Job(job) => {
+            let mut rev = active_revision(&target)
+                .await?
+                .unwrap_or_else(DeploymentRevision::empty);
+            match rev.jobs.iter_mut().find(|j| j.id == job.id) {
+                Some(existing) => *existing = *job,
+                None => rev.jobs.push(*job),
+            }
+            rev
+        }
+    };
+    revision = revision.clone_with_new_id();
+    let provenance = ctx.provenance();
+    if !provenance.is_empty() {
+        revision.provenance = Some(provenance);
+    }
+
+    let created = target
+        .client
+        .create_deployment(
+            &target.device_id,
+            &CreateDeployRevisionBody {
+                revision: revision.to_yaml()?,
+                active: Some(false),
+            },
+        )
+        .await
+        .context("failed to create deployment")?;
+    let revision_id = created
+        .id
+        .ok_or_else(|| anyhow!("server returned a deployment without id"))?;
+
+    target
+        .client
+        .update_deployment(
+            &target.device_id,
+            &revision_id,
+            &UpdateDeployRevisionBody {
+                active: Some(true),
+                ..Default::default()
+            },
+        )
+        .await
+        .context("failed to activate deployment")?;
+
+    set_output("revision", &revision_id)?;
+    Ok(revision_id)
+}
+
+/// Wait for `revision_id` the same way `m87 <device> deploy wait` does.
+pub async fn wait(device: &str, revision_id: &str, timeout: Duration) -> Result<WaitOutcome> {
+    let target = resolve_target(device).await?;
+    poll_until(timeout, Duration::from_secs(5), || async {
+        let snapshot = target
+            .client
+            .get_deployment_snapshot_part(
+                &target.device_id,
+                revision_id,
+                &SnapshotQuery::outcome_only(),
+            )
+            .await?;
+        Ok(match snapshot.outcome {
+            Outcome::Success | Outcome::Skipped => Some(WaitOutcome::Reached),
+            Outcome::Failed => Some(WaitOutcome::Failed),
+            Outcome::Unknown => None,
+        })
+    })
+    .await
+}
+
+/// Markdown summary of a deployment, for PR comments and job summaries. With
+/// `step_summary` it is also appended to `$GITHUB_STEP_SUMMARY`.
+pub async fn status_comment(
+    device: &str,
+    revision_id: Option<String>,
+    step_summary: bool,
+) -> Result<String> {
+    let target = resolve_target(device).await?;
+    let revision_id = match revision_id {
+        Some(id) => id,
+        None => target
+            .client
+            .get_active_deployment_id(&target.device_id)
+            .await?
+            .ok_or_else(|| {
+                anyhow!(
+                    "No active deployment set for device {}. Specify one with --revision",
+                    device
+                )
+            })?,
+    };
+
+    let revision = target
+        .client
+        .get_deployment(&target.device_id, &revision_id)
+        .await?;
+    let snapshot = target
+        .client
+        .get_deployment_snapshot(&target.device_id, &revision_id)
+        .await?;
+    let markdown = render_status_markdown(device, &revision, &snapshot);
+
+    if step_summary {
+        let Ok(path) = std::env::var("GITHUB_STEP_SUMMARY") else {
+            bail!("--step-summary needs GITHUB_STEP_SUMMARY (GitHub Actions)");
+        };
+        let mut f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path))?;
+        writeln!(f, "{}", markdown)?;
+    }
+    Ok(markdown)
+}
+
+fn outcome_icon(outcome: &Outcome) -> &'static str {
+    match outcome {
+        Outcome::Success => "✅",
+        Outcome::Failed => "❌",
+        Outcome::Unknown => "⏳",
+        Outcome::Skipped => "⏭️",
+    }
+}
+
+fn check_cell(ok: Option<bool>) -> &'static str {
+    match ok {
+        Some(true) => "✅",
+        Some(false) => "❌",
+        None => "–",
+    }
+}
+
+fn escape_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace('\n', " ")
+}
+
+pub fn render_status_markdown(
+    device: &str,
+    revision: &DeploymentRevision,
+    snap: &DeploymentStatusSnapshot,
+) -> String {
+    let mut out = format!(
+        "### {} Deployment `{}` on `{}`: {}\n\n",
+        outcome_icon(&snap.outcome),
+        snap.revision_id,
+        device,
+        snap.outcome
+    );
+
+    let provenance = revision.provenance.clone().unwrap_or_default();
+    let fields: Vec<(&str, &str)> = provenance
+        .fields()
+        .into_iter()
+        .chain(
+            revision
+                .annotations
+                .iter()
+                .map(|(k, v)| (k.as_str(), v.as_str())),
+        )
+        .collect();
+    if !fields.is_empty() {
+        for (k, v) in fields {
+            out.push_str(&format!("- **{}**: {}\n", k, v));
+        }
+        out.push('\n');
+    }
+    if let Some(err) = &snap.error {
+        out.push_str(&format!("> {}\n\n", escape_cell(err)));
+    }
+
+    out.push_str("| Job | Outcome | Alive | Healthy | Error |\n");
+    out.push_str("|-----|---------|-------|---------|-------|\n");
+    for run in snap.runs.iter().filter(|r| r.enabled) {
+        out.push_str(&format!(
+            "| `{}` | {} {} | {} | {} | {} |\n",
+            run.run_id,
+            outcome_icon(&run.outcome),
+            run.outcome,
+            check_cell(run.alive.as_ref().map(|a| a.ok)),
+            check_cell(run.healthy.as_ref().map(|h| h.ok)),
+            escape_cell(run.error.as_deref().unwrap_or("")),
+        ));
+    }
+
+    for run in &snap.runs {
+        for step in run.steps.iter().filter(|s| s.state == StepState::Failed) {
+            let tail = step
+                .attempt
+                .as_ref()
+                .and_then(|a| a.log_tail.as_deref())
+                .unwrap_or("");
+            out.push_str(&format!(
+                "\n<details><summary>{} / {} failed{}</summary>\n\n```\n{}\n```\n</details>\n",
+                run.run_id,
+                step.name,
+                step.exit_code
+                    .map(|c| format!(" (exit {})", c))
+                    .unwrap_or_default(),
+                tail.trim_end()
+            ));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use m87_shared::deploy_spec::{RunStatus, RunType, StepStatus};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_context_from_github_env() {
+        let env: BTreeMap<&str, &str> = [
+            ("GITHUB_SHA", "abc123"),
+            ("GITHUB_REF_NAME", "main"),
+            ("GITHUB_HEAD_REF", ""),
+            ("GITHUB_REPOSITORY", "acme/app"),
+            ("GITHUB_SERVER_URL", "https://github.com"),
+            ("GITHUB_RUN_ID", "42"),
+            ("GITHUB_ACTOR", "octocat"),
+        ]
+        .into();
+        let ctx = CiContext::from_lookup(|k| env.get(k).map(|v| v.to_string()));
+
+        assert_eq!(ctx.commit_sha.as_deref(), Some("abc123"));
+        assert_eq!(ctx.branch.as_deref(), Some("main"));
+        assert_eq!(
+            ctx.pipeline_url.as_deref(),
+            Some("https://github.com/acme/app/actions/runs/42")
+        );
+        let provenance = ctx.provenance();
+        assert_eq!(provenance.author.as_deref(), Some("octocat"));
+        assert_eq!(provenance.fields().len(), 5);
+    }
+
+    #[test]
+    fn test_context_outside_ci_is_empty() {
+        let ctx = CiContext::from_lookup(|_| None);
+        assert_eq!(ctx, CiContext::default());
+        assert!(ctx.provenance().is_empty());
+    }
+
+    #[test]
+    fn test_render_status_markdown() {
+        let mut revision = DeploymentRevision::empty();
+        revision.provenance = Some(Provenance {
+            git_sha: Some("abc123".to_string()),
+            ..Default::default()
+        });
+        revision
+            .annotations
+            .insert("ticket".to_string(), "OPS-7".to_string());
+        let snap = DeploymentStatusSnapshot {
+            revision_id: "rev-1".to_string(),
+            outcome: Outcome::Failed,
+            dirty: false,
+            error: None,
+            rollback: None,
+            annotations: BTreeMap::new(),
+            provenance: None,
+            runs: vec![RunStatus {
+                run_id: "web".to_string(),
+                enabled: true,
+                run_type: RunType::default(),
+                outcome: Outcome::Failed,
+                last_update: 0,
+                error: Some("pull | failed".to_string()),
+                alive: None,
+                healthy: None,
+                steps: vec![StepStatus {
+                    step_id: "web:pull".to_string(),
+                    name: "pull".to_string(),
+                    is_undo: false,
+                    defined_in_spec: true,
+                    state: StepState::Failed,
+                    last_update: None,
+                    attempt: None,
+                    attempts_total: 1,
+                    exit_code: Some(1),
+                    error: None,
+                }],
+                steps_omitted: false,
+                drift: None,
+            }],
+            total_runs: None,
+        };
+
+        let md = render_status_markdown("edge-1", &revision, &snap);
+        assert!(md.starts_with("### ❌ Deployment `rev-1` on `edge-1`: failed"));
+        assert!(md.contains("- **commit**: abc123\n- **ticket**: OPS-7\n"));
+        assert!(md.contains("| `web` | ❌ failed | – | – | pull \\| failed |"));
+        assert!(md.contains("web / pull failed (exit 1)"));
+    }
+}
@@ -1,25 +1,67 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use anyhow::Context;
 use anyhow::bail;
 use clap::{CommandFactory, Parser, Subcommand};
+use m87_shared::approval::ApprovalStatus;
+use m87_shared::bandwidth::BandwidthProfile;
+use m87_shared::deploy_spec::SnapshotQuery;
+use m87_shared::fleet_report::fleet_report_csv;
+use m87_shared::incident::IncidentState;
+use m87_shared::inventory::{PackageAction, Severity, VulnerabilityReport};
+use m87_shared::iot_bridge::{
+    DEFAULT_AWS_TOPIC_PREFIX, DEFAULT_AZURE_POLICY_NAME, IotBridgeTarget,
+};
+use m87_shared::log_filter::LogFilter;
+use m87_shared::log_format::LogLevel;
+use m87_shared::org::UpdateOrgLimitsBody;
+use m87_shared::patching::{CreatePatchPolicyBody, MaintenanceWindow};
+use m87_shared::probe::{ProbeSpec, ProbeTarget};
 use m87_shared::roles::Role;
+use m87_shared::rollout::{RolloutAction, RolloutDeviceState};
+use m87_shared::usage::{USAGE_EXPORT_VERSION, UsageExport, UsageQuery, is_month, usage_csv};
 
+use crate::access;
+use crate::admin;
+use crate::approvals;
 use crate::auth;
+#[cfg(feature = "runtime")]
+use crate::bench;
+use crate::ci;
 use crate::config::Config;
 use crate::device;
+#[cfg(feature = "runtime")]
+use crate::device::container::{ManifestFormat, ManifestOptions};
+use crate::device::debug::DebugServer;
 use crate::device::deploy::DeploymentUpdateArgs;
 use crate::device::deploy::SpecType;
 use crate::device::forward;
+use crate::device::profile::ProfileFormat;
 use crate::device::serial;
 use crate::devices;
+use crate::devices::{ExportFormat, ListFormat};
+use crate::incidents;
 use crate::org;
+use crate::rollouts;
+#[cfg(feature = "runtime")]
+use crate::sim;
+use crate::streams::e2e;
+use crate::streams::stream_type::FirewallBackend;
 use crate::tui;
 use crate::update;
 #[cfg(feature = "runtime")]
 use crate::util;
+use crate::util::clipboard::ClipboardPolicy;
 use crate::util::logging::init_logging;
 use crate::util::tls::set_tls_provider;
+use crate::util::wait::{WaitOutcome, parse_duration};
+
+/// Runs per page of `m87 <device> deployment status`
+const STATUS_PAGE_RUNS: usize = 20;
 
 /// Save owner_reference to config if org_id or email is provided
 #[cfg(feature = "runtime")]
@@ -74,14 +116,53 @@ struct Cli {
     #[arg(short, long, global = true)]
     verbose: bool,
 
+    /// Connect straight to a device on the local network, bypassing the relay.
+    /// Use the device address in place of its name: m87 --direct 192.168.1.42 shell
+    #[arg(long, global = true)]
+    direct: bool,
+
+    /// Device key used to authenticate --direct connections (defaults to $M87_DEVICE_KEY)
+    #[arg(long, global = true)]
+    device_key: Option<String>,
+
+    /// Certificate fingerprint of the device for --direct connections, from
+    /// `m87 local lan-fingerprint` (defaults to $M87_DEVICE_FINGERPRINT)
+    #[arg(long, global = true)]
+    fingerprint: Option<String>,
+
+    /// Run as a named instance with its own config, credentials, data and service,
+    /// e.g. to register several devices from one host: m87 --instance robot1 runtime start
+    #[arg(long, global = true, value_parser = parse_instance)]
+    instance: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Authenticate with make87 via browser
-    Login,
+    /// Set up the CLI: log in, pick an organization, optionally register this
+    /// machine as a device and install shell completion
+    Init,
+
+    /// Print a shell completion script, e.g. m87 completion zsh > ~/.zfunc/_m87
+    Completion {
+        /// bash, zsh, fish, elvish or powershell
+        shell: clap_complete::Shell,
+    },
+
+    /// Authenticate with make87 via browser, or with a password against a
+    /// self-hosted server in local auth mode (--server and --email)
+    Login {
+        /// Self-hosted server URL, e.g. https://m87.internal
+        #[arg(long, requires = "email")]
+        server: Option<String>,
+
+        /// Email of the server's local user; the password is prompted for or
+        /// read from M87_PASSWORD
+        #[arg(long, requires = "server")]
+        email: Option<String>,
+    },
 
     /// Logout and deauthenticate this device
     Logout,
@@ -91,15 +172,73 @@ enum Commands {
     #[command(subcommand)]
     Runtime(RuntimeCommands),
 
+    /// Inspect this device's deployment state without server connectivity
+    #[cfg(feature = "runtime")]
+    #[command(subcommand)]
+    Local(LocalCommands),
+
     /// Internal commands for privileged operations (hidden from help)
     #[cfg(feature = "runtime")]
     #[command(subcommand, hide = true)]
     Internal(InternalCommands),
 
+    /// Keep a connection to a device warm for following commands (started by
+    /// the CLI, hidden from help)
+    #[cfg(unix)]
+    #[command(hide = true)]
+    ConnectionHelper {
+        #[arg(long)]
+        host: String,
+
+        #[arg(long)]
+        device: String,
+    },
+
     /// Manage devices and view pending registrations
     #[command(subcommand)]
     Devices(DevicesCommands),
 
+    /// Print a one-line command that installs the runtime on a new device and
+    /// registers it without approval
+    InstallCommand {
+        /// Organization the devices join (defaults to the default organization)
+        #[arg(long = "org-id", conflicts_with = "personal")]
+        org_id: Option<String>,
+
+        /// Register the devices as your own instead of an organization's
+        #[arg(long)]
+        personal: bool,
+
+        /// How long the command works (e.g. 2h, 7d; default 24h)
+        #[arg(long, value_parser = parse_duration)]
+        expires: Option<Duration>,
+
+        /// How many devices may register with the command
+        #[arg(long)]
+        uses: Option<u32>,
+
+        /// Print the command and its details as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Define local shorthands for device names (m87 alias jetson=jetson-orin-nano-lab3)
+    Alias {
+        /// NAME=DEVICE to define an alias; lists all aliases when omitted
+        assignment: Option<String>,
+
+        /// Remove an alias
+        #[arg(long, value_name = "NAME", conflicts_with = "assignment")]
+        remove: Option<String>,
+    },
+
+    /// Find devices advertising on the local network (mDNS)
+    Discover {
+        /// How long to listen for announcements, in seconds
+        #[arg(long, default_value = "3")]
+        timeout: u64,
+    },
+
     /// Show CLI version information
     Version,
 
@@ -140,8 +279,21 @@ enum Commands {
         exclude: Vec<String>,
     },
 
+    /// List files at <device>:<path>, or all accessible devices without a path
     Ls {
-        path: String,
+        path: Option<String>,
+
+        /// Only list devices whose asset info matches key=value (device listing only)
+        #[arg(long, action = clap::ArgAction::Append)]
+        filter: Vec<String>,
+
+        /// Device listing format: table, json or geojson
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+
+        /// Print the inventory report instead (health, last seen, active revision)
+        #[arg(long, value_enum, conflicts_with = "format")]
+        export: Option<ExportFormat>,
     },
 
     #[command(external_subcommand)]
@@ -155,6 +307,353 @@ enum Commands {
 
     #[command(subcommand)]
     Org(OrgCommands),
+
+    /// Review operations waiting for a second operator's approval
+    #[command(subcommand)]
+    Approvals(ApprovalCommands),
+
+    /// Incidents opened by device alerts, with the events and actions that followed
+    #[command(subcommand)]
+    Incidents(IncidentCommands),
+
+    /// Roll a deployment out to many devices in waves, halting on failures
+    #[command(subcommand)]
+    Deploy(DeployCommands),
+
+    /// Time-boxed access to single devices, without permanent membership
+    #[command(subcommand)]
+    Access(AccessCommands),
+
+    /// Deployment helpers for CI pipelines (reads M87_TOKEN and M87_SERVER_URL)
+    #[command(subcommand)]
+    Ci(CiCommands),
+
+    /// Server administration and load testing
+    #[command(subcommand)]
+    Admin(AdminCommands),
+
+    /// Development helpers
+    #[command(subcommand)]
+    Dev(DevCommands),
+}
+
+#[derive(Subcommand)]
+enum DevCommands {
+    /// Mirror a local directory to the device on every change and optionally rerun a run after
+    /// each sync, for an edit-on-laptop/run-on-device loop (until Ctrl+C)
+    Sync {
+        /// Local directory to mirror
+        source: String,
+
+        /// Destination as <device>:<path>, e.g. the workdir of a run
+        dest: String,
+
+        /// Rerun this run of the device's deployment after each sync
+        #[arg(long)]
+        restart: Option<String>,
+
+        /// Exclude files matching pattern (can be used multiple times)
+        #[arg(long, short = 'e', action = clap::ArgAction::Append)]
+        exclude: Vec<String>,
+    },
+
+    /// Run simulated devices that register, heartbeat and accept deployments (until Ctrl+C)
+    #[cfg(feature = "runtime")]
+    Simulate {
+        /// Number of simulated devices
+        #[arg(long, default_value_t = 20)]
+        count: usize,
+
+        /// Server URL (defaults to the configured server)
+        #[arg(long)]
+        server: Option<String>,
+
+        /// Email or organization id to register the devices under
+        #[arg(long)]
+        owner: Option<String>,
+
+        /// Keep the devices registered on exit and reuse them next time
+        #[arg(long)]
+        keep: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum AdminCommands {
+    /// Collections, their sizes and indexes of each server's database, with missing indexes
+    DbStats {
+        /// Server URL (defaults to all configured servers)
+        #[arg(long)]
+        server: Option<String>,
+
+        /// List every index with its keys, TTL and uses
+        #[arg(long)]
+        indexes: bool,
+
+        /// Print the stats as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Load test a server's relay with simulated agents and clients (use a test server)
+    #[cfg(feature = "runtime")]
+    Bench {
+        /// Server URL (defaults to the configured server)
+        #[arg(long)]
+        server: Option<String>,
+
+        /// Email or organization id to register the simulated agents under
+        #[arg(long)]
+        owner: Option<String>,
+
+        /// Number of simulated agents
+        #[arg(long, default_value_t = 10)]
+        agents: usize,
+
+        /// Number of clients, spread round-robin over the agents
+        #[arg(long, default_value_t = 10)]
+        clients: usize,
+
+        /// Parallel streams per client
+        #[arg(long, default_value_t = 4)]
+        streams: usize,
+
+        /// Payload echoed through each stream, in KiB
+        #[arg(long, default_value_t = 1024)]
+        payload_kb: usize,
+
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum CiCommands {
+    /// Deploy a file as a new active deployment annotated with the pipeline's commit and branch
+    Deploy {
+        /// Device name or ID
+        device: String,
+
+        /// File to deploy (docker-compose.yml, run spec or deployment yaml)
+        file: PathBuf,
+
+        /// Spec type (auto detects by default)
+        #[arg(long, value_enum, default_value_t = SpecType::Auto)]
+        r#type: SpecType,
+
+        /// Optional display name for the run spec
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Block until the deployment succeeds or fails (exit 1 on failure, 2 on timeout)
+        #[arg(long)]
+        wait: bool,
+
+        /// Give up waiting after this long (e.g. 90s, 10m, 1h)
+        #[arg(long, default_value = "10m", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+
+    /// Print a markdown summary of a deployment for PR comments
+    StatusComment {
+        /// Device name or ID
+        device: String,
+
+        /// Deployment to summarize. Defaults to the active deployment
+        #[arg(long)]
+        revision: Option<String>,
+
+        /// Also append the summary to $GITHUB_STEP_SUMMARY
+        #[arg(long)]
+        step_summary: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ApprovalCommands {
+    /// List approval requests on devices you can access, newest first
+    List {
+        /// Only requests with this status (pending, approved, rejected, used, expired);
+        /// pending by default
+        #[arg(long, value_parser = parse_approval_status)]
+        status: Option<ApprovalStatus>,
+        /// Requests of any status
+        #[arg(long, conflicts_with = "status")]
+        all: bool,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        /// Print the requests as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Approve a request; the requester can then run the operation once
+    Approve {
+        id: String,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+    Reject {
+        id: String,
+        #[arg(long)]
+        comment: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IncidentCommands {
+    /// List incidents on devices you can access, newest first
+    List {
+        /// Only incidents in this state (open, acknowledged, resolved);
+        /// unresolved ones by default
+        #[arg(long, value_parser = parse_incident_state)]
+        state: Option<IncidentState>,
+        /// Incidents in any state
+        #[arg(long, conflicts_with = "state")]
+        all: bool,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        /// Print the incidents as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show an incident with its alerts, events and actions
+    Show {
+        id: String,
+        /// Print the incident as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Take an incident; later alerts and events are still attached to it
+    Ack {
+        id: String,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Close an incident; the next alert of the device opens a new one
+    Resolve {
+        id: String,
+        #[arg(long)]
+        note: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DeployCommands {
+    /// Start a staged rollout of a file to the named or matching devices
+    Rollout {
+        /// File to deploy (docker-compose.yml, run spec or deployment yaml)
+        file: PathBuf,
+
+        /// Device name or ID, in rollout order. Can be used multiple times
+        #[arg(long = "device", action = clap::ArgAction::Append)]
+        devices: Vec<String>,
+
+        /// key=value asset info filter (substring, case-insensitive).
+        /// Can be used multiple times
+        #[arg(long, action = clap::ArgAction::Append)]
+        filter: Vec<String>,
+
+        /// Spec type (auto detects by default)
+        #[arg(long, value_enum, default_value_t = SpecType::Auto)]
+        r#type: SpecType,
+
+        /// Optional display name for the run spec
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Share of devices updated once each wave started, e.g. 5,25,100
+        #[arg(long, value_delimiter = ',')]
+        waves: Option<Vec<u32>>,
+
+        /// Halt when more than this percentage of updated devices failed
+        #[arg(long)]
+        failure_threshold: Option<u32>,
+
+        /// How long a wave stays healthy before the next starts (e.g. 10m)
+        #[arg(long, value_parser = parse_duration)]
+        soak: Option<Duration>,
+
+        /// Devices without an outcome after this long count as failed
+        #[arg(long, value_parser = parse_duration)]
+        deploy_timeout: Option<Duration>,
+
+        /// Only halt on failures; keep the new revision on updated devices
+        #[arg(long)]
+        no_rollback: bool,
+
+        /// Print the rollout as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show a rollout, or list running and halted rollouts without --rollout
+    Status {
+        #[arg(long)]
+        rollout: Option<String>,
+        /// Also list finished rollouts
+        #[arg(long, conflicts_with = "rollout")]
+        all: bool,
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Stop starting new waves; devices keep the revision they run
+    Halt {
+        id: String,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Continue a halted rollout; its failures so far no longer count
+    Resume {
+        id: String,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Reactivate the previous revision on every updated device
+    Rollback {
+        id: String,
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Stop a rollout for good
+    Cancel {
+        id: String,
+        #[arg(long)]
+        note: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AccessCommands {
+    /// Give a user a role on one device until the TTL runs out
+    Grant {
+        /// Email of the user
+        email: String,
+        #[arg(long)]
+        device: String,
+        /// viewer, operator (same as editor), editor or admin
+        #[arg(long, value_parser = parse_grant_role)]
+        role: Role,
+        /// How long the access lasts, e.g. 30m, 4h or 2d
+        #[arg(long, value_parser = parse_duration)]
+        ttl: Duration,
+        /// Recorded with the grant and in the audit log
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// List active grants on devices you administer
+    List {
+        /// Also list expired and revoked grants
+        #[arg(long)]
+        all: bool,
+        /// Print the grants as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// End a grant before it expires
+    Revoke { id: String },
 }
 
 #[derive(Subcommand)]
@@ -177,6 +676,50 @@ enum OrgCommands {
         new_id: String,
     },
     List,
+    /// Advisories (CVEs) affecting the org's devices, most severe and widespread first
+    Vulnerabilities {
+        #[arg(long)]
+        org_id: Option<String>,
+        /// Only show advisories of this severity or higher (low, medium, high, critical)
+        #[arg(long, value_parser = parse_severity)]
+        severity: Option<Severity>,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Manage OS patch policies the org's devices apply in maintenance windows
+    #[clap(subcommand)]
+    PatchPolicies(PatchPolicyAction),
+    /// Show or change the org's quotas (devices, revisions, report storage, tunnels)
+    #[clap(subcommand)]
+    Limits(LimitsAction),
+    /// Custom patterns the org's devices mask in log tails, observe output and streamed logs
+    #[clap(subcommand)]
+    Redaction(RedactionAction),
+    /// Where incident state changes of the org's devices are posted
+    #[clap(subcommand)]
+    IncidentWebhook(IncidentWebhookAction),
+    /// Republish presence and telemetry of the org's devices to an IoT platform
+    #[clap(subcommand)]
+    IotBridge(IotBridgeAction),
+    /// Monthly usage for chargeback: device hours, relayed traffic and storage
+    Usage {
+        /// Only this org; all orgs you administer otherwise
+        #[arg(long)]
+        org_id: Option<String>,
+        /// First month to include (YYYY-MM)
+        #[arg(long, value_parser = parse_month)]
+        from: Option<String>,
+        /// Last month to include (YYYY-MM)
+        #[arg(long, value_parser = parse_month)]
+        to: Option<String>,
+        /// Print the records as CSV
+        #[arg(long, conflicts_with = "json")]
+        csv: bool,
+        /// Print the records as JSON
+        #[arg(long)]
+        json: bool,
+    },
     //     Invites {
     //         #[clap(subcommand)]
     //         action: InviteAction,
@@ -209,67 +752,298 @@ enum OrgDeviceAction {
 }
 
 #[derive(Subcommand)]
-enum MemberAction {
-    Add {
-        /// Email address of the user to add
-        email: String,
-        /// Role of the user to add
-        #[arg(value_parser = parse_role)]
-        role: Role,
-        /// Optional organization ID to add the user to. Otherwise will be attempted to auto resolve
+enum PatchPolicyAction {
+    List {
         #[arg(long)]
         org_id: Option<String>,
-    },
-    Update {
-        email: String,
-        #[arg(value_parser = parse_role)]
-        role: Role,
+        /// Print the policies as JSON
         #[arg(long)]
-        org_id: Option<String>,
+        json: bool,
     },
-    Remove {
-        email: String,
+    /// Create a policy; devices pick it up with their next heartbeat
+    Create {
+        name: String,
+        /// Weekly window in device local time, e.g. 'sat,sun 02:00-05:00' or 'daily 03:00-04:00'
+        #[arg(long, value_parser = parse_window)]
+        window: String,
+        /// Package to upgrade (repeatable); all upgradable packages if omitted
+        #[arg(long = "package", action = clap::ArgAction::Append)]
+        packages: Vec<String>,
+        /// Reboot when the OS reports that the upgrade requires it
+        #[arg(long)]
+        reboot: bool,
+        /// Only apply on devices whose facts match this condition (e.g. 'os.id == "debian"')
+        #[arg(long)]
+        when: Option<String>,
         #[arg(long)]
         org_id: Option<String>,
     },
-
-    List {
+    Delete {
+        /// Policy name or ID
+        name: String,
         #[arg(long)]
         org_id: Option<String>,
     },
 }
 
 #[derive(Subcommand)]
-enum ConfigCommands {
+enum LimitsAction {
+    /// Limits and current usage; all of your orgs unless --org-id is given
+    Show {
+        #[arg(long)]
+        org_id: Option<String>,
+        /// Print the limits as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Override the instance defaults (instance admins only). A limit left out
+    /// falls back to the default, 0 lifts it.
     Set {
-        /// Override API URL (e.g. https://eu.public.make87.dev)
         #[arg(long)]
-        runtime_server_url: Option<String>,
-
-        /// Set owner reference (email or org id)
+        org_id: Option<String>,
         #[arg(long)]
-        owner_reference: Option<String>,
-
+        max_devices: Option<u64>,
+        /// Deployment revisions kept across all devices of the org
         #[arg(long)]
-        make87_api_url: Option<String>,
-
+        max_revisions: Option<u64>,
         #[arg(long)]
-        make87_app_url: Option<String>,
-
+        max_report_storage_mb: Option<u64>,
+        /// Concurrent client sessions to the org's devices
         #[arg(long)]
-        trust_invalid_server_cert: Option<bool>,
+        max_tunnels: Option<u64>,
     },
-
-    Show,
-    File,
 }
 
 #[derive(Subcommand)]
-enum SshCommands {
-    Enable,
-    Disable,
-    #[command(external_subcommand)]
-    Connect(Vec<String>),
+enum RedactionAction {
+    Show {
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+    /// Replace the org's patterns (org admins). A match is masked, or only its
+    /// first capture group if it has one
+    Set {
+        #[arg(long)]
+        org_id: Option<String>,
+        /// Regular expression, e.g. 'ACME-[0-9]{6}' or 'serial=(\w+)'
+        #[arg(long = "pattern", required = true, action = clap::ArgAction::Append)]
+        patterns: Vec<String>,
+    },
+    /// Remove all of the org's patterns; the built-in rules stay
+    Clear {
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IncidentWebhookAction {
+    Show {
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+    /// Post opened, acknowledged and resolved incidents to URL (org admins)
+    Set {
+        url: String,
+        #[arg(long)]
+        org_id: Option<String>,
+        /// Sign requests with an HMAC-SHA256 of the body in X-M87-Signature-256
+        #[arg(long)]
+        secret: Option<String>,
+    },
+    Clear {
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum IotBridgeAction {
+    Show {
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+    /// Publish to AWS IoT Core topics <prefix>/<device>/presence and .../telemetry (org admins)
+    Aws {
+        /// Data endpoint, e.g. abc123-ats.iot.eu-central-1.amazonaws.com
+        endpoint: String,
+        /// Access key of an IAM user allowed iot:Publish
+        #[arg(long)]
+        access_key_id: String,
+        #[arg(long)]
+        secret_access_key: String,
+        #[arg(long, default_value = DEFAULT_AWS_TOPIC_PREFIX)]
+        topic_prefix: String,
+        #[arg(long)]
+        org_id: Option<String>,
+        /// Least time between two telemetry messages of a device (default 60s)
+        #[arg(long, value_parser = parse_duration)]
+        telemetry_interval: Option<Duration>,
+    },
+    /// Send device-to-cloud messages to an Azure IoT Hub, registering devices it lacks (org admins)
+    Azure {
+        /// e.g. my-hub.azure-devices.net
+        host_name: String,
+        /// Shared access policy with registry write and device connect
+        #[arg(long, default_value = DEFAULT_AZURE_POLICY_NAME)]
+        policy_name: String,
+        /// Primary key of the policy
+        #[arg(long)]
+        policy_key: String,
+        #[arg(long)]
+        org_id: Option<String>,
+        /// Least time between two telemetry messages of a device (default 60s)
+        #[arg(long, value_parser = parse_duration)]
+        telemetry_interval: Option<Duration>,
+    },
+    /// Save telemetry and the m87_online attribute on ThingsBoard as a tenant user (org admins)
+    Thingsboard {
+        /// e.g. https://thingsboard.example.com
+        url: String,
+        #[arg(long)]
+        username: String,
+        #[arg(long)]
+        password: String,
+        #[arg(long)]
+        org_id: Option<String>,
+        /// Least time between two telemetry messages of a device (default 60s)
+        #[arg(long, value_parser = parse_duration)]
+        telemetry_interval: Option<Duration>,
+    },
+    Clear {
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum MemberAction {
+    Add {
+        /// Email address of the user to add
+        email: String,
+        /// Role of the user to add
+        #[arg(value_parser = parse_role)]
+        role: Role,
+        /// Optional organization ID to add the user to. Otherwise will be attempted to auto resolve
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+    Update {
+        email: String,
+        #[arg(value_parser = parse_role)]
+        role: Role,
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+    Remove {
+        email: String,
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+
+    List {
+        #[arg(long)]
+        org_id: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    Set {
+        /// Override API URL (e.g. https://eu.public.make87.dev)
+        #[arg(long)]
+        runtime_server_url: Option<String>,
+
+        /// Set owner reference (email or org id)
+        #[arg(long)]
+        owner_reference: Option<String>,
+
+        #[arg(long)]
+        make87_api_url: Option<String>,
+
+        #[arg(long)]
+        make87_app_url: Option<String>,
+
+        #[arg(long)]
+        trust_invalid_server_cert: Option<bool>,
+
+        /// PEM file of CAs to trust besides the public ones, e.g. the `ca.pem`
+        /// of `m87-server admin init-ca` (empty to disable)
+        #[arg(long)]
+        ca_bundle: Option<String>,
+
+        /// Advertise the runtime on the local network via mDNS
+        #[arg(long)]
+        lan_discovery: Option<bool>,
+
+        /// Accept direct LAN connections authenticated with the device key
+        #[arg(long)]
+        lan_direct: Option<bool>,
+
+        /// UDP port for direct LAN connections
+        #[arg(long)]
+        lan_direct_port: Option<u16>,
+
+        /// gpsd address to report the device location from (empty to disable)
+        #[arg(long)]
+        location_gpsd: Option<String>,
+
+        /// Command printing the device location as `lat,lon[,alt]` or JSON (empty to disable)
+        #[arg(long)]
+        location_command: Option<String>,
+
+        /// OSC 52 clipboard access of programs in remote shells
+        #[arg(long, value_enum)]
+        clipboard: Option<ClipboardPolicy>,
+
+        /// Echo keystrokes locally in shells before the device confirms them
+        #[arg(long)]
+        predictive_echo: Option<bool>,
+
+        /// Encrypt shell, exec and file transfer streams end to end, hiding them from the relay
+        #[arg(long)]
+        end_to_end: Option<bool>,
+
+        /// Compress logs, support bundle and file transfer streams with zstd
+        #[arg(long)]
+        compression: Option<bool>,
+
+        /// Seconds an idle connection to a device is kept for the next command (0 to disable)
+        #[arg(long)]
+        warm_connection_secs: Option<u64>,
+
+        /// Proxy for all connections, e.g. socks5h://proxy:1080 (empty to disable)
+        #[arg(long)]
+        proxy: Option<String>,
+
+        /// Comma-separated hosts, domains and CIDRs reached without the proxy
+        #[arg(long)]
+        no_proxy: Option<String>,
+
+        /// Capture and upload core dumps of runs (runtime, needs root or passwordless sudo)
+        #[arg(long)]
+        core_dumps: Option<bool>,
+
+        /// Account network traffic to runs with eBPF and report top talkers
+        /// in metrics (runtime, Linux 5.7+, needs root)
+        #[arg(long)]
+        flow_metrics: Option<bool>,
+
+        /// Open the ports runs expose in this firewall (runtime, needs root or passwordless sudo)
+        #[arg(long, value_enum)]
+        firewall: Option<FirewallBackend>,
+    },
+
+    Show,
+    File,
+}
+
+#[derive(Subcommand)]
+enum SshCommands {
+    Enable,
+    Disable,
+    #[command(external_subcommand)]
+    Connect(Vec<String>),
 }
 
 #[derive(Parser, Debug)]
@@ -284,7 +1058,17 @@ pub struct DeviceRoot {
 #[derive(Subcommand, Debug)]
 pub enum DeviceCommand {
     /// Open interactive shell on the device
-    Shell,
+    Shell {
+        /// Join an open shell session (see `sessions list`) instead of starting a new one
+        #[arg(long, value_name = "SESSION")]
+        attach: Option<String>,
+        /// Only watch the attached session, keystrokes are not forwarded
+        #[arg(long, requires = "attach")]
+        read_only: bool,
+        /// Echo keystrokes locally before the device confirms them (for high-latency links)
+        #[arg(long, conflicts_with = "attach")]
+        predict: bool,
+    },
     /// Forward remote port(s) to localhost
     Forward {
         /// Port forwarding target(s). Supports single ports and ranges.
@@ -295,6 +1079,19 @@ pub enum DeviceCommand {
         ///   8080:192.168.1.50:9080  - forward to specific host
         ///   8080-8090:192.168.1.50:9080-9090/tcp - range with host and protocol
         targets: Vec<String>,
+
+        /// Resolve target host names on the device up front and pin the address
+        #[arg(long)]
+        resolve_remote: bool,
+    },
+    /// Run a local proxy whose connections leave from the device
+    Proxy {
+        /// Port of the local SOCKS5 server (CONNECT and UDP ASSOCIATE)
+        #[arg(long, value_name = "PORT")]
+        socks5: u16,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1")]
+        bind: IpAddr,
     },
     /// Run docker commands on the device
     Docker {
@@ -303,10 +1100,24 @@ pub enum DeviceCommand {
     },
     /// Stream container logs from the device
     Logs {
+        /// Follow service logs also on devices in the low bandwidth profile
         #[arg(short = 'f', long)]
         follow: bool,
+        /// Most lines to replay with --since
         #[arg(long, default_value = "100")]
         tail: usize,
+        /// Only show lines at this level or above (trace, debug, info, warn, error, fatal)
+        #[arg(long)]
+        level: Option<LogLevel>,
+        /// Only show lines of this run, or `m87` for the runtime's own
+        #[arg(long)]
+        service: Option<String>,
+        /// First replay lines the device kept from this far back (e.g. 10m)
+        #[arg(long, value_parser = parse_duration)]
+        since: Option<Duration>,
+        /// Only show lines matching this regular expression
+        #[arg(long)]
+        grep: Option<String>,
     },
     /// Show device system metrics
     #[clap(alias = "stats")]
@@ -322,6 +1133,51 @@ pub enum DeviceCommand {
         #[arg(required = true, last = true)]
         command: Vec<String>,
     },
+    /// Sync a local directory to the device, run a test command in it, stream its output and
+    /// pull result files back. Exits with the command's exit code
+    Test {
+        /// Local directory to sync
+        #[arg(long, default_value = ".")]
+        dir: PathBuf,
+        /// Directory on the device (default: /tmp/m87-test/<name of --dir>)
+        #[arg(long)]
+        remote_dir: Option<String>,
+        /// Run the command in a throwaway container of this image, in the synced directory
+        #[arg(long)]
+        image: Option<String>,
+        /// File or directory to copy back after the run, relative to the synced directory
+        /// (e.g. test-results/junit.xml; can be used multiple times)
+        #[arg(long, action = clap::ArgAction::Append)]
+        pull: Vec<String>,
+        /// Local directory for the pulled files
+        #[arg(long, default_value = "m87-test-results")]
+        out: PathBuf,
+        /// Exclude files matching pattern from the sync (can be used multiple times)
+        #[arg(long, short = 'e', action = clap::ArgAction::Append)]
+        exclude: Vec<String>,
+        #[arg(required = true, last = true)]
+        command: Vec<String>,
+    },
+    /// Run a graphical program on the device and show its windows on the local X server
+    Gui {
+        #[arg(required = true, last = true)]
+        command: Vec<String>,
+    },
+    /// Show the device's desktop in a local VNC viewer
+    Vnc {
+        /// Local port to listen on (default: any free port)
+        #[arg(long, default_value = "0")]
+        port: u16,
+        /// Port of a VNC server already running on the device
+        #[arg(long)]
+        remote_port: Option<u16>,
+        /// Launch the system's VNC viewer
+        #[arg(long)]
+        open: bool,
+        /// Limit the desktop stream to this many kbit/s
+        #[arg(long, value_name = "KBIT/S")]
+        max_kbps: Option<u64>,
+    },
     /// Connect to a serial device
     Serial {
         /// path to serial device (e.g., "/dev/ttyUSB0")
@@ -332,6 +1188,122 @@ pub enum DeviceCommand {
 
     Status,
 
+    /// Show system info, facts, labels, health, the active deployment, recent events and
+    /// open sessions, or edit asset info (location, owner contact, asset tag, install
+    /// date, notes, custom fields)
+    Info {
+        #[command(subcommand)]
+        action: Option<InfoAction>,
+
+        /// Print the overview as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show environment facts collected by the runtime (OS, runtimes, GPU, hardware)
+    Facts {
+        /// Only show facts under this key (e.g. "gpu" or "os.name")
+        key: Option<String>,
+
+        /// Recollect facts instead of using the runtime's cache
+        #[arg(long)]
+        refresh: bool,
+
+        /// Print facts as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show advisories (CVEs) matching the kernel and packages installed on the device
+    Vulnerabilities {
+        /// Only show advisories of this severity or higher (low, medium, high, critical)
+        #[arg(long, value_parser = parse_severity)]
+        severity: Option<Severity>,
+
+        /// Print the scan result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Install, remove or upgrade packages with the device's apt-get, dnf/yum or apk
+    #[clap(subcommand)]
+    Pkg(PkgAction),
+
+    /// Show the data the device's runtime sent and received per kind of traffic
+    Usage {
+        /// Print the totals as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the patch policy runs the device reported, newest first
+    Patches {
+        #[arg(long, default_value_t = 20)]
+        limit: u32,
+
+        /// Print the runs as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Exclude the device from patch policies, or include it again
+    #[clap(subcommand)]
+    Maintenance(MaintenanceAction),
+
+    /// Switch the device to the low bandwidth profile (longer heartbeats,
+    /// no automatic log following, stronger compression, fewer metrics,
+    /// capped report logs) or back to normal
+    BandwidthProfile {
+        /// normal or low
+        profile: BandwidthProfile,
+    },
+
+    /// Reachability checks the device runs toward endpoints it depends on
+    #[clap(subcommand)]
+    Probes(ProbeAction),
+
+    /// Require a second operator's approval for shells, exec, file access,
+    /// package changes and deployments on the device (owners only)
+    #[clap(subcommand)]
+    Approval(ApprovalModeAction),
+
+    /// Show or set alert rules evaluated by the server on each heartbeat
+    Alerts {
+        /// Alert when the battery drops to this percentage while not charging
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+        low_battery: Option<u8>,
+
+        /// Alert when an expression starts to hold, as NAME=EXPR
+        /// (e.g. 'hot=battery.percent < 20 && battery.power_source == "battery"')
+        #[arg(long = "condition", action = clap::ArgAction::Append)]
+        conditions: Vec<String>,
+
+        /// Remove a named alert condition
+        #[arg(long = "remove-condition", action = clap::ArgAction::Append)]
+        remove_conditions: Vec<String>,
+
+        /// Remove all alert rules (applied before other options)
+        #[arg(long)]
+        clear: bool,
+    },
+
+    /// Rename the device. Deployments and history are kept
+    Rename {
+        /// New device name (letters, digits, '-', '_' and '.')
+        new_name: String,
+    },
+
+    /// Block until the device reaches a state. Exits 0 when reached and 2 on timeout
+    Wait {
+        /// Wait until the device is connected to its server
+        #[arg(long, required = true)]
+        online: bool,
+
+        /// Give up after this long (e.g. 90s, 10m, 1h)
+        #[arg(long, default_value = "5m", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+
     Audit {
         // rfc date like 2026-01-31 or 2026-01-31T13:00:00
         #[arg(long)]
@@ -344,6 +1316,20 @@ pub enum DeviceCommand {
         details: bool,
     },
 
+    /// Timeline of connects, disconnects, reboots, deployments, failures,
+    /// rollbacks and sessions
+    Events {
+        /// How far back (e.g. 30m, 24h, 7d) or a date like 2026-01-31
+        #[arg(long, default_value = "24h", value_parser = parse_since)]
+        since: String,
+        /// Date like 2026-01-31 or 2026-01-31T13:00:00Z
+        #[arg(long)]
+        until: Option<String>,
+        /// Show at most this many of the newest events
+        #[arg(long, default_value = "200")]
+        max: u32,
+    },
+
     /// Add a run spec to a deployment (defaults to active deployment)
     Deploy(DeployArgs),
 
@@ -356,12 +1342,212 @@ pub enum DeviceCommand {
 
     #[clap(subcommand)]
     Access(AccessAction),
+
+    /// List or terminate the shell and exec sessions open on the device
+    #[clap(subcommand)]
+    Sessions(SessionsAction),
+
+    /// Attach a debug server to a process on the device and forward its port
+    #[clap(subcommand)]
+    Debug(DebugAction),
+
+    /// List or download the core dumps of the device's runs
+    #[clap(subcommand)]
+    Cores(CoresAction),
+
+    /// Sample a process with perf and download a flamegraph of where it spends CPU time
+    Profile {
+        /// Process to sample
+        #[arg(long)]
+        pid: u32,
+        /// How long to sample (e.g. 30s, 2m)
+        #[arg(long, default_value = "30s", value_parser = parse_duration)]
+        duration: Duration,
+        /// Samples per second
+        #[arg(long, default_value_t = 99)]
+        frequency: u32,
+        /// svg: flamegraph; folded: stacks for flamegraph.pl, inferno or speedscope
+        #[arg(long, value_enum, default_value_t = ProfileFormat::Svg)]
+        format: ProfileFormat,
+        /// Output file (default: profile-<device>-<pid>.svg or .folded)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Show the device clock offset and NTP state, or force a resync
+    #[clap(subcommand)]
+    Time(TimeAction),
+
+    /// Show the firewall rules the runtime manages for ports runs expose
+    #[clap(subcommand)]
+    Firewall(FirewallAction),
+
+    /// Manage the pinned key of end-to-end encrypted streams
+    #[clap(subcommand)]
+    E2e(E2eAction),
+
+    /// Collect runtime logs, config, revisions, run states and connection
+    /// diagnostics into a .tar.gz for support; secrets are masked
+    SupportBundle {
+        /// Where to write the bundle (default: <device>-support-<time>.tar.gz)
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+
+        /// Limit of the uncompressed content in MiB; the oldest logs are left out first
+        #[arg(long, default_value_t = 64)]
+        max_mb: u64,
+    },
+}
+
+impl DeviceCommand {
+    /// Commands that only need a stream to the device and therefore work with --direct
+    fn supports_direct(&self) -> bool {
+        matches!(
+            self,
+            DeviceCommand::Shell { .. }
+                | DeviceCommand::Forward { .. }
+                | DeviceCommand::Proxy { .. }
+                | DeviceCommand::Docker { .. }
+                | DeviceCommand::Logs { .. }
+                | DeviceCommand::Metrics
+                | DeviceCommand::Facts { .. }
+                | DeviceCommand::Exec { .. }
+                | DeviceCommand::Test { .. }
+                | DeviceCommand::Gui { .. }
+                | DeviceCommand::Vnc { .. }
+                | DeviceCommand::Serial { .. }
+                | DeviceCommand::Sessions(_)
+                | DeviceCommand::Debug(_)
+                | DeviceCommand::Cores(_)
+                | DeviceCommand::Profile { .. }
+                | DeviceCommand::Time(_)
+                | DeviceCommand::Firewall(_)
+                | DeviceCommand::Pkg(_)
+                | DeviceCommand::SupportBundle { .. }
+        )
+    }
 }
 
 fn parse_role(s: &str) -> Result<Role, String> {
     Role::from_str(s)
 }
 
+/// Roles of a grant; `operator` names the editor role, which may use
+/// shells, exec and containers.
+fn parse_grant_role(s: &str) -> Result<Role, String> {
+    match s.to_lowercase().as_str() {
+        "operator" => Ok(Role::Editor),
+        _ => Role::from_str(s),
+    }
+}
+
+fn parse_severity(s: &str) -> Result<Severity, String> {
+    Severity::from_label(s).ok_or_else(|| format!("unknown severity '{}'", s))
+}
+
+fn parse_approval_status(s: &str) -> Result<ApprovalStatus, String> {
+    ApprovalStatus::from_label(s).ok_or_else(|| format!("unknown status '{}'", s))
+}
+
+fn parse_probe_target(s: &str) -> Result<ProbeTarget, String> {
+    ProbeTarget::parse(s)
+}
+
+fn parse_incident_state(s: &str) -> Result<IncidentState, String> {
+    IncidentState::from_label(s).ok_or_else(|| format!("unknown state '{}'", s))
+}
+
+fn parse_window(s: &str) -> Result<String, String> {
+    MaintenanceWindow::parse(s).map(|w| w.to_string())
+}
+
+/// A duration back from now, as an RFC3339 timestamp, or a date passed on as is.
+fn parse_since(s: &str) -> Result<String, String> {
+    if s.contains('-') {
+        return Ok(s.to_string());
+    }
+    let ago = parse_duration(s)?;
+    let ago = chrono::Duration::from_std(ago).map_err(|e| e.to_string())?;
+    Ok((chrono::Utc::now() - ago).to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+}
+
+fn parse_month(s: &str) -> Result<String, String> {
+    if is_month(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!("invalid month '{}', expected YYYY-MM", s))
+    }
+}
+
+/// Instance names end up in paths and the systemd unit name.
+fn parse_instance(s: &str) -> Result<String, String> {
+    let valid = (1..=32).contains(&s.len())
+        && s.bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_');
+    if !valid {
+        return Err("use 1-32 lowercase letters, digits, '-' or '_'".to_string());
+    }
+    Ok(s.to_string())
+}
+
+async fn list_devices(
+    filter: &[String],
+    format: ListFormat,
+    export: Option<ExportFormat>,
+) -> anyhow::Result<()> {
+    let filters = devices::parse_key_values(filter)?;
+    if let Some(export) = export {
+        let mut rows = devices::fleet_report().await?;
+        rows.retain(|row| devices::info_matches(&row.info, &filters));
+        match export {
+            ExportFormat::Csv => print!("{}", fleet_report_csv(&rows)),
+            ExportFormat::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+        }
+        return Ok(());
+    }
+    // pending registrations have no asset info yet
+    let with_requests = filters.is_empty() && matches!(format, ListFormat::Table);
+    let (mut devices, requests) = tokio::try_join!(devices::list_devices(), async {
+        match with_requests {
+            true => auth::list_auth_requests().await,
+            false => Ok(vec![]),
+        }
+    })?;
+    devices.retain(|d| devices::matches_info_filters(d, &filters));
+    match format {
+        ListFormat::Json => println!("{}", serde_json::to_string_pretty(&devices)?),
+        ListFormat::Geojson => println!(
+            "{}",
+            serde_json::to_string_pretty(&devices::to_geojson(&devices))?
+        ),
+        ListFormat::Table => tui::device::print_devices_table(&devices, &requests),
+    }
+    Ok(())
+}
+
+/// Exit with the wait outcome's code so CI pipelines can gate on it.
+fn exit_with(outcome: WaitOutcome) -> anyhow::Result<()> {
+    match outcome {
+        WaitOutcome::Reached => Ok(()),
+        _ => std::process::exit(outcome.exit_code()),
+    }
+}
+
+#[derive(Subcommand, Debug)]
+pub enum InfoAction {
+    /// Set fields as key=value. Keys other than location, owner_contact,
+    /// asset_tag, install_date and notes are stored as custom fields
+    Set {
+        #[arg(required = true)]
+        fields: Vec<String>,
+    },
+    /// Remove fields
+    Unset {
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+}
+
 #[derive(Subcommand, Debug)]
 pub enum AccessAction {
     Add {
@@ -380,10 +1566,171 @@ pub enum AccessAction {
     },
 }
 
+#[derive(Subcommand, Debug)]
+pub enum SessionsAction {
+    /// Show who is connected
+    List,
+    /// Terminate a session by its id
+    Kill { id: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum E2eAction {
+    /// Show the fingerprints of the pinned and the reported device key
+    Show,
+    /// Pin a key obtained from the device itself (`m87 local e2e-key`)
+    Pin { key: String },
+    /// Forget the pinned key, e.g. after the device was reinstalled
+    Forget,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PkgAction {
+    /// Install packages (the package index is refreshed first)
+    Install {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Remove packages
+    Remove {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Upgrade the given packages, or all installed packages
+    Upgrade {
+        packages: Vec<String>,
+        /// Only show what would change
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MaintenanceAction {
+    /// Stop applying patch policies on the device
+    On {
+        /// Why the device is in maintenance, shown in device listings
+        #[arg(long)]
+        reason: Option<String>,
+    },
+    /// Apply patch policies again
+    Off,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ProbeAction {
+    /// Show the probes with their last result and uptime over the last day
+    List {
+        /// Print the probes as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add a probe, or replace the one with the same name
+    Add {
+        name: String,
+        /// ping:HOST, tcp:HOST:PORT or an http(s) URL
+        #[arg(value_parser = parse_probe_target)]
+        target: ProbeTarget,
+        /// HTTP status that counts as up; any 2xx by default
+        #[arg(long)]
+        expect_status: Option<u16>,
+        /// How often to check (e.g. 30s, 5m)
+        #[arg(long, default_value = "60s", value_parser = parse_duration)]
+        interval: Duration,
+        /// Give up on a check after this long
+        #[arg(long, default_value = "5s", value_parser = parse_duration)]
+        timeout: Duration,
+        /// Failures in a row before the probe is down and raises an alert
+        #[arg(long, default_value_t = 3)]
+        fail_after: u32,
+    },
+    Remove {
+        name: String,
+    },
+    /// Show reported results, newest first
+    Results {
+        /// Only results of this probe
+        name: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApprovalModeAction {
+    On,
+    Off,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CoresAction {
+    /// List core dumps, newest first
+    List,
+    /// Download and decompress a core dump
+    Get {
+        /// Core dump ID, or a unique prefix of it
+        id: String,
+        /// Output file (default: core.<executable>.<pid>)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DebugAction {
+    /// Start gdbserver (or debugpy) attached to a process, forward its port and print the
+    /// local connection string. Stops the debug server on Ctrl+C
+    Attach {
+        /// Process to debug
+        #[arg(long)]
+        pid: u32,
+        /// Use gdbserver, for native code (the default)
+        #[arg(long, conflicts_with = "python")]
+        gdb: bool,
+        /// Use debugpy, for Python processes
+        #[arg(long)]
+        python: bool,
+        /// Port of the debug server on the device (default: 2345 for gdb, 5678 for debugpy)
+        #[arg(long)]
+        port: Option<u16>,
+        /// Local port to forward to (default: the device port)
+        #[arg(long)]
+        local_port: Option<u16>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TimeAction {
+    /// Show the clock offset to this machine and whether NTP is synchronized
+    Status,
+    /// Force chrony or systemd-timesyncd to resync the clock now
+    Sync,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FirewallAction {
+    /// Show the backend, the ports opened for runs and what is always allowed
+    Status,
+}
+
 #[derive(Parser, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct DeployArgs {
+    #[command(subcommand)]
+    pub command: Option<DeploySubcommand>,
+
     /// File to add (docker-compose.yml or run spec yaml)
-    pub file: PathBuf,
+    #[arg(required = true)]
+    pub file: Option<PathBuf>,
 
     /// Spec type (auto detects by default)
     #[arg(long, value_enum, default_value_t = SpecType::Auto)]
@@ -398,6 +1745,67 @@ pub struct DeployArgs {
     pub deployment_id: Option<String>,
 }
 
+#[derive(Subcommand, Debug)]
+pub enum DeploySubcommand {
+    /// Check a deploy file without deploying it: YAML schema, `when` conditions and template
+    /// placeholders. Exits 1 if the file is invalid.
+    Validate {
+        file: PathBuf,
+
+        /// Spec type (auto detects by default)
+        #[arg(long, value_enum, default_value_t = SpecType::Auto)]
+        r#type: SpecType,
+    },
+
+    /// Show what deploying a file would change on the device: runs added, changed or removed,
+    /// steps that would run and files that would be written. Nothing is executed.
+    Plan {
+        file: PathBuf,
+
+        /// Spec type (auto detects by default)
+        #[arg(long, value_enum, default_value_t = SpecType::Auto)]
+        r#type: SpecType,
+
+        /// Optional display name for the run spec
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Print the plan as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Block until a deployment succeeds or fails.
+    /// Exits 0 on success, 1 on failure and 2 on timeout.
+    Wait {
+        /// Deployment to wait for. Defaults to the active deployment
+        #[arg(long)]
+        revision: Option<String>,
+
+        /// Give up after this long (e.g. 90s, 10m, 1h)
+        #[arg(long, default_value = "10m", value_parser = parse_duration)]
+        timeout: Duration,
+    },
+
+    /// List the files a run's steps uploaded through their `outputs`, or download one
+    Artifacts {
+        /// Run to list the artifacts of
+        run: String,
+
+        /// Download this artifact, by path or file name
+        #[arg(long)]
+        get: Option<String>,
+
+        /// Where to save the download. Defaults to its file name in the current directory
+        #[arg(long, short, requires = "get")]
+        output: Option<PathBuf>,
+
+        /// Deployment the run belongs to. Defaults to the active deployment
+        #[arg(long)]
+        revision: Option<String>,
+    },
+}
+
 #[derive(Parser, Debug)]
 pub struct UndeployArgs {
     /// File path or run spec name
@@ -410,7 +1818,8 @@ pub struct UndeployArgs {
 
 #[derive(Subcommand, Debug)]
 pub enum DeploymentCommand {
-    /// List deployments for this device
+    /// List deployments for this device, with the commit and author they came from
+    #[command(alias = "history")]
     List,
 
     /// Create a new deployment
@@ -454,6 +1863,14 @@ pub enum DeploymentCommand {
         /// Show logs of the steps
         #[arg(long)]
         logs: bool,
+
+        /// Only this run, with its steps
+        #[arg(long)]
+        run: Option<String>,
+
+        /// Leave out the steps of the runs
+        #[arg(long, conflicts_with = "run")]
+        no_steps: bool,
     },
 
     /// Clone an existing deployment into a new one
@@ -467,6 +1884,20 @@ pub enum DeploymentCommand {
 
     /// Update a deployment (remove/replace/move/rename specs; change name)
     Update(DeploymentUpdateArgs),
+
+    /// Set or remove free-form annotations on a deployment
+    Annotate {
+        /// Deployment to annotate. Defaults to the active deployment
+        #[arg(long)]
+        deployment_id: Option<String>,
+
+        /// Annotations to set as key=value
+        annotations: Vec<String>,
+
+        /// Annotation keys to remove
+        #[arg(long, action = clap::ArgAction::Append)]
+        remove: Vec<String>,
+    },
 }
 
 #[cfg(feature = "runtime")]
@@ -484,7 +1915,7 @@ enum RuntimeCommands {
     },
 
     Logout,
-    /// Run the runtime daemon (blocking, used by systemd service)
+    /// Run the runtime daemon (blocking, used by systemd service and containers)
     Run {
         /// Organization ID to register runtime under
         #[arg(long = "org-id", conflicts_with = "email")]
@@ -493,6 +1924,14 @@ enum RuntimeCommands {
         /// Email address to register runtime under
         #[arg(long, conflicts_with = "org_id")]
         email: Option<String>,
+
+        /// Log JSON lines to stdout, for container log collectors
+        #[arg(long)]
+        foreground: bool,
+
+        /// Serve /healthz and /readyz for orchestrators on this address (e.g. 0.0.0.0:8787)
+        #[arg(long)]
+        health_addr: Option<std::net::SocketAddr>,
     },
 
     /// Start the runtime service now (requires sudo)
@@ -533,6 +1972,15 @@ enum RuntimeCommands {
         /// Email address to register runtime under
         #[arg(long, conflicts_with = "org_id")]
         email: Option<String>,
+
+        /// Token from m87 install-command; the device is registered without
+        /// approval
+        #[arg(long)]
+        enrollment_token: Option<String>,
+
+        /// Server the runtime registers with, e.g. https://m87.internal
+        #[arg(long)]
+        server_url: Option<String>,
     },
 
     /// Remove auto-start on boot (requires sudo)
@@ -542,8 +1990,92 @@ enum RuntimeCommands {
         now: bool,
     },
 
+    /// Stop the runtime service and remove it (requires sudo)
+    Uninstall,
+
     /// Show local runtime service status
     Status,
+
+    /// Inspect the queue of deploy reports waiting for the server
+    #[command(subcommand)]
+    Events(EventsCommands),
+
+    /// Delete old logs, queued reports and orphaned workdirs beyond the configured disk limits
+    Gc,
+
+    /// Print a Docker Compose file or Kubernetes DaemonSet that runs the runtime in a container
+    Manifest {
+        /// Manifest format: compose or kubernetes
+        #[arg(long, value_enum, default_value_t = ManifestFormat::Compose)]
+        format: ManifestFormat,
+
+        /// Runtime image
+        #[arg(long, default_value = crate::device::container::DEFAULT_IMAGE)]
+        image: String,
+
+        /// Organization ID to register runtime under
+        #[arg(long = "org-id", conflicts_with = "email")]
+        org_id: Option<String>,
+
+        /// Email address to register runtime under
+        #[arg(long, conflicts_with = "org_id")]
+        email: Option<String>,
+
+        /// Port of the health endpoint
+        #[arg(long, default_value_t = crate::device::health::DEFAULT_HEALTH_PORT)]
+        health_port: u16,
+    },
+}
+
+#[cfg(feature = "runtime")]
+#[derive(Subcommand)]
+enum EventsCommands {
+    /// List undelivered and dead-lettered reports
+    Ls,
+}
+
+#[cfg(feature = "runtime")]
+#[derive(Subcommand)]
+enum LocalCommands {
+    /// Show the stored deployment and the local state of each run
+    Status,
+    /// Show step and observe logs that have not been delivered to the server yet
+    Logs {
+        /// Only show logs of this run
+        run_id: Option<String>,
+    },
+    /// Show current system metrics of this device
+    Metrics,
+    /// Queue a run to be executed again once the runtime picks it up
+    Rerun {
+        run_id: String,
+        /// Write files of an immutable run that were changed on the device
+        /// again instead of refusing to start it
+        #[arg(long)]
+        accept_changes: bool,
+    },
+    /// Trade the recovery code printed at install for a temporary key that
+    /// authenticates direct LAN connections, for when the server is
+    /// unreachable and credentials are lost. Prints the next recovery code
+    Unlock {
+        /// Recovery code; read from stdin if omitted
+        code: Option<String>,
+        /// How long the local key works
+        #[arg(long, default_value = "8h", value_parser = parse_duration)]
+        ttl: Duration,
+    },
+    /// Replace the recovery code with a new one and print it. Needs root or
+    /// the current code
+    RecoveryCode {
+        /// Current recovery code; read from stdin if omitted and not root
+        current: Option<String>,
+    },
+    /// Print this device's end-to-end key, to pin it out of band with
+    /// `m87 <device> e2e pin`
+    E2eKey,
+    /// Print the certificate fingerprint of direct LAN connections, to pin
+    /// with `m87 --direct --fingerprint`
+    LanFingerprint,
 }
 
 /// Hidden internal commands for privileged operations (not shown in help)
@@ -586,12 +2118,39 @@ enum InternalCommands {
         #[arg(long)]
         now: bool,
     },
+
+    /// Stop and remove the runtime service (must be run as root)
+    RuntimeUninstallPrivileged,
+
+    /// core_pattern handler: spool the core on stdin of a crashed process (run by the kernel)
+    CoreDump {
+        /// Directory the runtime picks spooled cores up from
+        #[arg(long)]
+        spool: PathBuf,
+        pid: u32,
+        signal: i32,
+        /// Unix time of the crash
+        time: u64,
+    },
 }
 
 #[derive(Subcommand)]
 enum DevicesCommands {
     /// List all accessible devices
-    List,
+    List {
+        /// Only show devices whose asset info matches key=value (substring, case-insensitive).
+        /// Can be used multiple times
+        #[arg(long, action = clap::ArgAction::Append)]
+        filter: Vec<String>,
+
+        /// Output format: table, json (including asset info) or geojson (for maps)
+        #[arg(long, value_enum, default_value_t = ListFormat::Table)]
+        format: ListFormat,
+
+        /// Print the inventory report instead (health, last seen, active revision)
+        #[arg(long, value_enum, conflicts_with = "format")]
+        export: Option<ExportFormat>,
+    },
 
     /// Show detailed information about a specific device
     Show {
@@ -610,6 +2169,29 @@ enum DevicesCommands {
         /// Device name or ID
         device: String,
     },
+
+    /// Merge a duplicate device into another, moving its deployments, reports
+    /// and audit logs over and deleting the duplicate (requires admin on both)
+    Merge {
+        /// Duplicate device name or ID, deleted after the merge
+        duplicate: String,
+
+        /// Device that keeps the merged history
+        #[arg(long)]
+        into: String,
+    },
+
+    /// Switch the bandwidth profile of every device whose asset info
+    /// matches all filters
+    BandwidthProfile {
+        /// normal or low
+        profile: BandwidthProfile,
+
+        /// key=value asset info filter (substring, case-insensitive).
+        /// Can be used multiple times
+        #[arg(long, required = true, action = clap::ArgAction::Append)]
+        filter: Vec<String>,
+    },
 }
 
 pub async fn cli() -> anyhow::Result<()> {
@@ -621,23 +2203,48 @@ pub async fn cli() -> anyhow::Result<()> {
     }
 
     let cli = Cli::parse();
+    // before anything reads config or data directories
+    if let Some(name) = &cli.instance {
+        crate::config::set_instance(name.clone());
+    }
     // if is runtime run aso set to verbose
-    let is_run = match &cli.command {
+    let (is_run, json_logs) = match &cli.command {
         #[cfg(feature = "runtime")]
-        Commands::Runtime(RuntimeCommands::Run { .. }) => true,
-        _ => false,
+        Commands::Runtime(RuntimeCommands::Run { foreground, .. }) => (true, *foreground),
+        _ => (false, false),
     };
+    // the runtime also logs to a size-capped file when configured
+    #[cfg(feature = "runtime")]
+    let log_file = is_run.then(crate::device::gc::runtime_log_file).flatten();
+    #[cfg(not(feature = "runtime"))]
+    let log_file = None;
     if cli.verbose || is_run {
-        init_logging("info");
+        init_logging("info", log_file, json_logs);
     } else {
-        init_logging("warn");
+        init_logging("warn", log_file, json_logs);
     }
     set_tls_provider();
 
+    let direct = cli.direct;
+    let device_key = cli.device_key;
+    let fingerprint = cli.fingerprint;
+    let notices = shows_notices(&cli.command);
+
     match cli.command {
-        Commands::Login => {
+        Commands::Init => {
+            crate::init::run(Cli::command()).await?;
+        }
+
+        Commands::Completion { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "m87", &mut std::io::stdout());
+        }
+
+        Commands::Login { server, email } => {
             tracing::info!("Logging in...");
-            auth::login_cli().await?;
+            match server.zip(email) {
+                Some((server, email)) => auth::login_password(&server, &email).await?,
+                None => auth::login_cli().await?,
+            }
             tracing::info!("Logged in successfully");
         }
 
@@ -647,6 +2254,27 @@ pub async fn cli() -> anyhow::Result<()> {
             tracing::info!("Logged out successfully");
         }
 
+        Commands::InstallCommand {
+            org_id,
+            personal,
+            expires,
+            uses,
+            json,
+        } => {
+            let command = crate::enrollment::install_command(
+                org_id,
+                personal,
+                expires.map(|d| d.as_secs()),
+                uses,
+            )
+            .await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&command)?);
+            } else {
+                tui::device::print_install_command(&command);
+            }
+        }
+
         Commands::Ssh(cmd) => match cmd {
             SshCommands::Enable => {
                 tracing::info!("Enabling SSH...");
@@ -701,17 +2329,29 @@ pub async fn cli() -> anyhow::Result<()> {
                 let sysinfo = util::system_info::get_system_info().await?;
                 auth::register_device(owner_scope, sysinfo).await?;
                 tracing::info!("Device registered as runtime successfully");
+                if let Some(code) = crate::device::recovery::ensure_code()? {
+                    tui::local::print_recovery_code(&code);
+                }
             }
             RuntimeCommands::Logout => {
                 auth::logout_device().await?;
                 tracing::info!("Logged out successfully");
             }
-            RuntimeCommands::Run { org_id, email } => {
+            RuntimeCommands::Run {
+                org_id,
+                email,
+                foreground: _,
+                health_addr,
+            } => {
                 save_owner_if_provided(org_id, email)?;
-                crate::runtime::run().await?;
+                crate::runtime::run(health_addr).await?;
             }
             RuntimeCommands::Start { org_id, email } => {
                 save_owner_if_provided(org_id, email)?;
+                // before start, which may re-exec under sudo
+                if let Some(code) = crate::device::recovery::ensure_code()? {
+                    tui::local::print_recovery_code(&code);
+                }
                 crate::runtime::start().await?;
             }
             RuntimeCommands::Stop => {
@@ -721,18 +2361,152 @@ pub async fn cli() -> anyhow::Result<()> {
                 save_owner_if_provided(org_id, email)?;
                 crate::runtime::restart().await?;
             }
-            RuntimeCommands::Enable { now, org_id, email } => {
+            RuntimeCommands::Enable {
+                now,
+                org_id,
+                email,
+                enrollment_token,
+                server_url,
+            } => {
                 save_owner_if_provided(org_id, email)?;
+                if enrollment_token.is_some() || server_url.is_some() {
+                    let mut cfg = Config::load()?;
+                    cfg.enrollment_token = enrollment_token.or(cfg.enrollment_token);
+                    cfg.runtime_server_url = server_url.or(cfg.runtime_server_url);
+                    cfg.save()?;
+                }
+                if let Some(code) = crate::device::recovery::ensure_code()? {
+                    tui::local::print_recovery_code(&code);
+                }
                 crate::runtime::enable(now).await?;
             }
             RuntimeCommands::Disable { now } => {
                 crate::runtime::disable(now).await?;
             }
+            RuntimeCommands::Uninstall => {
+                crate::runtime::uninstall().await?;
+            }
             RuntimeCommands::Status => {
                 crate::runtime::status().await?;
             }
+            RuntimeCommands::Events(EventsCommands::Ls) => {
+                let events = crate::device::deployment_manager::list_events().await?;
+                tui::local::print_events(&events);
+            }
+            RuntimeCommands::Gc => {
+                let limits = Config::load()?.disk_limits;
+                let reclaimed = crate::device::gc::run(&limits).await?;
+                tui::local::print_reclaimed(&reclaimed);
+            }
+            RuntimeCommands::Manifest {
+                format,
+                image,
+                org_id,
+                email,
+                health_port,
+            } => {
+                let opts = ManifestOptions {
+                    image,
+                    org_id,
+                    email,
+                    health_port,
+                };
+                print!("{}", crate::device::container::manifest(format, &opts));
+            }
         },
 
+        #[cfg(feature = "runtime")]
+        Commands::Local(cmd) => {
+            use crate::device::deployment_manager as dm;
+            match cmd {
+                LocalCommands::Status => {
+                    let states = dm::local_run_states()?;
+                    let events = dm::list_queued_events().await?;
+                    let ops = dm::list_local_ops().await?;
+                    tui::local::print_local_status(states.as_ref(), events.len(), &ops);
+                }
+                LocalCommands::Logs { run_id } => {
+                    let events = dm::list_queued_events().await?;
+                    tui::local::print_local_logs(&events, run_id.as_deref());
+                }
+                LocalCommands::Metrics => {
+                    let metrics = device::system_metrics::collect_system_metrics().await?;
+                    tui::local::print_local_metrics(&metrics);
+                }
+                LocalCommands::Rerun {
+                    run_id,
+                    accept_changes,
+                } => {
+                    let known = dm::local_run_states()?
+                        .map(|(_, runs)| runs.iter().any(|(spec, _)| spec.id == run_id))
+                        .unwrap_or(false);
+                    if !known {
+                        bail!("Run '{}' is not part of the stored deployment", run_id);
+                    }
+                    dm::enqueue_local_op(dm::LocalOp::Rerun {
+                        run_id: run_id.clone(),
+                        accept_changes,
+                    })
+                    .await?;
+                    tracing::info!("Queued rerun of {}", run_id);
+                }
+                LocalCommands::Unlock { code, ttl } => {
+                    let code = match code {
+                        Some(code) => code,
+                        None => {
+                            print!("Recovery code: ");
+                            std::io::Write::flush(&mut std::io::stdout())?;
+                            let mut input = String::new();
+                            std::io::stdin().read_line(&mut input)?;
+                            input
+                        }
+                    };
+                    let mut config = Config::load()?;
+                    let enabled_direct = !config.lan_direct;
+                    let unlocked = crate::device::recovery::unlock(&code, ttl, enabled_direct)?;
+                    if enabled_direct {
+                        config.lan_direct = true;
+                        config.save()?;
+                    }
+                    let fingerprint = crate::device::lan::lan_cert_fingerprint()?;
+                    tui::local::print_unlocked(
+                        &unlocked,
+                        &fingerprint,
+                        config.lan_direct_port,
+                        enabled_direct,
+                    );
+                }
+                LocalCommands::RecoveryCode { current } => {
+                    let current = match current {
+                        Some(code) => Some(code),
+                        None if crate::util::unix::is_root() => None,
+                        None => {
+                            print!("Current recovery code: ");
+                            std::io::Write::flush(&mut std::io::stdout())?;
+                            let mut input = String::new();
+                            std::io::stdin().read_line(&mut input)?;
+                            Some(input)
+                        }
+                    };
+                    let code = crate::device::recovery::replace_code(current.as_deref())?;
+                    tui::local::print_recovery_code(&code);
+                }
+                LocalCommands::E2eKey => {
+                    let key = e2e::device_public_key()?;
+                    println!("{}", key);
+                    println!("fingerprint {}", e2e::fingerprint(&key));
+                }
+                LocalCommands::LanFingerprint => {
+                    println!("{}", crate::device::lan::lan_cert_fingerprint()?);
+                }
+            }
+        }
+
+        #[cfg(unix)]
+        Commands::ConnectionHelper { host, device } => {
+            crate::streams::warm::serve(&host, &device).await?;
+        }
+
         #[cfg(feature = "runtime")]
         Commands::Internal(cmd) => match cmd {
             InternalCommands::RuntimeSetupPrivileged {
@@ -759,13 +2533,26 @@ pub async fn cli() -> anyhow::Result<()> {
             InternalCommands::RuntimeDisablePrivileged { now } => {
                 crate::runtime::internal_disable_privileged(now).await?;
             }
+            InternalCommands::RuntimeUninstallPrivileged => {
+                crate::runtime::internal_uninstall_privileged().await?;
+            }
+            InternalCommands::CoreDump {
+                spool,
+                pid,
+                signal,
+                time,
+            } => {
+                device::core_dumps::capture(&spool, pid, signal, time)?;
+            }
         },
 
         Commands::Devices(cmd) => match cmd {
-            DevicesCommands::List => {
-                let devices = devices::list_devices().await?;
-                let requests = auth::list_auth_requests().await?;
-                tui::device::print_devices_table(&devices, &requests);
+            DevicesCommands::List {
+                filter,
+                format,
+                export,
+            } => {
+                list_devices(&filter, format, export).await?;
             }
             DevicesCommands::Show { device } => {
                 eprintln!("Error: 'devices show' command is not yet implemented");
@@ -782,12 +2569,56 @@ pub async fn cli() -> anyhow::Result<()> {
                 auth::reject_auth_request(&device).await?;
                 tracing::info!("Device rejected successfully");
             }
+            DevicesCommands::Merge { duplicate, into } => {
+                let merged = devices::merge_devices(&duplicate, &into).await?;
+                println!("Merged {} into {} ({})", duplicate, merged.name, merged.short_id);
+            }
+            DevicesCommands::BandwidthProfile { profile, filter } => {
+                let filters = devices::parse_key_values(&filter)?;
+                let switched = devices::set_bandwidth_profile_matching(&filters, profile).await?;
+                if switched.is_empty() {
+                    println!("No devices match the filters");
+                }
+                for name in switched {
+                    println!("{} uses the {} bandwidth profile", name, profile);
+                }
+            }
         },
 
+        Commands::Alias { assignment, remove } => {
+            if let Some(alias) = remove {
+                devices::remove_alias(&alias)?;
+                println!("Removed alias '{}'", alias);
+            } else if let Some(assignment) = assignment {
+                let (alias, device) = assignment
+                    .split_once('=')
+                    .ok_or_else(|| anyhow::anyhow!("Expected NAME=DEVICE, got '{}'", assignment))?;
+                if Cli::command().find_subcommand(alias).is_some() {
+                    bail!("'{}' is an m87 command and cannot be used as an alias", alias);
+                }
+                devices::set_alias(alias, device)?;
+                println!("{} -> {}", alias, device);
+            } else {
+                let cfg = Config::load()?;
+                if cfg.device_aliases.is_empty() {
+                    println!("No aliases defined");
+                }
+                for (alias, device) in &cfg.device_aliases {
+                    println!("{} -> {}", alias, device);
+                }
+            }
+        }
+
+        Commands::Discover { timeout } => {
+            let found = crate::util::lan::discover(std::time::Duration::from_secs(timeout)).await?;
+            tui::device::print_lan_devices(&found);
+        }
+
         Commands::Version => {
             tracing::info!("[done]");
             println!("Version: {}", env!("CARGO_PKG_VERSION"));
             println!("Build: {}", env!("GIT_COMMIT"));
+            println!("Protocol: v{}", m87_shared::protocol::PROTOCOL_VERSION);
             println!("Rust: {}", env!("RUSTC_VERSION"));
             println!(
                 "Platform: {}/{}",
@@ -821,10 +2652,19 @@ pub async fn cli() -> anyhow::Result<()> {
                 device::fs::sync(&source, &dest, delete, dry_run, &exclude).await?;
             }
         }
-        Commands::Ls { path } => {
-            let resp = device::fs::list(&path).await?;
-            tui::fs::print_dir_entries(&resp);
-        }
+        Commands::Ls {
+            path,
+            filter,
+            format,
+            export,
+        } => match path {
+            Some(_) if export.is_some() => bail!("--export lists devices and takes no path"),
+            Some(path) => {
+                let resp = device::fs::list(&path).await?;
+                tui::fs::print_dir_entries(&resp);
+            }
+            None => list_devices(&filter, format, export).await?,
+        },
         Commands::Device(args) => {
             let parsed = match DeviceRoot::try_parse_from(
                 std::iter::once("m87").chain(args.iter().map(|s| s.as_str())),
@@ -832,6 +2672,12 @@ pub async fn cli() -> anyhow::Result<()> {
                 Ok(p) => p,
                 Err(e) => e.exit(), // Clean exit for help/version, error message for parse errors
             };
+            if direct {
+                if !parsed.command.supports_direct() {
+                    bail!("This command is not available with --direct");
+                }
+                crate::util::lan::enable_direct(&parsed.device, device_key, fingerprint)?;
+            }
             handle_device_command(parsed).await?;
         }
 
@@ -842,6 +2688,22 @@ pub async fn cli() -> anyhow::Result<()> {
                 make87_api_url,
                 make87_app_url,
                 trust_invalid_server_cert,
+                ca_bundle,
+                lan_discovery,
+                lan_direct,
+                lan_direct_port,
+                location_gpsd,
+                location_command,
+                clipboard,
+                predictive_echo,
+                end_to_end,
+                compression,
+                warm_connection_secs,
+                proxy,
+                no_proxy,
+                core_dumps,
+                flow_metrics,
+                firewall,
             } => {
                 let mut cfg = Config::load().context("Failed to load config")?;
 
@@ -865,18 +2727,388 @@ pub async fn cli() -> anyhow::Result<()> {
                     cfg.trust_invalid_server_cert = trust;
                 }
 
-                cfg.save().context("Failed to save config")?;
-                tracing::info!("Config updated");
+                if let Some(path) = ca_bundle {
+                    cfg.ca_bundle = Some(PathBuf::from(path)).filter(|p| !p.as_os_str().is_empty());
+                }
+
+                if let Some(enabled) = lan_discovery {
+                    cfg.lan_discovery = enabled;
+                }
+
+                if let Some(enabled) = lan_direct {
+                    cfg.lan_direct = enabled;
+                }
+
+                if let Some(port) = lan_direct_port {
+                    cfg.lan_direct_port = port;
+                }
+
+                if let Some(addr) = location_gpsd {
+                    cfg.location_gpsd = Some(addr).filter(|a| !a.is_empty());
+                }
+
+                if let Some(cmd) = location_command {
+                    cfg.location_command = Some(cmd).filter(|c| !c.is_empty());
+                }
+
+                if let Some(policy) = clipboard {
+                    cfg.clipboard = policy;
+                }
+
+                if let Some(enabled) = predictive_echo {
+                    cfg.predictive_echo = enabled;
+                }
+
+                if let Some(enabled) = end_to_end {
+                    cfg.end_to_end = enabled;
+                }
+
+                if let Some(enabled) = compression {
+                    cfg.compression = enabled;
+                }
+
+                if let Some(secs) = warm_connection_secs {
+                    cfg.warm_connection_secs = secs;
+                }
+
+                if let Some(url) = proxy {
+                    cfg.proxy.url = Some(url).filter(|u| !u.is_empty());
+                }
+
+                if let Some(rules) = no_proxy {
+                    cfg.proxy.no_proxy = rules
+                        .split(',')
+                        .map(|r| r.trim().to_string())
+                        .filter(|r| !r.is_empty())
+                        .collect();
+                }
+
+                if let Some(enabled) = core_dumps {
+                    cfg.core_dumps = enabled;
+                }
+
+                if let Some(enabled) = flow_metrics {
+                    cfg.flow_metrics = enabled;
+                }
+
+                if let Some(backend) = firewall {
+                    cfg.firewall = backend;
+                }
+
+                cfg.save().context("Failed to save config")?;
+                tracing::info!("Config updated");
+            }
+            ConfigCommands::Show => {
+                let cfg = Config::load().context("Failed to load config")?;
+                tracing::info!("Config laoded");
+                println!("{:#?}", cfg);
+            }
+            ConfigCommands::File => {
+                let path = Config::config_file_path().context("Failed to get config path")?;
+                tracing::info!("Config path loaded");
+                println!("{:#?}", path);
+            }
+        },
+
+        Commands::Ci(cmd) => match cmd {
+            CiCommands::Deploy {
+                device,
+                file,
+                r#type,
+                name,
+                wait,
+                timeout,
+            } => {
+                let revision_id = ci::deploy(&device, &file, r#type, name.as_deref()).await?;
+                tracing::info!("Activated deployment {} on {}", revision_id, device);
+                if wait {
+                    let outcome = ci::wait(&device, &revision_id, timeout).await?;
+                    match outcome {
+                        WaitOutcome::Reached => {
+                            tracing::info!("Deployment {} succeeded", revision_id)
+                        }
+                        WaitOutcome::Failed => tracing::error!("Deployment {} failed", revision_id),
+                        WaitOutcome::TimedOut => {
+                            tracing::error!("Timed out waiting for deployment {}", revision_id)
+                        }
+                    }
+                    exit_with(outcome)?;
+                }
+            }
+            CiCommands::StatusComment {
+                device,
+                revision,
+                step_summary,
+            } => {
+                let markdown = ci::status_comment(&device, revision, step_summary).await?;
+                println!("{}", markdown);
+            }
+        },
+
+        Commands::Admin(AdminCommands::DbStats {
+            server,
+            indexes,
+            json,
+        }) => {
+            let stats = admin::db_stats(server).await?;
+            if json {
+                let stats: Vec<_> = stats
+                    .into_iter()
+                    .map(|(server, collections)| {
+                        serde_json::json!({ "server": server, "collections": collections })
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&stats)?);
+            } else {
+                tui::admin::print_db_stats(&stats, indexes);
+            }
+        }
+
+        #[cfg(feature = "runtime")]
+        Commands::Admin(AdminCommands::Bench {
+            server,
+            owner,
+            agents,
+            clients,
+            streams,
+            payload_kb,
+            json,
+        }) => {
+            let opts = bench::BenchOptions {
+                server_url: server,
+                owner,
+                agents,
+                clients,
+                streams,
+                payload_bytes: payload_kb * 1024,
+            };
+            let report = bench::run(&opts).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                bench::print_report(&report);
+            }
+        }
+
+        Commands::Dev(DevCommands::Sync {
+            source,
+            dest,
+            restart,
+            exclude,
+        }) => {
+            device::fs::dev_sync(&source, &dest, restart.as_deref(), &exclude).await?;
+        }
+
+        #[cfg(feature = "runtime")]
+        Commands::Dev(DevCommands::Simulate {
+            count,
+            server,
+            owner,
+            keep,
+        }) => {
+            let opts = sim::SimulateOptions {
+                server_url: server,
+                owner,
+                count,
+                keep,
+            };
+            sim::simulate(&opts).await?;
+        }
+
+        Commands::Approvals(cmd) => match cmd {
+            ApprovalCommands::List {
+                status,
+                all,
+                limit,
+                json,
+            } => {
+                let status = match all {
+                    true => None,
+                    false => Some(status.unwrap_or(ApprovalStatus::Pending)),
+                };
+                let requests = approvals::list(status, limit).await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&requests)?);
+                } else {
+                    tui::approvals::print_approvals(&requests);
+                }
+            }
+            ApprovalCommands::Approve { id, comment } => {
+                let request = approvals::decide(&id, true, comment).await?;
+                println!(
+                    "Approved {} on {} for {}; it can be used once until {}",
+                    request.operation,
+                    request.device_name,
+                    request.requested_by,
+                    request.expires_at
+                );
+            }
+            ApprovalCommands::Reject { id, comment } => {
+                let request = approvals::decide(&id, false, comment).await?;
+                println!(
+                    "Rejected {} on {} for {}",
+                    request.operation, request.device_name, request.requested_by
+                );
+            }
+        },
+
+        Commands::Incidents(cmd) => match cmd {
+            IncidentCommands::List {
+                state,
+                all,
+                limit,
+                json,
+            } => {
+                let incidents = incidents::list(state, all, limit).await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&incidents)?);
+                } else {
+                    tui::incidents::print_incidents(&incidents);
+                }
+            }
+            IncidentCommands::Show { id, json } => {
+                let incident = incidents::get(&id).await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&incident)?);
+                } else {
+                    tui::incidents::print_incident(&incident);
+                }
+            }
+            IncidentCommands::Ack { id, note } => {
+                let incident = incidents::update(&id, IncidentState::Acknowledged, note).await?;
+                println!(
+                    "Acknowledged incident {} on {}",
+                    incident.id, incident.device_name
+                );
+            }
+            IncidentCommands::Resolve { id, note } => {
+                let incident = incidents::update(&id, IncidentState::Resolved, note).await?;
+                println!(
+                    "Resolved incident {} on {}",
+                    incident.id, incident.device_name
+                );
+            }
+        },
+
+        Commands::Deploy(cmd) => match cmd {
+            DeployCommands::Rollout {
+                file,
+                devices: device_names,
+                filter,
+                r#type,
+                name,
+                waves,
+                failure_threshold,
+                soak,
+                deploy_timeout,
+                no_rollback,
+                json,
+            } => {
+                let targets = rollouts::RolloutTargets {
+                    devices: device_names,
+                    filters: devices::parse_key_values(&filter)?,
+                };
+                let overrides = rollouts::PolicyOverrides {
+                    waves,
+                    failure_threshold_percent: failure_threshold,
+                    soak_secs: soak.map(|d| d.as_secs()),
+                    deploy_timeout_secs: deploy_timeout.map(|d| d.as_secs()),
+                    no_rollback,
+                };
+                let rollout =
+                    rollouts::start(&file, r#type, name.as_deref(), &targets, &overrides).await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&rollout)?);
+                } else {
+                    println!(
+                        "Started rollout {} of {} to {} devices in {} waves",
+                        rollout.id,
+                        rollout.revision_id,
+                        rollout.devices.len(),
+                        rollout.waves
+                    );
+                    println!("Follow it with: m87 deploy status --rollout {}", rollout.id);
+                }
+            }
+            DeployCommands::Status {
+                rollout,
+                all,
+                limit,
+                json,
+            } => match rollout {
+                Some(id) => {
+                    let rollout = rollouts::get(&id).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&rollout)?);
+                    } else {
+                        tui::rollouts::print_rollout(&rollout);
+                    }
+                }
+                None => {
+                    let list = rollouts::list(all, limit).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&list)?);
+                    } else {
+                        tui::rollouts::print_rollouts(&list);
+                    }
+                }
+            },
+            DeployCommands::Halt { id, note } => {
+                let rollout = rollouts::update(&id, RolloutAction::Halt, note).await?;
+                println!("Halted rollout {}", rollout.id);
+            }
+            DeployCommands::Resume { id, note } => {
+                let rollout = rollouts::update(&id, RolloutAction::Resume, note).await?;
+                println!(
+                    "Resumed rollout {} at wave {} of {}",
+                    rollout.id,
+                    rollout.current_wave + 1,
+                    rollout.waves
+                );
+            }
+            DeployCommands::Rollback { id, note } => {
+                let rollout = rollouts::update(&id, RolloutAction::Rollback, note).await?;
+                println!(
+                    "Rolling back {} devices of rollout {}",
+                    rollout.count(RolloutDeviceState::RolledBack),
+                    rollout.id
+                );
+            }
+            DeployCommands::Cancel { id, note } => {
+                let rollout = rollouts::update(&id, RolloutAction::Cancel, note).await?;
+                println!("Cancelled rollout {}", rollout.id);
+            }
+        },
+
+        Commands::Access(cmd) => match cmd {
+            AccessCommands::Grant {
+                email,
+                device,
+                role,
+                ttl,
+                reason,
+            } => {
+                let grant = access::grant(&email, &device, role, ttl, reason).await?;
+                println!(
+                    "Granted {} {} access to {} until {} (grant {})",
+                    email,
+                    grant.role.to_string(),
+                    grant.device_name,
+                    grant.expires_at,
+                    grant.id
+                );
             }
-            ConfigCommands::Show => {
-                let cfg = Config::load().context("Failed to load config")?;
-                tracing::info!("Config laoded");
-                println!("{:#?}", cfg);
+            AccessCommands::List { all, json } => {
+                let grants = access::list(all).await?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&grants)?);
+                } else {
+                    tui::access::print_access_grants(&grants);
+                }
             }
-            ConfigCommands::File => {
-                let path = Config::config_file_path().context("Failed to get config path")?;
-                tracing::info!("Config path loaded");
-                println!("{:#?}", path);
+            AccessCommands::Revoke { id } => {
+                let grant = access::revoke(&id).await?;
+                println!("Revoked {}'s access to {}", grant.email, grant.device_name);
             }
         },
 
@@ -923,6 +3155,186 @@ pub async fn cli() -> anyhow::Result<()> {
                     println!("User removed");
                 }
             },
+            OrgCommands::Vulnerabilities {
+                org_id,
+                severity,
+                json,
+            } => {
+                let mut devices = org::vulnerabilities(org_id).await?;
+                if let Some(min) = severity {
+                    for device in &mut devices {
+                        device
+                            .vulnerabilities
+                            .retain(|v| v.severity.is_some_and(|s| s >= min));
+                    }
+                }
+                let report = VulnerabilityReport::from_devices(&devices);
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    tui::org::print_vulnerability_report(&report);
+                }
+            }
+            OrgCommands::PatchPolicies(action) => match action {
+                PatchPolicyAction::List { org_id, json } => {
+                    let policies = org::patch_policies(org_id).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&policies)?);
+                    } else {
+                        tui::org::print_patch_policies(&policies);
+                    }
+                }
+                PatchPolicyAction::Create {
+                    name,
+                    window,
+                    packages,
+                    reboot,
+                    when,
+                    org_id,
+                } => {
+                    let body = CreatePatchPolicyBody {
+                        name,
+                        packages,
+                        window,
+                        reboot,
+                        when,
+                    };
+                    let created = org::create_patch_policy(org_id, body).await?;
+                    tui::org::print_patch_policies(&created);
+                }
+                PatchPolicyAction::Delete { name, org_id } => {
+                    let deleted = org::delete_patch_policy(org_id, &name).await?;
+                    println!("Deleted patch policy {} on {} server(s)", name, deleted);
+                }
+            },
+            OrgCommands::Usage {
+                org_id,
+                from,
+                to,
+                csv,
+                json,
+            } => {
+                let records = org::usage(UsageQuery { org_id, from, to }).await?;
+                if csv {
+                    print!("{}", usage_csv(&records));
+                } else if json {
+                    let export = UsageExport {
+                        version: USAGE_EXPORT_VERSION,
+                        records,
+                    };
+                    println!("{}", serde_json::to_string_pretty(&export)?);
+                } else {
+                    tui::org::print_org_usage(&records);
+                }
+            }
+            OrgCommands::Limits(action) => match action {
+                LimitsAction::Show { org_id, json } => {
+                    let statuses = org::limits(org_id).await?;
+                    if json {
+                        let statuses: Vec<_> = statuses.into_iter().map(|(_, s)| s).collect();
+                        println!("{}", serde_json::to_string_pretty(&statuses)?);
+                    } else {
+                        tui::org::print_org_limits(&statuses);
+                    }
+                }
+                LimitsAction::Set {
+                    org_id,
+                    max_devices,
+                    max_revisions,
+                    max_report_storage_mb,
+                    max_tunnels,
+                } => {
+                    let body = UpdateOrgLimitsBody {
+                        max_devices,
+                        max_revisions,
+                        max_report_storage_bytes: max_report_storage_mb
+                            .map(|mb| mb.saturating_mul(1024 * 1024)),
+                        max_concurrent_tunnels: max_tunnels,
+                    };
+                    let statuses = org::set_limits(org_id, body).await?;
+                    tui::org::print_org_limits(&statuses);
+                }
+            },
+            OrgCommands::Redaction(action) => {
+                let patterns = match action {
+                    RedactionAction::Show { org_id } => org::redaction(org_id).await?,
+                    RedactionAction::Set { org_id, patterns } => {
+                        org::set_redaction(org_id, patterns).await?
+                    }
+                    RedactionAction::Clear { org_id } => {
+                        org::set_redaction(org_id, Vec::new()).await?
+                    }
+                };
+                tui::org::print_redaction_patterns(&patterns);
+            }
+            OrgCommands::IncidentWebhook(action) => {
+                let hook = match action {
+                    IncidentWebhookAction::Show { org_id } => org::incident_webhook(org_id).await?,
+                    IncidentWebhookAction::Set {
+                        url,
+                        org_id,
+                        secret,
+                    } => org::set_incident_webhook(org_id, Some(url), secret).await?,
+                    IncidentWebhookAction::Clear { org_id } => {
+                        org::set_incident_webhook(org_id, None, None).await?
+                    }
+                };
+                tui::org::print_incident_webhook(&hook);
+            }
+            OrgCommands::IotBridge(action) => {
+                let secs = |interval: Option<Duration>| interval.map(|d| d.as_secs());
+                let bridge = match action {
+                    IotBridgeAction::Show { org_id } => org::iot_bridge(org_id).await?,
+                    IotBridgeAction::Aws {
+                        endpoint,
+                        access_key_id,
+                        secret_access_key,
+                        topic_prefix,
+                        org_id,
+                        telemetry_interval,
+                    } => {
+                        let target = IotBridgeTarget::AwsIot {
+                            endpoint,
+                            access_key_id,
+                            secret_access_key,
+                            topic_prefix,
+                        };
+                        org::set_iot_bridge(org_id, Some(target), secs(telemetry_interval)).await?
+                    }
+                    IotBridgeAction::Azure {
+                        host_name,
+                        policy_name,
+                        policy_key,
+                        org_id,
+                        telemetry_interval,
+                    } => {
+                        let target = IotBridgeTarget::AzureIotHub {
+                            host_name,
+                            policy_name,
+                            policy_key,
+                        };
+                        org::set_iot_bridge(org_id, Some(target), secs(telemetry_interval)).await?
+                    }
+                    IotBridgeAction::Thingsboard {
+                        url,
+                        username,
+                        password,
+                        org_id,
+                        telemetry_interval,
+                    } => {
+                        let target = IotBridgeTarget::Thingsboard {
+                            url,
+                            username,
+                            password,
+                        };
+                        org::set_iot_bridge(org_id, Some(target), secs(telemetry_interval)).await?
+                    }
+                    IotBridgeAction::Clear { org_id } => {
+                        org::set_iot_bridge(org_id, None, None).await?
+                    }
+                };
+                tui::org::print_iot_bridge(&bridge);
+            }
             OrgCommands::Devices(action) => match action {
                 OrgDeviceAction::List { org_id } => {
                     let devices = org::list_devices(org_id).await?;
@@ -960,20 +3372,48 @@ pub async fn cli() -> anyhow::Result<()> {
         },
     }
 
+    if notices {
+        crate::notices::show().await;
+    }
     Ok(())
 }
 
+/// Whether a command ends with unseen release notes and server notices; not
+/// those run by services, ssh or shell startup files.
+fn shows_notices(command: &Commands) -> bool {
+    match command {
+        Commands::Completion { .. } | Commands::Update | Commands::Ssh(_) => false,
+        #[cfg(feature = "runtime")]
+        Commands::Runtime(_) | Commands::Internal(_) => false,
+        #[cfg(unix)]
+        Commands::ConnectionHelper { .. } => false,
+        _ => true,
+    }
+}
+
 async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
     let device = cmd.device;
 
     match cmd.command {
-        DeviceCommand::Shell => {
-            let _ = tui::shell::run_shell(&device).await?;
+        DeviceCommand::Shell {
+            attach,
+            read_only,
+            predict,
+        } => {
+            tui::shell::run_shell(&device, attach, read_only, predict).await?;
+            Ok(())
+        }
+
+        DeviceCommand::Forward {
+            targets,
+            resolve_remote,
+        } => {
+            forward::open_local_forward(&device, targets, resolve_remote).await?;
             Ok(())
         }
 
-        DeviceCommand::Forward { targets } => {
-            forward::open_local_forward(&device, targets).await?;
+        DeviceCommand::Proxy { socks5, bind } => {
+            device::socks::open_socks_proxy(&device, bind, socks5).await?;
             Ok(())
         }
 
@@ -982,8 +3422,22 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
             Ok(())
         }
 
-        DeviceCommand::Logs { follow: _, tail: _ } => {
-            tui::log::run_logs(&device).await?;
+        DeviceCommand::Logs {
+            follow,
+            tail,
+            level,
+            service,
+            since,
+            grep,
+        } => {
+            let filter = LogFilter {
+                service,
+                since_secs: since.map(|d| d.as_secs()),
+                grep,
+            };
+            // a bad pattern fails here rather than on the device
+            filter.matcher().map_err(anyhow::Error::msg)?;
+            tui::log::run_logs(&device, level, follow, filter, tail).await?;
             Ok(())
         }
 
@@ -1001,6 +3455,47 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
             Ok(())
         }
 
+        DeviceCommand::Test {
+            dir,
+            remote_dir,
+            image,
+            pull,
+            out,
+            exclude,
+            command,
+        } => {
+            let run = device::remote_test::TestRun {
+                dir,
+                remote_dir,
+                image,
+                pull,
+                out,
+                exclude,
+                command,
+            };
+            let exit_code = device::remote_test::run_test(&device, run).await?;
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
+            Ok(())
+        }
+
+        #[cfg(unix)]
+        DeviceCommand::Gui { command } => {
+            device::gui::run_gui(&device, command).await?;
+            Ok(())
+        }
+
+        DeviceCommand::Vnc {
+            port,
+            remote_port,
+            open,
+            max_kbps,
+        } => {
+            device::vnc::open_vnc(&device, port, remote_port, open, max_kbps).await?;
+            Ok(())
+        }
+
         #[cfg(unix)]
         DeviceCommand::Serial { path, baud } => {
             let baud = baud.unwrap_or(115200);
@@ -1014,6 +3509,342 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
             Ok(())
         }
 
+        DeviceCommand::Info { action, json } => {
+            let info = match action {
+                None => {
+                    let overview = devices::get_device_overview(&device).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&overview)?);
+                    } else {
+                        tui::device::print_device_overview(&overview);
+                    }
+                    return Ok(());
+                }
+                Some(InfoAction::Set { fields }) => {
+                    devices::update_device_info(&device, &fields, &[]).await?
+                }
+                Some(InfoAction::Unset { keys }) => {
+                    devices::update_device_info(&device, &[], &keys).await?
+                }
+            };
+            tui::device::print_device_info(&device, &info);
+            Ok(())
+        }
+
+        DeviceCommand::Facts { key, refresh, json } => {
+            let facts = device::facts::fetch_facts(&device, refresh).await?;
+            let prefix = key.unwrap_or_default();
+            if json {
+                let selected: BTreeMap<&str, &str> = facts.matching(&prefix).collect();
+                println!("{}", serde_json::to_string_pretty(&selected)?);
+            } else {
+                tui::device::print_facts(&device, &facts, &prefix);
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Vulnerabilities { severity, json } => {
+            let mut scan = devices::get_vulnerabilities(&device).await?;
+            if json {
+                if let Some(min) = severity {
+                    scan.vulnerabilities
+                        .retain(|v| v.severity.is_some_and(|s| s >= min));
+                }
+                println!("{}", serde_json::to_string_pretty(&scan)?);
+            } else {
+                tui::device::print_vulnerabilities(&scan, severity);
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Usage { json } => {
+            let bandwidth = devices::get_bandwidth(&device).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&bandwidth)?);
+            } else {
+                tui::device::print_bandwidth(&device, &bandwidth);
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Pkg(action) => {
+            let (action, packages, dry_run) = match action {
+                PkgAction::Install { packages, dry_run } => {
+                    (PackageAction::Install, packages, dry_run)
+                }
+                PkgAction::Remove { packages, dry_run } => {
+                    (PackageAction::Remove, packages, dry_run)
+                }
+                PkgAction::Upgrade { packages, dry_run } => {
+                    (PackageAction::Upgrade, packages, dry_run)
+                }
+            };
+            let report = device::packages::run(&device, action, packages, dry_run).await?;
+            if !report.success {
+                bail!(
+                    "{} failed: {}",
+                    report.describe(),
+                    report.error.unwrap_or_default()
+                );
+            }
+            println!("Package {} finished", report.describe());
+            Ok(())
+        }
+
+        DeviceCommand::Patches { limit, json } => {
+            let runs = devices::get_patch_runs(&device, limit).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&runs)?);
+            } else {
+                tui::device::print_patch_runs(&runs);
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Maintenance(action) => {
+            match action {
+                MaintenanceAction::On { reason } => {
+                    devices::set_maintenance(&device, true, reason).await?;
+                    println!(
+                        "{} is in maintenance mode; patch policies are paused",
+                        device
+                    );
+                }
+                MaintenanceAction::Off => {
+                    devices::set_maintenance(&device, false, None).await?;
+                    println!("{} left maintenance mode", device);
+                }
+            }
+            Ok(())
+        }
+
+        DeviceCommand::BandwidthProfile { profile } => {
+            devices::set_bandwidth_profile(&device, profile).await?;
+            println!(
+                "{} uses the {} bandwidth profile from its next heartbeat",
+                device, profile
+            );
+            Ok(())
+        }
+
+        DeviceCommand::Probes(action) => {
+            match action {
+                ProbeAction::List { json } => {
+                    let probes = devices::get_probes(&device).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&probes)?);
+                    } else {
+                        tui::device::print_probes(&probes);
+                    }
+                }
+                ProbeAction::Add {
+                    name,
+                    mut target,
+                    expect_status,
+                    interval,
+                    timeout,
+                    fail_after,
+                } => {
+                    if let Some(status) = expect_status {
+                        match &mut target {
+                            ProbeTarget::Http { expect_status, .. } => {
+                                *expect_status = Some(status)
+                            }
+                            _ => bail!("--expect-status only applies to http probes"),
+                        }
+                    }
+                    let mut probe = ProbeSpec::new(&name, target);
+                    probe.interval_secs = interval.as_secs() as u32;
+                    probe.timeout_secs = timeout.as_secs() as u32;
+                    probe.fail_after = fail_after;
+                    let probes = devices::add_probe(&device, probe).await?;
+                    tui::device::print_probes(&probes);
+                }
+                ProbeAction::Remove { name } => {
+                    let probes = devices::remove_probe(&device, &name).await?;
+                    tui::device::print_probes(&probes);
+                }
+                ProbeAction::Results { name, limit, json } => {
+                    let results =
+                        devices::get_probe_results(&device, name.as_deref(), limit).await?;
+                    if json {
+                        println!("{}", serde_json::to_string_pretty(&results)?);
+                    } else {
+                        tui::device::print_probe_results(&results);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Approval(action) => {
+            let enabled = matches!(action, ApprovalModeAction::On);
+            devices::set_require_approval(&device, enabled).await?;
+            if enabled {
+                println!(
+                    "{} now needs a second operator's approval for shells, exec, file access, \
+                     package changes and deployments",
+                    device
+                );
+            } else {
+                println!("{} no longer requires approvals", device);
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Sessions(action) => {
+            let sessions = match action {
+                SessionsAction::List => device::sessions::sessions(&device, None).await?,
+                SessionsAction::Kill { id } => {
+                    let remaining = device::sessions::sessions(&device, Some(id.clone())).await?;
+                    println!("Terminated session {}", id);
+                    remaining
+                }
+            };
+            tui::device::print_sessions(&sessions);
+            Ok(())
+        }
+
+        DeviceCommand::E2e(action) => {
+            let resolved = devices::resolve_device_cached(&device).await?;
+            match action {
+                E2eAction::Show => {
+                    let reported = devices::list_devices()
+                        .await?
+                        .into_iter()
+                        .find(|d| d.id == resolved.id)
+                        .and_then(|d| d.system_info.e2e_public_key);
+                    tui::device::print_e2e_keys(
+                        &device,
+                        e2e::pinned_key(&resolved.id).as_deref(),
+                        reported.as_deref(),
+                        &e2e::operator_public_key()?,
+                    );
+                }
+                E2eAction::Pin { key } => {
+                    e2e::pin_key(&resolved.id, &key)?;
+                    println!(
+                        "Pinned the end-to-end key of {} ({})",
+                        device,
+                        e2e::fingerprint(&key)
+                    );
+                }
+                E2eAction::Forget => {
+                    if e2e::unpin_key(&resolved.id)? {
+                        println!(
+                            "Forgot the end-to-end key of {}; the next stream asks to confirm its key again",
+                            device
+                        );
+                    } else {
+                        println!("No end-to-end key pinned for {}", device);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Debug(DebugAction::Attach {
+            pid,
+            gdb: _,
+            python,
+            port,
+            local_port,
+        }) => {
+            let server = if python {
+                DebugServer::Debugpy
+            } else {
+                DebugServer::Gdb
+            };
+            let port = port.unwrap_or(server.default_port());
+            device::debug::attach(&device, server, pid, port, local_port.unwrap_or(port)).await
+        }
+
+        DeviceCommand::Cores(CoresAction::List) => {
+            let cores = device::deploy::list_core_dumps(&device).await?;
+            tui::deploy::print_core_dumps(&cores);
+            Ok(())
+        }
+
+        DeviceCommand::Cores(CoresAction::Get { id, output }) => {
+            let core = device::deploy::get_core_dump(&device, &id).await?;
+            let content = device::deploy::core_dump_content(&core)?;
+            let output = output.unwrap_or_else(|| {
+                let exe = core.report.executable.rsplit('/').next().unwrap_or("core");
+                PathBuf::from(format!("core.{}.{}", exe, core.report.pid))
+            });
+            std::fs::write(&output, &content)
+                .with_context(|| format!("failed to write {}", output.display()))?;
+            tracing::info!("Saved {} ({} bytes)", output.display(), content.len());
+            Ok(())
+        }
+
+        DeviceCommand::Profile {
+            pid,
+            duration,
+            frequency,
+            format,
+            output,
+        } => {
+            let output = output.unwrap_or_else(|| {
+                PathBuf::from(format!("profile-{}-{}.{}", device, pid, format.extension()))
+            });
+            let run = device::profile::ProfileRun {
+                pid,
+                duration,
+                frequency,
+                format,
+            };
+            device::profile::profile(&device, run, &output).await
+        }
+
+        DeviceCommand::Time(action) => {
+            let sync = matches!(action, TimeAction::Sync);
+            let reply = device::time::time(&device, sync).await?;
+            tui::device::print_time(&device, &reply, sync);
+            Ok(())
+        }
+
+        DeviceCommand::Firewall(FirewallAction::Status) => {
+            let reply = device::firewall::status(&device).await?;
+            tui::device::print_firewall(&device, &reply);
+            Ok(())
+        }
+
+        DeviceCommand::SupportBundle { output, max_mb } => {
+            let summary =
+                device::support::support_bundle(&device, output, max_mb * 1024 * 1024).await?;
+            tui::device::print_support_bundle(&summary);
+            Ok(())
+        }
+
+        DeviceCommand::Alerts {
+            low_battery,
+            conditions,
+            remove_conditions,
+            clear,
+        } => {
+            let conditions = devices::parse_key_values(&conditions)?;
+            let rules = devices::update_alert_rules(
+                &device,
+                low_battery,
+                &conditions,
+                &remove_conditions,
+                clear,
+            )
+            .await?;
+            tui::device::print_alert_rules(&device, &rules);
+            Ok(())
+        }
+
+        DeviceCommand::Rename { new_name } => {
+            if Cli::command().find_subcommand(&new_name).is_some() {
+                bail!("'{}' is an m87 command and cannot be used as a device name", new_name);
+            }
+            devices::rename_device(&device, &new_name).await?;
+            println!("Renamed {} to {}", device, new_name);
+            Ok(())
+        }
+
         DeviceCommand::Audit {
             until,
             since,
@@ -1027,6 +3858,12 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
             Ok(())
         }
 
+        DeviceCommand::Events { since, until, max } => {
+            let events = devices::get_device_events(&device, Some(since), until, max).await?;
+            tui::device::print_device_events(&events);
+            Ok(())
+        }
+
         DeviceCommand::Access(action) => match action {
             AccessAction::List => {
                 let users = devices::get_device_users(&device).await?;
@@ -1056,10 +3893,114 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
             }
         },
 
+        DeviceCommand::Wait { online, timeout } => {
+            let _ = online;
+            let outcome = devices::wait_for_online(&device, timeout).await?;
+            match outcome {
+                WaitOutcome::Reached => tracing::info!("Device {} is online", device),
+                _ => tracing::error!("Timed out waiting for device {} to come online", device),
+            }
+            exit_with(outcome)
+        }
+
+        DeviceCommand::Deploy(DeployArgs {
+            command: Some(DeploySubcommand::Validate { file, r#type }),
+            ..
+        }) => device::deploy::validate_spec_file(&file, r#type).await,
+
+        DeviceCommand::Deploy(DeployArgs {
+            command:
+                Some(DeploySubcommand::Plan {
+                    file,
+                    r#type,
+                    name,
+                    json,
+                }),
+            ..
+        }) => {
+            let plan = device::plan::plan_file(&device, &file, r#type, name.as_deref()).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&plan)?);
+            } else {
+                tui::deploy::print_plan(&device, &plan);
+            }
+            Ok(())
+        }
+
+        DeviceCommand::Deploy(DeployArgs {
+            command: Some(DeploySubcommand::Wait { revision, timeout }),
+            ..
+        }) => {
+            let revision = match revision {
+                Some(r) => r,
+                None => device::deploy::get_active_deployment_id(&device)
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "No active deployment set for device {}. Specify one with --revision",
+                            device
+                        )
+                    })?,
+            };
+            let outcome = device::deploy::wait_for_deployment(&device, &revision, timeout).await?;
+            match outcome {
+                WaitOutcome::Reached => tracing::info!("Deployment {} succeeded", revision),
+                WaitOutcome::Failed => tracing::error!("Deployment {} failed", revision),
+                WaitOutcome::TimedOut => {
+                    tracing::error!("Timed out waiting for deployment {}", revision)
+                }
+            }
+            exit_with(outcome)
+        }
+
+        DeviceCommand::Deploy(DeployArgs {
+            command:
+                Some(DeploySubcommand::Artifacts {
+                    run,
+                    get,
+                    output,
+                    revision,
+                }),
+            ..
+        }) => {
+            let revision = match revision {
+                Some(r) => r,
+                None => device::deploy::get_active_deployment_id(&device)
+                    .await?
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "No active deployment set for device {}. Specify one with --revision",
+                            device
+                        )
+                    })?,
+            };
+            let artifacts = device::deploy::get_run_artifacts(&device, &revision, &run).await?;
+            let Some(path) = get else {
+                tui::deploy::print_run_artifacts(&run, &artifacts);
+                return Ok(());
+            };
+            let content = device::deploy::artifact_content(&artifacts, &path)?;
+            let output = match output {
+                Some(o) => o,
+                None => PathBuf::from(
+                    Path::new(&path)
+                        .file_name()
+                        .ok_or_else(|| anyhow::anyhow!("cannot save {} without --output", path))?,
+                ),
+            };
+            std::fs::write(&output, &content)
+                .with_context(|| format!("failed to write {}", output.display()))?;
+            tracing::info!("Saved {} ({} bytes)", output.display(), content.len());
+            Ok(())
+        }
+
         DeviceCommand::Deploy(args) => {
+            let Some(file) = args.file else {
+                bail!("missing file to deploy");
+            };
             let _ = device::deploy::deploy_file(
                 &device,
-                args.file,
+                file,
                 args.r#type,
                 args.name,
                 args.deployment_id,
@@ -1100,6 +4041,8 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
             DeploymentCommand::Status {
                 deployment_id,
                 logs,
+                run,
+                no_steps,
             } => {
                 let deployment_id = match deployment_id {
                     Some(d) => d,
@@ -1114,13 +4057,44 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
                         }
                     },
                 };
+                // fetched a page of runs at a time, the next one once the
+                // previous is shown
+                let mut query = SnapshotQuery {
+                    run_id: run,
+                    no_steps,
+                    ..SnapshotQuery::page(0, STATUS_PAGE_RUNS)
+                };
                 let snapshot =
-                    device::deploy::get_deployment_snapshot(&device, &deployment_id).await?;
+                    device::deploy::get_deployment_snapshot_part(&device, &deployment_id, &query)
+                        .await?;
 
                 tracing::info!("Received deployment reports");
                 let mut config = tui::helper::RenderOpts::default();
                 config.show_logs_inline = logs;
-                tui::deploy::print_deployment_status_snapshot(&snapshot, &config);
+                tui::deploy::print_snapshot_header(&snapshot, &config);
+                tui::deploy::print_snapshot_runs(&snapshot.runs, &config);
+                // servers without pages send every run at once
+                let total = snapshot.total_runs.unwrap_or(snapshot.runs.len());
+                let interactive = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+                let mut shown = snapshot.runs.len();
+                while shown < total {
+                    if interactive && !tui::deploy::prompt_more_runs(shown, total) {
+                        break;
+                    }
+                    query.offset = shown;
+                    let page = device::deploy::get_deployment_snapshot_part(
+                        &device,
+                        &deployment_id,
+                        &query,
+                    )
+                    .await?;
+                    if page.runs.is_empty() {
+                        break;
+                    }
+                    tui::deploy::print_snapshot_runs(&page.runs, &config);
+                    shown += page.runs.len();
+                }
+                tui::deploy::print_snapshot_rollback(&snapshot, &config);
                 Ok(())
             }
 
@@ -1217,6 +4191,39 @@ async fn handle_device_command(cmd: DeviceRoot) -> anyhow::Result<()> {
                 tui::deploy::print_revision_short_detail(&deployment);
                 Ok(())
             }
+
+            DeploymentCommand::Annotate {
+                deployment_id,
+                annotations,
+                remove,
+            } => {
+                if annotations.is_empty() && remove.is_empty() {
+                    bail!("Nothing to do. Pass key=value pairs or --remove <key>");
+                }
+                let deployment_id = match deployment_id {
+                    Some(d) => d,
+                    None => device::deploy::get_active_deployment_id(&device)
+                        .await?
+                        .ok_or_else(|| {
+                            anyhow::anyhow!(
+                                "No active deployment set for device {}. Specify one with --deployment-id",
+                                device
+                            )
+                        })?,
+                };
+                let deployment = device::deploy::annotate_deployment(
+                    &device,
+                    &deployment_id,
+                    &annotations,
+                    &remove,
+                )
+                .await?;
+                tracing::info!("Updated annotations on deployment {}", deployment_id);
+                for (k, v) in &deployment.annotations {
+                    println!("{}={}", k, v);
+                }
+                Ok(())
+            }
         },
     }
 }
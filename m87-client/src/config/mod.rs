@@ -1,22 +1,310 @@
 use anyhow::{Context, Result, anyhow};
+use m87_shared::bandwidth::BandwidthProfile;
+use m87_shared::patching::PatchPolicy;
+use m87_shared::probe::ProbeSpec;
+use make87_sdk::streams::QuicTimeouts;
 use serde::{Deserialize, Serialize};
-#[cfg(feature = "runtime")]
-use sha1::{Digest, Sha1};
-use std::{fs, path::PathBuf};
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::OnceLock, time::Duration};
 use tracing::{error, info, warn};
 
-#[cfg(feature = "runtime")]
-use crate::util::mac;
+use crate::streams::stream_type::{FirewallBackend, StreamClass};
+use crate::util::clipboard::ClipboardPolicy;
+
+/// Overrides the m87 config directory (config.json, credentials, keys)
+pub const CONFIG_DIR_ENV: &str = "M87_CONFIG_DIR";
+/// Overrides the runtime's data directory (queued reports, workdirs, logs)
+pub const DATA_DIR_ENV: &str = "M87_DATA_DIR";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+static INSTANCE: OnceLock<String> = OnceLock::new();
+
+/// Name the process for `--instance`, so config, credentials and data of
+/// several runtimes on one host stay apart. Set once, before anything is loaded.
+pub fn set_instance(name: String) {
+    let _ = INSTANCE.set(name);
+}
+
+/// The instance set with `--instance`, if any.
+pub fn instance() -> Option<&'static str> {
+    INSTANCE.get().map(String::as_str)
+}
+
+/// `dir/instances/<name>` for a named instance, else `dir` unchanged.
+pub fn for_instance(dir: PathBuf) -> PathBuf {
+    match instance() {
+        Some(name) => dir.join("instances").join(name),
+        None => dir,
+    }
+}
+
+/// An absolute directory from `var`, if set.
+fn dir_from_env(var: &str) -> Option<PathBuf> {
+    let dir = PathBuf::from(std::env::var_os(var)?);
+    if dir.is_absolute() {
+        return Some(dir);
+    }
+    warn!(
+        "Ignoring {}: {} is not an absolute path",
+        var,
+        dir.display()
+    );
+    None
+}
+
+/// The m87 config directory from `M87_CONFIG_DIR`, if set.
+pub fn config_dir_override() -> Option<PathBuf> {
+    dir_from_env(CONFIG_DIR_ENV)
+}
 
 fn default_heartbeat_interval() -> u64 {
     300 // 5 min
 }
+fn default_warm_connection_secs() -> u64 {
+    600
+}
+fn default_true() -> bool {
+    true
+}
+fn default_upgrade_confirm_timeout() -> u64 {
+    600 // 10 min
+}
+fn default_lan_direct_port() -> u16 {
+    crate::util::lan::DEFAULT_DIRECT_PORT
+}
+// Builds for self-hosted servers, offline bundles included, must set these to
+// that server; otherwise the binaries talk to make87.com until a user logs in
+// with `--server`.
 fn default_make87_api_url() -> String {
-    "https://api.make87.com".to_string()
+    option_env!("M87_DEFAULT_API_URL")
+        .unwrap_or("https://api.make87.com")
+        .to_string()
 }
 
 fn default_make87_app_url() -> String {
-    "https://app.make87.com".to_string()
+    option_env!("M87_DEFAULT_APP_URL")
+        .unwrap_or("https://app.make87.com")
+        .to_string()
+}
+
+fn default_auth_domain() -> String {
+    option_env!("M87_DEFAULT_AUTH_DOMAIN")
+        .unwrap_or("https://auth.make87.com/")
+        .to_string()
+}
+
+fn default_auth_audience() -> String {
+    option_env!("M87_DEFAULT_AUTH_AUDIENCE")
+        .unwrap_or("https://auth.make87.com")
+        .to_string()
+}
+
+fn default_auth_client_id() -> String {
+    option_env!("M87_DEFAULT_AUTH_CLIENT_ID")
+        .unwrap_or("E2J7xfFLgexzvhHhz4YqaJBy8Ys82SmM")
+        .to_string()
+}
+
+/// Server the CLI manages devices on before any other is configured, e.g. an
+/// offline bundle's own server. Such builds never ask make87.com for servers.
+fn default_manager_server_urls() -> Vec<String> {
+    option_env!("M87_DEFAULT_SERVER_URL")
+        .map(|url| vec![url.to_string()])
+        .unwrap_or_default()
+}
+
+/// QUIC keepalive interval and idle timeout of one stream class, in seconds.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct StreamTimeouts {
+    pub keep_alive_secs: u64,
+    pub idle_timeout_secs: u64,
+}
+
+impl StreamTimeouts {
+    pub fn quic(&self) -> QuicTimeouts {
+        let keep_alive = self.keep_alive_secs.max(1);
+        QuicTimeouts {
+            keep_alive: Duration::from_secs(keep_alive),
+            // An idle timeout below two keepalives would close healthy connections.
+            idle: Duration::from_secs(self.idle_timeout_secs.max(keep_alive * 2)),
+        }
+    }
+}
+
+fn default_interactive_timeouts() -> StreamTimeouts {
+    StreamTimeouts {
+        keep_alive_secs: 5,
+        idle_timeout_secs: 30,
+    }
+}
+fn default_bulk_timeouts() -> StreamTimeouts {
+    StreamTimeouts {
+        keep_alive_secs: 15,
+        idle_timeout_secs: 300,
+    }
+}
+fn default_control_timeouts() -> StreamTimeouts {
+    StreamTimeouts {
+        keep_alive_secs: 5,
+        idle_timeout_secs: 180,
+    }
+}
+fn default_shell_ping_secs() -> u64 {
+    15
+}
+fn default_shell_resume_secs() -> u64 {
+    300 // 5 min
+}
+
+/// Keepalive tuning for connections behind NATs that drop idle flows.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeepaliveConfig {
+    /// shell, exec, ssh and serial streams
+    #[serde(default = "default_interactive_timeouts")]
+    pub interactive: StreamTimeouts,
+    /// port forwards, file transfer, logs, metrics and docker
+    #[serde(default = "default_bulk_timeouts")]
+    pub bulk: StreamTimeouts,
+    /// the runtime's control tunnel
+    #[serde(default = "default_control_timeouts")]
+    pub control: StreamTimeouts,
+    /// Application-level ping on shell streams, end to end through the relay. 0 disables it.
+    #[serde(default = "default_shell_ping_secs")]
+    pub shell_ping_secs: u64,
+    /// How long the runtime keeps a shell running after its connection was lost,
+    /// so the CLI can reconnect and resume it. 0 disables resuming.
+    #[serde(default = "default_shell_resume_secs")]
+    pub shell_resume_secs: u64,
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interactive: default_interactive_timeouts(),
+            bulk: default_bulk_timeouts(),
+            control: default_control_timeouts(),
+            shell_ping_secs: default_shell_ping_secs(),
+            shell_resume_secs: default_shell_resume_secs(),
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    pub fn timeouts(&self, class: StreamClass) -> QuicTimeouts {
+        match class {
+            StreamClass::Interactive => self.interactive.quic(),
+            StreamClass::Bulk => self.bulk.quic(),
+            StreamClass::Control => self.control.quic(),
+        }
+    }
+}
+
+fn default_max_interactive_sessions() -> u32 {
+    16
+}
+fn default_max_fs_transfers() -> u32 {
+    4
+}
+fn default_max_streams() -> u32 {
+    64
+}
+
+/// Concurrent streams a runtime accepts before refusing new ones as busy.
+/// 0 disables a limit.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StreamLimits {
+    /// shell, exec, ssh, serial, gui and vnc sessions
+    #[serde(default = "default_max_interactive_sessions")]
+    pub max_interactive_sessions: u32,
+    /// `m87 <device> cp` and `sync` transfers
+    #[serde(default = "default_max_fs_transfers")]
+    pub max_fs_transfers: u32,
+    /// all streams, including logs, metrics and port forwards
+    #[serde(default = "default_max_streams")]
+    pub max_streams: u32,
+}
+
+impl Default for StreamLimits {
+    fn default() -> Self {
+        Self {
+            max_interactive_sessions: default_max_interactive_sessions(),
+            max_fs_transfers: default_max_fs_transfers(),
+            max_streams: default_max_streams(),
+        }
+    }
+}
+
+fn default_log_max_file_mb() -> u64 {
+    10
+}
+fn default_log_max_files() -> u32 {
+    5
+}
+fn default_max_age_days() -> u64 {
+    14
+}
+fn default_max_event_queue_mb() -> u64 {
+    64
+}
+fn default_reserve_mb() -> u64 {
+    256
+}
+fn default_workdir_retention_days() -> u64 {
+    7
+}
+
+/// Size and age caps for the files a runtime writes under its data directory,
+/// and the free space it leaves when deploying. 0 disables a limit.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiskLimits {
+    /// Also write the runtime log to `logs/runtime.log` in the data directory
+    #[serde(default)]
+    pub log_file: bool,
+    /// Rotate the runtime log once it reaches this size
+    #[serde(default = "default_log_max_file_mb")]
+    pub log_max_file_mb: u64,
+    /// Rotated runtime logs kept next to the current one
+    #[serde(default = "default_log_max_files")]
+    pub log_max_files: u32,
+    /// Delete rotated logs and dead-lettered reports older than this
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: u64,
+    /// Drop the oldest dead-lettered, then undelivered reports beyond this size
+    #[serde(default = "default_max_event_queue_mb")]
+    pub max_event_queue_mb: u64,
+    /// Free space deployments and uploads must leave, checked before writing
+    #[serde(default = "default_reserve_mb")]
+    pub reserve_mb: u64,
+    /// Delete workdirs no revision uses once they were left unchanged this long
+    #[serde(default = "default_workdir_retention_days")]
+    pub workdir_retention_days: u64,
+}
+
+impl Default for DiskLimits {
+    fn default() -> Self {
+        Self {
+            log_file: false,
+            log_max_file_mb: default_log_max_file_mb(),
+            log_max_files: default_log_max_files(),
+            max_age_days: default_max_age_days(),
+            max_event_queue_mb: default_max_event_queue_mb(),
+            reserve_mb: default_reserve_mb(),
+            workdir_retention_days: default_workdir_retention_days(),
+        }
+    }
+}
+
+/// Outbound proxy for the REST API, logins, self-updates and QUIC tunnels.
+/// Without a URL, `HTTPS_PROXY` / `ALL_PROXY` and `NO_PROXY` from the
+/// environment apply.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ProxyConfig {
+    /// `http://`, `socks5://` or `socks5h://` proxy URL, credentials in the userinfo
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Hosts, domain suffixes (`.corp.example`), IPs or CIDR ranges reached
+    /// directly; `*` bypasses the proxy for everything
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -33,35 +321,177 @@ pub struct Config {
     #[serde(default = "default_heartbeat_interval")]
     pub heartbeat_interval_secs: u64,
     pub owner_reference: Option<String>,
+    /// Token from an install command, sent with the registration so the
+    /// device is approved right away; dropped once registered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enrollment_token: Option<String>,
     pub auth_domain: String,
     pub auth_audience: String,
     pub auth_client_id: String,
     #[serde(default)]
     pub trust_invalid_server_cert: bool,
 
-    #[serde(default)]
+    /// PEM bundle of CAs trusted in addition to the public roots, for servers
+    /// with certificates from a private CA
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<PathBuf>,
+
+    #[serde(default = "default_manager_server_urls")]
     pub manager_server_urls: Vec<String>,
 
     #[serde(default)]
     pub organization_id: Option<String>,
+
+    /// Advertise the runtime on the local network via mDNS
+    #[serde(default)]
+    pub lan_discovery: bool,
+    /// Accept direct LAN connections authenticated with the device key
+    #[serde(default)]
+    pub lan_direct: bool,
+    #[serde(default = "default_lan_direct_port")]
+    pub lan_direct_port: u16,
+
+    /// Revert a self-update to the previous binary if the new one has not
+    /// established a control tunnel within this many seconds. 0 disables it.
+    #[serde(default = "default_upgrade_confirm_timeout")]
+    pub upgrade_confirm_timeout_secs: u64,
+
+    /// gpsd address (e.g. `127.0.0.1:2947`) to read the device location from
+    #[serde(default)]
+    pub location_gpsd: Option<String>,
+    /// Command printing the device location as `lat,lon[,alt]` or JSON,
+    /// used when no gpsd address is configured
+    #[serde(default)]
+    pub location_command: Option<String>,
+
+    /// Local shorthands for device names, managed with `m87 alias`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub device_aliases: BTreeMap<String, String>,
+
+    /// QUIC keepalive and idle timeouts per stream class
+    #[serde(default)]
+    pub keepalive: KeepaliveConfig,
+
+    /// OSC 52 clipboard access of programs in remote terminals. The CLI applies it
+    /// to its own sessions, a runtime to every shell and exec session on the device.
+    #[serde(default)]
+    pub clipboard: ClipboardPolicy,
+
+    /// Show keystrokes in `m87 <device> shell` before the device echoes them
+    #[serde(default)]
+    pub predictive_echo: bool,
+
+    /// Encrypt shell, exec and file transfer streams between CLI and device so
+    /// the relay only sees ciphertext. The CLI encrypts its streams, a runtime
+    /// refuses those streams unencrypted.
+    #[serde(default)]
+    pub end_to_end: bool,
+
+    /// Compress logs, support bundle and file transfer streams with zstd.
+    /// The CLI asks for it, a runtime agrees unless it is unset there.
+    /// Interactive streams are never compressed.
+    #[serde(default = "default_true")]
+    pub compression: bool,
+
+    /// Keep the connection of `shell`, `exec`, `ssh` and file transfer
+    /// commands open in the background for this many idle seconds, so the
+    /// next command to the device starts without a handshake. 0 disables it.
+    #[serde(default = "default_warm_connection_secs")]
+    pub warm_connection_secs: u64,
+
+    /// Concurrent stream limits enforced by a runtime
+    #[serde(default)]
+    pub stream_limits: StreamLimits,
+
+    /// Data directory of the runtime, unless `M87_DATA_DIR` is set. Read once
+    /// at startup; defaults to `m87` in the platform data directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+
+    /// Log rotation, queued report caps and the free-space reserve of a runtime
+    #[serde(default)]
+    pub disk_limits: DiskLimits,
+
+    /// Outbound proxy
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+
+    /// Capture core dumps of runs through the kernel's `core_pattern` and
+    /// upload them (runtime, Linux; needs root or passwordless sudo)
+    #[serde(default)]
+    pub core_dumps: bool,
+
+    /// Account network traffic to the containers and units of runs with eBPF
+    /// and report the top talkers in metrics (runtime, Linux 5.7+ with cgroup
+    /// v2; needs root)
+    #[serde(default)]
+    pub flow_metrics: bool,
+
+    /// Firewall the runtime opens the ports runs `expose` in (runtime,
+    /// Linux; needs root or passwordless sudo)
+    #[serde(default)]
+    pub firewall: FirewallBackend,
+
+    /// Patch policies received from the server, applied by the runtime in
+    /// their maintenance windows
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patch_policies: Vec<PatchPolicy>,
+
+    /// Custom redaction patterns received from the server
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+
+    /// Probes received from the server, run by the runtime on their intervals
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub probes: Vec<ProbeSpec>,
+
+    /// Bandwidth profile received from the server
+    #[serde(default, skip_serializing_if = "BandwidthProfile::is_normal")]
+    pub bandwidth_profile: BandwidthProfile,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             runtime_server_url: None,
-            make87_api_url: "https://api.make87.com".to_string(),
-            make87_app_url: "https://app.make87.com".to_string(),
+            make87_api_url: default_make87_api_url(),
+            make87_app_url: default_make87_app_url(),
             device_id: get_default_device_id(),
             log_level: "info".to_string(),
             heartbeat_interval_secs: default_heartbeat_interval(),
             owner_reference: None,
-            auth_domain: "https://auth.make87.com/".to_string(),
-            auth_audience: "https://auth.make87.com".to_string(),
-            auth_client_id: "E2J7xfFLgexzvhHhz4YqaJBy8Ys82SmM".to_string(),
+            enrollment_token: None,
+            auth_domain: default_auth_domain(),
+            auth_audience: default_auth_audience(),
+            auth_client_id: default_auth_client_id(),
             trust_invalid_server_cert: false,
-            manager_server_urls: vec![],
+            ca_bundle: None,
+            manager_server_urls: default_manager_server_urls(),
             organization_id: None,
+            lan_discovery: false,
+            lan_direct: false,
+            lan_direct_port: default_lan_direct_port(),
+            upgrade_confirm_timeout_secs: default_upgrade_confirm_timeout(),
+            location_gpsd: None,
+            location_command: None,
+            device_aliases: BTreeMap::new(),
+            keepalive: KeepaliveConfig::default(),
+            clipboard: ClipboardPolicy::default(),
+            predictive_echo: false,
+            end_to_end: false,
+            compression: true,
+            warm_connection_secs: default_warm_connection_secs(),
+            stream_limits: StreamLimits::default(),
+            data_dir: None,
+            disk_limits: DiskLimits::default(),
+            proxy: ProxyConfig::default(),
+            core_dumps: false,
+            flow_metrics: false,
+            firewall: FirewallBackend::Off,
+            patch_policies: Vec::new(),
+            redact_patterns: Vec::new(),
+            probes: Vec::new(),
+            bandwidth_profile: BandwidthProfile::Normal,
         }
     }
 }
@@ -69,7 +499,7 @@ impl Default for Config {
 fn get_default_device_id() -> String {
     #[cfg(feature = "runtime")]
     {
-        return Config::deterministic_device_id();
+        Config::read_machine_id().unwrap_or_else(Config::generate_device_id)
     }
     #[cfg(not(feature = "runtime"))]
     {
@@ -107,23 +537,40 @@ impl Config {
             .to_string()
     }
 
-    /// Create a deterministic BSON-style ObjectId string from hostname and MAC address.
+    /// Generate a new BSON-style ObjectId string (12 random bytes as hex).
     /// Runtime-specific: Used for device registration
     #[cfg(feature = "runtime")]
-    pub fn deterministic_device_id() -> String {
-        use sysinfo::System;
+    pub fn generate_device_id() -> String {
+        let uuid = uuid::Uuid::new_v4();
+        uuid.as_bytes()[..12]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
 
-        let hostname = System::host_name().unwrap_or_else(|| "not found".to_string());
-        let mac = mac::get_mac_address().unwrap_or_else(|| "00:00:00:00:00:00".into());
+    /// The machine-id file holds the device id independently of config.json,
+    /// so the identity survives hostname or NIC changes and a reset config.
+    #[cfg(feature = "runtime")]
+    pub fn machine_id_path() -> Result<PathBuf> {
+        Ok(Self::m87_config_dir()?.join("machine-id"))
+    }
 
-        // Hash hostname + mac
-        let mut hasher = Sha1::new();
-        hasher.update(hostname.as_bytes());
-        hasher.update(mac.as_bytes());
-        let hash = hasher.finalize();
+    #[cfg(feature = "runtime")]
+    pub fn read_machine_id() -> Option<String> {
+        let id = fs::read_to_string(Self::machine_id_path().ok()?).ok()?;
+        let id = id.trim();
+        (id.len() == 24 && id.chars().all(|c| c.is_ascii_hexdigit())).then(|| id.to_string())
+    }
 
-        // Take first 12 bytes and convert to hex
-        hash[..12].iter().map(|b| format!("{:02x}", b)).collect()
+    #[cfg(feature = "runtime")]
+    pub fn write_machine_id(id: &str) -> Result<()> {
+        let path = Self::machine_id_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context("Failed to create config directory")?;
+        }
+        fs::write(&path, format!("{}\n", id)).context("Failed to write machine-id file")?;
+        info!("Device id stored in {:?}", path);
+        Ok(())
     }
 
     pub fn load() -> Result<Self> {
@@ -170,8 +617,39 @@ impl Config {
     }
 
     pub fn config_file_path() -> Result<PathBuf> {
-        let config_dir = Self::get_config_dir()?;
-        Ok(config_dir.join("m87").join("config.json"))
+        Ok(Self::m87_config_dir()?.join("config.json"))
+    }
+
+    /// Directory holding config.json, credentials and keys: `M87_CONFIG_DIR`,
+    /// else `m87` in the config directory.
+    pub fn m87_config_dir() -> Result<PathBuf> {
+        let dir = match config_dir_override() {
+            Some(dir) => dir,
+            None => Self::get_config_dir()?.join("m87"),
+        };
+        Ok(for_instance(dir))
+    }
+
+    /// Directory for the runtime's state: `M87_DATA_DIR`, else `data_dir` from
+    /// the config, else `m87` in the platform data directory. Resolved once per
+    /// process. A named instance gets its own subdirectory unless the config
+    /// sets `data_dir`, which is then already per instance.
+    pub fn data_dir() -> Result<PathBuf> {
+        if let Some(dir) = DATA_DIR.get() {
+            return Ok(dir.clone());
+        }
+        let dir = match dir_from_env(DATA_DIR_ENV) {
+            Some(dir) => for_instance(dir),
+            None => match Self::load().ok().and_then(|c| c.data_dir) {
+                Some(dir) => dir,
+                None => for_instance(
+                    dirs::data_dir()
+                        .context("Failed to get data directory")?
+                        .join("m87"),
+                ),
+            },
+        };
+        Ok(DATA_DIR.get_or_init(|| dir).clone())
     }
 
     /// Get config directory, respecting SUDO_USER on Unix systems.
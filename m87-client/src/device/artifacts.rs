@@ -0,0 +1,161 @@
+//! Output files of steps: the files under a run's workdir matching a step's
+//! `outputs`, read back after the step ran so they travel with its report.
+//! Paths without wildcards match that file; `*` and `?` match within a path
+//! segment and `**` matches any number of segments.
+
+use std::path::Path;
+
+use base64::Engine;
+use m87_shared::deploy_spec::{
+    MAX_ARTIFACT_BYTES, MAX_STEP_ARTIFACT_BYTES, MAX_STEP_ARTIFACTS, StepArtifact,
+};
+use tracing::warn;
+
+/// Directories walked at most while looking for matches
+const MAX_WALK_DIRS: usize = 1000;
+
+/// Files under `wd` matching `outputs`, in path order. Files over the size
+/// limits are listed without content.
+pub async fn collect(wd: &Path, outputs: &[String]) -> Vec<StepArtifact> {
+    if outputs.is_empty() {
+        return Vec::new();
+    }
+    let mut paths = match_files(wd, outputs).await;
+    paths.sort();
+    if paths.len() > MAX_STEP_ARTIFACTS {
+        warn!(
+            "{} output files match, uploading the first {}",
+            paths.len(),
+            MAX_STEP_ARTIFACTS
+        );
+        paths.truncate(MAX_STEP_ARTIFACTS);
+    }
+
+    let mut budget = MAX_STEP_ARTIFACT_BYTES;
+    let mut artifacts = Vec::new();
+    for rel in paths {
+        let path = wd.join(&rel);
+        let Ok(meta) = tokio::fs::metadata(&path).await else {
+            continue;
+        };
+        let size = meta.len();
+        let content = if size <= MAX_ARTIFACT_BYTES && size <= budget {
+            match tokio::fs::read(&path).await {
+                Ok(bytes) => {
+                    budget -= size;
+                    Some(base64::engine::general_purpose::STANDARD.encode(bytes))
+                }
+                Err(e) => {
+                    warn!("cannot read output {}: {}", path.display(), e);
+                    None
+                }
+            }
+        } else {
+            warn!("output {} is too large to upload ({} bytes)", rel, size);
+            None
+        };
+        artifacts.push(StepArtifact {
+            path: rel,
+            size,
+            content,
+        });
+    }
+    artifacts
+}
+
+/// Relative paths of the regular files under `wd` matching any pattern.
+async fn match_files(wd: &Path, outputs: &[String]) -> Vec<String> {
+    let patterns: Vec<&str> = outputs.iter().map(|p| p.trim_start_matches("./")).collect();
+    let mut found = Vec::new();
+    let mut stack = vec![String::new()];
+    let mut walked = 0;
+    while let Some(dir) = stack.pop() {
+        walked += 1;
+        if walked > MAX_WALK_DIRS {
+            warn!(
+                "stopped looking for outputs after {} directories",
+                MAX_WALK_DIRS
+            );
+            break;
+        }
+        let Ok(mut entries) = tokio::fs::read_dir(wd.join(&dir)).await else {
+            continue;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().to_string();
+            let rel = if dir.is_empty() {
+                name
+            } else {
+                format!("{}/{}", dir, name)
+            };
+            if file_type.is_dir() {
+                stack.push(rel);
+            } else if file_type.is_file() && patterns.iter().any(|p| matches(p, &rel)) {
+                found.push(rel);
+            }
+        }
+    }
+    found
+}
+
+/// Whether the relative `path` matches `pattern`.
+fn matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').collect();
+    matches_segments(&pattern, &path)
+}
+
+fn matches_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| matches_segments(rest, &path[i..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                matches_segment(first.as_bytes(), name.as_bytes())
+                    && matches_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn matches_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| matches_segment(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && matches_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && matches_segment(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_paths_match_only_that_file() {
+        assert!(matches("report.xml", "report.xml"));
+        assert!(matches("out/report.xml", "out/report.xml"));
+        assert!(!matches("report.xml", "out/report.xml"));
+        assert!(!matches("out/report.xml", "report.xml"));
+    }
+
+    #[test]
+    fn wildcards_stay_within_a_segment() {
+        assert!(matches("*.xml", "report.xml"));
+        assert!(matches("out/test-?.log", "out/test-1.log"));
+        assert!(!matches("*.xml", "out/report.xml"));
+        assert!(!matches("out/test-?.log", "out/test-12.log"));
+    }
+
+    #[test]
+    fn double_star_matches_any_depth() {
+        assert!(matches("**/*.xml", "report.xml"));
+        assert!(matches("**/*.xml", "a/b/report.xml"));
+        assert!(matches("results/**", "results/a/b.txt"));
+        assert!(!matches("results/**/*.xml", "other/a.xml"));
+    }
+}
@@ -0,0 +1,112 @@
+//! Bytes this runtime sent and received through the relay per
+//! [`TrafficClass`], since it started. Reported with heartbeats so the
+//! server can show a device's usage with `m87 <device> usage`.
+//!
+//! Streams count their payload as read and written on the QUIC stream, so
+//! compressed streams count their compressed size. Direct LAN connections
+//! do not use the device's uplink and are not counted.
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use m87_shared::bandwidth::{BandwidthReport, ClassTraffic, TrafficBytes, TrafficClass};
+
+use crate::streams::quic::{ByteCounter, QuicIo};
+
+/// How often heartbeats carry the counters after the first one of a tunnel
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+static COUNTERS: LazyLock<Vec<Arc<ByteCounter>>> = LazyLock::new(|| {
+    TrafficClass::ALL
+        .iter()
+        .map(|_| Arc::new(ByteCounter::default()))
+        .collect()
+});
+
+static STARTED_AT: LazyLock<u64> = LazyLock::new(|| {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+});
+
+/// UDP bytes of closed control connections, and the open one
+static WIRE: Mutex<(TrafficBytes, Option<quinn::Connection>)> = Mutex::new((
+    TrafficBytes {
+        sent: 0,
+        received: 0,
+    },
+    None,
+));
+
+fn counter(class: TrafficClass) -> &'static Arc<ByteCounter> {
+    let index = TrafficClass::ALL
+        .iter()
+        .position(|c| *c == class)
+        .unwrap_or(TrafficClass::ALL.len() - 1);
+    &COUNTERS[index]
+}
+
+/// Count the bytes of `io` under `class`.
+pub fn count(io: &mut QuicIo, class: TrafficClass) {
+    LazyLock::force(&STARTED_AT);
+    io.counter = Some(counter(class).clone());
+}
+
+pub fn record_sent(class: TrafficClass, bytes: usize) {
+    LazyLock::force(&STARTED_AT);
+    counter(class)
+        .sent
+        .fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub fn record_received(class: TrafficClass, bytes: usize) {
+    LazyLock::force(&STARTED_AT);
+    counter(class)
+        .received
+        .fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Count the UDP bytes of the control connection `conn`, replacing the
+/// previous one.
+pub fn track_connection(conn: &quinn::Connection) {
+    let mut wire = WIRE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(previous) = wire.1.replace(conn.clone()) {
+        let stats = previous.stats();
+        wire.0.sent += stats.udp_tx.bytes;
+        wire.0.received += stats.udp_rx.bytes;
+    }
+}
+
+/// The counters since the runtime started.
+pub fn report() -> BandwidthReport {
+    let classes = TrafficClass::ALL
+        .iter()
+        .map(|class| {
+            let counter = counter(*class);
+            ClassTraffic {
+                class: *class,
+                bytes: TrafficBytes {
+                    sent: counter.sent.load(Ordering::Relaxed),
+                    received: counter.received.load(Ordering::Relaxed),
+                },
+            }
+        })
+        .filter(|c| c.bytes.total() > 0)
+        .collect();
+
+    let wire = WIRE.lock().unwrap_or_else(|e| e.into_inner());
+    let mut total = wire.0;
+    if let Some(conn) = &wire.1 {
+        let stats = conn.stats();
+        total.sent += stats.udp_tx.bytes;
+        total.received += stats.udp_rx.bytes;
+    }
+
+    BandwidthReport {
+        since: *STARTED_AT,
+        classes,
+        wire: wire.1.is_some().then_some(total),
+    }
+}
@@ -0,0 +1,329 @@
+//! Battery and power source state from sysfs, falling back to upower, or
+//! `pmset` on macOS.
+
+use std::process::Command;
+use std::sync::OnceLock;
+
+use m87_shared::metrics::{BatteryMetrics, ChargingState, PowerSource};
+
+const POWER_SUPPLY_DIR: &str = "/sys/class/power_supply";
+const UPOWER_DISPLAY_DEVICE: &str = "/org/freedesktop/UPower/devices/DisplayDevice";
+
+/// One entry of `/sys/class/power_supply`.
+#[derive(Debug, Default)]
+struct PowerSupply {
+    kind: String,
+    /// "Device" for peripherals such as wireless mice
+    scope: Option<String>,
+    online: Option<bool>,
+    capacity: Option<f32>,
+    status: Option<String>,
+    energy_now: Option<f64>,
+    energy_full: Option<f64>,
+    power_now: Option<f64>,
+}
+
+/// The device battery, or `None` if it has none.
+pub fn collect_battery() -> Option<BatteryMetrics> {
+    if cfg!(target_os = "macos") {
+        return read_pmset();
+    }
+    battery_from_supplies(&read_power_supplies()).or_else(read_upower)
+}
+
+fn read_power_supplies() -> Vec<PowerSupply> {
+    let Ok(entries) = std::fs::read_dir(POWER_SUPPLY_DIR) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| {
+            let dir = entry.path();
+            let read = |name: &str| {
+                std::fs::read_to_string(dir.join(name))
+                    .ok()
+                    .map(|s| s.trim().to_string())
+            };
+            let number = |name: &str| read(name).and_then(|s| s.parse::<f64>().ok());
+            PowerSupply {
+                kind: read("type").unwrap_or_default(),
+                scope: read("scope"),
+                online: read("online").map(|s| s == "1"),
+                capacity: number("capacity").map(|c| c as f32),
+                status: read("status"),
+                // some drivers only report charge in µAh instead of energy in µWh
+                energy_now: number("energy_now").or_else(|| number("charge_now")),
+                energy_full: number("energy_full").or_else(|| number("charge_full")),
+                power_now: number("power_now").or_else(|| number("current_now")),
+            }
+        })
+        .collect()
+}
+
+fn charging_state(status: &str) -> ChargingState {
+    match status.to_lowercase().as_str() {
+        "charging" | "pending-charge" => ChargingState::Charging,
+        "discharging" | "pending-discharge" => ChargingState::Discharging,
+        "full" | "fully-charged" | "charged" => ChargingState::Full,
+        "not charging" | "ac attached" => ChargingState::NotCharging,
+        _ => ChargingState::Unknown,
+    }
+}
+
+/// Combine all system batteries, weighting by capacity when it is known.
+fn battery_from_supplies(supplies: &[PowerSupply]) -> Option<BatteryMetrics> {
+    let batteries: Vec<&PowerSupply> = supplies
+        .iter()
+        .filter(|s| s.kind == "Battery" && s.scope.as_deref() != Some("Device"))
+        .filter(|s| s.capacity.is_some() || s.energy_full.is_some())
+        .collect();
+    if batteries.is_empty() {
+        return None;
+    }
+
+    let (now, full) = batteries
+        .iter()
+        .map(|b| (b.energy_now, b.energy_full))
+        .try_fold((0.0, 0.0), |(n, f), (now, full)| {
+            Some((n + now?, f + full?))
+        })
+        .unwrap_or((0.0, 0.0));
+    let percent = if full > 0.0 {
+        (now / full * 100.0) as f32
+    } else {
+        let known: Vec<f32> = batteries.iter().filter_map(|b| b.capacity).collect();
+        known.iter().sum::<f32>() / known.len().max(1) as f32
+    };
+
+    let states: Vec<ChargingState> = batteries
+        .iter()
+        .map(|b| charging_state(b.status.as_deref().unwrap_or("")))
+        .collect();
+    let state = [
+        ChargingState::Charging,
+        ChargingState::Discharging,
+        ChargingState::NotCharging,
+        ChargingState::Full,
+    ]
+    .into_iter()
+    .find(|s| states.contains(s))
+    .unwrap_or_default();
+
+    let mains_online = supplies
+        .iter()
+        .filter(|s| s.kind != "Battery")
+        .any(|s| s.online == Some(true));
+    let power_source = if mains_online || state == ChargingState::Charging {
+        PowerSource::Ac
+    } else if state == ChargingState::Discharging {
+        PowerSource::Battery
+    } else {
+        PowerSource::Unknown
+    };
+
+    let draw: f64 = batteries.iter().filter_map(|b| b.power_now).sum();
+    let time_to_empty_secs = (state == ChargingState::Discharging && draw > 0.0 && now > 0.0)
+        .then(|| (now / draw * 3600.0) as u64);
+
+    Some(BatteryMetrics {
+        percent: percent.clamp(0.0, 100.0),
+        state,
+        power_source,
+        time_to_empty_secs,
+    })
+}
+
+static HAS_UPOWER: OnceLock<bool> = OnceLock::new();
+
+fn read_upower() -> Option<BatteryMetrics> {
+    let has_upower = *HAS_UPOWER.get_or_init(|| {
+        Command::new("upower")
+            .arg("--version")
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    });
+    if !has_upower {
+        return None;
+    }
+    let out = Command::new("upower")
+        .args(["-i", UPOWER_DISPLAY_DEVICE])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    parse_upower(&String::from_utf8_lossy(&out.stdout))
+}
+
+/// Parse `upower -i` output for the display device.
+fn parse_upower(output: &str) -> Option<BatteryMetrics> {
+    let field = |name: &str| {
+        output.lines().find_map(|line| {
+            let (key, value) = line.trim().split_once(':')?;
+            (key.trim() == name).then(|| value.trim())
+        })
+    };
+    if field("power supply") == Some("no") || field("present") == Some("no") {
+        return None;
+    }
+    let percent: f32 = field("percentage")?.trim_end_matches('%').parse().ok()?;
+    let state = charging_state(field("state").unwrap_or(""));
+    let power_source = match state {
+        ChargingState::Discharging => PowerSource::Battery,
+        ChargingState::Charging | ChargingState::Full => PowerSource::Ac,
+        _ => PowerSource::Unknown,
+    };
+    let time_to_empty_secs = field("time to empty").and_then(|v| {
+        let (amount, unit) = v.split_once(' ')?;
+        let amount: f64 = amount.parse().ok()?;
+        let secs = match unit {
+            "seconds" => amount,
+            "minutes" => amount * 60.0,
+            "hours" => amount * 3600.0,
+            "days" => amount * 86400.0,
+            _ => return None,
+        };
+        Some(secs as u64)
+    });
+    Some(BatteryMetrics {
+        percent,
+        state,
+        power_source,
+        time_to_empty_secs,
+    })
+}
+
+fn read_pmset() -> Option<BatteryMetrics> {
+    let out = Command::new("pmset")
+        .args(["-g", "batt"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    parse_pmset(&String::from_utf8_lossy(&out.stdout))
+}
+
+/// Parse `pmset -g batt` output, e.g.
+/// ` -InternalBattery-0 (id=4653155)\t87%; discharging; 5:12 remaining present: true`.
+/// Desktop Macs list no battery.
+fn parse_pmset(output: &str) -> Option<BatteryMetrics> {
+    let mut lines = output.lines();
+    let source = lines.next()?;
+    let battery = lines.find(|l| l.contains("InternalBattery"))?;
+    let (_, status) = battery.split_once('\t')?;
+    let mut fields = status.split(';').map(str::trim);
+    let percent: f32 = fields.next()?.trim_end_matches('%').parse().ok()?;
+    // "finishing charge" is still charging
+    let state = match fields.next().unwrap_or("") {
+        s if s.ends_with("charge") => ChargingState::Charging,
+        s => charging_state(s),
+    };
+    let power_source = if source.contains("'AC Power'") {
+        PowerSource::Ac
+    } else if source.contains("'Battery Power'") {
+        PowerSource::Battery
+    } else {
+        PowerSource::Unknown
+    };
+    let time_to_empty_secs = fields
+        .next()
+        .filter(|_| state == ChargingState::Discharging)
+        .and_then(|rest| {
+            let (h, m) = rest.split_whitespace().next()?.split_once(':')?;
+            Some(h.parse::<u64>().ok()? * 3600 + m.parse::<u64>().ok()? * 60)
+        });
+    Some(BatteryMetrics {
+        percent,
+        state,
+        power_source,
+        time_to_empty_secs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_battery_from_supplies() {
+        let supplies = vec![
+            PowerSupply {
+                kind: "Mains".to_string(),
+                online: Some(false),
+                ..Default::default()
+            },
+            PowerSupply {
+                kind: "Battery".to_string(),
+                capacity: Some(50.0),
+                status: Some("Discharging".to_string()),
+                energy_now: Some(20_000_000.0),
+                energy_full: Some(40_000_000.0),
+                power_now: Some(10_000_000.0),
+                ..Default::default()
+            },
+            // wireless mouse
+            PowerSupply {
+                kind: "Battery".to_string(),
+                scope: Some("Device".to_string()),
+                capacity: Some(5.0),
+                ..Default::default()
+            },
+        ];
+        let battery = battery_from_supplies(&supplies).unwrap();
+        assert_eq!(battery.percent, 50.0);
+        assert_eq!(battery.state, ChargingState::Discharging);
+        assert_eq!(battery.power_source, PowerSource::Battery);
+        assert_eq!(battery.time_to_empty_secs, Some(7200));
+
+        assert!(battery_from_supplies(&supplies[..1]).is_none());
+    }
+
+    #[test]
+    fn test_parse_upower() {
+        let output = "  native-path:          (null)
+  power supply:         yes
+  battery
+    present:             yes
+    state:               charging
+    percentage:          81%
+";
+        let battery = parse_upower(output).unwrap();
+        assert_eq!(battery.percent, 81.0);
+        assert_eq!(battery.state, ChargingState::Charging);
+        assert_eq!(battery.power_source, PowerSource::Ac);
+
+        let discharging = parse_upower(
+            "power supply: yes\nstate: discharging\npercentage: 40%\ntime to empty: 1.5 hours\n",
+        )
+        .unwrap();
+        assert_eq!(discharging.time_to_empty_secs, Some(5400));
+
+        assert!(parse_upower("  power supply:         no\n  percentage: 0%\n").is_none());
+    }
+
+    #[test]
+    fn test_parse_pmset() {
+        let output = "Now drawing from 'Battery Power'
+ -InternalBattery-0 (id=4653155)\t87%; discharging; 5:12 remaining present: true
+";
+        let battery = parse_pmset(output).unwrap();
+        assert_eq!(battery.percent, 87.0);
+        assert_eq!(battery.state, ChargingState::Discharging);
+        assert_eq!(battery.power_source, PowerSource::Battery);
+        assert_eq!(battery.time_to_empty_secs, Some(18720));
+
+        let charging = parse_pmset(
+            "Now drawing from 'AC Power'\n -InternalBattery-0 (id=1)\t95%; finishing charge; 0:10 remaining present: true\n",
+        )
+        .unwrap();
+        assert_eq!(charging.state, ChargingState::Charging);
+        assert_eq!(charging.power_source, PowerSource::Ac);
+        assert_eq!(charging.time_to_empty_secs, None);
+
+        let no_estimate = parse_pmset(
+            "Now drawing from 'Battery Power'\n -InternalBattery-0 (id=1)\t60%; discharging; (no estimate) present: true\n",
+        )
+        .unwrap();
+        assert_eq!(no_estimate.time_to_empty_secs, None);
+
+        assert!(parse_pmset("Now drawing from 'AC Power'\n").is_none());
+    }
+}
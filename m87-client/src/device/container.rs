@@ -0,0 +1,245 @@
+//! Running the runtime inside a container: detection, docker socket
+//! passthrough and the manifests printed by `m87 runtime manifest`.
+
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use tracing::{info, warn};
+
+use crate::config::{CONFIG_DIR_ENV, DATA_DIR_ENV};
+use crate::device::health::DEFAULT_HEALTH_PORT;
+
+/// Set to `1` to force container mode, e.g. for engines that leave no marker file
+pub const CONTAINER_ENV: &str = "M87_CONTAINER";
+pub const DEFAULT_IMAGE: &str = "ghcr.io/make87/m87:latest";
+
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+/// Host paths of the manifests, mounted at the same path in the container so
+/// bind mounts of deployed compose files resolve on the host
+const CONFIG_DIR: &str = "/etc/m87";
+const DATA_DIR: &str = "/var/lib/m87";
+
+/// Whether this process runs in a container (docker, podman, kubernetes).
+pub fn in_container() -> bool {
+    static IN_CONTAINER: OnceLock<bool> = OnceLock::new();
+    *IN_CONTAINER.get_or_init(|| {
+        if let Ok(v) = std::env::var(CONTAINER_ENV) {
+            return matches!(v.as_str(), "1" | "true");
+        }
+        Path::new("/.dockerenv").exists()
+            || Path::new("/run/.containerenv").exists()
+            || std::env::var_os("KUBERNETES_SERVICE_HOST").is_some()
+    })
+}
+
+/// The docker socket passed through to this runtime, if any. Honours
+/// `DOCKER_HOST=unix://...`.
+pub fn docker_socket() -> Option<PathBuf> {
+    let path = match std::env::var("DOCKER_HOST") {
+        Ok(host) => PathBuf::from(host.strip_prefix("unix://")?),
+        Err(_) => PathBuf::from(DOCKER_SOCKET),
+    };
+    path.exists().then_some(path)
+}
+
+/// Log what container mode changes, once at startup.
+pub fn log_environment() {
+    if !in_container() {
+        return;
+    }
+    info!("Running in a container, systemd service commands are disabled");
+    match docker_socket() {
+        Some(path) => info!("Docker socket available at {}", path.display()),
+        None => warn!(
+            "No docker socket found; mount it (-v {0}:{0}) to deploy containers",
+            DOCKER_SOCKET
+        ),
+    }
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum ManifestFormat {
+    /// docker-compose.yml with one runtime service
+    #[default]
+    Compose,
+    /// DaemonSet running one runtime per node
+    Kubernetes,
+}
+
+pub struct ManifestOptions {
+    pub image: String,
+    /// Organization or email to register the runtime under
+    pub org_id: Option<String>,
+    pub email: Option<String>,
+    pub health_port: u16,
+}
+
+impl Default for ManifestOptions {
+    fn default() -> Self {
+        Self {
+            image: DEFAULT_IMAGE.to_string(),
+            org_id: None,
+            email: None,
+            health_port: DEFAULT_HEALTH_PORT,
+        }
+    }
+}
+
+impl ManifestOptions {
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "runtime".to_string(),
+            "run".to_string(),
+            "--foreground".to_string(),
+            "--health-addr".to_string(),
+            format!("0.0.0.0:{}", self.health_port),
+        ];
+        if let Some(org_id) = &self.org_id {
+            args.extend(["--org-id".to_string(), org_id.clone()]);
+        }
+        if let Some(email) = &self.email {
+            args.extend(["--email".to_string(), email.clone()]);
+        }
+        args
+    }
+}
+
+/// Manifest that runs the runtime in a container with host networking (LAN
+/// discovery and direct connections), the docker socket and persistent config
+/// and data directories.
+pub fn manifest(format: ManifestFormat, opts: &ManifestOptions) -> String {
+    // JSON strings are valid YAML scalars
+    let args = serde_json::to_string(&opts.args()).unwrap_or_default();
+    let image = &opts.image;
+    let port = opts.health_port;
+    match format {
+        ManifestFormat::Compose => format!(
+            r#"services:
+  m87-runtime:
+    image: {image}
+    command: {args}
+    restart: unless-stopped
+    network_mode: host
+    environment:
+      {CONFIG_DIR_ENV}: {CONFIG_DIR}
+      {DATA_DIR_ENV}: {DATA_DIR}
+      {CONTAINER_ENV}: "1"
+    volumes:
+      - {DOCKER_SOCKET}:{DOCKER_SOCKET}
+      - {CONFIG_DIR}:{CONFIG_DIR}
+      - {DATA_DIR}:{DATA_DIR}
+    healthcheck:
+      test: ["CMD", "curl", "-fsS", "http://127.0.0.1:{port}/healthz"]
+      interval: 30s
+      timeout: 5s
+      retries: 3
+"#
+        ),
+        ManifestFormat::Kubernetes => format!(
+            r#"apiVersion: apps/v1
+kind: DaemonSet
+metadata:
+  name: m87-runtime
+  labels:
+    app: m87-runtime
+spec:
+  selector:
+    matchLabels:
+      app: m87-runtime
+  template:
+    metadata:
+      labels:
+        app: m87-runtime
+    spec:
+      hostNetwork: true
+      terminationGracePeriodSeconds: 30
+      containers:
+        - name: m87-runtime
+          image: {image}
+          args: {args}
+          env:
+            - name: {CONFIG_DIR_ENV}
+              value: {CONFIG_DIR}
+            - name: {DATA_DIR_ENV}
+              value: {DATA_DIR}
+            - name: {CONTAINER_ENV}
+              value: "1"
+          livenessProbe:
+            httpGet:
+              path: /healthz
+              port: {port}
+            periodSeconds: 30
+          readinessProbe:
+            httpGet:
+              path: /readyz
+              port: {port}
+            periodSeconds: 10
+          volumeMounts:
+            - name: docker-socket
+              mountPath: {DOCKER_SOCKET}
+            - name: config
+              mountPath: {CONFIG_DIR}
+            - name: data
+              mountPath: {DATA_DIR}
+      volumes:
+        - name: docker-socket
+          hostPath:
+            path: {DOCKER_SOCKET}
+            type: Socket
+        - name: config
+          hostPath:
+            path: {CONFIG_DIR}
+            type: DirectoryOrCreate
+        - name: data
+          hostPath:
+            path: {DATA_DIR}
+            type: DirectoryOrCreate
+"#
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifests_are_valid_yaml() {
+        let opts = ManifestOptions {
+            org_id: Some("acme".to_string()),
+            ..Default::default()
+        };
+
+        let compose: serde_yaml::Value =
+            serde_yaml::from_str(&manifest(ManifestFormat::Compose, &opts)).unwrap();
+        let service = &compose["services"]["m87-runtime"];
+        assert_eq!(service["image"].as_str(), Some(DEFAULT_IMAGE));
+        let command: Vec<&str> = service["command"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(|v| v.as_str())
+            .collect();
+        assert_eq!(
+            command,
+            [
+                "runtime",
+                "run",
+                "--foreground",
+                "--health-addr",
+                "0.0.0.0:8787",
+                "--org-id",
+                "acme"
+            ]
+        );
+
+        let daemonset: serde_yaml::Value =
+            serde_yaml::from_str(&manifest(ManifestFormat::Kubernetes, &opts)).unwrap();
+        let container = &daemonset["spec"]["template"]["spec"]["containers"][0];
+        assert_eq!(
+            container["livenessProbe"]["httpGet"]["port"].as_u64(),
+            Some(8787)
+        );
+        assert_eq!(container["args"].as_sequence().unwrap().len(), 7);
+    }
+}
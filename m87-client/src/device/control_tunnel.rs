@@ -11,7 +11,13 @@ use tracing::error;
 use tracing::{debug, warn};
 
 #[cfg(feature = "runtime")]
-use crate::{auth::AuthManager, config::Config, device::deployment_manager::DeploymentManager};
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    device::{bandwidth, deployment_manager::DeploymentManager},
+};
+#[cfg(feature = "runtime")]
+use m87_shared::bandwidth::TrafficClass;
 
 #[cfg(feature = "runtime")]
 pub use m87_shared::heartbeat::{HeartbeatRequest, HeartbeatResponse};
@@ -22,6 +28,55 @@ pub struct HeartbeatState {
     last_instruction_hash: String,
     heartbeat_interval: u64,
     first_heartbeat: bool,
+    /// Package inventory last sent on this tunnel
+    sent_inventory: Option<m87_shared::inventory::PackageInventory>,
+    /// Event keys of the heartbeats sent but not answered yet, in order
+    awaiting: std::collections::VecDeque<Option<String>>,
+    /// Set after a revision delta failed to apply; the next heartbeats leave
+    /// out the held revision until the server sent the full one
+    full_revision: bool,
+    /// When a heartbeat on this tunnel last carried the traffic counters
+    bandwidth_sent_at: Option<std::time::Instant>,
+    profile: m87_shared::bandwidth::BandwidthProfile,
+    /// When the report queue was last found empty on this tunnel
+    reports_flushed_at: Option<std::time::Instant>,
+}
+
+impl HeartbeatState {
+    /// Hash of the revision the runtime holds, for the server to send deltas against
+    #[cfg(feature = "runtime")]
+    fn held_revision(&self) -> Option<String> {
+        if self.full_revision {
+            return None;
+        }
+        Some(DeploymentManager::get_current_deploy_hash()).filter(|h| !h.is_empty())
+    }
+
+    /// Traffic counters for the next periodic heartbeat: with the first one
+    /// of a tunnel, then every [`REPORT_INTERVAL`](bandwidth::REPORT_INTERVAL).
+    #[cfg(feature = "runtime")]
+    fn bandwidth_report(&mut self) -> Option<m87_shared::bandwidth::BandwidthReport> {
+        if self
+            .bandwidth_sent_at
+            .is_some_and(|at| at.elapsed() < bandwidth::REPORT_INTERVAL)
+        {
+            return None;
+        }
+        self.bandwidth_sent_at = Some(std::time::Instant::now());
+        Some(bandwidth::report())
+    }
+
+    /// Whether deploy reports wait for the next upload window of the
+    /// bandwidth profile. A window opens once the queue was empty for the
+    /// profile's interval and stays open until it is drained.
+    #[cfg(feature = "runtime")]
+    fn defers_reports(&self) -> bool {
+        let interval = self.profile.report_upload_interval_secs();
+        interval > 0
+            && self
+                .reports_flushed_at
+                .is_some_and(|at| at.elapsed().as_secs() < interval)
+    }
 }
 
 // Runtime-specific: Maintain persistent control tunnel connection
@@ -29,34 +84,42 @@ pub struct HeartbeatState {
 pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Result<()> {
     use std::sync::Arc;
 
-    use crate::streams::quic::get_quic_connection;
-    use crate::streams::udp_manager::UdpChannelManager;
-    use bytes::{BufMut, Bytes, BytesMut};
+    use crate::streams::quic::get_quic_connection_with_timeouts;
+    use crate::streams::stream_type::StreamClass;
     use m87_shared::{
-        config::DeviceClientConfig, deploy_spec::build_instruction_hash, device::short_device_id,
+        config::DeviceClientConfig,
+        deploy_spec::build_instruction_hash,
+        device::short_device_id,
+        protocol::{PROTOCOL_VERSION, missing_features},
     };
     use quinn::Connection;
     use tokio::sync::watch;
 
+    use crate::device::deployment_manager::{recover_inflight, settle_event};
+
     let config = Config::load().context("Failed to load configuration")?;
     let token = AuthManager::get_device_token()?;
     let short_id = short_device_id(&config.device_id);
 
-    let control_host = format!(
-        "control-{}.{}",
-        short_id,
-        config.get_runtime_server_hostname()
+    let control_host = m87_shared::host::device_host(
+        &format!("control-{}", short_id),
+        &config.get_runtime_server_hostname(),
     );
     debug!("Connecting QUIC control tunnel to {}", control_host);
 
-    let (_endpoint, quic_conn): (_, Connection) =
-        get_quic_connection(&control_host, &token, config.trust_invalid_server_cert)
-            .await
-            .map_err(|e| {
-                error!("QUIC connect failed: {}", e);
-                e
-            })
-            .context("QUIC connect failed")?;
+    let (_endpoint, quic_conn): (_, Connection) = get_quic_connection_with_timeouts(
+        &control_host,
+        &token,
+        config.trust_invalid_server_cert,
+        config.keepalive.timeouts(StreamClass::Control),
+    )
+    .await
+    .map_err(|e| {
+        error!("QUIC connect failed: {}", e);
+        e
+    })
+    .context("QUIC connect failed")?;
+    bandwidth::track_connection(&quic_conn);
 
     //  SHUTDOWN SIGNAL
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
@@ -71,6 +134,10 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
     let current_deploy_hash = DeploymentManager::get_current_deploy_hash();
     let config_hash = DeviceClientConfig {
         heartbeat_interval_secs: Some(config.heartbeat_interval_secs as u32),
+        patch_policies: config.patch_policies.clone(),
+        redact_patterns: config.redact_patterns.clone(),
+        probes: config.probes.clone(),
+        bandwidth_profile: config.bandwidth_profile,
     }
     .get_hash()
     .to_string();
@@ -78,23 +145,74 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
 
     let state = Arc::new(tokio::sync::Mutex::new(HeartbeatState {
         last_instruction_hash: last_deploy_hash,
-        heartbeat_interval: config.heartbeat_interval_secs,
+        heartbeat_interval: config
+            .heartbeat_interval_secs
+            .max(config.bandwidth_profile.min_heartbeat_interval_secs()),
         first_heartbeat: true,
+        sent_inventory: None,
+        awaiting: Default::default(),
+        full_revision: false,
+        bandwidth_sent_at: None,
+        profile: config.bandwidth_profile,
+        reports_flushed_at: None,
     }));
 
+    // events sent over an earlier tunnel but never acked
+    if let Err(e) = recover_inflight().await {
+        error!("Failed to requeue inflight events: {}", e);
+    }
+
     let manager_clone = unit_manager.clone();
     let _receiver = tokio::spawn({
         let state = state.clone();
         let update_mutex = Arc::new(tokio::sync::Mutex::new(()));
         async move {
+            let mut skew_checked = false;
             loop {
                 tokio::select! {
                     _ = shutdown.changed() => break,
 
-                    msg = read_msg::<HeartbeatResponse>(&mut recv) => {
+                    msg = read_msg_sized::<HeartbeatResponse>(&mut recv) => {
                         let _ = update_mutex.lock().await;
-                        let resp = msg?;
+                        let (resp, size) = msg?;
+                        let deploys =
+                            resp.target_revision.is_some() || resp.revision_delta.is_some();
+                        let class = if deploys {
+                            TrafficClass::Deploy
+                        } else {
+                            TrafficClass::Control
+                        };
+                        bandwidth::record_received(class, size);
                         tracing::info!("Received heartbeat response");
+                        crate::update::slot::confirm_tunnel();
+                        crate::device::health::mark_heartbeat();
+
+                        if let Some(message) = &resp.upgrade_required {
+                            tracing::error!("Server refused this runtime: {}", message);
+                            // an event it carried stays inflight until the next tunnel
+                            state.lock().await.awaiting.pop_front();
+                            continue;
+                        }
+                        // the server answers heartbeats in order
+                        let sent_event = state.lock().await.awaiting.pop_front().flatten();
+                        if let Some(key) = sent_event
+                            && let Err(e) = settle_event(&key, resp.report_ack.as_ref()).await
+                        {
+                            tracing::error!("Failed to settle event {}: {}", key, e);
+                        }
+                        if !skew_checked {
+                            skew_checked = true;
+                            // servers that predate the negotiation do not send a version
+                            let server = resp.protocol_version.unwrap_or(1);
+                            if server < PROTOCOL_VERSION {
+                                warn!(
+                                    "Server speaks protocol v{} (runtime v{}), unsupported there: {}",
+                                    server,
+                                    PROTOCOL_VERSION,
+                                    missing_features(server).join("; ")
+                                );
+                            }
+                        }
 
                         let mut st = state.lock().await;
 
@@ -102,12 +220,52 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
                             tracing::info!("Received new config");
                             let mut new_cfg = Config::load()?;
                             if let Some(new) = cfg.heartbeat_interval_secs {
-                                st.heartbeat_interval = new as u64;
                                 new_cfg.heartbeat_interval_secs = new as u64;
                             }
+                            if cfg.bandwidth_profile != st.profile {
+                                tracing::info!(
+                                    "Switching to the {} bandwidth profile",
+                                    cfg.bandwidth_profile
+                                );
+                            }
+                            st.profile = cfg.bandwidth_profile;
+                            st.heartbeat_interval = new_cfg
+                                .heartbeat_interval_secs
+                                .max(st.profile.min_heartbeat_interval_secs());
+                            new_cfg.patch_policies = cfg.patch_policies;
+                            new_cfg.redact_patterns = cfg.redact_patterns;
+                            new_cfg.probes = cfg.probes;
+                            new_cfg.bandwidth_profile = cfg.bandwidth_profile;
                             new_cfg.save()?;
+                            crate::device::redaction::reload();
                         }
-                        if let Some(target_units_config) = resp.target_revision {
+                        let target_revision = match &resp.revision_delta {
+                            Some(delta) => {
+                                tracing::info!(
+                                    "Received target deployment delta ({} changed, {} removed runs)",
+                                    delta.changed.len(),
+                                    delta.removed.len()
+                                );
+                                match apply_revision_delta(delta) {
+                                    Ok(revision) => Some(revision),
+                                    Err(e) => {
+                                        warn!(
+                                            "Failed to apply deployment delta, requesting the full revision: {}",
+                                            e
+                                        );
+                                        st.full_revision = true;
+                                        continue;
+                                    }
+                                }
+                            }
+                            None => {
+                                if resp.target_revision.is_some() {
+                                    st.full_revision = false;
+                                }
+                                resp.target_revision
+                            }
+                        };
+                        if let Some(target_units_config) = target_revision {
                             tracing::info!("Received new target deployment");
                             let res = manager_clone.set_desired_units(target_units_config).await;
                             if let Err(e) = res {
@@ -132,36 +290,71 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
             loop {
                 use std::time::Duration;
 
-                use crate::device::deployment_manager::{ack_event, on_new_event};
+                use crate::device::deployment_manager::on_new_event;
+
+                let defer_reports = state.lock().await.defers_reports();
 
                 tokio::select! {
                     _ = shutdown.changed() => break,
                         // handle envent rx
 
-                    data = on_new_event() => {
+                    data = on_new_event(), if !defer_reports => {
                         let Some(claimed) = data else { continue };
-                        let st = state.lock().await;
+                        let mut st = state.lock().await;
 
+                        let mut report = claimed.report.clone();
+                        if let Some(max) = st.profile.max_report_log_tail() {
+                            report.cap_log_tail(max);
+                        }
                         let req = HeartbeatRequest {
                             last_instruction_hash: st.last_instruction_hash.clone(),
-                            deploy_report: Some(claimed.report.clone()),
+                            deploy_report: Some(report),
+                            protocol_version: Some(PROTOCOL_VERSION),
+                            idempotency_key: Some(claimed.key.clone()),
+                            report_seq: claimed.seq,
+                            held_revision: st.held_revision(),
                             ..Default::default()
                         };
 
                         tracing::info!("Sending heartbeat with event udpate");
 
-                        if write_msg(&mut send, &req).await.is_ok() {
-                            let _ = ack_event(&claimed).await;
+                        // deleted once the server acks it, requeued with the next tunnel otherwise
+                        st.awaiting.push_back(Some(claimed.key.clone()));
+                        match write_msg(&mut send, &req).await {
+                            Ok(size) => bandwidth::record_sent(TrafficClass::Deploy, size),
+                            Err(e) => warn!("Failed to send event {}: {}", claimed.key, e),
                         }
                     },
 
 
                     _ = async {
+                        // read before locking: gpsd may take a few seconds for a fix
+                        let location = crate::device::location::current().await;
+                        let time = crate::device::time_sync::status().await;
+                        let inventory = crate::device::inventory::current().await;
+                        // reports may wait for upload, summarize them meanwhile
+                        let deferring =
+                            state.lock().await.profile.report_upload_interval_secs() > 0;
+                        let digest = if deferring {
+                            crate::device::deployment_manager::report_digest().await.ok()
+                        } else {
+                            None
+                        };
                         let (req, interval) = {
                             let mut st = state.lock().await;
 
                             let mut req = HeartbeatRequest {
                                 last_instruction_hash: st.last_instruction_hash.clone(),
+                                protocol_version: Some(PROTOCOL_VERSION),
+                                location,
+                                battery: crate::device::battery::collect_battery(),
+                                time: Some(time),
+                                // the server compares this with its clock to get the offset
+                                sent_at: std::time::SystemTime::now()
+                                    .duration_since(std::time::UNIX_EPOCH)
+                                    .ok()
+                                    .map(|d| d.as_millis() as u64),
+                                held_revision: st.held_revision(),
                                 ..Default::default()
                             };
 
@@ -169,14 +362,45 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
                                 st.first_heartbeat = false;
                                 req.client_version = Some(env!("CARGO_PKG_VERSION").to_string());
                                 req.system_info = Some(get_system_info().await?);
+                                req.failed_upgrade = crate::update::slot::failed_upgrade();
+                                // lets the server tell a reboot from a dropped tunnel
+                                req.boot_time = Some(sysinfo::System::boot_time());
+                            }
+                            if st
+                                .sent_inventory
+                                .as_ref()
+                                .is_none_or(|sent| !sent.same_contents(&inventory))
+                            {
+                                st.sent_inventory = Some(inventory.clone());
+                                req.inventory = Some(inventory);
                             }
+                            req.package_operations =
+                                crate::device::package_manager::pending_reports();
+                            req.patch_runs = crate::device::patching::pending_reports();
+                            req.recovery_unlocks = crate::device::recovery::pending_reports();
+                            req.probe_results = crate::device::probes::pending_reports();
+                            req.bandwidth = st.bandwidth_report();
+                            if digest.as_ref().is_some_and(|d| d.pending_events == 0) {
+                                st.reports_flushed_at = Some(std::time::Instant::now());
+                            }
+                            req.report_digest = digest;
 
+                            st.awaiting.push_back(None);
                             (req, st.heartbeat_interval)
                         };
 
                         tracing::info!("Sending heartbeat request");
 
-                        write_msg(&mut send, &req).await?;
+                        let size = write_msg(&mut send, &req).await?;
+                        bandwidth::record_sent(TrafficClass::Control, size);
+                        if req.failed_upgrade.is_some() {
+                            crate::update::slot::clear_failed_upgrade();
+                        }
+                        let reported = req.package_operations.len();
+                        crate::device::package_manager::clear_reported(reported);
+                        crate::device::patching::clear_reported(req.patch_runs.len());
+                        crate::device::recovery::clear_reported(req.recovery_unlocks.len());
+                        crate::device::probes::clear_reported(req.probe_results.len());
                         tokio::time::sleep(Duration::from_secs(interval)).await;
                         Ok::<_, anyhow::Error>(())
                     } => {}
@@ -185,6 +409,40 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
         }
     });
 
+    let res = serve_device_streams(quic_conn, unit_manager, false).await;
+
+    crate::device::health::mark_disconnected();
+    let _ = shutdown_tx.send(true);
+    debug!("control tunnel terminated");
+    res
+}
+
+/// Rebuild the target revision from a delta against the held revision.
+#[cfg(feature = "runtime")]
+fn apply_revision_delta(
+    delta: &m87_shared::revision_delta::RevisionDelta,
+) -> Result<m87_shared::deploy_spec::DeploymentRevision> {
+    use crate::device::deployment_manager::RevisionStore;
+
+    let held = RevisionStore::get_desired_config()?.context("no revision is held")?;
+    delta.apply(&held).map_err(|e| anyhow::anyhow!(e))
+}
+
+/// Serve incoming device streams (and UDP datagrams) on an authenticated QUIC connection
+/// until it closes. Used by the relay control tunnel and by direct LAN connections
+/// (`direct`), whose peers hold the device key and may open any stream.
+#[cfg(feature = "runtime")]
+pub async fn serve_device_streams(
+    quic_conn: quinn::Connection,
+    unit_manager: Arc<DeploymentManager>,
+    direct: bool,
+) -> Result<()> {
+    use crate::streams::udp_manager::UdpChannelManager;
+    use bytes::{BufMut, Bytes, BytesMut};
+    use tokio::sync::watch;
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
     let udp_channels = UdpChannelManager::new();
 
     let (datagram_tx, mut datagram_rx) = tokio::sync::mpsc::channel::<(u32, Bytes)>(2048);
@@ -203,6 +461,9 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
                         let mut buf = BytesMut::with_capacity(4 + payload.len());
                         buf.put_u32(id);
                         buf.extend_from_slice(&payload);
+                        if !direct {
+                            bandwidth::record_sent(TrafficClass::Forward, buf.len());
+                        }
 
                         if conn.send_datagram(buf.freeze()).is_err() {
                             warn!("send_datagram failed — shutting down");
@@ -238,6 +499,9 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
                             }
                         };
 
+                        if !direct {
+                            bandwidth::record_received(TrafficClass::Forward, d.len());
+                        }
                         if d.len() < 4 {
                             continue;
                         }
@@ -272,7 +536,7 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
                     Ok((send, recv)) => {
                         debug!("QUIC: new control stream accepted");
 
-                        let io = QuicIo { recv, send };
+                        let io = QuicIo::new(recv, send);
                         let udp_channels_clone = udp_channels.clone();
                         let datagram_tx_clone = datagram_tx.clone();
                         let unit_manager_clone = unit_manager.clone();
@@ -280,7 +544,7 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
                         tokio::spawn(async move {
                             if let Err(e) =
                                 streams::router::handle_incoming_stream(
-                                    io, udp_channels_clone, datagram_tx_clone, unit_manager_clone
+                                    io, udp_channels_clone, datagram_tx_clone, unit_manager_clone, direct
                                 ).await
                             {
                                 warn!("control stream error: {:?}", e);
@@ -307,20 +571,25 @@ pub async fn connect_control_tunnel(unit_manager: Arc<DeploymentManager>) -> Res
 
     let _ = shutdown_tx.send(true);
     udp_channels.remove_all().await;
-    debug!("control tunnel terminated");
     Ok(())
 }
 
-pub async fn write_msg<T: Serialize>(io: &mut quinn::SendStream, msg: &T) -> Result<()> {
+/// Write a length prefixed JSON message, returning the bytes written.
+pub async fn write_msg<T: Serialize>(io: &mut quinn::SendStream, msg: &T) -> Result<usize> {
     let json = serde_json::to_vec(&msg)?;
     let len = (json.len() as u32).to_be_bytes();
 
     io.write_all(&len).await?;
     io.write_all(&json).await?;
-    Ok(())
+    Ok(len.len() + json.len())
 }
 
 pub async fn read_msg<T: DeserializeOwned>(io: &mut quinn::RecvStream) -> Result<T> {
+    Ok(read_msg_sized(io).await?.0)
+}
+
+/// Read a message and the bytes it took on the stream.
+pub async fn read_msg_sized<T: DeserializeOwned>(io: &mut quinn::RecvStream) -> Result<(T, usize)> {
     let mut len_buf = [0u8; 4];
     io.read_exact(&mut len_buf).await?;
     let len = u32::from_be_bytes(len_buf) as usize;
@@ -332,5 +601,5 @@ pub async fn read_msg<T: DeserializeOwned>(io: &mut quinn::RecvStream) -> Result
     // deserialize directly into enum
     let msg: T = serde_json::from_slice::<T>(&buf)?;
 
-    Ok(msg)
+    Ok((msg, len_buf.len() + len))
 }
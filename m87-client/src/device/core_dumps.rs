@@ -0,0 +1,434 @@
+//! Core dumps of runs. With `core_dumps` enabled, the runtime points the
+//! kernel's `core_pattern` at `m87 internal core-dump`, which the kernel runs
+//! as root with the core on stdin. The handler compresses the core into the
+//! spool directory with what it can learn about the process; the runtime
+//! links spooled cores to the run the process belonged to and queues them as
+//! reports. Cores of processes outside any run are dropped.
+
+use std::io::{Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use m87_shared::deploy_spec::{
+    CoreDumpReport, DeployReportKind, DeploymentRevision, MAX_CORE_DUMP_BYTES,
+};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::device::deployment_manager::{RevisionStore, data_dir, enqueue_event};
+use crate::util::command::{RUN_ID_ENV, binary_exists, current_exe_path, safe_run_command};
+use crate::util::shutdown::SHUTDOWN;
+use crate::util::unix::is_root;
+
+const CORE_PATTERN: &str = "/proc/sys/kernel/core_pattern";
+/// The kernel truncates longer patterns
+const MAX_PATTERN_LEN: usize = 127;
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+/// Cores waiting in the spool at most; further crashes are spooled without
+/// their core, so a crash loop cannot fill the disk
+const MAX_SPOOLED: usize = 8;
+
+/// What the handler learned about a crashed process, next to its core.
+#[derive(Debug, Serialize, Deserialize)]
+struct SpooledCore {
+    pid: u32,
+    signal: i32,
+    /// Unix time of the crash, seconds
+    time: u64,
+    executable: String,
+    /// `M87_RUN_ID` of the process, set for everything a run's commands start
+    #[serde(default)]
+    run_id: Option<String>,
+    /// `/proc/<pid>/cgroup`, to match containers and units of runs
+    #[serde(default)]
+    cgroup: String,
+    size: u64,
+    /// Size of `<stem>.core.zst`; missing when the core was not kept
+    #[serde(default)]
+    compressed_size: Option<u64>,
+}
+
+fn spool_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("cores"))
+}
+
+/// Set up or remove the `core_pattern` handler as the config changes, and
+/// queue spooled cores, until shutdown.
+pub fn spawn() {
+    tokio::spawn(async move {
+        let mut configured = None;
+        loop {
+            let enabled = Config::load().map(|c| c.core_dumps).unwrap_or(false);
+            if configured != Some(enabled) {
+                if let Err(e) = configure(enabled).await {
+                    warn!("Failed to configure core dump capture: {:#}", e);
+                }
+                configured = Some(enabled);
+            }
+            if let Err(e) = queue_spooled().await {
+                warn!("Failed to queue core dumps: {:#}", e);
+            }
+            tokio::select! {
+                _ = sleep(POLL_INTERVAL) => {}
+                _ = SHUTDOWN.cancelled() => break,
+            }
+        }
+    });
+}
+
+async fn configure(enabled: bool) -> Result<()> {
+    let current = tokio::fs::read_to_string(CORE_PATTERN)
+        .await
+        .context("cannot read the core pattern")?
+        .trim()
+        .to_string();
+    let previous_path = data_dir()?.join("core_pattern.previous");
+
+    if enabled {
+        let spool = spool_dir()?;
+        let pattern = handler_pattern(&current_exe_path()?, &spool)?;
+        if current == pattern {
+            return Ok(());
+        }
+        tokio::fs::create_dir_all(&spool).await?;
+        if !is_handler_pattern(&current) {
+            tokio::fs::write(&previous_path, &current).await?;
+        }
+        set_core_pattern(&pattern).await?;
+        info!("Capturing core dumps of runs");
+    } else if is_handler_pattern(&current) {
+        let previous = tokio::fs::read_to_string(&previous_path)
+            .await
+            .unwrap_or_else(|_| "core".to_string());
+        set_core_pattern(previous.trim()).await?;
+        let _ = tokio::fs::remove_file(&previous_path).await;
+        info!("Stopped capturing core dumps");
+    }
+    Ok(())
+}
+
+/// Pipe pattern running this binary's handler with the crashed process'
+/// pid, signal and time.
+fn handler_pattern(exe: &Path, spool: &Path) -> Result<String> {
+    let (exe, spool) = (exe.to_string_lossy(), spool.to_string_lossy());
+    // the kernel splits the pattern on spaces
+    if exe.contains(char::is_whitespace) || spool.contains(char::is_whitespace) {
+        bail!("the m87 binary and data directory paths must not contain spaces");
+    }
+    let pattern = format!("|{} internal core-dump --spool {} %P %s %t", exe, spool);
+    if pattern.len() > MAX_PATTERN_LEN {
+        bail!(
+            "the core pattern would be longer than {} characters",
+            MAX_PATTERN_LEN
+        );
+    }
+    Ok(pattern)
+}
+
+fn is_handler_pattern(pattern: &str) -> bool {
+    pattern.starts_with('|') && pattern.contains(" internal core-dump ")
+}
+
+/// Through `sudo -n` when the runtime is not root.
+async fn set_core_pattern(pattern: &str) -> Result<()> {
+    if !binary_exists("sysctl") {
+        bail!("sysctl is not installed");
+    }
+    let setting = format!("kernel.core_pattern={}", pattern);
+    let mut cmd = if is_root() {
+        Command::new("sysctl")
+    } else {
+        let mut cmd = Command::new("sudo");
+        cmd.arg("-n").arg("sysctl");
+        cmd
+    };
+    cmd.args(["-w", &setting]).kill_on_drop(true);
+    let out = safe_run_command(cmd, COMMAND_TIMEOUT).await?;
+    if !out.status.success() {
+        bail!(
+            "sysctl -w {} failed: {}",
+            setting,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Handler run by the kernel for a crashed process, with its core on stdin.
+pub fn capture(spool: &Path, pid: u32, signal: i32, time: u64) -> Result<()> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    let executable = std::fs::read_link(proc_dir.join("exe"))
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let run_id = std::fs::read(proc_dir.join("environ"))
+        .ok()
+        .and_then(|environ| env_value(&environ, RUN_ID_ENV));
+    let cgroup = std::fs::read_to_string(proc_dir.join("cgroup")).unwrap_or_default();
+
+    let keep = spooled_count(spool) < MAX_SPOOLED;
+    let cap = if keep { MAX_CORE_DUMP_BYTES } else { 0 };
+    let (size, compressed) = compress_capped(std::io::stdin().lock(), cap)?;
+
+    let stem = format!("{}-{}", time, pid);
+    let mut meta = SpooledCore {
+        pid,
+        signal,
+        time,
+        executable,
+        run_id,
+        cgroup,
+        size,
+        compressed_size: None,
+    };
+    if let Some(core) = compressed {
+        let path = spool.join(format!("{}.core.zst", stem));
+        std::fs::write(&path, &core)?;
+        chown_like(&path, spool);
+        meta.compressed_size = Some(core.len() as u64);
+    }
+    // written last: the runtime only picks up cores with their metadata
+    let path = spool.join(format!("{}.json", stem));
+    std::fs::write(&path, serde_json::to_vec(&meta)?)?;
+    chown_like(&path, spool);
+    Ok(())
+}
+
+fn spooled_count(spool: &Path) -> usize {
+    std::fs::read_dir(spool)
+        .map(|rd| {
+            rd.filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().is_some_and(|ext| ext == "json"))
+                .count()
+        })
+        .unwrap_or(0)
+}
+
+/// The handler runs as root; hand its files to the runtime's user.
+fn chown_like(path: &Path, dir: &Path) {
+    if let Ok(meta) = std::fs::metadata(dir) {
+        let _ = std::os::unix::fs::chown(path, Some(meta.uid()), Some(meta.gid()));
+    }
+}
+
+/// Read `input` to the end and zstd compress it, unless the compressed size
+/// goes over `cap`. Returns the input size and the compressed bytes.
+fn compress_capped(mut input: impl Read, cap: u64) -> std::io::Result<(u64, Option<Vec<u8>>)> {
+    let mut encoder = Some(zstd::stream::write::Encoder::new(Vec::new(), 3)?);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut size = 0u64;
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        size += n as u64;
+        if let Some(enc) = encoder.as_mut() {
+            enc.write_all(&buf[..n])?;
+            if enc.get_ref().len() as u64 > cap {
+                encoder = None;
+            }
+        }
+    }
+    let compressed = match encoder {
+        Some(enc) => Some(enc.finish()?).filter(|c| c.len() as u64 <= cap),
+        None => None,
+    };
+    Ok((size, compressed))
+}
+
+/// Value of `key` in a NUL separated environment block.
+pub(crate) fn env_value(environ: &[u8], key: &str) -> Option<String> {
+    environ.split(|b| *b == 0).find_map(|entry| {
+        let entry = std::str::from_utf8(entry).ok()?;
+        let (k, v) = entry.split_once('=')?;
+        (k == key).then(|| v.to_string())
+    })
+}
+
+/// Queue the spooled cores of runs as reports and clear the spool.
+async fn queue_spooled() -> Result<()> {
+    let spool = spool_dir()?;
+    let mut rd = match tokio::fs::read_dir(&spool).await {
+        Ok(rd) => rd,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut entries = Vec::new();
+    while let Some(e) = rd.next_entry().await? {
+        let path = e.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            entries.push(path);
+        }
+    }
+    if entries.is_empty() {
+        return Ok(());
+    }
+    entries.sort();
+
+    let revision = RevisionStore::get_desired_config()?;
+    for meta_path in entries {
+        let core_path = meta_path.with_extension("core.zst");
+        match tokio::fs::read(&meta_path)
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|b| Ok(serde_json::from_slice::<SpooledCore>(&b)?))
+        {
+            Ok(core) => queue_core(core, &core_path, revision.as_ref()).await?,
+            Err(e) => warn!("Skipping unreadable {}: {:#}", meta_path.display(), e),
+        }
+        let _ = tokio::fs::remove_file(&core_path).await;
+        let _ = tokio::fs::remove_file(&meta_path).await;
+    }
+    Ok(())
+}
+
+async fn queue_core(
+    core: SpooledCore,
+    core_path: &Path,
+    revision: Option<&DeploymentRevision>,
+) -> Result<()> {
+    let Some((run_id, revision_id)) = find_run(&core, revision).await else {
+        info!(
+            "Dropping core dump of {} (pid {}): not a process of a run",
+            core.executable, core.pid
+        );
+        return Ok(());
+    };
+    let content = match core.compressed_size {
+        Some(_) => tokio::fs::read(core_path)
+            .await
+            .ok()
+            .map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes)),
+        None => None,
+    };
+    let report = CoreDumpReport {
+        run_id,
+        revision_id,
+        pid: core.pid,
+        signal: core.signal,
+        executable: core.executable,
+        size: core.size,
+        compressed_size: core.compressed_size.filter(|_| content.is_some()),
+        content,
+        report_time: core.time * 1000,
+    };
+    info!("Queued core dump: {}", report.summary());
+    enqueue_event(DeployReportKind::CoreDump(report)).await
+}
+
+/// Run and revision the crashed process belonged to: by its `M87_RUN_ID`,
+/// else by the containers and units of the runs' drift checks.
+async fn find_run(
+    core: &SpooledCore,
+    revision: Option<&DeploymentRevision>,
+) -> Option<(String, String)> {
+    let revision = revision?;
+    let revision_id = revision.id.clone()?;
+    if let Some(run_id) = &core.run_id
+        && revision.jobs.iter().any(|job| &job.id == run_id)
+    {
+        return Some((run_id.clone(), revision_id));
+    }
+    for job in &revision.jobs {
+        let Some(check) = &job.drift else {
+            continue;
+        };
+        if check.units.iter().any(|u| cgroup_has_unit(&core.cgroup, u)) {
+            return Some((job.id.clone(), revision_id));
+        }
+        for container in &check.containers {
+            if let Some(id) = container_id(container).await
+                && core.cgroup.contains(&id)
+            {
+                return Some((job.id.clone(), revision_id));
+            }
+        }
+    }
+    None
+}
+
+/// Whether a process of `cgroup` (the `/proc/<pid>/cgroup` text) runs in
+/// systemd `unit`; units without a suffix are services.
+pub(crate) fn cgroup_has_unit(cgroup: &str, unit: &str) -> bool {
+    let unit = if unit.contains('.') {
+        unit.to_string()
+    } else {
+        format!("{}.service", unit)
+    };
+    cgroup
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .any(|path| path.split('/').any(|segment| segment == unit))
+}
+
+pub(crate) async fn container_id(name: &str) -> Option<String> {
+    if !binary_exists("docker") {
+        return None;
+    }
+    let mut cmd = Command::new("docker");
+    cmd.args(["inspect", "--format", "{{.Id}}", name])
+        .kill_on_drop(true);
+    let output = safe_run_command(cmd, COMMAND_TIMEOUT).await.ok()?;
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && !id.is_empty()).then_some(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handler_pattern_passes_pid_signal_and_time() {
+        let pattern =
+            handler_pattern(Path::new("/usr/bin/m87"), Path::new("/var/lib/m87/cores")).unwrap();
+        assert_eq!(
+            pattern,
+            "|/usr/bin/m87 internal core-dump --spool /var/lib/m87/cores %P %s %t"
+        );
+        assert!(is_handler_pattern(&pattern));
+        assert!(!is_handler_pattern(
+            "|/usr/lib/systemd/systemd-coredump %P %u %g %s %t"
+        ));
+        assert!(handler_pattern(Path::new("/opt/my tools/m87"), Path::new("/tmp")).is_err());
+    }
+
+    #[test]
+    fn cores_over_the_cap_are_counted_but_not_kept() {
+        let small = vec![7u8; 100_000];
+        let (size, compressed) = compress_capped(&small[..], 1024).unwrap();
+        assert_eq!(size, 100_000);
+        let compressed = compressed.unwrap();
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), small);
+
+        // pseudo-random bytes do not compress
+        let mut x = 1u32;
+        let noise: Vec<u8> = (0..100_000)
+            .map(|_| {
+                x = x.wrapping_mul(1103515245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect();
+        assert_eq!(compress_capped(&noise[..], 1024).unwrap(), (100_000, None));
+    }
+
+    #[test]
+    fn run_id_and_unit_of_the_process() {
+        let environ = b"PATH=/usr/bin\0M87_RUN_ID=camera\0HOME=/root\0";
+        assert_eq!(env_value(environ, "M87_RUN_ID").as_deref(), Some("camera"));
+        assert_eq!(env_value(environ, "M87_RUN"), None);
+
+        let cgroup = "0::/system.slice/camera.service\n";
+        assert!(cgroup_has_unit(cgroup, "camera"));
+        assert!(cgroup_has_unit(cgroup, "camera.service"));
+        assert!(!cgroup_has_unit(cgroup, "cam"));
+    }
+}
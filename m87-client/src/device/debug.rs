@@ -0,0 +1,149 @@
+//! `m87 <device> debug attach`: start gdbserver or debugpy for a process on
+//! the device, forward its port to this machine and print what to connect
+//! the local debugger to. The debug server is stopped when the session ends.
+
+use anyhow::Result;
+use tracing::{error, info};
+
+use crate::device::forward::forward_device_port;
+use crate::streams::stream_type::{ForwardTarget, TcpTarget};
+use crate::util::shutdown::SHUTDOWN;
+use crate::{auth::AuthManager, config::Config, devices, tui};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugServer {
+    /// gdbserver, for native code (gdb, VS Code cppdbg, CLion)
+    Gdb,
+    /// debugpy, for Python processes (VS Code)
+    Debugpy,
+}
+
+impl DebugServer {
+    pub fn default_port(self) -> u16 {
+        match self {
+            DebugServer::Gdb => 2345,
+            DebugServer::Debugpy => 5678,
+        }
+    }
+
+    fn binary(self) -> &'static str {
+        match self {
+            DebugServer::Gdb => "gdbserver",
+            DebugServer::Debugpy => "python3",
+        }
+    }
+}
+
+/// Attach `server` to `pid` on the device and keep the port forward open
+/// until Ctrl+C or until the debug server exits.
+pub async fn attach(
+    device: &str,
+    server: DebugServer,
+    pid: u32,
+    remote_port: u16,
+    local_port: u16,
+) -> Result<()> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let command = attach_command(server, pid, remote_port);
+    let device_name = device.to_string();
+    let mut debug_server =
+        tokio::spawn(async move { tui::exec::run_streamed(&device_name, command).await });
+
+    let target = ForwardTarget::Tcp(TcpTarget {
+        remote_host: "127.0.0.1".to_string(),
+        remote_port,
+        local_port,
+    });
+    let trust = config.trust_invalid_server_cert;
+    let mut forward = tokio::spawn(async move {
+        forward_device_port(&resolved.host, &token, &resolved.short_id, target, trust).await
+    });
+
+    println!("{}", connect_hint(server, local_port));
+
+    // the exec stream returns on Ctrl+C too; dropping it closes the stdin
+    // of the remote shell, which stops the debug server
+    tokio::select! {
+        res = &mut debug_server => match res {
+            Ok(Ok(_)) if SHUTDOWN.is_cancelled() => info!("Detached"),
+            Ok(Ok(_)) => info!("Debug server stopped"),
+            Ok(Err(e)) => error!("Debug server failed: {:#}", e),
+            Err(e) => error!("Debug server task failed: {}", e),
+        },
+        res = &mut forward => if let Ok(Err(e)) = res {
+            error!("Port forward failed: {:#}", e);
+        },
+    }
+    debug_server.abort();
+    forward.abort();
+    Ok(())
+}
+
+/// Shell command starting the debug server on localhost of the device. It
+/// runs until the exec stream's stdin closes, stopping gdbserver then, and
+/// ends early when gdbserver or the debugged process exits.
+fn attach_command(server: DebugServer, pid: u32, port: u16) -> String {
+    let bin = server.binary();
+    let check = format!(
+        "command -v {bin} >/dev/null || {{ echo '{bin} is not installed on the device' >&2; exit 127; }}"
+    );
+    let watch = |target: &str| {
+        format!("(while kill -0 {target} 2>/dev/null; do sleep 1; done; kill $$ 2>/dev/null) &")
+    };
+    match server {
+        DebugServer::Gdb => format!(
+            "{check}; gdbserver --attach 127.0.0.1:{port} {pid} </dev/null & server=$!; {} \
+             cat >/dev/null; kill $server 2>/dev/null",
+            watch("$server")
+        ),
+        // debugpy injects its listener into the process and exits; the
+        // listener stays until the process does
+        DebugServer::Debugpy => format!(
+            "{check}; python3 -m debugpy --listen 127.0.0.1:{port} --pid {pid} </dev/null || exit 1; \
+             {} cat >/dev/null",
+            watch(&pid.to_string())
+        ),
+    }
+}
+
+/// What to point the local debugger at.
+fn connect_hint(server: DebugServer, local_port: u16) -> String {
+    match server {
+        DebugServer::Gdb => format!(
+            "gdbserver forwarded to localhost:{port}\n\
+             \x20 gdb:     gdb -ex 'target remote localhost:{port}' <binary>\n\
+             \x20 VS Code: \"miDebuggerServerAddress\": \"localhost:{port}\" (cppdbg launch config)\n\
+             \x20 CLion:   Remote Debug configuration, 'target remote' args localhost:{port}\n\
+             Press Ctrl+C to detach",
+            port = local_port
+        ),
+        DebugServer::Debugpy => format!(
+            "debugpy forwarded to localhost:{port}\n\
+             \x20 VS Code: \"request\": \"attach\", \"connect\": {{ \"host\": \"localhost\", \"port\": {port} }}\n\
+             Press Ctrl+C to detach",
+            port = local_port
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gdbserver_listens_on_device_localhost_and_is_stopped_with_the_stream() {
+        let cmd = attach_command(DebugServer::Gdb, 4242, 2345);
+        assert!(cmd.contains("gdbserver --attach 127.0.0.1:2345 4242 </dev/null & server=$!"));
+        assert!(cmd.contains("kill $server"));
+        assert!(cmd.starts_with("command -v gdbserver"));
+    }
+
+    #[test]
+    fn hint_names_the_local_port() {
+        assert!(connect_hint(DebugServer::Gdb, 12345).contains("target remote localhost:12345"));
+        assert!(connect_hint(DebugServer::Debugpy, 5678).contains("\"port\": 5678"));
+    }
+}
@@ -1,12 +1,13 @@
 use anyhow::{Context, Result, anyhow, bail};
 use m87_shared::deploy_spec::{
-    CommandSpec, CreateDeployRevisionBody, DeployReport, DeploymentRevision,
-    DeploymentStatusSnapshot, LogSpec, ObserveHooks, ObserveSpec, OnFailure, RebootMode, RetrySpec,
-    RunSpec, RunType, Step, StopSpec, Undo, UndoMode, UpdateDeployRevisionBody, Workdir,
-    WorkdirMode,
+    CommandSpec, CoreDump, CreateDeployRevisionBody, DeployReport, DeploymentRevision,
+    DeploymentStatusSnapshot, LogSpec, ObserveHooks, ObserveSpec, OnFailure, Outcome, RebootMode,
+    RetrySpec, RunSpec, RunType, SnapshotQuery, Step, StepArtifacts, StopSpec, Undo, UndoMode,
+    UpdateDeployRevisionBody, Workdir, WorkdirMode,
 };
 use serde_yaml::Value;
 use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 
@@ -14,6 +15,9 @@ use crate::auth::AuthManager;
 use crate::config::Config;
 use crate::devices::resolve_device_cached;
 use crate::server;
+use crate::util::wait::{WaitOutcome, poll_until};
+
+const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(clap::ValueEnum, Debug, Clone, Copy)]
 pub enum SpecType {
@@ -318,6 +322,54 @@ pub async fn clone_deployment(
     Ok(created)
 }
 
+/// Set or remove free-form annotations on an existing deployment. Entries in
+/// `set` are `key=value` pairs.
+pub async fn annotate_deployment(
+    device_name: &str,
+    deployment_id: &str,
+    set: &[String],
+    remove: &[String],
+) -> Result<DeploymentRevision> {
+    let (device_id, api_url, token, trust_invalid) = ctx_for_device(device_name).await?;
+
+    let mut revision =
+        server::get_deployment(&api_url, &token, trust_invalid, &device_id, deployment_id)
+            .await
+            .context("failed to fetch deployment")?;
+
+    for key in remove {
+        revision.annotations.remove(key);
+    }
+    for entry in set {
+        let (key, value) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid annotation '{}', expected key=value", entry))?;
+        let key = key.trim();
+        if key.is_empty() {
+            bail!("invalid annotation '{}': empty key", entry);
+        }
+        revision
+            .annotations
+            .insert(key.to_string(), value.to_string());
+    }
+
+    server::update_deployment(
+        &api_url,
+        &token,
+        trust_invalid,
+        &device_id,
+        deployment_id,
+        UpdateDeployRevisionBody {
+            revision: Some(revision.to_yaml()?),
+            ..Default::default()
+        },
+    )
+    .await
+    .context("failed to update deployment")?;
+
+    Ok(revision)
+}
+
 use clap::Parser;
 
 #[derive(Parser, Debug)]
@@ -542,6 +594,59 @@ async fn file_to_run_spec(path: &Path, spec_type: SpecType) -> Result<RunSpec> {
     Ok(res)
 }
 
+/// A deploy input file: a single job or a complete deployment.
+pub(crate) enum SpecFile {
+    Job(Box<RunSpec>),
+    Deployment(Box<DeploymentRevision>),
+}
+
+pub(crate) async fn load_spec_file(
+    path: &Path,
+    spec_type: SpecType,
+    name: Option<&str>,
+) -> Result<SpecFile> {
+    let res = match spec_type {
+        SpecType::Compose => {
+            SpecFile::Job(Box::new(compose_file_to_runspec_yaml(path, name).await?))
+        }
+        SpecType::Runspec => {
+            SpecFile::Job(Box::new(RunSpec::from_yaml(&load_file_to_string(path)?)?))
+        }
+        SpecType::Deployment => SpecFile::Deployment(Box::new(DeploymentRevision::from_yaml(
+            &load_file_to_string(path)?,
+        )?)),
+        SpecType::Auto => {
+            let s = load_file_to_string(path)?;
+            if is_docker_compose_yaml(&s) {
+                SpecFile::Job(Box::new(compose_file_to_runspec_yaml(path, name).await?))
+            } else if let Ok(rs) = RunSpec::from_yaml(&s) {
+                SpecFile::Job(Box::new(rs))
+            } else {
+                SpecFile::Deployment(Box::new(
+                    DeploymentRevision::from_yaml(&s).context("Failed to parse deployment YAML")?,
+                ))
+            }
+        }
+    };
+    Ok(res)
+}
+
+/// Parse a deploy file and check its expressions, printing each problem found.
+pub async fn validate_spec_file(path: &Path, spec_type: SpecType) -> Result<()> {
+    let (runs, errors) = match load_spec_file(path, spec_type, None).await? {
+        SpecFile::Job(spec) => (1, spec.expression_errors()),
+        SpecFile::Deployment(revision) => (revision.jobs.len(), revision.expression_errors()),
+    };
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("{}", error);
+        }
+        bail!("{} is invalid ({} errors)", path.display(), errors.len());
+    }
+    println!("{} is valid ({} runs)", path.display(), runs);
+    Ok(())
+}
+
 pub async fn compose_file_to_runspec_yaml(file: &Path, name: Option<&str>) -> Result<RunSpec> {
     // Read compose file (kept verbatim; we do not attempt to interpret/transform compose contents).
     let compose = tokio::fs::read_to_string(file)
@@ -581,6 +686,7 @@ pub async fn compose_file_to_runspec_yaml(file: &Path, name: Option<&str>) -> Re
             on_exit_codes: None,
         }),
         undo: None,
+        ..Default::default()
     };
 
     let up = Step {
@@ -598,6 +704,7 @@ pub async fn compose_file_to_runspec_yaml(file: &Path, name: Option<&str>) -> Re
             )),
             timeout: Some(Duration::from_secs(5 * 60)),
         }),
+        ..Default::default()
     };
 
     let stop = StopSpec {
@@ -610,6 +717,7 @@ pub async fn compose_file_to_runspec_yaml(file: &Path, name: Option<&str>) -> Re
             timeout: Some(Duration::from_secs(5 * 60)),
             retry: None,
             undo: None,
+            ..Default::default()
         }],
     };
 
@@ -619,6 +727,8 @@ pub async fn compose_file_to_runspec_yaml(file: &Path, name: Option<&str>) -> Re
                 "docker compose -f {} logs -f --timestamps -n 50",
                 file_name
             ))),
+            format: None,
+            metrics: Vec::new(),
         }),
         liveness: Some(ObserveHooks {
             every: Duration::from_secs(5),
@@ -700,3 +810,139 @@ pub async fn get_deployment_snapshot(
 
     Ok(snapshot)
 }
+
+/// Part of the deployment snapshot, e.g. one page of runs.
+pub async fn get_deployment_snapshot_part(
+    device_name: &str,
+    deployment_id: &str,
+    query: &SnapshotQuery,
+) -> Result<DeploymentStatusSnapshot> {
+    let (device_id, api_url, token, trust_invalid) = ctx_for_device(device_name).await?;
+
+    server::get_device_revision_snapshot_part(
+        &api_url,
+        &token,
+        trust_invalid,
+        &device_id,
+        deployment_id,
+        query,
+    )
+    .await
+    .context("failed to fetch deployment status")
+}
+
+/// Files the steps of `run_id` uploaded in `deployment_id`, newest first.
+pub async fn get_run_artifacts(
+    device_name: &str,
+    deployment_id: &str,
+    run_id: &str,
+) -> Result<Vec<StepArtifacts>> {
+    let (device_id, api_url, token, trust_invalid) = ctx_for_device(device_name).await?;
+
+    server::list_run_artifacts(
+        &api_url,
+        &token,
+        trust_invalid,
+        &device_id,
+        deployment_id,
+        run_id,
+    )
+    .await
+    .context("failed to fetch run artifacts")
+}
+
+/// Content of the newest artifact at `path`, or named like it when no
+/// artifact has that exact path.
+pub fn artifact_content(steps: &[StepArtifacts], path: &str) -> Result<Vec<u8>> {
+    use base64::Engine;
+
+    let path = path.trim_start_matches("./");
+    let artifacts = || steps.iter().flat_map(|s| s.artifacts.iter());
+    let artifact = artifacts()
+        .find(|a| a.path == path)
+        .or_else(|| artifacts().find(|a| Path::new(&a.path).file_name() == Some(OsStr::new(path))))
+        .ok_or_else(|| anyhow!("no artifact {}", path))?;
+    let Some(content) = &artifact.content else {
+        bail!(
+            "{} ({} bytes) was too large to upload; fetch it from the device",
+            artifact.path,
+            artifact.size
+        );
+    };
+    base64::engine::general_purpose::STANDARD
+        .decode(content)
+        .with_context(|| format!("artifact {} is not valid base64", artifact.path))
+}
+
+pub async fn list_core_dumps(device_name: &str) -> Result<Vec<CoreDump>> {
+    let (device_id, api_url, token, trust_invalid) = ctx_for_device(device_name).await?;
+
+    server::list_core_dumps(&api_url, &token, trust_invalid, &device_id)
+        .await
+        .context("failed to fetch core dumps")
+}
+
+/// The core dump with `id`, or the only one whose id starts with it.
+pub async fn get_core_dump(device_name: &str, id: &str) -> Result<CoreDump> {
+    let (device_id, api_url, token, trust_invalid) = ctx_for_device(device_name).await?;
+
+    let id = if id.len() == 24 {
+        id.to_string()
+    } else {
+        let cores = server::list_core_dumps(&api_url, &token, trust_invalid, &device_id).await?;
+        let matching: Vec<&CoreDump> = cores.iter().filter(|c| c.id.starts_with(id)).collect();
+        match matching.as_slice() {
+            [core] => core.id.clone(),
+            [] => bail!("no core dump {}", id),
+            _ => bail!("{} matches several core dumps", id),
+        }
+    };
+    server::get_core_dump(&api_url, &token, trust_invalid, &device_id, &id)
+        .await
+        .context("failed to fetch core dump")
+}
+
+/// The uncompressed core of `core`.
+pub fn core_dump_content(core: &CoreDump) -> Result<Vec<u8>> {
+    use base64::Engine;
+
+    let Some(content) = &core.report.content else {
+        bail!(
+            "the core of {} ({} bytes) was too large to upload",
+            core.report.executable,
+            core.report.size
+        );
+    };
+    let compressed = base64::engine::general_purpose::STANDARD
+        .decode(content)
+        .context("core dump is not valid base64")?;
+    zstd::decode_all(&compressed[..]).context("core dump is not valid zstd")
+}
+
+/// Block until the deployment snapshot settles on success or failure, or until
+/// `timeout` elapses. Resolves the device once and polls the server directly.
+pub async fn wait_for_deployment(
+    device_name: &str,
+    deployment_id: &str,
+    timeout: Duration,
+) -> Result<WaitOutcome> {
+    let (device_id, api_url, token, trust_invalid) = ctx_for_device(device_name).await?;
+
+    poll_until(timeout, WAIT_POLL_INTERVAL, || async {
+        let snapshot = server::get_device_revision_snapshot_part(
+            &api_url,
+            &token,
+            trust_invalid,
+            &device_id,
+            deployment_id,
+            &SnapshotQuery::outcome_only(),
+        )
+        .await?;
+        Ok(match snapshot.outcome {
+            Outcome::Success | Outcome::Skipped => Some(WaitOutcome::Reached),
+            Outcome::Failed => Some(WaitOutcome::Failed),
+            Outcome::Unknown => None,
+        })
+    })
+    .await
+}
@@ -1,9 +1,10 @@
 use anyhow::{Context, Result, anyhow};
 use m87_shared::deploy_spec::{
-    DeployReportKind, DeploymentRevision, DeploymentRevisionReport, ObserveHooks, OnFailure,
-    Outcome, RetrySpec, RollbackPolicy, RollbackReport, RunReport, RunSpec, RunState, RunType,
-    Step, StepReport, Undo, UndoMode, WorkdirMode,
+    DeployReportKind, DeploymentRevision, DeploymentRevisionReport, DriftReport, ObserveHooks,
+    OnFailure, Outcome, RetrySpec, RollbackPolicy, RollbackReport, RunReport, RunSpec, RunState,
+    RunType, Step, StepReport, Undo, UndoMode, WorkdirMode, content_hash,
 };
+use m87_shared::heartbeat::{ReportAck, ReportAckStatus, ReportDigest};
 use std::{
     collections::{BTreeMap, HashMap, HashSet},
     fmt::Display,
@@ -12,18 +13,32 @@ use std::{
     time::{Duration, Instant},
 };
 use tokio::{fs, io::AsyncWriteExt, sync::RwLock, time::sleep};
+use ulid::Ulid;
 
 use crate::{
-    device::log_manager::LogManager,
+    config::Config,
+    device::{
+        artifacts,
+        drift::{self, same_content},
+        fact_collectors::get_facts,
+        gc::{self, Reclaimed},
+        immutable,
+        log_manager::LogManager,
+        redaction,
+        step_executor::{StepContext, StepExecutorRegistry},
+    },
+    streams::stream_type::{PlanAction, PlanReply, PlannedRun},
     util::{
-        command::{RunCommandError, run_command},
+        command::{CommandFailed, RunCommandError, run_command},
+        disk,
         shutdown::SHUTDOWN,
     },
 };
 const MAX_TAIL_BYTES: usize = 4 * 1024; // 4KB
+const EVENT_RETRY_DELAY: Duration = Duration::from_secs(30);
 
-fn data_dir() -> Result<PathBuf> {
-    Ok(dirs::data_dir().context("data_dir")?.join("m87"))
+pub(crate) fn data_dir() -> Result<PathBuf> {
+    Config::data_dir()
 }
 
 fn events_dir() -> Result<PathBuf> {
@@ -36,13 +51,39 @@ fn pending_dir() -> Result<PathBuf> {
 fn inflight_dir() -> Result<PathBuf> {
     Ok(events_dir()?.join("inflight"))
 }
+fn dead_letter_dir() -> Result<PathBuf> {
+    Ok(events_dir()?.join("dead"))
+}
+fn event_seq_path() -> Result<PathBuf> {
+    Ok(events_dir()?.join("seq"))
+}
+
+fn ops_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("ops"))
+}
 
 async fn ensure_dirs() -> Result<()> {
     fs::create_dir_all(pending_dir()?).await?;
     fs::create_dir_all(inflight_dir()?).await?;
+    fs::create_dir_all(dead_letter_dir()?).await?;
+    fs::create_dir_all(ops_dir()?).await?;
     Ok(())
 }
 
+/// Operations queued locally (e.g. via `m87 local`) that the runtime applies on its next tick.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum LocalOp {
+    /// Forget that a run completed so the reconcile loop executes it again
+    Rerun {
+        run_id: String,
+        /// Write immutable files changed on the device again instead of
+        /// refusing to run
+        #[serde(default)]
+        accept_changes: bool,
+    },
+}
+
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct LocalRunState {
     pub consecutive_health_failures: u32,
@@ -58,6 +99,15 @@ pub struct LocalRunState {
     pub last_health: bool,
     #[serde(default)]
     pub last_alive: bool,
+    /// Drift last reported for the run, see [`drift::Drift::key`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drift: Vec<String>,
+    /// Hashes of the files written read-only, by path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>,
+    /// Write changed immutable files again on the next run
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub accept_changes: bool,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -350,14 +400,22 @@ impl RevisionStore {
 pub struct DeploymentManager {
     root_dir: PathBuf,
     dirty: Arc<RwLock<HashSet<String>>>,
+    /// Hashes of runs whose `when` condition did not match the device facts
+    skipped: Arc<RwLock<HashSet<String>>>,
     log_manager: LogManager,
     rollback_policy: Arc<RwLock<Option<RollbackPolicy>>>,
     deployment_started_at: Arc<RwLock<Option<Instant>>>,
+    executors: Arc<StepExecutorRegistry>,
 }
 
 impl DeploymentManager {
     /// Create a new UnitManager with a custom state store.
     pub async fn new() -> Result<Self> {
+        Self::with_executors(StepExecutorRegistry::default()).await
+    }
+
+    /// Like [`DeploymentManager::new`], but with a custom set of step executors.
+    pub async fn with_executors(executors: StepExecutorRegistry) -> Result<Self> {
         let _ = ensure_dirs().await?;
         let _ = recover_inflight().await?;
         let root_dir = data_dir()?;
@@ -369,9 +427,11 @@ impl DeploymentManager {
         Ok(Self {
             root_dir,
             dirty: Arc::new(RwLock::new(HashSet::new())),
+            skipped: Arc::new(RwLock::new(HashSet::new())),
             log_manager,
             rollback_policy: Arc::new(RwLock::new(rollback_policy)),
             deployment_started_at: Arc::new(RwLock::new(None)),
+            executors: Arc::new(executors),
         })
     }
 
@@ -384,27 +444,55 @@ impl DeploymentManager {
 
     /// Get reference to the log manager for external use (e.g., streams/logs routing)
     pub async fn start_log_follow(&self) -> Result<()> {
-        if let Some(spec) = RevisionStore::get_desired_config()? {
-            for (_, unit) in spec.get_job_map() {
-                if let Some(observer_spec) = &unit.observe {
-                    if let Some(log_spec) = &observer_spec.logs {
-                        let workdir = self.resolve_workdir(&unit).await?;
-                        self.log_manager
-                            .follow_start(unit.id, log_spec, unit.env, workdir)
-                            .await;
-                    }
-                }
-            }
-        }
+        self.follow_logs(true).await
+    }
+
+    pub async fn stop_log_follow(&self) -> Result<()> {
+        self.unfollow_logs(true).await
+    }
 
+    /// Follow the logs of runs that declare log metrics, without showing
+    /// them, while the metrics stream is open.
+    pub async fn start_metrics_follow(&self) -> Result<()> {
+        self.follow_logs(false).await
+    }
+
+    pub async fn stop_metrics_follow(&self) -> Result<()> {
+        self.unfollow_logs(false).await
+    }
+
+    /// Runs whose logs are followed: all with a log spec to show them, only
+    /// those with log metrics otherwise.
+    fn followed_runs(show: bool) -> Result<Vec<RunSpec>> {
+        let Some(spec) = RevisionStore::get_desired_config()? else {
+            return Ok(Vec::new());
+        };
+        Ok(spec
+            .get_job_map()
+            .into_values()
+            .filter(|unit| {
+                let logs = unit.observe.as_ref().and_then(|o| o.logs.as_ref());
+                logs.is_some_and(|l| show || !l.metrics.is_empty())
+            })
+            .collect())
+    }
+
+    async fn follow_logs(&self, show: bool) -> Result<()> {
+        for unit in Self::followed_runs(show)? {
+            let Some(log_spec) = unit.observe.as_ref().and_then(|o| o.logs.clone()) else {
+                continue;
+            };
+            let workdir = self.resolve_workdir(&unit).await?;
+            self.log_manager
+                .follow_start(unit.id, &log_spec, unit.env, workdir, show)
+                .await;
+        }
         Ok(())
     }
 
-    pub async fn stop_log_follow(&self) -> Result<()> {
-        if let Some(spec) = RevisionStore::get_desired_config()? {
-            for (_, unit) in spec.get_job_map() {
-                self.log_manager.follow_stop(unit.id).await;
-            }
+    async fn unfollow_logs(&self, show: bool) -> Result<()> {
+        for unit in Self::followed_runs(show)? {
+            self.log_manager.follow_stop(unit.id, show).await;
         }
         Ok(())
     }
@@ -425,6 +513,8 @@ impl DeploymentManager {
         };
 
         RevisionStore::set_config(&config)?;
+        // env values of the new runs are masked from now on
+        redaction::reload();
 
         let mut dirty = self.dirty.write().await;
 
@@ -461,6 +551,109 @@ impl DeploymentManager {
         Ok(())
     }
 
+    /// What [`Self::set_desired_units`] and the reconcile loop would do to
+    /// apply `candidate`, without running or writing anything.
+    pub async fn plan(&self, candidate: &DeploymentRevision) -> Result<PlanReply> {
+        let current = RevisionStore::get_desired_config()?;
+        let mut plan = PlanReply {
+            current_revision_id: current.as_ref().and_then(|c| c.id.clone()),
+            candidate_revision_id: candidate.id.clone(),
+            unchanged: current
+                .as_ref()
+                .is_some_and(|c| c.get_hash() == candidate.get_hash()),
+            ..Default::default()
+        };
+        if plan.unchanged {
+            return Ok(plan);
+        }
+        let held = match &current {
+            Some(spec) => spec.get_job_map(),
+            None => BTreeMap::new(),
+        };
+        // enabled runs of the held revision by id
+        let previous: HashMap<&str, &RunSpec> =
+            held.values().map(|spec| (spec.id.as_str(), spec)).collect();
+
+        for spec in &candidate.jobs {
+            let wd = self.get_workspace_path(spec)?;
+            let replaced = previous.get(spec.id.as_str()).copied();
+            let mut run = PlannedRun {
+                run_id: spec.id.clone(),
+                run_type: spec.run_type.clone(),
+                action: PlanAction::Add,
+                reason: None,
+                stop_steps: Vec::new(),
+                steps: Vec::new(),
+                files: Vec::new(),
+                workdir: wd.display().to_string(),
+            };
+            if !spec.enabled {
+                run.action = match replaced {
+                    Some(old) => {
+                        run.stop_steps = stop_step_labels(old);
+                        PlanAction::Remove
+                    }
+                    None => PlanAction::Skip,
+                };
+                run.reason = Some("disabled".to_string());
+                plan.runs.push(run);
+                continue;
+            }
+
+            let mut state = LocalRunState::load(&wd).unwrap_or_default();
+            if held.contains_key(&spec.get_hash()) {
+                if state.ran_successful {
+                    run.action = PlanAction::Keep;
+                    plan.runs.push(run);
+                    continue;
+                }
+                run.action = PlanAction::Rerun;
+                run.reason = Some("its last run did not complete".to_string());
+            } else if let Some(old) = replaced {
+                run.action = PlanAction::Change;
+                run.stop_steps = stop_step_labels(old);
+                // stopping a service forgets the state of its work dir
+                if matches!(old.run_type, RunType::Service) && self.get_workspace_path(old)? == wd {
+                    state = LocalRunState::default();
+                }
+            }
+
+            if let Some(reason) = self.skip_reason(spec).await {
+                run.action = PlanAction::Skip;
+                run.reason = Some(reason);
+            } else if matches!(spec.run_type, RunType::Observe) {
+                // observed only, nothing is executed
+            } else if state.ran_successful {
+                run.reason = Some("completed before in the same work dir".to_string());
+            } else {
+                run.steps = spec.steps.iter().map(step_label).collect();
+                for (rel, content) in &spec.files {
+                    if !same_content(&wd.join(rel), content).await {
+                        run.files.push(rel.clone());
+                    }
+                }
+            }
+            plan.runs.push(run);
+        }
+
+        for old in held.values() {
+            if candidate.jobs.iter().any(|spec| spec.id == old.id) {
+                continue;
+            }
+            plan.runs.push(PlannedRun {
+                run_id: old.id.clone(),
+                run_type: old.run_type.clone(),
+                action: PlanAction::Remove,
+                reason: None,
+                stop_steps: stop_step_labels(old),
+                steps: Vec::new(),
+                files: Vec::new(),
+                workdir: self.get_workspace_path(old)?.display().to_string(),
+            });
+        }
+        Ok(plan)
+    }
+
     async fn set_dirty_ids(&self) -> Result<()> {
         let desired = match RevisionStore::get_desired_config()? {
             Some(spec) => spec.get_job_map(),
@@ -485,6 +678,7 @@ impl DeploymentManager {
         tokio::spawn(async move {
             let mut next_health: HashMap<String, Instant> = HashMap::new();
             let mut next_liveness: HashMap<String, Instant> = HashMap::new();
+            let mut next_drift: HashMap<String, Instant> = HashMap::new();
 
             // coarse tick keeps CPU low; checks run only when due
             let tick = Duration::from_millis(250);
@@ -496,6 +690,11 @@ impl DeploymentManager {
                     break;
                 }
 
+                // 0) pick up operations queued locally
+                if let Err(e) = self.apply_local_ops().await {
+                    tracing::error!("failed to apply local ops: {e}");
+                }
+
                 // 1) reconcile dirty changes (run missing / stop old)
                 if let Err(e) = self.reconcile_dirty().await {
                     tracing::error!("reconcile error: {e}");
@@ -528,10 +727,21 @@ impl DeploymentManager {
                 };
 
                 if let Some(spec) = desired_spec {
+                    let skipped = self.skipped.read().await.clone();
                     for (id, u) in spec.get_job_map().iter() {
-                        if !u.enabled {
+                        if !u.enabled || skipped.contains(id) {
                             continue;
                         }
+                        if let Some(check) = &u.drift {
+                            let due = next_drift.get(id).copied().unwrap_or(now);
+                            if now >= due && !self.dirty.read().await.contains(id) {
+                                next_drift.insert(id.clone(), now + check.every);
+                                let revision_id = spec.id.clone().expect("revision id is required");
+                                if let Err(e) = self.check_drift(id, u, &revision_id).await {
+                                    tracing::warn!("drift check of {} failed: {e}", u.id);
+                                }
+                            }
+                        }
                         let Some(obs) = &u.observe else {
                             continue;
                         };
@@ -614,9 +824,6 @@ impl DeploymentManager {
                     }
                 }
                 Some(spec) => {
-                    // Ensure workdir exists for service/job/observe (observe may still need a cwd)
-                    let wd = self.resolve_workdir(spec).await?;
-
                     let desired_revision_id = match &deploy_spec {
                         Some(spec) => spec.id.clone().expect("revision id is required"),
                         None => {
@@ -625,6 +832,25 @@ impl DeploymentManager {
                         }
                     };
 
+                    if let Some(reason) = self.skip_reason(spec).await {
+                        tracing::info!("skipping run {}: {}", spec.id, reason);
+                        self.skipped.write().await.insert(id.clone());
+                        let _ = enqueue_event(DeployReportKind::RunReport(RunReport {
+                            run_id: spec.id.clone(),
+                            revision_id: desired_revision_id,
+                            outcome: Outcome::Skipped,
+                            report_time: now_ms_u64(),
+                            error: Some(reason),
+                        }))
+                        .await;
+                        self.dirty.write().await.remove(&id);
+                        continue;
+                    }
+                    self.skipped.write().await.remove(&id);
+
+                    // Ensure workdir exists for service/job/observe (observe may still need a cwd)
+                    let wd = self.resolve_workdir(spec).await?;
+
                     // Apply/stop based on type
                     match spec.run_type {
                         RunType::Observe => {
@@ -654,6 +880,17 @@ impl DeploymentManager {
         Ok(())
     }
 
+    /// Why `spec` should not run on this device, if its `when` condition is
+    /// false or cannot be evaluated.
+    async fn skip_reason(&self, spec: &RunSpec) -> Option<String> {
+        let condition = spec.when.as_deref()?;
+        match get_facts(false).await.evaluate_condition(condition) {
+            Ok(true) => None,
+            Ok(false) => Some(format!("condition not met: {}", condition)),
+            Err(e) => Some(format!("invalid condition '{}': {}", condition, e)),
+        }
+    }
+
     async fn maybe_run_job(&self, spec: &RunSpec, revision_id: &str, wd: &Path) -> Result<()> {
         // normal job
         self.execute_unit_steps(spec, revision_id, wd).await
@@ -685,6 +922,9 @@ impl DeploymentManager {
                 }
                 WorkdirMode::Ephemeral => {
                     let path = self.get_workspace_path(spec)?;
+                    if spec.immutable {
+                        immutable::unlock_tree(&path).await;
+                    }
                     tokio::fs::remove_dir_all(path).await?;
                 }
             }
@@ -699,7 +939,8 @@ impl DeploymentManager {
             return Ok(());
         }
         // materialize files (only if any)
-        self.materialize_files(spec, wd).await?;
+        self.materialize_files(spec, revision_id, wd, &mut st)
+            .await?;
 
         let res = match self
             .execute_steps(
@@ -933,6 +1174,52 @@ impl DeploymentManager {
         }
     }
 
+    /// Report when the drift of a run changed since its last check. With
+    /// `remediate`, a drifted run is marked to run again, which rewrites its
+    /// files and runs its steps.
+    async fn check_drift(&self, hash: &str, spec: &RunSpec, revision_id: &str) -> Result<()> {
+        let Some(check) = &spec.drift else {
+            return Ok(());
+        };
+        let wd = self.resolve_workdir(spec).await?;
+        let mut st = LocalRunState::load(&wd)?;
+        if !st.ran_successful {
+            // not applied yet, or about to run again
+            return Ok(());
+        }
+
+        let found = drift::find_drift(spec, &wd).await;
+        let key = found.key();
+        if key == st.drift {
+            return Ok(());
+        }
+        let mut report = DriftReport {
+            run_id: spec.id.clone(),
+            revision_id: revision_id.to_string(),
+            files: found.files,
+            stopped: found.stopped,
+            remediated: false,
+            error: None,
+            report_time: now_ms_u64(),
+        };
+        if report.is_drifted() {
+            tracing::warn!("run {} drifted: {}", spec.id, report.summary());
+        } else {
+            tracing::info!("run {} no longer drifts", spec.id);
+        }
+
+        if report.is_drifted() && check.remediate {
+            st.ran_successful = false;
+            self.dirty.write().await.insert(hash.to_string());
+            report.remediated = true;
+            tracing::info!("restoring run {}", spec.id);
+        }
+        st.drift = key;
+        LocalRunState::save(&wd, &st)?;
+
+        enqueue_event(DeployReportKind::DriftReport(report)).await
+    }
+
     async fn check_rollback_on_observe_failure(
         &self,
         kind: ObserveKind,
@@ -1120,7 +1407,17 @@ impl DeploymentManager {
 
         let attempts = retry.attempts.max(1);
         for i in 0..attempts {
-            let res = run_step(unit_id, wd, env, step, revision_id, i, MAX_TAIL_BYTES).await;
+            let res = run_step(
+                &self.executors,
+                unit_id,
+                wd,
+                env,
+                step,
+                revision_id,
+                i,
+                MAX_TAIL_BYTES,
+            )
+            .await;
             match res {
                 Ok(()) => return Ok(()),
                 Err(e) => {
@@ -1144,41 +1441,90 @@ impl DeploymentManager {
     }
 
     fn get_workspace_path(&self, spec: &RunSpec) -> Result<PathBuf> {
-        let base = if let Some(wd) = &spec.workdir {
-            if let Some(p) = &wd.path {
-                PathBuf::from(p)
-            } else {
-                self.root_dir.join("jobs").join(&spec.id)
-            }
-        } else {
-            self.root_dir.join("jobs").join(&spec.id)
-        };
+        Ok(workspace_path(&self.root_dir, spec))
+    }
 
-        // choose persistent/ephemeral
-        let mode = spec
-            .workdir
-            .as_ref()
-            .map(|w| w.mode.clone())
-            .unwrap_or(WorkdirMode::Persistent);
-
-        let resolved = match mode {
-            WorkdirMode::Persistent => base,
-            WorkdirMode::Ephemeral => self
-                .root_dir
-                .join("tmp")
-                .join("jobs")
-                .join(&spec.get_hash()),
+    /// Apply operations queued by `m87 local` while the runtime was busy or offline.
+    /// Each leaves the queue once applied; on an error it and the ones after it
+    /// stay queued for the next tick.
+    async fn apply_local_ops(&self) -> Result<()> {
+        let ops = read_json_dir::<LocalOp>(&ops_dir()?).await?;
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let desired = match RevisionStore::get_desired_config()? {
+            Some(spec) => spec.get_job_map(),
+            None => BTreeMap::new(),
         };
 
-        Ok(resolved)
+        for (path, op) in ops {
+            self.apply_local_op(op, &desired).await?;
+            fs::remove_file(&path).await.context("delete op")?;
+        }
+        Ok(())
     }
 
-    async fn materialize_files(&self, spec: &RunSpec, wd: &Path) -> Result<()> {
-        if spec.files.is_empty() {
-            return Ok(());
+    async fn apply_local_op(&self, op: LocalOp, desired: &BTreeMap<String, RunSpec>) -> Result<()> {
+        match op {
+            LocalOp::Rerun {
+                run_id,
+                accept_changes,
+            } => {
+                let Some(spec) = desired.values().find(|u| u.id == run_id) else {
+                    tracing::warn!("queued rerun for unknown run {}", run_id);
+                    return Ok(());
+                };
+                let wd = self.resolve_workdir(spec).await?;
+                let mut st = LocalRunState::load(&wd)?;
+                st.ran_successful = false;
+                st.accept_changes |= accept_changes;
+                LocalRunState::save(&wd, &st)?;
+                self.dirty.write().await.insert(spec.get_hash());
+                tracing::info!("re-running {} (queued locally)", run_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the run's files. Files of immutable runs are checked against the
+    /// hashes they were written with first and locked afterwards.
+    async fn materialize_files(
+        &self,
+        spec: &RunSpec,
+        revision_id: &str,
+        wd: &Path,
+        st: &mut LocalRunState,
+    ) -> Result<()> {
+        if spec.immutable && !st.accept_changes {
+            let tampered = immutable::tampered_files(wd, &st.files).await;
+            if !tampered.is_empty() {
+                let error = format!(
+                    "files changed on the device: {}; run `m87 local rerun {} --accept-changes` \
+                     on the device to write them again",
+                    tampered.join(", "),
+                    spec.id
+                );
+                tracing::error!("Not running {}: {}", spec.id, error);
+                enqueue_event(files_failure(spec, revision_id, error.clone())).await?;
+                return Err(anyhow!(error));
+            }
         }
+
+        // files already on disk as sent are left alone, keeping their mtime
+        let mut changed = Vec::new();
         for (rel, content) in &spec.files {
             let p = wd.join(rel);
+            if !same_content(&p, content).await {
+                changed.push((rel, p, content));
+            }
+        }
+        let needed = changed.iter().map(|(_, _, c)| c.len() as u64).sum();
+        if let Err(e) = disk::check(wd, needed) {
+            tracing::error!("Not writing files of {}: {}", spec.id, e);
+            enqueue_event(files_failure(spec, revision_id, e.to_string())).await?;
+            return Err(e.into());
+        }
+        for (rel, p, content) in changed {
             if let Some(parent) = p.parent() {
                 let res = tokio::fs::create_dir_all(parent).await;
                 if let Err(e) = &res {
@@ -1186,35 +1532,270 @@ impl DeploymentManager {
                 }
                 res?;
             }
+            if st.files.contains_key(rel) {
+                immutable::unlock(&p).await;
+            }
             tokio::fs::write(&p, content).await?;
         }
+
+        st.files.clear();
+        if spec.immutable {
+            for (rel, content) in &spec.files {
+                immutable::lock(&wd.join(rel)).await;
+                st.files.insert(rel.clone(), content_hash(content));
+            }
+        }
+        st.accept_changes = false;
         Ok(())
     }
 }
 
-pub async fn enqueue_event(event: DeployReportKind) -> Result<()> {
+/// Failed `files` step of `spec`.
+fn files_failure(spec: &RunSpec, revision_id: &str, error: String) -> DeployReportKind {
+    DeployReportKind::StepReport(StepReport {
+        revision_id: revision_id.to_string(),
+        run_id: spec.id.clone(),
+        name: Some("files".to_string()),
+        attempts: 1,
+        log_tail: String::new(),
+        exit_code: None,
+        success: false,
+        is_undo: false,
+        error: Some(error),
+        report_time: now_ms_u64(),
+        artifacts: Vec::new(),
+    })
+}
+
+fn workspace_path(root_dir: &Path, spec: &RunSpec) -> PathBuf {
+    let base = if let Some(wd) = &spec.workdir {
+        if let Some(p) = &wd.path {
+            PathBuf::from(p)
+        } else {
+            root_dir.join("jobs").join(&spec.id)
+        }
+    } else {
+        root_dir.join("jobs").join(&spec.id)
+    };
+
+    // choose persistent/ephemeral
+    let mode = spec
+        .workdir
+        .as_ref()
+        .map(|w| w.mode.clone())
+        .unwrap_or(WorkdirMode::Persistent);
+
+    match mode {
+        WorkdirMode::Persistent => base,
+        WorkdirMode::Ephemeral => root_dir.join("tmp").join("jobs").join(spec.get_hash()),
+    }
+}
+
+/// The desired revision with the local state of each of its runs.
+pub type LocalRunStates = (DeploymentRevision, Vec<(RunSpec, LocalRunState)>);
+
+/// Read the local state of every run in the desired revision without a running runtime.
+pub fn local_run_states() -> Result<Option<LocalRunStates>> {
+    let Some(revision) = RevisionStore::get_desired_config()? else {
+        return Ok(None);
+    };
+    let root = data_dir()?;
+    let states = revision
+        .jobs
+        .iter()
+        .map(|spec| {
+            let st = LocalRunState::load(&workspace_path(&root, spec)).unwrap_or_default();
+            (spec.clone(), st)
+        })
+        .collect();
+    Ok(Some((revision, states)))
+}
+
+async fn read_json_dir<T: serde::de::DeserializeOwned>(dir: &Path) -> Result<Vec<(PathBuf, T)>> {
+    let mut files = Vec::new();
+    let mut rd = match fs::read_dir(dir).await {
+        Ok(rd) => rd,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    while let Some(e) = rd.next_entry().await? {
+        let p = e.path();
+        if p.extension().and_then(|s| s.to_str()) == Some("json") {
+            files.push(p);
+        }
+    }
+    files.sort(); // filenames start with a timestamp
+
+    let mut out = Vec::new();
+    for p in files {
+        let bytes = fs::read(&p).await?;
+        match serde_json::from_slice::<T>(&bytes) {
+            Ok(v) => out.push((p, v)),
+            Err(e) => tracing::warn!("skipping unreadable {}: {}", p.display(), e),
+        }
+    }
+    Ok(out)
+}
+
+/// Reports recorded on the device that have not been delivered to the server yet.
+pub async fn list_queued_events() -> Result<Vec<DeployReportKind>> {
+    Ok(list_events()
+        .await?
+        .into_iter()
+        .filter(|e| e.state != EventState::Dead)
+        .map(|e| e.event.report)
+        .collect())
+}
+
+/// Names of queued operations. Monotonic, so operations queued within the same
+/// millisecond still apply in order, and random across processes.
+static OP_IDS: std::sync::Mutex<ulid::Generator> = std::sync::Mutex::new(ulid::Generator::new());
+
+/// Queue an operation for the runtime to apply on its next reconcile tick.
+pub async fn enqueue_local_op(op: LocalOp) -> Result<()> {
     ensure_dirs().await?;
+    write_local_op(&ops_dir()?, &op).await
+}
 
-    let id = format!("{}", now_ms_u64());
-    let pending = pending_dir()?.join(format!("{id}.json"));
-    let tmp = pending.with_extension("json.tmp");
+async fn write_local_op(dir: &Path, op: &LocalOp) -> Result<()> {
+    let id = OP_IDS
+        .lock()
+        .map_err(|_| anyhow!("op id generator poisoned"))?
+        .generate()
+        .context("generate op id")?;
+    let path = dir.join(format!("{id}.json"));
+    let tmp = path.with_extension("json.tmp");
+    let mut f = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .await
+        .context("create op")?;
+    f.write_all(&serde_json::to_vec(op).context("serialize op")?)
+        .await
+        .context("write op")?;
+    f.flush().await.context("flush op")?;
+    drop(f);
+    fs::rename(&tmp, &path)
+        .await
+        .context("atomic rename tmp->ops")?;
+    Ok(())
+}
 
-    let bytes = serde_json::to_vec(&event).context("serialize event")?;
+pub async fn list_local_ops() -> Result<Vec<LocalOp>> {
+    Ok(read_json_dir(&ops_dir()?)
+        .await?
+        .into_iter()
+        .map(|(_, op)| op)
+        .collect())
+}
+
+/// A deploy report waiting in the event queue.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct QueuedEvent {
+    pub report: DeployReportKind,
+    /// How often the event was sent to the server
+    #[serde(default)]
+    pub deliveries: u32,
+    /// Why the server rejected it, for dead-lettered events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<String>,
+    /// Position in this device's report sequence (unset for events queued by older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+/// Older versions stored the bare report.
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum StoredEvent {
+    Queued(QueuedEvent),
+    Bare(DeployReportKind),
+}
+
+impl From<StoredEvent> for QueuedEvent {
+    fn from(ev: StoredEvent) -> Self {
+        match ev {
+            StoredEvent::Queued(ev) => ev,
+            StoredEvent::Bare(report) => QueuedEvent {
+                report,
+                deliveries: 0,
+                rejected: None,
+                seq: None,
+            },
+        }
+    }
+}
+
+async fn read_event(path: &Path) -> Result<QueuedEvent> {
+    let bytes = fs::read(path).await.context("read event")?;
+    let ev: StoredEvent = serde_json::from_slice(&bytes).context("parse event")?;
+    Ok(ev.into())
+}
+
+async fn write_event(path: &Path, event: &QueuedEvent) -> Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    let bytes = serde_json::to_vec(event).context("serialize event")?;
 
     let mut f = fs::File::create(&tmp).await.context("create tmp")?;
     f.write_all(&bytes).await.context("write tmp")?;
     f.flush().await.context("flush tmp")?;
     drop(f);
 
-    fs::rename(&tmp, &pending)
-        .await
-        .context("atomic rename tmp->pending")?;
+    fs::rename(&tmp, path).await.context("atomic rename tmp")?;
     Ok(())
 }
 
+/// Last sequence number handed out, loaded from disk on first use.
+static EVENT_SEQ: tokio::sync::Mutex<Option<u64>> = tokio::sync::Mutex::const_new(None);
+
+/// Next report sequence number. Persisted, so it keeps increasing across
+/// restarts even when the device clock jumps back.
+async fn next_event_seq() -> Result<u64> {
+    let mut last = EVENT_SEQ.lock().await;
+    let path = event_seq_path()?;
+    let current = match *last {
+        Some(n) => n,
+        None => match fs::read_to_string(&path).await {
+            Ok(s) => s.trim().parse().context("parse event seq")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e).context("read event seq"),
+        },
+    };
+    let next = current + 1;
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, next.to_string())
+        .await
+        .context("write event seq")?;
+    fs::rename(&tmp, &path)
+        .await
+        .context("atomic rename event seq")?;
+    *last = Some(next);
+    Ok(next)
+}
+
+pub async fn enqueue_event(mut event: DeployReportKind) -> Result<()> {
+    ensure_dirs().await?;
+    redaction::redact_report(&mut event);
+
+    // ULIDs sort by creation time, so the oldest event is still claimed first
+    let id = Ulid::new().to_string();
+    let pending = pending_dir()?.join(format!("{id}.json"));
+    let event = QueuedEvent {
+        report: event,
+        deliveries: 0,
+        rejected: None,
+        seq: Some(next_event_seq().await?),
+    };
+    write_event(&pending, &event).await
+}
+
 pub struct ClaimedEvent {
     pub path: PathBuf, // inflight file path
     pub report: DeployReportKind,
+    /// Idempotency key sent with the report; stable across retries
+    pub key: String,
+    pub seq: Option<u64>,
 }
 
 pub async fn recover_inflight() -> Result<()> {
@@ -1248,7 +1829,7 @@ pub async fn claim_next_event() -> Result<Option<ClaimedEvent>> {
             files.push(p);
         }
     }
-    files.sort(); // works if filename starts with timestamp
+    files.sort(); // works if filename starts with timestamp (or is a ULID)
 
     let Some(p) = files.first().cloned() else {
         return Ok(None);
@@ -1259,22 +1840,94 @@ pub async fn claim_next_event() -> Result<Option<ClaimedEvent>> {
         .await
         .context("claim rename pending->inflight")?;
 
-    let bytes = fs::read(&inflight_path).await.context("read inflight")?;
-    let event: DeployReportKind = serde_json::from_slice(&bytes).context("parse inflight")?;
+    let mut event = read_event(&inflight_path).await?;
+    event.deliveries += 1;
+    write_event(&inflight_path, &event).await?;
+
+    // the file name is the key (ULID, or a timestamp for events queued by older versions)
+    let key = inflight_path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string();
 
     Ok(Some(ClaimedEvent {
         path: inflight_path,
-        report: event,
+        report: event.report,
+        key,
+        seq: event.seq,
     }))
 }
 
-pub async fn ack_event(claimed: &ClaimedEvent) -> Result<()> {
-    fs::remove_file(&claimed.path)
+fn inflight_path(key: &str) -> Result<PathBuf> {
+    Ok(inflight_dir()?.join(format!("{key}.json")))
+}
+
+/// Delete an inflight event once the server acknowledged storing it.
+pub async fn ack_event(key: &str) -> Result<()> {
+    fs::remove_file(inflight_path(key)?)
         .await
         .context("delete inflight")?;
     Ok(())
 }
 
+/// Move an inflight event the server rejected as invalid to the dead-letter directory.
+pub async fn dead_letter_event(key: &str, reason: &str) -> Result<()> {
+    ensure_dirs().await?;
+    let path = inflight_path(key)?;
+    let mut event = read_event(&path).await?;
+    event.rejected = Some(reason.to_string());
+    write_event(&dead_letter_dir()?.join(format!("{key}.json")), &event).await?;
+    fs::remove_file(&path).await.context("delete inflight")?;
+    Ok(())
+}
+
+/// Queue an inflight event for another delivery.
+pub async fn release_event(key: &str) -> Result<()> {
+    let target = pending_dir()?.join(format!("{key}.json"));
+    fs::rename(inflight_path(key)?, &target)
+        .await
+        .context("release rename inflight->pending")?;
+    Ok(())
+}
+
+/// Settle an inflight event once the server answered the heartbeat that carried it.
+pub async fn settle_event(key: &str, ack: Option<&ReportAck>) -> Result<()> {
+    let Some(ack) = ack else {
+        // servers without acks answer only after storing the report
+        return ack_event(key).await;
+    };
+    if ack.idempotency_key != key {
+        tracing::warn!(
+            "ack for event {} while waiting for {}",
+            ack.idempotency_key,
+            key
+        );
+        return Ok(());
+    }
+    match &ack.status {
+        ReportAckStatus::Stored { report_id } => {
+            tracing::debug!("event {} stored as report {}", key, report_id);
+            ack_event(key).await
+        }
+        ReportAckStatus::Rejected { reason } => {
+            tracing::warn!("event {} rejected by the server: {}", key, reason);
+            dead_letter_event(key, reason).await
+        }
+        ReportAckStatus::Retry => {
+            // give the server some time before the next delivery
+            let key = key.to_string();
+            tokio::spawn(async move {
+                sleep(EVENT_RETRY_DELAY).await;
+                if let Err(e) = release_event(&key).await {
+                    tracing::error!("Failed to requeue event {}: {}", key, e);
+                }
+            });
+            Ok(())
+        }
+    }
+}
+
 pub async fn on_new_event() -> Option<ClaimedEvent> {
     loop {
         // Try immediately (covers backlog + missed cycles)
@@ -1291,7 +1944,161 @@ pub async fn on_new_event() -> Option<ClaimedEvent> {
     }
 }
 
+/// Where a queued event currently is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventState {
+    Pending,
+    Inflight,
+    Dead,
+}
+
+impl Display for EventState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            EventState::Pending => "pending",
+            EventState::Inflight => "inflight",
+            EventState::Dead => "dead",
+        })
+    }
+}
+
+pub struct EventEntry {
+    pub key: String,
+    pub state: EventState,
+    pub event: QueuedEvent,
+}
+
+/// All events in the queue and the dead-letter directory, oldest first.
+pub async fn list_events() -> Result<Vec<EventEntry>> {
+    let mut out = Vec::new();
+    for (state, dir) in [
+        (EventState::Inflight, inflight_dir()?),
+        (EventState::Pending, pending_dir()?),
+        (EventState::Dead, dead_letter_dir()?),
+    ] {
+        for (path, ev) in read_json_dir::<StoredEvent>(&dir).await? {
+            let key = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or_default()
+                .to_string();
+            out.push(EventEntry {
+                key,
+                state,
+                event: ev.into(),
+            });
+        }
+    }
+    Ok(out)
+}
+
+/// Digest of the reports not stored by the server yet.
+pub async fn report_digest() -> Result<ReportDigest> {
+    let events = list_events().await?;
+    Ok(ReportDigest::from_reports(
+        events
+            .iter()
+            .filter(|e| e.state != EventState::Dead)
+            .map(|e| &e.event.report),
+    ))
+}
+
+/// Delete dead-lettered reports older than `max_age`, then the oldest
+/// dead-lettered and after them the oldest undelivered reports until the
+/// queue fits in `max_bytes` (0 disables a limit). Inflight reports are kept.
+pub async fn gc_events(max_age: Duration, max_bytes: u64) -> Result<Reclaimed> {
+    let mut reclaimed = Reclaimed::default();
+    let mut kept = Vec::new();
+    for file in gc::files_by_name(&dead_letter_dir()?).await? {
+        if file.older_than(max_age) {
+            reclaimed.remove(&file).await;
+        } else {
+            kept.push((EventState::Dead, file));
+        }
+    }
+    if max_bytes == 0 {
+        return Ok(reclaimed);
+    }
+
+    for file in gc::files_by_name(&pending_dir()?).await? {
+        kept.push((EventState::Pending, file));
+    }
+    let mut total: u64 = kept.iter().map(|(_, f)| f.size).sum();
+    for (state, file) in &kept {
+        if total <= max_bytes {
+            break;
+        }
+        if *state == EventState::Pending {
+            tracing::warn!(
+                "report queue exceeds its size limit, dropping undelivered {}",
+                file.path.display()
+            );
+        }
+        reclaimed.remove(file).await;
+        total -= file.size;
+    }
+    Ok(reclaimed)
+}
+
+/// Remove workdirs under `jobs/` and `tmp/jobs/` that neither the desired nor
+/// the previous revision uses and that did not change for `retention` (zero
+/// disables this). Workdirs at a custom path are never removed.
+pub async fn gc_workdirs(retention: Duration) -> Result<Reclaimed> {
+    let mut reclaimed = Reclaimed::default();
+    if retention.is_zero() {
+        return Ok(reclaimed);
+    }
+    let root = data_dir()?;
+    let mut referenced = HashSet::new();
+    let revisions = [
+        RevisionStore::get_desired_config()?,
+        RevisionStore::get_previous_config()?,
+    ];
+    for revision in revisions.iter().flatten() {
+        referenced.extend(revision.jobs.iter().map(|spec| workspace_path(&root, spec)));
+    }
+
+    for dir in [root.join("jobs"), root.join("tmp").join("jobs")] {
+        let mut rd = match fs::read_dir(&dir).await {
+            Ok(rd) => rd,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(e) = rd.next_entry().await? {
+            let path = e.path();
+            if referenced.contains(&path) || !e.file_type().await.is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let usage = gc::dir_usage(path.clone()).await;
+            if usage.modified.elapsed().is_ok_and(|idle| idle > retention) {
+                tracing::info!("removing orphaned workdir {}", path.display());
+                // files of immutable runs cannot be removed otherwise
+                immutable::unlock_tree(&path).await;
+                reclaimed.remove_dir(&path).await;
+            }
+        }
+    }
+    Ok(reclaimed)
+}
+
+/// Stop steps run when the service `spec` is replaced or removed.
+fn stop_step_labels(spec: &RunSpec) -> Vec<String> {
+    match (&spec.run_type, &spec.stop) {
+        (RunType::Service, Some(stop)) => stop.steps.iter().map(step_label).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn step_label(step: &Step) -> String {
+    match (&step.name, &step.uses) {
+        (Some(name), _) => name.clone(),
+        (None, Some(uses)) => uses.clone(),
+        (None, None) => format!("{}", step.run),
+    }
+}
+
 async fn run_step(
+    executors: &StepExecutorRegistry,
     unit_id: &str,
     wd: &Path,
     env: &BTreeMap<String, String>,
@@ -1300,12 +2107,29 @@ async fn run_step(
     i: u32,
     max_tail_bytes: usize,
 ) -> Result<()> {
-    tracing::info!(
-        "running step {}. Attempt {}",
-        step.name.clone().unwrap_or(format!("{}", step.run)),
-        i + 1
-    );
-    let res = run_command(unit_id, wd, env, &step.run, step.timeout, max_tail_bytes).await;
+    tracing::info!("running step {}. Attempt {}", step_label(step), i + 1);
+    let ctx = StepContext {
+        run_id: unit_id,
+        wd,
+        env,
+        timeout: step.timeout,
+        max_tail_bytes,
+    };
+    // fail before an image pull or file write eats into the reserve; images
+    // land on the root filesystem, other writes mostly in the workdir
+    let guard = disk::check(wd, 0).and_then(|_| disk::check(Path::new("/"), 0));
+    let res = match guard {
+        Ok(()) => executors.execute(&ctx, step).await,
+        Err(e) => Err(RunCommandError::Failed(CommandFailed {
+            run_id: unit_id.to_string(),
+            exit_code: None,
+            timed_out: false,
+            stdout_tail: String::new(),
+            stderr_tail: String::new(),
+            combined_tail: String::new(),
+            error: Some(e.to_string()),
+        })),
+    };
     let res = match res {
         Ok(tail) => Ok(StepReport {
             revision_id: revision_id.to_string(),
@@ -1318,21 +2142,14 @@ async fn run_step(
             is_undo: false,
             error: None,
             report_time: now_ms_u64(),
+            artifacts: artifacts::collect(wd, &step.outputs).await,
         }),
         Err(RunCommandError::Other(e)) => {
-            tracing::error!(
-                "Failed to run step {}: {}",
-                step.name.clone().unwrap_or(format!("{}", step.run)),
-                e
-            );
+            tracing::error!("Failed to run step {}: {}", step_label(step), e);
             Err(e)
         }
         Err(RunCommandError::Io(e)) => {
-            tracing::error!(
-                "Failed to run step {}: {}",
-                step.name.clone().unwrap_or(format!("{}", step.run)),
-                e
-            );
+            tracing::error!("Failed to run step {}: {}", step_label(step), e);
             Err(e.into())
         }
         Err(RunCommandError::Failed(e)) => Ok(StepReport {
@@ -1346,6 +2163,7 @@ async fn run_step(
             is_undo: false,
             error: e.error,
             report_time: now_ms_u64(),
+            artifacts: artifacts::collect(wd, &step.outputs).await,
         }),
     };
     match res {
@@ -1375,10 +2193,7 @@ async fn run_undo(
     revision_id: &str,
     max_tail_bytes: usize,
 ) -> Result<()> {
-    tracing::info!(
-        "undo step {}",
-        step.name.clone().unwrap_or(format!("{}", step.run))
-    );
+    tracing::info!("undo step {}", step_label(step));
     let res = run_command(unit_id, wd, env, &undo.run, undo.timeout, max_tail_bytes).await;
     let res = match res {
         Ok(tail) => Ok(StepReport {
@@ -1392,6 +2207,7 @@ async fn run_undo(
             success: true,
             error: None,
             report_time: now_ms_u64(),
+            artifacts: Vec::new(),
         }),
         Err(RunCommandError::Other(e)) => Err(e),
         Err(RunCommandError::Io(e)) => Err(e.into()),
@@ -1406,6 +2222,7 @@ async fn run_undo(
             success: false,
             error: e.error,
             report_time: now_ms_u64(),
+            artifacts: Vec::new(),
         }),
     };
     match res {
@@ -1434,3 +2251,49 @@ fn merge_log_tails(primary: Option<String>, secondary: Option<String>) -> Option
         (None, None) => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_event_reads_both_formats() {
+        let legacy =
+            r#"{"type":"RollbackReport","data":{"revision_id":"r1","new_revision_id":null}}"#;
+        let ev: QueuedEvent = serde_json::from_str::<StoredEvent>(legacy).unwrap().into();
+        assert_eq!(ev.report.get_revision_id(), "r1");
+        assert_eq!(ev.deliveries, 0);
+
+        let queued = QueuedEvent {
+            deliveries: 3,
+            rejected: Some("revision not found".to_string()),
+            ..ev
+        };
+        let json = serde_json::to_string(&queued).unwrap();
+        let back: QueuedEvent = serde_json::from_str::<StoredEvent>(&json).unwrap().into();
+        assert_eq!(back.deliveries, 3);
+        assert_eq!(back.rejected.as_deref(), Some("revision not found"));
+    }
+
+    #[tokio::test]
+    async fn test_local_ops_queued_at_once_all_apply_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..50 {
+            let op = LocalOp::Rerun {
+                run_id: format!("run-{i}"),
+                accept_changes: false,
+            };
+            write_local_op(dir.path(), &op).await.unwrap();
+        }
+
+        let ops = read_json_dir::<LocalOp>(dir.path()).await.unwrap();
+        let run_ids: Vec<String> = ops
+            .into_iter()
+            .map(|(_, op)| match op {
+                LocalOp::Rerun { run_id, .. } => run_id,
+            })
+            .collect();
+        let expected: Vec<String> = (0..50).map(|i| format!("run-{i}")).collect();
+        assert_eq!(run_ids, expected);
+    }
+}
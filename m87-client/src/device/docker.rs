@@ -84,7 +84,7 @@ async fn spawn_socket_forward(device: &str, endpoint: &PathBuf) -> Result<()> {
 
     let device = device.to_string();
     tokio::spawn(async move {
-        if let Err(e) = open_local_forward(&device, vec![spec]).await {
+        if let Err(e) = open_local_forward(&device, vec![spec], false).await {
             eprintln!("Docker socket forward exited with error: {e}");
         }
     });
@@ -0,0 +1,97 @@
+//! Drift checks of runs: materialized files compared with the run spec by
+//! hash, and the containers and units of the run's `drift` spec that must be
+//! running. The deployment manager reports what changed between checks.
+
+use std::path::Path;
+use std::time::Duration;
+
+use m87_shared::deploy_spec::{RunSpec, content_hash};
+use tokio::process::Command;
+use tracing::warn;
+
+use crate::util::command::{binary_exists, safe_run_command};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What differs between a run spec and the device.
+#[derive(Debug, Default, PartialEq)]
+pub struct Drift {
+    /// Files changed or removed, relative to the workdir
+    pub files: Vec<String>,
+    /// Containers and units that are not running
+    pub stopped: Vec<String>,
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.stopped.is_empty()
+    }
+
+    /// Everything found, to tell whether the drift changed since the last check
+    pub fn key(&self) -> Vec<String> {
+        let files = self.files.iter().map(|f| format!("file:{}", f));
+        let stopped = self.stopped.iter().map(|s| format!("stopped:{}", s));
+        files.chain(stopped).collect()
+    }
+}
+
+/// Compare `spec` with the device; `wd` is the run's workdir.
+pub async fn find_drift(spec: &RunSpec, wd: &Path) -> Drift {
+    let mut drift = Drift::default();
+    for (rel, content) in &spec.files {
+        if !same_content(&wd.join(rel), content).await {
+            drift.files.push(rel.clone());
+        }
+    }
+    let Some(check) = &spec.drift else {
+        return drift;
+    };
+    for container in &check.containers {
+        if container_running(container).await == Some(false) {
+            drift.stopped.push(container.clone());
+        }
+    }
+    for unit in &check.units {
+        if unit_active(unit).await == Some(false) {
+            drift.stopped.push(unit.clone());
+        }
+    }
+    drift
+}
+
+/// Whether `path` holds `content`, by size and then [`content_hash`].
+pub async fn same_content(path: &Path, content: &str) -> bool {
+    match tokio::fs::metadata(path).await {
+        Ok(meta) if meta.len() == content.len() as u64 => {}
+        _ => return false,
+    }
+    match tokio::fs::read(path).await {
+        Ok(existing) => content_hash(&existing) == content_hash(content),
+        Err(_) => false,
+    }
+}
+
+/// `None` if docker cannot be asked; a missing container is not running.
+async fn container_running(name: &str) -> Option<bool> {
+    if !binary_exists("docker") {
+        warn!("cannot check container {}: docker is not installed", name);
+        return None;
+    }
+    let mut cmd = Command::new("docker");
+    cmd.args(["inspect", "--format", "{{.State.Running}}", name])
+        .kill_on_drop(true);
+    let output = safe_run_command(cmd, COMMAND_TIMEOUT).await.ok()?;
+    Some(output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+/// `None` without systemd.
+async fn unit_active(unit: &str) -> Option<bool> {
+    if !binary_exists("systemctl") {
+        warn!("cannot check unit {}: systemctl is not installed", unit);
+        return None;
+    }
+    let mut cmd = Command::new("systemctl");
+    cmd.args(["is-active", "--quiet", unit]).kill_on_drop(true);
+    let output = safe_run_command(cmd, COMMAND_TIMEOUT).await.ok()?;
+    Some(output.status.success())
+}
@@ -0,0 +1,416 @@
+//! Egress allowlists of runs. For runs of the desired revision with an
+//! `egress` policy the runtime keeps the nftables table `inet m87_egress`,
+//! which drops new connections the run opens to destinations outside
+//! `egress.allow`: from the cgroups of its processes, units and host network
+//! containers, and from the addresses of its bridged containers. Domains are
+//! resolved on every poll. Dropped packets are counted per run, and each
+//! poll that saw drops queues an event with the addresses they went to.
+//! Loopback and DNS to the device's nameservers stay allowed. Containers and
+//! units that (re)start are covered from the next poll, and processes a run
+//! command starts directly stay in the runtime's own cgroup and are not
+//! restricted. Needs nftables with cgroup v2 socket matching.
+
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Result, bail};
+use m87_shared::deploy_spec::{DeployReportKind, DeploymentRevision, EgressViolationReport};
+use m87_shared::egress::EgressTarget;
+use tokio::process::Command;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::device::deployment_manager::{RevisionStore, data_dir, enqueue_event};
+use crate::device::firewall_manager::{comment_safe, privileged};
+use crate::device::flows::run_cgroups;
+use crate::util::command::{binary_exists, safe_run_command};
+use crate::util::shutdown::SHUTDOWN;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const NFT_TABLE: &str = "inet m87_egress";
+/// Destinations kept per run and address family between two reports
+const MAX_DESTINATIONS: usize = 64;
+
+/// The policy of one run, with everything its rules are built from.
+#[derive(Debug, Clone, PartialEq)]
+struct RunEgress {
+    run_id: String,
+    revision_id: String,
+    /// Paths relative to the cgroup v2 root
+    cgroups: Vec<String>,
+    /// Addresses of its containers on bridge networks
+    addresses: Vec<IpAddr>,
+    allow: Vec<(IpAddr, u8)>,
+}
+
+/// Keep the egress rules in line with the desired revision and report what
+/// they dropped, until shutdown.
+pub fn spawn() {
+    tokio::spawn(async move {
+        // None until the first apply, which also removes a table left behind
+        // by a previous runtime
+        let mut applied: Option<Vec<RunEgress>> = None;
+        let mut last_error = None;
+        loop {
+            if let Some(runs) = &applied
+                && let Err(e) = report_violations(runs).await
+            {
+                let e = format!("{:#}", e);
+                if last_error.as_ref() != Some(&e) {
+                    warn!("Failed to read egress drops: {}", e);
+                    last_error = Some(e);
+                }
+                // e.g. the table was deleted; apply it again
+                applied = None;
+            }
+            if let Some(wanted) = wanted().await
+                && applied.as_ref() != Some(&wanted)
+            {
+                match apply(&wanted).await {
+                    Ok(()) => {
+                        applied = Some(wanted);
+                        last_error = None;
+                    }
+                    Err(e) => {
+                        let e = format!("{:#}", e);
+                        if last_error.as_ref() != Some(&e) {
+                            warn!("Failed to apply egress rules: {}", e);
+                            last_error = Some(e);
+                        }
+                    }
+                }
+            }
+            tokio::select! {
+                _ = sleep(POLL_INTERVAL) => {}
+                _ = SHUTDOWN.cancelled() => break,
+            }
+        }
+    });
+}
+
+/// None while the revision cannot be read, so a bad read never lifts the
+/// policies of running services.
+async fn wanted() -> Option<Vec<RunEgress>> {
+    let revision = RevisionStore::get_desired_config().ok()?;
+    Some(match revision {
+        Some(revision) => run_policies(&revision).await,
+        None => Vec::new(),
+    })
+}
+
+async fn run_policies(revision: &DeploymentRevision) -> Vec<RunEgress> {
+    let jobs: Vec<_> = revision
+        .jobs
+        .iter()
+        .filter_map(|job| Some((job, job.egress.as_ref()?)))
+        .collect();
+    if jobs.is_empty() {
+        return Vec::new();
+    }
+    let cgroups = run_cgroups(revision).await;
+    let mut runs = Vec::new();
+    for (job, policy) in jobs {
+        // the revision was validated on upload; skip what still fails
+        let mut allow = Vec::new();
+        for target in policy
+            .allow
+            .iter()
+            .filter_map(|s| EgressTarget::parse(s).ok())
+        {
+            match target {
+                EgressTarget::Network(addr, prefix) => allow.push((addr, prefix)),
+                EgressTarget::Domain(domain) => match resolve(&domain).await {
+                    Ok(addrs) => allow.extend(addrs.into_iter().map(host_network)),
+                    Err(e) => warn!(
+                        "Cannot resolve {} for the egress of {}: {}",
+                        domain, job.id, e
+                    ),
+                },
+            }
+        }
+        allow.sort();
+        allow.dedup();
+
+        let mut addresses = Vec::new();
+        for name in job.drift.iter().flat_map(|d| &d.containers) {
+            addresses.extend(container_addresses(name).await);
+        }
+        runs.push(RunEgress {
+            run_id: job.id.clone(),
+            revision_id: revision.id.clone().unwrap_or_default(),
+            cgroups: cgroups
+                .iter()
+                .filter(|(_, run_id)| *run_id == &job.id)
+                .map(|(path, _)| path.trim_start_matches('/').to_string())
+                .collect(),
+            addresses,
+            allow,
+        });
+    }
+    runs
+}
+
+async fn resolve(domain: &str) -> std::io::Result<BTreeSet<IpAddr>> {
+    Ok(tokio::net::lookup_host((domain, 0))
+        .await?
+        .map(|addr| addr.ip())
+        .collect())
+}
+
+fn host_network(addr: IpAddr) -> (IpAddr, u8) {
+    (addr, if addr.is_ipv4() { 32 } else { 128 })
+}
+
+/// Addresses of a container on its networks; none with host networking.
+async fn container_addresses(name: &str) -> Vec<IpAddr> {
+    if !binary_exists("docker") {
+        return Vec::new();
+    }
+    let mut cmd = Command::new("docker");
+    cmd.args([
+        "inspect",
+        "--format",
+        "{{range .NetworkSettings.Networks}}{{.IPAddress}} {{.GlobalIPv6Address}} {{end}}",
+        name,
+    ])
+    .kill_on_drop(true);
+    match safe_run_command(cmd, COMMAND_TIMEOUT).await {
+        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout)
+            .split_whitespace()
+            .filter_map(|a| a.parse().ok())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Nameservers of the device other than loopback ones, which are allowed
+/// anyway.
+fn nameservers() -> Vec<IpAddr> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .filter(|addr| !addr.is_loopback())
+        .collect()
+}
+
+/// Replace the table with the rules of `runs`, or remove it when no run has
+/// a policy. The ruleset is kept in the data dir while the table exists.
+async fn apply(runs: &[RunEgress]) -> Result<()> {
+    let path = data_dir()?.join("egress.nft");
+    if runs.is_empty() {
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            let mut args = vec!["delete", "table"];
+            args.extend(NFT_TABLE.split(' '));
+            // gone already after a reboot
+            let _ = privileged("nft", &args).await;
+            tokio::fs::remove_file(&path).await?;
+            info!("Removed the egress rules of runs");
+        }
+        return Ok(());
+    }
+    if !binary_exists("nft") {
+        bail!("nft is not installed");
+    }
+    tokio::fs::write(&path, nft_ruleset(runs, &nameservers())).await?;
+    privileged("nft", &["-f", &path.to_string_lossy()]).await?;
+    info!("Applied the egress policies of {} runs", runs.len());
+    Ok(())
+}
+
+/// Queue an event for each run whose rules dropped packets since the
+/// previous call, and start counting anew.
+async fn report_violations(runs: &[RunEgress]) -> Result<()> {
+    let (table_family, table) = NFT_TABLE.split_once(' ').unwrap_or(("inet", NFT_TABLE));
+    for (i, run) in runs.iter().enumerate() {
+        let counter = format!("blocked_{}", i);
+        let out = privileged("nft", &["reset", "counter", table_family, table, &counter]).await?;
+        let blocked = counter_packets(&out);
+        if blocked == 0 {
+            continue;
+        }
+        let mut destinations = Vec::new();
+        for set in [format!("dst4_{}", i), format!("dst6_{}", i)] {
+            let out = privileged("nft", &["list", "set", table_family, table, &set]).await?;
+            destinations.extend(set_elements(&out).iter().map(IpAddr::to_string));
+            privileged("nft", &["flush", "set", table_family, table, &set]).await?;
+        }
+        let report = EgressViolationReport {
+            run_id: run.run_id.clone(),
+            revision_id: run.revision_id.clone(),
+            blocked,
+            destinations,
+            report_time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        };
+        warn!("Run {}: {}", report.run_id, report.summary());
+        enqueue_event(DeployReportKind::EgressViolation(report)).await?;
+    }
+    Ok(())
+}
+
+/// `packets` of the output of `nft reset counter`.
+fn counter_packets(out: &str) -> u64 {
+    let mut words = out.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "packets" {
+            return words.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+        }
+    }
+    0
+}
+
+/// Addresses in the `elements = { ... }` of the output of `nft list set`.
+fn set_elements(out: &str) -> Vec<IpAddr> {
+    let Some((_, rest)) = out.split_once("elements = {") else {
+        return Vec::new();
+    };
+    let elements = rest.split('}').next().unwrap_or_default();
+    elements
+        .split([',', ' ', '\n', '\t'])
+        .filter_map(|e| e.parse().ok())
+        .collect()
+}
+
+fn nft_set(entries: &[String]) -> String {
+    format!("{{ {} }}", entries.join(", "))
+}
+
+/// The `inet m87_egress` table, replacing the previous one in one
+/// transaction. Traffic of a run jumps to its chain, which drops and records
+/// new connections to destinations it is not allowed to reach.
+fn nft_ruleset(runs: &[RunEgress], nameservers: &[IpAddr]) -> String {
+    let mut objects = String::new();
+    let mut output = vec!["oifname \"lo\" accept".to_string()];
+    let mut forward = Vec::new();
+    let mut chains = String::new();
+    for (i, run) in runs.iter().enumerate() {
+        objects.push_str(&format!(
+            "\tcounter blocked_{i} {{ packets 0 bytes 0 }}\n\
+             \tset dst4_{i} {{ type ipv4_addr; size {MAX_DESTINATIONS}; flags dynamic; }}\n\
+             \tset dst6_{i} {{ type ipv6_addr; size {MAX_DESTINATIONS}; flags dynamic; }}\n"
+        ));
+        for cgroup in &run.cgroups {
+            output.push(format!(
+                "socket cgroupv2 level {} \"{}\" jump run_{i}",
+                cgroup.split('/').count(),
+                cgroup
+            ));
+        }
+        for addr in &run.addresses {
+            let family = if addr.is_ipv4() { "ip" } else { "ip6" };
+            forward.push(format!("{family} saddr {addr} jump run_{i}"));
+        }
+
+        let mut rules = vec!["ct state established,related accept".to_string()];
+        for (family, v4) in [("ip", true), ("ip6", false)] {
+            let dns: Vec<String> = nameservers
+                .iter()
+                .filter(|addr| addr.is_ipv4() == v4)
+                .map(IpAddr::to_string)
+                .collect();
+            if !dns.is_empty() {
+                rules.push(format!(
+                    "{family} daddr {} meta l4proto {{ tcp, udp }} th dport 53 accept",
+                    nft_set(&dns)
+                ));
+            }
+            let allow: Vec<String> = run
+                .allow
+                .iter()
+                .filter(|(addr, _)| addr.is_ipv4() == v4)
+                .map(|(addr, prefix)| format!("{}/{}", addr, prefix))
+                .collect();
+            if !allow.is_empty() {
+                rules.push(format!("{family} daddr {} accept", nft_set(&allow)));
+            }
+        }
+        rules.push(format!("add @dst4_{i} {{ ip daddr }}"));
+        rules.push(format!("add @dst6_{i} {{ ip6 daddr }}"));
+        rules.push(format!(
+            "counter name blocked_{i} drop comment \"{}\"",
+            comment_safe(&run.run_id)
+        ));
+
+        chains.push_str(&format!("\tchain run_{i} {{\n"));
+        for rule in rules {
+            chains.push_str(&format!("\t\t{}\n", rule));
+        }
+        chains.push_str("\t}\n");
+    }
+
+    let hook = |name: &str, rules: &[String]| {
+        let mut chain = format!(
+            "\tchain {name} {{\n\t\ttype filter hook {name} priority filter; policy accept;\n"
+        );
+        for rule in rules {
+            chain.push_str(&format!("\t\t{}\n", rule));
+        }
+        chain.push_str("\t}\n");
+        chain
+    };
+    format!(
+        "table {t}\ndelete table {t}\ntable {t} {{\n{}{}{}{}}}\n",
+        objects,
+        hook("output", &output),
+        hook("forward", &forward),
+        chains,
+        t = NFT_TABLE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ruleset_sends_run_traffic_through_its_allowlist() {
+        let run = RunEgress {
+            run_id: "camera".to_string(),
+            revision_id: "rev".to_string(),
+            cgroups: vec!["system.slice/camera.service".to_string()],
+            addresses: vec!["172.17.0.5".parse().unwrap()],
+            allow: vec![
+                ("10.0.0.0".parse().unwrap(), 8),
+                ("2001:db8::".parse().unwrap(), 32),
+            ],
+        };
+        let ruleset = nft_ruleset(&[run], &["192.168.1.1".parse().unwrap()]);
+        assert!(ruleset.starts_with("table inet m87_egress\ndelete table inet m87_egress\n"));
+        assert!(
+            ruleset.contains(
+                "\t\tsocket cgroupv2 level 2 \"system.slice/camera.service\" jump run_0\n"
+            )
+        );
+        assert!(ruleset.contains("\t\tip saddr 172.17.0.5 jump run_0\n"));
+        assert!(ruleset.contains(
+            "\t\tip daddr { 192.168.1.1 } meta l4proto { tcp, udp } th dport 53 accept\n"
+        ));
+        assert!(ruleset.contains("\t\tip daddr { 10.0.0.0/8 } accept\n"));
+        assert!(ruleset.contains("\t\tip6 daddr { 2001:db8::/32 } accept\n"));
+        assert!(ruleset.contains("\t\tcounter name blocked_0 drop comment \"camera\"\n"));
+    }
+
+    #[test]
+    fn reads_drops_and_destinations() {
+        let counter =
+            "table inet m87_egress {\n\tcounter blocked_0 {\n\t\tpackets 3 bytes 180\n\t}\n}\n";
+        assert_eq!(counter_packets(counter), 3);
+        let set = "table inet m87_egress {\n\tset dst4_0 {\n\t\ttype ipv4_addr\n\t\tsize 64\n\t\t\
+                   flags dynamic\n\t\telements = { 203.0.113.9, 198.51.100.7,\n\t\t\t     192.0.2.1 }\n\t}\n}\n";
+        assert_eq!(
+            set_elements(set),
+            vec![
+                "203.0.113.9".parse::<IpAddr>().unwrap(),
+                "198.51.100.7".parse().unwrap(),
+                "192.0.2.1".parse().unwrap(),
+            ]
+        );
+        assert!(set_elements("table inet m87_egress {\n}\n").is_empty());
+    }
+}
@@ -0,0 +1,480 @@
+//! Fact collection on the runtime.
+//!
+//! Built-in collectors cover the OS, installed packages, container and
+//! language runtimes, GPU drivers and attached serial/USB hardware. Executables in
+//! `<config dir>/facts.d/` add custom facts by printing `key=value` lines,
+//! stored as `custom.<key>`. Facts are cached and only recollected when they
+//! are older than [`MAX_AGE`] or a refresh is requested.
+
+use async_trait::async_trait;
+use m87_shared::facts::DeviceFacts;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use sysinfo::System;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::util::command::{binary_exists, safe_run_command};
+
+const MAX_AGE: Duration = Duration::from_secs(3600);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type Facts = BTreeMap<String, String>;
+
+/// A source of device facts. Collectors never fail: facts that cannot be
+/// determined are left out.
+#[async_trait]
+pub trait FactCollector: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    async fn collect(&self) -> Facts;
+}
+
+/// Registered fact collectors.
+#[derive(Clone)]
+pub struct FactRegistry {
+    collectors: Vec<Arc<dyn FactCollector>>,
+}
+
+impl Default for FactRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(SystemFacts);
+        registry.register(PackageFacts);
+        registry.register(ContainerFacts);
+        registry.register(LanguageFacts);
+        registry.register(GpuFacts);
+        registry.register(HardwareFacts);
+        registry.register(TimeFacts);
+        registry.register(CustomFacts);
+        registry
+    }
+}
+
+impl FactRegistry {
+    pub fn empty() -> Self {
+        Self {
+            collectors: Vec::new(),
+        }
+    }
+
+    pub fn register<C: FactCollector + 'static>(&mut self, collector: C) {
+        self.collectors.push(Arc::new(collector));
+    }
+
+    pub async fn collect(&self) -> DeviceFacts {
+        let results = futures::future::join_all(self.collectors.iter().map(|c| async move {
+            let facts = c.collect().await;
+            debug!("collected {} {} facts", facts.len(), c.name());
+            facts
+        }))
+        .await;
+        DeviceFacts {
+            collected_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            facts: results.into_iter().flatten().collect(),
+        }
+    }
+}
+
+static CACHE: Mutex<Option<DeviceFacts>> = Mutex::const_new(None);
+
+/// Cached facts, recollected when stale or when `refresh` is set.
+pub async fn get_facts(refresh: bool) -> DeviceFacts {
+    let mut cache = CACHE.lock().await;
+    if let Some(facts) = cache.as_ref() {
+        let age = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_millis(facts.collected_at))
+            .unwrap_or_default();
+        if !refresh && age < MAX_AGE {
+            return facts.clone();
+        }
+    }
+    let facts = FactRegistry::default().collect().await;
+    *cache = Some(facts.clone());
+    facts
+}
+
+/// Run `program args` and return trimmed stdout (or stderr, which some tools
+/// print their version to) if it exits successfully.
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    if !binary_exists(program) {
+        return None;
+    }
+    let mut cmd = Command::new(program);
+    cmd.args(args).kill_on_drop(true);
+    let output = safe_run_command(cmd, COMMAND_TIMEOUT).await.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !stdout.is_empty() {
+        return Some(stdout);
+    }
+    Some(String::from_utf8_lossy(&output.stderr).trim().to_string()).filter(|s| !s.is_empty())
+}
+
+/// First version-like token (`1.2`, `v20.11.1`, `go1.22.1`, `"17.0.9"`) in
+/// `output`.
+fn extract_version(output: &str) -> Option<String> {
+    output
+        .split(|c: char| c.is_whitespace() || c == ',' || c == '(' || c == ')')
+        .map(|t| {
+            t.trim_matches('"')
+                .trim_start_matches("go")
+                .trim_start_matches('v')
+        })
+        .find(|t| {
+            let mut parts = t.split('.');
+            t.contains('.')
+                && parts.next().is_some_and(|p| p.parse::<u32>().is_ok())
+                && parts
+                    .next()
+                    .is_some_and(|p| p.starts_with(|c: char| c.is_ascii_digit()))
+        })
+        .map(str::to_string)
+}
+
+async fn version_of(program: &str, args: &[&str]) -> Option<String> {
+    extract_version(&command_output(program, args).await?)
+}
+
+pub struct SystemFacts;
+
+#[async_trait]
+impl FactCollector for SystemFacts {
+    fn name(&self) -> &'static str {
+        "system"
+    }
+
+    async fn collect(&self) -> Facts {
+        let mut facts = Facts::new();
+        facts.insert("arch".into(), std::env::consts::ARCH.into());
+        facts.insert("os".into(), std::env::consts::OS.into());
+        let values = [
+            ("os.name", System::name()),
+            ("os.version", System::os_version()),
+            ("os.id", System::distribution_id().into()),
+            ("kernel", System::kernel_version()),
+            ("hostname", System::host_name()),
+            (
+                "cpu.cores",
+                std::thread::available_parallelism()
+                    .ok()
+                    .map(|n| n.to_string()),
+            ),
+            ("m87.version", Some(env!("CARGO_PKG_VERSION").to_string())),
+        ];
+        for (key, value) in values {
+            if let Some(value) = value.filter(|v| !v.is_empty()) {
+                facts.insert(key.into(), value);
+            }
+        }
+        facts
+    }
+}
+
+pub struct ContainerFacts;
+
+#[async_trait]
+impl FactCollector for ContainerFacts {
+    fn name(&self) -> &'static str {
+        "containers"
+    }
+
+    async fn collect(&self) -> Facts {
+        let mut facts = Facts::new();
+        let runtimes = [
+            ("docker", &["--version"][..]),
+            ("podman", &["--version"][..]),
+            ("containerd", &["--version"][..]),
+            ("nerdctl", &["--version"][..]),
+        ];
+        for (program, args) in runtimes {
+            if let Some(version) = version_of(program, args).await {
+                facts.insert(format!("{}.version", program), version);
+            }
+        }
+        if let Some(version) = command_output("docker", &["compose", "version", "--short"]).await {
+            facts.insert("docker.compose.version".into(), version);
+        }
+        facts
+    }
+}
+
+pub struct LanguageFacts;
+
+#[async_trait]
+impl FactCollector for LanguageFacts {
+    fn name(&self) -> &'static str {
+        "languages"
+    }
+
+    async fn collect(&self) -> Facts {
+        let mut facts = Facts::new();
+        let runtimes = [
+            ("python", "python3", &["--version"][..]),
+            ("node", "node", &["--version"][..]),
+            ("java", "java", &["-version"][..]),
+            ("go", "go", &["version"][..]),
+        ];
+        for (key, program, args) in runtimes {
+            if let Some(version) = version_of(program, args).await {
+                facts.insert(format!("{}.version", key), version);
+            }
+        }
+        facts
+    }
+}
+
+pub struct GpuFacts;
+
+#[async_trait]
+impl FactCollector for GpuFacts {
+    fn name(&self) -> &'static str {
+        "gpu"
+    }
+
+    async fn collect(&self) -> Facts {
+        let mut facts = Facts::new();
+        if let Some(out) = command_output(
+            "nvidia-smi",
+            &["--query-gpu=name,driver_version", "--format=csv,noheader"],
+        )
+        .await
+        {
+            let gpus: Vec<(&str, &str)> = out
+                .lines()
+                .filter_map(|l| l.split_once(','))
+                .map(|(name, driver)| (name.trim(), driver.trim()))
+                .collect();
+            if let Some((name, driver)) = gpus.first() {
+                facts.insert("gpu.vendor".into(), "nvidia".into());
+                facts.insert("gpu.name".into(), name.to_string());
+                facts.insert("gpu.driver".into(), driver.to_string());
+                facts.insert("gpu.count".into(), gpus.len().to_string());
+            }
+            if let Some(cuda) = command_output("nvidia-smi", &[])
+                .await
+                .and_then(|o| cuda_version(&o))
+            {
+                facts.insert("gpu.cuda.version".into(), cuda);
+            }
+        } else if Path::new("/etc/nv_tegra_release").exists() {
+            // Jetson boards have no nvidia-smi
+            facts.insert("gpu.vendor".into(), "nvidia".into());
+            facts.insert("gpu.name".into(), "tegra".into());
+        } else if Path::new("/dev/kfd").exists() {
+            facts.insert("gpu.vendor".into(), "amd".into());
+            if let Some(version) = std::fs::read_to_string("/sys/module/amdgpu/version")
+                .ok()
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+            {
+                facts.insert("gpu.driver".into(), version);
+            }
+        }
+        facts
+    }
+}
+
+/// `CUDA Version: 12.4` from the nvidia-smi banner.
+fn cuda_version(output: &str) -> Option<String> {
+    let rest = &output[output.find("CUDA Version:")? + "CUDA Version:".len()..];
+    rest.split_whitespace().next().map(str::to_string)
+}
+
+pub struct HardwareFacts;
+
+#[async_trait]
+impl FactCollector for HardwareFacts {
+    fn name(&self) -> &'static str {
+        "hardware"
+    }
+
+    async fn collect(&self) -> Facts {
+        let mut facts = Facts::new();
+
+        let mut serial: Vec<String> = std::fs::read_dir("/dev")
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|e| e.file_name().into_string().ok())
+                    .filter(|n| n.starts_with("ttyUSB") || n.starts_with("ttyACM"))
+                    .map(|n| format!("/dev/{}", n))
+                    .collect()
+            })
+            .unwrap_or_default();
+        serial.sort();
+        if !serial.is_empty() {
+            facts.insert("serial.devices".into(), serial.join(","));
+        }
+
+        // USB devices that report a product name; hubs and root ports are skipped
+        let mut usb: Vec<String> = std::fs::read_dir("/sys/bus/usb/devices")
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|e| {
+                        let dir = e.path();
+                        let read = |f: &str| std::fs::read_to_string(dir.join(f)).ok();
+                        if read("bDeviceClass").is_some_and(|c| c.trim() == "09") {
+                            return None;
+                        }
+                        let product = read("product")?.trim().to_string();
+                        let id =
+                            format!("{}:{}", read("idVendor")?.trim(), read("idProduct")?.trim());
+                        Some(format!("{} {}", id, product))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        usb.sort();
+        usb.dedup();
+        if !usb.is_empty() {
+            facts.insert("usb.count".into(), usb.len().to_string());
+            facts.insert("usb.devices".into(), usb.join(","));
+        }
+        facts
+    }
+}
+
+/// `packages.manager`, `packages.count` and `package.<name>` = installed
+/// version, from the inventory also sent for the advisory scan.
+pub struct PackageFacts;
+
+#[async_trait]
+impl FactCollector for PackageFacts {
+    fn name(&self) -> &'static str {
+        "packages"
+    }
+
+    async fn collect(&self) -> Facts {
+        let inventory = crate::device::inventory::current().await;
+        let mut facts = Facts::new();
+        let Some(manager) = inventory.manager else {
+            return facts;
+        };
+        facts.insert("packages.manager".into(), manager.to_string());
+        facts.insert(
+            "packages.count".into(),
+            inventory.packages.len().to_string(),
+        );
+        for package in inventory.packages {
+            facts.insert(format!("package.{}", package.name), package.version);
+        }
+        facts
+    }
+}
+
+pub struct TimeFacts;
+
+#[async_trait]
+impl FactCollector for TimeFacts {
+    fn name(&self) -> &'static str {
+        "time"
+    }
+
+    async fn collect(&self) -> Facts {
+        let mut facts = Facts::new();
+        let status = crate::device::time_sync::status().await;
+        if let Some(service) = status.ntp_service {
+            facts.insert("time.ntp.service".into(), service);
+        }
+        if let Some(synchronized) = status.synchronized {
+            facts.insert("time.ntp.synchronized".into(), synchronized.to_string());
+        }
+        facts
+    }
+}
+
+pub struct CustomFacts;
+
+#[async_trait]
+impl FactCollector for CustomFacts {
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    async fn collect(&self) -> Facts {
+        let mut facts = Facts::new();
+        let Ok(dir) = Config::m87_config_dir().map(|d| d.join("facts.d")) else {
+            return facts;
+        };
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            return facts;
+        };
+        let mut paths: Vec<_> = entries.flatten().map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths.iter().filter(|p| p.is_file()) {
+            let mut cmd = Command::new(path);
+            cmd.kill_on_drop(true);
+            match safe_run_command(cmd, COMMAND_TIMEOUT).await {
+                Ok(output) if output.status.success() => {
+                    facts.extend(parse_custom_facts(&String::from_utf8_lossy(&output.stdout)));
+                }
+                Ok(output) => warn!(
+                    "fact script {} exited with {}",
+                    path.display(),
+                    output.status
+                ),
+                Err(e) => warn!("fact script {} failed: {}", path.display(), e),
+            }
+        }
+        facts
+    }
+}
+
+/// `key=value` lines from a fact script, keyed `custom.<key>`.
+fn parse_custom_facts(output: &str) -> Facts {
+    output
+        .lines()
+        .filter(|l| !l.trim_start().starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(k, v)| (k.trim(), v.trim()))
+        .filter(|(k, _)| !k.is_empty() && !k.contains(char::is_whitespace))
+        .map(|(k, v)| (format!("custom.{}", k), v.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_version() {
+        let cases = [
+            ("Docker version 24.0.7, build afdd53b", Some("24.0.7")),
+            ("Python 3.11.2", Some("3.11.2")),
+            ("v20.11.1", Some("20.11.1")),
+            ("openjdk version \"17.0.9\" 2023-10-17", Some("17.0.9")),
+            ("go version go1.22.1 linux/arm64", Some("1.22.1")),
+            ("podman version 4.3.1", Some("4.3.1")),
+            ("no version here", None),
+        ];
+        for (output, expected) in cases {
+            assert_eq!(extract_version(output).as_deref(), expected, "{}", output);
+        }
+    }
+
+    #[test]
+    fn test_cuda_version() {
+        let banner = "| NVIDIA-SMI 550.54.14   Driver Version: 550.54.14   CUDA Version: 12.4 |";
+        assert_eq!(cuda_version(banner).as_deref(), Some("12.4"));
+        assert_eq!(cuda_version("no gpu"), None);
+    }
+
+    #[test]
+    fn test_parse_custom_facts() {
+        let facts = parse_custom_facts("# site facts\nrack = R12\nzone=north\ninvalid line\n=x\n");
+        assert_eq!(facts.get("custom.rack").map(String::as_str), Some("R12"));
+        assert_eq!(facts.get("custom.zone").map(String::as_str), Some("north"));
+        assert_eq!(facts.len(), 2);
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::{Context, Result, anyhow};
+use m87_shared::facts::DeviceFacts;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    devices, server,
+    streams::{quic::open_quic_io, stream_type::StreamType},
+};
+
+/// Fetch the facts collected by the runtime on `device`.
+pub async fn fetch_facts(device: &str, refresh: bool) -> Result<DeviceFacts> {
+    let request = json!({ "device": device, "refresh": refresh });
+    server::vcr::call("device_facts", request, query_facts(device, refresh)).await
+}
+
+async fn query_facts(device: &str, refresh: bool) -> Result<DeviceFacts> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let stream_type = StreamType::Facts {
+        token: token.clone(),
+        refresh,
+    };
+    let (_conn, io) = open_quic_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        config.trust_invalid_server_cert,
+    )
+    .await?;
+
+    let line = BufReader::new(io)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("device closed the stream without sending facts"))?;
+    serde_json::from_str(&line).context("Failed to parse device facts")
+}
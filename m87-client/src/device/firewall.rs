@@ -0,0 +1,44 @@
+use anyhow::{Context, Result, anyhow};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    devices, server,
+    streams::{
+        quic::open_quic_io,
+        stream_type::{FirewallReply, StreamType},
+    },
+};
+
+/// Firewall rules the runtime of `device` manages for the ports runs expose.
+pub async fn status(device: &str) -> Result<FirewallReply> {
+    let request = json!({ "device": device });
+    server::vcr::call("device_firewall", request, query_status(device)).await
+}
+
+async fn query_status(device: &str) -> Result<FirewallReply> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let stream_type = StreamType::Firewall {
+        token: token.clone(),
+    };
+    let (_conn, io) = open_quic_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        config.trust_invalid_server_cert,
+    )
+    .await?;
+
+    let line = BufReader::new(io)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("device closed the stream without reporting its firewall"))?;
+    serde_json::from_str(&line).context("Failed to parse firewall status")
+}
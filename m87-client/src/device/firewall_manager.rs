@@ -0,0 +1,416 @@
+//! Firewall rules for the ports runs `expose`. With `firewall` set to
+//! `nftables` or `ufw`, the runtime opens the declared ports of the enabled
+//! runs of the desired revision and closes them again once a run is removed
+//! or the revision is undeployed. The nftables table drops every other
+//! inbound connection but always lets through loopback, replies to
+//! connections the device opened (the control tunnel among them), ICMP,
+//! DHCP and the LAN ports of the runtime.
+
+use std::sync::{LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use m87_shared::deploy_spec::{DeploymentRevision, PortProtocol};
+use tokio::process::Command;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::device::deployment_manager::{RevisionStore, data_dir};
+use crate::device::fact_collectors::get_facts;
+use crate::streams::stream_type::{FirewallBackend, FirewallReply, FirewallRule};
+use crate::util::command::{binary_exists, safe_run_command};
+use crate::util::shutdown::SHUTDOWN;
+use crate::util::unix::is_root;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+const NFT_TABLE: &str = "inet m87";
+/// Start of the comment of the ufw rules the runtime added
+const UFW_COMMENT: &str = "m87 ";
+const MDNS_PORT: u16 = 5353;
+
+static STATUS: LazyLock<Mutex<FirewallReply>> =
+    LazyLock::new(|| Mutex::new(FirewallReply::default()));
+
+fn status_lock() -> MutexGuard<'static, FirewallReply> {
+    STATUS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// What the runtime last applied, for the `Firewall` stream.
+pub fn status() -> FirewallReply {
+    status_lock().clone()
+}
+
+/// Everything a ruleset is built from; applied again when it changes.
+#[derive(Debug, Clone, PartialEq)]
+struct Wanted {
+    backend: FirewallBackend,
+    rules: Vec<FirewallRule>,
+    lan_direct_port: Option<u16>,
+    mdns: bool,
+}
+
+/// Keep the firewall in line with the config and the desired revision until
+/// shutdown. The rules stay in place while the runtime is stopped, as do the
+/// services they are for.
+pub fn spawn() {
+    tokio::spawn(async move {
+        let mut applied: Option<Wanted> = None;
+        let mut last_error = None;
+        loop {
+            if let Some(wanted) = wanted().await
+                && applied.as_ref() != Some(&wanted)
+            {
+                match apply(&wanted).await {
+                    Ok(()) => {
+                        record(&wanted, None);
+                        applied = Some(wanted);
+                        last_error = None;
+                    }
+                    Err(e) => {
+                        let e = format!("{:#}", e);
+                        if last_error.as_ref() != Some(&e) {
+                            warn!("Failed to apply firewall rules: {}", e);
+                        }
+                        record(&wanted, Some(e.clone()));
+                        last_error = Some(e);
+                    }
+                }
+            }
+            tokio::select! {
+                _ = sleep(POLL_INTERVAL) => {}
+                _ = SHUTDOWN.cancelled() => break,
+            }
+        }
+    });
+}
+
+/// None while the config or revision cannot be read, so a bad read never
+/// closes the ports of running services.
+async fn wanted() -> Option<Wanted> {
+    let config = Config::load().ok()?;
+    let rules = match config.firewall {
+        FirewallBackend::Off => Vec::new(),
+        _ => match RevisionStore::get_desired_config().ok()? {
+            Some(revision) => declared_rules(&revision).await,
+            None => Vec::new(),
+        },
+    };
+    Some(Wanted {
+        backend: config.firewall,
+        rules,
+        lan_direct_port: config.lan_direct.then_some(config.lan_direct_port),
+        mdns: config.lan_discovery,
+    })
+}
+
+fn record(wanted: &Wanted, error: Option<String>) {
+    let mut status = status_lock();
+    status.backend = wanted.backend;
+    status.rules = wanted.rules.clone();
+    status.always_allowed = match wanted.backend {
+        FirewallBackend::Nftables => always_allowed(wanted.lan_direct_port, wanted.mdns),
+        _ => Vec::new(),
+    };
+    if error.is_none() {
+        status.applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_millis() as u64);
+    }
+    status.error = error;
+}
+
+/// Ports of the enabled runs that apply to this device, one rule per port
+/// and protocol.
+async fn declared_rules(revision: &DeploymentRevision) -> Vec<FirewallRule> {
+    let facts = get_facts(false).await;
+    let mut rules: Vec<FirewallRule> = revision
+        .jobs
+        .iter()
+        .filter(|job| job.enabled)
+        .filter(|job| match job.when.as_deref() {
+            Some(condition) => facts.evaluate_condition(condition).unwrap_or(false),
+            None => true,
+        })
+        .flat_map(|job| {
+            job.expose.iter().map(|p| FirewallRule {
+                port: p.port,
+                protocol: p.protocol,
+                run_id: job.id.clone(),
+            })
+        })
+        .collect();
+    rules.sort();
+    rules.dedup_by(|a, b| a.port == b.port && a.protocol == b.protocol);
+    rules
+}
+
+/// Take down the rules of the backend applied before, if it changed, and
+/// bring the wanted ones up. Which backend was applied is kept across
+/// restarts, so turning the firewall off cleans up after the runtime.
+async fn apply(wanted: &Wanted) -> Result<()> {
+    let state = data_dir()?.join("firewall.applied");
+    let previous = match tokio::fs::read(&state).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+        Err(_) => FirewallBackend::Off,
+    };
+    if previous != wanted.backend && previous != FirewallBackend::Off {
+        match previous {
+            FirewallBackend::Nftables => remove_nft_table().await?,
+            _ => sync_ufw(&[]).await?,
+        }
+        info!("Removed the {} rules of runs", previous.as_str());
+    }
+
+    match wanted.backend {
+        FirewallBackend::Off => {
+            let _ = tokio::fs::remove_file(&state).await;
+            return Ok(());
+        }
+        FirewallBackend::Nftables => {
+            if !binary_exists("nft") {
+                bail!("nft is not installed");
+            }
+            let path = data_dir()?.join("firewall.nft");
+            let ruleset = nft_ruleset(&wanted.rules, wanted.lan_direct_port, wanted.mdns);
+            tokio::fs::write(&path, ruleset).await?;
+            privileged("nft", &["-f", &path.to_string_lossy()]).await?;
+        }
+        FirewallBackend::Ufw => {
+            if !binary_exists("ufw") {
+                bail!("ufw is not installed");
+            }
+            sync_ufw(&wanted.rules).await?;
+        }
+    }
+    tokio::fs::write(&state, serde_json::to_vec(&wanted.backend)?).await?;
+    info!(
+        "Applied {} rules for {} ports",
+        wanted.backend.as_str(),
+        wanted.rules.len()
+    );
+    Ok(())
+}
+
+/// Through `sudo -n` when the runtime is not root. Returns stdout.
+pub(crate) async fn privileged(program: &str, args: &[&str]) -> Result<String> {
+    let mut cmd = if is_root() {
+        Command::new(program)
+    } else {
+        let mut cmd = Command::new("sudo");
+        cmd.arg("-n").arg(program);
+        cmd
+    };
+    cmd.args(args).kill_on_drop(true);
+    let out = safe_run_command(cmd, COMMAND_TIMEOUT).await?;
+    if !out.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+async fn remove_nft_table() -> Result<()> {
+    let tables = privileged("nft", &["list", "tables"]).await?;
+    if tables
+        .lines()
+        .any(|l| l.trim() == format!("table {}", NFT_TABLE))
+    {
+        let mut args = vec!["delete", "table"];
+        args.extend(NFT_TABLE.split(' '));
+        privileged("nft", &args).await?;
+    }
+    Ok(())
+}
+
+/// Add the ufw rules in `rules` the runtime has not added yet and delete
+/// the ones it added that are no longer wanted.
+async fn sync_ufw(rules: &[FirewallRule]) -> Result<()> {
+    let added = privileged("ufw", &["show", "added"])
+        .await
+        .context("cannot list ufw rules")?;
+    let current = managed_ufw_rules(&added);
+    for rule in current.iter().filter(|r| !rules.contains(r)) {
+        privileged("ufw", &["delete", "allow", &ufw_port(rule)]).await?;
+    }
+    for rule in rules.iter().filter(|r| !current.contains(r)) {
+        let comment = format!("{}{}", UFW_COMMENT, comment_safe(&rule.run_id));
+        privileged("ufw", &["allow", &ufw_port(rule), "comment", &comment]).await?;
+    }
+    Ok(())
+}
+
+fn ufw_port(rule: &FirewallRule) -> String {
+    format!("{}/{}", rule.port, rule.protocol.as_str())
+}
+
+/// The runtime's rules in the output of `ufw show added`, lines like
+/// `ufw allow 8080/tcp comment 'm87 camera'`.
+fn managed_ufw_rules(added: &str) -> Vec<FirewallRule> {
+    added
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim().strip_prefix("ufw allow ")?;
+            let (port, comment) = rest.split_once(" comment ")?;
+            let run_id = comment.trim_matches('\'').strip_prefix(UFW_COMMENT)?;
+            let (port, protocol) = port.split_once('/')?;
+            let protocol = match protocol {
+                "tcp" => PortProtocol::Tcp,
+                "udp" => PortProtocol::Udp,
+                _ => return None,
+            };
+            Some(FirewallRule {
+                port: port.parse().ok()?,
+                protocol,
+                run_id: run_id.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Run ids in firewall comments, without quotes or spaces.
+pub(crate) fn comment_safe(run_id: &str) -> String {
+    run_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Inbound traffic the nftables table always lets through.
+fn always_allowed(lan_direct_port: Option<u16>, mdns: bool) -> Vec<String> {
+    let mut allowed = vec![
+        "iif \"lo\" accept".to_string(),
+        // replies, including those on the control tunnel and relay streams
+        "ct state established,related accept".to_string(),
+        "meta l4proto { icmp, ipv6-icmp } accept".to_string(),
+        "udp dport { 68, 546 } accept comment \"dhcp\"".to_string(),
+    ];
+    if let Some(port) = lan_direct_port {
+        allowed.push(format!(
+            "udp dport {} accept comment \"m87 lan direct\"",
+            port
+        ));
+    }
+    if mdns {
+        allowed.push(format!(
+            "udp dport {} accept comment \"m87 lan discovery\"",
+            MDNS_PORT
+        ));
+    }
+    allowed
+}
+
+/// The `inet m87` table, replacing the previous one in one transaction.
+/// Inbound connections to other ports are dropped, on the host and through
+/// the port mappings of containers.
+fn nft_ruleset(rules: &[FirewallRule], lan_direct_port: Option<u16>, mdns: bool) -> String {
+    let mut input = always_allowed(lan_direct_port, mdns);
+    input.push("ct state invalid drop".to_string());
+    for rule in rules {
+        input.push(format!(
+            "{} dport {} accept comment \"{}\"",
+            rule.protocol.as_str(),
+            rule.port,
+            comment_safe(&rule.run_id)
+        ));
+    }
+
+    // published container ports are forwarded after DNAT instead of
+    // reaching the input hook
+    let mut forward = Vec::new();
+    for protocol in [PortProtocol::Tcp, PortProtocol::Udp] {
+        let ports: Vec<String> = rules
+            .iter()
+            .filter(|r| r.protocol == protocol)
+            .map(|r| r.port.to_string())
+            .collect();
+        let mut rule = format!(
+            "ct state new ct status dnat meta l4proto {}",
+            protocol.as_str()
+        );
+        if !ports.is_empty() {
+            rule.push_str(&format!(
+                " ct original proto-dst != {{ {} }}",
+                ports.join(", ")
+            ));
+        }
+        rule.push_str(" drop");
+        forward.push(rule);
+    }
+
+    let chain = |name: &str, policy: &str, rules: &[String]| {
+        let mut chain = format!(
+            "\tchain {name} {{\n\t\ttype filter hook {name} priority filter; policy {policy};\n"
+        );
+        for rule in rules {
+            chain.push_str(&format!("\t\t{}\n", rule));
+        }
+        chain.push_str("\t}\n");
+        chain
+    };
+    format!(
+        "table {t}\ndelete table {t}\ntable {t} {{\n{}{}}}\n",
+        chain("input", "drop", &input),
+        chain("forward", "accept", &forward),
+        t = NFT_TABLE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(port: u16, protocol: PortProtocol, run_id: &str) -> FirewallRule {
+        FirewallRule {
+            port,
+            protocol,
+            run_id: run_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn ruleset_opens_declared_ports_and_keeps_replies() {
+        let ruleset = nft_ruleset(&[rule(8080, PortProtocol::Tcp, "web")], Some(7787), false);
+        assert!(ruleset.starts_with("table inet m87\ndelete table inet m87\n"));
+        assert!(ruleset.contains("policy drop;"));
+        assert!(ruleset.contains("\t\tct state established,related accept\n"));
+        assert!(ruleset.contains("\t\ttcp dport 8080 accept comment \"web\"\n"));
+        assert!(ruleset.contains("\t\tudp dport 7787 accept"));
+        assert!(ruleset.contains(
+            "ct state new ct status dnat meta l4proto tcp ct original proto-dst != { 8080 } drop"
+        ));
+        assert!(ruleset.contains("ct state new ct status dnat meta l4proto udp drop"));
+        assert!(!ruleset.contains("5353"));
+    }
+
+    #[test]
+    fn only_commented_ufw_rules_are_the_runtimes() {
+        let added = "Added user rules (see 'ufw status' for running firewall):\n\
+                     ufw allow 22/tcp\n\
+                     ufw allow 8080/tcp comment 'm87 web'\n\
+                     ufw allow 5000/udp comment 'm87 telemetry'\n\
+                     ufw allow 9000/tcp comment 'grafana'\n";
+        assert_eq!(
+            managed_ufw_rules(added),
+            vec![
+                rule(8080, PortProtocol::Tcp, "web"),
+                rule(5000, PortProtocol::Udp, "telemetry"),
+            ]
+        );
+        assert_eq!(comment_safe("cam 'front'"), "cam__front_");
+    }
+}
@@ -0,0 +1,543 @@
+//! Network traffic of runs, accounted with eBPF. With `flow_metrics`
+//! enabled, the runtime attaches a small `cgroup_skb` program to the cgroup
+//! of each container and unit of a run and of processes carrying a run's
+//! `M87_RUN_ID`. The programs add up the bytes going in and out, and the
+//! metrics stream reports the runs that moved the most as top talkers.
+//! Processes a run command starts directly stay in the runtime's own cgroup
+//! and are not counted. Needs root, cgroup v2 and Linux 5.7+ for BPF links.
+
+use std::collections::BTreeMap;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, MutexGuard, Once};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use m87_shared::deploy_spec::DeploymentRevision;
+use m87_shared::metrics::RunTraffic;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::device::core_dumps::{cgroup_has_unit, container_id, env_value};
+use crate::device::deployment_manager::RevisionStore;
+use crate::util::command::RUN_ID_ENV;
+use crate::util::shutdown::SHUTDOWN;
+
+/// Where cgroup v2 is mounted: on its own, or next to v1 in systemd's
+/// hybrid layout
+const CGROUP_ROOTS: [&str; 2] = ["/sys/fs/cgroup", "/sys/fs/cgroup/unified"];
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// Runs reported per metrics sample
+const TOP_TALKERS: usize = 5;
+
+// bpf(2) commands, types and attach points used here
+const BPF_MAP_CREATE: libc::c_int = 0;
+const BPF_MAP_LOOKUP_ELEM: libc::c_int = 1;
+const BPF_PROG_LOAD: libc::c_int = 5;
+const BPF_LINK_CREATE: libc::c_int = 28;
+const BPF_MAP_TYPE_ARRAY: u32 = 2;
+const BPF_PROG_TYPE_CGROUP_SKB: u32 = 8;
+const BPF_PSEUDO_MAP_FD: u8 = 1;
+const BPF_FUNC_MAP_LOOKUP_ELEM: i32 = 1;
+/// Also the counter's index in the map
+const BPF_CGROUP_INET_INGRESS: u32 = 0;
+const BPF_CGROUP_INET_EGRESS: u32 = 1;
+
+/// Counters by cgroup path, relative to the cgroup root
+static COUNTERS: LazyLock<Mutex<BTreeMap<String, Counter>>> =
+    LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+fn counters() -> MutexGuard<'static, BTreeMap<String, Counter>> {
+    COUNTERS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Programs counting the traffic of one cgroup. Dropping it closes the
+/// links, which detaches the programs.
+struct Counter {
+    run_id: String,
+    map: OwnedFd,
+    _links: [OwnedFd; 2],
+    /// rx and tx bytes at the previous sample
+    reported: (u64, u64),
+}
+
+/// Attach counters to the cgroups of runs as they come and go, until
+/// shutdown or until `flow_metrics` is turned off.
+pub fn spawn() {
+    tokio::spawn(async move {
+        let mut last_error = None;
+        loop {
+            let enabled = Config::load().map(|c| c.flow_metrics).unwrap_or(false);
+            let result = if enabled {
+                refresh().await
+            } else {
+                detach_all();
+                Ok(())
+            };
+            match result {
+                Ok(()) => last_error = None,
+                Err(e) => {
+                    let e = format!("{:#}", e);
+                    if last_error.as_ref() != Some(&e) {
+                        warn!("Flow accounting failed: {}", e);
+                        last_error = Some(e);
+                    }
+                }
+            }
+            tokio::select! {
+                _ = sleep(POLL_INTERVAL) => {}
+                _ = SHUTDOWN.cancelled() => break,
+            }
+        }
+        detach_all();
+    });
+}
+
+fn detach_all() {
+    let mut counters = counters();
+    if !counters.is_empty() {
+        counters.clear();
+        info!("Stopped accounting network flows");
+    }
+}
+
+async fn refresh() -> Result<()> {
+    let Some(root) = cgroup_root() else {
+        bail!("needs cgroup v2 mounted at {}", CGROUP_ROOTS.join(" or "));
+    };
+    let wanted = match RevisionStore::get_desired_config()? {
+        Some(revision) => run_cgroups(&revision).await,
+        None => BTreeMap::new(),
+    };
+
+    let mut counters = counters();
+    counters.retain(|path, c| wanted.get(path) == Some(&c.run_id));
+    let mut first_error = None;
+    for (path, run_id) in wanted {
+        if counters.contains_key(&path) {
+            continue;
+        }
+        match Counter::attach(root, &path, &run_id) {
+            Ok(counter) => {
+                info!("Accounting network flows of {} in {}", run_id, path);
+                counters.insert(path, counter);
+            }
+            // the container or unit stopped in the meantime
+            Err(_) if !cgroup_dir(root, &path).exists() => {}
+            Err(e) => {
+                first_error.get_or_insert(e.context(format!("cannot attach to cgroup {}", path)));
+            }
+        }
+    }
+    match first_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn cgroup_root() -> Option<&'static Path> {
+    CGROUP_ROOTS
+        .iter()
+        .map(Path::new)
+        .find(|root| root.join("cgroup.procs").exists())
+}
+
+fn cgroup_dir(root: &Path, path: &str) -> PathBuf {
+    root.join(path.trim_start_matches('/'))
+}
+
+/// Cgroups of the runs' processes, by the process' `M87_RUN_ID` or the
+/// containers and units of the runs' drift checks. Cgroups inside another
+/// one found are left out, since its programs count them already.
+pub(crate) async fn run_cgroups(revision: &DeploymentRevision) -> BTreeMap<String, String> {
+    let mut containers = Vec::new();
+    for job in &revision.jobs {
+        for name in job.drift.iter().flat_map(|d| &d.containers) {
+            if let Some(id) = container_id(name).await {
+                containers.push((id, job.id.clone()));
+            }
+        }
+    }
+    let own = std::fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|c| unified_path(&c).map(str::to_string));
+
+    let mut found = BTreeMap::new();
+    let Ok(procs) = std::fs::read_dir("/proc") else {
+        return found;
+    };
+    for entry in procs.filter_map(|e| e.ok()) {
+        let proc_dir = entry.path();
+        if entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+            .is_none()
+        {
+            continue;
+        }
+        let Ok(cgroup) = std::fs::read_to_string(proc_dir.join("cgroup")) else {
+            continue;
+        };
+        let Some(path) = unified_path(&cgroup) else {
+            continue;
+        };
+        // counting the runtime's cgroup would count the runtime itself
+        if found.contains_key(path) || own.as_deref().is_some_and(|own| contains(path, own)) {
+            continue;
+        }
+        let run_id = std::fs::read(proc_dir.join("environ"))
+            .ok()
+            .and_then(|environ| env_value(&environ, RUN_ID_ENV))
+            .filter(|id| revision.jobs.iter().any(|job| &job.id == id))
+            .or_else(|| {
+                revision
+                    .jobs
+                    .iter()
+                    .find(|job| {
+                        job.drift
+                            .iter()
+                            .flat_map(|d| &d.units)
+                            .any(|unit| cgroup_has_unit(&cgroup, unit))
+                    })
+                    .map(|job| job.id.clone())
+            })
+            .or_else(|| {
+                containers
+                    .iter()
+                    .find(|(id, _)| path.contains(id.as_str()))
+                    .map(|(_, run_id)| run_id.clone())
+            });
+        if let Some(run_id) = run_id {
+            found.insert(path.to_string(), run_id);
+        }
+    }
+    outermost(found)
+}
+
+/// Path in the cgroup v2 hierarchy of a `/proc/<pid>/cgroup` text.
+fn unified_path(cgroup: &str) -> Option<&str> {
+    cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(str::trim)
+}
+
+/// Whether cgroup `child` is `parent` or below it.
+fn contains(parent: &str, child: &str) -> bool {
+    parent == "/"
+        || child
+            .strip_prefix(parent)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn outermost(found: BTreeMap<String, String>) -> BTreeMap<String, String> {
+    found
+        .iter()
+        .filter(|(path, _)| {
+            !found
+                .keys()
+                .any(|other| other != *path && contains(other, path))
+        })
+        .map(|(path, run_id)| (path.clone(), run_id.clone()))
+        .collect()
+}
+
+/// Bytes each run moved since the previous call, most first.
+pub fn top_talkers() -> Vec<RunTraffic> {
+    let mut per_run: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    let mut counters = counters();
+    for counter in counters.values_mut() {
+        let (Ok(rx), Ok(tx)) = (
+            read_counter(&counter.map, BPF_CGROUP_INET_INGRESS),
+            read_counter(&counter.map, BPF_CGROUP_INET_EGRESS),
+        ) else {
+            continue;
+        };
+        let entry = per_run.entry(counter.run_id.clone()).or_default();
+        entry.0 += rx.saturating_sub(counter.reported.0);
+        entry.1 += tx.saturating_sub(counter.reported.1);
+        counter.reported = (rx, tx);
+    }
+
+    let mut talkers: Vec<RunTraffic> = per_run
+        .into_iter()
+        .map(|(run_id, (rx_bytes, tx_bytes))| RunTraffic {
+            run_id,
+            rx_bytes,
+            tx_bytes,
+        })
+        .filter(|t| t.total_bytes() > 0)
+        .collect();
+    talkers.sort_by_key(|t| std::cmp::Reverse(t.total_bytes()));
+    talkers.truncate(TOP_TALKERS);
+    talkers
+}
+
+impl Counter {
+    fn attach(root: &Path, path: &str, run_id: &str) -> Result<Self> {
+        raise_memlock_limit();
+        let cgroup = std::fs::File::open(cgroup_dir(root, path))?;
+        let map = create_map().context("cannot create the counter map")?;
+        let link = |attach_type: u32| -> Result<OwnedFd> {
+            let prog = load_program(&map, attach_type).context("cannot load the counter")?;
+            let mut attr = LinkCreateAttr {
+                prog_fd: prog.as_raw_fd() as u32,
+                target_fd: cgroup.as_raw_fd() as u32,
+                attach_type,
+                flags: 0,
+            };
+            bpf_fd(BPF_LINK_CREATE, &mut attr).context("cannot link the counter")
+        };
+        let links = [
+            link(BPF_CGROUP_INET_INGRESS)?,
+            link(BPF_CGROUP_INET_EGRESS)?,
+        ];
+        Ok(Self {
+            run_id: run_id.to_string(),
+            map,
+            _links: links,
+            reported: (0, 0),
+        })
+    }
+}
+
+/// Kernels before 5.11 charge BPF maps and programs to `RLIMIT_MEMLOCK`.
+fn raise_memlock_limit() {
+    static RAISED: Once = Once::new();
+    RAISED.call_once(|| {
+        let limit = libc::rlimit {
+            rlim_cur: libc::RLIM_INFINITY,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        // SAFETY: setrlimit only reads the struct
+        unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &limit) };
+    });
+}
+
+#[repr(C)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    map_flags: u32,
+}
+
+#[repr(C)]
+struct MapElemAttr {
+    map_fd: u32,
+    _pad: u32,
+    key: u64,
+    value: u64,
+    flags: u64,
+}
+
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+    prog_ifindex: u32,
+    expected_attach_type: u32,
+}
+
+#[repr(C)]
+struct LinkCreateAttr {
+    prog_fd: u32,
+    target_fd: u32,
+    attach_type: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Insn {
+    code: u8,
+    /// dst in the low and src in the high nibble on little endian
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+const fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Insn {
+    let regs = if cfg!(target_endian = "big") {
+        (dst << 4) | src
+    } else {
+        (src << 4) | dst
+    };
+    Insn {
+        code,
+        regs,
+        off,
+        imm,
+    }
+}
+
+/// Program adding the length of every packet to entry `key` of the array
+/// map `map_fd`, and letting the packet pass.
+fn counter_program(map_fd: RawFd, key: u32) -> Vec<Insn> {
+    vec![
+        // r6 = skb->len
+        insn(0x61, 6, 1, 0, 0),
+        // *(u32 *)(r10 - 4) = key
+        insn(0x62, 10, 0, -4, key as i32),
+        // r1 = map
+        insn(0x18, 1, BPF_PSEUDO_MAP_FD, 0, map_fd),
+        insn(0, 0, 0, 0, 0),
+        // r2 = r10 - 4
+        insn(0xbf, 2, 10, 0, 0),
+        insn(0x07, 2, 0, 0, -4),
+        // r0 = bpf_map_lookup_elem(r1, r2)
+        insn(0x85, 0, 0, 0, BPF_FUNC_MAP_LOOKUP_ELEM),
+        // if r0 == 0 goto allow
+        insn(0x15, 0, 0, 1, 0),
+        // lock *(u64 *)(r0 + 0) += r6
+        insn(0xdb, 0, 6, 0, 0),
+        // allow: return 1
+        insn(0xb7, 0, 0, 0, 1),
+        insn(0x95, 0, 0, 0, 0),
+    ]
+}
+
+fn create_map() -> std::io::Result<OwnedFd> {
+    let mut attr = MapCreateAttr {
+        map_type: BPF_MAP_TYPE_ARRAY,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 2,
+        map_flags: 0,
+    };
+    bpf_fd(BPF_MAP_CREATE, &mut attr)
+}
+
+fn load_program(map: &OwnedFd, attach_type: u32) -> std::io::Result<OwnedFd> {
+    let insns = counter_program(map.as_raw_fd(), attach_type);
+    let license = c"GPL";
+    let mut prog_name = [0u8; 16];
+    let name: &[u8] = if attach_type == BPF_CGROUP_INET_INGRESS {
+        b"m87_flow_rx"
+    } else {
+        b"m87_flow_tx"
+    };
+    prog_name[..name.len()].copy_from_slice(name);
+    let mut attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_CGROUP_SKB,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+        prog_name,
+        prog_ifindex: 0,
+        expected_attach_type: attach_type,
+    };
+    bpf_fd(BPF_PROG_LOAD, &mut attr)
+}
+
+fn read_counter(map: &OwnedFd, key: u32) -> std::io::Result<u64> {
+    let mut value = 0u64;
+    let mut attr = MapElemAttr {
+        map_fd: map.as_raw_fd() as u32,
+        _pad: 0,
+        key: &key as *const u32 as u64,
+        value: &mut value as *mut u64 as u64,
+        flags: 0,
+    };
+    bpf(BPF_MAP_LOOKUP_ELEM, &mut attr)?;
+    Ok(value)
+}
+
+fn bpf_fd<T>(cmd: libc::c_int, attr: &mut T) -> std::io::Result<OwnedFd> {
+    let fd = bpf(cmd, attr)?;
+    // SAFETY: these commands return a new file descriptor we own
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(target_os = "linux")]
+fn bpf<T>(cmd: libc::c_int, attr: &mut T) -> std::io::Result<RawFd> {
+    // SAFETY: attr is a live bpf_attr layout for cmd, and the pointers in it
+    // outlive the call
+    let ret = unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            cmd,
+            attr as *mut T as *mut libc::c_void,
+            std::mem::size_of::<T>() as libc::c_uint,
+        )
+    };
+    if ret < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(ret as RawFd)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bpf<T>(_cmd: libc::c_int, _attr: &mut T) -> std::io::Result<RawFd> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_loads_the_map_and_adds_the_packet_length() {
+        let prog = counter_program(7, BPF_CGROUP_INET_EGRESS);
+        assert_eq!(prog.len(), 11);
+        assert_eq!(prog[1].imm, 1);
+        // the 64 bit load carries the map fd
+        assert_eq!(prog[2], insn(0x18, 1, BPF_PSEUDO_MAP_FD, 0, 7));
+        assert_eq!(prog.last().unwrap().code, 0x95);
+        if cfg!(target_endian = "little") {
+            assert_eq!(prog[8].regs, 0x60);
+        }
+    }
+
+    #[test]
+    fn nested_cgroups_are_counted_by_the_outer_one() {
+        let cgroup = "0::/system.slice/docker-abc.scope\n";
+        assert_eq!(unified_path(cgroup), Some("/system.slice/docker-abc.scope"));
+        assert_eq!(unified_path("1:name=systemd:/init.scope\n"), None);
+
+        assert!(contains("/system.slice", "/system.slice/m87.service"));
+        assert!(!contains("/system.slice/m87", "/system.slice/m87.service"));
+        assert!(contains("/", "/user.slice"));
+
+        let found = BTreeMap::from([
+            (
+                "/system.slice/camera.service".to_string(),
+                "camera".to_string(),
+            ),
+            (
+                "/system.slice/camera.service/worker".to_string(),
+                "camera".to_string(),
+            ),
+            (
+                "/system.slice/docker-abc.scope".to_string(),
+                "nav".to_string(),
+            ),
+        ]);
+        let kept: Vec<_> = outermost(found).into_keys().collect();
+        assert_eq!(
+            kept,
+            [
+                "/system.slice/camera.service",
+                "/system.slice/docker-abc.scope"
+            ]
+        );
+    }
+}
@@ -1,29 +1,42 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
 use std::sync::Arc;
 
-use crate::devices;
+use crate::devices::{self, ResolvedDevice};
 use crate::streams::quic::connect_quic_only;
+use crate::streams::quic::open_quic_io;
 use crate::streams::quic::open_quic_stream;
-use crate::streams::stream_type::{ForwardTarget, SocketTarget, TcpTarget, UdpTarget};
+use crate::streams::stream_type::{
+    ForwardTarget, ResolveReply, SocketTarget, StreamClass, StreamType, TcpTarget, UdpTarget,
+};
 use crate::util::shutdown::SHUTDOWN;
 use crate::util::udp::decode_socket_addr;
 use crate::util::udp::encode_socket_addr;
 use crate::{auth::AuthManager, config::Config};
-use anyhow::Result;
+use anyhow::{Context, Result, anyhow, bail};
 use bytes::BufMut;
 use bytes::BytesMut;
 use tokio::io;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::TcpListener;
 use tokio::net::UdpSocket;
 use tokio::net::UnixListener;
 use tracing::{debug, error, info, warn};
 
-pub async fn open_local_forward(device_name: &str, forward_specs: Vec<String>) -> Result<()> {
+pub async fn open_local_forward(
+    device_name: &str,
+    forward_specs: Vec<String>,
+    resolve_remote: bool,
+) -> Result<()> {
     let config = Config::load()?;
     let resolved = devices::resolve_device_cached(device_name).await?;
     let token = AuthManager::get_cli_token().await?;
     let trust = config.trust_invalid_server_cert;
 
-    let forwards: Vec<ForwardTarget> = ForwardTarget::from_list(forward_specs)?;
+    let mut forwards: Vec<ForwardTarget> = ForwardTarget::from_list(forward_specs)?;
+    if resolve_remote {
+        pin_remote_hosts(&resolved, &token, trust, &mut forwards).await?;
+    }
 
     // spawn each forward as a background task
     for t in forwards {
@@ -45,6 +58,74 @@ pub async fn open_local_forward(device_name: &str, forward_specs: Vec<String>) -
     Ok(())
 }
 
+/// Resolve the host names of `forwards` on the device and replace them with
+/// the first address, so unknown names fail before anything listens and all
+/// connections of a forward go to the same address.
+async fn pin_remote_hosts(
+    resolved: &ResolvedDevice,
+    token: &str,
+    trust: bool,
+    forwards: &mut [ForwardTarget],
+) -> Result<()> {
+    let mut pinned: HashMap<String, IpAddr> = HashMap::new();
+    for forward in forwards.iter_mut() {
+        let host = match forward {
+            ForwardTarget::Tcp(t) => &mut t.remote_host,
+            ForwardTarget::Udp(t) => &mut t.remote_host,
+            _ => continue,
+        };
+        if host.parse::<IpAddr>().is_ok() {
+            continue;
+        }
+        let ip = match pinned.get(host.as_str()) {
+            Some(ip) => *ip,
+            None => {
+                let ip = resolve_on_device(resolved, token, trust, host).await?[0];
+                println!("{} → {} (resolved on {})", host, ip, resolved.short_id);
+                pinned.insert(host.clone(), ip);
+                ip
+            }
+        };
+        *host = ip.to_string();
+    }
+    Ok(())
+}
+
+/// Addresses of `host` from the device's resolver.
+pub async fn resolve_on_device(
+    resolved: &ResolvedDevice,
+    token: &str,
+    trust: bool,
+    host: &str,
+) -> Result<Vec<IpAddr>> {
+    let stream_type = StreamType::Resolve {
+        token: token.to_string(),
+        host: host.to_string(),
+    };
+    let (_conn, io) = open_quic_io(
+        &resolved.host,
+        token,
+        &resolved.short_id,
+        stream_type,
+        trust,
+    )
+    .await?;
+    let line = BufReader::new(io)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("device closed the stream without resolving {}", host))?;
+    let reply: ResolveReply =
+        serde_json::from_str(&line).context("Failed to parse resolve reply")?;
+    if let Some(error) = reply.error {
+        bail!(error);
+    }
+    if reply.addrs.is_empty() {
+        bail!("{} has no addresses on the device", host);
+    }
+    Ok(reply.addrs)
+}
+
 pub async fn forward_device_port(
     host_name: &str,
     token: &str,
@@ -105,8 +186,14 @@ async fn forward_device_port_tcp(
     let remote_host = forward_spec.remote_host.clone();
 
     debug!("Connecting to QUIC server...");
-    let (_endpoint, conn) =
-        connect_quic_only(host_name, token, device_short_id, trust_invalid_server_cert).await?;
+    let (_endpoint, conn) = connect_quic_only(
+        host_name,
+        token,
+        device_short_id,
+        trust_invalid_server_cert,
+        StreamClass::Bulk,
+    )
+    .await?;
     debug!("QUIC connection established, entering accept loop");
 
     println!(
@@ -159,8 +246,14 @@ async fn forward_device_port_udp(
     forward_spec: &UdpTarget,
     trust_invalid_server_cert: bool,
 ) -> Result<()> {
-    let (_endpoint, conn) =
-        connect_quic_only(host_name, token, device_short_id, trust_invalid_server_cert).await?;
+    let (_endpoint, conn) = connect_quic_only(
+        host_name,
+        token,
+        device_short_id,
+        trust_invalid_server_cert,
+        StreamClass::Bulk,
+    )
+    .await?;
 
     // Send StreamType::Forward over a QUIC stream
     let stream_type = forward_spec.to_stream_type(token);
@@ -308,8 +401,14 @@ async fn forward_device_socket(
         local_path, device_short_id, target.remote_path
     );
 
-    let (_endpoint, conn) =
-        connect_quic_only(host_name, token, device_short_id, trust_invalid_server_cert).await?;
+    let (_endpoint, conn) = connect_quic_only(
+        host_name,
+        token,
+        device_short_id,
+        trust_invalid_server_cert,
+        StreamClass::Bulk,
+    )
+    .await?;
 
     loop {
         tokio::select! {
@@ -16,8 +16,9 @@ use russh::client::{Config as ClientConfig, Handler};
 use russh_sftp::client::SftpSession;
 
 use crate::devices;
-use crate::streams::quic::open_quic_io;
+use crate::streams::quic::open_interactive_io;
 use crate::streams::stream_type::StreamType;
+use crate::util::command::shell_quote;
 use crate::util::shutdown::SHUTDOWN;
 use crate::{auth::AuthManager, config::Config};
 
@@ -59,16 +60,11 @@ pub async fn open_sftp_session(device_name: &str) -> anyhow::Result<SftpSession>
     // open raw tunnel through HTTPS upgrade
     let stream_type = StreamType::Ssh {
         token: token.to_string(),
+        transfer: true,
     };
-    let (_, io) = open_quic_io(
-        &resolved.host,
-        &token,
-        &resolved.short_id,
-        stream_type,
-        cfg.trust_invalid_server_cert,
-    )
-    .await
-    .context("Failed to connect to RAW metrics stream")?;
+    let (_, io) = open_interactive_io(&resolved, &token, stream_type, &cfg)
+        .await
+        .context("Failed to connect to RAW metrics stream")?;
 
     // minimal ssh client config
     let mut config = ClientConfig::default();
@@ -543,6 +539,134 @@ pub async fn watch_sync(src: &str, dst: &str, delete: bool, excludes: &[String])
     }
 }
 
+/// How often `dev sync` looks for local changes
+const DEV_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Mirror the local directory `src` to `<device>:<path>` whenever it changes,
+/// and rerun the run `restart` on the device after each change. Only the files
+/// changed since the previous pass are sent, over one SFTP session that is
+/// reopened if the tunnel drops.
+pub async fn dev_sync(
+    src: &str,
+    dst: &str,
+    restart: Option<&str>,
+    excludes: &[String],
+) -> Result<()> {
+    let src_path = LocalOrRemotePath::parse(src);
+    let dst_path = LocalOrRemotePath::parse(dst);
+    let (LocalOrRemotePath::Local(root), LocalOrRemotePath::Remote { device, path }) =
+        (&src_path, &dst_path)
+    else {
+        bail!("dev sync mirrors a local directory to <device>:<path>");
+    };
+
+    // the first pass brings the device in line, including deletes
+    sync(src, dst, true, false, excludes).await?;
+    restart_run(device, restart).await;
+    let mut last = read_local_tree(root, excludes).await?;
+    let dst_root = PathBuf::from(path);
+    let mut sftp_src = None;
+    let mut sftp_dst = None;
+    println!("Watching {} for changes (Ctrl+C to stop)", root.display());
+
+    loop {
+        tokio::select! {
+            _ = sleep(DEV_POLL_INTERVAL) => {}
+            _ = SHUTDOWN.cancelled() => return Ok(()),
+        }
+        let tree = match read_local_tree(root, excludes).await {
+            Ok(tree) => tree,
+            Err(e) => {
+                error!("cannot read {}: {e:#}", root.display());
+                continue;
+            }
+        };
+        let (upload, delete) = tree_changes(&last.files, &tree.files);
+        if upload.is_empty() && delete.is_empty() {
+            continue;
+        }
+        if sftp_dst.is_none() {
+            match open_sftp_session(device).await {
+                Ok(sftp) => sftp_dst = Some(sftp),
+                Err(e) => {
+                    error!("cannot reach {device}: {e:#}");
+                    continue;
+                }
+            }
+        }
+
+        let mut pass = Ok(());
+        for rel in &upload {
+            println!("uploading {rel}");
+            pass = copy_file(
+                &LocalOrRemotePath::Local(root.join(rel)),
+                &LocalOrRemotePath::from_path(&dst_path, &dst_root.join(rel)),
+                &mut sftp_src,
+                &mut sftp_dst,
+            )
+            .await;
+            if pass.is_err() {
+                break;
+            }
+        }
+        if pass.is_ok() {
+            for rel in &delete {
+                println!("deleting {rel}");
+                pass = delete_file(
+                    &LocalOrRemotePath::from_path(&dst_path, &dst_root.join(rel)),
+                    &mut sftp_dst,
+                )
+                .await;
+                if pass.is_err() {
+                    break;
+                }
+            }
+        }
+        if let Err(e) = pass {
+            // retried with a new session on the next pass
+            error!("sync failed: {e:#}");
+            sftp_dst = None;
+            continue;
+        }
+        last = tree;
+        restart_run(device, restart).await;
+    }
+}
+
+/// Files of `new` to upload because they are new or changed, and files of
+/// `old` to delete, each sorted.
+fn tree_changes(
+    old: &HashMap<String, FileInfo>,
+    new: &HashMap<String, FileInfo>,
+) -> (Vec<String>, Vec<String>) {
+    let mut upload: Vec<String> = new
+        .iter()
+        .filter(|(rel, info)| old.get(*rel).map(|o| &o.fingerprint) != Some(&info.fingerprint))
+        .map(|(rel, _)| rel.clone())
+        .collect();
+    let mut delete: Vec<String> = old
+        .keys()
+        .filter(|rel| !new.contains_key(*rel))
+        .cloned()
+        .collect();
+    upload.sort();
+    delete.sort();
+    (upload, delete)
+}
+
+/// Queue a rerun of `run` with the runtime on `device`.
+async fn restart_run(device: &str, run: Option<&str>) {
+    let Some(run) = run else {
+        return;
+    };
+    let command = format!("m87 local rerun {}", shell_quote(run));
+    match crate::tui::exec::run_streamed(device, command).await {
+        Ok(0) => println!("restarting {run}"),
+        Ok(code) => error!("rerun of {run} failed with exit code {code}"),
+        Err(e) => error!("rerun of {run} failed: {e:#}"),
+    }
+}
+
 async fn sync_remote_mtime(sftp: &SftpSession, remote_path: &str, src_mtime: u64) {
     let mut attrs = Metadata::default();
     attrs.mtime = Some(src_mtime as u32);
@@ -747,4 +871,29 @@ mod tests {
         assert!(matches_exclude(".git", &excludes));
         assert!(!matches_exclude("src/main.rs", &excludes));
     }
+
+    // --- tree_changes tests ---
+
+    fn tree(files: &[(&str, &str)]) -> HashMap<String, FileInfo> {
+        files
+            .iter()
+            .map(|(rel, fp)| {
+                (
+                    rel.to_string(),
+                    FileInfo {
+                        fingerprint: fp.to_string(),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tree_changes_uploads_new_and_changed_and_deletes_removed() {
+        let old = tree(&[("a.py", "1:1"), ("b.py", "1:1"), ("c.py", "1:1")]);
+        let new = tree(&[("a.py", "1:1"), ("b.py", "2:5"), ("d.py", "1:1")]);
+        let (upload, delete) = tree_changes(&old, &new);
+        assert_eq!(upload, vec!["b.py", "d.py"]);
+        assert_eq!(delete, vec!["c.py"]);
+    }
 }
@@ -0,0 +1,266 @@
+//! Size and age caps for the files a runtime writes under its data directory:
+//! its own log file, the queue of undelivered and dead-lettered reports and
+//! workdirs no revision uses anymore. Runs at startup, then hourly, and on
+//! demand with `m87 runtime gc`.
+
+use anyhow::{Context, Result};
+use std::{
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, time::sleep};
+use tracing::{info, warn};
+
+use crate::{
+    config::{Config, DiskLimits},
+    device::deployment_manager,
+    tui::fs::human_size,
+    util::{logging::RotatingFile, shutdown::SHUTDOWN},
+};
+
+const GC_INTERVAL: Duration = Duration::from_secs(3600);
+const MB: u64 = 1024 * 1024;
+const RUNTIME_LOG: &str = "runtime.log";
+
+/// Files deleted by a cleanup pass and the space they took.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Reclaimed {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Reclaimed {
+    /// Delete `file`, counting it unless it was already gone.
+    pub async fn remove(&mut self, file: &FileEntry) {
+        match fs::remove_file(&file.path).await {
+            Ok(()) => {
+                self.files += 1;
+                self.bytes += file.size;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete {}: {}", file.path.display(), e),
+        }
+    }
+
+    /// Delete the directory tree at `path`, counting the files in it.
+    pub async fn remove_dir(&mut self, path: &Path) {
+        let usage = dir_usage(path.to_path_buf()).await;
+        match fs::remove_dir_all(path).await {
+            Ok(()) => {
+                self.files += usage.files;
+                self.bytes += usage.bytes;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to delete {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl AddAssign for Reclaimed {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+    }
+}
+
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+impl FileEntry {
+    /// Whether the file was last written more than `age` ago. A zero age never matches.
+    pub fn older_than(&self, age: Duration) -> bool {
+        !age.is_zero() && self.modified.elapsed().is_ok_and(|e| e > age)
+    }
+}
+
+/// Regular files in `dir` sorted by name, which is oldest first for files
+/// named by ULID or timestamp. A missing directory has no files.
+pub async fn files_by_name(dir: &Path) -> Result<Vec<FileEntry>> {
+    let mut files = Vec::new();
+    let mut rd = match fs::read_dir(dir).await {
+        Ok(rd) => rd,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+        Err(e) => return Err(e).with_context(|| format!("read {}", dir.display())),
+    };
+    while let Some(e) = rd.next_entry().await? {
+        let Ok(meta) = e.metadata().await else {
+            continue;
+        };
+        if meta.is_file() {
+            files.push(FileEntry {
+                path: e.path(),
+                size: meta.len(),
+                modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            });
+        }
+    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// Files in a directory tree, their size and when the tree last changed.
+pub struct DirUsage {
+    pub files: u64,
+    pub bytes: u64,
+    pub modified: SystemTime,
+}
+
+/// Walk the tree at `path` without following symlinks.
+pub async fn dir_usage(path: PathBuf) -> DirUsage {
+    tokio::task::spawn_blocking(move || {
+        let mut usage = DirUsage {
+            files: 0,
+            bytes: 0,
+            modified: SystemTime::UNIX_EPOCH,
+        };
+        let mut stack = vec![path];
+        while let Some(p) = stack.pop() {
+            let Ok(meta) = std::fs::symlink_metadata(&p) else {
+                continue;
+            };
+            if let Ok(modified) = meta.modified() {
+                usage.modified = usage.modified.max(modified);
+            }
+            if meta.is_dir() {
+                if let Ok(rd) = std::fs::read_dir(&p) {
+                    stack.extend(rd.flatten().map(|e| e.path()));
+                }
+            } else {
+                usage.files += 1;
+                usage.bytes += meta.len();
+            }
+        }
+        usage
+    })
+    .await
+    .unwrap_or(DirUsage {
+        files: 0,
+        bytes: 0,
+        modified: SystemTime::now(),
+    })
+}
+
+pub fn logs_dir() -> Result<PathBuf> {
+    Ok(deployment_manager::data_dir()?.join("logs"))
+}
+
+/// The runtime log file, if `disk_limits.log_file` is enabled.
+pub fn runtime_log_file() -> Option<RotatingFile> {
+    let limits = Config::load().ok()?.disk_limits;
+    if !limits.log_file {
+        return None;
+    }
+    let path = logs_dir().ok()?.join(RUNTIME_LOG);
+    match RotatingFile::open(&path, limits.log_max_file_mb * MB, limits.log_max_files) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            // tracing is not set up yet
+            eprintln!("Failed to open {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// The runtime log and its rotations that exist, newest first.
+pub async fn runtime_logs() -> Result<Vec<PathBuf>> {
+    let prefix = format!("{RUNTIME_LOG}.");
+    let mut logs: Vec<(u32, PathBuf)> = files_by_name(&logs_dir()?)
+        .await?
+        .into_iter()
+        .filter_map(|file| {
+            let name = file.path.file_name()?.to_str()?;
+            let n = if name == RUNTIME_LOG {
+                0
+            } else {
+                name.strip_prefix(&prefix)?.parse().ok()?
+            };
+            Some((n, file.path))
+        })
+        .collect();
+    logs.sort_by_key(|(n, _)| *n);
+    Ok(logs.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Rotated runtime logs older than `max_age` or beyond `log_max_files`
+/// (e.g. after the limit was lowered).
+async fn gc_logs(limits: &DiskLimits, max_age: Duration) -> Result<Reclaimed> {
+    let mut reclaimed = Reclaimed::default();
+    let prefix = format!("{RUNTIME_LOG}.");
+    for file in files_by_name(&logs_dir()?).await? {
+        let Some(n) = file
+            .path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(&prefix))
+            .and_then(|n| n.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if n > limits.log_max_files || file.older_than(max_age) {
+            reclaimed.remove(&file).await;
+        }
+    }
+    Ok(reclaimed)
+}
+
+/// One cleanup pass over logs and queued reports.
+pub async fn run(limits: &DiskLimits) -> Result<Reclaimed> {
+    let max_age = Duration::from_secs(limits.max_age_days * 86_400);
+    let mut reclaimed = gc_logs(limits, max_age).await?;
+    reclaimed += deployment_manager::gc_events(max_age, limits.max_event_queue_mb * MB).await?;
+    let retention = Duration::from_secs(limits.workdir_retention_days * 86_400);
+    reclaimed += deployment_manager::gc_workdirs(retention).await?;
+    Ok(reclaimed)
+}
+
+/// Clean up now and then every hour until shutdown. Limits are re-read each
+/// time, so config changes apply without a restart.
+pub fn spawn() {
+    tokio::spawn(async move {
+        loop {
+            let limits = Config::load().map(|c| c.disk_limits).unwrap_or_default();
+            match run(&limits).await {
+                Ok(r) if r.files > 0 => info!(
+                    "Disk cleanup removed {} files ({})",
+                    r.files,
+                    human_size(r.bytes)
+                ),
+                Ok(_) => {}
+                Err(e) => warn!("Disk cleanup failed: {:#}", e),
+            }
+            tokio::select! {
+                _ = sleep(GC_INTERVAL) => {}
+                _ = SHUTDOWN.cancelled() => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_remove_dir_counts_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let wd = dir.path().join("jobs").join("old-spec");
+        std::fs::create_dir_all(wd.join("data")).unwrap();
+        std::fs::write(wd.join("run_state.json"), "{}").unwrap();
+        std::fs::write(wd.join("data").join("blob"), vec![0u8; 100]).unwrap();
+
+        let mut reclaimed = Reclaimed::default();
+        reclaimed.remove_dir(&wd).await;
+        assert_eq!(
+            reclaimed,
+            Reclaimed {
+                files: 2,
+                bytes: 102
+            }
+        );
+        assert!(!wd.exists());
+    }
+}
@@ -0,0 +1,186 @@
+//! `m87 <device> gui <cmd>`: run a graphical program on the device and show
+//! its windows on the local X server. The runtime forwards the X connections
+//! the program makes (see `streams::gui`); this side dials the local display
+//! for each of them.
+
+use anyhow::{Context, Result, anyhow, bail};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::mpsc;
+
+use crate::streams::mux::{CONTROL, Frame, Mux, OUTPUT, pump_frames, read_frame};
+use crate::streams::quic::open_quic_io;
+use crate::streams::stream_type::{GuiEvent, StreamType};
+use crate::{auth::AuthManager, config::Config, devices, util::shutdown::SHUTDOWN};
+
+/// Where the local X server listens.
+#[derive(Debug, PartialEq)]
+enum XServer {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl XServer {
+    async fn connect(&self, mux: &mut Mux, id: u32) -> std::io::Result<()> {
+        match self {
+            XServer::Unix(path) => mux.attach(id, UnixStream::connect(path).await?),
+            XServer::Tcp(addr) => mux.attach(id, TcpStream::connect(addr).await?),
+        }
+        Ok(())
+    }
+}
+
+/// Parse `DISPLAY` (`[host]:N[.screen]`, or a socket path as set by XQuartz).
+fn x_server(display: &str) -> Option<XServer> {
+    let colon = display.rfind(':')?;
+    let (host, rest) = (&display[..colon], &display[colon + 1..]);
+    let number_str = rest.split('.').next()?;
+    let number: u32 = number_str.parse().ok()?;
+    if host.starts_with('/') {
+        return Some(XServer::Unix(PathBuf::from(format!(
+            "{}:{}",
+            host, number_str
+        ))));
+    }
+    match host {
+        "" | "unix" => Some(XServer::Unix(PathBuf::from(format!(
+            "/tmp/.X11-unix/X{}",
+            number
+        )))),
+        host => Some(XServer::Tcp(format!("{}:{}", host, 6000 + number))),
+    }
+}
+
+/// First MIT-MAGIC-COOKIE-1 in `xauth list` output, as hex.
+fn cookie_from_xauth_list(list: &str) -> Option<String> {
+    list.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        let _display = fields.next()?;
+        (fields.next()? == "MIT-MAGIC-COOKIE-1")
+            .then(|| fields.next())
+            .flatten()
+            .map(str::to_string)
+    })
+}
+
+/// Cookie the local X server expects, if it uses one.
+async fn local_cookie(display: &str) -> Option<String> {
+    let output = tokio::process::Command::new("xauth")
+        .args(["list", display])
+        .output()
+        .await
+        .ok()?;
+    cookie_from_xauth_list(&String::from_utf8_lossy(&output.stdout))
+}
+
+pub async fn run_gui(device: &str, command: Vec<String>) -> Result<()> {
+    // not `display`, which tracing's macros shadow
+    let local_display = std::env::var("DISPLAY")
+        .context("DISPLAY is not set; `gui` needs a local X server (XQuartz on macOS)")?;
+    let server = x_server(&local_display)
+        .ok_or_else(|| anyhow!("Unsupported DISPLAY '{}'", local_display))?;
+    let xauth_cookie = local_cookie(&local_display).await;
+
+    let config = Config::load()?;
+    let token = AuthManager::get_cli_token().await?;
+    let resolved = devices::resolve_device_cached(device).await?;
+
+    let stream_type = StreamType::Gui {
+        token: token.to_string(),
+        command: command.join(" "),
+        xauth_cookie,
+    };
+    let (_conn, io) = open_quic_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        config.trust_invalid_server_cert,
+    )
+    .await
+    .context("Failed to open gui stream")?;
+
+    let (mut reader, writer) = tokio::io::split(io);
+    let (out_tx, out_rx) = mpsc::unbounded_channel::<Frame>();
+    tokio::spawn(pump_frames(writer, out_rx));
+    let mut mux = Mux::new(out_tx.clone());
+    // Connection ids only grow, so late frames of a refused one are not redialed.
+    let mut next_conn = 1u32;
+    let mut stdout = tokio::io::stdout();
+
+    let exit_code = loop {
+        let frame = tokio::select! {
+            frame = read_frame(&mut reader) => frame?,
+            _ = SHUTDOWN.cancelled() => std::process::exit(130),
+        };
+        let Some((id, data)) = frame else {
+            bail!("Connection to the device closed");
+        };
+        match id {
+            OUTPUT => {
+                stdout.write_all(&data).await?;
+                stdout.flush().await?;
+            }
+            CONTROL => match serde_json::from_slice::<GuiEvent>(&data)? {
+                GuiEvent::Started { display: remote } => {
+                    tracing::info!("Running on display {} of {}", remote, device);
+                }
+                GuiEvent::Exited { exit_code } => break exit_code,
+                GuiEvent::Error { message } => bail!("{}", message),
+            },
+            id => {
+                if id >= next_conn && !data.is_empty() {
+                    next_conn = id + 1;
+                    if let Err(e) = server.connect(&mut mux, id).await {
+                        tracing::warn!("Failed to connect to X server {}: {}", local_display, e);
+                        let _ = out_tx.send((id, Vec::new()));
+                        continue;
+                    }
+                }
+                mux.deliver(id, data);
+            }
+        }
+    };
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x_server_from_display() {
+        assert_eq!(
+            x_server(":0"),
+            Some(XServer::Unix(PathBuf::from("/tmp/.X11-unix/X0")))
+        );
+        assert_eq!(
+            x_server("unix:1.0"),
+            Some(XServer::Unix(PathBuf::from("/tmp/.X11-unix/X1")))
+        );
+        assert_eq!(
+            x_server("localhost:10.0"),
+            Some(XServer::Tcp("localhost:6010".to_string()))
+        );
+        assert_eq!(
+            x_server("/private/tmp/com.apple.launchd.abc/org.xquartz:0"),
+            Some(XServer::Unix(PathBuf::from(
+                "/private/tmp/com.apple.launchd.abc/org.xquartz:0"
+            )))
+        );
+        assert_eq!(x_server("wayland-0"), None);
+    }
+
+    #[test]
+    fn test_cookie_from_xauth_list() {
+        let list = "laptop/unix:0  XDM-AUTHORIZATION-1  0011\n\
+                    laptop/unix:0  MIT-MAGIC-COOKIE-1  7f3a9c\n";
+        assert_eq!(cookie_from_xauth_list(list), Some("7f3a9c".to_string()));
+        assert_eq!(cookie_from_xauth_list(""), None);
+    }
+}
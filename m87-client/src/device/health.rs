@@ -0,0 +1,105 @@
+//! Liveness and readiness endpoints for container orchestrators
+//! (`m87 runtime run --health-addr`).
+//!
+//! `/healthz` answers 200 while the runtime is up, `/readyz` only while the
+//! control tunnel is connected and the server answers heartbeats.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use axum::{Json, Router, http::StatusCode, routing::get};
+use serde::Serialize;
+use tokio::net::TcpListener;
+use tracing::info;
+
+use crate::device::container;
+use crate::util::shutdown::SHUTDOWN;
+
+pub const DEFAULT_HEALTH_PORT: u16 = 8787;
+
+static CONNECTED: AtomicBool = AtomicBool::new(false);
+/// Unix seconds of the last heartbeat response, 0 before the first one
+static LAST_HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+/// Called by the control tunnel when the server answered a heartbeat.
+pub fn mark_heartbeat() {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    LAST_HEARTBEAT.store(now, Ordering::Relaxed);
+    CONNECTED.store(true, Ordering::Relaxed);
+}
+
+/// Called by the control tunnel when the connection ended.
+pub fn mark_disconnected() {
+    CONNECTED.store(false, Ordering::Relaxed);
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    status: &'static str,
+    connected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_heartbeat: Option<u64>,
+    version: &'static str,
+    container: bool,
+    docker: bool,
+}
+
+/// What `/healthz` reports, also part of support bundles.
+pub fn health() -> Health {
+    let connected = CONNECTED.load(Ordering::Relaxed);
+    let status = if SHUTDOWN.is_cancelled() {
+        "stopping"
+    } else if connected {
+        "ok"
+    } else {
+        "connecting"
+    };
+    Health {
+        status,
+        connected,
+        last_heartbeat: Some(LAST_HEARTBEAT.load(Ordering::Relaxed)).filter(|t| *t > 0),
+        version: env!("CARGO_PKG_VERSION"),
+        container: container::in_container(),
+        docker: container::docker_socket().is_some(),
+    }
+}
+
+async fn healthz() -> (StatusCode, Json<Health>) {
+    let health = health();
+    let code = if SHUTDOWN.is_cancelled() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        StatusCode::OK
+    };
+    (code, Json(health))
+}
+
+async fn readyz() -> (StatusCode, Json<Health>) {
+    let health = health();
+    let code = if health.status == "ok" {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, Json(health))
+}
+
+/// Serve the health endpoints on `addr` until shutdown.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz));
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind health endpoint on {}", addr))?;
+    info!("Health endpoint listening on http://{}", addr);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(SHUTDOWN.cancelled())
+        .await
+        .context("Health endpoint failed")
+}
@@ -0,0 +1,94 @@
+//! Read-only files of runs with `immutable: true`: written with mode 0444,
+//! owned by the runtime (root on installed devices), and marked immutable with
+//! `chattr +i` where the filesystem supports it. Their hashes are kept in the
+//! run's local state and checked before the run's steps start again.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use m87_shared::deploy_spec::content_hash;
+use tokio::process::Command;
+use tracing::debug;
+
+use crate::util::command::{binary_exists, safe_run_command};
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Make `path` writable again before it is replaced.
+pub async fn unlock(path: &Path) {
+    if !tokio::fs::try_exists(path).await.unwrap_or(false) {
+        return;
+    }
+    chattr("-i", path, false).await;
+    set_mode(path, 0o644).await;
+}
+
+/// Make `path` read-only and, where supported, immutable.
+pub async fn lock(path: &Path) {
+    set_mode(path, 0o444).await;
+    chattr("+i", path, false).await;
+}
+
+/// Lift the immutable flag of everything under `dir`, so it can be removed.
+pub async fn unlock_tree(dir: &Path) {
+    chattr("-i", dir, true).await;
+}
+
+/// Files under `wd` whose content no longer has the hash they were written
+/// with, including removed ones.
+pub async fn tampered_files(wd: &Path, written: &BTreeMap<String, String>) -> Vec<String> {
+    let mut tampered = Vec::new();
+    for (rel, hash) in written {
+        let same = match tokio::fs::read(wd.join(rel)).await {
+            Ok(content) => content_hash(&content) == *hash,
+            Err(_) => false,
+        };
+        if !same {
+            tampered.push(rel.clone());
+        }
+    }
+    tampered
+}
+
+#[cfg(unix)]
+async fn set_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+
+    let permissions = std::fs::Permissions::from_mode(mode);
+    if let Err(e) = tokio::fs::set_permissions(path, permissions).await {
+        debug!("cannot set mode {:o} on {}: {}", mode, path.display(), e);
+    }
+}
+
+#[cfg(not(unix))]
+async fn set_mode(path: &Path, mode: u32) {
+    let Ok(meta) = tokio::fs::metadata(path).await else {
+        return;
+    };
+    let mut permissions = meta.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    let _ = tokio::fs::set_permissions(path, permissions).await;
+}
+
+/// Best effort: needs root and a filesystem with the immutable attribute.
+async fn chattr(flag: &str, path: &Path, recursive: bool) {
+    if !binary_exists("chattr") {
+        return;
+    }
+    let mut cmd = Command::new("chattr");
+    if recursive {
+        cmd.arg("-R");
+    }
+    cmd.arg(flag).arg(path).kill_on_drop(true);
+    match safe_run_command(cmd, COMMAND_TIMEOUT).await {
+        Ok(output) if output.status.success() => {}
+        Ok(output) => debug!(
+            "chattr {} {} failed: {}",
+            flag,
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => debug!("chattr {} {} failed: {}", flag, path.display(), e),
+    }
+}
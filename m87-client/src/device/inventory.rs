@@ -0,0 +1,191 @@
+//! Installed packages and kernel, reported to the server for the advisory
+//! scan behind `m87 <device> vulnerabilities`.
+//!
+//! The dpkg, rpm and apk databases are read. Package lists change rarely, so
+//! the inventory is cached for [`REFRESH_INTERVAL`]; the control tunnel sends
+//! it with the first heartbeat and again whenever it changed.
+
+use m87_shared::inventory::{Package, PackageInventory, PackageManager};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use sysinfo::System;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::util::command::{binary_exists, safe_run_command};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+const APK_DB: &str = "/lib/apk/db/installed";
+
+const DPKG_FORMAT: &str =
+    "${db:Status-Abbrev}\t${Package}\t${Version}\t${source:Package}\t${source:Version}\n";
+const RPM_FORMAT: &str = "%{NAME}\t%|EPOCH?{%{EPOCH}:}:{}|%{VERSION}-%{RELEASE}\t%{SOURCERPM}\n";
+
+static CACHE: Mutex<Option<(Instant, PackageInventory)>> = Mutex::const_new(None);
+
+/// The cached inventory, recollected when older than [`REFRESH_INTERVAL`].
+pub async fn current() -> PackageInventory {
+    let mut cache = CACHE.lock().await;
+    if let Some((at, inventory)) = cache.as_ref()
+        && at.elapsed() < REFRESH_INTERVAL
+    {
+        return inventory.clone();
+    }
+    let inventory = collect().await;
+    debug!("collected {} installed packages", inventory.packages.len());
+    *cache = Some((Instant::now(), inventory.clone()));
+    inventory
+}
+
+/// Recollect on the next [`current`], after packages were changed.
+pub async fn invalidate() {
+    *CACHE.lock().await = None;
+}
+
+async fn collect() -> PackageInventory {
+    let (manager, mut packages) =
+        if let Some(out) = output("dpkg-query", &["-W", "-f", DPKG_FORMAT]).await {
+            (Some(PackageManager::Dpkg), parse_dpkg(&out))
+        } else if let Some(out) = output("rpm", &["-qa", "--qf", RPM_FORMAT]).await {
+            (Some(PackageManager::Rpm), parse_rpm(&out))
+        } else if let Ok(db) = tokio::fs::read_to_string(APK_DB).await {
+            (Some(PackageManager::Apk), parse_apk(&db))
+        } else {
+            (None, Vec::new())
+        };
+    packages.sort();
+    PackageInventory {
+        collected_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0),
+        os_id: Some(System::distribution_id()).filter(|id| !id.is_empty()),
+        os_version: System::os_version(),
+        kernel: System::kernel_version(),
+        manager,
+        packages,
+    }
+}
+
+/// Lines of [`DPKG_FORMAT`]; only fully installed (`ii`) packages are kept.
+fn parse_dpkg(out: &str) -> Vec<Package> {
+    out.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let status = fields.next()?;
+            if !status.starts_with("ii") {
+                return None;
+            }
+            let name = fields.next()?.to_string();
+            let version = fields.next()?.to_string();
+            let source = fields.next().filter(|s| !s.is_empty() && *s != name);
+            let source_version = fields.next().filter(|v| !v.is_empty() && *v != version);
+            Some(Package {
+                source: source.map(str::to_string),
+                source_version: source_version.map(str::to_string),
+                name,
+                version,
+            })
+        })
+        .collect()
+}
+
+/// Lines of [`RPM_FORMAT`]. The source package name is the source RPM file
+/// name without `-<version>-<release>.src.rpm`.
+fn parse_rpm(out: &str) -> Vec<Package> {
+    out.lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let name = fields.next()?.to_string();
+            let version = fields.next()?.to_string();
+            // signing keys are listed as packages
+            if name == "gpg-pubkey" {
+                return None;
+            }
+            let source = fields
+                .next()
+                .and_then(|srpm| srpm.strip_suffix(".src.rpm"))
+                .and_then(|srpm| srpm.rsplitn(3, '-').nth(2))
+                .filter(|s| *s != name)
+                .map(str::to_string);
+            Some(Package {
+                name,
+                version,
+                source,
+                source_version: None,
+            })
+        })
+        .collect()
+}
+
+/// Blocks of `/lib/apk/db/installed`: `P:` name, `V:` version, `o:` origin.
+fn parse_apk(db: &str) -> Vec<Package> {
+    db.split("\n\n")
+        .filter_map(|block| {
+            let field = |key: &str| {
+                block
+                    .lines()
+                    .find_map(|l| l.strip_prefix(key))
+                    .map(str::to_string)
+            };
+            let name = field("P:")?;
+            let version = field("V:")?;
+            let source = field("o:").filter(|o| *o != name);
+            Some(Package {
+                name,
+                version,
+                source,
+                source_version: None,
+            })
+        })
+        .collect()
+}
+
+async fn output(program: &str, args: &[&str]) -> Option<String> {
+    if !binary_exists(program) {
+        return None;
+    }
+    let mut cmd = Command::new(program);
+    cmd.args(args).kill_on_drop(true);
+    let out = safe_run_command(cmd, COMMAND_TIMEOUT).await.ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&out.stdout).into_owned()).filter(|s| !s.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_package_databases() {
+        let dpkg = "ii \tlibssl3\t3.0.11-1~deb12u2\topenssl\t3.0.11-1~deb12u2\n\
+                    ii \tbash\t5.2.15-2+b2\tbash\t5.2.15-2\n\
+                    rc \told-pkg\t1.0\told-pkg\t1.0\n";
+        let packages = parse_dpkg(dpkg);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].source.as_deref(), Some("openssl"));
+        assert_eq!(packages[0].source_version, None);
+        assert_eq!(packages[1].source, None);
+        assert_eq!(packages[1].source_version.as_deref(), Some("5.2.15-2"));
+
+        let rpm = "openssl-libs\t1:3.0.7-25.el9_3\topenssl-3.0.7-25.el9_3.src.rpm\n\
+                   gpg-pubkey\t3228467c-613798eb\t(none)\n\
+                   bash\t5.1.8-6.el9_1\tbash-5.1.8-6.el9_1.src.rpm\n";
+        let packages = parse_rpm(rpm);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].version, "1:3.0.7-25.el9_3");
+        assert_eq!(packages[0].source.as_deref(), Some("openssl"));
+        assert_eq!(packages[1].source, None);
+
+        let apk = "C:Q1abc=\nP:libcrypto3\nV:3.1.4-r5\no:openssl\n\n\
+                   C:Q1def=\nP:busybox\nV:1.36.1-r15\no:busybox\n";
+        let packages = parse_apk(apk);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "libcrypto3");
+        assert_eq!(packages[0].source.as_deref(), Some("openssl"));
+        assert_eq!(packages[1].source, None);
+    }
+}
@@ -0,0 +1,312 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use quinn::{Endpoint, ServerConfig, TransportConfig};
+use quinn_proto::crypto::rustls::QuicServerConfig;
+use russh::keys::ssh_key::rand_core::{OsRng, RngCore};
+use rustls::ServerConfig as RustlsServerConfig;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::time::timeout;
+use tracing::{debug, info, warn};
+
+use m87_shared::device::short_device_id;
+
+use crate::auth::AuthManager;
+use crate::config::Config;
+use crate::device::control_tunnel::serve_device_streams;
+use crate::device::deployment_manager::DeploymentManager;
+use crate::device::recovery;
+use crate::util::lan::{
+    AUTH_NONCE_LEN, AUTH_TIMEOUT, AUTH_VERSION, DIRECT_SERVER_NAME, SERVICE_TYPE, auth_proof,
+    cert_fingerprint, key_hash,
+};
+use crate::util::private_file::write_private;
+use crate::util::shutdown::SHUTDOWN;
+
+/// How often the direct listener checks whether a recovery unlock expired
+const UNLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Advertise this runtime via mDNS and, if enabled, accept direct LAN connections.
+/// Runs until shutdown.
+pub async fn run_lan_services(unit_manager: Arc<DeploymentManager>) -> Result<()> {
+    if let Err(e) = recovery::end_expired_unlock() {
+        warn!("Failed to end an expired recovery unlock: {:?}", e);
+    }
+    let config = Config::load()?;
+    if !config.lan_discovery && !config.lan_direct {
+        return Ok(());
+    }
+
+    let mdns = if config.lan_discovery {
+        match advertise(&config) {
+            Ok(mdns) => Some(mdns),
+            Err(e) => {
+                warn!("LAN discovery disabled: {:?}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    if config.lan_direct {
+        serve_direct(config.lan_direct_port, unit_manager).await?;
+    }
+    // also after an expired recovery unlock ended direct connections
+    SHUTDOWN.cancelled().await;
+
+    if let Some(mdns) = mdns {
+        let _ = mdns.shutdown();
+    }
+    Ok(())
+}
+
+fn advertise(config: &Config) -> Result<ServiceDaemon> {
+    let mdns = ServiceDaemon::new().context("Failed to start mDNS daemon")?;
+
+    let hostname = sysinfo::System::host_name().unwrap_or_else(|| "m87-device".to_string());
+    let short_id = short_device_id(&config.device_id);
+    let direct = if config.lan_direct { "1" } else { "0" };
+    let properties = [
+        ("device_id", config.device_id.as_str()),
+        ("short_id", short_id.as_str()),
+        ("name", hostname.as_str()),
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("direct", direct),
+    ];
+
+    let info = ServiceInfo::new(
+        SERVICE_TYPE,
+        &format!("m87-{}", short_id),
+        &format!("{}.local.", hostname),
+        "",
+        config.lan_direct_port,
+        &properties[..],
+    )
+    .context("Failed to build mDNS service record")?
+    .enable_addr_auto();
+
+    mdns.register(info)
+        .context("Failed to register mDNS service")?;
+    info!("Advertising device on local network as {}", SERVICE_TYPE);
+    Ok(mdns)
+}
+
+/// Certificate and key of the direct listener, created once and kept next to
+/// the credentials so clients can pin the certificate.
+fn direct_identity() -> Result<(CertificateDer<'static>, PrivateKeyDer<'static>)> {
+    let dir = Config::m87_config_dir()?;
+    let (cert_path, key_path) = (dir.join("lan_direct.crt"), dir.join("lan_direct.key"));
+    if let (Ok(cert), Ok(key)) = (std::fs::read(&cert_path), std::fs::read(&key_path)) {
+        return Ok((
+            CertificateDer::from(cert),
+            PrivateKeyDer::from(PrivatePkcs8KeyDer::from(key)),
+        ));
+    }
+
+    let ck = rcgen::generate_simple_self_signed(vec![DIRECT_SERVER_NAME.to_string()])
+        .map_err(|e| anyhow!("rcgen: {e}"))?;
+    let (cert, key) = (ck.cert.der().to_vec(), ck.signing_key.serialize_der());
+    write_private(&key_path, &key)?;
+    write_private(&cert_path, &cert)?;
+    info!("Created the certificate of direct LAN connections");
+    Ok((
+        CertificateDer::from(cert),
+        PrivateKeyDer::from(PrivatePkcs8KeyDer::from(key)),
+    ))
+}
+
+/// Fingerprint clients pin for direct connections, see [`cert_fingerprint`].
+pub fn lan_cert_fingerprint() -> Result<String> {
+    Ok(cert_fingerprint(&direct_identity()?.0))
+}
+
+/// Direct listener on `port` of all addresses: one `[::]` socket with
+/// IPV6_V6ONLY off, so IPv6 and IPv4 peers (as mapped addresses) both reach
+/// it, or `0.0.0.0` on hosts without IPv6.
+fn direct_endpoint(config: ServerConfig, port: u16) -> std::io::Result<Endpoint> {
+    let socket = match dual_stack_socket(port) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!(
+                "IPv6 unavailable ({}), direct LAN connections over IPv4 only",
+                e
+            );
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+            socket
+        }
+    };
+    socket.set_nonblocking(true)?;
+    Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(config),
+        UdpSocket::from(socket),
+        Arc::new(quinn::TokioRuntime),
+    )
+}
+
+fn dual_stack_socket(port: u16) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    Ok(socket)
+}
+
+fn direct_server_config(
+    cert: CertificateDer<'static>,
+    key: PrivateKeyDer<'static>,
+) -> Result<ServerConfig> {
+    let mut tls = RustlsServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .context("Failed to build TLS config")?;
+    tls.alpn_protocols = vec![b"m87-quic".to_vec()];
+
+    let crypto = QuicServerConfig::try_from(tls).context("failed converting rustls→quic config")?;
+    let mut cfg = ServerConfig::with_crypto(Arc::new(crypto));
+
+    let mut transport = TransportConfig::default();
+    transport.keep_alive_interval(Some(Duration::from_secs(5)));
+    cfg.transport_config(Arc::new(transport));
+    Ok(cfg)
+}
+
+async fn serve_direct(port: u16, unit_manager: Arc<DeploymentManager>) -> Result<()> {
+    let (cert, key) = direct_identity()?;
+    let fingerprint: Arc<str> = cert_fingerprint(&cert).into();
+    let endpoint = direct_endpoint(direct_server_config(cert, key)?, port)
+        .with_context(|| format!("Failed to bind direct listener on udp/{}", port))?;
+    info!("Accepting direct LAN connections on udp/{}", port);
+    let mut unlock_check = tokio::time::interval(UNLOCK_CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = SHUTDOWN.cancelled() => break,
+            _ = unlock_check.tick() => match recovery::end_expired_unlock() {
+                Ok(true) => {
+                    info!("Recovery unlock expired; no longer accepting direct LAN connections");
+                    break;
+                }
+                Ok(false) => {}
+                Err(e) => warn!("Failed to end an expired recovery unlock: {:?}", e),
+            },
+            incoming = endpoint.accept() => {
+                let Some(incoming) = incoming else { break };
+                let unit_manager = unit_manager.clone();
+                let fingerprint = fingerprint.clone();
+                tokio::spawn(async move {
+                    let conn = match incoming.await {
+                        Ok(c) => c,
+                        Err(e) => {
+                            debug!("direct: handshake failed: {:?}", e);
+                            return;
+                        }
+                    };
+                    let remote = conn.remote_address();
+                    if let Err(e) = authenticate(&conn, &fingerprint).await {
+                        warn!("direct: rejected connection from {}: {}", remote, e);
+                        conn.close(1u32.into(), b"unauthorized");
+                        return;
+                    }
+                    info!("direct: accepted connection from {}", remote);
+                    if let Err(e) = serve_device_streams(conn, unit_manager, true).await {
+                        warn!("direct: connection from {} failed: {:?}", remote, e);
+                    }
+                });
+            }
+        }
+    }
+
+    endpoint.close(0u32.into(), b"shutdown");
+    Ok(())
+}
+
+/// Challenge the client on its first bidirectional stream: it has to answer a
+/// random nonce with [`auth_proof`] keyed with the device key, or with a local
+/// key from a recovery unlock. Keys never cross the network.
+async fn authenticate(conn: &quinn::Connection, fingerprint: &str) -> Result<()> {
+    let (mut send, mut recv) = timeout(AUTH_TIMEOUT, conn.accept_bi())
+        .await
+        .map_err(|_| anyhow!("auth timeout"))??;
+
+    let mut version = [0u8; 1];
+    timeout(AUTH_TIMEOUT, recv.read_exact(&mut version))
+        .await
+        .map_err(|_| anyhow!("auth timeout"))??;
+    if version[0] != AUTH_VERSION {
+        return Err(anyhow!("unsupported auth version {}", version[0]));
+    }
+
+    let mut nonce = [0u8; AUTH_NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    send.write_all(&nonce).await?;
+
+    let mut proof = [0u8; AUTH_NONCE_LEN];
+    timeout(AUTH_TIMEOUT, recv.read_exact(&mut proof))
+        .await
+        .map_err(|_| anyhow!("auth timeout"))??;
+
+    let device_key_hash = key_hash(&AuthManager::get_device_token()?);
+    let accepted = if constant_time_eq(&proof, &auth_proof(&device_key_hash, &nonce, fingerprint)) {
+        true
+    } else if let Some(local_key_hash) = recovery::local_key_hash()
+        && constant_time_eq(&proof, &auth_proof(&local_key_hash, &nonce, fingerprint))
+    {
+        // break-glass key from `m87 local unlock`
+        warn!("direct: accepted a recovery key");
+        true
+    } else {
+        false
+    };
+    if !accepted {
+        return Err(anyhow!("invalid device key"));
+    }
+    send.write_all(&[1]).await?;
+    send.finish()?;
+    Ok(())
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::*;
+
+    #[test]
+    fn test_direct_socket_takes_ipv4_and_ipv6() {
+        let Ok(socket) = dual_stack_socket(0) else {
+            return; // no IPv6 on this host
+        };
+        let socket = UdpSocket::from(socket);
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        let mut buf = [0u8; 8];
+        for peer in [
+            IpAddr::from(Ipv4Addr::LOCALHOST),
+            IpAddr::from(Ipv6Addr::LOCALHOST),
+        ] {
+            let Ok(sender) = UdpSocket::bind((peer, 0)) else {
+                continue; // loopback of this family not configured
+            };
+            sender.send_to(b"hello", (peer, port)).unwrap();
+            let (n, from) = socket.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"hello");
+            assert_eq!(from.ip().to_canonical(), peer);
+        }
+    }
+}
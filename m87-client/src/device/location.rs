@@ -0,0 +1,190 @@
+//! Device location for mobile robots, read from gpsd or a configured command.
+//!
+//! The location is reported with the heartbeat and included in system metrics.
+//! Reads are cached for a few seconds so the metrics stream does not hammer
+//! the source.
+
+use anyhow::{Context, Result, anyhow, bail};
+use m87_shared::metrics::Location;
+use serde_json::Value;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::config::Config;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+static CACHE: Mutex<Option<(Instant, Option<Location>)>> = Mutex::const_new(None);
+
+/// The current location, or `None` without a configured source or fix.
+pub async fn current() -> Option<Location> {
+    let mut cache = CACHE.lock().await;
+    if let Some((at, location)) = cache.as_ref()
+        && at.elapsed() < REFRESH_INTERVAL
+    {
+        return location.clone();
+    }
+
+    let location = match read().await {
+        Ok(location) => location,
+        Err(e) => {
+            debug!("Failed to read location: {:#}", e);
+            None
+        }
+    };
+    *cache = Some((Instant::now(), location.clone()));
+    location
+}
+
+async fn read() -> Result<Option<Location>> {
+    let config = Config::load()?;
+    let location = if let Some(addr) = &config.location_gpsd {
+        tokio::time::timeout(READ_TIMEOUT, read_gpsd(addr))
+            .await
+            .map_err(|_| anyhow!("no fix from gpsd at {} within {:?}", addr, READ_TIMEOUT))??
+    } else if let Some(cmd) = &config.location_command {
+        read_command(cmd).await?
+    } else {
+        return Ok(None);
+    };
+    Ok(Some(location).filter(|l| l.is_valid()))
+}
+
+/// Watch gpsd until it reports a TPV with at least a 2D fix.
+async fn read_gpsd(addr: &str) -> Result<Location> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("Failed to connect to gpsd at {}", addr))?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(b"?WATCH={\"enable\":true,\"json\":true};\n")
+        .await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let Ok(msg) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        if msg["class"] == "TPV"
+            && let Some(location) = parse_tpv(&msg)
+        {
+            return Ok(location);
+        }
+    }
+    bail!("gpsd closed the connection without a fix")
+}
+
+async fn read_command(cmd: &str) -> Result<Location> {
+    let output = tokio::time::timeout(
+        READ_TIMEOUT,
+        tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| anyhow!("location command timed out"))?
+    .context("Failed to run location command")?;
+    if !output.status.success() {
+        bail!("location command exited with {}", output.status);
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_location_output(&stdout)
+        .ok_or_else(|| anyhow!("unrecognized location output: {}", stdout.trim()))
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// gpsd TPV report; mode 2 and 3 are 2D and 3D fixes.
+fn parse_tpv(msg: &Value) -> Option<Location> {
+    if msg["mode"].as_u64().unwrap_or(0) < 2 {
+        return None;
+    }
+    let accuracy = match (msg["epx"].as_f64(), msg["epy"].as_f64()) {
+        (Some(x), Some(y)) => Some(x.max(y)),
+        _ => msg["eph"].as_f64(),
+    };
+    Some(Location {
+        latitude: msg["lat"].as_f64()?,
+        longitude: msg["lon"].as_f64()?,
+        altitude_m: msg["altHAE"].as_f64().or_else(|| msg["alt"].as_f64()),
+        accuracy_m: accuracy,
+        speed_mps: msg["speed"].as_f64(),
+        heading_deg: msg["track"].as_f64(),
+        timestamp: now_millis(),
+    })
+}
+
+/// Accepts `lat,lon[,alt]`, a JSON object with `lat`/`latitude` and
+/// `lon`/`longitude` keys, or a gpsd TPV line.
+fn parse_location_output(output: &str) -> Option<Location> {
+    let output = output.trim();
+    if let Ok(msg) = serde_json::from_str::<Value>(output) {
+        if msg["class"] == "TPV" {
+            return parse_tpv(&msg);
+        }
+        let field = |names: &[&str]| names.iter().find_map(|n| msg[*n].as_f64());
+        return Some(Location {
+            latitude: field(&["lat", "latitude"])?,
+            longitude: field(&["lon", "lng", "longitude"])?,
+            altitude_m: field(&["alt", "altitude"]),
+            accuracy_m: field(&["accuracy"]),
+            speed_mps: field(&["speed"]),
+            heading_deg: field(&["heading", "track"]),
+            timestamp: now_millis(),
+        });
+    }
+
+    let parts: Vec<f64> = output
+        .split(',')
+        .map(|p| p.trim().parse().ok())
+        .collect::<Option<_>>()?;
+    if !(2..=3).contains(&parts.len()) {
+        return None;
+    }
+    Some(Location {
+        latitude: parts[0],
+        longitude: parts[1],
+        altitude_m: parts.get(2).copied(),
+        accuracy_m: None,
+        speed_mps: None,
+        heading_deg: None,
+        timestamp: now_millis(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_location_output() {
+        let csv = parse_location_output("52.52, 13.405,34\n").unwrap();
+        assert_eq!((csv.latitude, csv.longitude), (52.52, 13.405));
+        assert_eq!(csv.altitude_m, Some(34.0));
+
+        let json = parse_location_output(r#"{"latitude": 48.1, "lng": 11.5}"#).unwrap();
+        assert_eq!((json.latitude, json.longitude), (48.1, 11.5));
+
+        let tpv = parse_location_output(
+            r#"{"class":"TPV","mode":3,"lat":1.5,"lon":2.5,"altHAE":10.0,"eph":4.2,"speed":0.3,"track":90.0}"#,
+        )
+        .unwrap();
+        assert_eq!(tpv.accuracy_m, Some(4.2));
+        assert_eq!(tpv.heading_deg, Some(90.0));
+
+        assert!(parse_location_output(r#"{"class":"TPV","mode":1}"#).is_none());
+        assert!(parse_location_output("no fix").is_none());
+        assert!(parse_location_output("1,2,3,4").is_none());
+    }
+}
@@ -1,8 +1,13 @@
 use anyhow::{Context, Result, anyhow};
 use m87_shared::deploy_spec::{CommandSpec, LogSpec, ObserveHooks};
+use m87_shared::log_format::{LogParser, LogRecord};
 use std::{
     collections::{BTreeMap, HashMap},
     path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
     time::Duration,
 };
 use tokio::{
@@ -12,12 +17,14 @@ use tokio::{
 };
 use tokio_util::sync::CancellationToken;
 
+use crate::device::log_metrics;
 use crate::util::command::build_command;
-use crate::util::format::format_log;
+use crate::util::format::{format_log, format_record};
 
 /// On-demand logs only:
 /// - Snapshot: run once, capture bounded output (for `m87 <device> logs` and incident evidence)
-/// - Follow: stream while a client is connected (`m87 <device> logs -f`)
+/// - Follow: stream while a client is connected (`m87 <device> logs -f`), or
+///   count lines into log metrics while the metrics stream is open
 ///
 /// No always-on processes.
 #[derive(Clone)]
@@ -41,9 +48,11 @@ enum LogCmd {
         spec: LogSpec,
         env: BTreeMap<String, String>,
         workdir: PathBuf,
+        show: bool,
     },
     FollowStop {
         run_id: String,
+        show: bool,
     },
     StopAll,
 }
@@ -51,6 +60,53 @@ enum LogCmd {
 struct FollowStream {
     cancel: CancellationToken,
     followers: u64,
+    shown: Arc<AtomicU64>,
+}
+
+/// What is done with each line of a followed run.
+struct LineSink {
+    unit: String,
+    parser: Option<LogParser>,
+    /// The run declares log metrics
+    counted: bool,
+    /// Followers that show the lines, as opposed to only counting them for
+    /// the metrics stream
+    shown: Arc<AtomicU64>,
+}
+
+impl LineSink {
+    fn handle(&self, line: &str) {
+        let record = match &self.parser {
+            Some(parser) => parser.parse(line),
+            None => LogRecord {
+                message: line.to_string(),
+                ..Default::default()
+            },
+        };
+        if self.counted {
+            log_metrics::observe(&self.unit, &record);
+        }
+        if self.shown.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        if self.parser.is_some() {
+            // the parsed level travels as a field so that the logs stream
+            // can filter on it; the event itself stays at info
+            let level = record.level.map(|l| l.as_str()).unwrap_or("info");
+            tracing::info!(
+                log_level = level,
+                log_source = self.unit.as_str(),
+                "[observe]{}",
+                format_record(&self.unit, &record, true)
+            );
+        } else {
+            tracing::info!(
+                log_source = self.unit.as_str(),
+                "[observe]{}",
+                format_log(&self.unit, line, true)
+            );
+        }
+    }
 }
 
 impl LogManager {
@@ -85,11 +141,15 @@ impl LogManager {
                         spec,
                         env,
                         workdir,
+                        show,
                     }) => {
                         // Check if we already have a follow stream for this unit
                         if let Some(stream) = follows.get_mut(&run_id) {
                             // Increment follower count
                             stream.followers += 1;
+                            if show {
+                                stream.shown.fetch_add(1, Ordering::Relaxed);
+                            }
                             tracing::debug!(
                                 "Added follower to {} (total: {})",
                                 run_id,
@@ -106,14 +166,31 @@ impl LogManager {
                             continue;
                         };
 
+                        let parser = match spec.format.as_ref().map(LogParser::new) {
+                            Some(Ok(parser)) => Some(parser),
+                            Some(Err(e)) => {
+                                tracing::warn!("log format of {} ignored: {}", run_id, e);
+                                None
+                            }
+                            None => None,
+                        };
+                        let shown = Arc::new(AtomicU64::new(show as u64));
+                        let sink = Arc::new(LineSink {
+                            unit: run_id.clone(),
+                            parser,
+                            counted: log_metrics::register(&run_id, &spec.metrics),
+                            shown: shown.clone(),
+                        });
+
                         let cancel = CancellationToken::new();
-                        match spawn_follow(&run_id, &follow, &env, &workdir, cancel.clone()).await {
+                        match spawn_follow(follow, sink, &env, &workdir, cancel.clone()).await {
                             Ok(()) => {
                                 follows.insert(
                                     run_id.clone(),
                                     FollowStream {
                                         cancel,
                                         followers: 1,
+                                        shown,
                                     },
                                 );
                                 tracing::debug!("Started follow stream for {}", run_id);
@@ -124,9 +201,16 @@ impl LogManager {
                         }
                     }
 
-                    Some(LogCmd::FollowStop { run_id }) => {
+                    Some(LogCmd::FollowStop { run_id, show }) => {
                         if let Some(stream) = follows.get_mut(&run_id) {
                             stream.followers = stream.followers.saturating_sub(1);
+                            if show {
+                                let _ = stream.shown.fetch_update(
+                                    Ordering::Relaxed,
+                                    Ordering::Relaxed,
+                                    |n| n.checked_sub(1),
+                                );
+                            }
                             tracing::debug!(
                                 "Removed follower from {} (remaining: {})",
                                 run_id,
@@ -202,12 +286,15 @@ impl LogManager {
 
     /// Start streaming logs for a unit. Increments follower count.
     /// Multiple followers can watch the same unit; the stream is shared.
+    /// Lines are only logged while a follower with `show` is left; the others
+    /// only feed the unit's log metrics.
     pub async fn follow_start(
         &self,
         run_id: String,
         spec: &LogSpec,
         env: BTreeMap<String, String>,
         workdir: PathBuf,
+        show: bool,
     ) {
         let _ = self
             .tx
@@ -216,14 +303,15 @@ impl LogManager {
                 spec: spec.clone(),
                 env,
                 workdir,
+                show,
             })
             .await;
     }
 
     /// Stop streaming logs for a unit. Decrements follower count.
     /// Stream is only cancelled when follower count reaches 0.
-    pub async fn follow_stop(&self, run_id: String) {
-        let _ = self.tx.send(LogCmd::FollowStop { run_id }).await;
+    pub async fn follow_stop(&self, run_id: String, show: bool) {
+        let _ = self.tx.send(LogCmd::FollowStop { run_id, show }).await;
     }
 
     pub async fn stop_all(&self) {
@@ -232,8 +320,8 @@ impl LogManager {
 }
 
 async fn spawn_follow(
-    run_id: &str,
     spec: &CommandSpec,
+    sink: Arc<LineSink>,
     env: &BTreeMap<String, String>,
     workdir: &Path,
     cancel: CancellationToken,
@@ -254,7 +342,7 @@ async fn spawn_follow(
 
     async fn follow_lines<R: tokio::io::AsyncRead + Unpin + Send + 'static>(
         reader: R,
-        unit: String,
+        sink: Arc<LineSink>,
         cancel: CancellationToken,
     ) {
         let mut lines = BufReader::new(reader).lines();
@@ -266,7 +354,7 @@ async fn spawn_follow(
                 }
                 res = lines.next_line() => {
                     match res {
-                        Ok(Some(line)) => tracing::info!("[observe]{}", format_log(&unit, &line, true)),
+                        Ok(Some(line)) => sink.handle(&line),
                         Ok(None) => break, // EOF
                         Err(_) => break,   // I/O error; best-effort
                     }
@@ -275,11 +363,8 @@ async fn spawn_follow(
         }
     }
 
-    let unit = run_id.to_string();
-    tokio::spawn(follow_lines(stdout, unit, cancel.clone()));
-
-    let unit = run_id.to_string();
-    tokio::spawn(follow_lines(stderr, unit, cancel.clone()));
+    tokio::spawn(follow_lines(stdout, sink.clone(), cancel.clone()));
+    tokio::spawn(follow_lines(stderr, sink, cancel.clone()));
 
     // Ensure the process is terminated when cancellation is requested.
     tokio::spawn({
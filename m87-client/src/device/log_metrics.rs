@@ -0,0 +1,58 @@
+//! Values of the log metrics declared by runs (`observe.logs.metrics`), fed by
+//! the followed log lines and read by the metrics stream. Values are kept for
+//! as long as the runtime runs and the run declares the same metrics.
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use m87_shared::log_format::LogRecord;
+use m87_shared::log_metrics::{LogMetricSample, LogMetricSpec, LogMetrics};
+use once_cell::sync::Lazy;
+
+static METRICS: Lazy<Mutex<BTreeMap<String, LogMetrics>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+fn metrics() -> MutexGuard<'static, BTreeMap<String, LogMetrics>> {
+    METRICS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Whether lines of `run_id` are counted, after (re)declaring its metrics.
+/// Unchanged declarations keep their values.
+pub fn register(run_id: &str, specs: &[LogMetricSpec]) -> bool {
+    let mut metrics = metrics();
+    if specs.is_empty() {
+        metrics.remove(run_id);
+        return false;
+    }
+    if let Some(current) = metrics.get(run_id)
+        && current.specs().eq(specs.iter())
+    {
+        return true;
+    }
+    match LogMetrics::new(specs) {
+        Ok(new) => {
+            metrics.insert(run_id.to_string(), new);
+            true
+        }
+        Err(e) => {
+            tracing::warn!("log metrics of {} ignored: {}", run_id, e);
+            metrics.remove(run_id);
+            false
+        }
+    }
+}
+
+pub fn observe(run_id: &str, record: &LogRecord) {
+    if let Some(metrics) = metrics().get_mut(run_id) {
+        metrics.observe(record);
+    }
+}
+
+pub fn samples() -> Vec<LogMetricSample> {
+    metrics()
+        .iter()
+        .flat_map(|(run_id, metrics)| metrics.samples(run_id))
+        .collect()
+}
@@ -1,19 +1,81 @@
 #[cfg(feature = "runtime")]
+pub mod artifacts;
+#[cfg(feature = "runtime")]
+pub mod bandwidth;
+#[cfg(feature = "runtime")]
+pub mod battery;
+#[cfg(feature = "runtime")]
+pub mod container;
+#[cfg(feature = "runtime")]
+pub mod core_dumps;
+#[cfg(feature = "runtime")]
 pub mod deployment_manager;
 #[cfg(feature = "runtime")]
+pub mod drift;
+#[cfg(feature = "runtime")]
+pub mod egress;
+#[cfg(feature = "runtime")]
+pub mod fact_collectors;
+#[cfg(feature = "runtime")]
+pub mod firewall_manager;
+#[cfg(feature = "runtime")]
+pub mod flows;
+#[cfg(feature = "runtime")]
+pub mod gc;
+#[cfg(feature = "runtime")]
+pub mod health;
+#[cfg(feature = "runtime")]
+pub mod immutable;
+#[cfg(feature = "runtime")]
+pub mod inventory;
+#[cfg(feature = "runtime")]
+pub mod location;
+#[cfg(feature = "runtime")]
 pub mod log_manager;
 #[cfg(feature = "runtime")]
+pub mod log_metrics;
+#[cfg(feature = "runtime")]
+pub mod package_manager;
+#[cfg(feature = "runtime")]
+pub mod patching;
+#[cfg(feature = "runtime")]
+pub mod probes;
+#[cfg(feature = "runtime")]
+pub mod recovery;
+#[cfg(feature = "runtime")]
+pub mod redaction;
+#[cfg(feature = "runtime")]
+pub mod step_executor;
+#[cfg(feature = "runtime")]
 pub mod system_metrics;
+#[cfg(feature = "runtime")]
+pub mod time_sync;
 
+pub mod debug;
 pub mod docker;
+pub mod facts;
+pub mod firewall;
 pub mod forward;
 pub mod fs;
+pub mod packages;
+pub mod plan;
+pub mod profile;
+pub mod remote_test;
+pub mod sessions;
+pub mod socks;
+pub mod support;
+pub mod time;
 
 #[cfg(feature = "runtime")]
 pub mod control_tunnel;
+#[cfg(feature = "runtime")]
+pub mod lan;
 
+#[cfg(unix)]
+pub mod gui;
 #[cfg(unix)] // won't compile on Windows because no PTY
 pub mod serial;
 pub mod ssh;
+pub mod vnc;
 
 pub mod deploy;
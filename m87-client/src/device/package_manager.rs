@@ -0,0 +1,376 @@
+//! Package installs, removals and upgrades requested with `m87 <device> pkg`.
+//!
+//! apt-get, dnf/yum and apk run non-interactively, through `sudo -n` when the
+//! runtime is not root. One operation runs at a time, and apt-get and apk wait
+//! for locks held by other processes (e.g. unattended-upgrades) instead of
+//! failing. Operations an operator started are queued for the next heartbeat,
+//! which records them in the device's audit log; patch policy runs are
+//! reported on their own (see [`crate::device::patching`]).
+
+use std::collections::VecDeque;
+use std::process::Stdio;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use m87_shared::inventory::{PackageAction, PackageOperationReport};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Command;
+use tokio::sync::{Mutex, mpsc};
+
+use crate::util::command::binary_exists;
+use crate::util::unix::is_root;
+
+/// Seconds apt-get and apk wait for a lock held by another process
+const LOCK_WAIT_SECS: u32 = 300;
+/// Lines of output kept in the report
+const LOG_TAIL_LINES: usize = 40;
+/// Reports kept while the server is unreachable
+const MAX_PENDING: usize = 50;
+
+static RUNNING: Mutex<()> = Mutex::const_new(());
+static PENDING: std::sync::Mutex<VecDeque<PackageOperationReport>> =
+    std::sync::Mutex::new(VecDeque::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Apt,
+    Dnf,
+    Yum,
+    Apk,
+}
+
+impl Tool {
+    fn detect() -> Option<Self> {
+        [Tool::Apt, Tool::Dnf, Tool::Yum, Tool::Apk]
+            .into_iter()
+            .find(|tool| binary_exists(tool.program()))
+    }
+
+    fn program(self) -> &'static str {
+        match self {
+            Tool::Apt => "apt-get",
+            Tool::Dnf => "dnf",
+            Tool::Yum => "yum",
+            Tool::Apk => "apk",
+        }
+    }
+
+    /// Commands run in order, stopping at the first failure. Dry runs leave
+    /// the package indexes as they are.
+    fn commands(
+        self,
+        action: PackageAction,
+        packages: &[String],
+        dry_run: bool,
+    ) -> Vec<Vec<String>> {
+        let program = self.program().to_string();
+        let mut commands = Vec::new();
+        let mut args = match self {
+            Tool::Apt => {
+                if action != PackageAction::Remove && !dry_run {
+                    commands.push(vec![program.clone(), "update".to_string()]);
+                }
+                let verb: &[&str] = match (action, packages.is_empty()) {
+                    (PackageAction::Install, _) => &["install"],
+                    (PackageAction::Remove, _) => &["remove"],
+                    (PackageAction::Upgrade, true) => &["upgrade"],
+                    (PackageAction::Upgrade, false) => &["install", "--only-upgrade"],
+                };
+                let mut args: Vec<String> = verb.iter().map(|s| s.to_string()).collect();
+                args.push("-y".to_string());
+                // keep locally modified config files instead of prompting and
+                // wait for dpkg locks
+                for option in [
+                    "Dpkg::Options::=--force-confdef".to_string(),
+                    "Dpkg::Options::=--force-confold".to_string(),
+                    format!("DPkg::Lock::Timeout={}", LOCK_WAIT_SECS),
+                ] {
+                    args.extend(["-o".to_string(), option]);
+                }
+                if dry_run {
+                    args.push("--simulate".to_string());
+                }
+                args
+            }
+            Tool::Dnf | Tool::Yum => {
+                let verb = match action {
+                    PackageAction::Install => "install",
+                    PackageAction::Remove => "remove",
+                    PackageAction::Upgrade => "upgrade",
+                };
+                let mut args = vec![verb.to_string(), "-y".to_string()];
+                // resolve and test the transaction without applying it
+                if dry_run {
+                    args.push("--setopt=tsflags=test".to_string());
+                }
+                args
+            }
+            Tool::Apk => {
+                let verb = match action {
+                    PackageAction::Install => "add",
+                    PackageAction::Remove => "del",
+                    PackageAction::Upgrade => "upgrade",
+                };
+                let mut args = vec![verb.to_string()];
+                if action != PackageAction::Remove && !dry_run {
+                    args.push("--update-cache".to_string());
+                }
+                if dry_run {
+                    args.push("--simulate".to_string());
+                }
+                args.extend(["--wait".to_string(), LOCK_WAIT_SECS.to_string()]);
+                args
+            }
+        };
+        args.extend(packages.iter().cloned());
+        args.insert(0, program);
+        commands.push(args);
+        commands
+    }
+}
+
+/// Package names are passed as arguments, never through a shell; a leading
+/// `-` would still be read as an option.
+fn validate(action: PackageAction, packages: &[String]) -> Result<()> {
+    if packages.is_empty() && action != PackageAction::Upgrade {
+        bail!("no packages given");
+    }
+    for package in packages {
+        let valid = !package.is_empty()
+            && !package.starts_with('-')
+            && !package.chars().any(|c| c.is_whitespace() || c.is_control());
+        if !valid {
+            bail!("invalid package name '{}'", package);
+        }
+    }
+    Ok(())
+}
+
+/// Output lines of the running operation: sent to the operator and the last
+/// [`LOG_TAIL_LINES`] kept for the report.
+struct Log {
+    output: mpsc::UnboundedSender<String>,
+    tail: VecDeque<String>,
+}
+
+impl Log {
+    fn line(&mut self, line: String) {
+        if self.tail.len() == LOG_TAIL_LINES {
+            self.tail.pop_front();
+        }
+        self.tail.push_back(line.clone());
+        let _ = self.output.send(line);
+    }
+}
+
+/// Run `action` with the device's package manager, sending its output to
+/// `output` line by line. `cancelled` resolving (the operator killed the
+/// session) stops the package manager.
+pub async fn run(
+    action: PackageAction,
+    packages: Vec<String>,
+    dry_run: bool,
+    user: String,
+    output: mpsc::UnboundedSender<String>,
+    cancelled: impl Future<Output = ()>,
+) -> PackageOperationReport {
+    let started_at = now_ms();
+    let tool = Tool::detect();
+    let mut log = Log {
+        output,
+        tail: VecDeque::new(),
+    };
+
+    let result = match (validate(action, &packages), tool) {
+        (Err(e), _) => Err(e),
+        (Ok(()), None) => Err(anyhow!(
+            "no supported package manager found (apt-get, dnf, yum or apk)"
+        )),
+        (Ok(()), Some(tool)) => {
+            execute(tool, action, &packages, dry_run, &mut log, cancelled).await
+        }
+    };
+    let (exit_code, error) = match result {
+        Ok(0) => (Some(0), None),
+        Ok(code) => (Some(code), Some(format!("exited with code {}", code))),
+        Err(e) => (None, Some(format!("{:#}", e))),
+    };
+
+    let report = PackageOperationReport {
+        action,
+        packages,
+        dry_run,
+        tool: tool.map(|t| t.program().to_string()),
+        user,
+        started_at,
+        report_time: now_ms(),
+        exit_code,
+        success: error.is_none(),
+        error,
+        log_tail: Vec::from(log.tail).join("\n"),
+    };
+    if !dry_run {
+        // report the changed packages with the next heartbeat
+        crate::device::inventory::invalidate().await;
+    }
+    report
+}
+
+/// Exit code of the first failing command, or 0.
+async fn execute(
+    tool: Tool,
+    action: PackageAction,
+    packages: &[String],
+    dry_run: bool,
+    log: &mut Log,
+    cancelled: impl Future<Output = ()>,
+) -> Result<i32> {
+    tokio::pin!(cancelled);
+    let _running = match RUNNING.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => {
+            log.line("waiting for another package operation to finish".to_string());
+            tokio::select! {
+                guard = RUNNING.lock() => guard,
+                _ = &mut cancelled => bail!("cancelled"),
+            }
+        }
+    };
+
+    for args in tool.commands(action, packages, dry_run) {
+        log.line(format!("$ {}", args.join(" ")));
+        let mut cmd = if is_root() {
+            Command::new(&args[0])
+        } else {
+            let mut cmd = Command::new("sudo");
+            cmd.arg("-n").arg(&args[0]);
+            cmd
+        };
+        cmd.args(&args[1..])
+            .env("DEBIAN_FRONTEND", "noninteractive")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = cmd
+            .spawn()
+            .with_context(|| format!("failed to start {}", args[0]))?;
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_lines(stdout, tx.clone()));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_lines(stderr, tx));
+        }
+        loop {
+            tokio::select! {
+                line = rx.recv() => match line {
+                    Some(line) => log.line(line),
+                    None => break,
+                },
+                _ = &mut cancelled => {
+                    let _ = child.kill().await;
+                    bail!("cancelled while running {}", args[0]);
+                }
+            }
+        }
+
+        let status = child.wait().await?;
+        let code = status.code().unwrap_or(-1);
+        if code != 0 {
+            return Ok(code);
+        }
+    }
+    Ok(0)
+}
+
+async fn forward_lines<R: AsyncRead + Unpin>(reader: R, tx: mpsc::UnboundedSender<String>) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf);
+                // progress bars redraw with \r; keep the final state
+                let line = line.trim_end().rsplit('\r').next().unwrap_or_default();
+                if tx.send(line.to_string()).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Send `report` with the next heartbeat.
+pub fn queue(report: PackageOperationReport) {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() == MAX_PENDING {
+        pending.pop_front();
+    }
+    pending.push_back(report);
+}
+
+/// Reports not yet carried by a heartbeat, oldest first.
+pub fn pending_reports() -> Vec<PackageOperationReport> {
+    PENDING.lock().unwrap().iter().cloned().collect()
+}
+
+/// Drop the first `count` reports once a heartbeat carried them.
+pub fn clear_reported(count: usize) {
+    let mut pending = PENDING.lock().unwrap();
+    let count = count.min(pending.len());
+    pending.drain(..count);
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_commands() {
+        let pkgs = vec!["curl".to_string(), "jq".to_string()];
+
+        let apt = Tool::Apt.commands(PackageAction::Install, &pkgs, false);
+        assert_eq!(apt.len(), 2);
+        assert_eq!(apt[0], ["apt-get", "update"]);
+        assert_eq!(apt[1][..3], ["apt-get", "install", "-y"]);
+        assert!(apt[1].ends_with(&pkgs));
+
+        let apt = Tool::Apt.commands(PackageAction::Upgrade, &[], true);
+        assert_eq!(apt.len(), 1);
+        assert_eq!(apt[0][1], "upgrade");
+        assert!(apt[0].contains(&"--simulate".to_string()));
+
+        let apt = Tool::Apt.commands(PackageAction::Upgrade, &pkgs[..1], false);
+        assert_eq!(apt[1][1..3], ["install", "--only-upgrade"]);
+
+        let dnf = Tool::Dnf.commands(PackageAction::Remove, &pkgs, true);
+        assert_eq!(
+            dnf,
+            [["dnf", "remove", "-y", "--setopt=tsflags=test", "curl", "jq"]]
+        );
+
+        let apk = Tool::Apk.commands(PackageAction::Upgrade, &[], false);
+        assert_eq!(apk, [["apk", "upgrade", "--update-cache", "--wait", "300"]]);
+    }
+
+    #[test]
+    fn test_validate_packages() {
+        let names = |n: &[&str]| n.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(validate(PackageAction::Upgrade, &[]).is_ok());
+        assert!(validate(PackageAction::Install, &[]).is_err());
+        assert!(validate(PackageAction::Install, &names(&["libssl3=3.0.11-1", "g++"])).is_ok());
+        assert!(validate(PackageAction::Remove, &names(&["--purge"])).is_err());
+        assert!(validate(PackageAction::Install, &names(&["curl jq"])).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use anyhow::{Result, bail};
+use m87_shared::inventory::{PackageAction, PackageOperationReport};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    devices,
+    streams::{
+        quic::open_quic_io,
+        stream_type::{PackageEvent, StreamType},
+    },
+};
+
+/// Run a package operation on `device`, printing the package manager output
+/// as it arrives, and return its report.
+pub async fn run(
+    device: &str,
+    action: PackageAction,
+    packages: Vec<String>,
+    dry_run: bool,
+) -> Result<PackageOperationReport> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let stream_type = StreamType::Packages {
+        token: token.clone(),
+        action,
+        packages,
+        dry_run,
+    };
+    let (_conn, io) = open_quic_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        config.trust_invalid_server_cert,
+    )
+    .await?;
+
+    let mut lines = BufReader::new(io).lines();
+    while let Some(line) = lines.next_line().await? {
+        match serde_json::from_str::<PackageEvent>(&line) {
+            Ok(PackageEvent::Output { line }) => println!("{}", line),
+            Ok(PackageEvent::Done { report }) => return Ok(report),
+            // refusals (missing role, busy device) are plain text
+            Err(_) => bail!("{}", line.trim()),
+        }
+    }
+    bail!("device closed the stream before the package operation finished")
+}
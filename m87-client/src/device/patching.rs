@@ -0,0 +1,252 @@
+//! Patch policies received from the server, applied in their maintenance
+//! windows.
+//!
+//! Policies are checked every minute against the device's local time. Each
+//! runs at most once per window; the time of its last run is kept in
+//! `patching.json` in the data directory, so a restart inside a window does
+//! not patch twice. A reboot the OS asks for is scheduled a few minutes out,
+//! which leaves the next heartbeat time to deliver the report.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{Datelike, Local, Timelike};
+use m87_shared::inventory::PackageAction;
+use m87_shared::patching::{MaintenanceWindow, PatchPolicy, PatchRunReport};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::device::{fact_collectors::get_facts, package_manager};
+use crate::util::command::binary_exists;
+use crate::util::shutdown::SHUTDOWN;
+use crate::util::unix::is_root;
+
+const TICK: Duration = Duration::from_secs(60);
+const REBOOT_DELAY_MINUTES: u32 = 2;
+/// Reports kept while the server is unreachable
+const MAX_PENDING: usize = 50;
+
+static PENDING: std::sync::Mutex<VecDeque<PatchRunReport>> = std::sync::Mutex::new(VecDeque::new());
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Unix millis of the last run per policy id
+    #[serde(default)]
+    last_run: BTreeMap<String, u64>,
+}
+
+fn state_path() -> Result<PathBuf> {
+    Ok(Config::data_dir()?.join("patching.json"))
+}
+
+fn load_state(path: &Path) -> State {
+    std::fs::read(path)
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn save_state(path: &Path, state: &State) -> Result<()> {
+    let data = serde_json::to_vec_pretty(state)?;
+    std::fs::write(path, data).with_context(|| format!("failed to write {}", path.display()))
+}
+
+pub fn spawn() {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = check().await {
+                warn!("Patch policy check failed: {:#}", e);
+            }
+            tokio::select! {
+                _ = sleep(TICK) => {}
+                _ = SHUTDOWN.cancelled() => break,
+            }
+        }
+    });
+}
+
+/// Whether a run at `last_run` already covered the window open at `now`.
+fn ran_in_window(window: &MaintenanceWindow, last_run: Option<u64>, now: u64) -> bool {
+    let length = window.length_minutes() as u64 * 60_000;
+    last_run.is_some_and(|t| now.saturating_sub(t) < length)
+}
+
+async fn check() -> Result<()> {
+    let policies = Config::load()?.patch_policies;
+    let path = state_path()?;
+    let mut state = load_state(&path);
+    let stale = state.last_run.len();
+    state
+        .last_run
+        .retain(|id, _| policies.iter().any(|p| &p.id == id));
+    if state.last_run.len() != stale {
+        save_state(&path, &state)?;
+    }
+
+    let now = Local::now();
+    let now_ms = now.timestamp_millis() as u64;
+    let weekday = now.weekday().num_days_from_monday();
+    let minute = now.hour() * 60 + now.minute();
+    for policy in policies {
+        let window = match MaintenanceWindow::parse(&policy.window) {
+            Ok(window) => window,
+            Err(e) => {
+                warn!("Skipping patch policy {}: {}", policy.name, e);
+                continue;
+            }
+        };
+        let last_run = state.last_run.get(&policy.id).copied();
+        if !window.contains(weekday, minute) || ran_in_window(&window, last_run, now_ms) {
+            continue;
+        }
+        if let Some(condition) = &policy.when {
+            match get_facts(false).await.evaluate_condition(condition) {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    warn!(
+                        "Skipping patch policy {}: invalid condition: {}",
+                        policy.name, e
+                    );
+                    continue;
+                }
+            }
+        }
+
+        // recorded first: an upgrade that crashes the runtime is not retried
+        // in a loop
+        state.last_run.insert(policy.id.clone(), now_ms);
+        save_state(&path, &state)?;
+        let report = apply(&policy).await;
+        let rebooting = report.rebooting;
+        queue(report);
+        if rebooting {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn apply(policy: &PatchPolicy) -> PatchRunReport {
+    info!("Applying patch policy {}", policy.name);
+    // nobody watches the output; it ends up in the report's log tail
+    let (tx, _rx) = mpsc::unbounded_channel();
+    let operation = package_manager::run(
+        PackageAction::Upgrade,
+        policy.packages.clone(),
+        false,
+        format!("policy:{}", policy.name),
+        tx,
+        // an upgrade is never interrupted halfway
+        std::future::pending(),
+    )
+    .await;
+    if let Some(error) = &operation.error {
+        warn!("Patch policy {} failed: {}", policy.name, error);
+    }
+
+    let reboot_required = operation.success && reboot_required().await;
+    let rebooting = reboot_required && policy.reboot && schedule_reboot(&policy.name).await;
+    if reboot_required && !rebooting {
+        info!("Patch policy {} left a reboot pending", policy.name);
+    }
+    PatchRunReport {
+        policy_id: policy.id.clone(),
+        policy_name: policy.name.clone(),
+        operation,
+        reboot_required,
+        rebooting,
+    }
+}
+
+/// Debian and Ubuntu flag pending reboots with a file, RHEL and Fedora
+/// answer through `needs-restarting -r` (exit code 1).
+async fn reboot_required() -> bool {
+    if Path::new("/var/run/reboot-required").exists() {
+        return true;
+    }
+    if !binary_exists("needs-restarting") {
+        return false;
+    }
+    Command::new("needs-restarting")
+        .arg("-r")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.code() == Some(1))
+}
+
+async fn schedule_reboot(policy: &str) -> bool {
+    let message = format!("m87 patch policy {}", policy);
+    let delay = format!("+{}", REBOOT_DELAY_MINUTES);
+    let mut cmd = if is_root() {
+        Command::new("shutdown")
+    } else {
+        let mut cmd = Command::new("sudo");
+        cmd.arg("-n").arg("shutdown");
+        cmd
+    };
+    match cmd.args(["-r", &delay, &message]).output().await {
+        Ok(out) if out.status.success() => {
+            info!(
+                "Rebooting in {} minutes for patch policy {}",
+                REBOOT_DELAY_MINUTES, policy
+            );
+            true
+        }
+        Ok(out) => {
+            warn!(
+                "Failed to schedule reboot: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+            false
+        }
+        Err(e) => {
+            warn!("Failed to schedule reboot: {}", e);
+            false
+        }
+    }
+}
+
+fn queue(report: PatchRunReport) {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() == MAX_PENDING {
+        pending.pop_front();
+    }
+    pending.push_back(report);
+}
+
+/// Runs not yet carried by a heartbeat, oldest first.
+pub fn pending_reports() -> Vec<PatchRunReport> {
+    PENDING.lock().unwrap().iter().cloned().collect()
+}
+
+/// Drop the first `count` runs once a heartbeat carried them.
+pub fn clear_reported(count: usize) {
+    let mut pending = PENDING.lock().unwrap();
+    let count = count.min(pending.len());
+    pending.drain(..count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ran_in_window() {
+        let window = MaintenanceWindow::parse("daily 02:00-04:00").unwrap();
+        let now = 30 * 24 * 3_600_000;
+        assert!(!ran_in_window(&window, None, now));
+        assert!(ran_in_window(&window, Some(now - 60_000), now));
+        // yesterday's run
+        assert!(!ran_in_window(&window, Some(now - 24 * 3_600_000), now));
+    }
+}
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow, bail};
+use m87_shared::deploy_spec::DeploymentRevision;
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    device::deploy::{self, SpecFile, SpecType, load_spec_file},
+    devices, server,
+    streams::{
+        quic::open_quic_io,
+        stream_type::{PlanReply, StreamType},
+    },
+};
+
+/// What `m87 <device> deploy <file>` would change on `device`, computed by
+/// its runtime against the revision it holds. A run spec or compose file is
+/// added to the active deployment, replacing a run of the same id.
+pub async fn plan_file(
+    device: &str,
+    file: &Path,
+    spec_type: SpecType,
+    name: Option<&str>,
+) -> Result<PlanReply> {
+    let candidate = match load_spec_file(file, spec_type, name).await? {
+        SpecFile::Deployment(revision) => *revision,
+        SpecFile::Job(job) => match deploy::get_active_deployment_id(device).await? {
+            Some(id) => {
+                let mut revision = deploy::get_deployment(device, &id).await?;
+                revision.jobs.retain(|j| j.id != job.id);
+                revision.jobs.push(*job);
+                revision
+            }
+            None => DeploymentRevision::new(vec![*job], None),
+        },
+    };
+    plan(device, &candidate).await
+}
+
+/// Ask the runtime of `device` what applying `candidate` would change.
+pub async fn plan(device: &str, candidate: &DeploymentRevision) -> Result<PlanReply> {
+    let request = json!({ "device": device, "revision": candidate.id });
+    server::vcr::call("device_plan", request, query_plan(device, candidate)).await
+}
+
+async fn query_plan(device: &str, candidate: &DeploymentRevision) -> Result<PlanReply> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let stream_type = StreamType::Plan {
+        token: token.clone(),
+    };
+    let (_conn, mut io) = open_quic_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        config.trust_invalid_server_cert,
+    )
+    .await?;
+
+    let mut line = serde_json::to_vec(candidate)?;
+    line.push(b'\n');
+    io.write_all(&line).await?;
+    io.flush().await?;
+
+    let line = BufReader::new(io)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("device closed the stream without a plan"))?;
+    let reply: PlanReply = serde_json::from_str(&line).context("Failed to parse the plan")?;
+    if let Some(error) = &reply.error {
+        bail!("{}", error);
+    }
+    Ok(reply)
+}
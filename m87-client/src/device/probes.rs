@@ -0,0 +1,221 @@
+//! Probes received from the server, run from the device on their intervals.
+//!
+//! Each probe runs in its own task, restarted when its definition changes;
+//! the config is checked for changes every few seconds. Results wait for the
+//! next heartbeat, which drops them once sent.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use m87_shared::probe::{ProbeResult, ProbeSpec, ProbeTarget};
+use tokio::net::TcpStream;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+use tokio::time::{MissedTickBehavior, interval, sleep, timeout};
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::util::command::binary_exists;
+use crate::util::proxy;
+use crate::util::shutdown::SHUTDOWN;
+
+const RELOAD: Duration = Duration::from_secs(15);
+/// Results kept while the server is unreachable
+const MAX_PENDING: usize = 500;
+
+static PENDING: std::sync::Mutex<VecDeque<ProbeResult>> = std::sync::Mutex::new(VecDeque::new());
+
+/// What a probe measured when it reached its target.
+struct Reached {
+    latency: Duration,
+    status: Option<u16>,
+}
+
+pub fn spawn() {
+    tokio::spawn(async move {
+        let mut running: BTreeMap<String, (ProbeSpec, JoinHandle<()>)> = BTreeMap::new();
+        loop {
+            match Config::load() {
+                Ok(config) => reschedule(&mut running, config.probes),
+                Err(e) => warn!("Failed to load probes: {:#}", e),
+            }
+            tokio::select! {
+                _ = sleep(RELOAD) => {}
+                _ = SHUTDOWN.cancelled() => break,
+            }
+        }
+        for (_, (_, task)) in running {
+            task.abort();
+        }
+    });
+}
+
+/// Stop the tasks of removed or changed probes and start the new ones.
+fn reschedule(running: &mut BTreeMap<String, (ProbeSpec, JoinHandle<()>)>, probes: Vec<ProbeSpec>) {
+    running.retain(|name, (spec, task)| {
+        let keep = probes.iter().any(|p| p == spec);
+        if !keep {
+            info!("Stopping probe {}", name);
+            task.abort();
+        }
+        keep
+    });
+    for probe in probes {
+        if running.contains_key(&probe.name) {
+            continue;
+        }
+        if let Err(e) = probe.validate() {
+            warn!("Skipping {}", e);
+            continue;
+        }
+        info!("Starting probe {} ({})", probe.name, probe.target);
+        let task = tokio::spawn(run_probe(probe.clone()));
+        running.insert(probe.name.clone(), (probe, task));
+    }
+}
+
+async fn run_probe(probe: ProbeSpec) {
+    let mut ticks = interval(Duration::from_secs(probe.interval_secs as u64));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let limit = Duration::from_secs(probe.timeout_secs as u64);
+    let mut failures = 0;
+    loop {
+        ticks.tick().await;
+        let checked_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let outcome = match timeout(limit, check(&probe.target, limit)).await {
+            Ok(outcome) => outcome,
+            Err(_) => Err(anyhow::anyhow!("timed out after {}s", probe.timeout_secs)),
+        };
+        let result = match outcome {
+            Ok(reached) => {
+                failures = 0;
+                ProbeResult {
+                    name: probe.name.clone(),
+                    ok: true,
+                    checked_at,
+                    latency_ms: Some(reached.latency.as_millis() as u64),
+                    status: reached.status,
+                    error: None,
+                    consecutive_failures: 0,
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                ProbeResult {
+                    name: probe.name.clone(),
+                    ok: false,
+                    checked_at,
+                    latency_ms: None,
+                    status: None,
+                    error: Some(format!("{:#}", e)),
+                    consecutive_failures: failures,
+                }
+            }
+        };
+        queue(result);
+    }
+}
+
+async fn check(target: &ProbeTarget, limit: Duration) -> Result<Reached> {
+    let started = Instant::now();
+    match target {
+        ProbeTarget::Ping { host } => {
+            if !binary_exists("ping") {
+                bail!("ping is not installed");
+            }
+            let out = Command::new("ping")
+                .args(["-c", "1", "-W", &limit.as_secs().to_string(), host])
+                .output()
+                .await?;
+            if !out.status.success() {
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                match stderr.trim() {
+                    "" => bail!("no reply from {}", host),
+                    reason => bail!("{}", reason),
+                }
+            }
+            let latency = ping_time(&String::from_utf8_lossy(&out.stdout))
+                .unwrap_or_else(|| started.elapsed());
+            Ok(Reached {
+                latency,
+                status: None,
+            })
+        }
+        ProbeTarget::Tcp { host, port } => {
+            TcpStream::connect((host.as_str(), *port)).await?;
+            Ok(Reached {
+                latency: started.elapsed(),
+                status: None,
+            })
+        }
+        ProbeTarget::Http { url, expect_status } => {
+            // through the proxy, as the device's other requests
+            let client = proxy::apply(reqwest::Client::builder())?
+                .timeout(limit)
+                .build()?;
+            let res = client.get(url).send().await?;
+            let latency = started.elapsed();
+            let status = res.status();
+            let ok = match expect_status {
+                Some(code) => status.as_u16() == *code,
+                None => status.is_success(),
+            };
+            if !ok {
+                bail!("unexpected status {}", status);
+            }
+            Ok(Reached {
+                latency,
+                status: Some(status.as_u16()),
+            })
+        }
+    }
+}
+
+/// Round trip from `ping` output, e.g. `... time=12.3 ms`.
+fn ping_time(output: &str) -> Option<Duration> {
+    let (_, rest) = output.split_once("time=")?;
+    let millis: f64 = rest
+        .split(|c: char| c.is_whitespace() || c == 'm')
+        .next()?
+        .parse()
+        .ok()?;
+    Some(Duration::from_micros((millis * 1000.0).round() as u64))
+}
+
+fn queue(result: ProbeResult) {
+    let mut pending = PENDING.lock().unwrap();
+    if pending.len() == MAX_PENDING {
+        pending.pop_front();
+    }
+    pending.push_back(result);
+}
+
+/// Results not yet carried by a heartbeat, oldest first.
+pub fn pending_reports() -> Vec<ProbeResult> {
+    PENDING.lock().unwrap().iter().cloned().collect()
+}
+
+/// Drop the first `count` results once a heartbeat carried them.
+pub fn clear_reported(count: usize) {
+    let mut pending = PENDING.lock().unwrap();
+    let count = count.min(pending.len());
+    pending.drain(..count);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ping_time() {
+        let linux = "64 bytes from 10.0.0.1: icmp_seq=1 ttl=64 time=0.412 ms";
+        assert_eq!(ping_time(linux), Some(Duration::from_micros(412)));
+        let busybox = "64 bytes from 10.0.0.1: seq=0 ttl=64 time=12.000 ms";
+        assert_eq!(ping_time(busybox), Some(Duration::from_millis(12)));
+        assert_eq!(ping_time("1 packets transmitted, 0 received"), None);
+    }
+}
@@ -0,0 +1,388 @@
+//! `m87 <device> profile`: sample a process with `perf record` on the device,
+//! symbolicate the samples there with `perf script` and turn them into a
+//! flamegraph SVG or folded stacks on this machine.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use russh_sftp::client::SftpSession;
+use tokio::io::AsyncReadExt;
+
+use crate::device::fs;
+use crate::tui;
+use crate::util::shutdown::SHUTDOWN;
+
+const SVG_WIDTH: f64 = 1200.0;
+const FRAME_HEIGHT: f64 = 16.0;
+/// Frames narrower than this many pixels are left out of the SVG
+const MIN_FRAME_WIDTH: f64 = 0.1;
+/// Creates the directory the recording goes to and prints its path
+const MKTEMP_COMMAND: &str = "mktemp -d \"${TMPDIR:-/tmp}/m87-profile.XXXXXX\"";
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum ProfileFormat {
+    /// Flamegraph image; hovering a frame in a browser shows its samples
+    #[default]
+    Svg,
+    /// One `frame;frame;frame count` line per stack, for flamegraph.pl, inferno or speedscope
+    Folded,
+}
+
+impl ProfileFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ProfileFormat::Svg => "svg",
+            ProfileFormat::Folded => "folded",
+        }
+    }
+}
+
+pub struct ProfileRun {
+    pub pid: u32,
+    pub duration: Duration,
+    /// Samples per second
+    pub frequency: u32,
+    pub format: ProfileFormat,
+}
+
+/// Profile `run.pid` on the device and write the result to `out`.
+pub async fn profile(device: &str, run: ProfileRun, out: &Path) -> Result<()> {
+    // a fresh private directory, so other users of the device can neither
+    // plant nor read the recording
+    let (code, output) = tui::exec::run_captured(device, MKTEMP_COMMAND.to_string()).await?;
+    let remote_dir = output.trim().to_string();
+    if code != 0 || !is_plain_path(&remote_dir) {
+        bail!(
+            "failed to create a temporary directory on the device: {}",
+            output.trim()
+        );
+    }
+
+    tracing::info!(
+        "Sampling process {} for {}s at {} Hz",
+        run.pid,
+        run.duration.as_secs(),
+        run.frequency
+    );
+    let command = record_command(&remote_dir, &run);
+    let recorded = tui::exec::run_streamed(device, command).await;
+    let script = match recorded {
+        Ok(0) if !SHUTDOWN.is_cancelled() => read_script(device, &remote_dir).await,
+        _ => Ok(String::new()),
+    };
+    // removed whatever happened, perf may leave a partial recording
+    let cleanup = format!("rm -rf -- '{}'", remote_dir);
+    if let Err(e) = tui::exec::run_captured(device, cleanup).await {
+        tracing::warn!("Failed to remove {} on the device: {:#}", remote_dir, e);
+    }
+    let exit_code = recorded?;
+
+    if SHUTDOWN.is_cancelled() {
+        bail!("profiling cancelled");
+    }
+    if exit_code != 0 {
+        bail!("perf exited with code {}", exit_code);
+    }
+
+    let stacks = fold_perf_script(&script?);
+    if stacks.is_empty() {
+        bail!(
+            "no samples were recorded for process {}; is it running and using CPU?",
+            run.pid
+        );
+    }
+    let samples: u64 = stacks.values().sum();
+    let content = match run.format {
+        ProfileFormat::Svg => flamegraph_svg(&stacks, &format!("{} pid {}", device, run.pid)),
+        ProfileFormat::Folded => stacks
+            .iter()
+            .map(|(stack, count)| format!("{} {}\n", stack, count))
+            .collect(),
+    };
+    tokio::fs::write(out, content)
+        .await
+        .with_context(|| format!("failed to write {}", out.display()))?;
+    println!("{} samples written to {}", samples, out.display());
+    Ok(())
+}
+
+/// Whether `path` is absolute and safe to put in single quotes.
+fn is_plain_path(path: &str) -> bool {
+    path.starts_with('/')
+        && path
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '_' | '-'))
+}
+
+async fn read_script(device: &str, dir: &str) -> Result<String> {
+    let sftp = fs::open_sftp_session(device).await?;
+    read_remote(&sftp, &format!("{}/perf.script", dir)).await
+}
+
+async fn read_remote(sftp: &SftpSession, path: &str) -> Result<String> {
+    let mut file = sftp
+        .open(path.to_string())
+        .await
+        .with_context(|| format!("cannot open {} on the device", path))?;
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).await?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// Shell command recording `run.pid` into `dir` and symbolicating the
+/// samples on the device, where the binaries and their symbols are.
+fn record_command(dir: &str, run: &ProfileRun) -> String {
+    format!(
+        "command -v perf >/dev/null || {{ echo 'perf is not installed on the device' >&2; exit 127; }}; \
+         perf record -F {freq} -g -p {pid} -o {dir}/perf.data -- sleep {secs} \
+         && perf script -i {dir}/perf.data > {dir}/perf.script",
+        freq = run.frequency,
+        pid = run.pid,
+        secs = run.duration.as_secs().max(1),
+    )
+}
+
+/// Collapse `perf script` output into `comm;root;...;leaf` stacks with
+/// their sample counts. Frames perf could not symbolicate are named after
+/// their binary, e.g. `[libfoo.so]`.
+fn fold_perf_script(script: &str) -> BTreeMap<String, u64> {
+    let mut stacks = BTreeMap::new();
+    let mut comm: Option<String> = None;
+    let mut frames: Vec<String> = Vec::new();
+
+    let mut flush = |comm: &mut Option<String>, frames: &mut Vec<String>| {
+        if let Some(comm) = comm.take() {
+            let mut stack = comm;
+            for frame in frames.iter().rev() {
+                stack.push(';');
+                stack.push_str(frame);
+            }
+            *stacks.entry(stack).or_insert(0) += 1;
+        }
+        frames.clear();
+    };
+
+    for line in script.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.trim().is_empty() {
+            flush(&mut comm, &mut frames);
+        } else if line.starts_with(char::is_whitespace) {
+            if comm.is_some() {
+                frames.push(frame_name(line.trim()));
+            }
+        } else {
+            flush(&mut comm, &mut frames);
+            comm = Some(sample_comm(line));
+        }
+    }
+    flush(&mut comm, &mut frames);
+    stacks
+}
+
+/// Command name of a sample header line like `my app  1234 5678.9: 10101 cpu-clock:`;
+/// it is everything before the first numeric (pid or pid/tid) field.
+fn sample_comm(line: &str) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let end = words
+        .iter()
+        .position(|w| {
+            w.split('/')
+                .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .unwrap_or(1)
+        .max(1);
+    words[..end.min(words.len())].join(" ").replace(';', ":")
+}
+
+/// Function name of a frame line like `7f1c2 do_work+0x1c (/usr/bin/app)`.
+fn frame_name(line: &str) -> String {
+    let rest = line.split_once(' ').map(|(_, r)| r).unwrap_or(line);
+    let (symbol, dso) = match rest.rfind(" (") {
+        Some(i) => (&rest[..i], rest[i + 2..].trim_end_matches(')')),
+        None => (rest, ""),
+    };
+    let symbol = match symbol.rfind("+0x") {
+        Some(i) => &symbol[..i],
+        None => symbol,
+    };
+    let name = if symbol.is_empty() || symbol == "[unknown]" {
+        match dso.rsplit('/').next() {
+            Some(bin) if !bin.is_empty() && bin != "unknown" => format!("[{}]", bin),
+            _ => "[unknown]".to_string(),
+        }
+    } else {
+        symbol.to_string()
+    };
+    name.replace(';', ":")
+}
+
+#[derive(Default)]
+struct Frame {
+    samples: u64,
+    children: BTreeMap<String, Frame>,
+}
+
+/// Render folded stacks as a flamegraph: callers at the bottom, frame widths
+/// proportional to samples, frames sorted by name.
+fn flamegraph_svg(stacks: &BTreeMap<String, u64>, title: &str) -> String {
+    let mut root = Frame::default();
+    let mut depth = 0;
+    for (stack, count) in stacks {
+        root.samples += count;
+        let mut node = &mut root;
+        for (i, frame) in stack.split(';').enumerate() {
+            node = node.children.entry(frame.to_string()).or_default();
+            node.samples += count;
+            depth = depth.max(i + 1);
+        }
+    }
+
+    let height = (depth as f64 + 1.0) * FRAME_HEIGHT + 40.0;
+    let mut svg = format!(
+        "<?xml version=\"1.0\" standalone=\"no\"?>\n\
+         <svg version=\"1.1\" width=\"{w}\" height=\"{h}\" xmlns=\"http://www.w3.org/2000/svg\" \
+         font-family=\"Verdana, sans-serif\" font-size=\"12\">\n\
+         <rect x=\"0\" y=\"0\" width=\"{w}\" height=\"{h}\" fill=\"#f8f8f8\"/>\n\
+         <text x=\"{cx}\" y=\"20\" text-anchor=\"middle\" font-size=\"16\">{title}</text>\n",
+        w = SVG_WIDTH,
+        h = height,
+        cx = SVG_WIDTH / 2.0,
+        title = xml_escape(title),
+    );
+    let scale = (SVG_WIDTH - 20.0) / root.samples.max(1) as f64;
+    let mut x = 10.0;
+    for (name, frame) in &root.children {
+        render_frame(&mut svg, name, frame, x, 0, height, scale, root.samples);
+        x += frame.samples as f64 * scale;
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_frame(
+    svg: &mut String,
+    name: &str,
+    frame: &Frame,
+    x: f64,
+    depth: usize,
+    height: f64,
+    scale: f64,
+    total: u64,
+) {
+    let width = frame.samples as f64 * scale;
+    if width < MIN_FRAME_WIDTH {
+        return;
+    }
+    let y = height - 10.0 - (depth as f64 + 1.0) * FRAME_HEIGHT;
+    let name_xml = xml_escape(name);
+    svg.push_str(&format!(
+        "<g><title>{} ({} samples, {:.2}%)</title>\
+         <rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{}\" fill=\"{}\" rx=\"2\"/>",
+        name_xml,
+        frame.samples,
+        frame.samples as f64 * 100.0 / total as f64,
+        x,
+        y,
+        width,
+        FRAME_HEIGHT - 1.0,
+        frame_color(name),
+    ));
+    // about 7px per character at this font size
+    let fits = ((width - 6.0) / 7.0) as usize;
+    if fits >= 3 {
+        let label: String = if name.chars().count() <= fits {
+            name.to_string()
+        } else {
+            name.chars().take(fits - 2).chain("..".chars()).collect()
+        };
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>",
+            x + 3.0,
+            y + FRAME_HEIGHT - 4.5,
+            xml_escape(&label)
+        ));
+    }
+    svg.push_str("</g>\n");
+
+    let mut child_x = x;
+    for (child_name, child) in &frame.children {
+        render_frame(
+            svg,
+            child_name,
+            child,
+            child_x,
+            depth + 1,
+            height,
+            scale,
+            total,
+        );
+        child_x += child.samples as f64 * scale;
+    }
+}
+
+/// Warm colour derived from the name, so a function keeps its colour
+/// between captures.
+fn frame_color(name: &str) -> String {
+    let hash = name
+        .bytes()
+        .fold(2166136261u32, |h, b| (h ^ b as u32).wrapping_mul(16777619));
+    let r = 205 + (hash % 50);
+    let g = (hash >> 8) % 200;
+    let b = (hash >> 16) % 55;
+    format!("rgb({},{},{})", r, g, b)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "\
+my app  1234 5678.000001:   10101010 cpu-clock:pppH:
+\t    55d0a1 compute+0x1c (/usr/bin/app)
+\t    55d0b2 main+0x20 (/usr/bin/app)
+\t    7f0001 [unknown] (/usr/lib/libc.so.6)
+
+my app  1234 5678.010001:   10101010 cpu-clock:pppH:
+\t    55d0a1 compute+0x1c (/usr/bin/app)
+\t    55d0b2 main+0x20 (/usr/bin/app)
+\t    7f0001 [unknown] (/usr/lib/libc.so.6)
+
+worker 1234/1240 5678.020001:   10101010 cpu-clock:pppH:
+\t    55d0c3 std::vector<int>::push_back(int const&)+0x8 (/usr/bin/app)
+";
+
+    #[test]
+    fn only_plain_temporary_paths_are_used() {
+        assert!(is_plain_path("/tmp/m87-profile.Ab3_x9"));
+        assert!(!is_plain_path("m87-profile.Ab3_x9"));
+        assert!(!is_plain_path("/tmp/x'; rm -rf ~; '"));
+        assert!(!is_plain_path(""));
+    }
+
+    #[test]
+    fn perf_script_is_folded_root_first() {
+        let stacks = fold_perf_script(SCRIPT);
+        assert_eq!(stacks.len(), 2);
+        assert_eq!(stacks["my app;[libc.so.6];main;compute"], 2);
+        assert_eq!(stacks["worker;std::vector<int>::push_back(int const&)"], 1);
+    }
+
+    #[test]
+    fn svg_escapes_names_and_scales_to_samples() {
+        let svg = flamegraph_svg(&fold_perf_script(SCRIPT), "dev pid 1234");
+        assert!(svg.contains("std::vector&lt;int&gt;::push_back(int const&amp;) (1 samples"));
+        assert!(svg.contains("my app (2 samples, 66.67%)"));
+    }
+}
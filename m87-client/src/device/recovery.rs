@@ -0,0 +1,270 @@
+//! Break-glass recovery for when the server is unreachable and the usual
+//! credentials are lost.
+//!
+//! A single-use recovery code is printed when the runtime is installed; only
+//! its hash stays on the device. `m87 local unlock` trades the code for a
+//! short-lived local key that the LAN listener accepts like the device key,
+//! and prints the next code. Unlocks are reported with the next heartbeat.
+//! Direct connections an unlock turned on are turned off when its key expires.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use chrono::{SecondsFormat, Utc};
+use m87_shared::device::RecoveryUnlock;
+use russh::keys::ssh_key::rand_core::{OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::config::Config;
+use crate::device::lan::constant_time_eq;
+use crate::util::private_file::write_private;
+
+/// Unambiguous characters: no 0/O or 1/I
+const CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_GROUPS: usize = 4;
+const CODE_GROUP_LEN: usize = 5;
+
+#[derive(Serialize, Deserialize, Default)]
+struct RecoveryState {
+    /// SHA-256 of the unused code, normalized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    local_key: Option<LocalKey>,
+    /// Unlocks not yet carried by a heartbeat
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    unreported: Vec<RecoveryUnlock>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LocalKey {
+    key_hash: String,
+    /// Unix seconds
+    expires_at: i64,
+    /// Direct connections were off before the unlock
+    #[serde(default)]
+    enabled_direct: bool,
+}
+
+/// Result of [`unlock`].
+pub struct Unlocked {
+    /// Authenticates `--direct` connections until `expires_at`
+    pub local_key: String,
+    pub expires_at: String,
+    /// Replaces the code just used
+    pub next_code: String,
+}
+
+fn state_path() -> Result<PathBuf> {
+    Ok(Config::m87_config_dir()?.join("recovery.json"))
+}
+
+fn load_state() -> RecoveryState {
+    state_path()
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn save_state(state: &RecoveryState) -> Result<()> {
+    write_private(&state_path()?, &serde_json::to_vec_pretty(state)?)
+}
+
+fn random_bytes(n: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; n];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+fn format_code(bytes: &[u8]) -> String {
+    bytes
+        .chunks(CODE_GROUP_LEN)
+        .map(|group| {
+            group
+                .iter()
+                .map(|b| CODE_ALPHABET[(b % 32) as usize] as char)
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Codes are compared without case, dashes or spaces.
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn hash(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+fn unix_now() -> i64 {
+    Utc::now().timestamp()
+}
+
+fn rfc3339(unix_secs: i64) -> String {
+    chrono::DateTime::from_timestamp(unix_secs, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn new_code() -> Result<String> {
+    let code = format_code(&random_bytes(CODE_GROUPS * CODE_GROUP_LEN));
+    let mut state = load_state();
+    state.code_hash = Some(hash(&normalize_code(&code)));
+    save_state(&state)?;
+    Ok(code)
+}
+
+/// Replace the recovery code with a new one and return it. Needs root or the
+/// current code, so that a shell on the device alone cannot take over recovery.
+pub fn replace_code(current: Option<&str>) -> Result<String> {
+    if !crate::util::unix::is_root() {
+        let Some(current) = current else {
+            bail!("Replacing the recovery code needs root or the current code");
+        };
+        let Some(code_hash) = load_state().code_hash else {
+            bail!("No recovery code is set on this device; run as root to create one");
+        };
+        if !constant_time_eq(
+            hash(&normalize_code(current)).as_bytes(),
+            code_hash.as_bytes(),
+        ) {
+            bail!("Invalid recovery code");
+        }
+    }
+    new_code()
+}
+
+/// Create a recovery code if the device has none. Returns it only if it is
+/// new, since it cannot be shown again.
+pub fn ensure_code() -> Result<Option<String>> {
+    if load_state().code_hash.is_some() {
+        return Ok(None);
+    }
+    new_code().map(Some)
+}
+
+/// Use up `code` for a local key valid for `ttl`. `enables_direct` tells that
+/// direct connections are turned on for it, to turn them off once it expires.
+pub fn unlock(code: &str, ttl: Duration, enables_direct: bool) -> Result<Unlocked> {
+    let mut state = load_state();
+    let Some(code_hash) = state.code_hash.take() else {
+        bail!("No recovery code is set on this device");
+    };
+    if !constant_time_eq(hash(&normalize_code(code)).as_bytes(), code_hash.as_bytes()) {
+        bail!("Invalid recovery code");
+    }
+
+    let local_key: String = random_bytes(32)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let now = unix_now();
+    let expires_at = now + ttl.as_secs() as i64;
+    let user = crate::util::unix::resolve_invoking_user()
+        .map(|u| u.username)
+        .unwrap_or_else(|_| "unknown".to_string());
+    // a second unlock keeps the first one's duty to turn direct connections off
+    let enabled_direct =
+        enables_direct || state.local_key.as_ref().is_some_and(|k| k.enabled_direct);
+    state.local_key = Some(LocalKey {
+        key_hash: hash(&local_key),
+        expires_at,
+        enabled_direct,
+    });
+    state.unreported.push(RecoveryUnlock {
+        user,
+        unlocked_at: rfc3339(now),
+        expires_at: rfc3339(expires_at),
+    });
+    // single use: the code is gone from the state even if no new one is saved
+    save_state(&state)?;
+
+    Ok(Unlocked {
+        local_key,
+        expires_at: rfc3339(expires_at),
+        next_code: new_code()?,
+    })
+}
+
+/// Hash of the unexpired local key from [`unlock`], if any; see
+/// [`crate::util::lan::key_hash`].
+pub fn local_key_hash() -> Option<String> {
+    let key = load_state().local_key?;
+    (key.expires_at > unix_now()).then_some(key.key_hash)
+}
+
+/// Drop the local key once it expired, and turn direct connections off again
+/// if its unlock turned them on. Returns whether it did.
+pub fn end_expired_unlock() -> Result<bool> {
+    let mut state = load_state();
+    let Some(key) = &state.local_key else {
+        return Ok(false);
+    };
+    if key.expires_at > unix_now() {
+        return Ok(false);
+    }
+    let enabled_direct = key.enabled_direct;
+    state.local_key = None;
+    save_state(&state)?;
+    if !enabled_direct {
+        return Ok(false);
+    }
+    let mut config = Config::load()?;
+    config.lan_direct = false;
+    config.save()?;
+    Ok(true)
+}
+
+/// Unlocks not yet carried by a heartbeat, oldest first.
+pub fn pending_reports() -> Vec<RecoveryUnlock> {
+    load_state().unreported
+}
+
+/// Drop the first `count` unlocks once a heartbeat carried them.
+pub fn clear_reported(count: usize) {
+    if count == 0 {
+        return;
+    }
+    let mut state = load_state();
+    let count = count.min(state.unreported.len());
+    state.unreported.drain(..count);
+    if let Err(e) = save_state(&state) {
+        warn!("Failed to clear reported recovery unlocks: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_code() {
+        let code = format_code(&random_bytes(CODE_GROUPS * CODE_GROUP_LEN));
+        assert_eq!(code.len(), CODE_GROUPS * (CODE_GROUP_LEN + 1) - 1);
+        assert!(
+            code.split('-')
+                .all(|g| g.len() == CODE_GROUP_LEN
+                    && g.bytes().all(|c| CODE_ALPHABET.contains(&c)))
+        );
+        assert_eq!(format_code(&[0, 31, 32, 33, 255]), "A9AB9");
+    }
+
+    #[test]
+    fn test_normalize_code() {
+        assert_eq!(normalize_code(" abcde-FGH23 "), "ABCDEFGH23");
+        assert_eq!(
+            hash(&normalize_code("abcde fgh23")),
+            hash(&normalize_code("ABCDE-FGH23"))
+        );
+    }
+}
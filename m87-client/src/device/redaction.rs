@@ -0,0 +1,72 @@
+//! Masking of secrets in what the runtime sends off the device: step log
+//! tails, observe output and errors in deploy reports, and streamed logs.
+//! Combines the built-in rules with the custom patterns of the device's orgs
+//! and the values of secret env vars of the desired and previous revision.
+
+use std::sync::{Arc, RwLock};
+
+use m87_shared::{deploy_spec::DeployReportKind, redact::Redactor};
+use once_cell::sync::Lazy;
+
+use crate::{config::Config, device::deployment_manager::RevisionStore};
+
+static REDACTOR: Lazy<RwLock<Arc<Redactor>>> = Lazy::new(|| RwLock::new(Arc::new(build())));
+
+fn build() -> Redactor {
+    let patterns = Config::load()
+        .map(|c| c.redact_patterns)
+        .unwrap_or_default();
+    let mut redactor = Redactor::new(&patterns);
+    let revisions = [
+        RevisionStore::get_desired_config(),
+        RevisionStore::get_previous_config(),
+    ];
+    for revision in revisions.into_iter().flatten().flatten() {
+        for spec in &revision.jobs {
+            redactor.add_env(&spec.env);
+        }
+    }
+    redactor
+}
+
+fn current() -> Arc<Redactor> {
+    match REDACTOR.read() {
+        Ok(redactor) => redactor.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    }
+}
+
+/// Pick up changed patterns or a new desired revision.
+pub fn reload() {
+    let redactor = Arc::new(build());
+    match REDACTOR.write() {
+        Ok(mut current) => *current = redactor,
+        Err(poisoned) => *poisoned.into_inner() = redactor,
+    }
+}
+
+pub fn redact(text: &str) -> String {
+    current().redact(text)
+}
+
+fn redact_opt(text: &mut Option<String>) {
+    if let Some(text) = text {
+        *text = redact(text);
+    }
+}
+
+/// Mask the free text of `report` before it is queued.
+pub fn redact_report(report: &mut DeployReportKind) {
+    match report {
+        DeployReportKind::DeploymentRevisionReport(r) => redact_opt(&mut r.error),
+        DeployReportKind::RunReport(r) => redact_opt(&mut r.error),
+        DeployReportKind::StepReport(r) => {
+            redact_opt(&mut r.error);
+            r.log_tail = redact(&r.log_tail);
+        }
+        DeployReportKind::RollbackReport(_) => {}
+        DeployReportKind::RunState(r) => redact_opt(&mut r.log_tail),
+        DeployReportKind::DriftReport(r) => redact_opt(&mut r.error),
+        DeployReportKind::CoreDump(_) | DeployReportKind::EgressViolation(_) => {}
+    }
+}
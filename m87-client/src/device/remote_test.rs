@@ -0,0 +1,138 @@
+//! `m87 <device> test`: sync a local directory to the device, run a test
+//! command there (on the host or in a container), stream its output and pull
+//! result files such as JUnit reports back.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use crate::device::fs;
+use crate::tui;
+use crate::util::command::shell_quote;
+use crate::util::shutdown::SHUTDOWN;
+
+/// Where synced directories land on the device unless `--remote-dir` is given
+const REMOTE_BASE: &str = "/tmp/m87-test";
+
+pub struct TestRun {
+    /// Local directory to sync
+    pub dir: PathBuf,
+    /// Directory on the device; defaults to one named after `dir`
+    pub remote_dir: Option<String>,
+    /// Run the command in a container of this image, with the directory
+    /// mounted as its working directory
+    pub image: Option<String>,
+    /// Files or directories, relative to the remote directory, to copy back
+    pub pull: Vec<String>,
+    /// Local directory the pulled files go to
+    pub out: PathBuf,
+    /// Sync excludes, as for `m87 sync`
+    pub exclude: Vec<String>,
+    pub command: Vec<String>,
+}
+
+/// Run the test and return the command's exit code. Results are pulled
+/// back whether the command passed or not.
+pub async fn run_test(device: &str, run: TestRun) -> Result<i32> {
+    if !run.dir.is_dir() {
+        bail!("{} is not a directory", run.dir.display());
+    }
+    let remote_dir = match &run.remote_dir {
+        Some(d) => d.trim_end_matches('/').to_string(),
+        None => default_remote_dir(&run.dir)?,
+    };
+
+    tracing::info!("Syncing {} to {}:{}", run.dir.display(), device, remote_dir);
+    fs::sync(
+        &run.dir.to_string_lossy(),
+        &format!("{}:{}", device, remote_dir),
+        true,
+        false,
+        &run.exclude,
+    )
+    .await
+    .context("failed to sync the test directory")?;
+
+    let command = remote_command(&remote_dir, run.image.as_deref(), &run.command.join(" "));
+    tracing::info!("Running {}", run.command.join(" "));
+    let exit_code = tui::exec::run_streamed(device, command).await?;
+    if SHUTDOWN.is_cancelled() {
+        return Ok(exit_code);
+    }
+
+    pull_results(device, &remote_dir, &run.pull, &run.out).await?;
+    Ok(exit_code)
+}
+
+fn default_remote_dir(dir: &Path) -> Result<String> {
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("cannot resolve {}", dir.display()))?;
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "root".to_string());
+    Ok(format!("{}/{}", REMOTE_BASE, name))
+}
+
+/// Shell command running `command` in `remote_dir`, in a throwaway container
+/// of `image` if given.
+fn remote_command(remote_dir: &str, image: Option<&str>, command: &str) -> String {
+    match image {
+        Some(image) => format!(
+            "docker run --rm -v {}:/work -w /work {} sh -c {}",
+            shell_quote(remote_dir),
+            shell_quote(image),
+            shell_quote(command)
+        ),
+        None => format!("cd {} && {}", shell_quote(remote_dir), command),
+    }
+}
+
+async fn pull_results(device: &str, remote_dir: &str, pull: &[String], out: &Path) -> Result<()> {
+    if pull.is_empty() {
+        return Ok(());
+    }
+    let sftp = fs::open_sftp_session(device).await?;
+    for rel in pull {
+        let rel = rel.trim_start_matches("./").trim_end_matches('/');
+        let remote = format!("{}/{}", remote_dir, rel);
+        let meta = match sftp.metadata(remote.clone()).await {
+            Ok(meta) => meta,
+            Err(_) => {
+                tracing::warn!("{} was not created on the device", rel);
+                continue;
+            }
+        };
+        let local = out.join(rel);
+        let src = format!("{}:{}", device, remote);
+        if meta.is_dir() {
+            fs::sync(&src, &local.to_string_lossy(), false, false, &[]).await?;
+        } else {
+            fs::copy(&src, &local.to_string_lossy()).await?;
+        }
+        tracing::info!("Pulled {} to {}", rel, local.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_command_runs_in_the_synced_directory() {
+        assert_eq!(
+            remote_command("/tmp/m87-test/app", None, "pytest -q"),
+            "cd '/tmp/m87-test/app' && pytest -q"
+        );
+    }
+
+    #[test]
+    fn container_command_mounts_the_synced_directory() {
+        assert_eq!(
+            remote_command("/tmp/m87-test/app", Some("ros:humble"), "colcon test"),
+            "docker run --rm -v '/tmp/m87-test/app':/work -w /work 'ros:humble' sh -c 'colcon test'"
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    devices, server,
+    streams::{
+        quic::open_quic_io,
+        stream_type::{SessionInfo, SessionsReply, StreamType},
+    },
+};
+
+/// Shell and exec sessions open on `device`, after terminating `kill` if set.
+pub async fn sessions(device: &str, kill: Option<String>) -> Result<Vec<SessionInfo>> {
+    let request = json!({ "device": device, "kill": kill });
+    server::vcr::call("device_sessions", request, query_sessions(device, kill)).await
+}
+
+async fn query_sessions(device: &str, kill: Option<String>) -> Result<Vec<SessionInfo>> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let stream_type = StreamType::Sessions {
+        token: token.clone(),
+        kill,
+    };
+    let (_conn, io) = open_quic_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        config.trust_invalid_server_cert,
+    )
+    .await?;
+
+    let line = BufReader::new(io)
+        .lines()
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("device closed the stream without listing sessions"))?;
+    let reply: SessionsReply =
+        serde_json::from_str(&line).context("Failed to parse device sessions")?;
+    if let Some(error) = reply.error {
+        bail!(error);
+    }
+    Ok(reply.sessions)
+}
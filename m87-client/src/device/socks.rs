@@ -0,0 +1,393 @@
+//! `m87 <device> proxy --socks5 <port>`: a local SOCKS5 server (RFC 1928)
+//! whose connections leave from the device. CONNECT opens a TCP forward
+//! stream per connection; UDP ASSOCIATE opens a UDP forward channel per
+//! destination and relays its datagrams. Names are resolved on the device.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Result, bail};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::streams::quic::{connect_quic_only, open_quic_stream};
+use crate::streams::stream_type::{StreamClass, TcpTarget, UdpTarget};
+use crate::util::shutdown::SHUTDOWN;
+use crate::util::udp::{decode_socket_addr, encode_socket_addr};
+use crate::{auth::AuthManager, config::Config, devices};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const NO_ACCEPTABLE_METHOD: u8 = 0xFF;
+const CMD_CONNECT: u8 = 1;
+const CMD_UDP_ASSOCIATE: u8 = 3;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const REPLY_OK: u8 = 0;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// UDP forward channels of the connection, by channel id.
+type Channels = Arc<Mutex<HashMap<u32, mpsc::Sender<(u32, Bytes)>>>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SocksAddr {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl SocksAddr {
+    fn host(&self) -> String {
+        match self {
+            SocksAddr::Ip(addr) => addr.ip().to_string(),
+            SocksAddr::Domain(name, _) => name.clone(),
+        }
+    }
+
+    fn port(&self) -> u16 {
+        match self {
+            SocksAddr::Ip(addr) => addr.port(),
+            SocksAddr::Domain(_, port) => *port,
+        }
+    }
+
+    fn encode(&self, buf: &mut BytesMut) {
+        match self {
+            SocksAddr::Ip(SocketAddr::V4(v4)) => {
+                buf.put_u8(ATYP_IPV4);
+                buf.extend_from_slice(&v4.ip().octets());
+            }
+            SocksAddr::Ip(SocketAddr::V6(v6)) => {
+                buf.put_u8(ATYP_IPV6);
+                buf.extend_from_slice(&v6.ip().octets());
+            }
+            SocksAddr::Domain(name, _) => {
+                buf.put_u8(ATYP_DOMAIN);
+                buf.put_u8(name.len() as u8);
+                buf.extend_from_slice(name.as_bytes());
+            }
+        }
+        buf.put_u16(self.port());
+    }
+}
+
+impl std::fmt::Display for SocksAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SocksAddr::Ip(addr) => write!(f, "{}", addr),
+            SocksAddr::Domain(name, port) => write!(f, "{}:{}", name, port),
+        }
+    }
+}
+
+pub async fn open_socks_proxy(device: &str, bind: IpAddr, port: u16) -> Result<()> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let listener = TcpListener::bind((bind, port)).await?;
+    let (_endpoint, conn) = connect_quic_only(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        config.trust_invalid_server_cert,
+        StreamClass::Bulk,
+    )
+    .await?;
+    println!(
+        "SOCKS5 proxy: socks5h://{} → {}",
+        listener.local_addr()?,
+        device
+    );
+
+    let channels: Channels = Arc::default();
+    tokio::spawn(demux_datagrams(conn.clone(), channels.clone()));
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (client, peer) = accepted?;
+                debug!("New SOCKS5 client {peer}");
+                let conn = conn.clone();
+                let token = token.clone();
+                let channels = channels.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_client(client, conn, &token, channels).await {
+                        debug!("SOCKS5 client {peer} closed: {e:#}");
+                    }
+                });
+            }
+            reason = conn.closed() => {
+                warn!("Connection closed: {:?}", reason);
+                break;
+            }
+            _ = SHUTDOWN.cancelled() => {
+                info!("Shutdown requested — closing SOCKS5 proxy");
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn serve_client(
+    mut client: TcpStream,
+    conn: quinn::Connection,
+    token: &str,
+    channels: Channels,
+) -> Result<()> {
+    // greeting: VER NMETHODS METHODS
+    let mut head = [0u8; 2];
+    client.read_exact(&mut head).await?;
+    if head[0] != VERSION {
+        bail!("not a SOCKS5 client (version {})", head[0]);
+    }
+    let mut methods = vec![0u8; head[1] as usize];
+    client.read_exact(&mut methods).await?;
+    if !methods.contains(&NO_AUTH) {
+        client.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await?;
+        bail!("client requires authentication");
+    }
+    client.write_all(&[VERSION, NO_AUTH]).await?;
+
+    // request: VER CMD RSV ATYP DST.ADDR DST.PORT
+    let mut req = [0u8; 4];
+    client.read_exact(&mut req).await?;
+    let Some(target) = read_addr(&mut client, req[3]).await? else {
+        reply(&mut client, REPLY_ADDRESS_NOT_SUPPORTED, None).await?;
+        bail!("unsupported address type {}", req[3]);
+    };
+
+    match req[1] {
+        CMD_CONNECT => {
+            info!("SOCKS5 CONNECT {}", target);
+            let stream_type = TcpTarget {
+                remote_host: target.host(),
+                remote_port: target.port(),
+                local_port: 0,
+            }
+            .to_stream_type(token);
+            let mut remote = open_quic_stream(&conn, stream_type).await?;
+            // the device connects when the stream arrives and closes it on failure
+            reply(&mut client, REPLY_OK, None).await?;
+            io::copy_bidirectional(&mut client, &mut remote).await?;
+            Ok(())
+        }
+        CMD_UDP_ASSOCIATE => udp_associate(client, conn, token, channels).await,
+        cmd => {
+            reply(&mut client, REPLY_COMMAND_NOT_SUPPORTED, None).await?;
+            bail!("unsupported command {}", cmd)
+        }
+    }
+}
+
+async fn read_addr(client: &mut TcpStream, atyp: u8) -> Result<Option<SocksAddr>> {
+    let host = match atyp {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip).await?;
+            IpAddr::from(ip).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            client.read_exact(&mut ip).await?;
+            IpAddr::from(ip).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = client.read_u8().await? as usize;
+            let mut name = vec![0u8; len];
+            client.read_exact(&mut name).await?;
+            String::from_utf8_lossy(&name).into_owned()
+        }
+        _ => return Ok(None),
+    };
+    let port = client.read_u16().await?;
+    Ok(Some(match host.parse::<IpAddr>() {
+        Ok(ip) => SocksAddr::Ip(SocketAddr::new(ip, port)),
+        Err(_) => SocksAddr::Domain(host, port),
+    }))
+}
+
+async fn reply(client: &mut TcpStream, code: u8, bound: Option<SocketAddr>) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(22);
+    buf.extend_from_slice(&[VERSION, code, 0]);
+    let bound = bound.unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+    SocksAddr::Ip(bound).encode(&mut buf);
+    client.write_all(&buf).await?;
+    Ok(())
+}
+
+/// Relay the datagrams of one UDP association until its TCP connection closes.
+async fn udp_associate(
+    mut client: TcpStream,
+    conn: quinn::Connection,
+    token: &str,
+    channels: Channels,
+) -> Result<()> {
+    let relay = UdpSocket::bind((client.local_addr()?.ip(), 0)).await?;
+    reply(&mut client, REPLY_OK, Some(relay.local_addr()?)).await?;
+    info!("SOCKS5 UDP ASSOCIATE on {}", relay.local_addr()?);
+
+    let mut targets = HashMap::new();
+    let result = relay_datagrams(&mut client, &relay, &conn, token, &channels, &mut targets).await;
+    let mut channels = channels.lock().unwrap();
+    for chan in targets.keys() {
+        channels.remove(chan);
+    }
+    result
+}
+
+async fn relay_datagrams(
+    client: &mut TcpStream,
+    relay: &UdpSocket,
+    conn: &quinn::Connection,
+    token: &str,
+    channels: &Channels,
+    targets: &mut HashMap<u32, SocksAddr>,
+) -> Result<()> {
+    let client_ip = client.peer_addr()?.ip();
+    let (tx, mut rx) = mpsc::channel::<(u32, Bytes)>(256);
+    let mut by_target: HashMap<SocksAddr, u32> = HashMap::new();
+    let mut app: Option<SocketAddr> = None;
+    let mut buf = vec![0u8; 65535];
+    let mut eof = [0u8; 1];
+
+    loop {
+        tokio::select! {
+            received = relay.recv_from(&mut buf) => {
+                let (n, src) = received?;
+                if src.ip() != client_ip {
+                    continue;
+                }
+                let Some((target, off)) = parse_udp_header(&buf[..n]) else {
+                    debug!("dropping malformed or fragmented SOCKS5 datagram");
+                    continue;
+                };
+                app = Some(src);
+                let chan = match by_target.get(&target) {
+                    Some(chan) => *chan,
+                    None => {
+                        let chan = open_udp_channel(conn, token, &target).await?;
+                        channels.lock().unwrap().insert(chan, tx.clone());
+                        by_target.insert(target.clone(), chan);
+                        targets.insert(chan, target);
+                        chan
+                    }
+                };
+                // [channel_id][src_addr_header][payload], as udp forwards send it
+                let mut d = BytesMut::with_capacity(4 + 19 + n - off);
+                d.put_u32(chan);
+                encode_socket_addr(&mut d, src);
+                d.extend_from_slice(&buf[off..n]);
+                conn.send_datagram(d.freeze())?;
+            }
+            Some((chan, body)) = rx.recv() => {
+                let (Some(app), Some(target)) = (app, targets.get(&chan)) else {
+                    continue;
+                };
+                let Some((_, hdr_len)) = decode_socket_addr(&body) else {
+                    continue;
+                };
+                let mut d = BytesMut::with_capacity(3 + 262 + body.len());
+                d.extend_from_slice(&[0, 0, 0]);
+                target.encode(&mut d);
+                d.extend_from_slice(&body[hdr_len..]);
+                relay.send_to(&d, app).await?;
+            }
+            // the association ends with its TCP connection
+            _ = client.read(&mut eof) => return Ok(()),
+        }
+    }
+}
+
+async fn open_udp_channel(
+    conn: &quinn::Connection,
+    token: &str,
+    target: &SocksAddr,
+) -> Result<u32> {
+    let stream_type = UdpTarget {
+        remote_host: target.host(),
+        remote_port: target.port(),
+        local_port: 0,
+    }
+    .to_stream_type(token);
+    let mut io = open_quic_stream(conn, stream_type).await?;
+    let mut id = [0u8; 4];
+    io.recv.read_exact(&mut id).await?;
+    io.send.finish()?;
+    debug!("SOCKS5 UDP channel {} → {}", u32::from_be_bytes(id), target);
+    Ok(u32::from_be_bytes(id))
+}
+
+/// Hand each datagram of the connection to the association owning its channel.
+async fn demux_datagrams(conn: quinn::Connection, channels: Channels) {
+    while let Ok(d) = conn.read_datagram().await {
+        if d.len() < 4 {
+            continue;
+        }
+        let chan = u32::from_be_bytes([d[0], d[1], d[2], d[3]]);
+        let tx = channels.lock().unwrap().get(&chan).cloned();
+        if let Some(tx) = tx {
+            let _ = tx.try_send((chan, d.slice(4..)));
+        }
+    }
+}
+
+/// Destination and payload offset of a SOCKS5 UDP datagram
+/// (`RSV RSV FRAG ATYP DST.ADDR DST.PORT DATA`). Fragments are not supported.
+fn parse_udp_header(buf: &[u8]) -> Option<(SocksAddr, usize)> {
+    if buf.len() < 4 || buf[2] != 0 {
+        return None;
+    }
+    let (addr, off) = match buf[3] {
+        ATYP_IPV4 => {
+            let ip: [u8; 4] = buf.get(4..8)?.try_into().ok()?;
+            (SocksAddr::Ip(SocketAddr::new(IpAddr::from(ip), 0)), 8)
+        }
+        ATYP_IPV6 => {
+            let ip: [u8; 16] = buf.get(4..20)?.try_into().ok()?;
+            (SocksAddr::Ip(SocketAddr::new(IpAddr::from(ip), 0)), 20)
+        }
+        ATYP_DOMAIN => {
+            let len = *buf.get(4)? as usize;
+            let name = std::str::from_utf8(buf.get(5..5 + len)?).ok()?;
+            (SocksAddr::Domain(name.to_string(), 0), 5 + len)
+        }
+        _ => return None,
+    };
+    let port = u16::from_be_bytes(buf.get(off..off + 2)?.try_into().ok()?);
+    let addr = match addr {
+        SocksAddr::Ip(ip) => SocksAddr::Ip(SocketAddr::new(ip.ip(), port)),
+        SocksAddr::Domain(name, _) => SocksAddr::Domain(name, port),
+    };
+    Some((addr, off + 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_udp_header_round_trip() {
+        for target in [
+            SocksAddr::Ip("192.168.1.20:53".parse().unwrap()),
+            SocksAddr::Ip("[fd00::5]:5353".parse().unwrap()),
+            SocksAddr::Domain("mydb.internal".to_string(), 5432),
+        ] {
+            let mut d = BytesMut::new();
+            d.extend_from_slice(&[0, 0, 0]);
+            target.encode(&mut d);
+            d.extend_from_slice(b"payload");
+            let (parsed, off) = parse_udp_header(&d).unwrap();
+            assert_eq!(parsed, target);
+            assert_eq!(&d[off..], b"payload");
+        }
+        // fragments are dropped
+        assert!(parse_udp_header(&[0, 0, 1, ATYP_IPV4, 1, 2, 3, 4, 0, 53]).is_none());
+    }
+}
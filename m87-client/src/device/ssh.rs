@@ -9,7 +9,7 @@ use crate::{
     auth::AuthManager,
     config::Config,
     devices,
-    streams::{quic::open_quic_io, stream_type::StreamType},
+    streams::{quic::open_interactive_io, stream_type::StreamType},
     util::command::current_exe_path,
 };
 
@@ -21,31 +21,28 @@ pub async fn connect_device_ssh(device_name: &str) -> Result<()> {
 
     let token = AuthManager::get_cli_token().await?;
 
-    let (conn, mut quic) = open_quic_io(
-        &resolved.host,
-        &token,
-        &resolved.short_id,
-        StreamType::Ssh {
-            token: token.to_string(),
-        },
-        config.trust_invalid_server_cert,
-    )
-    .await
-    .context("Failed to connect to device")?;
+    let stream_type = StreamType::Ssh {
+        token: token.to_string(),
+        transfer: false,
+    };
+    let (conn, io) = open_interactive_io(&resolved, &token, stream_type, &config)
+        .await
+        .context("Failed to connect to device")?;
+    let (mut reader, mut writer) = io::split(io);
 
     let mut stdin = io::stdin();
     let mut stdout = io::stdout();
 
     // stdin → QUIC
     let to_remote = async {
-        io::copy(&mut stdin, &mut quic.send).await?;
-        let _ = quic.send.finish();
+        io::copy(&mut stdin, &mut writer).await?;
+        let _ = writer.shutdown().await;
         Result::<()>::Ok(())
     };
 
     // QUIC → stdout
     let to_local = async {
-        io::copy(&mut quic.recv, &mut stdout).await?;
+        io::copy(&mut reader, &mut stdout).await?;
         let _ = stdout.shutdown().await;
         Result::<()>::Ok(())
     };
@@ -60,13 +57,21 @@ pub async fn connect_device_ssh(device_name: &str) -> Result<()> {
             // local shutdown / Ctrl-C / service exit
         }
 
-        _ = conn.closed() => {
+        _ = async {
+            match &conn {
+                Some(conn) => {
+                    conn.closed().await;
+                }
+                // a warm connection ends the stream when it closes
+                None => std::future::pending().await,
+            }
+        } => {
             // remote device disconnected
         }
     }
 
     // best-effort cleanup
-    let _ = quic.send.finish();
+    let _ = writer.shutdown().await;
     Ok(())
 }
 
@@ -92,11 +97,18 @@ fn m87_ssh_block() -> String {
     let exe_path = current_exe_path()
         .map(|p| p.to_string_lossy().into_owned())
         .unwrap_or_else(|_| "m87".to_string());
+    // e.g. installs under C:\Program Files
+    let exe_path = match exe_path.contains(' ') {
+        true => format!("\"{}\"", exe_path),
+        false => exe_path,
+    };
 
     format!(
         r#"
 Host *.m87
     ProxyCommand {} ssh %h %r --transport
+    ServerAliveInterval 15
+    ServerAliveCountMax 4
 "#,
         exe_path
     )
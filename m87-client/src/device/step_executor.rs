@@ -0,0 +1,493 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use m87_shared::deploy_spec::{CommandSpec, Step};
+use m87_shared::expr;
+use m87_shared::facts::FACTS_PREFIX;
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::device::fact_collectors::get_facts;
+use crate::util::command::{CommandFailed, RunCommandError, run_command};
+
+/// Executor kind used when a step does not set `uses`.
+pub const DEFAULT_STEP_KIND: &str = "command";
+
+/// Everything an executor needs to know about where and for whom it runs.
+pub struct StepContext<'a> {
+    pub run_id: &'a str,
+    pub wd: &'a Path,
+    pub env: &'a BTreeMap<String, String>,
+    pub timeout: Option<Duration>,
+    pub max_tail_bytes: usize,
+}
+
+/// A kind of deployment step. Executors return the output tail on success. Failures that
+/// should show up as a failed step report (rather than abort the run) are returned as
+/// `RunCommandError::Failed`.
+#[async_trait]
+pub trait StepExecutor: Send + Sync {
+    /// Name referenced by `uses:` in a step
+    fn kind(&self) -> &'static str;
+
+    async fn execute(
+        &self,
+        ctx: &StepContext<'_>,
+        run: &CommandSpec,
+        with: &Map<String, Value>,
+    ) -> Result<String, RunCommandError>;
+}
+
+/// Registered step executors by kind.
+#[derive(Clone)]
+pub struct StepExecutorRegistry {
+    executors: HashMap<&'static str, Arc<dyn StepExecutor>>,
+}
+
+impl Default for StepExecutorRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(CommandExecutor);
+        registry.register(CopyFileExecutor);
+        registry.register(TemplateExecutor);
+        registry.register(HttpExecutor);
+        registry.register(SystemdUnitExecutor);
+        registry
+    }
+}
+
+impl StepExecutorRegistry {
+    pub fn empty() -> Self {
+        Self {
+            executors: HashMap::new(),
+        }
+    }
+
+    /// Register an executor, replacing any existing one of the same kind.
+    pub fn register<E: StepExecutor + 'static>(&mut self, executor: E) {
+        self.executors.insert(executor.kind(), Arc::new(executor));
+    }
+
+    pub fn get(&self, kind: &str) -> Option<Arc<dyn StepExecutor>> {
+        self.executors.get(kind).cloned()
+    }
+
+    pub fn kinds(&self) -> Vec<&'static str> {
+        let mut kinds: Vec<_> = self.executors.keys().copied().collect();
+        kinds.sort();
+        kinds
+    }
+
+    /// Resolve the executor for `step` and run it.
+    pub async fn execute(
+        &self,
+        ctx: &StepContext<'_>,
+        step: &Step,
+    ) -> Result<String, RunCommandError> {
+        let kind = step.uses.as_deref().unwrap_or(DEFAULT_STEP_KIND);
+        let executor = self.get(kind).ok_or_else(|| {
+            RunCommandError::Other(anyhow!(
+                "unknown step kind '{}' (known: {})",
+                kind,
+                self.kinds().join(", ")
+            ))
+        })?;
+        executor.execute(ctx, &step.run, &step.with).await
+    }
+}
+
+/// Build a failure that is reported as a failed step.
+fn step_failed(ctx: &StepContext<'_>, msg: impl Into<String>) -> RunCommandError {
+    let msg = msg.into();
+    RunCommandError::Failed(CommandFailed {
+        run_id: ctx.run_id.to_string(),
+        exit_code: None,
+        timed_out: false,
+        stdout_tail: String::new(),
+        stderr_tail: msg.clone(),
+        combined_tail: msg.clone(),
+        error: Some(msg),
+    })
+}
+
+#[allow(clippy::result_large_err)]
+fn param_str<'a>(
+    ctx: &StepContext<'_>,
+    with: &'a Map<String, Value>,
+    key: &str,
+) -> Result<Option<&'a str>, RunCommandError> {
+    match with.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s)),
+        Some(_) => Err(step_failed(ctx, format!("'{}' must be a string", key))),
+    }
+}
+
+#[allow(clippy::result_large_err)]
+fn required_str<'a>(
+    ctx: &StepContext<'_>,
+    with: &'a Map<String, Value>,
+    key: &str,
+) -> Result<&'a str, RunCommandError> {
+    param_str(ctx, with, key)?.ok_or_else(|| step_failed(ctx, format!("missing '{}'", key)))
+}
+
+fn param_bool(with: &Map<String, Value>, key: &str, default: bool) -> bool {
+    with.get(key).and_then(Value::as_bool).unwrap_or(default)
+}
+
+/// Paths in step parameters are relative to the run's workdir unless absolute.
+fn resolve_path(wd: &Path, p: &str) -> PathBuf {
+    let p = Path::new(p);
+    if p.is_absolute() {
+        p.to_path_buf()
+    } else {
+        wd.join(p)
+    }
+}
+
+async fn write_file(
+    ctx: &StepContext<'_>,
+    dest: &Path,
+    content: &[u8],
+    mode: Option<&str>,
+) -> Result<(), RunCommandError> {
+    if let Some(parent) = dest.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(RunCommandError::Io)?;
+    }
+    tokio::fs::write(dest, content)
+        .await
+        .map_err(|e| step_failed(ctx, format!("write {}: {}", dest.display(), e)))?;
+    if let Some(mode) = mode {
+        set_mode(ctx, dest, mode).await?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn set_mode(ctx: &StepContext<'_>, path: &Path, mode: &str) -> Result<(), RunCommandError> {
+    use std::os::unix::fs::PermissionsExt;
+    let bits = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .map_err(|_| step_failed(ctx, format!("invalid mode '{}'", mode)))?;
+    tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(bits))
+        .await
+        .map_err(RunCommandError::Io)
+}
+
+#[cfg(not(unix))]
+async fn set_mode(
+    _ctx: &StepContext<'_>,
+    _path: &Path,
+    _mode: &str,
+) -> Result<(), RunCommandError> {
+    Ok(())
+}
+
+/// Replace `{{ expr }}` placeholders with values computed from `vars` (see
+/// [`m87_shared::expr`]). Unset values are an error unless given a `default(...)`.
+pub fn render_template(input: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    expr::render_template(input, vars).map_err(|e| e.to_string())
+}
+
+// ---------------------------------------------------------
+// Built-in executors
+// ---------------------------------------------------------
+
+/// `command`: run `run` as a process (the original step behavior).
+pub struct CommandExecutor;
+
+#[async_trait]
+impl StepExecutor for CommandExecutor {
+    fn kind(&self) -> &'static str {
+        DEFAULT_STEP_KIND
+    }
+
+    async fn execute(
+        &self,
+        ctx: &StepContext<'_>,
+        run: &CommandSpec,
+        _with: &Map<String, Value>,
+    ) -> Result<String, RunCommandError> {
+        run_command(
+            ctx.run_id,
+            ctx.wd,
+            ctx.env,
+            run,
+            ctx.timeout,
+            ctx.max_tail_bytes,
+        )
+        .await
+    }
+}
+
+/// `copy_file`: with `from`, `to` and optional `mode` (octal string).
+pub struct CopyFileExecutor;
+
+#[async_trait]
+impl StepExecutor for CopyFileExecutor {
+    fn kind(&self) -> &'static str {
+        "copy_file"
+    }
+
+    async fn execute(
+        &self,
+        ctx: &StepContext<'_>,
+        _run: &CommandSpec,
+        with: &Map<String, Value>,
+    ) -> Result<String, RunCommandError> {
+        let from = resolve_path(ctx.wd, required_str(ctx, with, "from")?);
+        let to = resolve_path(ctx.wd, required_str(ctx, with, "to")?);
+        let content = tokio::fs::read(&from)
+            .await
+            .map_err(|e| step_failed(ctx, format!("read {}: {}", from.display(), e)))?;
+        write_file(ctx, &to, &content, param_str(ctx, with, "mode")?).await?;
+        Ok(format!("copied {} -> {}", from.display(), to.display()))
+    }
+}
+
+/// `template`: with `src` (file) or `content` (inline), `dest` and optional `mode`.
+/// Placeholders are expressions over the run's env and device facts, e.g.
+/// `{{ PORT }}` or `{{ default(facts.gpu.name, "none") }}`.
+pub struct TemplateExecutor;
+
+#[async_trait]
+impl StepExecutor for TemplateExecutor {
+    fn kind(&self) -> &'static str {
+        "template"
+    }
+
+    async fn execute(
+        &self,
+        ctx: &StepContext<'_>,
+        _run: &CommandSpec,
+        with: &Map<String, Value>,
+    ) -> Result<String, RunCommandError> {
+        let template = match (
+            param_str(ctx, with, "content")?,
+            param_str(ctx, with, "src")?,
+        ) {
+            (Some(content), _) => content.to_string(),
+            (None, Some(src)) => {
+                let src = resolve_path(ctx.wd, src);
+                tokio::fs::read_to_string(&src)
+                    .await
+                    .map_err(|e| step_failed(ctx, format!("read {}: {}", src.display(), e)))?
+            }
+            (None, None) => return Err(step_failed(ctx, "missing 'src' or 'content'")),
+        };
+        let dest = resolve_path(ctx.wd, required_str(ctx, with, "dest")?);
+        let mut vars = ctx.env.clone();
+        if template.contains(FACTS_PREFIX) {
+            vars.extend(get_facts(false).await.template_vars());
+        }
+        let rendered = render_template(&template, &vars).map_err(|e| step_failed(ctx, e))?;
+        write_file(
+            ctx,
+            &dest,
+            rendered.as_bytes(),
+            param_str(ctx, with, "mode")?,
+        )
+        .await?;
+        Ok(format!("rendered {}", dest.display()))
+    }
+}
+
+/// `http`: with `url`, optional `method` (GET), `body`, `headers` (map) and
+/// `expect_status` (defaults to any 2xx).
+pub struct HttpExecutor;
+
+#[async_trait]
+impl StepExecutor for HttpExecutor {
+    fn kind(&self) -> &'static str {
+        "http"
+    }
+
+    async fn execute(
+        &self,
+        ctx: &StepContext<'_>,
+        _run: &CommandSpec,
+        with: &Map<String, Value>,
+    ) -> Result<String, RunCommandError> {
+        let url = required_str(ctx, with, "url")?;
+        let method = param_str(ctx, with, "method")?.unwrap_or("GET");
+        let method = reqwest::Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|_| step_failed(ctx, format!("invalid method '{}'", method)))?;
+
+        let client = reqwest::Client::builder()
+            .timeout(ctx.timeout.unwrap_or(Duration::from_secs(30)))
+            .build()
+            .map_err(|e| RunCommandError::Other(e.into()))?;
+        let mut req = client.request(method, url);
+        if let Some(Value::Object(headers)) = with.get("headers") {
+            for (k, v) in headers {
+                if let Some(v) = v.as_str() {
+                    req = req.header(k, v);
+                }
+            }
+        }
+        if let Some(body) = param_str(ctx, with, "body")? {
+            req = req.body(body.to_string());
+        }
+
+        let res = req
+            .send()
+            .await
+            .map_err(|e| step_failed(ctx, format!("request failed: {}", e)))?;
+        let status = res.status();
+        let body = res.text().await.unwrap_or_default();
+        let mut tail = body.as_bytes();
+        if tail.len() > ctx.max_tail_bytes {
+            tail = &tail[tail.len() - ctx.max_tail_bytes..];
+        }
+        let tail = String::from_utf8_lossy(tail).to_string();
+
+        let ok = match with.get("expect_status").and_then(Value::as_u64) {
+            Some(code) => u64::from(status.as_u16()) == code,
+            None => status.is_success(),
+        };
+        if !ok {
+            let mut err = step_failed(ctx, format!("unexpected status {}", status));
+            if let RunCommandError::Failed(f) = &mut err {
+                f.exit_code = Some(i32::from(status.as_u16()));
+                f.combined_tail = tail;
+            }
+            return Err(err);
+        }
+        Ok(tail)
+    }
+}
+
+/// `systemd_unit`: with `name`, `content` or `src`, optional `enable` (true) and
+/// `start` (true). Writes the unit to `/etc/systemd/system` and reloads systemd.
+pub struct SystemdUnitExecutor;
+
+#[async_trait]
+impl StepExecutor for SystemdUnitExecutor {
+    fn kind(&self) -> &'static str {
+        "systemd_unit"
+    }
+
+    async fn execute(
+        &self,
+        ctx: &StepContext<'_>,
+        _run: &CommandSpec,
+        with: &Map<String, Value>,
+    ) -> Result<String, RunCommandError> {
+        let name = required_str(ctx, with, "name")?;
+        if name.contains('/') || !name.contains('.') {
+            return Err(step_failed(ctx, format!("invalid unit name '{}'", name)));
+        }
+        let content = match (
+            param_str(ctx, with, "content")?,
+            param_str(ctx, with, "src")?,
+        ) {
+            (Some(content), _) => content.to_string(),
+            (None, Some(src)) => {
+                let src = resolve_path(ctx.wd, src);
+                tokio::fs::read_to_string(&src)
+                    .await
+                    .map_err(|e| step_failed(ctx, format!("read {}: {}", src.display(), e)))?
+            }
+            (None, None) => return Err(step_failed(ctx, "missing 'src' or 'content'")),
+        };
+
+        let dest = Path::new("/etc/systemd/system").join(name);
+        write_file(ctx, &dest, content.as_bytes(), Some("644")).await?;
+
+        let systemctl = |args: &[&str]| {
+            let mut argv = vec!["systemctl".to_string()];
+            argv.extend(args.iter().map(|a| a.to_string()));
+            CommandSpec::Argv(argv)
+        };
+
+        let mut out = vec![format!("installed {}", dest.display())];
+        let mut commands = vec![systemctl(&["daemon-reload"])];
+        match (
+            param_bool(with, "enable", true),
+            param_bool(with, "start", true),
+        ) {
+            (true, true) => commands.push(systemctl(&["enable", "--now", name])),
+            (true, false) => commands.push(systemctl(&["enable", name])),
+            (false, true) => commands.push(systemctl(&["restart", name])),
+            (false, false) => {}
+        }
+        for cmd in commands {
+            let tail = run_command(
+                ctx.run_id,
+                ctx.wd,
+                ctx.env,
+                &cmd,
+                ctx.timeout,
+                ctx.max_tail_bytes,
+            )
+            .await?;
+            if !tail.is_empty() {
+                out.push(tail);
+            }
+        }
+        Ok(out.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> BTreeMap<String, String> {
+        let mut v = BTreeMap::new();
+        v.insert("PORT".to_string(), "8080".to_string());
+        v.insert("HOST".to_string(), "0.0.0.0".to_string());
+        v
+    }
+
+    #[test]
+    fn test_render_template_substitutes() {
+        let out = render_template("listen {{HOST}}:{{ PORT }};", &vars()).unwrap();
+        assert_eq!(out, "listen 0.0.0.0:8080;");
+    }
+
+    #[test]
+    fn test_render_template_unknown_var() {
+        let err = render_template("{{ MISSING }}", &vars()).unwrap_err();
+        assert!(err.contains("MISSING"));
+    }
+
+    #[test]
+    fn test_render_template_default() {
+        let out = render_template(r#"{{ default(MISSING, "8000") }}"#, &vars()).unwrap();
+        assert_eq!(out, "8000");
+    }
+
+    #[test]
+    fn test_render_template_unterminated() {
+        assert!(render_template("value {{ PORT", &vars()).is_err());
+    }
+
+    #[test]
+    fn test_default_registry_kinds() {
+        let registry = StepExecutorRegistry::default();
+        assert_eq!(
+            registry.kinds(),
+            vec!["command", "copy_file", "http", "systemd_unit", "template"]
+        );
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let wd = Path::new("/data/jobs/a");
+        assert_eq!(
+            resolve_path(wd, "x.conf"),
+            PathBuf::from("/data/jobs/a/x.conf")
+        );
+        assert_eq!(
+            resolve_path(wd, "/etc/x.conf"),
+            PathBuf::from("/etc/x.conf")
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! `m87 <device> support-bundle`: collect what the runtime reports about
+//! itself and how this machine reaches it into one `.tar.gz` to attach to a
+//! support request.
+
+use std::path::{Component, Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::{Context, Result, anyhow, bail};
+use flate2::{Compression, write::GzEncoder};
+use m87_shared::redact::{scrub, scrub_json};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    devices,
+    streams::{
+        quic::open_bulk_io,
+        stream_type::{BundleEntry, StreamType},
+    },
+    util::lan::direct_target,
+};
+
+/// What was written by [`support_bundle`].
+pub struct BundleSummary {
+    pub path: PathBuf,
+    pub files: usize,
+    pub bytes: u64,
+    /// Files of which only the end was included
+    pub truncated: Vec<String>,
+}
+
+/// Path inside the bundle for a name sent by the device; only plain relative
+/// paths are accepted.
+fn entry_path(name: &str) -> Option<PathBuf> {
+    let path = Path::new(name);
+    let plain = path.components().all(|c| matches!(c, Component::Normal(_)));
+    (plain && !name.is_empty()).then(|| path.to_path_buf())
+}
+
+/// Scrub text content again on this side, in case the device runs a version
+/// that masks less.
+fn rescrub(name: &str, content: Vec<u8>) -> Vec<u8> {
+    if name.ends_with(".json") {
+        if let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&content) {
+            scrub_json(&mut value);
+            if let Ok(content) = serde_json::to_vec_pretty(&value) {
+                return content;
+            }
+        }
+        return content;
+    }
+    match String::from_utf8(content) {
+        Ok(text) => scrub(&text).into_bytes(),
+        Err(e) => e.into_bytes(),
+    }
+}
+
+/// Collect a support bundle of `device` into `output` (default
+/// `<device>-support-<time>.tar.gz` in the current directory), with at most
+/// `max_bytes` of uncompressed content from the device.
+pub async fn support_bundle(
+    device: &str,
+    output: Option<PathBuf>,
+    max_bytes: u64,
+) -> Result<BundleSummary> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let started = Instant::now();
+    let stream_type = StreamType::SupportBundle {
+        token: token.clone(),
+        max_bytes,
+    };
+    let (conn, io) = open_bulk_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        &config,
+    )
+    .await?;
+    let connected_in = started.elapsed();
+
+    let mut reader = BufReader::new(io);
+    let mut entries: Vec<(PathBuf, Vec<u8>)> = Vec::new();
+    let mut truncated = Vec::new();
+    let mut received = 0u64;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+        let entry: BundleEntry = match serde_json::from_str(line.trim_end()) {
+            Ok(entry) => entry,
+            // a refusal (e.g. PERMISSION_DENIED) comes as a plain text line
+            Err(_) if entries.is_empty() => bail!("{}", line.trim()),
+            Err(e) => return Err(e).context("Failed to parse support bundle entry"),
+        };
+        received += entry.size;
+        // skipped.txt is sent beyond the limit; anything more is not
+        if received > max_bytes.saturating_add(64 * 1024) {
+            bail!("device sent more than the requested {} bytes", max_bytes);
+        }
+        let mut content = vec![0u8; entry.size as usize];
+        reader
+            .read_exact(&mut content)
+            .await
+            .with_context(|| format!("device closed the stream while sending {}", entry.name))?;
+        let Some(path) = entry_path(&entry.name) else {
+            bail!("device sent an invalid file name '{}'", entry.name);
+        };
+        if entry.truncated {
+            truncated.push(entry.name.clone());
+        }
+        entries.push((path, rescrub(&entry.name, content)));
+    }
+    if entries.is_empty() {
+        return Err(anyhow!("device closed the stream without sending anything"));
+    }
+
+    let stats = conn.stats();
+    let status = match devices::get_device_status(device).await {
+        Ok(status) => serde_json::to_value(status)?,
+        Err(e) => json!({ "error": format!("{:#}", e) }),
+    };
+    let mut diagnostics = json!({
+        "cli_version": env!("CARGO_PKG_VERSION"),
+        "collected_at": chrono::Utc::now().to_rfc3339(),
+        "device": device,
+        "device_id": resolved.id,
+        "server_url": resolved.url,
+        "host": resolved.host,
+        "direct": direct_target().is_some(),
+        "remote_address": conn.remote_address().to_string(),
+        "connect_ms": connected_in.as_millis() as u64,
+        "transfer_ms": started.elapsed().saturating_sub(connected_in).as_millis() as u64,
+        "rtt_ms": conn.rtt().as_millis() as u64,
+        "lost_packets": stats.path.lost_packets,
+        "congestion_events": stats.path.congestion_events,
+        "received_bytes": stats.udp_rx.bytes,
+        "server_device_status": status,
+    });
+    scrub_json(&mut diagnostics);
+    entries.push((
+        PathBuf::from("client/diagnostics.json"),
+        serde_json::to_vec_pretty(&diagnostics)?,
+    ));
+
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    let base = format!("{}-support-{}", device, stamp);
+    let path = output.unwrap_or_else(|| PathBuf::from(format!("{}.tar.gz", base)));
+    let files = entries.len();
+    let bytes = entries.iter().map(|(_, c)| c.len() as u64).sum();
+    let written = path.clone();
+    tokio::task::spawn_blocking(move || write_tarball(&written, &base, &entries))
+        .await?
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(BundleSummary {
+        path,
+        files,
+        bytes,
+        truncated,
+    })
+}
+
+fn write_tarball(path: &Path, base: &str, entries: &[(PathBuf, Vec<u8>)]) -> Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut tar = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mtime = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    for (name, content) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        tar.append_data(&mut header, Path::new(base).join(name), content.as_slice())?;
+    }
+    tar.into_inner()?.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(
+            entry_path("logs/runtime.log"),
+            Some(PathBuf::from("logs/runtime.log"))
+        );
+        assert_eq!(entry_path("../etc/passwd"), None);
+        assert_eq!(entry_path("/etc/passwd"), None);
+        assert_eq!(entry_path(""), None);
+    }
+
+    #[test]
+    fn test_rescrub() {
+        let json = rescrub("config.json", br#"{"owner_token":"abc"}"#.to_vec());
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["owner_token"], "[REDACTED]");
+        let log = rescrub("logs/runtime.log", b"password=x\n".to_vec());
+        assert_eq!(log, b"password=[REDACTED]\n");
+    }
+}
@@ -1,6 +1,6 @@
 use anyhow::Result;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     process::Command,
     sync::OnceLock,
     time::{SystemTime, UNIX_EPOCH},
@@ -100,10 +100,16 @@ pub async fn collect_system_metrics() -> Result<SystemMetrics> {
 
     let mut total_gb = 0;
     let mut used_gb = 0;
+    let mut seen = HashSet::new();
 
     for disk in &disks {
         let t = disk.total_space();
         let a = disk.available_space();
+        // APFS volumes share their container's space, so every volume of
+        // one container reports the same totals; count it once
+        if cfg!(target_os = "macos") && !seen.insert((t, a)) {
+            continue;
+        }
         total_gb += t / (1024 * 1024 * 1024);
         used_gb += (t - a) / (1024 * 1024 * 1024);
     }
@@ -184,6 +190,11 @@ pub async fn collect_system_metrics() -> Result<SystemMetrics> {
         network,
         gpu,
         timestamp: now as u64,
+        location: super::location::current().await,
+        battery: super::battery::collect_battery(),
+        streams: Some(crate::streams::limits::usage()),
+        log_metrics: super::log_metrics::samples(),
+        top_talkers: super::flows::top_talkers(),
     })
 }
 
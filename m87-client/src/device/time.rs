@@ -0,0 +1,66 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    devices, server,
+    streams::{
+        quic::open_quic_io,
+        stream_type::{StreamType, TimeReply},
+    },
+};
+
+/// Clock and NTP state of `device`, after forcing a resync if `sync` is set.
+/// `status.offset_ms` is the device clock minus the local one.
+pub async fn time(device: &str, sync: bool) -> Result<TimeReply> {
+    let request = json!({ "device": device, "sync": sync });
+    server::vcr::call("device_time", request, query_time(device, sync)).await
+}
+
+async fn query_time(device: &str, sync: bool) -> Result<TimeReply> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let stream_type = StreamType::Time {
+        token: token.clone(),
+        sync,
+    };
+    let (_conn, io) = open_quic_io(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        stream_type,
+        config.trust_invalid_server_cert,
+    )
+    .await?;
+
+    let mut lines = BufReader::new(io).lines();
+    let sent = now_ms();
+    let line = lines
+        .next_line()
+        .await?
+        .ok_or_else(|| anyhow!("device closed the stream without reporting its time"))?;
+    let received = now_ms();
+    let mut reply: TimeReply =
+        serde_json::from_str(&line).context("Failed to parse device time")?;
+    if let Some(error) = reply.error {
+        bail!(error);
+    }
+    // the device read its clock about halfway through the round trip; a
+    // forced resync makes this less exact, which is fine for a status line
+    let local = (sent + received) / 2;
+    reply.status.offset_ms = Some(reply.device_time as i64 - local as i64);
+    Ok(reply)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
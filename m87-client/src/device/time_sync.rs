@@ -0,0 +1,127 @@
+//! NTP state of the device and forced resyncs (`m87 <device> time sync`).
+//!
+//! chrony and systemd-timesyncd are supported. The status is reported with
+//! every heartbeat, so it is cached for a few minutes.
+
+use anyhow::{Result, bail};
+use m87_shared::device::TimeStatus;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use tokio::sync::Mutex;
+
+use crate::util::command::{binary_exists, safe_run_command};
+use crate::util::unix::is_root;
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const CHRONY: &str = "chrony";
+pub const TIMESYNCD: &str = "systemd-timesyncd";
+
+static CACHE: Mutex<Option<(Instant, TimeStatus)>> = Mutex::const_new(None);
+
+/// NTP client and sync state, without the offset (measured by the server).
+pub async fn status() -> TimeStatus {
+    let mut cache = CACHE.lock().await;
+    if let Some((at, status)) = cache.as_ref()
+        && at.elapsed() < REFRESH_INTERVAL
+    {
+        return status.clone();
+    }
+    let status = read_status().await;
+    *cache = Some((Instant::now(), status.clone()));
+    status
+}
+
+async fn read_status() -> TimeStatus {
+    if let Some(tracking) = output("chronyc", &["-c", "tracking"]).await {
+        return TimeStatus {
+            ntp_service: Some(CHRONY.to_string()),
+            synchronized: chrony_synchronized(&tracking),
+            offset_ms: None,
+        };
+    }
+    let synchronized = output("timedatectl", &["show", "-p", "NTPSynchronized", "--value"])
+        .await
+        .map(|v| v == "yes");
+    let timesyncd = output("systemctl", &["is-active", TIMESYNCD])
+        .await
+        .is_some_and(|v| v == "active");
+    TimeStatus {
+        ntp_service: timesyncd.then(|| TIMESYNCD.to_string()),
+        synchronized,
+        offset_ms: None,
+    }
+}
+
+/// Leap status, the last field of `chronyc -c tracking`, is "Not synchronised"
+/// while chrony has no usable source.
+fn chrony_synchronized(tracking: &str) -> Option<bool> {
+    let leap = tracking.lines().next()?.rsplit(',').next()?.trim();
+    if leap.is_empty() {
+        return None;
+    }
+    Some(leap != "Not synchronised")
+}
+
+async fn output(program: &str, args: &[&str]) -> Option<String> {
+    if !binary_exists(program) {
+        return None;
+    }
+    let mut cmd = Command::new(program);
+    cmd.args(args).kill_on_drop(true);
+    let out = safe_run_command(cmd, COMMAND_TIMEOUT).await.ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Step the clock now (chrony) or restart timesyncd so it resyncs. Runs
+/// through `sudo -n` when the runtime is not root. Returns the command output.
+pub async fn sync() -> Result<String> {
+    let (program, args): (&str, &[&str]) = if binary_exists("chronyc") {
+        ("chronyc", &["makestep"])
+    } else if binary_exists("timedatectl") {
+        ("systemctl", &["restart", TIMESYNCD])
+    } else {
+        bail!("no supported NTP client found (chrony or systemd-timesyncd)");
+    };
+
+    let mut cmd = if is_root() {
+        Command::new(program)
+    } else {
+        let mut cmd = Command::new("sudo");
+        cmd.arg("-n").arg(program);
+        cmd
+    };
+    cmd.args(args).kill_on_drop(true);
+    let out = safe_run_command(cmd, COMMAND_TIMEOUT).await?;
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&out.stdout),
+        String::from_utf8_lossy(&out.stderr)
+    )
+    .trim()
+    .to_string();
+    if !out.status.success() {
+        bail!("{} {} failed: {}", program, args.join(" "), text);
+    }
+    // report the new state right away
+    *CACHE.lock().await = None;
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chrony_synchronized() {
+        let synced = "A9FEA97B,169.254.169.123,4,1718000000.1,-0.000001,0.000002,0.000010,-2.1,0.001,0.02,0.0005,0.0002,64.4,Normal";
+        assert_eq!(chrony_synchronized(synced), Some(true));
+        let unsynced = "00000000,,0,0.0,0.0,0.0,0.0,0.0,0.0,0.0,1.0,1.0,0.0,Not synchronised";
+        assert_eq!(chrony_synchronized(unsynced), Some(false));
+        assert_eq!(chrony_synchronized(""), None);
+    }
+}
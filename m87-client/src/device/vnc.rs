@@ -0,0 +1,144 @@
+//! `m87 <device> vnc`: expose the device's desktop on a local port for any VNC
+//! viewer. Every viewer connection gets its own stream; the runtime connects
+//! it to a VNC server on the device or starts one (see `streams::vnc`).
+
+use anyhow::Result;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant, sleep};
+use tracing::{debug, info, warn};
+
+use crate::streams::quic::{connect_quic_only, open_quic_stream};
+use crate::streams::stream_type::{StreamClass, StreamType};
+use crate::util::shutdown::SHUTDOWN;
+use crate::{auth::AuthManager, config::Config, devices};
+
+pub async fn open_vnc(
+    device: &str,
+    local_port: u16,
+    remote_port: Option<u16>,
+    open_viewer: bool,
+    max_kbps: Option<u64>,
+) -> Result<()> {
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let listener = TcpListener::bind(("127.0.0.1", local_port)).await?;
+    let addr = listener.local_addr()?;
+    let (_endpoint, conn) = connect_quic_only(
+        &resolved.host,
+        &token,
+        &resolved.short_id,
+        config.trust_invalid_server_cert,
+        StreamClass::Interactive,
+    )
+    .await?;
+
+    let url = format!("vnc://{}", addr);
+    println!("VNC: {} → {}", url, device);
+    if open_viewer && let Err(e) = open_url(&url) {
+        warn!("Failed to open a VNC viewer: {}", e);
+    }
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (local, peer) = accepted?;
+                info!("New VNC viewer connection from {peer}");
+                let stream_type = StreamType::Vnc {
+                    token: token.to_string(),
+                    port: remote_port,
+                };
+                let remote = open_quic_stream(&conn, stream_type).await?;
+
+                tokio::spawn(async move {
+                    let (mut local_read, mut local_write) = tokio::io::split(local);
+                    let (mut remote_read, mut remote_write) = tokio::io::split(remote);
+                    tokio::select! {
+                        r = tokio::io::copy(&mut local_read, &mut remote_write) => {
+                            debug!("VNC viewer {peer} closed: {r:?}");
+                        }
+                        r = copy_shaped(&mut remote_read, &mut local_write, max_kbps) => {
+                            debug!("VNC stream for {peer} closed: {r:?}");
+                        }
+                    }
+                });
+            }
+            reason = conn.closed() => {
+                warn!("Connection closed: {:?}", reason);
+                break;
+            }
+            _ = SHUTDOWN.cancelled() => break,
+        }
+    }
+    Ok(())
+}
+
+/// Copy the framebuffer stream at no more than `max_kbps`. Reading slower
+/// lets QUIC flow control slow the device down, so the whole path is shaped,
+/// not only the last hop.
+async fn copy_shaped<R, W>(r: &mut R, w: &mut W, max_kbps: Option<u64>) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let Some(max_kbps) = max_kbps else {
+        return tokio::io::copy(r, w).await;
+    };
+    let start = Instant::now();
+    let mut total = 0u64;
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        let n = r.read(&mut buf).await?;
+        if n == 0 {
+            w.shutdown().await?;
+            return Ok(total);
+        }
+        w.write_all(&buf[..n]).await?;
+        total += n as u64;
+        let wait = shaping_delay(total, max_kbps, start.elapsed());
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// How long to pause so `sent` bytes over `elapsed` stay within `max_kbps`.
+fn shaping_delay(sent: u64, max_kbps: u64, elapsed: Duration) -> Duration {
+    let bytes_per_sec = (max_kbps * 1000 / 8).max(1);
+    let due = Duration::from_secs_f64(sent as f64 / bytes_per_sec as f64);
+    due.saturating_sub(elapsed)
+}
+
+fn open_url(url: &str) -> std::io::Result<()> {
+    #[cfg(target_os = "macos")]
+    let mut cmd = std::process::Command::new("open");
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = std::process::Command::new("cmd");
+        cmd.args(["/C", "start", ""]);
+        cmd
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut cmd = std::process::Command::new("xdg-open");
+    cmd.arg(url).spawn().map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shaping_delay() {
+        // 80 kbit/s = 10 kB/s
+        assert_eq!(
+            shaping_delay(10_000, 80, Duration::from_millis(250)),
+            Duration::from_millis(750)
+        );
+        assert_eq!(
+            shaping_delay(10_000, 80, Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+}
@@ -1,13 +1,31 @@
-use std::io::{self, Write};
-
-use anyhow::{Result, anyhow};
-use m87_shared::device::{AuditLog, DeviceStatus, PublicDevice};
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use m87_shared::bandwidth::{BandwidthProfile, DeviceBandwidth};
+use m87_shared::deploy_spec::{DeploymentStatusSnapshot, SnapshotQuery};
+use m87_shared::device::{
+    AlertRules, AuditLog, DeviceAssetInfo, DeviceMaintenance, DeviceStatus, DeviceTimelineEvent,
+    PublicDevice, UpdateDeviceBody, validate_device_name,
+};
+use m87_shared::expr::Expr;
+use m87_shared::facts::DeviceFacts;
+use m87_shared::fleet_report::FleetReportRow;
+use m87_shared::inventory::DeviceVulnerabilities;
+use m87_shared::patching::PatchRun;
+use m87_shared::probe::{ProbeRecord, ProbeSpec, ProbeStatus};
 use m87_shared::roles::Role;
 use m87_shared::users::User;
-use tracing::warn;
+use serde::Serialize;
+use tracing::{info, warn};
 
+use crate::device::{deploy, facts, sessions};
+use crate::streams::stream_type::SessionInfo;
 use crate::util::device_cache;
+use crate::util::lan;
 use crate::util::servers_parallel::fanout_servers;
+use crate::util::wait::{WaitOutcome, poll_until};
 use crate::{auth::AuthManager, config::Config, server};
 
 pub async fn list_devices() -> Result<Vec<PublicDevice>> {
@@ -21,14 +39,39 @@ pub async fn list_devices() -> Result<Vec<PublicDevice>> {
     })
     .await?;
 
-    let mut out = Vec::new();
-
-    for (server_url, device) in results {
-        device_cache::update_cache(&device, &server_url)?;
-        out.push(device);
+    // one cache write per server rather than per device
+    let mut by_server: BTreeMap<String, Vec<PublicDevice>> = BTreeMap::new();
+    for (server_url, device) in &results {
+        by_server
+            .entry(server_url.clone())
+            .or_default()
+            .push(device.clone());
+    }
+    for (server_url, devices) in &by_server {
+        device_cache::update_cache_bulk(devices, server_url)?;
     }
 
-    Ok(out)
+    Ok(results.into_iter().map(|(_, device)| device).collect())
+}
+
+/// Inventory report rows of all devices on all servers, by name.
+pub async fn fleet_report() -> Result<Vec<FleetReportRow>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move {
+            let report = server::get_fleet_report(&server_url, &token, trust).await?;
+            Ok(report.devices)
+        }
+    })
+    .await?;
+
+    let mut rows: Vec<FleetReportRow> = results.into_iter().map(|(_, row)| row).collect();
+    rows.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(rows)
 }
 
 pub async fn get_device_by_name(name: &str) -> Result<PublicDevice> {
@@ -46,24 +89,85 @@ pub async fn get_device_by_name(name: &str) -> Result<PublicDevice> {
 }
 
 pub async fn resolve_device_cached(name: &str) -> Result<ResolvedDevice> {
-    // 1) try cache first
+    // 0) direct LAN mode: the device is addressed by IP, no server involved
+    if let Some(target) = lan::direct_target() {
+        return Ok(ResolvedDevice {
+            short_id: name.to_string(),
+            host: target.addr.to_string(),
+            url: String::new(),
+            id: String::new(),
+        });
+    }
+
+    // 1) local aliases take precedence over device names
+    let name = &resolve_alias(name);
+
+    // 2) try cache first
     let cached = device_cache::try_cache(name)?;
 
     if let Some(res) = select_from_cache(name, cached)? {
         return Ok(res);
     }
 
-    // 2) warm cache (parallel fan-out)
+    // 3) warm cache (parallel fan-out)
     list_devices().await?;
 
-    // 3) re-read cache
+    // 4) re-read cache
     let cached = device_cache::try_cache(name)?;
 
-    select_from_cache(name, cached)?.ok_or_else(|| anyhow!("Device '{}' not found", name))
+    if let Some(res) = select_from_cache(name, cached)? {
+        return Ok(res);
+    }
+
+    // 5) no exact match: accept an unambiguous prefix or fuzzy match
+    let candidates = device_cache::fuzzy_candidates(name)?;
+    if let [only] = candidates.as_slice() {
+        info!("Using device '{}' for '{}'", only.name, name);
+    }
+
+    select_from_cache(name, candidates)?.ok_or_else(|| anyhow!("Device '{}' not found", name))
+}
+
+/// Expand a local alias (see `m87 alias`) to the device name it points to.
+pub fn resolve_alias(name: &str) -> String {
+    Config::load()
+        .ok()
+        .and_then(|cfg| cfg.device_aliases.get(name).cloned())
+        .unwrap_or_else(|| name.to_string())
+}
+
+pub fn set_alias(alias: &str, device: &str) -> Result<()> {
+    if alias.is_empty() || device.is_empty() {
+        bail!("Expected NAME=DEVICE");
+    }
+    if alias.starts_with('-') || alias.contains(char::is_whitespace) {
+        bail!("Invalid alias name '{}'", alias);
+    }
+
+    let mut cfg = Config::load()?;
+    cfg.device_aliases
+        .insert(alias.to_string(), device.to_string());
+    cfg.save()
+}
+
+pub fn remove_alias(alias: &str) -> Result<()> {
+    let mut cfg = Config::load()?;
+    if cfg.device_aliases.remove(alias).is_none() {
+        bail!("No alias named '{}'", alias);
+    }
+    cfg.save()
 }
 
 fn prompt_cached_selection(name: &str, devices: Vec<device_cache::CachedDevice>) -> Result<String> {
-    println!("Multiple cached devices named '{}':", name);
+    if !io::stdin().is_terminal() {
+        let names: Vec<String> = devices
+            .iter()
+            .map(|d| format!("{} ({})", d.name, d.short_id))
+            .collect();
+        bail!("Device name '{}' is ambiguous: {}", name, names.join(", "));
+    }
+
+    println!("Multiple devices match '{}':", name);
 
     for (i, d) in devices.iter().enumerate() {
         println!(
@@ -153,6 +257,202 @@ pub async fn get_device_status(name: &str) -> Result<DeviceStatus> {
     Ok(status)
 }
 
+pub async fn get_vulnerabilities(name: &str) -> Result<DeviceVulnerabilities> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::get_device_vulnerabilities(&resolved.url, &token, &resolved.id, trust).await
+}
+
+pub async fn get_bandwidth(name: &str) -> Result<DeviceBandwidth> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::get_device_bandwidth(&resolved.url, &token, &resolved.id, trust).await
+}
+
+pub async fn get_patch_runs(name: &str, limit: u32) -> Result<Vec<PatchRun>> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::get_device_patch_runs(&resolved.url, &token, &resolved.id, limit, trust).await
+}
+
+pub async fn get_probes(name: &str) -> Result<Vec<ProbeStatus>> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::get_device_probes(&resolved.url, &token, &resolved.id, trust).await
+}
+
+/// Add `probe` to the device, replacing a probe of the same name.
+pub async fn add_probe(name: &str, probe: ProbeSpec) -> Result<Vec<ProbeStatus>> {
+    probe.validate().map_err(|e| anyhow!(e))?;
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let mut probes: Vec<ProbeSpec> =
+        server::get_device_probes(&resolved.url, &token, &resolved.id, trust)
+            .await?
+            .into_iter()
+            .map(|status| status.probe)
+            .collect();
+    match probes.iter_mut().find(|p| p.name == probe.name) {
+        Some(existing) => *existing = probe,
+        None => probes.push(probe),
+    }
+    server::set_device_probes(&resolved.url, &token, &resolved.id, probes, trust).await
+}
+
+pub async fn remove_probe(name: &str, probe: &str) -> Result<Vec<ProbeStatus>> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let current = server::get_device_probes(&resolved.url, &token, &resolved.id, trust).await?;
+    if !current.iter().any(|status| status.probe.name == probe) {
+        bail!("{} has no probe named '{}'", name, probe);
+    }
+    let probes = current
+        .into_iter()
+        .map(|status| status.probe)
+        .filter(|p| p.name != probe)
+        .collect();
+    server::set_device_probes(&resolved.url, &token, &resolved.id, probes, trust).await
+}
+
+pub async fn get_probe_results(
+    name: &str,
+    probe: Option<&str>,
+    limit: u32,
+) -> Result<Vec<ProbeRecord>> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::get_device_probe_results(&resolved.url, &token, &resolved.id, probe, limit, trust).await
+}
+
+/// Turn maintenance mode on or off. Devices in maintenance get no patch
+/// policies with their next heartbeat.
+pub async fn set_maintenance(name: &str, enabled: bool, reason: Option<String>) -> Result<()> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::update_device(
+        &resolved.url,
+        &token,
+        &resolved.id,
+        UpdateDeviceBody {
+            maintenance: Some(DeviceMaintenance {
+                enabled,
+                reason,
+                since: None,
+            }),
+            ..Default::default()
+        },
+        trust,
+    )
+    .await
+}
+
+/// Switch the device's bandwidth profile. The runtime applies it with the
+/// config of its next heartbeat.
+pub async fn set_bandwidth_profile(name: &str, profile: BandwidthProfile) -> Result<()> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::update_device(
+        &resolved.url,
+        &token,
+        &resolved.id,
+        UpdateDeviceBody {
+            bandwidth_profile: Some(profile),
+            ..Default::default()
+        },
+        trust,
+    )
+    .await
+}
+
+/// Switch the bandwidth profile of every device whose asset info matches
+/// `filters`. Returns the names of the switched devices.
+pub async fn set_bandwidth_profile_matching(
+    filters: &[(String, String)],
+    profile: BandwidthProfile,
+) -> Result<Vec<String>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    // names may repeat across servers, so update through the listing server
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move { server::list_devices(&server_url, &token, trust).await }
+    })
+    .await?;
+
+    let mut switched = Vec::new();
+    for (server_url, device) in results {
+        if !matches_info_filters(&device, filters) {
+            continue;
+        }
+        let body = UpdateDeviceBody {
+            bandwidth_profile: Some(profile),
+            ..Default::default()
+        };
+        server::update_device(&server_url, &token, &device.id, body, trust).await?;
+        switched.push(device.name);
+    }
+    Ok(switched)
+}
+
+/// Only owners may change this; the server enforces it.
+pub async fn set_require_approval(name: &str, enabled: bool) -> Result<()> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::update_device(
+        &resolved.url,
+        &token,
+        &resolved.id,
+        UpdateDeviceBody {
+            require_approval: Some(enabled),
+            ..Default::default()
+        },
+        trust,
+    )
+    .await
+}
+
 pub async fn get_audit_logs(
     name: &str,
     until: Option<String>,
@@ -179,6 +479,32 @@ pub async fn get_audit_logs(
     Ok(logs)
 }
 
+/// The device's events timeline between `since` and `until` (RFC3339), at
+/// most the `max` newest events.
+pub async fn get_device_events(
+    name: &str,
+    since: Option<String>,
+    until: Option<String>,
+    max: u32,
+) -> Result<Vec<DeviceTimelineEvent>> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::get_device_events(
+        &resolved.url,
+        &token,
+        trust,
+        &resolved.id,
+        max,
+        since,
+        until,
+    )
+    .await
+}
+
 pub async fn get_device_users(name: &str) -> Result<Vec<User>> {
     let resolved = resolve_device_cached(name).await?;
 
@@ -243,3 +569,418 @@ pub async fn update_access(name: &str, email_or_org_id: &str, role: Role) -> Res
 
     Ok(())
 }
+
+/// Block until the device holds a control tunnel to its server, or until `timeout` elapses.
+pub async fn wait_for_online(name: &str, timeout: Duration) -> Result<WaitOutcome> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    poll_until(timeout, Duration::from_secs(5), || async {
+        let devices = server::list_devices(&resolved.url, &token, trust).await?;
+        let device = devices
+            .into_iter()
+            .find(|d| d.id == resolved.id)
+            .ok_or_else(|| anyhow!("Device '{}' not found", name))?;
+        Ok(device.online.then_some(WaitOutcome::Reached))
+    })
+    .await
+}
+
+/// Asset info (location, contact, custom fields, ...) stored on the server for a device.
+pub async fn get_device_info(name: &str) -> Result<DeviceAssetInfo> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let device = server::get_device(&resolved.url, &token, trust, &resolved.id).await?;
+    Ok(device.info)
+}
+
+/// Everything `m87 <device> info` shows about a device.
+#[derive(Serialize)]
+pub struct DeviceOverview {
+    pub device: PublicDevice,
+    pub status: DeviceStatus,
+    /// Active deployment without the steps of its runs
+    pub deployment: Option<DeploymentStatusSnapshot>,
+    /// Newest events, oldest first
+    pub events: Vec<DeviceTimelineEvent>,
+    /// Collected from the device; `None` while it is offline or refuses
+    pub facts: Option<DeviceFacts>,
+    pub sessions: Option<Vec<SessionInfo>>,
+}
+
+/// Events [`get_device_overview`] includes
+pub const OVERVIEW_EVENTS: u32 = 5;
+
+/// Gather the device, its status, active deployment and newest events from
+/// the server and, while it is online, its facts and open sessions from the
+/// device itself. Only the device itself is required; the other parts are
+/// left empty when they cannot be fetched.
+pub async fn get_device_overview(name: &str) -> Result<DeviceOverview> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+    let device = server::get_device(&resolved.url, &token, trust, &resolved.id).await?;
+
+    let deployment = async {
+        let Some(id) = deploy::get_active_deployment_id(name).await? else {
+            return Ok(None);
+        };
+        let query = SnapshotQuery {
+            no_steps: true,
+            ..Default::default()
+        };
+        deploy::get_deployment_snapshot_part(name, &id, &query)
+            .await
+            .map(Some)
+    };
+    let on_device = async {
+        if !device.online {
+            return (None, None);
+        }
+        let (facts, sessions) = tokio::join!(
+            facts::fetch_facts(name, false),
+            sessions::sessions(name, None)
+        );
+        (facts.ok(), sessions.ok())
+    };
+    let (status, deployment, events, (facts, sessions)) = tokio::join!(
+        server::get_device_status(&resolved.url, &token, &resolved.id, trust),
+        deployment,
+        get_device_events(name, None, None, OVERVIEW_EVENTS),
+        on_device,
+    );
+
+    Ok(DeviceOverview {
+        status: status.unwrap_or_else(|e| {
+            warn!("Failed to fetch device status: {}", e);
+            DeviceStatus::default()
+        }),
+        deployment: deployment.unwrap_or_else(|e: anyhow::Error| {
+            warn!("Failed to fetch the active deployment: {}", e);
+            None
+        }),
+        events: events.unwrap_or_else(|e| {
+            warn!("Failed to fetch device events: {}", e);
+            Vec::new()
+        }),
+        facts,
+        sessions,
+        device,
+    })
+}
+
+/// Set (`key=value`) and remove (`unset`) asset info fields. Returns the stored info.
+pub async fn update_device_info(
+    name: &str,
+    set: &[String],
+    unset: &[String],
+) -> Result<DeviceAssetInfo> {
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let mut info = server::get_device(&resolved.url, &token, trust, &resolved.id)
+        .await?
+        .info;
+    for key in unset {
+        info.set(key, None);
+    }
+    for (key, value) in parse_key_values(set)? {
+        info.set(&key, Some(value));
+    }
+
+    server::update_device(
+        &resolved.url,
+        &token,
+        &resolved.id,
+        UpdateDeviceBody {
+            info: Some(info.clone()),
+            ..Default::default()
+        },
+        trust,
+    )
+    .await?;
+    Ok(info)
+}
+
+/// Rename a device. The server enforces unique names per owner; deployments
+/// and reports are keyed by device id and carry over unchanged.
+pub async fn rename_device(name: &str, new_name: &str) -> Result<()> {
+    validate_device_name(new_name).map_err(|e| anyhow!(e))?;
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let mut config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    server::update_device(
+        &resolved.url,
+        &token,
+        &resolved.id,
+        UpdateDeviceBody {
+            name: Some(new_name.to_string()),
+            ..Default::default()
+        },
+        trust,
+    )
+    .await?;
+
+    let old_name = device_cache::try_get_name_from_long_id(&resolved.id).ok();
+    device_cache::invalidate(&resolved.id)?;
+
+    // keep aliases pointing at the device
+    let mut changed = false;
+    for target in config.device_aliases.values_mut() {
+        if Some(&*target) == old_name.as_ref() {
+            *target = new_name.to_string();
+            changed = true;
+        }
+    }
+    if changed {
+        config.save()?;
+    }
+    Ok(())
+}
+
+/// Merge `duplicate`, e.g. a ghost left behind by an identity change, into
+/// `device`. The server moves the duplicate's history over and deletes it.
+pub async fn merge_devices(duplicate: &str, device: &str) -> Result<PublicDevice> {
+    let from = resolve_device_cached(duplicate).await?;
+    let into = resolve_device_cached(device).await?;
+    if from.id == into.id {
+        bail!("'{}' and '{}' are the same device", duplicate, device);
+    }
+    if from.url != into.url {
+        bail!("Devices on different servers cannot be merged");
+    }
+
+    let token = AuthManager::get_cli_token().await?;
+    let trust = Config::load()?.trust_invalid_server_cert;
+    let merged = server::merge_device(&into.url, &token, &into.id, &from.id, trust).await?;
+
+    device_cache::invalidate(&from.id)?;
+    device_cache::invalidate(&into.id)?;
+    Ok(merged)
+}
+
+/// Alert rules the server evaluates for a device. With `clear` all rules are
+/// removed before `low_battery_percent` is applied; `None` leaves it unchanged.
+pub async fn update_alert_rules(
+    name: &str,
+    low_battery_percent: Option<u8>,
+    conditions: &[(String, String)],
+    remove: &[String],
+    clear: bool,
+) -> Result<AlertRules> {
+    for (rule, source) in conditions {
+        Expr::parse(source).map_err(|e| anyhow!("invalid condition '{}': {}", rule, e))?;
+    }
+    let resolved = resolve_device_cached(name).await?;
+
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let mut rules = server::get_device(&resolved.url, &token, trust, &resolved.id)
+        .await?
+        .alert_rules;
+    if !clear && low_battery_percent.is_none() && conditions.is_empty() && remove.is_empty() {
+        return Ok(rules);
+    }
+    if clear {
+        rules = AlertRules::default();
+    }
+    if low_battery_percent.is_some() {
+        rules.low_battery_percent = low_battery_percent;
+    }
+    for rule in remove {
+        if rules.conditions.remove(rule).is_none() {
+            bail!("no alert condition named '{}'", rule);
+        }
+    }
+    rules.conditions.extend(conditions.iter().cloned());
+
+    server::update_device(
+        &resolved.url,
+        &token,
+        &resolved.id,
+        UpdateDeviceBody {
+            alert_rules: Some(rules.clone()),
+            ..Default::default()
+        },
+        trust,
+    )
+    .await?;
+    Ok(rules)
+}
+
+/// Parse `key=value` arguments (asset info updates and listing filters).
+pub fn parse_key_values(args: &[String]) -> Result<Vec<(String, String)>> {
+    args.iter()
+        .map(|arg| {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid '{}', expected key=value", arg))?;
+            let key = key.trim();
+            if key.is_empty() {
+                return Err(anyhow!("invalid '{}': empty key", arg));
+            }
+            Ok((key.to_string(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// True if every filter matches the device's asset info (case-insensitive substring).
+pub fn matches_info_filters(device: &PublicDevice, filters: &[(String, String)]) -> bool {
+    info_matches(&device.info, filters)
+}
+
+pub fn info_matches(info: &DeviceAssetInfo, filters: &[(String, String)]) -> bool {
+    filters.iter().all(|(key, value)| {
+        info.get(key)
+            .is_some_and(|v| v.to_lowercase().contains(&value.to_lowercase()))
+    })
+}
+
+/// Output of device listings.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default)]
+pub enum ListFormat {
+    #[default]
+    Table,
+    Json,
+    /// FeatureCollection of devices with a reported location, for map dashboards
+    Geojson,
+}
+
+/// Exports of the device listing.
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    /// Inventory report with health, last contact and active revision, for spreadsheets
+    Csv,
+    /// The same report as JSON
+    Json,
+}
+
+/// GeoJSON FeatureCollection with one point per device that reported a location.
+pub fn to_geojson(devices: &[PublicDevice]) -> serde_json::Value {
+    let features: Vec<serde_json::Value> = devices
+        .iter()
+        .filter_map(|d| {
+            let location = d.last_location.as_ref()?;
+            let mut properties = serde_json::Map::new();
+            properties.extend(
+                d.info
+                    .entries()
+                    .into_iter()
+                    .map(|(k, v)| (k.into(), v.into())),
+            );
+            let fixed = serde_json::json!({
+                "id": d.id,
+                "name": d.name,
+                "short_id": d.short_id,
+                "online": d.online,
+                "version": d.version,
+                "timestamp": location.timestamp,
+                "accuracy_m": location.accuracy_m,
+                "speed_mps": location.speed_mps,
+                "heading_deg": location.heading_deg,
+            });
+            // device fields win over custom asset info keys of the same name
+            if let serde_json::Value::Object(fixed) = fixed {
+                properties.extend(fixed);
+            }
+            Some(serde_json::json!({
+                "type": "Feature",
+                "id": d.id,
+                "geometry": location.to_geojson_geometry(),
+                "properties": properties,
+            }))
+        })
+        .collect();
+    serde_json::json!({ "type": "FeatureCollection", "features": features })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_values() {
+        let parsed = parse_key_values(&["location=Berlin, Hall 3".to_string()]).unwrap();
+        assert_eq!(
+            parsed,
+            vec![("location".to_string(), "Berlin, Hall 3".to_string())]
+        );
+        assert!(parse_key_values(&["location".to_string()]).is_err());
+        assert!(parse_key_values(&["=x".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_asset_info_set_and_filter() {
+        let mut info = DeviceAssetInfo::default();
+        info.set("location", Some("Berlin Hall 3".to_string()));
+        info.set("rack", Some("R12".to_string()));
+        assert_eq!(
+            info.entries(),
+            vec![("location", "Berlin Hall 3"), ("rack", "R12")]
+        );
+
+        info.set("rack", None);
+        assert!(info.custom_fields.is_empty());
+
+        let filters = vec![("location".to_string(), "berlin".to_string())];
+        let mut device: PublicDevice = serde_json::from_value(serde_json::json!({
+            "id": "1", "name": "edge-1", "short_id": "abc123", "updated_at": "", "created_at": "",
+            "online": true, "version": "", "target_version": "",
+            "system_info": { "hostname": "", "username": "", "public_ip_address": null,
+                             "operating_system": "", "cpu_name": "" }
+        }))
+        .unwrap();
+        assert!(!matches_info_filters(&device, &filters));
+        device.info = info;
+        assert!(matches_info_filters(&device, &filters));
+    }
+
+    #[test]
+    fn test_to_geojson() {
+        let mut device: PublicDevice = serde_json::from_value(serde_json::json!({
+            "id": "1", "name": "robot-1", "short_id": "abc123", "updated_at": "", "created_at": "",
+            "online": true, "version": "", "target_version": "",
+            "system_info": { "hostname": "", "username": "", "public_ip_address": null,
+                             "operating_system": "", "cpu_name": "" },
+            "info": { "location": "Yard" }
+        }))
+        .unwrap();
+        let unlocated = device.clone();
+        device.last_location = Some(m87_shared::metrics::Location {
+            latitude: 52.5,
+            longitude: 13.4,
+            altitude_m: None,
+            accuracy_m: Some(3.0),
+            speed_mps: None,
+            heading_deg: None,
+            timestamp: 1_700_000_000_000,
+        });
+
+        let geojson = to_geojson(&[device, unlocated]);
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 1);
+        assert_eq!(
+            features[0]["geometry"]["coordinates"],
+            serde_json::json!([13.4, 52.5])
+        );
+        assert_eq!(features[0]["properties"]["name"], "robot-1");
+        assert_eq!(features[0]["properties"]["location"], "Yard");
+    }
+}
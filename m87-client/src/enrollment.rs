@@ -0,0 +1,58 @@
+//! `m87 install-command`: one line that installs the runtime on a new
+//! device and registers it without approval.
+
+use anyhow::{Result, anyhow, bail};
+use m87_shared::enrollment::{CreateInstallCommandBody, InstallCommand};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    server,
+    util::servers_parallel::{find_on_servers, is_forbidden, is_not_found},
+};
+
+/// New install command for devices of `org_id`, the default organization
+/// unless `personal`, or the caller's own devices. Servers are asked one at
+/// a time, so only the first that may enroll into the org issues a token.
+pub async fn install_command(
+    org_id: Option<String>,
+    personal: bool,
+    ttl_secs: Option<u64>,
+    max_uses: Option<u32>,
+) -> Result<InstallCommand> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let body = CreateInstallCommandBody {
+        org_id: match personal {
+            true => None,
+            false => org_id.or(config.organization_id),
+        },
+        ttl_secs,
+        max_uses,
+    };
+    body.validate().map_err(|e| anyhow!(e))?;
+
+    let found = find_on_servers(config.manager_server_urls, 1, |server_url| {
+        let token = token.clone();
+        let body = &body;
+        async move {
+            match server::create_install_command(&server_url, &token, trust, body).await {
+                Ok(command) => Ok(Some(command)),
+                Err(e) if is_not_found(&e) || is_forbidden(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await?;
+
+    match (found, &body.org_id) {
+        (Some((_, command)), _) => Ok(command),
+        (None, Some(org_id)) => bail!(
+            "no server lets you enroll devices into {}; editors of the organization can",
+            org_id
+        ),
+        (None, None) => bail!("no server issued an install command"),
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::{Result, bail};
+use m87_shared::incident::{Incident, IncidentState, UpdateIncidentBody};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    server,
+    util::servers_parallel::{fanout_servers, find_on_servers, is_not_found},
+};
+
+/// Incidents on all servers, newest first.
+pub async fn list(state: Option<IncidentState>, all: bool, limit: u32) -> Result<Vec<Incident>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move { server::list_incidents(&server_url, &token, trust, state, all, limit).await }
+    })
+    .await?;
+
+    let mut incidents: Vec<Incident> = results.into_iter().map(|(_, i)| i).collect();
+    // RFC 3339 timestamps of one format sort by time
+    incidents.sort_by(|a, b| b.opened_at.cmp(&a.opened_at));
+    incidents.truncate(limit as usize);
+    Ok(incidents)
+}
+
+/// Incident `id` from whichever server holds it.
+pub async fn get(id: &str) -> Result<Incident> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let found = find_on_servers(config.manager_server_urls, 4, |server_url| {
+        let token = token.clone();
+        async move {
+            match server::get_incident(&server_url, &token, trust, id).await {
+                Ok(incident) => Ok(Some(incident)),
+                // ids are unique across servers, the others do not know it
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await?;
+
+    match found {
+        Some((_, incident)) => Ok(incident),
+        None => bail!("no incident {}", id),
+    }
+}
+
+/// Acknowledge or resolve incident `id` on whichever server holds it.
+pub async fn update(id: &str, state: IncidentState, note: Option<String>) -> Result<Incident> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+    let body = UpdateIncidentBody { state, note };
+
+    let found = find_on_servers(config.manager_server_urls, 4, |server_url| {
+        let token = token.clone();
+        let body = body.clone();
+        async move {
+            match server::update_incident(&server_url, &token, trust, id, &body).await {
+                Ok(incident) => Ok(Some(incident)),
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await?;
+
+    match found {
+        Some((_, incident)) => Ok(incident),
+        None => bail!("no incident {}", id),
+    }
+}
@@ -0,0 +1,270 @@
+//! `m87 init`: first-run setup. Logs in, picks the default organization,
+//! optionally registers this machine as a device and installs shell
+//! completion, then points at the commands to try next.
+
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+
+use anyhow::{Result, anyhow, bail};
+use clap_complete::Shell;
+
+use crate::{
+    auth::{self, AuthManager},
+    config::Config,
+    org,
+    tui::helper::{bold, cyan, dim, green, role_badge},
+};
+
+/// Run the wizard. `cmd` is the CLI definition completions are generated from.
+pub async fn run(mut cmd: clap::Command) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        bail!("m87 init asks questions; run it in a terminal");
+    }
+
+    heading("Log in");
+    login().await?;
+
+    heading("Organization");
+    let org_id = choose_org().await?;
+
+    #[cfg(feature = "runtime")]
+    let registered = {
+        heading("This machine");
+        register_machine(org_id.as_deref()).await?
+    };
+    #[cfg(not(feature = "runtime"))]
+    let registered = false;
+
+    heading("Shell completion");
+    install_completion(&mut cmd)?;
+
+    print_next_steps(org_id.as_deref(), registered);
+    Ok(())
+}
+
+fn heading(title: &str) {
+    println!("\n{}", bold(title));
+}
+
+/// Answer to `question`, trimmed; empty when the user just hits Enter.
+fn prompt(question: &str) -> Result<String> {
+    print!("{}: ", question);
+    io::stdout().flush()?;
+    let mut input = String::new();
+    if io::stdin().read_line(&mut input)? == 0 {
+        bail!("setup aborted");
+    }
+    Ok(input.trim().to_string())
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    let answer = prompt(&format!("{} [{}]", question, hint))?;
+    Ok(match answer.to_lowercase().as_str() {
+        "" => default,
+        answer => answer.starts_with('y'),
+    })
+}
+
+async fn login() -> Result<()> {
+    if AuthManager::has_cli_credentials()? {
+        println!("{}", green("Already logged in"));
+        return Ok(());
+    }
+    println!("Devices are managed on make87, or on a self-hosted server.");
+    let server = prompt("Self-hosted server URL (Enter for make87)")?;
+    if server.is_empty() {
+        auth::login_cli().await?;
+    } else {
+        let email = prompt("Email")?;
+        if email.is_empty() {
+            bail!("logging in to {} needs the email of a local user", server);
+        }
+        auth::login_password(&server, &email).await?;
+    }
+    if !AuthManager::has_cli_credentials()? {
+        bail!("login did not complete; run m87 init again");
+    }
+    println!("{}", green("Logged in"));
+    Ok(())
+}
+
+/// Accept pending invites, then pick or create the default organization.
+/// `None` when the user keeps working without one.
+async fn choose_org() -> Result<Option<String>> {
+    let invites = org::list_invites().await.unwrap_or_default();
+    for invite in &invites {
+        let question = format!(
+            "You were invited to organization {}. Join?",
+            bold(&invite.org_id)
+        );
+        if confirm(&question, true)? {
+            org::handle_invite(&invite.id, true).await?;
+        }
+    }
+
+    let mut orgs = org::list_organizations().await?;
+    orgs.sort_by(|a, b| a.id.cmp(&b.id));
+    let current = Config::load()?.organization_id;
+
+    if orgs.is_empty() {
+        println!("You are in no organization yet; devices can also be yours alone.");
+    }
+    for (i, o) in orgs.iter().enumerate() {
+        let default = match current.as_deref() == Some(o.id.as_str()) {
+            true => dim(" (default)"),
+            false => String::new(),
+        };
+        println!("  {}. {} {}{}", i + 1, o.id, role_badge(&o.role), default);
+    }
+    println!("  n. create an organization (server admins only)");
+    let keep = match current {
+        Some(_) => "keep the default",
+        None => "continue without one",
+    };
+    let choice = prompt(&format!("Organization (Enter to {})", keep))?;
+
+    let org_id = match choice.as_str() {
+        "" => return Ok(current),
+        "n" | "N" => {
+            let id = prompt("Organization id")?;
+            if id.is_empty() {
+                bail!("an organization needs an id");
+            }
+            let owner_email = match invites.first() {
+                Some(invite) => invite.email.clone(),
+                None => prompt("Owner email")?,
+            };
+            org::create_organization(&id, &owner_email).await?;
+            println!("{} {}", green("Created organization"), id);
+            id
+        }
+        choice => {
+            let index = choice
+                .parse::<usize>()
+                .ok()
+                .filter(|i| (1..=orgs.len()).contains(i))
+                .ok_or_else(|| anyhow!("no organization {}", choice))?;
+            orgs[index - 1].id.clone()
+        }
+    };
+
+    // accepting an invite stores the config too, so load it again
+    let mut config = Config::load()?;
+    config.organization_id = Some(org_id.clone());
+    config.save()?;
+    Ok(Some(org_id))
+}
+
+/// Offer to run the runtime on this machine, owned by `org_id` when given.
+/// Returns whether the machine is a device.
+#[cfg(feature = "runtime")]
+async fn register_machine(org_id: Option<&str>) -> Result<bool> {
+    if AuthManager::has_device_credentials()? {
+        println!(
+            "{}",
+            green("This machine is registered as a device already")
+        );
+        return Ok(true);
+    }
+    if !confirm("Register this machine as a device?", false)? {
+        return Ok(false);
+    }
+    if let Some(org_id) = org_id {
+        let mut config = Config::load()?;
+        config.owner_reference = Some(org_id.to_string());
+        config.save()?;
+    }
+    // before start, which may re-exec under sudo
+    if let Some(code) = crate::device::recovery::ensure_code()? {
+        crate::tui::local::print_recovery_code(&code);
+    }
+    crate::runtime::start().await?;
+    println!(
+        "The runtime started and asked to join. Approve it with {}",
+        cyan("m87 devices approve <name>")
+    );
+    Ok(true)
+}
+
+/// Where a shell loads completions from without further setup, with the
+/// line to add to its startup file when it does not.
+fn completion_path(shell: Shell) -> Option<(PathBuf, Option<&'static str>)> {
+    let home = dirs::home_dir()?;
+    match shell {
+        Shell::Bash => Some((
+            home.join(".local/share/bash-completion/completions/m87"),
+            None,
+        )),
+        Shell::Zsh => Some((
+            home.join(".zfunc/_m87"),
+            Some("fpath=(~/.zfunc $fpath); autoload -Uz compinit; compinit"),
+        )),
+        Shell::Fish => Some((home.join(".config/fish/completions/m87.fish"), None)),
+        _ => None,
+    }
+}
+
+fn install_completion(cmd: &mut clap::Command) -> Result<()> {
+    let Some(shell) = Shell::from_env() else {
+        println!(
+            "Could not tell your shell; print a completion script with {}",
+            cyan("m87 completion <shell>")
+        );
+        return Ok(());
+    };
+    let Some((path, startup_line)) = completion_path(shell) else {
+        println!(
+            "Load completion from your {} profile with {}",
+            shell,
+            cyan(&format!("m87 completion {}", shell))
+        );
+        return Ok(());
+    };
+    if !confirm(&format!("Install {} completion?", shell), true)? {
+        return Ok(());
+    }
+
+    let mut script = Vec::new();
+    clap_complete::generate(shell, cmd, "m87", &mut script);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, script)?;
+    println!("{} {}", green("Wrote"), path.display());
+    if let Some(line) = startup_line {
+        println!(
+            "Add this to your {} startup file if it is not there:",
+            shell
+        );
+        println!("  {}", line);
+    }
+    println!("{}", dim("Completion works in new shells"));
+    Ok(())
+}
+
+fn print_next_steps(org_id: Option<&str>, registered: bool) {
+    heading("Next steps");
+    let mut steps = vec![("m87 devices list", "devices you can reach")];
+    if org_id.is_some() {
+        steps.push(("m87 org devices list", "devices of your organization"));
+    }
+    if registered {
+        steps.push(("m87 devices approve <name>", "let this machine join"));
+    }
+    steps.extend([
+        (
+            "m87 <device> info",
+            "system, health and deployment of a device",
+        ),
+        ("m87 <device> shell", "a shell on the device"),
+        (
+            "m87 <device> deploy <file>",
+            "run a compose file or deployment",
+        ),
+        ("m87 --help", "everything else"),
+    ]);
+    for (command, what) in steps {
+        println!("  {} {}", cyan(&format!("{:<28}", command)), dim(what));
+    }
+}
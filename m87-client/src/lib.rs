@@ -1,7 +1,18 @@
+pub mod admin;
+pub mod access;
+pub mod approvals;
 pub mod auth;
+#[cfg(feature = "runtime")]
+pub mod bench;
+pub mod ci;
 pub mod config;
 pub mod device;
 pub mod devices;
+pub mod enrollment;
+pub mod incidents;
+pub mod init;
+pub mod notices;
+pub mod rollouts;
 
 // Runtime module (Linux-only via build.rs)
 #[cfg(feature = "runtime")]
@@ -10,6 +21,10 @@ pub mod runtime;
 pub mod streams;
 
 pub mod server;
+#[cfg(feature = "runtime")]
+pub mod service;
+#[cfg(feature = "runtime")]
+pub mod sim;
 pub mod update;
 pub mod util;
 
@@ -0,0 +1,217 @@
+//! What changed and what is going away, shown on stderr once per CLI version:
+//! the release notes on the first run of a new version, and the notices
+//! servers push (see [`CliNotice`]), e.g. that an API is being sunset.
+
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use futures::future::join_all;
+use m87_shared::notices::{CliNotice, NoticeKind};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthManager,
+    config::Config,
+    server,
+    tui::helper::{cyan, dim, yellow},
+    update,
+};
+
+/// Opts out of release notes and notices, e.g. in scripts run in a terminal
+pub const NO_NOTICES_ENV: &str = "M87_NO_NOTICES";
+
+/// How often servers are asked for notices
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+/// Longest a command waits for release notes or notices at its end
+const FETCH_TIMEOUT: Duration = Duration::from_secs(3);
+
+const CLI_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What this user was shown, in `notices.json` next to the config.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NoticeState {
+    /// CLI version the rest belongs to
+    version: String,
+    /// Ids of the notices shown to this version
+    #[serde(default)]
+    shown: Vec<String>,
+    /// When servers were last asked, seconds since the epoch
+    #[serde(default)]
+    checked_at: u64,
+}
+
+/// Show what this user has not seen yet. Failures are only logged; nothing
+/// is shown outside of terminals or when replaying recorded calls.
+pub async fn show() {
+    if !std::io::stderr().is_terminal()
+        || std::env::var_os(NO_NOTICES_ENV).is_some()
+        || std::env::var_os(server::vcr::RECORD_ENV).is_some()
+        || server::vcr::replaying()
+    {
+        return;
+    }
+    if let Err(e) = show_pending().await {
+        tracing::debug!("Could not show notices: {:?}", e);
+    }
+}
+
+async fn show_pending() -> Result<()> {
+    let mut state = match load_state() {
+        Some(state) if state.version == CLI_VERSION => state,
+        previous => {
+            // a first install has no notes to catch up on
+            if previous.is_some() {
+                let notes = update::show_release_notes(CLI_VERSION);
+                if let Ok(Err(e)) = tokio::time::timeout(FETCH_TIMEOUT, notes).await {
+                    tracing::debug!("No release notes for v{}: {:?}", CLI_VERSION, e);
+                }
+            }
+            NoticeState {
+                version: CLI_VERSION.to_string(),
+                ..Default::default()
+            }
+        }
+    };
+
+    let now = unix_now();
+    if now.saturating_sub(state.checked_at) >= CHECK_INTERVAL.as_secs()
+        && AuthManager::has_cli_credentials().unwrap_or(false)
+    {
+        state.checked_at = now;
+        let notices = tokio::time::timeout(FETCH_TIMEOUT, fetch_notices())
+            .await
+            .unwrap_or_default();
+        for notice in unseen(&notices, &state, CLI_VERSION) {
+            print_notice(notice);
+            state.shown.push(notice.id.clone());
+        }
+    }
+    save_state(&state)
+}
+
+/// Record that `version`'s release notes were shown, e.g. by `m87 update`,
+/// so its first run does not show them again.
+pub fn mark_notes_shown(version: &str) {
+    let state = NoticeState {
+        version: version.to_string(),
+        ..Default::default()
+    };
+    if let Err(e) = save_state(&state) {
+        tracing::debug!("Could not save notice state: {:?}", e);
+    }
+}
+
+/// Notices of all servers; servers that fail or predate notices have none.
+async fn fetch_notices() -> Vec<CliNotice> {
+    let (Ok(token), Ok(config)) = (AuthManager::get_cli_token().await, Config::load()) else {
+        return Vec::new();
+    };
+    let trust = config.trust_invalid_server_cert;
+    let fetches = config
+        .manager_server_urls
+        .iter()
+        .map(|url| server::list_notices(url, &token, trust));
+    join_all(fetches)
+        .await
+        .into_iter()
+        .flat_map(Result::unwrap_or_default)
+        .collect()
+}
+
+/// Notices for `version` not shown to it yet, each id once.
+fn unseen<'a>(notices: &'a [CliNotice], state: &NoticeState, version: &str) -> Vec<&'a CliNotice> {
+    let mut out: Vec<&CliNotice> = Vec::new();
+    for notice in notices {
+        if notice.applies_to(version)
+            && !state.shown.contains(&notice.id)
+            && !out.iter().any(|n| n.id == notice.id)
+        {
+            out.push(notice);
+        }
+    }
+    out
+}
+
+fn print_notice(notice: &CliNotice) {
+    let label = match notice.kind {
+        NoticeKind::Deprecation => yellow("Deprecated:"),
+        NoticeKind::Info => cyan("Notice:"),
+    };
+    let sunset = match &notice.sunset {
+        Some(date) => format!(" (until {})", date),
+        None => String::new(),
+    };
+    eprintln!("\n{} {}{}", label, notice.message, sunset);
+    if let Some(fixed_in) = &notice.fixed_in {
+        eprintln!(
+            "{}",
+            dim(&format!(
+                "Fixed in v{}: run m87 update",
+                fixed_in.trim_start_matches('v')
+            ))
+        );
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn state_path() -> Result<PathBuf> {
+    Ok(Config::m87_config_dir()?.join("notices.json"))
+}
+
+fn load_state() -> Option<NoticeState> {
+    let contents = std::fs::read_to_string(state_path().ok()?).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn save_state(state: &NoticeState) -> Result<()> {
+    let path = state_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+    std::fs::write(&path, serde_json::to_vec_pretty(state)?).context("Failed to write notice state")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notice(id: &str, fixed_in: Option<&str>) -> CliNotice {
+        CliNotice {
+            id: id.to_string(),
+            kind: NoticeKind::Deprecation,
+            message: "API v1 goes away".to_string(),
+            sunset: None,
+            fixed_in: fixed_in.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_unseen_skips_shown_fixed_and_duplicate_notices() {
+        let notices = vec![
+            notice("shown", None),
+            notice("fixed", Some("1.0.0")),
+            notice("new", None),
+            // the same notice from a second server
+            notice("new", None),
+        ];
+        let state = NoticeState {
+            version: "1.0.0".to_string(),
+            shown: vec!["shown".to_string()],
+            checked_at: 0,
+        };
+        let ids: Vec<&str> = unseen(&notices, &state, "1.0.0")
+            .iter()
+            .map(|n| n.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["new"]);
+    }
+}
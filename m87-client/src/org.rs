@@ -1,10 +1,18 @@
 use std::collections::HashSet;
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
 use m87_shared::{
     device::PublicDevice,
-    org::{Invite, Organization},
+    inventory::DeviceVulnerabilities,
+    iot_bridge::{IotBridgeTarget, OrgIotBridge, UpdateOrgIotBridgeBody},
+    org::{
+        Invite, OrgIncidentWebhook, OrgLimitsStatus, Organization, UpdateOrgIncidentWebhookBody,
+        UpdateOrgLimitsBody, UpdateOrgRedactionBody,
+    },
+    patching::{CreatePatchPolicyBody, PublicPatchPolicy},
+    redact::check_pattern,
     roles::Role,
+    usage::{OrgUsageRecord, USAGE_EXPORT_VERSION, UsageQuery, merge_usage},
     users::User,
 };
 
@@ -234,6 +242,354 @@ pub async fn list_devices(org_id: Option<String>) -> Result<Vec<PublicDevice>> {
     Ok(out)
 }
 
+/// Advisory scan results of the organization's devices on all servers.
+pub async fn vulnerabilities(org_id: Option<String>) -> Result<Vec<DeviceVulnerabilities>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        async move { server::list_org_vulnerabilities(&server_url, &token, trust, &org_id).await }
+    })
+    .await?;
+
+    Ok(results.into_iter().map(|(_, device)| device).collect())
+}
+
+/// Patch policies of the organization on all servers.
+pub async fn patch_policies(org_id: Option<String>) -> Result<Vec<PublicPatchPolicy>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        async move { server::list_patch_policies(&server_url, &token, trust, &org_id).await }
+    })
+    .await?;
+
+    Ok(results.into_iter().map(|(_, policy)| policy).collect())
+}
+
+/// Create the policy on every server, so devices on each of them apply it.
+pub async fn create_patch_policy(
+    org_id: Option<String>,
+    body: CreatePatchPolicyBody,
+) -> Result<Vec<PublicPatchPolicy>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    let results = fanout_servers(config.manager_server_urls, 4, true, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        let body = body.clone();
+        async move {
+            let policy =
+                server::create_patch_policy(&server_url, &token, trust, &org_id, &body).await?;
+            Ok(vec![policy])
+        }
+    })
+    .await?;
+
+    Ok(results.into_iter().map(|(_, policy)| policy).collect())
+}
+
+/// Delete the policies named `name_or_id` (or with that id) on all servers;
+/// returns how many were deleted.
+pub async fn delete_patch_policy(org_id: Option<String>, name_or_id: &str) -> Result<usize> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        async move {
+            let mut deleted = Vec::new();
+            for policy in server::list_patch_policies(&server_url, &token, trust, &org_id).await? {
+                let policy = policy.policy;
+                if policy.id == name_or_id || policy.name == name_or_id {
+                    server::delete_patch_policy(&server_url, &token, trust, &org_id, &policy.id)
+                        .await?;
+                    deleted.push(policy.id);
+                }
+            }
+            Ok(deleted)
+        }
+    })
+    .await?;
+
+    if results.is_empty() {
+        bail!("no patch policy named '{}'", name_or_id);
+    }
+    Ok(results.len())
+}
+
+/// Limits and usage of the caller's orgs on every server, as (server, status);
+/// only `org_id` if given.
+pub async fn limits(org_id: Option<String>) -> Result<Vec<(String, OrgLimitsStatus)>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let mut results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move { server::list_limits(&server_url, &token, trust).await }
+    })
+    .await?;
+
+    if let Some(org_id) = org_id {
+        results.retain(|(_, status)| status.org_id == org_id);
+    }
+    results.sort_by(|a, b| (&a.1.org_id, &a.0).cmp(&(&b.1.org_id, &b.0)));
+    Ok(results)
+}
+
+/// Override the org's limits on every server; needs an instance admin.
+pub async fn set_limits(
+    org_id: Option<String>,
+    body: UpdateOrgLimitsBody,
+) -> Result<Vec<(String, OrgLimitsStatus)>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    fanout_servers(config.manager_server_urls, 4, true, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        let body = body.clone();
+        async move {
+            let status = server::set_org_limits(&server_url, &token, trust, &org_id, &body).await?;
+            Ok(vec![status])
+        }
+    })
+    .await
+}
+
+/// Custom redaction patterns of the org, from all servers.
+pub async fn redaction(org_id: Option<String>) -> Result<Vec<String>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        async move {
+            let redaction = server::get_org_redaction(&server_url, &token, trust, &org_id).await?;
+            Ok(redaction.patterns)
+        }
+    })
+    .await?;
+
+    let mut patterns: Vec<String> = results.into_iter().map(|(_, p)| p).collect();
+    patterns.sort();
+    patterns.dedup();
+    Ok(patterns)
+}
+
+/// Replace the org's redaction patterns on every server.
+pub async fn set_redaction(org_id: Option<String>, patterns: Vec<String>) -> Result<Vec<String>> {
+    for pattern in &patterns {
+        check_pattern(pattern).map_err(|e| anyhow!("invalid pattern '{}': {}", pattern, e))?;
+    }
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+    let body = UpdateOrgRedactionBody { patterns };
+
+    let results = fanout_servers(config.manager_server_urls, 4, true, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        let body = body.clone();
+        async move {
+            let redaction =
+                server::set_org_redaction(&server_url, &token, trust, &org_id, &body).await?;
+            Ok(vec![redaction])
+        }
+    })
+    .await?;
+    Ok(results
+        .into_iter()
+        .next()
+        .map(|(_, r)| r.patterns)
+        .unwrap_or(body.patterns))
+}
+
+/// Incident webhook of the org, from the first server that has one.
+pub async fn incident_webhook(org_id: Option<String>) -> Result<OrgIncidentWebhook> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        async move {
+            let hook =
+                server::get_org_incident_webhook(&server_url, &token, trust, &org_id).await?;
+            Ok(vec![hook])
+        }
+    })
+    .await?;
+
+    Ok(results
+        .into_iter()
+        .map(|(_, hook)| hook)
+        .find(|hook| hook.url.is_some())
+        .unwrap_or(OrgIncidentWebhook {
+            org_id,
+            ..Default::default()
+        }))
+}
+
+/// Replace the org's incident webhook on every server; no `url` removes it.
+pub async fn set_incident_webhook(
+    org_id: Option<String>,
+    url: Option<String>,
+    secret: Option<String>,
+) -> Result<OrgIncidentWebhook> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+    let body = UpdateOrgIncidentWebhookBody { url, secret };
+
+    let results = fanout_servers(config.manager_server_urls, 4, true, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        let body = body.clone();
+        async move {
+            let hook = server::set_org_incident_webhook(&server_url, &token, trust, &org_id, &body)
+                .await?;
+            Ok(vec![hook])
+        }
+    })
+    .await?;
+    Ok(results
+        .into_iter()
+        .next()
+        .map(|(_, hook)| hook)
+        .unwrap_or(OrgIncidentWebhook {
+            org_id,
+            ..Default::default()
+        }))
+}
+
+/// IoT bridge of the org, from the first server that has one.
+pub async fn iot_bridge(org_id: Option<String>) -> Result<OrgIotBridge> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        async move {
+            let bridge = server::get_org_iot_bridge(&server_url, &token, trust, &org_id).await?;
+            Ok(vec![bridge])
+        }
+    })
+    .await?;
+
+    Ok(results
+        .into_iter()
+        .map(|(_, bridge)| bridge)
+        .find(|bridge| bridge.platform.is_some())
+        .unwrap_or(OrgIotBridge {
+            org_id,
+            ..Default::default()
+        }))
+}
+
+/// Replace the org's IoT bridge on every server; no `target` removes it.
+pub async fn set_iot_bridge(
+    org_id: Option<String>,
+    target: Option<IotBridgeTarget>,
+    telemetry_interval_secs: Option<u64>,
+) -> Result<OrgIotBridge> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let org_id = get_or_resolve_default_org_id(org_id).await?;
+    let body = UpdateOrgIotBridgeBody {
+        target,
+        telemetry_interval_secs,
+    };
+
+    let results = fanout_servers(config.manager_server_urls, 4, true, |server_url| {
+        let token = token.clone();
+        let org_id = org_id.clone();
+        let body = body.clone();
+        async move {
+            let bridge =
+                server::set_org_iot_bridge(&server_url, &token, trust, &org_id, &body).await?;
+            Ok(vec![bridge])
+        }
+    })
+    .await?;
+    Ok(results
+        .into_iter()
+        .next()
+        .map(|(_, bridge)| bridge)
+        .unwrap_or(OrgIotBridge {
+            org_id,
+            ..Default::default()
+        }))
+}
+
+/// Monthly usage of the orgs the caller administers, added up over all
+/// servers.
+pub async fn usage(query: UsageQuery) -> Result<Vec<OrgUsageRecord>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        let query = query.clone();
+        async move {
+            let export = server::get_usage(&server_url, &token, trust, &query).await?;
+            if export.version != USAGE_EXPORT_VERSION {
+                bail!(
+                    "{} exports usage in version {}, this client reads version {}",
+                    server_url,
+                    export.version,
+                    USAGE_EXPORT_VERSION
+                );
+            }
+            Ok(export.records)
+        }
+    })
+    .await?;
+
+    Ok(merge_usage(results.into_iter().map(|(_, record)| record)))
+}
+
 pub async fn add_device(org_id: Option<String>, device_name: &str) -> Result<()> {
     let token = AuthManager::get_cli_token().await?;
     let config = Config::load()?;
@@ -0,0 +1,219 @@
+//! `m87 deploy`: staged rollouts of a deployment to many devices at once.
+
+use std::path::Path;
+
+use anyhow::{Result, anyhow, bail};
+use m87_shared::deploy_spec::DeploymentRevision;
+use m87_shared::device::PublicDevice;
+use m87_shared::rollout::{
+    CreateRolloutBody, Rollout, RolloutAction, RolloutPolicy, UpdateRolloutBody,
+};
+
+use crate::{
+    auth::AuthManager,
+    ci::CiContext,
+    config::Config,
+    device::deploy::{SpecFile, SpecType, load_spec_file},
+    devices::matches_info_filters,
+    server,
+    util::servers_parallel::{fanout_servers, find_on_servers, is_not_found},
+};
+
+/// Which devices a rollout covers: named ones in the given order, then
+/// those whose asset info matches all filters, by name.
+pub struct RolloutTargets {
+    pub devices: Vec<String>,
+    pub filters: Vec<(String, String)>,
+}
+
+/// Policy settings given on the command line. The others come from the
+/// `rollout` of the deployment file, then the defaults.
+#[derive(Debug, Default)]
+pub struct PolicyOverrides {
+    pub waves: Option<Vec<u32>>,
+    pub failure_threshold_percent: Option<u32>,
+    pub soak_secs: Option<u64>,
+    pub deploy_timeout_secs: Option<u64>,
+    pub no_rollback: bool,
+}
+
+impl PolicyOverrides {
+    /// `None` when nothing is overridden, so the server reads the file's.
+    fn apply(&self, base: Option<RolloutPolicy>) -> Option<RolloutPolicy> {
+        if self.waves.is_none()
+            && self.failure_threshold_percent.is_none()
+            && self.soak_secs.is_none()
+            && self.deploy_timeout_secs.is_none()
+            && !self.no_rollback
+        {
+            return None;
+        }
+        let mut policy = base.unwrap_or_default();
+        if let Some(waves) = &self.waves {
+            policy.waves = waves.clone();
+        }
+        if let Some(threshold) = self.failure_threshold_percent {
+            policy.failure_threshold_percent = threshold;
+        }
+        if let Some(soak) = self.soak_secs {
+            policy.soak_secs = soak;
+        }
+        if let Some(timeout) = self.deploy_timeout_secs {
+            policy.deploy_timeout_secs = timeout;
+        }
+        if self.no_rollback {
+            policy.auto_rollback = false;
+        }
+        Some(policy)
+    }
+}
+
+/// Start rolling `file` out to the targets. A run spec or compose file
+/// becomes a deployment of that run alone.
+pub async fn start(
+    file: &Path,
+    spec_type: SpecType,
+    name: Option<&str>,
+    targets: &RolloutTargets,
+    overrides: &PolicyOverrides,
+) -> Result<Rollout> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let mut revision = match load_spec_file(file, spec_type, name).await? {
+        SpecFile::Deployment(rev) => *rev,
+        SpecFile::Job(job) => DeploymentRevision::new(vec![*job], None),
+    };
+    revision = revision.clone_with_new_id();
+    let provenance = CiContext::from_env().provenance();
+    if !provenance.is_empty() {
+        revision.provenance = Some(provenance);
+    }
+
+    let listed = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move { server::list_devices(&server_url, &token, trust).await }
+    })
+    .await?;
+    let (server_url, device_ids) = select_targets(listed, targets)?;
+
+    let policy = overrides.apply(revision.rollout.clone());
+    if let Some(policy) = &policy {
+        policy.validate().map_err(|e| anyhow!(e))?;
+    }
+    let body = CreateRolloutBody {
+        revision: revision.to_yaml()?,
+        device_ids,
+        policy,
+    };
+    server::create_rollout(&server_url, &token, trust, &body).await
+}
+
+/// Server and ids of the targeted devices, which must all be on one server.
+fn select_targets(
+    listed: Vec<(String, PublicDevice)>,
+    targets: &RolloutTargets,
+) -> Result<(String, Vec<String>)> {
+    let mut selected: Vec<&(String, PublicDevice)> = Vec::new();
+    for name in &targets.devices {
+        let found = listed
+            .iter()
+            .find(|(_, d)| &d.name == name || &d.short_id == name || &d.id == name);
+        match found {
+            Some(entry) => selected.push(entry),
+            None => bail!("Device '{}' not found", name),
+        }
+    }
+    if !targets.filters.is_empty() {
+        let mut matching: Vec<&(String, PublicDevice)> = listed
+            .iter()
+            .filter(|(_, d)| matches_info_filters(d, &targets.filters))
+            .collect();
+        matching.sort_by(|a, b| a.1.name.cmp(&b.1.name));
+        selected.extend(matching);
+    }
+    let Some((server_url, _)) = selected.first() else {
+        bail!("no devices to roll out to; name them or pass --filter key=value");
+    };
+    if selected.iter().any(|(url, _)| url != server_url) {
+        bail!("the devices are on several servers; start one rollout per server");
+    }
+
+    let mut ids: Vec<String> = Vec::with_capacity(selected.len());
+    for (_, device) in &selected {
+        if !ids.contains(&device.id) {
+            ids.push(device.id.clone());
+        }
+    }
+    Ok((server_url.clone(), ids))
+}
+
+/// Rollouts on all servers, newest first.
+pub async fn list(all: bool, limit: u32) -> Result<Vec<Rollout>> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let results = fanout_servers(config.manager_server_urls, 4, false, |server_url| {
+        let token = token.clone();
+        async move { server::list_rollouts(&server_url, &token, trust, all, limit).await }
+    })
+    .await?;
+
+    let mut rollouts: Vec<Rollout> = results.into_iter().map(|(_, r)| r).collect();
+    // RFC 3339 timestamps of one format sort by time
+    rollouts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    rollouts.truncate(limit as usize);
+    Ok(rollouts)
+}
+
+/// Rollout `id` from whichever server holds it.
+pub async fn get(id: &str) -> Result<Rollout> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+
+    let found = find_on_servers(config.manager_server_urls, 4, |server_url| {
+        let token = token.clone();
+        async move {
+            match server::get_rollout(&server_url, &token, trust, id).await {
+                Ok(rollout) => Ok(Some(rollout)),
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await?;
+
+    match found {
+        Some((_, rollout)) => Ok(rollout),
+        None => bail!("no rollout {}", id),
+    }
+}
+
+/// Halt, resume, roll back or cancel rollout `id` on whichever server holds it.
+pub async fn update(id: &str, action: RolloutAction, note: Option<String>) -> Result<Rollout> {
+    let token = AuthManager::get_cli_token().await?;
+    let config = Config::load()?;
+    let trust = config.trust_invalid_server_cert;
+    let body = UpdateRolloutBody { action, note };
+
+    let found = find_on_servers(config.manager_server_urls, 4, |server_url| {
+        let token = token.clone();
+        let body = body.clone();
+        async move {
+            match server::update_rollout(&server_url, &token, trust, id, &body).await {
+                Ok(rollout) => Ok(Some(rollout)),
+                Err(e) if is_not_found(&e) => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await?;
+
+    match found {
+        Some((_, rollout)) => Ok(rollout),
+        None => bail!("no rollout {}", id),
+    }
+}
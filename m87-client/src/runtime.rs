@@ -1,11 +1,9 @@
 use anyhow::{Context, Result, bail};
-use std::fs::{self, File, OpenOptions, Permissions};
-use std::io::Write;
-use std::os::unix::fs::PermissionsExt;
+use std::fs::{self, File, OpenOptions};
+use std::net::SocketAddr;
 use std::os::unix::io::AsRawFd;
-use std::path::{Path, PathBuf};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tempfile::NamedTempFile;
 use tokio::{
     signal::unix::{SignalKind, signal},
     time::{Duration, sleep},
@@ -13,105 +11,34 @@ use tokio::{
 use tracing::{error, info, warn};
 
 use crate::config::Config;
+use crate::device::container;
 use crate::device::control_tunnel;
+use crate::device::core_dumps;
 use crate::device::deployment_manager::DeploymentManager;
+use crate::device::egress;
+use crate::device::firewall_manager;
+use crate::device::flows;
+use crate::device::gc;
+use crate::device::health;
+use crate::device::lan;
+use crate::device::patching;
+use crate::device::probes;
+use crate::service::{ServiceManager, ServiceSpec, service_name};
+use crate::update;
 use crate::util::command::current_exe_path;
 use crate::util::shutdown::SHUTDOWN;
 use crate::util::system_info::get_system_info;
-use crate::util::unix::{
-    is_root, reexec_with_sudo, run_systemctl, run_systemctl_checked, validate_exec_path,
-};
+use crate::util::unix::{is_root, reexec_with_sudo, validate_exec_path};
 use crate::{auth::register_device, util::tls::set_tls_provider};
 
-const SERVICE_NAME: &str = "m87-runtime";
-const SERVICE_FILE: &str = "/etc/systemd/system/m87-runtime.service";
-const SERVICE_FILE_MODE: u32 = 0o644;
-
-/// Generate the systemd service file content with all XDG environment variables
-fn generate_service_content(exe_path: &Path, username: &str, home: &Path) -> String {
-    let home = home.display();
-
-    format!(
-        r#"[Unit]
-Description=m87 Runtime Service
-After=network-online.target
-Wants=network-online.target
-
-[Service]
-Type=simple
-ExecStart={exe_path} runtime run
-WorkingDirectory={home}
-Restart=on-failure
-RestartSec=3
-User={username}
-
-# Deterministic environment for user's config/data directories
-Environment=HOME={home}
-Environment=XDG_CONFIG_HOME={home}/.config
-Environment=XDG_DATA_HOME={home}/.local/share
-Environment=XDG_CACHE_HOME={home}/.cache
-Environment=RUST_LOG=info
-
-# Security hardening
-UMask=0077
-
-# Logging
-StandardOutput=journal
-StandardError=journal
-SyslogIdentifier=m87-runtime
-
-# Resource limits
-TimeoutStopSec=30
-StartLimitBurst=5
-StartLimitIntervalSec=30
-
-[Install]
-WantedBy=multi-user.target
-"#,
-        exe_path = exe_path.display(),
-        username = username,
-        home = home,
-    )
-}
-
-/// Write service file atomically using tempfile
-/// Returns Ok(true) if file was changed, Ok(false) if no change needed
-fn write_service_file_atomic(content: &str) -> Result<bool> {
-    let service_path = Path::new(SERVICE_FILE);
-
-    // Check if content differs from existing
-    if service_path.exists() {
-        let existing =
-            fs::read_to_string(service_path).context("Failed to read existing service file")?;
-        if existing == content {
-            return Ok(false); // No change needed
-        }
+/// The service commands drive the host's init system, which containers do not run.
+fn service_manager() -> Result<ServiceManager> {
+    if container::in_container() {
+        bail!(
+            "Running in a container: use 'm87 runtime run --foreground' as the entrypoint and manage it with the container engine (see 'm87 runtime manifest')"
+        );
     }
-
-    // Create temp file in same directory for atomic rename
-    let dir = service_path
-        .parent()
-        .unwrap_or(Path::new("/etc/systemd/system"));
-    let mut tmp = NamedTempFile::new_in(dir).context("Failed to create temporary service file")?;
-
-    tmp.write_all(content.as_bytes())
-        .context("Failed to write service content")?;
-
-    tmp.as_file()
-        .sync_all()
-        .context("Failed to sync service file to disk")?;
-
-    // Persist atomically (rename)
-    let tmp_path = tmp.into_temp_path();
-    tmp_path
-        .persist(service_path)
-        .context("Failed to persist service file")?;
-
-    // Set permissions explicitly
-    fs::set_permissions(service_path, Permissions::from_mode(SERVICE_FILE_MODE))
-        .context("Failed to set service file permissions")?;
-
-    Ok(true)
+    ServiceManager::detect()
 }
 
 /// Internal function called by hidden subcommand after sudo re-exec
@@ -134,32 +61,24 @@ pub async fn internal_setup_privileged(
     // Validate exe path
     validate_exec_path(&exe_path)?;
 
-    // Generate service content
-    let content = generate_service_content(&exe_path, username, &home_dir);
-
-    // Write atomically
-    let file_changed = write_service_file_atomic(&content)?;
-
-    if file_changed {
-        info!("Service file updated at {}", SERVICE_FILE);
+    let manager = service_manager()?;
+    let spec = ServiceSpec::new(exe_path, username, home_dir);
 
-        // Reload systemd daemon
-        run_systemctl_checked(&["daemon-reload"])?;
+    if manager.install(&spec)? {
+        info!(
+            "{} service file updated at {}",
+            manager.name(),
+            manager.service_file(&spec.name).display()
+        );
     }
 
     // Handle enable/start based on flags
-    if enable_now {
+    if enable_now || enable {
         // enable --now: enable at boot AND start immediately
-        run_systemctl_checked(&["enable", "--now", SERVICE_NAME])?;
-        info!("Enabled and started m87-runtime service");
-    } else if enable {
-        // enable without --now: just enable at boot
-        run_systemctl_checked(&["enable", SERVICE_NAME])?;
-        info!("Enabled m87-runtime service (starts on boot)");
+        manager.enable(&spec.name, enable_now)?;
     } else if restart_if_running {
         // Match systemd behavior: restart if running, start if stopped
-        run_systemctl_checked(&["restart", SERVICE_NAME])?;
-        info!("Restarted m87-runtime service");
+        manager.restart(&spec.name)?;
     }
 
     Ok(())
@@ -171,9 +90,7 @@ pub async fn internal_stop_privileged() -> Result<()> {
         bail!("internal_stop_privileged must be run as root");
     }
 
-    run_systemctl_checked(&["stop", SERVICE_NAME])?;
-    info!("Stopped m87-runtime service");
-    Ok(())
+    service_manager()?.stop(&service_name())
 }
 
 /// Internal function to disable the service (must be run as root)
@@ -182,18 +99,24 @@ pub async fn internal_disable_privileged(now: bool) -> Result<()> {
         bail!("internal_disable_privileged must be run as root");
     }
 
-    if now {
-        run_systemctl_checked(&["disable", "--now", SERVICE_NAME])?;
-        info!("Disabled and stopped m87-runtime service");
-    } else {
-        run_systemctl_checked(&["disable", SERVICE_NAME])?;
-        info!("Disabled m87-runtime service");
+    service_manager()?.disable(&service_name(), now)
+}
+
+/// Internal function to remove the service (must be run as root)
+pub async fn internal_uninstall_privileged() -> Result<()> {
+    if !is_root() {
+        bail!("internal_uninstall_privileged must be run as root");
     }
+
+    let manager = service_manager()?;
+    manager.uninstall(&service_name())?;
+    info!("Removed {} service", service_name());
     Ok(())
 }
 
 /// Unified setup function that handles all installation scenarios
 async fn setup_service(enable: bool, enable_now: bool, restart_if_running: bool) -> Result<()> {
+    service_manager()?;
     // Resolve user info from passwd database
     let user_info =
         crate::util::unix::resolve_invoking_user().context("Failed to determine user identity")?;
@@ -274,6 +197,7 @@ pub async fn restart() -> Result<()> {
 /// CLI: m87 runtime stop
 /// Stops the runtime service
 pub async fn stop() -> Result<()> {
+    service_manager()?;
     if is_root() {
         internal_stop_privileged().await
     } else {
@@ -284,6 +208,7 @@ pub async fn stop() -> Result<()> {
 /// CLI: m87 runtime disable [--now]
 /// Disables auto-start on boot
 pub async fn disable(now: bool) -> Result<()> {
+    service_manager()?;
     if is_root() {
         internal_disable_privileged(now).await
     } else {
@@ -295,17 +220,23 @@ pub async fn disable(now: bool) -> Result<()> {
     }
 }
 
+/// CLI: m87 runtime uninstall
+/// Stops the runtime service and removes it
+pub async fn uninstall() -> Result<()> {
+    service_manager()?;
+    if is_root() {
+        internal_uninstall_privileged().await
+    } else {
+        reexec_with_sudo(&["internal", "runtime-uninstall-privileged"])
+    }
+}
+
 /// CLI: m87 runtime status
 /// Shows service status (no sudo required for viewing status)
 pub async fn status() -> Result<()> {
-    let status = run_systemctl(&["status", "--lines=0", SERVICE_NAME])?;
-
-    // Exit code 3 means service not running, which is valid for status
-    // Exit code 4 means service unknown/not installed
-    if let Some(code) = status.code() {
-        if code == 4 {
-            warn!("Service not installed. Run 'm87 runtime enable --now' to install and start.");
-        }
+    let manager = service_manager()?;
+    if !manager.status(&service_name())? {
+        warn!("Service not installed. Run 'm87 runtime enable --now' to install and start.");
     }
 
     Ok(())
@@ -314,10 +245,7 @@ pub async fn status() -> Result<()> {
 /// Acquire an exclusive lock to prevent multiple runtime instances.
 /// Returns the lock file handle which must be kept alive to hold the lock.
 fn acquire_runtime_lock() -> Result<File> {
-    let lock_path = dirs::data_dir()
-        .ok_or_else(|| anyhow::anyhow!("Could not determine data directory"))?
-        .join("m87")
-        .join("runtime.lock");
+    let lock_path = Config::data_dir()?.join("runtime.lock");
 
     // Ensure parent directory exists
     if let Some(parent) = lock_path.parent() {
@@ -343,13 +271,23 @@ fn acquire_runtime_lock() -> Result<File> {
 }
 
 /// CLI: m87 runtime run
-/// Main runtime daemon entry point (used by systemd service)
-pub async fn run() -> Result<()> {
+/// Main runtime daemon entry point (used by systemd service and containers).
+/// Serves the health endpoints on `health_addr` when set.
+pub async fn run(health_addr: Option<SocketAddr>) -> Result<()> {
     // Acquire lock first - exits if another instance is running
     // The lock is held for the lifetime of this function (until process exits)
     let _lock = acquire_runtime_lock()?;
 
     info!("Running device");
+    container::log_environment();
+
+    if let Some(addr) = health_addr {
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(addr).await {
+                error!("{:#}", e);
+            }
+        });
+    }
 
     // Handle both SIGTERM (systemd stop) and SIGINT (Ctrl+C)
     let mut sigterm =
@@ -377,6 +315,9 @@ async fn login_and_run() -> Result<()> {
     set_tls_provider();
 
     let config = Config::load()?;
+    // arm the revert watchdog before anything that could keep the tunnel down
+    update::slot::check_on_startup(Duration::from_secs(config.upgrade_confirm_timeout_secs));
+
     let system_info = get_system_info().await?;
     loop {
         let success = register_device(config.owner_reference.clone(), system_info.clone()).await;
@@ -389,6 +330,22 @@ async fn login_and_run() -> Result<()> {
     let unit_manager = DeploymentManager::new().await?;
     let manager = Arc::new(unit_manager);
     manager.clone().start();
+    gc::spawn();
+    core_dumps::spawn();
+    firewall_manager::spawn();
+    egress::spawn();
+    flows::spawn();
+    patching::spawn();
+    probes::spawn();
+
+    tokio::spawn({
+        let manager = manager.clone();
+        async move {
+            if let Err(e) = lan::run_lan_services(manager).await {
+                warn!("LAN services stopped: {:?}", e);
+            }
+        }
+    });
 
     loop {
         if SHUTDOWN.is_cancelled() {
@@ -0,0 +1,1790 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use m87_shared::access::{AccessGrant, CreateAccessGrantBody};
+use m87_shared::approval::{ApprovalRequest, ApprovalStatus, DecideApprovalBody};
+use m87_shared::bandwidth::DeviceBandwidth;
+use m87_shared::db_stats::CollectionStats;
+use m87_shared::deploy_spec::{
+    CoreDump, CreateDeployRevisionBody, DeployReport, DeploymentRevision, DeploymentStatusSnapshot,
+    SnapshotQuery, StepArtifacts, UpdateDeployRevisionBody,
+};
+use m87_shared::device::{AuditLog, DeviceStatus, DeviceTimelineEvent, UpdateDeviceBody};
+use m87_shared::enrollment::{CreateInstallCommandBody, InstallCommand};
+use m87_shared::fleet_report::FleetReport;
+use m87_shared::incident::{Incident, IncidentState, UpdateIncidentBody};
+use m87_shared::inventory::DeviceVulnerabilities;
+use m87_shared::iot_bridge::{OrgIotBridge, UpdateOrgIotBridgeBody};
+use m87_shared::notices::CliNotice;
+use m87_shared::org::{
+    AcceptRejectBody, AddDeviceBody, CreateOrganizationBody, Invite, InviteMemberBody,
+    OrgIncidentWebhook, OrgLimitsStatus, OrgRedaction, Organization, UpdateOrgIncidentWebhookBody,
+    UpdateOrgLimitsBody, UpdateOrgRedactionBody, UpdateOrganizationBody,
+};
+use m87_shared::patching::{CreatePatchPolicyBody, PatchRun, PublicPatchPolicy};
+use m87_shared::probe::{ProbeRecord, ProbeSpec, ProbeStatus};
+use m87_shared::protocol::{PROTOCOL_HEADER, PROTOCOL_VERSION};
+use m87_shared::relay::RelayStats;
+use m87_shared::roles::Role;
+use m87_shared::rollout::{CreateRolloutBody, Rollout, UpdateRolloutBody};
+use m87_shared::usage::{UsageExport, UsageQuery};
+use m87_shared::users::User;
+use make87_sdk::Make87Client;
+use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde_json::json;
+
+use tracing::error;
+
+use crate::util::proxy;
+
+pub mod vcr;
+
+// Import shared types
+pub use m87_shared::auth::{
+    AuthRequestAction, CheckAuthRequest, DeviceAuthRequest, DeviceAuthRequestBody,
+    DeviceAuthRequestCheckResponse, LocalLoginBody, LocalLoginResponse,
+};
+pub use m87_shared::device::PublicDevice;
+pub use m87_shared::heartbeat::{HeartbeatRequest, HeartbeatResponse};
+
+pub async fn get_server_url_and_owner_reference(
+    make87_api_url: &str,
+    make87_app_url: &str,
+    owner_reference: Option<String>,
+    server_url: Option<String>,
+) -> Result<(String, String)> {
+    // if owner ref and server url are some return them right away
+    if let (Some(owner_ref), Some(server)) = (&owner_reference, &server_url) {
+        return Ok((server.clone(), owner_ref.clone()));
+    }
+
+    let client = proxy::client()?;
+
+    let post_url = format!("{}/v1/device/login", make87_api_url);
+
+    #[derive(serde::Serialize)]
+    struct EmptyBody {
+        owner_reference: Option<String>,
+        server_url: Option<String>,
+    }
+
+    let id: String = client
+        .post(&post_url)
+        .json(&EmptyBody {
+            owner_reference: owner_reference.clone(),
+            server_url,
+        })
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|e| {
+            error!("{:?}", e);
+            e
+        })?
+        .json()
+        .await?;
+
+    if owner_reference.is_none() {
+        // we only need the user to interact if we are missing a assigned owner. If we know the owner server can be aut oassigned
+        let browser_url = format!("{}/devices/login/{}", make87_app_url, id);
+        tracing::error!("No server configured.");
+        tracing::error!("Open this link in your browser to log in:");
+        tracing::error!("{}", browser_url);
+        tracing::error!("Waiting for authentication...");
+    }
+
+    let get_url = format!("{}/v1/device/login/{}", make87_api_url, id);
+
+    #[derive(serde::Deserialize)]
+    struct LoginUrlResponse {
+        url: Option<String>,
+        owner_reference: Option<String>,
+    }
+
+    let mut wait_time = 0;
+
+    loop {
+        let resp = client
+            .get(&get_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<LoginUrlResponse>()
+            .await?;
+
+        if let (Some(url), Some(owner_reference)) = (resp.url, resp.owner_reference) {
+            return Ok((url, owner_reference));
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        wait_time += 2;
+        if wait_time >= 120 {
+            tracing::error!("Timeout waiting 120s for authentication");
+            return Err(anyhow::anyhow!("Timeout waiting for authentication"));
+        }
+    }
+}
+
+pub async fn get_manager_server_urls(make87_api_url: &str, token: &str) -> Result<Vec<String>> {
+    let client = proxy::client()?;
+
+    let get_url = format!("{}/v1/server", make87_api_url);
+    // get will return all server objects.. get url form each json object
+
+    #[derive(serde::Deserialize)]
+    struct Server {
+        url: String,
+    }
+
+    let response = client
+        .get(&get_url)
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Vec<Server>>()
+        .await?;
+
+    let manager_urls = response.into_iter().map(|s| s.url).collect::<Vec<String>>();
+
+    Ok(manager_urls)
+}
+
+// Runtime-specific: Used by device registration
+#[cfg(feature = "runtime")]
+pub async fn set_auth_request(
+    api_url: &str,
+    body: DeviceAuthRequestBody,
+    trust_invalid_server_cert: bool,
+) -> Result<String> {
+    vcr::call(
+        "set_auth_request",
+        json!({ "api_url": api_url, "body": body }),
+        async {
+            let url = format!("{}/auth/request", api_url);
+            let client = get_client(trust_invalid_server_cert)?;
+
+            let res = client.post(&url).json(&body).send().await?;
+            match res.error_for_status() {
+                Ok(r) => {
+                    // returns a string with device id on success
+                    let device_id: String = r.json().await?;
+                    Ok(device_id)
+                }
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+// Runtime-specific: Used by device registration
+#[cfg(feature = "runtime")]
+pub async fn check_auth_request(
+    api_url: &str,
+    request_id: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<DeviceAuthRequestCheckResponse> {
+    vcr::call(
+        "check_auth_request",
+        json!({ "api_url": api_url, "request_id": request_id }),
+        async {
+            let url = format!("{}/auth/request/check", api_url);
+            let client = get_client(trust_invalid_server_cert)?;
+
+            let res = client
+                .post(&url)
+                .json(&CheckAuthRequest {
+                    request_id: request_id.to_string(),
+                })
+                .send()
+                .await?;
+            match res.error_for_status() {
+                Ok(r) => {
+                    // returns a string with device id on success
+                    let response: DeviceAuthRequestCheckResponse = r.json().await?;
+                    Ok(response)
+                }
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+// m87 command line: Password login against a server in local auth mode.
+// Not recorded by the vcr, the body carries the password.
+pub async fn local_login(
+    api_url: &str,
+    body: &LocalLoginBody,
+    trust_invalid_server_cert: bool,
+) -> Result<LocalLoginResponse> {
+    let url = format!("{}/auth/login", api_url);
+    let client = get_client(trust_invalid_server_cert)?;
+
+    let res = client.post(&url).json(body).send().await?;
+    match res.status() {
+        reqwest::StatusCode::UNAUTHORIZED => Err(anyhow!("Invalid email or password")),
+        reqwest::StatusCode::NOT_FOUND => Err(anyhow!(
+            "{} does not offer password login, use `m87 login` without --server",
+            api_url
+        )),
+        _ => Ok(res.error_for_status()?.json().await?),
+    }
+}
+
+// m87 command line: List pending device auth requests
+pub async fn list_auth_requests(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<DeviceAuthRequest>, anyhow::Error> {
+    vcr::call("list_auth_requests", json!({ "api_url": api_url }), async {
+        let url = format!("{}/auth/request", api_url);
+        let client = get_client(trust_invalid_server_cert)?;
+
+        let res = client.get(&url).bearer_auth(token).send().await?;
+        match res.error_for_status() {
+            Ok(r) => {
+                let response: Vec<DeviceAuthRequest> = r.json().await?;
+                Ok(response)
+            }
+            Err(e) => Err(anyhow!(e)),
+        }
+    })
+    .await
+}
+
+// m87 command line: Approve or reject device registration
+pub async fn handle_auth_request(
+    api_url: &str,
+    token: &str,
+    request_id: &str,
+    accept: bool,
+    trust_invalid_server_cert: bool,
+) -> Result<(), anyhow::Error> {
+    vcr::call(
+        "handle_auth_request",
+        json!({ "api_url": api_url, "request_id": request_id, "accept": accept }),
+        async {
+            let url = format!("{}/auth/request/approve", api_url);
+            let client = get_client(trust_invalid_server_cert)?;
+
+            let res = client
+                .post(&url)
+                .bearer_auth(token)
+                .json(&AuthRequestAction {
+                    accept,
+                    request_id: request_id.to_string(),
+                })
+                .send()
+                .await?;
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+fn sdk_client(api_url: &str, token: &str, trust_invalid_server_cert: bool) -> Result<Make87Client> {
+    Make87Client::with_client_builder(
+        api_url,
+        token,
+        trust_invalid_server_cert,
+        proxy::apply(reqwest::Client::builder())?,
+    )
+}
+
+// m87 command line: List all accessible devices
+pub async fn list_devices(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<PublicDevice>> {
+    vcr::call("list_devices", json!({ "api_url": api_url }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .list_devices()
+            .await
+    })
+    .await
+}
+
+pub async fn get_fleet_report(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<FleetReport> {
+    vcr::call("get_fleet_report", json!({ "api_url": api_url }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .get_fleet_report()
+            .await
+    })
+    .await
+}
+
+pub async fn get_device(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+) -> Result<PublicDevice> {
+    vcr::call(
+        "get_device",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn delete_device(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+) -> Result<()> {
+    vcr::call(
+        "delete_device",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .delete_device(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+// m87 command line: Relay load of a server (admins only)
+pub async fn get_relay_stats(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<RelayStats> {
+    vcr::call("get_relay_stats", json!({ "api_url": api_url }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .relay_stats()
+            .await
+    })
+    .await
+}
+
+// m87 command line: Collections and indexes of a server's database (admins only)
+pub async fn get_db_stats(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<CollectionStats>> {
+    vcr::call("get_db_stats", json!({ "api_url": api_url }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .db_stats()
+            .await
+    })
+    .await
+}
+
+// m87 command line: Limits and usage of the caller's orgs
+pub async fn list_limits(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<OrgLimitsStatus>> {
+    vcr::call("list_limits", json!({ "api_url": api_url }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .list_limits()
+            .await
+    })
+    .await
+}
+
+// m87 command line: Notices to show, e.g. deprecations
+pub async fn list_notices(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<CliNotice>> {
+    vcr::call("list_notices", json!({ "api_url": api_url }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .list_notices()
+            .await
+    })
+    .await
+}
+
+// m87 command line: Override the limits of an org (admins only)
+pub async fn set_org_limits(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    org_id: &str,
+    body: &UpdateOrgLimitsBody,
+) -> Result<OrgLimitsStatus> {
+    vcr::call(
+        "set_org_limits",
+        json!({ "api_url": api_url, "org_id": org_id, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .set_org_limits(org_id, body)
+                .await
+        },
+    )
+    .await
+}
+
+// m87 command line: Custom redaction patterns of an org
+pub async fn get_org_redaction(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    org_id: &str,
+) -> Result<OrgRedaction> {
+    vcr::call(
+        "get_org_redaction",
+        json!({ "api_url": api_url, "org_id": org_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_org_redaction(org_id)
+                .await
+        },
+    )
+    .await
+}
+
+// m87 command line: Replace the custom redaction patterns of an org
+pub async fn set_org_redaction(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    org_id: &str,
+    body: &UpdateOrgRedactionBody,
+) -> Result<OrgRedaction> {
+    vcr::call(
+        "set_org_redaction",
+        json!({ "api_url": api_url, "org_id": org_id, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .set_org_redaction(org_id, body)
+                .await
+        },
+    )
+    .await
+}
+
+// m87 command line: Monthly usage of the orgs the caller administers
+pub async fn get_usage(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    query: &UsageQuery,
+) -> Result<UsageExport> {
+    vcr::call(
+        "get_usage",
+        json!({ "api_url": api_url, "query": query }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .usage(query)
+                .await
+        },
+    )
+    .await
+}
+
+fn get_client(trust_invalid_server_cert: bool) -> Result<Client> {
+    // announce our protocol version so the server can refuse us with a clear message
+    let mut headers = HeaderMap::new();
+    headers.insert(PROTOCOL_HEADER, HeaderValue::from(PROTOCOL_VERSION));
+    let builder = proxy::apply(reqwest::Client::builder())?
+        .default_headers(headers)
+        .timeout(Duration::from_secs(10));
+
+    // if its localhost we accept invalid certificates
+    if trust_invalid_server_cert {
+        Ok(builder.danger_accept_invalid_certs(true).build()?)
+    } else {
+        // otherwise we verify the certificate
+        Ok(builder.build()?)
+    }
+}
+
+pub async fn update_device(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    body: UpdateDeviceBody,
+    trust_invalid_server_cert: bool,
+) -> Result<()> {
+    vcr::call(
+        "update_device",
+        json!({ "api_url": api_url, "device_id": device_id, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .update_device(device_id, &body)
+                .await
+                .inspect_err(|e| tracing::error!("Error reporting device details: {}", e))
+        },
+    )
+    .await
+}
+
+pub async fn merge_device(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    from: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<PublicDevice> {
+    vcr::call(
+        "merge_device",
+        json!({ "api_url": api_url, "device_id": device_id, "from": from }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .merge_device(device_id, from)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_status(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<DeviceStatus> {
+    vcr::call(
+        "get_device_status",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_status(device_id)
+                .await
+                .inspect_err(|e| tracing::error!("Error getting device status: {}", e))
+        },
+    )
+    .await
+}
+
+pub async fn get_device_vulnerabilities(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<DeviceVulnerabilities> {
+    vcr::call(
+        "get_device_vulnerabilities",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_vulnerabilities(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_bandwidth(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<DeviceBandwidth> {
+    vcr::call(
+        "get_device_bandwidth",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_bandwidth(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_patch_runs(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    limit: u32,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<PatchRun>> {
+    vcr::call(
+        "get_device_patch_runs",
+        json!({ "api_url": api_url, "device_id": device_id, "limit": limit }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_patch_runs(device_id, limit)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_probes(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<ProbeStatus>> {
+    vcr::call(
+        "get_device_probes",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_probes(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn set_device_probes(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    probes: Vec<ProbeSpec>,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<ProbeStatus>> {
+    vcr::call(
+        "set_device_probes",
+        json!({ "api_url": api_url, "device_id": device_id, "probes": probes }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .set_device_probes(device_id, probes.clone())
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_probe_results(
+    api_url: &str,
+    token: &str,
+    device_id: &str,
+    name: Option<&str>,
+    limit: u32,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<ProbeRecord>> {
+    vcr::call(
+        "get_device_probe_results",
+        json!({ "api_url": api_url, "device_id": device_id, "name": name, "limit": limit }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_probe_results(device_id, name, limit)
+                .await
+        },
+    )
+    .await
+}
+
+// ------------------------- Approvals -------------------------
+
+pub async fn list_approvals(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    status: Option<ApprovalStatus>,
+    limit: u32,
+) -> Result<Vec<ApprovalRequest>> {
+    vcr::call(
+        "list_approvals",
+        json!({ "api_url": api_url, "status": status, "limit": limit }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .list_approvals(status, limit)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn decide_approval(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+    body: &DecideApprovalBody,
+) -> Result<ApprovalRequest> {
+    vcr::call(
+        "decide_approval",
+        json!({ "api_url": api_url, "id": id, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .decide_approval(id, body)
+                .await
+        },
+    )
+    .await
+}
+
+// ------------------------- Incidents -------------------------
+
+pub async fn list_incidents(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    state: Option<IncidentState>,
+    all: bool,
+    limit: u32,
+) -> Result<Vec<Incident>> {
+    vcr::call(
+        "list_incidents",
+        json!({ "api_url": api_url, "state": state, "all": all, "limit": limit }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .list_incidents(state, all, limit)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_incident(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+) -> Result<Incident> {
+    vcr::call(
+        "get_incident",
+        json!({ "api_url": api_url, "id": id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_incident(id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn update_incident(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+    body: &UpdateIncidentBody,
+) -> Result<Incident> {
+    vcr::call(
+        "update_incident",
+        json!({ "api_url": api_url, "id": id, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .update_incident(id, body)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_org_incident_webhook(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    org_id: &str,
+) -> Result<OrgIncidentWebhook> {
+    vcr::call(
+        "get_org_incident_webhook",
+        json!({ "api_url": api_url, "org_id": org_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_org_incident_webhook(org_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn set_org_incident_webhook(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    org_id: &str,
+    body: &UpdateOrgIncidentWebhookBody,
+) -> Result<OrgIncidentWebhook> {
+    vcr::call(
+        "set_org_incident_webhook",
+        // the secret stays out of recordings
+        json!({ "api_url": api_url, "org_id": org_id, "url": body.url, "signed": body.secret.is_some() }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .set_org_incident_webhook(org_id, body)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_org_iot_bridge(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    org_id: &str,
+) -> Result<OrgIotBridge> {
+    vcr::call(
+        "get_org_iot_bridge",
+        json!({ "api_url": api_url, "org_id": org_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_org_iot_bridge(org_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn set_org_iot_bridge(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    org_id: &str,
+    body: &UpdateOrgIotBridgeBody,
+) -> Result<OrgIotBridge> {
+    vcr::call(
+        "set_org_iot_bridge",
+        // credentials stay out of recordings
+        json!({
+            "api_url": api_url,
+            "org_id": org_id,
+            "platform": body.target.as_ref().map(|t| t.platform()),
+            "endpoint": body.target.as_ref().map(|t| t.endpoint()),
+            "telemetry_interval_secs": body.telemetry_interval_secs,
+        }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .set_org_iot_bridge(org_id, body)
+                .await
+        },
+    )
+    .await
+}
+
+// ------------------------- Rollouts -------------------------
+
+pub async fn list_rollouts(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    all: bool,
+    limit: u32,
+) -> Result<Vec<Rollout>> {
+    vcr::call(
+        "list_rollouts",
+        json!({ "api_url": api_url, "all": all, "limit": limit }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .list_rollouts(all, limit)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_rollout(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+) -> Result<Rollout> {
+    vcr::call(
+        "get_rollout",
+        json!({ "api_url": api_url, "id": id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_rollout(id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn create_rollout(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    body: &CreateRolloutBody,
+) -> Result<Rollout> {
+    vcr::call(
+        "create_rollout",
+        json!({ "api_url": api_url, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .create_rollout(body)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn create_install_command(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    body: &CreateInstallCommandBody,
+) -> Result<InstallCommand> {
+    vcr::call(
+        "create_install_command",
+        json!({ "api_url": api_url, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .create_install_command(body)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn update_rollout(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+    body: &UpdateRolloutBody,
+) -> Result<Rollout> {
+    vcr::call(
+        "update_rollout",
+        json!({ "api_url": api_url, "id": id, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .update_rollout(id, body)
+                .await
+        },
+    )
+    .await
+}
+
+// ------------------------- Access grants -------------------------
+
+pub async fn list_access_grants(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    all: bool,
+) -> Result<Vec<AccessGrant>> {
+    vcr::call(
+        "list_access_grants",
+        json!({ "api_url": api_url, "all": all }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .list_access_grants(all)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn create_access_grant(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    body: &CreateAccessGrantBody,
+) -> Result<AccessGrant> {
+    vcr::call(
+        "create_access_grant",
+        json!({ "api_url": api_url, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .create_access_grant(body)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn revoke_access_grant(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+) -> Result<AccessGrant> {
+    vcr::call(
+        "revoke_access_grant",
+        json!({ "api_url": api_url, "id": id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .revoke_access_grant(id)
+                .await
+        },
+    )
+    .await
+}
+
+// ------------------------- Deployment -------------------------
+
+pub async fn get_deployments(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    offset: Option<u64>,
+    limit: Option<u64>,
+) -> Result<Vec<DeploymentRevision>> {
+    vcr::call(
+        "get_deployments",
+        json!({ "api_url": api_url, "device_id": device_id, "offset": offset, "limit": limit }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .list_deployments(device_id, offset, limit)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_deployment(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    revision_id: &str,
+) -> Result<DeploymentRevision> {
+    vcr::call(
+        "get_deployment",
+        json!({ "api_url": api_url, "device_id": device_id, "revision_id": revision_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_deployment(device_id, revision_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn create_deployment(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    body: CreateDeployRevisionBody,
+) -> Result<DeploymentRevision> {
+    vcr::call(
+        "create_deployment",
+        json!({ "api_url": api_url, "device_id": device_id, "body": body }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .create_deployment(device_id, &body)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn update_deployment(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    revision_id: &str,
+    body: UpdateDeployRevisionBody,
+) -> Result<()> {
+    vcr::call("update_deployment", json!({ "api_url": api_url, "device_id": device_id, "revision_id": revision_id, "body": body }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .update_deployment(device_id, revision_id, &body)
+            .await
+    })
+    .await
+}
+
+pub async fn delete_deployment(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    revision_id: &str,
+) -> Result<()> {
+    vcr::call(
+        "delete_deployment",
+        json!({ "api_url": api_url, "device_id": device_id, "revision_id": revision_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .delete_deployment(device_id, revision_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_active_deployment_id(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+) -> Result<Option<String>> {
+    vcr::call(
+        "get_active_deployment_id",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_active_deployment_id(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_deployment_reports(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    deployment_id: &str,
+) -> Result<Vec<DeployReport>> {
+    vcr::call(
+        "get_deployment_reports",
+        json!({ "api_url": api_url, "device_id": device_id, "deployment_id": deployment_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_deployment_reports(device_id, deployment_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_revision_snapshot(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    deployment_id: &str,
+) -> Result<DeploymentStatusSnapshot> {
+    vcr::call(
+        "get_device_revision_snapshot",
+        json!({ "api_url": api_url, "device_id": device_id, "deployment_id": deployment_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_deployment_snapshot(device_id, deployment_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_revision_snapshot_part(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    deployment_id: &str,
+    query: &SnapshotQuery,
+) -> Result<DeploymentStatusSnapshot> {
+    vcr::call(
+        "get_device_revision_snapshot_part",
+        json!({ "api_url": api_url, "device_id": device_id, "deployment_id": deployment_id, "query": query }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_deployment_snapshot_part(device_id, deployment_id, query)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn list_run_artifacts(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    deployment_id: &str,
+    run_id: &str,
+) -> Result<Vec<StepArtifacts>> {
+    vcr::call(
+        "list_run_artifacts",
+        json!({ "api_url": api_url, "device_id": device_id, "deployment_id": deployment_id, "run_id": run_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .list_run_artifacts(device_id, deployment_id, run_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn list_core_dumps(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+) -> Result<Vec<CoreDump>> {
+    vcr::call(
+        "list_core_dumps",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .list_core_dumps(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_core_dump(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    core_id: &str,
+) -> Result<CoreDump> {
+    vcr::call(
+        "get_core_dump",
+        json!({ "api_url": api_url, "device_id": device_id, "core_id": core_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_core_dump(device_id, core_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_audit_logs(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    limit: u32,
+    since: Option<String>, // RFC3339, e.g. "2026-01-01T00:00:00Z"
+    until: Option<String>,
+) -> Result<Vec<AuditLog>> {
+    vcr::call("get_device_audit_logs", json!({ "api_url": api_url, "device_id": device_id, "limit": limit, "since": since, "until": until }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .get_device_audit_logs(device_id, limit, since.as_deref(), until.as_deref())
+            .await
+    })
+    .await
+}
+
+pub async fn get_device_events(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    limit: u32,
+    since: Option<String>,
+    until: Option<String>,
+) -> Result<Vec<DeviceTimelineEvent>> {
+    vcr::call(
+        "get_device_events",
+        json!({ "api_url": api_url, "device_id": device_id, "limit": limit, "since": since, "until": until }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_events(device_id, limit, since.as_deref(), until.as_deref())
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn get_device_users(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+) -> Result<Vec<User>> {
+    vcr::call(
+        "get_device_users",
+        json!({ "api_url": api_url, "device_id": device_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .get_device_users(device_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn remove_device_access(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    email_or_org_id: &str,
+) -> Result<()> {
+    vcr::call(
+        "remove_device_access",
+        json!({ "api_url": api_url, "device_id": device_id, "email_or_org_id": email_or_org_id }),
+        async {
+            sdk_client(api_url, token, trust_invalid_server_cert)?
+                .remove_device_access(device_id, email_or_org_id)
+                .await
+        },
+    )
+    .await
+}
+
+pub async fn add_device_access(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    email_or_org_id: &str,
+    role: Role,
+) -> Result<()> {
+    vcr::call("add_device_access", json!({ "api_url": api_url, "device_id": device_id, "email_or_org_id": email_or_org_id, "role": role }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .add_device_access(device_id, email_or_org_id, role)
+            .await
+    })
+    .await
+}
+
+pub async fn update_device_access(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    device_id: &str,
+    email_or_org_id: &str,
+    role: Role,
+) -> Result<()> {
+    vcr::call("update_device_access", json!({ "api_url": api_url, "device_id": device_id, "email_or_org_id": email_or_org_id, "role": role }), async {
+        sdk_client(api_url, token, trust_invalid_server_cert)?
+            .update_device_access(device_id, email_or_org_id, role)
+            .await
+    })
+    .await
+}
+
+pub async fn list_organizations(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<Vec<Organization>> {
+    vcr::call("list_organizations", json!({ "api_url": api_url }), async {
+        let url = format!("{}/organization", api_url);
+        let client = get_client(trust_invalid_server_cert)?;
+
+        let res = client.get(&url).bearer_auth(token).send().await?;
+
+        match res.error_for_status() {
+            Ok(r) => Ok(r.json().await?),
+            Err(e) => Err(anyhow!(e)),
+        }
+    })
+    .await
+}
+
+pub async fn create_organization(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+    owner_email: &str,
+) -> Result<()> {
+    vcr::call(
+        "create_organization",
+        json!({ "api_url": api_url, "id": id, "owner_email": owner_email }),
+        async {
+            let url = format!("{}/organization", api_url);
+            let client = get_client(trust_invalid_server_cert)?;
+
+            let body = CreateOrganizationBody {
+                id: id.to_string(),
+                owner_email: owner_email.to_string(),
+            };
+
+            let res = client
+                .post(&url)
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await?;
+
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn delete_organization(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+) -> Result<()> {
+    vcr::call(
+        "delete_organization",
+        json!({ "api_url": api_url, "id": id }),
+        async {
+            let url = format!("{}/organization/{}", api_url, id);
+            let client = get_client(trust_invalid_server_cert)?;
+
+            let res = client.delete(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn update_organization(
+    api_url: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    id: &str,
+    new_id: &str,
+) -> Result<Organization> {
+    vcr::call(
+        "update_organization",
+        json!({ "api_url": api_url, "id": id, "new_id": new_id }),
+        async {
+            let url = format!("{}/organization/{}", api_url, id);
+            let client = get_client(trust_invalid_server_cert)?;
+
+            let body = UpdateOrganizationBody {
+                new_id: new_id.to_string(),
+            };
+
+            let res = client
+                .put(&url)
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.json().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn list_organization_members(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: String,
+) -> Result<Vec<User>> {
+    vcr::call(
+        "list_organization_members",
+        json!({ "server_url": server_url, "org_id": org_id }),
+        async {
+            let url = format!("{}/organization/{}/members", server_url, org_id);
+            let client = get_client(trust)?;
+
+            let res = client.get(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.json().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn add_organization_member(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: String,
+    email: String,
+    role: Role,
+) -> Result<()> {
+    vcr::call(
+        "add_organization_member",
+        json!({ "server_url": server_url, "org_id": org_id, "email": email, "role": role }),
+        async {
+            let url = format!("{}/organization/{}/members", server_url, org_id);
+            let client = get_client(trust)?;
+
+            let body = InviteMemberBody { email, role };
+            let res = client
+                .post(&url)
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await?;
+
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn remove_organization_member(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: String,
+    user_id: String,
+) -> Result<()> {
+    vcr::call(
+        "remove_organization_member",
+        json!({ "server_url": server_url, "org_id": org_id, "user_id": user_id }),
+        async {
+            let url = format!("{}/organization/{}/members/{}", server_url, org_id, user_id);
+            let client = get_client(trust)?;
+
+            let res = client.delete(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn list_organization_invites(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+) -> Result<Vec<Invite>> {
+    vcr::call(
+        "list_organization_invites",
+        json!({ "server_url": server_url }),
+        async {
+            let url = format!("{}/invites", server_url);
+            let client = get_client(trust)?;
+
+            let res = client.get(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.json().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn handle_organization_invite(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    invite_id: &str,
+    accept: bool,
+) -> Result<String> {
+    vcr::call(
+        "handle_organization_invite",
+        json!({ "server_url": server_url, "invite_id": invite_id, "accept": accept }),
+        async {
+            let url = format!("{}/invites/{}", server_url, invite_id);
+            let client = get_client(trust)?;
+            let body = AcceptRejectBody {
+                invite_id: invite_id.to_string(),
+                accepted: accept,
+            };
+            let res = client
+                .post(&url)
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.text().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn list_org_devices(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: &str,
+) -> Result<Vec<PublicDevice>> {
+    vcr::call(
+        "list_org_devices",
+        json!({ "server_url": server_url, "org_id": org_id }),
+        async {
+            let url = format!("{}/organization/{}/devices", server_url, org_id);
+            let client = get_client(trust)?;
+
+            let res = client.get(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.json().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn list_org_vulnerabilities(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: &str,
+) -> Result<Vec<DeviceVulnerabilities>> {
+    vcr::call(
+        "list_org_vulnerabilities",
+        json!({ "server_url": server_url, "org_id": org_id }),
+        async {
+            let url = format!("{}/organization/{}/vulnerabilities", server_url, org_id);
+            let client = get_client(trust)?;
+
+            let res = client.get(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.json().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn add_org_device(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: &str,
+    device_id: &str,
+) -> Result<()> {
+    vcr::call(
+        "add_org_device",
+        json!({ "server_url": server_url, "org_id": org_id, "device_id": device_id }),
+        async {
+            let url = format!("{}/organization/{}/devices", server_url, org_id);
+            let client = get_client(trust)?;
+
+            let body = AddDeviceBody {
+                device_id: device_id.to_string(),
+            };
+
+            let res = client
+                .post(&url)
+                .bearer_auth(token)
+                .json(&body)
+                .send()
+                .await?;
+
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn list_patch_policies(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: &str,
+) -> Result<Vec<PublicPatchPolicy>> {
+    vcr::call(
+        "list_patch_policies",
+        json!({ "server_url": server_url, "org_id": org_id }),
+        async {
+            let url = format!("{}/organization/{}/patch-policies", server_url, org_id);
+            let client = get_client(trust)?;
+
+            let res = client.get(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.json().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn create_patch_policy(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: &str,
+    body: &CreatePatchPolicyBody,
+) -> Result<PublicPatchPolicy> {
+    vcr::call(
+        "create_patch_policy",
+        json!({ "server_url": server_url, "org_id": org_id, "body": body }),
+        async {
+            let url = format!("{}/organization/{}/patch-policies", server_url, org_id);
+            let client = get_client(trust)?;
+
+            let res = client
+                .post(&url)
+                .bearer_auth(token)
+                .json(body)
+                .send()
+                .await?;
+
+            match res.error_for_status() {
+                Ok(r) => Ok(r.json().await?),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn delete_patch_policy(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: &str,
+    policy_id: &str,
+) -> Result<()> {
+    vcr::call(
+        "delete_patch_policy",
+        json!({ "server_url": server_url, "org_id": org_id, "policy_id": policy_id }),
+        async {
+            let url = format!(
+                "{}/organization/{}/patch-policies/{}",
+                server_url, org_id, policy_id
+            );
+            let client = get_client(trust)?;
+
+            let res = client.delete(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
+
+pub async fn remove_org_device(
+    server_url: &str,
+    token: &str,
+    trust: bool,
+    org_id: &str,
+    device_id: &str,
+) -> Result<()> {
+    vcr::call(
+        "remove_org_device",
+        json!({ "server_url": server_url, "org_id": org_id, "device_id": device_id }),
+        async {
+            let url = format!(
+                "{}/organization/{}/devices/{}",
+                server_url, org_id, device_id
+            );
+            let client = get_client(trust)?;
+
+            let res = client.delete(&url).bearer_auth(token).send().await?;
+
+            match res.error_for_status() {
+                Ok(_) => Ok(()),
+                Err(e) => Err(anyhow!(e)),
+            }
+        },
+    )
+    .await
+}
@@ -0,0 +1,406 @@
+//! Record and replay of server calls. With `M87_VCR_RECORD=<file>` the server
+//! calls of this module and the one-shot device queries (facts, sessions, time) are
+//! written to a cassette; with `M87_VCR_REPLAY=<file>` the answers come from
+//! the cassette and nothing goes over the network. Used for deterministic
+//! tests and offline demos. Tokens, secrets, passwords and keys are redacted
+//! before anything is written, also where a string carries them, as in an
+//! install command or an error with a bearer token.
+
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result, anyhow};
+use m87_shared::redact::{REDACTED, is_secret_key, scrub};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use tracing::{error, warn};
+
+pub const RECORD_ENV: &str = "M87_VCR_RECORD";
+pub const REPLAY_ENV: &str = "M87_VCR_REPLAY";
+/// Stands in for the CLI token while replaying
+pub const REPLAY_TOKEN: &str = "vcr-replay";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+/// One call: its name, the arguments that identify it and the answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub call: String,
+    #[serde(default)]
+    pub request: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+struct Recorder {
+    mode: Mode,
+    /// File the cassette is saved to after each recorded call
+    path: Option<PathBuf>,
+    cassette: Cassette,
+    played: Vec<bool>,
+}
+
+/// Records or replays server calls once a cassette is installed. The module
+/// functions use the process-wide one, set up from the environment.
+#[derive(Default)]
+pub struct Vcr {
+    recorder: Mutex<Option<Recorder>>,
+}
+
+static VCR: Lazy<Vcr> = Lazy::new(|| Vcr {
+    recorder: Mutex::new(from_env()),
+});
+
+fn from_env() -> Option<Recorder> {
+    if let Ok(path) = std::env::var(REPLAY_ENV) {
+        let path = PathBuf::from(path);
+        // stay in replay mode on errors, so nothing silently hits the network
+        let cassette = load(&path).unwrap_or_else(|e| {
+            error!("Failed to load cassette: {:#}", e);
+            Cassette::default()
+        });
+        return Some(Recorder::new(Mode::Replay, None, cassette));
+    }
+    let path = PathBuf::from(std::env::var(RECORD_ENV).ok()?);
+    // keep earlier recordings, so several commands build one cassette
+    let cassette = load(&path).unwrap_or_default();
+    Some(Recorder::new(Mode::Record, Some(path), cassette))
+}
+
+fn load(path: &PathBuf) -> Result<Cassette> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+impl Recorder {
+    fn new(mode: Mode, path: Option<PathBuf>, cassette: Cassette) -> Self {
+        let played = vec![false; cassette.interactions.len()];
+        Self {
+            mode,
+            path,
+            cassette,
+            played,
+        }
+    }
+
+    fn replay(&mut self, call: &str, request: &Value) -> Result<Option<Value>> {
+        let matches: Vec<usize> = (0..self.cassette.interactions.len())
+            .filter(|&i| {
+                let it = &self.cassette.interactions[i];
+                it.call == call && &it.request == request
+            })
+            .collect();
+        // in order, then the last answer again (polling loops)
+        let i = *matches
+            .iter()
+            .find(|&&i| !self.played[i])
+            .or(matches.last())
+            .ok_or_else(|| anyhow!("No recorded response for {} {}", call, request))?;
+        self.played[i] = true;
+
+        let it = &self.cassette.interactions[i];
+        match &it.error {
+            Some(e) => Err(anyhow!("{}", e)),
+            None => Ok(it.response.clone()),
+        }
+    }
+
+    fn record(&mut self, interaction: Interaction) {
+        self.cassette.interactions.push(interaction);
+        self.played.push(true);
+        let Some(path) = &self.path else { return };
+        let res = serde_json::to_string_pretty(&self.cassette)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(std::fs::write(path, json)?));
+        if let Err(e) = res {
+            warn!("Failed to save cassette {}: {:#}", path.display(), e);
+        }
+    }
+}
+
+impl Vcr {
+    /// Use `cassette` instead of the `M87_VCR_*` environment, e.g. in tests.
+    pub fn install(&self, mode: Mode, cassette: Cassette) {
+        *self.recorder.lock().unwrap() = Some(Recorder::new(mode, None, cassette));
+    }
+
+    /// Stop recording or replaying and return the cassette.
+    pub fn uninstall(&self) -> Option<Cassette> {
+        self.recorder.lock().unwrap().take().map(|r| r.cassette)
+    }
+
+    pub fn replaying(&self) -> bool {
+        self.mode() == Some(Mode::Replay)
+    }
+
+    fn mode(&self) -> Option<Mode> {
+        self.recorder.lock().unwrap().as_ref().map(|r| r.mode)
+    }
+
+    /// Run a server call through the recorder. `request` holds the arguments
+    /// that tell calls of the same name apart; when replaying, `fut` is never
+    /// polled.
+    pub async fn call<T, F>(&self, name: &str, mut request: Value, fut: F) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T>>,
+    {
+        let Some(mode) = self.mode() else {
+            return fut.await;
+        };
+        redact(&mut request);
+
+        match mode {
+            Mode::Replay => {
+                let response = self
+                    .recorder
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .context("Record/replay was uninstalled")?
+                    .replay(name, &request)?;
+                Ok(serde_json::from_value(response.unwrap_or(Value::Null))?)
+            }
+            Mode::Record => {
+                let res = fut.await;
+                let (response, error) = match &res {
+                    Ok(v) => {
+                        let mut v = serde_json::to_value(v)?;
+                        redact(&mut v);
+                        (Some(v), None)
+                    }
+                    Err(e) => (None, Some(scrub(&format!("{:#}", e)))),
+                };
+                if let Some(recorder) = self.recorder.lock().unwrap().as_mut() {
+                    recorder.record(Interaction {
+                        call: name.to_string(),
+                        request,
+                        response,
+                        error,
+                    });
+                }
+                res
+            }
+        }
+    }
+}
+
+/// Use `cassette` instead of the `M87_VCR_*` environment.
+pub fn install(mode: Mode, cassette: Cassette) {
+    VCR.install(mode, cassette)
+}
+
+/// Stop recording or replaying and return the cassette.
+pub fn uninstall() -> Option<Cassette> {
+    VCR.uninstall()
+}
+
+pub fn replaying() -> bool {
+    VCR.replaying()
+}
+
+/// [`Vcr::call`] on the process-wide recorder.
+pub async fn call<T, F>(name: &str, request: Value, fut: F) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: Future<Output = Result<T>>,
+{
+    VCR.call(name, request, fut).await
+}
+
+/// Replace secret strings at any depth: the values of fields named like a
+/// token, secret, password or key, and secrets inside any string. Other
+/// values keep their type, so replayed answers still parse.
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                match v {
+                    Value::String(s) if is_secret_field(key) => *s = REDACTED.to_string(),
+                    _ => redact(v),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(s) => *s = scrub(s),
+        _ => {}
+    }
+}
+
+fn is_secret_field(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    // ids of keys and tokens tell calls apart and are no secret
+    !key.ends_with("_id") && (is_secret_key(&key) || key.contains("key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use m87_shared::auth::{
+        DeviceAuthRequestBody, DeviceAuthRequestCheckResponse, LocalLoginResponse,
+    };
+    use m87_shared::device::DeviceSystemInfo;
+    use m87_shared::enrollment::{InstallCommand, render_install_command};
+    use m87_shared::org::UpdateOrgIncidentWebhookBody;
+    use serde_json::json;
+
+    #[test]
+    fn test_redact_nested_secrets() {
+        let mut v = json!({
+            "token": "secret",
+            "device": {"API_Key": "k", "name": "pi", "key_id": "k1"},
+            "list": [{"refresh_token": "r", "ssh_keys": ["ssh-ed25519 AAAA"]}],
+            "webhook_secret": "s",
+            "api_key": null,
+            "token_expires_in": 3600
+        });
+        redact(&mut v);
+        assert_eq!(
+            v,
+            json!({
+                "token": REDACTED,
+                "device": {"API_Key": REDACTED, "name": "pi", "key_id": "k1"},
+                "list": [{"refresh_token": REDACTED, "ssh_keys": ["ssh-ed25519 AAAA"]}],
+                "webhook_secret": REDACTED,
+                "api_key": null,
+                "token_expires_in": 3600
+            })
+        );
+    }
+
+    #[test]
+    fn test_redact_call_payloads() {
+        let body = DeviceAuthRequestBody {
+            device_info: DeviceSystemInfo::default(),
+            owner_scope: "org:acme".to_string(),
+            device_id: "d1".to_string(),
+            enrollment_token: Some("m87e_live".to_string()),
+        };
+        let responses = [
+            json!({ "api_url": "https://m87.internal", "body": body }),
+            serde_json::to_value(DeviceAuthRequestCheckResponse {
+                state: "approved".to_string(),
+                api_key: Some("m87k_live".to_string()),
+            })
+            .unwrap(),
+            serde_json::to_value(LocalLoginResponse {
+                access_token: "eyJ.live.sig".to_string(),
+                expires_at: 1_700_000_000,
+            })
+            .unwrap(),
+            serde_json::to_value(UpdateOrgIncidentWebhookBody {
+                url: Some("https://hooks.internal/m87".to_string()),
+                secret: Some("hook_live".to_string()),
+            })
+            .unwrap(),
+        ];
+        for mut v in responses {
+            redact(&mut v);
+            let recorded = v.to_string();
+            assert!(!recorded.contains("live"), "{}", recorded);
+        }
+    }
+
+    #[test]
+    fn test_redact_install_command_token() {
+        let live = InstallCommand {
+            command: render_install_command(
+                "https://get.make87.com",
+                "https://m87.internal",
+                "acme",
+                "m87e_live",
+            ),
+            owner_reference: "acme".to_string(),
+            server_url: "https://m87.internal".to_string(),
+            expires_at: "2026-01-01T00:00:00Z".to_string(),
+            max_uses: 1,
+        };
+        let mut v = serde_json::to_value(&live).unwrap();
+        redact(&mut v);
+        let recorded: InstallCommand = serde_json::from_value(v).unwrap();
+        assert_eq!(
+            recorded.command,
+            "curl -fsSL 'https://get.make87.com' | M87_RUNTIME_SERVER_URL='https://m87.internal' \
+             M87_OWNER='acme' M87_ENROLLMENT_TOKEN='[REDACTED]' sh"
+        );
+        assert_eq!(recorded.owner_reference, live.owner_reference);
+        assert_eq!(recorded.server_url, live.server_url);
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let vcr = Vcr::default();
+        vcr.install(Mode::Record, Cassette::default());
+        let ok: Vec<String> = vcr
+            .call("list", json!({"page": 1}), async {
+                Ok(vec!["a".to_string()])
+            })
+            .await
+            .unwrap();
+        assert_eq!(ok, vec!["a"]);
+        let _ = vcr
+            .call::<(), _>("delete", json!({"id": "x"}), async {
+                Err(anyhow!("404 Not Found"))
+            })
+            .await;
+        let cassette = vcr.uninstall().unwrap();
+        assert_eq!(cassette.interactions.len(), 2);
+
+        vcr.install(Mode::Replay, cassette);
+        let replayed: Vec<String> = vcr
+            .call("list", json!({"page": 1}), async {
+                Err(anyhow!("replay must not call the server"))
+            })
+            .await
+            .unwrap();
+        assert_eq!(replayed, vec!["a"]);
+        let err = vcr
+            .call::<(), _>("delete", json!({"id": "x"}), async { Ok(()) })
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "404 Not Found");
+        assert!(
+            vcr.call::<(), _>("list", json!({"page": 2}), async { Ok(()) })
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_record_keeps_secrets_out_of_the_cassette() {
+        let vcr = Vcr::default();
+        vcr.install(Mode::Record, Cassette::default());
+        let live: LocalLoginResponse = vcr
+            .call("login", json!({"api_url": "https://m87.internal"}), async {
+                Ok(LocalLoginResponse {
+                    access_token: "eyJ.live.sig".to_string(),
+                    expires_at: 1,
+                })
+            })
+            .await
+            .unwrap();
+        // the caller still gets the real token
+        assert_eq!(live.access_token, "eyJ.live.sig");
+        let _ = vcr
+            .call::<(), _>("get", json!({}), async {
+                Err(anyhow!("rejected Authorization: Bearer eyJ.live.sig"))
+            })
+            .await;
+        let cassette = serde_json::to_string(&vcr.uninstall().unwrap()).unwrap();
+        assert!(!cassette.contains("live"), "{}", cassette);
+    }
+}
@@ -0,0 +1,164 @@
+//! launchd daemon (macOS) in /Library/LaunchDaemons, run as the invoking user
+//! and restarted when it exits with an error.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tracing::info;
+
+use super::{ServiceSpec, run, run_checked};
+
+const DAEMON_DIR: &str = "/Library/LaunchDaemons";
+
+fn label(name: &str) -> String {
+    format!("com.make87.{}", name)
+}
+
+/// `system/<label>`, how launchctl addresses a loaded daemon
+fn target(name: &str) -> String {
+    format!("system/{}", label(name))
+}
+
+pub fn service_file(name: &str) -> PathBuf {
+    Path::new(DAEMON_DIR).join(format!("{}.plist", label(name)))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+pub fn content(spec: &ServiceSpec) -> String {
+    let program: String = std::iter::once(spec.exe_path.to_string_lossy().to_string())
+        .chain(spec.args())
+        .map(|a| format!("    <string>{}</string>\n", xml_escape(&a)))
+        .collect();
+    let env: String = spec
+        .env()
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "    <key>{k}</key>\n    <string>{}</string>\n",
+                xml_escape(v)
+            )
+        })
+        .collect();
+    let log = format!("/var/log/{}.log", spec.name);
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+  <key>Label</key>
+  <string>{label}</string>
+  <key>ProgramArguments</key>
+  <array>
+{program}  </array>
+  <key>UserName</key>
+  <string>{username}</string>
+  <key>WorkingDirectory</key>
+  <string>{home}</string>
+  <key>EnvironmentVariables</key>
+  <dict>
+{env}  </dict>
+  <key>RunAtLoad</key>
+  <true/>
+  <key>KeepAlive</key>
+  <dict>
+    <key>SuccessfulExit</key>
+    <false/>
+  </dict>
+  <key>ThrottleInterval</key>
+  <integer>3</integer>
+  <key>ExitTimeOut</key>
+  <integer>30</integer>
+  <key>Umask</key>
+  <integer>63</integer>
+  <key>StandardOutPath</key>
+  <string>{log}</string>
+  <key>StandardErrorPath</key>
+  <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = label(&spec.name),
+        program = program,
+        username = xml_escape(&spec.username),
+        home = xml_escape(&spec.home.to_string_lossy()),
+        env = env,
+        log = log,
+    )
+}
+
+fn is_loaded(name: &str) -> bool {
+    Command::new("launchctl")
+        .args(["print", &target(name)])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
+
+/// Load the daemon, which starts it (RunAtLoad).
+fn bootstrap(name: &str) -> Result<()> {
+    if !is_loaded(name) {
+        let plist = service_file(name);
+        run_checked(
+            "launchctl",
+            &["bootstrap", "system", &plist.to_string_lossy()],
+        )?;
+    }
+    Ok(())
+}
+
+pub fn enable(name: &str, now: bool) -> Result<()> {
+    run_checked("launchctl", &["enable", &target(name)])?;
+    if now {
+        bootstrap(name)?;
+        info!("Enabled and started {} service", name);
+    } else {
+        info!("Enabled {} service (starts on boot)", name);
+    }
+    Ok(())
+}
+
+pub fn disable(name: &str, now: bool) -> Result<()> {
+    run_checked("launchctl", &["disable", &target(name)])?;
+    if now && is_loaded(name) {
+        run_checked("launchctl", &["bootout", &target(name)])?;
+        info!("Disabled and stopped {} service", name);
+    } else {
+        info!("Disabled {} service", name);
+    }
+    Ok(())
+}
+
+pub fn stop(name: &str) -> Result<()> {
+    // the runtime exits cleanly on SIGTERM, so KeepAlive leaves it stopped
+    if is_loaded(name) {
+        run_checked("launchctl", &["kill", "SIGTERM", &target(name)])?;
+    }
+    info!("Stopped {} service", name);
+    Ok(())
+}
+
+pub fn restart(name: &str) -> Result<()> {
+    if is_loaded(name) {
+        run_checked("launchctl", &["kickstart", "-k", &target(name)])?;
+    } else {
+        bootstrap(name)?;
+    }
+    info!("Restarted {} service", name);
+    Ok(())
+}
+
+pub fn status(name: &str) -> Result<()> {
+    if !is_loaded(name) {
+        println!("{} is not loaded", label(name));
+        return Ok(());
+    }
+    run("launchctl", &["print", &target(name)])?;
+    Ok(())
+}
@@ -0,0 +1,327 @@
+//! Installing the runtime as a system service. systemd, OpenRC (Alpine),
+//! SysV init and launchd (macOS) are supported; the init system is detected
+//! at runtime and can be forced with `M87_SERVICE_MANAGER`.
+
+use anyhow::{Context, Result, bail};
+use std::fs::{self, Permissions};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use tempfile::NamedTempFile;
+
+use crate::util::command::binary_exists;
+
+mod launchd;
+mod openrc;
+mod systemd;
+mod sysv;
+
+/// Forces the init system: systemd, openrc, sysv or launchd
+pub const SERVICE_MANAGER_ENV: &str = "M87_SERVICE_MANAGER";
+
+const SERVICE_NAME: &str = "m87-runtime";
+
+/// `m87-runtime`, or `m87-runtime-<name>` for a named instance.
+pub fn service_name() -> String {
+    match crate::config::instance() {
+        Some(name) => format!("{}-{}", SERVICE_NAME, name),
+        None => SERVICE_NAME.to_string(),
+    }
+}
+
+/// Who and what the service runs.
+pub struct ServiceSpec {
+    pub name: String,
+    pub exe_path: PathBuf,
+    pub username: String,
+    pub home: PathBuf,
+}
+
+impl ServiceSpec {
+    pub fn new(exe_path: PathBuf, username: &str, home: PathBuf) -> Self {
+        Self {
+            name: service_name(),
+            exe_path,
+            username: username.to_string(),
+            home,
+        }
+    }
+
+    fn description(&self) -> String {
+        match crate::config::instance() {
+            Some(name) => format!("m87 Runtime Service ({})", name),
+            None => "m87 Runtime Service".to_string(),
+        }
+    }
+
+    /// Arguments after the executable
+    fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(name) = crate::config::instance() {
+            args.extend(["--instance".to_string(), name.to_string()]);
+        }
+        args.extend(["runtime".to_string(), "run".to_string()]);
+        args
+    }
+
+    /// Environment that points the runtime at the user's config and data directories
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let home = self.home.display();
+        vec![
+            ("HOME", home.to_string()),
+            ("XDG_CONFIG_HOME", format!("{home}/.config")),
+            ("XDG_DATA_HOME", format!("{home}/.local/share")),
+            ("XDG_CACHE_HOME", format!("{home}/.cache")),
+            ("RUST_LOG", "info".to_string()),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceManager {
+    Systemd,
+    OpenRc,
+    SysV,
+    Launchd,
+}
+
+impl ServiceManager {
+    /// The init system of this host.
+    pub fn detect() -> Result<Self> {
+        if let Ok(name) = std::env::var(SERVICE_MANAGER_ENV) {
+            return Self::from_name(&name);
+        }
+        if cfg!(target_os = "macos") {
+            return Ok(Self::Launchd);
+        }
+        // the check sd_booted() does
+        if Path::new("/run/systemd/system").exists() {
+            return Ok(Self::Systemd);
+        }
+        if Path::new("/run/openrc").exists() || binary_exists("openrc-run") {
+            return Ok(Self::OpenRc);
+        }
+        if Path::new("/etc/init.d").is_dir() {
+            return Ok(Self::SysV);
+        }
+        bail!(
+            "No supported init system found (systemd, OpenRC, SysV init or launchd); set {} to choose one",
+            SERVICE_MANAGER_ENV
+        )
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "systemd" => Ok(Self::Systemd),
+            "openrc" => Ok(Self::OpenRc),
+            "sysv" => Ok(Self::SysV),
+            "launchd" => Ok(Self::Launchd),
+            other => bail!(
+                "Unknown {} '{}': use systemd, openrc, sysv or launchd",
+                SERVICE_MANAGER_ENV,
+                other
+            ),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Systemd => "systemd",
+            Self::OpenRc => "OpenRC",
+            Self::SysV => "SysV init",
+            Self::Launchd => "launchd",
+        }
+    }
+
+    /// Path of the unit file, init script or property list
+    pub fn service_file(&self, name: &str) -> PathBuf {
+        match self {
+            Self::Systemd => systemd::service_file(name),
+            Self::OpenRc | Self::SysV => Path::new("/etc/init.d").join(name),
+            Self::Launchd => launchd::service_file(name),
+        }
+    }
+
+    /// Write the service definition. Returns whether it changed.
+    pub fn install(&self, spec: &ServiceSpec) -> Result<bool> {
+        let (content, mode) = match self {
+            Self::Systemd => (systemd::content(spec), 0o644),
+            Self::OpenRc => (openrc::content(spec), 0o755),
+            Self::SysV => (sysv::content(spec), 0o755),
+            Self::Launchd => (launchd::content(spec), 0o644),
+        };
+        let changed = write_file_atomic(&self.service_file(&spec.name), &content, mode)?;
+        if changed && *self == Self::Systemd {
+            systemd::daemon_reload()?;
+        }
+        Ok(changed)
+    }
+
+    /// Start at boot, and right away with `now`.
+    pub fn enable(&self, name: &str, now: bool) -> Result<()> {
+        match self {
+            Self::Systemd => systemd::enable(name, now),
+            Self::OpenRc => openrc::enable(name, now),
+            Self::SysV => sysv::enable(name, now),
+            Self::Launchd => launchd::enable(name, now),
+        }
+    }
+
+    /// No longer start at boot, and stop right away with `now`.
+    pub fn disable(&self, name: &str, now: bool) -> Result<()> {
+        match self {
+            Self::Systemd => systemd::disable(name, now),
+            Self::OpenRc => openrc::disable(name, now),
+            Self::SysV => sysv::disable(name, now),
+            Self::Launchd => launchd::disable(name, now),
+        }
+    }
+
+    pub fn stop(&self, name: &str) -> Result<()> {
+        match self {
+            Self::Systemd => systemd::stop(name),
+            Self::OpenRc => openrc::stop(name),
+            Self::SysV => sysv::stop(name),
+            Self::Launchd => launchd::stop(name),
+        }
+    }
+
+    /// Restart if running, start if stopped.
+    pub fn restart(&self, name: &str) -> Result<()> {
+        match self {
+            Self::Systemd => systemd::restart(name),
+            Self::OpenRc => openrc::restart(name),
+            Self::SysV => sysv::restart(name),
+            Self::Launchd => launchd::restart(name),
+        }
+    }
+
+    /// Print the service status. Returns false if the service is not installed.
+    pub fn status(&self, name: &str) -> Result<bool> {
+        if *self == Self::Systemd {
+            return systemd::status(name);
+        }
+        if !self.service_file(name).exists() {
+            return Ok(false);
+        }
+        match self {
+            Self::OpenRc => openrc::status(name)?,
+            Self::SysV => sysv::status(name)?,
+            _ => launchd::status(name)?,
+        };
+        Ok(true)
+    }
+
+    /// Stop and disable the service and remove its definition.
+    pub fn uninstall(&self, name: &str) -> Result<()> {
+        let path = self.service_file(name);
+        if !path.exists() {
+            bail!("Service {} is not installed", name);
+        }
+        self.disable(name, true)?;
+        fs::remove_file(&path).with_context(|| format!("Failed to remove {}", path.display()))?;
+        if *self == Self::Systemd {
+            systemd::daemon_reload()?;
+        }
+        Ok(())
+    }
+}
+
+/// Run a service command with inherited stdio.
+fn run(program: &str, args: &[&str]) -> Result<ExitStatus> {
+    Command::new(program)
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .with_context(|| format!("Failed to run {} {:?}", program, args))
+}
+
+fn run_checked(program: &str, args: &[&str]) -> Result<()> {
+    let status = run(program, args)?;
+    if !status.success() {
+        bail!(
+            "{} {:?} failed with exit code {:?}",
+            program,
+            args,
+            status.code()
+        );
+    }
+    Ok(())
+}
+
+/// Write `content` to `path` atomically with `mode`.
+/// Returns Ok(true) if file was changed, Ok(false) if no change needed
+fn write_file_atomic(path: &Path, content: &str, mode: u32) -> Result<bool> {
+    // Check if content differs from existing
+    if path.exists() {
+        let existing = fs::read_to_string(path).context("Failed to read existing service file")?;
+        if existing == content {
+            return Ok(false); // No change needed
+        }
+    }
+
+    // Create temp file in same directory for atomic rename
+    let dir = path
+        .parent()
+        .context("Service file has no parent directory")?;
+    fs::create_dir_all(dir).context("Failed to create service directory")?;
+    let mut tmp = NamedTempFile::new_in(dir).context("Failed to create temporary service file")?;
+
+    tmp.write_all(content.as_bytes())
+        .context("Failed to write service content")?;
+
+    tmp.as_file()
+        .sync_all()
+        .context("Failed to sync service file to disk")?;
+
+    // Persist atomically (rename)
+    let tmp_path = tmp.into_temp_path();
+    tmp_path
+        .persist(path)
+        .context("Failed to persist service file")?;
+
+    // Set permissions explicitly
+    fs::set_permissions(path, Permissions::from_mode(mode))
+        .context("Failed to set service file permissions")?;
+
+    Ok(true)
+}
+
+/// Quote for a POSIX shell.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> ServiceSpec {
+        ServiceSpec::new(
+            PathBuf::from("/usr/local/bin/m87"),
+            "pi",
+            PathBuf::from("/home/pi"),
+        )
+    }
+
+    #[test]
+    fn test_service_definitions_run_the_runtime() {
+        let spec = spec();
+        assert!(systemd::content(&spec).contains("ExecStart=/usr/local/bin/m87 runtime run\n"));
+        assert!(openrc::content(&spec).contains("command_args=\"runtime run\"\n"));
+        assert!(openrc::content(&spec).contains("command_user='pi'\n"));
+        assert!(sysv::content(&spec).contains("RUN_AS='pi'\n"));
+        let plist = launchd::content(&spec);
+        assert!(plist.contains("<string>/usr/local/bin/m87</string>"));
+        assert!(plist.contains("<key>UserName</key>\n  <string>pi</string>"));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
@@ -0,0 +1,94 @@
+//! OpenRC init script (Alpine and Gentoo), supervised by supervise-daemon so
+//! the runtime is restarted when it exits.
+
+use anyhow::Result;
+use tracing::info;
+
+use super::{ServiceSpec, run, run_checked, shell_quote};
+
+pub fn content(spec: &ServiceSpec) -> String {
+    let env: String = spec
+        .env()
+        .iter()
+        .map(|(k, v)| format!("export {k}={}\n", shell_quote(v)))
+        .collect();
+    let log = format!("/var/log/{}.log", spec.name);
+
+    format!(
+        r#"#!/sbin/openrc-run
+
+description={description}
+command={exe_path}
+command_args="{args}"
+command_user={username}
+directory={home}
+supervisor=supervise-daemon
+respawn_delay=3
+respawn_max=5
+respawn_period=30
+umask=0077
+output_log={log}
+error_log={log}
+retry="TERM/30/KILL/5"
+
+{env}
+depend() {{
+	need net
+	after firewall
+}}
+
+start_pre() {{
+	checkpath --file --owner {username} --mode 0640 {log}
+}}
+"#,
+        description = shell_quote(&spec.description()),
+        exe_path = shell_quote(&spec.exe_path.to_string_lossy()),
+        // instance names are plain words, and openrc-run evaluates command_args
+        args = spec.args().join(" "),
+        username = shell_quote(&spec.username),
+        home = shell_quote(&spec.home.to_string_lossy()),
+        log = log,
+        env = env,
+    )
+}
+
+pub fn enable(name: &str, now: bool) -> Result<()> {
+    run_checked("rc-update", &["add", name, "default"])?;
+    if now {
+        run_checked("rc-service", &[name, "start"])?;
+        info!("Enabled and started {} service", name);
+    } else {
+        info!("Enabled {} service (starts on boot)", name);
+    }
+    Ok(())
+}
+
+pub fn disable(name: &str, now: bool) -> Result<()> {
+    run_checked("rc-update", &["del", name, "default"])?;
+    if now {
+        stop(name)?;
+        info!("Disabled and stopped {} service", name);
+    } else {
+        info!("Disabled {} service", name);
+    }
+    Ok(())
+}
+
+pub fn stop(name: &str) -> Result<()> {
+    run_checked("rc-service", &[name, "stop"])?;
+    info!("Stopped {} service", name);
+    Ok(())
+}
+
+pub fn restart(name: &str) -> Result<()> {
+    // restart also starts a stopped service
+    run_checked("rc-service", &[name, "restart"])?;
+    info!("Restarted {} service", name);
+    Ok(())
+}
+
+pub fn status(name: &str) -> Result<()> {
+    // non-zero while stopped, which is a valid status
+    run("rc-service", &[name, "status"])?;
+    Ok(())
+}
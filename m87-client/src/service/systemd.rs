@@ -0,0 +1,109 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use super::ServiceSpec;
+use crate::util::unix::{run_systemctl, run_systemctl_checked};
+
+const SERVICE_DIR: &str = "/etc/systemd/system";
+
+pub fn service_file(name: &str) -> PathBuf {
+    Path::new(SERVICE_DIR).join(format!("{}.service", name))
+}
+
+/// Generate the systemd service file content with all XDG environment variables
+pub fn content(spec: &ServiceSpec) -> String {
+    let env: String = spec
+        .env()
+        .iter()
+        .map(|(k, v)| format!("Environment={k}={v}\n"))
+        .collect();
+
+    format!(
+        r#"[Unit]
+Description={description}
+After=network-online.target
+Wants=network-online.target
+
+[Service]
+Type=simple
+ExecStart={exe_path} {args}
+WorkingDirectory={home}
+Restart=on-failure
+RestartSec=3
+User={username}
+
+# Deterministic environment for user's config/data directories
+{env}
+# Security hardening
+UMask=0077
+
+# Logging
+StandardOutput=journal
+StandardError=journal
+SyslogIdentifier={name}
+
+# Resource limits
+TimeoutStopSec=30
+StartLimitBurst=5
+StartLimitIntervalSec=30
+
+[Install]
+WantedBy=multi-user.target
+"#,
+        description = spec.description(),
+        exe_path = spec.exe_path.display(),
+        args = spec.args().join(" "),
+        home = spec.home.display(),
+        username = spec.username,
+        name = spec.name,
+    )
+}
+
+pub fn daemon_reload() -> Result<()> {
+    run_systemctl_checked(&["daemon-reload"])
+}
+
+pub fn enable(name: &str, now: bool) -> Result<()> {
+    if now {
+        // enable --now: enable at boot AND start immediately
+        run_systemctl_checked(&["enable", "--now", name])?;
+        info!("Enabled and started {} service", name);
+    } else {
+        run_systemctl_checked(&["enable", name])?;
+        info!("Enabled {} service (starts on boot)", name);
+    }
+    Ok(())
+}
+
+pub fn disable(name: &str, now: bool) -> Result<()> {
+    if now {
+        run_systemctl_checked(&["disable", "--now", name])?;
+        info!("Disabled and stopped {} service", name);
+    } else {
+        run_systemctl_checked(&["disable", name])?;
+        info!("Disabled {} service", name);
+    }
+    Ok(())
+}
+
+pub fn stop(name: &str) -> Result<()> {
+    run_systemctl_checked(&["stop", name])?;
+    info!("Stopped {} service", name);
+    Ok(())
+}
+
+pub fn restart(name: &str) -> Result<()> {
+    // Match systemd behavior: restart if running, start if stopped
+    run_systemctl_checked(&["restart", name])?;
+    info!("Restarted {} service", name);
+    Ok(())
+}
+
+pub fn status(name: &str) -> Result<bool> {
+    let status = run_systemctl(&["status", "--lines=0", name])?;
+
+    // Exit code 3 means service not running, which is valid for status
+    // Exit code 4 means service unknown/not installed
+    Ok(status.code() != Some(4))
+}
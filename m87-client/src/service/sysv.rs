@@ -0,0 +1,151 @@
+//! SysV init script with LSB headers, registered with update-rc.d (Debian)
+//! or chkconfig (Red Hat). SysV init does not restart crashed services.
+
+use anyhow::{Result, bail};
+use tracing::info;
+
+use super::{ServiceSpec, run, run_checked, shell_quote};
+use crate::util::command::binary_exists;
+
+pub fn content(spec: &ServiceSpec) -> String {
+    let args: Vec<String> = spec.args().iter().map(|a| shell_quote(a)).collect();
+    let env: String = spec
+        .env()
+        .iter()
+        .map(|(k, v)| format!("export {k}={}\n", shell_quote(v)))
+        .collect();
+
+    format!(
+        r#"#!/bin/sh
+### BEGIN INIT INFO
+# Provides:          {name}
+# Required-Start:    $network $remote_fs
+# Required-Stop:     $network $remote_fs
+# Default-Start:     2 3 4 5
+# Default-Stop:      0 1 6
+# Short-Description: {description}
+### END INIT INFO
+# chkconfig: 2345 90 10
+# description: {description}
+
+NAME={name}
+EXEC={exe_path}
+ARGS="{args}"
+RUN_AS={username}
+PIDFILE=/var/run/$NAME.pid
+LOGFILE=/var/log/$NAME.log
+
+{env}
+is_running() {{
+    [ -f "$PIDFILE" ] && kill -0 "$(cat "$PIDFILE")" 2>/dev/null
+}}
+
+start() {{
+    if is_running; then
+        echo "$NAME is already running"
+        return 0
+    fi
+    touch "$LOGFILE" && chown "$RUN_AS" "$LOGFILE" && chmod 0640 "$LOGFILE"
+    cd "$HOME" || return 1
+    su -s /bin/sh "$RUN_AS" -c "umask 077; exec $EXEC $ARGS >>$LOGFILE 2>&1" &
+    echo $! > "$PIDFILE"
+    echo "Started $NAME"
+}}
+
+stop() {{
+    if ! is_running; then
+        echo "$NAME is not running"
+        rm -f "$PIDFILE"
+        return 0
+    fi
+    kill -TERM "$(cat "$PIDFILE")"
+    i=0
+    while is_running && [ $i -lt 30 ]; do
+        sleep 1
+        i=$((i + 1))
+    done
+    is_running && kill -KILL "$(cat "$PIDFILE")"
+    rm -f "$PIDFILE"
+    echo "Stopped $NAME"
+}}
+
+case "$1" in
+    start) start ;;
+    stop) stop ;;
+    restart) stop; start ;;
+    status)
+        if is_running; then
+            echo "$NAME is running (pid $(cat "$PIDFILE"))"
+        else
+            echo "$NAME is not running"
+            exit 3
+        fi
+        ;;
+    *)
+        echo "Usage: $0 {{start|stop|restart|status}}"
+        exit 2
+        ;;
+esac
+"#,
+        name = spec.name,
+        description = spec.description(),
+        exe_path = shell_quote(&spec.exe_path.to_string_lossy()),
+        args = args.join(" "),
+        username = shell_quote(&spec.username),
+        env = env,
+    )
+}
+
+fn script(name: &str) -> String {
+    format!("/etc/init.d/{}", name)
+}
+
+pub fn enable(name: &str, now: bool) -> Result<()> {
+    if binary_exists("update-rc.d") {
+        run_checked("update-rc.d", &[name, "defaults"])?;
+    } else if binary_exists("chkconfig") {
+        run_checked("chkconfig", &["--add", name])?;
+    } else {
+        bail!("Neither update-rc.d nor chkconfig found to enable {}", name);
+    }
+    if now {
+        run_checked(&script(name), &["start"])?;
+        info!("Enabled and started {} service", name);
+    } else {
+        info!("Enabled {} service (starts on boot)", name);
+    }
+    Ok(())
+}
+
+pub fn disable(name: &str, now: bool) -> Result<()> {
+    if binary_exists("update-rc.d") {
+        run_checked("update-rc.d", &["-f", name, "remove"])?;
+    } else if binary_exists("chkconfig") {
+        run_checked("chkconfig", &["--del", name])?;
+    }
+    if now {
+        stop(name)?;
+        info!("Disabled and stopped {} service", name);
+    } else {
+        info!("Disabled {} service", name);
+    }
+    Ok(())
+}
+
+pub fn stop(name: &str) -> Result<()> {
+    run_checked(&script(name), &["stop"])?;
+    info!("Stopped {} service", name);
+    Ok(())
+}
+
+pub fn restart(name: &str) -> Result<()> {
+    run_checked(&script(name), &["restart"])?;
+    info!("Restarted {} service", name);
+    Ok(())
+}
+
+pub fn status(name: &str) -> Result<()> {
+    // exit code 3 means not running, which is a valid status
+    run(&script(name), &["status"])?;
+    Ok(())
+}
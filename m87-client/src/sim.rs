@@ -0,0 +1,465 @@
+//! Simulated devices for development, demos and load tests. A simulated device
+//! is a real registration on the server, driven in-process: it keeps a control
+//! tunnel open, sends heartbeats and accepts deployments, whose runs get their
+//! files written to a temp dir and are reported successful without executing
+//! any steps.
+
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures::{StreamExt, stream};
+use m87_shared::deploy_spec::{
+    DeployReportKind, DeploymentRevision, DeploymentRevisionReport, Outcome, RunReport, RunType,
+};
+use m87_shared::device::{DeviceSystemInfo, short_device_id};
+use m87_shared::heartbeat::{HeartbeatRequest, HeartbeatResponse};
+use m87_shared::protocol::PROTOCOL_VERSION;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Mutex, mpsc};
+use tracing::{debug, info, warn};
+use ulid::Ulid;
+
+use crate::auth::{AuthManager, owner_scope_of};
+use crate::config::Config;
+use crate::device::control_tunnel::{read_msg, write_msg};
+use crate::server::{self, DeviceAuthRequestBody};
+use crate::streams::quic::{get_quic_connection_with_timeouts, keepalive_timeouts};
+use crate::streams::stream_type::StreamClass;
+use crate::util::shutdown::SHUTDOWN;
+
+/// Registrations and tunnel handshakes in flight at once.
+pub const SETUP_CONCURRENCY: usize = 32;
+const HEARTBEAT_INTERVAL_SECS: u64 = 30;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Server and owner simulated devices register with.
+pub struct Target {
+    pub api_url: String,
+    /// Server hostname, used for the tunnel hosts
+    pub host: String,
+    pub token: String,
+    pub owner_scope: String,
+    pub trust: bool,
+}
+
+impl Target {
+    /// Resolve the server and owner, falling back to the configured ones.
+    pub async fn resolve(server_url: Option<String>, owner: Option<String>) -> Result<Self> {
+        let config = Config::load()?;
+        let token = AuthManager::get_cli_token().await?;
+        let api_url = server_url
+            .or_else(|| config.runtime_server_url.clone())
+            .or_else(|| config.manager_server_urls.first().cloned())
+            .context("No server configured; pass --server")?;
+        let host = api_url
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_end_matches('/')
+            .to_string();
+        let owner = owner
+            .or_else(|| config.owner_reference.clone())
+            .context("No owner configured; pass --owner <email or org id>")?;
+        Ok(Self {
+            api_url,
+            host,
+            token,
+            owner_scope: owner_scope_of(&owner),
+            trust: config.trust_invalid_server_cert,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimAgent {
+    pub device_id: String,
+    pub short_id: String,
+    pub api_key: String,
+}
+
+/// Register a device and approve it with the CLI token. `name` prefixes the
+/// hostname shown for the device.
+pub async fn register(target: &Target, name: &str) -> Result<SimAgent> {
+    let device_id = Config::generate_device_id();
+    let short_id = short_device_id(&device_id);
+    let body = DeviceAuthRequestBody {
+        device_info: system_info(&format!("{}-{}", name, short_id)),
+        owner_scope: target.owner_scope.clone(),
+        device_id: device_id.clone(),
+        enrollment_token: None,
+    };
+    let request_id = server::set_auth_request(&target.api_url, body, target.trust).await?;
+    server::handle_auth_request(
+        &target.api_url,
+        &target.token,
+        &request_id,
+        true,
+        target.trust,
+    )
+    .await?;
+    let api_key = server::check_auth_request(&target.api_url, &request_id, target.trust)
+        .await?
+        .api_key
+        .context("Registration was not approved")?;
+    Ok(SimAgent {
+        device_id,
+        short_id,
+        api_key,
+    })
+}
+
+fn system_info(hostname: &str) -> DeviceSystemInfo {
+    DeviceSystemInfo {
+        hostname: hostname.to_string(),
+        username: "m87-sim".to_string(),
+        operating_system: "simulated".to_string(),
+        architecture: std::env::consts::ARCH.to_string(),
+        cpu_name: "simulated".to_string(),
+        ..Default::default()
+    }
+}
+
+/// Open the control tunnel of a simulated device.
+pub async fn connect(
+    agent: &SimAgent,
+    host: &str,
+    trust: bool,
+) -> Result<(quinn::Endpoint, quinn::Connection)> {
+    let control_host = m87_shared::host::device_host(&format!("control-{}", agent.short_id), host);
+    get_quic_connection_with_timeouts(
+        &control_host,
+        &agent.api_key,
+        trust,
+        keepalive_timeouts(StreamClass::Control),
+    )
+    .await
+}
+
+pub struct SimulateOptions {
+    /// Server to register with; defaults to the configured one
+    pub server_url: Option<String>,
+    /// Email or organization id the devices register under
+    pub owner: Option<String>,
+    pub count: usize,
+    /// Keep the devices registered on exit, to reuse them next time
+    pub keep: bool,
+}
+
+/// Run `count` simulated devices until Ctrl+C. Devices kept from an earlier
+/// run are reused before new ones are registered.
+pub async fn simulate(opts: &SimulateOptions) -> Result<()> {
+    let target = Arc::new(Target::resolve(opts.server_url.clone(), opts.owner.clone()).await?);
+
+    let mut agents = load_kept()?;
+    let reused = agents.len().min(opts.count);
+    let surplus = agents.split_off(reused);
+    let registered: Vec<Result<SimAgent>> = stream::iter(reused..opts.count)
+        .map(|_| register(&target, "m87-sim"))
+        .buffer_unordered(SETUP_CONCURRENCY)
+        .collect()
+        .await;
+    for agent in registered {
+        match agent {
+            Ok(agent) => agents.push(agent),
+            Err(e) => warn!("Failed to register a simulated device: {:#}", e),
+        }
+    }
+    // keep the unused ones stored for a later, larger run
+    save_kept(&[agents.as_slice(), surplus.as_slice()].concat())?;
+
+    let root = std::env::temp_dir().join("m87-sim");
+    info!(
+        "Running {} simulated devices ({} reused), work dirs in {}",
+        agents.len(),
+        reused,
+        root.display()
+    );
+    for agent in &agents {
+        println!("{}  m87-sim-{}", agent.short_id, agent.short_id);
+    }
+    println!("Press Ctrl+C to stop");
+
+    let tasks: Vec<_> = agents
+        .iter()
+        .cloned()
+        .map(|agent| {
+            let target = target.clone();
+            let work_dir = root.join(&agent.short_id);
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = run_device(&agent, &target, &work_dir) => {}
+                    _ = SHUTDOWN.cancelled() => {}
+                }
+            })
+        })
+        .collect();
+    SHUTDOWN.cancelled().await;
+    for task in tasks {
+        let _ = task.await;
+    }
+
+    if !opts.keep {
+        info!("Deleting {} simulated devices", agents.len());
+        for agent in &agents {
+            if let Err(e) = server::delete_device(
+                &target.api_url,
+                &target.token,
+                target.trust,
+                &agent.device_id,
+            )
+            .await
+            {
+                warn!(
+                    "Failed to delete simulated device {}: {:#}",
+                    agent.short_id, e
+                );
+            }
+        }
+        save_kept(&surplus)?;
+        let _ = std::fs::remove_dir_all(&root);
+    }
+    Ok(())
+}
+
+fn kept_path() -> Result<PathBuf> {
+    Ok(Config::m87_config_dir()?.join("simulated.json"))
+}
+
+fn load_kept() -> Result<Vec<SimAgent>> {
+    let path = kept_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = std::fs::read_to_string(&path)?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_kept(agents: &[SimAgent]) -> Result<()> {
+    let path = kept_path()?;
+    if agents.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(agents)?)?;
+    Ok(())
+}
+
+/// Keep a device online, reconnecting when its tunnel drops.
+async fn run_device(agent: &SimAgent, target: &Target, work_dir: &Path) {
+    loop {
+        match connect(agent, &target.host, target.trust).await {
+            Ok((_endpoint, conn)) => {
+                tokio::spawn(refuse_streams(conn.clone()));
+                if let Err(e) = heartbeat(&conn, agent, work_dir).await {
+                    debug!("Simulated device {} disconnected: {:#}", agent.short_id, e);
+                }
+                conn.close(0u32.into(), b"simulated device stopped");
+            }
+            Err(e) => warn!(
+                "Simulated device {} failed to connect: {:#}",
+                agent.short_id, e
+            ),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Simulated devices have no shell, logs or files to serve.
+async fn refuse_streams(conn: quinn::Connection) {
+    while let Ok((mut send, _recv)) = conn.accept_bi().await {
+        let _ = send
+            .write_all(b"UNSUPPORTED: this is a simulated device\n")
+            .await;
+        let _ = send.finish();
+    }
+}
+
+struct HeartbeatState {
+    last_instruction_hash: String,
+    interval_secs: u64,
+}
+
+/// Heartbeat over the control tunnel like the runtime does, applying target
+/// revisions and sending their reports with the next heartbeats.
+async fn heartbeat(conn: &quinn::Connection, agent: &SimAgent, work_dir: &Path) -> Result<()> {
+    let (mut send, mut recv) = conn.open_bi().await?;
+    send.write_all(&[0x01]).await?;
+
+    let state = Arc::new(Mutex::new(HeartbeatState {
+        last_instruction_hash: String::new(),
+        interval_secs: HEARTBEAT_INTERVAL_SECS,
+    }));
+    let (report_tx, mut report_rx) = mpsc::unbounded_channel();
+
+    let receiver = tokio::spawn({
+        let state = state.clone();
+        let work_dir = work_dir.to_path_buf();
+        let short_id = agent.short_id.clone();
+        async move {
+            while let Ok(resp) = read_msg::<HeartbeatResponse>(&mut recv).await {
+                if let Some(message) = &resp.upgrade_required {
+                    warn!("Server refused simulated device {}: {}", short_id, message);
+                    continue;
+                }
+                let mut st = state.lock().await;
+                if let Some(interval) = resp.config.and_then(|c| c.heartbeat_interval_secs) {
+                    st.interval_secs = interval as u64;
+                }
+                if let Some(revision) = resp.target_revision {
+                    info!(
+                        "Simulated device {} received revision {}",
+                        short_id,
+                        revision.id.as_deref().unwrap_or("-")
+                    );
+                    for report in apply_revision(&work_dir, &revision).await {
+                        let _ = report_tx.send(report);
+                    }
+                }
+                st.last_instruction_hash = resp.instruction_hash;
+            }
+        }
+    });
+
+    let mut req = HeartbeatRequest {
+        last_instruction_hash: String::new(),
+        client_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+        system_info: Some(system_info(&format!("m87-sim-{}", agent.short_id))),
+        protocol_version: Some(PROTOCOL_VERSION),
+        ..Default::default()
+    };
+    let res = loop {
+        if let Err(e) = write_msg(&mut send, &req).await {
+            break Err(e);
+        }
+        let interval = state.lock().await.interval_secs;
+
+        let deploy_report = tokio::select! {
+            report = report_rx.recv() => match report {
+                Some(report) => Some(report),
+                // the receiver stops when the server ends the control stream
+                None => break Err(anyhow::anyhow!("control stream closed")),
+            },
+            _ = tokio::time::sleep(Duration::from_secs(interval)) => None,
+            res = conn.closed() => break Err(res.into()),
+        };
+        req = HeartbeatRequest {
+            last_instruction_hash: state.lock().await.last_instruction_hash.clone(),
+            idempotency_key: deploy_report.as_ref().map(|_| Ulid::new().to_string()),
+            deploy_report,
+            protocol_version: Some(PROTOCOL_VERSION),
+            ..Default::default()
+        };
+    };
+    receiver.abort();
+    res
+}
+
+/// Materialize the files of every enabled service and job under `work_dir`,
+/// replacing the previous revision, and report them successful.
+async fn apply_revision(work_dir: &Path, revision: &DeploymentRevision) -> Vec<DeployReportKind> {
+    let revision_id = revision.id.clone().unwrap_or_default();
+    let _ = tokio::fs::remove_dir_all(work_dir).await;
+
+    let mut reports = Vec::new();
+    let mut failed = None;
+    for job in &revision.jobs {
+        if !job.enabled || job.run_type == RunType::Observe {
+            continue;
+        }
+        let (outcome, error) = match write_files(&work_dir.join(&job.id), &job.files).await {
+            Ok(()) => (Outcome::Success, None),
+            Err(e) => {
+                failed = Some(format!("{}: {:#}", job.id, e));
+                (Outcome::Failed, Some(format!("{:#}", e)))
+            }
+        };
+        reports.push(DeployReportKind::RunReport(RunReport {
+            run_id: job.id.clone(),
+            revision_id: revision_id.clone(),
+            outcome,
+            report_time: now_ms(),
+            error,
+        }));
+    }
+    reports.push(DeployReportKind::DeploymentRevisionReport(
+        DeploymentRevisionReport {
+            revision_id,
+            outcome: if failed.is_some() {
+                Outcome::Failed
+            } else {
+                Outcome::Success
+            },
+            dirty: false,
+            error: failed,
+        },
+    ));
+    reports
+}
+
+async fn write_files(dir: &Path, files: &std::collections::BTreeMap<String, String>) -> Result<()> {
+    tokio::fs::create_dir_all(dir).await?;
+    for (rel, content) in files {
+        // files land on the developer's machine, so keep them in the work dir
+        let inside = Path::new(rel)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        if !inside {
+            anyhow::bail!("file path {} leaves the work dir", rel);
+        }
+        let path = dir.join(rel);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, content).await?;
+    }
+    Ok(())
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_apply_revision_writes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let revision: DeploymentRevision = serde_json::from_value(serde_json::json!({
+            "id": "rev1",
+            "jobs": [
+                {"id": "app", "type": "service", "enabled": true,
+                 "files": {"conf/app.yml": "port: 80"}},
+                {"id": "evil", "type": "job", "enabled": true,
+                 "files": {"../escape": "x"}},
+                {"id": "off", "type": "job", "enabled": false}
+            ]
+        }))
+        .unwrap();
+
+        let reports = apply_revision(dir.path(), &revision).await;
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("app/conf/app.yml")).unwrap(),
+            "port: 80"
+        );
+        assert!(!dir.path().join("escape").exists());
+        assert_eq!(reports.len(), 3);
+        match &reports[2] {
+            DeployReportKind::DeploymentRevisionReport(r) => {
+                assert_eq!(r.revision_id, "rev1");
+                assert!(matches!(r.outcome, Outcome::Failed));
+            }
+            other => panic!("unexpected report {:?}", other),
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use anyhow::{anyhow, Result};
 use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 use crate::config::Config;
+use crate::util::proxy;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Auth0Claims {
@@ -39,7 +39,7 @@ pub async fn validate_token(token: &str) -> Result<Auth0Claims> {
         .kid
         .ok_or_else(|| anyhow!("Token missing 'kid' field"))?;
 
-    let jwks: Jwks = Client::new()
+    let jwks: Jwks = proxy::client()?
         .get(&jwks_url)
         .send()
         .await
@@ -0,0 +1,411 @@
+//! zstd compression of logs, support bundle and file transfer streams
+//! (`compression` in the config), for devices on metered links.
+//!
+//! The CLI lists the algorithms it reads in the stream header under
+//! [`HEADER_KEY`]. A runtime that compresses the stream answers with
+//! [`REPLY_MAGIC`] before anything else; from then on both directions are one
+//! zstd stream each, flushed with every write so the other side can decode
+//! what was sent so far. Runtimes that do not answer get a plain stream.
+//! Interactive streams are never compressed: their writes are too small to
+//! gain anything, and compressing keystrokes next to secrets under end-to-end
+//! encryption would leak through the sizes.
+
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context as TaskContext, Poll, ready};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use zstd::stream::raw::{Decoder, Encoder, InBuffer, Operation, OutBuffer};
+
+use crate::config::Config;
+use crate::streams::e2e::StreamIo;
+use crate::streams::stream_type::StreamType;
+use crate::util::lan::direct_target;
+
+/// Stream header field listing the algorithms the CLI reads
+pub const HEADER_KEY: &str = "compress";
+pub const ZSTD: &str = "zstd";
+/// First bytes a runtime sends on a stream it compresses
+pub const REPLY_MAGIC: &[u8; 8] = b"M87ZST1\n";
+/// zstd level of the CLI; runtimes use the level of their bandwidth profile
+const LEVEL: i32 = 3;
+/// Largest payload compressed per write
+const MAX_CHUNK: usize = 64 * 1024;
+/// How long the CLI waits for the runtime's reply before it assumes an
+/// older runtime
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Payload of compressed streams of this process, and its bytes on the wire
+static PAYLOAD_BYTES: AtomicU64 = AtomicU64::new(0);
+static WIRE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Whether streams of this type are compressed when enabled.
+pub fn applies_to(stream_type: &StreamType) -> bool {
+    matches!(
+        stream_type,
+        StreamType::Logs { .. }
+            | StreamType::SupportBundle { .. }
+            | StreamType::Ssh { transfer: true, .. }
+    )
+}
+
+/// Whether the CLI asks for compression of a `stream_type` stream. Direct
+/// LAN connections are fast enough without.
+pub fn wanted(stream_type: &StreamType, config: &Config) -> bool {
+    config.compression && applies_to(stream_type) && direct_target().is_none()
+}
+
+/// Ask for compression in a stream header.
+pub fn request(header: &mut serde_json::Value) {
+    if let Some(fields) = header.as_object_mut() {
+        fields.insert(HEADER_KEY.to_string(), serde_json::json!([ZSTD]));
+    }
+}
+
+/// Whether a stream header asks for zstd.
+pub fn requested(header: &serde_json::Value) -> bool {
+    header
+        .get(HEADER_KEY)
+        .and_then(|algorithms| algorithms.as_array())
+        .is_some_and(|algorithms| algorithms.iter().any(|a| a.as_str() == Some(ZSTD)))
+}
+
+/// Payload bytes of the compressed streams of this process and the bytes
+/// they took on the wire.
+pub fn totals() -> (u64, u64) {
+    (
+        PAYLOAD_BYTES.load(Ordering::Relaxed),
+        WIRE_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+/// Compression ratio, e.g. `4.2x`, of `payload` bytes sent as `wire` bytes.
+pub fn ratio(payload: u64, wire: u64) -> String {
+    if wire == 0 {
+        return "-".to_string();
+    }
+    format!("{:.1}x", payload as f64 / wire as f64)
+}
+
+/// CLI side: wait for the runtime's answer to a header that asked for
+/// compression. Bytes other than [`REPLY_MAGIC`], e.g. a refusal or the
+/// output of an older runtime, are passed on unchanged.
+pub async fn negotiate(mut io: StreamIo, label: &'static str) -> Result<StreamIo> {
+    let mut reply = Vec::with_capacity(REPLY_MAGIC.len());
+    let mut buf = [0u8; 8];
+    let read = tokio::time::timeout(REPLY_TIMEOUT, async {
+        while reply.len() < REPLY_MAGIC.len() && REPLY_MAGIC.starts_with(&reply) {
+            let n = io.read(&mut buf[..REPLY_MAGIC.len() - reply.len()]).await?;
+            if n == 0 {
+                break;
+            }
+            reply.extend_from_slice(&buf[..n]);
+        }
+        Ok::<_, std::io::Error>(())
+    })
+    .await;
+    if let Ok(Err(e)) = read {
+        return Err(anyhow!("Failed to read the stream reply: {}", e));
+    }
+
+    let io = if reply == REPLY_MAGIC {
+        CompressedIo::new(io, label, LEVEL)?
+    } else {
+        CompressedIo::passthrough(io, reply)
+    };
+    Ok(StreamIo::Compressed(Box::new(io)))
+}
+
+/// Runtime side: compress a stream whose header asked for it at zstd `level`.
+pub async fn accept(mut io: StreamIo, label: &'static str, level: i32) -> Result<StreamIo> {
+    io.write_all(REPLY_MAGIC).await?;
+    Ok(StreamIo::Compressed(Box::new(CompressedIo::new(
+        io, label, level,
+    )?)))
+}
+
+fn broken(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct Codec {
+    encoder: Encoder<'static>,
+    decoder: Decoder<'static>,
+    /// Payload and wire bytes, sent and received
+    sent: (u64, u64),
+    received: (u64, u64),
+}
+
+impl Codec {
+    fn new(level: i32) -> std::io::Result<Self> {
+        Ok(Self {
+            encoder: Encoder::new(level)?,
+            decoder: Decoder::new()?,
+            sent: (0, 0),
+            received: (0, 0),
+        })
+    }
+
+    /// Compress `data` and flush, so the other side can decode it at once.
+    fn compress(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut buf = vec![0u8; zstd::zstd_safe::CCtx::out_size()];
+        let mut input = InBuffer::around(data);
+        while input.pos() < data.len() {
+            let mut output = OutBuffer::around(buf.as_mut_slice());
+            self.encoder.run(&mut input, &mut output)?;
+            let n = output.pos();
+            out.extend_from_slice(&buf[..n]);
+        }
+        loop {
+            let mut output = OutBuffer::around(buf.as_mut_slice());
+            let remaining = self.encoder.flush(&mut output)?;
+            let n = output.pos();
+            out.extend_from_slice(&buf[..n]);
+            if remaining == 0 {
+                break;
+            }
+        }
+        self.sent.0 += data.len() as u64;
+        self.sent.1 += out.len() as u64;
+        Ok(out)
+    }
+
+    fn decompress(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut plain = Vec::new();
+        let mut buf = vec![0u8; zstd::zstd_safe::DCtx::out_size()];
+        let mut input = InBuffer::around(data);
+        loop {
+            let mut output = OutBuffer::around(buf.as_mut_slice());
+            self.decoder
+                .run(&mut input, &mut output)
+                .map_err(|_| broken("invalid compressed stream"))?;
+            let n = output.pos();
+            plain.extend_from_slice(&buf[..n]);
+            if input.pos() == data.len() && n < buf.len() {
+                break;
+            }
+        }
+        self.received.0 += plain.len() as u64;
+        self.received.1 += data.len() as u64;
+        Ok(plain)
+    }
+}
+
+/// Stream compressed with zstd in both directions, or passed through when
+/// the runtime did not agree to compress.
+///
+/// Like `QuicIo`, a write completes once its compressed bytes were handed to
+/// the inner stream; after `Pending` the caller must retry with the same bytes.
+pub struct CompressedIo<T> {
+    inner: T,
+    codec: Option<Codec>,
+    label: &'static str,
+    /// Decoded bytes not yet returned
+    plain: Vec<u8>,
+    plain_pos: usize,
+    /// Compressed bytes being written and the payload length they carry
+    outgoing: Vec<u8>,
+    outgoing_pos: usize,
+    outgoing_len: usize,
+}
+
+impl<T> CompressedIo<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn new(inner: T, label: &'static str, level: i32) -> std::io::Result<Self> {
+        Ok(Self::with(
+            inner,
+            Some(Codec::new(level)?),
+            label,
+            Vec::new(),
+        ))
+    }
+
+    /// Uncompressed stream that first returns `read`, the bytes taken from
+    /// `inner` while waiting for the reply.
+    fn passthrough(inner: T, read: Vec<u8>) -> Self {
+        Self::with(inner, None, "", read)
+    }
+
+    fn with(inner: T, codec: Option<Codec>, label: &'static str, read: Vec<u8>) -> Self {
+        Self {
+            inner,
+            codec,
+            label,
+            plain: read,
+            plain_pos: 0,
+            outgoing: Vec::new(),
+            outgoing_pos: 0,
+            outgoing_len: 0,
+        }
+    }
+
+    fn poll_outgoing(&mut self, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        while self.outgoing_pos < self.outgoing.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.outgoing[self.outgoing_pos..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing_pos += n;
+        }
+        self.outgoing.clear();
+        self.outgoing_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Drop for CompressedIo<T> {
+    fn drop(&mut self) {
+        let Some(codec) = &self.codec else {
+            return;
+        };
+        let payload = codec.sent.0 + codec.received.0;
+        let wire = codec.sent.1 + codec.received.1;
+        PAYLOAD_BYTES.fetch_add(payload, Ordering::Relaxed);
+        WIRE_BYTES.fetch_add(wire, Ordering::Relaxed);
+        tracing::info!(
+            "{} stream: sent {} bytes as {} ({}), received {} bytes as {} ({})",
+            self.label,
+            codec.sent.0,
+            codec.sent.1,
+            ratio(codec.sent.0, codec.sent.1),
+            codec.received.0,
+            codec.received.1,
+            ratio(codec.received.0, codec.received.1)
+        );
+    }
+}
+
+impl<T> AsyncRead for CompressedIo<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = buf.remaining().min(this.plain.len() - this.plain_pos);
+                buf.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            let Some(codec) = this.codec.as_mut() else {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            };
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.plain = codec.decompress(chunk_buf.filled())?;
+            this.plain_pos = 0;
+        }
+    }
+}
+
+impl<T> AsyncWrite for CompressedIo<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        let Some(codec) = this.codec.as_mut() else {
+            return Pin::new(&mut this.inner).poll_write(cx, data);
+        };
+        if this.outgoing.is_empty() {
+            if data.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let len = data.len().min(MAX_CHUNK);
+            this.outgoing = codec.compress(&data[..len])?;
+            this.outgoing_len = len;
+        }
+        ready!(this.poll_outgoing(cx))?;
+        Poll::Ready(Ok(std::mem::take(&mut this.outgoing_len)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_outgoing(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_outgoing(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_compressed_round_trip() {
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        let mut cli = CompressedIo::new(a, "test", LEVEL).unwrap();
+        let mut device = CompressedIo::new(b, "test", 12).unwrap();
+
+        let logs: Vec<u8> = (0..2000)
+            .flat_map(|i| {
+                format!("2026-10-16T10:00:00Z INFO service started {}\n", i % 7).into_bytes()
+            })
+            .collect();
+        let sent = logs.clone();
+        let writer = tokio::spawn(async move {
+            for line in sent.chunks(50) {
+                device.write_all(line).await.unwrap();
+            }
+            device.shutdown().await.unwrap();
+            device.codec.as_ref().unwrap().sent
+        });
+
+        let mut received = Vec::new();
+        cli.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, logs);
+        let (payload, wire) = writer.await.unwrap();
+        assert_eq!(payload, logs.len() as u64);
+        assert!(wire * 2 < payload);
+    }
+
+    #[tokio::test]
+    async fn test_passthrough_replays_reply() {
+        let (a, mut b) = tokio::io::duplex(1024);
+        b.write_all(b"PERMISSION_DENIED: no\n").await.unwrap();
+        drop(b);
+
+        let mut prefix = vec![0u8; 3];
+        let mut a = a;
+        a.read_exact(&mut prefix).await.unwrap();
+        let mut io = CompressedIo::passthrough(a, prefix);
+        let mut text = String::new();
+        io.read_to_string(&mut text).await.unwrap();
+        assert_eq!(text, "PERMISSION_DENIED: no\n");
+    }
+
+    #[test]
+    fn test_requested() {
+        let mut header = serde_json::json!({ "type": "Logs", "token": "t" });
+        assert!(!requested(&header));
+        request(&mut header);
+        assert!(requested(&header));
+        assert_eq!(ratio(420, 100), "4.2x");
+    }
+}
@@ -0,0 +1,890 @@
+//! End-to-end encryption of shell, exec and file transfer streams, so the
+//! relay only forwards ciphertext (`end_to_end` in the config).
+//!
+//! Every runtime has a static X25519 key whose public half it reports in its
+//! system info; the CLI pins it once the user confirmed its fingerprint on
+//! the device. Every operator has a static key
+//! too. The CLI sends its operator key and an ephemeral key with the stream
+//! header, the runtime answers with [`REPLY_MAGIC`] and an ephemeral key of
+//! its own, and both derive one ChaCha20-Poly1305 key per direction from the
+//! four Diffie-Hellman results. The runtime proves it holds the pinned key
+//! with a first, empty frame. Payload travels in frames of a u32 length and
+//! the sealed bytes, numbered per direction. A side that is done sends a
+//! sealed close frame carrying its frame count, so a stream cut off by the
+//! relay ends in an error rather than looking complete.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll, ready};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::config::Config;
+use crate::devices::{self, ResolvedDevice};
+use crate::streams::compress::CompressedIo;
+use crate::streams::quic::QuicIo;
+use crate::streams::stream_type::StreamType;
+use crate::util::private_file::write_private;
+
+/// Stream header field carrying the CLI's [`Hello`]
+pub const HEADER_KEY: &str = "e2e";
+/// First bytes a runtime sends on a stream it encrypts
+pub const REPLY_MAGIC: &[u8; 8] = b"M87E2E1\n";
+/// Largest plaintext per frame
+const MAX_FRAME: usize = 16 * 1024;
+const TAG_LEN: usize = 16;
+const KDF_INFO: &[u8] = b"m87 e2e v1";
+/// Set in the length of the close frame
+const CLOSE_FLAG: u32 = 1 << 31;
+/// Associated data of the close frame, so no data frame passes for one
+const CLOSE_AAD: &[u8] = b"m87 e2e close";
+/// How long the CLI waits for the runtime's reply
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEVICE_KEY_FILE: &str = "e2e_device.key";
+const OPERATOR_KEY_FILE: &str = "e2e_operator.key";
+const PINNED_KEYS_FILE: &str = "e2e_known_devices.json";
+
+/// Public keys the CLI sends with the stream header, base64 encoded.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Hello {
+    pub operator_key: String,
+    pub ephemeral_key: String,
+}
+
+/// Secrets of the CLI between sending its [`Hello`] and the runtime's reply.
+pub struct ClientHandshake {
+    operator: StaticSecret,
+    ephemeral: StaticSecret,
+}
+
+/// Whether streams of this type are encrypted end to end when enabled.
+pub fn applies_to(stream_type: &StreamType) -> bool {
+    matches!(
+        stream_type,
+        StreamType::Terminal { .. } | StreamType::Exec { .. } | StreamType::Ssh { .. }
+    )
+}
+
+fn encode_key(key: &PublicKey) -> String {
+    STANDARD.encode(key.as_bytes())
+}
+
+fn decode_key(key: &str) -> Result<PublicKey> {
+    let bytes: [u8; 32] = STANDARD
+        .decode(key.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| anyhow!("Invalid end-to-end key"))?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// Short form of a public key for comparing it by eye: 128 bits as eight
+/// groups, e.g. `3f2a:91c0:…`.
+pub fn fingerprint(key: &str) -> String {
+    let digest = Sha256::digest(key.trim().as_bytes());
+    digest[..16]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn key_path(name: &str) -> Result<PathBuf> {
+    Ok(Config::m87_config_dir()?.join(name))
+}
+
+fn load_or_create_secret(name: &str) -> Result<StaticSecret> {
+    let path = key_path(name)?;
+    if let Ok(contents) = fs::read_to_string(&path) {
+        let bytes: [u8; 32] = STANDARD
+            .decode(contents.trim())
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| anyhow!("Invalid key in {:?}", path))?;
+        return Ok(StaticSecret::from(bytes));
+    }
+    let secret = StaticSecret::random();
+    write_private(&path, STANDARD.encode(secret.to_bytes()).as_bytes())?;
+    Ok(secret)
+}
+
+/// Public key of this runtime, created on first use.
+pub fn device_public_key() -> Result<String> {
+    Ok(encode_key(&PublicKey::from(&load_or_create_secret(
+        DEVICE_KEY_FILE,
+    )?)))
+}
+
+/// Public key identifying this operator to runtimes, created on first use.
+pub fn operator_public_key() -> Result<String> {
+    Ok(encode_key(&PublicKey::from(&load_or_create_secret(
+        OPERATOR_KEY_FILE,
+    )?)))
+}
+
+/// Device keys pinned by this CLI, by device id.
+fn load_pins() -> BTreeMap<String, String> {
+    key_path(PINNED_KEYS_FILE)
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// The pinned key of `device_id`, if any.
+pub fn pinned_key(device_id: &str) -> Option<String> {
+    load_pins().remove(device_id)
+}
+
+/// Pin `key` for `device_id`. Later streams only trust this key, whatever
+/// the server reports.
+pub fn pin_key(device_id: &str, key: &str) -> Result<()> {
+    decode_key(key)?;
+    let mut pins = load_pins();
+    pins.insert(device_id.to_string(), key.trim().to_string());
+    write_private(
+        &key_path(PINNED_KEYS_FILE)?,
+        &serde_json::to_vec_pretty(&pins)?,
+    )
+}
+
+/// Forget the pinned key of `device_id`, e.g. after the device was
+/// reinstalled. Returns false if none was pinned.
+pub fn unpin_key(device_id: &str) -> Result<bool> {
+    let mut pins = load_pins();
+    if pins.remove(device_id).is_none() {
+        return Ok(false);
+    }
+    write_private(
+        &key_path(PINNED_KEYS_FILE)?,
+        &serde_json::to_vec_pretty(&pins)?,
+    )?;
+    Ok(true)
+}
+
+/// The key streams to `device` are sealed for. Only pinned keys are used: the
+/// key the server reports is pinned once the user typed its fingerprint as
+/// `m87 local e2e-key` shows it on the device, as the server could report
+/// its own key.
+pub async fn device_key(device: &ResolvedDevice) -> Result<String> {
+    if let Some(key) = pinned_key(&device.id) {
+        return Ok(key);
+    }
+    let reported = devices::list_devices()
+        .await?
+        .into_iter()
+        .find(|d| d.id == device.id)
+        .ok_or_else(|| anyhow!("Device not found"))?;
+    let key = reported.system_info.e2e_public_key.ok_or_else(|| {
+        anyhow!(
+            "Device '{}' has not reported an end-to-end key; update its runtime",
+            reported.name
+        )
+    })?;
+    if !confirm_fingerprint(&reported.name, &key)? {
+        bail!(
+            "The end-to-end key of '{}' is not pinned. Run `m87 local e2e-key` on the device and pin the key with `m87 {} e2e pin <key>`",
+            reported.name,
+            reported.name
+        );
+    }
+    pin_key(&device.id, &key)?;
+    tracing::info!(
+        "Pinned the end-to-end key of '{}' ({})",
+        reported.name,
+        fingerprint(&key)
+    );
+    Ok(key)
+}
+
+/// Ask for the fingerprint the device shows and check it against `key`.
+/// Without a terminal nothing is confirmed.
+fn confirm_fingerprint(name: &str, key: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    eprintln!(
+        "The end-to-end key of '{}' is not pinned yet. Run `m87 local e2e-key` on the device.",
+        name
+    );
+    eprint!("Fingerprint it shows (Enter to cancel): ");
+    std::io::stderr().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let typed = input.trim();
+    if typed.is_empty() {
+        return Ok(false);
+    }
+    if !same_fingerprint(typed, &fingerprint(key)) {
+        bail!(
+            "Fingerprint {} does not match the key the server reports for '{}' ({}); nothing was pinned",
+            typed,
+            name,
+            fingerprint(key)
+        );
+    }
+    Ok(true)
+}
+
+/// Fingerprints compared ignoring case and separators, as typed by hand.
+fn same_fingerprint(typed: &str, expected: &str) -> bool {
+    let digits = |s: &str| {
+        s.chars()
+            .filter(char::is_ascii_hexdigit)
+            .map(|c| c.to_ascii_lowercase())
+            .collect::<String>()
+    };
+    let typed = digits(typed);
+    !typed.is_empty() && typed == digits(expected)
+}
+
+/// Start a handshake: the [`Hello`] to send in the stream header and the
+/// secrets to finish it with.
+pub fn client_hello() -> Result<(Hello, ClientHandshake)> {
+    let operator = load_or_create_secret(OPERATOR_KEY_FILE)?;
+    let ephemeral = StaticSecret::random();
+    let hello = Hello {
+        operator_key: encode_key(&PublicKey::from(&operator)),
+        ephemeral_key: encode_key(&PublicKey::from(&ephemeral)),
+    };
+    Ok((
+        hello,
+        ClientHandshake {
+            operator,
+            ephemeral,
+        },
+    ))
+}
+
+/// The stream header of `stream_type` with `hello` added.
+pub fn header_with_hello(stream_type: &StreamType, hello: &Hello) -> Result<serde_json::Value> {
+    let mut header = serde_json::to_value(stream_type)?;
+    header
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("stream header is not an object"))?
+        .insert(HEADER_KEY.to_string(), serde_json::to_value(hello)?);
+    Ok(header)
+}
+
+/// The [`Hello`] in a stream header, if the CLI asked for encryption.
+pub fn hello_from_header(header: &serde_json::Value) -> Option<Hello> {
+    serde_json::from_value(header.get(HEADER_KEY)?.clone()).ok()
+}
+
+/// Directional keys from the four DH results `dh`, bound to the public keys
+/// in `transcript` (device, operator, CLI ephemeral, device ephemeral).
+fn derive_keys(dh: [[u8; 32]; 4], transcript: [&PublicKey; 4]) -> ([u8; 32], [u8; 32]) {
+    let mut salt = Sha256::new();
+    for key in transcript {
+        salt.update(key.as_bytes());
+    }
+    let ikm = dh.concat();
+    let mut okm = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(&salt.finalize()), &ikm)
+        .expand(KDF_INFO, &mut okm)
+        .expect("64 bytes is a valid HKDF-SHA256 output length");
+    let mut to_device = [0u8; 32];
+    let mut to_client = [0u8; 32];
+    to_device.copy_from_slice(&okm[..32]);
+    to_client.copy_from_slice(&okm[32..]);
+    (to_device, to_client)
+}
+
+fn diffie_hellman(secret: &StaticSecret, public: &PublicKey) -> Result<[u8; 32]> {
+    let shared = secret.diffie_hellman(public);
+    if !shared.was_contributory() {
+        bail!("Invalid end-to-end key");
+    }
+    Ok(shared.to_bytes())
+}
+
+/// Finish the CLI side of the handshake on `io`, after the header carrying
+/// the [`Hello`] of `handshake` was sent. `device_key` is the pinned key.
+pub async fn seal_client<T>(
+    mut io: T,
+    handshake: ClientHandshake,
+    device_key: &str,
+) -> Result<SealedIo<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let device_static = decode_key(device_key)?;
+    let mut reply = [0u8; 40];
+    let read = tokio::time::timeout(HANDSHAKE_TIMEOUT, io.read_exact(&mut reply))
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "The device did not answer the end-to-end handshake; its runtime may be too old"
+            )
+        })?;
+    if read.is_err() || !reply.starts_with(REPLY_MAGIC) {
+        // refusals such as PERMISSION_DENIED are sent instead of the reply
+        let mut text = reply.to_vec();
+        let mut rest = Vec::new();
+        let _ = tokio::time::timeout(
+            Duration::from_secs(1),
+            (&mut io).take(1024).read_to_end(&mut rest),
+        )
+        .await;
+        text.extend_from_slice(&rest);
+        let text = String::from_utf8_lossy(&text);
+        let text = text.trim_matches(char::from(0)).trim();
+        if text.is_empty() {
+            bail!("The device closed the stream");
+        }
+        bail!("The device did not start end-to-end encryption: {}", text);
+    }
+    let mut device_ephemeral = [0u8; 32];
+    device_ephemeral.copy_from_slice(&reply[REPLY_MAGIC.len()..]);
+    let device_ephemeral = PublicKey::from(device_ephemeral);
+
+    let dh = [
+        diffie_hellman(&handshake.ephemeral, &device_static)?,
+        diffie_hellman(&handshake.operator, &device_static)?,
+        diffie_hellman(&handshake.ephemeral, &device_ephemeral)?,
+        diffie_hellman(&handshake.operator, &device_ephemeral)?,
+    ];
+    let (to_device, to_client) = derive_keys(
+        dh,
+        [
+            &device_static,
+            &PublicKey::from(&handshake.operator),
+            &PublicKey::from(&handshake.ephemeral),
+            &device_ephemeral,
+        ],
+    );
+
+    let mut sealed = SealedIo::new(io, &to_device, &to_client);
+    // only the holder of the pinned key can seal the confirmation
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, sealed.read_frame()).await {
+        Ok(Ok(Some(confirmation))) if confirmation.is_empty() => Ok(sealed),
+        _ => bail!(
+            "The device could not prove it holds its pinned end-to-end key. If it was \
+             reinstalled, forget the old key with `m87 <device> e2e forget`"
+        ),
+    }
+}
+
+/// Runtime side of the handshake for a stream whose header carried `hello`.
+pub async fn seal_device<T>(io: T, hello: &Hello) -> Result<SealedIo<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    seal_device_with(io, hello, &load_or_create_secret(DEVICE_KEY_FILE)?).await
+}
+
+async fn seal_device_with<T>(mut io: T, hello: &Hello, device: &StaticSecret) -> Result<SealedIo<T>>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let operator = decode_key(&hello.operator_key)?;
+    let client_ephemeral = decode_key(&hello.ephemeral_key)?;
+    let ephemeral = StaticSecret::random();
+
+    let dh = [
+        diffie_hellman(device, &client_ephemeral)?,
+        diffie_hellman(device, &operator)?,
+        diffie_hellman(&ephemeral, &client_ephemeral)?,
+        diffie_hellman(&ephemeral, &operator)?,
+    ];
+    let (to_device, to_client) = derive_keys(
+        dh,
+        [
+            &PublicKey::from(device),
+            &operator,
+            &client_ephemeral,
+            &PublicKey::from(&ephemeral),
+        ],
+    );
+
+    let reply = [
+        REPLY_MAGIC.as_slice(),
+        PublicKey::from(&ephemeral).as_bytes(),
+    ]
+    .concat();
+    io.write_all(&reply).await?;
+    let mut sealed = SealedIo::new(io, &to_client, &to_device);
+    sealed.write_frame(&[]).await?;
+    Ok(sealed)
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&counter.to_be_bytes());
+    Nonce::from(nonce)
+}
+
+fn broken(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// A frame opened by [`SealedIo`].
+enum Frame {
+    Data(Vec<u8>),
+    /// The peer is done writing
+    Close,
+}
+
+/// Stream encrypted with the keys of a finished handshake.
+///
+/// Like `QuicIo`, a write completes once its frame was handed to the inner
+/// stream; after `Pending` the caller must retry with the same bytes.
+pub struct SealedIo<T> {
+    inner: T,
+    seal: ChaCha20Poly1305,
+    sealed_count: u64,
+    open: ChaCha20Poly1305,
+    opened_count: u64,
+    /// Ciphertext read but not yet opened
+    incoming: Vec<u8>,
+    /// Opened bytes not yet returned
+    plain: Vec<u8>,
+    plain_pos: usize,
+    /// Frame being written and the plaintext length it carries
+    outgoing: Vec<u8>,
+    outgoing_pos: usize,
+    outgoing_len: usize,
+    /// Whether our close frame was queued
+    closing: bool,
+    /// Whether the peer's close frame arrived
+    peer_closed: bool,
+}
+
+impl<T> SealedIo<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn new(inner: T, seal_key: &[u8; 32], open_key: &[u8; 32]) -> Self {
+        Self {
+            inner,
+            seal: ChaCha20Poly1305::new(Key::from_slice(seal_key)),
+            sealed_count: 0,
+            open: ChaCha20Poly1305::new(Key::from_slice(open_key)),
+            opened_count: 0,
+            incoming: Vec::new(),
+            plain: Vec::new(),
+            plain_pos: 0,
+            outgoing: Vec::new(),
+            outgoing_pos: 0,
+            outgoing_len: 0,
+            closing: false,
+            peer_closed: false,
+        }
+    }
+
+    fn seal_frame(&mut self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let sealed = self
+            .seal
+            .encrypt(&nonce(self.sealed_count), data)
+            .map_err(|_| broken("end-to-end encryption failed"))?;
+        self.sealed_count += 1;
+        Ok([(sealed.len() as u32).to_be_bytes().as_slice(), &sealed].concat())
+    }
+
+    /// The close frame: the number of frames sent before it, sealed with
+    /// [`CLOSE_AAD`].
+    fn seal_close(&mut self) -> std::io::Result<Vec<u8>> {
+        let payload = Payload {
+            msg: &self.sealed_count.to_be_bytes(),
+            aad: CLOSE_AAD,
+        };
+        let sealed = self
+            .seal
+            .encrypt(&nonce(self.sealed_count), payload)
+            .map_err(|_| broken("end-to-end encryption failed"))?;
+        self.sealed_count += 1;
+        let len = sealed.len() as u32 | CLOSE_FLAG;
+        Ok([len.to_be_bytes().as_slice(), &sealed].concat())
+    }
+
+    /// Open the next complete frame in `incoming`, if there is one.
+    fn open_frame(&mut self) -> std::io::Result<Option<Frame>> {
+        if self.incoming.len() < 4 {
+            return Ok(None);
+        }
+        if self.peer_closed {
+            return Err(broken("end-to-end frame after the close frame"));
+        }
+        let len = u32::from_be_bytes([
+            self.incoming[0],
+            self.incoming[1],
+            self.incoming[2],
+            self.incoming[3],
+        ]);
+        let close = len & CLOSE_FLAG != 0;
+        let len = (len & !CLOSE_FLAG) as usize;
+        if !(TAG_LEN..=MAX_FRAME + TAG_LEN).contains(&len) {
+            return Err(broken("invalid end-to-end frame"));
+        }
+        if self.incoming.len() < 4 + len {
+            return Ok(None);
+        }
+        let payload = Payload {
+            msg: &self.incoming[4..4 + len],
+            aad: if close { CLOSE_AAD } else { &[] },
+        };
+        let plain = self
+            .open
+            .decrypt(&nonce(self.opened_count), payload)
+            .map_err(|_| broken("end-to-end decryption failed"))?;
+        let count = self.opened_count;
+        self.opened_count += 1;
+        self.incoming.drain(..4 + len);
+        if !close {
+            return Ok(Some(Frame::Data(plain)));
+        }
+        if plain != count.to_be_bytes() {
+            return Err(broken("end-to-end close frame with a wrong frame count"));
+        }
+        self.peer_closed = true;
+        Ok(Some(Frame::Close))
+    }
+
+    /// Read one whole frame; `None` once the peer closed the stream.
+    async fn read_frame(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.open_frame()? {
+                Some(Frame::Data(plain)) => return Ok(Some(plain)),
+                Some(Frame::Close) => return Ok(None),
+                None => {}
+            }
+            let n = self.inner.read(&mut buf).await?;
+            if n == 0 {
+                return Err(cut_off());
+            }
+            self.incoming.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    async fn write_frame(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let frame = self.seal_frame(data)?;
+        self.inner.write_all(&frame).await
+    }
+
+    fn poll_outgoing(&mut self, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        while self.outgoing_pos < self.outgoing.len() {
+            let n = ready!(
+                Pin::new(&mut self.inner).poll_write(cx, &self.outgoing[self.outgoing_pos..])
+            )?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.outgoing_pos += n;
+        }
+        self.outgoing.clear();
+        self.outgoing_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> AsyncRead for SealedIo<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.plain_pos < this.plain.len() {
+                let n = buf.remaining().min(this.plain.len() - this.plain_pos);
+                buf.put_slice(&this.plain[this.plain_pos..this.plain_pos + n]);
+                this.plain_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            match this.open_frame()? {
+                Some(Frame::Data(plain)) => {
+                    this.plain = plain;
+                    this.plain_pos = 0;
+                    continue;
+                }
+                Some(Frame::Close) => return Poll::Ready(Ok(())),
+                None if this.peer_closed => return Poll::Ready(Ok(())),
+                None => {}
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                return Poll::Ready(Err(cut_off()));
+            }
+            this.incoming.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<T> AsyncWrite for SealedIo<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if this.closing {
+            return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+        }
+        if this.outgoing.is_empty() {
+            if data.is_empty() {
+                return Poll::Ready(Ok(0));
+            }
+            let len = data.len().min(MAX_FRAME);
+            this.outgoing = this.seal_frame(&data[..len])?;
+            this.outgoing_len = len;
+        }
+        ready!(this.poll_outgoing(cx))?;
+        Poll::Ready(Ok(std::mem::take(&mut this.outgoing_len)))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_outgoing(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_outgoing(cx))?;
+        if !this.closing {
+            this.outgoing = this.seal_close()?;
+            this.closing = true;
+            ready!(this.poll_outgoing(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// End of the inner stream without the peer's close frame.
+fn cut_off() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        "end-to-end stream ended without its close frame",
+    )
+}
+
+/// A shell, exec, ssh, logs or support bundle stream, encrypted end to end
+/// or not.
+pub enum StreamIo {
+    Plain(QuicIo),
+    Sealed(Box<SealedIo<QuicIo>>),
+    /// Relayed by the device's warm connection helper
+    #[cfg(unix)]
+    Warm(UnixStream),
+    #[cfg(unix)]
+    WarmSealed(Box<SealedIo<UnixStream>>),
+    /// Any of the above, zstd compressed (see [`crate::streams::compress`])
+    Compressed(Box<CompressedIo<StreamIo>>),
+}
+
+impl From<QuicIo> for StreamIo {
+    fn from(io: QuicIo) -> Self {
+        StreamIo::Plain(io)
+    }
+}
+
+impl From<SealedIo<QuicIo>> for StreamIo {
+    fn from(io: SealedIo<QuicIo>) -> Self {
+        StreamIo::Sealed(Box::new(io))
+    }
+}
+
+impl AsyncRead for StreamIo {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            StreamIo::Plain(io) => Pin::new(io).poll_read(cx, buf),
+            StreamIo::Sealed(io) => Pin::new(io.as_mut()).poll_read(cx, buf),
+            #[cfg(unix)]
+            StreamIo::Warm(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(unix)]
+            StreamIo::WarmSealed(io) => Pin::new(io.as_mut()).poll_read(cx, buf),
+            StreamIo::Compressed(io) => Pin::new(io.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for StreamIo {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut TaskContext<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            StreamIo::Plain(io) => Pin::new(io).poll_write(cx, data),
+            StreamIo::Sealed(io) => Pin::new(io.as_mut()).poll_write(cx, data),
+            #[cfg(unix)]
+            StreamIo::Warm(io) => Pin::new(io).poll_write(cx, data),
+            #[cfg(unix)]
+            StreamIo::WarmSealed(io) => Pin::new(io.as_mut()).poll_write(cx, data),
+            StreamIo::Compressed(io) => Pin::new(io.as_mut()).poll_write(cx, data),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            StreamIo::Plain(io) => Pin::new(io).poll_flush(cx),
+            StreamIo::Sealed(io) => Pin::new(io.as_mut()).poll_flush(cx),
+            #[cfg(unix)]
+            StreamIo::Warm(io) => Pin::new(io).poll_flush(cx),
+            #[cfg(unix)]
+            StreamIo::WarmSealed(io) => Pin::new(io.as_mut()).poll_flush(cx),
+            StreamIo::Compressed(io) => Pin::new(io.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            StreamIo::Plain(io) => Pin::new(io).poll_shutdown(cx),
+            StreamIo::Sealed(io) => Pin::new(io.as_mut()).poll_shutdown(cx),
+            #[cfg(unix)]
+            StreamIo::Warm(io) => Pin::new(io).poll_shutdown(cx),
+            #[cfg(unix)]
+            StreamIo::WarmSealed(io) => Pin::new(io.as_mut()).poll_shutdown(cx),
+            StreamIo::Compressed(io) => Pin::new(io.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello_for(handshake: &ClientHandshake) -> Hello {
+        Hello {
+            operator_key: encode_key(&PublicKey::from(&handshake.operator)),
+            ephemeral_key: encode_key(&PublicKey::from(&handshake.ephemeral)),
+        }
+    }
+
+    fn handshake() -> ClientHandshake {
+        ClientHandshake {
+            operator: StaticSecret::random(),
+            ephemeral: StaticSecret::random(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sealed_round_trip() {
+        let (client_io, device_io) = tokio::io::duplex(64 * 1024);
+        let device = StaticSecret::random();
+        let device_key = encode_key(&PublicKey::from(&device));
+        let client = handshake();
+        let hello = hello_for(&client);
+
+        let device_side = tokio::spawn(async move {
+            let mut io = seal_device_with(device_io, &hello, &device).await.unwrap();
+            let mut buf = vec![0u8; 5];
+            io.read_exact(&mut buf).await.unwrap();
+            io.write_all(&vec![7u8; 40_000]).await.unwrap();
+            io.shutdown().await.unwrap();
+            buf
+        });
+
+        let mut io = seal_client(client_io, client, &device_key).await.unwrap();
+        io.write_all(b"hello").await.unwrap();
+        io.flush().await.unwrap();
+        let mut reply = Vec::new();
+        io.read_to_end(&mut reply).await.unwrap();
+
+        assert_eq!(device_side.await.unwrap(), b"hello");
+        assert_eq!(reply, vec![7u8; 40_000]);
+    }
+
+    #[tokio::test]
+    async fn test_wrong_device_key_is_refused() {
+        let (client_io, device_io) = tokio::io::duplex(64 * 1024);
+        let pinned = encode_key(&PublicKey::from(&StaticSecret::random()));
+        let client = handshake();
+        let hello = hello_for(&client);
+
+        let device_side = tokio::spawn(async move {
+            let io = seal_device_with(device_io, &hello, &StaticSecret::random()).await;
+            // keep the stream open until the client gave up
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            io.is_ok()
+        });
+
+        assert!(seal_client(client_io, client, &pinned).await.is_err());
+        assert!(device_side.await.unwrap());
+    }
+
+    #[test]
+    fn test_tampered_frame_is_refused() {
+        let (a, _) = tokio::io::duplex(64);
+        let key = [1u8; 32];
+        let mut sender = SealedIo::new(a, &key, &key);
+        let mut frame = sender.seal_frame(b"ls").unwrap();
+        let last = frame.len() - 1;
+        frame[last] ^= 1;
+
+        let (b, _) = tokio::io::duplex(64);
+        let mut receiver = SealedIo::new(b, &key, &key);
+        receiver.incoming = frame;
+        assert!(receiver.open_frame().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stream_without_close_frame_is_cut_off() {
+        let key = [1u8; 32];
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        let mut sender = SealedIo::new(a, &key, &key);
+        sender.write_all(b"partial").await.unwrap();
+        sender.flush().await.unwrap();
+        drop(sender);
+
+        let mut receiver = SealedIo::new(b, &key, &key);
+        let mut out = Vec::new();
+        let err = receiver.read_to_end(&mut out).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_data_frame_cannot_pass_for_close() {
+        let (a, _) = tokio::io::duplex(64);
+        let key = [1u8; 32];
+        let mut sender = SealedIo::new(a, &key, &key);
+        let mut frame = sender.seal_frame(&0u64.to_be_bytes()).unwrap();
+        frame[0] |= 0x80;
+
+        let (b, _) = tokio::io::duplex(64);
+        let mut receiver = SealedIo::new(b, &key, &key);
+        receiver.incoming = frame;
+        assert!(receiver.open_frame().is_err());
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let fp = fingerprint("c2VjcmV0");
+        assert_eq!(fp.len(), 39);
+        assert_eq!(fp, fingerprint(" c2VjcmV0\n"));
+        assert!(same_fingerprint(&fp.to_uppercase(), &fp));
+        assert!(same_fingerprint(&fp.replace(':', " "), &fp));
+        assert!(!same_fingerprint(&fp[..14], &fp));
+        assert!(!same_fingerprint("yes", &fp));
+    }
+}
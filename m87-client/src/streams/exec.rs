@@ -21,7 +21,10 @@ use tokio::process::Command;
 use tokio::sync::{Mutex, mpsc};
 use tokio::{select, time::Duration};
 
-use crate::streams::quic::QuicIo;
+use crate::config::Config;
+use crate::streams::e2e::StreamIo;
+use crate::streams::sessions::Session;
+use crate::util::clipboard::Osc52Filter;
 
 #[derive(Deserialize)]
 struct ExecRequest {
@@ -37,10 +40,19 @@ struct ExecResult {
 
 /// Get the user's shell, falling back to /bin/sh
 fn get_shell() -> String {
-    std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+    if let Ok(shell) = std::env::var("SHELL") {
+        return shell;
+    }
+    #[cfg(unix)]
+    if let Some(shell) = crate::util::ssh::login_shell() {
+        return shell;
+    }
+    "/bin/sh".to_string()
 }
 
-pub async fn handle_exec_io(io: QuicIo) {
+/// `approved` is the command named in the stream header, which approvers
+/// saw; older CLIs name none.
+pub async fn handle_exec_io(io: StreamIo, approved: Option<String>, session: &Session) {
     // Split into reader/writer
     let (reader, writer) = tokio::io::split(io);
     let mut reader = BufReader::new(reader);
@@ -63,16 +75,29 @@ pub async fn handle_exec_io(io: QuicIo) {
         }
     };
 
+    if approved.is_some_and(|approved| approved != config.command) {
+        let mut w = writer.lock().await;
+        let _ = w
+            .write_all(b"Invalid request: command differs from the stream header\n")
+            .await;
+        return;
+    }
+
+    session.set_detail(&config.command);
     if config.tty {
-        run_with_pty(reader, writer, config).await;
+        run_with_pty(reader, writer, config, session).await;
     } else {
-        run_piped(reader, writer, config).await;
+        run_piped(reader, writer, config, session).await;
     }
 }
 
 /// Run command with piped stdio (no PTY) - for simple commands and -i mode
-async fn run_piped<R, W>(mut reader: R, writer: Arc<Mutex<W>>, config: ExecRequest)
-where
+async fn run_piped<R, W>(
+    mut reader: R,
+    writer: Arc<Mutex<W>>,
+    config: ExecRequest,
+    session: &Session,
+) where
     R: AsyncReadExt + Unpin + Send + 'static,
     W: AsyncWriteExt + Unpin + Send + 'static,
 {
@@ -188,8 +213,14 @@ where
         }
     });
 
-    // Wait for child to exit
-    let status = child.wait().await;
+    // Wait for child to exit, or kill it when the session is terminated
+    let status = select! {
+        status = child.wait() => status,
+        _ = session.cancelled() => {
+            let _ = child.kill().await;
+            child.wait().await
+        }
+    };
 
     // Clean up tasks
     stdout_task.abort();
@@ -216,8 +247,12 @@ pub(crate) fn get_shell_for_test() -> String {
 }
 
 /// Run command with PTY - for TUI applications (vim, htop, etc.)
-async fn run_with_pty<R, W>(mut reader: R, writer: Arc<Mutex<W>>, config: ExecRequest)
-where
+async fn run_with_pty<R, W>(
+    mut reader: R,
+    writer: Arc<Mutex<W>>,
+    config: ExecRequest,
+    session: &Session,
+) where
     R: AsyncReadExt + Unpin + Send + 'static,
     W: AsyncWriteExt + Unpin + Send + 'static,
 {
@@ -284,6 +319,7 @@ where
 
     // PTY -> channel (blocking reader thread)
     let (pty_tx, mut pty_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let mut osc52 = Osc52Filter::new(Config::load().map(|c| c.clipboard).unwrap_or_default());
     tokio::task::spawn_blocking(move || {
         let mut pty_reader = pty_reader;
         let mut buf = [0u8; 4096];
@@ -323,6 +359,7 @@ where
 
             // PTY -> Client
             Some(out) = pty_rx.recv() => {
+                let out = osc52.filter(&out);
                 let mut w = writer.lock().await;
                 if w.write_all(&out).await.is_err() {
                     break 'outer;
@@ -343,6 +380,13 @@ where
                 }
             }
 
+            // Killed via `sessions kill`
+            _ = session.cancelled() => {
+                let mut w = writer.lock().await;
+                let _ = w.write_all(b"\r\nSession terminated by an operator\r\n").await;
+                break 'outer;
+            }
+
             else => break 'outer,
         }
     }
@@ -0,0 +1,17 @@
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use crate::{device::fact_collectors::get_facts, streams::quic::QuicIo};
+
+/// Write the device facts as one JSON line and close the stream.
+pub async fn handle_facts_io(refresh: bool, io: &mut QuicIo) {
+    let facts = get_facts(refresh).await;
+    match serde_json::to_string(&facts) {
+        Ok(json) => {
+            let _ = io.write_all(json.as_bytes()).await;
+            let _ = io.write_all(b"\n").await;
+        }
+        Err(e) => warn!("failed to serialize facts: {}", e),
+    }
+    let _ = io.shutdown().await;
+}
@@ -0,0 +1,16 @@
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use crate::{device::firewall_manager, streams::quic::QuicIo};
+
+/// Reply with the firewall rules the runtime manages as one JSON line.
+pub async fn handle_firewall_io(io: &mut QuicIo) {
+    match serde_json::to_string(&firewall_manager::status()) {
+        Ok(json) => {
+            let _ = io.write_all(json.as_bytes()).await;
+            let _ = io.write_all(b"\n").await;
+        }
+        Err(e) => warn!("failed to serialize firewall status: {}", e),
+    }
+    let _ = io.shutdown().await;
+}
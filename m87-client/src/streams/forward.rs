@@ -114,10 +114,26 @@ pub async fn start_udp_forward(
                     if let Some(f) = map.get(&src_addr) {
                         f.clone()
                     } else {
-                        let sock = Arc::new(UdpSocket::bind("0.0.0.0:0").await.unwrap());
-                        sock.connect((target_host.as_str(), target_port))
-                            .await
-                            .unwrap();
+                        let target_addr =
+                            match tokio::net::lookup_host((target_host.as_str(), target_port))
+                                .await
+                                .ok()
+                                .and_then(|mut addrs| addrs.next())
+                            {
+                                Some(addr) => addr,
+                                None => {
+                                    warn!("cannot resolve UDP target {}", target_host);
+                                    continue;
+                                }
+                            };
+                        // bind the family of the target, which may be IPv6-only
+                        let bind = if target_addr.is_ipv6() {
+                            "[::]:0"
+                        } else {
+                            "0.0.0.0:0"
+                        };
+                        let sock = Arc::new(UdpSocket::bind(bind).await.unwrap());
+                        sock.connect(target_addr).await.unwrap();
 
                         let f = Flow {
                             sock: sock.clone(),
@@ -0,0 +1,249 @@
+//! X11 forwarding for `m87 <device> gui`. The runtime listens on a free X
+//! display, runs the command against it and carries every connection the app
+//! makes back to the CLI, which hands it to the operator's X server.
+//!
+//! Protocol (see [`crate::streams::mux`]): command output is sent on
+//! [`OUTPUT`], [`GuiEvent`]s on [`CONTROL`] and each X connection under its own
+//! id, starting at 1.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::UnixListener;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, timeout};
+use tracing::{debug, warn};
+
+use crate::streams::mux::{CONTROL, Frame, Mux, OUTPUT, pump_frames, read_frame};
+use crate::streams::quic::QuicIo;
+use crate::streams::sessions::Session;
+use crate::streams::stream_type::GuiEvent;
+use crate::util::private_file::write_private;
+
+const X11_DIR: &str = "/tmp/.X11-unix";
+/// Displays below this are left to real X servers on the device.
+const FIRST_DISPLAY: u32 = 10;
+const LAST_DISPLAY: u32 = 99;
+
+pub async fn handle_gui_io(
+    command: String,
+    xauth_cookie: Option<String>,
+    session: &Session,
+    io: QuicIo,
+) {
+    session.set_detail(&command);
+    let (mut reader, writer) = tokio::io::split(io);
+    let (out_tx, out_rx) = mpsc::unbounded_channel::<Frame>();
+    let pump = tokio::spawn(pump_frames(writer, out_rx));
+
+    match run(command, xauth_cookie, session, &mut reader, &out_tx).await {
+        Ok(exit_code) => send_event(&out_tx, &GuiEvent::Exited { exit_code }),
+        Err(e) => send_event(&out_tx, &GuiEvent::Error { message: e }),
+    }
+
+    drop(out_tx);
+    let _ = timeout(Duration::from_secs(2), pump).await;
+}
+
+/// Run the command on a forwarded display and return its exit code.
+async fn run<R: AsyncRead + Unpin>(
+    command: String,
+    xauth_cookie: Option<String>,
+    session: &Session,
+    reader: &mut R,
+    out: &mpsc::UnboundedSender<Frame>,
+) -> Result<i32, String> {
+    let (display, socket, listener) =
+        bind_display().map_err(|e| format!("failed to open an X display: {}", e))?;
+    let display_guard = RemoveOnDrop(vec![socket]);
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c")
+        .arg(&command)
+        .env("DISPLAY", format!(":{}", display))
+        // Wayland-first toolkits would otherwise look for a compositor.
+        .env("GDK_BACKEND", "x11")
+        .env("QT_QPA_PLATFORM", "xcb")
+        .env_remove("WAYLAND_DISPLAY")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+
+    let mut xauth_guard = RemoveOnDrop(Vec::new());
+    if let Some(cookie) = xauth_cookie {
+        let cookie = decode_hex(&cookie).ok_or("invalid X authority cookie")?;
+        let path = std::env::temp_dir().join(format!("m87-xauth-{}", session.id()));
+        write_private(&path, &xauthority(display, &cookie))
+            .map_err(|e| format!("failed to write X authority file: {:#}", e))?;
+        cmd.env("XAUTHORITY", &path);
+        xauth_guard.0.push(path);
+    }
+
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("failed to start command: {}", e))?;
+    let output = [
+        child.stdout.take().map(|s| forward_output(s, out.clone())),
+        child.stderr.take().map(|s| forward_output(s, out.clone())),
+    ];
+    send_event(
+        out,
+        &GuiEvent::Started {
+            display: format!(":{}", display),
+        },
+    );
+
+    let mut mux = Mux::new(out.clone());
+    let mut next_id = 1u32;
+    let exit_code = loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    debug!("gui: X connection {}", next_id);
+                    mux.attach(next_id, stream);
+                    next_id += 1;
+                }
+                Err(e) => warn!("gui: accept failed: {}", e),
+            },
+
+            frame = read_frame(reader) => match frame {
+                Ok(Some((id, data))) if id != OUTPUT && id != CONTROL => mux.deliver(id, data),
+                Ok(Some(_)) => {}
+                // CLI went away; the app has nothing to draw on anymore.
+                Ok(None) | Err(_) => {
+                    let _ = child.kill().await;
+                    break -1;
+                }
+            },
+
+            status = child.wait() => {
+                break status.ok().and_then(|s| s.code()).unwrap_or(-1);
+            }
+
+            _ = session.cancelled() => {
+                let _ = child.kill().await;
+                return Err("session terminated".to_string());
+            }
+        }
+    };
+
+    // Deliver the rest of the output before reporting the exit.
+    for task in output.into_iter().flatten() {
+        let _ = timeout(Duration::from_secs(1), task).await;
+    }
+    drop(display_guard);
+    drop(xauth_guard);
+    Ok(exit_code)
+}
+
+/// Listen on the first free display from [`FIRST_DISPLAY`].
+fn bind_display() -> std::io::Result<(u32, PathBuf, UnixListener)> {
+    std::fs::create_dir_all(X11_DIR)?;
+    for display in FIRST_DISPLAY..=LAST_DISPLAY {
+        let socket = PathBuf::from(format!("{}/X{}", X11_DIR, display));
+        let lock = format!("/tmp/.X{}-lock", display);
+        if socket.exists() || Path::new(&lock).exists() {
+            continue;
+        }
+        match UnixListener::bind(&socket) {
+            Ok(listener) => return Ok((display, socket, listener)),
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::AddrInUse,
+        "all displays are in use",
+    ))
+}
+
+fn forward_output<R>(mut r: R, out: mpsc::UnboundedSender<Frame>) -> tokio::task::JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        loop {
+            match r.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if out.send((OUTPUT, buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn send_event(out: &mpsc::UnboundedSender<Frame>, event: &GuiEvent) {
+    if let Ok(json) = serde_json::to_vec(event) {
+        let _ = out.send((CONTROL, json));
+    }
+}
+
+/// An `.Xauthority` file with one MIT-MAGIC-COOKIE-1 entry for `display` on
+/// any host, so Xlib finds it regardless of the device's hostname.
+fn xauthority(display: u32, cookie: &[u8]) -> Vec<u8> {
+    const FAMILY_WILD: u16 = 0xffff;
+    let mut entry = FAMILY_WILD.to_be_bytes().to_vec();
+    for field in [
+        b"".as_slice(),
+        display.to_string().as_bytes(),
+        b"MIT-MAGIC-COOKIE-1",
+        cookie,
+    ] {
+        entry.extend_from_slice(&(field.len() as u16).to_be_bytes());
+        entry.extend_from_slice(field);
+    }
+    entry
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+struct RemoveOnDrop(Vec<PathBuf>);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xauthority_entry() {
+        let entry = xauthority(12, &[0xab, 0xcd]);
+        assert_eq!(
+            entry,
+            [
+                b"\xff\xff\x00\x00\x00\x0212".as_slice(),
+                b"\x00\x12MIT-MAGIC-COOKIE-1",
+                b"\x00\x02\xab\xcd",
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("00ff1A"), Some(vec![0x00, 0xff, 0x1a]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
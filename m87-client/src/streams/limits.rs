@@ -0,0 +1,147 @@
+//! Per-device caps on concurrent streams (`stream_limits` in the config), so a
+//! burst of sessions or file transfers cannot overload a small device. Streams
+//! over a limit are refused by the router and counted in the device metrics.
+
+use std::sync::Mutex;
+
+use m87_shared::metrics::StreamMetrics;
+
+use crate::config::StreamLimits;
+use crate::streams::compress;
+use crate::streams::stream_type::{StreamClass, StreamType};
+
+/// Which limit, besides the total, a stream counts against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Interactive,
+    Transfer,
+    Other,
+}
+
+impl Kind {
+    pub fn of(stream_type: &StreamType) -> Self {
+        match stream_type {
+            StreamType::Ssh { transfer: true, .. } => Kind::Transfer,
+            st if st.class() == StreamClass::Interactive => Kind::Interactive,
+            _ => Kind::Other,
+        }
+    }
+}
+
+struct Usage {
+    counts: Mutex<StreamMetrics>,
+}
+
+static USAGE: Usage = Usage {
+    counts: Mutex::new(StreamMetrics {
+        active: 0,
+        interactive: 0,
+        transfers: 0,
+        rejected: 0,
+        compressed_payload_bytes: 0,
+        compressed_wire_bytes: 0,
+    }),
+};
+
+/// Slot of an open stream. Dropping it frees the slot.
+pub struct Permit<'a> {
+    usage: &'a Usage,
+    kind: Kind,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut counts = self.usage.counts.lock().unwrap();
+        counts.active -= 1;
+        match self.kind {
+            Kind::Interactive => counts.interactive -= 1,
+            Kind::Transfer => counts.transfers -= 1,
+            Kind::Other => {}
+        }
+    }
+}
+
+impl Usage {
+    fn acquire(&self, kind: Kind, limits: &StreamLimits) -> Result<Permit<'_>, String> {
+        let mut counts = self.counts.lock().unwrap();
+        let refused = if at_limit(counts.active, limits.max_streams) {
+            Some(format!("{} streams are open", counts.active))
+        } else {
+            match kind {
+                Kind::Interactive
+                    if at_limit(counts.interactive, limits.max_interactive_sessions) =>
+                {
+                    Some(format!(
+                        "{} interactive sessions are open",
+                        counts.interactive
+                    ))
+                }
+                Kind::Transfer if at_limit(counts.transfers, limits.max_fs_transfers) => {
+                    Some(format!("{} file transfers are running", counts.transfers))
+                }
+                _ => None,
+            }
+        };
+        if let Some(reason) = refused {
+            counts.rejected += 1;
+            return Err(reason);
+        }
+
+        counts.active += 1;
+        match kind {
+            Kind::Interactive => counts.interactive += 1,
+            Kind::Transfer => counts.transfers += 1,
+            Kind::Other => {}
+        }
+        Ok(Permit { usage: self, kind })
+    }
+}
+
+fn at_limit(open: u32, max: u32) -> bool {
+    max != 0 && open >= max
+}
+
+/// Take a slot for a new stream, or the reason the device is too busy.
+pub fn acquire(kind: Kind, limits: &StreamLimits) -> Result<Permit<'static>, String> {
+    USAGE.acquire(kind, limits)
+}
+
+pub fn usage() -> StreamMetrics {
+    let mut usage = USAGE.counts.lock().unwrap().clone();
+    (usage.compressed_payload_bytes, usage.compressed_wire_bytes) = compress::totals();
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_refuse_and_release() {
+        let usage = Usage {
+            counts: Mutex::new(StreamMetrics::default()),
+        };
+        let limits = StreamLimits {
+            max_interactive_sessions: 1,
+            max_fs_transfers: 0,
+            max_streams: 3,
+        };
+
+        let shell = usage.acquire(Kind::Interactive, &limits).unwrap();
+        assert!(usage.acquire(Kind::Interactive, &limits).is_err());
+        let _cp1 = usage.acquire(Kind::Transfer, &limits).unwrap();
+        let _cp2 = usage.acquire(Kind::Transfer, &limits).unwrap();
+        assert_eq!(
+            usage.acquire(Kind::Other, &limits).err().as_deref(),
+            Some("3 streams are open")
+        );
+
+        drop(shell);
+        assert!(usage.acquire(Kind::Interactive, &limits).is_ok());
+        let counts = usage.counts.lock().unwrap();
+        assert_eq!(
+            (counts.active, counts.transfers, counts.rejected),
+            (2, 2, 2)
+        );
+    }
+}
@@ -1,15 +1,46 @@
 use anyhow::{Result, anyhow};
+use m87_shared::log_filter::{LogFilter, LogMatcher, RUNTIME_SOURCE};
+use m87_shared::log_format::LogLevel;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::broadcast::error::RecvError;
 
 use crate::util::format;
 use crate::{
-    device::deployment_manager::DeploymentManager, streams::quic::QuicIo, util::logging::get_log_rx,
+    device::{deployment_manager::DeploymentManager, redaction},
+    streams::e2e::StreamIo,
+    util::logging::{LogLine, get_log_rx, recent_lines},
 };
 
-pub async fn handle_logs_io(io: &mut QuicIo, unit_manager: Arc<DeploymentManager>) -> Result<()> {
-    let _ = unit_manager.start_log_follow().await?;
+/// Stream runtime logs, and service logs if `services` is set, only lines at
+/// `level` or above when it is set and that pass `filter`. With
+/// `filter.since_secs` the last `tail` kept lines of that window are sent
+/// first.
+pub async fn handle_logs_io(
+    io: &mut StreamIo,
+    unit_manager: Arc<DeploymentManager>,
+    level: Option<LogLevel>,
+    services: bool,
+    filter: LogFilter,
+    tail: Option<usize>,
+) -> Result<()> {
+    let matcher = match filter.matcher() {
+        Ok(m) => m,
+        Err(e) => {
+            let _ = io.write_all(format!("{}\n", e).as_bytes()).await;
+            return Err(anyhow!(e));
+        }
+    };
+    if services {
+        unit_manager.start_log_follow().await?;
+    } else {
+        let _ = io
+            .write_all(
+                b"(low bandwidth profile: service logs are not followed, use --follow to see them)\n",
+            )
+            .await;
+    }
     let mut app_rx = match get_log_rx() {
         Some(r) => r,
         None => {
@@ -18,6 +49,26 @@ pub async fn handle_logs_io(io: &mut QuicIo, unit_manager: Arc<DeploymentManager
         }
     };
 
+    // subscribed before the replay, so no line falls between the two
+    if let Some(since) = filter.since_secs {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let from = now.saturating_sub(since.saturating_mul(1000));
+        let replay: Vec<String> = recent_lines()
+            .into_iter()
+            .filter(|line| line.time >= from)
+            .filter_map(|line| render(&line, level, &matcher))
+            .collect();
+        let skip = tail.map_or(0, |t| replay.len().saturating_sub(t));
+        for line in &replay[skip..] {
+            if io.write_all(line.as_bytes()).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+
     loop {
         tokio::select! {
             res = app_rx.recv() => {
@@ -35,19 +86,36 @@ pub async fn handle_logs_io(io: &mut QuicIo, unit_manager: Arc<DeploymentManager
                     }
                 };
 
-                let formatted_msg = if line.trim().contains("[observe]") {
-                    line.replace("[observe]", "")
-                } else {
-                    format::format_log("m87", &line, true)
+                let Some(formatted_msg) = render(&line, level, &matcher) else {
+                    continue;
                 };
-
+                // one write per line: a compressed stream flushes each write
                 if io.write_all(formatted_msg.as_bytes()).await.is_err() { break; }
-                if io.write_all(b"\n").await.is_err() { break; }
             }
         }
     }
 
-    let _ = unit_manager.stop_log_follow().await?;
+    if services {
+        unit_manager.stop_log_follow().await?;
+    }
 
     Ok(())
 }
+
+/// The line as sent to the CLI, or `None` if it is filtered out. Patterns
+/// match the redacted text, so they can not probe for secrets.
+fn render(line: &LogLine, level: Option<LogLevel>, matcher: &LogMatcher) -> Option<String> {
+    if level.is_some_and(|min| line.level < min) {
+        return None;
+    }
+    let text = redaction::redact(&line.text);
+    let formatted = if text.trim().contains("[observe]") {
+        text.replace("[observe]", "")
+    } else {
+        format::format_log(RUNTIME_SOURCE, &text, true)
+    };
+    if !matcher.matches(&line.source, &formatted) {
+        return None;
+    }
+    Some(format!("{}\n", formatted))
+}
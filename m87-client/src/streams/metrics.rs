@@ -1,11 +1,19 @@
+use std::sync::Arc;
+
 use tokio::io::AsyncWriteExt;
 
 use crate::{
+    device::deployment_manager::DeploymentManager,
     streams::quic::QuicIo,
     streams::shared::{SharedReceiver, acquire_metrics_task},
 };
 
-pub async fn handle_system_metrics_io(io: &mut QuicIo) {
+pub async fn handle_system_metrics_io(io: &mut QuicIo, unit_manager: Arc<DeploymentManager>) {
+    // log metrics are only counted while someone is watching
+    if let Err(e) = unit_manager.start_metrics_follow().await {
+        tracing::warn!("following logs for log metrics failed: {:#}", e);
+    }
+
     // Start metrics subscription task
     let (_task, mut rx): (_, SharedReceiver) = acquire_metrics_task("system-metrics").await;
 
@@ -21,6 +29,7 @@ pub async fn handle_system_metrics_io(io: &mut QuicIo) {
 
     // No manual ref decrement — dropping rx handles it
     drop(rx);
+    let _ = unit_manager.stop_metrics_follow().await;
 
     // Flush & close
     let _ = io.shutdown().await;
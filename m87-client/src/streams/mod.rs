@@ -1,6 +1,11 @@
 // Shared modules (used by both m87 runtime and m87 command line)
+pub mod compress;
+pub mod e2e;
+pub mod mux;
 pub mod quic;
 pub mod stream_type;
+#[cfg(unix)]
+pub mod warm;
 
 // Runtime-specific: These modules handle incoming streams on the device side
 // Only compiled when runtime feature is enabled
@@ -9,20 +14,42 @@ mod docker;
 #[cfg(feature = "runtime")]
 mod exec;
 #[cfg(feature = "runtime")]
+mod facts;
+#[cfg(feature = "runtime")]
+mod firewall;
+#[cfg(feature = "runtime")]
+mod gui;
+#[cfg(feature = "runtime")]
+pub mod limits;
+#[cfg(feature = "runtime")]
 mod logs;
 #[cfg(feature = "runtime")]
 mod metrics;
 #[cfg(feature = "runtime")]
+mod packages;
+#[cfg(feature = "runtime")]
+mod plan;
+#[cfg(feature = "runtime")]
+mod resolve;
+#[cfg(feature = "runtime")]
 pub mod router;
 #[cfg(feature = "runtime")]
 mod serial;
 #[cfg(feature = "runtime")]
+mod sessions;
+#[cfg(feature = "runtime")]
 mod shared;
 #[cfg(feature = "runtime")]
 mod ssh;
 #[cfg(feature = "runtime")]
+mod support;
+#[cfg(feature = "runtime")]
 mod terminal;
 #[cfg(feature = "runtime")]
+mod time;
+#[cfg(feature = "runtime")]
+mod vnc;
+#[cfg(feature = "runtime")]
 mod forward;
 #[cfg(feature = "runtime")]
 pub mod udp_manager;
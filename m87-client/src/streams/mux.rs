@@ -0,0 +1,170 @@
+//! Several byte streams multiplexed over one QUIC stream, used to carry the
+//! X11 connections of `m87 <device> gui` in the reverse direction: the app
+//! connects on the device and the CLI dials the local X server.
+//!
+//! Frames are `[id: u32 BE][len: u32 BE][payload]`. An empty payload closes
+//! connection `id`; [`OUTPUT`] and [`CONTROL`] are reserved.
+
+use std::collections::HashMap;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// Output of the remote command.
+pub const OUTPUT: u32 = 0;
+/// One JSON control message per frame.
+pub const CONTROL: u32 = u32::MAX;
+
+const MAX_FRAME: usize = 1024 * 1024;
+
+pub type Frame = (u32, Vec<u8>);
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(
+    w: &mut W,
+    id: u32,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut buf = Vec::with_capacity(8 + payload.len());
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    buf.extend_from_slice(payload);
+    w.write_all(&buf).await
+}
+
+/// Read the next frame, or `None` when the stream ended between frames.
+pub async fn read_frame<R: AsyncRead + Unpin>(r: &mut R) -> std::io::Result<Option<Frame>> {
+    let mut header = [0u8; 8];
+    match r.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let id = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+    let len = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    if len > MAX_FRAME {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds the limit", len),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload).await?;
+    Ok(Some((id, payload)))
+}
+
+/// Local ends of the multiplexed connections. Data read from them is queued
+/// on `out` as frames; [`Mux::deliver`] writes incoming frames to them.
+pub struct Mux {
+    out: mpsc::UnboundedSender<Frame>,
+    conns: HashMap<u32, mpsc::UnboundedSender<Vec<u8>>>,
+}
+
+impl Mux {
+    pub fn new(out: mpsc::UnboundedSender<Frame>) -> Self {
+        Self {
+            out,
+            conns: HashMap::new(),
+        }
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.conns.contains_key(&id)
+    }
+
+    /// Pump `stream` as connection `id` until either side closes it.
+    pub fn attach<S>(&mut self, id: u32, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+
+        let out = self.out.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 16 * 1024];
+            loop {
+                match reader.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        if out.send((id, buf[..n].to_vec())).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            let _ = out.send((id, Vec::new()));
+        });
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+        tokio::spawn(async move {
+            while let Some(data) = rx.recv().await {
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown().await;
+        });
+        self.conns.insert(id, tx);
+    }
+
+    /// Write `data` to connection `id`; empty data closes it.
+    pub fn deliver(&mut self, id: u32, data: Vec<u8>) {
+        if data.is_empty() {
+            self.conns.remove(&id);
+        } else if let Some(conn) = self.conns.get(&id)
+            && conn.send(data).is_err()
+        {
+            self.conns.remove(&id);
+        }
+    }
+}
+
+/// Write frames queued by a [`Mux`] (and the caller) to the stream.
+pub async fn pump_frames<W: AsyncWrite + Unpin>(
+    mut w: W,
+    mut frames: mpsc::UnboundedReceiver<Frame>,
+) -> std::io::Result<()> {
+    while let Some((id, payload)) = frames.recv().await {
+        write_frame(&mut w, id, &payload).await?;
+    }
+    w.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_round_trip() {
+        let (mut a, mut b) = tokio::io::duplex(64);
+        write_frame(&mut a, 7, b"hello").await.unwrap();
+        write_frame(&mut a, CONTROL, b"").await.unwrap();
+        drop(a);
+
+        assert_eq!(
+            read_frame(&mut b).await.unwrap(),
+            Some((7, b"hello".to_vec()))
+        );
+        assert_eq!(read_frame(&mut b).await.unwrap(), Some((CONTROL, vec![])));
+        assert_eq!(read_frame(&mut b).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_mux_pumps_connection() {
+        let (out_tx, mut out_rx) = mpsc::unbounded_channel();
+        let mut mux = Mux::new(out_tx);
+        let (local, mut remote) = tokio::io::duplex(64);
+        mux.attach(3, local);
+
+        remote.write_all(b"ping").await.unwrap();
+        assert_eq!(out_rx.recv().await, Some((3, b"ping".to_vec())));
+
+        mux.deliver(3, b"pong".to_vec());
+        let mut buf = [0u8; 4];
+        remote.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        mux.deliver(3, Vec::new());
+        assert!(!mux.contains(3));
+        assert_eq!(remote.read(&mut buf).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,49 @@
+use m87_shared::inventory::PackageAction;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{
+    device::package_manager,
+    streams::{quic::QuicIo, sessions::Session, stream_type::PackageEvent},
+};
+
+/// Run a package operation, writing its output and final report as
+/// [`PackageEvent`] lines. The operation finishes even if the operator
+/// disconnects; only killing the session stops it.
+pub async fn handle_packages_io(
+    action: PackageAction,
+    packages: Vec<String>,
+    dry_run: bool,
+    user: String,
+    session: &Session,
+    io: &mut QuicIo,
+) {
+    info!("package {} of {:?} by {}", action, packages, user);
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let run = package_manager::run(action, packages, dry_run, user, tx, session.cancelled());
+    tokio::pin!(run);
+
+    let report = loop {
+        tokio::select! {
+            Some(line) = rx.recv() => write_event(io, &PackageEvent::Output { line }).await,
+            report = &mut run => break report,
+        }
+    };
+    while let Ok(line) = rx.try_recv() {
+        write_event(io, &PackageEvent::Output { line }).await;
+    }
+    package_manager::queue(report.clone());
+    write_event(io, &PackageEvent::Done { report }).await;
+    let _ = io.shutdown().await;
+}
+
+async fn write_event(io: &mut QuicIo, event: &PackageEvent) {
+    match serde_json::to_string(event) {
+        Ok(json) => {
+            let _ = io.write_all(json.as_bytes()).await;
+            let _ = io.write_all(b"\n").await;
+        }
+        Err(e) => warn!("failed to serialize package event: {}", e),
+    }
+}
@@ -0,0 +1,54 @@
+use std::sync::Arc;
+
+use m87_shared::deploy_spec::DeploymentRevision;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tracing::warn;
+
+use crate::{
+    device::deployment_manager::DeploymentManager,
+    streams::{quic::QuicIo, stream_type::PlanReply},
+};
+
+/// Largest candidate revision accepted, files included
+const MAX_CANDIDATE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Read the candidate revision line, reply with the plan as one JSON line.
+pub async fn handle_plan_io(io: &mut QuicIo, unit_manager: Arc<DeploymentManager>) {
+    let mut line = String::new();
+    let read = BufReader::new((&mut *io).take(MAX_CANDIDATE_BYTES))
+        .read_line(&mut line)
+        .await;
+
+    let reply = match read {
+        Ok(0) => PlanReply {
+            error: Some("no revision sent".to_string()),
+            ..Default::default()
+        },
+        Ok(_) => match serde_json::from_str::<DeploymentRevision>(&line) {
+            Ok(candidate) => unit_manager
+                .plan(&candidate)
+                .await
+                .unwrap_or_else(|e| PlanReply {
+                    error: Some(e.to_string()),
+                    ..Default::default()
+                }),
+            Err(e) => PlanReply {
+                error: Some(format!("invalid revision: {}", e)),
+                ..Default::default()
+            },
+        },
+        Err(e) => PlanReply {
+            error: Some(format!("failed to read the revision: {}", e)),
+            ..Default::default()
+        },
+    };
+
+    match serde_json::to_string(&reply) {
+        Ok(json) => {
+            let _ = io.write_all(json.as_bytes()).await;
+            let _ = io.write_all(b"\n").await;
+        }
+        Err(e) => warn!("failed to serialize plan: {}", e),
+    }
+    let _ = io.shutdown().await;
+}
@@ -1,204 +1,206 @@
-use anyhow::{Context, Result};
-use quinn::{ClientConfig, Endpoint, IdleTimeout};
-use quinn_proto::crypto::rustls::QuicClientConfig;
-use rustls::{ClientConfig as RustlsClientConfig, RootCertStore};
-use std::time::Duration;
-use std::{net::SocketAddr, sync::Arc};
-use std::{pin::Pin, task::Poll};
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
-use tracing::{debug, error, warn};
+use anyhow::Result;
+use quinn::Endpoint;
+#[cfg(unix)]
+use serde::Serialize;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use make87_sdk::streams::get_quic_connection_via_proxy;
+use make87_sdk::streams::open_stream;
+pub use make87_sdk::streams::{
+    ByteCounter, QuicIo, QuicTimeouts, connect_with_token, connect_with_token_and_timeouts,
+    get_quic_connection,
+};
+
+use crate::config::Config;
+use crate::devices::ResolvedDevice;
+use crate::streams::compress;
+use crate::streams::e2e::{self, StreamIo};
+use crate::streams::stream_type::{StreamClass, StreamType};
+#[cfg(unix)]
+use crate::streams::warm;
+use crate::util::lan::{connect_direct, direct_target};
+use crate::util::proxy;
 
-use crate::streams::stream_type::StreamType;
-use crate::util::tls::NoVerify; // reuse the same NoVerify struct
-
-async fn resolve_host(host: &str, port: u16) -> Result<SocketAddr> {
-    for i in 0..10 {
-        match tokio::net::lookup_host((host, port)).await {
-            Ok(addrs) => {
-                for addr in addrs {
-                    if addr.is_ipv4() {
-                        return Ok(addr);
-                    }
-                }
-            }
-            Err(_) => { /* ignore and retry */ }
-        }
-
-        let backoff_ms = 200 + i * 150;
-        tokio::time::sleep(std::time::Duration::from_millis(backoff_ms)).await;
-    }
-
-    Err(anyhow::anyhow!("DNS resolution failed after retries"))
+pub async fn open_quic_io(
+    host: &str,
+    token: &str,
+    device_short_id: &str,
+    stream_type: StreamType,
+    trust_invalid: bool,
+) -> Result<(quinn::Connection, QuicIo)> {
+    let class = stream_type.class();
+    let (_endpoint, conn) =
+        connect_quic_only(host, token, device_short_id, trust_invalid, class).await?;
+    let io = open_quic_stream(&conn, stream_type).await?;
+    Ok((conn, io))
 }
 
-pub async fn get_quic_connection(
-    host_name: &str,
+/// Open a shell, exec or ssh stream to `device`, sealed end to end when
+/// `end_to_end` is set in the config. Direct LAN connections skip the relay
+/// and stay unsealed. Streams through a warm connection (see
+/// [`crate::streams::warm`]) come without a connection of their own. File
+/// transfers are compressed inside the sealed stream (see [`compress`]).
+pub async fn open_interactive_io(
+    device: &ResolvedDevice,
     token: &str,
-    trust_invalid_server_cert: bool,
-) -> Result<(Endpoint, quinn::Connection)> {
-    // if hostname ends with :port extract port otherwise use 443
-    let port = if let Some(Ok(port)) = host_name
-        .rsplit_once(':')
-        .map(|(_, port)| port.parse::<u16>())
-    {
-        port
-    } else {
-        443
-    };
-    let port_free_host_name = host_name
-        .strip_suffix(&format!(":{}", port))
-        .unwrap_or(host_name);
-    let server_addr = resolve_host(port_free_host_name, port).await?;
-
-    // 2. Root store (system roots)
-    let mut root_store = RootCertStore::empty();
-    root_store
-        .roots
-        .extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-
-    // 3. Build rustls QUIC client config
-    debug!(
-        "Creating QUIC client config with trust_invalid_server_cert={}",
-        trust_invalid_server_cert
-    );
-
-    let mut tls = if trust_invalid_server_cert {
-        warn!("QUIC: trusting invalid server certificate");
-        RustlsClientConfig::builder()
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(NoVerify))
-            .with_no_client_auth()
-    } else {
-        RustlsClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth()
-    };
-
-    // 4. QUIC requires ALPN
-    tls.alpn_protocols = vec![b"m87-quic".to_vec()];
-
-    // 5. Convert rustls → QUIC crypto config
-    let crypto =
-        Arc::new(QuicClientConfig::try_from(tls).context("failed converting rustls→quic config")?);
-
-    let mut client_cfg = ClientConfig::new(crypto);
-    let mut transport = quinn::TransportConfig::default();
-    transport.keep_alive_interval(Some(Duration::from_secs(5)));
-    //make sure we dont black hole packets
-    transport
-        .initial_mtu(1200)
-        .min_mtu(1200)
-        .mtu_discovery_config(None)
-        .enable_segmentation_offload(false);
-    transport.max_idle_timeout(Some(
-        IdleTimeout::try_from(Duration::from_secs(180)).unwrap(),
-    )); // 10 seconds
-    client_cfg.transport_config(Arc::new(transport));
-
-    // 6. Create QUIC client endpoint (local ephemeral port)
-    let mut endpoint =
-        Endpoint::client("[::]:0".parse().unwrap()).context("failed creating QUIC endpoint")?;
-    endpoint.set_default_client_config(client_cfg);
-
-    let connecting = endpoint
-        .connect(server_addr, port_free_host_name)
-        .context("QUIC connect() failed")?;
-
-    let conn = connecting
-        .await
-        .map_err(|e| {
-            error!("QUIC connect failed: {}", e);
-            e
-        })
-        .context("QUIC handshake failed")?;
-
-    let mut send = conn.open_uni().await?;
-    debug!("Connected to server");
-    debug!("Sending token");
-    let token_bytes = token.as_bytes();
-    send.write_all(&(token_bytes.len() as u16).to_be_bytes())
+    stream_type: StreamType,
+    config: &Config,
+) -> Result<(Option<quinn::Connection>, StreamIo)> {
+    let compressed = compress::wanted(&stream_type, config);
+    let label = stream_type.variant_name();
+    if !config.end_to_end || direct_target().is_some() {
+        let mut header = serde_json::to_value(&stream_type)?;
+        if compressed {
+            compress::request(&mut header);
+        }
+        #[cfg(unix)]
+        if let Some(io) = open_warm(device, config, &header).await {
+            let io = negotiate_if(StreamIo::Warm(io), compressed, label).await?;
+            return Ok((None, io));
+        }
+        let (_endpoint, conn) = connect_quic_only(
+            &device.host,
+            token,
+            &device.short_id,
+            config.trust_invalid_server_cert,
+            stream_type.class(),
+        )
         .await?;
-    send.write_all(token_bytes).await?;
-    send.finish()?;
-
-    Ok((endpoint, conn))
-}
+        let io = open_stream(&conn, &header).await?;
+        return Ok((
+            Some(conn),
+            negotiate_if(io.into(), compressed, label).await?,
+        ));
+    }
 
-pub struct QuicIo {
-    pub recv: quinn::RecvStream,
-    pub send: quinn::SendStream,
+    let device_key = e2e::device_key(device).await?;
+    let (hello, handshake) = e2e::client_hello()?;
+    let mut header = e2e::header_with_hello(&stream_type, &hello)?;
+    if compressed {
+        compress::request(&mut header);
+    }
+    #[cfg(unix)]
+    if let Some(io) = open_warm(device, config, &header).await {
+        let sealed = e2e::seal_client(io, handshake, &device_key).await?;
+        let io = StreamIo::WarmSealed(Box::new(sealed));
+        return Ok((None, negotiate_if(io, compressed, label).await?));
+    }
+    let (_endpoint, conn) = connect_quic_only(
+        &device.host,
+        token,
+        &device.short_id,
+        config.trust_invalid_server_cert,
+        stream_type.class(),
+    )
+    .await?;
+    let io = open_stream(&conn, &header).await?;
+    let sealed = e2e::seal_client(io, handshake, &device_key).await?;
+    Ok((
+        Some(conn),
+        negotiate_if(sealed.into(), compressed, label).await?,
+    ))
 }
 
-impl AsyncRead for QuicIo {
-    fn poll_read(
-        mut self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        buf: &mut ReadBuf<'_>,
-    ) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.recv).poll_read(cx, buf)
+/// Open a logs or support bundle stream, compressed when `compression` is
+/// set in the config and the runtime agrees.
+pub async fn open_bulk_io(
+    host: &str,
+    token: &str,
+    device_short_id: &str,
+    stream_type: StreamType,
+    config: &Config,
+) -> Result<(quinn::Connection, StreamIo)> {
+    let compressed = compress::wanted(&stream_type, config);
+    let mut header = serde_json::to_value(&stream_type)?;
+    if compressed {
+        compress::request(&mut header);
     }
+    let (_endpoint, conn) = connect_quic_only(
+        host,
+        token,
+        device_short_id,
+        config.trust_invalid_server_cert,
+        stream_type.class(),
+    )
+    .await?;
+    let io = open_stream(&conn, &header).await?;
+    let io = negotiate_if(io.into(), compressed, stream_type.variant_name()).await?;
+    Ok((conn, io))
 }
 
-impl AsyncWrite for QuicIo {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-        data: &[u8],
-    ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut self.send)
-            .poll_write(cx, data)
-            .map_err(|e| std::io::Error::from(e))
+async fn negotiate_if(io: StreamIo, compressed: bool, label: &'static str) -> Result<StreamIo> {
+    if compressed {
+        compress::negotiate(io, label).await
+    } else {
+        Ok(io)
     }
+}
 
-    fn poll_flush(
-        mut self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.send).poll_flush(cx)
+/// A stream through the device's warm connection. Without one, a helper is
+/// started for the next command and this one connects on its own.
+#[cfg(unix)]
+async fn open_warm<T: Serialize>(
+    device: &ResolvedDevice,
+    config: &Config,
+    header: &T,
+) -> Option<UnixStream> {
+    if config.warm_connection_secs == 0 || direct_target().is_some() {
+        return None;
     }
-
-    fn poll_shutdown(
-        mut self: Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.send).poll_shutdown(cx)
+    let io = warm::open(&device.host, &device.short_id, header).await;
+    if io.is_none() {
+        warm::spawn(&device.host, &device.short_id);
     }
+    io
 }
 
-pub async fn open_quic_io(
+pub async fn connect_quic_only(
     host: &str,
     token: &str,
     device_short_id: &str,
-    stream_type: StreamType,
     trust_invalid: bool,
-) -> Result<(quinn::Connection, QuicIo)> {
-    let (_endpoint, conn) = connect_quic_only(host, token, device_short_id, trust_invalid).await?;
-    let io = open_quic_stream(&conn, stream_type).await?;
-    Ok((conn, io))
+    class: StreamClass,
+) -> Result<(Endpoint, quinn::Connection)> {
+    let timeouts = keepalive_timeouts(class);
+    if let Some(target) = direct_target() {
+        return connect_direct(target, timeouts).await;
+    }
+    let full_host = m87_shared::host::device_host(device_short_id, host);
+    get_quic_connection_with_timeouts(&full_host, token, trust_invalid, timeouts).await
 }
 
-pub async fn connect_quic_only(
-    host: &str,
+/// Connect to the relay at `host_name[:port]`, through the configured proxy
+/// unless the host is excluded by `no_proxy`.
+pub async fn get_quic_connection_with_timeouts(
+    host_name: &str,
     token: &str,
-    device_short_id: &str,
     trust_invalid: bool,
+    timeouts: QuicTimeouts,
 ) -> Result<(Endpoint, quinn::Connection)> {
-    let full_host = format!("{}.{}", device_short_id, host);
-    get_quic_connection(&full_host, token, trust_invalid).await
+    match proxy::quic_proxy(host_name)? {
+        Some(proxy) => {
+            get_quic_connection_via_proxy(host_name, token, trust_invalid, timeouts, &proxy).await
+        }
+        None => {
+            make87_sdk::streams::get_quic_connection_with_timeouts(
+                host_name,
+                token,
+                trust_invalid,
+                timeouts,
+            )
+            .await
+        }
+    }
 }
 
-pub async fn open_quic_stream(conn: &quinn::Connection, stream_type: StreamType) -> Result<QuicIo> {
-    debug!("Opening QUIC stream");
-    let (mut send, recv) = conn.open_bi().await?;
-
-    let json = serde_json::to_vec(&stream_type)?;
-    let len = (json.len() as u32).to_be_bytes();
-
-    send.write_all(&len).await?;
-    send.write_all(&json).await?;
-    send.flush().await?;
-
-    debug!("Stream opened");
+/// Configured keepalive and idle timeouts of a stream class.
+pub fn keepalive_timeouts(class: StreamClass) -> QuicTimeouts {
+    Config::load()
+        .map(|config| config.keepalive.timeouts(class))
+        .unwrap_or_default()
+}
 
-    Ok(QuicIo { recv, send })
+pub async fn open_quic_stream(conn: &quinn::Connection, stream_type: StreamType) -> Result<QuicIo> {
+    open_stream(conn, &stream_type).await
 }
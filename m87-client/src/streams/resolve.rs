@@ -0,0 +1,29 @@
+use tokio::io::AsyncWriteExt;
+use tracing::{debug, warn};
+
+use crate::{streams::quic::QuicIo, streams::stream_type::ResolveReply};
+
+/// Reply with the addresses of `host` from the device's resolver as one JSON line.
+pub async fn handle_resolve_io(host: String, io: &mut QuicIo) {
+    let mut reply = ResolveReply::default();
+    match tokio::net::lookup_host((host.as_str(), 0)).await {
+        Ok(addrs) => {
+            for addr in addrs {
+                if !reply.addrs.contains(&addr.ip()) {
+                    reply.addrs.push(addr.ip());
+                }
+            }
+            debug!("resolved {} to {:?}", host, reply.addrs);
+        }
+        Err(e) => reply.error = Some(format!("cannot resolve {}: {}", host, e)),
+    }
+
+    match serde_json::to_string(&reply) {
+        Ok(json) => {
+            let _ = io.write_all(json.as_bytes()).await;
+            let _ = io.write_all(b"\n").await;
+        }
+        Err(e) => warn!("failed to serialize resolve reply: {}", e),
+    }
+    let _ = io.shutdown().await;
+}
@@ -1,17 +1,31 @@
 use bytes::Bytes;
+use m87_shared::log_filter::RUNTIME_SOURCE;
+use m87_shared::roles::{Role, granted_stream_role, required_stream_role};
 use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
 use tracing::{debug, warn};
 
 // use crate::streams::auth::validate_token;
+use crate::config::Config;
+use crate::device::bandwidth;
 use crate::device::deployment_manager::DeploymentManager;
+use crate::streams::compress;
+use crate::streams::e2e::{self, StreamIo};
+use crate::streams::gui::handle_gui_io;
+use crate::streams::limits;
 use crate::streams::quic::QuicIo;
 use crate::streams::serial::handle_serial_io;
+use crate::streams::sessions::{self, handle_sessions_io};
 use crate::streams::stream_type::StreamType;
+use crate::streams::terminal::handle_attach_io;
 use crate::streams::udp_manager::UdpChannelManager;
+use crate::streams::vnc::handle_vnc_io;
 use crate::streams::{
-    docker::handle_docker_io, exec::handle_exec_io, forward::handle_port_forward_io,
-    logs::handle_logs_io, metrics::handle_system_metrics_io, ssh::handle_ssh_io,
-    terminal::handle_terminal_io,
+    docker::handle_docker_io, exec::handle_exec_io, facts::handle_facts_io,
+    firewall::handle_firewall_io, forward::handle_port_forward_io, logs::handle_logs_io,
+    metrics::handle_system_metrics_io, packages::handle_packages_io, plan::handle_plan_io,
+    resolve::handle_resolve_io, ssh::handle_ssh_io, support::handle_support_io,
+    terminal::handle_terminal_io, time::handle_time_io,
 };
 
 pub async fn handle_incoming_stream(
@@ -19,10 +33,11 @@ pub async fn handle_incoming_stream(
     manager: UdpChannelManager,
     datagram_tx: tokio::sync::mpsc::Sender<(u32, Bytes)>,
     unit_manager: Arc<DeploymentManager>,
+    direct: bool,
 ) -> anyhow::Result<()> {
     debug!("router: parsing stream type header");
-    let stream_type = match StreamType::from_incoming_stream(&mut io.recv).await {
-        Ok(st) => st,
+    let (stream_type, header) = match StreamType::from_incoming_stream(&mut io.recv).await {
+        Ok(parsed) => parsed,
         Err(e) => {
             warn!("router: failed to parse stream type: {e:?}");
             return Err(e);
@@ -31,6 +46,65 @@ pub async fn handle_incoming_stream(
 
     debug!("router: stream type = {:?}", stream_type.variant_name());
 
+    if !direct {
+        let class = stream_type.traffic_class();
+        // the length prefix and header were read before counting started
+        let header_len = serde_json::to_vec(&header).map_or(0, |h| h.len());
+        bandwidth::record_received(class, 4 + header_len);
+        bandwidth::count(&mut io, class);
+    }
+
+    // The relay records the caller's role; relays without per-stream roles
+    // only admitted editors. Direct LAN peers hold the device key.
+    let granted = if direct {
+        Role::Owner
+    } else {
+        granted_stream_role(&header).unwrap_or(Role::Editor)
+    };
+    let required = required_stream_role(&header);
+    if !Role::allows(&granted, &required) {
+        warn!(
+            "router: refusing {} stream from a {}",
+            stream_type.variant_name(),
+            granted.to_string()
+        );
+        let message = format!(
+            "PERMISSION_DENIED: {} streams require the {} role\n",
+            stream_type.variant_name(),
+            required.to_string()
+        );
+        let _ = io.write_all(message.as_bytes()).await;
+        let _ = io.shutdown().await;
+        return Ok(());
+    }
+
+    let config = Config::load().unwrap_or_default();
+    // Held until the handler returns; ssh moves it into its task.
+    let permit = match limits::acquire(limits::Kind::of(&stream_type), &config.stream_limits) {
+        Ok(permit) => permit,
+        Err(reason) => {
+            warn!(
+                "router: refusing {} stream, device is busy: {}",
+                stream_type.variant_name(),
+                reason
+            );
+            let message = format!("BUSY: device is busy ({}), try again later\n", reason);
+            let _ = io.write_all(message.as_bytes()).await;
+            let _ = io.shutdown().await;
+            return Ok(());
+        }
+    };
+
+    // Direct LAN streams never pass the relay
+    let require_e2e = config.end_to_end && !direct;
+    let profile = config.bandwidth_profile;
+    // the low bandwidth profile compresses whatever `compression` says
+    let compression = ((config.compression || !profile.is_normal())
+        && compress::applies_to(&stream_type)
+        && compress::requested(&header))
+    .then(|| profile.compression_level());
+    let label = stream_type.variant_name();
+
     // let token = stream_type.get_token();
     // if let Err(e) = validate_token(token).await {
     //     warn!("router: token validation failed: {e:?}");
@@ -38,17 +112,105 @@ pub async fn handle_incoming_stream(
     // }
 
     match stream_type {
-        StreamType::Terminal { term, .. } => {
+        StreamType::Terminal {
+            token,
+            resume: Some(id),
+            ..
+        } => {
+            debug!("router: handing stream to terminal session {}", id);
+            let Some(io) = accept_e2e(io, &header, require_e2e).await else {
+                return Ok(());
+            };
+            // On success the session's handler owns the stream from here on.
+            if let Err((mut io, reason)) = sessions::resume(&id, &token, io) {
+                let _ = io
+                    .write_all(format!("\r\nCannot resume session: {}\r\n", reason).as_bytes())
+                    .await;
+                let _ = io.shutdown().await;
+            }
+            return Ok(());
+        }
+        StreamType::Terminal {
+            token,
+            attach: Some(target),
+            read_only,
+            ..
+        } => {
+            debug!("router: dispatching to terminal attach handler");
+            let Some(mut io) = accept_e2e(io, &header, require_e2e).await else {
+                return Ok(());
+            };
+            let mode = if read_only { "read-only" } else { "read-write" };
+            let session = sessions::open("attach", &token, Some(format!("{} ({})", target, mode)));
+            handle_attach_io(&target, read_only, &session, &mut io).await;
+        }
+        StreamType::Terminal {
+            read_only: true, ..
+        } => {
+            // approval is only waived for watching an attached session
+            warn!("router: refusing read-only terminal without a session to attach to");
+            let Some(mut io) = accept_e2e(io, &header, require_e2e).await else {
+                return Ok(());
+            };
+            let _ = io
+                .write_all(b"\r\nRead-only terminals need a session to attach to\r\n")
+                .await;
+            let _ = io.shutdown().await;
+            return Ok(());
+        }
+        StreamType::Terminal {
+            token,
+            term,
+            predict,
+            ..
+        } => {
             debug!("router: dispatching to terminal handler");
-            handle_terminal_io(term, &mut io).await;
+            let Some(mut io) = accept_e2e(io, &header, require_e2e).await else {
+                return Ok(());
+            };
+            let session = sessions::open("shell", &token, term.clone());
+            handle_terminal_io(term, predict, &session, &mut io).await;
         }
-        StreamType::Exec { .. } => {
+        StreamType::Exec { token, command } => {
             debug!("router: dispatching to exec handler");
-            handle_exec_io(io).await;
+            let Some(io) = accept_e2e(io, &header, require_e2e).await else {
+                return Ok(());
+            };
+            let session = sessions::open("exec", &token, None);
+            handle_exec_io(io, command, &session).await;
         }
-        StreamType::Logs { .. } => {
+        StreamType::Gui {
+            token,
+            command,
+            xauth_cookie,
+        } => {
+            debug!("router: dispatching to gui handler");
+            let session = sessions::open("gui", &token, None);
+            handle_gui_io(command, xauth_cookie, &session, io).await;
+        }
+        StreamType::Vnc { token, port } => {
+            debug!("router: dispatching to vnc handler");
+            let session = sessions::open("vnc", &token, None);
+            handle_vnc_io(port, &session, &mut io).await;
+        }
+        StreamType::Logs {
+            level,
+            follow,
+            filter,
+            tail,
+            ..
+        } => {
             debug!("router: dispatching to logs handler");
-            let _ = handle_logs_io(&mut io, unit_manager).await;
+            let Some(mut io) = accept_compression(io.into(), compression, label).await else {
+                return Ok(());
+            };
+            // asking for a service's lines follows service logs
+            let for_service = filter
+                .service
+                .as_deref()
+                .is_some_and(|s| s != RUNTIME_SOURCE);
+            let services = follow || for_service || profile.follows_logs();
+            let _ = handle_logs_io(&mut io, unit_manager, level, services, filter, tail).await;
         }
         StreamType::Forward { target, .. } => {
             debug!("router: dispatching to port forward handler");
@@ -60,7 +222,50 @@ pub async fn handle_incoming_stream(
         }
         StreamType::Metrics { .. } => {
             debug!("router: dispatching to metrics handler");
-            handle_system_metrics_io(&mut io).await;
+            handle_system_metrics_io(&mut io, unit_manager).await;
+        }
+        StreamType::Facts { refresh, .. } => {
+            debug!("router: dispatching to facts handler");
+            handle_facts_io(refresh, &mut io).await;
+        }
+        StreamType::Sessions { kill, .. } => {
+            debug!("router: dispatching to sessions handler");
+            handle_sessions_io(kill, &mut io).await;
+        }
+        StreamType::Time { sync, .. } => {
+            debug!("router: dispatching to time handler");
+            handle_time_io(sync, &mut io).await;
+        }
+        StreamType::Firewall { .. } => {
+            debug!("router: dispatching to firewall handler");
+            handle_firewall_io(&mut io).await;
+        }
+        StreamType::Plan { .. } => {
+            debug!("router: dispatching to plan handler");
+            handle_plan_io(&mut io, unit_manager).await;
+        }
+        StreamType::Resolve { host, .. } => {
+            debug!("router: dispatching to resolve handler");
+            handle_resolve_io(host, &mut io).await;
+        }
+        StreamType::Packages {
+            token,
+            action,
+            packages,
+            dry_run,
+        } => {
+            debug!("router: dispatching to packages handler");
+            let detail = format!("{} {}", action, packages.join(" "));
+            let session = sessions::open("pkg", &token, Some(detail.trim().to_string()));
+            let user = sessions::operator(&token);
+            handle_packages_io(action, packages, dry_run, user, &session, &mut io).await;
+        }
+        StreamType::SupportBundle { max_bytes, .. } => {
+            debug!("router: dispatching to support bundle handler");
+            let Some(mut io) = accept_compression(io.into(), compression, label).await else {
+                return Ok(());
+            };
+            handle_support_io(max_bytes, &mut io).await;
         }
         StreamType::Docker { .. } => {
             debug!("router: dispatching to docker handler");
@@ -68,8 +273,16 @@ pub async fn handle_incoming_stream(
         }
         StreamType::Ssh { .. } => {
             debug!("router: dispatching to ssh handler");
+            let Some(io) = accept_e2e(io, &header, require_e2e).await else {
+                return Ok(());
+            };
+            // file transfers are compressed inside the sealed stream
+            let Some(io) = accept_compression(io, compression, label).await else {
+                return Ok(());
+            };
             tokio::spawn(async move {
                 handle_ssh_io(io).await;
+                drop(permit);
             });
             return Ok(());
         }
@@ -77,3 +290,53 @@ pub async fn handle_incoming_stream(
     debug!("router: handler finished");
     Ok(())
 }
+
+/// Seal a shell, exec or ssh stream if the CLI asked for it. Unsealed ones
+/// are refused when `required`. `None` once the stream was closed.
+async fn accept_e2e(
+    mut io: QuicIo,
+    header: &serde_json::Value,
+    required: bool,
+) -> Option<StreamIo> {
+    let Some(hello) = e2e::hello_from_header(header) else {
+        if required {
+            warn!("router: refusing stream without end-to-end encryption");
+            let message = "E2E_REQUIRED: this device only accepts end-to-end encrypted \
+                           streams, run `m87 config set --end-to-end true`\n";
+            let _ = io.write_all(message.as_bytes()).await;
+            let _ = io.shutdown().await;
+            return None;
+        }
+        return Some(io.into());
+    };
+    debug!(
+        "router: sealing stream for operator key {}",
+        e2e::fingerprint(&hello.operator_key)
+    );
+    match e2e::seal_device(io, &hello).await {
+        Ok(sealed) => Some(sealed.into()),
+        Err(e) => {
+            warn!("router: end-to-end handshake failed: {e:?}");
+            None
+        }
+    }
+}
+
+/// Compress a logs, support bundle or file transfer stream at zstd `level`
+/// if the device decided to compress it. `None` once the stream was closed.
+async fn accept_compression(
+    io: StreamIo,
+    level: Option<i32>,
+    label: &'static str,
+) -> Option<StreamIo> {
+    let Some(level) = level else {
+        return Some(io);
+    };
+    match compress::accept(io, label, level).await {
+        Ok(io) => Some(io),
+        Err(e) => {
+            warn!("router: failed to start compression: {e:?}");
+            None
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Registry of the shell and exec sessions open on this device, so operators
+//! can see who is connected (`m87 <device> sessions list`) and terminate
+//! stuck sessions (`m87 <device> sessions kill <id>`).
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use once_cell::sync::Lazy;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::sync::{CancellationToken, WaitForCancellationFuture};
+use tracing::{info, warn};
+
+use crate::streams::e2e::StreamIo;
+use crate::streams::quic::QuicIo;
+use crate::streams::stream_type::{SessionInfo, SessionsReply};
+
+struct Entry {
+    info: SessionInfo,
+    cancel: CancellationToken,
+    shared: Option<SharedTerminal>,
+    resume: Option<mpsc::Sender<StreamIo>>,
+}
+
+/// PTY output fan-out and input path of a shell that other operators can
+/// attach to (`m87 <device> shell --attach <id>`).
+#[derive(Clone)]
+pub struct SharedTerminal {
+    pub output: broadcast::Sender<Vec<u8>>,
+    pub input: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+static SESSIONS: Lazy<Mutex<HashMap<String, Entry>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Handle to a registered session. Dropping it removes the session.
+pub struct Session {
+    id: String,
+    cancel: CancellationToken,
+}
+
+impl Session {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Resolves when the session was killed by an operator.
+    pub fn cancelled(&self) -> WaitForCancellationFuture<'_> {
+        self.cancel.cancelled()
+    }
+
+    /// Make the session attachable. The registry keeps only the input sender
+    /// and a way to subscribe, so attached streams see the output end when
+    /// the owning shell exits.
+    pub fn share(
+        &self,
+        output: &broadcast::Sender<Vec<u8>>,
+        input: mpsc::UnboundedSender<Vec<u8>>,
+    ) {
+        if let Some(entry) = SESSIONS.lock().unwrap().get_mut(&self.id) {
+            entry.shared = Some(SharedTerminal {
+                output: output.clone(),
+                input,
+            });
+        }
+    }
+
+    /// Accept replacement streams for this session, handed over by [`resume`]
+    /// when the operator reconnects after losing the connection.
+    pub fn resumable(&self) -> mpsc::Receiver<StreamIo> {
+        let (tx, rx) = mpsc::channel(1);
+        if let Some(entry) = SESSIONS.lock().unwrap().get_mut(&self.id) {
+            entry.resume = Some(tx);
+        }
+        rx
+    }
+
+    pub fn set_detail(&self, detail: &str) {
+        if let Some(entry) = SESSIONS.lock().unwrap().get_mut(&self.id) {
+            entry.info.detail = Some(detail.to_string());
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        SESSIONS.lock().unwrap().remove(&self.id);
+    }
+}
+
+pub fn open(kind: &str, token: &str, detail: Option<String>) -> Session {
+    let id = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+    let cancel = CancellationToken::new();
+    let info = SessionInfo {
+        id: id.clone(),
+        kind: kind.to_string(),
+        user: operator(token),
+        started_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        detail,
+    };
+    info!("{} session {} opened by {}", kind, id, info.user);
+
+    SESSIONS.lock().unwrap().insert(
+        id.clone(),
+        Entry {
+            info,
+            cancel: cancel.clone(),
+            shared: None,
+            resume: None,
+        },
+    );
+    Session { id, cancel }
+}
+
+pub fn list() -> Vec<SessionInfo> {
+    let mut sessions: Vec<SessionInfo> = SESSIONS
+        .lock()
+        .unwrap()
+        .values()
+        .map(|e| e.info.clone())
+        .collect();
+    sessions.sort_by_key(|s| s.started_at);
+    sessions
+}
+
+/// Output subscription and input path of an attached stream.
+pub type Attachment = (broadcast::Receiver<Vec<u8>>, mpsc::UnboundedSender<Vec<u8>>);
+
+/// Subscribe to the output of a shared shell session and get its input path.
+pub fn attach(id: &str) -> Option<Attachment> {
+    let sessions = SESSIONS.lock().unwrap();
+    let shared = sessions.get(id)?.shared.as_ref()?;
+    Some((shared.output.subscribe(), shared.input.clone()))
+}
+
+/// Hand a reconnected stream to the session `id`. Only the operator that
+/// opened the session may resume it; on failure the stream is returned.
+pub fn resume(id: &str, token: &str, io: StreamIo) -> Result<(), (StreamIo, String)> {
+    let sessions = SESSIONS.lock().unwrap();
+    let Some(entry) = sessions.get(id) else {
+        return Err((io, format!("no session with id '{}'", id)));
+    };
+    if entry.info.user != operator(token) {
+        return Err((io, format!("session '{}' belongs to another operator", id)));
+    }
+    let Some(resume) = &entry.resume else {
+        return Err((io, format!("session '{}' can not be resumed", id)));
+    };
+    resume.try_send(io).map_err(|e| {
+        let io = match e {
+            mpsc::error::TrySendError::Full(io) | mpsc::error::TrySendError::Closed(io) => io,
+        };
+        (io, format!("session '{}' is already being resumed", id))
+    })
+}
+
+/// Signal the session to terminate. Returns false if no such session is open.
+pub fn kill(id: &str) -> bool {
+    match SESSIONS.lock().unwrap().get(id) {
+        Some(entry) => {
+            info!("terminating {} session {}", entry.info.kind, id);
+            entry.cancel.cancel();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Reply with the open sessions as one JSON line, after killing `kill` if set.
+pub async fn handle_sessions_io(kill_id: Option<String>, io: &mut QuicIo) {
+    let mut reply = SessionsReply::default();
+    if let Some(id) = kill_id
+        && !kill(&id)
+    {
+        reply.error = Some(format!("no session with id '{}'", id));
+    }
+    reply.sessions = list();
+
+    match serde_json::to_string(&reply) {
+        Ok(json) => {
+            let _ = io.write_all(json.as_bytes()).await;
+            let _ = io.write_all(b"\n").await;
+        }
+        Err(e) => warn!("failed to serialize sessions: {}", e),
+    }
+    let _ = io.shutdown().await;
+}
+
+/// Best-effort operator name for display. Tokens were verified by the server
+/// before the stream reached the device, so the JWT payload is only decoded.
+pub fn operator(token: &str) -> String {
+    let claims = token
+        .split('.')
+        .nth(1)
+        .and_then(|payload| URL_SAFE_NO_PAD.decode(payload).ok())
+        .and_then(|bytes| serde_json::from_slice::<serde_json::Value>(&bytes).ok());
+
+    let Some(serde_json::Value::Object(claims)) = claims else {
+        return "api-key".to_string();
+    };
+    claims
+        .iter()
+        .find(|(k, _)| *k == "email" || k.ends_with("/email"))
+        .or_else(|| claims.get_key_value("sub"))
+        .and_then(|(_, v)| v.as_str())
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jwt(claims: &str) -> String {
+        format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims))
+    }
+
+    #[test]
+    fn test_operator_from_token() {
+        assert_eq!(
+            operator(&jwt(
+                r#"{"sub":"auth0|1","https://make87.com/email":"a@b.c"}"#
+            )),
+            "a@b.c"
+        );
+        assert_eq!(operator(&jwt(r#"{"sub":"auth0|1"}"#)), "auth0|1");
+        assert_eq!(operator("m87_plain_api_key"), "api-key");
+    }
+
+    #[test]
+    fn test_register_list_kill() {
+        let session = open("shell", "key", Some("xterm".to_string()));
+        assert!(list().iter().any(|s| s.id == session.id()));
+
+        assert!(kill(session.id()));
+        assert!(session.cancel.is_cancelled());
+
+        let id = session.id().to_string();
+        drop(session);
+        assert!(!list().iter().any(|s| s.id == id));
+        assert!(!kill(&id));
+    }
+}
@@ -5,6 +5,7 @@ use dashmap::DashMap;
 use once_cell::sync::Lazy;
 use tokio::sync::{broadcast, watch};
 
+use crate::config::Config;
 use crate::device::system_metrics::collect_system_metrics;
 
 /// Shared, ref-counted background publishers (logs/metrics/etc.)
@@ -81,14 +82,19 @@ pub async fn acquire_metrics_task(name: &str) -> (Arc<SharedTask>, SharedReceive
         let task = task.clone();
         async move {
             use tokio::time::{Duration, interval};
-            let mut ticker = interval(Duration::from_secs(1));
+            // a changed profile applies once no metrics stream is open
+            let profile = Config::load().unwrap_or_default().bandwidth_profile;
+            let mut ticker = interval(Duration::from_secs(profile.metrics_interval_secs()));
 
             loop {
                 tokio::select! {
                     _ = shutdown_rx.changed() => break,
                     _ = ticker.tick() => {
                         match collect_system_metrics().await {
-                            Ok(m) => {
+                            Ok(mut m) => {
+                                if !profile.detailed_metrics() {
+                                    m.reduce_detail();
+                                }
                                 if let Ok(payload) = serde_json::to_string(&m) {
                                     let _ = task.tx.send(payload);
                                 }
@@ -3,11 +3,11 @@ use std::path::PathBuf;
 use russh::server;
 
 use crate::{
-    streams::quic::QuicIo,
+    streams::e2e::StreamIo,
     util::ssh::{M87SshHandler, make_server_config},
 };
 
-pub async fn handle_ssh_io(io: QuicIo) {
+pub async fn handle_ssh_io(io: StreamIo) {
     let config = make_server_config();
     let handler = M87SshHandler::new(PathBuf::from("/"));
 
@@ -1,5 +1,11 @@
+use m87_shared::bandwidth::TrafficClass;
+use m87_shared::deploy_spec::{PortProtocol, RunType};
+use m87_shared::device::TimeStatus;
+use m87_shared::inventory::{PackageAction, PackageOperationReport};
+use m87_shared::log_filter::LogFilter;
+use m87_shared::log_format::LogLevel;
 use serde::{Deserialize, Serialize};
-use std::{fmt, num::ParseIntError};
+use std::{fmt, net::IpAddr, num::ParseIntError};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct UdpTarget {
@@ -117,6 +123,26 @@ fn parse_port_spec(s: &str) -> Result<(u16, u16), ForwardParseError> {
     }
 }
 
+/// Split a forward spec on ':' outside of brackets, so IPv6 hosts can be given as `[fd00::5]`.
+fn split_spec(body: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_brackets = false;
+    for (i, c) in body.char_indices() {
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            ':' if !in_brackets => {
+                parts.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&body[start..]);
+    parts
+}
+
 // Examples accepted (SSH -L style: local_port:remote_host:remote_port):
 // "8080"                         -> forward local:8080 to remote 127.0.0.1:8080
 // "8080:1337"                    -> forward local:8080 to remote 127.0.0.1:1337
@@ -124,6 +150,7 @@ fn parse_port_spec(s: &str) -> Result<(u16, u16), ForwardParseError> {
 // "8080/tcp"                     -> TCP only
 // "8080:1337/udp"                -> UDP only
 // "1554:192.168.0.101:554/tcp"   -> TCP to specific host
+// "8080:[fd00::5]:80"            -> forward local:8080 to remote [fd00::5]:80
 // "8080-8090"                    -> forward local:8080-8090 to remote 8080-8090
 // "8080-8090:9080-9090"          -> forward local:8080-8090 to remote 9080-9090 (offset mapping)
 // "8080-8090:192.168.0.101:9080-9090/tcp" -> TCP range to specific host with offset
@@ -187,7 +214,7 @@ impl ForwardTarget {
                 None => None,
             };
 
-            let nums: Vec<&str> = body.split(':').collect();
+            let nums = split_spec(body);
 
             // Parse port specs (may be single ports or ranges)
             let (local_start, local_end, remote_host, remote_start, _remote_end) =
@@ -233,7 +260,8 @@ impl ForwardTarget {
                             )));
                         }
 
-                        (l_start, l_end, (*host).to_string(), r_start, r_end)
+                        let host = host.trim_start_matches('[').trim_end_matches(']');
+                        (l_start, l_end, host.to_string(), r_start, r_end)
                     }
 
                     _ => {
@@ -285,12 +313,40 @@ pub enum StreamType {
     Terminal {
         token: String,
         term: Option<String>,
+        /// Join an existing shell session instead of starting a new shell.
+        #[serde(default)]
+        attach: Option<String>,
+        /// Only watch the attached session; devices refuse it without `attach`.
+        #[serde(default)]
+        read_only: bool,
+        /// Reconnect to a shell session whose connection was lost.
+        #[serde(default)]
+        resume: Option<String>,
+        /// Report the echo state for predictive local echo.
+        #[serde(default)]
+        predict: bool,
     },
     Exec {
         token: String,
+        /// The command, shown to approvers; devices refuse to run another
+        #[serde(default)]
+        command: Option<String>,
     },
     Logs {
         token: String,
+        /// Only lines at this level or above
+        #[serde(default)]
+        level: Option<LogLevel>,
+        /// Follow service logs on devices whose bandwidth profile does not
+        /// do so by default
+        #[serde(default)]
+        follow: bool,
+        /// Applied on the device, so only matching lines are sent
+        #[serde(default)]
+        filter: LogFilter,
+        /// Most lines to replay with `filter.since_secs`
+        #[serde(default)]
+        tail: Option<usize>,
     },
     Forward {
         token: String,
@@ -309,10 +365,312 @@ pub enum StreamType {
     },
     Ssh {
         token: String,
+        /// SFTP session of `m87 <device> cp`/`sync`/`ls`, limited separately
+        /// from interactive ssh
+        #[serde(default)]
+        transfer: bool,
+    },
+    Facts {
+        token: String,
+        /// Recollect instead of returning cached facts
+        #[serde(default)]
+        refresh: bool,
+    },
+    Sessions {
+        token: String,
+        /// Terminate this session before listing
+        #[serde(default)]
+        kill: Option<String>,
+    },
+    Time {
+        token: String,
+        /// Force an NTP resync before reporting
+        #[serde(default)]
+        sync: bool,
+    },
+    /// Firewall rules the runtime manages for the ports runs expose
+    Firewall {
+        token: String,
+    },
+    /// What applying a deployment revision would change, without applying
+    /// it. The client sends the revision as one JSON line after the header.
+    Plan {
+        token: String,
+    },
+    Gui {
+        token: String,
+        command: String,
+        /// MIT-MAGIC-COOKIE-1 of the local X server, hex encoded
+        #[serde(default)]
+        xauth_cookie: Option<String>,
+    },
+    Vnc {
+        token: String,
+        /// Port of a running VNC server; when unset the runtime uses 5900 or
+        /// starts a server for the local desktop
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    /// Resolve a host name with the device's resolver, for forwards to names
+    /// only known on the device network
+    Resolve {
+        token: String,
+        host: String,
+    },
+    /// Run the device's package manager (`m87 <device> pkg`)
+    Packages {
+        token: String,
+        action: PackageAction,
+        #[serde(default)]
+        packages: Vec<String>,
+        /// Only show what would change
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Collect logs, state and diagnostics for `m87 <device> support-bundle`
+    SupportBundle {
+        token: String,
+        /// Stop adding files once this many bytes were sent
+        max_bytes: u64,
+    },
+}
+
+/// Application-level ping a client sends on a `Terminal` stream, so dead
+/// paths through the relay are noticed and NAT mappings stay open. Like the
+/// `0xFF` resize frame it never occurs in terminal input.
+pub const TERMINAL_PING: u8 = 0xFE;
+
+/// Sent by the runtime on `Terminal` streams opened with `predict`, followed
+/// by `1` or `0` and BEL, when it changes whether typed characters are echoed
+/// verbatim. Terminals ignore this private OSC; the CLI strips it.
+pub const PREDICT_FRAME: &[u8] = b"\x1b]7787;m87-echo=";
+
+pub fn predict_frame(echoes: bool) -> Vec<u8> {
+    [PREDICT_FRAME, if echoes { b"1\x07" } else { b"0\x07" }].concat()
+}
+
+/// Keepalive class of a connection, selecting its QUIC timeouts from
+/// [`crate::config::KeepaliveConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamClass {
+    Interactive,
+    Bulk,
+    Control,
+}
+
+/// A shell or exec session open on the device.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SessionInfo {
+    pub id: String,
+    /// `shell` or `exec`
+    pub kind: String,
+    /// Operator the session was opened by (email or subject of the token)
+    pub user: String,
+    /// Unix timestamp in seconds
+    pub started_at: u64,
+    /// Command of an exec session or TERM of a shell
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+/// Reply on a `Sessions` stream: the open sessions, or why the request failed.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SessionsReply {
+    pub sessions: Vec<SessionInfo>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Reply on a `Time` stream: the device clock and NTP state.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct TimeReply {
+    /// Device clock (unix millis) when the reply was written
+    pub device_time: u64,
+    pub status: TimeStatus,
+    /// Output of the resync command
+    #[serde(default)]
+    pub output: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Firewall the runtime manages the rules of.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirewallBackend {
+    /// Leave the firewall alone
+    #[default]
+    Off,
+    /// An `inet m87` table dropping inbound connections to undeclared ports
+    Nftables,
+    /// `ufw allow` rules for the declared ports, under ufw's own policy
+    Ufw,
+}
+
+impl FirewallBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FirewallBackend::Off => "off",
+            FirewallBackend::Nftables => "nftables",
+            FirewallBackend::Ufw => "ufw",
+        }
+    }
+}
+
+/// A port opened for a run.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FirewallRule {
+    pub port: u16,
+    pub protocol: PortProtocol,
+    pub run_id: String,
+}
+
+/// Reply on a `Firewall` stream: what the runtime applied and last failed at.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct FirewallReply {
+    pub backend: FirewallBackend,
+    #[serde(default)]
+    pub rules: Vec<FirewallRule>,
+    /// Traffic let through whatever the runs declare
+    #[serde(default)]
+    pub always_allowed: Vec<String>,
+    /// Unix millis of the last successful apply
+    #[serde(default)]
+    pub applied_at: Option<u64>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// What the runtime would do with a run to apply a candidate revision.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlanAction {
+    Add,
+    /// Replaces the run of the same id; a replaced service is stopped first
+    Change,
+    /// Unchanged, but its last run did not complete, so it runs again
+    Rerun,
+    /// Unchanged and completed; nothing happens
+    Keep,
+    /// Stopped if it is a service, then forgotten
+    Remove,
+    /// Disabled, or its `when` condition does not hold on the device
+    Skip,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlannedRun {
+    pub run_id: String,
+    pub run_type: RunType,
+    pub action: PlanAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Stop steps of the service being replaced or removed, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop_steps: Vec<String>,
+    /// Steps that would run, in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<String>,
+    /// Files that would be written because they are missing or differ,
+    /// relative to `workdir`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    pub workdir: String,
+}
+
+/// Reply on a `Plan` stream: the runs of the candidate and the held
+/// revision, in that order, or why no plan could be made.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct PlanReply {
+    /// Revision the runtime holds now
+    #[serde(default)]
+    pub current_revision_id: Option<String>,
+    #[serde(default)]
+    pub candidate_revision_id: Option<String>,
+    /// The candidate is the held revision; applying it does nothing
+    #[serde(default)]
+    pub unchanged: bool,
+    #[serde(default)]
+    pub runs: Vec<PlannedRun>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Reply on a `Resolve` stream: the addresses of the host as seen by the device.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct ResolveReply {
+    #[serde(default)]
+    pub addrs: Vec<IpAddr>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// One JSON line on a `Packages` stream.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum PackageEvent {
+    /// A line of package manager output
+    Output { line: String },
+    /// Last message of the stream
+    Done { report: PackageOperationReport },
+}
+
+/// Header line of one file on a `SupportBundle` stream; `size` bytes of
+/// content follow it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundleEntry {
+    /// Path inside the bundle
+    pub name: String,
+    pub size: u64,
+    /// Only the end of the file was sent
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// Control message on a `Gui` stream (see `streams::mux::CONTROL`).
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GuiEvent {
+    /// The command was started with this `DISPLAY`.
+    Started {
+        display: String,
+    },
+    Exited {
+        exit_code: i32,
+    },
+    Error {
+        message: String,
     },
 }
 
 impl StreamType {
+    pub fn class(&self) -> StreamClass {
+        match self {
+            StreamType::Terminal { .. }
+            | StreamType::Exec { .. }
+            | StreamType::Ssh { .. }
+            | StreamType::Serial { .. }
+            | StreamType::Gui { .. }
+            | StreamType::Vnc { .. } => StreamClass::Interactive,
+            _ => StreamClass::Bulk,
+        }
+    }
+
+    /// Class the stream's bytes are counted under in `m87 <device> usage`.
+    pub fn traffic_class(&self) -> TrafficClass {
+        match self {
+            StreamType::Ssh { transfer: true, .. } | StreamType::SupportBundle { .. } => {
+                TrafficClass::Files
+            }
+            StreamType::Logs { .. } => TrafficClass::Logs,
+            StreamType::Metrics { .. } => TrafficClass::Metrics,
+            StreamType::Forward { .. } => TrafficClass::Forward,
+            StreamType::Docker { .. } => TrafficClass::Interactive,
+            st if st.class() == StreamClass::Interactive => TrafficClass::Interactive,
+            _ => TrafficClass::Other,
+        }
+    }
+
     pub fn variant_name(&self) -> &'static str {
         match self {
             StreamType::Terminal { .. } => "Terminal",
@@ -323,6 +681,16 @@ impl StreamType {
             StreamType::Metrics { .. } => "Metrics",
             StreamType::Docker { .. } => "Docker",
             StreamType::Ssh { .. } => "Ssh",
+            StreamType::Facts { .. } => "Facts",
+            StreamType::Sessions { .. } => "Sessions",
+            StreamType::Time { .. } => "Time",
+            StreamType::Firewall { .. } => "Firewall",
+            StreamType::Plan { .. } => "Plan",
+            StreamType::Gui { .. } => "Gui",
+            StreamType::Vnc { .. } => "Vnc",
+            StreamType::Resolve { .. } => "Resolve",
+            StreamType::Packages { .. } => "Packages",
+            StreamType::SupportBundle { .. } => "SupportBundle",
         }
     }
 
@@ -335,11 +703,25 @@ impl StreamType {
             StreamType::Serial { token, .. } => token,
             StreamType::Metrics { token } => token,
             StreamType::Docker { token } => token,
-            StreamType::Ssh { token } => token,
+            StreamType::Ssh { token, .. } => token,
+            StreamType::Facts { token, .. } => token,
+            StreamType::Sessions { token, .. } => token,
+            StreamType::Time { token, .. } => token,
+            StreamType::Firewall { token } => token,
+            StreamType::Plan { token } => token,
+            StreamType::Gui { token, .. } => token,
+            StreamType::Vnc { token, .. } => token,
+            StreamType::Resolve { token, .. } => token,
+            StreamType::Packages { token, .. } => token,
+            StreamType::SupportBundle { token, .. } => token,
         }
     }
 
-    pub async fn from_incoming_stream(recv: &mut quinn::RecvStream) -> anyhow::Result<StreamType> {
+    /// Read the stream header. The raw JSON is returned too, for the role
+    /// checks in `m87_shared::roles`.
+    pub async fn from_incoming_stream(
+        recv: &mut quinn::RecvStream,
+    ) -> anyhow::Result<(StreamType, serde_json::Value)> {
         // length header
         let mut len_buf = [0u8; 4];
         recv.read_exact(&mut len_buf).await?;
@@ -349,9 +731,9 @@ impl StreamType {
         let mut buf = vec![0u8; len];
         recv.read_exact(&mut buf).await?;
 
-        // deserialize directly into enum
-        let msg: StreamType = serde_json::from_slice(&buf)?;
-        Ok(msg)
+        let header: serde_json::Value = serde_json::from_slice(&buf)?;
+        let msg = StreamType::deserialize(&header)?;
+        Ok((msg, header))
     }
 }
 
@@ -518,6 +900,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_ipv6_host() {
+        let targets = ForwardTarget::from_list(vec!["8080:[fd00::5]:9090/udp".to_string()]).unwrap();
+        assert_eq!(targets.len(), 1);
+        match &targets[0] {
+            ForwardTarget::Udp(t) => {
+                assert_eq!(t.local_port, 8080);
+                assert_eq!(t.remote_port, 9090);
+                assert_eq!(t.remote_host, "fd00::5");
+            }
+            _ => panic!("Expected UdpTarget"),
+        }
+    }
+
     // --- StreamType helper tests ---
 
     #[test]
@@ -527,21 +923,30 @@ mod tests {
         assert_eq!(
             StreamType::Terminal {
                 token: token.clone(),
-                term: None
+                term: None,
+                attach: None,
+                read_only: false,
+                resume: None,
+                predict: false,
             }
             .variant_name(),
             "Terminal"
         );
         assert_eq!(
             StreamType::Exec {
-                token: token.clone()
+                token: token.clone(),
+                command: None,
             }
             .variant_name(),
             "Exec"
         );
         assert_eq!(
             StreamType::Logs {
-                token: token.clone()
+                token: token.clone(),
+                level: None,
+                follow: false,
+                filter: LogFilter::default(),
+                tail: None,
             }
             .variant_name(),
             "Logs"
@@ -581,7 +986,95 @@ mod tests {
             .variant_name(),
             "Docker"
         );
-        assert_eq!(StreamType::Ssh { token }.variant_name(), "Ssh");
+        assert_eq!(
+            StreamType::Facts {
+                token: token.clone(),
+                refresh: false
+            }
+            .variant_name(),
+            "Facts"
+        );
+        assert_eq!(
+            StreamType::Sessions {
+                token: token.clone(),
+                kill: None
+            }
+            .variant_name(),
+            "Sessions"
+        );
+        assert_eq!(
+            StreamType::Time {
+                token: token.clone(),
+                sync: true
+            }
+            .variant_name(),
+            "Time"
+        );
+        assert_eq!(
+            StreamType::Firewall {
+                token: token.clone()
+            }
+            .variant_name(),
+            "Firewall"
+        );
+        assert_eq!(
+            StreamType::Plan {
+                token: token.clone()
+            }
+            .variant_name(),
+            "Plan"
+        );
+        assert_eq!(
+            StreamType::Gui {
+                token: token.clone(),
+                command: "rqt".to_string(),
+                xauth_cookie: None,
+            }
+            .variant_name(),
+            "Gui"
+        );
+        assert_eq!(
+            StreamType::Vnc {
+                token: token.clone(),
+                port: None,
+            }
+            .variant_name(),
+            "Vnc"
+        );
+        assert_eq!(
+            StreamType::Resolve {
+                token: token.clone(),
+                host: "mydb.internal".to_string(),
+            }
+            .variant_name(),
+            "Resolve"
+        );
+        assert_eq!(
+            StreamType::Packages {
+                token: token.clone(),
+                action: PackageAction::Upgrade,
+                packages: vec![],
+                dry_run: true,
+            }
+            .variant_name(),
+            "Packages"
+        );
+        assert_eq!(
+            StreamType::SupportBundle {
+                token: token.clone(),
+                max_bytes: 1024,
+            }
+            .variant_name(),
+            "SupportBundle"
+        );
+        assert_eq!(
+            StreamType::Ssh {
+                token,
+                transfer: false
+            }
+            .variant_name(),
+            "Ssh"
+        );
     }
 
     #[test]
@@ -591,21 +1084,30 @@ mod tests {
         assert_eq!(
             StreamType::Terminal {
                 token: token.clone(),
-                term: Some("xterm".to_string())
+                term: Some("xterm".to_string()),
+                attach: Some("1a2b3c4d".to_string()),
+                read_only: true,
+                resume: None,
+                predict: false,
             }
             .get_token(),
             "my-unique-token"
         );
         assert_eq!(
             StreamType::Exec {
-                token: token.clone()
+                token: token.clone(),
+                command: None,
             }
             .get_token(),
             "my-unique-token"
         );
         assert_eq!(
             StreamType::Logs {
-                token: token.clone()
+                token: token.clone(),
+                level: None,
+                follow: false,
+                filter: LogFilter::default(),
+                tail: None,
             }
             .get_token(),
             "my-unique-token"
@@ -624,7 +1126,14 @@ mod tests {
             .get_token(),
             "my-unique-token"
         );
-        assert_eq!(StreamType::Ssh { token }.get_token(), "my-unique-token");
+        assert_eq!(
+            StreamType::Ssh {
+                token,
+                transfer: false
+            }
+            .get_token(),
+            "my-unique-token"
+        );
     }
 
     // --- ForwardParseError Display tests ---
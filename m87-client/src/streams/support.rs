@@ -0,0 +1,206 @@
+//! Device side of `m87 <device> support-bundle`: runtime logs, config,
+//! revisions, run states, queued reports and diagnostics, each sent as a
+//! [`BundleEntry`] line and its content. Everything is scrubbed of secrets
+//! before it leaves the device.
+
+use std::path::Path;
+
+use anyhow::Result;
+use m87_shared::redact::{scrub, scrub_json};
+use serde::Serialize;
+use serde_json::json;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::warn;
+
+use crate::{
+    config::Config,
+    device::{
+        deployment_manager::{self, RevisionStore},
+        fact_collectors::get_facts,
+        gc, health, redaction,
+    },
+    streams::{e2e::StreamIo, stream_type::BundleEntry},
+    util::system_info::get_system_info,
+};
+
+/// At most this much of each log file is sent, from its end
+const MAX_LOG_BYTES: u64 = 8 * 1024 * 1024;
+
+struct Bundle<'a> {
+    io: &'a mut StreamIo,
+    /// Bytes that may still be sent
+    left: u64,
+    skipped: Vec<String>,
+}
+
+impl Bundle<'_> {
+    async fn add(&mut self, name: &str, content: &[u8], truncated: bool) -> Result<()> {
+        if content.len() as u64 > self.left {
+            self.skipped.push(name.to_string());
+            return Ok(());
+        }
+        self.left -= content.len() as u64;
+        let entry = BundleEntry {
+            name: name.to_string(),
+            size: content.len() as u64,
+            truncated,
+        };
+        let mut header = serde_json::to_vec(&entry)?;
+        header.push(b'\n');
+        self.io.write_all(&header).await?;
+        self.io.write_all(content).await?;
+        Ok(())
+    }
+
+    /// Add `value` as scrubbed JSON, or why it could not be collected.
+    async fn add_json<T: Serialize>(&mut self, name: &str, value: Result<T>) -> Result<()> {
+        let value = value.and_then(|v| Ok(serde_json::to_value(v)?));
+        match value {
+            Ok(mut value) => {
+                scrub_json(&mut value);
+                let content = serde_json::to_vec_pretty(&value)?;
+                self.add(&format!("{}.json", name), &content, false).await
+            }
+            Err(e) => {
+                let message = scrub(&format!("{:#}\n", e));
+                self.add(&format!("{}.error.txt", name), message.as_bytes(), false)
+                    .await
+            }
+        }
+    }
+
+    /// Add the end of the log at `path`, as much as is left of the limit.
+    async fn add_log(&mut self, path: &Path) -> Result<()> {
+        let Some(file_name) = path.file_name().and_then(|n| n.to_str()) else {
+            return Ok(());
+        };
+        let name = format!("logs/{}", file_name);
+        let (mut text, truncated) = match read_tail(path, self.left.min(MAX_LOG_BYTES)).await {
+            Ok(tail) => tail,
+            Err(e) => {
+                let message = format!("{:#}\n", e);
+                return self
+                    .add(&format!("{}.error.txt", name), message.as_bytes(), false)
+                    .await;
+            }
+        };
+        if truncated {
+            // drop the partial first line
+            let start = text.find('\n').map(|i| i + 1).unwrap_or(text.len());
+            text.drain(..start);
+        }
+        // masking can make a line longer than it was
+        let text = redaction::redact(&text);
+        let start = text.len().saturating_sub(self.left as usize);
+        let start = (start..text.len())
+            .find(|&i| text.is_char_boundary(i))
+            .unwrap_or(text.len());
+        self.add(&name, &text.as_bytes()[start..], truncated || start > 0)
+            .await
+    }
+}
+
+/// The last `max` bytes of the file at `path` and whether that is not all of it.
+async fn read_tail(path: &Path, max: u64) -> Result<(String, bool)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    let start = len.saturating_sub(max);
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    let mut buf = Vec::with_capacity((len - start) as usize);
+    file.take(max).read_to_end(&mut buf).await?;
+    Ok((String::from_utf8_lossy(&buf).into_owned(), start > 0))
+}
+
+fn run_states() -> Result<serde_json::Value> {
+    let Some((revision, states)) = deployment_manager::local_run_states()? else {
+        return Ok(serde_json::Value::Null);
+    };
+    let runs: Vec<_> = states
+        .into_iter()
+        .map(|(spec, state)| json!({ "id": spec.id, "state": state }))
+        .collect();
+    Ok(json!({ "revision": revision.id, "runs": runs }))
+}
+
+async fn queued_events() -> Result<serde_json::Value> {
+    let events: Vec<_> = deployment_manager::list_events()
+        .await?
+        .into_iter()
+        .map(|e| json!({ "key": e.key, "state": e.state.to_string(), "event": e.event }))
+        .collect();
+    Ok(json!(events))
+}
+
+fn diagnostics() -> Result<serde_json::Value> {
+    let config = Config::load()?;
+    Ok(json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "health": health::health(),
+        "server_url": config.get_runtime_server_url(),
+        "control_host": config.get_runtime_server_hostname(),
+        "end_to_end": config.end_to_end,
+        "trust_invalid_server_cert": config.trust_invalid_server_cert,
+    }))
+}
+
+async fn collect(bundle: &mut Bundle<'_>) -> Result<()> {
+    bundle.add_json("diagnostics", diagnostics()).await?;
+    bundle.add_json("config", Config::load()).await?;
+    bundle
+        .add_json("revisions/desired", RevisionStore::get_desired_config())
+        .await?;
+    bundle
+        .add_json("revisions/previous", RevisionStore::get_previous_config())
+        .await?;
+    bundle.add_json("run_states", run_states()).await?;
+    bundle
+        .add_json("queued_reports", queued_events().await)
+        .await?;
+    bundle
+        .add_json("system_info", get_system_info().await)
+        .await?;
+    bundle
+        .add_json("facts", Ok::<_, anyhow::Error>(get_facts(false).await))
+        .await?;
+    match gc::runtime_logs().await {
+        Ok(logs) => {
+            for path in logs {
+                if bundle.left == 0 {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy();
+                    bundle.skipped.push(format!("logs/{}", name));
+                    continue;
+                }
+                bundle.add_log(&path).await?;
+            }
+        }
+        Err(e) => {
+            bundle.add_json("logs", Err::<(), _>(e)).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Send the bundle entries, at most `max_bytes` of content, and close the
+/// stream. Files that do not fit are listed in `skipped.txt`.
+pub async fn handle_support_io(max_bytes: u64, io: &mut StreamIo) {
+    let mut bundle = Bundle {
+        io,
+        left: max_bytes,
+        skipped: Vec::new(),
+    };
+    if let Err(e) = collect(&mut bundle).await {
+        warn!("support bundle: {:#}", e);
+        let _ = bundle.io.shutdown().await;
+        return;
+    }
+    if !bundle.skipped.is_empty() {
+        let list = format!(
+            "Not included, the bundle size limit was reached:\n{}\n",
+            bundle.skipped.join("\n")
+        );
+        // the note itself does not count against the limit
+        bundle.left = u64::MAX;
+        let _ = bundle.add("skipped.txt", list.as_bytes(), false).await;
+    }
+    let _ = bundle.io.shutdown().await;
+}
@@ -1,20 +1,35 @@
-use portable_pty::{CommandBuilder, PtySize, native_pty_system};
-use tokio::sync::{Mutex, mpsc, oneshot};
+use portable_pty::{CommandBuilder, MasterPty, PtySize, native_pty_system};
+use tokio::sync::{Mutex, broadcast, mpsc, oneshot};
 use tokio::{
     io::AsyncReadExt,
     io::AsyncWriteExt,
     select,
-    time::{Duration, timeout},
+    time::{Duration, Instant, sleep_until, timeout},
 };
+use tracing::info;
 
 use std::path::Path;
 use std::{io::Read, io::Write, sync::Arc};
 
-use crate::streams::quic::QuicIo;
-
-pub async fn handle_terminal_io(term: Option<String>, io: &mut QuicIo) {
+use crate::config::Config;
+use crate::streams::e2e::StreamIo;
+use crate::streams::sessions::{self, Session};
+use crate::streams::stream_type::{TERMINAL_PING as PING, predict_frame};
+use crate::util::clipboard::Osc52Filter;
+
+/// Output kept for a disconnected shell and replayed when it is resumed.
+const MAX_BACKLOG: usize = 64 * 1024;
+
+pub async fn handle_terminal_io(
+    term: Option<String>,
+    predict: bool,
+    session: &Session,
+    io: &mut StreamIo,
+) {
     // Notify client that shell is initializing
-    let _ = io.write_all(b"\n\rInitializing shell..").await;
+    let _ = io
+        .write_all(format!("\n\rInitializing shell (session {})..", session.id()).as_bytes())
+        .await;
 
     // --------------------------------------------------------------------
     // 1. Create PTY
@@ -58,6 +73,11 @@ pub async fn handle_terminal_io(term: Option<String>, io: &mut QuicIo) {
     let term = term.as_deref().unwrap_or("xterm-256color");
     cmd.env("TERM", term);
     cmd.env("COLORTERM", "truecolor");
+    // launchd starts services without a locale, which leaves zsh unable to
+    // handle UTF-8 input
+    if cfg!(target_os = "macos") && std::env::var_os("LANG").is_none() {
+        cmd.env("LANG", "en_US.UTF-8");
+    }
 
     let mut child = match pair.slave.spawn_command(cmd) {
         Ok(c) => c,
@@ -136,21 +156,50 @@ pub async fn handle_terminal_io(term: Option<String>, io: &mut QuicIo) {
 
     let _ = io.write_all(b"Shell connected successfully\r\n").await;
 
+    // Other operators can attach to this shell: PTY output is fanned out to
+    // them and their keystrokes are fed into the same PTY.
+    let (shared_tx, _) = broadcast::channel::<Vec<u8>>(256);
+    let (attach_tx, mut attach_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    session.share(&shared_tx, attach_tx);
+
     // --------------------------------------------------------------------
     // 5. Main loop: IO <-> PTY
     // --------------------------------------------------------------------
+    // When the client's connection drops, the shell keeps running for the
+    // resume window and its output is buffered until the client reconnects.
+    let config = Config::load().ok();
+    let resume_window = Duration::from_secs(
+        config
+            .as_ref()
+            .map(|c| c.keepalive.shell_resume_secs)
+            .unwrap_or(0),
+    );
+    let mut osc52 = Osc52Filter::new(config.map(|c| c.clipboard).unwrap_or_default());
+    let mut resume_rx = session.resumable();
+    let mut connected = true;
+    let mut detached_until = Instant::now();
+    let mut backlog: Vec<u8> = Vec::new();
+    let shell_pid = child.process_id();
+    let mut reported_echo: Option<bool> = None;
+
     let mut io_read_buf = [0u8; 1024];
     let mut input_buf: Vec<u8> = Vec::new();
     'outer: loop {
         select! {
             // ---------- CLIENT → PTY ----------
-            r = io.read(&mut io_read_buf) => {
+            r = io.read(&mut io_read_buf), if connected => {
                 match r {
                     Ok(0) => break 'outer,
                     Ok(n) => {
                         input_buf.extend_from_slice(&io_read_buf[..n]);
 
                         while !input_buf.is_empty() {
+                            // ----- PING FRAME -----
+                            if input_buf[0] == PING {
+                                input_buf.drain(..1);
+                                continue;
+                            }
+
                             // ----- RESIZE FRAME -----
                             if input_buf.len() >= 5 && input_buf[0] == 0xFF {
                                 let rows = u16::from_be_bytes([input_buf[1], input_buf[2]]);
@@ -169,46 +218,116 @@ pub async fn handle_terminal_io(term: Option<String>, io: &mut QuicIo) {
                             }
 
                             // ----- NORMAL INPUT -----
-                            // everything until next resize marker or end
-                            let next_resize = input_buf
+                            // everything until next control frame or end
+                            let next_frame = input_buf
                                 .iter()
-                                .position(|&b| b == 0xFF)
+                                .position(|&b| b == 0xFF || b == PING)
                                 .unwrap_or(input_buf.len());
+                            if next_frame == 0 {
+                                // incomplete resize frame, wait for the rest
+                                break;
+                            }
+
+                            let payload: Vec<u8> = input_buf.drain(..next_frame).collect();
 
-                            let payload: Vec<u8> = input_buf.drain(..next_resize).collect();
-
-                            if !payload.is_empty() {
-                                let writer = writer.clone();
-
-                                if tokio::task::spawn_blocking(move || {
-                                    let mut w = writer.blocking_lock();
-                                    w.write_all(&payload)?;
-                                    w.flush()
-                                })
-                                .await
-                                .is_err()
-                                {
-                                    break 'outer;
-                                }
+                            let writer = writer.clone();
+
+                            if tokio::task::spawn_blocking(move || {
+                                let mut w = writer.blocking_lock();
+                                w.write_all(&payload)?;
+                                w.flush()
+                            })
+                            .await
+                            .is_err()
+                            {
+                                break 'outer;
                             }
                         }
                     }
-                    Err(_) => break 'outer,
+                    Err(e) => {
+                        info!("shell session {} lost its connection: {}", session.id(), e);
+                        connected = false;
+                        detached_until = Instant::now() + resume_window;
+                        input_buf.clear();
+                    }
                 }
             }
 
             // ---------- PTY → CLIENT ----------
             Some(out) = pty_rx.recv() => {
-                if io.write_all(&out).await.is_err() {
+                let out = osc52.filter(&out);
+                let _ = shared_tx.send(out.clone());
+                if !connected {
+                    backlog.extend_from_slice(&out);
+                    if backlog.len() > MAX_BACKLOG {
+                        backlog.drain(..backlog.len() - MAX_BACKLOG);
+                    }
+                } else if io.write_all(&out).await.is_err() {
+                    info!("shell session {} lost its connection", session.id());
+                    connected = false;
+                    detached_until = Instant::now() + resume_window;
+                    backlog.extend_from_slice(&out);
+                }
+            }
+
+            // ---------- ATTACHED OPERATOR → PTY ----------
+            Some(payload) = attach_rx.recv() => {
+                let writer = writer.clone();
+                if tokio::task::spawn_blocking(move || {
+                    let mut w = writer.blocking_lock();
+                    w.write_all(&payload)?;
+                    w.flush()
+                })
+                .await
+                .is_err()
+                {
                     break 'outer;
                 }
             }
 
+            // ---------- Client reconnected ----------
+            Some(resumed) = resume_rx.recv(), if !connected => {
+                info!("shell session {} resumed", session.id());
+                *io = resumed;
+                connected = true;
+                reported_echo = None;
+                let _ = io.write_all(b"\r\nSession resumed\r\n").await;
+                if io.write_all(&backlog).await.is_err() {
+                    connected = false;
+                    detached_until = Instant::now() + resume_window;
+                } else {
+                    backlog.clear();
+                }
+            }
+
+            // ---------- Resume window expired ----------
+            _ = sleep_until(detached_until), if !connected => {
+                info!("shell session {} was not resumed, closing it", session.id());
+                break 'outer;
+            }
+
             // ---------- Shell exit ----------
             _ = tokio::time::sleep(Duration::from_millis(50)) => {
                 if let Ok(Some(_)) = child.try_wait() {
                     break 'outer;
                 }
+
+                // ---------- Echo state for predictive local echo ----------
+                if predict && connected {
+                    let echoes = echoes_input(pair.master.as_ref(), shell_pid);
+                    if reported_echo != Some(echoes) {
+                        reported_echo = Some(echoes);
+                        let _ = io.write_all(&predict_frame(echoes)).await;
+                    }
+                }
+            }
+
+            // ---------- Killed via `sessions kill` ----------
+            _ = session.cancelled() => {
+                if connected {
+                    let _ = io.write_all(b"\r\nSession terminated by an operator\r\n").await;
+                }
+                break 'outer;
             }
         }
     }
@@ -217,9 +336,114 @@ pub async fn handle_terminal_io(term: Option<String>, io: &mut QuicIo) {
     // 6. Cleanup
     // --------------------------------------------------------------------
     let _ = child.kill();
+    if connected {
+        let _ = io.shutdown().await;
+    }
+}
+
+/// Mirror the shell session `target` onto `io`. Read-only attachments only
+/// receive output; otherwise keystrokes go to the shared PTY. Resize frames
+/// and pings are dropped since the owner's terminal determines the PTY size.
+pub async fn handle_attach_io(target: &str, read_only: bool, session: &Session, io: &mut StreamIo) {
+    let Some((mut output, input)) = sessions::attach(target) else {
+        let _ = io
+            .write_all(format!("\r\nNo shell session with id '{}'\r\n", target).as_bytes())
+            .await;
+        let _ = io.shutdown().await;
+        return;
+    };
+
+    let mode = if read_only { "read-only" } else { "read-write" };
+    let _ = io
+        .write_all(format!("\r\nAttached to session {} ({})\r\n", target, mode).as_bytes())
+        .await;
+
+    let mut io_read_buf = [0u8; 1024];
+    let mut input_buf: Vec<u8> = Vec::new();
+    loop {
+        select! {
+            r = io.read(&mut io_read_buf) => {
+                match r {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => {
+                        input_buf.extend_from_slice(&io_read_buf[..n]);
+                        let payload = strip_control_frames(&mut input_buf);
+                        if !read_only && !payload.is_empty() && input.send(payload).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+
+            out = output.recv() => {
+                match out {
+                    Ok(out) => {
+                        if io.write_all(&out).await.is_err() {
+                            break;
+                        }
+                    }
+                    // Slow attachment: skip what it missed rather than stall the owner.
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => {
+                        let _ = io.write_all(b"\r\nShell session ended\r\n").await;
+                        break;
+                    }
+                }
+            }
+
+            _ = session.cancelled() => {
+                let _ = io.write_all(b"\r\nSession terminated by an operator\r\n").await;
+                break;
+            }
+        }
+    }
+
     let _ = io.shutdown().await;
 }
 
+/// Remove pings and complete `0xFF rows cols` resize frames from `buf` and
+/// return the input bytes in between. An incomplete trailing frame stays in `buf`.
+fn strip_control_frames(buf: &mut Vec<u8>) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(buf.len());
+    let mut i = 0;
+    while i < buf.len() {
+        if buf[i] == PING {
+            i += 1;
+        } else if buf[i] == 0xFF {
+            if buf.len() - i < 5 {
+                break;
+            }
+            i += 5;
+        } else {
+            payload.push(buf[i]);
+            i += 1;
+        }
+    }
+    buf.drain(..i);
+    payload
+}
+
+/// Whether characters typed now will be echoed verbatim. Readline turns off
+/// ICANON and echoes itself while the login shell is in the foreground;
+/// otherwise only canonical mode with ECHO on (e.g. `read`) echoes. Full-screen
+/// programs and password prompts do neither.
+fn echoes_input(master: &dyn MasterPty, shell_pid: Option<u32>) -> bool {
+    let Some(fd) = master.as_raw_fd() else {
+        return false;
+    };
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return false;
+    }
+    if termios.c_lflag & libc::ICANON != 0 {
+        return termios.c_lflag & libc::ECHO != 0;
+    }
+    match (master.process_group_leader(), shell_pid) {
+        (Some(leader), Some(pid)) => leader as u32 == pid,
+        _ => false,
+    }
+}
+
 fn detect_shell() -> String {
     if cfg!(windows) {
         return "powershell.exe".to_string();
@@ -230,7 +454,19 @@ fn detect_shell() -> String {
             return shell;
         }
     }
-    for c in ["/bin/bash", "/bin/zsh", "/usr/bin/fish", "/bin/sh"] {
+    // services run without $SHELL, e.g. under launchd
+    #[cfg(unix)]
+    if let Some(shell) = crate::util::ssh::login_shell()
+        && Path::new(&shell).exists()
+    {
+        return shell;
+    }
+    let candidates = if cfg!(target_os = "macos") {
+        ["/bin/zsh", "/bin/bash", "/usr/bin/fish", "/bin/sh"]
+    } else {
+        ["/bin/bash", "/bin/zsh", "/usr/bin/fish", "/bin/sh"]
+    };
+    for c in candidates {
         if Path::new(c).exists() {
             return c.to_string();
         }
@@ -281,9 +517,11 @@ mod tests {
             "powershell.exe",
         ];
 
-        // Either from env var or one of the common shells
+        // Either from env var, the passwd entry or one of the common shells
         let is_common = common_shells.iter().any(|s| shell.ends_with(s) || *s == shell);
         let from_env = std::env::var("SHELL").is_ok();
+        #[cfg(unix)]
+        let from_env = from_env || crate::util::ssh::login_shell().as_deref() == Some(shell.as_str());
 
         assert!(is_common || from_env);
     }
@@ -307,6 +545,19 @@ mod tests {
         assert_eq!(cols, 80);
     }
 
+    #[test]
+    fn test_strip_control_frames() {
+        let mut buf = vec![
+            b'l', b's', 0xFF, 0x00, 0x18, 0x00, 0x50, PING, b'\r', 0xFF, 0x00,
+        ];
+        assert_eq!(strip_control_frames(&mut buf), b"ls\r");
+        assert_eq!(buf, vec![0xFF, 0x00]);
+
+        buf.extend_from_slice(&[0x18, 0x00, 0x50]);
+        assert!(strip_control_frames(&mut buf).is_empty());
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn test_pty_size_parsing_large_terminal() {
         // Test larger terminal size
@@ -0,0 +1,33 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+use crate::{device::time_sync, streams::quic::QuicIo, streams::stream_type::TimeReply};
+
+/// Reply with the device clock and NTP state as one JSON line, after forcing
+/// a resync if `sync` is set.
+pub async fn handle_time_io(sync: bool, io: &mut QuicIo) {
+    let mut reply = TimeReply::default();
+    if sync {
+        info!("forcing time resync");
+        match time_sync::sync().await {
+            Ok(output) => reply.output = Some(output).filter(|o| !o.is_empty()),
+            Err(e) => reply.error = Some(format!("{:#}", e)),
+        }
+    }
+    reply.status = time_sync::status().await;
+    reply.device_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+
+    match serde_json::to_string(&reply) {
+        Ok(json) => {
+            let _ = io.write_all(json.as_bytes()).await;
+            let _ = io.write_all(b"\n").await;
+        }
+        Err(e) => warn!("failed to serialize time: {}", e),
+    }
+    let _ = io.shutdown().await;
+}
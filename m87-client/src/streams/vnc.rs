@@ -0,0 +1,213 @@
+//! Remote desktop for `m87 <device> vnc`. The stream is connected to a VNC
+//! server on the device; when none is listening on the default port, one is
+//! started for the local desktop (wayvnc on Wayland, x11vnc on X11) and
+//! stopped again when the stream ends.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+use tokio::time::{Duration, Instant, sleep};
+use tracing::{debug, warn};
+
+use crate::streams::quic::QuicIo;
+use crate::streams::sessions::Session;
+
+const DEFAULT_PORT: u16 = 5900;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Desktop session found on the device.
+#[derive(Debug, PartialEq)]
+enum Desktop {
+    Wayland {
+        runtime_dir: PathBuf,
+        socket: String,
+    },
+    X11 {
+        display: String,
+    },
+}
+
+pub async fn handle_vnc_io(port: Option<u16>, session: &Session, io: &mut QuicIo) {
+    let (mut server, _child) = match connect_or_start(port).await {
+        Ok(connected) => connected,
+        Err(reason) => {
+            warn!("vnc: {}", reason);
+            // Viewers show the reason of a failed RFB 3.3 handshake to the user.
+            let _ = io.write_all(&rfb_failure(&reason)).await;
+            let _ = io.shutdown().await;
+            return;
+        }
+    };
+    session.set_detail(&format!(
+        "127.0.0.1:{}",
+        server.peer_addr().map(|a| a.port()).unwrap_or_default()
+    ));
+
+    tokio::select! {
+        res = tokio::io::copy_bidirectional(io, &mut server) => {
+            debug!("vnc: stream closed: {:?}", res);
+        }
+        _ = session.cancelled() => {}
+    }
+}
+
+/// Connect to the requested or default VNC server, or start one. The child
+/// is returned so the server is killed when the stream ends.
+async fn connect_or_start(port: Option<u16>) -> Result<(TcpStream, Option<Child>), String> {
+    if let Some(port) = port {
+        let stream = TcpStream::connect(("127.0.0.1", port))
+            .await
+            .map_err(|e| format!("no VNC server on port {}: {}", port, e))?;
+        return Ok((stream, None));
+    }
+    if let Ok(stream) = TcpStream::connect(("127.0.0.1", DEFAULT_PORT)).await {
+        return Ok((stream, None));
+    }
+
+    let desktop =
+        find_desktop().ok_or("no VNC server is running and no desktop session was found")?;
+    let port = free_port()
+        .await
+        .map_err(|e| format!("failed to pick a port: {}", e))?;
+    let mut cmd = server_command(&desktop, port);
+    let program = cmd.as_std().get_program().to_string_lossy().into_owned();
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {}", program, e))?;
+    debug!(
+        "vnc: started {} for {:?} on port {}",
+        program, desktop, port
+    );
+
+    let deadline = Instant::now() + STARTUP_TIMEOUT;
+    loop {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)).await {
+            return Ok((stream, Some(child)));
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            return Err(format!("{} exited with {}", program, status));
+        }
+        if Instant::now() >= deadline {
+            return Err(format!("{} did not start listening", program));
+        }
+        sleep(Duration::from_millis(200)).await;
+    }
+}
+
+fn find_desktop() -> Option<Desktop> {
+    find_desktop_in(Path::new("/run/user"), Path::new("/tmp/.X11-unix"))
+}
+
+/// Prefer a Wayland compositor of any logged-in user, then the lowest X display.
+fn find_desktop_in(run_user: &Path, x11_dir: &Path) -> Option<Desktop> {
+    let mut runtime_dirs: Vec<PathBuf> = std::fs::read_dir(run_user)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .collect();
+    runtime_dirs.sort();
+    for runtime_dir in runtime_dirs {
+        if runtime_dir.join("wayland-0").exists() {
+            return Some(Desktop::Wayland {
+                runtime_dir,
+                socket: "wayland-0".to_string(),
+            });
+        }
+    }
+
+    let mut displays: Vec<u32> = std::fs::read_dir(x11_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| e.file_name().to_str()?.strip_prefix('X')?.parse().ok())
+        .collect();
+    displays.sort();
+    displays.first().map(|n| Desktop::X11 {
+        display: format!(":{}", n),
+    })
+}
+
+fn server_command(desktop: &Desktop, port: u16) -> Command {
+    match desktop {
+        Desktop::Wayland {
+            runtime_dir,
+            socket,
+        } => {
+            let mut cmd = Command::new("wayvnc");
+            cmd.env("XDG_RUNTIME_DIR", runtime_dir)
+                .env("WAYLAND_DISPLAY", socket)
+                .arg("127.0.0.1")
+                .arg(port.to_string());
+            cmd
+        }
+        Desktop::X11 { display } => {
+            let mut cmd = Command::new("x11vnc");
+            cmd.args(["-display", display, "-auth", "guess", "-localhost", "-nopw"])
+                .args(["-once", "-quiet", "-rfbport", &port.to_string()]);
+            cmd
+        }
+    }
+}
+
+async fn free_port() -> std::io::Result<u16> {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// RFB 3.3 server greeting that refuses the connection with `reason`.
+fn rfb_failure(reason: &str) -> Vec<u8> {
+    let mut msg = b"RFB 003.003\n".to_vec();
+    msg.extend_from_slice(&0u32.to_be_bytes());
+    msg.extend_from_slice(&(reason.len() as u32).to_be_bytes());
+    msg.extend_from_slice(reason.as_bytes());
+    msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfb_failure() {
+        assert_eq!(
+            rfb_failure("no"),
+            b"RFB 003.003\n\x00\x00\x00\x00\x00\x00\x00\x02no".to_vec()
+        );
+    }
+
+    #[test]
+    fn test_find_desktop_prefers_wayland() {
+        let root = std::env::temp_dir().join(format!("m87-vnc-{}", std::process::id()));
+        let run_user = root.join("run");
+        let x11 = root.join("x11");
+        std::fs::create_dir_all(run_user.join("1000")).unwrap();
+        std::fs::create_dir_all(&x11).unwrap();
+        std::fs::write(x11.join("X1"), b"").unwrap();
+        std::fs::write(x11.join("X0"), b"").unwrap();
+
+        assert_eq!(
+            find_desktop_in(&run_user, &x11),
+            Some(Desktop::X11 {
+                display: ":0".to_string()
+            })
+        );
+
+        std::fs::write(run_user.join("1000/wayland-0"), b"").unwrap();
+        assert_eq!(
+            find_desktop_in(&run_user, &x11),
+            Some(Desktop::Wayland {
+                runtime_dir: run_user.join("1000"),
+                socket: "wayland-0".to_string()
+            })
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! Warm connections: after a `shell`, `exec`, `ssh` or file transfer command,
+//! a background `m87` process keeps a connection to the device open for
+//! `warm_connection_secs`, so following commands to it skip DNS, the QUIC
+//! handshake and the token exchange.
+//!
+//! The helper listens on a unix socket only the user can open. A command
+//! sends the stream header as u32 BE length prefixed JSON; the helper opens
+//! the stream on its connection and answers [`OPENED`], or [`FAILED`] and a
+//! u16 BE length prefixed error. The socket then carries the stream's bytes.
+//! The first command to a device starts the helper and connects on its own.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::{interval, timeout};
+use tracing::{debug, info};
+
+use make87_sdk::streams::open_stream;
+
+use crate::auth::AuthManager;
+use crate::config::{Config, instance};
+use crate::streams::quic::connect_quic_only;
+use crate::streams::stream_type::StreamClass;
+use crate::util::command::current_exe_path;
+
+const OPENED: u8 = 0;
+const FAILED: u8 = 1;
+/// A helper not accepting within this long is treated as gone
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+/// Helpers exit once idle after this long, so a new one picks up refreshed
+/// credentials and config
+const MAX_LIFETIME: Duration = Duration::from_secs(3600);
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_HEADER: usize = 64 * 1024;
+
+/// Streams a helper relays and when it last relayed one.
+struct Usage {
+    active: usize,
+    last_used: Instant,
+}
+
+fn socket_dir() -> Result<PathBuf> {
+    let mut dir = dirs::runtime_dir()
+        .or_else(dirs::cache_dir)
+        .ok_or_else(|| anyhow!("Could not determine cache directory"))?;
+    dir.push("m87");
+    dir.push("warm");
+    Ok(dir)
+}
+
+/// Socket of the helper for `short_id` on `host`. Instances with their own
+/// config and credentials get their own helpers.
+fn socket_path(host: &str, short_id: &str) -> Result<PathBuf> {
+    let mut hasher = Sha256::new();
+    hasher.update(Config::config_file_path()?.to_string_lossy().as_bytes());
+    hasher.update([0]);
+    hasher.update(host.as_bytes());
+    let digest: String = hasher.finalize()[..6]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(socket_dir()?.join(format!("{}-{}.sock", short_id, digest)))
+}
+
+/// Open a stream with `header` through the helper of the device, if one runs.
+pub async fn open<T: Serialize>(host: &str, short_id: &str, header: &T) -> Option<UnixStream> {
+    let path = socket_path(host, short_id).ok()?;
+    if !path.exists() {
+        return None;
+    }
+    match timeout(OPEN_TIMEOUT, request(&path, header)).await {
+        Ok(Ok(stream)) => {
+            debug!("Using the warm connection to {}", short_id);
+            Some(stream)
+        }
+        Ok(Err(e)) => {
+            debug!("Warm connection to {} unusable: {:#}", short_id, e);
+            None
+        }
+        Err(_) => {
+            debug!("Warm connection to {} timed out", short_id);
+            None
+        }
+    }
+}
+
+async fn request<T: Serialize>(path: &Path, header: &T) -> Result<UnixStream> {
+    let mut stream = match timeout(CONNECT_TIMEOUT, UnixStream::connect(path)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            // left behind by a helper that did not exit cleanly
+            if e.kind() == std::io::ErrorKind::ConnectionRefused {
+                let _ = fs::remove_file(path);
+            }
+            return Err(e.into());
+        }
+        Err(_) => bail!("the helper did not accept"),
+    };
+    let json = serde_json::to_vec(header)?;
+    stream.write_u32(json.len() as u32).await?;
+    stream.write_all(&json).await?;
+    match stream.read_u8().await? {
+        OPENED => Ok(stream),
+        _ => {
+            let len = stream.read_u16().await?;
+            let mut message = vec![0u8; len as usize];
+            stream.read_exact(&mut message).await?;
+            bail!("{}", String::from_utf8_lossy(&message))
+        }
+    }
+}
+
+/// Start a helper for the device in the background, unless one is running.
+pub fn spawn(host: &str, short_id: &str) {
+    let Ok(path) = socket_path(host, short_id) else {
+        return;
+    };
+    if path.exists() {
+        return;
+    }
+    let exe = match current_exe_path() {
+        Ok(exe) => exe,
+        Err(e) => {
+            debug!("Not starting a connection helper: {:#}", e);
+            return;
+        }
+    };
+    let mut cmd = std::process::Command::new(exe);
+    if let Some(name) = instance() {
+        cmd.args(["--instance", name]);
+    }
+    cmd.args(["connection-helper", "--host", host, "--device", short_id])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // outlives the command and its terminal's Ctrl+C
+        .process_group(0);
+    if let Err(e) = cmd.spawn() {
+        debug!("Starting the connection helper failed: {}", e);
+    }
+}
+
+/// Stop all helpers of this user from taking new streams. Each exits once
+/// its open streams ended.
+pub fn stop_all() -> Result<()> {
+    let dir = socket_dir()?;
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "sock") {
+            let _ = fs::remove_file(&path);
+        }
+    }
+    Ok(())
+}
+
+/// Connect to the device and relay streams over the connection until it has
+/// been idle for `warm_connection_secs`. Run by `m87 connection-helper`.
+pub async fn serve(host: &str, short_id: &str) -> Result<()> {
+    let config = Config::load()?;
+    let persist = Duration::from_secs(config.warm_connection_secs);
+    if persist.is_zero() {
+        return Ok(());
+    }
+    let token = AuthManager::get_cli_token().await?;
+    let (endpoint, conn) = connect_quic_only(
+        host,
+        &token,
+        short_id,
+        config.trust_invalid_server_cert,
+        StreamClass::Interactive,
+    )
+    .await?;
+    let path = socket_path(host, short_id)?;
+    let listener = bind(&path).await?;
+    info!("Keeping the connection to {} warm", short_id);
+
+    let started = Instant::now();
+    let usage = Arc::new(Mutex::new(Usage {
+        active: 0,
+        last_used: started,
+    }));
+    let mut check = interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else {
+                    continue;
+                };
+                usage.lock().unwrap().active += 1;
+                let conn = conn.clone();
+                let usage = usage.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = relay(stream, &conn).await {
+                        debug!("Relayed stream ended: {:#}", e);
+                    }
+                    let mut usage = usage.lock().unwrap();
+                    usage.active -= 1;
+                    usage.last_used = Instant::now();
+                });
+            }
+            _ = conn.closed() => break,
+            _ = check.tick() => {
+                let usage = usage.lock().unwrap();
+                if usage.active > 0 {
+                    continue;
+                }
+                if usage.last_used.elapsed() >= persist
+                    || started.elapsed() >= MAX_LIFETIME
+                    || !path.exists()
+                {
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = fs::remove_file(&path);
+    conn.close(0u32.into(), b"idle");
+    let _ = timeout(Duration::from_secs(1), endpoint.wait_idle()).await;
+    Ok(())
+}
+
+/// Listen on `path`, unless another helper already does.
+async fn bind(path: &Path) -> Result<UnixListener> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow!("invalid socket path"))?;
+    fs::create_dir_all(dir).context("Failed to create the socket directory")?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            bail!("another connection helper is running");
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path).context("Failed to listen for commands")?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Open the stream a command asked for and copy between both until either
+/// side ends.
+async fn relay(mut local: UnixStream, conn: &quinn::Connection) -> Result<()> {
+    let len = local.read_u32().await? as usize;
+    if len > MAX_HEADER {
+        bail!("stream header of {} bytes exceeds the limit", len);
+    }
+    let mut json = vec![0u8; len];
+    local.read_exact(&mut json).await?;
+    let header: serde_json::Value = serde_json::from_slice(&json)?;
+    let mut remote = match open_stream(conn, &header).await {
+        Ok(remote) => remote,
+        Err(e) => {
+            let message = format!("{:#}", e);
+            let message = &message.as_bytes()[..message.len().min(u16::MAX as usize)];
+            local.write_u8(FAILED).await?;
+            local.write_u16(message.len() as u16).await?;
+            local.write_all(message).await?;
+            return Err(e);
+        }
+    };
+    local.write_u8(OPENED).await?;
+    tokio::io::copy_bidirectional(&mut local, &mut remote).await?;
+    Ok(())
+}
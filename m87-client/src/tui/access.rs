@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use m87_shared::access::AccessGrant;
+
+use crate::tui::helper::{Align, ColSpec, RenderOpts, Table, dim, role_badge, terminal_width};
+
+/// Time left on an active grant, e.g. `3h 20m`.
+fn remaining(expires_at: &str) -> String {
+    let Ok(time) = expires_at.parse::<DateTime<Utc>>() else {
+        return expires_at.to_string();
+    };
+    let mins = time.signed_duration_since(Utc::now()).num_minutes().max(0);
+    match (mins / 1440, mins / 60 % 24, mins % 60) {
+        (0, 0, m) => format!("{}m", m),
+        (0, h, m) => format!("{}h {}m", h, m),
+        (d, h, _) => format!("{}d {}h", d, h),
+    }
+}
+
+pub fn print_access_grants(grants: &[AccessGrant]) {
+    if grants.is_empty() {
+        println!("{}", dim("No access grants"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ID",
+                min: 24,
+                max: Some(24),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "USER",
+                min: 12,
+                max: Some(32),
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DEVICE",
+                min: 8,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "ROLE",
+                min: 6,
+                max: Some(8),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "EXPIRES IN",
+                min: 10,
+                max: Some(12),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "GRANTED BY",
+                min: 12,
+                max: Some(32),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    for grant in grants {
+        let expires = match (grant.active, &grant.revoked_by) {
+            (true, _) => remaining(&grant.expires_at),
+            (false, Some(_)) => dim("revoked"),
+            (false, None) => dim("expired"),
+        };
+        t.row(
+            &mut out,
+            &[
+                &grant.id,
+                &grant.email,
+                &grant.device_name,
+                &role_badge(&grant.role),
+                &expires,
+                &grant.granted_by,
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
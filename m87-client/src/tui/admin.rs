@@ -0,0 +1,191 @@
+use crate::tui::fs::human_size;
+use crate::tui::helper::{Align, ColSpec, RenderOpts, Table, bold, dim, terminal_width, yellow};
+use m87_shared::db_stats::CollectionStats;
+
+/// Collections per server, then the indexes the server expects but misses
+/// and the ones it does not create. `indexes` lists every index as well.
+pub fn print_db_stats(stats: &[(String, Vec<CollectionStats>)], indexes: bool) {
+    if stats.is_empty() {
+        println!("{}", dim("No servers answered"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+    let size = |v: Option<u64>| v.map(human_size).unwrap_or_else(|| "-".to_string());
+
+    for (i, (server, collections)) in stats.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("{}", bold(server));
+
+        let t = Table::new(
+            term_w.saturating_sub(2),
+            1,
+            vec![
+                ColSpec {
+                    title: "COLLECTION",
+                    min: 10,
+                    max: Some(28),
+                    weight: 2,
+                    align: Align::Left,
+                    wrap: false,
+                },
+                ColSpec {
+                    title: "DOCUMENTS",
+                    min: 9,
+                    max: Some(14),
+                    weight: 0,
+                    align: Align::Right,
+                    wrap: false,
+                },
+                ColSpec {
+                    title: "DATA",
+                    min: 9,
+                    max: Some(12),
+                    weight: 0,
+                    align: Align::Right,
+                    wrap: false,
+                },
+                ColSpec {
+                    title: "INDEX SIZE",
+                    min: 10,
+                    max: Some(12),
+                    weight: 0,
+                    align: Align::Right,
+                    wrap: false,
+                },
+                ColSpec {
+                    title: "INDEXES",
+                    min: 7,
+                    max: Some(16),
+                    weight: 0,
+                    align: Align::Right,
+                    wrap: false,
+                },
+            ],
+        );
+        let mut out = String::new();
+        t.header(&mut out, &opts);
+        for c in collections {
+            let count = if c.missing_indexes.is_empty() {
+                c.indexes.len().to_string()
+            } else {
+                yellow(&format!(
+                    "{} ({} missing)",
+                    c.indexes.len(),
+                    c.missing_indexes.len()
+                ))
+            };
+            t.row(
+                &mut out,
+                &[
+                    &c.name,
+                    &c.documents.to_string(),
+                    &size(c.data_bytes),
+                    &size(c.index_bytes),
+                    &count,
+                ],
+                &opts,
+            );
+        }
+        print!("{out}");
+
+        for c in collections {
+            for keys in &c.missing_indexes {
+                println!("{} {} {{ {} }}", yellow("missing"), c.name, keys);
+            }
+        }
+        for c in collections {
+            for index in c.indexes.iter().filter(|i| !i.expected) {
+                let uses = match index.ops {
+                    Some(0) => "unused".to_string(),
+                    Some(ops) => format!("{} uses", ops),
+                    None => "uses unknown".to_string(),
+                };
+                println!(
+                    "{} {} {} {}",
+                    dim("not created by the server"),
+                    c.name,
+                    index.name,
+                    dim(&format!("({})", uses))
+                );
+            }
+        }
+
+        if indexes {
+            println!();
+            print_indexes(collections, term_w, &opts);
+        }
+    }
+}
+
+fn print_indexes(collections: &[CollectionStats], term_w: usize, opts: &RenderOpts) {
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "COLLECTION",
+                min: 10,
+                max: Some(28),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "INDEX",
+                min: 8,
+                max: Some(40),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "KEYS",
+                min: 12,
+                max: None,
+                weight: 3,
+                align: Align::Left,
+                wrap: true,
+            },
+            ColSpec {
+                title: "TTL",
+                min: 5,
+                max: Some(10),
+                weight: 0,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "USES",
+                min: 6,
+                max: Some(14),
+                weight: 0,
+                align: Align::Right,
+                wrap: false,
+            },
+        ],
+    );
+    let mut out = String::new();
+    t.header(&mut out, opts);
+    for c in collections {
+        for index in &c.indexes {
+            let ttl = index
+                .expire_after_secs
+                .map(|s| format!("{}s", s))
+                .unwrap_or_else(|| "-".to_string());
+            let uses = index
+                .ops
+                .map(|o| o.to_string())
+                .unwrap_or_else(|| "-".to_string());
+            t.row(
+                &mut out,
+                &[&c.name, &index.name, &index.keys, &ttl, &uses],
+                opts,
+            );
+        }
+    }
+    print!("{out}");
+}
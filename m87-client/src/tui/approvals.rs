@@ -0,0 +1,99 @@
+use crate::tui::helper::{
+    Align, ColSpec, RenderOpts, Table, dim, format_relative_time, green, red, terminal_width,
+    yellow,
+};
+use m87_shared::approval::{ApprovalRequest, ApprovalStatus};
+
+pub fn print_approvals(requests: &[ApprovalRequest]) {
+    if requests.is_empty() {
+        println!("{}", dim("No approval requests"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ID",
+                min: 24,
+                max: Some(24),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DEVICE",
+                min: 8,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "OPERATION",
+                min: 9,
+                max: None,
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "REQUESTED BY",
+                min: 12,
+                max: Some(32),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "STATUS",
+                min: 8,
+                max: Some(10),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "CREATED",
+                min: 10,
+                max: Some(16),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    for request in requests {
+        let operation = if request.details.is_empty() {
+            request.operation.clone()
+        } else {
+            format!("{} ({})", request.operation, request.details)
+        };
+        let status = match request.status {
+            ApprovalStatus::Pending => yellow("pending"),
+            ApprovalStatus::Approved => green("approved"),
+            ApprovalStatus::Rejected => red("rejected"),
+            status => dim(&status.to_string()),
+        };
+        t.row(
+            &mut out,
+            &[
+                &request.id,
+                &request.device_name,
+                &operation,
+                &request.requested_by,
+                &status,
+                &format_relative_time(&request.created_at),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
@@ -1,19 +1,35 @@
+use std::io::Write;
+
 use m87_shared::deploy_spec::{
-    DeploymentRevision, DeploymentStatusSnapshot, Outcome, RunStatus, StepState,
+    CoreDump, DeploymentRevision, DeploymentStatusSnapshot, Outcome, RunStatus, RunType,
+    StepArtifacts, StepState,
 };
 
+use crate::streams::stream_type::{PlanAction, PlanReply};
+use crate::tui::fs::human_size;
 use crate::tui::helper;
 
 pub fn print_revision_list_header() {
-    println!("{:<36} {:>4} {:>8}", "REVISION", "JOBS", "ROLLBACK");
+    println!(
+        "{:<36} {:>4} {:>8} {:<10} AUTHOR",
+        "REVISION", "JOBS", "ROLLBACK", "COMMIT"
+    );
 }
 
 pub fn print_revision_short(rev: &DeploymentRevision) {
+    let provenance = rev.provenance.as_ref();
+    let commit = provenance
+        .and_then(|p| p.git_sha.as_deref())
+        .map(|sha| sha.get(..10).unwrap_or(sha))
+        .unwrap_or("-");
+    let author = provenance.and_then(|p| p.author.as_deref()).unwrap_or("-");
     println!(
-        "{:<36} {:>4} {:>8}",
+        "{:<36} {:>4} {:>8} {:<10} {}",
         rev.id.as_deref().unwrap_or("<none>"),
         rev.jobs.len(),
-        if rev.rollback.is_some() { "yes" } else { "no" }
+        if rev.rollback.is_some() { "yes" } else { "no" },
+        commit,
+        author
     );
 }
 
@@ -53,6 +69,73 @@ pub fn print_deployment_status_snapshot(
     snap: &DeploymentStatusSnapshot,
     opts: &helper::RenderOpts,
 ) {
+    print_snapshot_header(snap, opts);
+    print_snapshot_runs(&snap.runs, opts);
+    print_snapshot_rollback(snap, opts);
+}
+
+/// Revision status, provenance and annotations, before the runs.
+pub fn print_snapshot_header(snap: &DeploymentStatusSnapshot, opts: &helper::RenderOpts) {
+    let term_w = helper::terminal_width().unwrap_or(96).max(60);
+    let mut out = String::new();
+
+    // header
+    out.push_str(&helper::kv_line(
+        term_w,
+        "deployment",
+        &helper::bold(&snap.revision_id),
+        opts,
+    ));
+    out.push('\n');
+
+    let status_txt = format!("{} {}", glyph_for_outcome(&snap.outcome), snap.outcome);
+    let status_colored = helper::colorize(opts.use_color, &status_txt, status_color(&snap.outcome));
+    out.push_str(&helper::kv_line(term_w, "status", &status_colored, opts));
+    out.push('\n');
+
+    if snap.dirty {
+        out.push_str(&helper::kv_line(
+            term_w,
+            "dirty",
+            &helper::colorize(opts.use_color, "true", helper::AnsiColor::Red),
+            opts,
+        ));
+        out.push('\n');
+    }
+
+    if let Some(e) = snap
+        .error
+        .as_ref()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+    {
+        out.push_str(&helper::kv_line(term_w, "error", e, opts));
+        out.push('\n');
+    }
+
+    // provenance and annotations, so a revision can be traced back to its source
+    if let Some(p) = &snap.provenance {
+        for (k, v) in p.fields() {
+            out.push_str(&helper::kv_line(term_w, k, v, opts));
+            out.push('\n');
+        }
+    }
+    for (k, v) in &snap.annotations {
+        out.push_str(&helper::kv_line(term_w, k, v, opts));
+        out.push('\n');
+    }
+
+    if !snap.runs.is_empty() {
+        out.push_str(&helper::separator_line(term_w, opts));
+        out.push('\n');
+        out.push('\n');
+    }
+
+    print!("{out}");
+}
+
+/// Runs with their observe checks and steps, e.g. one page of a snapshot.
+pub fn print_snapshot_runs(runs: &[RunStatus], opts: &helper::RenderOpts) {
     let term_w = helper::terminal_width().unwrap_or(96).max(60);
 
     let steps_table = helper::Table::new(
@@ -104,47 +187,7 @@ pub fn print_deployment_status_snapshot(
 
     let mut out = String::new();
 
-    // header
-    out.push_str(&helper::kv_line(
-        term_w,
-        "deployment",
-        &helper::bold(&snap.revision_id),
-        opts,
-    ));
-    out.push('\n');
-
-    let status_txt = format!("{} {}", glyph_for_outcome(&snap.outcome), snap.outcome);
-    let status_colored = helper::colorize(opts.use_color, &status_txt, status_color(&snap.outcome));
-    out.push_str(&helper::kv_line(term_w, "status", &status_colored, opts));
-    out.push('\n');
-
-    if snap.dirty {
-        out.push_str(&helper::kv_line(
-            term_w,
-            "dirty",
-            &helper::colorize(opts.use_color, "true", helper::AnsiColor::Red),
-            opts,
-        ));
-        out.push('\n');
-    }
-
-    if let Some(e) = snap
-        .error
-        .as_ref()
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-    {
-        out.push_str(&helper::kv_line(term_w, "error", e, opts));
-        out.push('\n');
-    }
-
-    if !snap.runs.is_empty() {
-        out.push_str(&helper::separator_line(term_w, opts));
-        out.push('\n');
-        out.push('\n');
-    }
-
-    for run in &snap.runs {
+    for run in runs {
         let enabled = if run.enabled {
             helper::colorize(opts.use_color, "✓ enabled", helper::AnsiColor::Green)
         } else {
@@ -155,6 +198,7 @@ pub fn print_deployment_status_snapshot(
             Outcome::Success => "✓ success",
             Outcome::Failed => "✗ failure",
             Outcome::Unknown => "? unknown",
+            Outcome::Skipped => "↷ skipped",
         };
         let outcome_colored =
             helper::colorize(opts.use_color, outcome_txt, status_color(&run.outcome));
@@ -190,7 +234,7 @@ pub fn print_deployment_status_snapshot(
         out.push_str(&run_info);
         out.push('\n');
 
-        if run.healthy.is_some() || run.alive.is_some() {
+        if run.healthy.is_some() || run.alive.is_some() || run.drift.is_some() {
             out.push_str(&format!("  {}", helper::gray("observe")));
             out.push('\n');
         }
@@ -233,6 +277,20 @@ pub fn print_deployment_status_snapshot(
             );
         }
 
+        if let Some(d) = &run.drift {
+            let s = if d.remediated { "restored" } else { "drifted" };
+            push_check_row_snapshot(
+                &mut out,
+                &steps_table,
+                opts,
+                "[drift]",
+                &helper::colorize(opts.use_color, s, helper::AnsiColor::Yellow),
+                d.report_time,
+                &d.summary(),
+                opts.show_logs_inline,
+            );
+        }
+
         out.push_str(&format!(
             "  {}    {}",
             helper::gray("steps"),
@@ -240,6 +298,13 @@ pub fn print_deployment_status_snapshot(
         ));
         out.push('\n');
 
+        if run.steps_omitted {
+            out.push_str(&format!(
+                "  {}\n",
+                helper::gray(&format!("left out, see --run {}", run.run_id))
+            ));
+        }
+
         for st in &run.steps {
             // undo rows: show only if defined in spec AND executed (attempt exists) OR state not Pending
             if st.is_undo && (st.attempt.is_none() && st.state == StepState::Pending) {
@@ -329,6 +394,26 @@ pub fn print_deployment_status_snapshot(
         out.push('\n');
     }
 
+    print!("{out}");
+}
+
+/// Ask on stderr whether to show the next page of runs; Enter continues.
+pub fn prompt_more_runs(shown: usize, total: usize) -> bool {
+    eprint!(
+        "-- {} of {} runs, Enter for more, q to stop -- ",
+        shown, total
+    );
+    let _ = std::io::stderr().flush();
+    let mut input = String::new();
+    match std::io::stdin().read_line(&mut input) {
+        Ok(0) | Err(_) => false,
+        Ok(_) => !input.trim().eq_ignore_ascii_case("q"),
+    }
+}
+
+pub fn print_snapshot_rollback(snap: &DeploymentStatusSnapshot, opts: &helper::RenderOpts) {
+    let term_w = helper::terminal_width().unwrap_or(96).max(60);
+    let mut out = String::new();
     if let Some(rb) = &snap.rollback {
         out.push('\n');
         out.push_str(&helper::kv_line(
@@ -356,6 +441,58 @@ pub fn print_deployment_status_snapshot(
     print!("{out}");
 }
 
+/// Files the steps of `run_id` uploaded, one row per file.
+pub fn print_run_artifacts(run_id: &str, steps: &[StepArtifacts]) {
+    if steps.is_empty() {
+        println!("No artifacts for run {}", run_id);
+        return;
+    }
+    println!(
+        "{:<20} {:<40} {:>10} UPLOADED",
+        "STEP", "PATH", "BYTES"
+    );
+    for step in steps {
+        let name = step.step.as_deref().unwrap_or("-");
+        let time = helper::format_time(step.report_time, false);
+        for artifact in &step.artifacts {
+            let uploaded = match artifact.content {
+                Some(_) => time.clone(),
+                None => helper::dim("too large, not uploaded"),
+            };
+            println!(
+                "{:<20} {:<40} {:>10} {}",
+                name, artifact.path, artifact.size, uploaded
+            );
+        }
+    }
+}
+
+pub fn print_core_dumps(cores: &[CoreDump]) {
+    if cores.is_empty() {
+        println!("No core dumps");
+        return;
+    }
+    println!(
+        "{:<24} {:<20} {:<20} {:>10} PROCESS",
+        "ID", "TIME", "RUN", "SIZE"
+    );
+    for core in cores {
+        let r = &core.report;
+        let mut size = human_size(r.size);
+        if r.compressed_size.is_none() {
+            size = helper::dim(&format!("{} (not uploaded)", size));
+        }
+        println!(
+            "{:<24} {:<20} {:<20} {:>10} {}",
+            core.id,
+            helper::format_time(r.report_time, false),
+            r.run_id,
+            size,
+            r.summary()
+        );
+    }
+}
+
 // counts ok/total over main steps, and includes undo steps only if executed
 fn step_stats_from_snapshot(run: &RunStatus) -> (usize, usize, usize, usize) {
     let mut ok = 0usize;
@@ -425,7 +562,7 @@ fn status_color(o: &Outcome) -> helper::AnsiColor {
     match o {
         Outcome::Success => helper::AnsiColor::Green,
         Outcome::Failed => helper::AnsiColor::Red,
-        Outcome::Unknown => helper::AnsiColor::Dim,
+        Outcome::Unknown | Outcome::Skipped => helper::AnsiColor::Dim,
     }
 }
 
@@ -434,5 +571,79 @@ fn glyph_for_outcome(o: &Outcome) -> &'static str {
         Outcome::Success => "✓",
         Outcome::Failed => "✗",
         Outcome::Unknown => "?",
+        Outcome::Skipped => "↷",
+    }
+}
+
+/// Runs of a deployment plan, one line each with the steps and files that
+/// would run and be written below it, then the counts.
+pub fn print_plan(device: &str, plan: &PlanReply) {
+    let current = plan.current_revision_id.as_deref().unwrap_or("nothing");
+    let candidate = plan.candidate_revision_id.as_deref().unwrap_or("<new>");
+    println!(
+        "{} {} → {}",
+        helper::bold(device),
+        helper::dim(current),
+        candidate
+    );
+    if plan.unchanged {
+        println!(
+            "  {}",
+            helper::dim("the device holds this revision; nothing to do")
+        );
+        return;
     }
+
+    let width = plan.runs.iter().map(|r| r.run_id.len()).max().unwrap_or(0);
+    for run in &plan.runs {
+        let (sign, label) = match run.action {
+            PlanAction::Add => (helper::green("+"), helper::green("add")),
+            PlanAction::Change => (helper::yellow("~"), helper::yellow("change")),
+            PlanAction::Rerun => (helper::yellow("~"), helper::yellow("rerun")),
+            PlanAction::Keep => (helper::dim("="), helper::dim("keep")),
+            PlanAction::Remove => (helper::red("-"), helper::red("remove")),
+            PlanAction::Skip => (helper::dim("!"), helper::dim("skip")),
+        };
+        let kind = match run.run_type {
+            RunType::Service => "service",
+            RunType::Job => "job",
+            RunType::Observe => "observe",
+        };
+        let reason = run
+            .reason
+            .as_deref()
+            .map(|r| format!(" ({})", r))
+            .unwrap_or_default();
+        println!(
+            "  {} {:<width$}  {:<7}  {}{}",
+            sign,
+            run.run_id,
+            kind,
+            label,
+            helper::dim(&reason)
+        );
+        if !run.stop_steps.is_empty() {
+            println!(
+                "      {} {}",
+                helper::dim("stop: "),
+                run.stop_steps.join(", ")
+            );
+        }
+        if !run.steps.is_empty() {
+            println!("      {} {}", helper::dim("steps:"), run.steps.join(", "));
+        }
+        if !run.files.is_empty() {
+            println!("      {} {}", helper::dim("files:"), run.files.join(", "));
+        }
+    }
+
+    let count = |action: PlanAction| plan.runs.iter().filter(|r| r.action == action).count();
+    println!(
+        "{} to add, {} to change, {} to rerun, {} to remove, {} skipped",
+        count(PlanAction::Add),
+        count(PlanAction::Change),
+        count(PlanAction::Rerun),
+        count(PlanAction::Remove),
+        count(PlanAction::Skip)
+    );
 }
@@ -1,13 +1,31 @@
 use crate::{
+    device::support::BundleSummary,
+    devices::DeviceOverview,
+    streams::{
+        e2e::fingerprint,
+        stream_type::{FirewallBackend, FirewallReply, SessionInfo, TimeReply},
+    },
+    tui::fs::human_size,
     tui::helper::{
-        Align, ColSpec, RenderOpts, Table, bold, cyan, dim, green, pending_badge, red, role_badge,
-        status_badge, terminal_width, yellow,
+        Align, ColSpec, RenderOpts, Table, battery_label, bold, cyan, dim, format_relative_millis,
+        format_relative_time, green, pending_badge, red, role_badge, severity_badge, status_badge,
+        terminal_width, yellow,
     },
-    util::device_cache::try_get_name_from_long_id,
+    util::{device_cache::try_get_name_from_long_id, lan::LanDevice},
 };
 use m87_shared::{
     auth::DeviceAuthRequest,
-    device::{AuditLog, DeviceStatus, PublicDevice},
+    bandwidth::DeviceBandwidth,
+    deploy_spec::Outcome,
+    device::{
+        AlertRules, AuditLog, DeviceAssetInfo, DeviceStatus, DeviceTimelineEvent, PublicDevice,
+        TimeStatus, TimelineEventKind,
+    },
+    enrollment::InstallCommand,
+    facts::DeviceFacts,
+    inventory::{DeviceVulnerabilities, Severity},
+    patching::PatchRun,
+    probe::{ProbeRecord, ProbeStatus},
 };
 
 pub fn print_devices_table(devices: &[PublicDevice], auth_requests: &[DeviceAuthRequest]) {
@@ -32,106 +50,1082 @@ pub fn print_devices_table(devices: &[PublicDevice], auth_requests: &[DeviceAuth
                 wrap: false,
             },
             ColSpec {
-                title: "NAME",
+                title: "NAME",
+                min: 10,
+                max: Some(18),
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "STATUS",
+                min: 6,
+                max: Some(8),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "Role",
+                min: 10,
+                max: Some(16),
+                weight: 4,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "ARCH",
+                min: 4,
+                max: Some(6),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "OS",
+                min: 12,
+                max: Some(26),
+                weight: 3,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "BATTERY",
+                min: 7,
+                max: Some(13),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "IP",
+                min: 10,
+                max: Some(39),
+                weight: 4,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    // Auth requests table (REQUEST column is important/copy-friendly)
+    let t_auth = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "NAME",
+                min: 10,
+                max: Some(22),
+                weight: 3,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "STATUS",
+                min: 6,
+                max: Some(8),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "ARCH",
+                min: 4,
+                max: Some(6),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "OS",
+                min: 12,
+                max: Some(26),
+                weight: 3,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "IP",
+                min: 10,
+                max: Some(39),
+                weight: 4,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "REQUEST",
+                min: 8,
+                max: None,
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+
+    // ---- AUTH REQUESTS (only if > 0, shown first) ----
+    if !auth_requests.is_empty() {
+        out.push_str(&format!("{}\n", bold("Auth requests")));
+        t_auth.header(&mut out, &opts);
+
+        for req in auth_requests {
+            let ip = req.device_info.public_ip_address.as_deref().unwrap_or("-");
+
+            out.push_str("  ");
+            t_auth.row(
+                &mut out,
+                &[
+                    &req.device_info.hostname,
+                    &pending_badge(true),
+                    &req.device_info.architecture,
+                    &req.device_info.operating_system,
+                    ip,
+                    &req.request_id,
+                ],
+                &opts,
+            );
+        }
+
+        out.push('\n');
+    }
+
+    // ---- DEVICES ----
+    if !devices.is_empty() {
+        out.push_str(&format!("{}\n", bold("Devices")));
+        t_devices.header(&mut out, &opts);
+
+        for dev in devices {
+            let os = dev.system_info.operating_system.as_str();
+            let ip = dev.system_info.public_ip_address.as_deref().unwrap_or("-");
+            let battery = match &dev.battery {
+                Some(b) => battery_label(b),
+                None => dim("-"),
+            };
+
+            t_devices.row(
+                &mut out,
+                &[
+                    &dev.short_id,
+                    &dev.name,
+                    &status_badge(dev.online),
+                    &role_badge(&dev.role),
+                    &dev.system_info.architecture,
+                    os,
+                    &battery,
+                    ip,
+                ],
+                &opts,
+            );
+        }
+
+        // a skewed clock breaks TLS, token expiry and report ordering
+        for dev in devices {
+            if let Some(time) = dev.time.as_ref().filter(|t| t.is_skewed()) {
+                out.push_str(&format!(
+                    "{} {}\n",
+                    yellow("warning:"),
+                    clock_warning(&dev.name, time)
+                ));
+            }
+        }
+        for dev in devices {
+            if let Some(maintenance) = &dev.maintenance {
+                let reason = maintenance
+                    .reason
+                    .as_deref()
+                    .map(|r| format!(": {}", r))
+                    .unwrap_or_default();
+                out.push_str(&format!(
+                    "{}\n",
+                    dim(&format!("{} is in maintenance mode{}", dev.name, reason))
+                ));
+            }
+            if !dev.config.bandwidth_profile.is_normal() {
+                out.push_str(&format!(
+                    "{}\n",
+                    dim(&format!(
+                        "{} uses the {} bandwidth profile",
+                        dev.name, dev.config.bandwidth_profile
+                    ))
+                ));
+            }
+        }
+    }
+
+    print!("{out}");
+}
+
+fn clock_warning(name: &str, time: &TimeStatus) -> String {
+    let offset = time.offset_ms.unwrap_or(0) as f64 / 1000.0;
+    let ntp = match (time.synchronized, time.ntp_service.as_deref()) {
+        (Some(true), _) => String::new(),
+        (_, Some(service)) => format!(", {} not synchronized", service),
+        (_, None) => ", no NTP client".to_string(),
+    };
+    format!(
+        "{} clock is off by {:+.1}s{} (run 'm87 {} time sync')",
+        name, offset, ntp, name
+    )
+}
+
+pub fn print_device_info(name: &str, info: &DeviceAssetInfo) {
+    println!("{}", bold(name));
+    let entries = info.entries();
+    if entries.is_empty() {
+        println!(
+            "{}",
+            dim("No asset info set. Use 'm87 <device> info set key=value'")
+        );
+        return;
+    }
+    let width = entries.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    for (key, value) in entries {
+        println!("  {}  {}", dim(&format!("{:<width$}", key)), value);
+    }
+}
+
+/// Facts worth showing in the device overview, in this order, when collected.
+const OVERVIEW_FACTS: [&str; 7] = [
+    "os.name",
+    "os.version",
+    "gpu.name",
+    "gpu.driver",
+    "docker.compose.version",
+    "packages.manager",
+    "packages.count",
+];
+
+/// Aligned `key  value` lines below a section title.
+fn print_fields(title: &str, fields: &[(&str, String)]) {
+    println!("{}", bold(title));
+    let width = fields.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    for (key, value) in fields {
+        println!("  {}  {}", dim(&format!("{:<width$}", key)), value);
+    }
+}
+
+/// `m87 <device> info`: system info, facts, labels, health, the active
+/// deployment, the newest events and open sessions.
+pub fn print_device_overview(overview: &DeviceOverview) {
+    let dev = &overview.device;
+    let seen = match (dev.online, &dev.last_connection) {
+        (false, Some(last)) => format!(", last seen {}", format_relative_time(last)),
+        _ => String::new(),
+    };
+    println!(
+        "{}  {}  {}{}",
+        bold(&dev.name),
+        status_badge(dev.online),
+        dim(&format!("{} v{}", dev.short_id, dev.version)),
+        dim(&seen)
+    );
+    println!();
+
+    let sys = &dev.system_info;
+    let mut system = vec![
+        ("hostname", sys.hostname.clone()),
+        ("os", sys.operating_system.clone()),
+        ("arch", sys.architecture.clone()),
+    ];
+    let cpu = match sys.cores {
+        Some(cores) => format!("{} ({} cores)", sys.cpu_name, cores),
+        None => sys.cpu_name.clone(),
+    };
+    system.push(("cpu", cpu));
+    if let Some(memory) = sys.memory {
+        system.push(("memory", format!("{:.1} GB", memory)));
+    }
+    if !sys.gpus.is_empty() {
+        system.push(("gpus", sys.gpus.join(", ")));
+    }
+    if let Some(ip) = &sys.public_ip_address {
+        system.push(("public ip", ip.clone()));
+    }
+    if let Some(battery) = &dev.battery {
+        system.push(("battery", battery_label(battery)));
+    }
+    print_fields("System", &system);
+    println!();
+
+    match &overview.facts {
+        Some(facts) => {
+            let selected: Vec<(&str, String)> = OVERVIEW_FACTS
+                .iter()
+                .filter_map(|key| facts.get(key).map(|v| (*key, v.to_string())))
+                .collect();
+            print_fields("Facts", &selected);
+            println!(
+                "  {}",
+                dim(&format!(
+                    "{} facts collected {}; 'm87 {} facts' lists all",
+                    facts.facts.len(),
+                    format_relative_millis(facts.collected_at),
+                    dev.name
+                ))
+            );
+        }
+        None => {
+            println!("{}", bold("Facts"));
+            println!("  {}", dim("unavailable while the device is offline"));
+        }
+    }
+    println!();
+
+    let labels = dev.info.entries();
+    if labels.is_empty() {
+        println!("{}", bold("Labels"));
+        println!(
+            "  {}",
+            dim(&format!(
+                "none; 'm87 {} info set key=value' adds some",
+                dev.name
+            ))
+        );
+    } else {
+        let labels: Vec<(&str, String)> = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        print_fields("Labels", &labels);
+    }
+    println!();
+
+    let status = &overview.status;
+    let unhealthy: Vec<&str> = status
+        .observations
+        .iter()
+        .filter(|o| !o.alive || !o.healthy)
+        .map(|o| o.name.as_str())
+        .collect();
+    let mut health = vec![(
+        "runs",
+        match unhealthy.len() {
+            0 => green(&format!(
+                "{} observed, all healthy",
+                status.observations.len()
+            )),
+            _ => red(&format!(
+                "{} observed, unhealthy: {}",
+                status.observations.len(),
+                unhealthy.join(", ")
+            )),
+        },
+    )];
+    let crashes: u32 = status.observations.iter().map(|o| o.crashes).sum();
+    if crashes > 0 {
+        health.push(("crashes", yellow(&crashes.to_string())));
+    }
+    if !status.incidents.is_empty() {
+        health.push(("incidents", red(&status.incidents.len().to_string())));
+    }
+    if let Some(time) = dev.time.as_ref().filter(|t| t.is_skewed()) {
+        health.push(("clock", yellow(&clock_warning(&dev.name, time))));
+    }
+    if let Some(maintenance) = &dev.maintenance {
+        let reason = maintenance.reason.as_deref().unwrap_or("no reason given");
+        health.push(("maintenance", yellow(reason)));
+    }
+    if status.pending_reports > 0 {
+        health.push((
+            "reports",
+            dim(&format!("{} wait for upload", status.pending_reports)),
+        ));
+    }
+    print_fields("Health", &health);
+    println!();
+
+    println!("{}", bold("Deployment"));
+    match &overview.deployment {
+        Some(snapshot) => {
+            let opts = RenderOpts::default();
+            crate::tui::deploy::print_snapshot_header(snapshot, &opts);
+            let failed = snapshot
+                .runs
+                .iter()
+                .filter(|r| r.outcome == Outcome::Failed)
+                .count();
+            let runs = format!(
+                "{} runs, {} failed; 'm87 {} deployment status' for details",
+                snapshot.runs.len(),
+                failed,
+                dev.name
+            );
+            println!("  {}", dim(&runs));
+        }
+        None => println!("  {}", dim("no active deployment")),
+    }
+    println!();
+
+    println!("{}", bold("Recent events"));
+    print_device_events(&overview.events);
+    println!();
+
+    println!("{}", bold("Sessions"));
+    match &overview.sessions {
+        Some(sessions) => print_sessions(sessions),
+        None => println!("  {}", dim("unavailable while the device is offline")),
+    }
+}
+
+/// Facts whose key starts with `prefix` (all facts when empty).
+pub fn print_facts(name: &str, facts: &DeviceFacts, prefix: &str) {
+    println!("{}", bold(name));
+    let entries: Vec<(&str, &str)> = facts.matching(prefix).collect();
+    if entries.is_empty() {
+        println!("{}", dim("No matching facts"));
+        return;
+    }
+    let width = entries.iter().map(|(k, _)| k.len()).max().unwrap_or(0);
+    for (key, value) in entries {
+        println!("  {}  {}", dim(&format!("{:<width$}", key)), value);
+    }
+}
+
+pub fn print_alert_rules(name: &str, rules: &AlertRules) {
+    println!("{}", bold(name));
+    if rules.is_empty() {
+        println!(
+            "{}",
+            dim(
+                "No alert rules set. Use 'm87 <device> alerts --low-battery <percent>' or --condition"
+            )
+        );
+        return;
+    }
+    if let Some(percent) = rules.low_battery_percent {
+        println!("  {}  at or below {}%", dim("low battery"), percent);
+    }
+    for (name, condition) in &rules.conditions {
+        println!("  {}  {}", dim(name), condition);
+    }
+}
+
+pub fn print_lan_devices(devices: &[LanDevice]) {
+    if devices.is_empty() {
+        println!("{}", dim("No devices found on the local network"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ID",
+                min: 6,
+                max: Some(8),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "NAME",
+                min: 10,
+                max: Some(22),
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "VERSION",
+                min: 7,
+                max: Some(12),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DIRECT",
+                min: 6,
+                max: Some(6),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "ADDRESS",
+                min: 10,
+                max: None,
+                weight: 4,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+
+    for dev in devices {
+        let addr = dev
+            .addresses
+            .first()
+            .map(|ip| match ip {
+                std::net::IpAddr::V6(_) => format!("[{}]:{}", ip, dev.port),
+                std::net::IpAddr::V4(_) => format!("{}:{}", ip, dev.port),
+            })
+            .unwrap_or_else(|| "-".to_string());
+        let direct = if dev.direct { green("yes") } else { dim("no") };
+
+        t.row(
+            &mut out,
+            &[&dev.short_id, &dev.name, &dev.version, &direct, &addr],
+            &opts,
+        );
+    }
+
+    print!("{out}");
+}
+
+pub fn print_vulnerabilities(scan: &DeviceVulnerabilities, min: Option<Severity>) {
+    let os = scan
+        .ecosystem
+        .as_deref()
+        .unwrap_or("untracked distribution");
+    let kernel = scan.kernel.as_deref().unwrap_or("-");
+    println!(
+        "{}  {} · kernel {} · {} packages",
+        bold(&scan.device_name),
+        os,
+        kernel,
+        scan.package_count
+    );
+    if let Some(err) = &scan.scan_error {
+        println!("{}", yellow(&format!("Last scan failed: {}", err)));
+    }
+    let Some(scanned_at) = scan.scanned_at else {
+        println!("{}", dim("Advisory scan pending"));
+        return;
+    };
+    println!(
+        "{}",
+        dim(&format!("Scanned {}", format_relative_millis(scanned_at)))
+    );
+
+    let vulns: Vec<_> = scan
+        .vulnerabilities
+        .iter()
+        .filter(|v| min.is_none_or(|min| v.severity.is_some_and(|s| s >= min)))
+        .collect();
+    if vulns.is_empty() {
+        println!("{}", green("No known vulnerabilities"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(120).max(60);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "SEVERITY",
+                min: 8,
+                max: Some(8),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "ID",
+                min: 14,
+                max: Some(20),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "PACKAGE",
+                min: 8,
+                max: Some(20),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "INSTALLED",
+                min: 8,
+                max: Some(20),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "FIXED",
+                min: 8,
+                max: Some(20),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "SUMMARY",
+                min: 10,
+                max: None,
+                weight: 4,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+
+    for v in &vulns {
+        let severity = severity_badge(v.severity);
+        let fixed = v.fixed_version.as_deref().unwrap_or("-");
+        let summary = v.summary.as_deref().unwrap_or("-");
+        t.row(
+            &mut out,
+            &[
+                &severity,
+                v.display_id(),
+                &v.package,
+                &v.installed_version,
+                fixed,
+                summary,
+            ],
+            &opts,
+        );
+    }
+
+    print!("{out}");
+    println!("{}", dim(&format!("{} advisories", vulns.len())));
+}
+
+pub fn print_patch_runs(runs: &[PatchRun]) {
+    if runs.is_empty() {
+        println!("{}", dim("No patch runs reported"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "STARTED",
+                min: 10,
+                max: Some(16),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "POLICY",
+                min: 8,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "RESULT",
+                min: 6,
+                max: Some(6),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "REBOOT",
+                min: 9,
+                max: Some(9),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DETAILS",
+                min: 10,
+                max: None,
+                weight: 3,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+
+    for run in runs {
+        let report = &run.report;
+        let operation = &report.operation;
+        let result = if operation.success {
+            green("ok")
+        } else {
+            red("failed")
+        };
+        let reboot = match (report.rebooting, report.reboot_required) {
+            (true, _) => yellow("rebooting"),
+            (false, true) => yellow("pending"),
+            (false, false) => dim("-"),
+        };
+        let details = operation
+            .error
+            .clone()
+            .unwrap_or_else(|| operation.describe());
+        t.row(
+            &mut out,
+            &[
+                &format_relative_millis(operation.started_at),
+                &report.policy_name,
+                &result,
+                &reboot,
+                &details,
+            ],
+            &opts,
+        );
+    }
+
+    print!("{out}");
+}
+
+/// Traffic of `name` per class since the server first got a report, with
+/// each class's share of the total.
+pub fn print_bandwidth(name: &str, bandwidth: &DeviceBandwidth) {
+    let Some(since) = bandwidth.totals_since else {
+        println!("{}", dim("The device has not reported its traffic yet"));
+        return;
+    };
+    let total: u64 = bandwidth.totals.iter().map(|c| c.bytes.total()).sum();
+
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+    let column = |title, weight, align| ColSpec {
+        title,
+        min: 8,
+        max: Some(14),
+        weight,
+        align,
+        wrap: false,
+    };
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            column("CLASS", 1, Align::Left),
+            column("SENT", 0, Align::Right),
+            column("RECEIVED", 0, Align::Right),
+            column("TOTAL", 0, Align::Right),
+            column("SHARE", 0, Align::Right),
+        ],
+    );
+
+    println!("{}", bold(name));
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    let mut classes = bandwidth.totals.clone();
+    classes.sort_by_key(|c| std::cmp::Reverse(c.bytes.total()));
+    for class in &classes {
+        let share = class.bytes.total() as f64 * 100.0 / total.max(1) as f64;
+        t.row(
+            &mut out,
+            &[
+                class.class.as_str(),
+                &human_size(class.bytes.sent),
+                &human_size(class.bytes.received),
+                &human_size(class.bytes.total()),
+                &format!("{:.1}%", share),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+
+    println!("  {}  {}", dim("payload     "), human_size(total));
+    if bandwidth.wire_total.total() > 0 {
+        println!(
+            "  {}  {} {}",
+            dim("on the wire "),
+            human_size(bandwidth.wire_total.total()),
+            dim("(control tunnel, with QUIC overhead)")
+        );
+    }
+    println!(
+        "  {}  {}",
+        dim("counted     "),
+        format_relative_millis(since * 1000)
+    );
+    if let Some(reported_at) = bandwidth.reported_at {
+        println!(
+            "  {}  {}",
+            dim("last report "),
+            format_relative_millis(reported_at * 1000)
+        );
+    }
+}
+
+pub fn print_probes(probes: &[ProbeStatus]) {
+    if probes.is_empty() {
+        println!("{}", dim("No probes"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "NAME",
+                min: 4,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "TARGET",
                 min: 10,
-                max: Some(18),
-                weight: 2,
+                max: None,
+                weight: 3,
                 align: Align::Left,
                 wrap: false,
             },
             ColSpec {
-                title: "STATUS",
-                min: 6,
-                max: Some(8),
+                title: "EVERY",
+                min: 5,
+                max: Some(6),
                 weight: 0,
-                align: Align::Left,
+                align: Align::Right,
                 wrap: false,
             },
             ColSpec {
-                title: "Role",
-                min: 10,
-                max: Some(16),
-                weight: 4,
+                title: "STATE",
+                min: 7,
+                max: Some(7),
+                weight: 0,
                 align: Align::Left,
                 wrap: false,
             },
             ColSpec {
-                title: "ARCH",
-                min: 4,
-                max: Some(6),
+                title: "LATENCY",
+                min: 7,
+                max: Some(8),
                 weight: 0,
-                align: Align::Left,
+                align: Align::Right,
                 wrap: false,
             },
             ColSpec {
-                title: "OS",
-                min: 12,
-                max: Some(26),
-                weight: 3,
-                align: Align::Left,
+                title: "UPTIME 24H",
+                min: 10,
+                max: Some(10),
+                weight: 0,
+                align: Align::Right,
                 wrap: false,
             },
             ColSpec {
-                title: "IP",
+                title: "CHECKED",
                 min: 10,
-                max: Some(39),
-                weight: 4,
+                max: Some(16),
+                weight: 0,
                 align: Align::Left,
                 wrap: false,
             },
         ],
     );
 
-    // Auth requests table (REQUEST column is important/copy-friendly)
-    let t_auth = Table::new(
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+
+    for status in probes {
+        let probe = &status.probe;
+        let (state, latency, checked) = match &status.last {
+            Some(last) if last.ok => (
+                green("up"),
+                last.latency_ms
+                    .map(|ms| format!("{}ms", ms))
+                    .unwrap_or_default(),
+                format_relative_millis(last.checked_at),
+            ),
+            Some(last) if last.consecutive_failures >= probe.fail_after => (
+                red("down"),
+                dim("-"),
+                format_relative_millis(last.checked_at),
+            ),
+            Some(last) => (
+                yellow("failing"),
+                dim("-"),
+                format_relative_millis(last.checked_at),
+            ),
+            None => (dim("pending"), dim("-"), dim("-")),
+        };
+        let uptime = match status.uptime_percent() {
+            Some(percent) => format!("{:.1}%", percent),
+            None => dim("-"),
+        };
+        t.row(
+            &mut out,
+            &[
+                &probe.name,
+                &probe.target.to_string(),
+                &format!("{}s", probe.interval_secs),
+                &state,
+                &latency,
+                &uptime,
+                &checked,
+            ],
+            &opts,
+        );
+    }
+
+    print!("{out}");
+}
+
+pub fn print_probe_results(results: &[ProbeRecord]) {
+    if results.is_empty() {
+        println!("{}", dim("No probe results reported"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
         term_w.saturating_sub(2),
         1,
         vec![
             ColSpec {
-                title: "NAME",
+                title: "CHECKED",
                 min: 10,
-                max: Some(22),
-                weight: 3,
+                max: Some(16),
+                weight: 0,
                 align: Align::Left,
                 wrap: false,
             },
             ColSpec {
-                title: "STATUS",
+                title: "PROBE",
+                min: 5,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "RESULT",
                 min: 6,
+                max: Some(6),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "LATENCY",
+                min: 7,
+                max: Some(8),
+                weight: 0,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DETAILS",
+                min: 10,
+                max: None,
+                weight: 3,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+
+    for record in results {
+        let result = &record.result;
+        let (outcome, latency) = match result.ok {
+            true => (
+                green("ok"),
+                result
+                    .latency_ms
+                    .map(|ms| format!("{}ms", ms))
+                    .unwrap_or_default(),
+            ),
+            false => (red("failed"), dim("-")),
+        };
+        let details = match (&result.error, result.status) {
+            (Some(error), _) => error.clone(),
+            (None, Some(status)) => format!("HTTP {}", status),
+            (None, None) => String::new(),
+        };
+        t.row(
+            &mut out,
+            &[
+                &format_relative_millis(result.checked_at),
+                &result.name,
+                &outcome,
+                &latency,
+                &details,
+            ],
+            &opts,
+        );
+    }
+
+    print!("{out}");
+}
+
+pub fn print_sessions(sessions: &[SessionInfo]) {
+    if sessions.is_empty() {
+        println!("{}", dim("No open sessions"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ID",
+                min: 8,
                 max: Some(8),
                 weight: 0,
                 align: Align::Left,
                 wrap: false,
             },
             ColSpec {
-                title: "ARCH",
-                min: 4,
-                max: Some(6),
+                title: "KIND",
+                min: 5,
+                max: Some(5),
                 weight: 0,
                 align: Align::Left,
                 wrap: false,
             },
             ColSpec {
-                title: "OS",
-                min: 12,
-                max: Some(26),
-                weight: 3,
+                title: "USER",
+                min: 10,
+                max: Some(32),
+                weight: 2,
                 align: Align::Left,
                 wrap: false,
             },
             ColSpec {
-                title: "IP",
+                title: "STARTED",
                 min: 10,
-                max: Some(39),
-                weight: 4,
+                max: Some(14),
+                weight: 0,
                 align: Align::Left,
                 wrap: false,
             },
             ColSpec {
-                title: "REQUEST",
-                min: 8,
+                title: "DETAIL",
+                min: 10,
                 max: None,
-                weight: 2,
+                weight: 3,
                 align: Align::Left,
                 wrap: false,
             },
@@ -139,59 +1133,158 @@ pub fn print_devices_table(devices: &[PublicDevice], auth_requests: &[DeviceAuth
     );
 
     let mut out = String::new();
+    t.header(&mut out, &opts);
 
-    // ---- AUTH REQUESTS (only if > 0, shown first) ----
-    if !auth_requests.is_empty() {
-        out.push_str(&format!("{}\n", bold("Auth requests")));
-        t_auth.header(&mut out, &opts);
-
-        for req in auth_requests {
-            let ip = req.device_info.public_ip_address.as_deref().unwrap_or("-");
+    for session in sessions {
+        let started = chrono::DateTime::from_timestamp(session.started_at as i64, 0)
+            .map(|t| format_relative_time(&t.to_rfc3339()))
+            .unwrap_or_else(|| "-".to_string());
+        let detail = session.detail.as_deref().unwrap_or("-");
+        t.row(
+            &mut out,
+            &[&session.id, &session.kind, &session.user, &started, detail],
+            &opts,
+        );
+    }
 
-            out.push_str("  ");
-            t_auth.row(
-                &mut out,
-                &[
-                    &req.device_info.hostname,
-                    &pending_badge(true),
-                    &req.device_info.architecture,
-                    &req.device_info.operating_system,
-                    ip,
-                    &req.request_id,
-                ],
-                &opts,
-            );
-        }
+    print!("{out}");
+}
 
-        out.push('\n');
+pub fn print_support_bundle(summary: &BundleSummary) {
+    println!(
+        "Wrote {} ({} files, {} uncompressed)",
+        bold(&summary.path.display().to_string()),
+        summary.files,
+        human_size(summary.bytes)
+    );
+    if !summary.truncated.is_empty() {
+        println!(
+            "{}",
+            dim(&format!(
+                "Only the end of {} was included",
+                summary.truncated.join(", ")
+            ))
+        );
     }
+    println!(
+        "{}",
+        dim("Secrets were masked; look through the bundle before sharing it")
+    );
+}
 
-    // ---- DEVICES ----
-    if !devices.is_empty() {
-        out.push_str(&format!("{}\n", bold("Devices")));
-        t_devices.header(&mut out, &opts);
+pub fn print_install_command(command: &InstallCommand) {
+    println!("Run this on the new device (Linux, x86_64 or aarch64):");
+    println!();
+    println!("  {}", command.command);
+    println!();
+    let devices = match command.max_uses {
+        1 => "one device".to_string(),
+        n => format!("up to {} devices", n),
+    };
+    println!(
+        "It installs the runtime and registers {} under {} with {}, until {}.",
+        devices,
+        bold(&command.owner_reference),
+        command.server_url,
+        command.expires_at
+    );
+    println!(
+        "{}",
+        yellow("Anyone with the command can add devices until then; it is not shown again")
+    );
+}
 
-        for dev in devices {
-            let os = dev.system_info.operating_system.as_str();
-            let ip = dev.system_info.public_ip_address.as_deref().unwrap_or("-");
+pub fn print_time(name: &str, reply: &TimeReply, synced: bool) {
+    println!("{}", bold(name));
+    if let Some(output) = &reply.output {
+        for line in output.lines() {
+            println!("  {}", dim(line));
+        }
+    }
+    let status = &reply.status;
+    let time = chrono::DateTime::from_timestamp_millis(reply.device_time as i64)
+        .map(|t| t.to_rfc3339())
+        .unwrap_or_else(|| "-".to_string());
+    let offset = match status.offset_ms {
+        Some(ms) if status.is_skewed() => red(&format!("{:+.3}s", ms as f64 / 1000.0)),
+        Some(ms) => format!("{:+.3}s", ms as f64 / 1000.0),
+        None => dim("-"),
+    };
+    let synchronized = match status.synchronized {
+        Some(true) => green("yes"),
+        Some(false) => yellow("no"),
+        None => dim("unknown"),
+    };
+    let service = status.ntp_service.as_deref().unwrap_or("none");
+    println!("  {}  {}", dim("time        "), time);
+    println!("  {}  {}", dim("offset      "), offset);
+    println!("  {}  {}", dim("ntp service "), service);
+    println!("  {}  {}", dim("synchronized"), synchronized);
+    if synced && status.synchronized != Some(true) {
+        println!(
+            "{}",
+            dim("Resync requested; NTP may take a few seconds to report synchronized")
+        );
+    }
+}
 
-            t_devices.row(
-                &mut out,
-                &[
-                    &dev.short_id,
-                    &dev.name,
-                    &status_badge(dev.online),
-                    &role_badge(&dev.role),
-                    &dev.system_info.architecture,
-                    os,
-                    ip,
-                ],
-                &opts,
-            );
+pub fn print_firewall(name: &str, reply: &FirewallReply) {
+    println!("{}", bold(name));
+    let applied = match reply.applied_at {
+        Some(at) => format_relative_millis(at),
+        None => dim("never"),
+    };
+    println!("  {}  {}", dim("backend"), reply.backend.as_str());
+    println!("  {}  {}", dim("applied"), applied);
+    if let Some(error) = &reply.error {
+        println!("  {}  {}", dim("error  "), red(error));
+    }
+    if reply.backend == FirewallBackend::Off {
+        println!(
+            "{}",
+            dim("Not managed; enable with 'm87 config set --firewall nftables' on the device")
+        );
+        return;
+    }
+    println!();
+    if reply.rules.is_empty() {
+        println!("{}", dim("No run exposes ports"));
+    }
+    for rule in &reply.rules {
+        println!(
+            "  {:>5}/{}  {}",
+            rule.port,
+            rule.protocol.as_str(),
+            rule.run_id
+        );
+    }
+    if !reply.always_allowed.is_empty() {
+        println!();
+        println!("{}", dim("Always allowed:"));
+        for rule in &reply.always_allowed {
+            println!("  {}", dim(rule));
         }
     }
+}
 
-    print!("{out}");
+/// Fingerprints of the end-to-end keys for `name`: the pinned and the
+/// reported device key, and this operator's key.
+pub fn print_e2e_keys(name: &str, pinned: Option<&str>, reported: Option<&str>, operator: &str) {
+    let pinned_fp = match pinned {
+        Some(key) => fingerprint(key),
+        None => dim("not pinned yet"),
+    };
+    let reported_fp = match (reported, pinned) {
+        (Some(key), Some(pinned)) if key.trim() != pinned.trim() => {
+            red(&format!("{} (differs from pinned)", fingerprint(key)))
+        }
+        (Some(key), _) => fingerprint(key),
+        (None, _) => dim("none, the runtime does not support end-to-end encryption"),
+    };
+    println!("{}", bold(name));
+    println!("  {}  {}", dim("pinned key  "), pinned_fp);
+    println!("  {}  {}", dim("reported key"), reported_fp);
+    println!("  {}  {}", dim("operator key"), fingerprint(operator));
 }
 
 pub fn print_device_status(name: &str, status: &DeviceStatus) {
@@ -199,6 +1292,15 @@ pub fn print_device_status(name: &str, status: &DeviceStatus) {
     let opts = RenderOpts::default();
 
     println!("{} {}", "Device", bold(name));
+    if status.pending_reports > 0 {
+        println!(
+            "  {}",
+            dim(&format!(
+                "{} reports wait for upload; run states are from the last heartbeat",
+                status.pending_reports
+            ))
+        );
+    }
 
     if status.observations.is_empty() && status.incidents.is_empty() {
         println!("  {}", dim("No observations or incidents"));
@@ -348,6 +1450,98 @@ pub fn print_device_status(name: &str, status: &DeviceStatus) {
     }
 }
 
+pub fn print_device_events(events: &[DeviceTimelineEvent]) {
+    if events.is_empty() {
+        println!("{}", dim("No events in this time range"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    fn kind_badge(kind: TimelineEventKind) -> String {
+        let label = kind.as_str();
+        match kind {
+            TimelineEventKind::Connected | TimelineEventKind::Deployment => green(label),
+            TimelineEventKind::Disconnected
+            | TimelineEventKind::Reboot
+            | TimelineEventKind::Drift
+            | TimelineEventKind::Egress => yellow(label),
+            TimelineEventKind::RunFailed
+            | TimelineEventKind::StepFailed
+            | TimelineEventKind::Crash
+            | TimelineEventKind::Rollback => red(label),
+            TimelineEventKind::Session => cyan(label),
+            TimelineEventKind::Audit => dim(label),
+        }
+    }
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "TIME",
+                min: 19,
+                max: Some(19),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "EVENT",
+                min: 12,
+                max: Some(12),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "SUMMARY",
+                min: 20,
+                max: None,
+                weight: 4,
+                align: Align::Left,
+                wrap: true,
+            },
+            ColSpec {
+                title: "BY",
+                min: 2,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    out.push_str("  ");
+    t.header(&mut out, &opts);
+
+    for event in events {
+        let time = match event.timestamp.parse::<chrono::DateTime<chrono::Utc>>() {
+            Ok(t) => t
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            Err(_) => event.timestamp.clone(),
+        };
+        let actor = match &event.actor {
+            Some(actor) => actor.clone(),
+            None => dim("-"),
+        };
+        out.push_str("  ");
+        t.row(
+            &mut out,
+            &[&time, &kind_badge(event.kind), &event.summary, &actor],
+            &opts,
+        );
+    }
+
+    print!("{out}");
+}
+
 pub fn print_deployment_reports(reports: &[AuditLog], show_details: bool) {
     if reports.is_empty() {
         println!("{}", dim("No deployment reports found"));
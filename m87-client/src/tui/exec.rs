@@ -1,11 +1,13 @@
-use crate::streams::quic::open_quic_io;
+use crate::streams::e2e::StreamIo;
+use crate::streams::quic::open_interactive_io;
 use crate::streams::stream_type::StreamType;
+use crate::tui::term::RawMode;
+use crate::util::clipboard::{ClipboardPolicy, Osc52Filter};
 use crate::{auth::AuthManager, config::Config, devices, util::shutdown::SHUTDOWN};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use termion::raw::IntoRawMode;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::sync::{Mutex, mpsc};
 
 #[derive(Serialize)]
@@ -33,28 +35,29 @@ pub async fn run_exec(device: &str, command: Vec<String>, stdin: bool, tty: bool
 
     let token = AuthManager::get_cli_token().await?;
 
+    // Join command into single string (shell will interpret operators like && |)
+    let cmd_str = command.join(" ");
+
     let stream_type = StreamType::Exec {
         token: token.to_string(),
+        command: Some(cmd_str.clone()),
     };
-    let (_, io) = open_quic_io(
-        &resolved.host,
-        &token,
-        &resolved.short_id,
-        stream_type,
-        config.trust_invalid_server_cert,
-    )
-    .await
-    .context("Failed to connect to RAW metrics stream")?;
+    let (_, io) = open_interactive_io(&resolved, &token, stream_type, &config)
+        .await
+        .context("Failed to connect to RAW metrics stream")?;
     tracing::info!("Connected to device");
 
-    // Join command into single string (shell will interpret operators like && |)
-    let cmd_str = command.join(" ");
-
     match (stdin, tty) {
-        (false, false) => run_output_only(io, cmd_str).await,
+        (false, false) => {
+            let exit_code = run_output_only(io, cmd_str, &mut tokio::io::stdout()).await?;
+            if exit_code != 0 {
+                std::process::exit(exit_code);
+            }
+            Ok(())
+        }
         (true, false) => run_with_stdin(io, cmd_str).await,
-        (false, true) => run_tty_readonly(io, cmd_str).await,
-        (true, true) => run_with_tty(io, cmd_str).await,
+        (false, true) => run_tty_readonly(io, cmd_str, config.clipboard).await,
+        (true, true) => run_with_tty(io, cmd_str, config.clipboard).await,
     }
 }
 
@@ -65,10 +68,44 @@ fn try_parse_exit_code(line: &str) -> Option<i32> {
         .map(|r| r.exit_code)
 }
 
-/// No stdin, no tty: just send command config and stream output
-async fn run_output_only<IO>(io: IO, cmd_str: String) -> Result<()>
+/// Run `command` on the device without stdin or tty, streaming its output,
+/// and return its exit code, 130 when interrupted with Ctrl+C.
+pub async fn run_streamed(device: &str, command: String) -> Result<i32> {
+    let io = open_exec(device, &command).await?;
+    run_output_only(io, command, &mut tokio::io::stdout()).await
+}
+
+/// Run `command` on the device like [`run_streamed`], but return its output
+/// instead of printing it, e.g. to read back a path it created.
+pub async fn run_captured(device: &str, command: String) -> Result<(i32, String)> {
+    let io = open_exec(device, &command).await?;
+    let mut output = Vec::new();
+    let exit_code = run_output_only(io, command, &mut output).await?;
+    Ok((exit_code, String::from_utf8_lossy(&output).into_owned()))
+}
+
+async fn open_exec(device: &str, command: &str) -> Result<StreamIo> {
+    rustls::crypto::CryptoProvider::install_default(rustls::crypto::ring::default_provider()).ok();
+
+    let config = Config::load()?;
+    let resolved = devices::resolve_device_cached(device).await?;
+    let token = AuthManager::get_cli_token().await?;
+
+    let stream_type = StreamType::Exec {
+        token: token.to_string(),
+        command: Some(command.to_string()),
+    };
+    let (_, io) = open_interactive_io(&resolved, &token, stream_type, &config)
+        .await
+        .context("Failed to connect to exec stream")?;
+    Ok(io)
+}
+
+/// No stdin, no tty: just send command config and copy the output to `out`
+async fn run_output_only<IO, W>(io: IO, cmd_str: String, out: &mut W) -> Result<i32>
 where
     IO: AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
 {
     let (reader, mut writer) = tokio::io::split(io);
     let mut reader = BufReader::new(reader);
@@ -83,7 +120,6 @@ where
         .await?;
     writer.flush().await?;
 
-    let mut stdout = tokio::io::stdout();
     let mut exit_code = 0;
 
     // Stream output until connection closes or Ctrl+C
@@ -92,7 +128,7 @@ where
         line.clear();
         tokio::select! {
             _ = SHUTDOWN.cancelled() => {
-                std::process::exit(130);
+                return Ok(130);
             }
             result = reader.read_line(&mut line) => {
                 match result {
@@ -101,8 +137,8 @@ where
                         if let Some(code) = try_parse_exit_code(&line) {
                             exit_code = code;
                         } else {
-                            stdout.write_all(line.as_bytes()).await?;
-                            stdout.flush().await?;
+                            out.write_all(line.as_bytes()).await?;
+                            out.flush().await?;
                         }
                     }
                     Err(_) => break,
@@ -111,10 +147,7 @@ where
         }
     }
 
-    if exit_code != 0 {
-        std::process::exit(exit_code);
-    }
-    Ok(())
+    Ok(exit_code)
 }
 
 /// Stdin forwarding without raw mode (line-buffered input for prompts)
@@ -210,14 +243,14 @@ where
 }
 
 /// TTY mode without stdin: raw terminal output only (read-only view of TUI apps)
-async fn run_tty_readonly<IO>(io: IO, cmd_str: String) -> Result<()>
+async fn run_tty_readonly<IO>(io: IO, cmd_str: String, clipboard: ClipboardPolicy) -> Result<()>
 where
     IO: AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
 {
     let (mut reader, mut writer) = tokio::io::split(io);
 
     // Enter raw mode for proper escape sequence rendering
-    let raw_mode = std::io::stdout().into_raw_mode()?;
+    let raw_mode = RawMode::enable()?;
     let mut stdout = tokio::io::stdout();
 
     // Send command config with tty: true
@@ -233,6 +266,7 @@ where
     let mut exit_code = 0;
     let mut buf = [0u8; 4096];
     let mut pending = Vec::new();
+    let mut osc52 = Osc52Filter::new(clipboard);
 
     // Stream output until connection closes or Ctrl+C
     loop {
@@ -256,7 +290,7 @@ where
                             // Output everything except the JSON line
                             let output_end = pending.len() - last.len() - 1;
                             if output_end > 0 {
-                                stdout.write_all(&pending[..output_end]).await?;
+                                stdout.write_all(&osc52.filter(&pending[..output_end])).await?;
                                 stdout.flush().await?;
                             }
                             pending.clear();
@@ -264,7 +298,7 @@ where
                         }
 
                         // No exit code found, output everything
-                        stdout.write_all(&pending).await?;
+                        stdout.write_all(&osc52.filter(&pending)).await?;
                         stdout.flush().await?;
                         pending.clear();
                     }
@@ -283,7 +317,7 @@ where
 }
 
 /// Full TTY mode: raw terminal, bidirectional stdin/stdout (for vim, htop, etc.)
-async fn run_with_tty<IO>(io: IO, cmd_str: String) -> Result<()>
+async fn run_with_tty<IO>(io: IO, cmd_str: String, clipboard: ClipboardPolicy) -> Result<()>
 where
     IO: AsyncReadExt + AsyncWriteExt + Unpin + Send + 'static,
 {
@@ -291,7 +325,7 @@ where
     let writer = Arc::new(Mutex::new(writer));
 
     // Enter raw mode
-    let raw_mode = std::io::stdout().into_raw_mode()?;
+    let raw_mode = RawMode::enable()?;
     let mut stdout = tokio::io::stdout();
 
     // Send command config with tty: true
@@ -350,6 +384,7 @@ where
         let mut reader = reader;
         let mut buf = [0u8; 4096];
         let mut pending = Vec::new();
+        let mut osc52 = Osc52Filter::new(clipboard);
 
         loop {
             let n = reader.read(&mut buf).await?;
@@ -371,7 +406,9 @@ where
                 // Output everything except the JSON line
                 let output_end = pending.len() - last.len() - 1;
                 if output_end > 0 {
-                    stdout.write_all(&pending[..output_end]).await?;
+                    stdout
+                        .write_all(&osc52.filter(&pending[..output_end]))
+                        .await?;
                     stdout.flush().await?;
                 }
                 pending.clear();
@@ -379,7 +416,7 @@ where
             }
 
             // No exit code found, output everything
-            stdout.write_all(&pending).await?;
+            stdout.write_all(&osc52.filter(&pending)).await?;
             stdout.flush().await?;
             pending.clear();
         }
@@ -26,7 +26,7 @@ fn mode_string(perm: u32, ty: FileType) -> String {
     )
 }
 
-fn human_size(size: u64) -> String {
+pub fn human_size(size: u64) -> String {
     const KB: f64 = 1024.0;
     const MB: f64 = KB * 1024.0;
     const GB: f64 = MB * 1024.0;
@@ -1,4 +1,6 @@
 use chrono::{DateTime, Local, TimeZone, Utc};
+use m87_shared::inventory::Severity;
+use m87_shared::metrics::{BatteryMetrics, ChargingState};
 use m87_shared::roles::Role;
 use ratatui::crossterm;
 
@@ -519,3 +521,36 @@ pub fn role_badge(role: &Role) -> String {
         Role::Viewer => green("viewer"),
     }
 }
+
+pub fn severity_badge(severity: Option<Severity>) -> String {
+    match severity {
+        Some(Severity::Critical) => bold(&red("critical")),
+        Some(Severity::High) => red("high"),
+        Some(Severity::Medium) => yellow("medium"),
+        Some(Severity::Low) => green("low"),
+        None => dim("unknown"),
+    }
+}
+
+/// Unix milliseconds as a relative time ("3h ago").
+pub fn format_relative_millis(millis: u64) -> String {
+    match DateTime::<Utc>::from_timestamp_millis(millis as i64) {
+        Some(time) => format_relative_time(&time.to_rfc3339()),
+        None => "-".to_string(),
+    }
+}
+
+/// Battery level with its charging state, red when low and discharging.
+pub fn battery_label(battery: &BatteryMetrics) -> String {
+    let state = match battery.state {
+        ChargingState::Charging => " charging",
+        ChargingState::Full => " full",
+        _ => "",
+    };
+    let label = format!("{:.0}%{}", battery.percent, state);
+    if battery.percent <= 20.0 && battery.state != ChargingState::Charging {
+        red(&label)
+    } else {
+        label
+    }
+}
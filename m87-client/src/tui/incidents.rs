@@ -0,0 +1,183 @@
+use crate::tui::helper::{
+    Align, ColSpec, RenderOpts, Table, cyan, dim, format_relative_time, green, kv_line, red,
+    terminal_width, yellow,
+};
+use m87_shared::incident::{Incident, IncidentEntryKind, IncidentState};
+
+fn state_badge(state: IncidentState) -> String {
+    match state {
+        IncidentState::Open => red("open"),
+        IncidentState::Acknowledged => yellow("acknowledged"),
+        IncidentState::Resolved => green("resolved"),
+    }
+}
+
+fn local_time(timestamp: &str) -> String {
+    match timestamp.parse::<chrono::DateTime<chrono::Utc>>() {
+        Ok(t) => t
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+        Err(_) => timestamp.to_string(),
+    }
+}
+
+pub fn print_incidents(incidents: &[Incident]) {
+    if incidents.is_empty() {
+        println!("{}", dim("No incidents"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ID",
+                min: 24,
+                max: Some(24),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DEVICE",
+                min: 8,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "STATE",
+                min: 12,
+                max: Some(12),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "TITLE",
+                min: 10,
+                max: None,
+                weight: 3,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "OPENED",
+                min: 10,
+                max: Some(16),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    for incident in incidents {
+        t.row(
+            &mut out,
+            &[
+                &incident.id,
+                &incident.device_name,
+                &state_badge(incident.state),
+                &incident.title,
+                &format_relative_time(&incident.opened_at),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
+
+/// One incident with everything attached to it, oldest first.
+pub fn print_incident(incident: &Incident) {
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    let mut out = String::new();
+    let mut kv = |label: &str, value: &str| {
+        out.push_str(&kv_line(term_w, label, value, &opts));
+        out.push('\n');
+    };
+    kv("incident", &incident.id);
+    kv("device", &incident.device_name);
+    kv("rule", &incident.rule);
+    kv("title", &incident.title);
+    kv("state", &state_badge(incident.state));
+    kv("opened", &local_time(&incident.opened_at));
+    if let Some(by) = &incident.acknowledged_by {
+        kv("acked by", by);
+    }
+    if let Some(by) = &incident.resolved_by {
+        let at = incident.resolved_at.as_deref().map(local_time);
+        kv("resolved", &format!("{} by {}", at.unwrap_or_default(), by));
+    }
+    out.push('\n');
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "TIME",
+                min: 19,
+                max: Some(19),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "KIND",
+                min: 6,
+                max: Some(6),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "SUMMARY",
+                min: 20,
+                max: None,
+                weight: 4,
+                align: Align::Left,
+                wrap: true,
+            },
+            ColSpec {
+                title: "BY",
+                min: 2,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    out.push_str("  ");
+    t.header(&mut out, &opts);
+    for entry in &incident.entries {
+        let kind = match entry.kind {
+            IncidentEntryKind::Alert => red("alert"),
+            IncidentEntryKind::Event => yellow("event"),
+            IncidentEntryKind::Action => cyan("action"),
+        };
+        let actor = match &entry.actor {
+            Some(actor) => actor.clone(),
+            None => dim("-"),
+        };
+        out.push_str("  ");
+        t.row(
+            &mut out,
+            &[&local_time(&entry.timestamp), &kind, &entry.summary, &actor],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
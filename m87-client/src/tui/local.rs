@@ -0,0 +1,236 @@
+use m87_shared::deploy_spec::{DeployReportKind, DeploymentRevision, RunSpec};
+use m87_shared::metrics::SystemMetrics;
+
+use crate::device::deployment_manager::{EventEntry, LocalOp, LocalRunState};
+use crate::device::gc::Reclaimed;
+use crate::device::recovery::Unlocked;
+use crate::tui::fs::human_size;
+use crate::tui::helper::{battery_label, bold, dim, format_time, yellow};
+
+fn state_label(st: &LocalRunState) -> &'static str {
+    if st.consecutive_alive_failures > 0 || st.consecutive_health_failures > 0 {
+        "failing"
+    } else if st.ran_successful {
+        "done"
+    } else {
+        "pending"
+    }
+}
+
+pub fn print_local_status(
+    revision: Option<&(DeploymentRevision, Vec<(RunSpec, LocalRunState)>)>,
+    queued_events: usize,
+    queued_ops: &[LocalOp],
+) {
+    let Some((rev, runs)) = revision else {
+        println!("{}", dim("No deployment stored on this device"));
+        return;
+    };
+
+    println!(
+        "{} {}",
+        bold("Deployment"),
+        rev.id.as_deref().unwrap_or("-")
+    );
+    println!(
+        "  {:<36} {:>8} {:>8} {:>8} {:>10} {:>10}",
+        "JOB ID", "ENABLED", "STATE", "ALIVE", "HEALTHY", "FAILURES"
+    );
+    for (spec, st) in runs {
+        println!(
+            "  {:<36} {:>8} {:>8} {:>8} {:>10} {:>10}",
+            spec.id,
+            spec.enabled,
+            state_label(st),
+            st.last_alive,
+            st.last_health,
+            st.consecutive_alive_failures + st.consecutive_health_failures
+        );
+    }
+
+    println!();
+    println!("{} {}", bold("Undelivered reports:"), queued_events);
+    if !queued_ops.is_empty() {
+        println!("{}", bold("Queued operations"));
+        for op in queued_ops {
+            match op {
+                LocalOp::Rerun {
+                    run_id,
+                    accept_changes,
+                } => {
+                    let note = if *accept_changes {
+                        " (accepting changed files)"
+                    } else {
+                        ""
+                    };
+                    println!("  rerun {}{}", run_id, note)
+                }
+            }
+        }
+    }
+}
+
+pub fn print_local_logs(events: &[DeployReportKind], run_id: Option<&str>) {
+    let mut printed = false;
+    for ev in events {
+        let (id, time, title, tail) = match ev {
+            DeployReportKind::StepReport(r) => (
+                &r.run_id,
+                r.report_time,
+                format!(
+                    "step {}{}",
+                    r.name.as_deref().unwrap_or("-"),
+                    if r.success { "" } else { " (failed)" }
+                ),
+                r.log_tail.as_str(),
+            ),
+            DeployReportKind::RunState(r) => (
+                &r.run_id,
+                r.report_time,
+                "observe".to_string(),
+                r.log_tail.as_deref().unwrap_or(""),
+            ),
+            _ => continue,
+        };
+        if run_id.is_some_and(|want| want != id) || tail.trim().is_empty() {
+            continue;
+        }
+        printed = true;
+        println!("{} {} {}", dim(&format_time(time, false)), bold(id), title);
+        for line in tail.lines() {
+            println!("  {}", line);
+        }
+    }
+    if !printed {
+        println!("{}", dim("No undelivered logs stored on this device"));
+    }
+}
+
+fn report_kind(report: &DeployReportKind) -> &'static str {
+    match report {
+        DeployReportKind::DeploymentRevisionReport(_) => "revision",
+        DeployReportKind::RunReport(_) => "run",
+        DeployReportKind::StepReport(_) => "step",
+        DeployReportKind::RollbackReport(_) => "rollback",
+        DeployReportKind::RunState(_) => "observe",
+        DeployReportKind::DriftReport(_) => "drift",
+        DeployReportKind::CoreDump(_) => "core dump",
+        DeployReportKind::EgressViolation(_) => "egress",
+    }
+}
+
+pub fn print_events(events: &[EventEntry]) {
+    if events.is_empty() {
+        println!("{}", dim("No queued or dead-lettered reports"));
+        return;
+    }
+    println!(
+        "{:<26} {:<8} {:<8} {:<36} {:>10}  REJECTED",
+        "KEY", "STATE", "KIND", "RUN", "DELIVERIES"
+    );
+    for e in events {
+        let report = &e.event.report;
+        println!(
+            "{:<26} {:<8} {:<8} {:<36} {:>10}  {}",
+            e.key,
+            e.state,
+            report_kind(report),
+            report
+                .get_run_id()
+                .unwrap_or_else(|| report.get_revision_id().to_string()),
+            e.event.deliveries,
+            e.event.rejected.as_deref().unwrap_or("")
+        );
+    }
+}
+
+pub fn print_reclaimed(r: &Reclaimed) {
+    if r.files == 0 {
+        println!("{}", dim("Nothing to clean up"));
+        return;
+    }
+    println!(
+        "Removed {} files, reclaimed {}",
+        r.files,
+        human_size(r.bytes)
+    );
+}
+
+pub fn print_local_metrics(m: &SystemMetrics) {
+    println!("{} {} ({}/{})", bold("Host"), m.hostname, m.os, m.arch);
+    println!("  uptime   {}s", m.uptime_secs);
+    println!(
+        "  cpu      {:.1}% of {} cores (load {:.2} {:.2} {:.2})",
+        m.cpu.usage_percent, m.cpu.cores, m.cpu.load_avg.0, m.cpu.load_avg.1, m.cpu.load_avg.2
+    );
+    println!(
+        "  memory   {:.1}% ({} / {} MB)",
+        m.memory.usage_percent, m.memory.used_mb, m.memory.total_mb
+    );
+    println!(
+        "  disk     {:.1}% ({} / {} GB)",
+        m.disk.usage_percent, m.disk.used_gb, m.disk.total_gb
+    );
+    println!(
+        "  network  rx {:.2} Mbps, tx {:.2} Mbps",
+        m.network.rx_mbps, m.network.tx_mbps
+    );
+    for t in &m.top_talkers {
+        println!(
+            "  run      {} rx {}, tx {}",
+            t.run_id,
+            human_size(t.rx_bytes),
+            human_size(t.tx_bytes)
+        );
+    }
+    for gpu in &m.gpu {
+        println!(
+            "  gpu      {} {:.1}% ({} / {} MB)",
+            gpu.name, gpu.usage_percent, gpu.memory_used_mb, gpu.memory_total_mb
+        );
+    }
+    if let Some(battery) = &m.battery {
+        println!("  battery  {}", battery_label(battery));
+    }
+    if let Some(loc) = &m.location {
+        println!("  location {:.6}, {:.6}", loc.latitude, loc.longitude);
+    }
+}
+
+pub fn print_recovery_code(code: &str) {
+    println!();
+    println!("{} {}", bold("Recovery code:"), code);
+    println!(
+        "{}",
+        yellow("Store it offline now, it is not shown again. 'm87 local unlock' trades it")
+    );
+    println!(
+        "{}",
+        yellow("for local access when the server is unreachable and credentials are lost.")
+    );
+    println!();
+}
+
+pub fn print_unlocked(unlocked: &Unlocked, fingerprint: &str, port: u16, enabled_direct: bool) {
+    println!("{} {}", bold("Local key:"), unlocked.local_key);
+    println!("  valid until {}", unlocked.expires_at);
+    println!(
+        "  use it from the LAN: m87 --direct --device-key <key> --fingerprint {} <ip>:{} shell",
+        fingerprint, port
+    );
+    if enabled_direct {
+        println!(
+            "{}",
+            yellow("Direct LAN connections were off and are now enabled; restart the runtime")
+        );
+        println!(
+            "{}",
+            yellow("with 'sudo m87 runtime restart' to apply. They turn off when the key expires.")
+        );
+    }
+    println!(
+        "{}",
+        dim("The unlock is reported to the server once the device reconnects.")
+    );
+    print_recovery_code(&unlocked.next_code);
+}
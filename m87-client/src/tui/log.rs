@@ -1,12 +1,22 @@
-use crate::streams::quic::open_quic_io;
+use crate::streams::quic::open_bulk_io;
 use crate::streams::stream_type::StreamType;
 use crate::{auth::AuthManager, config::Config, devices, util::shutdown::SHUTDOWN};
 use anyhow::{Context, Result};
+use m87_shared::log_filter::LogFilter;
+use m87_shared::log_format::LogLevel;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc;
 
-/// Stream live logs from a device using RAW upgraded connection.
-pub async fn run_logs(device: &str) -> Result<()> {
+/// Stream live logs from a device using RAW upgraded connection, only lines
+/// at `level` or above when it is set and that pass `filter`. `follow` asks
+/// for service logs from devices in the low bandwidth profile.
+pub async fn run_logs(
+    device: &str,
+    level: Option<LogLevel>,
+    follow: bool,
+    filter: LogFilter,
+    tail: usize,
+) -> Result<()> {
     let config = Config::load()?;
 
     let resolved = devices::resolve_device_cached(device).await?;
@@ -17,13 +27,17 @@ pub async fn run_logs(device: &str) -> Result<()> {
 
     let stream_type = StreamType::Logs {
         token: token.to_string(),
+        level,
+        follow,
+        filter,
+        tail: Some(tail),
     };
-    let (_, mut io) = open_quic_io(
+    let (_, mut io) = open_bulk_io(
         &resolved.host,
         &token,
         &resolved.short_id,
         stream_type,
-        config.trust_invalid_server_cert,
+        &config,
     )
     .await
     .context("Failed to connect to RAW metrics stream")?;
@@ -3,18 +3,20 @@ use crate::{
     config::Config,
     devices,
     streams::{quic::open_quic_io, stream_type::StreamType},
+    tui::fs::human_size,
 };
 use anyhow::{Result, anyhow};
 use m87_shared::metrics::SystemMetrics;
 
-use ratatui::Terminal;
+use ratatui::{Terminal, crossterm};
 use tokio::io::{AsyncBufReadExt, BufReader};
 
 pub async fn run_metrics(device: &str) -> Result<()> {
     let result = run_metrics_inner(device).await;
 
-    // ensure alternate screen is closed
-    println!("{}", termion::screen::ToMainScreen);
+    // ensure raw mode and the alternate screen are left
+    let _ = crossterm::terminal::disable_raw_mode();
+    let _ = crossterm::execute!(std::io::stdout(), crossterm::terminal::LeaveAlternateScreen);
 
     if let Err(ref e) = result {
         tracing::error!("Error: {e:?}");
@@ -97,16 +99,18 @@ pub async fn ui_loop(
     mut rx: tokio::sync::mpsc::Receiver<SystemMetrics>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     use ratatui::{
-        backend::TermionBackend,
+        backend::CrosstermBackend,
+        crossterm::{
+            event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+            execute,
+            terminal::{EnterAlternateScreen, enable_raw_mode},
+        },
         layout::{Alignment, Constraint, Direction, Layout, Rect},
         style::{Color, Style},
         widgets::{Block, Borders, Gauge, Paragraph, Row, Sparkline, Table},
     };
     use std::cmp::min;
     use std::collections::VecDeque;
-    use termion::{
-        async_stdin, event::Key, input::TermRead, raw::IntoRawMode, screen::IntoAlternateScreen,
-    };
 
     const HISTORY_LEN: usize = 200;
 
@@ -126,15 +130,13 @@ pub async fn ui_loop(
             .collect()
     }
 
-    // Terminal init
-    let stdout = std::io::stdout();
-    let raw = stdout.into_raw_mode()?;
-    let screen = raw.into_alternate_screen()?;
-    let backend = TermionBackend::new(screen);
+    // Terminal init; run_metrics restores the terminal
+    enable_raw_mode()?;
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let mut keys = async_stdin().keys();
-
     // State
     let mut latest: Option<SystemMetrics> = None;
 
@@ -150,10 +152,16 @@ pub async fn ui_loop(
     let mut gpu_util_history: VecDeque<f64> = VecDeque::with_capacity(HISTORY_LEN);
 
     loop {
-        // keyboard
-        if let Some(Ok(key)) = keys.next() {
-            match key {
-                Key::Ctrl('c') | Key::Char('q') | Key::Esc => return Ok(()),
+        // keyboard; Windows consoles also report key releases
+        if event::poll(std::time::Duration::ZERO)?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match key.code {
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(());
+                }
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
                 _ => {}
             }
         }
@@ -206,18 +214,33 @@ pub async fn ui_loop(
             let m = latest.as_ref().unwrap();
 
             // -------- root layout --------
+            // log metrics get a table below the header, up to 8 rows
+            let log_metrics_height = if m.log_metrics.is_empty() {
+                0
+            } else {
+                m.log_metrics.len().min(8) as u16 + 3
+            };
+            let top_talkers_height = if m.top_talkers.is_empty() {
+                0
+            } else {
+                m.top_talkers.len() as u16 + 3
+            };
             let root_chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([
-                    Constraint::Length(3),      // header
-                    Constraint::Percentage(45), // CPU + NET
-                    Constraint::Percentage(52), // MEM + DISK + GPU
+                    Constraint::Length(3),                  // header
+                    Constraint::Length(log_metrics_height), // log metrics
+                    Constraint::Length(top_talkers_height), // top talkers
+                    Constraint::Percentage(45),             // CPU + NET
+                    Constraint::Percentage(52),             // MEM + DISK + GPU
                 ])
                 .split(size);
 
             let header_area = root_chunks[0];
-            let cpu_net_area = root_chunks[1];
-            let mem_disk_gpu_area = root_chunks[2];
+            let log_metrics_area = root_chunks[1];
+            let top_talkers_area = root_chunks[2];
+            let cpu_net_area = root_chunks[3];
+            let mem_disk_gpu_area = root_chunks[4];
 
             // -------- header --------
             let uptime_h = m.uptime_secs / 3600;
@@ -233,6 +256,65 @@ pub async fn ui_loop(
 
             f.render_widget(header_paragraph, header_area);
 
+            // -------- log metrics --------
+            if !m.log_metrics.is_empty() {
+                let rows: Vec<Row> = m
+                    .log_metrics
+                    .iter()
+                    .map(|s| {
+                        Row::new(vec![
+                            s.run_id.clone(),
+                            s.name.clone(),
+                            s.kind.as_str().to_string(),
+                            format!("{}", s.value),
+                        ])
+                    })
+                    .collect();
+                let table = Table::new(
+                    rows,
+                    [
+                        Constraint::Percentage(30),
+                        Constraint::Percentage(30),
+                        Constraint::Percentage(15),
+                        Constraint::Percentage(25),
+                    ],
+                )
+                .header(
+                    Row::new(vec!["Run", "Metric", "Type", "Value"])
+                        .style(Style::default().fg(Color::LightBlue)),
+                )
+                .block(Block::default().borders(Borders::ALL).title("Log Metrics"));
+                f.render_widget(table, log_metrics_area);
+            }
+
+            // -------- top talkers --------
+            if !m.top_talkers.is_empty() {
+                let rows: Vec<Row> = m
+                    .top_talkers
+                    .iter()
+                    .map(|t| {
+                        Row::new(vec![
+                            t.run_id.clone(),
+                            human_size(t.rx_bytes),
+                            human_size(t.tx_bytes),
+                        ])
+                    })
+                    .collect();
+                let table = Table::new(
+                    rows,
+                    [
+                        Constraint::Percentage(50),
+                        Constraint::Percentage(25),
+                        Constraint::Percentage(25),
+                    ],
+                )
+                .header(
+                    Row::new(vec!["Run", "RX", "TX"]).style(Style::default().fg(Color::LightBlue)),
+                )
+                .block(Block::default().borders(Borders::ALL).title("Top Talkers"));
+                f.render_widget(table, top_talkers_area);
+            }
+
             // -------- CPU + NET row --------
             let cpu_net_rows = Layout::default()
                 .direction(Direction::Vertical)
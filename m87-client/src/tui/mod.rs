@@ -1,11 +1,20 @@
 pub mod exec;
 pub mod log;
 pub mod metric;
+pub mod predict;
 pub mod shell;
+pub mod term;
 
+pub mod access;
+pub mod admin;
+pub mod approvals;
 pub mod deploy;
 pub mod device;
 pub mod fs;
 pub mod helper;
+pub mod incidents;
+#[cfg(feature = "runtime")]
+pub mod local;
 pub mod org;
+pub mod rollouts;
 pub mod user;
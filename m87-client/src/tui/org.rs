@@ -1,5 +1,13 @@
-use crate::tui::helper::{Align, ColSpec, RenderOpts, Table, dim, role_badge, terminal_width};
-use m87_shared::org::Organization; // adjust if needed
+use crate::tui::fs::human_size;
+use crate::tui::helper::{
+    Align, ColSpec, RenderOpts, Table, dim, format_relative_time, green, red, role_badge,
+    severity_badge, terminal_width, yellow,
+};
+use m87_shared::inventory::VulnerabilityReport;
+use m87_shared::iot_bridge::OrgIotBridge;
+use m87_shared::org::{OrgIncidentWebhook, OrgLimitsStatus, Organization}; // adjust if needed
+use m87_shared::patching::PublicPatchPolicy;
+use m87_shared::usage::OrgUsageRecord;
 
 pub fn print_device_organizations(orgs: &[Organization]) {
     if orgs.is_empty() {
@@ -47,3 +55,407 @@ pub fn print_device_organizations(orgs: &[Organization]) {
 
     print!("{out}");
 }
+
+pub fn print_vulnerability_report(report: &VulnerabilityReport) {
+    println!(
+        "{}",
+        dim(&format!("{} devices scanned", report.devices_scanned))
+    );
+    if !report.devices_pending.is_empty() {
+        println!(
+            "{}",
+            yellow(&format!(
+                "Not scanned yet: {}",
+                report.devices_pending.join(", ")
+            ))
+        );
+    }
+    if report.vulnerabilities.is_empty() {
+        println!("{}", green("No known vulnerabilities"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(120).max(60);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "SEVERITY",
+                min: 8,
+                max: Some(8),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "ID",
+                min: 14,
+                max: Some(20),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DEVICES",
+                min: 7,
+                max: Some(7),
+                weight: 0,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "PACKAGES",
+                min: 8,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "SUMMARY",
+                min: 10,
+                max: None,
+                weight: 4,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+
+    for v in &report.vulnerabilities {
+        let severity = severity_badge(v.severity);
+        let devices = v.devices.len().to_string();
+        let packages = v.packages.join(", ");
+        let summary = v.summary.as_deref().unwrap_or("-");
+        t.row(
+            &mut out,
+            &[&severity, v.display_id(), &devices, &packages, summary],
+            &opts,
+        );
+    }
+
+    print!("{out}");
+}
+
+/// Policies created on several servers are listed once.
+pub fn print_patch_policies(policies: &[PublicPatchPolicy]) {
+    let mut policies: Vec<_> = policies.iter().map(|p| &p.policy).collect();
+    policies.sort_by(|a, b| a.name.cmp(&b.name));
+    policies.dedup_by(|a, b| a.name == b.name && a.window == b.window);
+    if policies.is_empty() {
+        println!("{}", dim("No patch policies"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "NAME",
+                min: 8,
+                max: Some(24),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "WINDOW",
+                min: 12,
+                max: Some(32),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "REBOOT",
+                min: 6,
+                max: Some(6),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "PACKAGES",
+                min: 8,
+                max: None,
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "WHEN",
+                min: 6,
+                max: None,
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    for policy in policies {
+        let reboot = if policy.reboot {
+            yellow("yes")
+        } else {
+            dim("no")
+        };
+        let packages = if policy.packages.is_empty() {
+            "all".to_string()
+        } else {
+            policy.packages.join(", ")
+        };
+        t.row(
+            &mut out,
+            &[
+                &policy.name,
+                &policy.window,
+                &reboot,
+                &packages,
+                policy.when.as_deref().unwrap_or("-"),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
+
+/// `used / max`, red once the limit is reached; `-` stands for no limit.
+fn usage_cell(used: u64, max: Option<u64>, fmt: fn(u64) -> String) -> String {
+    match max {
+        Some(max) if used >= max => red(&format!("{} / {}", fmt(used), fmt(max))),
+        Some(max) => format!("{} / {}", fmt(used), fmt(max)),
+        None => format!("{} / {}", fmt(used), dim("-")),
+    }
+}
+
+pub fn print_redaction_patterns(patterns: &[String]) {
+    if patterns.is_empty() {
+        println!(
+            "{}",
+            dim("No custom redaction patterns; the built-in rules apply")
+        );
+        return;
+    }
+    for pattern in patterns {
+        println!("{}", pattern);
+    }
+}
+
+pub fn print_incident_webhook(hook: &OrgIncidentWebhook) {
+    match &hook.url {
+        Some(url) if hook.signed => println!("{} {}", url, dim("(signed)")),
+        Some(url) => println!("{}", url),
+        None => println!("{}", dim("No incident webhook")),
+    }
+}
+
+pub fn print_iot_bridge(bridge: &OrgIotBridge) {
+    let Some(platform) = bridge.platform else {
+        println!("{}", dim("No IoT bridge"));
+        return;
+    };
+    println!(
+        "{} {} {}",
+        platform.as_str(),
+        bridge.endpoint.as_deref().unwrap_or_default(),
+        dim(&format!(
+            "(telemetry every {}s)",
+            bridge.telemetry_interval_secs
+        ))
+    );
+    match (&bridge.last_error, &bridge.last_published_at) {
+        (Some(error), _) => println!("{} {}", red("Failing:"), error),
+        (None, Some(at)) => println!("{} {}", green("Published"), format_relative_time(at)),
+        (None, None) => println!("{}", yellow("Nothing published yet")),
+    }
+}
+
+/// Limits and usage per org, as (server, status).
+pub fn print_org_limits(statuses: &[(String, OrgLimitsStatus)]) {
+    if statuses.is_empty() {
+        println!("{}", dim("No organizations found"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ORG ID",
+                min: 12,
+                max: Some(32),
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "SERVER",
+                min: 12,
+                max: None,
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DEVICES",
+                min: 9,
+                max: Some(16),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "REVISIONS",
+                min: 9,
+                max: Some(16),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "REPORTS",
+                min: 9,
+                max: Some(20),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "TUNNELS",
+                min: 9,
+                max: Some(16),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let count = |v: u64| v.to_string();
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    for (server, status) in statuses {
+        let (limits, usage) = (&status.limits, &status.usage);
+        t.row(
+            &mut out,
+            &[
+                &status.org_id,
+                server,
+                &usage_cell(usage.devices, limits.max_devices, count),
+                &usage_cell(usage.revisions, limits.max_revisions, count),
+                &usage_cell(
+                    usage.report_storage_bytes,
+                    limits.max_report_storage_bytes,
+                    human_size,
+                ),
+                &usage_cell(
+                    usage.concurrent_tunnels,
+                    limits.max_concurrent_tunnels,
+                    count,
+                ),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
+
+/// Monthly usage per org, oldest month first.
+pub fn print_org_usage(records: &[OrgUsageRecord]) {
+    if records.is_empty() {
+        println!("{}", dim("No usage recorded"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ORG ID",
+                min: 12,
+                max: Some(32),
+                weight: 2,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "MONTH",
+                min: 7,
+                max: Some(7),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "DEVICE HOURS",
+                min: 12,
+                max: Some(16),
+                weight: 1,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "RELAYED",
+                min: 8,
+                max: Some(12),
+                weight: 1,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "PEAK STORAGE",
+                min: 12,
+                max: Some(14),
+                weight: 1,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "PEAK DEVICES",
+                min: 12,
+                max: Some(14),
+                weight: 1,
+                align: Align::Right,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    for r in records {
+        t.row(
+            &mut out,
+            &[
+                &r.org_id,
+                &r.month,
+                &format!("{:.1}", r.device_hours()),
+                &human_size(r.relay_bytes),
+                &human_size(r.peak_storage_bytes),
+                &r.peak_devices.to_string(),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
@@ -0,0 +1,189 @@
+//! Predictive local echo for shells over high-latency links, in the spirit of
+//! mosh: printable keystrokes are shown immediately and the device's echo of
+//! them is dropped once it arrives. If the echo differs, the prediction is
+//! erased and the device's output shown instead.
+//!
+//! The runtime tells the client in-band whether typed characters are echoed
+//! verbatim (see [`PREDICT_FRAME`]), so nothing is predicted in full-screen
+//! programs or at password prompts.
+
+use crate::streams::stream_type::PREDICT_FRAME;
+
+const DEL: u8 = 0x7f;
+
+pub struct Predictor {
+    enabled: bool,
+    /// Last echo state reported by the device.
+    remote_echoes: bool,
+    /// A control key was sent; its effect is unknown until output arrives.
+    awaiting: bool,
+    /// Predicted bytes on screen that the device has not echoed yet.
+    unconfirmed: Vec<u8>,
+    /// Start of a state frame split across output chunks.
+    partial: Vec<u8>,
+}
+
+impl Predictor {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            remote_echoes: false,
+            awaiting: false,
+            unconfirmed: Vec::new(),
+            partial: Vec::new(),
+        }
+    }
+
+    /// Forget predictions and echo state, e.g. after a reconnect.
+    pub fn reset(&mut self) {
+        self.remote_echoes = false;
+        self.awaiting = false;
+        self.unconfirmed.clear();
+        self.partial.clear();
+    }
+
+    /// Bytes to show locally for keystrokes that are sent to the device.
+    pub fn on_input(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut echo = Vec::new();
+        if !self.enabled {
+            return echo;
+        }
+        for &b in input {
+            let predictable = self.remote_echoes && !self.awaiting;
+            match b {
+                0x20..=0x7e if predictable => {
+                    echo.push(b);
+                    self.unconfirmed.push(b);
+                }
+                DEL if predictable && !self.unconfirmed.is_empty() => {
+                    echo.extend_from_slice(b"\x08 \x08");
+                    self.unconfirmed.pop();
+                }
+                0x20..=0x7e => {}
+                _ => self.awaiting = true,
+            }
+        }
+        echo
+    }
+
+    /// Bytes to write to the terminal for output received from the device.
+    pub fn on_output(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let out = self.strip_frames(chunk);
+        if out.is_empty() {
+            return out;
+        }
+        self.awaiting = false;
+
+        let mut i = 0;
+        while i < out.len() && !self.unconfirmed.is_empty() {
+            if out[i] != self.unconfirmed[0] {
+                break;
+            }
+            self.unconfirmed.remove(0);
+            i += 1;
+        }
+
+        let mut display = Vec::with_capacity(out.len());
+        if i < out.len() && !self.unconfirmed.is_empty() {
+            // Mispredicted: erase what is left of the prediction.
+            let n = self.unconfirmed.len();
+            display.extend(std::iter::repeat_n(0x08, n));
+            display.extend(std::iter::repeat_n(b' ', n));
+            display.extend(std::iter::repeat_n(0x08, n));
+            self.unconfirmed.clear();
+        }
+        display.extend_from_slice(&out[i..]);
+        display
+    }
+
+    /// Remove echo state frames from the output and apply them.
+    fn strip_frames(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.partial);
+        data.extend_from_slice(chunk);
+
+        let frame_len = PREDICT_FRAME.len() + 2;
+        let mut out = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let rest = &data[i..];
+            if rest[0] == 0x1b {
+                if rest.len() < frame_len
+                    && PREDICT_FRAME.starts_with(&rest[..rest.len().min(PREDICT_FRAME.len())])
+                {
+                    self.partial = rest.to_vec();
+                    break;
+                }
+                if rest.starts_with(PREDICT_FRAME) && rest[frame_len - 1] == 0x07 {
+                    self.remote_echoes = rest[PREDICT_FRAME.len()] == b'1';
+                    if !self.remote_echoes && !self.unconfirmed.is_empty() {
+                        // Echo turned off while predictions are pending; the
+                        // following output decides whether they were right.
+                        self.awaiting = true;
+                    }
+                    i += frame_len;
+                    continue;
+                }
+            }
+            out.push(rest[0]);
+            i += 1;
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streams::stream_type::predict_frame;
+
+    #[test]
+    fn test_confirmed_prediction_is_not_printed_twice() {
+        let mut p = Predictor::new(true);
+        assert_eq!(p.on_output(&predict_frame(true)), b"");
+        assert_eq!(p.on_input(b"ls"), b"ls");
+        assert_eq!(p.on_output(b"l"), b"");
+        assert_eq!(p.on_output(b"s"), b"");
+        assert_eq!(p.on_input(b"\r"), b"");
+        assert_eq!(p.on_output(b"\r\nfile\r\n"), b"\r\nfile\r\n");
+    }
+
+    #[test]
+    fn test_misprediction_is_erased() {
+        let mut p = Predictor::new(true);
+        p.on_output(&predict_frame(true));
+        assert_eq!(p.on_input(b"ab"), b"ab");
+        assert_eq!(p.on_output(b"aX"), b"\x08 \x08X");
+    }
+
+    #[test]
+    fn test_no_prediction_without_remote_echo() {
+        let mut p = Predictor::new(true);
+        assert_eq!(p.on_input(b"secret"), b"");
+        let split = predict_frame(true);
+        assert_eq!(p.on_output(&split[..4]), b"");
+        assert_eq!(p.on_output(&split[4..]), b"");
+        assert_eq!(p.on_input(b"a"), b"a");
+        p.on_output(&predict_frame(false));
+        assert_eq!(p.on_input(b"b"), b"");
+    }
+
+    #[test]
+    fn test_control_keys_pause_prediction_until_output() {
+        let mut p = Predictor::new(true);
+        p.on_output(&predict_frame(true));
+        assert_eq!(p.on_input(b"\x1b[Dx"), b"");
+        p.on_output(b"\x08");
+        assert_eq!(p.on_input(b"y"), b"y");
+        assert_eq!(p.on_input(&[DEL]), b"\x08 \x08");
+    }
+
+    #[test]
+    fn test_disabled_predictor_only_strips_frames() {
+        let mut p = Predictor::new(false);
+        assert_eq!(
+            p.on_output(&[predict_frame(true), b"$ ".to_vec()].concat()),
+            b"$ "
+        );
+        assert_eq!(p.on_input(b"ls"), b"");
+    }
+}
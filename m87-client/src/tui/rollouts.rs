@@ -0,0 +1,229 @@
+use crate::tui::helper::{
+    Align, ColSpec, RenderOpts, Table, cyan, dim, format_relative_time, green, kv_line, red,
+    terminal_width, yellow,
+};
+use m87_shared::rollout::{Rollout, RolloutDeviceState, RolloutState};
+
+fn state_badge(state: RolloutState) -> String {
+    match state {
+        RolloutState::Running => cyan("running"),
+        RolloutState::Halted => yellow("halted"),
+        RolloutState::RolledBack => red("rolled back"),
+        RolloutState::Completed => green("completed"),
+        RolloutState::Cancelled => dim("cancelled"),
+    }
+}
+
+fn device_badge(state: RolloutDeviceState) -> String {
+    match state {
+        RolloutDeviceState::Pending => dim("pending"),
+        RolloutDeviceState::Deploying => cyan("deploying"),
+        RolloutDeviceState::Healthy => green("healthy"),
+        RolloutDeviceState::Failed => red("failed"),
+        RolloutDeviceState::RolledBack => yellow("rolled back"),
+    }
+}
+
+/// `healthy/failed/total`, e.g. `12/1/40`
+fn progress(rollout: &Rollout) -> String {
+    format!(
+        "{}/{}/{}",
+        rollout.count(RolloutDeviceState::Healthy),
+        rollout.count(RolloutDeviceState::Failed),
+        rollout.devices.len()
+    )
+}
+
+pub fn print_rollouts(rollouts: &[Rollout]) {
+    if rollouts.is_empty() {
+        println!("{}", dim("No rollouts"));
+        return;
+    }
+
+    let term_w = terminal_width().unwrap_or(96);
+    let opts = RenderOpts::default();
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "ID",
+                min: 24,
+                max: Some(24),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "REVISION",
+                min: 8,
+                max: Some(36),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "STATE",
+                min: 11,
+                max: Some(11),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "WAVE",
+                min: 5,
+                max: Some(7),
+                weight: 0,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "OK/FAIL/ALL",
+                min: 11,
+                max: Some(16),
+                weight: 0,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "STARTED",
+                min: 10,
+                max: Some(16),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+        ],
+    );
+
+    let mut out = String::new();
+    t.header(&mut out, &opts);
+    for rollout in rollouts {
+        t.row(
+            &mut out,
+            &[
+                &rollout.id,
+                &rollout.revision_id,
+                &state_badge(rollout.state),
+                &format!("{}/{}", rollout.current_wave + 1, rollout.waves),
+                &progress(rollout),
+                &format_relative_time(&rollout.created_at),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
+
+/// One rollout with the state of each device, in wave order.
+pub fn print_rollout(rollout: &Rollout) {
+    let term_w = terminal_width().unwrap_or(96).max(60);
+    let opts = RenderOpts::default();
+
+    let mut out = String::new();
+    let mut kv = |label: &str, value: &str| {
+        out.push_str(&kv_line(term_w, label, value, &opts));
+        out.push('\n');
+    };
+    let waves: Vec<String> = rollout
+        .policy
+        .waves
+        .iter()
+        .map(|p| format!("{}%", p))
+        .collect();
+    kv("rollout", &rollout.id);
+    kv("revision", &rollout.revision_id);
+    kv("state", &state_badge(rollout.state));
+    if let Some(reason) = &rollout.reason {
+        kv("reason", reason);
+    }
+    kv(
+        "wave",
+        &format!(
+            "{} of {} ({})",
+            rollout.current_wave + 1,
+            rollout.waves,
+            waves.join(" → ")
+        ),
+    );
+    kv(
+        "threshold",
+        &format!(
+            "{}% failed, soak {}s, {}",
+            rollout.policy.failure_threshold_percent,
+            rollout.policy.soak_secs,
+            match rollout.policy.auto_rollback {
+                true => "rolls back",
+                false => "halts only",
+            }
+        ),
+    );
+    kv("devices", &progress(rollout));
+    kv(
+        "started",
+        &format!(
+            "{} by {}",
+            format_relative_time(&rollout.created_at),
+            rollout.created_by
+        ),
+    );
+    out.push('\n');
+
+    let t = Table::new(
+        term_w.saturating_sub(2),
+        1,
+        vec![
+            ColSpec {
+                title: "DEVICE",
+                min: 8,
+                max: Some(32),
+                weight: 1,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "WAVE",
+                min: 4,
+                max: Some(4),
+                weight: 0,
+                align: Align::Right,
+                wrap: false,
+            },
+            ColSpec {
+                title: "STATE",
+                min: 11,
+                max: Some(11),
+                weight: 0,
+                align: Align::Left,
+                wrap: false,
+            },
+            ColSpec {
+                title: "ERROR",
+                min: 10,
+                max: None,
+                weight: 3,
+                align: Align::Left,
+                wrap: true,
+            },
+        ],
+    );
+
+    out.push_str("  ");
+    t.header(&mut out, &opts);
+    for device in &rollout.devices {
+        out.push_str("  ");
+        t.row(
+            &mut out,
+            &[
+                &device.device_name,
+                &(device.wave + 1).to_string(),
+                &device_badge(device.state),
+                device.error.as_deref().unwrap_or_default(),
+            ],
+            &opts,
+        );
+    }
+    print!("{out}");
+}
@@ -1,57 +1,47 @@
-use crate::streams::quic::open_quic_io;
-use crate::streams::stream_type::StreamType;
+use crate::streams::e2e::StreamIo;
+use crate::streams::quic::open_interactive_io;
+use crate::streams::stream_type::{StreamType, TERMINAL_PING};
+use crate::tui::predict::Predictor;
+use crate::tui::term::{self, RawMode, Resizes};
+use crate::util::clipboard::Osc52Filter;
 use crate::util::shutdown::SHUTDOWN;
 use crate::{auth::AuthManager, config::Config, devices};
 use anyhow::Result;
-use termion::{raw::IntoRawMode, terminal_size};
-use tokio::io::AsyncWriteExt;
-use tokio::signal::unix::{SignalKind, signal};
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant, interval_at, sleep};
 
-pub async fn run_shell(device: &str) -> Result<()> {
+/// Ctrl-] detaches from a session joined with `--attach`, as in telnet.
+const DETACH_KEY: u8 = 0x1d;
+
+/// How a connection to the shell ended.
+enum Ended {
+    /// The shell exited, the session was killed or the user left.
+    Closed,
+    /// The connection broke while the session may still be running.
+    Lost,
+}
+
+pub async fn run_shell(
+    device: &str,
+    attach: Option<String>,
+    read_only: bool,
+    predict: bool,
+) -> Result<()> {
     let config = Config::load()?;
     tracing::info!("Resolving device address.");
     let resolved = devices::resolve_device_cached(device).await?;
 
-    let token = AuthManager::get_cli_token().await?;
     let term = std::env::var("TERM").ok();
-    // --- open QUIC terminal stream ---
-    let stream_type = StreamType::Terminal {
-        token: token.to_string(),
-        term,
-    };
-    tracing::info!("Connecting to device.");
-    let (_, io) = open_quic_io(
-        &resolved.host,
-        &token,
-        &resolved.short_id,
-        stream_type,
-        config.trust_invalid_server_cert,
-    )
-    .await?;
-
-    let mut reader = io.recv;
-    let mut writer = io.send;
-
-    // --- raw mode ---
-    let _raw_mode = std::io::stdout().into_raw_mode()?;
-
-    // --- send initial terminal size ---
-    if let Ok((cols, rows)) = terminal_size() {
-        let mut buf = [0u8; 5];
-        buf[0] = 0xFF;
-        buf[1] = (rows >> 8) as u8;
-        buf[2] = rows as u8;
-        buf[3] = (cols >> 8) as u8;
-        buf[4] = cols as u8;
-
-        writer.write_all(&buf).await?;
-        writer.flush().await?;
-    }
-
-    tracing::info!("Connected.");
+    let attached = attach.is_some();
+    let ping = Duration::from_secs(config.keepalive.shell_ping_secs);
+    let resume_window = Duration::from_secs(config.keepalive.shell_resume_secs);
+    // Attached operators share the owner's screen, so only the owner predicts.
+    let predict = (predict || config.predictive_echo) && !attached;
 
     // === stdin → channel ===
+    // Outlives single connections so a resumed session keeps reading input.
     let (stdin_tx, mut stdin_rx) = mpsc::unbounded_channel::<Vec<u8>>();
 
     std::thread::spawn(move || {
@@ -73,69 +63,199 @@ pub async fn run_shell(device: &str) -> Result<()> {
         }
     });
 
-    // === writer task: stdin + resize (fused) ===
-    let mut sigwinch = signal(SignalKind::window_change())?;
+    let mut resizes = Resizes::new()?;
+    let mut raw_mode = None;
+    let mut session_id: Option<String> = None;
+    let mut lost_at: Option<Instant> = None;
+    let mut backoff = Duration::from_secs(1);
+    let mut osc52 = Osc52Filter::new(config.clipboard);
+    let mut predictor = Predictor::new(predict);
 
-    let mut writer_task = tokio::spawn(async move {
-        loop {
-            tokio::select! {
-                // ----- stdin -----
-                Some(bytes) = stdin_rx.recv() => {
-                    if bytes.is_empty() {
-                        let _ = writer.shutdown().await;
-                        break;
-                    }
-
-                    writer.write_all(&bytes).await?;
-                    writer.flush().await?;
-                }
+    loop {
+        let token = AuthManager::get_cli_token().await?;
+        // --- open QUIC terminal stream ---
+        let stream_type = StreamType::Terminal {
+            token: token.to_string(),
+            term: term.clone(),
+            attach: attach.clone(),
+            read_only,
+            resume: session_id.clone(),
+            predict,
+        };
+        tracing::info!("Connecting to device.");
+        let connected = open_interactive_io(&resolved, &token, stream_type, &config).await;
 
-                // ----- resize -----
-                _ = sigwinch.recv() => {
-                    if let Ok((cols, rows)) = terminal_size() {
-                        let mut buf = [0u8; 5];
-                        buf[0] = 0xFF;
-                        buf[1] = (rows >> 8) as u8;
-                        buf[2] = rows as u8;
-                        buf[3] = (cols >> 8) as u8;
-                        buf[4] = cols as u8;
-
-                        writer.write_all(&buf).await?;
-                        writer.flush().await?;
-                    }
+        let (_conn, io) = match (connected, lost_at) {
+            (Ok(connected), _) => connected,
+            (Err(e), None) => return Err(e),
+            (Err(e), Some(lost_at)) => {
+                if lost_at.elapsed() >= resume_window {
+                    print!("Could not reconnect: {}\r\n", e);
+                    return Ok(());
+                }
+                tracing::debug!("Reconnect failed: {}", e);
+                tokio::select! {
+                    _ = sleep(backoff) => {}
+                    _ = SHUTDOWN.cancelled() => return Ok(()),
                 }
+                backoff = (backoff * 2).min(Duration::from_secs(10));
+                continue;
             }
-        }
-
-        Ok::<_, anyhow::Error>(())
-    });
+        };
 
-    // === reader → stdout ===
-    let mut reader_task = tokio::spawn(async move {
-        let mut stdout = tokio::io::stdout();
-        let mut buf = [0u8; 8192];
+        // --- raw mode ---
+        if raw_mode.is_none() {
+            raw_mode = Some(RawMode::enable()?);
+            tracing::info!("Connected.");
+            if attached {
+                print!("Press Ctrl-] to detach.\r\n");
+            }
+        }
+        backoff = Duration::from_secs(1);
+        predictor.reset();
 
-        loop {
-            let n = match reader.read(&mut buf).await? {
-                Some(n) => n,
-                None => break,
-            };
-            if n == 0 {
+        match run_connection(
+            io,
+            &mut stdin_rx,
+            &mut resizes,
+            ping,
+            attached,
+            &mut session_id,
+            &mut osc52,
+            &mut predictor,
+        )
+        .await?
+        {
+            Ended::Closed => break,
+            // Attachments resume by attaching again, own shells by session id.
+            Ended::Lost if !resume_window.is_zero() && (attached || session_id.is_some()) => {
+                print!("\r\nConnection lost, reconnecting…\r\n");
+                let _ = std::io::stdout().flush();
+                lost_at = Some(Instant::now());
+            }
+            Ended::Lost => {
+                print!("\r\nConnection lost\r\n");
                 break;
             }
-            stdout.write_all(&buf[..n]).await?;
-            stdout.flush().await?;
         }
+    }
 
-        Ok::<_, anyhow::Error>(())
-    });
+    Ok(())
+}
+
+/// Pump stdin, resizes and pings to the device and its output to stdout
+/// until the stream closes or breaks.
+#[allow(clippy::too_many_arguments)]
+async fn run_connection(
+    io: StreamIo,
+    stdin_rx: &mut mpsc::UnboundedReceiver<Vec<u8>>,
+    resizes: &mut Resizes,
+    ping: Duration,
+    attached: bool,
+    session_id: &mut Option<String>,
+    osc52: &mut Osc52Filter,
+    predictor: &mut Predictor,
+) -> Result<Ended> {
+    let (mut reader, mut writer) = tokio::io::split(io);
 
-    // === shutdown ===
-    tokio::select! {
-        _ = &mut reader_task => writer_task.abort(),
-        _ = &mut writer_task => {},
-        _ = SHUTDOWN.cancelled() => {},
+    // --- send initial terminal size ---
+    if writer.write_all(&resize_frame()).await.is_err() {
+        return Ok(Ended::Lost);
     }
 
-    Ok(())
+    let mut stdout = tokio::io::stdout();
+    let mut buf = [0u8; 8192];
+    let mut banner: Vec<u8> = Vec::new();
+    let mut pings = interval_at(Instant::now() + ping, ping.max(Duration::from_secs(1)));
+
+    loop {
+        tokio::select! {
+            // ----- device → stdout -----
+            r = reader.read(&mut buf) => {
+                let n = match r {
+                    Ok(0) => return Ok(Ended::Closed),
+                    Ok(n) => n,
+                    Err(_) => return Ok(Ended::Lost),
+                };
+                // The device names the session in its greeting; remember it to resume.
+                if session_id.is_none() && !attached && banner.len() < 512 {
+                    banner.extend_from_slice(&buf[..n]);
+                    *session_id = session_id_from_banner(&banner);
+                }
+                let shown = predictor.on_output(&buf[..n]);
+                stdout.write_all(&osc52.filter(&shown)).await?;
+                stdout.flush().await?;
+            }
+
+            // ----- stdin -----
+            Some(bytes) = stdin_rx.recv() => {
+                if bytes.is_empty() || (attached && bytes.contains(&DETACH_KEY)) {
+                    let _ = writer.shutdown().await;
+                    return Ok(Ended::Closed);
+                }
+                let echo = predictor.on_input(&bytes);
+                if !echo.is_empty() {
+                    stdout.write_all(&echo).await?;
+                    stdout.flush().await?;
+                }
+                if writer.write_all(&bytes).await.is_err() {
+                    return Ok(Ended::Lost);
+                }
+            }
+
+            // ----- resize -----
+            _ = resizes.changed() => {
+                if writer.write_all(&resize_frame()).await.is_err() {
+                    return Ok(Ended::Lost);
+                }
+            }
+
+            // ----- keepalive -----
+            _ = pings.tick(), if !ping.is_zero() => {
+                if writer.write_all(&[TERMINAL_PING]).await.is_err() {
+                    return Ok(Ended::Lost);
+                }
+            }
+
+            _ = SHUTDOWN.cancelled() => return Ok(Ended::Closed),
+        }
+    }
+}
+
+fn resize_frame() -> Vec<u8> {
+    match term::size() {
+        Some((cols, rows)) => {
+            let mut buf = vec![0xFF];
+            buf.extend_from_slice(&rows.to_be_bytes());
+            buf.extend_from_slice(&cols.to_be_bytes());
+            buf
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Extract the id from the device's `Initializing shell (session <id>)..` greeting.
+fn session_id_from_banner(banner: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(banner);
+    let rest = &text[text.find("(session ")? + "(session ".len()..];
+    let id = &rest[..rest.find(')')?];
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())).then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_id_from_banner() {
+        assert_eq!(
+            session_id_from_banner(b"\n\rInitializing shell (session 1a2b3c4d).."),
+            Some("1a2b3c4d".to_string())
+        );
+        assert_eq!(
+            session_id_from_banner(b"\n\rInitializing shell (sess"),
+            None
+        );
+        assert_eq!(session_id_from_banner(b"$ echo (session x y)"), None);
+    }
 }
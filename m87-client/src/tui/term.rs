@@ -0,0 +1,155 @@
+//! The local terminal of interactive commands (shell, exec -t, password
+//! prompts) on unix terminals and Windows consoles. Windows consoles get
+//! virtual terminal input and output, so keys reach the device as the same
+//! escape sequences a unix terminal sends and its output renders as is.
+
+use anyhow::Result;
+use ratatui::crossterm;
+
+/// Raw mode of the terminal until dropped: keys are passed on as typed,
+/// Ctrl-C included, without local echo.
+pub struct RawMode {
+    #[cfg(unix)]
+    _raw: termion::raw::RawTerminal<std::io::Stdout>,
+    #[cfg(windows)]
+    _modes: console::Modes,
+}
+
+impl RawMode {
+    pub fn enable() -> Result<Self> {
+        Ok(Self {
+            #[cfg(unix)]
+            _raw: termion::raw::IntoRawMode::into_raw_mode(std::io::stdout())?,
+            #[cfg(windows)]
+            _modes: console::Modes::set(
+                |input| {
+                    input
+                        & !(console::ENABLE_PROCESSED_INPUT
+                            | console::ENABLE_LINE_INPUT
+                            | console::ENABLE_ECHO_INPUT)
+                        | console::ENABLE_VIRTUAL_TERMINAL_INPUT
+                },
+                |output| output | console::ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+            )?,
+        })
+    }
+}
+
+/// Columns and rows of the terminal.
+pub fn size() -> Option<(u16, u16)> {
+    crossterm::terminal::size().ok()
+}
+
+/// Resizes of the terminal: SIGWINCH on unix. Windows consoles signal
+/// resizes only as input events, which raw reads of stdin skip, so the size
+/// is polled there.
+pub struct Resizes {
+    #[cfg(unix)]
+    signal: tokio::signal::unix::Signal,
+    #[cfg(not(unix))]
+    poll: tokio::time::Interval,
+    #[cfg(not(unix))]
+    last: Option<(u16, u16)>,
+}
+
+#[cfg(not(unix))]
+const RESIZE_POLL: std::time::Duration = std::time::Duration::from_millis(250);
+
+impl Resizes {
+    pub fn new() -> Result<Self> {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+            Ok(Self {
+                signal: signal(SignalKind::window_change())?,
+            })
+        }
+        #[cfg(not(unix))]
+        Ok(Self {
+            poll: tokio::time::interval(RESIZE_POLL),
+            last: size(),
+        })
+    }
+
+    /// Wait for the next resize.
+    pub async fn changed(&mut self) {
+        #[cfg(unix)]
+        self.signal.recv().await;
+        #[cfg(not(unix))]
+        loop {
+            self.poll.tick().await;
+            let now = size();
+            if now != self.last {
+                self.last = now;
+                return;
+            }
+        }
+    }
+}
+
+/// A line typed without echo; `None` at the end of input.
+pub fn read_hidden_line() -> Result<Option<String>> {
+    #[cfg(unix)]
+    {
+        use termion::input::TermRead;
+        Ok(std::io::stdin()
+            .lock()
+            .read_passwd(&mut std::io::stderr())?)
+    }
+    #[cfg(windows)]
+    {
+        let _echo_off =
+            console::Modes::set(|input| input & !console::ENABLE_ECHO_INPUT, |output| output)?;
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+    }
+}
+
+#[cfg(windows)]
+mod console {
+    use crossterm_winapi::{ConsoleMode, Handle};
+
+    pub const ENABLE_PROCESSED_INPUT: u32 = 0x0001;
+    pub const ENABLE_LINE_INPUT: u32 = 0x0002;
+    pub const ENABLE_ECHO_INPUT: u32 = 0x0004;
+    pub const ENABLE_VIRTUAL_TERMINAL_INPUT: u32 = 0x0200;
+    pub const ENABLE_VIRTUAL_TERMINAL_PROCESSING: u32 = 0x0004;
+
+    /// Changed console input and output modes, restored on drop.
+    pub struct Modes {
+        input: ConsoleMode,
+        input_was: u32,
+        output: ConsoleMode,
+        output_was: u32,
+    }
+
+    impl Modes {
+        pub fn set(
+            input: impl FnOnce(u32) -> u32,
+            output: impl FnOnce(u32) -> u32,
+        ) -> std::io::Result<Self> {
+            let input_mode = ConsoleMode::from(Handle::current_in_handle()?);
+            let output_mode = ConsoleMode::from(Handle::current_out_handle()?);
+            let modes = Self {
+                input_was: input_mode.mode()?,
+                output_was: output_mode.mode()?,
+                input: input_mode,
+                output: output_mode,
+            };
+            // on error, dropping `modes` restores what was already changed
+            modes.input.set_mode(input(modes.input_was))?;
+            modes.output.set_mode(output(modes.output_was))?;
+            Ok(modes)
+        }
+    }
+
+    impl Drop for Modes {
+        fn drop(&mut self) {
+            let _ = self.input.set_mode(self.input_was);
+            let _ = self.output.set_mode(self.output_was);
+        }
+    }
+}
@@ -3,31 +3,107 @@ use self_update::cargo_crate_version;
 use self_update::version::bump_is_greater;
 use serde::Deserialize;
 use std::fs::File;
-use tracing::{error, info};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tracing::{error, info, warn};
+
+use crate::util::proxy;
+
+pub mod slot;
 
 const GITHUB_LATEST_RELEASE_URL: &str = "https://api.github.com/repos/make87/m87/releases/latest";
+const GITHUB_RELEASE_BY_TAG_URL: &str = "https://api.github.com/repos/make87/m87/releases/tags";
 
-fn arch_bin_name() -> &'static str {
-    #[cfg(target_arch = "x86_64")]
-    {
-        "m87-x86_64-unknown-linux-musl"
+/// Lines of release notes shown after an update
+const MAX_NOTE_LINES: usize = 12;
+
+#[cfg(target_arch = "x86_64")]
+const ARCH: &str = "x86_64";
+
+#[cfg(target_arch = "aarch64")]
+const ARCH: &str = "aarch64";
+
+#[cfg(target_arch = "riscv64")]
+const ARCH: &str = "riscv64gc";
+
+/// Name of the binary inside release tarballs
+const BINARY_NAME: &str = "m87";
+
+/// C library of the host. The m87 binary itself is static, so this is
+/// sniffed from the system rather than taken from the build target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Libc {
+    Musl,
+    Gnu,
+}
+
+fn host_libc() -> Libc {
+    // Alpine and other musl systems ship /lib/ld-musl-<arch>.so.1
+    let musl_loader = std::fs::read_dir("/lib").is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|e| e.file_name().to_string_lossy().starts_with("ld-musl-"))
+    });
+    if musl_loader {
+        return Libc::Musl;
     }
+    match Command::new("ldd").arg("--version").output() {
+        // musl's ldd prints its banner to stderr and exits non-zero
+        Ok(out) => {
+            let banner = format!(
+                "{}{}",
+                String::from_utf8_lossy(&out.stdout),
+                String::from_utf8_lossy(&out.stderr)
+            );
+            if banner.to_lowercase().contains("musl") {
+                Libc::Musl
+            } else {
+                Libc::Gnu
+            }
+        }
+        // no dynamic loader tooling at all: only static binaries are safe
+        Err(_) => Libc::Musl,
+    }
+}
 
-    #[cfg(target_arch = "aarch64")]
-    {
-        "m87-aarch64-unknown-linux-musl"
+/// Release targets this host can run, best first. Static musl builds run
+/// everywhere; glibc builds only where glibc is installed.
+fn target_triples(libc: Libc) -> Vec<String> {
+    if cfg!(target_os = "macos") {
+        return vec![format!("{ARCH}-apple-darwin")];
+    }
+    if cfg!(windows) {
+        return vec![format!("{ARCH}-pc-windows-msvc")];
     }
+    let mut triples = vec![format!("{ARCH}-unknown-linux-musl")];
+    if libc == Libc::Gnu {
+        triples.push(format!("{ARCH}-unknown-linux-gnu"));
+    }
+    triples
+}
 
-    // #[cfg(target_arch = "riscv64")]
-    // {
-    //     "m87-riscv64gc-unknown-linux-musl"
-    // }
+/// Release asset names this host can install, best first: plain binaries,
+/// then `.tar.gz` archives for targets published without one.
+fn asset_candidates(libc: Libc) -> Vec<String> {
+    let triples = target_triples(libc);
+    let binaries = triples
+        .iter()
+        .map(|t| format!("{BINARY_NAME}-{t}{}", std::env::consts::EXE_SUFFIX));
+    let tarballs = triples.iter().map(|t| format!("{BINARY_NAME}-{t}.tar.gz"));
+    binaries.chain(tarballs).collect()
 }
 
 #[derive(Debug, Deserialize)]
 struct GitHubRelease {
     tag_name: String,
+    #[serde(default)]
     assets: Vec<GitHubAsset>,
+    /// Release notes, markdown
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    html_url: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -41,10 +117,10 @@ pub async fn update(interactive: bool) -> Result<bool> {
         println!("Checking for updates...");
     }
     let current_version = cargo_crate_version!();
-    let asset_name = arch_bin_name();
+    let candidates = asset_candidates(host_libc());
 
     // Fetch the "latest" release from GitHub (the one explicitly tagged as latest)
-    let client = reqwest::Client::new();
+    let client = proxy::client()?;
     let release: GitHubRelease = client
         .get(GITHUB_LATEST_RELEASE_URL)
         .header("User-Agent", "m87-client")
@@ -66,11 +142,13 @@ pub async fn update(interactive: bool) -> Result<bool> {
     }
 
     // Find our asset in the release
-    let asset = release
-        .assets
-        .iter()
-        .find(|a| a.name == asset_name)
-        .ok_or_else(|| anyhow!("Asset '{}' not found in release", asset_name))?;
+    let asset = select_asset(&release.assets, &candidates).ok_or_else(|| {
+        anyhow!(
+            "No asset for this platform in release (looked for {})",
+            candidates.join(", ")
+        )
+    })?;
+    let asset_name = asset.name.as_str();
 
     if interactive {
         println!("New release found: v{} → v{}", current_version, new_version);
@@ -79,14 +157,30 @@ pub async fn update(interactive: bool) -> Result<bool> {
 
     // Create temp directory for download
     let tmp_dir = self_update::TempDir::new()?;
-    let tmp_path = tmp_dir.path().join(asset_name);
+    let download_path = tmp_dir.path().join(asset_name);
 
-    // Download the raw binary directly (no archive extraction needed)
-    let tmp_file = File::create(&tmp_path)?;
-    self_update::Download::from_url(&asset.browser_download_url)
-        .set_header(reqwest::header::ACCEPT, "application/octet-stream".parse()?)
-        .show_progress(interactive)
-        .download_to(tmp_file)?;
+    download(
+        &client,
+        &asset.browser_download_url,
+        &download_path,
+        interactive,
+    )
+    .await?;
+
+    // Raw binaries are used as is, tarballs are unpacked next to the download
+    let tmp_path = if asset_name.ends_with(".tar.gz") {
+        let unpack_dir = tmp_dir.path().join("unpacked");
+        std::fs::create_dir_all(&unpack_dir)?;
+        self_update::Extract::from_source(&download_path)
+            .archive(self_update::ArchiveKind::Tar(Some(
+                self_update::Compression::Gz,
+            )))
+            .extract_into(&unpack_dir)?;
+        find_binary(&unpack_dir)
+            .ok_or_else(|| anyhow!("'{}' not found in {}", BINARY_NAME, asset_name))?
+    } else {
+        download_path
+    };
 
     // Make executable on Unix
     #[cfg(unix)]
@@ -95,18 +189,144 @@ pub async fn update(interactive: bool) -> Result<bool> {
         std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(0o755))?;
     }
 
+    // Keep the current binary in the previous slot so the runtime can revert
+    let kept_previous = match slot::keep_previous() {
+        Ok(_) => true,
+        Err(e) => {
+            warn!(
+                "Could not keep previous binary, automatic revert disabled: {:?}",
+                e
+            );
+            false
+        }
+    };
+
     // Replace the current binary
     if interactive {
         println!("Replacing binary...");
     }
     self_update::self_replace::self_replace(&tmp_path)?;
 
+    if kept_previous && let Err(e) = slot::mark_pending(current_version, new_version) {
+        warn!("Failed to record pending upgrade: {:?}", e);
+    }
+
     if interactive {
         println!("Updated from v{} → v{}", current_version, new_version);
+        print_release_notes(&release);
+        crate::notices::mark_notes_shown(new_version);
     }
     Ok(true)
 }
 
+/// Print the concise notes of the release tagged `v<version>`, if published.
+pub async fn show_release_notes(version: &str) -> Result<()> {
+    let release: GitHubRelease = proxy::client()?
+        .get(format!("{}/v{}", GITHUB_RELEASE_BY_TAG_URL, version))
+        .header("User-Agent", "m87-client")
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    print_release_notes(&release);
+    Ok(())
+}
+
+fn print_release_notes(release: &GitHubRelease) {
+    let lines = release
+        .body
+        .as_deref()
+        .map(|body| concise_notes(body, MAX_NOTE_LINES))
+        .unwrap_or_default();
+    if lines.is_empty() {
+        return;
+    }
+    eprintln!("\nWhat's new in m87 {}:", release.tag_name);
+    for line in lines {
+        eprintln!("{}", line);
+    }
+    if let Some(url) = &release.html_url {
+        eprintln!("Full notes: {}", url);
+    }
+}
+
+/// Headings and bullet points of markdown release notes, without links to
+/// authors and pull requests, at most `max` lines.
+fn concise_notes(body: &str, max: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in body.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("<!--") || line.starts_with("**Full Changelog") {
+            continue;
+        }
+        let line = line.replace("**", "");
+        let line = match line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+            // "Fix x by @someone in https://github.com/.../pull/1"
+            Some(item) => format!("  • {}", item.split(" by @").next().unwrap_or(item)),
+            None => line.trim_start_matches('#').trim().to_string(),
+        };
+        if lines.len() == max {
+            lines.push("  …".to_string());
+            break;
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// Download `url` to `path`, through the proxy if one is configured.
+async fn download(
+    client: &reqwest::Client,
+    url: &str,
+    path: &Path,
+    interactive: bool,
+) -> Result<()> {
+    let mut res = client
+        .get(url)
+        .header("User-Agent", "m87-client")
+        .header(reqwest::header::ACCEPT, "application/octet-stream")
+        .send()
+        .await?
+        .error_for_status()?;
+    let total = res.content_length().filter(|t| *t > 0);
+    let mut file = File::create(path)?;
+    let mut done = 0u64;
+    while let Some(chunk) = res.chunk().await? {
+        file.write_all(&chunk)?;
+        done += chunk.len() as u64;
+        if interactive && let Some(total) = total {
+            print!("\r{:>3}%", done * 100 / total);
+            let _ = std::io::stdout().flush();
+        }
+    }
+    if interactive {
+        println!();
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// First candidate published in the release.
+fn select_asset<'a>(assets: &'a [GitHubAsset], candidates: &[String]) -> Option<&'a GitHubAsset> {
+    candidates
+        .iter()
+        .find_map(|name| assets.iter().find(|a| &a.name == name))
+}
+
+/// The binary at the top of an unpacked tarball or in its single directory.
+fn find_binary(dir: &Path) -> Option<PathBuf> {
+    let top = dir.join(BINARY_NAME);
+    if top.is_file() {
+        return Some(top);
+    }
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|e| e.path().join(BINARY_NAME))
+        .find(|p| p.is_file())
+}
+
 /// Helper for daemon use — silently apply and exit if updated.
 pub async fn daemon_check_and_update() -> Result<()> {
     match update(false).await {
@@ -125,25 +345,74 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_arch_bin_name_format() {
-        let name = arch_bin_name();
-        assert!(name.starts_with("m87-"));
-        assert!(name.contains("-unknown-linux-musl"));
+    fn test_concise_notes() {
+        let body = "## What's Changed\r\n* **cli**: add `m87 init` by @dev in https://github.com/make87/m87/pull/7\r\n\r\n**Full Changelog**: https://github.com/make87/m87/compare/v1...v2";
+        assert_eq!(
+            concise_notes(body, 12),
+            vec!["What's Changed", "  • cli: add `m87 init`"]
+        );
+        let long = "- a\n- b\n- c";
+        assert_eq!(concise_notes(long, 2), vec!["  • a", "  • b", "  …"]);
+    }
+
+    #[test]
+    fn test_asset_candidates_format() {
+        let names = asset_candidates(Libc::Gnu);
+        assert!(names.iter().all(|n| n.starts_with("m87-")));
+        if cfg!(target_os = "macos") {
+            assert!(names[0].ends_with("-apple-darwin"));
+        } else if cfg!(windows) {
+            assert!(names[0].ends_with("-pc-windows-msvc.exe"));
+        } else {
+            assert!(names[0].ends_with("-unknown-linux-musl"));
+        }
+        assert!(names.last().unwrap().ends_with(".tar.gz"));
     }
 
     #[test]
-    fn test_arch_bin_name_known_arch() {
-        let name = arch_bin_name();
-        let known_archs = [
-            "m87-x86_64-unknown-linux-musl",
-            "m87-aarch64-unknown-linux-musl",
-            "m87-riscv64gc-unknown-linux-musl",
+    #[cfg(target_os = "linux")]
+    fn test_asset_candidates_by_libc() {
+        // glibc builds are only offered where glibc is installed
+        let musl = asset_candidates(Libc::Musl);
+        assert_eq!(musl.len(), 2);
+        assert!(musl.iter().all(|n| !n.contains("-gnu")));
+
+        let gnu = asset_candidates(Libc::Gnu);
+        assert_eq!(gnu[0], format!("m87-{ARCH}-unknown-linux-musl"));
+        assert_eq!(gnu[1], format!("m87-{ARCH}-unknown-linux-gnu"));
+        assert_eq!(gnu[2], format!("m87-{ARCH}-unknown-linux-musl.tar.gz"));
+    }
+
+    #[test]
+    fn test_select_asset_prefers_binary_over_tarball() {
+        let asset = |name: &str| GitHubAsset {
+            name: name.to_string(),
+            browser_download_url: format!("https://example.com/{name}"),
+        };
+        let candidates = vec![
+            "m87-riscv64gc-unknown-linux-musl".to_string(),
+            "m87-riscv64gc-unknown-linux-musl.tar.gz".to_string(),
         ];
-        assert!(
-            known_archs.contains(&name),
-            "Unknown architecture: {}",
-            name
+
+        let tarball_only = [
+            asset("m87-x86_64-unknown-linux-musl"),
+            asset("m87-riscv64gc-unknown-linux-musl.tar.gz"),
+        ];
+        assert_eq!(
+            select_asset(&tarball_only, &candidates).unwrap().name,
+            "m87-riscv64gc-unknown-linux-musl.tar.gz"
         );
+
+        let both = [
+            asset("m87-riscv64gc-unknown-linux-musl.tar.gz"),
+            asset("m87-riscv64gc-unknown-linux-musl"),
+        ];
+        assert_eq!(
+            select_asset(&both, &candidates).unwrap().name,
+            "m87-riscv64gc-unknown-linux-musl"
+        );
+
+        assert!(select_asset(&tarball_only[..1], &candidates).is_none());
     }
 
     #[test]
@@ -217,12 +486,11 @@ mod tests {
         assert!(!release.assets.is_empty(), "Release should have assets");
 
         // Check that our architecture's binary exists
-        let asset_name = arch_bin_name();
-        let our_asset = release.assets.iter().find(|a| a.name == asset_name);
+        let candidates = asset_candidates(host_libc());
         assert!(
-            our_asset.is_some(),
+            select_asset(&release.assets, &candidates).is_some(),
             "Release should contain asset for current arch: {}",
-            asset_name
+            candidates.join(", ")
         );
     }
 }
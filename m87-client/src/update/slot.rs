@@ -0,0 +1,299 @@
+//! Blue/green slots for the runtime binary.
+//!
+//! A self-update keeps the replaced binary next to the new one (`<exe>.prev`)
+//! and records a pending upgrade. The next runtime start watches the upgrade:
+//! once the control tunnel is up the upgrade is confirmed, otherwise (or when
+//! the new binary keeps crashing on startup) the previous binary is restored
+//! and the process exits so systemd restarts the old version. The old version
+//! then reports the failed upgrade with its first heartbeat.
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{SecondsFormat, Utc};
+use m87_shared::device::FailedUpgrade;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::config::Config;
+
+const PREVIOUS_SUFFIX: &str = ".prev";
+/// Starts of the new binary before it is considered crash-looping. Stays
+/// below systemd's `StartLimitBurst` so the revert happens before systemd
+/// gives up on the unit.
+const MAX_START_ATTEMPTS: u32 = 3;
+/// Pending upgrades older than this are ignored, e.g. after `m87 update` on a
+/// machine that only starts the runtime much later.
+const MAX_PENDING_AGE: Duration = Duration::from_secs(24 * 3600);
+
+/// Cancelled once the control tunnel received its first heartbeat response.
+static TUNNEL_CONFIRMED: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct UpgradeState {
+    from_version: String,
+    to_version: String,
+    /// Unix seconds when the new binary was installed
+    started_at: u64,
+    #[serde(default)]
+    attempts: u32,
+    #[serde(default)]
+    failed: Option<FailedUpgrade>,
+}
+
+#[derive(Debug, PartialEq)]
+enum StartupAction {
+    /// No upgrade in flight
+    Nothing,
+    /// Drop a stale or foreign state file
+    Clear,
+    /// The new binary is running: count the start and watch the tunnel
+    Watch,
+    /// The new binary keeps crashing: revert right away
+    Revert(String),
+    /// The previous binary is running again: report the failure
+    Report,
+}
+
+fn decide(state: Option<&UpgradeState>, current_version: &str, now: u64) -> StartupAction {
+    let Some(state) = state else {
+        return StartupAction::Nothing;
+    };
+    if state.failed.is_some() {
+        return StartupAction::Report;
+    }
+    if state.to_version != current_version
+        || now.saturating_sub(state.started_at) > MAX_PENDING_AGE.as_secs()
+    {
+        return StartupAction::Clear;
+    }
+    if state.attempts >= MAX_START_ATTEMPTS {
+        return StartupAction::Revert(format!(
+            "runtime v{} restarted {} times without a control tunnel",
+            state.to_version, state.attempts
+        ));
+    }
+    StartupAction::Watch
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn state_path() -> Result<PathBuf> {
+    Ok(Config::data_dir()?.join("upgrade.json"))
+}
+
+fn load_state() -> Option<UpgradeState> {
+    let path = state_path().ok()?;
+    let contents = std::fs::read_to_string(path).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+fn save_state(state: &UpgradeState) -> Result<()> {
+    let path = state_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).context("Failed to create data directory")?;
+    }
+    std::fs::write(&path, serde_json::to_vec_pretty(state)?)
+        .context("Failed to write upgrade state")
+}
+
+fn clear_state() {
+    if let Ok(path) = state_path() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+fn previous_slot(exe: &Path) -> PathBuf {
+    let mut name = exe.as_os_str().to_owned();
+    name.push(PREVIOUS_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Copy the running binary into the previous slot before it gets replaced.
+pub fn keep_previous() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Failed to resolve current executable")?;
+    let prev = previous_slot(&exe);
+    std::fs::copy(&exe, &prev)
+        .with_context(|| format!("Failed to copy {} to {}", exe.display(), prev.display()))?;
+    Ok(prev)
+}
+
+/// Record that `to_version` was just installed over `from_version`.
+pub fn mark_pending(from_version: &str, to_version: &str) -> Result<()> {
+    save_state(&UpgradeState {
+        from_version: from_version.to_string(),
+        to_version: to_version.to_string(),
+        started_at: unix_now(),
+        attempts: 0,
+        failed: None,
+    })
+}
+
+/// Called by the control tunnel once the server answered a heartbeat.
+pub fn confirm_tunnel() {
+    TUNNEL_CONFIRMED.cancel();
+}
+
+/// The failed upgrade to report with the next heartbeat, if any.
+pub fn failed_upgrade() -> Option<FailedUpgrade> {
+    load_state().and_then(|s| s.failed)
+}
+
+/// Forget the failed upgrade once the server has been told about it.
+pub fn clear_failed_upgrade() {
+    if failed_upgrade().is_some() {
+        clear_state();
+    }
+}
+
+/// Restore the previous binary, record the failure and exit so the service
+/// manager restarts the old version. Returns only if there is nothing to
+/// revert to, in which case the new binary keeps running.
+fn revert(mut state: UpgradeState, reason: String) {
+    error!("Reverting runtime upgrade: {}", reason);
+
+    let restored = std::env::current_exe()
+        .context("Failed to resolve current executable")
+        .and_then(|exe| {
+            let prev = previous_slot(&exe);
+            if !prev.exists() {
+                return Err(anyhow!("previous binary {} not found", prev.display()));
+            }
+            self_update::self_replace::self_replace(&prev)
+                .context("Failed to restore previous binary")
+        });
+    if let Err(e) = restored {
+        error!("Revert failed, keeping v{}: {:?}", state.to_version, e);
+        clear_state();
+        return;
+    }
+
+    state.failed = Some(FailedUpgrade {
+        from_version: state.from_version.clone(),
+        to_version: state.to_version.clone(),
+        reason,
+        failed_at: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    });
+    if let Err(e) = save_state(&state) {
+        warn!("Failed to record failed upgrade: {:?}", e);
+    }
+    info!(
+        "Restored v{}; exiting for restart via systemd",
+        state.from_version
+    );
+    std::process::exit(1); // non-zero so systemd restarts "on-failure"
+}
+
+/// Inspect the upgrade state on runtime start. Reverts immediately for a
+/// crash-looping binary; otherwise spawns the tunnel watchdog when an upgrade
+/// is pending.
+pub fn check_on_startup(confirm_timeout: Duration) {
+    let current_version = env!("CARGO_PKG_VERSION");
+    let state = load_state();
+
+    match decide(state.as_ref(), current_version, unix_now()) {
+        StartupAction::Nothing | StartupAction::Report => {}
+        StartupAction::Clear => clear_state(),
+        StartupAction::Revert(reason) => revert(state.unwrap(), reason),
+        StartupAction::Watch => {
+            let mut state = state.unwrap();
+            if confirm_timeout.is_zero() {
+                clear_state();
+                return;
+            }
+            state.attempts += 1;
+            if let Err(e) = save_state(&state) {
+                warn!("Failed to update upgrade state: {:?}", e);
+            }
+            info!(
+                "Watching upgrade v{} -> v{} (start {}/{}), reverting after {}s without a control tunnel",
+                state.from_version,
+                state.to_version,
+                state.attempts,
+                MAX_START_ATTEMPTS,
+                confirm_timeout.as_secs()
+            );
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = TUNNEL_CONFIRMED.cancelled() => {
+                        info!("Upgrade to v{} confirmed", state.to_version);
+                        clear_state();
+                    }
+                    _ = tokio::time::sleep(confirm_timeout) => {
+                        let reason = format!(
+                            "no control tunnel within {}s after upgrading to v{}",
+                            confirm_timeout.as_secs(),
+                            state.to_version
+                        );
+                        revert(state, reason);
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(attempts: u32) -> UpgradeState {
+        UpgradeState {
+            from_version: "1.0.0".to_string(),
+            to_version: "1.1.0".to_string(),
+            started_at: 1_000,
+            attempts,
+            failed: None,
+        }
+    }
+
+    #[test]
+    fn test_decide() {
+        assert_eq!(decide(None, "1.1.0", 1_000), StartupAction::Nothing);
+        assert_eq!(
+            decide(Some(&pending(0)), "1.1.0", 1_060),
+            StartupAction::Watch
+        );
+        assert!(matches!(
+            decide(Some(&pending(MAX_START_ATTEMPTS)), "1.1.0", 1_060),
+            StartupAction::Revert(_)
+        ));
+        // reverted binary or manual reinstall of another version
+        assert_eq!(
+            decide(Some(&pending(0)), "1.0.0", 1_060),
+            StartupAction::Clear
+        );
+        // stale state
+        assert_eq!(
+            decide(Some(&pending(0)), "1.1.0", 1_000 + 2 * 24 * 3600),
+            StartupAction::Clear
+        );
+    }
+
+    #[test]
+    fn test_decide_reports_failure() {
+        let mut state = pending(1);
+        state.failed = Some(FailedUpgrade {
+            from_version: "1.0.0".to_string(),
+            to_version: "1.1.0".to_string(),
+            reason: "no control tunnel".to_string(),
+            failed_at: "2026-01-01T00:00:00Z".to_string(),
+        });
+        assert_eq!(decide(Some(&state), "1.0.0", 1_060), StartupAction::Report);
+    }
+
+    #[test]
+    fn test_previous_slot() {
+        assert_eq!(
+            previous_slot(Path::new("/usr/local/bin/m87")),
+            PathBuf::from("/usr/local/bin/m87.prev")
+        );
+    }
+}
@@ -0,0 +1,138 @@
+//! OSC 52 clipboard sequences (`ESC ] 52 ; <selection> ; <base64 | ?> BEL|ST`)
+//! let programs on the device set the operator's local clipboard, e.g. when
+//! yanking in vim or copying in tmux. A `?` payload asks the terminal to reply
+//! with the clipboard contents, which would send it to the device.
+
+use serde::{Deserialize, Serialize};
+
+/// Which OSC 52 requests are passed through to the local terminal.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ClipboardPolicy {
+    /// Strip all OSC 52 sequences.
+    Off,
+    /// Programs may set the clipboard but not read it.
+    #[default]
+    Write,
+    /// Pass OSC 52 through unchanged, including clipboard reads.
+    ReadWrite,
+}
+
+const OSC52: &[u8] = b"\x1b]52;";
+/// Longest sequence held back while waiting for its terminator; larger ones
+/// are passed on so a missing terminator cannot stall the output.
+const MAX_SEQUENCE: usize = 1024 * 1024;
+
+/// Applies a [`ClipboardPolicy`] to a terminal output stream. Sequences split
+/// across chunks are held back until complete.
+pub struct Osc52Filter {
+    policy: ClipboardPolicy,
+    pending: Vec<u8>,
+}
+
+impl Osc52Filter {
+    pub fn new(policy: ClipboardPolicy) -> Self {
+        Self {
+            policy,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn filter(&mut self, chunk: &[u8]) -> Vec<u8> {
+        if self.policy == ClipboardPolicy::ReadWrite {
+            return chunk.to_vec();
+        }
+
+        let mut data = std::mem::take(&mut self.pending);
+        data.extend_from_slice(chunk);
+
+        let mut out = Vec::with_capacity(data.len());
+        let mut i = 0;
+        while i < data.len() {
+            let rest = &data[i..];
+            if rest[0] == 0x1b {
+                // possible start of a sequence at the end of the chunk
+                if rest.len() < OSC52.len() && OSC52.starts_with(rest) {
+                    self.pending = rest.to_vec();
+                    break;
+                }
+                if rest.starts_with(OSC52) {
+                    match sequence_end(rest) {
+                        Some(end) => {
+                            if self.allows(&rest[OSC52.len()..end]) {
+                                out.extend_from_slice(&rest[..end]);
+                            }
+                            i += end;
+                            continue;
+                        }
+                        None if rest.len() < MAX_SEQUENCE => {
+                            self.pending = rest.to_vec();
+                            break;
+                        }
+                        None => {}
+                    }
+                }
+            }
+            out.push(rest[0]);
+            i += 1;
+        }
+        out
+    }
+
+    /// `body` is `<selection> ; <payload>` followed by the terminator.
+    fn allows(&self, body: &[u8]) -> bool {
+        match self.policy {
+            ClipboardPolicy::Off => false,
+            ClipboardPolicy::ReadWrite => true,
+            ClipboardPolicy::Write => {
+                let payload = body.splitn(2, |&b| b == b';').nth(1).unwrap_or_default();
+                !payload.starts_with(b"?")
+            }
+        }
+    }
+}
+
+/// Index after the BEL or ST terminating the sequence at the start of `seq`.
+fn sequence_end(seq: &[u8]) -> Option<usize> {
+    let body = &seq[OSC52.len()..];
+    let pos = body
+        .iter()
+        .enumerate()
+        .find(|&(i, &b)| b == 0x07 || (b == 0x1b && body.get(i + 1) == Some(&b'\\')))
+        .map(|(i, _)| i)?;
+    let terminator = if body[pos] == 0x07 { 1 } else { 2 };
+    Some(OSC52.len() + pos + terminator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SET: &[u8] = b"\x1b]52;c;aGVsbG8=\x07";
+    const QUERY: &[u8] = b"\x1b]52;c;?\x1b\\";
+
+    #[test]
+    fn test_write_policy_drops_queries_only() {
+        let mut filter = Osc52Filter::new(ClipboardPolicy::Write);
+        let input = [b"ab".as_slice(), SET, QUERY, b"\x1b[?2004hcd"].concat();
+        assert_eq!(
+            filter.filter(&input),
+            [b"ab".as_slice(), SET, b"\x1b[?2004hcd"].concat()
+        );
+    }
+
+    #[test]
+    fn test_off_policy_strips_split_sequence() {
+        let mut filter = Osc52Filter::new(ClipboardPolicy::Off);
+        assert_eq!(filter.filter(b"x\x1b]5"), b"x");
+        assert_eq!(filter.filter(b"2;c;aGVs"), b"");
+        assert_eq!(filter.filter(b"bG8=\x07y"), b"y");
+        assert_eq!(filter.filter(b"\x1b[0m"), b"\x1b[0m");
+    }
+
+    #[test]
+    fn test_read_write_passes_everything() {
+        let mut filter = Osc52Filter::new(ClipboardPolicy::ReadWrite);
+        assert_eq!(filter.filter(QUERY), QUERY);
+    }
+}
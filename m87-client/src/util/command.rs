@@ -54,6 +54,11 @@ pub fn binary_exists(name: &str) -> bool {
     false
 }
 
+/// Quote for a POSIX shell.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
 pub fn build_command(cmd: &CommandSpec) -> Result<Command> {
     match cmd {
         CommandSpec::Argv(argv) => {
@@ -167,6 +172,10 @@ async fn read_to_tail<R: AsyncRead + Unpin>(
     Ok(out)
 }
 
+/// Set for the commands of a run, and so for the processes they start, to
+/// tell which run a process belongs to
+pub const RUN_ID_ENV: &str = "M87_RUN_ID";
+
 pub async fn run_command(
     run_id: &str,
     wd: &Path,
@@ -180,6 +189,7 @@ pub async fn run_command(
     for (k, v) in env {
         c.env(k, v);
     }
+    c.env(RUN_ID_ENV, run_id);
 
     c.stdout(Stdio::piped());
     c.stderr(Stdio::piped());
@@ -57,6 +57,56 @@ pub fn try_cache(name: &str) -> Result<Vec<CachedDevice>> {
     Ok(valid)
 }
 
+/// Fresh cached devices whose name matches `query` as a prefix, substring
+/// or, failing both, a subsequence (see [`match_names`]).
+pub fn fuzzy_candidates(query: &str) -> Result<Vec<CachedDevice>> {
+    let cache = load_cache()?;
+    let names: Vec<&str> = cache.keys().map(String::as_str).collect();
+
+    let mut out = Vec::new();
+    for name in match_names(query, &names) {
+        out.extend(try_cache(name)?);
+    }
+    Ok(out)
+}
+
+/// Case-insensitive name matching in decreasing order of strictness: if any
+/// name starts with `query` only those are returned, otherwise names
+/// containing it, otherwise names containing its characters in order.
+pub fn match_names<'a>(query: &str, names: &[&'a str]) -> Vec<&'a str> {
+    let query = query.to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    let lowered: Vec<(String, &'a str)> = names.iter().map(|n| (n.to_lowercase(), *n)).collect();
+
+    let is_subsequence = |name: &str| {
+        let mut chars = name.chars();
+        query.chars().all(|q| chars.any(|c| c == q))
+    };
+
+    let passes: [&dyn Fn(&str) -> bool; 3] = [
+        &|n| n.starts_with(&query),
+        &|n| n.contains(&query),
+        &is_subsequence,
+    ];
+
+    for pass in passes {
+        let mut matched: Vec<&'a str> = lowered
+            .iter()
+            .filter(|(lower, _)| pass(lower))
+            .map(|(_, name)| *name)
+            .collect();
+        if !matched.is_empty() {
+            matched.sort_unstable();
+            return matched;
+        }
+    }
+
+    Vec::new()
+}
+
 pub fn update_cache(device: &PublicDevice, server_url: &str) -> Result<()> {
     let mut cache = load_cache()?;
     let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
@@ -120,6 +170,22 @@ pub fn update_cache_bulk(devices: &[PublicDevice], server_url: &str) -> Result<(
     Ok(())
 }
 
+/// Drop every cached entry for the device with this id, e.g. after a rename.
+pub fn invalidate(id: &str) -> Result<()> {
+    let mut cache = load_cache()?;
+    for list in cache.values_mut() {
+        list.retain(|d| d.id != id);
+    }
+    cache.retain(|_, list| !list.is_empty());
+
+    let path = cache_path()?;
+    if !path.exists() {
+        return Ok(());
+    }
+    let data = serde_json::to_vec_pretty(&cache)?;
+    write_atomic(&path, &data)
+}
+
 fn write_atomic(path: &PathBuf, data: &[u8]) -> Result<()> {
     let tmp = path.with_extension("tmp");
     fs::write(&tmp, data)?;
@@ -158,6 +224,28 @@ mod tests {
         assert_eq!(deserialized.updated_at, 1700000000);
     }
 
+    #[test]
+    fn test_match_names() {
+        let names = [
+            "jetson-orin-nano-lab3",
+            "jetson-orin-nano-lab4",
+            "pi-kitchen",
+            "Gateway-01",
+        ];
+
+        assert_eq!(
+            match_names("jetson", &names),
+            vec!["jetson-orin-nano-lab3", "jetson-orin-nano-lab4"]
+        );
+        assert_eq!(match_names("lab3", &names), vec!["jetson-orin-nano-lab3"]);
+        assert_eq!(match_names("gate", &names), vec!["Gateway-01"]);
+        assert_eq!(match_names("pkit", &names), vec!["pi-kitchen"]);
+        // prefix matches win over looser substring matches
+        assert_eq!(match_names("pi", &names), vec!["pi-kitchen"]);
+        assert!(match_names("xyz", &names).is_empty());
+        assert!(match_names("", &names).is_empty());
+    }
+
     #[test]
     fn test_cache_path_structure() {
         let path = cache_path().unwrap();
@@ -0,0 +1,99 @@
+//! Free-space guard: deployments, image pulls and SFTP uploads fail early when
+//! they would eat into the reserve (`disk_limits.reserve_mb`) instead of
+//! filling the filesystem and leaving the device unable to boot or update.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::tui::fs::human_size;
+
+const MB: u64 = 1024 * 1024;
+
+/// A write that would leave less than the reserve free.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsufficientSpace {
+    pub path: PathBuf,
+    pub available: u64,
+    pub needed: u64,
+    pub reserve: u64,
+}
+
+impl fmt::Display for InsufficientSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "insufficient disk space on {}: {} available",
+            self.path.display(),
+            human_size(self.available)
+        )?;
+        if self.needed > 0 {
+            write!(f, ", {} needed", human_size(self.needed))?;
+        }
+        write!(f, " and {} reserved", human_size(self.reserve))
+    }
+}
+
+impl std::error::Error for InsufficientSpace {}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`,
+/// or on its nearest existing ancestor. None when it cannot be determined.
+#[cfg(unix)]
+pub fn available_bytes(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let st = nix::sys::statvfs::statvfs(existing).ok()?;
+    Some(st.blocks_available() as u64 * st.fragment_size() as u64)
+}
+
+#[cfg(not(unix))]
+pub fn available_bytes(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Configured reserve in bytes. 0 disables the guard.
+pub fn reserve_bytes() -> u64 {
+    Config::load()
+        .map(|c| c.disk_limits)
+        .unwrap_or_default()
+        .reserve_mb
+        * MB
+}
+
+/// Fail if writing `needed` bytes under `path` would leave less than `reserve` free.
+pub fn ensure_space(path: &Path, needed: u64, reserve: u64) -> Result<(), InsufficientSpace> {
+    if reserve == 0 {
+        return Ok(());
+    }
+    let Some(available) = available_bytes(path) else {
+        return Ok(());
+    };
+    if available >= needed.saturating_add(reserve) {
+        return Ok(());
+    }
+    Err(InsufficientSpace {
+        path: path.to_path_buf(),
+        available,
+        needed,
+        reserve,
+    })
+}
+
+/// [`ensure_space`] with the configured reserve.
+pub fn check(path: &Path, needed: u64) -> Result<(), InsufficientSpace> {
+    ensure_space(path, needed, reserve_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_space() {
+        let dir = std::env::temp_dir();
+        assert!(ensure_space(&dir, u64::MAX, 0).is_ok());
+        assert!(ensure_space(&dir.join("missing/file"), 0, 1).is_ok());
+        let err = ensure_space(&dir, 1, u64::MAX / 2).unwrap_err();
+        assert_eq!(err.needed, 1);
+        assert!(err.to_string().starts_with("insufficient disk space on "));
+    }
+}
@@ -1,9 +1,15 @@
 use chrono::{SecondsFormat, Utc};
+use m87_shared::log_format::{LogLevel, LogRecord};
 
 const RESET: &str = "\x1b[0m";
 const GREY: &str = "\x1b[90m";
 const CYAN: &str = "\x1b[36m";
 const WHITE: &str = "\x1b[37m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const GREEN: &str = "\x1b[32m";
+const BLUE: &str = "\x1b[34m";
+const MAGENTA: &str = "\x1b[35m";
 
 pub fn format_log(source: &str, message: &str, ansi: bool) -> String {
     let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true);
@@ -22,6 +28,58 @@ pub fn format_log(source: &str, message: &str, ansi: bool) -> String {
     )
 }
 
+fn level_color(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Fatal => MAGENTA,
+        LogLevel::Error => RED,
+        LogLevel::Warn => YELLOW,
+        LogLevel::Info => GREEN,
+        LogLevel::Debug => BLUE,
+        LogLevel::Trace => GREY,
+    }
+}
+
+/// Like [`format_log`] for a parsed line: its own timestamp if it had one,
+/// the level in its color and the remaining fields as `key=value`.
+pub fn format_record(source: &str, record: &LogRecord, ansi: bool) -> String {
+    let ts = record
+        .ts
+        .clone()
+        .unwrap_or_else(|| Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true));
+    let msg = record.message.trim_end_matches('\n');
+    let fields: String = record
+        .fields
+        .iter()
+        .map(|(k, v)| {
+            if v.contains(char::is_whitespace) {
+                format!(" {}={:?}", k, v)
+            } else {
+                format!(" {}={}", k, v)
+            }
+        })
+        .collect();
+
+    if !ansi {
+        let level = record
+            .level
+            .map(|l| format!("{} ", l.as_str().to_uppercase()))
+            .unwrap_or_default();
+        return format!("[{ts}] [{source}] {level}{msg}{fields}");
+    }
+
+    let level = record
+        .level
+        .map(|l| format!("{}{}{} ", level_color(l), l.as_str().to_uppercase(), RESET))
+        .unwrap_or_default();
+    format!(
+        "{grey}[{ts}]{reset} {cyan}[{source}]{reset} {level}{white}{msg}{reset}{grey}{fields}{reset}",
+        grey = GREY,
+        cyan = CYAN,
+        white = WHITE,
+        reset = RESET,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -74,4 +132,32 @@ mod tests {
         let result = format_log("", "message", false);
         assert!(result.contains(" message"));
     }
+
+    #[test]
+    fn test_format_record() {
+        let mut record = LogRecord {
+            level: Some(LogLevel::Warn),
+            message: "frame dropped".to_string(),
+            ts: Some("2024-05-01T10:00:00Z".to_string()),
+            ..Default::default()
+        };
+        record.fields.insert("camera".into(), "front left".into());
+        record.fields.insert("fps".into(), "12".into());
+        assert_eq!(
+            format_record("vision", &record, false),
+            r#"[2024-05-01T10:00:00Z] [vision] WARN frame dropped camera="front left" fps=12"#
+        );
+        let result = format_record("vision", &record, true);
+        assert!(result.contains(&format!("{}WARN{}", YELLOW, RESET)));
+    }
+
+    #[test]
+    fn test_format_record_without_level() {
+        let record = LogRecord {
+            message: "plain".to_string(),
+            ..Default::default()
+        };
+        let result = format_record("src", &record, false);
+        assert!(result.ends_with("[src] plain"));
+    }
 }
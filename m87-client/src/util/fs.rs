@@ -20,6 +20,8 @@ use russh_sftp::protocol::{
     Attrs, Data, File, FileAttributes, Handle, Name, OpenFlags, Status, StatusCode, Version,
 };
 
+use crate::util::disk;
+
 /// Global handle counter – we give each open file a unique string handle.
 static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
 
@@ -41,6 +43,8 @@ pub struct M87SftpHandler {
     // just to keep track of negotiated version if you ever care
     version: Option<u32>,
     dir_handles: Arc<Mutex<HashMap<String, DirListing>>>,
+    // free space writes must leave, in bytes (0: unchecked)
+    reserve: u64,
 }
 
 impl M87SftpHandler {
@@ -50,9 +54,16 @@ impl M87SftpHandler {
             open_files: Arc::new(Mutex::new(HashMap::new())),
             version: None,
             dir_handles: Arc::new(Mutex::new(HashMap::new())),
+            reserve: 0,
         }
     }
 
+    /// Refuse writes that would leave less than `reserve` bytes free.
+    pub fn with_reserve(mut self, reserve: u64) -> Self {
+        self.reserve = reserve;
+        self
+    }
+
     fn next_handle() -> String {
         NEXT_HANDLE.fetch_add(1, Ordering::Relaxed).to_string()
     }
@@ -283,6 +294,16 @@ impl russh_sftp::server::Handler for M87SftpHandler {
             None => return Err(StatusCode::NoSuchFile),
         };
 
+        // only growing the file takes space
+        let len = of.file.metadata().await.map(|m| m.len()).unwrap_or(0);
+        let growth = (offset + data.len() as u64).saturating_sub(len);
+        if growth > 0
+            && let Err(e) = disk::ensure_space(&of.path, growth, self.reserve)
+        {
+            tracing::warn!("SFTP write refused: {}", e);
+            return Ok(self.make_status_err(id, StatusCode::Failure, &e.to_string()));
+        }
+
         if let Err(e) = of.file.seek(std::io::SeekFrom::Start(offset)).await {
             tracing::error!(?e, "SFTP write: seek failed");
             return Err(StatusCode::Failure);
@@ -526,7 +547,7 @@ pub async fn run_sftp_server<S>(root: PathBuf, stream: S) -> anyhow::Result<()>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let handler = M87SftpHandler::new(root);
+    let handler = M87SftpHandler::new(root).with_reserve(disk::reserve_bytes());
     russh_sftp::server::run(stream, handler).await;
     Ok(())
 }
@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use hmac::{Hmac, Mac};
+use make87_sdk::streams::connect_verified;
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use once_cell::sync::OnceCell;
+use quinn::Endpoint;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::WebPkiSupportedAlgorithms;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use sha2::{Digest, Sha256};
+use tokio::time::timeout;
+use tracing::{debug, warn};
+
+use crate::streams::quic::QuicTimeouts;
+
+/// mDNS service type advertised by runtimes on the local network.
+pub const SERVICE_TYPE: &str = "_m87._udp.local.";
+/// Default UDP port of the direct (relay-less) QUIC listener.
+pub const DEFAULT_DIRECT_PORT: u16 = 7887;
+/// Environment variable holding the device key used for `--direct` connections.
+pub const DEVICE_KEY_ENV_VAR: &str = "M87_DEVICE_KEY";
+/// Environment variable holding the certificate fingerprint pinned for `--direct` connections.
+pub const FINGERPRINT_ENV_VAR: &str = "M87_DEVICE_FINGERPRINT";
+/// SNI presented on direct connections. The runtime uses a self-signed certificate for it,
+/// which clients pin by fingerprint.
+pub const DIRECT_SERVER_NAME: &str = "m87-direct";
+/// First byte of the authentication stream, so the handshake can change later
+pub const AUTH_VERSION: u8 = 1;
+/// Length of the runtime's challenge and of the client's proof
+pub const AUTH_NONCE_LEN: usize = 32;
+/// How long either side waits for the other during authentication
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct LanDevice {
+    pub name: String,
+    pub device_id: String,
+    pub short_id: String,
+    pub version: String,
+    pub addresses: Vec<IpAddr>,
+    pub port: u16,
+    pub direct: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectTarget {
+    pub addr: SocketAddr,
+    pub device_key: String,
+    /// SHA-256 of the runtime's certificate, hex encoded
+    pub fingerprint: String,
+}
+
+static DIRECT_TARGET: OnceCell<DirectTarget> = OnceCell::new();
+
+/// Parse `host[:port]` into a socket address, defaulting to [`DEFAULT_DIRECT_PORT`].
+pub fn parse_direct_addr(s: &str) -> Result<SocketAddr> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    let ip: IpAddr = s
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .map_err(|_| anyhow!("Invalid direct address '{}'. Expected <ip>[:port]", s))?;
+    Ok(SocketAddr::new(ip, DEFAULT_DIRECT_PORT))
+}
+
+/// Route all device streams of this process directly to `addr` instead of the relay.
+pub fn enable_direct(
+    addr: &str,
+    device_key: Option<String>,
+    fingerprint: Option<String>,
+) -> Result<()> {
+    let addr = parse_direct_addr(addr)?;
+    let device_key = device_key
+        .or_else(|| std::env::var(DEVICE_KEY_ENV_VAR).ok())
+        .filter(|k| !k.is_empty())
+        .ok_or_else(|| {
+            anyhow!(
+                "--direct requires the device key. Pass --device-key or set {}",
+                DEVICE_KEY_ENV_VAR
+            )
+        })?;
+    let fingerprint = fingerprint
+        .or_else(|| std::env::var(FINGERPRINT_ENV_VAR).ok())
+        .filter(|f| !f.is_empty())
+        .ok_or_else(|| {
+            anyhow!(
+                "--direct requires the device's certificate fingerprint. Pass --fingerprint or \
+                 set {}; 'm87 local lan-fingerprint' prints it on the device and the server \
+                 lists it as lan_cert_fingerprint in 'm87 devices list --format json'",
+                FINGERPRINT_ENV_VAR
+            )
+        })?;
+    let fingerprint = parse_fingerprint(&fingerprint)?;
+
+    DIRECT_TARGET
+        .set(DirectTarget {
+            addr,
+            device_key,
+            fingerprint,
+        })
+        .map_err(|_| anyhow!("Direct target already set"))
+}
+
+pub fn direct_target() -> Option<&'static DirectTarget> {
+    DIRECT_TARGET.get()
+}
+
+/// Connect straight to a runtime's LAN listener. Only the certificate pinned in
+/// `target` is accepted, and the device key is proven without being sent.
+pub async fn connect_direct(
+    target: &DirectTarget,
+    timeouts: QuicTimeouts,
+) -> Result<(Endpoint, quinn::Connection)> {
+    debug!("Connecting directly to {}", target.addr);
+    let verifier = Arc::new(PinnedCert::new(&target.fingerprint));
+    let (endpoint, conn) = connect_verified(target.addr, DIRECT_SERVER_NAME, verifier, timeouts)
+        .await
+        .with_context(|| format!("Direct connection to {} failed", target.addr))?;
+    prove_key(&conn, &target.device_key, &target.fingerprint)
+        .await
+        .with_context(|| format!("Direct authentication with {} failed", target.addr))?;
+    Ok((endpoint, conn))
+}
+
+/// Answer the runtime's challenge with [`auth_proof`] on the first bidirectional stream.
+async fn prove_key(conn: &quinn::Connection, key: &str, fingerprint: &str) -> Result<()> {
+    let (mut send, mut recv) = conn.open_bi().await?;
+    send.write_all(&[AUTH_VERSION]).await?;
+
+    let mut nonce = [0u8; AUTH_NONCE_LEN];
+    timeout(AUTH_TIMEOUT, recv.read_exact(&mut nonce))
+        .await
+        .map_err(|_| anyhow!("auth timeout"))??;
+    send.write_all(&auth_proof(&key_hash(key), &nonce, fingerprint))
+        .await?;
+    send.finish()?;
+
+    let mut accepted = [0u8; 1];
+    match timeout(AUTH_TIMEOUT, recv.read_exact(&mut accepted)).await {
+        Ok(Ok(())) if accepted[0] == 1 => Ok(()),
+        Err(_) => Err(anyhow!("auth timeout")),
+        _ => Err(anyhow!("the device rejected the key")),
+    }
+}
+
+/// SHA-256 of a key, hex encoded. Proofs are keyed with it, so the runtime
+/// can check keys it only keeps the hash of.
+pub fn key_hash(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// HMAC-SHA256 over the runtime's challenge and certificate fingerprint, keyed
+/// with [`key_hash`] of the device or recovery key.
+pub fn auth_proof(key_hash: &str, nonce: &[u8], fingerprint: &str) -> [u8; AUTH_NONCE_LEN] {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key_hash.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(nonce);
+    mac.update(fingerprint.as_bytes());
+    let mut proof = [0u8; AUTH_NONCE_LEN];
+    proof.copy_from_slice(&mac.finalize().into_bytes());
+    proof
+}
+
+/// SHA-256 of a DER certificate, hex encoded.
+pub fn cert_fingerprint(der: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(der))
+}
+
+/// Accept fingerprints with colons, spaces or in upper case.
+fn parse_fingerprint(s: &str) -> Result<String> {
+    let fingerprint: String = s
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid fingerprint '{}'. Expected 64 hex characters", s);
+    }
+    Ok(fingerprint)
+}
+
+/// Certificate verifier that accepts only the certificate with a given fingerprint,
+/// while still checking the handshake signatures made with its key.
+#[derive(Debug)]
+struct PinnedCert {
+    fingerprint: String,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl PinnedCert {
+    fn new(fingerprint: &str) -> Self {
+        Self {
+            fingerprint: fingerprint.to_string(),
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        }
+    }
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if cert_fingerprint(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "certificate does not match the pinned fingerprint".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// Browse the local network for advertising runtimes.
+pub async fn discover(timeout: Duration) -> Result<Vec<LanDevice>> {
+    let mdns = ServiceDaemon::new().context("Failed to start mDNS daemon")?;
+    let receiver = mdns
+        .browse(SERVICE_TYPE)
+        .context("Failed to browse for devices")?;
+
+    let mut found: BTreeMap<String, LanDevice> = BTreeMap::new();
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let event = match tokio::time::timeout_at(deadline, receiver.recv_async()).await {
+            Ok(Ok(event)) => event,
+            Ok(Err(_)) | Err(_) => break,
+        };
+
+        if let ServiceEvent::ServiceResolved(info) = event {
+            let device_id = info
+                .get_property_val_str("device_id")
+                .unwrap_or_default()
+                .to_string();
+            if device_id.is_empty() {
+                continue;
+            }
+            let mut addresses: Vec<IpAddr> = info.get_addresses().iter().cloned().collect();
+            addresses.sort_by_key(|a| (a.is_ipv6(), *a));
+
+            found.insert(
+                device_id.clone(),
+                LanDevice {
+                    name: info
+                        .get_property_val_str("name")
+                        .unwrap_or_default()
+                        .to_string(),
+                    short_id: info
+                        .get_property_val_str("short_id")
+                        .unwrap_or_default()
+                        .to_string(),
+                    version: info
+                        .get_property_val_str("version")
+                        .unwrap_or_default()
+                        .to_string(),
+                    direct: info.get_property_val_str("direct") == Some("1"),
+                    port: info.get_port(),
+                    addresses,
+                    device_id,
+                },
+            );
+        }
+    }
+
+    if let Err(e) = mdns.shutdown() {
+        warn!("Failed to shut down mDNS daemon: {}", e);
+    }
+
+    Ok(found.into_values().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_direct_addr_default_port() {
+        let addr = parse_direct_addr("192.168.1.42").unwrap();
+        assert_eq!(addr, "192.168.1.42:7887".parse().unwrap());
+    }
+
+    #[test]
+    fn test_parse_direct_addr_with_port() {
+        let addr = parse_direct_addr("192.168.1.42:9000").unwrap();
+        assert_eq!(addr.port(), 9000);
+    }
+
+    #[test]
+    fn test_parse_direct_addr_ipv6() {
+        let addr = parse_direct_addr("[fe80::1]").unwrap();
+        assert_eq!(addr.port(), DEFAULT_DIRECT_PORT);
+        let addr = parse_direct_addr("[fe80::1]:9000").unwrap();
+        assert_eq!(addr.port(), 9000);
+    }
+
+    #[test]
+    fn test_parse_direct_addr_rejects_hostname() {
+        assert!(parse_direct_addr("my-device").is_err());
+    }
+
+    #[test]
+    fn test_parse_fingerprint_normalizes() {
+        let hex = "ab".repeat(32);
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(parse_fingerprint(&colons).unwrap(), hex);
+        assert!(parse_fingerprint("abcd").is_err());
+        assert!(parse_fingerprint(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_auth_proof_binds_key_nonce_and_certificate() {
+        let hash = key_hash("device-key");
+        let proof = auth_proof(&hash, &[1; AUTH_NONCE_LEN], "fp");
+        assert_eq!(proof, auth_proof(&hash, &[1; AUTH_NONCE_LEN], "fp"));
+        assert_ne!(
+            proof,
+            auth_proof(&key_hash("other"), &[1; AUTH_NONCE_LEN], "fp")
+        );
+        assert_ne!(proof, auth_proof(&hash, &[2; AUTH_NONCE_LEN], "fp"));
+        assert_ne!(proof, auth_proof(&hash, &[1; AUTH_NONCE_LEN], "other"));
+    }
+}
@@ -1,7 +1,16 @@
+use std::collections::VecDeque;
+use std::ffi::OsString;
 use std::fmt::{self, Write};
-use std::sync::{Once, OnceLock};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Once, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::broadcast;
 
+use m87_shared::log_filter::RUNTIME_SOURCE;
+use m87_shared::log_format::LogLevel;
+
 use tracing::field::Visit;
 use tracing::{Event, Subscriber};
 use tracing_subscriber::fmt as tracing_fmt;
@@ -9,13 +18,40 @@ use tracing_subscriber::layer::{Context, Layer};
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{EnvFilter, prelude::*};
 
-static LOG_TX: OnceLock<broadcast::Sender<String>> = OnceLock::new();
+static LOG_TX: OnceLock<broadcast::Sender<LogLine>> = OnceLock::new();
+/// Lines kept for `logs --since`
+const RECENT_LINES: usize = 2000;
+static RECENT: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+
+/// A line for the logs stream, with what it is filtered by.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub level: LogLevel,
+    /// Run id of an observed service line, `m87` for the runtime's own
+    pub source: String,
+    /// Unix millis
+    pub time: u64,
+    pub text: String,
+}
 
 struct MsgVisitor {
     msg: String,
+    /// Level parsed from an observed service line
+    log_level: Option<LogLevel>,
+    /// Run of an observed service line
+    log_source: Option<String>,
 }
 
 impl Visit for MsgVisitor {
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        match field.name() {
+            "log_level" => self.log_level = LogLevel::parse(value),
+            "log_source" => self.log_source = Some(value.to_string()),
+            "message" => self.msg = value.to_string(),
+            _ => {}
+        }
+    }
+
     fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
         if field.name() == "message" {
             self.msg = format!("{value:?}");
@@ -24,11 +60,11 @@ impl Visit for MsgVisitor {
 }
 
 pub struct LogBroadcastLayer {
-    tx: broadcast::Sender<String>,
+    tx: broadcast::Sender<LogLine>,
 }
 
 impl LogBroadcastLayer {
-    pub fn new(tx: broadcast::Sender<String>) -> Self {
+    pub fn new(tx: broadcast::Sender<LogLine>) -> Self {
         Self { tx }
     }
 }
@@ -39,7 +75,11 @@ where
 {
     fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
         // Extract message field
-        let mut visitor = MsgVisitor { msg: String::new() };
+        let mut visitor = MsgVisitor {
+            msg: String::new(),
+            log_level: None,
+            log_source: None,
+        };
         event.record(&mut visitor);
 
         // Metadata
@@ -62,11 +102,172 @@ where
             let _ = write!(line, "{}", visitor.msg);
         }
 
+        let level = visitor.log_level.unwrap_or(match *level {
+            tracing::Level::ERROR => LogLevel::Error,
+            tracing::Level::WARN => LogLevel::Warn,
+            tracing::Level::INFO => LogLevel::Info,
+            tracing::Level::DEBUG => LogLevel::Debug,
+            tracing::Level::TRACE => LogLevel::Trace,
+        });
+        let line = LogLine {
+            level,
+            source: visitor
+                .log_source
+                .unwrap_or_else(|| RUNTIME_SOURCE.to_string()),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            text: line,
+        };
+        if let Ok(mut recent) = RECENT.lock() {
+            if recent.len() == RECENT_LINES {
+                recent.pop_front();
+            }
+            recent.push_back(line.clone());
+        }
         let _ = self.tx.send(line);
     }
 }
 
-pub fn init_tracing_with_log_layer(default_level: &str) -> broadcast::Sender<String> {
+/// Collects every field of an event as JSON values.
+struct JsonVisitor(serde_json::Map<String, serde_json::Value>);
+
+impl Visit for JsonVisitor {
+    fn record_i64(&mut self, field: &tracing::field::Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &tracing::field::Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &tracing::field::Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{value:?}").into());
+    }
+}
+
+/// One JSON object per line on stdout, for log collectors of container
+/// orchestrators (`m87 runtime run --foreground`).
+pub struct JsonStdoutLayer;
+
+impl<S> Layer<S> for JsonStdoutLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let mut fields = serde_json::Map::new();
+        fields.insert(
+            "timestamp".into(),
+            chrono::Utc::now()
+                .to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+                .into(),
+        );
+        fields.insert("level".into(), meta.level().as_str().into());
+        fields.insert("target".into(), meta.target().into());
+        let mut visitor = JsonVisitor(fields);
+        event.record(&mut visitor);
+
+        let line = serde_json::Value::Object(visitor.0).to_string();
+        let mut stdout = io::stdout().lock();
+        let _ = io::Write::write_all(&mut stdout, line.as_bytes());
+        let _ = io::Write::write_all(&mut stdout, b"\n");
+    }
+}
+
+/// Log file that is moved to `<path>.1` (shifting older ones up to
+/// `<path>.<keep>`) before it grows past `max_bytes`. 0 disables rotation.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: u32,
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, keep: u32) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            keep,
+            file: Some(file),
+            size,
+        })
+    }
+
+    /// Path of the `n`th rotated file.
+    pub fn rotated_path(path: &Path, n: u32) -> PathBuf {
+        let mut name = OsString::from(path.as_os_str());
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(Self::rotated_path(&self.path, self.keep));
+            for n in (1..self.keep).rev() {
+                let _ = fs::rename(
+                    Self::rotated_path(&self.path, n),
+                    Self::rotated_path(&self.path, n + 1),
+                );
+            }
+            fs::rename(&self.path, Self::rotated_path(&self.path, 1))?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl io::Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_bytes > 0 && self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(open_append(&self.path)?),
+        };
+        let n = file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn init_tracing_with_log_layer(
+    default_level: &str,
+    file: Option<RotatingFile>,
+    json: bool,
+) -> broadcast::Sender<LogLine> {
     let (tx, _rx) = broadcast::channel(32_768);
     LOG_TX.set(tx.clone()).ok();
 
@@ -74,9 +275,23 @@ pub fn init_tracing_with_log_layer(default_level: &str) -> broadcast::Sender<Str
         .or_else(|_| EnvFilter::try_new(default_level))
         .unwrap_or_else(|_| EnvFilter::new("info"));
 
+    let file_layer = file.map(|f| {
+        tracing_fmt::layer()
+            .with_ansi(false)
+            .with_writer(Mutex::new(f))
+    });
+
+    let (text_layer, json_layer) = if json {
+        (None, Some(JsonStdoutLayer))
+    } else {
+        (Some(tracing_fmt::layer()), None)
+    };
+
     tracing_subscriber::registry()
         .with(filter)
-        .with(tracing_fmt::layer())
+        .with(text_layer)
+        .with(json_layer)
+        .with(file_layer)
         .with(LogBroadcastLayer::new(tx.clone()))
         .init();
 
@@ -85,14 +300,15 @@ pub fn init_tracing_with_log_layer(default_level: &str) -> broadcast::Sender<Str
 
 static INIT: Once = Once::new();
 
-pub fn init_logging(log_level: &str) {
+/// Set up tracing to stdout, as JSON lines when `json` is set, and, when
+/// `file` is set, to that file as well.
+pub fn init_logging(log_level: &str, file: Option<RotatingFile>, json: bool) {
     INIT.call_once(|| {
-        let _ = init_tracing_with_log_layer(log_level);
+        let _ = init_tracing_with_log_layer(log_level, file, json);
     });
 }
 
 pub fn timestamp_hms() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap_or_default();
@@ -107,10 +323,18 @@ pub fn human_time(ts: u64) -> String {
     format!("{:02}:{:02}:{:02}", h, m, s)
 }
 
-pub fn get_log_rx() -> Option<broadcast::Receiver<String>> {
+pub fn get_log_rx() -> Option<broadcast::Receiver<LogLine>> {
     LOG_TX.get().map(|tx| tx.subscribe())
 }
 
+/// The last lines sent to the logs stream, oldest first.
+pub fn recent_lines() -> Vec<LogLine> {
+    RECENT
+        .lock()
+        .map(|recent| recent.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,6 +372,25 @@ mod tests {
         assert_eq!(human_time(300), "00:05:00");
     }
 
+    #[test]
+    fn test_rotating_file_keeps_newest() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("runtime.log");
+        let mut f = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            f.write_all(line.as_bytes()).unwrap();
+        }
+        f.flush().unwrap();
+
+        let read = |n: u32| fs::read_to_string(RotatingFile::rotated_path(&path, n)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(read(1), "third\n");
+        assert_eq!(read(2), "second\n");
+        assert!(!RotatingFile::rotated_path(&path, 3).exists());
+    }
+
     #[test]
     fn test_timestamp_hms_format() {
         let ts = timestamp_hms();
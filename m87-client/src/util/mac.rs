@@ -20,7 +20,16 @@ pub fn parse_mac_from_ip_link_line(line: &str) -> Option<String> {
     }
 }
 
-/// Try reading MAC from /sys/class/net or ip link
+/// Parse MAC address from BSD `ifconfig` output line starting with "ether"
+pub fn parse_mac_from_ifconfig_line(line: &str) -> Option<String> {
+    let mut parts = line.split_whitespace();
+    if parts.next() != Some("ether") {
+        return None;
+    }
+    parts.next().map(|mac| mac.to_string())
+}
+
+/// Try reading MAC from /sys/class/net, ip link, or ifconfig on macOS
 pub fn get_mac_address() -> Option<String> {
     // Try sysfs first
     if let Ok(entries) = fs::read_dir("/sys/class/net/") {
@@ -45,6 +54,18 @@ pub fn get_mac_address() -> Option<String> {
         }
     }
 
+    // macOS has neither sysfs nor iproute2
+    if let Ok(out) = Command::new("ifconfig").output() {
+        let text = String::from_utf8_lossy(&out.stdout);
+        for line in text.lines() {
+            if let Some(mac) = parse_mac_from_ifconfig_line(line)
+                && is_valid_mac(&mac)
+            {
+                return Some(mac);
+            }
+        }
+    }
+
     None
 }
 
@@ -101,6 +122,19 @@ mod tests {
         assert_eq!(parse_mac_from_ip_link_line(""), None);
     }
 
+    #[test]
+    fn test_parse_mac_from_ifconfig_line() {
+        let line = "\tether a4:83:e7:12:34:56 ";
+        assert_eq!(
+            parse_mac_from_ifconfig_line(line),
+            Some("a4:83:e7:12:34:56".to_string())
+        );
+        assert_eq!(
+            parse_mac_from_ifconfig_line("\tinet 192.168.1.20 netmask 0xffffff00"),
+            None
+        );
+    }
+
     #[test]
     fn test_get_mac_address_returns_something() {
         // On a real Linux system, this should return a MAC address
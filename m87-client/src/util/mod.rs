@@ -1,6 +1,7 @@
 pub mod command;
 pub mod logging;
 pub mod network;
+pub mod proxy;
 pub mod shutdown;
 pub mod subprocess;
 
@@ -16,11 +17,17 @@ pub mod system_info;
 #[cfg(feature = "runtime")]
 pub mod unix;
 
+pub mod clipboard;
 pub mod device_cache;
+pub mod disk;
 pub mod docker;
 pub mod format;
 pub mod fs;
+pub mod lan;
+pub mod private_file;
 pub mod servers_parallel;
+#[cfg(feature = "runtime")]
 pub mod ssh;
 pub mod tls;
 pub mod udp;
+pub mod wait;
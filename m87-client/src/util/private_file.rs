@@ -0,0 +1,35 @@
+//! Files only their owner may read, e.g. keys and recovery state.
+
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Write `contents` to `path` with mode 0600, creating its directory. Under
+/// sudo the file goes to the user that ran it, since the runtime reads it as
+/// that user.
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to open {:?}", path))?;
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {:?}", path))?;
+
+    #[cfg(unix)]
+    if let Ok(sudo_user) = std::env::var("SUDO_USER") {
+        let _ = std::process::Command::new("chown")
+            .args([sudo_user.as_str(), path.to_str().unwrap_or("")])
+            .status();
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Outbound proxy shared by the REST clients, the OAuth login, self-update
+//! downloads and QUIC tunnels.
+//!
+//! The proxy in config.json wins over `HTTPS_PROXY` / `ALL_PROXY`; `NO_PROXY`
+//! rules from the environment are added to the configured ones. QUIC needs a
+//! proxy that can carry UDP: SOCKS5 or an HTTP proxy speaking CONNECT-UDP.
+
+use anyhow::{Context, Result};
+use make87_sdk::proxy::{UdpProxy, without_userinfo};
+use std::net::IpAddr;
+use std::sync::OnceLock;
+
+use crate::config::Config;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxySettings {
+    pub url: String,
+    pub no_proxy: Vec<String>,
+}
+
+static SETTINGS: OnceLock<Option<ProxySettings>> = OnceLock::new();
+
+/// The proxy of this process, read once from config.json and the environment.
+pub fn settings() -> Option<&'static ProxySettings> {
+    SETTINGS
+        .get_or_init(|| {
+            let config = Config::load().map(|c| c.proxy).unwrap_or_default();
+            resolve(config.url, config.no_proxy, |name| std::env::var(name).ok())
+        })
+        .as_ref()
+}
+
+fn resolve(
+    url: Option<String>,
+    mut no_proxy: Vec<String>,
+    env: impl Fn(&str) -> Option<String>,
+) -> Option<ProxySettings> {
+    let from_env = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| env(name))
+            .filter(|v| !v.trim().is_empty())
+    };
+    let url = url
+        .filter(|u| !u.trim().is_empty())
+        .or_else(|| from_env(&["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]))?;
+    if let Some(rules) = from_env(&["NO_PROXY", "no_proxy"]) {
+        no_proxy.extend(
+            rules
+                .split(',')
+                .map(|r| r.trim().to_string())
+                .filter(|r| !r.is_empty()),
+        );
+    }
+    Some(ProxySettings { url, no_proxy })
+}
+
+/// Route a reqwest client through the proxy, except for `no_proxy` hosts.
+/// Also trusts the configured CA bundle, as every client is built through here.
+pub fn apply(builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+    let builder = make87_sdk::tls::trust_extra_roots(builder)?;
+    let Some(settings) = settings() else {
+        return Ok(builder);
+    };
+    let proxy = reqwest::Proxy::all(&settings.url)
+        .with_context(|| format!("Invalid proxy URL '{}'", without_userinfo(&settings.url)))?
+        .no_proxy(reqwest::NoProxy::from_string(&settings.no_proxy.join(",")));
+    Ok(builder.proxy(proxy))
+}
+
+/// A reqwest client going through the proxy.
+pub fn client() -> Result<reqwest::Client> {
+    Ok(apply(reqwest::Client::builder())?.build()?)
+}
+
+/// The proxy carrying QUIC to `host[:port]`, if one applies.
+pub fn quic_proxy(host: &str) -> Result<Option<UdpProxy>> {
+    let Some(settings) = settings() else {
+        return Ok(None);
+    };
+    // no_proxy rules match the relay address, not its encoded name
+    let (name, _) = m87_shared::host::split_host_port(host);
+    let target =
+        m87_shared::host::decode_ip(name).map_or_else(|| host.to_string(), |ip| ip.to_string());
+    if bypass(&target, &settings.no_proxy) {
+        return Ok(None);
+    }
+    UdpProxy::from_url(&settings.url).map(Some)
+}
+
+/// Whether `host` (optionally with a port) is reached without the proxy.
+pub fn bypass(host: &str, rules: &[String]) -> bool {
+    let host = strip_port(host).to_ascii_lowercase();
+    let ip = host.parse::<IpAddr>().ok();
+    rules.iter().any(|rule| {
+        let rule = rule.trim().to_ascii_lowercase();
+        if rule == "*" {
+            return true;
+        }
+        if let Some((net, bits)) = rule.split_once('/') {
+            return match (ip, net.parse::<IpAddr>(), bits.parse::<u32>()) {
+                (Some(ip), Ok(net), Ok(bits)) => in_cidr(ip, net, bits),
+                _ => false,
+            };
+        }
+        let domain = rule.trim_start_matches("*.").trim_start_matches('.');
+        !domain.is_empty() && (host == domain || host.ends_with(&format!(".{domain}")))
+    })
+}
+
+/// `host:port` and `[v6]:port` without the port; bare IPv6 literals are kept.
+fn strip_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.split_once(':') {
+        Some((name, port)) if !port.contains(':') => name,
+        _ => host,
+    }
+}
+
+fn in_cidr(ip: IpAddr, net: IpAddr, bits: u32) -> bool {
+    match (ip, net) {
+        (IpAddr::V4(ip), IpAddr::V4(net)) if bits <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(net) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(net)) if bits <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(net) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_prefers_config_over_env() {
+        let env = |name: &str| match name {
+            "HTTPS_PROXY" => Some("http://env-proxy:3128".to_string()),
+            "NO_PROXY" => Some("localhost, .internal".to_string()),
+            _ => None,
+        };
+        let settings = resolve(
+            Some("socks5h://cfg-proxy:1080".to_string()),
+            vec!["10.0.0.0/8".to_string()],
+            env,
+        )
+        .unwrap();
+        assert_eq!(settings.url, "socks5h://cfg-proxy:1080");
+        assert_eq!(settings.no_proxy, ["10.0.0.0/8", "localhost", ".internal"]);
+
+        assert_eq!(
+            resolve(None, vec![], env).unwrap().url,
+            "http://env-proxy:3128"
+        );
+        assert!(resolve(None, vec![], |_| None).is_none());
+    }
+
+    #[test]
+    fn test_bypass() {
+        let rules: Vec<String> = ["localhost", ".corp.example", "10.0.0.0/8", "fd00::/8"]
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert!(bypass("localhost:8080", &rules));
+        assert!(bypass("api.corp.example", &rules));
+        assert!(bypass("corp.example:443", &rules));
+        assert!(bypass("10.1.2.3", &rules));
+        assert!(bypass("[fd00::1]:443", &rules));
+        assert!(!bypass("evilcorp.example", &rules));
+        assert!(!bypass("11.0.0.1", &rules));
+        assert!(!bypass("eu.public.make87.dev:443", &rules));
+        assert!(bypass("anything", &["*".to_string()]));
+    }
+}
@@ -1,7 +1,131 @@
+//! Bounded-concurrency requests: one request per server (or device, or any
+//! other key), at most a few at a time, each with its own timeout. Requests
+//! that fail or time out are reported apart from the results of the others.
+
 use anyhow::{Result, anyhow};
 use futures::{StreamExt, stream};
-use std::{future::Future, time::Duration};
+use std::{fmt::Display, future::Future, time::Duration};
+
+/// Timeout of a single request unless [`Fanout::timeout`] sets another
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fanout {
+    concurrency: usize,
+    timeout: Duration,
+}
+
+/// Results of a fan-out in the order of its keys, failed requests apart.
+pub struct Outcomes<K, T> {
+    pub ok: Vec<(K, T)>,
+    pub failed: Vec<(K, anyhow::Error)>,
+}
+
+impl<K: Display, T> Outcomes<K, T> {
+    /// Warn that the results lack what the failed requests would have
+    /// returned, naming each and why.
+    pub fn warn_partial(&self, what: &str) {
+        if self.failed.is_empty() {
+            return;
+        }
+        let reasons: Vec<String> = self
+            .failed
+            .iter()
+            .map(|(key, e)| format!("{} ({:#})", key, e))
+            .collect();
+        tracing::warn!(
+            "No answer from {} of {} {}, results are incomplete: {}",
+            self.failed.len(),
+            self.failed.len() + self.ok.len(),
+            what,
+            reasons.join(", ")
+        );
+    }
+}
+
+impl Fanout {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run `f` for every key and wait for all of them.
+    pub async fn run<K, T, F, Fut>(self, keys: Vec<K>, f: F) -> Outcomes<K, T>
+    where
+        K: Clone + Display,
+        F: Fn(K) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let results: Vec<(K, Result<T>)> = stream::iter(keys)
+            .map(|key| {
+                let request = f(key.clone());
+                async move {
+                    tracing::debug!(key = %key, "fanout: starting");
+                    let res = match tokio::time::timeout(self.timeout, request).await {
+                        Ok(res) => res,
+                        Err(_) => Err(anyhow!("timed out after {}s", self.timeout.as_secs())),
+                    };
+                    tracing::debug!(key = %key, ok = res.is_ok(), "fanout: finished");
+                    (key, res)
+                }
+            })
+            // ordered, so output follows the keys whatever answers first
+            .buffered(self.concurrency)
+            .collect()
+            .await;
+
+        let mut outcomes = Outcomes {
+            ok: Vec::new(),
+            failed: Vec::new(),
+        };
+        for (key, res) in results {
+            match res {
+                Ok(value) => outcomes.ok.push((key, value)),
+                Err(e) => outcomes.failed.push((key, e)),
+            }
+        }
+        outcomes
+    }
 
+    /// The first key, in the order requests complete, for which `f` found
+    /// something. Fails on the first request that fails.
+    pub async fn find<K, T, F, Fut>(self, keys: Vec<K>, f: F) -> Result<Option<(K, T)>>
+    where
+        K: Clone + Display,
+        F: Fn(K) -> Fut,
+        Fut: Future<Output = Result<Option<T>>>,
+    {
+        let mut stream = stream::iter(keys)
+            .map(|key| {
+                let request = f(key.clone());
+                async move {
+                    let found = tokio::time::timeout(self.timeout, request)
+                        .await
+                        .map_err(|_| anyhow!("request to {} timed out", key))??;
+                    Ok::<Option<(K, T)>, anyhow::Error>(found.map(|val| (key, val)))
+                }
+            })
+            .buffer_unordered(self.concurrency);
+
+        while let Some(res) = stream.next().await {
+            if let Some(found) = res? {
+                return Ok(Some(found));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+/// Items of every server, tagged with its url. Servers that fail are skipped
+/// with a warning, or fail the whole call with `raise_error`.
 pub async fn fanout_servers<T, F, Fut>(
     server_urls: Vec<String>,
     concurrency: usize,
@@ -12,55 +136,24 @@ where
     F: Fn(String) -> Fut + Copy,
     Fut: Future<Output = Result<Vec<T>>>,
 {
-    let concurrency = concurrency.max(1);
-
-    let per_server_results: Vec<Result<Vec<(String, T)>>> = stream::iter(server_urls)
-        .map(|server_url| {
-            let url_for_items = server_url.clone();
-            async move {
-                tracing::debug!(server_url = %server_url, "fanout: starting");
-
-                // pick a sane timeout for your use-case
-                let res = tokio::time::timeout(Duration::from_secs(10), f(server_url)).await;
-
-                let mapped = match res {
-                    Ok(Ok(items)) => Ok(items
-                        .into_iter()
-                        .map(|item| (url_for_items.clone(), item))
-                        .collect::<Vec<_>>()),
-                    Ok(Err(e)) => Err(e),
-                    Err(_) => {
-                        tracing::warn!(server_url = %url_for_items, "fanout: timeout; skipping");
-                        match raise_error {
-                            true => Err(anyhow!("request to {} timed out", url_for_items)),
-                            false => Ok(Vec::new()),
-                        }
-                    }
-                };
-
-                tracing::debug!(server_url = %url_for_items, ok = mapped.is_ok(), "fanout: finished");
-                mapped
-            }
-        })
-        .buffer_unordered(concurrency)
-        .collect()
-        .await;
-
-    let mut out = Vec::new();
-    for res in per_server_results {
-        match res {
-            Ok(mut v) => out.append(&mut v),
-            Err(err) => {
-                tracing::warn!(error = %err, "fanout: request failed; skipping");
-                match raise_error {
-                    true => return Err(err),
-                    false => {}
-                };
-            }
+    let outcomes = Fanout::new(concurrency).run(server_urls, f).await;
+    if raise_error {
+        if let Some((_, e)) = outcomes.failed.into_iter().next() {
+            return Err(e);
         }
+    } else {
+        outcomes.warn_partial("servers");
     }
 
-    Ok(out)
+    Ok(outcomes
+        .ok
+        .into_iter()
+        .flat_map(|(server_url, items)| {
+            items
+                .into_iter()
+                .map(move |item| (server_url.clone(), item))
+        })
+        .collect())
 }
 
 pub async fn find_on_servers<T, F, Fut>(
@@ -72,23 +165,21 @@ where
     F: Fn(String) -> Fut + Copy,
     Fut: Future<Output = Result<Option<T>>>,
 {
-    let mut stream = stream::iter(server_urls)
-        .map(|server_url| {
-            let server_url_clone = server_url.clone();
-            async move {
-                let res = f(server_url).await?;
-                Ok::<Option<(String, T)>, anyhow::Error>(res.map(|val| (server_url_clone, val)))
-            }
-        })
-        .buffer_unordered(concurrency);
+    Fanout::new(concurrency).find(server_urls, f).await
+}
 
-    while let Some(res) = stream.next().await {
-        if let Some(found) = res? {
-            return Ok(Some(found));
-        }
-    }
+/// Whether a server answered 404, e.g. because an id lives on another server.
+pub fn is_not_found(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .is_some_and(|status| status == reqwest::StatusCode::NOT_FOUND)
+}
 
-    Ok(None)
+/// Whether a server answered 403, e.g. because an org lives on another server.
+pub fn is_forbidden(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .is_some_and(|status| status == reqwest::StatusCode::FORBIDDEN)
 }
 
 #[cfg(test)]
@@ -130,6 +221,63 @@ mod tests {
         assert!(result.unwrap().is_empty());
     }
 
+    #[tokio::test]
+    async fn test_fanout_servers_skips_failed_server() {
+        let servers = vec!["http://server1".to_string(), "http://server2".to_string()];
+        let result = fanout_servers(servers, 2, false, |url| async move {
+            if url == "http://server1" {
+                Err(anyhow!("unreachable"))
+            } else {
+                Ok(vec![1])
+            }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), vec![("http://server2".to_string(), 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_fanout_keeps_key_order_and_reports_timeouts() {
+        let outcomes = Fanout::new(3)
+            .timeout(Duration::from_millis(50))
+            .run(vec![1u64, 2, 3], |key| async move {
+                if key == 2 {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+                // the first key answers last
+                tokio::time::sleep(Duration::from_millis(10 * (3 - key.min(3)))).await;
+                Ok(key * 10)
+            })
+            .await;
+
+        assert_eq!(outcomes.ok, vec![(1, 10), (3, 30)]);
+        assert_eq!(outcomes.failed.len(), 1);
+        assert_eq!(outcomes.failed[0].0, 2);
+    }
+
+    #[tokio::test]
+    async fn test_fanout_bounds_concurrency() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let outcomes = Fanout::new(2)
+            .run((0..8).collect(), |key: u32| {
+                let (running, peak) = (&running, &peak);
+                async move {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    Ok(key)
+                }
+            })
+            .await;
+
+        assert_eq!(outcomes.ok.len(), 8);
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+
     #[tokio::test]
     async fn test_find_on_servers_finds_first() {
         let servers = vec!["http://server1".to_string(), "http://server2".to_string()];
@@ -10,6 +10,7 @@ use tracing::{error, info, warn};
 use russh::server::{self, Auth, Config as ServerConfig, Handle, Msg, Session};
 use russh::{Channel, ChannelId};
 
+use crate::config::{config_dir_override, for_instance};
 use crate::util::fs::run_sftp_server;
 
 /// One PTY-backed shell session per SSH channel.
@@ -298,10 +299,13 @@ impl M87SshHandler {
 
 /// Get or create the SSH keys directory.
 fn ssh_keys_dir() -> Result<PathBuf> {
-    let dir = dirs::config_dir()
-        .ok_or_else(|| anyhow!("No config directory found"))?
-        .join("m87")
-        .join("ssh_keys");
+    let dir = match config_dir_override() {
+        Some(dir) => dir,
+        None => dirs::config_dir()
+            .ok_or_else(|| anyhow!("No config directory found"))?
+            .join("m87"),
+    };
+    let dir = for_instance(dir).join("ssh_keys");
     std::fs::create_dir_all(&dir)?;
     Ok(dir)
 }
@@ -650,9 +654,28 @@ impl server::Handler for M87SshHandler {
     }
 }
 
+/// The login shell from the passwd database, which on macOS is served by
+/// Directory Services rather than /etc/passwd.
+#[cfg(unix)]
+pub fn login_shell() -> Option<String> {
+    use std::ffi::CStr;
+    unsafe {
+        let uid = libc::geteuid();
+        let pwd = libc::getpwuid(uid);
+        if pwd.is_null() || (*pwd).pw_shell.is_null() {
+            return None;
+        }
+        let shell = CStr::from_ptr((*pwd).pw_shell).to_str().ok()?;
+        // service accounts have no interactive shell
+        let interactive =
+            !shell.is_empty() && !shell.ends_with("/nologin") && !shell.ends_with("/false");
+        interactive.then(|| shell.to_string())
+    }
+}
+
 #[cfg(unix)]
 fn default_shell() -> String {
-    use std::{ffi::CStr, path::Path};
+    use std::path::Path;
     // 1. $SHELL (most reliable)
     if let Ok(shell) = std::env::var("SHELL") {
         if !shell.is_empty() {
@@ -660,22 +683,15 @@ fn default_shell() -> String {
         }
     }
 
-    // 2. /etc/passwd via libc
-    unsafe {
-        let uid = libc::geteuid();
-        let pwd = libc::getpwuid(uid);
-        if !pwd.is_null() {
-            let shell = CStr::from_ptr((*pwd).pw_shell);
-            if let Ok(shell) = shell.to_str() {
-                if !shell.is_empty() {
-                    return shell.to_string();
-                }
-            }
-        }
+    // 2. passwd database via libc
+    if let Some(shell) = login_shell() {
+        return shell;
     }
 
-    // 3. Common fallbacks
-    if Path::new("/bin/bash").exists() {
+    // 3. Common fallbacks; zsh is the default shell on macOS
+    if cfg!(target_os = "macos") && Path::new("/bin/zsh").exists() {
+        "/bin/zsh".to_string()
+    } else if Path::new("/bin/bash").exists() {
         "/bin/bash".to_string()
     } else {
         "/bin/sh".to_string()
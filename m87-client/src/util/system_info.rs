@@ -39,6 +39,10 @@ pub async fn get_system_info() -> Result<DeviceSystemInfo> {
     // add
 
     sys_info.username = username();
+    sys_info.e2e_public_key = crate::streams::e2e::device_public_key().ok();
+    if crate::config::Config::load().is_ok_and(|c| c.lan_direct) {
+        sys_info.lan_cert_fingerprint = crate::device::lan::lan_cert_fingerprint().ok();
+    }
 
     let mut sys = System::new_all();
     sys.refresh_all();
@@ -1,55 +1,8 @@
 use std::sync::Once;
 
-use anyhow::Result;
-use rustls::{
-    SignatureScheme,
-    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
-    pki_types::{CertificateDer, ServerName, UnixTime},
-};
+pub use make87_sdk::tls::NoVerify;
 
-#[derive(Debug)]
-pub struct NoVerify;
-
-impl ServerCertVerifier for NoVerify {
-    fn verify_server_cert(
-        &self,
-        _end_entity: &CertificateDer<'_>,
-        _intermediates: &[CertificateDer<'_>],
-        _server_name: &ServerName<'_>,
-        _ocsp: &[u8],
-        _now: UnixTime,
-    ) -> Result<ServerCertVerified, rustls::Error> {
-        Ok(ServerCertVerified::assertion())
-    }
-
-    fn verify_tls12_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &rustls::DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        Ok(HandshakeSignatureValid::assertion())
-    }
-
-    fn verify_tls13_signature(
-        &self,
-        _message: &[u8],
-        _cert: &CertificateDer<'_>,
-        _dss: &rustls::DigitallySignedStruct,
-    ) -> Result<HandshakeSignatureValid, rustls::Error> {
-        Ok(HandshakeSignatureValid::assertion())
-    }
-
-    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
-        vec![
-            SignatureScheme::ECDSA_NISTP256_SHA256,
-            SignatureScheme::ECDSA_NISTP384_SHA384,
-            SignatureScheme::ED25519,
-            SignatureScheme::RSA_PSS_SHA256,
-            SignatureScheme::RSA_PKCS1_SHA256,
-        ]
-    }
-}
+use crate::config::Config;
 
 static INIT: Once = Once::new();
 
@@ -57,13 +10,36 @@ pub fn set_tls_provider() {
     INIT.call_once(|| {
         rustls::crypto::CryptoProvider::install_default(rustls::crypto::ring::default_provider())
             .expect("failed to install ring crypto provider");
+        trust_ca_bundle();
     });
 }
 
+/// Trust the `ca_bundle` of config.json for REST and QUIC connections.
+fn trust_ca_bundle() {
+    let Some(path) = Config::load().ok().and_then(|c| c.ca_bundle) else {
+        return;
+    };
+    let res = std::fs::read(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|pem| make87_sdk::tls::add_trusted_roots(&pem));
+    match res {
+        Ok(count) => tracing::debug!(
+            "Trusting {} CA certificate(s) from {}",
+            count,
+            path.display()
+        ),
+        Err(e) => tracing::warn!("Ignoring CA bundle {}: {}", path.display(), e),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use rustls::client::danger::ServerCertVerifier;
+    use rustls::{
+        SignatureScheme,
+        client::danger::ServerCertVerifier,
+        pki_types::{CertificateDer, ServerName, UnixTime},
+    };
 
     #[test]
     fn test_no_verify_server_cert() {
@@ -153,8 +153,14 @@ pub fn reexec_with_sudo(args: &[&str]) -> Result<()> {
     let exe_path =
         std::env::current_exe().context("Failed to get current executable path")?;
 
+    // keep the instance, so the privileged half works on the same service
+    let instance = crate::config::instance()
+        .map(|name| vec!["--instance", name])
+        .unwrap_or_default();
+
     let status = Command::new("sudo")
         .arg(exe_path.as_os_str())
+        .args(instance)
         .args(args)
         .stdin(std::process::Stdio::inherit())
         .stdout(std::process::Stdio::inherit())
@@ -0,0 +1,146 @@
+//! Polling helpers for the blocking `wait` commands used in CI pipelines.
+
+use std::future::Future;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use tokio::time::Instant;
+
+use crate::util::shutdown::SHUTDOWN;
+
+/// Result of a wait command. Maps onto the process exit code so pipelines
+/// can gate on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitOutcome {
+    /// The awaited condition was reached (exit 0).
+    Reached,
+    /// The awaited state settled on a failure (exit 1).
+    Failed,
+    /// The timeout elapsed first (exit 2).
+    TimedOut,
+}
+
+impl WaitOutcome {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            WaitOutcome::Reached => 0,
+            WaitOutcome::Failed => 1,
+            WaitOutcome::TimedOut => 2,
+        }
+    }
+}
+
+/// Parse durations like `90`, `30s`, `10m`, `1h30m`. A bare number is seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut total = 0u64;
+    let mut num = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let n: u64 = num
+            .parse()
+            .map_err(|_| format!("invalid duration '{}'", s))?;
+        num.clear();
+        total += match c {
+            's' => n,
+            'm' => n * 60,
+            'h' => n * 3600,
+            'd' => n * 86400,
+            _ => return Err(format!("invalid duration unit '{}' in '{}'", c, s)),
+        };
+    }
+    if !num.is_empty() {
+        return Err(format!("missing unit after '{}' in '{}'", num, s));
+    }
+    Ok(Duration::from_secs(total))
+}
+
+/// Call `check` every `interval` until it returns an outcome or `timeout`
+/// elapses. Errors from `check` are logged and retried, so a flaky network
+/// during a rollout does not fail the pipeline.
+pub async fn poll_until<F, Fut>(
+    timeout: Duration,
+    interval: Duration,
+    mut check: F,
+) -> Result<WaitOutcome>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Option<WaitOutcome>>>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        match check().await {
+            Ok(Some(outcome)) => return Ok(outcome),
+            Ok(None) => {}
+            Err(e) => tracing::warn!("wait: {:#}", e),
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(WaitOutcome::TimedOut);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval.min(deadline - now)) => {}
+            _ = SHUTDOWN.cancelled() => bail!("interrupted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10x").is_err());
+        assert!(parse_duration("1h30").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_poll_until_times_out() {
+        let outcome = poll_until(
+            Duration::from_millis(30),
+            Duration::from_millis(5),
+            || async { Ok(None) },
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome, WaitOutcome::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_poll_until_retries_errors() {
+        let mut calls = 0;
+        let outcome = poll_until(Duration::from_secs(1), Duration::from_millis(1), || {
+            calls += 1;
+            let n = calls;
+            async move {
+                match n {
+                    1 => Err(anyhow::anyhow!("flaky")),
+                    2 => Ok(None),
+                    _ => Ok(Some(WaitOutcome::Reached)),
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(outcome, WaitOutcome::Reached);
+        assert_eq!(calls, 3);
+    }
+}
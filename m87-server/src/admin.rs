@@ -0,0 +1,109 @@
+//! `m87-server admin ...`: one-off bootstrapping of offline deployments.
+//!
+//! - `create-user --email <email> [--name <name>]` adds a local auth user
+//! - `set-password --email <email>` replaces the password of one
+//! - `init-ca [--force]` creates a CA and a server certificate signed by it
+//!
+//! Passwords are read from `M87_ADMIN_PASSWORD` or the first line of stdin.
+
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::{
+    api::certificate::create_local_ca,
+    config::AppConfig,
+    db::Mongo,
+    models::user::UserDoc,
+    response::{ServerError, ServerResult},
+    util::fs::write_private,
+};
+
+const USAGE: &str = "usage: m87-server admin <create-user --email <email> [--name <name>] | set-password --email <email> | init-ca [--force]>";
+
+const MIN_PASSWORD_LEN: usize = 12;
+
+pub async fn run(args: &[String], config: &AppConfig) -> ServerResult<()> {
+    let Some(command) = args.first() else {
+        return Err(ServerError::bad_request(USAGE));
+    };
+    match command.as_str() {
+        "create-user" => {
+            let email = flag(args, "--email").ok_or_else(|| ServerError::bad_request(USAGE))?;
+            let name = flag(args, "--name");
+            let password = read_password()?;
+            let db = connect(config).await?;
+            UserDoc::create_local(&db, &email, name, &password).await?;
+            println!("Created user {}", email);
+            if !config.admin_emails.contains(&email) {
+                println!("Add it to ADMIN_EMAILS to make it an instance admin");
+            }
+        }
+        "set-password" => {
+            let email = flag(args, "--email").ok_or_else(|| ServerError::bad_request(USAGE))?;
+            let password = read_password()?;
+            let db = connect(config).await?;
+            UserDoc::set_password(&db, &email, &password).await?;
+            println!("Password of {} updated", email);
+        }
+        "init-ca" => init_ca(config, args.iter().any(|a| a == "--force"))?,
+        _ => return Err(ServerError::bad_request(USAGE)),
+    }
+    Ok(())
+}
+
+fn flag(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|a| a == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+async fn connect(config: &AppConfig) -> ServerResult<Arc<Mongo>> {
+    let db = Arc::new(Mongo::connect(&config.mongo_uri, &config.mongo_db).await?);
+    db.ensure_indexes().await?;
+    Ok(db)
+}
+
+fn read_password() -> ServerResult<String> {
+    let password = match std::env::var("M87_ADMIN_PASSWORD") {
+        Ok(password) => password,
+        Err(_) => {
+            eprintln!("Password (read from stdin):");
+            let mut line = String::new();
+            std::io::stdin().lock().read_line(&mut line)?;
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(ServerError::bad_request(&format!(
+            "password must be at least {} characters",
+            MIN_PASSWORD_LEN
+        )));
+    }
+    Ok(password)
+}
+
+fn init_ca(config: &AppConfig, force: bool) -> ServerResult<()> {
+    let dir = PathBuf::from(&config.certificate_path);
+    let ca_path = dir.join("ca.pem");
+    if ca_path.exists() && !force {
+        return Err(ServerError::bad_request(&format!(
+            "{} already exists, pass --force to replace it and the server certificate",
+            ca_path.display()
+        )));
+    }
+    std::fs::create_dir_all(&dir)?;
+    let ca = create_local_ca(config)?;
+    std::fs::write(&ca_path, &ca.ca_cert)?;
+    write_private(&dir.join("ca.key"), &ca.ca_key)?;
+    std::fs::write(dir.join("fullchain.pem"), &ca.cert)?;
+    write_private(&dir.join("private.key"), &ca.key)?;
+    println!("Wrote CA certificate to {}", ca_path.display());
+    println!(
+        "Server certificate for {} written; set STAGING=0 so the server uses it",
+        config.public_address
+    );
+    println!("Clients trust it with: m87 config set --ca-bundle <path to ca.pem>");
+    Ok(())
+}
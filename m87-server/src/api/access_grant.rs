@@ -0,0 +1,226 @@
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::routing::{delete, get};
+use axum::{Json, Router};
+use m87_shared::access::{AccessGrant, CreateAccessGrantBody, MAX_GRANT_TTL_SECS};
+use m87_shared::roles::Role;
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::api::approval::operator;
+use crate::auth::claims::Claims;
+use crate::models::access_grant::AccessGrantDoc;
+use crate::models::audit_logs::AuditLogDoc;
+use crate::models::org;
+use crate::response::{ServerAppResult, ServerError, ServerResponse, ServerResult};
+use crate::util::app_state::AppState;
+use crate::util::pagination::RequestPagination;
+
+/// How often expired grants are looked for.
+const EXPIRY_TICK: Duration = Duration::from_secs(60);
+
+pub fn create_route() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_grants).post(create_grant))
+        .route("/{id}", delete(revoke_grant))
+}
+
+#[derive(Deserialize)]
+struct GrantQuery {
+    /// Also list expired and revoked grants
+    #[serde(default)]
+    all: bool,
+}
+
+fn grant_details(grant: &AccessGrantDoc) -> String {
+    let mut details = format!(
+        "role={} email={} expires_at={}",
+        grant.role.to_string(),
+        grant.email,
+        grant.expires_at.try_to_rfc3339_string().unwrap_or_default()
+    );
+    if let Some(reason) = &grant.reason {
+        details.push_str(&format!(" reason={}", reason));
+    }
+    details
+}
+
+async fn list_grants(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<GrantQuery>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<AccessGrant>> {
+    let docs = AccessGrantDoc::list(
+        &state.db,
+        &claims.scopes_with_min_role(Role::Admin)?,
+        query.all,
+        &pagination,
+    )
+    .await?;
+
+    Ok(ServerResponse::builder()
+        .body(docs.iter().map(|d| d.to_public()).collect())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn create_grant(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(body): Json<CreateAccessGrantBody>,
+) -> ServerAppResult<AccessGrant> {
+    if !body.email.contains('@') {
+        return Err(ServerError::bad_request(
+            "access can only be granted to a user's email",
+        ));
+    }
+    if body.ttl_secs == 0 || body.ttl_secs > MAX_GRANT_TTL_SECS {
+        return Err(ServerError::bad_request(&format!(
+            "ttl must be between 1s and {} days",
+            MAX_GRANT_TTL_SECS / 86400
+        )));
+    }
+    if body.role == Role::Owner {
+        return Err(ServerError::bad_request("ownership cannot be granted"));
+    }
+    let device_oid = ObjectId::parse_str(&body.device_id)
+        .map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
+
+    // Require Admin on this device
+    let device = claims
+        .find_one_with_scope_and_role(
+            &state.db.devices(),
+            doc! { "_id": &device_oid },
+            Role::Admin,
+        )
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+    if body.role.rank() > claims.get_role(&device)?.rank() {
+        return Err(ServerError::forbidden("Cannot grant a higher role"));
+    }
+
+    let requester_orgs = org::get_user_orgs(&state.db, &claims.user_email).await?;
+    let user_orgs = org::get_user_orgs(&state.db, &body.email).await?;
+    let do_share_orgs = requester_orgs.iter().any(|org| user_orgs.contains(org));
+    if !do_share_orgs && !state.config.allow_cros_org_device_sharing {
+        return Err(ServerError::forbidden(
+            "Cannot grant access to a user of a different organization",
+        ));
+    }
+
+    let grant = AccessGrantDoc::create(&state.db, &device, &body, &operator(&claims)).await?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!("Granted temporary access {}", &device_oid),
+        &grant_details(&grant),
+        Some(device_oid),
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(grant.to_public())
+        .status_code(axum::http::StatusCode::CREATED)
+        .build())
+}
+
+async fn revoke_grant(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<AccessGrant> {
+    let oid = ObjectId::parse_str(&id)?;
+    let grant = claims
+        .find_one_with_scope_and_role(&state.db.access_grants(), doc! { "_id": oid }, Role::Admin)
+        .await?
+        .ok_or_else(|| ServerError::not_found("Access grant not found"))?;
+
+    let revoked = AccessGrantDoc::revoke(&state.db, oid, &operator(&claims))
+        .await?
+        .ok_or_else(|| ServerError::bad_request("the grant already ended"))?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!("Revoked temporary access {}", &grant.device_id),
+        &grant_details(&grant),
+        Some(grant.device_id),
+    )
+    .await;
+    close_connections(&state, &grant).await;
+
+    Ok(ServerResponse::builder()
+        .body(revoked.to_public())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+/// Audit the end of each grant as it expires and close the grantee's relay
+/// connections to the device, until the server stops. Access itself ends with
+/// the expiry: claims only count unexpired grants, and every new stream checks
+/// them again.
+pub async fn run_expiry(state: AppState) {
+    let mut tick = tokio::time::interval(EXPIRY_TICK);
+    loop {
+        tick.tick().await;
+        if let Err(e) = audit_expired(&state).await {
+            warn!("Expiring access grants failed: {}", e);
+        }
+    }
+}
+
+async fn audit_expired(state: &AppState) -> ServerResult<()> {
+    while let Some(grant) = AccessGrantDoc::take_expired(&state.db).await? {
+        let claims = Claims {
+            roles: vec![],
+            is_admin: false,
+            user_name: "access grant expiry".to_string(),
+            user_email: grant.granted_by.clone(),
+            user_id: None,
+        };
+        let _ = AuditLogDoc::add(
+            &state.db,
+            &claims,
+            &state.config,
+            &format!("Temporary access expired {}", &grant.device_id),
+            &grant_details(&grant),
+            Some(grant.device_id),
+        )
+        .await;
+        close_connections(state, &grant).await;
+    }
+    Ok(())
+}
+
+/// Close the grantee's connections to the grant's device on this server.
+/// Grantees with access besides the grant can reconnect right away.
+async fn close_connections(state: &AppState, grant: &AccessGrantDoc) {
+    let device = match state
+        .db
+        .devices()
+        .find_one(doc! { "_id": grant.device_id })
+        .await
+    {
+        Ok(Some(device)) => device,
+        Ok(None) => return,
+        Err(e) => {
+            warn!(
+                "Looking up the device of an ended access grant failed: {}",
+                e
+            );
+            return;
+        }
+    };
+    let closed = state.relay.close_clients(&device.short_id, &grant.email);
+    if closed > 0 {
+        info!(
+            "Closed {} connections of {} to {} as their access ended",
+            closed, grant.email, device.short_id
+        );
+    }
+}
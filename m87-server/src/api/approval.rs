@@ -0,0 +1,251 @@
+use axum::extract::{Path, Query, State};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use m87_shared::approval::{ApprovalRequest, ApprovalStatus, DecideApprovalBody};
+use m87_shared::deploy_spec::DeploymentRevision;
+use m87_shared::roles::Role;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{DateTime, doc};
+use serde::Deserialize;
+
+use crate::auth::claims::Claims;
+use crate::models::approval::{ApprovalCheck, ApprovalRequestDoc};
+use crate::models::audit_logs::AuditLogDoc;
+use crate::models::device::DeviceDoc;
+use crate::response::{ServerAppResult, ServerError, ServerResponse, ServerResult};
+use crate::util::app_state::AppState;
+use crate::util::events::DeviceEventKind;
+use crate::util::pagination::RequestPagination;
+
+pub fn create_route() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_approvals))
+        .route("/{id}", post(decide_approval))
+}
+
+#[derive(Deserialize)]
+struct ApprovalQuery {
+    status: Option<ApprovalStatus>,
+    device_id: Option<String>,
+}
+
+/// Name approval requests and access grants record for the caller.
+pub(crate) fn operator(claims: &Claims) -> String {
+    if claims.user_email.is_empty() {
+        claims.user_name.clone()
+    } else {
+        claims.user_email.clone()
+    }
+}
+
+/// Gate for destructive operations on devices that require approval: passes
+/// once per approval of the caller's request for `operation`, otherwise files
+/// one (audited and announced as a device alert) and returns why the
+/// operation was refused.
+pub async fn require_approval(
+    state: &AppState,
+    claims: &Claims,
+    device: &DeviceDoc,
+    operation: &str,
+    details: &str,
+) -> ServerResult<Result<(), String>> {
+    match check_approval(state, claims, device, operation, details).await? {
+        Ok(approval) => {
+            use_approval(state, claims, device, operation, approval).await?;
+            Ok(Ok(()))
+        }
+        Err(message) => Ok(Err(message)),
+    }
+}
+
+/// First half of [`require_approval`], for operations on several devices:
+/// the approval to use up with [`use_approval`] once every device passed
+/// (`None` if the device needs none), or why the operation is refused.
+pub async fn check_approval(
+    state: &AppState,
+    claims: &Claims,
+    device: &DeviceDoc,
+    operation: &str,
+    details: &str,
+) -> ServerResult<Result<Option<ApprovalRequestDoc>, String>> {
+    if !device.require_approval {
+        return Ok(Ok(None));
+    }
+    let requested_by = operator(claims);
+    let check =
+        ApprovalRequestDoc::find_or_file(&state.db, device, &requested_by, operation, details)
+            .await?;
+    let request = match check {
+        ApprovalCheck::Approved(request) => return Ok(Ok(Some(request))),
+        ApprovalCheck::Filed(request) => {
+            let id = request.to_public().id;
+            let _ = AuditLogDoc::add(
+                &state.db,
+                claims,
+                &state.config,
+                &format!("Requested approval {} for {}", id, operation),
+                details,
+                device.id,
+            )
+            .await;
+            state.events.publish(
+                device,
+                DeviceEventKind::Alert {
+                    rule: "approval_requested".to_string(),
+                    message: format!(
+                        "{} asks to run {} on {} (request {})",
+                        requested_by, operation, device.name, id
+                    ),
+                },
+            );
+            request
+        }
+        ApprovalCheck::Pending(request) => request,
+    };
+    let id = request.to_public().id;
+    Ok(Err(format!(
+        "{} on {} needs a second operator's approval; request {} is pending \
+         (m87 approvals approve {})",
+        operation, device.name, id, id
+    )))
+}
+
+/// Use up an approval [`check_approval`] found. Fails if it was used or
+/// expired in between.
+pub async fn use_approval(
+    state: &AppState,
+    claims: &Claims,
+    device: &DeviceDoc,
+    operation: &str,
+    approval: Option<ApprovalRequestDoc>,
+) -> ServerResult<()> {
+    let Some(approval) = approval else {
+        return Ok(());
+    };
+    let id = approval
+        .id
+        .ok_or_else(|| ServerError::internal_error("approval request without id"))?;
+    let request = ApprovalRequestDoc::take(&state.db, id)
+        .await?
+        .ok_or_else(|| {
+            ServerError::forbidden(&format!(
+                "approval {} for {} on {} was used or expired meanwhile",
+                id, operation, device.name
+            ))
+        })?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        claims,
+        &state.config,
+        &format!("Used approval {} for {}", request.to_public().id, operation),
+        &format!(
+            "approved by {}",
+            request.decided_by.as_deref().unwrap_or_default()
+        ),
+        device.id,
+    )
+    .await;
+    Ok(())
+}
+
+/// What a deploy approval is given for: the revision's content hash, so a
+/// different revision needs its own approval, and its jobs for the approver.
+pub fn deploy_details(revision: &DeploymentRevision) -> String {
+    let jobs: Vec<&str> = revision.jobs.iter().map(|j| j.id.as_str()).collect();
+    format!(
+        "revision {} with jobs {}",
+        &revision.get_hash()[..12],
+        jobs.join(", ")
+    )
+}
+
+async fn list_approvals(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<ApprovalQuery>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<ApprovalRequest>> {
+    let mut filter = doc! {};
+    if let Some(device_id) = &query.device_id {
+        filter.insert("device_id", ObjectId::parse_str(device_id)?);
+    }
+    // pending and approved requests past their time are listed as expired
+    let now = DateTime::now();
+    match query.status {
+        Some(ApprovalStatus::Expired) => {
+            filter.insert("status", doc! { "$in": ["pending", "approved"] });
+            filter.insert("expires_at", doc! { "$lte": now });
+        }
+        Some(status @ (ApprovalStatus::Pending | ApprovalStatus::Approved)) => {
+            filter.insert("status", status.to_string());
+            filter.insert("expires_at", doc! { "$gt": now });
+        }
+        Some(status) => {
+            filter.insert("status", status.to_string());
+        }
+        None => {}
+    }
+    let docs = ApprovalRequestDoc::list(
+        &state.db,
+        &claims.scopes_with_min_role(Role::Viewer)?,
+        filter,
+        &pagination,
+    )
+    .await?;
+
+    Ok(ServerResponse::builder()
+        .body(docs.iter().map(|d| d.to_public()).collect())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn decide_approval(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<DecideApprovalBody>,
+) -> ServerAppResult<ApprovalRequest> {
+    let oid = ObjectId::parse_str(&id)?;
+    let request = claims
+        .find_one_with_access(&state.db.approval_requests(), doc! { "_id": oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Approval request not found"))?;
+    if !Role::allows(&claims.get_role(&request)?, &Role::Admin) {
+        return Err(ServerError::forbidden(
+            "approving operations needs the admin role on the device",
+        ));
+    }
+    // API keys have no person behind them that could be the second one
+    if claims.user_id.is_none() {
+        return Err(ServerError::forbidden("approvals must be given by a user"));
+    }
+    let decided_by = operator(&claims);
+    if decided_by == request.requested_by {
+        return Err(ServerError::forbidden(
+            "a request must be approved by someone other than its requester",
+        ));
+    }
+
+    let decided =
+        ApprovalRequestDoc::decide(&state.db, oid, body.approve, &decided_by, body.comment).await?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!(
+            "{} approval request {} for {} by {}",
+            if body.approve { "Approved" } else { "Rejected" },
+            id,
+            decided.operation,
+            decided.requested_by
+        ),
+        &decided.details,
+        Some(decided.device_id),
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(decided.to_public())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
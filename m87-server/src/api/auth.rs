@@ -1,41 +1,199 @@
-use axum::extract::State;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroU32;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, State};
 use axum::{
     Json, Router,
     routing::{get, post},
 };
-use mongodb::bson::doc;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use m87_shared::auth::{LocalLoginBody, LocalLoginResponse};
+use m87_shared::enrollment::{
+    CreateInstallCommandBody, DEFAULT_ENROLLMENT_TTL_SECS, InstallCommand, render_install_command,
+};
+use mongodb::bson::{doc, oid::ObjectId};
 use tokio::join;
 
 use crate::auth::claims::Claims;
+use crate::auth::local;
 use crate::models::api_key::{ApiKeyDoc, CreateApiKey};
 use crate::models::device::{CreateDeviceBody, DeviceDoc};
 use crate::models::device_auth_request::{
     self, AuthRequestAction, CheckAuthRequest, DeviceAuthRequest, DeviceAuthRequestBody,
     DeviceAuthRequestCheckResponse, DeviceAuthRequestDoc,
 };
+use crate::models::enrollment_token::EnrollmentTokenDoc;
+use crate::models::org;
+use crate::models::org_limits;
 use crate::models::roles::Role;
+use crate::models::user::UserDoc;
 use crate::response::{ResponsePagination, ServerAppResult, ServerError, ServerResponse};
 use crate::util::app_state::AppState;
 use crate::util::pagination::RequestPagination;
 
+static LOGIN_THROTTLE: LazyLock<LoginThrottle> = LazyLock::new(LoginThrottle::new);
+
+/// Limiter entries kept before idle ones are dropped
+const MAX_LOGIN_KEYS: usize = 10_000;
+
+fn login_quota(period: Duration, burst: u32) -> Quota {
+    Quota::with_period(period)
+        .unwrap()
+        .allow_burst(NonZeroU32::new(burst).unwrap())
+}
+
+struct LoginThrottle {
+    /// Login attempts per client address: a burst of 10, then one every 6 seconds.
+    by_ip: DefaultKeyedRateLimiter<IpAddr>,
+    /// Login attempts per account: a burst of 5, then one a minute, however
+    /// many addresses they come from.
+    by_account: DefaultKeyedRateLimiter<String>,
+}
+
+impl LoginThrottle {
+    fn new() -> Self {
+        Self {
+            by_ip: RateLimiter::keyed(login_quota(Duration::from_secs(6), 10)),
+            by_account: RateLimiter::keyed(login_quota(Duration::from_secs(60), 5)),
+        }
+    }
+
+    /// Count an attempt to log in to `email` from `ip`; false if either is
+    /// over its limit.
+    fn allow(&self, ip: IpAddr, email: &str) -> bool {
+        if self.by_ip.len() > MAX_LOGIN_KEYS {
+            self.by_ip.retain_recent();
+        }
+        if self.by_account.len() > MAX_LOGIN_KEYS {
+            self.by_account.retain_recent();
+        }
+        let account = email.trim().to_lowercase();
+        self.by_ip.check_key(&ip).is_ok() && self.by_account.check_key(&account).is_ok()
+    }
+}
+
 pub fn create_route() -> Router<AppState> {
     Router::new()
         .route("/request", get(get_auth_requests).post(post_auth_request))
         .route("/request/check", post(check_auth_request))
         .route("/request/approve", post(handle_auth_request))
+        .route("/login", post(local_login))
+        .route("/install-command", post(create_install_command))
+}
+
+/// Password login of servers in local auth mode. Attempts are throttled per
+/// client address and per account.
+async fn local_login(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Json(payload): Json<LocalLoginBody>,
+) -> ServerAppResult<LocalLoginResponse> {
+    if !state.config.local_auth {
+        return Err(ServerError::not_found(
+            "Password login is not enabled on this server",
+        ));
+    }
+    if !LOGIN_THROTTLE.allow(peer.ip(), &payload.email) {
+        tracing::warn!("Throttled login for {} from {}", payload.email, peer.ip());
+        return Err(ServerError::too_many_requests(
+            "Too many login attempts, try again later",
+        ));
+    }
+    let user = UserDoc::verify_password(&state.db, &payload.email, &payload.password)
+        .await
+        .inspect_err(|_| tracing::warn!("Failed login for {}", payload.email))?;
+    let (access_token, expires_at) = local::issue_token(&user, &state.config)?;
+    Ok(ServerResponse::builder()
+        .body(LocalLoginResponse {
+            access_token,
+            expires_at,
+        })
+        .ok()
+        .build())
 }
 
 async fn post_auth_request(
     State(state): State<AppState>,
     Json(payload): Json<DeviceAuthRequestBody>,
 ) -> ServerAppResult<String> {
-    let request_id = DeviceAuthRequestDoc::create(&state.db, payload).await?;
+    let approved = match &payload.enrollment_token {
+        Some(token) => {
+            EnrollmentTokenDoc::redeem(&state.db, token, &payload.owner_scope).await?;
+            true
+        }
+        None => false,
+    };
+    let request_id = DeviceAuthRequestDoc::create(&state.db, payload, approved).await?;
     Ok(ServerResponse::builder()
         .body(request_id)
         .status_code(axum::http::StatusCode::OK)
         .build())
 }
 
+/// A new enrollment token with the one-line install command that uses it;
+/// the token is not shown again.
+async fn create_install_command(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateInstallCommandBody>,
+) -> ServerAppResult<InstallCommand> {
+    payload
+        .validate()
+        .map_err(|e| ServerError::bad_request(&e))?;
+    let (owner_scope, owner_reference) = match &payload.org_id {
+        Some(org_id) => {
+            let scope = org::org_scope(org_id);
+            if !claims.has_scope_and_role(&scope, Role::Editor) {
+                return Err(ServerError::forbidden(
+                    "Only editors of the organization can enroll devices",
+                ));
+            }
+            (scope, org_id.clone())
+        }
+        None => (
+            format!("user:{}", claims.user_email),
+            claims.user_email.clone(),
+        ),
+    };
+    let max_uses = payload.max_uses.unwrap_or(1);
+    let (token_doc, token) = EnrollmentTokenDoc::create(
+        &state.db,
+        owner_scope,
+        &claims.user_email,
+        payload.ttl_secs.unwrap_or(DEFAULT_ENROLLMENT_TTL_SECS),
+        max_uses,
+    )
+    .await?;
+    tracing::info!(
+        "{} created an enrollment token for {} ({} uses)",
+        claims.user_email,
+        owner_reference,
+        max_uses
+    );
+
+    let server_url = format!("https://{}", state.config.public_address);
+    Ok(ServerResponse::builder()
+        .body(InstallCommand {
+            command: render_install_command(
+                &state.config.install_script_url,
+                &server_url,
+                &owner_reference,
+                &token,
+            ),
+            owner_reference,
+            server_url,
+            expires_at: token_doc
+                .expires_at
+                .try_to_rfc3339_string()
+                .unwrap_or_default(),
+            max_uses,
+        })
+        .ok()
+        .build())
+}
+
 async fn get_auth_requests(
     claims: Claims,
     State(state): State<AppState>,
@@ -90,6 +248,27 @@ async fn check_auth_request(
             .build());
     }
 
+    // A device that re-registers under its existing id (reinstall, lost
+    // credentials) keeps its identity, name and deployment history
+    let existing = match ObjectId::parse_str(&request.device_id) {
+        Ok(oid) => state.db.devices().find_one(doc! { "_id": oid }).await?,
+        Err(_) => None,
+    };
+
+    if let Some(device) = &existing
+        && device.owner_scope != request.owner_scope
+    {
+        return Err(ServerError::bad_request(
+            "Device is already registered to another owner",
+        ));
+    }
+
+    if existing.is_none()
+        && let Some(org_id) = request.owner_scope.strip_prefix("org:")
+    {
+        org_limits::check_new_device(&state.db, &state.config, org_id).await?;
+    }
+
     // Delete the request now that it's processed
     let _ = requests_col
         .delete_one(doc! { "request_id": &payload.request_id })
@@ -112,20 +291,33 @@ async fn check_auth_request(
     )
     .await?;
 
-    // request approved -> create device + API key, then delete request
-    let _ = DeviceDoc::create_from(
-        &state.db,
-        CreateDeviceBody {
-            id: Some(request.device_id.clone()),
-            name: request.device_info.hostname.clone(),
-            owner_scope: request.owner_scope.clone(),
-            allowed_scopes: vec![],
-            target_version: Some("latest".to_string()),
-            api_key_id: api_key_doc.id.clone().unwrap(),
-            system_info: request.device_info.clone(),
-        },
-    )
-    .await?;
+    match existing {
+        Some(device) => {
+            device
+                .rebind(
+                    &state.db,
+                    api_key_doc.id.unwrap(),
+                    &request.device_info,
+                )
+                .await?;
+        }
+        // request approved -> create device + API key, then delete request
+        None => {
+            DeviceDoc::create_from(
+                &state.db,
+                CreateDeviceBody {
+                    id: Some(request.device_id.clone()),
+                    name: request.device_info.hostname.clone(),
+                    owner_scope: request.owner_scope.clone(),
+                    allowed_scopes: vec![],
+                    target_version: Some("latest".to_string()),
+                    api_key_id: api_key_doc.id.unwrap(),
+                    system_info: request.device_info.clone(),
+                },
+            )
+            .await?;
+        }
+    }
 
     Ok(ServerResponse::builder()
         .body(DeviceAuthRequestCheckResponse {
@@ -143,13 +335,23 @@ async fn handle_auth_request(
 ) -> ServerAppResult<()> {
     let requests_col = state.db.device_auth_requests();
 
-    let _ = claims
+    let request = claims
         .find_one_with_access(&requests_col, doc! { "request_id": &payload.request_id })
         .await?
         .ok_or_else(|| ServerError::not_found("Auth request not found"))?;
 
     match payload.accept {
         true => {
+            // tell the approver now rather than the device on its next check
+            if let Some(org_id) = request.owner_scope.strip_prefix("org:") {
+                let registered = match ObjectId::parse_str(&request.device_id) {
+                    Ok(oid) => state.db.devices().find_one(doc! { "_id": oid }).await?,
+                    Err(_) => None,
+                };
+                if registered.is_none() {
+                    org_limits::check_new_device(&state.db, &state.config, org_id).await?;
+                }
+            }
             // Update request to mark as approved
             claims
                 .update_one_with_access(
@@ -169,3 +371,35 @@ async fn handle_auth_request(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn test_account_locks_out_whatever_the_address() {
+        let throttle = LoginThrottle::new();
+        for i in 0..5 {
+            assert!(throttle.allow(ip(i), "user@example.com"));
+        }
+        // spelled differently and from yet another address
+        assert!(!throttle.allow(ip(100), " User@Example.com"));
+        // other accounts are unaffected
+        assert!(throttle.allow(ip(101), "other@example.com"));
+    }
+
+    #[test]
+    fn test_address_locks_out_whatever_the_account() {
+        let throttle = LoginThrottle::new();
+        for i in 0..10 {
+            assert!(throttle.allow(ip(1), &format!("user{}@example.com", i)));
+        }
+        assert!(!throttle.allow(ip(1), "fresh@example.com"));
+        // other addresses are unaffected
+        assert!(throttle.allow(ip(2), "fresh@example.com"));
+    }
+}
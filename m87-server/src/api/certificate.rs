@@ -1,10 +1,12 @@
 use axum::{Extension, Json, extract::State};
+use m87_shared::host;
 use pem::parse_many;
 use rustls::pki_types::{PrivateKeyDer, PrivatePkcs1KeyDer, PrivatePkcs8KeyDer};
 use rustls::{
     ServerConfig as RustlsServerConfig, crypto::ring::default_provider, pki_types::CertificateDer,
 };
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::{fs, sync::watch};
@@ -111,7 +113,13 @@ pub async fn load_cert_and_key(
 pub fn create_selfsigned_pair(
     cfg: &AppConfig,
 ) -> ServerResult<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
-    let ck = rcgen::generate_simple_self_signed(vec![cfg.public_address.clone()])
+    let (public_host, _) = host::split_host_port(&cfg.public_address);
+    let mut names = vec![public_host.to_string()];
+    if public_host.parse::<IpAddr>().is_ok() {
+        // device SNIs of a relay at an IP literal
+        names.push(format!("*.{}", host::sni_domain(public_host)));
+    }
+    let ck = rcgen::generate_simple_self_signed(names)
         .map_err(|e| ServerError::internal_error(&format!("rcgen: {e}")))?;
 
     let cert = CertificateDer::from(ck.cert.der().to_vec());
@@ -120,17 +128,69 @@ pub fn create_selfsigned_pair(
     Ok((vec![cert], key))
 }
 
+/// PEM files of a local CA and a server certificate it signed
+pub struct LocalCa {
+    pub ca_cert: String,
+    pub ca_key: String,
+    pub cert: String,
+    pub key: String,
+}
+
+/// Create a CA and a certificate for `public_address` signed by it, for
+/// deployments without a public CA. Clients trust the CA certificate instead of
+/// accepting any certificate.
+pub fn create_local_ca(cfg: &AppConfig) -> ServerResult<LocalCa> {
+    let err = |e: rcgen::Error| ServerError::internal_error(&format!("rcgen: {e}"));
+
+    let ca_key = rcgen::KeyPair::generate().map_err(err)?;
+    let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).map_err(err)?;
+    ca_params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "m87 server CA");
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    ca_params.key_usages = vec![
+        rcgen::KeyUsagePurpose::KeyCertSign,
+        rcgen::KeyUsagePurpose::CrlSign,
+    ];
+    let ca_cert = ca_params.self_signed(&ca_key).map_err(err)?;
+    let ca_key_pem = ca_key.serialize_pem();
+    let issuer = rcgen::Issuer::new(ca_params, ca_key);
+
+    let (public_host, _) = host::split_host_port(&cfg.public_address);
+    // the relay itself and the device SNIs under it
+    let names = vec![
+        public_host.to_string(),
+        format!("*.{}", host::sni_domain(public_host)),
+    ];
+    let key = rcgen::KeyPair::generate().map_err(err)?;
+    let mut params = rcgen::CertificateParams::new(names).map_err(err)?;
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, public_host);
+    let cert = params.signed_by(&key, &issuer).map_err(err)?;
+
+    Ok(LocalCa {
+        ca_cert: ca_cert.pem(),
+        ca_key: ca_key_pem,
+        cert: cert.pem(),
+        key: key.serialize_pem(),
+    })
+}
+
 pub async fn create_tls_config(cfg: &AppConfig) -> ServerResult<RustlsServerConfig> {
     let (certs, key) = load_cert_and_key(cfg).await?;
 
     let provider = Arc::new(default_provider());
-    let tls = RustlsServerConfig::builder_with_provider(provider)
+    let mut tls = RustlsServerConfig::builder_with_provider(provider)
         .with_protocol_versions(&[&rustls::version::TLS13])
         .unwrap()
         .with_no_client_auth()
         .with_single_cert(certs, key)
         .map_err(|e| ServerError::internal_error(&format!("TLS build: {e}")))?;
 
+    // gRPC clients require h2 to be negotiated via ALPN
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
     Ok(tls)
 }
 
@@ -84,6 +84,13 @@ impl ClientConn {
         }
     }
 
+    pub fn close(&self, reason: &[u8]) {
+        match self {
+            ClientConn::Raw(conn) => conn.close(0u32.into(), reason),
+            ClientConn::Web(web) => web.quinn_conn.close(0u32.into(), reason),
+        }
+    }
+
     pub async fn closed(&self) {
         match self {
             ClientConn::Raw(conn) => {
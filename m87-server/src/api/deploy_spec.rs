@@ -1,18 +1,23 @@
-use axum::extract::{Path, State};
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
 use axum::routing::get;
 use axum::{Json, Router};
 use m87_shared::deploy_spec::{
-    CreateDeployRevisionBody, DeployReport, DeploymentRevision, DeploymentStatusSnapshot,
-    UpdateDeployRevisionBody,
+    CoreDump, CreateDeployRevisionBody, DeployReport, DeploymentRevision, DeploymentStatusSnapshot,
+    SnapshotQuery, StepArtifacts, UpdateDeployRevisionBody,
 };
 use mongodb::bson::{doc, oid::ObjectId};
 
+use crate::api::approval::{deploy_details, require_approval};
 use crate::auth::claims::Claims;
 use crate::models::audit_logs::AuditLogDoc;
+use crate::models::deploy_file::{DeployFileDoc, ref_changes};
 use crate::models::deploy_spec::{
     DeployReportDoc, DeployRevisionDoc, to_report_delete_doc, to_update_doc,
 };
 use crate::models::device::DeviceDoc;
+use crate::models::org_limits;
 use crate::response::{ResponsePagination, ServerAppResult, ServerError, ServerResponse};
 use crate::util::app_state::AppState;
 use crate::util::pagination::RequestPagination;
@@ -39,13 +44,35 @@ pub fn create_route() -> Router<AppState> {
             "/{device_id}/revisions/{revision_id}/reports",
             get(list_device_revision_reports),
         )
+        .route(
+            "/{device_id}/revisions/{revision_id}/runs/{run_id}/artifacts",
+            get(list_run_artifacts),
+        )
         //get deployment snapshot
+        .route("/{device_id}/cores", get(list_core_dumps))
+        .route("/{device_id}/cores/{core_id}", get(get_core_dump))
         .route(
             "/{device_id}/revisions/{revision_id}/snapshot",
             get(get_device_revision_snapshot),
         )
 }
 
+/// How often files no revision refers to anymore are deleted
+const FILE_SWEEP_TICK: Duration = Duration::from_secs(3600);
+
+/// Delete stored run files left unreferenced, until the server stops.
+pub async fn run_file_sweep(state: AppState) {
+    let mut tick = tokio::time::interval(FILE_SWEEP_TICK);
+    loop {
+        tick.tick().await;
+        match DeployFileDoc::sweep(&state.db).await {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!("Deleted {} unreferenced deployment files", deleted),
+            Err(e) => tracing::warn!("Sweeping deployment files failed: {}", e),
+        }
+    }
+}
+
 async fn list_device_revisions(
     claims: Claims,
     State(state): State<AppState>,
@@ -68,7 +95,8 @@ async fn list_device_revisions(
 
     let docs = DeployRevisionDoc::list_for_device(&state.db, device_oid, &pagination).await?;
     let total_count = docs.len() as u64;
-    let out: Vec<DeploymentRevision> = docs.into_iter().map(|doc| doc.revision).collect();
+    let mut out: Vec<DeploymentRevision> = docs.into_iter().map(|doc| doc.revision).collect();
+    DeployFileDoc::attach(&state.db, &mut out).await?;
 
     Ok(ServerResponse::builder()
         .body(out)
@@ -136,6 +164,11 @@ async fn create_device_revision(
     let revision: m87_shared::deploy_spec::DeploymentRevision =
         m87_shared::deploy_spec::DeploymentRevision::from_yaml(&payload.revision)
             .map_err(|e| ServerError::internal_error(&format!("{:?}", e)))?;
+    let details = deploy_details(&revision);
+    org_limits::check_new_revision(&state.db, &state.config, &device).await?;
+    if let Err(message) = require_approval(&state, &claims, &device, "deploy", &details).await? {
+        return Err(ServerError::forbidden(&message));
+    }
 
     let doc = DeployRevisionDoc::create(
         &state.db,
@@ -184,7 +217,7 @@ async fn get_revision_by_id(
 
     let doc = doc_opt.ok_or_else(|| ServerError::not_found("Revision not found"))?;
     Ok(ServerResponse::builder()
-        .body(doc.revision)
+        .body(doc.into_revision(&state.db).await?)
         .status_code(axum::http::StatusCode::OK)
         .build())
 }
@@ -211,7 +244,20 @@ async fn update_revision_by_id(
     )
     .await;
 
-    let (update_doc, extra_filter) = to_update_doc(&payload)?;
+    let device = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": device_oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+    let details = format!(
+        "update of revision {}: {}",
+        id,
+        serde_json::to_string(&payload).unwrap_or_default()
+    );
+    if let Err(message) = require_approval(&state, &claims, &device, "deploy", &details).await? {
+        return Err(ServerError::forbidden(&message));
+    }
+
+    let (update_doc, extra_filter) = to_update_doc(&state.db, &payload).await?;
     let report_delete_doc = to_report_delete_doc(&payload, &id, &device_oid)?;
 
     // if its an update with a new revision that is set as active. set the old active to false
@@ -235,6 +281,13 @@ async fn update_revision_by_id(
     if let Some(extra) = extra_filter {
         filter.extend(extra);
     }
+    // by _id, as replacing the revision may change its id
+    let target = state
+        .db
+        .deploy_revisions()
+        .find_one(filter.clone())
+        .await?
+        .and_then(|doc| doc.id);
     let success = claims
         .update_one_with_access::<DeployRevisionDoc>(
             &state.db.deploy_revisions(),
@@ -246,6 +299,9 @@ async fn update_revision_by_id(
     if !success {
         return Err(ServerError::not_found("Revision not found"));
     }
+    if let Some(target) = target {
+        DeployRevisionDoc::sync_file_refs(&state.db, target).await?;
+    }
     if let Some((filter, update_doc)) = set_inactive {
         let success = claims
             .update_one_with_access::<DeployRevisionDoc>(
@@ -315,15 +371,18 @@ async fn delete_revision(
     .await;
 
     // authorize by selecting first
+    let filter = doc! { "revision.id": &id, "device_id": &device_oid };
+    let stored = state.db.deploy_revisions().find_one(filter.clone()).await?;
     let success = claims
-        .delete_one_with_access(
-            &state.db.deploy_revisions(),
-            doc! { "revision.id": &id, "device_id": &device_oid },
-        )
+        .delete_one_with_access(&state.db.deploy_revisions(), filter)
         .await?;
     if !success {
         return Err(ServerError::not_found("Revision not found"));
     }
+    if let Some(stored) = stored {
+        let (_, removed) = ref_changes(&stored.file_hashes, &[]);
+        DeployFileDoc::update_refs(&state.db, &[], &removed).await?;
+    }
 
     let _ = AuditLogDoc::add(
         &state.db,
@@ -380,10 +439,99 @@ async fn list_device_revision_reports(
         .build())
 }
 
+/// Files the run's steps left behind, from each step's latest report with any.
+async fn list_run_artifacts(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((device_id, revision_id, run_id)): Path<(String, String, String)>,
+) -> ServerAppResult<Vec<StepArtifacts>> {
+    let device_oid = ObjectId::parse_str(&device_id)
+        .map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
+
+    // Ensure caller can access the device
+    let dev_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    if dev_opt.is_none() {
+        return Err(ServerError::not_found("Device not found"));
+    }
+
+    let artifacts =
+        DeployReportDoc::latest_step_artifacts(&state.db, &device_oid, &revision_id, &run_id)
+            .await?;
+
+    Ok(ServerResponse::builder()
+        .body(artifacts)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn list_core_dumps(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(device_id): Path<String>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<CoreDump>> {
+    let device_oid = ObjectId::parse_str(&device_id)
+        .map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
+
+    // Ensure caller can access the device
+    let dev_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    if dev_opt.is_none() {
+        return Err(ServerError::not_found("Device not found"));
+    }
+
+    let cores = DeployReportDoc::list_core_dumps(&state.db, &device_oid, &pagination).await?;
+    let count = cores.len() as u64;
+
+    Ok(ServerResponse::builder()
+        .body(cores)
+        .status_code(axum::http::StatusCode::OK)
+        .pagination(ResponsePagination {
+            count,
+            offset: pagination.offset,
+            limit: pagination.limit,
+        })
+        .build())
+}
+
+async fn get_core_dump(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((device_id, core_id)): Path<(String, String)>,
+) -> ServerAppResult<CoreDump> {
+    let device_oid = ObjectId::parse_str(&device_id)
+        .map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
+    let core_oid =
+        ObjectId::parse_str(&core_id).map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
+
+    // Ensure caller can access the device
+    let dev_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    if dev_opt.is_none() {
+        return Err(ServerError::not_found("Device not found"));
+    }
+
+    let core = DeployReportDoc::get_core_dump(&state.db, &device_oid, &core_oid)
+        .await?
+        .ok_or_else(|| ServerError::not_found("Core dump not found"))?;
+
+    Ok(ServerResponse::builder()
+        .body(core)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+/// The revision's status, or part of it with `offset`/`limit`, `run_id` or
+/// `no_steps` for revisions with many runs.
 async fn get_device_revision_snapshot(
     claims: Claims,
     State(state): State<AppState>,
     Path((device_id, revision_id)): Path<(String, String)>,
+    Query(query): Query<SnapshotQuery>,
 ) -> ServerAppResult<DeploymentStatusSnapshot> {
     let device_oid = ObjectId::parse_str(&device_id)
         .map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
@@ -396,12 +544,13 @@ async fn get_device_revision_snapshot(
         return Err(ServerError::not_found("Device not found"));
     }
 
-    let snapshot = DeployReportDoc::compute_deployment_status_snapshot_for_device(
+    let mut snapshot = DeployReportDoc::compute_deployment_status_snapshot_for_device(
         &state.db,
         &device_oid,
         &revision_id,
     )
     .await?;
+    snapshot.select(&query);
 
     Ok(ServerResponse::builder()
         .body(snapshot)
@@ -1,25 +1,50 @@
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
 use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use m87_shared::device::{AddDeviceAccessBody, AuditLog, DeviceStatus};
+use futures::TryStreamExt;
+use m87_shared::bandwidth::DeviceBandwidth;
+use m87_shared::device::{
+    AddDeviceAccessBody, AuditLog, DeviceStatus, DeviceTimelineEvent, MergeDeviceBody,
+    TimelineEventKind, validate_device_name,
+};
+use m87_shared::fleet_report::{FLEET_REPORT_VERSION, FleetReport, fleet_report_csv};
+use m87_shared::inventory::DeviceVulnerabilities;
+use m87_shared::metrics::Location;
+use m87_shared::patching::PatchRun;
+use m87_shared::probe::{ProbeRecord, ProbeStatus, SetProbesBody, validate_probes};
 use m87_shared::roles::Role;
 use m87_shared::users::User;
-use mongodb::bson::doc;
 use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{DateTime, doc};
+use serde::Deserialize;
 
 use crate::api::deploy_spec::create_route as deploy_spec_route;
+use crate::api::web_terminal::create_route as web_terminal_route;
+use crate::auth::access_control::AccessControlled;
 use crate::auth::claims::Claims;
 use crate::models::audit_logs::AuditLogDoc;
+use crate::models::deploy_spec::DeployReportDoc;
 use crate::models::device::{DeviceDoc, PublicDevice, UpdateDeviceBody};
+use crate::models::device_history::DeviceHistoryDoc;
+use crate::models::device_inventory::DeviceInventoryDoc;
+use crate::models::device_location::DeviceLocationDoc;
 use crate::models::org;
+use crate::models::patching::PatchRunDoc;
+use crate::models::probe::ProbeResultDoc;
 use crate::models::user::UserDoc;
-use crate::response::{ResponsePagination, ServerAppResult, ServerError, ServerResponse};
+use crate::response::{
+    ResponsePagination, ServerAppResult, ServerError, ServerResponse, ServerResult,
+};
 use crate::util::app_state::AppState;
 use crate::util::pagination::RequestPagination;
 
 pub fn create_route() -> Router<AppState> {
     Router::new()
         .route("/", get(get_devices))
+        .route("/report", get(get_fleet_report))
+        .route("/report/csv", get(get_fleet_report_csv))
         .route(
             "/{id}",
             get(get_device_by_id)
@@ -27,7 +52,19 @@ pub fn create_route() -> Router<AppState> {
                 .delete(delete_device),
         )
         .route("/{id}/status", get(get_device_status))
+        .route("/{id}/merge", post(merge_device))
         .route("/{id}/audit_logs", get(get_audit_logs_by_device_id))
+        .route("/{id}/events", get(get_device_events))
+        .route("/{id}/location", get(get_device_location))
+        .route("/{id}/location/history", get(get_device_location_history))
+        .route("/{id}/vulnerabilities", get(get_device_vulnerabilities))
+        .route("/{id}/bandwidth", get(get_device_bandwidth))
+        .route("/{id}/patch-runs", get(get_device_patch_runs))
+        .route(
+            "/{id}/probes",
+            get(get_device_probes).post(set_device_probes),
+        )
+        .route("/{id}/probes/results", get(get_device_probe_results))
         .route("/{id}/users", get(get_device_users))
         .route("/{id}/access", post(add_device_access))
         .route(
@@ -35,6 +72,7 @@ pub fn create_route() -> Router<AppState> {
             delete(remove_device_access),
         )
         .merge(deploy_spec_route())
+        .merge(web_terminal_route())
 }
 
 async fn get_devices(
@@ -73,6 +111,54 @@ async fn get_devices(
         .build())
 }
 
+/// Rows of all devices the caller can see, by name.
+async fn fleet_report(claims: &Claims, state: &AppState) -> ServerResult<FleetReport> {
+    let filter = DeviceDoc::access_filter(&claims.scopes_with_min_role(Role::Viewer)?);
+    let devices: Vec<DeviceDoc> = state
+        .db
+        .devices()
+        .find(filter)
+        .sort(doc! { "name": 1 })
+        .await?
+        .try_collect()
+        .await?;
+
+    let now = DateTime::now();
+    let mut rows = Vec::with_capacity(devices.len());
+    for device in devices {
+        let Ok(role) = claims.get_role(&device) else {
+            continue;
+        };
+        let online = state.relay.has_tunnel(&device.short_id).await;
+        rows.push(device.report_row(&state.db, &role, online, now).await?);
+    }
+    Ok(FleetReport {
+        version: FLEET_REPORT_VERSION,
+        generated_at: now.try_to_rfc3339_string().unwrap_or_default(),
+        devices: rows,
+    })
+}
+
+async fn get_fleet_report(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> ServerAppResult<FleetReport> {
+    let report = fleet_report(&claims, &state).await?;
+    Ok(ServerResponse::builder().body(report).ok().build())
+}
+
+async fn get_fleet_report_csv(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> Result<Response, ServerError> {
+    let report = fleet_report(&claims, &state).await?;
+    Ok((
+        [(CONTENT_TYPE, "text/csv; charset=utf-8")],
+        fleet_report_csv(&report.devices),
+    )
+        .into_response())
+}
+
 async fn get_device_by_id(
     claims: Claims,
     State(state): State<AppState>,
@@ -114,6 +200,40 @@ async fn update_device_by_id(
         Some(device_id.clone()),
     )
     .await;
+    if let Some(rules) = &payload.alert_rules {
+        let errors = rules.condition_errors();
+        if !errors.is_empty() {
+            return Err(ServerError::bad_request(&format!(
+                "invalid alert conditions: {}",
+                errors.join("; ")
+            )));
+        }
+    }
+    if payload.require_approval.is_some() {
+        // otherwise an operator could lift the approval requirement on their own
+        let device = claims
+            .find_one_with_access(&state.db.devices(), doc! { "_id": device_id })
+            .await?
+            .ok_or_else(|| ServerError::not_found("Device not found"))?;
+        if !Role::allows(&claims.get_role(&device)?, &Role::Owner) {
+            return Err(ServerError::forbidden(
+                "only owners can change whether a device requires approvals",
+            ));
+        }
+    }
+    if let Some(name) = &payload.name {
+        validate_device_name(name).map_err(|e| ServerError::bad_request(&e))?;
+        let device = claims
+            .find_one_with_access(&state.db.devices(), doc! { "_id": device_id })
+            .await?
+            .ok_or_else(|| ServerError::not_found("Device not found"))?;
+        if DeviceDoc::name_taken(&state.db, &device.owner_scope, name, Some(&device_id)).await? {
+            return Err(ServerError::bad_request(&format!(
+                "a device named '{}' already exists",
+                name
+            )));
+        }
+    }
     // Build the Mongo update document
     let update_doc = payload.to_update_doc(); // implement this helper on UpdateDeviceBody
 
@@ -151,6 +271,79 @@ async fn update_device_by_id(
         .build())
 }
 
+async fn merge_device(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<MergeDeviceBody>,
+) -> ServerAppResult<PublicDevice> {
+    let device_oid =
+        ObjectId::parse_str(&id).map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
+    let source_oid = ObjectId::parse_str(&payload.from)
+        .map_err(|_| ServerError::bad_request("Invalid ObjectId"))?;
+    if device_oid == source_oid {
+        return Err(ServerError::bad_request(
+            "Cannot merge a device into itself",
+        ));
+    }
+
+    // Require Admin on both devices
+    let devices_col = state.db.devices();
+    let device = claims
+        .find_one_with_scope_and_role(&devices_col, doc! { "_id": &device_oid }, Role::Admin)
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+    let source = claims
+        .find_one_with_scope_and_role(&devices_col, doc! { "_id": &source_oid }, Role::Admin)
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device to merge not found"))?;
+    if device.owner_scope != source.owner_scope {
+        return Err(ServerError::forbidden(
+            "Cannot merge devices with different owners",
+        ));
+    }
+
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!(
+            "Requested device merge {} into {}",
+            &source_oid, &device_oid
+        ),
+        &format!("{}", &payload),
+        Some(device_oid),
+    )
+    .await;
+
+    device.merge_from(&state.db, &source).await?;
+
+    let merged = claims
+        .find_one_with_access(&devices_col, doc! { "_id": device_oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found after merge"))?;
+    let role = claims.get_role(&merged)?;
+    let pub_device = merged.to_public_device(&role);
+
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!(
+            "Merged device {} ({}) into {}",
+            &source.name, &source_oid, &device_oid
+        ),
+        "",
+        Some(device_oid),
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(pub_device)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
 async fn delete_device(
     claims: Claims,
     State(state): State<AppState>,
@@ -211,6 +404,355 @@ async fn get_audit_logs_by_device_id(
         .build())
 }
 
+/// Connectivity history, deployment reports and (for admins) audit entries of
+/// the device, merged oldest first. `limit` and `offset` count from the newest.
+async fn get_device_events(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<DeviceTimelineEvent>> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device = claims
+        .find_one_with_scope_and_role::<DeviceDoc>(
+            &state.db.devices(),
+            doc! { "_id": &device_oid },
+            Role::Viewer,
+        )
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+
+    // every source may hold the newest `offset + limit` events
+    let per_source = RequestPagination {
+        offset: 0,
+        limit: (pagination.offset + pagination.limit as u64).min(1000) as u32,
+        since: pagination.since,
+        until: pagination.until,
+    };
+
+    let mut events: Vec<DeviceTimelineEvent> =
+        DeviceHistoryDoc::list_for_device(&state.db, device_oid, &per_source)
+            .await?
+            .into_iter()
+            .filter_map(|h| {
+                Some(DeviceTimelineEvent {
+                    timestamp: h.timestamp.try_to_rfc3339_string().ok()?,
+                    kind: h.kind,
+                    summary: h.summary,
+                    actor: None,
+                })
+            })
+            .collect();
+    events.extend(
+        DeployReportDoc::list_timeline(&state.db, &device_oid, &per_source)
+            .await?
+            .iter()
+            .filter_map(|r| r.to_timeline_event()),
+    );
+    // audit entries are admin only, as on /audit_logs
+    if claims
+        .get_role(&device)
+        .is_ok_and(|role| Role::allows(&role, &Role::Admin))
+    {
+        let audit_logs = AuditLogDoc::list_for_device(&state.db, device_oid, &per_source).await?;
+        events.extend(audit_logs.into_iter().filter_map(|log| {
+            let kind = match log.action.as_str() {
+                "Connected to device" | "Opened web terminal" => TimelineEventKind::Session,
+                _ => TimelineEventKind::Audit,
+            };
+            Some(DeviceTimelineEvent {
+                timestamp: log.timestamp.try_to_rfc3339_string().ok()?,
+                kind,
+                summary: log.action,
+                actor: Some(log.user_name),
+            })
+        }));
+    }
+
+    // all timestamps come from the same formatter, so they sort as strings
+    events.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+    let mut events: Vec<_> = events
+        .into_iter()
+        .skip(pagination.offset as usize)
+        .take(pagination.limit as usize)
+        .collect();
+    events.reverse();
+    annotate_reconnects(&mut events);
+
+    Ok(ServerResponse::builder()
+        .body(events)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+/// Add how long the device was offline to each reconnect that follows a
+/// disconnect, the heartbeat gaps of the timeline.
+fn annotate_reconnects(events: &mut [DeviceTimelineEvent]) {
+    let mut disconnected_at: Option<chrono::DateTime<chrono::Utc>> = None;
+    for event in events.iter_mut() {
+        let Ok(at) = event.timestamp.parse::<chrono::DateTime<chrono::Utc>>() else {
+            continue;
+        };
+        match event.kind {
+            TimelineEventKind::Disconnected => disconnected_at = Some(at),
+            TimelineEventKind::Connected => {
+                if let Some(since) = disconnected_at.take() {
+                    let secs = (at - since).num_seconds().max(0);
+                    event.summary = format!(
+                        "{} after {} offline",
+                        event.summary,
+                        format_gap(secs as u64)
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn format_gap(secs: u64) -> String {
+    match secs {
+        s if s < 60 => format!("{}s", s),
+        s if s < 3600 => format!("{}m {}s", s / 60, s % 60),
+        s if s < 86400 => format!("{}h {}m", s / 3600, (s % 3600) / 60),
+        s => format!("{}d {}h", s / 86400, (s % 86400) / 3600),
+    }
+}
+
+async fn get_device_location(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<Location> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    let device = device_opt.ok_or_else(|| ServerError::not_found("Device not found"))?;
+    let location = device
+        .last_location
+        .ok_or_else(|| ServerError::not_found("Device has not reported a location"))?;
+
+    Ok(ServerResponse::builder()
+        .body(location)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn get_device_location_history(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<Location>> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    let _ = device_opt.ok_or_else(|| ServerError::not_found("Device not found"))?;
+
+    let locations = DeviceLocationDoc::list_for_device(&state.db, device_oid, &pagination).await?;
+
+    Ok(ServerResponse::builder()
+        .body(locations)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn get_device_vulnerabilities(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<DeviceVulnerabilities> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    let device = device_opt.ok_or_else(|| ServerError::not_found("Device not found"))?;
+    let inventory = DeviceInventoryDoc::for_device(&state.db, device_oid)
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device has not reported its packages"))?;
+
+    Ok(ServerResponse::builder()
+        .body(inventory.to_device_vulnerabilities(&device))
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn get_device_bandwidth(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<DeviceBandwidth> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    let device = device_opt.ok_or_else(|| ServerError::not_found("Device not found"))?;
+
+    Ok(ServerResponse::builder()
+        .body(device.bandwidth)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn get_device_patch_runs(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<PatchRun>> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    let _ = device_opt.ok_or_else(|| ServerError::not_found("Device not found"))?;
+
+    let runs = PatchRunDoc::list_for_device(&state.db, device_oid, &pagination).await?;
+
+    Ok(ServerResponse::builder()
+        .body(runs.iter().map(|r| r.to_patch_run()).collect())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+/// Probes of the device with their last result and checks of the last day.
+async fn probe_statuses(state: &AppState, device: &DeviceDoc) -> ServerResult<Vec<ProbeStatus>> {
+    let since = DateTime::from_system_time(
+        DateTime::now().to_system_time() - std::time::Duration::from_hours(24),
+    );
+    let counts = ProbeResultDoc::counts_since(&state.db, device.id.unwrap(), since).await?;
+    Ok(device
+        .config
+        .probes
+        .iter()
+        .map(|probe| {
+            let (checks_24h, ok_24h) = counts.get(&probe.name).copied().unwrap_or_default();
+            ProbeStatus {
+                probe: probe.clone(),
+                last: device
+                    .last_probe_results
+                    .iter()
+                    .find(|r| r.name == probe.name)
+                    .cloned(),
+                checks_24h,
+                ok_24h,
+            }
+        })
+        .collect())
+}
+
+async fn get_device_probes(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<Vec<ProbeStatus>> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+
+    Ok(ServerResponse::builder()
+        .body(probe_statuses(&state, &device).await?)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+/// Replace the device's probes; it picks them up with its next heartbeat.
+async fn set_device_probes(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<SetProbesBody>,
+) -> ServerAppResult<Vec<ProbeStatus>> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device = claims
+        .find_one_with_scope_and_role(
+            &state.db.devices(),
+            doc! { "_id": &device_oid },
+            Role::Editor,
+        )
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+    validate_probes(&body.probes).map_err(|e| ServerError::bad_request(&e))?;
+
+    // results of a changed probe say nothing about the new one
+    let kept: Vec<_> = device
+        .last_probe_results
+        .iter()
+        .filter(|r| {
+            let old = device.config.probes.iter().find(|p| p.name == r.name);
+            let new = body.probes.iter().find(|p| p.name == r.name);
+            old.is_some() && old == new
+        })
+        .cloned()
+        .collect();
+    let updated = state
+        .db
+        .devices()
+        .find_one_and_update(
+            doc! { "_id": device_oid },
+            doc! { "$set": {
+                "config.probes": mongodb::bson::to_bson(&body.probes).unwrap(),
+                "last_probe_results": mongodb::bson::to_bson(&kept).unwrap(),
+                "updated_at": DateTime::now(),
+            }},
+        )
+        .return_document(mongodb::options::ReturnDocument::After)
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+
+    let details: Vec<String> = body
+        .probes
+        .iter()
+        .map(|p| format!("{}: {} every {}s", p.name, p.target, p.interval_secs))
+        .collect();
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!("Updated probes on device {}", device.name),
+        &details.join("\n"),
+        Some(device_oid),
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(probe_statuses(&state, &updated).await?)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+#[derive(Deserialize)]
+struct ProbeResultsQuery {
+    /// Only results of this probe
+    name: Option<String>,
+}
+
+async fn get_device_probe_results(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<ProbeResultsQuery>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<ProbeRecord>> {
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device_opt = claims
+        .find_one_with_access(&state.db.devices(), doc! { "_id": &device_oid })
+        .await?;
+    let _ = device_opt.ok_or_else(|| ServerError::not_found("Device not found"))?;
+
+    let results =
+        ProbeResultDoc::list_for_device(&state.db, device_oid, query.name.as_deref(), &pagination)
+            .await?;
+
+    Ok(ServerResponse::builder()
+        .body(results.iter().map(|r| r.to_record()).collect())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
 async fn get_device_status(
     claims: Claims,
     State(state): State<AppState>,
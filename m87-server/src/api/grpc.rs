@@ -0,0 +1,335 @@
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt};
+use m87_shared::deploy_spec::DeploymentRevision;
+use m87_shared::grpc::v1::{self, make87_server::Make87, make87_server::Make87Server};
+use mongodb::bson::{doc, oid::ObjectId};
+use tokio_stream::wrappers::BroadcastStream;
+use tonic::{Request, Response, Status};
+
+use crate::api::approval::{deploy_details, require_approval};
+use crate::auth::claims::Claims;
+use crate::models::audit_logs::AuditLogDoc;
+use crate::models::deploy_file::DeployFileDoc;
+use crate::models::deploy_spec::{DeployReportDoc, DeployRevisionDoc};
+use crate::models::device::DeviceDoc;
+use crate::models::org_limits;
+use crate::response::{ServerError, ServerResult};
+use crate::util::app_state::AppState;
+use crate::util::events::{DeviceEvent, DeviceEventKind};
+use crate::util::pagination::RequestPagination;
+
+/// gRPC routes, served next to the REST API on the unified port.
+pub fn create_router(state: AppState) -> axum::Router {
+    tonic::service::Routes::new(Make87Server::new(GrpcApi { state })).into_axum_router()
+}
+
+struct GrpcApi {
+    state: AppState,
+}
+
+fn parse_oid(id: &str) -> ServerResult<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| ServerError::bad_request("Invalid ObjectId"))
+}
+
+fn to_pagination(p: Option<v1::Pagination>) -> RequestPagination {
+    let p = p.unwrap_or_default();
+    RequestPagination {
+        offset: p.offset,
+        limit: match p.limit {
+            0 => 50,
+            l => l.min(RequestPagination::max_limit().limit),
+        },
+        since: None,
+        until: None,
+    }
+}
+
+impl GrpcApi {
+    async fn claims<T>(&self, req: &Request<T>) -> ServerResult<Claims> {
+        let token = req
+            .metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .ok_or_else(|| ServerError::missing_token("missing API key"))?;
+        Claims::from_bearer_or_key(token, &self.state.db, &self.state.config).await
+    }
+
+    async fn device_with_access(
+        &self,
+        claims: &Claims,
+        device_id: &str,
+    ) -> ServerResult<DeviceDoc> {
+        let device_oid = parse_oid(device_id)?;
+        claims
+            .find_one_with_access(&self.state.db.devices(), doc! { "_id": device_oid })
+            .await?
+            .ok_or_else(|| ServerError::not_found("Device not found"))
+    }
+
+    async fn to_proto_device(
+        &self,
+        claims: &Claims,
+        device: DeviceDoc,
+    ) -> ServerResult<v1::Device> {
+        let role = claims.get_role(&device)?;
+        let mut public = device.to_public_device(&role);
+        if self.state.relay.has_tunnel(&public.short_id).await {
+            public.online = true;
+        }
+        Ok(public.into())
+    }
+}
+
+fn to_proto_event(ev: &DeviceEvent) -> v1::Event {
+    let device_id = ev.device.id.map(|id| id.to_hex()).unwrap_or_default();
+    let kind = match &ev.kind {
+        DeviceEventKind::Connected => v1::event::Kind::Connected(v1::DeviceConnected {}),
+        DeviceEventKind::Disconnected => v1::event::Kind::Disconnected(v1::DeviceDisconnected {}),
+        DeviceEventKind::Report(report) => {
+            v1::event::Kind::Report(v1::Report::from_kind(&device_id, report.clone()))
+        }
+        DeviceEventKind::Alert { rule, message } => v1::event::Kind::Alert(v1::DeviceAlert {
+            rule: rule.clone(),
+            message: message.clone(),
+        }),
+    };
+    v1::Event {
+        device_id,
+        time: ev.time,
+        kind: Some(kind),
+    }
+}
+
+type EventStream = Pin<Box<dyn Stream<Item = Result<v1::Event, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl Make87 for GrpcApi {
+    async fn list_devices(
+        &self,
+        req: Request<v1::ListDevicesRequest>,
+    ) -> Result<Response<v1::ListDevicesResponse>, Status> {
+        let claims = self.claims(&req).await?;
+        let pagination = to_pagination(req.into_inner().pagination);
+
+        let devices_col = self.state.db.devices();
+        let docs = claims.list_with_access(&devices_col, &pagination).await?;
+        let total_count = claims.count_with_access(&devices_col).await?;
+
+        let mut devices = Vec::with_capacity(docs.len());
+        for device in docs {
+            if let Ok(d) = self.to_proto_device(&claims, device).await {
+                devices.push(d);
+            }
+        }
+
+        Ok(Response::new(v1::ListDevicesResponse {
+            devices,
+            total_count,
+        }))
+    }
+
+    async fn get_device(
+        &self,
+        req: Request<v1::GetDeviceRequest>,
+    ) -> Result<Response<v1::Device>, Status> {
+        let claims = self.claims(&req).await?;
+        let device = self
+            .device_with_access(&claims, &req.get_ref().device_id)
+            .await?;
+        Ok(Response::new(self.to_proto_device(&claims, device).await?))
+    }
+
+    async fn list_deployments(
+        &self,
+        req: Request<v1::ListDeploymentsRequest>,
+    ) -> Result<Response<v1::ListDeploymentsResponse>, Status> {
+        let claims = self.claims(&req).await?;
+        let req = req.into_inner();
+        let device = self.device_with_access(&claims, &req.device_id).await?;
+        let pagination = to_pagination(req.pagination);
+
+        let docs =
+            DeployRevisionDoc::list_for_device(&self.state.db, device.id.unwrap(), &pagination)
+                .await?;
+        let mut revisions: Vec<DeploymentRevision> =
+            docs.into_iter().map(|doc| doc.revision).collect();
+        DeployFileDoc::attach(&self.state.db, &mut revisions).await?;
+        let deployments = revisions
+            .iter()
+            .map(|revision| v1::Deployment::from_revision(&req.device_id, revision))
+            .collect();
+
+        Ok(Response::new(v1::ListDeploymentsResponse { deployments }))
+    }
+
+    async fn get_deployment(
+        &self,
+        req: Request<v1::GetDeploymentRequest>,
+    ) -> Result<Response<v1::Deployment>, Status> {
+        let claims = self.claims(&req).await?;
+        let req = req.into_inner();
+        let device_oid = parse_oid(&req.device_id)?;
+
+        let doc = claims
+            .find_one_with_access(
+                &self.state.db.deploy_revisions(),
+                doc! { "revision.id": &req.revision_id, "device_id": device_oid },
+            )
+            .await?
+            .ok_or_else(|| ServerError::not_found("Revision not found"))?;
+
+        Ok(Response::new(v1::Deployment::from_revision(
+            &req.device_id,
+            &doc.into_revision(&self.state.db).await?,
+        )))
+    }
+
+    async fn create_deployment(
+        &self,
+        req: Request<v1::CreateDeploymentRequest>,
+    ) -> Result<Response<v1::Deployment>, Status> {
+        let claims = self.claims(&req).await?;
+        let req = req.into_inner();
+        let device = self.device_with_access(&claims, &req.device_id).await?;
+        let device_oid = device.id.unwrap();
+
+        let _ = AuditLogDoc::add(
+            &self.state.db,
+            &claims,
+            &self.state.config,
+            &format!("Requested deployment revision creation for {}", &device_oid),
+            "via gRPC",
+            Some(device_oid),
+        )
+        .await;
+
+        let revision = DeploymentRevision::from_yaml(&req.spec_yaml)
+            .map_err(|e| ServerError::bad_request(&format!("Invalid revision: {}", e)))?;
+        let details = deploy_details(&revision);
+        org_limits::check_new_revision(&self.state.db, &self.state.config, &device).await?;
+        if let Err(message) =
+            require_approval(&self.state, &claims, &device, "deploy", &details).await?
+        {
+            return Err(ServerError::forbidden(&message).into());
+        }
+
+        let doc = DeployRevisionDoc::create(
+            &self.state.db,
+            revision,
+            Some(device_oid),
+            None,
+            req.active.unwrap_or(true),
+            device.owner_scope,
+            device.allowed_scopes,
+        )
+        .await?;
+
+        let _ = AuditLogDoc::add(
+            &self.state.db,
+            &claims,
+            &self.state.config,
+            &format!("Added deployment revision for {}", &device_oid),
+            &format!("{}", &doc.revision),
+            Some(device_oid),
+        )
+        .await;
+
+        Ok(Response::new(v1::Deployment::from_revision(
+            &req.device_id,
+            &doc.revision,
+        )))
+    }
+
+    async fn get_active_deployment(
+        &self,
+        req: Request<v1::GetActiveDeploymentRequest>,
+    ) -> Result<Response<v1::GetActiveDeploymentResponse>, Status> {
+        let claims = self.claims(&req).await?;
+        let device = self
+            .device_with_access(&claims, &req.get_ref().device_id)
+            .await?;
+
+        let active =
+            DeployRevisionDoc::get_active_device_deployment(&self.state.db, device.id.unwrap())
+                .await?;
+
+        Ok(Response::new(v1::GetActiveDeploymentResponse {
+            revision_id: active.and_then(|d| d.revision.id),
+        }))
+    }
+
+    async fn get_deployment_status(
+        &self,
+        req: Request<v1::GetDeploymentStatusRequest>,
+    ) -> Result<Response<v1::DeploymentStatus>, Status> {
+        let claims = self.claims(&req).await?;
+        let req = req.into_inner();
+        let device = self.device_with_access(&claims, &req.device_id).await?;
+
+        let snapshot = DeployReportDoc::compute_deployment_status_snapshot_for_device(
+            &self.state.db,
+            &device.id.unwrap(),
+            &req.revision_id,
+        )
+        .await?;
+
+        Ok(Response::new(snapshot.into()))
+    }
+
+    async fn list_reports(
+        &self,
+        req: Request<v1::ListReportsRequest>,
+    ) -> Result<Response<v1::ListReportsResponse>, Status> {
+        let claims = self.claims(&req).await?;
+        let req = req.into_inner();
+        let device = self.device_with_access(&claims, &req.device_id).await?;
+        let pagination = to_pagination(req.pagination);
+
+        let docs = DeployReportDoc::list_for_device(
+            &self.state.db,
+            &device.id.unwrap(),
+            &req.revision_id,
+            &pagination,
+        )
+        .await?;
+        let reports = docs
+            .into_iter()
+            .map(|doc| doc.to_pub_report().into())
+            .collect();
+
+        Ok(Response::new(v1::ListReportsResponse { reports }))
+    }
+
+    type StreamEventsStream = EventStream;
+
+    async fn stream_events(
+        &self,
+        req: Request<v1::StreamEventsRequest>,
+    ) -> Result<Response<Self::StreamEventsStream>, Status> {
+        let claims = self.claims(&req).await?;
+        let device_ids = req.into_inner().device_ids;
+
+        let stream = BroadcastStream::new(self.state.events.subscribe()).filter_map(move |ev| {
+            let out = match ev {
+                Ok(ev) => {
+                    let device_id = ev.device.id.map(|id| id.to_hex()).unwrap_or_default();
+                    let wanted = device_ids.is_empty() || device_ids.contains(&device_id);
+                    if wanted && claims.get_role(ev.device.as_ref()).is_ok() {
+                        Some(Ok(to_proto_event(&ev)))
+                    } else {
+                        None
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("gRPC event subscriber lagging: {}", e);
+                    None
+                }
+            };
+            futures::future::ready(out)
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
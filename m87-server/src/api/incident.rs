@@ -0,0 +1,264 @@
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use axum::{Json, Router};
+use hmac::{Hmac, Mac};
+use m87_shared::incident::{
+    Incident, IncidentEntryKind, IncidentNotification, IncidentState, UpdateIncidentBody,
+};
+use m87_shared::roles::Role;
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use sha2::Sha256;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::api::approval::operator;
+use crate::auth::claims::Claims;
+use crate::models::audit_logs::AuditLogDoc;
+use crate::models::incident::{IncidentDoc, IncidentEntryDoc, IncidentWebhookDoc};
+use crate::response::{ServerAppResult, ServerError, ServerResponse, ServerResult};
+use crate::util::app_state::AppState;
+use crate::util::events::{DeviceEvent, DeviceEventKind};
+use crate::util::pagination::RequestPagination;
+
+/// Alerts that are requests for attention rather than something going wrong
+const NOT_INCIDENTS: &[&str] = &["approval_requested"];
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+const WEBHOOK_ATTEMPTS: u32 = 3;
+
+pub fn create_route() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_incidents))
+        .route("/{id}", get(get_incident).post(update_incident))
+}
+
+#[derive(Deserialize)]
+struct IncidentQuery {
+    state: Option<IncidentState>,
+    device_id: Option<String>,
+    /// Also list resolved incidents
+    #[serde(default)]
+    all: bool,
+}
+
+async fn list_incidents(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<IncidentQuery>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<Incident>> {
+    let mut filter = doc! {};
+    if let Some(device_id) = &query.device_id {
+        filter.insert("device_id", ObjectId::parse_str(device_id)?);
+    }
+    match query.state {
+        Some(incident_state) => {
+            filter.insert("state", incident_state.to_string());
+        }
+        None if !query.all => {
+            filter.insert("state", doc! { "$ne": IncidentState::Resolved.to_string() });
+        }
+        None => {}
+    }
+    let docs = IncidentDoc::list(
+        &state.db,
+        &claims.scopes_with_min_role(Role::Viewer)?,
+        filter,
+        &pagination,
+    )
+    .await?;
+
+    Ok(ServerResponse::builder()
+        .body(docs.iter().map(|d| d.to_public()).collect())
+        .ok()
+        .build())
+}
+
+async fn get_incident(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<Incident> {
+    let oid = ObjectId::parse_str(&id)?;
+    let incident = claims
+        .find_one_with_access(&state.db.incidents(), doc! { "_id": oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Incident not found"))?;
+    Ok(ServerResponse::builder()
+        .body(incident.to_public())
+        .ok()
+        .build())
+}
+
+/// Acknowledge or resolve an incident; needs the editor role on its device.
+async fn update_incident(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<UpdateIncidentBody>,
+) -> ServerAppResult<Incident> {
+    let oid = ObjectId::parse_str(&id)?;
+    let incident = claims
+        .find_one_with_access(&state.db.incidents(), doc! { "_id": oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Incident not found"))?;
+    if !Role::allows(&claims.get_role(&incident)?, &Role::Editor) {
+        return Err(ServerError::forbidden(
+            "acknowledging or resolving incidents needs the editor role on the device",
+        ));
+    }
+    if !incident.state.can_become(body.state) {
+        return Err(ServerError::bad_request(&format!(
+            "the incident is {} and cannot become {}",
+            incident.state, body.state
+        )));
+    }
+
+    // audited first, so the action still lands on the incident it resolves
+    let action = match body.state {
+        IncidentState::Resolved => "Resolved",
+        _ => "Acknowledged",
+    };
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!("{} incident {}", action, id),
+        body.note.as_deref().unwrap_or_default(),
+        Some(incident.device_id),
+    )
+    .await;
+    let updated = IncidentDoc::transition(
+        &state.db,
+        &state.config,
+        &incident,
+        body.state,
+        &operator(&claims),
+    )
+    .await?;
+    notify(&state, &updated).await;
+
+    Ok(ServerResponse::builder()
+        .body(updated.to_public())
+        .ok()
+        .build())
+}
+
+/// Open incidents for device alerts and attach what follows them, until the
+/// server stops.
+pub async fn run_watcher(state: AppState) {
+    let mut events = state.events.subscribe();
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("Incident watcher missed {} device events", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        if let Err(e) = on_event(&state, &event).await {
+            warn!("Updating incidents failed: {}", e);
+        }
+    }
+}
+
+async fn on_event(state: &AppState, event: &DeviceEvent) -> ServerResult<()> {
+    let Some(device_id) = event.device.id else {
+        return Ok(());
+    };
+    let summary = match &event.kind {
+        DeviceEventKind::Alert { rule, message } => {
+            if NOT_INCIDENTS.contains(&rule.as_str()) {
+                return Ok(());
+            }
+            if let Some(opened) =
+                IncidentDoc::on_alert(&state.db, &event.device, rule, message).await?
+            {
+                notify(state, &opened).await;
+            }
+            return Ok(());
+        }
+        DeviceEventKind::Report(report) => match report.timeline_entry() {
+            Some((_, summary)) => summary,
+            None => return Ok(()),
+        },
+        DeviceEventKind::Connected => "Connected to the server".to_string(),
+        DeviceEventKind::Disconnected => "Disconnected from the server".to_string(),
+    };
+    let entry = IncidentEntryDoc::now(IncidentEntryKind::Event, &summary, None);
+    IncidentDoc::attach(&state.db, device_id, entry).await?;
+    Ok(())
+}
+
+/// Post the incident's new state to the webhooks of its device's orgs, in
+/// the background.
+async fn notify(state: &AppState, incident: &IncidentDoc) {
+    let hooks = match IncidentWebhookDoc::for_incident(&state.db, incident).await {
+        Ok(hooks) => hooks,
+        Err(e) => {
+            warn!("Loading incident webhooks failed: {}", e);
+            return;
+        }
+    };
+    if hooks.is_empty() {
+        return;
+    }
+    let body = match serde_json::to_vec(&IncidentNotification::new(incident.to_public())) {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Encoding incident notification failed: {}", e);
+            return;
+        }
+    };
+    for hook in hooks {
+        tokio::spawn(deliver(hook, body.clone()));
+    }
+}
+
+async fn deliver(hook: IncidentWebhookDoc, body: Vec<u8>) {
+    let http = match reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!("Incident webhook of org {} not sent: {}", hook.org_id, e);
+            return;
+        }
+    };
+    let signature = hook.secret.as_ref().and_then(|secret| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+        mac.update(&body);
+        Some(format!(
+            "sha256={}",
+            hex::encode(mac.finalize().into_bytes())
+        ))
+    });
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        let mut req = http
+            .post(&hook.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.clone());
+        if let Some(signature) = &signature {
+            req = req.header("X-M87-Signature-256", signature);
+        }
+        match req.send().await {
+            Ok(res) if res.status().is_success() => return,
+            Ok(res) => warn!(
+                "Incident webhook of org {} answered {} (attempt {})",
+                hook.org_id,
+                res.status(),
+                attempt
+            ),
+            Err(e) => warn!(
+                "Incident webhook of org {} failed: {} (attempt {})",
+                hook.org_id, e, attempt
+            ),
+        }
+        if attempt < WEBHOOK_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(5 * attempt as u64)).await;
+        }
+    }
+}
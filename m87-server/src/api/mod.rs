@@ -1,9 +1,17 @@
+mod access_grant;
+mod approval;
 pub mod auth;
-mod certificate;
+pub mod certificate;
 mod client_connection;
 pub mod deploy_spec;
 pub mod device;
+mod grpc;
+mod incident;
 mod org;
 mod quic;
+mod rollout;
 pub mod serve;
+mod usage;
+mod web_terminal;
 mod web_transport;
+mod webhook;
@@ -5,15 +5,26 @@ use axum::{Json, Router};
 use mongodb::bson::{doc, oid::ObjectId};
 
 use m87_shared::device::PublicDevice;
+use m87_shared::inventory::DeviceVulnerabilities;
+use m87_shared::iot_bridge::{OrgIotBridge, UpdateOrgIotBridgeBody};
 use m87_shared::org::{
-    AddDeviceBody, CreateOrganizationBody, InviteMemberBody, Organization, UpdateOrganizationBody,
+    AddDeviceBody, CreateOrganizationBody, InviteMemberBody, OrgIncidentWebhook, OrgLimitsStatus,
+    OrgRedaction, Organization, UpdateOrgIncidentWebhookBody, UpdateOrgLimitsBody,
+    UpdateOrgRedactionBody, UpdateOrganizationBody,
 };
+use m87_shared::patching::{CreatePatchPolicyBody, PublicPatchPolicy};
 use m87_shared::roles::Role;
 use m87_shared::users::User;
 
 use crate::auth::claims::Claims;
 use crate::models::audit_logs::AuditLogDoc;
+use crate::models::device_inventory::DeviceInventoryDoc;
+use crate::models::incident::IncidentWebhookDoc;
+use crate::models::iot_bridge::IotBridgeDoc;
 use crate::models::org;
+use crate::models::org_limits::{self, OrgLimitsDoc};
+use crate::models::org_redaction::OrgRedactionDoc;
+use crate::models::patching::PatchPolicyDoc;
 use crate::models::roles::{CreateRoleBinding, RoleDoc};
 use crate::models::user::UserDoc;
 use crate::response::{ServerAppResult, ServerError, ServerResponse};
@@ -33,6 +44,25 @@ pub fn create_route() -> Router<AppState> {
         .route("/{id}/members/{member}", delete(remove_organization_member))
         .route("/{id}/devices", get(list_org_devices).post(add_org_device))
         .route("/{id}/devices/{device_id}", delete(remove_org_device))
+        .route("/{id}/vulnerabilities", get(list_org_vulnerabilities))
+        .route(
+            "/{id}/patch-policies",
+            get(list_patch_policies).post(create_patch_policy),
+        )
+        .route(
+            "/{id}/patch-policies/{policy_id}",
+            delete(delete_patch_policy),
+        )
+        .route("/{id}/limits", get(get_org_limits).post(set_org_limits))
+        .route(
+            "/{id}/redaction",
+            get(get_org_redaction).post(set_org_redaction),
+        )
+        .route(
+            "/{id}/incident-webhook",
+            get(get_incident_webhook).post(set_incident_webhook),
+        )
+        .route("/{id}/iot-bridge", get(get_iot_bridge).post(set_iot_bridge))
 }
 
 async fn list_organizations(claims: Claims) -> ServerAppResult<Vec<Organization>> {
@@ -153,6 +183,9 @@ async fn delete_organization(
         .delete_many(doc! { "reference_id": org::org_ref(&id) })
         .await?;
 
+    // 3) Stop publishing its devices to its IoT platform
+    IotBridgeDoc::set(&state.db, &id, None, None, &claims.user_email).await?;
+
     let _ = AuditLogDoc::add(
         &state.db,
         &claims,
@@ -354,6 +387,107 @@ async fn list_org_devices(
         .build())
 }
 
+/// Advisory scan results of every device of the organization.
+async fn list_org_vulnerabilities(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<Vec<DeviceVulnerabilities>> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Viewer) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+    let out = DeviceInventoryDoc::for_scope(&state.db, &scope).await?;
+
+    Ok(ServerResponse::builder()
+        .body(out)
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn list_patch_policies(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<Vec<PublicPatchPolicy>> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Viewer) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+    let policies = PatchPolicyDoc::list_for_scope(&state.db, &scope).await?;
+
+    Ok(ServerResponse::builder()
+        .body(policies.iter().map(|p| p.to_public_policy()).collect())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+/// Devices of the organization pick the policy up with their next heartbeat.
+async fn create_patch_policy(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<CreatePatchPolicyBody>,
+) -> ServerAppResult<PublicPatchPolicy> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Admin) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    let policy =
+        PatchPolicyDoc::create(&state.db, payload, scope, claims.user_email.clone()).await?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!("Created patch policy {}", policy.name),
+        &format!(
+            "org={} window={} packages={} reboot={}",
+            id,
+            policy.window,
+            policy.packages.join(","),
+            policy.reboot
+        ),
+        None,
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(policy.to_public_policy())
+        .status_code(axum::http::StatusCode::CREATED)
+        .build())
+}
+
+async fn delete_patch_policy(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path((id, policy_id)): Path<(String, String)>,
+) -> ServerAppResult<()> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Admin) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+    let policy_oid = ObjectId::parse_str(&policy_id)
+        .map_err(|_| ServerError::bad_request("Invalid policy ObjectId"))?;
+
+    if !PatchPolicyDoc::delete(&state.db, &scope, policy_oid).await? {
+        return Err(ServerError::not_found("Patch policy not found"));
+    }
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Deleted patch policy",
+        &format!("org={} policy_id={}", id, policy_id),
+        None,
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .status_code(axum::http::StatusCode::NO_CONTENT)
+        .build())
+}
+
 async fn add_org_device(
     claims: Claims,
     State(state): State<AppState>,
@@ -376,6 +510,9 @@ async fn add_org_device(
         )
         .await?;
     let device = device_opt.ok_or_else(|| ServerError::not_found("Device not found"))?;
+    if device.owner_scope != scope && !device.allowed_scopes.contains(&scope) {
+        org_limits::check_new_device(&state.db, &state.config, &id).await?;
+    }
 
     let _ = AuditLogDoc::add(
         &state.db,
@@ -435,3 +572,263 @@ async fn remove_org_device(
         .status_code(axum::http::StatusCode::NO_CONTENT)
         .build())
 }
+
+// --------------------
+// GET /organizations/{id}/limits
+// --------------------
+
+async fn get_org_limits(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<OrgLimitsStatus> {
+    let scope = org::org_scope(&id);
+    if !claims.is_admin && !claims.has_scope_and_role(&scope, Role::Viewer) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    let status = org_limits::status(&state.db, &state.config, &state.relay, &id).await?;
+    Ok(ServerResponse::builder().body(status).ok().build())
+}
+
+// --------------------
+// POST /organizations/{id}/limits
+// --------------------
+
+async fn set_org_limits(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateOrgLimitsBody>,
+) -> ServerAppResult<OrgLimitsStatus> {
+    // org admins must not be able to lift their own quotas
+    if !claims.is_admin {
+        return Err(ServerError::forbidden(
+            "Only instance admins can change organization limits",
+        ));
+    }
+
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Set organization limits",
+        &format!("org={} limits={:?}", id, payload),
+        None,
+    )
+    .await;
+    OrgLimitsDoc::set(&state.db, &id, payload, &claims.user_email).await?;
+
+    let status = org_limits::status(&state.db, &state.config, &state.relay, &id).await?;
+    Ok(ServerResponse::builder().body(status).ok().build())
+}
+
+// --------------------
+// GET /organizations/{id}/redaction
+// --------------------
+
+async fn get_org_redaction(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<OrgRedaction> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Viewer) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    let patterns = OrgRedactionDoc::patterns(&state.db, &id).await?;
+    Ok(ServerResponse::builder()
+        .body(OrgRedaction {
+            org_id: id,
+            patterns,
+        })
+        .ok()
+        .build())
+}
+
+// --------------------
+// POST /organizations/{id}/redaction
+// --------------------
+
+/// Devices of the organization pick the patterns up with their next heartbeat.
+async fn set_org_redaction(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateOrgRedactionBody>,
+) -> ServerAppResult<OrgRedaction> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Admin) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    OrgRedactionDoc::set(&state.db, &id, payload.patterns.clone(), &claims.user_email).await?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Set organization redaction patterns",
+        &format!("org={} patterns={}", id, payload.patterns.len()),
+        None,
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(OrgRedaction {
+            org_id: id,
+            patterns: payload.patterns,
+        })
+        .ok()
+        .build())
+}
+
+// --------------------
+// GET /organizations/{id}/incident-webhook
+// --------------------
+
+fn to_incident_webhook(org_id: String, hook: Option<IncidentWebhookDoc>) -> OrgIncidentWebhook {
+    OrgIncidentWebhook {
+        org_id,
+        signed: hook.as_ref().is_some_and(|h| h.secret.is_some()),
+        url: hook.map(|h| h.url),
+    }
+}
+
+async fn get_incident_webhook(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<OrgIncidentWebhook> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Admin) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    let hook = IncidentWebhookDoc::get(&state.db, &id).await?;
+    Ok(ServerResponse::builder()
+        .body(to_incident_webhook(id, hook))
+        .ok()
+        .build())
+}
+
+// --------------------
+// POST /organizations/{id}/incident-webhook
+// --------------------
+
+async fn set_incident_webhook(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateOrgIncidentWebhookBody>,
+) -> ServerAppResult<OrgIncidentWebhook> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Admin) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    let hook = IncidentWebhookDoc::set(
+        &state.db,
+        &id,
+        payload.url,
+        payload.secret,
+        &claims.user_email,
+    )
+    .await?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Set organization incident webhook",
+        &format!(
+            "org={} url={}",
+            id,
+            hook.as_ref().map(|h| h.url.as_str()).unwrap_or("-")
+        ),
+        None,
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(to_incident_webhook(id, hook))
+        .ok()
+        .build())
+}
+
+// --------------------
+// GET /organizations/{id}/iot-bridge
+// --------------------
+
+fn to_iot_bridge(org_id: String, bridge: Option<IotBridgeDoc>) -> OrgIotBridge {
+    match bridge {
+        Some(bridge) => bridge.to_public(),
+        None => OrgIotBridge {
+            org_id,
+            ..Default::default()
+        },
+    }
+}
+
+async fn get_iot_bridge(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<OrgIotBridge> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Admin) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    let bridge = IotBridgeDoc::get(&state.db, &id).await?;
+    Ok(ServerResponse::builder()
+        .body(to_iot_bridge(id, bridge))
+        .ok()
+        .build())
+}
+
+// --------------------
+// POST /organizations/{id}/iot-bridge
+// --------------------
+
+async fn set_iot_bridge(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateOrgIotBridgeBody>,
+) -> ServerAppResult<OrgIotBridge> {
+    let scope = org::org_scope(&id);
+    if !claims.has_scope_and_role(&scope, Role::Admin) {
+        return Err(ServerError::forbidden("Not authorized for organization"));
+    }
+
+    let bridge = IotBridgeDoc::set(
+        &state.db,
+        &id,
+        payload.target,
+        payload.telemetry_interval_secs,
+        &claims.user_email,
+    )
+    .await?;
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Set organization IoT bridge",
+        &match &bridge {
+            Some(b) => format!(
+                "org={} platform={} endpoint={}",
+                id,
+                b.target.platform().as_str(),
+                b.target.endpoint()
+            ),
+            None => format!("org={} platform=-", id),
+        },
+        None,
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(to_iot_bridge(id, bridge))
+        .ok()
+        .build())
+}
@@ -1,37 +1,51 @@
 use futures::future::{AbortHandle, Abortable};
 use governor::{Quota, RateLimiter};
-use m87_shared::heartbeat::HeartbeatRequest;
-use m87_shared::roles::Role;
+use m87_shared::approval::stream_operation;
+use m87_shared::device::TimelineEventKind;
+use m87_shared::heartbeat::{HeartbeatRequest, ReportAck, ReportAckStatus};
+use m87_shared::host;
+use m87_shared::incident::IncidentEntryKind;
+use m87_shared::roles::{GRANTED_ROLE_KEY, Role, required_stream_role};
 use mongodb::bson::doc;
-use quinn::{ConnectionError, Endpoint};
+use quinn::ConnectionError;
 use serde::Serialize;
 use serde::de::DeserializeOwned;
-use std::net::SocketAddr;
 use std::num::NonZeroU32;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{Duration, Instant};
-use tokio::io::{self, AsyncWriteExt};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::sync::{Semaphore, watch};
 use tokio::time::timeout;
 use tracing::{debug, error, info, warn};
 
+use crate::api::approval::require_approval;
 use crate::api::client_connection::ClientConn;
 use crate::auth::claims::Claims;
 use crate::models::audit_logs::AuditLogDoc;
 use crate::models::device::DeviceDoc;
+use crate::models::device_history::DeviceHistoryDoc;
+use crate::models::idempotency::{Claim, IdempotencyKeyDoc};
+use crate::models::incident::{IncidentDoc, IncidentEntryDoc};
+use crate::models::org_limits;
+use crate::relay::copy;
+use crate::relay::relay_state::OrgSessionGuard;
 use crate::response::ServerError;
 use crate::response::ServerResult;
 use crate::util::app_state::AppState;
+use crate::util::events::DeviceEventKind;
+use crate::util::net;
 
 const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
 const MAX_TOKEN_LEN: usize = 4096;
 const MAX_CONCURRENT_HANDSHAKES: usize = 64;
+const MAX_STREAM_HEADER: usize = 64 * 1024;
 
 pub async fn run_quic_endpoint(
     state: AppState,
     mut reload_rx: watch::Receiver<()>,
 ) -> ServerResult<()> {
-    let addr = SocketAddr::from(([0, 0, 0, 0], state.config.unified_port));
+    let port = state.config.unified_port;
 
     loop {
         // Build fresh TLS config
@@ -39,10 +53,10 @@ pub async fn run_quic_endpoint(
             crate::api::certificate::create_quic_server_config(&state.config).await?;
 
         // Create fresh endpoint
-        let endpoint = Endpoint::server(server_config, addr)
+        let endpoint = net::quic_endpoint(server_config, port)
             .map_err(|e| ServerError::internal_error(&format!("bind QUIC: {e:?}")))?;
 
-        info!("QUIC listening on udp://{}", addr);
+        info!("QUIC listening on udp port {}", port);
         let handshake_sem = Arc::new(Semaphore::new(MAX_CONCURRENT_HANDSHAKES));
         // Accept loop for this endpoint
         loop {
@@ -141,7 +155,8 @@ async fn handle_quic_connection(conn: quinn::Connection, state: AppState) -> Ser
         })
         .unwrap_or_default();
 
-    let public = &state.config.public_address;
+    // devices of a relay at an IP literal are addressed under its encoded name
+    let public = &host::sni_domain(host::split_host_port(&state.config.public_address).0);
     info!("extracting token");
     let Some(token) = extract_token(&conn).await else {
         conn.close(0x100u32.into(), b"missing-token");
@@ -158,16 +173,17 @@ async fn handle_quic_connection(conn: quinn::Connection, state: AppState) -> Ser
     }
 
     if let Some(device_id) = extract_device_id_from_sni(&sni, public) {
+        // Any role may connect; each stream is checked against STREAM_ROLES.
         let res = claims
             .find_one_with_scope_and_role::<DeviceDoc>(
                 &state.db.devices(),
                 doc! { "short_id": &device_id },
-                Role::Editor,
+                Role::Viewer,
             )
             .await;
         // .await?
         // .ok_or_else(|| ServerError::not_found("Device not found"))?;
-        match res {
+        let role = match res {
             Ok(Some(device)) => {
                 let _ = AuditLogDoc::add(
                     &state.db,
@@ -178,6 +194,7 @@ async fn handle_quic_connection(conn: quinn::Connection, state: AppState) -> Ser
                     device.id.clone(),
                 )
                 .await;
+                claims.get_role(&device)?
             }
             Ok(None) => {
                 let _ = AuditLogDoc::add(
@@ -209,9 +226,15 @@ async fn handle_quic_connection(conn: quinn::Connection, state: AppState) -> Ser
 
         if state.relay.has_tunnel(&device_id).await {
             debug!(%device_id, "forwarding to device");
-            let _ =
-                handle_forward_supervised(ClientConn::Raw(conn), device_id.clone(), state.clone())
-                    .await;
+            let _ = handle_forward_supervised(
+                ClientConn::Raw(conn),
+                device_id.clone(),
+                token,
+                role,
+                claims,
+                state.clone(),
+            )
+            .await;
         } else {
             warn!(%device_id, "no tunnel registered for device");
             // print all tunnel ids
@@ -268,7 +291,7 @@ async fn handle_control_tunnel(
 ) -> ServerResult<()> {
     let device_id = device_short_id.to_string();
 
-    claims
+    let device = claims
         .find_one_with_scope_and_role::<DeviceDoc>(
             &state.db.devices(),
             doc! { "short_id": &device_id },
@@ -279,15 +302,23 @@ async fn handle_control_tunnel(
 
     // NOW publish as active tunnel
     state.relay.replace_tunnel(&device_id, conn.clone()).await;
+    state.events.publish(&device, DeviceEventKind::Connected);
+    record_history(
+        &state,
+        &device,
+        TimelineEventKind::Connected,
+        format!("Connected from {}", conn.remote_address().ip()),
+    )
+    .await;
 
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
     // Connection owner: only place that closes conn / awaits conn.closed()
-    loop {
+    let close_reason = loop {
         tokio::select! {
             reason = conn.closed() => {
                 let _ = shutdown_tx.send(true);
                 log_close_reason(&device_id, &reason);
-                break;
+                break reason;
             }
             res = conn.accept_bi() => {
                 if let Ok((send, recv)) = res {
@@ -303,16 +334,41 @@ async fn handle_control_tunnel(
                 }
             }
         }
-    }
+    };
 
     state
         .relay
         .remove_if_match(&device_id, conn.stable_id())
         .await;
+    state.events.publish(&device, DeviceEventKind::Disconnected);
+    record_history(
+        &state,
+        &device,
+        TimelineEventKind::Disconnected,
+        format!("Disconnected: {}", close_reason),
+    )
+    .await;
 
     Ok(())
 }
 
+/// Add a connectivity event to the device's timeline; failures are only logged.
+async fn record_history(
+    state: &AppState,
+    device: &DeviceDoc,
+    kind: TimelineEventKind,
+    summary: String,
+) {
+    let Some(device_id) = device.id else {
+        return;
+    };
+    if let Err(err) =
+        DeviceHistoryDoc::add(&state.db, &state.config, device_id, kind, summary).await
+    {
+        warn!(device = %device.short_id, "failed to record device history: {}", err);
+    }
+}
+
 fn log_close_reason(device_id: &str, reason: &ConnectionError) {
     match reason {
         ConnectionError::ApplicationClosed(_) => info!(%device_id, "device gracefully closed"),
@@ -348,7 +404,7 @@ async fn run_heartbeat_loop(
 
             msg = read_msg::<HeartbeatRequest>(&mut recv) => {
                 info!("heartbeat received");
-                let req = match msg {
+                let mut req = match msg {
                     Ok(r) => r,
                     Err(e) => {
                         warn!(%device_id, "heartbeat read error: {e}");
@@ -363,7 +419,114 @@ async fn run_heartbeat_loop(
                     break;
                 };
 
-                let body = device.handle_heartbeat(claims.clone(), &state.db, req, &state.config).await?;
+                let mut duplicate_ack = None;
+                if let Some(key) = &req.idempotency_key {
+                    let claim = IdempotencyKeyDoc::claim(&state.db, &state.config, device.id.unwrap(), key).await?;
+                    // a retry of an event we already stored: ack it again, but do not apply it twice
+                    if let Claim::Seen(Some(report_id)) = claim {
+                        info!(%device_id, "duplicate heartbeat {key}, skipping its events");
+                        req.deploy_report = None;
+                        req.failed_upgrade = None;
+                        req.location = None;
+                        duplicate_ack = Some(ReportAck {
+                            idempotency_key: key.clone(),
+                            status: ReportAckStatus::Stored { report_id: report_id.to_hex() },
+                        });
+                    }
+                }
+
+                let deploy_report = req.deploy_report.clone();
+                let battery_alert = req.battery.as_ref().and_then(|b| device.battery_alert(b));
+                let condition_alerts = device.condition_alerts(&req);
+                let probe_transitions = device.probe_transitions(&req);
+                let recovery_unlocks = std::mem::take(&mut req.recovery_unlocks);
+                if let Some(metrics) = &req.metrics {
+                    state.iot.telemetry(&device, metrics);
+                }
+                let mut body = device.handle_heartbeat(claims.clone(), &state.db, req, &state.config).await?;
+                if duplicate_ack.is_some() {
+                    body.report_ack = duplicate_ack;
+                }
+                let not_stored = matches!(
+                    &body.report_ack,
+                    Some(ReportAck { status: ReportAckStatus::Rejected { .. } | ReportAckStatus::Retry, .. })
+                );
+                if let Some(mut report) = deploy_report.filter(|_| !not_stored) {
+                    // subscribers only get what happened, not uploaded files
+                    report.strip_contents();
+                    state.events.publish(&device, DeviceEventKind::Report(report));
+                }
+                if let Some(message) = battery_alert {
+                    let _ = AuditLogDoc::add(
+                        &state.db,
+                        &claims,
+                        &state.config,
+                        &format!("Low battery alert on device {}", device.name),
+                        &message,
+                        device.id,
+                    )
+                    .await;
+                    state.events.publish(
+                        &device,
+                        DeviceEventKind::Alert { rule: "low_battery".to_string(), message },
+                    );
+                }
+                for (rule, message) in condition_alerts {
+                    let _ = AuditLogDoc::add(
+                        &state.db,
+                        &claims,
+                        &state.config,
+                        &format!("Alert '{}' on device {}", rule, device.name),
+                        &message,
+                        device.id,
+                    )
+                    .await;
+                    state.events.publish(&device, DeviceEventKind::Alert { rule, message });
+                }
+                for transition in probe_transitions {
+                    let action = match transition.down {
+                        true => format!("Probe {} down on device {}", transition.name, device.name),
+                        false => format!("Probe {} up on device {}", transition.name, device.name),
+                    };
+                    let _ = AuditLogDoc::add(
+                        &state.db,
+                        &claims,
+                        &state.config,
+                        &action,
+                        &transition.message,
+                        device.id,
+                    )
+                    .await;
+                    if transition.down {
+                        state.events.publish(
+                            &device,
+                            DeviceEventKind::Alert { rule: "probe_failed".to_string(), message: transition.message },
+                        );
+                    } else if let Some(id) = device.id {
+                        // a recovery does not alert, but belongs to the open incident
+                        let entry = IncidentEntryDoc::now(IncidentEntryKind::Event, &transition.message, None);
+                        let _ = IncidentDoc::attach(&state.db, id, entry).await;
+                    }
+                }
+                for unlock in recovery_unlocks {
+                    let message = format!(
+                        "{} unlocked local access with the recovery code at {}, valid until {}",
+                        unlock.user, unlock.unlocked_at, unlock.expires_at
+                    );
+                    let _ = AuditLogDoc::add(
+                        &state.db,
+                        &claims,
+                        &state.config,
+                        &format!("Recovery code used on device {}", device.name),
+                        &message,
+                        device.id,
+                    )
+                    .await;
+                    state.events.publish(
+                        &device,
+                        DeviceEventKind::Alert { rule: "recovery_unlock".to_string(), message },
+                    );
+                }
 
                 info!("sending heartbeat response");
                 match write_msg(&mut send, &body).await {
@@ -404,13 +567,37 @@ async fn wait_for_device_conn(
     }
 }
 
+/// Relay the streams of a client connection to the device. `role` and
+/// `claims` are those the connection was accepted with; every stream checks
+/// `token` again, so expired or revoked access stops new streams.
 pub async fn handle_forward_supervised(
     client_conn: ClientConn,
     device_id: String,
+    token: String,
+    role: Role,
+    claims: Claims,
     state: AppState,
 ) -> io::Result<()> {
     const RECONNECT_TIMEOUT: Duration = Duration::from_secs(45);
 
+    // counts against the org's tunnel limit until the client goes away,
+    // including while waiting for the device to reconnect
+    let _org_session = match open_org_session(&state, &device_id).await {
+        Ok(guard) => guard,
+        Err(e) => {
+            warn!(%device_id, "refusing forward: {}", e);
+            client_conn.close(e.to_string().as_bytes());
+            return Err(io::Error::other(e.to_string()));
+        }
+    };
+    // lets the end of an access grant close the connection
+    let closer = client_conn.clone();
+    let _client = state
+        .relay
+        .register_client(&device_id, &claims.user_email, move || {
+            closer.close(b"access-ended")
+        });
+
     loop {
         // wait (with timeout) for a device tunnel
         let Some(device_conn) = wait_for_device_conn(&state, &device_id, RECONNECT_TIMEOUT).await
@@ -423,7 +610,16 @@ pub async fn handle_forward_supervised(
         };
 
         debug!(%device_id, "starting forward session");
-        match handle_forward_once(&client_conn, &device_conn, &device_id).await {
+        match handle_forward_once(
+            &client_conn,
+            &device_conn,
+            &device_id,
+            &token,
+            &role,
+            &state,
+        )
+        .await
+        {
             ForwardEnd::ClientClosed => {
                 debug!(%device_id, "client closed, ending supervised forward");
                 return Ok(());
@@ -436,19 +632,45 @@ pub async fn handle_forward_supervised(
     }
 }
 
+async fn open_org_session(
+    state: &AppState,
+    device_id: &str,
+) -> ServerResult<Option<OrgSessionGuard>> {
+    let Some(device) = state
+        .db
+        .devices()
+        .find_one(doc! { "short_id": device_id })
+        .await?
+    else {
+        return Ok(None);
+    };
+    org_limits::open_session(&state.db, &state.config, &state.relay, &device)
+        .await
+        .map(Some)
+}
+
 const MAX_PARALLEL_STREAMS: usize = 128;
 
 async fn handle_forward_once(
     client_conn: &ClientConn,
     device_conn: &quinn::Connection,
     device_id: &str,
+    token: &str,
+    role: &Role,
+    state: &AppState,
 ) -> ForwardEnd {
     let active_streams = Arc::new(tokio::sync::Semaphore::new(MAX_PARALLEL_STREAMS));
-    spawn_udp_bridge(
-        client_conn.clone(),
-        device_conn.clone(),
-        device_id.to_string(),
-    );
+    let meter = state.relay.relay_meter(device_id);
+    // Datagrams belong to UDP forwards, which only roles allowed to forward can set up.
+    let forward_role = required_stream_role(&serde_json::json!({ "type": "Forward" }));
+    if Role::allows(role, &forward_role) {
+        spawn_udp_bridge(
+            client_conn.clone(),
+            device_conn.clone(),
+            device_id.to_string(),
+            meter.clone(),
+        );
+    }
 
     let client_closed_fut = client_conn.closed();
     tokio::pin!(client_closed_fut);
@@ -491,10 +713,43 @@ async fn handle_forward_once(
 
                 let dev_conn = device_conn.clone();
                 let device_id = device_id.to_string();
+                let token = token.to_string();
+                let state = state.clone();
+                let meter = meter.clone();
 
                 tokio::spawn(async move {
                     let _permit = permit;
 
+                    let header = match timeout(AUTH_TIMEOUT, read_stream_header(&mut client_recv)).await {
+                        Ok(Ok(header)) => header,
+                        Ok(Err(e)) => {
+                            warn!(%device_id, "invalid stream header: {e}");
+                            return;
+                        }
+                        Err(_) => {
+                            warn!(%device_id, "timed out waiting for stream header");
+                            return;
+                        }
+                    };
+                    let authorized = match current_access(&state, &token, &device_id).await {
+                        Ok((claims, role)) => match authorize_stream(header, &role) {
+                            Ok(header) => approve_stream(&state, &claims, &device_id, &header)
+                                .await
+                                .map(|()| header),
+                            Err(message) => Err(message),
+                        },
+                        Err(message) => Err(message),
+                    };
+                    let header = match authorized {
+                        Ok(header) => header,
+                        Err(message) => {
+                            warn!(%device_id, "{message}");
+                            let _ = client_send.write_all(format!("PERMISSION_DENIED: {message}\n").as_bytes()).await;
+                            let _ = client_send.shutdown().await;
+                            return;
+                        }
+                    };
+
                     debug!("forward: opening device stream");
 
                     let (mut dev_send, mut dev_recv) = match dev_conn.open_bi().await {
@@ -506,17 +761,23 @@ async fn handle_forward_once(
                         }
                     };
 
+                    if let Err(e) = write_msg(&mut dev_send, &header).await {
+                        warn!(%device_id, "failed to forward stream header: {e}");
+                        return;
+                    }
+
                     let (abort_uplink, reg_up) = AbortHandle::new_pair();
                     let (abort_down, reg_dn) = AbortHandle::new_pair();
 
+                    let uplink_meter = meter.clone();
                     let uplink = tokio::spawn(Abortable::new(async move {
-                        let r = tokio::io::copy(&mut client_recv, &mut dev_send).await;
+                        let r = copy::client_to_device(&mut client_recv, &mut dev_send, &uplink_meter).await;
                         let _ = dev_send.finish();
                         r
                     }, reg_up));
 
                     let downlink = tokio::spawn(Abortable::new(async move {
-                        let r = tokio::io::copy(&mut dev_recv, &mut client_send).await;
+                        let r = copy::device_to_client(&mut dev_recv, &mut client_send, &meter).await;
                         let _ = client_send.shutdown().await;
                         r
                     }, reg_dn));
@@ -537,15 +798,117 @@ async fn handle_forward_once(
     }
 }
 
+/// Read the u32-length-prefixed JSON header a client sends first on every stream.
+async fn read_stream_header<R: AsyncRead + Unpin>(recv: &mut R) -> ServerResult<serde_json::Value> {
+    let mut len_buf = [0u8; 4];
+    recv.read_exact(&mut len_buf)
+        .await
+        .map_err(|e| ServerError::bad_request(&format!("failed to read header length: {e}")))?;
+    let len = u32::from_be_bytes(len_buf) as usize;
+    if len > MAX_STREAM_HEADER {
+        return Err(ServerError::bad_request("stream header too large"));
+    }
+
+    let mut buf = vec![0u8; len];
+    recv.read_exact(&mut buf)
+        .await
+        .map_err(|e| ServerError::bad_request(&format!("failed to read header: {e}")))?;
+    serde_json::from_slice(&buf)
+        .map_err(|e| ServerError::bad_request(&format!("invalid stream header: {e}")))
+}
+
+/// The claims of `token` and its role on the device as of now, so grants that
+/// expired or were revoked since the connection opened no longer count.
+async fn current_access(
+    state: &AppState,
+    token: &str,
+    device_id: &str,
+) -> Result<(Claims, Role), String> {
+    let claims = Claims::from_bearer_or_key(token, &state.db, &state.config)
+        .await
+        .map_err(|e| format!("access check failed: {e}"))?;
+    let device = claims
+        .find_one_with_scope_and_role::<DeviceDoc>(
+            &state.db.devices(),
+            doc! { "short_id": device_id },
+            Role::Viewer,
+        )
+        .await
+        .map_err(|e| format!("access check failed: {e}"))?
+        .ok_or_else(|| "your access to the device ended".to_string())?;
+    let role = claims
+        .get_role(&device)
+        .map_err(|e| format!("access check failed: {e}"))?;
+    Ok((claims, role))
+}
+
+/// Check `role` against the stream's required role and record it in the
+/// header so the runtime can check again.
+fn authorize_stream(
+    mut header: serde_json::Value,
+    role: &Role,
+) -> Result<serde_json::Value, String> {
+    let required = required_stream_role(&header);
+    let stream_type = header
+        .get("type")
+        .and_then(|t| t.as_str())
+        .unwrap_or("unknown");
+    if !Role::allows(role, &required) {
+        return Err(format!(
+            "{} streams require the {} role, you have {}",
+            stream_type,
+            required.to_string(),
+            role.to_string()
+        ));
+    }
+    let Some(fields) = header.as_object_mut() else {
+        return Err("stream header is not an object".to_string());
+    };
+    fields.insert(
+        GRANTED_ROLE_KEY.to_string(),
+        serde_json::Value::String(role.to_string()),
+    );
+    Ok(header)
+}
+
+/// Hold back streams that need a second operator's approval on devices
+/// that require it, see [`require_approval`].
+async fn approve_stream(
+    state: &AppState,
+    claims: &Claims,
+    device_id: &str,
+    header: &serde_json::Value,
+) -> Result<(), String> {
+    let Some((operation, details)) = stream_operation(header) else {
+        return Ok(());
+    };
+    let device = state
+        .db
+        .devices()
+        .find_one(doc! { "short_id": device_id })
+        .await
+        .map_err(|e| format!("device lookup failed: {e}"))?
+        .ok_or_else(|| "device not found".to_string())?;
+    require_approval(state, claims, &device, operation, &details)
+        .await
+        .map_err(|e| e.to_string())?
+}
+
 const MAX_UDP_PAYLOAD: usize = 64 * 1024;
 const UDP_SEND_BACKOFF: Duration = Duration::from_millis(1);
 
-fn spawn_udp_bridge(client: ClientConn, device: quinn::Connection, device_id: String) {
+fn spawn_udp_bridge(
+    client: ClientConn,
+    device: quinn::Connection,
+    device_id: String,
+    meter: Arc<AtomicU64>,
+) {
     // CLIENT → DEVICE
     {
         let client = client.clone();
         let device = device.clone();
         let dev_id = device_id.clone();
+        let meter = meter.clone();
 
         let udp_limiter = Arc::new(RateLimiter::direct(Quota::per_second(
             NonZeroU32::new(50_000).unwrap(),
@@ -576,6 +939,7 @@ fn spawn_udp_bridge(client: ClientConn, device: quinn::Connection, device_id: St
                             continue;
                         }
 
+                        meter.fetch_add(d.len() as u64, Ordering::Relaxed);
                         if let Err(e) = device.send_datagram(d) {
                             warn!(%dev_id, "udp client->device send error: {e:?}");
                             tokio::time::sleep(UDP_SEND_BACKOFF).await;
@@ -591,6 +955,7 @@ fn spawn_udp_bridge(client: ClientConn, device: quinn::Connection, device_id: St
         let client = client.clone();
         let device = device.clone();
         let dev_id = device_id.clone();
+        let meter = meter.clone();
 
         let udp_limiter = Arc::new(RateLimiter::direct(Quota::per_second(
             NonZeroU32::new(50_000).unwrap(),
@@ -621,6 +986,7 @@ fn spawn_udp_bridge(client: ClientConn, device: quinn::Connection, device_id: St
                             continue;
                         }
 
+                        meter.fetch_add(d.len() as u64, Ordering::Relaxed);
                         if let Err(e) = client.send_datagram(d) {
                             warn!(%dev_id, "udp device->client send error: {e:?}");
                             tokio::time::sleep(UDP_SEND_BACKOFF).await;
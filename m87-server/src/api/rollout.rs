@@ -0,0 +1,451 @@
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::routing::get;
+use axum::{Json, Router};
+use m87_shared::deploy_spec::DeploymentRevision;
+use m87_shared::roles::Role;
+use m87_shared::rollout::{
+    CreateRolloutBody, Rollout, RolloutAction, RolloutDeviceState, RolloutState, UpdateRolloutBody,
+};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{DateTime, doc};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::api::approval::{check_approval, deploy_details, operator, use_approval};
+use crate::auth::access_control::AccessControlled;
+use crate::auth::claims::Claims;
+use crate::models::audit_logs::AuditLogDoc;
+use crate::models::deploy_spec::{DeployReportDoc, DeployRevisionDoc};
+use crate::models::device::DeviceDoc;
+use crate::models::org_limits;
+use crate::models::rollout::RolloutDoc;
+use crate::response::{ServerAppResult, ServerError, ServerResponse, ServerResult};
+use crate::util::app_state::AppState;
+use crate::util::events::DeviceEventKind;
+use crate::util::pagination::RequestPagination;
+
+/// How often running rollouts are advanced
+const ROLLOUT_TICK: Duration = Duration::from_secs(30);
+
+pub fn create_route() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_rollouts).post(create_rollout))
+        .route("/{id}", get(get_rollout).post(update_rollout))
+}
+
+#[derive(Deserialize)]
+struct RolloutQuery {
+    /// Also list finished rollouts
+    #[serde(default)]
+    all: bool,
+}
+
+async fn list_rollouts(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<RolloutQuery>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<Rollout>> {
+    let filter = match query.all {
+        true => doc! {},
+        false => doc! {
+            "state": {
+                "$in": [RolloutState::Running.to_string(), RolloutState::Halted.to_string()]
+            }
+        },
+    };
+    let docs = RolloutDoc::list(
+        &state.db,
+        &claims.scopes_with_min_role(Role::Viewer)?,
+        filter,
+        &pagination,
+    )
+    .await?;
+
+    Ok(ServerResponse::builder()
+        .body(docs.iter().map(|d| d.to_public()).collect())
+        .ok()
+        .build())
+}
+
+async fn get_rollout(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<Rollout> {
+    let oid = ObjectId::parse_str(&id)?;
+    let rollout = claims
+        .find_one_with_access(&state.db.rollouts(), doc! { "_id": oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Rollout not found"))?;
+    Ok(ServerResponse::builder()
+        .body(rollout.to_public())
+        .ok()
+        .build())
+}
+
+/// Start a rollout; needs the editor role on every device.
+async fn create_rollout(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(body): Json<CreateRolloutBody>,
+) -> ServerAppResult<Rollout> {
+    let revision = DeploymentRevision::from_yaml(&body.revision)
+        .map_err(|e| ServerError::bad_request(&format!("invalid YAML in `revision`: {}", e)))?;
+    let policy = body
+        .policy
+        .clone()
+        .or_else(|| revision.rollout.clone())
+        .unwrap_or_default();
+
+    let mut devices: Vec<DeviceDoc> = Vec::with_capacity(body.device_ids.len());
+    for device_id in &body.device_ids {
+        let oid = ObjectId::parse_str(device_id)?;
+        if devices.iter().any(|d| d.id == Some(oid)) {
+            continue;
+        }
+        let device = claims
+            .find_one_with_scope_and_role(&state.db.devices(), doc! { "_id": oid }, Role::Editor)
+            .await?
+            .ok_or_else(|| ServerError::not_found("Device not found"))?;
+        devices.push(device);
+    }
+
+    // every device is checked before any approval is used up, so a refusal
+    // leaves the approvals of the others for the next attempt
+    let details = deploy_details(&revision);
+    let mut approvals = Vec::with_capacity(devices.len());
+    let mut refused = Vec::new();
+    for device in &devices {
+        match check_approval(&state, &claims, device, "deploy", &details).await? {
+            Ok(approval) => approvals.push(approval),
+            Err(message) => refused.push(message),
+        }
+        org_limits::check_new_revision(&state.db, &state.config, device).await?;
+    }
+    if !refused.is_empty() {
+        return Err(ServerError::forbidden(&refused.join("; ")));
+    }
+    for (device, approval) in devices.iter().zip(approvals) {
+        use_approval(&state, &claims, device, "deploy", approval).await?;
+    }
+
+    let rollout =
+        RolloutDoc::create(&state.db, revision, devices, policy, &operator(&claims)).await?;
+    for device in &rollout.devices {
+        let _ = AuditLogDoc::add(
+            &state.db,
+            &claims,
+            &state.config,
+            &format!(
+                "Started rollout {} of revision {}",
+                rollout.id, rollout.revision_id
+            ),
+            &format!("wave {} of {}", device.wave + 1, rollout.waves),
+            Some(device.device_id),
+        )
+        .await;
+    }
+
+    Ok(ServerResponse::builder()
+        .body(rollout.to_public())
+        .status_code(axum::http::StatusCode::CREATED)
+        .build())
+}
+
+/// Halt, resume, roll back or cancel a rollout; needs the editor role on
+/// every device of it.
+async fn update_rollout(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(body): Json<UpdateRolloutBody>,
+) -> ServerAppResult<Rollout> {
+    let oid = ObjectId::parse_str(&id)?;
+    let mut rollout = claims
+        .find_one_with_access(&state.db.rollouts(), doc! { "_id": oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Rollout not found"))?;
+    let ids: Vec<ObjectId> = rollout.devices.iter().map(|d| d.device_id).collect();
+    let mut filter = doc! { "_id": { "$in": &ids } };
+    filter.extend(DeviceDoc::access_filter(
+        &claims.scopes_with_min_role(Role::Editor)?,
+    ));
+    if state.db.devices().count_documents(filter).await? < ids.len() as u64 {
+        return Err(ServerError::forbidden(
+            "changing a rollout needs the editor role on all of its devices",
+        ));
+    }
+
+    let by = operator(&claims);
+    let reason = |verb: &str| match &body.note {
+        Some(note) => format!("{} by {}: {}", verb, by, note),
+        None => format!("{} by {}", verb, by),
+    };
+    let mut reactivate = Vec::new();
+    match (body.action, rollout.state) {
+        (RolloutAction::Halt, RolloutState::Running) => {
+            rollout.state = RolloutState::Halted;
+            rollout.reason = Some(reason("halted"));
+        }
+        (RolloutAction::Resume, RolloutState::Halted) => {
+            let (_, failed) = rollout.updated_and_failed();
+            rollout.accepted_failures += failed as u32;
+            rollout.state = RolloutState::Running;
+            rollout.reason = None;
+        }
+        (RolloutAction::Rollback, RolloutState::Running | RolloutState::Halted) => {
+            reactivate = roll_back(&mut rollout, reason("rolled back"));
+        }
+        (RolloutAction::Cancel, RolloutState::Running | RolloutState::Halted) => {
+            rollout.state = RolloutState::Cancelled;
+            rollout.reason = Some(reason("cancelled"));
+        }
+        (action, current) => {
+            let verb = match action {
+                RolloutAction::Halt => "halt",
+                RolloutAction::Resume => "resume",
+                RolloutAction::Rollback => "roll back",
+                RolloutAction::Cancel => "cancel",
+            };
+            return Err(ServerError::bad_request(&format!(
+                "cannot {} a rollout that is {}",
+                verb, current
+            )));
+        }
+    }
+    if !rollout.save(&state.db).await? {
+        return Err(ServerError::bad_request(
+            "the rollout changed meanwhile, try again",
+        ));
+    }
+
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        &format!("Rollout {} is {}", rollout.id, rollout.state),
+        rollout.reason.as_deref().unwrap_or_default(),
+        None,
+    )
+    .await;
+    reactivate_previous(&state, &claims, &rollout, reactivate).await;
+
+    Ok(ServerResponse::builder()
+        .body(rollout.to_public())
+        .ok()
+        .build())
+}
+
+/// Mark every device that got the revision as rolled back and the rollout
+/// as rolled back. Returns the devices with the revision to reactivate,
+/// once the rollout is saved.
+fn roll_back(rollout: &mut RolloutDoc, reason: String) -> Vec<(ObjectId, Option<String>)> {
+    let mut reactivate = Vec::new();
+    for device in &mut rollout.devices {
+        if matches!(
+            device.state,
+            RolloutDeviceState::Deploying
+                | RolloutDeviceState::Healthy
+                | RolloutDeviceState::Failed
+        ) {
+            device.state = RolloutDeviceState::RolledBack;
+            reactivate.push((device.device_id, device.previous_revision_id.clone()));
+        }
+    }
+    rollout.state = RolloutState::RolledBack;
+    rollout.reason = Some(reason);
+    reactivate
+}
+
+async fn reactivate_previous(
+    state: &AppState,
+    claims: &Claims,
+    rollout: &RolloutDoc,
+    devices: Vec<(ObjectId, Option<String>)>,
+) {
+    for (device_id, previous) in devices {
+        if let Err(e) = DeployRevisionDoc::activate(&state.db, device_id, previous.as_deref()).await
+        {
+            warn!("Rolling back {} failed: {}", device_id, e);
+            continue;
+        }
+        let _ = AuditLogDoc::add(
+            &state.db,
+            claims,
+            &state.config,
+            &format!("Rollout {} rolled back {}", rollout.id, rollout.revision_id),
+            &match &previous {
+                Some(previous) => format!("reactivated revision {}", previous),
+                None => "left without an active revision, as before the rollout".to_string(),
+            },
+            Some(device_id),
+        )
+        .await;
+    }
+}
+
+/// Advance running rollouts wave by wave, until the server stops.
+pub async fn run_controller(state: AppState) {
+    let mut tick = tokio::time::interval(ROLLOUT_TICK);
+    loop {
+        tick.tick().await;
+        let rollouts = match RolloutDoc::running(&state.db).await {
+            Ok(rollouts) => rollouts,
+            Err(e) => {
+                warn!("Loading rollouts failed: {}", e);
+                continue;
+            }
+        };
+        for mut rollout in rollouts {
+            if let Err(e) = advance(&state, &mut rollout).await {
+                warn!("Advancing rollout {} failed: {}", rollout.id, e);
+            }
+        }
+    }
+}
+
+/// Who the controller acts as in audit logs.
+fn controller_claims(rollout: &RolloutDoc) -> Claims {
+    Claims {
+        roles: vec![],
+        is_admin: false,
+        user_name: format!("rollout {}", rollout.id),
+        user_email: rollout.created_by.clone(),
+        user_id: None,
+    }
+}
+
+/// Assess the updated devices, then halt, move to the next wave or finish.
+/// Revisions are only switched once the new state is saved, so a rollout an
+/// operator changed meanwhile is left for the next tick.
+async fn advance(state: &AppState, rollout: &mut RolloutDoc) -> ServerResult<()> {
+    let now = DateTime::now();
+    let mut failed = Vec::new();
+    for device in rollout.devices.iter_mut() {
+        if !matches!(
+            device.state,
+            RolloutDeviceState::Deploying | RolloutDeviceState::Healthy
+        ) {
+            continue;
+        }
+        let activated_at = device.activated_at.unwrap_or(now);
+        let elapsed = (now.timestamp_millis() - activated_at.timestamp_millis()).max(0) / 1000;
+        let assessed = match DeployReportDoc::compute_deployment_status_snapshot_for_device(
+            &state.db,
+            &device.device_id,
+            &rollout.revision_id,
+        )
+        .await
+        {
+            Ok(snapshot) => rollout.policy.assess(&snapshot, elapsed as u64),
+            Err(ServerError::NotFound(_)) => Some((
+                RolloutDeviceState::Failed,
+                Some("the revision was removed from the device".to_string()),
+            )),
+            Err(e) => return Err(e),
+        };
+        let Some((next, error)) = assessed else {
+            continue;
+        };
+        if next == RolloutDeviceState::Failed && device.state != RolloutDeviceState::Failed {
+            failed.push((device.device_id, error.clone().unwrap_or_default()));
+        }
+        device.state = next;
+        device.error = error;
+    }
+
+    let mut reactivate = Vec::new();
+    let (updated, failures) = rollout.updated_and_failed();
+    if rollout.policy.exceeded(failures, updated) {
+        let reason = format!(
+            "{} of {} updated devices failed, above the threshold of {}%",
+            failures, updated, rollout.policy.failure_threshold_percent
+        );
+        match rollout.policy.auto_rollback {
+            true => reactivate = roll_back(rollout, reason),
+            false => {
+                rollout.state = RolloutState::Halted;
+                rollout.reason = Some(reason);
+            }
+        }
+    } else if rollout
+        .devices
+        .iter()
+        .filter(|d| d.wave <= rollout.current_wave)
+        .all(|d| {
+            matches!(
+                d.state,
+                RolloutDeviceState::Healthy | RolloutDeviceState::Failed
+            )
+        })
+    {
+        if rollout.current_wave + 1 >= rollout.waves {
+            rollout.state = RolloutState::Completed;
+        } else {
+            rollout.current_wave += 1;
+        }
+    }
+
+    let mut activate = Vec::new();
+    if rollout.state == RolloutState::Running {
+        for device in rollout.devices.iter_mut() {
+            if device.wave <= rollout.current_wave && device.state == RolloutDeviceState::Pending {
+                device.state = RolloutDeviceState::Deploying;
+                device.activated_at = Some(now);
+                activate.push((device.device_id, device.wave));
+            }
+        }
+    }
+
+    if !rollout.save(&state.db).await? {
+        return Ok(());
+    }
+    if rollout.state != RolloutState::Running {
+        info!(
+            "Rollout {} of {} is {}",
+            rollout.id, rollout.revision_id, rollout.state
+        );
+    }
+
+    let claims = controller_claims(rollout);
+    for (device_id, wave) in activate {
+        DeployRevisionDoc::activate(&state.db, device_id, Some(&rollout.revision_id)).await?;
+        let _ = AuditLogDoc::add(
+            &state.db,
+            &claims,
+            &state.config,
+            &format!(
+                "Rollout {} activated revision {}",
+                rollout.id, rollout.revision_id
+            ),
+            &format!("wave {} of {}", wave + 1, rollout.waves),
+            Some(device_id),
+        )
+        .await;
+    }
+    // failed devices become alerts, so they open incidents
+    for (device_id, error) in failed {
+        if let Some(device) = state
+            .db
+            .devices()
+            .find_one(doc! { "_id": device_id })
+            .await?
+        {
+            state.events.publish(
+                &device,
+                DeviceEventKind::Alert {
+                    rule: "rollout_failed".to_string(),
+                    message: format!(
+                        "Revision {} of rollout {} failed on {}: {}",
+                        rollout.revision_id, rollout.id, device.name, error
+                    ),
+                },
+            );
+        }
+    }
+    reactivate_previous(state, &claims, rollout, reactivate).await;
+    Ok(())
+}
@@ -2,11 +2,21 @@ use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use axum::{
     Extension, Router,
-    http::{Method, header},
-    response::IntoResponse,
+    extract::{Request, State},
+    http::{HeaderValue, Method, header},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use axum_server::tls_rustls::RustlsConfig;
+use m87_shared::db_stats::CollectionStats;
+use m87_shared::notices::CliNotice;
+use m87_shared::org::OrgLimitsStatus;
+use m87_shared::protocol::{
+    Compatibility, PROTOCOL_HEADER, PROTOCOL_VERSION, check_compatibility, parse_protocol,
+    upgrade_message,
+};
+use m87_shared::relay::{RelayStats, resident_memory_bytes};
 use reqwest::StatusCode;
 use tokio::sync::watch;
 use tower_http::{
@@ -20,23 +30,130 @@ use tracing::{info, warn};
 
 use crate::{
     api::{
-        auth,
+        access_grant, approval, auth,
         certificate::{create_tls_config, update_cert},
-        device, org,
+        deploy_spec, device, grpc, incident, org,
         quic::run_quic_endpoint,
+        rollout, usage,
         web_transport::run_webtransport,
+        webhook,
     },
+    auth::claims::Claims,
     config::AppConfig,
     db::Mongo,
+    models::org_limits,
     relay::relay_state::RelayState,
-    response::ServerResult,
-    util::app_state::AppState,
+    response::{ServerAppResult, ServerError, ServerResponse, ServerResult},
+    util::{
+        advisories,
+        app_state::AppState,
+        events::EventBus,
+        iot_bridge::{self, IotBridge},
+        net,
+    },
 };
 
 async fn get_status() -> impl IntoResponse {
     "ok"
 }
 
+/// Notices for CLIs from `CLI_NOTICES_FILE`, read on every request so they
+/// can be changed without a restart. A broken file is logged and ignored.
+async fn get_notices(State(state): State<AppState>) -> ServerAppResult<Vec<CliNotice>> {
+    let notices = match &state.config.cli_notices_file {
+        Some(path) => match read_notices(path) {
+            Ok(notices) => notices,
+            Err(e) => {
+                warn!("Ignoring CLI notices in {}: {}", path, e);
+                Vec::new()
+            }
+        },
+        None => Vec::new(),
+    };
+    Ok(ServerResponse::builder()
+        .body(notices)
+        .status_code(StatusCode::OK)
+        .build())
+}
+
+fn read_notices(path: &str) -> Result<Vec<CliNotice>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&contents).map_err(|e| e.to_string())
+}
+
+/// Tunnel count and memory of this server, for `m87 admin bench`.
+async fn get_relay_stats(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> ServerAppResult<RelayStats> {
+    if !claims.is_admin {
+        return Err(ServerError::unauthorized(""));
+    }
+    Ok(ServerResponse::builder()
+        .body(RelayStats {
+            tunnels: state.relay.tunnel_count().await,
+            rss_bytes: resident_memory_bytes(),
+        })
+        .status_code(StatusCode::OK)
+        .build())
+}
+
+/// Collections, their indexes and index use, for `m87 admin db-stats`.
+async fn get_db_stats(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> ServerAppResult<Vec<CollectionStats>> {
+    if !claims.is_admin {
+        return Err(ServerError::unauthorized(""));
+    }
+    Ok(ServerResponse::builder()
+        .body(state.db.collection_stats().await?)
+        .status_code(StatusCode::OK)
+        .build())
+}
+
+/// Limits and usage of every org the caller belongs to, for `m87 limits`.
+async fn get_limits(
+    claims: Claims,
+    State(state): State<AppState>,
+) -> ServerAppResult<Vec<OrgLimitsStatus>> {
+    let mut org_ids: Vec<&str> = claims
+        .roles
+        .iter()
+        .filter_map(|r| r.scope.strip_prefix("org:"))
+        .collect();
+    org_ids.sort();
+    org_ids.dedup();
+
+    let mut statuses = Vec::with_capacity(org_ids.len());
+    for org_id in org_ids {
+        statuses.push(org_limits::status(&state.db, &state.config, &state.relay, org_id).await?);
+    }
+    Ok(ServerResponse::builder()
+        .body(statuses)
+        .status_code(StatusCode::OK)
+        .build())
+}
+
+/// Refuse clients below the minimum protocol version with an upgrade hint and
+/// announce the server's version on every response.
+async fn negotiate_protocol(req: Request, next: Next) -> Response {
+    let peer = parse_protocol(
+        req.headers()
+            .get(PROTOCOL_HEADER)
+            .and_then(|v| v.to_str().ok()),
+    );
+    let mut res = match check_compatibility(peer) {
+        Compatibility::PeerTooOld => {
+            ServerError::upgrade_required(&upgrade_message("This m87 client", peer)).into_response()
+        }
+        Compatibility::Compatible | Compatibility::PeerNewer => next.run(req).await,
+    };
+    res.headers_mut()
+        .insert(PROTOCOL_HEADER, HeaderValue::from(PROTOCOL_VERSION));
+    res
+}
+
 pub async fn serve(
     db: Arc<Mongo>,
     relay: Arc<RelayState>,
@@ -49,10 +166,13 @@ pub async fn serve(
 
     let (reload_tx, reload_rx) = watch::channel(());
 
+    let (iot, iot_rx) = IotBridge::new();
     let state = AppState {
         db: db.clone(),
         config: cfg.clone(),
         relay: relay.clone(),
+        events: EventBus::new(),
+        iot,
     };
 
     // CORS for REST
@@ -63,18 +183,30 @@ pub async fn serve(
             header::AUTHORIZATION,
             header::CONTENT_TYPE,
             header::HeaderName::from_static("sec-websocket-protocol"),
-        ]);
+            header::HeaderName::from_static(PROTOCOL_HEADER),
+        ])
+        .expose_headers([header::HeaderName::from_static(PROTOCOL_HEADER)]);
 
     // Admin route: writes certs to disk + signals reload
     let admin = Router::new()
         .route("/update-cert", post(update_cert))
+        .route("/relay", get(get_relay_stats))
+        .route("/db-stats", get(get_db_stats))
         .layer(Extension(reload_tx.clone()));
 
     let app = Router::new()
+        .nest("/access-grants", access_grant::create_route())
+        .nest("/approvals", approval::create_route())
         .nest("/auth", auth::create_route())
         .nest("/device", device::create_route())
+        .nest("/incidents", incident::create_route())
         .nest("/organization", org::create_route())
+        .nest("/rollouts", rollout::create_route())
+        .nest("/usage", usage::create_route())
+        .nest("/webhook", webhook::create_route())
         .nest("/admin", admin)
+        .route("/limits", get(get_limits))
+        .route("/notices", get(get_notices))
         .route("/status", get(get_status))
         .layer(cors)
         .layer(SetSensitiveHeadersLayer::new(std::iter::once(
@@ -86,9 +218,12 @@ pub async fn serve(
         ))
         .layer(TraceLayer::new_for_http())
         .layer(CompressionLayer::new())
-        .with_state(state.clone());
+        .layer(middleware::from_fn(negotiate_protocol))
+        .with_state(state.clone())
+        // gRPC is merged after the REST layers: its Events stream must not hit the request timeout
+        .merge(grpc::create_router(state.clone()));
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], cfg.unified_port));
+    let addr = net::listen_addr(cfg.unified_port);
     info!("HTTPS/QUIC listening on {}", addr);
 
     // ===== HTTPS SERVER LOOP WITH HOT RELOAD =====
@@ -106,7 +241,11 @@ pub async fn serve(
                 let handle = axum_server::Handle::new();
                 let server = axum_server::bind_rustls(addr, tls_cfg)
                     .handle(handle.clone())
-                    .serve(app.clone().into_make_service());
+                    // login throttling keys on the client address
+                    .serve(
+                        app.clone()
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    );
 
                 tokio::select! {
                     _ = server => {
@@ -122,6 +261,14 @@ pub async fn serve(
         }
     });
 
+    tokio::spawn(advisories::run_scanner(state.clone()));
+    tokio::spawn(access_grant::run_expiry(state.clone()));
+    tokio::spawn(usage::run_meter(state.clone()));
+    tokio::spawn(incident::run_watcher(state.clone()));
+    tokio::spawn(iot_bridge::run(state.clone(), iot_rx));
+    tokio::spawn(deploy_spec::run_file_sweep(state.clone()));
+    tokio::spawn(rollout::run_controller(state.clone()));
+
     // ===== QUIC SERVER =====
     let quic_task = tokio::spawn(run_quic_endpoint(state.clone(), reload_rx.clone()));
     let wt_task = tokio::spawn(run_webtransport(state.clone(), reload_rx.clone()));
@@ -0,0 +1,194 @@
+//! Monthly usage per org for chargeback. A background meter adds connected
+//! device time and relayed bytes every minute and samples storage every hour;
+//! org admins read the records with `GET /usage` or `GET /usage/csv`.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::time::{Duration, Instant};
+
+use axum::Router;
+use axum::extract::{Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use futures::TryStreamExt;
+use m87_shared::roles::Role;
+use m87_shared::usage::{
+    OrgUsageRecord, USAGE_EXPORT_VERSION, UsageExport, UsageQuery, is_month, usage_csv,
+};
+use mongodb::bson::doc;
+use tracing::warn;
+
+use crate::auth::claims::Claims;
+use crate::models::device::DeviceDoc;
+use crate::models::org_limits;
+use crate::models::org_usage::{OrgUsageDoc, current_month};
+use crate::response::{ServerAppResult, ServerError, ServerResponse, ServerResult};
+use crate::util::app_state::AppState;
+
+/// How often connected time and relayed bytes are added up.
+const METER_TICK: Duration = Duration::from_secs(60);
+/// Storage and device count are sampled every this many ticks.
+const PEAK_EVERY: u32 = 60;
+
+pub fn create_route() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_usage))
+        .route("/csv", get(get_usage_csv))
+}
+
+/// Records the caller may see: of orgs they administer, of any org for
+/// instance admins.
+async fn records(
+    claims: &Claims,
+    state: &AppState,
+    query: &UsageQuery,
+) -> ServerResult<Vec<OrgUsageRecord>> {
+    for month in [&query.from, &query.to].into_iter().flatten() {
+        if !is_month(month) {
+            return Err(ServerError::bad_request(&format!(
+                "invalid month '{}', expected YYYY-MM",
+                month
+            )));
+        }
+    }
+
+    let org_ids = if claims.is_admin {
+        query.org_id.clone().map(|id| vec![id])
+    } else {
+        let administered: Vec<String> = claims
+            .roles
+            .iter()
+            .filter(|r| Role::allows(&r.role, &Role::Admin))
+            .filter_map(|r| r.scope.strip_prefix("org:"))
+            .map(str::to_string)
+            .collect();
+        match &query.org_id {
+            Some(id) if administered.contains(id) => Some(vec![id.clone()]),
+            Some(_) => {
+                return Err(ServerError::forbidden(
+                    "Usage is only shown to organization admins",
+                ));
+            }
+            None => Some(administered),
+        }
+    };
+
+    let docs = OrgUsageDoc::list(
+        &state.db,
+        org_ids.as_deref(),
+        query.from.as_deref(),
+        query.to.as_deref(),
+    )
+    .await?;
+    Ok(docs.iter().map(|d| d.to_record()).collect())
+}
+
+async fn get_usage(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+) -> ServerAppResult<UsageExport> {
+    let records = records(&claims, &state, &query).await?;
+    Ok(ServerResponse::builder()
+        .body(UsageExport {
+            version: USAGE_EXPORT_VERSION,
+            records,
+        })
+        .ok()
+        .build())
+}
+
+async fn get_usage_csv(
+    claims: Claims,
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Response, ServerError> {
+    let records = records(&claims, &state, &query).await?;
+    Ok((
+        [(CONTENT_TYPE, "text/csv; charset=utf-8")],
+        usage_csv(&records),
+    )
+        .into_response())
+}
+
+pub async fn run_meter(state: AppState) {
+    let mut tick = tokio::time::interval(METER_TICK);
+    let mut last = Instant::now();
+    let mut ticks = 0u32;
+    loop {
+        tick.tick().await;
+        // whole seconds only; the rest carries over to the next tick
+        let seconds = last.elapsed().as_secs();
+        last += Duration::from_secs(seconds);
+        if let Err(e) = meter(&state, seconds).await {
+            warn!("Metering usage failed: {}", e);
+        }
+        if ticks.is_multiple_of(PEAK_EVERY)
+            && let Err(e) = sample_peaks(&state).await
+        {
+            warn!("Sampling org storage failed: {}", e);
+        }
+        ticks = ticks.wrapping_add(1);
+    }
+}
+
+/// Credit `seconds` to every connected device and the bytes relayed since the
+/// last tick to each device's orgs.
+async fn meter(state: &AppState, seconds: u64) -> ServerResult<()> {
+    let connected: HashSet<String> = state.relay.tunnel_ids().await.into_iter().collect();
+    let relayed = state.relay.take_relay_bytes();
+    let short_ids: BTreeSet<String> = connected.iter().chain(relayed.keys()).cloned().collect();
+    if short_ids.is_empty() {
+        return Ok(());
+    }
+
+    let devices: Vec<DeviceDoc> = state
+        .db
+        .devices()
+        .find(doc! { "short_id": { "$in": Vec::from_iter(short_ids) } })
+        .await?
+        .try_collect()
+        .await?;
+
+    let mut per_org: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for device in &devices {
+        let device_seconds = if connected.contains(&device.short_id) {
+            seconds
+        } else {
+            0
+        };
+        let bytes = relayed.get(&device.short_id).copied().unwrap_or(0);
+        for org_id in org_limits::device_org_ids(device) {
+            let entry = per_org.entry(org_id).or_default();
+            entry.0 += device_seconds;
+            entry.1 += bytes;
+        }
+    }
+
+    let month = current_month();
+    for (org_id, (device_seconds, bytes)) in per_org {
+        OrgUsageDoc::add(&state.db, &org_id, &month, device_seconds, bytes).await?;
+    }
+    Ok(())
+}
+
+async fn sample_peaks(state: &AppState) -> ServerResult<()> {
+    let devices: Vec<DeviceDoc> = state
+        .db
+        .devices()
+        .find(doc! {})
+        .await?
+        .try_collect()
+        .await?;
+    let org_ids: BTreeSet<String> = devices
+        .iter()
+        .flat_map(org_limits::device_org_ids)
+        .collect();
+
+    let month = current_month();
+    for org_id in org_ids {
+        let (devices, bytes) = org_limits::storage(&state.db, &org_id).await?;
+        OrgUsageDoc::raise_peaks(&state.db, &org_id, &month, bytes, devices).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use axum::Router;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::HeaderMap;
+use axum::http::header::{AUTHORIZATION, SEC_WEBSOCKET_PROTOCOL};
+use axum::response::Response;
+use axum::routing::get;
+use m87_shared::roles::{Role, required_stream_role};
+use mongodb::bson::doc;
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::api::approval::require_approval;
+use crate::api::quic::write_msg;
+use crate::auth::claims::Claims;
+use crate::models::audit_logs::AuditLogDoc;
+use crate::models::device::DeviceDoc;
+use crate::response::{ServerError, ServerResult};
+use crate::util::app_state::AppState;
+
+/// Subprotocol the web app offers next to `bearer.<token>`, since browsers
+/// cannot set an Authorization header on WebSockets.
+const TERMINAL_PROTOCOL: &str = "m87-terminal";
+const BEARER_PROTOCOL_PREFIX: &str = "bearer.";
+
+/// Prefix of a resize frame on the device's terminal stream, followed by
+/// rows and cols as big-endian u16.
+const RESIZE_FRAME: u8 = 0xFF;
+
+pub fn create_route() -> Router<AppState> {
+    // This router is mounted under /device already.
+    Router::new().route("/{id}/terminal", get(open_terminal))
+}
+
+#[derive(Deserialize)]
+struct TerminalQuery {
+    term: Option<String>,
+}
+
+/// Stream header the device runtime expects, as sent by `m87 <device> shell`.
+#[derive(Serialize)]
+#[serde(tag = "type")]
+enum DeviceStream {
+    Terminal {
+        token: String,
+        term: Option<String>,
+        granted_role: String,
+    },
+}
+
+/// Text messages from the browser. Binary messages are raw keyboard input.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    Input { data: String },
+    Resize { cols: u16, rows: u16 },
+}
+
+async fn open_terminal(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<TerminalQuery>,
+) -> ServerResult<Response> {
+    let token =
+        bearer_token(&headers).ok_or_else(|| ServerError::missing_token("missing API key"))?;
+    let claims = Claims::from_bearer_or_key(&token, &state.db, &state.config).await?;
+
+    let device_oid = ObjectId::parse_str(&id)?;
+    let device = claims
+        .find_one_with_scope_and_role::<DeviceDoc>(
+            &state.db.devices(),
+            doc! { "_id": &device_oid },
+            required_stream_role(&serde_json::json!({ "type": "Terminal" })),
+        )
+        .await?
+        .ok_or_else(|| ServerError::not_found("Device not found"))?;
+    let role = claims.get_role(&device)?;
+
+    if let Err(message) = require_approval(&state, &claims, &device, "shell", "").await? {
+        return Err(ServerError::forbidden(&message));
+    }
+
+    let conn = state
+        .relay
+        .get_tunnel(&device.short_id)
+        .await
+        .ok_or_else(|| ServerError::not_found("device tunnel not connected"))?;
+
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Opened web terminal",
+        "",
+        device.id,
+    )
+    .await;
+
+    let short_id = device.short_id.clone();
+    // the end of an access grant closes the terminal
+    let ended = Arc::new(Notify::new());
+    let notify = ended.clone();
+    let client = state
+        .relay
+        .register_client(&short_id, &claims.user_email, move || notify.notify_one());
+    Ok(ws
+        .protocols([TERMINAL_PROTOCOL])
+        .on_upgrade(move |socket| async move {
+            let _client = client;
+            if let Err(e) = bridge(socket, conn, token, query.term, role, ended).await {
+                warn!(%short_id, "web terminal closed with error: {:?}", e);
+            }
+        }))
+}
+
+/// Token from the Authorization header or the `bearer.<token>` subprotocol.
+fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    if let Some(token) = headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+    {
+        return Some(token.to_string());
+    }
+    headers
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .find_map(|p| p.trim().strip_prefix(BEARER_PROTOCOL_PREFIX))
+        .map(str::to_string)
+}
+
+/// Pump the WebSocket to a terminal stream on the device until either closes.
+async fn bridge(
+    mut socket: WebSocket,
+    conn: quinn::Connection,
+    token: String,
+    term: Option<String>,
+    role: Role,
+    ended: Arc<Notify>,
+) -> ServerResult<()> {
+    let (mut send, mut recv) = conn
+        .open_bi()
+        .await
+        .map_err(|e| ServerError::internal_error(&format!("device open_bi failed: {e}")))?;
+    let header = DeviceStream::Terminal {
+        token,
+        term,
+        granted_role: role.to_string(),
+    };
+    write_msg(&mut send, &header).await?;
+
+    let mut buf = vec![0u8; 8192];
+    loop {
+        tokio::select! {
+            _ = ended.notified() => {
+                let _ = socket.send(Message::Close(None)).await;
+                break;
+            }
+
+            read = recv.read(&mut buf) => match read {
+                Ok(Some(n)) => {
+                    if socket.send(Message::Binary(buf[..n].to_vec().into())).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) | Err(_) => {
+                    let _ = socket.send(Message::Close(None)).await;
+                    break;
+                }
+            },
+
+            msg = socket.recv() => {
+                let input = match msg {
+                    Some(Ok(Message::Binary(data))) => data.to_vec(),
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                        Ok(ClientMessage::Input { data }) => data.into_bytes(),
+                        Ok(ClientMessage::Resize { cols, rows }) => resize_frame(cols, rows),
+                        Err(e) => {
+                            debug!("web terminal: ignoring message: {e}");
+                            continue;
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    Some(Ok(_)) => continue,
+                };
+                if send.write_all(&input).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = send.finish();
+    Ok(())
+}
+
+fn resize_frame(cols: u16, rows: u16) -> Vec<u8> {
+    let mut frame = vec![RESIZE_FRAME];
+    frame.extend_from_slice(&rows.to_be_bytes());
+    frame.extend_from_slice(&cols.to_be_bytes());
+    frame
+}
@@ -1,4 +1,4 @@
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{sync::Arc, time::Duration};
 
 use bytes::Bytes;
 use h3::{ext::Protocol, quic::BidiStream, server::Connection as H3Connection};
@@ -21,7 +21,7 @@ use crate::{
     auth::claims::Claims,
     models::{audit_logs::AuditLogDoc, device::DeviceDoc},
     response::{ServerError, ServerResult},
-    util::app_state::AppState,
+    util::{app_state::AppState, net},
 };
 
 use super::quic::handle_forward_supervised;
@@ -30,7 +30,7 @@ pub async fn run_webtransport(
     state: AppState,
     mut reload_rx: watch::Receiver<()>,
 ) -> ServerResult<()> {
-    let addr = SocketAddr::from(([0, 0, 0, 0], state.config.webtransport_port));
+    let port = state.config.webtransport_port;
 
     loop {
         let (certs, key) = load_cert_and_key(&state.config).await?;
@@ -58,10 +58,10 @@ pub async fn run_webtransport(
         transport_config.keep_alive_interval(Some(Duration::from_secs(5)));
         server_config.transport = Arc::new(transport_config);
 
-        let endpoint = quinn::Endpoint::server(server_config, addr)
+        let endpoint = net::quic_endpoint(server_config, port)
             .map_err(|e| ServerError::internal_error(&format!("WT bind: {e}")))?;
 
-        info!("WebTransport listening (HTTP/3) on udp://{}", addr);
+        info!("WebTransport listening (HTTP/3) on udp port {}", port);
 
         loop {
             tokio::select! {
@@ -199,11 +199,11 @@ async fn handle_webtransport_forward(
         .find_one_with_scope_and_role::<DeviceDoc>(
             &state.db.devices(),
             doc! { "short_id": &device_id },
-            Role::Editor,
+            Role::Viewer,
         )
         .await;
 
-    match res {
+    let role = match res {
         Ok(Some(device)) => {
             let _ = AuditLogDoc::add(
                 &state.db,
@@ -214,6 +214,7 @@ async fn handle_webtransport_forward(
                 device.id.clone(),
             )
             .await;
+            claims.get_role(&device)?
         }
         Ok(None) => {
             let _ = AuditLogDoc::add(
@@ -248,8 +249,15 @@ async fn handle_webtransport_forward(
     };
     let web = WebConn::new(Arc::new(session), inner_conn.clone());
     tokio::spawn(async move {
-        if let Err(e) =
-            handle_forward_supervised(ClientConn::Web(web), device_id.clone(), state.clone()).await
+        if let Err(e) = handle_forward_supervised(
+            ClientConn::Web(web),
+            device_id.clone(),
+            token,
+            role,
+            claims,
+            state.clone(),
+        )
+        .await
         {
             warn!(%device_id, "WT forward error: {:?}", e);
         }
@@ -0,0 +1,540 @@
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+use std::sync::LazyLock;
+
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use bytes::Bytes;
+use futures::TryStreamExt;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use m87_shared::deploy_spec::DeploymentRevision;
+use m87_shared::roles::Role;
+use m87_shared::webhook::{
+    CreateWebhookBody, CreatedWebhook, Webhook, WebhookAction, WebhookDelivery,
+};
+use mongodb::bson::{doc, oid::ObjectId};
+
+use crate::api::approval::{check_approval, deploy_details, use_approval};
+use crate::auth::claims::Claims;
+use crate::models::audit_logs::AuditLogDoc;
+use crate::models::deploy_spec::DeployRevisionDoc;
+use crate::models::device::DeviceDoc;
+use crate::models::org;
+use crate::models::org_limits;
+use crate::models::user::UserDoc;
+use crate::models::webhook::{PushInfo, WebhookDeliveryDoc, WebhookDoc, retag_image};
+use crate::response::{
+    ResponsePagination, ServerAppResult, ServerError, ServerResponse, ServerResult,
+};
+use crate::util::app_state::AppState;
+use crate::util::pagination::RequestPagination;
+
+/// Failed verifications logged per webhook: a burst, then one a minute, so
+/// unsigned requests cannot flood the delivery log.
+static FAILED_DELIVERY_LOG: LazyLock<DefaultKeyedRateLimiter<ObjectId>> = LazyLock::new(|| {
+    RateLimiter::keyed(
+        Quota::per_minute(NonZeroU32::new(1).unwrap()).allow_burst(NonZeroU32::new(5).unwrap()),
+    )
+});
+
+pub fn create_route() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_webhooks).post(create_webhook))
+        .route("/{id}", get(get_webhook).delete(delete_webhook))
+        .route("/{id}/deliveries", get(list_webhook_deliveries))
+        // called by GitHub/GitLab/registries, authenticated by the webhook secret
+        .route("/{id}/deliver", post(deliver_webhook))
+}
+
+fn parse_oid(id: &str) -> ServerResult<ObjectId> {
+    ObjectId::parse_str(id).map_err(|_| ServerError::bad_request("Invalid ObjectId"))
+}
+
+async fn find_webhook(claims: &Claims, state: &AppState, id: &str) -> ServerResult<WebhookDoc> {
+    let oid = parse_oid(id)?;
+    claims
+        .find_one_with_access(&state.db.webhooks(), doc! { "_id": oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Webhook not found"))
+}
+
+async fn list_webhooks(
+    claims: Claims,
+    State(state): State<AppState>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<Webhook>> {
+    let col = state.db.webhooks();
+    let docs = claims.list_with_access(&col, &pagination).await?;
+    let total_count = claims.count_with_access(&col).await?;
+
+    Ok(ServerResponse::builder()
+        .body(docs.iter().map(WebhookDoc::to_webhook).collect())
+        .status_code(axum::http::StatusCode::OK)
+        .pagination(ResponsePagination {
+            count: total_count,
+            offset: pagination.offset,
+            limit: pagination.limit,
+        })
+        .build())
+}
+
+async fn create_webhook(
+    claims: Claims,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateWebhookBody>,
+) -> ServerAppResult<CreatedWebhook> {
+    let name = payload.name.trim();
+    if name.is_empty() {
+        return Err(ServerError::bad_request("Webhook name must not be empty"));
+    }
+
+    // deliveries act with the creator's rights, looked up again each time
+    if claims.user_id.is_none() {
+        return Err(ServerError::forbidden("Webhooks must be created by a user"));
+    }
+
+    let owner_scope = match &payload.org_id {
+        Some(org_id) => {
+            let scope = org::org_scope(org_id);
+            if !claims.has_scope_and_role(&scope, Role::Editor) {
+                return Err(ServerError::forbidden("Not authorized for organization"));
+            }
+            scope
+        }
+        None => UserDoc::create_owner_scope(&claims.user_email),
+    };
+
+    // fail early if the creator cannot run the action at all
+    match &payload.action {
+        WebhookAction::ReapplyDeployment {
+            revision_id,
+            device_ids,
+            org_id,
+            ..
+        } => {
+            if device_ids.is_empty() && org_id.is_none() {
+                return Err(ServerError::bad_request(
+                    "Action needs device_ids or org_id as target",
+                ));
+            }
+            claims
+                .find_one_with_access(
+                    &state.db.deploy_revisions(),
+                    doc! { "revision.id": revision_id },
+                )
+                .await?
+                .ok_or_else(|| ServerError::not_found("Revision not found"))?;
+            for device_id in device_ids {
+                claims
+                    .find_one_with_scope_and_role(
+                        &state.db.devices(),
+                        doc! { "_id": parse_oid(device_id)? },
+                        Role::Editor,
+                    )
+                    .await?;
+            }
+            if let Some(org_id) = org_id
+                && !claims.has_scope_and_role(&org::org_scope(org_id), Role::Editor)
+            {
+                return Err(ServerError::forbidden("Not authorized for organization"));
+            }
+        }
+    }
+
+    let doc = WebhookDoc::create(
+        &state.db,
+        name.to_string(),
+        payload.provider,
+        payload.action,
+        payload.branch,
+        owner_scope,
+        claims.user_email.clone(),
+    )
+    .await?;
+
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Created webhook",
+        &format!(
+            "id={} name={} provider={}",
+            doc.id.map(|id| id.to_hex()).unwrap_or_default(),
+            doc.name,
+            doc.provider
+        ),
+        None,
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .body(CreatedWebhook {
+            webhook: doc.to_webhook(),
+            secret: doc.secret.clone(),
+        })
+        .status_code(axum::http::StatusCode::CREATED)
+        .build())
+}
+
+async fn get_webhook(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<Webhook> {
+    let doc = find_webhook(&claims, &state, &id).await?;
+    Ok(ServerResponse::builder()
+        .body(doc.to_webhook())
+        .status_code(axum::http::StatusCode::OK)
+        .build())
+}
+
+async fn delete_webhook(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> ServerAppResult<()> {
+    let oid = parse_oid(&id)?;
+    claims
+        .delete_one_with_access(&state.db.webhooks(), doc! { "_id": oid })
+        .await?;
+    state
+        .db
+        .webhook_deliveries()
+        .delete_many(doc! { "webhook_id": oid })
+        .await?;
+
+    let _ = AuditLogDoc::add(
+        &state.db,
+        &claims,
+        &state.config,
+        "Deleted webhook",
+        &format!("id={}", id),
+        None,
+    )
+    .await;
+
+    Ok(ServerResponse::builder()
+        .status_code(axum::http::StatusCode::NO_CONTENT)
+        .build())
+}
+
+async fn list_webhook_deliveries(
+    claims: Claims,
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    pagination: RequestPagination,
+) -> ServerAppResult<Vec<WebhookDelivery>> {
+    let webhook = find_webhook(&claims, &state, &id).await?;
+    let webhook_id = webhook.id.unwrap();
+    let docs = WebhookDeliveryDoc::list_for_webhook(&state.db, webhook_id, &pagination).await?;
+    let total_count = state
+        .db
+        .webhook_deliveries()
+        .count_documents(doc! { "webhook_id": webhook_id })
+        .await?;
+
+    Ok(ServerResponse::builder()
+        .body(docs.iter().map(WebhookDeliveryDoc::to_delivery).collect())
+        .status_code(axum::http::StatusCode::OK)
+        .pagination(ResponsePagination {
+            count: total_count,
+            offset: pagination.offset,
+            limit: pagination.limit,
+        })
+        .build())
+}
+
+async fn deliver_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ServerAppResult<String> {
+    let oid = parse_oid(&id)?;
+    let webhook = state
+        .db
+        .webhooks()
+        .find_one(doc! { "_id": oid })
+        .await?
+        .ok_or_else(|| ServerError::not_found("Webhook not found"))?;
+
+    if !webhook.verify(&headers, &body) {
+        if FAILED_DELIVERY_LOG.check_key(&oid).is_err() {
+            tracing::debug!("Not logging failed verification for webhook {}", oid);
+            return Err(ServerError::unauthorized("Invalid webhook signature"));
+        }
+        let _ = WebhookDeliveryDoc::add(
+            &state.db,
+            &state.config,
+            oid,
+            &PushInfo::default(),
+            false,
+            &Err("signature verification failed".to_string()),
+            vec![],
+        )
+        .await;
+        return Err(ServerError::unauthorized("Invalid webhook signature"));
+    }
+
+    let payload: serde_json::Value = serde_json::from_slice(&body)
+        .map_err(|e| ServerError::bad_request(&format!("Invalid JSON payload: {}", e)))?;
+    let info = webhook.push_info(&headers, &payload);
+    let Some(delivery_id) =
+        WebhookDeliveryDoc::claim(&state.db, &state.config, oid, &info, &body).await?
+    else {
+        return Ok(ServerResponse::builder()
+            .body("ignored redelivered payload".to_string())
+            .status_code(axum::http::StatusCode::OK)
+            .build());
+    };
+
+    let (result, device_ids) = match webhook.should_trigger(&info) {
+        Ok(()) => run_action(&state, &webhook, info.tag.as_deref().unwrap_or_default()).await,
+        Err(skipped) => (Ok(skipped), vec![]),
+    };
+
+    let _ = WebhookDeliveryDoc::finish(&state.db, delivery_id, &result, device_ids).await;
+
+    match result {
+        Ok(message) => Ok(ServerResponse::builder()
+            .body(message)
+            .status_code(axum::http::StatusCode::OK)
+            .build()),
+        Err(message) => Err(ServerError::bad_request(&message)),
+    }
+}
+
+/// Run the webhook's action for a verified delivery. Returns the outcome message
+/// and the devices that were touched.
+async fn run_action(
+    state: &AppState,
+    webhook: &WebhookDoc,
+    tag: &str,
+) -> (Result<String, String>, Vec<ObjectId>) {
+    match &webhook.action {
+        WebhookAction::ReapplyDeployment {
+            revision_id,
+            device_ids,
+            org_id,
+            image,
+        } => {
+            reapply_deployment(
+                state,
+                webhook,
+                revision_id,
+                device_ids,
+                org_id.as_deref(),
+                image.as_deref(),
+                tag,
+            )
+            .await
+        }
+    }
+}
+
+async fn reapply_deployment(
+    state: &AppState,
+    webhook: &WebhookDoc,
+    revision_id: &str,
+    device_ids: &[String],
+    org_id: Option<&str>,
+    image: Option<&str>,
+    tag: &str,
+) -> (Result<String, String>, Vec<ObjectId>) {
+    let claims = match creator_claims(state, webhook).await {
+        Ok(claims) => claims,
+        Err(e) => return (Err(format!("webhook creator: {}", e)), vec![]),
+    };
+
+    // with its files, as the image may be referenced in one
+    let stored =
+        match DeployRevisionDoc::get_by_revision_id(&state.db, revision_id.to_string()).await {
+            Ok(doc) => doc.into_revision(&state.db).await,
+            Err(e) => Err(e),
+        };
+    let template = match stored {
+        Ok(revision) => revision,
+        Err(e) => return (Err(format!("revision {}: {}", revision_id, e)), vec![]),
+    };
+
+    let revision = match image {
+        Some(image) => match retag_revision(template, image, tag) {
+            Ok(rev) => rev,
+            Err(e) => return (Err(e), vec![]),
+        },
+        None => template,
+    };
+
+    let devices = match target_devices(state, device_ids, org_id).await {
+        Ok(devices) => devices,
+        Err(e) => return (Err(format!("failed to resolve devices: {}", e)), vec![]),
+    };
+    if devices.is_empty() {
+        return (Err("no target devices found".to_string()), vec![]);
+    }
+    if let Err(e) = check_deploy_rights(&claims, &devices, org_id) {
+        return (Err(e), vec![]);
+    }
+
+    // the revision limit applies as for revisions created by hand
+    for device in devices.values() {
+        if let Err(e) = org_limits::check_new_revision(&state.db, &state.config, device).await {
+            return (Err(format!("{}: {}", device.short_id, e)), vec![]);
+        }
+    }
+
+    // devices that require approval file a request for the creator; nothing
+    // is activated until all of them are approved and the payload redelivered
+    let details = deploy_details(&revision);
+    let mut approvals = Vec::with_capacity(devices.len());
+    let mut refused = Vec::new();
+    for device in devices.values() {
+        match check_approval(state, &claims, device, "deploy", &details).await {
+            Ok(Ok(approval)) => approvals.push(approval),
+            Ok(Err(message)) => refused.push(message),
+            Err(e) => refused.push(format!("{}: {}", device.short_id, e)),
+        }
+    }
+    if !refused.is_empty() {
+        return (Err(refused.join("; ")), vec![]);
+    }
+
+    let new_id = revision.id.clone().unwrap_or_default();
+
+    let mut applied = Vec::new();
+    let mut failed = Vec::new();
+    for (device, approval) in devices.values().zip(approvals) {
+        let device_oid = device.id.unwrap();
+        if let Err(e) = use_approval(state, &claims, device, "deploy", approval).await {
+            failed.push(format!("{}: {}", device.short_id, e));
+            continue;
+        }
+        match DeployRevisionDoc::activate_for_device(&state.db, device, revision.clone()).await {
+            Ok(()) => {
+                applied.push(device_oid);
+                let _ = AuditLogDoc::add(
+                    &state.db,
+                    &claims,
+                    &state.config,
+                    &format!("Webhook activated deployment revision for {}", &device_oid),
+                    &format!("revision={} tag={}", new_id, tag),
+                    Some(device_oid),
+                )
+                .await;
+            }
+            Err(e) => failed.push(format!("{}: {}", device.short_id, e)),
+        }
+    }
+
+    let message = format!(
+        "applied revision {} (tag {}) to {} device(s)",
+        new_id,
+        tag,
+        applied.len()
+    );
+    if failed.is_empty() {
+        (Ok(message), applied)
+    } else {
+        (
+            Err(format!("{}, failed: {}", message, failed.join(", "))),
+            applied,
+        )
+    }
+}
+
+/// The creator's current claims: a webhook acts on their behalf, so each
+/// delivery runs with the rights they have now, not at creation.
+async fn creator_claims(state: &AppState, webhook: &WebhookDoc) -> ServerResult<Claims> {
+    let user = state
+        .db
+        .users()
+        .find_one(doc! { "email": &webhook.created_by })
+        .await?
+        .ok_or_else(|| ServerError::forbidden("user no longer exists"))?;
+    let mut claims = Claims::for_user(&user, &state.db, &state.config).await?;
+    claims.user_name = format!("webhook {}", webhook.name);
+    Ok(claims)
+}
+
+/// Whether the creator may still deploy to the organization and every
+/// target device.
+fn check_deploy_rights(
+    claims: &Claims,
+    devices: &BTreeMap<ObjectId, DeviceDoc>,
+    org_id: Option<&str>,
+) -> Result<(), String> {
+    if claims.is_admin {
+        return Ok(());
+    }
+    if let Some(org_id) = org_id
+        && !claims.has_scope_and_role(&org::org_scope(org_id), Role::Editor)
+    {
+        return Err(format!(
+            "webhook creator is no longer an editor of organization {}",
+            org_id
+        ));
+    }
+    let denied: Vec<&str> = devices
+        .values()
+        .filter(|device| {
+            !claims
+                .get_role(*device)
+                .is_ok_and(|role| Role::allows(&role, &Role::Editor))
+        })
+        .map(|device| device.short_id.as_str())
+        .collect();
+    if !denied.is_empty() {
+        return Err(format!(
+            "webhook creator may no longer deploy to {}",
+            denied.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Re-tag `image` in the revision. The id is recomputed from the new content so
+/// each tag ends up as its own revision.
+fn retag_revision(
+    mut revision: DeploymentRevision,
+    image: &str,
+    tag: &str,
+) -> Result<DeploymentRevision, String> {
+    let original_id = revision.id.take();
+    let yaml = revision
+        .to_yaml()
+        .map_err(|e| format!("failed to serialize revision: {}", e))?;
+    let retagged = retag_image(&yaml, image, tag);
+    if retagged == yaml {
+        revision.id = original_id;
+        return Ok(revision);
+    }
+    DeploymentRevision::from_yaml(&retagged)
+        .map_err(|e| format!("re-tagged revision is invalid: {}", e))
+}
+
+async fn target_devices(
+    state: &AppState,
+    device_ids: &[String],
+    org_id: Option<&str>,
+) -> ServerResult<BTreeMap<ObjectId, DeviceDoc>> {
+    let mut oids = Vec::with_capacity(device_ids.len());
+    for id in device_ids {
+        oids.push(parse_oid(id)?);
+    }
+
+    let mut filters = vec![doc! { "_id": { "$in": oids } }];
+    if let Some(org_id) = org_id {
+        filters.push(doc! { "allowed_scopes": org::org_scope(org_id) });
+    }
+
+    let mut out = BTreeMap::new();
+    let mut cursor = state.db.devices().find(doc! { "$or": filters }).await?;
+    while let Some(device) = cursor.try_next().await? {
+        if let Some(id) = device.id {
+            out.insert(id, device);
+        }
+    }
+    Ok(out)
+}
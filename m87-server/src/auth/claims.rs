@@ -15,6 +15,7 @@ use crate::{
     config::AppConfig,
     db::Mongo,
     models::{
+        access_grant::AccessGrantDoc,
         api_key::ApiKeyDoc,
         roles::{Role, RoleDoc},
         user::UserDoc,
@@ -50,7 +51,7 @@ impl FromRequestParts<AppState> for Claims {
 }
 
 impl Claims {
-    fn scopes_with_min_role(&self, required: Role) -> Result<Vec<String>, ServerError> {
+    pub fn scopes_with_min_role(&self, required: Role) -> Result<Vec<String>, ServerError> {
         let scopes: Vec<String> = self
             .roles
             .iter()
@@ -74,33 +75,7 @@ impl Claims {
         if is_jwt {
             // Handle JWT
             let user = UserDoc::get_or_create(&token, db, config).await?;
-
-            if !user.approved {
-                return Err(ServerError::unauthorized("user not approved"));
-            }
-
-            let reference_id = user.get_reference_id();
-            let mut roles = RoleDoc::list_for_reference(db, &reference_id).await?;
-
-            roles.push(RoleDoc {
-                id: None,
-                reference_id: reference_id.clone(),
-                scope: reference_id.clone(),
-                role: Role::Owner,
-                created_at: None,
-            });
-
-            let is_admin = match &user.email {
-                Some(email) => config.admin_emails.contains(email),
-                None => false,
-            };
-            Ok(Self {
-                roles,
-                is_admin,
-                user_name: user.name.clone().unwrap_or("unknown".to_string()),
-                user_email: user.email.clone().unwrap_or("unknown".to_string()),
-                user_id: user.id.clone(),
-            })
+            Self::for_user(&user, db, config).await
         } else {
             // check if token is config.admin_key
             match &config.admin_key {
@@ -131,6 +106,43 @@ impl Claims {
         }
     }
 
+    /// Claims of `user` with their current roles, also used to act on their
+    /// behalf outside a request.
+    pub async fn for_user(
+        user: &UserDoc,
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+    ) -> ServerResult<Self> {
+        if !user.approved {
+            return Err(ServerError::unauthorized("user not approved"));
+        }
+
+        let reference_id = user.get_reference_id();
+        let mut roles = RoleDoc::list_for_reference(db, &reference_id).await?;
+        // time-boxed grants count only until they expire
+        roles.extend(AccessGrantDoc::roles_for_reference(db, &reference_id).await?);
+
+        roles.push(RoleDoc {
+            id: None,
+            reference_id: reference_id.clone(),
+            scope: reference_id.clone(),
+            role: Role::Owner,
+            created_at: None,
+        });
+
+        let is_admin = match &user.email {
+            Some(email) => config.admin_emails.contains(email),
+            None => false,
+        };
+        Ok(Self {
+            roles,
+            is_admin,
+            user_name: user.name.clone().unwrap_or("unknown".to_string()),
+            user_email: user.email.clone().unwrap_or("unknown".to_string()),
+            user_id: user.id,
+        })
+    }
+
     pub async fn find_one_with_access<T>(
         &self,
         coll: &Collection<T>,
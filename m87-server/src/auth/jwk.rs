@@ -5,6 +5,7 @@ use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    auth::local,
     config::AppConfig,
     response::{ServerError, ServerResult},
 };
@@ -33,6 +34,9 @@ struct Jwk {
 }
 
 pub async fn validate_token(token: &str, config: &Arc<AppConfig>) -> ServerResult<DecodedClaims> {
+    if config.local_auth {
+        return local::validate_token(token, config);
+    }
     let issuer = config.oauth.issuer.to_string();
     // add trailing / if missing
     let issuer = match issuer.ends_with('/') {
@@ -80,6 +84,9 @@ pub async fn get_email_and_name_from_token(
     token: &str,
     config: &Arc<AppConfig>,
 ) -> ServerResult<(Option<String>, Option<String>)> {
+    if config.local_auth {
+        return local::email_and_name(token, config);
+    }
     let issuer = config.oauth.issuer.trim_end_matches('/').to_string();
     let userinfo_url = format!("{}/userinfo", issuer);
 
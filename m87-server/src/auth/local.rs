@@ -0,0 +1,139 @@
+//! Password login for servers without access to an OAuth issuer.
+//!
+//! With `AUTH_MODE=local` the server checks passwords of users created with
+//! `m87-server admin create-user` and hands out HS256 tokens it signs itself.
+//! The tokens carry the same claims the OAuth issuer would, so everything after
+//! token validation is unchanged.
+
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use rand::{Rng, distributions::Alphanumeric};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::jwk::DecodedClaims,
+    config::AppConfig,
+    models::user::UserDoc,
+    response::{ServerError, ServerResult},
+    util::fs::write_private,
+};
+
+/// Issuer and audience of locally signed tokens
+pub const LOCAL_ISSUER: &str = "m87-server";
+
+const SECRET_FILE: &str = "local_auth.secret";
+
+static SECRET: OnceLock<Vec<u8>> = OnceLock::new();
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LocalClaims {
+    sub: String,
+    email: String,
+    name: Option<String>,
+    exp: usize,
+    iat: usize,
+    iss: String,
+    aud: Vec<String>,
+}
+
+/// Signing secret: `LOCAL_AUTH_SECRET`, or one generated on first use and kept
+/// next to the TLS certificates so tokens survive restarts.
+fn secret(config: &AppConfig) -> ServerResult<&'static [u8]> {
+    if let Some(secret) = SECRET.get() {
+        return Ok(secret);
+    }
+    let secret = match &config.local_auth_secret {
+        Some(secret) => secret.clone(),
+        None => load_or_create_secret(config)?,
+    };
+    Ok(SECRET.get_or_init(|| secret.into_bytes()))
+}
+
+fn load_or_create_secret(config: &AppConfig) -> ServerResult<String> {
+    let path = PathBuf::from(&config.certificate_path).join(SECRET_FILE);
+    if let Ok(secret) = std::fs::read_to_string(&path) {
+        let secret = secret.trim().to_string();
+        if !secret.is_empty() {
+            return Ok(secret);
+        }
+    }
+    let secret: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(64)
+        .map(char::from)
+        .collect();
+    std::fs::create_dir_all(&config.certificate_path)?;
+    write_private(&path, &secret)?;
+    tracing::info!("Generated local auth secret at {}", path.display());
+    Ok(secret)
+}
+
+fn now_secs() -> usize {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as usize)
+        .unwrap_or(0)
+}
+
+/// Sign a token for `user`; returns it with its expiry as a unix timestamp.
+pub fn issue_token(user: &UserDoc, config: &Arc<AppConfig>) -> ServerResult<(String, u64)> {
+    let email = user
+        .email
+        .clone()
+        .ok_or_else(|| ServerError::internal_error("local user without email"))?;
+    let iat = now_secs();
+    let exp = iat + config.local_token_ttl_hours.max(1) as usize * 3600;
+    let claims = LocalClaims {
+        sub: user.sub.clone(),
+        email,
+        name: user.name.clone(),
+        exp,
+        iat,
+        iss: LOCAL_ISSUER.to_string(),
+        aud: vec![LOCAL_ISSUER.to_string()],
+    };
+    let token = encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(secret(config)?),
+    )
+    .map_err(|e| ServerError::internal_error(&format!("Failed to sign token: {}", e)))?;
+    Ok((token, exp as u64))
+}
+
+fn decode_local(token: &str, config: &AppConfig) -> ServerResult<LocalClaims> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_audience(&[LOCAL_ISSUER]);
+    validation.set_issuer(&[LOCAL_ISSUER]);
+    let decoded = decode::<LocalClaims>(
+        token,
+        &DecodingKey::from_secret(secret(config)?),
+        &validation,
+    )
+    .map_err(|e| ServerError::invalid_token(&format!("Token verification failed: {}", e)))?;
+    Ok(decoded.claims)
+}
+
+/// Validate a locally signed token.
+pub fn validate_token(token: &str, config: &AppConfig) -> ServerResult<DecodedClaims> {
+    let claims = decode_local(token, config)?;
+    Ok(DecodedClaims {
+        sub: claims.sub,
+        exp: claims.exp,
+        iat: claims.iat,
+        iss: claims.iss,
+        aud: claims.aud,
+    })
+}
+
+/// Email and name carried by a locally signed token; stands in for the
+/// issuer's userinfo endpoint.
+pub fn email_and_name(
+    token: &str,
+    config: &AppConfig,
+) -> ServerResult<(Option<String>, Option<String>)> {
+    let claims = decode_local(token, config)?;
+    Ok((Some(claims.email), claims.name))
+}
@@ -1,3 +1,4 @@
 pub mod access_control;
 pub mod claims;
 pub mod jwk;
+pub mod local;
@@ -1,3 +1,5 @@
+use m87_shared::enrollment::DEFAULT_INSTALL_SCRIPT_URL;
+use m87_shared::org::OrgLimits;
 use serde::Deserialize;
 
 use crate::response::ServerResult;
@@ -22,10 +24,30 @@ fn default_audit_retention_days() -> u32 {
     7
 }
 
+fn default_location_retention_days() -> u32 {
+    30
+}
+
 fn default_allow_cros_org_device_sharing() -> bool {
     false
 }
 
+fn default_osv_url() -> String {
+    "https://api.osv.dev".to_string()
+}
+
+fn default_vulnerability_scan_interval_hours() -> u32 {
+    24
+}
+
+fn default_install_script_url() -> String {
+    DEFAULT_INSTALL_SCRIPT_URL.to_string()
+}
+
+fn default_local_token_ttl_hours() -> u32 {
+    12
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AppConfig {
     pub mongo_uri: String,
@@ -45,8 +67,43 @@ pub struct AppConfig {
     pub report_retention_days: u32,
     #[serde(default = "default_audit_retention_days")]
     pub audit_retention_days: u32,
+    #[serde(default = "default_location_retention_days")]
+    pub location_retention_days: u32,
     #[serde(default = "default_allow_cros_org_device_sharing")]
     pub allow_cros_org_device_sharing: bool,
+    /// OSV API (or a mirror of it) that device package inventories are matched against
+    #[serde(default = "default_osv_url")]
+    pub osv_url: String,
+    /// Rescan every inventory this often for new advisories; 0 disables scanning
+    #[serde(default = "default_vulnerability_scan_interval_hours")]
+    pub vulnerability_scan_interval_hours: u32,
+    /// Authenticate users against passwords stored by this server instead of
+    /// the OAuth issuer, for deployments without internet access
+    #[serde(default)]
+    pub local_auth: bool,
+    /// HMAC secret for tokens issued in local auth mode; generated into the
+    /// certificate directory if unset
+    #[serde(default)]
+    pub local_auth_secret: Option<String>,
+    #[serde(default = "default_local_token_ttl_hours")]
+    pub local_token_ttl_hours: u32,
+    /// Quotas of organizations without an override set by an instance admin
+    #[serde(default)]
+    pub default_org_limits: OrgLimits,
+    /// Install script that install commands download, e.g. a mirror of it
+    #[serde(default = "default_install_script_url")]
+    pub install_script_url: String,
+    /// JSON file with the notices CLIs show their users, e.g. deprecations
+    #[serde(default)]
+    pub cli_notices_file: Option<String>,
+}
+
+/// A limit from the environment; unset or `0` is unlimited
+fn limit_from_env(name: &str) -> Option<u64> {
+    std::env::var(name)
+        .ok()
+        .map(|v| v.parse().unwrap())
+        .filter(|&v: &u64| v > 0)
 }
 
 impl AppConfig {
@@ -102,12 +159,42 @@ impl AppConfig {
             .unwrap_or_else(|_| "7".to_string())
             .parse()
             .unwrap();
+        let location_retention_days = std::env::var("LOCATION_RETENTION_DAYS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()
+            .unwrap();
 
         let allow_cros_org_device_sharing = std::env::var("ALLOW_CROSS_ORG_DEVICE_SHARING")
             .unwrap_or_else(|_| "false".to_string())
             .parse()
             .unwrap();
 
+        let osv_url = std::env::var("OSV_URL").unwrap_or_else(|_| default_osv_url());
+        let vulnerability_scan_interval_hours = std::env::var("VULNERABILITY_SCAN_INTERVAL_HOURS")
+            .unwrap_or_else(|_| "24".to_string())
+            .parse()
+            .unwrap();
+
+        let local_auth =
+            std::env::var("AUTH_MODE").unwrap_or_else(|_| "oauth".to_string()) == "local";
+        let local_auth_secret = std::env::var("LOCAL_AUTH_SECRET").ok();
+        let local_token_ttl_hours = std::env::var("LOCAL_TOKEN_TTL_HOURS")
+            .unwrap_or_else(|_| "12".to_string())
+            .parse()
+            .unwrap();
+
+        let default_org_limits = OrgLimits {
+            max_devices: limit_from_env("DEFAULT_ORG_MAX_DEVICES"),
+            max_revisions: limit_from_env("DEFAULT_ORG_MAX_REVISIONS"),
+            max_report_storage_bytes: limit_from_env("DEFAULT_ORG_MAX_REPORT_STORAGE_MB")
+                .map(|mb| mb * 1024 * 1024),
+            max_concurrent_tunnels: limit_from_env("DEFAULT_ORG_MAX_TUNNELS"),
+        };
+
+        let install_script_url =
+            std::env::var("INSTALL_SCRIPT_URL").unwrap_or_else(|_| default_install_script_url());
+        let cli_notices_file = std::env::var("CLI_NOTICES_FILE").ok();
+
         Ok(Self {
             mongo_uri,
             mongo_db,
@@ -123,7 +210,16 @@ impl AppConfig {
             admin_key,
             report_retention_days,
             audit_retention_days,
+            location_retention_days,
             allow_cros_org_device_sharing,
+            osv_url,
+            vulnerability_scan_interval_hours,
+            local_auth,
+            local_auth_secret,
+            local_token_ttl_hours,
+            default_org_limits,
+            install_script_url,
+            cli_notices_file,
         })
     }
 }
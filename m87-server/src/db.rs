@@ -1,19 +1,44 @@
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::db_stats::{CollectionStats, IndexStats};
+use tracing::warn;
 
 use crate::{
     models::{
+        access_grant::AccessGrantDoc,
         api_key::ApiKeyDoc,
+        approval::ApprovalRequestDoc,
         audit_logs::AuditLogDoc,
+        deploy_file::DeployFileDoc,
         deploy_spec::{DeployReportDoc, DeployRevisionDoc},
         device::DeviceDoc,
         device_auth_request::DeviceAuthRequestDoc,
+        device_history::DeviceHistoryDoc,
+        device_inventory::DeviceInventoryDoc,
+        device_location::DeviceLocationDoc,
+        enrollment_token::EnrollmentTokenDoc,
+        idempotency::IdempotencyKeyDoc,
+        incident::{IncidentDoc, IncidentWebhookDoc},
+        iot_bridge::IotBridgeDoc,
+        org_limits::OrgLimitsDoc,
+        org_redaction::OrgRedactionDoc,
+        org_usage::OrgUsageDoc,
+        patching::{PatchPolicyDoc, PatchRunDoc},
+        probe::ProbeResultDoc,
         roles::RoleDoc,
+        rollout::RolloutDoc,
         user::UserDoc,
+        webhook::{WebhookDeliveryDoc, WebhookDoc},
     },
     response::ServerResult,
 };
 use mongodb::{Client, Collection, IndexModel, options::ClientOptions};
 use mongodb::{bson::doc, options::IndexOptions};
+use mongodb::{
+    bson::{Bson, Document},
+    error::ErrorKind,
+};
 
 #[derive(Clone)]
 pub struct Mongo {
@@ -40,6 +65,22 @@ impl Mongo {
         self.col("devices")
     }
 
+    pub fn device_locations(&self) -> Collection<DeviceLocationDoc> {
+        self.col("device_locations")
+    }
+
+    pub fn device_history(&self) -> Collection<DeviceHistoryDoc> {
+        self.col("device_history")
+    }
+
+    pub fn device_inventories(&self) -> Collection<DeviceInventoryDoc> {
+        self.col("device_inventories")
+    }
+
+    pub fn idempotency_keys(&self) -> Collection<IdempotencyKeyDoc> {
+        self.col("idempotency_keys")
+    }
+
     pub fn users(&self) -> Collection<UserDoc> {
         self.col("users")
     }
@@ -48,6 +89,10 @@ impl Mongo {
         self.col("device_auth_requests")
     }
 
+    pub fn enrollment_tokens(&self) -> Collection<EnrollmentTokenDoc> {
+        self.col("enrollment_tokens")
+    }
+
     pub fn roles(&self) -> Collection<RoleDoc> {
         self.col("roles")
     }
@@ -60,6 +105,10 @@ impl Mongo {
         self.col("deploy_revisions")
     }
 
+    pub fn deploy_files(&self) -> Collection<DeployFileDoc> {
+        self.col("deploy_files")
+    }
+
     pub fn deploy_reports(&self) -> Collection<DeployReportDoc> {
         self.col("deploy_reports")
     }
@@ -68,212 +117,498 @@ impl Mongo {
         self.col("audit_logs")
     }
 
-    pub async fn ensure_indexes(&self) -> ServerResult<()> {
-        // Add indexes as needed later (expires_at TTL, etc.)
-        self.roles()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "reference_id": 1, "scope": 1  })
-                    .options(IndexOptions::builder().unique(true).build())
-                    .build(),
-            )
-            .await?;
-        self.roles()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "scope": 1  })
-                    .options(IndexOptions::builder().build())
-                    .build(),
-            )
-            .await?;
-        self.roles()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "reference_id": 1  })
-                    .options(IndexOptions::builder().build())
-                    .build(),
-            )
-            .await?;
+    pub fn webhooks(&self) -> Collection<WebhookDoc> {
+        self.col("webhooks")
+    }
 
-        self.device_auth_requests()
-            .create_index(IndexModel::builder().keys(doc! { "request_id": 1 }).build())
-            .await?;
+    pub fn patch_policies(&self) -> Collection<PatchPolicyDoc> {
+        self.col("patch_policies")
+    }
 
-        // TTL index for DeviceAuthRequestDoc (auto-delete after 24 hours)
-        self.device_auth_requests()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "created_at": 1 })
-                    .options(
-                        IndexOptions::builder()
-                            .expire_after(Some(Duration::from_secs(60 * 60 * 24 * 2))) // 2 days
-                            .build(),
-                    )
-                    .build(),
-            )
-            .await?;
+    pub fn patch_runs(&self) -> Collection<PatchRunDoc> {
+        self.col("patch_runs")
+    }
 
-        self.device_auth_requests()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "owner_scope": 1 })
-                    .build(),
-            )
-            .await?;
+    pub fn probe_results(&self) -> Collection<ProbeResultDoc> {
+        self.col("probe_results")
+    }
 
-        self.devices()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "owner_scope": 1 })
-                    .build(),
-            )
-            .await?;
-        self.devices()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "allowed_scopes": 1 })
-                    .build(),
-            )
-            .await?;
+    pub fn approval_requests(&self) -> Collection<ApprovalRequestDoc> {
+        self.col("approval_requests")
+    }
 
-        self.api_keys()
-            .create_index(IndexModel::builder().keys(doc! { "key_id": 1 }).build())
-            .await?;
+    pub fn incidents(&self) -> Collection<IncidentDoc> {
+        self.col("incidents")
+    }
 
-        // add index to users sub
-        self.users()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "sub": 1 })
-                    .options(
-                        IndexOptions::builder()
-                            .unique(true)
-                            .name(Some("sub_unique".to_string()))
-                            .build(),
-                    )
-                    .build(),
-            )
-            .await?;
+    pub fn incident_webhooks(&self) -> Collection<IncidentWebhookDoc> {
+        self.col("incident_webhooks")
+    }
 
-        self.deploy_revisions()
-            .create_index(IndexModel::builder().keys(doc! { "device_id": 1 }).build())
-            .await?;
+    pub fn iot_bridges(&self) -> Collection<IotBridgeDoc> {
+        self.col("iot_bridges")
+    }
 
-        // self.deploy_revisions()
-        //     .create_index(IndexModel::builder().keys(doc! { "group_id": 1 }).build())
-        //     .await?;
-
-        // add compund for device/group_id and active flag
-        self.deploy_revisions()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "device_id": 1, "active": 1 })
-                    .build(),
-            )
-            .await?;
-        self.deploy_revisions()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "group_id": 1, "active": 1 })
-                    .build(),
-            )
-            .await?;
-        self.deploy_revisions()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "device_id": 1, "revision.id": 1 })
-                    .build(),
-            )
-            .await?;
-        self.deploy_revisions()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "device_id": 1, "revision.id": 1, "kind.type": 1 })
-                    .build(),
-            )
+    pub fn rollouts(&self) -> Collection<RolloutDoc> {
+        self.col("rollouts")
+    }
+
+    pub fn access_grants(&self) -> Collection<AccessGrantDoc> {
+        self.col("access_grants")
+    }
+
+    pub fn org_limits(&self) -> Collection<OrgLimitsDoc> {
+        self.col("org_limits")
+    }
+
+    pub fn org_redaction(&self) -> Collection<OrgRedactionDoc> {
+        self.col("org_redaction")
+    }
+
+    pub fn org_usage(&self) -> Collection<OrgUsageDoc> {
+        self.col("org_usage")
+    }
+
+    pub fn webhook_deliveries(&self) -> Collection<WebhookDeliveryDoc> {
+        self.col("webhook_deliveries")
+    }
+
+    /// Create the indexes of [`expected_indexes`], then warn about the ones
+    /// collections still lack. An index failing to build (e.g. one with the
+    /// same name but other options exists, or the user may not create
+    /// indexes) does not stop the server; queries it serves just scan.
+    pub async fn ensure_indexes(&self) -> ServerResult<()> {
+        let mut failed = HashMap::new();
+        for (collection, model) in expected_indexes() {
+            let keys = format_keys(&model.keys);
+            match self.col::<Document>(collection).create_index(model).await {
+                Ok(_) => {}
+                Err(e) if matches!(*e.kind, ErrorKind::Command(_)) => {
+                    failed.insert((collection, keys), e.to_string());
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let missing = match self.missing_indexes().await {
+            Ok(missing) => missing,
+            Err(e) => {
+                warn!("Could not check the database indexes: {}", e);
+                return Ok(());
+            }
+        };
+        for (collection, keys) in missing {
+            match failed.remove(&(collection, keys.clone())) {
+                Some(reason) => warn!(
+                    "Collection {} is missing the index {{ {} }}: {}",
+                    collection, keys, reason
+                ),
+                None => warn!(
+                    "Collection {} is missing the index {{ {} }}",
+                    collection, keys
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Indexes of [`expected_indexes`] the collections lack, as collection and
+    /// key pattern. An index with the same keys counts, whatever its name.
+    pub async fn missing_indexes(&self) -> ServerResult<Vec<(&'static str, String)>> {
+        let mut existing: HashMap<&str, Vec<IndexModel>> = HashMap::new();
+        let mut missing = Vec::new();
+        for (collection, model) in expected_indexes() {
+            if !existing.contains_key(collection) {
+                existing.insert(collection, self.indexes_of(collection).await?);
+            }
+            if !existing[collection].iter().any(|i| satisfies(i, &model)) {
+                missing.push((collection, format_keys(&model.keys)));
+            }
+        }
+        Ok(missing)
+    }
+
+    /// Size, indexes and index use of every collection, for
+    /// `m87 admin db-stats`.
+    pub async fn collection_stats(&self) -> ServerResult<Vec<CollectionStats>> {
+        let expected = expected_indexes();
+        let mut names = self
+            .client
+            .database(&self.db_name)
+            .list_collection_names()
             .await?;
+        names.extend(expected.iter().map(|(c, _)| c.to_string()));
+        names.sort();
+        names.dedup();
 
-        self.deploy_reports()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! {
-                        "device_id": 1,
-                        "revision_id": 1,
-                        "kind.type": 1,
-                        "kind.data.run_id": 1,
-                        "kind.data.report_time": -1
+        let mut stats = Vec::with_capacity(names.len());
+        for name in names {
+            let col = self.col::<Document>(&name);
+            let indexes = self.indexes_of(&name).await?;
+            let ops = index_ops(&col).await;
+            let storage = storage_stats(&col).await;
+            let wanted: Vec<&IndexModel> = expected
+                .iter()
+                .filter(|(c, _)| *c == name)
+                .map(|(_, m)| m)
+                .collect();
+            stats.push(CollectionStats {
+                documents: col.estimated_document_count().await?,
+                data_bytes: storage.as_ref().and_then(|s| number(s.get("size"))),
+                index_bytes: storage
+                    .as_ref()
+                    .and_then(|s| number(s.get("totalIndexSize"))),
+                missing_indexes: wanted
+                    .iter()
+                    .filter(|m| !indexes.iter().any(|i| satisfies(i, m)))
+                    .map(|m| format_keys(&m.keys))
+                    .collect(),
+                indexes: indexes
+                    .iter()
+                    .map(|i| {
+                        let options = i.options.as_ref();
+                        let index_name = options.and_then(|o| o.name.clone()).unwrap_or_default();
+                        IndexStats {
+                            ops: ops.as_ref().and_then(|ops| ops.get(&index_name).copied()),
+                            expected: index_name == "_id_"
+                                || wanted.iter().any(|m| satisfies(i, m)),
+                            name: index_name,
+                            keys: format_keys(&i.keys),
+                            expire_after_secs: options
+                                .and_then(|o| o.expire_after)
+                                .map(|d| d.as_secs()),
+                        }
                     })
-                    .build(),
-            )
-            .await?;
+                    .collect(),
+                name,
+            });
+        }
+        Ok(stats)
+    }
 
-        // group + revision.id
-        // self.deploy_revisions()
-        //     .create_index(
-        //         IndexModel::builder()
-        //             .keys(doc! { "group_id": 1, "revision.id": 1 })
-        //             .build(),
-        //     )
-        //     .await?;
-
-        self.deploy_reports()
-            .create_index(IndexModel::builder().keys(doc! { "device_id": 1 }).build())
-            .await?;
+    /// Indexes of a collection; none if it does not exist yet.
+    async fn indexes_of(&self, collection: &str) -> ServerResult<Vec<IndexModel>> {
+        match self.col::<Document>(collection).list_indexes().await {
+            Ok(cursor) => Ok(cursor.try_collect().await?),
+            Err(e) if is_namespace_not_found(&e) => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
 
-        // self.deploy_reports()
-        //     .create_index(IndexModel::builder().keys(doc! { "group_id": 1 }).build())
-        //     .await?;
-
-        // ttl index
-        self.deploy_reports()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "expires_at": 1 })
-                    .options(
-                        IndexOptions::builder()
-                            .name(Some("ttl_deploy_reports_expires_at".to_string()))
-                            .expire_after(Some(Duration::from_secs(0)))
-                            .partial_filter_expression(doc! { "expires_at": { "$exists": true } })
-                            .build(),
-                    )
-                    .build(),
-            )
-            .await?;
-        // index on device id and revision id
-        self.deploy_reports()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "device_id": 1, "revision_id": 1 })
-                    .build(),
-            )
-            .await?;
-        self.deploy_reports()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "device_id": 1, "revision_id": 1, "kind.type": 1 })
-                    .build(),
-            )
-            .await?;
+/// Every index the server's queries rely on, by collection. Compound indexes
+/// also serve queries on their leading keys, so those get no index of their
+/// own.
+fn expected_indexes() -> Vec<(&'static str, IndexModel)> {
+    vec![
+        (
+            "roles",
+            IndexModel::builder()
+                .keys(doc! { "reference_id": 1, "scope": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+        ("roles", index(doc! { "scope": 1 })),
+        ("device_auth_requests", index(doc! { "request_id": 1 })),
+        // auto-delete auth requests after 2 days
+        (
+            "device_auth_requests",
+            IndexModel::builder()
+                .keys(doc! { "created_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .expire_after(Some(Duration::from_secs(60 * 60 * 24 * 2)))
+                        .build(),
+                )
+                .build(),
+        ),
+        ("device_auth_requests", index(doc! { "owner_scope": 1 })),
+        ("devices", index(doc! { "owner_scope": 1 })),
+        ("devices", index(doc! { "allowed_scopes": 1 })),
+        (
+            "device_locations",
+            index(doc! { "device_id": 1, "recorded_at": -1 }),
+        ),
+        expiring("device_locations", "expires_at", true),
+        (
+            "device_history",
+            index(doc! { "device_id": 1, "timestamp": -1 }),
+        ),
+        expiring("device_history", "expires_at", true),
+        (
+            "device_inventories",
+            IndexModel::builder()
+                .keys(doc! { "device_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+        ("device_inventories", index(doc! { "scanned_at": 1 })),
+        (
+            "idempotency_keys",
+            IndexModel::builder()
+                .keys(doc! { "device_id": 1, "key": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .name(Some("device_key_unique".to_string()))
+                        .build(),
+                )
+                .build(),
+        ),
+        expiring("idempotency_keys", "expires_at", false),
+        ("api_keys", index(doc! { "key_id": 1 })),
+        (
+            "users",
+            IndexModel::builder()
+                .keys(doc! { "sub": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .name(Some("sub_unique".to_string()))
+                        .build(),
+                )
+                .build(),
+        ),
+        // active revision of a device or group
+        (
+            "deploy_revisions",
+            index(doc! { "device_id": 1, "active": 1 }),
+        ),
+        (
+            "deploy_revisions",
+            index(doc! { "group_id": 1, "active": 1 }),
+        ),
+        (
+            "deploy_revisions",
+            index(doc! { "device_id": 1, "revision.id": 1 }),
+        ),
+        // revision list, newest first
+        (
+            "deploy_revisions",
+            index(doc! { "device_id": 1, "index": -1 }),
+        ),
+        ("deploy_revisions", index(doc! { "revision.id": 1 })),
+        // unreferenced files past their grace period
+        ("deploy_files", index(doc! { "refs": 1, "touched_at": 1 })),
+        // latest run states and reports by kind
+        (
+            "deploy_reports",
+            index(doc! {
+                "device_id": 1,
+                "revision_id": 1,
+                "kind.type": 1,
+                "kind.data.run_id": 1,
+                "kind.data.report_time": -1
+            }),
+        ),
+        // status snapshots, in the order the device sent the reports
+        (
+            "deploy_reports",
+            index(doc! { "device_id": 1, "revision_id": 1, "created_at": 1 }),
+        ),
+        // device events timeline
+        (
+            "deploy_reports",
+            index(doc! { "device_id": 1, "created_at": -1 }),
+        ),
+        expiring("deploy_reports", "expires_at", true),
+        (
+            "audit_logs",
+            index(doc! { "device_id": 1, "timestamp": -1 }),
+        ),
+        expiring("audit_logs", "expires_at", true),
+        ("webhooks", index(doc! { "owner_scope": 1 })),
+        (
+            "webhook_deliveries",
+            index(doc! { "webhook_id": 1, "received_at": -1 }),
+        ),
+        expiring("webhook_deliveries", "expires_at", true),
+        // each verified payload runs its webhook's action once
+        (
+            "webhook_deliveries",
+            IndexModel::builder()
+                .keys(doc! { "webhook_id": 1, "payload_hash": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .name(Some("webhook_payload_unique".to_string()))
+                        .partial_filter_expression(doc! { "payload_hash": { "$exists": true } })
+                        .build(),
+                )
+                .build(),
+        ),
+        ("patch_policies", index(doc! { "owner_scope": 1 })),
+        (
+            "patch_runs",
+            index(doc! { "device_id": 1, "received_at": -1 }),
+        ),
+        expiring("patch_runs", "expires_at", true),
+        (
+            "probe_results",
+            index(doc! { "device_id": 1, "received_at": -1 }),
+        ),
+        expiring("probe_results", "expires_at", true),
+        (
+            "approval_requests",
+            index(doc! { "device_id": 1, "requested_by": 1, "operation": 1 }),
+        ),
+        ("approval_requests", index(doc! { "created_at": -1 })),
+        expiring("approval_requests", "purge_at", false),
+        ("incidents", index(doc! { "device_id": 1, "state": 1 })),
+        ("incidents", index(doc! { "device_id": 1, "opened_at": -1 })),
+        ("incidents", index(doc! { "opened_at": -1 })),
+        (
+            "incidents",
+            IndexModel::builder()
+                .keys(doc! { "purge_at": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .name(Some("ttl_incidents_purge_at".to_string()))
+                        .expire_after(Some(Duration::from_secs(0)))
+                        .partial_filter_expression(doc! { "purge_at": { "$type": "date" } })
+                        .build(),
+                )
+                .build(),
+        ),
+        (
+            "incident_webhooks",
+            IndexModel::builder()
+                .keys(doc! { "org_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+        (
+            "iot_bridges",
+            IndexModel::builder()
+                .keys(doc! { "org_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+        (
+            "enrollment_tokens",
+            IndexModel::builder()
+                .keys(doc! { "token_hash": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+        expiring("enrollment_tokens", "expires_at", false),
+        ("rollouts", index(doc! { "state": 1 })),
+        ("rollouts", index(doc! { "created_at": -1 })),
+        (
+            "access_grants",
+            index(doc! { "reference_id": 1, "expires_at": 1 }),
+        ),
+        ("access_grants", index(doc! { "ended": 1, "expires_at": 1 })),
+        expiring("access_grants", "purge_at", false),
+        (
+            "org_limits",
+            IndexModel::builder()
+                .keys(doc! { "org_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+        (
+            "org_redaction",
+            IndexModel::builder()
+                .keys(doc! { "org_id": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+        (
+            "org_usage",
+            IndexModel::builder()
+                .keys(doc! { "org_id": 1, "month": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+        ),
+    ]
+}
 
-        self.audit_logs()
-            .create_index(
-                IndexModel::builder()
-                    .keys(doc! { "expires_at": 1 })
-                    .options(
-                        IndexOptions::builder()
-                            .name(Some("ttl_audit_logs_expires_at".to_string()))
-                            .expire_after(Some(Duration::from_secs(0)))
-                            .partial_filter_expression(doc! { "expires_at": { "$exists": true } })
-                            .build(),
-                    )
-                    .build(),
-            )
-            .await?;
+fn index(keys: Document) -> IndexModel {
+    IndexModel::builder().keys(keys).build()
+}
 
-        self.audit_logs()
-            .create_index(IndexModel::builder().keys(doc! { "device_id": 1 }).build())
-            .await?;
+/// TTL index deleting documents once their `field` date has passed, named
+/// `ttl_<collection>_<field>`. Partial ones skip documents without the field.
+fn expiring(collection: &'static str, field: &str, partial: bool) -> (&'static str, IndexModel) {
+    let options = IndexOptions::builder()
+        .name(Some(format!("ttl_{}_{}", collection, field)))
+        .expire_after(Some(Duration::from_secs(0)))
+        .partial_filter_expression(partial.then(|| doc! { field: { "$exists": true } }))
+        .build();
+    (
+        collection,
+        IndexModel::builder()
+            .keys(doc! { field: 1 })
+            .options(options)
+            .build(),
+    )
+}
 
-        Ok(())
+/// Whether `existing` serves the queries `expected` is for: the same keys in
+/// the same order and, for TTL indexes, an expiry.
+fn satisfies(existing: &IndexModel, expected: &IndexModel) -> bool {
+    let ttl = |m: &IndexModel| m.options.as_ref().is_some_and(|o| o.expire_after.is_some());
+    format_keys(&existing.keys) == format_keys(&expected.keys) && (ttl(existing) || !ttl(expected))
+}
+
+/// Key pattern as `a: 1, b: -1`, the same whether the directions were
+/// stored as integers or doubles.
+fn format_keys(keys: &Document) -> String {
+    keys.iter()
+        .map(|(k, v)| match v {
+            Bson::Int32(n) => format!("{}: {}", k, n),
+            Bson::Int64(n) => format!("{}: {}", k, n),
+            Bson::Double(f) => format!("{}: {}", k, *f as i64),
+            Bson::String(s) => format!("{}: {}", k, s),
+            v => format!("{}: {}", k, v),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn number(value: Option<&Bson>) -> Option<u64> {
+    match value? {
+        Bson::Int32(n) => Some((*n).max(0) as u64),
+        Bson::Int64(n) => Some((*n).max(0) as u64),
+        Bson::Double(f) => Some(f.max(0.0) as u64),
+        _ => None,
     }
 }
+
+/// Uses per index name since the database started; None where the user may
+/// not run `$indexStats`.
+async fn index_ops(col: &Collection<Document>) -> Option<HashMap<String, u64>> {
+    let cursor = col.aggregate([doc! { "$indexStats": {} }]).await.ok()?;
+    let entries: Vec<Document> = cursor.try_collect().await.ok()?;
+    Some(
+        entries
+            .iter()
+            .filter_map(|e| {
+                let name = e.get_str("name").ok()?;
+                let ops = number(e.get_document("accesses").ok()?.get("ops"))?;
+                Some((name.to_string(), ops))
+            })
+            .collect(),
+    )
+}
+
+/// `storageStats` of the collection; None where the user may not run
+/// `$collStats` or the collection does not exist.
+async fn storage_stats(col: &Collection<Document>) -> Option<Document> {
+    let cursor = col
+        .aggregate([doc! { "$collStats": { "storageStats": {} } }])
+        .await
+        .ok()?;
+    let entries: Vec<Document> = cursor.try_collect().await.ok()?;
+    entries
+        .into_iter()
+        .next()?
+        .get_document("storageStats")
+        .ok()
+        .cloned()
+}
+
+fn is_namespace_not_found(e: &mongodb::error::Error) -> bool {
+    matches!(&*e.kind, ErrorKind::Command(c) if c.code == 26)
+}
@@ -1,3 +1,4 @@
+mod admin;
 mod api;
 mod auth;
 mod config;
@@ -15,6 +16,16 @@ use crate::{relay::relay_state::RelayState, response::ServerResult};
 
 #[tokio::main]
 async fn main() -> ServerResult<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("admin") {
+        let config = config::AppConfig::from_env()?;
+        if let Err(e) = admin::run(&args[1..], &config).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     println!("Booting Nexus...");
     init_tracing();
     info!("Starting nexus");
@@ -30,6 +41,9 @@ async fn main() -> ServerResult<()> {
     info!("Connecting to database");
     let db = Arc::new(db::Mongo::connect(&mongo_uri, &db_name).await?);
     db.ensure_indexes().await?;
+    if config.local_auth {
+        info!("Local auth mode: users log in with passwords stored by this server");
+    }
     let config = Arc::new(config);
     // Shared relay state
     let relay_state = Arc::new(RelayState::new());
@@ -0,0 +1,379 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::{
+    access::{AccessGrant, CreateAccessGrantBody},
+    roles::Role,
+};
+use mongodb::{
+    bson::{DateTime, doc, oid::ObjectId},
+    options::{FindOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::access_control::AccessControlled,
+    db::Mongo,
+    models::{device::DeviceDoc, roles::RoleDoc, user::UserDoc},
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+/// Ended grants are kept this long, for listings.
+const KEEP_DAYS: u64 = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessGrantDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// `user:<email>`, as in role bindings
+    pub reference_id: String,
+    pub email: String,
+    pub device_id: ObjectId,
+    pub device_name: String,
+    pub role: Role,
+    pub granted_by: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+    pub created_at: DateTime,
+    pub expires_at: DateTime,
+    /// Set once the end of the grant was audited, by expiry or revocation
+    #[serde(default)]
+    pub ended: bool,
+    #[serde(default)]
+    pub revoked_by: Option<String>,
+    /// Removed by a TTL index
+    pub purge_at: DateTime,
+    // copied from the device, so listings follow device access
+    pub owner_scope: String,
+    pub allowed_scopes: Vec<String>,
+}
+
+impl AccessControlled for AccessGrantDoc {
+    fn owner_scope_field() -> &'static str {
+        "owner_scope"
+    }
+    fn allowed_scopes_field() -> Option<&'static str> {
+        Some("allowed_scopes")
+    }
+    fn owner_scope(&self) -> &str {
+        &self.owner_scope
+    }
+    fn allowed_scopes(&self) -> Option<Vec<String>> {
+        Some(self.allowed_scopes.clone())
+    }
+}
+
+fn purge_after(expires_at: DateTime) -> DateTime {
+    DateTime::from_system_time(expires_at.to_system_time() + Duration::from_hours(24 * KEEP_DAYS))
+}
+
+/// Role bindings of the grants still active at `now`.
+fn active_roles(grants: Vec<AccessGrantDoc>, now: DateTime) -> Vec<RoleDoc> {
+    grants
+        .iter()
+        .filter(|g| g.is_active_at(now))
+        .map(AccessGrantDoc::to_role)
+        .collect()
+}
+
+impl AccessGrantDoc {
+    pub async fn create(
+        db: &Arc<Mongo>,
+        device: &DeviceDoc,
+        body: &CreateAccessGrantBody,
+        granted_by: &str,
+    ) -> ServerResult<Self> {
+        let device_id = device
+            .id
+            .ok_or_else(|| ServerError::internal_error("device without id"))?;
+        let now = DateTime::now();
+        let expires_at =
+            DateTime::from_system_time(now.to_system_time() + Duration::from_secs(body.ttl_secs));
+        let mut doc = Self {
+            id: None,
+            reference_id: UserDoc::create_reference_id(&body.email),
+            email: body.email.clone(),
+            device_id,
+            device_name: device.name.clone(),
+            role: body.role.clone(),
+            granted_by: granted_by.to_string(),
+            reason: body.reason.clone(),
+            created_at: now,
+            expires_at,
+            ended: false,
+            revoked_by: None,
+            purge_at: purge_after(expires_at),
+            owner_scope: device.owner_scope.clone(),
+            allowed_scopes: device.allowed_scopes.clone(),
+        };
+        let res = db
+            .access_grants()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert access grant"))?;
+        doc.id = res.inserted_id.as_object_id();
+        Ok(doc)
+    }
+
+    /// Role bindings for the unexpired grants of `reference_id`, merged into
+    /// the caller's claims.
+    pub async fn roles_for_reference(
+        db: &Arc<Mongo>,
+        reference_id: &str,
+    ) -> ServerResult<Vec<RoleDoc>> {
+        let now = DateTime::now();
+        let grants: Vec<Self> = db
+            .access_grants()
+            .find(doc! {
+                "reference_id": reference_id,
+                "ended": false,
+                "expires_at": { "$gt": now },
+            })
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to decode access grant"))?;
+        Ok(active_roles(grants, now))
+    }
+
+    /// Newest first; ended grants only if `include_ended`.
+    pub async fn list(
+        db: &Arc<Mongo>,
+        scopes: &Vec<String>,
+        include_ended: bool,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Self>> {
+        let mut filter = Self::access_filter(scopes);
+        if !include_ended {
+            filter.insert("ended", false);
+            filter.insert("expires_at", doc! { "$gt": DateTime::now() });
+        }
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "created_at": -1 })
+            .build();
+        db.access_grants()
+            .find(filter)
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    /// End an active grant now. `None` if it already ended.
+    pub async fn revoke(
+        db: &Arc<Mongo>,
+        id: ObjectId,
+        revoked_by: &str,
+    ) -> ServerResult<Option<Self>> {
+        let now = DateTime::now();
+        let revoked = db
+            .access_grants()
+            .find_one_and_update(
+                doc! { "_id": id, "ended": false, "expires_at": { "$gt": now } },
+                doc! { "$set": {
+                    "ended": true,
+                    "revoked_by": revoked_by,
+                    "expires_at": now,
+                    "purge_at": purge_after(now),
+                } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(revoked)
+    }
+
+    /// Claim one grant that expired without its end being audited yet.
+    /// Atomic, so with several servers each expiry is handled once.
+    pub async fn take_expired(db: &Arc<Mongo>) -> ServerResult<Option<Self>> {
+        let expired = db
+            .access_grants()
+            .find_one_and_update(
+                doc! { "ended": false, "expires_at": { "$lte": DateTime::now() } },
+                doc! { "$set": { "ended": true } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(expired)
+    }
+
+    pub async fn delete_for_device(db: &Arc<Mongo>, device_id: ObjectId) -> ServerResult<()> {
+        db.access_grants()
+            .delete_many(doc! { "device_id": device_id })
+            .await?;
+        Ok(())
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.is_active_at(DateTime::now())
+    }
+
+    fn is_active_at(&self, now: DateTime) -> bool {
+        !self.ended && self.expires_at > now
+    }
+
+    /// The binding the grant adds to the grantee's claims: its role on the
+    /// grant's device only.
+    fn to_role(&self) -> RoleDoc {
+        RoleDoc {
+            id: None,
+            reference_id: self.reference_id.clone(),
+            scope: DeviceDoc::scope_for_device(&self.device_id),
+            role: self.role.clone(),
+            created_at: Some(self.created_at),
+        }
+    }
+
+    pub fn to_public(&self) -> AccessGrant {
+        AccessGrant {
+            id: self.id.map(|id| id.to_hex()).unwrap_or_default(),
+            email: self.email.clone(),
+            device_id: self.device_id.to_hex(),
+            device_name: self.device_name.clone(),
+            role: self.role.clone(),
+            granted_by: self.granted_by.clone(),
+            reason: self.reason.clone(),
+            created_at: self.created_at.try_to_rfc3339_string().unwrap_or_default(),
+            expires_at: self.expires_at.try_to_rfc3339_string().unwrap_or_default(),
+            active: self.is_active(),
+            revoked_by: self.revoked_by.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::claims::Claims;
+
+    use super::*;
+
+    /// A device as far as access checks go
+    struct Device {
+        id: ObjectId,
+    }
+
+    impl AccessControlled for Device {
+        fn owner_scope_field() -> &'static str {
+            "owner_scope"
+        }
+        fn allowed_scopes_field() -> Option<&'static str> {
+            Some("allowed_scopes")
+        }
+        fn owner_scope(&self) -> &str {
+            "org:owner"
+        }
+        fn allowed_scopes(&self) -> Option<Vec<String>> {
+            Some(vec![DeviceDoc::scope_for_device(&self.id)])
+        }
+    }
+
+    fn at(now: DateTime, secs: i64) -> DateTime {
+        DateTime::from_millis(now.timestamp_millis() + secs * 1000)
+    }
+
+    fn grant(
+        device_id: ObjectId,
+        now: DateTime,
+        expires_in_secs: i64,
+        ended: bool,
+    ) -> AccessGrantDoc {
+        let email = "contractor@example.com";
+        let expires_at = at(now, expires_in_secs);
+        AccessGrantDoc {
+            id: Some(ObjectId::new()),
+            reference_id: UserDoc::create_reference_id(email),
+            email: email.to_string(),
+            device_id,
+            device_name: "edge-1".to_string(),
+            role: Role::Editor,
+            granted_by: "admin@example.com".to_string(),
+            reason: None,
+            created_at: at(now, -3600),
+            expires_at,
+            ended,
+            revoked_by: None,
+            purge_at: purge_after(expires_at),
+            owner_scope: "org:owner".to_string(),
+            allowed_scopes: vec![],
+        }
+    }
+
+    fn claims(roles: Vec<RoleDoc>) -> Claims {
+        Claims {
+            roles,
+            is_admin: false,
+            user_name: "contractor".to_string(),
+            user_email: "contractor@example.com".to_string(),
+            user_id: None,
+        }
+    }
+
+    #[test]
+    fn test_active_grant_gives_its_role_on_its_device() {
+        let now = DateTime::now();
+        let device = Device {
+            id: ObjectId::new(),
+        };
+        let roles = active_roles(vec![grant(device.id, now, 60, false)], now);
+        assert_eq!(roles.len(), 1);
+        assert_eq!(claims(roles).get_role(&device).unwrap(), Role::Editor);
+    }
+
+    #[test]
+    fn test_expired_grant_gives_no_access() {
+        let now = DateTime::now();
+        let device = Device {
+            id: ObjectId::new(),
+        };
+        let expired = grant(device.id, now, -1, false);
+        assert!(!expired.is_active_at(now));
+        // expiring right now counts as expired
+        let expiring = grant(device.id, now, 0, false);
+        assert!(!expiring.is_active_at(now));
+
+        let roles = active_roles(vec![expired, expiring], now);
+        assert!(roles.is_empty());
+        assert!(claims(roles).get_role(&device).is_err());
+
+        // the same grant stops counting once its time passes
+        let active = grant(device.id, now, 60, false);
+        assert!(active.is_active_at(now));
+        assert!(!active.is_active_at(at(now, 61)));
+    }
+
+    #[test]
+    fn test_revoked_grant_gives_no_access() {
+        let now = DateTime::now();
+        let device = Device {
+            id: ObjectId::new(),
+        };
+        // revoked before its expiry
+        let mut revoked = grant(device.id, now, 3600, true);
+        revoked.revoked_by = Some("admin@example.com".to_string());
+        assert!(!revoked.is_active_at(now));
+
+        let roles = active_roles(vec![revoked], now);
+        assert!(roles.is_empty());
+        assert!(claims(roles).get_role(&device).is_err());
+    }
+
+    #[test]
+    fn test_grant_gives_no_access_to_other_devices() {
+        let now = DateTime::now();
+        let granted = Device {
+            id: ObjectId::new(),
+        };
+        let other = Device {
+            id: ObjectId::new(),
+        };
+        let claims = claims(active_roles(vec![grant(granted.id, now, 60, false)], now));
+
+        assert!(claims.get_role(&granted).is_ok());
+        assert!(claims.get_role(&other).is_err());
+        assert!(!claims.has_scope_and_role(&DeviceDoc::scope_for_device(&other.id), Role::Viewer));
+        assert!(!claims.has_scope_and_role("org:owner", Role::Viewer));
+    }
+}
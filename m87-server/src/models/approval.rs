@@ -0,0 +1,274 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::approval::{
+    APPROVAL_VALIDITY_MINUTES, ApprovalRequest, ApprovalStatus, PENDING_TTL_MINUTES,
+};
+use mongodb::{
+    bson::{DateTime, Document, doc, oid::ObjectId},
+    options::{FindOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::access_control::AccessControlled,
+    db::Mongo,
+    models::device::DeviceDoc,
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+/// Decided requests are kept this long after they expire, for listings.
+const KEEP_DAYS: u64 = 7;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApprovalRequestDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub device_name: String,
+    pub operation: String,
+    #[serde(default)]
+    pub details: String,
+    pub requested_by: String,
+    pub status: ApprovalStatus,
+    #[serde(default)]
+    pub decided_by: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub decided_at: Option<DateTime>,
+    #[serde(default)]
+    pub used_at: Option<DateTime>,
+    pub created_at: DateTime,
+    /// End of the wait for a decision, or of the approval's validity
+    pub expires_at: DateTime,
+    /// Removed by a TTL index
+    pub purge_at: DateTime,
+    // copied from the device, so listings follow device access
+    pub owner_scope: String,
+    pub allowed_scopes: Vec<String>,
+}
+
+impl AccessControlled for ApprovalRequestDoc {
+    fn owner_scope_field() -> &'static str {
+        "owner_scope"
+    }
+    fn allowed_scopes_field() -> Option<&'static str> {
+        Some("allowed_scopes")
+    }
+    fn owner_scope(&self) -> &str {
+        &self.owner_scope
+    }
+    fn allowed_scopes(&self) -> Option<Vec<String>> {
+        Some(self.allowed_scopes.clone())
+    }
+}
+
+/// Outcome of [`ApprovalRequestDoc::find_or_file`].
+pub enum ApprovalCheck {
+    /// An approval is ready; the operation may run once it is used up with
+    /// [`ApprovalRequestDoc::take`]
+    Approved(ApprovalRequestDoc),
+    /// A new request now waits for a decision
+    Filed(ApprovalRequestDoc),
+    /// An earlier request still waits for a decision
+    Pending(ApprovalRequestDoc),
+}
+
+fn minutes_from_now(minutes: u64) -> DateTime {
+    DateTime::from_system_time(DateTime::now().to_system_time() + Duration::from_secs(minutes * 60))
+}
+
+fn purge_after(expires_at: DateTime) -> DateTime {
+    DateTime::from_system_time(expires_at.to_system_time() + Duration::from_hours(24 * KEEP_DAYS))
+}
+
+impl ApprovalRequestDoc {
+    /// Find an approval of `requested_by` for this operation, or file a
+    /// request for one unless the same request is already pending. Nothing
+    /// is used up, so callers can check several devices before acting.
+    pub async fn find_or_file(
+        db: &Arc<Mongo>,
+        device: &DeviceDoc,
+        requested_by: &str,
+        operation: &str,
+        details: &str,
+    ) -> ServerResult<ApprovalCheck> {
+        let device_id = device
+            .id
+            .ok_or_else(|| ServerError::internal_error("device without id"))?;
+        let now = DateTime::now();
+        let filter = |status: ApprovalStatus| {
+            doc! {
+                "device_id": device_id,
+                "requested_by": requested_by,
+                "operation": operation,
+                "details": details,
+                "status": status.to_string(),
+                "expires_at": { "$gt": now },
+            }
+        };
+
+        if let Some(approved) = db
+            .approval_requests()
+            .find_one(filter(ApprovalStatus::Approved))
+            .await?
+        {
+            return Ok(ApprovalCheck::Approved(approved));
+        }
+
+        if let Some(pending) = db
+            .approval_requests()
+            .find_one(filter(ApprovalStatus::Pending))
+            .await?
+        {
+            return Ok(ApprovalCheck::Pending(pending));
+        }
+
+        let expires_at = minutes_from_now(PENDING_TTL_MINUTES);
+        let mut doc = Self {
+            id: None,
+            device_id,
+            device_name: device.name.clone(),
+            operation: operation.to_string(),
+            details: details.to_string(),
+            requested_by: requested_by.to_string(),
+            status: ApprovalStatus::Pending,
+            decided_by: None,
+            comment: None,
+            decided_at: None,
+            used_at: None,
+            created_at: now,
+            expires_at,
+            purge_at: purge_after(expires_at),
+            owner_scope: device.owner_scope.clone(),
+            allowed_scopes: device.allowed_scopes.clone(),
+        };
+        let res = db
+            .approval_requests()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert approval request"))?;
+        doc.id = res.inserted_id.as_object_id();
+        Ok(ApprovalCheck::Filed(doc))
+    }
+
+    /// Use up an approval found by [`Self::find_or_file`]. `None` if it was
+    /// used or expired meanwhile.
+    pub async fn take(db: &Arc<Mongo>, id: ObjectId) -> ServerResult<Option<Self>> {
+        let now = DateTime::now();
+        let used = db
+            .approval_requests()
+            .find_one_and_update(
+                doc! {
+                    "_id": id,
+                    "status": ApprovalStatus::Approved.to_string(),
+                    "expires_at": { "$gt": now },
+                },
+                doc! { "$set": {
+                    "status": ApprovalStatus::Used.to_string(),
+                    "used_at": now,
+                } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?;
+        Ok(used)
+    }
+
+    /// Approve or reject a pending request. Fails if it was decided
+    /// meanwhile or expired.
+    pub async fn decide(
+        db: &Arc<Mongo>,
+        id: ObjectId,
+        approve: bool,
+        decided_by: &str,
+        comment: Option<String>,
+    ) -> ServerResult<Self> {
+        let now = DateTime::now();
+        let (status, expires_at) = if approve {
+            (
+                ApprovalStatus::Approved,
+                minutes_from_now(APPROVAL_VALIDITY_MINUTES),
+            )
+        } else {
+            (ApprovalStatus::Rejected, now)
+        };
+        db.approval_requests()
+            .find_one_and_update(
+                doc! {
+                    "_id": id,
+                    "status": ApprovalStatus::Pending.to_string(),
+                    "expires_at": { "$gt": now },
+                },
+                doc! { "$set": {
+                    "status": status.to_string(),
+                    "decided_by": decided_by,
+                    "comment": comment,
+                    "decided_at": now,
+                    "expires_at": expires_at,
+                    "purge_at": purge_after(expires_at),
+                } },
+            )
+            .return_document(ReturnDocument::After)
+            .await?
+            .ok_or_else(|| ServerError::bad_request("the request is no longer pending"))
+    }
+
+    /// Newest first, limited to `filter` (e.g. a status).
+    pub async fn list(
+        db: &Arc<Mongo>,
+        scopes: &Vec<String>,
+        mut filter: Document,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Self>> {
+        filter.extend(Self::access_filter(scopes));
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "created_at": -1 })
+            .build();
+        db.approval_requests()
+            .find(filter)
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    pub async fn delete_for_device(db: &Arc<Mongo>, device_id: ObjectId) -> ServerResult<()> {
+        db.approval_requests()
+            .delete_many(doc! { "device_id": device_id })
+            .await?;
+        Ok(())
+    }
+
+    /// Pending and approved requests past their time are reported as expired.
+    pub fn effective_status(&self) -> ApprovalStatus {
+        match self.status {
+            ApprovalStatus::Pending | ApprovalStatus::Approved
+                if self.expires_at <= DateTime::now() =>
+            {
+                ApprovalStatus::Expired
+            }
+            status => status,
+        }
+    }
+
+    pub fn to_public(&self) -> ApprovalRequest {
+        ApprovalRequest {
+            id: self.id.map(|id| id.to_hex()).unwrap_or_default(),
+            device_id: self.device_id.to_hex(),
+            device_name: self.device_name.clone(),
+            operation: self.operation.clone(),
+            details: self.details.clone(),
+            requested_by: self.requested_by.clone(),
+            status: self.effective_status(),
+            decided_by: self.decided_by.clone(),
+            comment: self.comment.clone(),
+            created_at: self.created_at.try_to_rfc3339_string().unwrap_or_default(),
+            expires_at: self.expires_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+}
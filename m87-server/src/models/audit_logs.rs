@@ -2,6 +2,7 @@ use std::{sync::Arc, time::Duration};
 
 use futures::TryStreamExt;
 use m87_shared::device::AuditLog;
+use m87_shared::incident::IncidentEntryKind;
 use mongodb::{
     bson::{DateTime, doc, oid::ObjectId},
     options::FindOptions,
@@ -12,6 +13,7 @@ use crate::{
     auth::claims::Claims,
     config::AppConfig,
     db::Mongo,
+    models::incident::{IncidentDoc, IncidentEntryDoc},
     response::{ServerError, ServerResult},
     util::pagination::RequestPagination,
 };
@@ -60,6 +62,19 @@ impl AuditLogDoc {
             .insert_one(&doc)
             .await
             .map_err(|_| ServerError::internal_error("Failed to insert API key"))?;
+
+        // what people do on a device during an incident becomes part of it
+        if claims.user_id.is_some()
+            && let Some(device_id) = device_id
+        {
+            let actor = if claims.user_email.is_empty() {
+                claims.user_name.clone()
+            } else {
+                claims.user_email.clone()
+            };
+            let entry = IncidentEntryDoc::now(IncidentEntryKind::Action, action, Some(actor));
+            IncidentDoc::attach(db, device_id, entry).await?;
+        }
         Ok(())
     }
 
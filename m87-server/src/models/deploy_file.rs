@@ -0,0 +1,253 @@
+//! Files of run specs, stored once per content and shared by every revision
+//! whose runs contain them, so iterating on a deployment does not store the
+//! same files again with each revision.
+//!
+//! Stored revisions keep a [`content_hash`] per path in `RunSpec::file_refs`
+//! and list the hashes in `DeployRevisionDoc::file_hashes`; `refs` counts
+//! the revisions listing a file. Files are stored before the revision that
+//! refers to them, so unreferenced ones are only deleted after [`GRACE`].
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::TryStreamExt;
+use m87_shared::deploy_spec::{DeploymentRevision, RunSpec, content_hash};
+use mongodb::bson::{DateTime, doc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::Mongo,
+    response::{ServerError, ServerResult},
+};
+
+/// Unreferenced files younger than this may belong to a revision being stored
+const GRACE: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployFileDoc {
+    /// [`content_hash`] of `content`
+    #[serde(rename = "_id")]
+    pub hash: String,
+    pub content: String,
+    pub size: i64,
+    /// Revisions referring to the file
+    pub refs: i64,
+    /// When a revision last stored the file
+    pub touched_at: DateTime,
+}
+
+impl DeployFileDoc {
+    /// Store the files of every run of `revision` and replace them by refs.
+    pub async fn detach(db: &Arc<Mongo>, revision: &mut DeploymentRevision) -> ServerResult<()> {
+        for run in &mut revision.jobs {
+            Self::detach_run(db, run).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn detach_run(db: &Arc<Mongo>, run: &mut RunSpec) -> ServerResult<()> {
+        for (path, content) in std::mem::take(&mut run.files) {
+            let hash = content_hash(&content);
+            db.deploy_files()
+                .update_one(
+                    doc! { "_id": &hash },
+                    doc! {
+                        "$setOnInsert": {
+                            "content": &content,
+                            "size": content.len() as i64,
+                            "refs": 0i64,
+                        },
+                        "$set": { "touched_at": DateTime::now() },
+                    },
+                )
+                .upsert(true)
+                .await?;
+            run.file_refs.insert(path, hash);
+        }
+        Ok(())
+    }
+
+    /// Put the stored contents behind the refs of `revisions` back into their
+    /// runs' files, with one query for all of them.
+    pub async fn attach(db: &Arc<Mongo>, revisions: &mut [DeploymentRevision]) -> ServerResult<()> {
+        let hashes: BTreeSet<&String> = revisions
+            .iter()
+            .flat_map(|r| r.jobs.iter())
+            .flat_map(|run| run.file_refs.values())
+            .collect();
+        if hashes.is_empty() {
+            return Ok(());
+        }
+        let files: Vec<Self> = db
+            .deploy_files()
+            .find(doc! { "_id": { "$in": hashes.into_iter().collect::<Vec<_>>() } })
+            .await?
+            .try_collect()
+            .await?;
+        let contents: HashMap<String, String> =
+            files.into_iter().map(|f| (f.hash, f.content)).collect();
+
+        for run in revisions.iter_mut().flat_map(|r| r.jobs.iter_mut()) {
+            for (path, hash) in std::mem::take(&mut run.file_refs) {
+                let content = contents.get(&hash).ok_or_else(|| {
+                    ServerError::internal_error(&format!(
+                        "Stored file {} of run {} is missing",
+                        path, run.id
+                    ))
+                })?;
+                run.files.insert(path, content.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Count a revision's new refs and drop its removed ones, deleting files
+    /// no revision refers to anymore.
+    pub async fn update_refs(
+        db: &Arc<Mongo>,
+        added: &[String],
+        removed: &[String],
+    ) -> ServerResult<()> {
+        if !added.is_empty() {
+            db.deploy_files()
+                .update_many(
+                    doc! { "_id": { "$in": added } },
+                    doc! { "$inc": { "refs": 1i64 } },
+                )
+                .await?;
+        }
+        if !removed.is_empty() {
+            db.deploy_files()
+                .update_many(
+                    doc! { "_id": { "$in": removed } },
+                    doc! { "$inc": { "refs": -1i64 } },
+                )
+                .await?;
+            db.deploy_files()
+                .delete_many(doc! {
+                    "_id": { "$in": removed },
+                    "refs": { "$lte": 0 },
+                    "touched_at": { "$lt": grace_cutoff() },
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Delete unreferenced files past [`GRACE`]: those dropped while recently
+    /// stored, or stored for revisions that failed to insert.
+    pub async fn sweep(db: &Arc<Mongo>) -> ServerResult<u64> {
+        let res = db
+            .deploy_files()
+            .delete_many(doc! {
+                "refs": { "$lte": 0 },
+                "touched_at": { "$lt": grace_cutoff() },
+            })
+            .await?;
+        Ok(res.deleted_count)
+    }
+}
+
+fn grace_cutoff() -> DateTime {
+    DateTime::from_system_time(std::time::SystemTime::now() - GRACE)
+}
+
+/// Hashes of the stored files `revision` refers to, without duplicates.
+pub fn referenced_hashes(revision: &DeploymentRevision) -> Vec<String> {
+    let hashes: BTreeSet<&String> = revision
+        .jobs
+        .iter()
+        .flat_map(|run| run.file_refs.values())
+        .collect();
+    hashes.into_iter().cloned().collect()
+}
+
+/// Refs to add and to drop when a revision's [`referenced_hashes`] go from
+/// `old` to `new`: empty `old` on create, empty `new` on delete.
+pub fn ref_changes(old: &[String], new: &[String]) -> (Vec<String>, Vec<String>) {
+    let added = new.iter().filter(|h| !old.contains(h)).cloned().collect();
+    let removed = old.iter().filter(|h| !new.contains(h)).cloned().collect();
+    (added, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn revision(runs: &[&[(&str, &str)]]) -> DeploymentRevision {
+        let mut revision = DeploymentRevision::empty();
+        for (i, files) in runs.iter().enumerate() {
+            let mut run = RunSpec {
+                id: format!("run-{}", i),
+                ..Default::default()
+            };
+            for (path, hash) in files.iter() {
+                run.file_refs.insert(path.to_string(), hash.to_string());
+            }
+            revision.jobs.push(run);
+        }
+        revision
+    }
+
+    /// What [`DeployFileDoc::update_refs`] does to the `refs` of each file.
+    fn apply(refs: &mut BTreeMap<String, i64>, (added, removed): (Vec<String>, Vec<String>)) {
+        for hash in added {
+            *refs.entry(hash).or_default() += 1;
+        }
+        for hash in removed {
+            *refs.entry(hash).or_default() -= 1;
+        }
+        refs.retain(|_, count| *count > 0);
+    }
+
+    fn counts(refs: &BTreeMap<String, i64>) -> Vec<(&str, i64)> {
+        refs.iter().map(|(h, c)| (h.as_str(), *c)).collect()
+    }
+
+    #[test]
+    fn test_create_counts_a_shared_file_once_per_revision() {
+        // the same file in two runs and under two paths
+        let rev = revision(&[
+            &[("a.txt", "h1"), ("b.txt", "h1")],
+            &[("c.txt", "h1"), ("d.txt", "h2")],
+        ]);
+        let hashes = referenced_hashes(&rev);
+        assert_eq!(hashes, vec!["h1", "h2"]);
+
+        let mut refs = BTreeMap::new();
+        apply(&mut refs, ref_changes(&[], &hashes));
+        assert_eq!(counts(&refs), vec![("h1", 1), ("h2", 1)]);
+    }
+
+    #[test]
+    fn test_refs_follow_create_sync_and_delete() {
+        let mut refs = BTreeMap::new();
+        let first = referenced_hashes(&revision(&[&[("a", "h1"), ("b", "h2")]]));
+        let second = referenced_hashes(&revision(&[&[("b", "h2"), ("c", "h3")]]));
+        apply(&mut refs, ref_changes(&[], &first));
+        apply(&mut refs, ref_changes(&[], &second));
+        assert_eq!(counts(&refs), vec![("h1", 1), ("h2", 2), ("h3", 1)]);
+
+        // an update of the first revision swaps h1 for h3
+        let updated = referenced_hashes(&revision(&[&[("b", "h2")], &[("c", "h3")]]));
+        let (added, removed) = ref_changes(&first, &updated);
+        assert_eq!(
+            (added.as_slice(), removed.as_slice()),
+            (&["h3".to_string()][..], &["h1".to_string()][..])
+        );
+        apply(&mut refs, (added, removed));
+        assert_eq!(counts(&refs), vec![("h2", 2), ("h3", 2)]);
+
+        // an unchanged sync touches nothing
+        assert_eq!(ref_changes(&updated, &updated), (vec![], vec![]));
+
+        apply(&mut refs, ref_changes(&second, &[]));
+        apply(&mut refs, ref_changes(&updated, &[]));
+        assert!(refs.is_empty());
+    }
+}
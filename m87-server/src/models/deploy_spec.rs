@@ -6,11 +6,11 @@ use std::{
 use futures::TryStreamExt;
 use m87_shared::{
     deploy_spec::{
-        DeployReport, DeployReportKind, DeploymentRevision, DeploymentStatusSnapshot, ObserveKind,
-        ObserveStatusItem, Outcome, RollbackStatus, RunSpec, RunStatus, StepAttemptStatus,
-        StepState, StepStatus, UpdateDeployRevisionBody,
+        CoreDump, DeployReport, DeployReportKind, DeploymentRevision, DeploymentStatusSnapshot,
+        ObserveKind, ObserveStatusItem, Outcome, RollbackStatus, RunSpec, RunStatus, StepArtifacts,
+        StepAttemptStatus, StepState, StepStatus, UpdateDeployRevisionBody,
     },
-    device::ObserveStatus,
+    device::{DeviceTimelineEvent, ObserveStatus},
 };
 use mongodb::{
     bson::{DateTime as BsonDateTime, Document, doc, oid::ObjectId, to_bson},
@@ -21,6 +21,10 @@ use serde::{Deserialize, Serialize};
 use crate::{
     auth::access_control::AccessControlled,
     db::Mongo,
+    models::{
+        deploy_file::{DeployFileDoc, ref_changes, referenced_hashes},
+        device::DeviceDoc,
+    },
     response::{ServerError, ServerResult},
     util::pagination::RequestPagination,
 };
@@ -42,6 +46,13 @@ pub struct DeployRevisionDoc {
 
     pub owner_scope: String,
     pub allowed_scopes: Vec<String>,
+
+    /// Stored files the runs refer to, see [`deploy_file`](crate::models::deploy_file)
+    #[serde(default)]
+    pub file_hashes: Vec<String>,
+    /// Staged rollout that activates this revision, see [`rollout`](crate::models::rollout)
+    #[serde(default)]
+    pub rollout_id: Option<ObjectId>,
 }
 
 impl AccessControlled for DeployRevisionDoc {
@@ -59,7 +70,10 @@ impl AccessControlled for DeployRevisionDoc {
     }
 }
 
-pub fn to_update_doc(
+/// Update of a stored revision for `body`. Files of new runs are stored
+/// apart; [`DeployRevisionDoc::sync_file_refs`] counts them once applied.
+pub async fn to_update_doc(
+    db: &Arc<Mongo>,
     body: &UpdateDeployRevisionBody,
 ) -> ServerResult<(Document, Option<Document>)> {
     let mut which = 0;
@@ -90,8 +104,10 @@ pub fn to_update_doc(
 
     if let Some(yaml) = &body.revision {
         // DeploymentRevision::from_yaml ensures id is set on the server side
-        let rev: DeploymentRevision = DeploymentRevision::from_yaml(yaml)
+        let mut rev: DeploymentRevision = DeploymentRevision::from_yaml(yaml)
             .map_err(|e| ServerError::bad_request(&format!("invalid YAML in `revision`: {}", e)))?;
+        check_expressions(rev.expression_errors())?;
+        DeployFileDoc::detach(db, &mut rev).await?;
         return Ok((
             doc! { "$set": { "revision": to_bson(&rev).map_err(|e| ServerError::bad_request(&format!("revision -> bson failed: {}", e)))? } },
             None,
@@ -99,9 +115,11 @@ pub fn to_update_doc(
     }
 
     if let Some(yaml) = &body.add_run_spec {
-        let rs: RunSpec = serde_yaml::from_str(yaml).map_err(|e| {
+        let mut rs: RunSpec = serde_yaml::from_str(yaml).map_err(|e| {
             ServerError::bad_request(&format!("invalid YAML in `add_run_spec`: {}", e))
         })?;
+        check_expressions(rs.expression_errors())?;
+        DeployFileDoc::detach_run(db, &mut rs).await?;
         return Ok((
             doc! { "$push": { "revision.jobs": to_bson(&rs).map_err(|e| ServerError::bad_request(&format!("RunSpec -> bson failed: {}", e)))? } },
             None,
@@ -109,9 +127,11 @@ pub fn to_update_doc(
     }
 
     if let Some(yaml) = &body.update_run_spec {
-        let rs: RunSpec = serde_yaml::from_str(yaml).map_err(|e| {
+        let mut rs: RunSpec = serde_yaml::from_str(yaml).map_err(|e| {
             ServerError::bad_request(&format!("invalid YAML in `update_run_spec`: {}", e))
         })?;
+        check_expressions(rs.expression_errors())?;
+        DeployFileDoc::detach_run(db, &mut rs).await?;
         return Ok((
             doc! { "$set": { "revision.jobs.$": to_bson(&rs).map_err(|e| ServerError::bad_request(&format!("RunSpec -> bson failed: {}", e)))? } },
             Some(doc! { "revision.jobs.id": &rs.id }),
@@ -129,6 +149,17 @@ pub fn to_update_doc(
     Err(ServerError::internal_error("This should be unreachable"))
 }
 
+/// Reject specs whose `when` conditions or templates do not parse.
+fn check_expressions(errors: Vec<String>) -> ServerResult<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(ServerError::bad_request(&format!(
+        "invalid expressions: {}",
+        errors.join("; ")
+    )))
+}
+
 pub fn to_report_delete_doc(
     body: &UpdateDeployRevisionBody,
     revision_id: &str,
@@ -176,6 +207,7 @@ impl DeployRevisionDoc {
         owner_scope: String,
         allowed_scopes: Vec<String>,
     ) -> ServerResult<Self> {
+        check_expressions(revision.expression_errors())?;
         // index is the cnt of current docs for the dive or group
         let index = match (device_id, group_id) {
             (Some(device_id), _) => db
@@ -195,9 +227,12 @@ impl DeployRevisionDoc {
             }
         };
 
-        let doc = Self {
+        let mut stored = revision.clone();
+        DeployFileDoc::detach(db, &mut stored).await?;
+        let file_hashes = referenced_hashes(&stored);
+        let mut doc = Self {
             id: None,
-            revision,
+            revision: stored,
             device_id,
             group_id,
             active,
@@ -205,14 +240,48 @@ impl DeployRevisionDoc {
             index,
             owner_scope,
             allowed_scopes,
+            file_hashes,
+            rollout_id: None,
         };
         db.deploy_revisions()
             .insert_one(&doc)
             .await
             .map_err(|_| ServerError::internal_error("Failed to insert API key"))?;
+        let (added, _) = ref_changes(&[], &doc.file_hashes);
+        DeployFileDoc::update_refs(db, &added, &[]).await?;
+        // the caller gets the revision as sent, with its files
+        doc.revision = revision;
         Ok(doc)
     }
 
+    /// The revision with the contents of its stored files in place.
+    pub async fn into_revision(self, db: &Arc<Mongo>) -> ServerResult<DeploymentRevision> {
+        let mut revisions = [self.revision];
+        DeployFileDoc::attach(db, &mut revisions).await?;
+        let [revision] = revisions;
+        Ok(revision)
+    }
+
+    /// Recount the stored files of the revision `id` after an update changed
+    /// its runs.
+    pub async fn sync_file_refs(db: &Arc<Mongo>, id: ObjectId) -> ServerResult<()> {
+        let Some(doc) = db.deploy_revisions().find_one(doc! { "_id": id }).await? else {
+            return Ok(());
+        };
+        let current = referenced_hashes(&doc.revision);
+        if current == doc.file_hashes {
+            return Ok(());
+        }
+        db.deploy_revisions()
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": { "file_hashes": &current } },
+            )
+            .await?;
+        let (added, removed) = ref_changes(&doc.file_hashes, &current);
+        DeployFileDoc::update_refs(db, &added, &removed).await
+    }
+
     pub async fn get_active_device_deployment(
         db: &Arc<Mongo>,
         device_id: ObjectId,
@@ -261,6 +330,66 @@ impl DeployRevisionDoc {
             .await?;
         doc.ok_or(ServerError::not_found("Deploy revision not found"))
     }
+
+    /// Make `revision` the active deployment of `device`. Reuses the device's
+    /// existing revision with the same id, otherwise stores a new one, and
+    /// invalidates the device's deployment hash so the next heartbeat picks it up.
+    pub async fn activate_for_device(
+        db: &Arc<Mongo>,
+        device: &DeviceDoc,
+        revision: DeploymentRevision,
+    ) -> ServerResult<()> {
+        let device_oid = device
+            .id
+            .ok_or_else(|| ServerError::internal_error("Device without id"))?;
+
+        let revision_id = revision.id.clone().unwrap_or_default();
+        if !Self::activate(db, device_oid, Some(&revision_id)).await? {
+            Self::create(
+                db,
+                revision,
+                Some(device_oid),
+                None,
+                true,
+                device.owner_scope.clone(),
+                device.allowed_scopes.clone(),
+            )
+            .await?;
+            DeviceDoc::invalidate_deployment_hash(db, &device_oid).await?;
+        }
+        Ok(())
+    }
+
+    /// Make the stored revision `revision_id` of the device its active one,
+    /// or leave it without an active revision for `None`. Returns whether
+    /// the device has that revision.
+    pub async fn activate(
+        db: &Arc<Mongo>,
+        device_oid: ObjectId,
+        revision_id: Option<&str>,
+    ) -> ServerResult<bool> {
+        db.deploy_revisions()
+            .update_many(
+                doc! { "device_id": device_oid, "active": true },
+                doc! { "$set": { "active": false } },
+            )
+            .await?;
+        let found = match revision_id {
+            Some(revision_id) => {
+                db.deploy_revisions()
+                    .update_one(
+                        doc! { "device_id": device_oid, "revision.id": revision_id },
+                        doc! { "$set": { "active": true } },
+                    )
+                    .await?
+                    .matched_count
+                    > 0
+            }
+            None => true,
+        };
+        DeviceDoc::invalidate_deployment_hash(db, &device_oid).await?;
+        Ok(found)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -276,8 +405,12 @@ pub struct DeployReportDoc {
     /// TTL target (Mongo will delete when this time is reached)
     pub expires_at: Option<BsonDateTime>,
 
-    /// When the report was received/created
+    /// When the server received the report
     pub created_at: BsonDateTime,
+
+    /// Position in the device's report sequence, from runtimes that send one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -289,6 +422,9 @@ pub struct CreateDeployReportBody {
     /// Optional TTL (server can set a default if None)
     #[serde(default)]
     pub expires_at: Option<BsonDateTime>,
+
+    #[serde(default)]
+    pub seq: Option<u64>,
 }
 
 impl DeployReportDoc {
@@ -456,6 +592,7 @@ impl DeployReportDoc {
             kind: body.kind,
             expires_at: body.expires_at,
             created_at: now,
+            seq: body.seq,
         };
         let res = db.deploy_reports().insert_one(&doc).await.map_err(|e| {
             ServerError::internal_error(&format!("Failed to create deploy report: {:?}", e))
@@ -471,6 +608,7 @@ impl DeployReportDoc {
             kind: self.kind.clone(),
             expires_at: self.expires_at.map(|dt| dt.timestamp_millis() as u64),
             created_at: self.created_at.timestamp_millis() as u64,
+            seq: self.seq,
         }
     }
 
@@ -505,6 +643,161 @@ impl DeployReportDoc {
         Ok(results)
     }
 
+    /// Reports of the device that belong on its events timeline (applied
+    /// revisions, rollbacks, crashes, failed runs and steps), newest first.
+    pub async fn list_timeline(
+        db: &Arc<Mongo>,
+        device_id: &ObjectId,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Self>> {
+        let mut filter = doc! {
+            "device_id": device_id,
+            "$or": [
+                { "kind.type": "DeploymentRevisionReport" },
+                { "kind.type": "RollbackReport" },
+                { "kind.type": "RunReport", "kind.data.outcome": "failed" },
+                { "kind.type": "StepReport", "kind.data.success": false },
+                { "kind.type": "RunState", "kind.data.alive": false },
+                { "kind.type": "CoreDump" },
+                { "kind.type": "EgressViolation" },
+            ],
+        };
+        let mut range = Document::new();
+        if let Some(since) = pagination.since {
+            range.insert("$gte", since);
+        }
+        if let Some(until) = pagination.until {
+            range.insert("$lte", until);
+        }
+        if !range.is_empty() {
+            filter.insert("created_at", range);
+        }
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "created_at": -1 })
+            .projection(doc! { "kind.data.content": 0 })
+            .build();
+        let cursor = db
+            .deploy_reports()
+            .find(filter)
+            .with_options(options)
+            .await?;
+        cursor
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    /// Artifacts of the run's steps, from the latest report of each step
+    /// that has any.
+    pub async fn latest_step_artifacts(
+        db: &Arc<Mongo>,
+        device_id: &ObjectId,
+        revision_id: &str,
+        run_id: &str,
+    ) -> ServerResult<Vec<StepArtifacts>> {
+        let filter = doc! {
+            "device_id": device_id,
+            "revision_id": revision_id,
+            "kind.type": "StepReport",
+            "kind.data.run_id": run_id,
+            "kind.data.artifacts.0": { "$exists": true },
+        };
+        let options = FindOptions::builder()
+            .sort(doc! { "kind.data.report_time": -1 })
+            .build();
+        let mut cursor = db
+            .deploy_reports()
+            .find(filter)
+            .with_options(options)
+            .await?;
+        let mut out: Vec<StepArtifacts> = Vec::new();
+        while let Some(report_doc) = cursor
+            .try_next()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?
+        {
+            let DeployReportKind::StepReport(report) = report_doc.kind else {
+                continue;
+            };
+            if out.iter().any(|s| s.step == report.name) {
+                continue;
+            }
+            out.push(StepArtifacts {
+                step: report.name,
+                report_time: report.report_time,
+                artifacts: report.artifacts,
+            });
+        }
+        Ok(out)
+    }
+
+    /// Core dumps of the device, newest first, without their content.
+    pub async fn list_core_dumps(
+        db: &Arc<Mongo>,
+        device_id: &ObjectId,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<CoreDump>> {
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "created_at": -1 })
+            .projection(doc! { "kind.data.content": 0 })
+            .build();
+        let mut cursor = db
+            .deploy_reports()
+            .find(doc! { "device_id": device_id, "kind.type": "CoreDump" })
+            .with_options(options)
+            .await?;
+        let mut out = Vec::new();
+        while let Some(report_doc) = cursor
+            .try_next()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?
+        {
+            if let Some(core) = report_doc.into_core_dump() {
+                out.push(core);
+            }
+        }
+        Ok(out)
+    }
+
+    /// A core dump of the device with its content.
+    pub async fn get_core_dump(
+        db: &Arc<Mongo>,
+        device_id: &ObjectId,
+        id: &ObjectId,
+    ) -> ServerResult<Option<CoreDump>> {
+        let report_doc = db
+            .deploy_reports()
+            .find_one(doc! { "_id": id, "device_id": device_id, "kind.type": "CoreDump" })
+            .await?;
+        Ok(report_doc.and_then(Self::into_core_dump))
+    }
+
+    fn into_core_dump(self) -> Option<CoreDump> {
+        match (self.id, self.kind) {
+            (Some(id), DeployReportKind::CoreDump(report)) => Some(CoreDump {
+                id: id.to_hex(),
+                report,
+            }),
+            _ => None,
+        }
+    }
+
+    /// The report as an entry of the device events timeline, for the kinds
+    /// [`Self::list_timeline`] returns.
+    pub fn to_timeline_event(&self) -> Option<DeviceTimelineEvent> {
+        let (kind, summary) = self.kind.timeline_entry()?;
+        Some(DeviceTimelineEvent {
+            timestamp: self.created_at.try_to_rfc3339_string().ok()?,
+            kind,
+            summary,
+            actor: None,
+        })
+    }
+
     pub async fn compute_deployment_status_snapshot_for_device(
         db: &Arc<Mongo>,
         device_id: &ObjectId,
@@ -566,35 +859,47 @@ impl DeployReportDoc {
                 alive: None,
                 healthy: None,
                 steps,
+                steps_omitted: false,
+                drift: None,
             });
         }
 
-        // 2) Query Mongo with a cursor; stream docs and update snapshot in place.
-        // Sort by report_time ascending so “latest” comparisons are cheap and predictable.
+        // 2) Load the latest reports and put them in the order the device
+        // produced them, so later reports simply overwrite earlier ones below.
         let options = FindOptions::builder()
-            .sort(doc! { "report_time": 1i32 })
+            .sort(doc! { "created_at": -1i32 })
+            .limit(Some(MAX_SNAPSHOT_REPORTS))
             .batch_size(Some(256))
             .build();
 
         let mut cursor = db
             .deploy_reports()
-            .find(doc! { "device_id": device_id, "revision_id": revision_id })
+            .find(doc! {
+                "device_id": device_id,
+                "revision_id": revision_id,
+                // no part of the status, and the largest reports
+                "kind.type": { "$ne": "CoreDump" },
+            })
             .with_options(options)
             .await?;
+        let mut reports = Vec::new();
+        while let Some(doc) = cursor
+            .try_next()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?
+        {
+            reports.push(doc.to_pub_report());
+        }
+        order_reports(&mut reports);
 
         // Top-level revision fields
         let mut dirty = false;
         let mut rev_error: Option<String> = None;
         let mut rev_outcome = Outcome::Unknown;
         let mut rollback: Option<RollbackStatus> = None;
+        let mut skipped = vec![false; runs.len()];
 
-        while let Some(doc) = cursor
-            .try_next()
-            .await
-            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?
-        {
-            let r = doc.to_pub_report();
-
+        for r in reports {
             match r.kind {
                 DeployReportKind::DeploymentRevisionReport(x) => {
                     dirty = x.dirty;
@@ -610,11 +915,19 @@ impl DeployReportDoc {
                         report_time: None,
                     });
                 }
+                DeployReportKind::CoreDump(_) | DeployReportKind::EgressViolation(_) => {}
                 DeployReportKind::RunReport(x) => {
                     if let Some(&ri) = run_id_to_idx.get(&x.run_id) {
                         let run = &mut runs[ri];
                         let t = x.report_time as u64;
                         run.last_update = run.last_update.max(t);
+                        // a later report supersedes a skip (the condition may match again)
+                        skipped[ri] = x.outcome == Outcome::Skipped;
+                        if skipped[ri] {
+                            for step in run.steps.iter_mut().filter(|s| !s.is_undo) {
+                                step.state = StepState::Skipped;
+                            }
+                        }
                         if let Some(e) =
                             x.error.as_ref().map(|e| e.trim()).filter(|s| !s.is_empty())
                         {
@@ -637,21 +950,20 @@ impl DeployReportDoc {
                                 log_tail,
                             };
                             match kind {
-                                ObserveKind::Alive => {
-                                    if run.alive.as_ref().map(|a| a.report_time).unwrap_or(0) <= t {
-                                        run.alive = Some(item);
-                                    }
-                                }
-                                ObserveKind::Healthy => {
-                                    if run.healthy.as_ref().map(|a| a.report_time).unwrap_or(0) <= t
-                                    {
-                                        run.healthy = Some(item);
-                                    }
-                                }
+                                ObserveKind::Alive => run.alive = Some(item),
+                                ObserveKind::Healthy => run.healthy = Some(item),
                             }
                         }
                     }
                 }
+                DeployReportKind::DriftReport(x) => {
+                    if let Some(&ri) = run_id_to_idx.get(&x.run_id) {
+                        let run = &mut runs[ri];
+                        run.last_update = run.last_update.max(x.report_time);
+                        // a report without drift clears the earlier ones
+                        run.drift = x.is_drifted().then_some(x);
+                    }
+                }
                 DeployReportKind::StepReport(s) => {
                     if let Some(&ri) = run_id_to_idx.get(&s.run_id) {
                         let run = &mut runs[ri];
@@ -707,16 +1019,18 @@ impl DeployReportDoc {
                             log_tail: Some(s.log_tail.clone()),
                         };
 
-                        if st.attempt.as_ref().map(|a| a.report_time).unwrap_or(0) <= t {
-                            st.attempt = Some(attempt);
-                        }
+                        st.attempt = Some(attempt);
                     }
                 }
             }
         }
 
         // 3) Derive run outcomes without building any more maps.
-        for run in &mut runs {
+        for (run, skipped) in runs.iter_mut().zip(skipped) {
+            if skipped {
+                run.outcome = Outcome::Skipped;
+                continue;
+            }
             if run.error.is_some() {
                 run.outcome = Outcome::Failed;
                 continue;
@@ -733,6 +1047,8 @@ impl DeployReportDoc {
             Outcome::Unknown
         } else if runs.iter().any(|r| r.outcome == Outcome::Success) {
             Outcome::Success
+        } else if !runs.is_empty() && runs.iter().all(|r| r.outcome == Outcome::Skipped) {
+            Outcome::Skipped
         } else {
             Outcome::Unknown
         };
@@ -743,11 +1059,55 @@ impl DeployReportDoc {
             dirty,
             error: rev_error,
             rollback,
+            annotations: deployment.annotations,
+            provenance: deployment.provenance,
             runs,
+            total_runs: None,
         })
     }
 }
 
+/// Reports a status snapshot is built from. Older ones are mostly superseded
+/// by later reports of the same runs and steps, and a revision that keeps
+/// reporting would otherwise have all of its reports loaded at once.
+const MAX_SNAPSHOT_REPORTS: i64 = 5_000;
+
+/// How far a device clock may run ahead of the server, and how far report times
+/// may go backwards between consecutive reports (queued concurrently).
+const CLOCK_SKEW_TOLERANCE_MS: u64 = 60_000;
+
+/// Sort reports by device time, or by sequence number when the device clock
+/// looks wrong.
+fn order_reports(reports: &mut [DeployReport]) {
+    if clocks_look_skewed(reports) {
+        // reports of runtimes without sequence numbers go first, as received
+        reports.sort_by_key(|r| (r.seq.is_some(), r.seq, r.created_at));
+    } else {
+        reports.sort_by_key(|r| r.kind.report_time().unwrap_or(r.created_at));
+    }
+}
+
+/// A report from the future, or report times going backwards in sequence order
+/// (e.g. a Raspberry Pi without RTC that starts at an old time until NTP syncs).
+fn clocks_look_skewed(reports: &[DeployReport]) -> bool {
+    let ahead = reports.iter().any(|r| {
+        r.kind
+            .report_time()
+            .is_some_and(|t| t > r.created_at + CLOCK_SKEW_TOLERANCE_MS)
+    });
+    if ahead {
+        return true;
+    }
+    let mut by_seq: Vec<(u64, u64)> = reports
+        .iter()
+        .filter_map(|r| Some((r.seq?, r.kind.report_time()?)))
+        .collect();
+    by_seq.sort_unstable();
+    by_seq
+        .windows(2)
+        .any(|w| w[1].1 + CLOCK_SKEW_TOLERANCE_MS < w[0].1)
+}
+
 fn undo_slot(step_index: usize) -> usize {
     step_index * 2 + 1
 }
@@ -795,3 +1155,113 @@ fn outcome_from_steps(steps: &[StepStatus]) -> Outcome {
         Outcome::Unknown
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use m87_shared::deploy_spec::RunState;
+
+    use super::*;
+
+    /// Server time the reports below were received around
+    const NOW: u64 = 1_700_000_000_000;
+
+    fn report(run_id: &str, created_at: u64, report_time: u64, seq: Option<u64>) -> DeployReport {
+        DeployReport {
+            device_id: "device".to_string(),
+            revision_id: "rev".to_string(),
+            kind: DeployReportKind::RunState(RunState {
+                run_id: run_id.to_string(),
+                revision_id: "rev".to_string(),
+                healthy: Some(true),
+                alive: Some(true),
+                report_time,
+                log_tail: None,
+            }),
+            expires_at: None,
+            created_at,
+            seq,
+        }
+    }
+
+    fn ordered(mut reports: Vec<DeployReport>) -> Vec<String> {
+        order_reports(&mut reports);
+        reports
+            .into_iter()
+            .map(|r| match r.kind {
+                DeployReportKind::RunState(s) => s.run_id,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_with_sane_clocks_are_ordered_by_device_time() {
+        // received out of order, sequence numbers disagreeing with the times
+        let reports = vec![
+            report("b", NOW + 10, NOW - 1_000, Some(1)),
+            report("a", NOW + 20, NOW - 2_000, Some(3)),
+            report("c", NOW + 30, NOW, Some(2)),
+        ];
+        assert!(!clocks_look_skewed(&reports));
+        assert_eq!(ordered(reports), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_small_steps_back_are_not_skew() {
+        // reports queued concurrently may go back a little in sequence order
+        let reports = vec![
+            report("a", NOW, NOW, Some(1)),
+            report("b", NOW, NOW - CLOCK_SKEW_TOLERANCE_MS, Some(2)),
+            report("c", NOW, NOW + CLOCK_SKEW_TOLERANCE_MS, None),
+        ];
+        assert!(!clocks_look_skewed(&reports));
+    }
+
+    #[test]
+    fn test_reports_from_the_future_are_ordered_by_seq() {
+        let reports = vec![
+            report("c", NOW, NOW + 3 * CLOCK_SKEW_TOLERANCE_MS, Some(3)),
+            report("a", NOW, NOW + 5 * CLOCK_SKEW_TOLERANCE_MS, Some(1)),
+            report("b", NOW, NOW + 4 * CLOCK_SKEW_TOLERANCE_MS, Some(2)),
+        ];
+        assert!(clocks_look_skewed(&reports));
+        assert_eq!(ordered(reports), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_clock_going_back_is_ordered_by_seq() {
+        // no RTC: the device starts in 1970 and jumps ahead once NTP syncs,
+        // then reboots into 1970 again
+        let reports = vec![
+            report("a", NOW, 5_000, Some(1)),
+            report("b", NOW + 1, NOW, Some(2)),
+            report("c", NOW + 2, 6_000, Some(3)),
+        ];
+        assert!(clocks_look_skewed(&reports));
+        assert_eq!(ordered(reports), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_reports_without_seq_go_first_when_skewed() {
+        let reports = vec![
+            report("c", NOW + 3, NOW, Some(2)),
+            report("y", NOW + 2, 1_000, None),
+            report("b", NOW + 4, 5_000, Some(1)),
+            report("x", NOW + 1, NOW, None),
+            report("d", NOW + 5, 7_000, Some(3)),
+        ];
+        assert!(clocks_look_skewed(&reports));
+        assert_eq!(ordered(reports), vec!["x", "y", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_missing_seq_cannot_show_skew_going_back() {
+        // without sequence numbers only reports from the future are noticed
+        let reports = vec![
+            report("b", NOW + 2, NOW, None),
+            report("a", NOW + 1, 5_000, None),
+        ];
+        assert!(!clocks_look_skewed(&reports));
+        assert_eq!(ordered(reports), vec!["a", "b"]);
+    }
+}
@@ -3,8 +3,16 @@ use std::fmt::Display;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
 
+use m87_shared::bandwidth::{BandwidthProfile, DeviceBandwidth};
 use m87_shared::deploy_spec::{DeployReportKind, DeploymentRevision, build_instruction_hash};
-use m87_shared::device::DeviceStatus;
+use m87_shared::device::{
+    DeviceStatus, IncidentInfo, ObserveStatus, TimelineEventKind, alert_scope,
+};
+use m87_shared::fleet_report::FleetReportRow;
+use m87_shared::metrics::{BatteryMetrics, Location};
+use m87_shared::probe::{ProbeResult, ProbeTransition, latest_results, probe_transitions};
+use m87_shared::protocol::{Compatibility, PROTOCOL_VERSION, check_compatibility, upgrade_message};
+use m87_shared::revision_delta::{RevisionDelta, run_hashes};
 use m87_shared::roles::Role;
 use m87_shared::users::User;
 use mongodb::bson::{DateTime, Document, doc, oid::ObjectId};
@@ -13,14 +21,30 @@ use serde::{Deserialize, Serialize};
 
 // Import shared types
 pub use m87_shared::config::DeviceClientConfig;
-pub use m87_shared::device::{DeviceSystemInfo, PublicDevice, short_device_id};
-pub use m87_shared::heartbeat::{HeartbeatRequest, HeartbeatResponse};
+pub use m87_shared::device::{
+    AlertRules, DeviceAssetInfo, DeviceMaintenance, DeviceSystemInfo, FailedUpgrade, PublicDevice,
+    TimeStatus, short_device_id,
+};
+pub use m87_shared::heartbeat::{
+    HeartbeatRequest, HeartbeatResponse, ReportAck, ReportAckStatus, ReportDigest,
+};
 use tokio_stream::StreamExt;
 
 use crate::config::AppConfig;
+use crate::models::access_grant::AccessGrantDoc;
+use crate::models::approval::ApprovalRequestDoc;
 use crate::models::audit_logs::AuditLogDoc;
 use crate::models::deploy_spec::{CreateDeployReportBody, DeployReportDoc, DeployRevisionDoc};
+use crate::models::device_history::DeviceHistoryDoc;
+use crate::models::device_inventory::DeviceInventoryDoc;
+use crate::models::device_location::DeviceLocationDoc;
+use crate::models::idempotency::IdempotencyKeyDoc;
+use crate::models::incident::IncidentDoc;
 use crate::models::org;
+use crate::models::org_limits;
+use crate::models::org_redaction::OrgRedactionDoc;
+use crate::models::patching::{PatchPolicyDoc, PatchRunDoc};
+use crate::models::probe::ProbeResultDoc;
 use crate::models::roles::{CreateRoleBinding, RoleDoc};
 use crate::models::user::UserDoc;
 use crate::{
@@ -29,6 +53,11 @@ use crate::{
     response::{ServerError, ServerResult},
 };
 
+/// A reported boot time further than this from the stored one is a reboot
+const BOOT_TIME_TOLERANCE_SECS: u64 = 120;
+/// Incidents listed in the device status, newest first
+const STATUS_INCIDENTS: i64 = 5;
+
 fn default_stable_version() -> String {
     "latest".to_string()
 }
@@ -44,6 +73,18 @@ pub struct UpdateDeviceBody {
     pub owner_scope: Option<String>,
     #[serde(default)]
     pub allowed_scopes: Option<Vec<String>>,
+    #[serde(default)]
+    pub info: Option<DeviceAssetInfo>,
+    #[serde(default)]
+    pub alert_rules: Option<AlertRules>,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub maintenance: Option<DeviceMaintenance>,
+    #[serde(default)]
+    pub require_approval: Option<bool>,
+    #[serde(default)]
+    pub bandwidth_profile: Option<BandwidthProfile>,
 }
 
 impl Display for UpdateDeviceBody {
@@ -77,11 +118,49 @@ impl UpdateDeviceBody {
             update_fields.insert("target_version", target_version);
         }
 
+        if let Some(info) = &self.info {
+            update_fields.insert("info", mongodb::bson::to_bson(info).unwrap());
+        }
+
+        if let Some(alert_rules) = &self.alert_rules {
+            update_fields.insert("alert_rules", mongodb::bson::to_bson(alert_rules).unwrap());
+        }
+
+        if let Some(name) = &self.name {
+            update_fields.insert("name", name);
+        }
+
+        if let Some(maintenance) = &self.maintenance {
+            if maintenance.enabled {
+                let maintenance = DeviceMaintenance {
+                    since: Some(DateTime::now().timestamp_millis() as u64),
+                    ..maintenance.clone()
+                };
+                update_fields.insert("maintenance", mongodb::bson::to_bson(&maintenance).unwrap());
+            } else {
+                update_fields.insert("maintenance", mongodb::bson::Bson::Null);
+            }
+        }
+
+        if let Some(require_approval) = self.require_approval {
+            update_fields.insert("require_approval", require_approval);
+        }
+
         if let Some(config) = &self.config {
-            update_fields.insert("config", mongodb::bson::to_bson(config).unwrap());
+            let mut config = config.clone();
+            if let Some(profile) = self.bandwidth_profile {
+                config.bandwidth_profile = profile;
+            }
+            update_fields.insert("config", mongodb::bson::to_bson(&config).unwrap());
 
             let new_hash = config.get_hash().to_string();
             update_fields.insert("last_instruction_hash", new_hash);
+        } else if let Some(profile) = &self.bandwidth_profile {
+            // sent with the config on the next heartbeat, which hashes it
+            update_fields.insert(
+                "config.bandwidth_profile",
+                mongodb::bson::to_bson(profile).unwrap(),
+            );
         }
 
         // Always set these system timestamps
@@ -124,6 +203,41 @@ pub struct DeviceDoc {
     pub last_config_hash: String,
     #[serde(default)]
     pub last_deployment_hash: String,
+    /// Hash of the revision last sent to the runtime, as sent
+    #[serde(default)]
+    pub last_sent_revision: String,
+    /// Hashes of that revision's runs, the base of the next [`RevisionDelta`]
+    #[serde(default)]
+    pub last_sent_runs: Vec<String>,
+    #[serde(default)]
+    pub last_failed_upgrade: Option<FailedUpgrade>,
+    #[serde(default)]
+    pub info: DeviceAssetInfo,
+    #[serde(default)]
+    pub last_location: Option<Location>,
+    #[serde(default)]
+    pub last_battery: Option<BatteryMetrics>,
+    /// Unix time of the last boot the runtime reported
+    #[serde(default)]
+    pub last_boot_time: Option<u64>,
+    #[serde(default)]
+    pub alert_rules: AlertRules,
+    #[serde(default)]
+    pub last_time: Option<TimeStatus>,
+    #[serde(default)]
+    pub maintenance: Option<DeviceMaintenance>,
+    /// Destructive operations need a second operator's approval
+    #[serde(default)]
+    pub require_approval: bool,
+    /// Last reported result of each probe in the config
+    #[serde(default)]
+    pub last_probe_results: Vec<ProbeResult>,
+    /// Traffic of the runtime per class, from its heartbeats
+    #[serde(default)]
+    pub bandwidth: DeviceBandwidth,
+    /// Deploy reports the runtime holds back, from its last periodic heartbeat
+    #[serde(default)]
+    pub report_digest: Option<ReportDigest>,
 }
 
 impl DeviceDoc {
@@ -149,11 +263,13 @@ impl DeviceDoc {
             }
         };
 
+        let name = Self::unique_name(db, &create_body.owner_scope, &create_body.name).await?;
+
         let now = DateTime::now();
         let node = DeviceDoc {
             id: Some(device_id.clone()),
             short_id: short_device_id(&device_id.to_string()),
-            name: create_body.name,
+            name,
             updated_at: now,
             created_at: now,
             version: "".to_string(),
@@ -167,11 +283,193 @@ impl DeviceDoc {
             api_key_id: create_body.api_key_id,
             last_config_hash: "".to_string(),
             last_deployment_hash: "".to_string(),
+            last_sent_revision: "".to_string(),
+            last_sent_runs: Vec::new(),
+            last_failed_upgrade: None,
+            info: DeviceAssetInfo::default(),
+            last_location: None,
+            last_battery: None,
+            last_boot_time: None,
+            alert_rules: AlertRules::default(),
+            last_time: None,
+            maintenance: None,
+            require_approval: false,
+            last_probe_results: Vec::new(),
+            bandwidth: DeviceBandwidth::default(),
+            report_digest: None,
         };
         let _ = db.devices().insert_one(node.clone()).await?;
         Ok(())
     }
 
+    /// Whether a device other than `exclude` in `owner_scope` is named `name`.
+    pub async fn name_taken(
+        db: &Arc<Mongo>,
+        owner_scope: &str,
+        name: &str,
+        exclude: Option<&ObjectId>,
+    ) -> ServerResult<bool> {
+        let mut filter = doc! { "owner_scope": owner_scope, "name": name };
+        if let Some(id) = exclude {
+            filter.insert("_id", doc! { "$ne": id });
+        }
+        let count = db.devices().count_documents(filter).await?;
+        Ok(count > 0)
+    }
+
+    /// `base` if no device in `owner_scope` uses it yet, otherwise the first
+    /// free `base-2`, `base-3`, ...
+    pub async fn unique_name(
+        db: &Arc<Mongo>,
+        owner_scope: &str,
+        base: &str,
+    ) -> ServerResult<String> {
+        let mut name = base.to_string();
+        let mut n = 1;
+        while Self::name_taken(db, owner_scope, &name, None).await? {
+            n += 1;
+            name = format!("{}-{}", base, n);
+        }
+        Ok(name)
+    }
+
+    /// Hand an already registered device a new API key after it re-registered
+    /// (e.g. following a reinstall), keeping its name, deployments and
+    /// reports. The previous key and its role bindings are revoked.
+    pub async fn rebind(
+        &self,
+        db: &Arc<Mongo>,
+        api_key_id: ObjectId,
+        system_info: &DeviceSystemInfo,
+    ) -> ServerResult<()> {
+        db.api_keys()
+            .delete_many(doc! { "_id": self.api_key_id })
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to delete API keys"))?;
+        db.roles()
+            .delete_many(doc! { "reference_id": self.api_key_id })
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to delete roles"))?;
+
+        db.devices()
+            .update_one(
+                doc! { "_id": self.id },
+                doc! { "$set": {
+                    "api_key_id": api_key_id,
+                    "system_info": mongodb::bson::to_bson(system_info).unwrap(),
+                    "updated_at": DateTime::now(),
+                } },
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Adopt the history of `source`, a duplicate of this device registered
+    /// under another identity: deployments, reports, audit logs, locations
+    /// and access grants move here, this device takes over the source's name
+    /// and any asset info or alert rules it lacks, then `source` and its API
+    /// key are deleted. Adopted deployments are ordered before this device's
+    /// own and only stay active if this device has no active deployment.
+    pub async fn merge_from(&self, db: &Arc<Mongo>, source: &DeviceDoc) -> ServerResult<()> {
+        let target_id = self.id.unwrap();
+        let source_id = source.id.unwrap();
+
+        let adopted = db
+            .deploy_revisions()
+            .count_documents(doc! { "device_id": source_id })
+            .await?;
+        let has_active = db
+            .deploy_revisions()
+            .count_documents(doc! { "device_id": target_id, "active": true })
+            .await?
+            > 0;
+        db.deploy_revisions()
+            .update_many(
+                doc! { "device_id": target_id },
+                doc! { "$inc": { "index": adopted as i64 } },
+            )
+            .await?;
+        let mut moved = doc! {
+            "device_id": target_id,
+            "allowed_scopes": self.allowed_scopes.clone(),
+        };
+        if has_active {
+            moved.insert("active", false);
+        }
+        db.deploy_revisions()
+            .update_many(doc! { "device_id": source_id }, doc! { "$set": moved })
+            .await?;
+
+        let reassign = doc! { "$set": { "device_id": target_id } };
+        db.deploy_reports()
+            .update_many(doc! { "device_id": source_id }, reassign.clone())
+            .await?;
+        db.audit_logs()
+            .update_many(doc! { "device_id": source_id }, reassign.clone())
+            .await?;
+        db.device_locations()
+            .update_many(doc! { "device_id": source_id }, reassign.clone())
+            .await?;
+        db.device_history()
+            .update_many(doc! { "device_id": source_id }, reassign.clone())
+            .await?;
+        db.patch_runs()
+            .update_many(doc! { "device_id": source_id }, reassign.clone())
+            .await?;
+        db.probe_results()
+            .update_many(doc! { "device_id": source_id }, reassign)
+            .await?;
+        // the target reports its own packages
+        DeviceInventoryDoc::delete_for_device(db, source_id).await?;
+        // approvals name the device they were given for
+        ApprovalRequestDoc::delete_for_device(db, source_id).await?;
+
+        let source_scope = Self::scope_for_device(&source_id);
+        let mut allowed_scopes = self.allowed_scopes.clone();
+        for scope in &source.allowed_scopes {
+            if *scope != source_scope && !allowed_scopes.contains(scope) {
+                allowed_scopes.push(scope.clone());
+            }
+        }
+
+        let mut update = doc! {
+            "name": &source.name,
+            "allowed_scopes": allowed_scopes,
+            "last_deployment_hash": "",
+            "updated_at": DateTime::now(),
+        };
+        if self.info.is_empty() {
+            update.insert("info", mongodb::bson::to_bson(&source.info).unwrap());
+        }
+        if self.alert_rules.is_empty() {
+            update.insert(
+                "alert_rules",
+                mongodb::bson::to_bson(&source.alert_rules).unwrap(),
+            );
+        }
+        if self.config.probes.is_empty() && !source.config.probes.is_empty() {
+            update.insert(
+                "config.probes",
+                mongodb::bson::to_bson(&source.config.probes).unwrap(),
+            );
+        }
+
+        db.api_keys()
+            .delete_many(doc! { "_id": source.api_key_id })
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to delete API keys"))?;
+        db.roles()
+            .delete_many(doc! { "reference_id": source.api_key_id })
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to delete roles"))?;
+        db.devices().delete_one(doc! { "_id": source_id }).await?;
+
+        db.devices()
+            .update_one(doc! { "_id": target_id }, doc! { "$set": update })
+            .await?;
+        Ok(())
+    }
+
     pub async fn invalidate_deployment_hash(
         db: &Arc<Mongo>,
         device_oid: &ObjectId,
@@ -202,6 +500,12 @@ impl DeviceDoc {
             .await
             .map_err(|_| ServerError::internal_error("Failed to delete roles"))?;
 
+        DeviceInventoryDoc::delete_for_device(db, self.id.unwrap()).await?;
+        PatchRunDoc::delete_for_device(db, self.id.unwrap()).await?;
+        ProbeResultDoc::delete_for_device(db, self.id.unwrap()).await?;
+        ApprovalRequestDoc::delete_for_device(db, self.id.unwrap()).await?;
+        AccessGrantDoc::delete_for_device(db, self.id.unwrap()).await?;
+
         // Check access and delete device
         let success = claims
             .delete_one_with_access(&db.devices(), doc! { "_id": &self.id.clone().unwrap() })
@@ -215,6 +519,36 @@ impl DeviceDoc {
         Ok(())
     }
 
+    /// Alert for a heartbeat's battery reading under the device's alert rules.
+    pub fn battery_alert(&self, battery: &BatteryMetrics) -> Option<String> {
+        self.alert_rules.battery_alert(self.last_battery.as_ref(), battery)
+    }
+
+    /// Alert conditions that start to hold with this heartbeat, as (rule, message).
+    pub fn condition_alerts(&self, req: &HeartbeatRequest) -> Vec<(String, String)> {
+        if self.alert_rules.conditions.is_empty() {
+            return Vec::new();
+        }
+        let previous = alert_scope(self.last_battery.as_ref(), self.last_location.as_ref());
+        let current = alert_scope(
+            req.battery.as_ref().or(self.last_battery.as_ref()),
+            req.location
+                .as_ref()
+                .filter(|l| l.is_valid())
+                .or(self.last_location.as_ref()),
+        );
+        self.alert_rules.condition_alerts(&previous, &current)
+    }
+
+    /// Probes that went down or came back up with this heartbeat.
+    pub fn probe_transitions(&self, req: &HeartbeatRequest) -> Vec<ProbeTransition> {
+        probe_transitions(
+            &self.config.probes,
+            &self.last_probe_results,
+            &req.probe_results,
+        )
+    }
+
     pub async fn handle_heartbeat(
         &self,
         claims: Claims,
@@ -222,6 +556,24 @@ impl DeviceDoc {
         payload: HeartbeatRequest,
         config: &Arc<AppConfig>,
     ) -> ServerResult<HeartbeatResponse> {
+        // runtimes that predate the negotiation do not send a version
+        let protocol = payload.protocol_version.unwrap_or(1);
+        if check_compatibility(protocol) == Compatibility::PeerTooOld {
+            let message =
+                upgrade_message(format!("The m87 runtime on device {}", self.name), protocol);
+            tracing::warn!("{}", message);
+            return Ok(HeartbeatResponse {
+                up_to_date: true,
+                config: None,
+                instruction_hash: payload.last_instruction_hash,
+                target_revision: None,
+                revision_delta: None,
+                protocol_version: Some(PROTOCOL_VERSION),
+                upgrade_required: Some(message),
+                report_ack: None,
+            });
+        }
+
         let mut update_fields = doc! {};
         if let Some(sys_info) = payload.system_info {
             update_fields.insert("system_info", mongodb::bson::to_bson(&sys_info).unwrap());
@@ -229,6 +581,177 @@ impl DeviceDoc {
         if let Some(client_version) = payload.client_version {
             update_fields.insert("version", client_version);
         }
+        if let Some(failed) = &payload.failed_upgrade {
+            update_fields.insert(
+                "last_failed_upgrade",
+                mongodb::bson::to_bson(failed).unwrap(),
+            );
+            let _ = AuditLogDoc::add(
+                db,
+                &claims,
+                config,
+                &format!(
+                    "Runtime upgrade from {} to {} failed on device {}; reverted to previous binary",
+                    &failed.from_version,
+                    &failed.to_version,
+                    self.id.unwrap()
+                ),
+                &failed.reason,
+                self.id,
+            )
+            .await;
+        }
+
+        if let Some(location) = payload.location.filter(|l| l.is_valid()) {
+            update_fields.insert("last_location", mongodb::bson::to_bson(&location).unwrap());
+            if let Err(err) = DeviceLocationDoc::add(db, config, self.id.unwrap(), location).await {
+                tracing::error!("Failed to record device location: {}", err);
+            }
+        }
+
+        if let Some(battery) = &payload.battery {
+            update_fields.insert("last_battery", mongodb::bson::to_bson(battery).unwrap());
+        }
+
+        if let Some(report) = payload.bandwidth.clone() {
+            let mut bandwidth = self.bandwidth.clone();
+            let now = DateTime::now().timestamp_millis() as u64 / 1000;
+            bandwidth.add_report(report, now);
+            update_fields.insert("bandwidth", mongodb::bson::to_bson(&bandwidth).unwrap());
+        }
+
+        // periodic heartbeats carry a digest only while reports are held back
+        if payload.deploy_report.is_none() {
+            update_fields.insert(
+                "report_digest",
+                mongodb::bson::to_bson(&payload.report_digest).unwrap(),
+            );
+        }
+
+        if let Some(boot_time) = payload.boot_time {
+            // boot times derived from uptime jitter by a few seconds
+            let rebooted = self
+                .last_boot_time
+                .is_some_and(|last| boot_time.abs_diff(last) > BOOT_TIME_TOLERANCE_SECS);
+            if rebooted {
+                let booted_at = DateTime::from_millis(boot_time as i64 * 1000)
+                    .try_to_rfc3339_string()
+                    .unwrap_or_else(|_| boot_time.to_string());
+                let summary = format!("Device rebooted at {}", booted_at);
+                if let Err(err) = DeviceHistoryDoc::add(
+                    db,
+                    config,
+                    self.id.unwrap(),
+                    TimelineEventKind::Reboot,
+                    summary,
+                )
+                .await
+                {
+                    tracing::error!("Failed to record device reboot: {}", err);
+                }
+            }
+            update_fields.insert("last_boot_time", boot_time as i64);
+        }
+
+        if let Some(inventory) = &payload.inventory
+            && let Err(err) = DeviceInventoryDoc::store(db, self.id.unwrap(), inventory).await
+        {
+            tracing::error!("Failed to store package inventory: {}", err);
+        }
+
+        for report in &payload.package_operations {
+            let outcome = if report.success {
+                "succeeded"
+            } else {
+                "failed"
+            };
+            let mut details = format!(
+                "tool: {}\nexit code: {}\n",
+                report.tool.as_deref().unwrap_or("-"),
+                report
+                    .exit_code
+                    .map(|c| c.to_string())
+                    .unwrap_or("-".to_string()),
+            );
+            if let Some(error) = &report.error {
+                details.push_str(&format!("error: {}\n", error));
+            }
+            details.push_str(&report.log_tail);
+            let _ = AuditLogDoc::add(
+                db,
+                &claims,
+                config,
+                &format!(
+                    "Package {} by {} {} on device {}",
+                    report.describe(),
+                    report.user,
+                    outcome,
+                    self.name
+                ),
+                &details,
+                self.id,
+            )
+            .await;
+        }
+
+        for run in &payload.patch_runs {
+            let operation = &run.operation;
+            let outcome = match (operation.success, run.rebooting) {
+                (true, true) => "succeeded, rebooting",
+                (true, false) => "succeeded",
+                (false, _) => "failed",
+            };
+            let mut details = format!(
+                "packages: {}\nexit code: {}\n",
+                operation.describe(),
+                operation
+                    .exit_code
+                    .map(|c| c.to_string())
+                    .unwrap_or("-".to_string()),
+            );
+            if let Some(error) = &operation.error {
+                details.push_str(&format!("error: {}\n", error));
+            }
+            details.push_str(&operation.log_tail);
+            let _ = AuditLogDoc::add(
+                db,
+                &claims,
+                config,
+                &format!(
+                    "Patch policy {} {} on device {}",
+                    run.policy_name, outcome, self.name
+                ),
+                &details,
+                self.id,
+            )
+            .await;
+            if let Err(err) = PatchRunDoc::add(db, config, self.id.unwrap(), run.clone()).await {
+                tracing::error!("Failed to store patch run: {}", err);
+            }
+        }
+
+        if !payload.probe_results.is_empty() {
+            let latest = latest_results(
+                &self.config.probes,
+                &self.last_probe_results,
+                &payload.probe_results,
+            );
+            update_fields.insert(
+                "last_probe_results",
+                mongodb::bson::to_bson(&latest).unwrap(),
+            );
+            if let Err(err) =
+                ProbeResultDoc::add_many(db, config, self.id.unwrap(), &payload.probe_results).await
+            {
+                tracing::error!("Failed to store probe results: {}", err);
+            }
+        }
+
+        if let Some(sent_at) = payload.sent_at {
+            let mut time = payload.time.clone().unwrap_or_default();
+            time.offset_ms = Some(sent_at as i64 - DateTime::now().timestamp_millis());
+            update_fields.insert("last_time", mongodb::bson::to_bson(&time).unwrap());
+        }
 
         if !update_fields.is_empty() {
             update_fields.insert("updated_at", DateTime::now());
@@ -246,20 +769,55 @@ impl DeviceDoc {
             )
             .await;
 
+        let mut report_ack = None;
         if let Some(deploy_report) = payload.deploy_report {
             let body = CreateDeployReportBody {
                 device_id: self.id.clone().unwrap(),
                 revision_id: deploy_report.get_revision_id().to_string(),
                 kind: deploy_report.clone(),
+                seq: payload.report_seq,
                 expires_at: Some(DateTime::from_system_time(
                     SystemTime::now()
                         + Duration::from_hours(24 * config.report_retention_days as u64),
                 )),
             };
-            let res = DeployReportDoc::create_or_update(db, body).await;
-            if let Err(err) = res {
-                tracing::error!("Failed to create deploy report: {}", err);
-            }
+            let stored = match org_limits::check_report_storage(db, config, self).await {
+                Ok(()) => DeployReportDoc::create_or_update(db, body).await,
+                Err(err) => Err(err),
+            };
+            let status = match stored {
+                Ok(report) => {
+                    let report_id = report.id.unwrap();
+                    if let Some(key) = &payload.idempotency_key
+                        && let Err(err) =
+                            IdempotencyKeyDoc::set_report(db, self.id.unwrap(), key, report_id)
+                                .await
+                    {
+                        tracing::error!("Failed to record acked report: {}", err);
+                    }
+                    ReportAckStatus::Stored {
+                        report_id: report_id.to_hex(),
+                    }
+                }
+                // the revision or run is gone, resending will not help
+                Err(ServerError::NotFound(reason)) => ReportAckStatus::Rejected { reason },
+                // resending the same report will not fit either
+                Err(ServerError::QuotaExceeded(reason)) => {
+                    tracing::warn!("Deploy report of {} not stored: {}", self.name, reason);
+                    ReportAckStatus::Rejected { reason }
+                }
+                Err(err) => {
+                    tracing::error!("Failed to create deploy report: {}", err);
+                    ReportAckStatus::Retry
+                }
+            };
+            report_ack = payload
+                .idempotency_key
+                .clone()
+                .map(|idempotency_key| ReportAck {
+                    idempotency_key,
+                    status,
+                });
 
             if let DeployReportKind::RollbackReport(rollback) = deploy_report {
                 // change active deplotment to rollback.new_revision_id
@@ -302,29 +860,82 @@ impl DeviceDoc {
             }
         }
 
-        let target_hash =
-            build_instruction_hash(&self.last_deployment_hash, &self.last_config_hash);
+        // patch policies and redaction patterns change with the device's
+        // scopes and maintenance mode, so the config sent is rebuilt on every
+        // heartbeat
+        let mut target_config = self.config.clone();
+        target_config.patch_policies = PatchPolicyDoc::for_device(db, self).await?;
+        target_config.redact_patterns = OrgRedactionDoc::for_device(db, self).await?;
+        let config_hash = target_config.get_hash().to_string();
+        let target_hash = build_instruction_hash(&self.last_deployment_hash, &config_hash);
         if payload.last_instruction_hash == target_hash {
             return Ok(HeartbeatResponse {
                 up_to_date: true,
                 config: None,
                 instruction_hash: target_hash.clone(),
                 target_revision: None,
+                revision_delta: None,
+                protocol_version: Some(PROTOCOL_VERSION),
+                upgrade_required: None,
+                report_ack,
             });
         }
 
         let out = DeployRevisionDoc::get_active_device_deployment(&db, self.id.unwrap()).await;
-        let target_revision = match out {
-            Ok(Some(revision)) => Some(revision.revision),
+        let mut target_revision = match out {
+            Ok(Some(doc)) => match doc.into_revision(db).await {
+                Ok(revision) => Some(revision),
+                Err(err) => {
+                    tracing::error!(
+                        "Failed to load the active revision of device {}: {:?}",
+                        self.short_id,
+                        err
+                    );
+                    None
+                }
+            },
             Ok(None) => Some(DeploymentRevision::empty()),
-            _ => None,
+            Err(err) => {
+                tracing::error!(
+                    "Failed to find the active revision of device {}: {:?}",
+                    self.short_id,
+                    err
+                );
+                None
+            }
         };
 
         let new_deployment_hash = match &target_revision {
             Some(revision) => revision.get_hash(),
             None => "".to_string(),
         };
-        let config_hash = self.config.get_hash().to_string();
+
+        let mut update = doc! {
+            "last_deployment_hash": &new_deployment_hash,
+            "last_config_hash": &config_hash,
+        };
+        let mut revision_delta = None;
+        if let Some(revision) = target_revision.as_mut() {
+            revision.downgrade_for(protocol);
+            let sent_hash = revision.get_hash();
+            let sent_runs = run_hashes(revision);
+            // the runtime only holds the runs last sent if it applied them
+            if let Some(held) = &payload.held_revision
+                && !held.is_empty()
+                && *held == self.last_sent_revision
+            {
+                let delta = RevisionDelta::between(held, &self.last_sent_runs, revision);
+                if delta.saves_runs() {
+                    revision_delta = Some(delta);
+                }
+            }
+            update.insert("last_sent_revision", sent_hash);
+            update.insert("last_sent_runs", sent_runs);
+        }
+        if revision_delta.is_some() {
+            target_revision = None;
+        }
+
         // update last_deployment_hash in database
         let _ = db
             .devices()
@@ -333,46 +944,116 @@ impl DeviceDoc {
                     "_id": &self.id.unwrap()
                 },
                 doc! {
-                    "$set": doc! {
-                        "last_deployment_hash": &new_deployment_hash,
-                        "last_config_hash": &config_hash,
-                    }
+                    "$set": update
                 },
             )
             .await;
 
         let resp = HeartbeatResponse {
             up_to_date: false,
-            config: Some(self.config.clone()),
+            config: Some(target_config),
             instruction_hash: build_instruction_hash(&new_deployment_hash, &config_hash),
             target_revision,
+            revision_delta,
+            protocol_version: Some(PROTOCOL_VERSION),
+            upgrade_required: None,
+            report_ack,
         };
         Ok(resp)
     }
 
-    pub async fn get_status(&self, db: &Arc<Mongo>) -> ServerResult<DeviceStatus> {
+    /// Id of the active revision with the latest state of its runs.
+    async fn active_observations(
+        &self,
+        db: &Arc<Mongo>,
+    ) -> ServerResult<Option<(String, Vec<ObserveStatus>)>> {
         let active_revision =
             DeployRevisionDoc::get_active_device_deployment(db, self.id.clone().unwrap()).await?;
-        let observations = match active_revision {
-            Some(revision) => {
-                let observations = DeployReportDoc::get_device_observations_since(
-                    db,
-                    &revision.revision.id.unwrap(),
-                    &self.id.clone().unwrap(),
-                    // since,
-                )
-                .await?;
-                observations
-            }
-            None => vec![],
+        let Some(revision) = active_revision else {
+            return Ok(None);
         };
+        let revision_id = revision.revision.id.unwrap();
+        let mut observations = DeployReportDoc::get_device_observations_since(
+            db,
+            &revision_id,
+            &self.id.unwrap(),
+            // since,
+        )
+        .await?;
+        if let Some(digest) = &self.report_digest {
+            digest.apply(&revision_id, &mut observations);
+        }
+        Ok(Some((revision_id, observations)))
+    }
+
+    pub async fn get_status(&self, db: &Arc<Mongo>) -> ServerResult<DeviceStatus> {
+        let observations = self
+            .active_observations(db)
+            .await?
+            .map(|(_, observations)| observations)
+            .unwrap_or_default();
+        let incidents = IncidentDoc::latest_for_device(db, self.id.unwrap(), STATUS_INCIDENTS)
+            .await?
+            .into_iter()
+            .map(|incident| IncidentInfo {
+                id: incident.id.map(|id| id.to_hex()).unwrap_or_default(),
+                start_time: incident
+                    .opened_at
+                    .try_to_rfc3339_string()
+                    .unwrap_or_default(),
+                // unresolved incidents show their state instead
+                end_time: incident
+                    .resolved_at
+                    .and_then(|t| t.try_to_rfc3339_string().ok())
+                    .unwrap_or_else(|| incident.state.to_string()),
+            })
+            .collect();
         let status = DeviceStatus {
-            incidents: vec![],
+            incidents,
             observations,
+            pending_reports: self
+                .report_digest
+                .as_ref()
+                .map_or(0, |digest| digest.pending_events),
         };
         Ok(status)
     }
 
+    /// The device's row of the fleet report.
+    pub async fn report_row(
+        self,
+        db: &Arc<Mongo>,
+        role: &Role,
+        online: bool,
+        now: DateTime,
+    ) -> ServerResult<FleetReportRow> {
+        let device_id = self.id.unwrap();
+        let (active_revision, unhealthy_runs) = match self.active_observations(db).await? {
+            Some((revision_id, observations)) => {
+                let unhealthy = observations.iter().filter(|o| !o.alive || !o.healthy);
+                (Some(revision_id), unhealthy.count() as u64)
+            }
+            None => (None, 0),
+        };
+        let open_incidents = IncidentDoc::count_unresolved(db, device_id).await?;
+        let last_seen = match online {
+            true => Some(now),
+            // heartbeats that changed anything bump `updated_at`
+            false => DeviceHistoryDoc::last_disconnect(db, device_id)
+                .await?
+                .or(Some(self.updated_at)),
+        };
+        let mut device = self.to_public_device(role);
+        device.online = online;
+        Ok(FleetReportRow::new(
+            &device,
+            last_seen.and_then(|t| t.try_to_rfc3339_string().ok()),
+            active_revision,
+            open_incidents,
+            unhealthy_runs,
+        ))
+    }
+
     pub async fn add_or_update_device_access(
         &self,
         db: &Arc<Mongo>,
@@ -546,6 +1227,14 @@ impl DeviceDoc {
             config: self.config.clone(),
             system_info: self.system_info.clone(),
             role: role.clone(),
+            last_failed_upgrade: self.last_failed_upgrade.clone(),
+            info: self.info.clone(),
+            last_location: self.last_location.clone(),
+            battery: self.last_battery.clone(),
+            alert_rules: self.alert_rules.clone(),
+            time: self.last_time.clone(),
+            maintenance: self.maintenance.clone().filter(|m| m.enabled),
+            require_approval: self.require_approval,
         }
     }
 }
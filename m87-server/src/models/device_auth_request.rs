@@ -33,7 +33,13 @@ pub struct DeviceAuthRequestDoc {
 }
 
 impl DeviceAuthRequestDoc {
-    pub async fn create(db: &Arc<Mongo>, body: DeviceAuthRequestBody) -> ServerResult<String> {
+    /// Store the request, `approved` already when it came with a valid
+    /// enrollment token.
+    pub async fn create(
+        db: &Arc<Mongo>,
+        body: DeviceAuthRequestBody,
+        approved: bool,
+    ) -> ServerResult<String> {
         let request_uuid = uuid::Uuid::new_v4().to_string();
         let request = DeviceAuthRequestDoc {
             id: None,
@@ -42,7 +48,7 @@ impl DeviceAuthRequestDoc {
             device_info: body.device_info,
             device_id: body.device_id.to_string(),
             owner_scope: body.owner_scope.to_string(),
-            approved: false,
+            approved,
         };
         let _ = db
             .device_auth_requests()
@@ -0,0 +1,107 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::device::TimelineEventKind;
+use mongodb::{
+    bson::{DateTime, Document, doc, oid::ObjectId},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::AppConfig,
+    db::Mongo,
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+/// Connectivity history of a device: tunnel ups and downs and reboots, which
+/// no other collection keeps. Part of the device events timeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceHistoryDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub timestamp: DateTime,
+    pub kind: TimelineEventKind,
+    pub summary: String,
+    #[serde(default)]
+    pub expires_at: Option<DateTime>,
+}
+
+impl DeviceHistoryDoc {
+    pub async fn add(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        device_id: ObjectId,
+        kind: TimelineEventKind,
+        summary: String,
+    ) -> ServerResult<()> {
+        let expires_at = Some(DateTime::from_system_time(
+            DateTime::now().to_system_time()
+                + Duration::from_hours((config.audit_retention_days * 24) as u64),
+        ));
+        let doc = Self {
+            id: None,
+            device_id,
+            timestamp: DateTime::now(),
+            kind,
+            summary,
+            expires_at,
+        };
+        db.device_history()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert device history"))?;
+        Ok(())
+    }
+
+    /// When the device's control tunnel last went down, while still recorded.
+    pub async fn last_disconnect(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+    ) -> ServerResult<Option<DateTime>> {
+        // kinds are stored in snake_case
+        let last = db
+            .device_history()
+            .find_one(doc! { "device_id": device_id, "kind": "disconnected" })
+            .sort(doc! { "timestamp": -1 })
+            .await?;
+        Ok(last.map(|doc| doc.timestamp))
+    }
+
+    /// Newest first, limited to `pagination.since`/`until` when given.
+    pub async fn list_for_device(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<DeviceHistoryDoc>> {
+        let mut filter = doc! { "device_id": device_id };
+        let mut range = Document::new();
+        if let Some(since) = pagination.since {
+            range.insert("$gte", since);
+        }
+        if let Some(until) = pagination.until {
+            range.insert("$lte", until);
+        }
+        if !range.is_empty() {
+            filter.insert("timestamp", range);
+        }
+
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "timestamp": -1 })
+            .build();
+
+        let cursor = db
+            .device_history()
+            .find(filter)
+            .with_options(options)
+            .await?;
+        cursor
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+}
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use m87_shared::inventory::{DeviceVulnerabilities, PackageInventory, Vulnerability};
+use mongodb::{
+    bson::{DateTime, doc, oid::ObjectId},
+    options::{FindOptions, UpdateOptions},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::Mongo,
+    models::device::DeviceDoc,
+    response::{ServerError, ServerResult},
+};
+
+/// Latest package inventory of a device and the advisories matching it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceInventoryDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub inventory: PackageInventory,
+    pub received_at: DateTime,
+    /// Unset until the advisory scanner matched this inventory
+    #[serde(default)]
+    pub scanned_at: Option<DateTime>,
+    #[serde(default)]
+    pub scan_error: Option<String>,
+    #[serde(default)]
+    pub vulnerabilities: Vec<Vulnerability>,
+}
+
+impl DeviceInventoryDoc {
+    /// Replace the device's inventory and queue it for a scan.
+    pub async fn store(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        inventory: &PackageInventory,
+    ) -> ServerResult<()> {
+        let inventory = mongodb::bson::to_bson(inventory)
+            .map_err(|_| ServerError::internal_error("Failed to encode inventory"))?;
+        db.device_inventories()
+            .update_one(
+                doc! { "device_id": device_id },
+                doc! {
+                    "$set": {
+                        "inventory": inventory,
+                        "received_at": DateTime::now(),
+                        "scanned_at": null,
+                    },
+                    "$setOnInsert": { "vulnerabilities": [] },
+                },
+            )
+            .with_options(UpdateOptions::builder().upsert(true).build())
+            .await?;
+        Ok(())
+    }
+
+    pub async fn for_device(db: &Arc<Mongo>, device_id: ObjectId) -> ServerResult<Option<Self>> {
+        Ok(db
+            .device_inventories()
+            .find_one(doc! { "device_id": device_id })
+            .await?)
+    }
+
+    /// Scan results of the devices whose scopes include `scope`; devices that
+    /// have not reported an inventory are listed without a scan.
+    pub async fn for_scope(
+        db: &Arc<Mongo>,
+        scope: &str,
+    ) -> ServerResult<Vec<DeviceVulnerabilities>> {
+        let devices: Vec<DeviceDoc> = db
+            .devices()
+            .find(doc! { "allowed_scopes": scope })
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?;
+        let ids: Vec<ObjectId> = devices.iter().filter_map(|d| d.id).collect();
+        let inventories: Vec<Self> = db
+            .device_inventories()
+            .find(doc! { "device_id": { "$in": ids } })
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?;
+
+        Ok(devices
+            .iter()
+            .map(
+                |device| match inventories.iter().find(|i| Some(i.device_id) == device.id) {
+                    Some(inventory) => inventory.to_device_vulnerabilities(device),
+                    None => DeviceVulnerabilities {
+                        device_id: device.id.map(|id| id.to_hex()).unwrap_or_default(),
+                        device_name: device.name.clone(),
+                        ..Default::default()
+                    },
+                },
+            )
+            .collect())
+    }
+
+    /// Inventories never scanned or last scanned before `scanned_before`,
+    /// unscanned first.
+    pub async fn due_for_scan(
+        db: &Arc<Mongo>,
+        scanned_before: DateTime,
+        limit: i64,
+    ) -> ServerResult<Vec<Self>> {
+        let options = FindOptions::builder()
+            .sort(doc! { "scanned_at": 1 })
+            .limit(Some(limit))
+            .build();
+        let cursor = db
+            .device_inventories()
+            .find(doc! {
+                "$or": [
+                    { "scanned_at": null },
+                    { "scanned_at": { "$lt": scanned_before } },
+                ]
+            })
+            .with_options(options)
+            .await?;
+        cursor
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    /// Record the outcome of a scan. A failed scan keeps the previous result
+    /// and is retried; a newer inventory that arrived meanwhile stays queued.
+    pub async fn set_scan_result(
+        &self,
+        db: &Arc<Mongo>,
+        result: Result<Vec<Vulnerability>, String>,
+    ) -> ServerResult<()> {
+        let update = match result {
+            Ok(vulnerabilities) => doc! {
+                "scanned_at": DateTime::now(),
+                "scan_error": null,
+                "vulnerabilities": mongodb::bson::to_bson(&vulnerabilities)
+                    .map_err(|_| ServerError::internal_error("Failed to encode vulnerabilities"))?,
+            },
+            Err(error) => doc! { "scan_error": error },
+        };
+        db.device_inventories()
+            .update_one(
+                doc! { "_id": self.id.unwrap(), "received_at": self.received_at },
+                doc! { "$set": update },
+            )
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_for_device(db: &Arc<Mongo>, device_id: ObjectId) -> ServerResult<()> {
+        db.device_inventories()
+            .delete_many(doc! { "device_id": device_id })
+            .await?;
+        Ok(())
+    }
+
+    pub fn to_device_vulnerabilities(&self, device: &DeviceDoc) -> DeviceVulnerabilities {
+        DeviceVulnerabilities {
+            device_id: self.device_id.to_hex(),
+            device_name: device.name.clone(),
+            inventory_collected_at: self.inventory.collected_at,
+            scanned_at: self.scanned_at.map(|t| t.timestamp_millis() as u64),
+            scan_error: self.scan_error.clone(),
+            ecosystem: self.inventory.osv_ecosystem(),
+            kernel: self.inventory.kernel.clone(),
+            package_count: self.inventory.packages.len(),
+            vulnerabilities: self.vulnerabilities.clone(),
+        }
+    }
+}
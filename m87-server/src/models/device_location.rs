@@ -0,0 +1,90 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::metrics::Location;
+use mongodb::{
+    bson::{DateTime, Document, doc, oid::ObjectId},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::AppConfig,
+    db::Mongo,
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+/// One reported position of a device, kept for the location history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceLocationDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub location: Location,
+    pub recorded_at: DateTime,
+    #[serde(default)]
+    pub expires_at: Option<DateTime>,
+}
+
+impl DeviceLocationDoc {
+    pub async fn add(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        device_id: ObjectId,
+        location: Location,
+    ) -> ServerResult<()> {
+        let expires_at = Some(DateTime::from_system_time(
+            DateTime::now().to_system_time()
+                + Duration::from_hours((config.location_retention_days * 24) as u64),
+        ));
+        let doc = Self {
+            id: None,
+            device_id,
+            location,
+            recorded_at: DateTime::now(),
+            expires_at,
+        };
+        db.device_locations()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert device location"))?;
+        Ok(())
+    }
+
+    /// Newest first, limited to `pagination.since`/`until` when given.
+    pub async fn list_for_device(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Location>> {
+        let mut filter = doc! { "device_id": device_id };
+        let mut range = Document::new();
+        if let Some(since) = pagination.since {
+            range.insert("$gte", since);
+        }
+        if let Some(until) = pagination.until {
+            range.insert("$lte", until);
+        }
+        if !range.is_empty() {
+            filter.insert("recorded_at", range);
+        }
+
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "recorded_at": -1 })
+            .build();
+
+        let cursor = db
+            .device_locations()
+            .find(filter)
+            .with_options(options)
+            .await?;
+        let results: Vec<DeviceLocationDoc> = cursor
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?;
+        Ok(results.into_iter().map(|d| d.location).collect())
+    }
+}
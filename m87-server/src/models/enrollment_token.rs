@@ -0,0 +1,93 @@
+use std::{sync::Arc, time::Duration};
+
+use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use rand::{Rng, distributions::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    db::Mongo,
+    response::{ServerError, ServerResult},
+};
+
+/// Token of an install command: devices registering with it under its
+/// owner are approved right away, up to `max_uses` of them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnrollmentTokenDoc {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// SHA-256 of the token; the token itself is only shown once
+    pub token_hash: String,
+    /// `org:<id>` or `user:<email>`
+    pub owner_scope: String,
+    pub created_by: String,
+    pub created_at: DateTime,
+    /// Removed by a TTL index
+    pub expires_at: DateTime,
+    pub max_uses: u32,
+    #[serde(default)]
+    pub uses: u32,
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl EnrollmentTokenDoc {
+    /// Store a new token and return it with the plaintext token.
+    pub async fn create(
+        db: &Arc<Mongo>,
+        owner_scope: String,
+        created_by: &str,
+        ttl_secs: u64,
+        max_uses: u32,
+    ) -> ServerResult<(Self, String)> {
+        let token = format!(
+            "m87e_{}",
+            rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(40)
+                .map(char::from)
+                .collect::<String>()
+        );
+        let now = DateTime::now();
+        let doc = Self {
+            id: ObjectId::new(),
+            token_hash: hash_token(&token),
+            owner_scope,
+            created_by: created_by.to_string(),
+            created_at: now,
+            expires_at: DateTime::from_system_time(
+                now.to_system_time() + Duration::from_secs(ttl_secs),
+            ),
+            max_uses,
+            uses: 0,
+        };
+        db.enrollment_tokens().insert_one(&doc).await?;
+        Ok((doc, token))
+    }
+
+    /// Count one use of `token` for a device registering under
+    /// `owner_scope`. Fails when the token is unknown, expired, used up or
+    /// belongs to another owner.
+    pub async fn redeem(db: &Arc<Mongo>, token: &str, owner_scope: &str) -> ServerResult<()> {
+        let redeemed = db
+            .enrollment_tokens()
+            .find_one_and_update(
+                doc! {
+                    "token_hash": hash_token(token),
+                    "owner_scope": owner_scope,
+                    "expires_at": { "$gt": DateTime::now() },
+                    "$expr": { "$lt": ["$uses", "$max_uses"] },
+                },
+                doc! { "$inc": { "uses": 1 } },
+            )
+            .await?;
+        match redeemed {
+            Some(_) => Ok(()),
+            None => Err(ServerError::unauthorized(
+                "Invalid, expired or used up enrollment token",
+            )),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::{sync::Arc, time::Duration};
+
+use mongodb::{
+    bson::{DateTime, doc, oid::ObjectId},
+    error::{ErrorKind, WriteFailure},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::AppConfig,
+    db::Mongo,
+    response::{ServerError, ServerResult},
+};
+
+const DUPLICATE_KEY: i32 = 11000;
+
+/// An idempotency key a device sent with a heartbeat. The unique index on
+/// `device_id` + `key` makes sure each key is ingested only once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyKeyDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub key: String,
+    /// The report stored for this key, once there is one
+    #[serde(default)]
+    pub report_id: Option<ObjectId>,
+    pub created_at: DateTime,
+    /// Kept as long as the reports, longer than any device retries
+    pub expires_at: DateTime,
+}
+
+/// Outcome of [`IdempotencyKeyDoc::claim`].
+pub enum Claim {
+    New,
+    /// Seen before; the report stored by that delivery, if it got that far
+    Seen(Option<ObjectId>),
+}
+
+impl IdempotencyKeyDoc {
+    /// Record `key` for `device_id`, or look up what an earlier delivery stored.
+    pub async fn claim(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        device_id: ObjectId,
+        key: &str,
+    ) -> ServerResult<Claim> {
+        let doc = Self {
+            id: None,
+            device_id,
+            key: key.to_string(),
+            report_id: None,
+            created_at: DateTime::now(),
+            expires_at: DateTime::from_system_time(
+                DateTime::now().to_system_time()
+                    + Duration::from_hours(24 * config.report_retention_days as u64),
+            ),
+        };
+        match db.idempotency_keys().insert_one(&doc).await {
+            Ok(_) => Ok(Claim::New),
+            Err(e) if is_duplicate_key(&e) => {
+                let seen = db
+                    .idempotency_keys()
+                    .find_one(doc! { "device_id": device_id, "key": key })
+                    .await
+                    .map_err(|e| {
+                        ServerError::internal_error(&format!(
+                            "Failed to read idempotency key: {:?}",
+                            e
+                        ))
+                    })?;
+                Ok(Claim::Seen(seen.and_then(|d| d.report_id)))
+            }
+            Err(e) => Err(ServerError::internal_error(&format!(
+                "Failed to record idempotency key: {:?}",
+                e
+            ))),
+        }
+    }
+
+    /// Remember the report stored for `key`, so redeliveries are acked with it.
+    pub async fn set_report(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        key: &str,
+        report_id: ObjectId,
+    ) -> ServerResult<()> {
+        db.idempotency_keys()
+            .update_one(
+                doc! { "device_id": device_id, "key": key },
+                doc! { "$set": { "report_id": report_id } },
+            )
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to update idempotency key"))?;
+        Ok(())
+    }
+}
+
+pub(crate) fn is_duplicate_key(e: &mongodb::error::Error) -> bool {
+    matches!(
+        e.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(we)) if we.code == DUPLICATE_KEY
+    )
+}
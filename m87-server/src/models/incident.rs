@@ -0,0 +1,362 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::incident::{
+    Incident, IncidentEntry, IncidentEntryKind, IncidentState, MAX_INCIDENT_ENTRIES,
+};
+use mongodb::{
+    bson::{DateTime, Document, doc, oid::ObjectId},
+    options::{FindOptions, ReturnDocument},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::access_control::AccessControlled,
+    config::AppConfig,
+    db::Mongo,
+    models::device::DeviceDoc,
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncidentEntryDoc {
+    pub timestamp: DateTime,
+    pub kind: IncidentEntryKind,
+    pub summary: String,
+    #[serde(default)]
+    pub actor: Option<String>,
+}
+
+impl IncidentEntryDoc {
+    pub fn now(kind: IncidentEntryKind, summary: &str, actor: Option<String>) -> Self {
+        Self {
+            timestamp: DateTime::now(),
+            kind,
+            summary: summary.to_string(),
+            actor,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncidentDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub device_name: String,
+    pub rule: String,
+    pub title: String,
+    pub state: IncidentState,
+    pub opened_at: DateTime,
+    pub updated_at: DateTime,
+    #[serde(default)]
+    pub acknowledged_by: Option<String>,
+    #[serde(default)]
+    pub acknowledged_at: Option<DateTime>,
+    #[serde(default)]
+    pub resolved_by: Option<String>,
+    #[serde(default)]
+    pub resolved_at: Option<DateTime>,
+    #[serde(default)]
+    pub entries: Vec<IncidentEntryDoc>,
+    /// Removed by a TTL index once resolved and past the audit retention
+    #[serde(default)]
+    pub purge_at: Option<DateTime>,
+    // copied from the device, so listings follow device access
+    pub owner_scope: String,
+    pub allowed_scopes: Vec<String>,
+}
+
+impl AccessControlled for IncidentDoc {
+    fn owner_scope_field() -> &'static str {
+        "owner_scope"
+    }
+    fn allowed_scopes_field() -> Option<&'static str> {
+        Some("allowed_scopes")
+    }
+    fn owner_scope(&self) -> &str {
+        &self.owner_scope
+    }
+    fn allowed_scopes(&self) -> Option<Vec<String>> {
+        Some(self.allowed_scopes.clone())
+    }
+}
+
+fn unresolved(device_id: ObjectId) -> Document {
+    doc! {
+        "device_id": device_id,
+        "state": { "$ne": IncidentState::Resolved.to_string() },
+    }
+}
+
+impl IncidentDoc {
+    /// Attach the alert to the device's unresolved incident, or open one for
+    /// it. Returns the incident if it was opened.
+    pub async fn on_alert(
+        db: &Arc<Mongo>,
+        device: &DeviceDoc,
+        rule: &str,
+        message: &str,
+    ) -> ServerResult<Option<Self>> {
+        let device_id = device
+            .id
+            .ok_or_else(|| ServerError::internal_error("device without id"))?;
+        let entry = IncidentEntryDoc::now(IncidentEntryKind::Alert, message, None);
+        if Self::attach(db, device_id, entry.clone()).await? {
+            return Ok(None);
+        }
+
+        let now = DateTime::now();
+        let mut doc = Self {
+            id: None,
+            device_id,
+            device_name: device.name.clone(),
+            rule: rule.to_string(),
+            title: message.to_string(),
+            state: IncidentState::Open,
+            opened_at: now,
+            updated_at: now,
+            acknowledged_by: None,
+            acknowledged_at: None,
+            resolved_by: None,
+            resolved_at: None,
+            entries: vec![entry],
+            purge_at: None,
+            owner_scope: device.owner_scope.clone(),
+            allowed_scopes: device.allowed_scopes.clone(),
+        };
+        let res = db
+            .incidents()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert incident"))?;
+        doc.id = res.inserted_id.as_object_id();
+        Ok(Some(doc))
+    }
+
+    /// Add `entry` to the device's unresolved incident. Returns whether there
+    /// was one.
+    pub async fn attach(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        entry: IncidentEntryDoc,
+    ) -> ServerResult<bool> {
+        let entry = mongodb::bson::to_bson(&entry)
+            .map_err(|_| ServerError::internal_error("Failed to encode incident entry"))?;
+        let res = db
+            .incidents()
+            .update_one(
+                unresolved(device_id),
+                doc! {
+                    "$push": { "entries": {
+                        "$each": [entry],
+                        "$slice": -(MAX_INCIDENT_ENTRIES as i64),
+                    } },
+                    "$set": { "updated_at": DateTime::now() },
+                },
+            )
+            .await?;
+        Ok(res.matched_count > 0)
+    }
+
+    /// Acknowledge or resolve the incident. Fails if the incident cannot
+    /// move to `next` or changed meanwhile.
+    pub async fn transition(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        current: &Self,
+        next: IncidentState,
+        by: &str,
+    ) -> ServerResult<Self> {
+        if !current.state.can_become(next) {
+            return Err(ServerError::bad_request(&format!(
+                "the incident is {} and cannot become {}",
+                current.state, next
+            )));
+        }
+        let now = DateTime::now();
+        let mut set = doc! {
+            "state": next.to_string(),
+            "updated_at": now,
+        };
+        match next {
+            IncidentState::Acknowledged => {
+                set.insert("acknowledged_by", by);
+                set.insert("acknowledged_at", now);
+            }
+            IncidentState::Resolved => {
+                set.insert("resolved_by", by);
+                set.insert("resolved_at", now);
+                set.insert(
+                    "purge_at",
+                    DateTime::from_system_time(
+                        now.to_system_time()
+                            + Duration::from_hours((config.audit_retention_days * 24) as u64),
+                    ),
+                );
+            }
+            IncidentState::Open => {}
+        }
+        db.incidents()
+            .find_one_and_update(
+                doc! { "_id": current.id, "state": current.state.to_string() },
+                doc! { "$set": set },
+            )
+            .return_document(ReturnDocument::After)
+            .await?
+            .ok_or_else(|| ServerError::bad_request("the incident changed meanwhile"))
+    }
+
+    /// Newest first, limited to `filter` (e.g. a state).
+    pub async fn list(
+        db: &Arc<Mongo>,
+        scopes: &Vec<String>,
+        mut filter: Document,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Self>> {
+        filter.extend(Self::access_filter(scopes));
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "opened_at": -1 })
+            .build();
+        db.incidents()
+            .find(filter)
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    /// Incidents of a device that are not resolved yet.
+    pub async fn count_unresolved(db: &Arc<Mongo>, device_id: ObjectId) -> ServerResult<u64> {
+        Ok(db
+            .incidents()
+            .count_documents(unresolved(device_id))
+            .await?)
+    }
+
+    /// The newest incidents of a device, for its status.
+    pub async fn latest_for_device(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        limit: i64,
+    ) -> ServerResult<Vec<Self>> {
+        let options = FindOptions::builder()
+            .limit(Some(limit))
+            .sort(doc! { "opened_at": -1 })
+            .build();
+        db.incidents()
+            .find(doc! { "device_id": device_id })
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    pub fn to_public(&self) -> Incident {
+        let rfc3339 = |t: &DateTime| t.try_to_rfc3339_string().unwrap_or_default();
+        Incident {
+            id: self.id.map(|id| id.to_hex()).unwrap_or_default(),
+            device_id: self.device_id.to_hex(),
+            device_name: self.device_name.clone(),
+            rule: self.rule.clone(),
+            title: self.title.clone(),
+            state: self.state,
+            opened_at: rfc3339(&self.opened_at),
+            updated_at: rfc3339(&self.updated_at),
+            acknowledged_by: self.acknowledged_by.clone(),
+            resolved_by: self.resolved_by.clone(),
+            resolved_at: self.resolved_at.as_ref().map(rfc3339),
+            entries: self
+                .entries
+                .iter()
+                .map(|e| IncidentEntry {
+                    timestamp: rfc3339(&e.timestamp),
+                    kind: e.kind,
+                    summary: e.summary.clone(),
+                    actor: e.actor.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Incident webhook of one org. The secret is kept to sign requests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncidentWebhookDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub org_id: String,
+    pub url: String,
+    #[serde(default)]
+    pub secret: Option<String>,
+    pub updated_by: String,
+    pub updated_at: DateTime,
+}
+
+impl IncidentWebhookDoc {
+    /// Replace the webhook of `org_id`, or remove it without a `url`.
+    pub async fn set(
+        db: &Arc<Mongo>,
+        org_id: &str,
+        url: Option<String>,
+        secret: Option<String>,
+        updated_by: &str,
+    ) -> ServerResult<Option<Self>> {
+        let Some(url) = url.filter(|u| !u.trim().is_empty()) else {
+            db.incident_webhooks()
+                .delete_one(doc! { "org_id": org_id })
+                .await?;
+            return Ok(None);
+        };
+        let url = url.trim().to_string();
+        if !url.starts_with("https://") && !url.starts_with("http://") {
+            return Err(ServerError::bad_request(
+                "the webhook url must start with https:// or http://",
+            ));
+        }
+        let doc = Self {
+            id: None,
+            org_id: org_id.to_string(),
+            url,
+            secret: secret.filter(|s| !s.is_empty()),
+            updated_by: updated_by.to_string(),
+            updated_at: DateTime::now(),
+        };
+        db.incident_webhooks()
+            .replace_one(doc! { "org_id": org_id }, &doc)
+            .upsert(true)
+            .await?;
+        Ok(Some(doc))
+    }
+
+    pub async fn get(db: &Arc<Mongo>, org_id: &str) -> ServerResult<Option<Self>> {
+        Ok(db
+            .incident_webhooks()
+            .find_one(doc! { "org_id": org_id })
+            .await?)
+    }
+
+    /// Webhooks of the orgs that own or share the incident's device.
+    pub async fn for_incident(db: &Arc<Mongo>, incident: &IncidentDoc) -> ServerResult<Vec<Self>> {
+        let mut org_ids: Vec<&str> = std::iter::once(&incident.owner_scope)
+            .chain(incident.allowed_scopes.iter())
+            .filter_map(|scope| scope.strip_prefix("org:"))
+            .collect();
+        org_ids.sort();
+        org_ids.dedup();
+        if org_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(db
+            .incident_webhooks()
+            .find(doc! { "org_id": { "$in": org_ids } })
+            .await?
+            .try_collect()
+            .await?)
+    }
+}
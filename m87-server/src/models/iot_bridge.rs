@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::TryStreamExt;
+use m87_shared::iot_bridge::{DEFAULT_TELEMETRY_INTERVAL_SECS, IotBridgeTarget, OrgIotBridge};
+use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::Mongo,
+    models::{device::DeviceDoc, org::org_scope},
+    response::{ServerError, ServerResult},
+};
+
+/// Shortest telemetry interval an org can set
+const MIN_TELEMETRY_INTERVAL_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IotBridgeDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub org_id: String,
+    // kept in plain text: the server signs and logs in with them
+    pub target: IotBridgeTarget,
+    pub telemetry_interval_secs: u64,
+    pub updated_by: String,
+    pub updated_at: DateTime,
+    #[serde(default)]
+    pub last_published_at: Option<DateTime>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl IotBridgeDoc {
+    /// Replace the bridge of `org_id`, or remove it without a `target`.
+    pub async fn set(
+        db: &Arc<Mongo>,
+        org_id: &str,
+        target: Option<IotBridgeTarget>,
+        telemetry_interval_secs: Option<u64>,
+        updated_by: &str,
+    ) -> ServerResult<Option<Self>> {
+        let Some(target) = target else {
+            db.iot_bridges()
+                .delete_one(doc! { "org_id": org_id })
+                .await?;
+            return Ok(None);
+        };
+        validate(&target)?;
+        let telemetry_interval_secs =
+            telemetry_interval_secs.unwrap_or(DEFAULT_TELEMETRY_INTERVAL_SECS);
+        if telemetry_interval_secs < MIN_TELEMETRY_INTERVAL_SECS {
+            return Err(ServerError::bad_request(&format!(
+                "the telemetry interval must be at least {}s",
+                MIN_TELEMETRY_INTERVAL_SECS
+            )));
+        }
+        let doc = Self {
+            id: None,
+            org_id: org_id.to_string(),
+            target,
+            telemetry_interval_secs,
+            updated_by: updated_by.to_string(),
+            updated_at: DateTime::now(),
+            last_published_at: None,
+            last_error: None,
+        };
+        db.iot_bridges()
+            .replace_one(doc! { "org_id": org_id }, &doc)
+            .upsert(true)
+            .await?;
+        Ok(Some(doc))
+    }
+
+    pub async fn get(db: &Arc<Mongo>, org_id: &str) -> ServerResult<Option<Self>> {
+        Ok(db.iot_bridges().find_one(doc! { "org_id": org_id }).await?)
+    }
+
+    pub async fn all(db: &Arc<Mongo>) -> ServerResult<Vec<Self>> {
+        Ok(db.iot_bridges().find(doc! {}).await?.try_collect().await?)
+    }
+
+    /// Whether the org of the bridge owns or shares the device.
+    pub fn covers(&self, device: &DeviceDoc) -> bool {
+        let scope = org_scope(&self.org_id);
+        device.owner_scope == scope || device.allowed_scopes.contains(&scope)
+    }
+
+    /// Note the outcome of a publish, for `m87 org iot-bridge show`.
+    pub async fn record(db: &Arc<Mongo>, org_id: &str, error: Option<String>) -> ServerResult<()> {
+        let update = match error {
+            Some(error) => doc! { "$set": { "last_error": error } },
+            None => doc! {
+                "$set": { "last_published_at": DateTime::now() },
+                "$unset": { "last_error": "" },
+            },
+        };
+        db.iot_bridges()
+            .update_one(doc! { "org_id": org_id }, update)
+            .await?;
+        Ok(())
+    }
+
+    pub fn to_public(&self) -> OrgIotBridge {
+        OrgIotBridge {
+            org_id: self.org_id.clone(),
+            platform: Some(self.target.platform()),
+            endpoint: Some(self.target.endpoint().to_string()),
+            telemetry_interval_secs: self.telemetry_interval_secs,
+            last_published_at: self
+                .last_published_at
+                .and_then(|t| t.try_to_rfc3339_string().ok()),
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+fn validate(target: &IotBridgeTarget) -> ServerResult<()> {
+    let endpoint = target.endpoint().trim();
+    let missing = |what: &str| ServerError::bad_request(&format!("the bridge needs {}", what));
+    if endpoint.is_empty() {
+        return Err(missing("an endpoint"));
+    }
+    match target {
+        IotBridgeTarget::AwsIot {
+            endpoint,
+            access_key_id,
+            secret_access_key,
+            ..
+        } => {
+            if endpoint.contains("://") || endpoint.contains('/') {
+                return Err(ServerError::bad_request(
+                    "the AWS IoT endpoint is a host name, e.g. abc123-ats.iot.eu-central-1.amazonaws.com",
+                ));
+            }
+            if access_key_id.is_empty() || secret_access_key.is_empty() {
+                return Err(missing("an access key id and secret"));
+            }
+        }
+        IotBridgeTarget::AzureIotHub {
+            host_name,
+            policy_key,
+            ..
+        } => {
+            if host_name.contains("://") || host_name.contains('/') {
+                return Err(ServerError::bad_request(
+                    "the IoT Hub host name has no scheme, e.g. my-hub.azure-devices.net",
+                ));
+            }
+            if policy_key.is_empty() {
+                return Err(missing("a policy key"));
+            }
+            if BASE64.decode(policy_key).is_err() {
+                return Err(ServerError::bad_request(
+                    "the policy key is not base64; copy the primary key of the shared access policy",
+                ));
+            }
+        }
+        IotBridgeTarget::Thingsboard {
+            url,
+            username,
+            password,
+        } => {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(ServerError::bad_request(
+                    "the ThingsBoard url must start with https:// or http://",
+                ));
+            }
+            if username.is_empty() || password.is_empty() {
+                return Err(missing("a username and password"));
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,8 +1,25 @@
+pub mod access_grant;
 pub mod api_key;
+pub mod approval;
 pub mod audit_logs;
+pub mod deploy_file;
 pub mod deploy_spec;
 pub mod device;
 pub mod device_auth_request;
+pub mod device_history;
+pub mod device_inventory;
+pub mod device_location;
+pub mod enrollment_token;
+pub mod idempotency;
+pub mod incident;
+pub mod iot_bridge;
 pub mod org;
+pub mod org_limits;
+pub mod org_redaction;
+pub mod org_usage;
+pub mod patching;
+pub mod probe;
 pub mod roles;
+pub mod rollout;
 pub mod user;
+pub mod webhook;
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use m87_shared::org::{OrgLimits, OrgLimitsStatus, OrgUsage};
+use mongodb::Collection;
+use mongodb::bson::{Bson, DateTime, Document, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::AppConfig,
+    db::Mongo,
+    models::{device::DeviceDoc, org::org_scope},
+    relay::relay_state::{OrgSessionGuard, RelayState},
+    response::{ServerError, ServerResult},
+};
+
+/// Limits an instance admin set for one org, overriding the instance defaults
+/// field by field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgLimitsDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub org_id: String,
+    pub limits: OrgLimits,
+    pub updated_by: String,
+    pub updated_at: DateTime,
+}
+
+impl OrgLimitsDoc {
+    /// Replace the override of `org_id`.
+    pub async fn set(
+        db: &Arc<Mongo>,
+        org_id: &str,
+        limits: OrgLimits,
+        updated_by: &str,
+    ) -> ServerResult<()> {
+        let doc = Self {
+            id: None,
+            org_id: org_id.to_string(),
+            limits,
+            updated_by: updated_by.to_string(),
+            updated_at: DateTime::now(),
+        };
+        db.org_limits()
+            .replace_one(doc! { "org_id": org_id }, &doc)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    /// Limits in force for `org_id`; `Some(0)` in an override lifts a default.
+    pub async fn effective(
+        db: &Arc<Mongo>,
+        config: &AppConfig,
+        org_id: &str,
+    ) -> ServerResult<OrgLimits> {
+        let set = db
+            .org_limits()
+            .find_one(doc! { "org_id": org_id })
+            .await?
+            .map(|d| d.limits)
+            .unwrap_or_default();
+        let defaults = &config.default_org_limits;
+        let pick = |set: Option<u64>, default: Option<u64>| set.or(default).filter(|&v| v > 0);
+        Ok(OrgLimits {
+            max_devices: pick(set.max_devices, defaults.max_devices),
+            max_revisions: pick(set.max_revisions, defaults.max_revisions),
+            max_report_storage_bytes: pick(
+                set.max_report_storage_bytes,
+                defaults.max_report_storage_bytes,
+            ),
+            max_concurrent_tunnels: pick(
+                set.max_concurrent_tunnels,
+                defaults.max_concurrent_tunnels,
+            ),
+        })
+    }
+}
+
+/// Orgs a device counts against: the one owning it and those it was added to.
+pub fn device_org_ids(device: &DeviceDoc) -> Vec<String> {
+    let mut org_ids: Vec<String> = std::iter::once(&device.owner_scope)
+        .chain(device.allowed_scopes.iter())
+        .filter_map(|scope| scope.strip_prefix("org:"))
+        .map(str::to_string)
+        .collect();
+    org_ids.sort();
+    org_ids.dedup();
+    org_ids
+}
+
+fn org_devices_filter(org_id: &str) -> Document {
+    let scope = org_scope(org_id);
+    doc! { "$or": [ { "owner_scope": &scope }, { "allowed_scopes": &scope } ] }
+}
+
+async fn org_device_ids(db: &Arc<Mongo>, org_id: &str) -> ServerResult<Vec<ObjectId>> {
+    let devices: Vec<DeviceDoc> = db
+        .devices()
+        .find(org_devices_filter(org_id))
+        .await?
+        .try_collect()
+        .await?;
+    Ok(devices.into_iter().filter_map(|d| d.id).collect())
+}
+
+async fn revision_count(db: &Arc<Mongo>, device_ids: &[ObjectId]) -> ServerResult<u64> {
+    Ok(db
+        .deploy_revisions()
+        .count_documents(doc! { "device_id": { "$in": device_ids } })
+        .await?)
+}
+
+/// Size of the documents of `device_ids` in `collection`, as stored.
+async fn stored_bytes<T: Send + Sync>(
+    collection: &Collection<T>,
+    device_ids: &[ObjectId],
+) -> ServerResult<u64> {
+    summed_bytes(
+        collection,
+        vec![
+            doc! { "$match": { "device_id": { "$in": device_ids } } },
+            doc! { "$group": { "_id": null, "bytes": { "$sum": { "$bsonSize": "$$ROOT" } } } },
+        ],
+    )
+    .await
+}
+
+/// Size of the stored files the revisions of `device_ids` refer to. Files
+/// are shared between revisions, so each one counts once however many
+/// revisions and devices of the org use it.
+async fn file_storage_bytes(db: &Arc<Mongo>, device_ids: &[ObjectId]) -> ServerResult<u64> {
+    summed_bytes(
+        &db.deploy_revisions(),
+        vec![
+            doc! { "$match": { "device_id": { "$in": device_ids } } },
+            doc! { "$unwind": "$file_hashes" },
+            doc! { "$group": { "_id": "$file_hashes" } },
+            doc! { "$lookup": {
+                "from": "deploy_files",
+                "localField": "_id",
+                "foreignField": "_id",
+                "as": "file",
+            } },
+            doc! { "$unwind": "$file" },
+            doc! { "$group": { "_id": null, "bytes": { "$sum": { "$bsonSize": "$file" } } } },
+        ],
+    )
+    .await
+}
+
+/// Runs `pipeline`, which ends in a single group with a `bytes` sum.
+async fn summed_bytes<T: Send + Sync>(
+    collection: &Collection<T>,
+    pipeline: Vec<Document>,
+) -> ServerResult<u64> {
+    let mut cursor = collection.aggregate(pipeline).await?;
+    let bytes = match cursor.try_next().await? {
+        Some(row) => match row.get("bytes") {
+            Some(Bson::Int32(v)) => *v as u64,
+            Some(Bson::Int64(v)) => *v as u64,
+            _ => 0,
+        },
+        None => 0,
+    };
+    Ok(bytes)
+}
+
+async fn report_storage_bytes(db: &Arc<Mongo>, device_ids: &[ObjectId]) -> ServerResult<u64> {
+    stored_bytes(&db.deploy_reports(), device_ids).await
+}
+
+/// Devices of `org_id` and what their deployment revisions, the files those
+/// refer to and their reports take up in the database.
+pub async fn storage(db: &Arc<Mongo>, org_id: &str) -> ServerResult<(u64, u64)> {
+    let device_ids = org_device_ids(db, org_id).await?;
+    let bytes = stored_bytes(&db.deploy_revisions(), &device_ids).await?
+        + file_storage_bytes(db, &device_ids).await?
+        + report_storage_bytes(db, &device_ids).await?;
+    Ok((device_ids.len() as u64, bytes))
+}
+
+/// What `org_id` currently uses of its limits.
+pub async fn usage(db: &Arc<Mongo>, relay: &RelayState, org_id: &str) -> ServerResult<OrgUsage> {
+    let device_ids = org_device_ids(db, org_id).await?;
+    Ok(OrgUsage {
+        devices: device_ids.len() as u64,
+        revisions: revision_count(db, &device_ids).await?,
+        report_storage_bytes: report_storage_bytes(db, &device_ids).await?,
+        concurrent_tunnels: relay.org_session_count(org_id),
+    })
+}
+
+pub async fn status(
+    db: &Arc<Mongo>,
+    config: &AppConfig,
+    relay: &RelayState,
+    org_id: &str,
+) -> ServerResult<OrgLimitsStatus> {
+    Ok(OrgLimitsStatus {
+        org_id: org_id.to_string(),
+        limits: OrgLimitsDoc::effective(db, config, org_id).await?,
+        usage: usage(db, relay, org_id).await?,
+    })
+}
+
+/// Refuse a device joining `org_id` once the org is at its device limit.
+pub async fn check_new_device(
+    db: &Arc<Mongo>,
+    config: &AppConfig,
+    org_id: &str,
+) -> ServerResult<()> {
+    let Some(max) = OrgLimitsDoc::effective(db, config, org_id)
+        .await?
+        .max_devices
+    else {
+        return Ok(());
+    };
+    let count = db
+        .devices()
+        .count_documents(org_devices_filter(org_id))
+        .await?;
+    if count >= max {
+        return Err(ServerError::quota_exceeded(&format!(
+            "Organization {} reached its limit of {} devices; remove a device or ask your instance admin to raise the limit",
+            org_id, max
+        )));
+    }
+    Ok(())
+}
+
+/// Refuse a new deployment revision of `device` while one of its orgs keeps as
+/// many revisions as it may.
+pub async fn check_new_revision(
+    db: &Arc<Mongo>,
+    config: &AppConfig,
+    device: &DeviceDoc,
+) -> ServerResult<()> {
+    for org_id in device_org_ids(device) {
+        let Some(max) = OrgLimitsDoc::effective(db, config, &org_id)
+            .await?
+            .max_revisions
+        else {
+            continue;
+        };
+        let count = revision_count(db, &org_device_ids(db, &org_id).await?).await?;
+        if count >= max {
+            return Err(ServerError::quota_exceeded(&format!(
+                "Organization {} keeps {} of at most {} deployment revisions; delete old revisions first",
+                org_id, count, max
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Refuse storing another deploy report of `device` while one of its orgs is
+/// out of report storage. Reports free up as they expire.
+pub async fn check_report_storage(
+    db: &Arc<Mongo>,
+    config: &AppConfig,
+    device: &DeviceDoc,
+) -> ServerResult<()> {
+    for org_id in device_org_ids(device) {
+        let Some(max) = OrgLimitsDoc::effective(db, config, &org_id)
+            .await?
+            .max_report_storage_bytes
+        else {
+            continue;
+        };
+        let used = report_storage_bytes(db, &org_device_ids(db, &org_id).await?).await?;
+        if used >= max {
+            return Err(ServerError::quota_exceeded(&format!(
+                "Organization {} uses {} of {} bytes of report storage",
+                org_id, used, max
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Count a client session to `device` against the tunnel limits of its orgs.
+pub async fn open_session(
+    db: &Arc<Mongo>,
+    config: &AppConfig,
+    relay: &RelayState,
+    device: &DeviceDoc,
+) -> ServerResult<OrgSessionGuard> {
+    let mut limits = Vec::new();
+    for org_id in device_org_ids(device) {
+        let max = OrgLimitsDoc::effective(db, config, &org_id)
+            .await?
+            .max_concurrent_tunnels;
+        limits.push((org_id, max));
+    }
+    relay.open_org_session(&limits).map_err(|(org_id, max)| {
+        ServerError::quota_exceeded(&format!(
+            "Organization {} has all {} concurrent tunnels in use",
+            org_id, max
+        ))
+    })
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use m87_shared::redact::check_pattern;
+use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::Mongo,
+    models::{device::DeviceDoc, org_limits::device_org_ids},
+    response::{ServerError, ServerResult},
+};
+
+/// Most custom patterns an org may set
+const MAX_PATTERNS: usize = 50;
+const MAX_PATTERN_LEN: usize = 500;
+
+/// Custom redaction patterns of one org, sent to its devices with the config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgRedactionDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub org_id: String,
+    pub patterns: Vec<String>,
+    pub updated_by: String,
+    pub updated_at: DateTime,
+}
+
+impl OrgRedactionDoc {
+    /// Replace the patterns of `org_id` after checking that each compiles.
+    pub async fn set(
+        db: &Arc<Mongo>,
+        org_id: &str,
+        patterns: Vec<String>,
+        updated_by: &str,
+    ) -> ServerResult<()> {
+        if patterns.len() > MAX_PATTERNS {
+            return Err(ServerError::bad_request(&format!(
+                "at most {} redaction patterns are allowed",
+                MAX_PATTERNS
+            )));
+        }
+        for pattern in &patterns {
+            if pattern.len() > MAX_PATTERN_LEN {
+                return Err(ServerError::bad_request(&format!(
+                    "redaction patterns are limited to {} characters",
+                    MAX_PATTERN_LEN
+                )));
+            }
+            check_pattern(pattern).map_err(|e| {
+                ServerError::bad_request(&format!("invalid pattern '{}': {}", pattern, e))
+            })?;
+        }
+        let doc = Self {
+            id: None,
+            org_id: org_id.to_string(),
+            patterns,
+            updated_by: updated_by.to_string(),
+            updated_at: DateTime::now(),
+        };
+        db.org_redaction()
+            .replace_one(doc! { "org_id": org_id }, &doc)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn patterns(db: &Arc<Mongo>, org_id: &str) -> ServerResult<Vec<String>> {
+        Ok(db
+            .org_redaction()
+            .find_one(doc! { "org_id": org_id })
+            .await?
+            .map(|d| d.patterns)
+            .unwrap_or_default())
+    }
+
+    /// Patterns of all orgs of `device`, sorted so the config hash is stable.
+    pub async fn for_device(db: &Arc<Mongo>, device: &DeviceDoc) -> ServerResult<Vec<String>> {
+        let org_ids = device_org_ids(device);
+        if org_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let docs: Vec<Self> = db
+            .org_redaction()
+            .find(doc! { "org_id": { "$in": org_ids } })
+            .await?
+            .try_collect()
+            .await?;
+        let mut patterns: Vec<String> = docs.into_iter().flat_map(|d| d.patterns).collect();
+        patterns.sort();
+        patterns.dedup();
+        Ok(patterns)
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use m87_shared::usage::OrgUsageRecord;
+use mongodb::bson::{DateTime, Document, doc, oid::ObjectId};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+
+use crate::{db::Mongo, response::ServerResult};
+
+/// Month usage records are kept under, `YYYY-MM` in UTC.
+pub fn current_month() -> String {
+    chrono::Utc::now().format("%Y-%m").to_string()
+}
+
+/// Usage of one org in one calendar month, added to by the usage meter.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrgUsageDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub org_id: String,
+    pub month: String,
+    #[serde(default)]
+    pub device_seconds: u64,
+    #[serde(default)]
+    pub relay_bytes: u64,
+    #[serde(default)]
+    pub peak_storage_bytes: u64,
+    #[serde(default)]
+    pub peak_devices: u64,
+    pub updated_at: DateTime,
+}
+
+impl OrgUsageDoc {
+    /// Add connected device time and relayed bytes to the org's month.
+    pub async fn add(
+        db: &Arc<Mongo>,
+        org_id: &str,
+        month: &str,
+        device_seconds: u64,
+        relay_bytes: u64,
+    ) -> ServerResult<()> {
+        db.org_usage()
+            .update_one(
+                doc! { "org_id": org_id, "month": month },
+                doc! {
+                    "$inc": {
+                        "device_seconds": device_seconds as i64,
+                        "relay_bytes": relay_bytes as i64,
+                    },
+                    "$set": { "updated_at": DateTime::now() },
+                },
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    /// Keep the highest storage and device count seen in the org's month.
+    pub async fn raise_peaks(
+        db: &Arc<Mongo>,
+        org_id: &str,
+        month: &str,
+        storage_bytes: u64,
+        devices: u64,
+    ) -> ServerResult<()> {
+        db.org_usage()
+            .update_one(
+                doc! { "org_id": org_id, "month": month },
+                doc! {
+                    "$max": {
+                        "peak_storage_bytes": storage_bytes as i64,
+                        "peak_devices": devices as i64,
+                    },
+                    "$set": { "updated_at": DateTime::now() },
+                },
+            )
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    /// Records of `org_ids` (all orgs if `None`) between the months `from`
+    /// and `to`, both inclusive, by org and month.
+    pub async fn list(
+        db: &Arc<Mongo>,
+        org_ids: Option<&[String]>,
+        from: Option<&str>,
+        to: Option<&str>,
+    ) -> ServerResult<Vec<OrgUsageDoc>> {
+        let mut filter = Document::new();
+        if let Some(org_ids) = org_ids {
+            filter.insert("org_id", doc! { "$in": org_ids });
+        }
+        let mut month = Document::new();
+        if let Some(from) = from {
+            month.insert("$gte", from);
+        }
+        if let Some(to) = to {
+            month.insert("$lte", to);
+        }
+        if !month.is_empty() {
+            filter.insert("month", month);
+        }
+        let options = FindOptions::builder()
+            .sort(doc! { "org_id": 1, "month": 1 })
+            .build();
+        Ok(db
+            .org_usage()
+            .find(filter)
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    pub fn to_record(&self) -> OrgUsageRecord {
+        OrgUsageRecord {
+            org_id: self.org_id.clone(),
+            month: self.month.clone(),
+            device_seconds: self.device_seconds,
+            relay_bytes: self.relay_bytes,
+            peak_storage_bytes: self.peak_storage_bytes,
+            peak_devices: self.peak_devices,
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::{sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::patching::{
+    CreatePatchPolicyBody, MaintenanceWindow, PatchPolicy, PatchRun, PatchRunReport,
+    PublicPatchPolicy,
+};
+use mongodb::{
+    bson::{DateTime, doc, oid::ObjectId},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::AppConfig,
+    db::Mongo,
+    models::device::DeviceDoc,
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchPolicyDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    pub window: String,
+    #[serde(default)]
+    pub reboot: bool,
+    #[serde(default)]
+    pub when: Option<String>,
+    /// Devices with this scope in their allowed scopes get the policy
+    pub owner_scope: String,
+    pub created_by: String,
+    pub created_at: DateTime,
+}
+
+impl PatchPolicyDoc {
+    pub async fn create(
+        db: &Arc<Mongo>,
+        body: CreatePatchPolicyBody,
+        owner_scope: String,
+        created_by: String,
+    ) -> ServerResult<Self> {
+        if body.name.trim().is_empty() {
+            return Err(ServerError::bad_request("policy name is empty"));
+        }
+        let window =
+            MaintenanceWindow::parse(&body.window).map_err(|e| ServerError::bad_request(&e))?;
+        let taken = db
+            .patch_policies()
+            .find_one(doc! { "owner_scope": &owner_scope, "name": &body.name })
+            .await?
+            .is_some();
+        if taken {
+            return Err(ServerError::bad_request(&format!(
+                "a patch policy named '{}' already exists",
+                body.name
+            )));
+        }
+        if let Some(when) = &body.when {
+            m87_shared::expr::Expr::parse(when)
+                .map_err(|e| ServerError::bad_request(&format!("invalid condition: {}", e)))?;
+        }
+
+        let mut doc = Self {
+            id: None,
+            name: body.name,
+            packages: body.packages,
+            window: window.to_string(),
+            reboot: body.reboot,
+            when: body.when,
+            owner_scope,
+            created_by,
+            created_at: DateTime::now(),
+        };
+        let res = db
+            .patch_policies()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert patch policy"))?;
+        doc.id = res.inserted_id.as_object_id();
+        Ok(doc)
+    }
+
+    pub async fn list_for_scope(db: &Arc<Mongo>, scope: &str) -> ServerResult<Vec<Self>> {
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": 1 })
+            .build();
+        db.patch_policies()
+            .find(doc! { "owner_scope": scope })
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    /// Delete a policy of `scope`; false if there is none with that id.
+    pub async fn delete(db: &Arc<Mongo>, scope: &str, id: ObjectId) -> ServerResult<bool> {
+        let res = db
+            .patch_policies()
+            .delete_one(doc! { "_id": id, "owner_scope": scope })
+            .await?;
+        Ok(res.deleted_count > 0)
+    }
+
+    /// Policies a device applies: those of its scopes, none while it is in
+    /// maintenance mode. Ordered by creation so the config hash is stable.
+    pub async fn for_device(db: &Arc<Mongo>, device: &DeviceDoc) -> ServerResult<Vec<PatchPolicy>> {
+        if device.maintenance.as_ref().is_some_and(|m| m.enabled) {
+            return Ok(Vec::new());
+        }
+        let options = FindOptions::builder()
+            .sort(doc! { "created_at": 1 })
+            .build();
+        let docs: Vec<Self> = db
+            .patch_policies()
+            .find(doc! { "owner_scope": { "$in": &device.allowed_scopes } })
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?;
+        Ok(docs.iter().map(|d| d.to_policy()).collect())
+    }
+
+    pub fn to_policy(&self) -> PatchPolicy {
+        PatchPolicy {
+            id: self.id.map(|id| id.to_hex()).unwrap_or_default(),
+            name: self.name.clone(),
+            packages: self.packages.clone(),
+            window: self.window.clone(),
+            reboot: self.reboot,
+            when: self.when.clone(),
+        }
+    }
+
+    pub fn to_public_policy(&self) -> PublicPatchPolicy {
+        PublicPatchPolicy {
+            policy: self.to_policy(),
+            scope: self.owner_scope.clone(),
+            created_by: self.created_by.clone(),
+            created_at: self.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+}
+
+/// A policy run reported by a device, kept like deploy reports.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatchRunDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub received_at: DateTime,
+    pub report: PatchRunReport,
+    #[serde(default)]
+    pub expires_at: Option<DateTime>,
+}
+
+impl PatchRunDoc {
+    pub async fn add(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        device_id: ObjectId,
+        report: PatchRunReport,
+    ) -> ServerResult<()> {
+        let expires_at = Some(DateTime::from_system_time(
+            DateTime::now().to_system_time()
+                + Duration::from_hours(24 * config.report_retention_days as u64),
+        ));
+        let doc = Self {
+            id: None,
+            device_id,
+            received_at: DateTime::now(),
+            report,
+            expires_at,
+        };
+        db.patch_runs()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert patch run"))?;
+        Ok(())
+    }
+
+    pub async fn list_for_device(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Self>> {
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "received_at": -1 })
+            .build();
+        db.patch_runs()
+            .find(doc! { "device_id": device_id })
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    pub async fn delete_for_device(db: &Arc<Mongo>, device_id: ObjectId) -> ServerResult<()> {
+        db.patch_runs()
+            .delete_many(doc! { "device_id": device_id })
+            .await?;
+        Ok(())
+    }
+
+    pub fn to_patch_run(&self) -> PatchRun {
+        PatchRun {
+            device_id: self.device_id.to_hex(),
+            received_at: self.received_at.try_to_rfc3339_string().unwrap_or_default(),
+            report: self.report.clone(),
+        }
+    }
+}
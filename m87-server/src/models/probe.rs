@@ -0,0 +1,133 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use futures::TryStreamExt;
+use m87_shared::probe::{ProbeRecord, ProbeResult};
+use mongodb::{
+    bson::{DateTime, Document, doc, oid::ObjectId},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::AppConfig,
+    db::Mongo,
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+/// A probe result reported by a device, kept like patch runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResultDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub device_id: ObjectId,
+    pub received_at: DateTime,
+    pub result: ProbeResult,
+    #[serde(default)]
+    pub expires_at: Option<DateTime>,
+}
+
+#[derive(Deserialize)]
+struct ProbeCounts {
+    #[serde(rename = "_id")]
+    name: String,
+    checks: i64,
+    ok: i64,
+}
+
+impl ProbeResultDoc {
+    pub async fn add_many(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        device_id: ObjectId,
+        results: &[ProbeResult],
+    ) -> ServerResult<()> {
+        if results.is_empty() {
+            return Ok(());
+        }
+        let now = DateTime::now();
+        let expires_at = Some(DateTime::from_system_time(
+            now.to_system_time() + Duration::from_hours(24 * config.report_retention_days as u64),
+        ));
+        let docs = results.iter().map(|result| Self {
+            id: None,
+            device_id,
+            received_at: now,
+            result: result.clone(),
+            expires_at,
+        });
+        db.probe_results()
+            .insert_many(docs)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert probe results"))?;
+        Ok(())
+    }
+
+    /// Newest first, of one probe if `name` is set.
+    pub async fn list_for_device(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        name: Option<&str>,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Self>> {
+        let mut filter = doc! { "device_id": device_id };
+        if let Some(name) = name {
+            filter.insert("result.name", name);
+        }
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "received_at": -1 })
+            .build();
+        db.probe_results()
+            .find(filter)
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    /// Checks and successful checks per probe name received since `since`.
+    pub async fn counts_since(
+        db: &Arc<Mongo>,
+        device_id: ObjectId,
+        since: DateTime,
+    ) -> ServerResult<BTreeMap<String, (u64, u64)>> {
+        let pipeline: Vec<Document> = vec![
+            doc! { "$match": {
+                "device_id": device_id,
+                "received_at": { "$gte": since },
+            }},
+            doc! { "$group": {
+                "_id": "$result.name",
+                "checks": { "$sum": 1 },
+                "ok": { "$sum": { "$cond": ["$result.ok", 1, 0] } },
+            }},
+        ];
+        let mut cursor = db.probe_results().aggregate(pipeline).await?;
+        let mut counts = BTreeMap::new();
+        while let Some(doc) = cursor.try_next().await? {
+            let c: ProbeCounts = mongodb::bson::from_document(doc).map_err(|e| {
+                ServerError::internal_error(&format!("Failed to parse probe counts: {}", e))
+            })?;
+            counts.insert(c.name, (c.checks.max(0) as u64, c.ok.max(0) as u64));
+        }
+        Ok(counts)
+    }
+
+    pub async fn delete_for_device(db: &Arc<Mongo>, device_id: ObjectId) -> ServerResult<()> {
+        db.probe_results()
+            .delete_many(doc! { "device_id": device_id })
+            .await?;
+        Ok(())
+    }
+
+    pub fn to_record(&self) -> ProbeRecord {
+        ProbeRecord {
+            device_id: self.device_id.to_hex(),
+            received_at: self.received_at.try_to_rfc3339_string().unwrap_or_default(),
+            result: self.result.clone(),
+        }
+    }
+}
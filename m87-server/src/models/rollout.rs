@@ -0,0 +1,265 @@
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use m87_shared::{
+    deploy_spec::DeploymentRevision,
+    rollout::{Rollout, RolloutDevice, RolloutDeviceState, RolloutPolicy, RolloutState},
+};
+use mongodb::{
+    bson::{DateTime, Document, doc, oid::ObjectId},
+    options::FindOptions,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::access_control::AccessControlled,
+    db::Mongo,
+    models::{deploy_spec::DeployRevisionDoc, device::DeviceDoc},
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+/// Most devices in one rollout
+pub const MAX_ROLLOUT_DEVICES: usize = 5000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RolloutDeviceDoc {
+    pub device_id: ObjectId,
+    pub device_name: String,
+    pub wave: u32,
+    pub state: RolloutDeviceState,
+    #[serde(default)]
+    pub previous_revision_id: Option<String>,
+    #[serde(default)]
+    pub activated_at: Option<DateTime>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RolloutDoc {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub revision_id: String,
+    pub policy: RolloutPolicy,
+    pub state: RolloutState,
+    pub current_wave: u32,
+    pub waves: u32,
+    pub created_by: String,
+    pub created_at: DateTime,
+    /// Also the version the controller and operators compare before saving
+    pub updated_at: DateTime,
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Failed devices an operator accepted by resuming the rollout
+    #[serde(default)]
+    pub accepted_failures: u32,
+    pub devices: Vec<RolloutDeviceDoc>,
+    // union of the device scopes, so every device's viewers see the rollout
+    pub owner_scope: String,
+    pub allowed_scopes: Vec<String>,
+}
+
+impl AccessControlled for RolloutDoc {
+    fn owner_scope_field() -> &'static str {
+        "owner_scope"
+    }
+    fn allowed_scopes_field() -> Option<&'static str> {
+        Some("allowed_scopes")
+    }
+    fn owner_scope(&self) -> &str {
+        &self.owner_scope
+    }
+    fn allowed_scopes(&self) -> Option<Vec<String>> {
+        Some(self.allowed_scopes.clone())
+    }
+}
+
+impl RolloutDoc {
+    /// Store the revision on each device, inactive and tagged with the
+    /// rollout, and start the first wave with the next controller tick.
+    /// Devices are updated in the given order.
+    pub async fn create(
+        db: &Arc<Mongo>,
+        revision: DeploymentRevision,
+        devices: Vec<DeviceDoc>,
+        policy: RolloutPolicy,
+        created_by: &str,
+    ) -> ServerResult<Self> {
+        policy
+            .validate()
+            .map_err(|e| ServerError::bad_request(&e))?;
+        if devices.is_empty() {
+            return Err(ServerError::bad_request("a rollout needs devices"));
+        }
+        if devices.len() > MAX_ROLLOUT_DEVICES {
+            return Err(ServerError::bad_request(&format!(
+                "a rollout covers at most {} devices",
+                MAX_ROLLOUT_DEVICES
+            )));
+        }
+        let revision_id = revision
+            .id
+            .clone()
+            .ok_or_else(|| ServerError::bad_request("the revision has no id"))?;
+        let id = ObjectId::new();
+        let wave_ends = policy.wave_ends(devices.len());
+
+        let mut entries = Vec::with_capacity(devices.len());
+        let mut allowed_scopes: Vec<String> = Vec::new();
+        for (position, device) in devices.iter().enumerate() {
+            let device_oid = device
+                .id
+                .ok_or_else(|| ServerError::internal_error("Device without id"))?;
+            let previous = DeployRevisionDoc::get_active_device_deployment(db, device_oid).await?;
+            let filter = doc! { "device_id": device_oid, "revision.id": &revision_id };
+            if db
+                .deploy_revisions()
+                .count_documents(filter.clone())
+                .await?
+                == 0
+            {
+                DeployRevisionDoc::create(
+                    db,
+                    revision.clone(),
+                    Some(device_oid),
+                    None,
+                    false,
+                    device.owner_scope.clone(),
+                    device.allowed_scopes.clone(),
+                )
+                .await?;
+            }
+            db.deploy_revisions()
+                .update_one(filter, doc! { "$set": { "rollout_id": id } })
+                .await?;
+
+            for scope in std::iter::once(&device.owner_scope).chain(&device.allowed_scopes) {
+                if !allowed_scopes.contains(scope) {
+                    allowed_scopes.push(scope.clone());
+                }
+            }
+            entries.push(RolloutDeviceDoc {
+                device_id: device_oid,
+                device_name: device.name.clone(),
+                wave: wave_ends
+                    .iter()
+                    .position(|&end| position < end)
+                    .unwrap_or(0) as u32,
+                state: RolloutDeviceState::Pending,
+                previous_revision_id: previous.and_then(|p| p.revision.id),
+                activated_at: None,
+                error: None,
+            });
+        }
+
+        let now = DateTime::now();
+        let doc = Self {
+            id,
+            revision_id,
+            policy,
+            state: RolloutState::Running,
+            current_wave: 0,
+            waves: wave_ends.len() as u32,
+            created_by: created_by.to_string(),
+            created_at: now,
+            updated_at: now,
+            reason: None,
+            accepted_failures: 0,
+            devices: entries,
+            owner_scope: devices[0].owner_scope.clone(),
+            allowed_scopes,
+        };
+        db.rollouts().insert_one(&doc).await?;
+        Ok(doc)
+    }
+
+    pub async fn list(
+        db: &Arc<Mongo>,
+        scopes: &Vec<String>,
+        mut filter: Document,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<Self>> {
+        filter.extend(Self::access_filter(scopes));
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "created_at": -1 })
+            .build();
+        db.rollouts()
+            .find(filter)
+            .with_options(options)
+            .await?
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))
+    }
+
+    pub async fn running(db: &Arc<Mongo>) -> ServerResult<Vec<Self>> {
+        Ok(db
+            .rollouts()
+            .find(doc! { "state": RolloutState::Running.to_string() })
+            .await?
+            .try_collect()
+            .await?)
+    }
+
+    /// Store the rollout unless someone else saved it since it was loaded.
+    /// Returns whether it was stored.
+    pub async fn save(&mut self, db: &Arc<Mongo>) -> ServerResult<bool> {
+        let loaded_at = self.updated_at;
+        self.updated_at = DateTime::now();
+        let replaced = db
+            .rollouts()
+            .replace_one(doc! { "_id": self.id, "updated_at": loaded_at }, &*self)
+            .await?;
+        Ok(replaced.matched_count > 0)
+    }
+
+    /// Devices whose wave started, and how many of them failed beyond the
+    /// failures accepted so far.
+    pub fn updated_and_failed(&self) -> (usize, usize) {
+        let started: Vec<&RolloutDeviceDoc> = self
+            .devices
+            .iter()
+            .filter(|d| d.wave <= self.current_wave)
+            .collect();
+        let failed = started
+            .iter()
+            .filter(|d| d.state == RolloutDeviceState::Failed)
+            .count();
+        (
+            started.len(),
+            failed.saturating_sub(self.accepted_failures as usize),
+        )
+    }
+
+    pub fn to_public(&self) -> Rollout {
+        let rfc3339 = |t: &DateTime| t.try_to_rfc3339_string().unwrap_or_default();
+        Rollout {
+            id: self.id.to_hex(),
+            revision_id: self.revision_id.clone(),
+            policy: self.policy.clone(),
+            state: self.state,
+            current_wave: self.current_wave as usize,
+            waves: self.waves as usize,
+            created_by: self.created_by.clone(),
+            created_at: rfc3339(&self.created_at),
+            updated_at: rfc3339(&self.updated_at),
+            reason: self.reason.clone(),
+            devices: self
+                .devices
+                .iter()
+                .map(|d| RolloutDevice {
+                    device_id: d.device_id.to_hex(),
+                    device_name: d.device_name.clone(),
+                    wave: d.wave as usize,
+                    state: d.state,
+                    previous_revision_id: d.previous_revision_id.clone(),
+                    activated_at: d.activated_at.as_ref().map(rfc3339),
+                    error: d.error.clone(),
+                })
+                .collect(),
+        }
+    }
+}
@@ -2,12 +2,15 @@ use crate::{
     auth::jwk::{get_email_and_name_from_token, validate_token},
     config::AppConfig,
     db::Mongo,
-    response::ServerResult,
+    response::{ServerError, ServerResult},
 };
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
 use m87_shared::{roles::Role, users::User};
 use mongodb::bson::{DateTime, doc, oid::ObjectId};
+use rand_core::OsRng;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserDoc {
@@ -24,6 +27,10 @@ pub struct UserDoc {
     pub created_at: Option<DateTime>,
     pub last_login: Option<DateTime>,
     pub total_logins: u64,
+
+    /// Argon2 hash of the password of a local auth user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_hash: Option<String>,
 }
 
 impl UserDoc {
@@ -51,6 +58,11 @@ impl UserDoc {
             return Ok(user);
         }
 
+        // Local users exist only once an admin created them
+        if config.local_auth {
+            return Err(ServerError::unauthorized("unknown user"));
+        }
+
         // Otherwise create new user
         let (email, name) = get_email_and_name_from_token(token, config).await?;
         // check if user domain is in config.user_auto_accept_domains if users_need_approval is true
@@ -76,12 +88,94 @@ impl UserDoc {
             created_at: Some(now.clone()),
             last_login: Some(now),
             total_logins: 1,
+            password_hash: None,
         };
 
         collection.insert_one(new_user.clone()).await?;
         Ok(new_user)
     }
 
+    /// Subject of a local auth user, standing in for the issuer's `sub`
+    pub fn local_sub(email: &str) -> String {
+        format!("local|{}", email)
+    }
+
+    /// Create a user that logs in with a password (local auth mode).
+    pub async fn create_local(
+        db: &Arc<Mongo>,
+        email: &str,
+        name: Option<String>,
+        password: &str,
+    ) -> ServerResult<UserDoc> {
+        let collection = db.users();
+        if collection
+            .find_one(doc! { "email": email })
+            .await?
+            .is_some()
+        {
+            return Err(ServerError::bad_request(&format!(
+                "user {} already exists",
+                email
+            )));
+        }
+        let now = DateTime::now();
+        let mut user = UserDoc {
+            id: None,
+            name,
+            email: Some(email.to_string()),
+            sub: Self::local_sub(email),
+            approved: true,
+            created_at: Some(now),
+            last_login: None,
+            total_logins: 0,
+            password_hash: Some(hash_password(password).await?),
+        };
+        let res = collection.insert_one(user.clone()).await?;
+        user.id = res.inserted_id.as_object_id();
+        Ok(user)
+    }
+
+    /// Replace the password of a local auth user.
+    pub async fn set_password(db: &Arc<Mongo>, email: &str, password: &str) -> ServerResult<()> {
+        let hash = hash_password(password).await?;
+        let res = db
+            .users()
+            .update_one(
+                doc! { "email": email, "sub": Self::local_sub(email) },
+                doc! { "$set": { "password_hash": hash } },
+            )
+            .await?;
+        if res.matched_count == 0 {
+            return Err(ServerError::not_found(&format!("no local user {}", email)));
+        }
+        Ok(())
+    }
+
+    /// The local auth user with `email`, if `password` matches.
+    pub async fn verify_password(
+        db: &Arc<Mongo>,
+        email: &str,
+        password: &str,
+    ) -> ServerResult<UserDoc> {
+        let user = db
+            .users()
+            .find_one(doc! { "email": email, "sub": Self::local_sub(email) })
+            .await?;
+        let hash = user.as_ref().and_then(|u| u.password_hash.clone());
+        let password = password.to_string();
+        let matches =
+            tokio::task::spawn_blocking(move || password_matches(hash.as_deref(), &password))
+                .await
+                .map_err(|e| {
+                    ServerError::internal_error(&format!("Password check failed: {}", e))
+                })?;
+        // same error for unknown users and wrong passwords
+        match user {
+            Some(user) if matches => Ok(user),
+            _ => Err(ServerError::unauthorized("invalid email or password")),
+        }
+    }
+
     pub fn get_reference_id(&self) -> String {
         Self::create_reference_id(&self.email.clone().unwrap_or(self.sub.clone()))
     }
@@ -106,3 +200,86 @@ impl UserDoc {
         }
     }
 }
+
+/// Checked for users without a password, with the same cost as real hashes.
+static DUMMY_PASSWORD_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_password_blocking("m87 dummy password").unwrap_or_default());
+
+/// Hashes off the async workers, as Argon2 takes a while by design.
+async fn hash_password(password: &str) -> ServerResult<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_password_blocking(&password))
+        .await
+        .map_err(|e| ServerError::internal_error(&format!("Failed to hash password: {}", e)))?
+}
+
+fn hash_password_blocking(password: &str) -> ServerResult<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| ServerError::internal_error(&format!("Failed to hash password: {}", e)))?;
+    Ok(hash.to_string())
+}
+
+/// Whether `password` matches `hash`. Users without a password are checked
+/// against a dummy hash, as slow as a wrong password, so timing does not
+/// reveal accounts. Blocking.
+fn password_matches(hash: Option<&str>, password: &str) -> bool {
+    let Some(hash) = hash else {
+        let _ = PasswordHash::new(&DUMMY_PASSWORD_HASH)
+            .map(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed));
+        return false;
+    };
+    PasswordHash::new(hash)
+        .and_then(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn timed(check: impl Fn() -> bool) -> (bool, Duration) {
+        let start = Instant::now();
+        let matched = check();
+        (matched, start.elapsed())
+    }
+
+    #[test]
+    fn test_password_matches_only_the_hashed_password() {
+        let hash = hash_password_blocking("correct horse").unwrap();
+        assert!(password_matches(Some(&hash), "correct horse"));
+        assert!(!password_matches(Some(&hash), "wrong horse"));
+        assert!(!password_matches(Some("not a hash"), "correct horse"));
+        assert!(!password_matches(None, "correct horse"));
+    }
+
+    #[test]
+    fn test_unknown_users_take_as_long_as_wrong_passwords() {
+        let hash = hash_password_blocking("correct horse").unwrap();
+        LazyLock::force(&DUMMY_PASSWORD_HASH);
+
+        let mut unknown = Vec::new();
+        let mut wrong = Vec::new();
+        for _ in 0..5 {
+            let (matched, took) = timed(|| password_matches(None, "guess"));
+            assert!(!matched);
+            unknown.push(took);
+            let (matched, took) = timed(|| password_matches(Some(&hash), "guess"));
+            assert!(!matched);
+            wrong.push(took);
+        }
+        unknown.sort();
+        wrong.sort();
+        let (unknown, wrong) = (unknown[2], wrong[2]);
+        // an unknown user is not answered noticeably faster (or slower)
+        assert!(
+            unknown * 2 > wrong && wrong * 2 > unknown,
+            "unknown user {:?}, wrong password {:?}",
+            unknown,
+            wrong
+        );
+    }
+}
@@ -0,0 +1,582 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::http::HeaderMap;
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use m87_shared::webhook::{Webhook, WebhookAction, WebhookDelivery, WebhookProvider};
+use mongodb::{
+    bson::{DateTime, doc, oid::ObjectId},
+    options::FindOptions,
+};
+use rand::{Rng, distributions::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    auth::access_control::AccessControlled,
+    config::AppConfig,
+    db::Mongo,
+    models::idempotency::is_duplicate_key,
+    response::{ServerError, ServerResult},
+    util::pagination::RequestPagination,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub name: String,
+    pub provider: WebhookProvider,
+    // kept in plain text: it is the HMAC key for incoming deliveries
+    pub secret: String,
+    pub action: WebhookAction,
+    #[serde(default)]
+    pub branch: Option<String>,
+    pub owner_scope: String,
+    pub created_by: String,
+    pub created_at: DateTime,
+}
+
+impl AccessControlled for WebhookDoc {
+    fn owner_scope_field() -> &'static str {
+        "owner_scope"
+    }
+    fn allowed_scopes_field() -> Option<&'static str> {
+        None
+    }
+    fn owner_scope(&self) -> &str {
+        &self.owner_scope
+    }
+    fn allowed_scopes(&self) -> Option<Vec<String>> {
+        None
+    }
+}
+
+/// What was read from a delivery payload.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PushInfo {
+    pub event: Option<String>,
+    pub tag: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl WebhookDoc {
+    pub async fn create(
+        db: &Arc<Mongo>,
+        name: String,
+        provider: WebhookProvider,
+        action: WebhookAction,
+        branch: Option<String>,
+        owner_scope: String,
+        created_by: String,
+    ) -> ServerResult<Self> {
+        let secret: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(40)
+            .map(char::from)
+            .collect();
+
+        let mut doc = Self {
+            id: None,
+            name,
+            provider,
+            secret,
+            action,
+            branch,
+            owner_scope,
+            created_by,
+            created_at: DateTime::now(),
+        };
+        let res = db
+            .webhooks()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert webhook"))?;
+        doc.id = res.inserted_id.as_object_id();
+        Ok(doc)
+    }
+
+    pub fn to_webhook(&self) -> Webhook {
+        let id = self.id.map(|id| id.to_hex()).unwrap_or_default();
+        Webhook {
+            delivery_path: format!("/webhook/{}/deliver", id),
+            id,
+            name: self.name.clone(),
+            provider: self.provider,
+            action: self.action.clone(),
+            branch: self.branch.clone(),
+            owner_scope: self.owner_scope.clone(),
+            created_at: self.created_at.try_to_rfc3339_string().unwrap_or_default(),
+        }
+    }
+
+    /// Check the delivery against the webhook secret the way the provider signs it.
+    pub fn verify(&self, headers: &HeaderMap, body: &[u8]) -> bool {
+        match self.provider {
+            WebhookProvider::Github => header(headers, "x-hub-signature-256")
+                .is_some_and(|sig| verify_hmac_sha256(&self.secret, sig, body)),
+            WebhookProvider::Gitlab => header(headers, "x-gitlab-token")
+                .is_some_and(|token| token.as_bytes().ct_eq(self.secret.as_bytes()).into()),
+            WebhookProvider::Registry => {
+                if let Some(sig) = header(headers, "x-m87-signature-256") {
+                    return verify_hmac_sha256(&self.secret, sig, body);
+                }
+                header(headers, "authorization").is_some_and(|auth| {
+                    let auth = auth.strip_prefix("Bearer ").unwrap_or(auth);
+                    auth.as_bytes().ct_eq(self.secret.as_bytes()).into()
+                })
+            }
+        }
+    }
+
+    pub fn push_info(&self, headers: &HeaderMap, payload: &Value) -> PushInfo {
+        match self.provider {
+            WebhookProvider::Github => git_push_info(
+                header(headers, "x-github-event"),
+                payload,
+                &["after", "head_commit.id"],
+            ),
+            WebhookProvider::Gitlab => git_push_info(
+                header(headers, "x-gitlab-event"),
+                payload,
+                &["checkout_sha", "after"],
+            ),
+            WebhookProvider::Registry => registry_push_info(payload),
+        }
+    }
+
+    /// Whether this delivery should run the action. Pings and non-push events
+    /// are logged but ignored, as are pushes to other branches.
+    pub fn should_trigger(&self, info: &PushInfo) -> Result<(), String> {
+        if info.tag.is_none() {
+            return Err(format!(
+                "ignored {} event without tag",
+                info.event.as_deref().unwrap_or("unknown")
+            ));
+        }
+        if let (Some(want), Some(branch)) = (&self.branch, &info.branch)
+            && want != branch
+        {
+            return Err(format!("ignored push to branch {}", branch));
+        }
+        Ok(())
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn verify_hmac_sha256(secret: &str, signature: &str, body: &[u8]) -> bool {
+    let Some(hex_sig) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    let Ok(expected) = hex::decode(hex_sig.trim()) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&expected).is_ok()
+}
+
+fn json_str<'a>(payload: &'a Value, path: &str) -> Option<&'a str> {
+    path.split('.')
+        .try_fold(payload, |v, key| match key.parse::<usize>() {
+            Ok(i) => v.get(i),
+            Err(_) => v.get(key),
+        })
+        .and_then(Value::as_str)
+        .filter(|s| !s.is_empty())
+}
+
+fn git_push_info(event: Option<&str>, payload: &Value, sha_fields: &[&str]) -> PushInfo {
+    let git_ref = json_str(payload, "ref").unwrap_or_default();
+    let mut info = PushInfo {
+        event: event.map(str::to_string),
+        ..Default::default()
+    };
+    if let Some(tag) = git_ref.strip_prefix("refs/tags/") {
+        info.tag = Some(tag.to_string());
+    } else if let Some(branch) = git_ref.strip_prefix("refs/heads/") {
+        info.branch = Some(branch.to_string());
+        // branch pushes are tagged with the short commit sha, as most CI pipelines do
+        info.tag = sha_fields
+            .iter()
+            .find_map(|f| json_str(payload, f))
+            .filter(|sha| sha.chars().any(|c| c != '0'))
+            .map(|sha| sha.chars().take(7).collect());
+    }
+    info
+}
+
+fn registry_push_info(payload: &Value) -> PushInfo {
+    // Docker Hub
+    if let Some(tag) = json_str(payload, "push_data.tag") {
+        return PushInfo {
+            event: Some("push".to_string()),
+            tag: Some(tag.to_string()),
+            branch: None,
+        };
+    }
+    // Harbor
+    if let Some(tag) = json_str(payload, "event_data.resources.0.tag") {
+        return PushInfo {
+            event: json_str(payload, "type").map(str::to_string),
+            tag: Some(tag.to_string()),
+            branch: None,
+        };
+    }
+    // distribution (registry:2) notifications
+    if let Some(tag) = json_str(payload, "events.0.target.tag") {
+        return PushInfo {
+            event: json_str(payload, "events.0.action").map(str::to_string),
+            tag: Some(tag.to_string()),
+            branch: None,
+        };
+    }
+    PushInfo::default()
+}
+
+/// Replace the tag of every reference to `image` in `text`. Untagged references
+/// get the tag appended, digest references are left alone.
+pub fn retag_image(text: &str, image: &str, tag: &str) -> String {
+    fn is_name_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '/' | '.' | '-' | '_')
+    }
+    fn is_tag_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_')
+    }
+
+    if image.is_empty() {
+        return text.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find(image) {
+        let before = rest[..pos].chars().next_back();
+        let after = &rest[pos + image.len()..];
+        out.push_str(&rest[..pos]);
+        out.push_str(image);
+
+        if before.is_some_and(is_name_char) {
+            rest = after;
+            continue;
+        }
+        match after.chars().next() {
+            Some(':') => {
+                let old_len = after[1..]
+                    .find(|c: char| !is_tag_char(c))
+                    .unwrap_or(after.len() - 1);
+                // `image:` followed by a port number is a different image
+                if after[1 + old_len..].starts_with('/') {
+                    rest = after;
+                    continue;
+                }
+                out.push(':');
+                out.push_str(tag);
+                rest = &after[1 + old_len..];
+            }
+            Some(c) if is_name_char(c) || c == '@' => rest = after,
+            _ => {
+                out.push(':');
+                out.push_str(tag);
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDeliveryDoc {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    pub webhook_id: ObjectId,
+    pub received_at: DateTime,
+    pub event: Option<String>,
+    pub verified: bool,
+    pub success: bool,
+    pub tag: Option<String>,
+    pub message: String,
+    #[serde(default)]
+    pub device_ids: Vec<ObjectId>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime>,
+    /// SHA-256 of the verified payload; unique per webhook, so a redelivered
+    /// or replayed payload runs the action only once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_hash: Option<String>,
+}
+
+impl WebhookDeliveryDoc {
+    pub async fn add(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        webhook_id: ObjectId,
+        info: &PushInfo,
+        verified: bool,
+        result: &Result<String, String>,
+        device_ids: Vec<ObjectId>,
+    ) -> ServerResult<()> {
+        let (success, message) = match result {
+            Ok(msg) => (true, msg.clone()),
+            Err(msg) => (false, msg.clone()),
+        };
+
+        let doc = Self {
+            id: None,
+            webhook_id,
+            received_at: DateTime::now(),
+            event: info.event.clone(),
+            verified,
+            success,
+            tag: info.tag.clone(),
+            message,
+            device_ids,
+            expires_at: Some(delivery_expiry(config)),
+            payload_hash: None,
+        };
+        db.webhook_deliveries()
+            .insert_one(&doc)
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to insert webhook delivery"))?;
+        Ok(())
+    }
+
+    /// Log a verified delivery before its action runs. `None` if the same
+    /// payload was delivered to this webhook before and did not fail; a failed
+    /// one runs again, e.g. once a pending approval was given.
+    pub async fn claim(
+        db: &Arc<Mongo>,
+        config: &Arc<AppConfig>,
+        webhook_id: ObjectId,
+        info: &PushInfo,
+        body: &[u8],
+    ) -> ServerResult<Option<ObjectId>> {
+        let doc = Self {
+            id: None,
+            webhook_id,
+            received_at: DateTime::now(),
+            event: info.event.clone(),
+            verified: true,
+            success: false,
+            tag: info.tag.clone(),
+            message: "running".to_string(),
+            device_ids: vec![],
+            expires_at: Some(delivery_expiry(config)),
+            payload_hash: Some(hex::encode(Sha256::digest(body))),
+        };
+        let hash = doc.payload_hash.clone();
+        match db.webhook_deliveries().insert_one(&doc).await {
+            Ok(res) => res
+                .inserted_id
+                .as_object_id()
+                .map(Some)
+                .ok_or_else(|| ServerError::internal_error("Webhook delivery has no id")),
+            Err(e) if is_duplicate_key(&e) => {
+                let retried = db
+                    .webhook_deliveries()
+                    .find_one_and_update(
+                        doc! {
+                            "webhook_id": webhook_id,
+                            "payload_hash": hash,
+                            "success": false,
+                            "message": { "$ne": "running" },
+                        },
+                        doc! { "$set": {
+                            "received_at": DateTime::now(),
+                            "message": "running",
+                            "device_ids": [],
+                        } },
+                    )
+                    .await?;
+                Ok(retried.and_then(|doc| doc.id))
+            }
+            Err(_) => Err(ServerError::internal_error(
+                "Failed to insert webhook delivery",
+            )),
+        }
+    }
+
+    /// Record the outcome of a claimed delivery.
+    pub async fn finish(
+        db: &Arc<Mongo>,
+        id: ObjectId,
+        result: &Result<String, String>,
+        device_ids: Vec<ObjectId>,
+    ) -> ServerResult<()> {
+        let (success, message) = match result {
+            Ok(msg) => (true, msg),
+            Err(msg) => (false, msg),
+        };
+        db.webhook_deliveries()
+            .update_one(
+                doc! { "_id": id },
+                doc! { "$set": {
+                    "success": success,
+                    "message": message.as_str(),
+                    "device_ids": device_ids,
+                } },
+            )
+            .await
+            .map_err(|_| ServerError::internal_error("Failed to update webhook delivery"))?;
+        Ok(())
+    }
+
+    pub async fn list_for_webhook(
+        db: &Arc<Mongo>,
+        webhook_id: ObjectId,
+        pagination: &RequestPagination,
+    ) -> ServerResult<Vec<WebhookDeliveryDoc>> {
+        let options = FindOptions::builder()
+            .skip(Some(pagination.offset))
+            .limit(Some(pagination.limit as i64))
+            .sort(doc! { "received_at": -1 })
+            .build();
+
+        let cursor = db
+            .webhook_deliveries()
+            .find(doc! { "webhook_id": webhook_id })
+            .with_options(options)
+            .await?;
+        let results: Vec<WebhookDeliveryDoc> = cursor
+            .try_collect()
+            .await
+            .map_err(|_| ServerError::internal_error("Cursor decode failed"))?;
+        Ok(results)
+    }
+
+    pub fn to_delivery(&self) -> WebhookDelivery {
+        WebhookDelivery {
+            id: self.id.map(|id| id.to_hex()).unwrap_or_default(),
+            webhook_id: self.webhook_id.to_hex(),
+            received_at: self.received_at.try_to_rfc3339_string().unwrap_or_default(),
+            event: self.event.clone(),
+            verified: self.verified,
+            success: self.success,
+            tag: self.tag.clone(),
+            message: self.message.clone(),
+            device_ids: self.device_ids.iter().map(|id| id.to_hex()).collect(),
+        }
+    }
+}
+
+fn delivery_expiry(config: &AppConfig) -> DateTime {
+    DateTime::from_system_time(
+        DateTime::now().to_system_time()
+            + Duration::from_hours((config.audit_retention_days * 24) as u64),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_hmac_sha256() {
+        // the example from GitHub's webhook documentation
+        let secret = "It's a Secret to Everybody";
+        let body = b"Hello, World!";
+        let good = "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17";
+        let cases = [
+            (good, true),
+            // signatures are compared, not their spelling
+            (
+                "sha256=757107EA0EB2509FC211221CCE984B8A37570B6D7586C22C46F4379C8B043E17",
+                true,
+            ),
+            (
+                "sha256=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e18",
+                false,
+            ),
+            // truncated signatures must not match on their prefix
+            ("sha256=757107ea0eb2509fc211221cce984b8a", false),
+            ("sha256=", false),
+            ("sha256=not-hex", false),
+            (
+                "sha1=757107ea0eb2509fc211221cce984b8a37570b6d7586c22c46f4379c8b043e17",
+                false,
+            ),
+            ("", false),
+        ];
+        for (signature, valid) in cases {
+            assert_eq!(
+                verify_hmac_sha256(secret, signature, body),
+                valid,
+                "{}",
+                signature
+            );
+        }
+        assert!(!verify_hmac_sha256("another secret", good, body));
+        assert!(!verify_hmac_sha256(secret, good, b"Hello, World?"));
+    }
+
+    #[test]
+    fn test_retag_image() {
+        let cases = [
+            (
+                "image: ghcr.io/acme/app:1.0",
+                "ghcr.io/acme/app",
+                "image: ghcr.io/acme/app:abc1234",
+            ),
+            (
+                "image: ghcr.io/acme/app\n",
+                "ghcr.io/acme/app",
+                "image: ghcr.io/acme/app:abc1234\n",
+            ),
+            (
+                "image: \"ghcr.io/acme/app:latest\"",
+                "ghcr.io/acme/app",
+                "image: \"ghcr.io/acme/app:abc1234\"",
+            ),
+            // registries with a port
+            (
+                "image: localhost:5000/app:1.0",
+                "localhost:5000/app",
+                "image: localhost:5000/app:abc1234",
+            ),
+            (
+                "image: localhost:5000/app:1.0",
+                "localhost",
+                "image: localhost:5000/app:1.0",
+            ),
+            // digests pin the image
+            (
+                "image: ghcr.io/acme/app@sha256:0123abcd",
+                "ghcr.io/acme/app",
+                "image: ghcr.io/acme/app@sha256:0123abcd",
+            ),
+            // other images sharing part of the name
+            (
+                "image: ghcr.io/acme/app-worker:1.0",
+                "ghcr.io/acme/app",
+                "image: ghcr.io/acme/app-worker:1.0",
+            ),
+            (
+                "image: mirror.io/ghcr.io/acme/app:1.0",
+                "ghcr.io/acme/app",
+                "image: mirror.io/ghcr.io/acme/app:1.0",
+            ),
+            (
+                "a: acme/app:1\nb: acme/app\nc: acme/app:2",
+                "acme/app",
+                "a: acme/app:abc1234\nb: acme/app:abc1234\nc: acme/app:abc1234",
+            ),
+            ("image: acme/app:1", "", "image: acme/app:1"),
+        ];
+        for (text, image, expected) in cases {
+            assert_eq!(
+                retag_image(text, image, "abc1234"),
+                expected,
+                "{} in {:?}",
+                image,
+                text
+            );
+        }
+    }
+}
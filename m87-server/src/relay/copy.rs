@@ -0,0 +1,132 @@
+//! Stream bridging between clients and devices. Device data is passed on in
+//! the chunks quinn received it in, and client data is read into pooled
+//! buffers whose frozen slices quinn sends without copying them again.
+
+use std::io::IoSlice;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Most a single read from a client may fill.
+const CHUNK_SIZE: usize = 16 * 1024;
+/// Allocation the chunks of a client stream are cut from. It is reused once
+/// quinn has dropped every chunk sent from it.
+const BUFFER_SIZE: usize = 256 * 1024;
+/// Chunks taken from a device stream per write to the client.
+const MAX_CHUNKS: usize = 32;
+/// Idle buffers kept for the next streams.
+const POOL_SIZE: usize = 1024;
+
+static POOL: Mutex<Vec<BytesMut>> = Mutex::new(Vec::new());
+
+/// Buffer from the pool, returned to it on drop.
+struct PooledBuf(BytesMut);
+
+impl PooledBuf {
+    fn take() -> Self {
+        let buf = POOL.lock().unwrap().pop();
+        PooledBuf(buf.unwrap_or_else(|| BytesMut::with_capacity(BUFFER_SIZE)))
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        let mut buf = std::mem::take(&mut self.0);
+        buf.clear();
+        // Chunks still queued in quinn keep the allocation shared; such
+        // buffers are left to be freed with the chunks.
+        if !buf.try_reclaim(BUFFER_SIZE) {
+            return;
+        }
+        let mut pool = POOL.lock().unwrap();
+        if pool.len() < POOL_SIZE {
+            pool.push(buf);
+        }
+    }
+}
+
+/// Copy a client stream to a device stream until the client finishes it,
+/// adding what was sent to `meter` as it goes.
+pub async fn client_to_device<R>(
+    client: &mut R,
+    device: &mut quinn::SendStream,
+    meter: &AtomicU64,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin + ?Sized,
+{
+    let mut buf = PooledBuf::take();
+    let mut total = 0u64;
+    loop {
+        buf.0.reserve(CHUNK_SIZE);
+        let n = (&mut *client)
+            .take(CHUNK_SIZE as u64)
+            .read_buf(&mut buf.0)
+            .await?;
+        if n == 0 {
+            return Ok(total);
+        }
+        total += n as u64;
+        meter.fetch_add(n as u64, Ordering::Relaxed);
+        device.write_chunk(buf.0.split().freeze()).await?;
+    }
+}
+
+/// Copy a device stream to a client stream until the device finishes it,
+/// adding what was sent to `meter` as it goes.
+pub async fn device_to_client<W>(
+    device: &mut quinn::RecvStream,
+    client: &mut W,
+    meter: &AtomicU64,
+) -> std::io::Result<u64>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let mut chunks: [Bytes; MAX_CHUNKS] = Default::default();
+    let mut total = 0u64;
+    while let Some(n) = device.read_chunks(&mut chunks).await? {
+        let sent = write_all_vectored(client, &mut chunks[..n]).await?;
+        meter.fetch_add(sent, Ordering::Relaxed);
+        total += sent;
+    }
+    Ok(total)
+}
+
+/// Write every chunk, handing the writer as many at once as it accepts.
+async fn write_all_vectored<W>(w: &mut W, chunks: &mut [Bytes]) -> std::io::Result<u64>
+where
+    W: AsyncWrite + Unpin + ?Sized,
+{
+    let total = chunks.iter().map(Bytes::len).sum::<usize>() as u64;
+    let mut start = 0;
+    loop {
+        while start < chunks.len() && chunks[start].is_empty() {
+            start += 1;
+        }
+        if start == chunks.len() {
+            return Ok(total);
+        }
+
+        let mut slices = [IoSlice::new(&[]); MAX_CHUNKS];
+        let live = &chunks[start..chunks.len().min(start + MAX_CHUNKS)];
+        for (slice, chunk) in slices.iter_mut().zip(live) {
+            *slice = IoSlice::new(chunk);
+        }
+        let mut n = w.write_vectored(&slices[..live.len()]).await?;
+        if n == 0 {
+            return Err(std::io::ErrorKind::WriteZero.into());
+        }
+
+        while n > 0 {
+            let chunk = &mut chunks[start];
+            let k = n.min(chunk.len());
+            chunk.advance(k);
+            n -= k;
+            if chunk.is_empty() {
+                start += 1;
+            }
+        }
+    }
+}
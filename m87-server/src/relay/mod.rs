@@ -1 +1,2 @@
+pub mod copy;
 pub mod relay_state;
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
 use quinn::Connection;
 use tokio::sync::RwLock;
@@ -9,6 +10,51 @@ use tracing::{info, warn};
 pub struct RelayState {
     tunnels: Arc<RwLock<HashMap<String, Connection>>>,
     lost: Arc<RwLock<HashMap<String, ()>>>, // just a set, we don't need Instant
+    /// Open client sessions per org, for the concurrent tunnel quota
+    org_sessions: Arc<Mutex<HashMap<String, u64>>>,
+    /// Bytes relayed per device since the usage meter last took them
+    relay_bytes: Arc<Mutex<HashMap<String, Arc<AtomicU64>>>>,
+    /// Client connections to devices, so ending someone's access can close theirs
+    clients: Arc<Mutex<HashMap<u64, RelayClient>>>,
+    next_client_id: Arc<AtomicU64>,
+}
+
+struct RelayClient {
+    device_short_id: String,
+    email: String,
+    close: Box<dyn Fn() + Send>,
+}
+
+/// A client connection listed in the relay until dropped.
+pub struct RelayClientGuard {
+    clients: Arc<Mutex<HashMap<u64, RelayClient>>>,
+    id: u64,
+}
+
+impl Drop for RelayClientGuard {
+    fn drop(&mut self) {
+        self.clients.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// A client session counted against its device's orgs until dropped.
+pub struct OrgSessionGuard {
+    org_sessions: Arc<Mutex<HashMap<String, u64>>>,
+    org_ids: Vec<String>,
+}
+
+impl Drop for OrgSessionGuard {
+    fn drop(&mut self) {
+        let mut sessions = self.org_sessions.lock().unwrap();
+        for org_id in &self.org_ids {
+            if let Some(count) = sessions.get_mut(org_id) {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    sessions.remove(org_id);
+                }
+            }
+        }
+    }
 }
 
 impl RelayState {
@@ -16,9 +62,108 @@ impl RelayState {
         Self {
             tunnels: Arc::new(RwLock::new(HashMap::new())),
             lost: Arc::new(RwLock::new(HashMap::new())),
+            org_sessions: Arc::new(Mutex::new(HashMap::new())),
+            relay_bytes: Arc::new(Mutex::new(HashMap::new())),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            next_client_id: Arc::new(AtomicU64::new(0)),
         }
     }
 
+    /// Count a client session against each `(org_id, limit)`; fails with the
+    /// first org at its limit, counting nothing.
+    pub fn open_org_session(
+        &self,
+        limits: &[(String, Option<u64>)],
+    ) -> Result<OrgSessionGuard, (String, u64)> {
+        let mut sessions = self.org_sessions.lock().unwrap();
+        for (org_id, limit) in limits {
+            let open = sessions.get(org_id).copied().unwrap_or(0);
+            if let Some(limit) = limit
+                && open >= *limit
+            {
+                return Err((org_id.clone(), *limit));
+            }
+        }
+        for (org_id, _) in limits {
+            *sessions.entry(org_id.clone()).or_insert(0) += 1;
+        }
+        Ok(OrgSessionGuard {
+            org_sessions: self.org_sessions.clone(),
+            org_ids: limits.iter().map(|(org_id, _)| org_id.clone()).collect(),
+        })
+    }
+
+    /// Client sessions currently open to devices of `org_id`
+    pub fn org_session_count(&self, org_id: &str) -> u64 {
+        self.org_sessions
+            .lock()
+            .unwrap()
+            .get(org_id)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Counter that bytes relayed for `device_short_id` are added to.
+    pub fn relay_meter(&self, device_short_id: &str) -> Arc<AtomicU64> {
+        self.relay_bytes
+            .lock()
+            .unwrap()
+            .entry(device_short_id.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Bytes relayed per device since the last call; counters no forward
+    /// holds any more are dropped.
+    pub fn take_relay_bytes(&self) -> HashMap<String, u64> {
+        let mut meters = self.relay_bytes.lock().unwrap();
+        let taken = meters
+            .iter()
+            .map(|(id, meter)| (id.clone(), meter.swap(0, Ordering::Relaxed)))
+            .filter(|(_, bytes)| *bytes > 0)
+            .collect();
+        meters.retain(|_, meter| Arc::strong_count(meter) > 1);
+        taken
+    }
+
+    /// List a connection of `email` to `device_short_id`, which `close`
+    /// closes, until the guard drops.
+    pub fn register_client(
+        &self,
+        device_short_id: &str,
+        email: &str,
+        close: impl Fn() + Send + 'static,
+    ) -> RelayClientGuard {
+        let id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        self.clients.lock().unwrap().insert(
+            id,
+            RelayClient {
+                device_short_id: device_short_id.to_string(),
+                email: email.to_string(),
+                close: Box::new(close),
+            },
+        );
+        RelayClientGuard {
+            clients: self.clients.clone(),
+            id,
+        }
+    }
+
+    /// Close the connections of `email` to `device_short_id`, with their
+    /// shells, forwards and warm connections. Returns how many were closed.
+    pub fn close_clients(&self, device_short_id: &str, email: &str) -> usize {
+        let clients = self.clients.lock().unwrap();
+        let mut closed = 0;
+        for client in clients.values() {
+            if client.device_short_id == device_short_id && client.email.eq_ignore_ascii_case(email)
+            {
+                (client.close)();
+                closed += 1;
+            }
+        }
+        closed
+    }
+
     /// Insert a new tunnel and close the old one if present.
     pub async fn replace_tunnel(&self, device_short_id: &str, conn: Connection) {
         info!("Replacing tunnel for device {}", device_short_id);
@@ -85,4 +230,61 @@ impl RelayState {
         let tunnels = self.tunnels.read().await;
         tunnels.get(device_short_id).cloned()
     }
+
+    /// Number of active (non-lost) tunnels
+    pub async fn tunnel_count(&self) -> usize {
+        let lost = self.lost.read().await;
+        let tunnels = self.tunnels.read().await;
+        tunnels.keys().filter(|id| !lost.contains_key(*id)).count()
+    }
+
+    /// Short ids of devices with an active (non-lost) tunnel
+    pub async fn tunnel_ids(&self) -> Vec<String> {
+        let lost = self.lost.read().await;
+        let tunnels = self.tunnels.read().await;
+        tunnels
+            .keys()
+            .filter(|id| !lost.contains_key(*id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use super::*;
+
+    fn closer() -> (Arc<AtomicBool>, impl Fn() + Send + 'static) {
+        let closed = Arc::new(AtomicBool::new(false));
+        let flag = closed.clone();
+        (closed, move || flag.store(true, Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_ending_access_closes_only_that_device_and_user() {
+        let relay = RelayState::new();
+        let (granted, close) = closer();
+        let _granted = relay.register_client("dev1", "contractor@example.com", close);
+        let (other_device, close) = closer();
+        let _other_device = relay.register_client("dev2", "contractor@example.com", close);
+        let (other_user, close) = closer();
+        let _other_user = relay.register_client("dev1", "owner@example.com", close);
+
+        assert_eq!(relay.close_clients("dev1", "Contractor@example.com"), 1);
+        assert!(granted.load(Ordering::SeqCst));
+        assert!(!other_device.load(Ordering::SeqCst));
+        assert!(!other_user.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_dropped_connections_are_not_closed() {
+        let relay = RelayState::new();
+        let (closed, close) = closer();
+        drop(relay.register_client("dev1", "contractor@example.com", close));
+
+        assert_eq!(relay.close_clients("dev1", "contractor@example.com"), 0);
+        assert!(!closed.load(Ordering::SeqCst));
+    }
 }
@@ -206,6 +206,11 @@ pub enum ServerError {
     BadRequest(String),
     NotFound(String),
     Timeout(String),
+    UpgradeRequired(String),
+    /// An organization limit would be exceeded
+    QuotaExceeded(String),
+    /// The caller sent too many requests, e.g. login attempts
+    TooManyRequests(String),
 }
 
 impl Display for ServerError {
@@ -216,6 +221,9 @@ impl Display for ServerError {
             ServerError::BadRequest(message) => write!(f, "Bad Request: {}", message),
             ServerError::NotFound(message) => write!(f, "Not Found: {}", message),
             ServerError::Timeout(message) => write!(f, "Timeout: {}", message),
+            ServerError::UpgradeRequired(message) => write!(f, "Upgrade Required: {}", message),
+            ServerError::QuotaExceeded(message) => write!(f, "Quota Exceeded: {}", message),
+            ServerError::TooManyRequests(message) => write!(f, "Too Many Requests: {}", message),
         }
     }
 }
@@ -316,6 +324,18 @@ impl ServerError {
     pub fn timeout(message: &str) -> Self {
         ServerError::Timeout(message.to_string())
     }
+
+    pub fn upgrade_required(message: &str) -> Self {
+        ServerError::UpgradeRequired(message.to_string())
+    }
+
+    pub fn quota_exceeded(message: &str) -> Self {
+        ServerError::QuotaExceeded(message.to_string())
+    }
+
+    pub fn too_many_requests(message: &str) -> Self {
+        ServerError::TooManyRequests(message.to_string())
+    }
 }
 
 // Tell axum how `AppError` should be converted into a response.
@@ -350,6 +370,9 @@ impl IntoResponse for ServerError {
             ServerError::BadRequest(message) => (StatusCode::BAD_REQUEST, message),
             ServerError::NotFound(message) => (StatusCode::NOT_FOUND, message),
             ServerError::Timeout(message) => (StatusCode::REQUEST_TIMEOUT, message),
+            ServerError::UpgradeRequired(message) => (StatusCode::UPGRADE_REQUIRED, message),
+            ServerError::QuotaExceeded(message) => (StatusCode::FORBIDDEN, message),
+            ServerError::TooManyRequests(message) => (StatusCode::TOO_MANY_REQUESTS, message),
         };
 
         error!("Returning error response {} {}", status, message);
@@ -375,3 +398,28 @@ impl IntoResponse for ServerError {
 
 pub type ServerResult<T> = Result<T, ServerError>;
 pub type ServerAppResult<T> = Result<ServerResponse<T>, ServerError>;
+
+impl From<ServerError> for tonic::Status {
+    fn from(err: ServerError) -> Self {
+        error!("Returning gRPC error {}", err);
+        match err {
+            ServerError::InternalError(message) => tonic::Status::internal(message),
+            ServerError::AuthError(AuthError::Forbidden(message)) => {
+                tonic::Status::permission_denied(message)
+            }
+            ServerError::AuthError(AuthError::InvalidToken(message))
+            | ServerError::AuthError(AuthError::MissingToken(message))
+            | ServerError::AuthError(AuthError::ExpiredToken(message))
+            | ServerError::AuthError(AuthError::Unauthorized(message)) => {
+                tonic::Status::unauthenticated(message)
+            }
+            ServerError::BadRequest(message) => tonic::Status::invalid_argument(message),
+            ServerError::NotFound(message) => tonic::Status::not_found(message),
+            ServerError::Timeout(message) => tonic::Status::deadline_exceeded(message),
+            ServerError::UpgradeRequired(message) => tonic::Status::failed_precondition(message),
+            ServerError::QuotaExceeded(message) | ServerError::TooManyRequests(message) => {
+                tonic::Status::resource_exhausted(message)
+            }
+        }
+    }
+}
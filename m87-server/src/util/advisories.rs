@@ -0,0 +1,333 @@
+//! Advisory scan of device package inventories against OSV
+//! (<https://osv.dev>), which merges the distro security trackers and NVD.
+//!
+//! Inventories are scanned shortly after a device reports them and again
+//! every `vulnerability_scan_interval_hours`, so newly published advisories
+//! show up without the device reporting anything. Servers without internet
+//! access point `osv_url` at a mirror or set the interval to 0.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use m87_shared::inventory::{
+    AdvisoryQuery, PackageInventory, Severity, Vulnerability, cvss3_base_score,
+};
+use mongodb::bson::DateTime;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{
+    models::device_inventory::DeviceInventoryDoc, response::ServerResult, util::app_state::AppState,
+};
+
+/// How often the scanner looks for new or outdated scans.
+const TICK: Duration = Duration::from_secs(60);
+/// Inventories scanned per tick.
+const DEVICES_PER_TICK: i64 = 20;
+/// Maximum queries in one OSV `querybatch` request.
+const QUERY_BATCH: usize = 1000;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    #[serde(default)]
+    results: Vec<QueryResult>,
+}
+
+#[derive(Deserialize, Default)]
+struct QueryResult {
+    #[serde(default)]
+    vulns: Vec<VulnRef>,
+    #[serde(default)]
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct VulnRef {
+    id: String,
+    #[serde(default)]
+    modified: String,
+}
+
+#[derive(Deserialize)]
+struct OsvVuln {
+    id: String,
+    #[serde(default)]
+    modified: String,
+    #[serde(default)]
+    summary: Option<String>,
+    #[serde(default)]
+    details: Option<String>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    severity: Vec<OsvSeverity>,
+    #[serde(default)]
+    affected: Vec<OsvAffected>,
+    #[serde(default)]
+    database_specific: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct OsvSeverity {
+    #[serde(rename = "type")]
+    kind: String,
+    score: String,
+}
+
+#[derive(Deserialize)]
+struct OsvAffected {
+    #[serde(default)]
+    package: Option<OsvPackage>,
+    #[serde(default)]
+    ranges: Vec<OsvRange>,
+    #[serde(default)]
+    ecosystem_specific: Option<Value>,
+    #[serde(default)]
+    database_specific: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct OsvPackage {
+    name: String,
+    ecosystem: String,
+}
+
+#[derive(Deserialize)]
+struct OsvRange {
+    #[serde(default)]
+    events: Vec<HashMap<String, String>>,
+}
+
+/// The parts of an OSV record a scan result needs.
+struct Advisory {
+    modified: String,
+    aliases: Vec<String>,
+    severity: Option<Severity>,
+    summary: Option<String>,
+    /// First fixed version per (ecosystem, package)
+    fixed: HashMap<(String, String), String>,
+}
+
+impl From<OsvVuln> for Advisory {
+    fn from(vuln: OsvVuln) -> Self {
+        let scores = vuln.severity.iter().filter_map(|s| match s.kind.as_str() {
+            "CVSS_V3" => cvss3_base_score(&s.score).and_then(Severity::from_score),
+            _ => Severity::from_label(&s.score),
+        });
+        // Debian urgency, GHSA and distro ratings
+        let top_level = vuln
+            .database_specific
+            .as_ref()
+            .and_then(|v| v.get("severity"));
+        let labels = vuln
+            .affected
+            .iter()
+            .flat_map(|a| {
+                [
+                    a.ecosystem_specific.as_ref().and_then(|v| v.get("urgency")),
+                    a.database_specific.as_ref().and_then(|v| v.get("severity")),
+                ]
+            })
+            .chain([top_level])
+            .flatten()
+            .filter_map(|v| v.as_str().and_then(Severity::from_label));
+        let severity = scores.chain(labels).max();
+
+        let mut fixed = HashMap::new();
+        for affected in &vuln.affected {
+            let Some(package) = &affected.package else {
+                continue;
+            };
+            let version = affected
+                .ranges
+                .iter()
+                .flat_map(|r| &r.events)
+                .find_map(|e| e.get("fixed"));
+            if let Some(version) = version {
+                fixed
+                    .entry((package.ecosystem.clone(), package.name.clone()))
+                    .or_insert_with(|| version.clone());
+            }
+        }
+
+        let summary = vuln.summary.filter(|s| !s.trim().is_empty()).or_else(|| {
+            let line = vuln.details?.lines().next()?.trim().to_string();
+            (!line.is_empty()).then(|| truncate(&line, 120))
+        });
+        Advisory {
+            modified: vuln.modified,
+            aliases: vuln.aliases,
+            severity,
+            summary,
+            fixed,
+        }
+    }
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    match s.char_indices().nth(max) {
+        Some((end, _)) => format!("{}…", &s[..end]),
+        None => s.to_string(),
+    }
+}
+
+struct Osv {
+    http: reqwest::Client,
+    url: String,
+    /// Advisories by id, refetched when OSV reports a newer `modified`
+    advisories: HashMap<String, Advisory>,
+}
+
+impl Osv {
+    async fn post<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        body: Value,
+    ) -> Result<T, String> {
+        let res = self
+            .http
+            .post(format!("{}{}", self.url, path))
+            .json(&body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("OSV request failed: {}", e))?;
+        res.json()
+            .await
+            .map_err(|e| format!("Invalid OSV response: {}", e))
+    }
+
+    /// Ids and modification times of the advisories affecting each query.
+    async fn query(&self, queries: &[AdvisoryQuery]) -> Result<Vec<Vec<VulnRef>>, String> {
+        let body = |q: &AdvisoryQuery, page_token: Option<&str>| {
+            let mut query = json!({
+                "package": { "name": q.name, "ecosystem": q.ecosystem },
+                "version": q.version,
+            });
+            if let Some(token) = page_token {
+                query["page_token"] = json!(token);
+            }
+            query
+        };
+        let mut found = Vec::with_capacity(queries.len());
+        for chunk in queries.chunks(QUERY_BATCH) {
+            let batch: BatchResponse = self
+                .post(
+                    "/v1/querybatch",
+                    json!({ "queries": chunk.iter().map(|q| body(q, None)).collect::<Vec<_>>() }),
+                )
+                .await?;
+            let mut results = batch.results.into_iter();
+            for query in chunk {
+                let mut result = results.next().unwrap_or_default();
+                let mut vulns = std::mem::take(&mut result.vulns);
+                // queries with many advisories (old kernels) are paginated
+                while let Some(token) = result.next_page_token.take() {
+                    result = self.post("/v1/query", body(query, Some(&token))).await?;
+                    vulns.append(&mut result.vulns);
+                }
+                found.push(vulns);
+            }
+        }
+        Ok(found)
+    }
+
+    async fn advisory(&mut self, vuln: &VulnRef) -> Result<&Advisory, String> {
+        let stale = self
+            .advisories
+            .get(&vuln.id)
+            .is_none_or(|a| a.modified != vuln.modified);
+        if stale {
+            let record: OsvVuln = self
+                .http
+                .get(format!("{}/v1/vulns/{}", self.url, vuln.id))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| format!("OSV request failed: {}", e))?
+                .json()
+                .await
+                .map_err(|e| format!("Invalid OSV record {}: {}", vuln.id, e))?;
+            self.advisories.insert(record.id.clone(), record.into());
+        }
+        self.advisories
+            .get(&vuln.id)
+            .ok_or_else(|| format!("OSV returned no record for {}", vuln.id))
+    }
+
+    async fn scan(&mut self, inventory: &PackageInventory) -> Result<Vec<Vulnerability>, String> {
+        let queries = inventory.advisory_queries();
+        let found = self.query(&queries).await?;
+        let mut out = Vec::new();
+        for (query, vulns) in queries.iter().zip(found) {
+            for vuln in vulns {
+                let advisory = self.advisory(&vuln).await?;
+                out.push(Vulnerability {
+                    id: vuln.id.clone(),
+                    aliases: advisory.aliases.clone(),
+                    package: query.name.clone(),
+                    installed_version: query.version.clone(),
+                    fixed_version: advisory
+                        .fixed
+                        .get(&(query.ecosystem.clone(), query.name.clone()))
+                        .cloned(),
+                    severity: advisory.severity,
+                    summary: advisory.summary.clone(),
+                });
+            }
+        }
+        out.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.id.cmp(&b.id)));
+        Ok(out)
+    }
+}
+
+/// Scan queued and outdated inventories until the server stops.
+pub async fn run_scanner(state: AppState) {
+    let hours = state.config.vulnerability_scan_interval_hours;
+    if hours == 0 {
+        info!("Vulnerability scanning disabled");
+        return;
+    }
+    let http = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(http) => http,
+        Err(e) => {
+            warn!("Vulnerability scanning disabled: {}", e);
+            return;
+        }
+    };
+    let mut osv = Osv {
+        http,
+        url: state.config.osv_url.trim_end_matches('/').to_string(),
+        advisories: HashMap::new(),
+    };
+    info!("Matching device packages against {}", osv.url);
+
+    let rescan = Duration::from_hours(hours as u64);
+    let mut tick = tokio::time::interval(TICK);
+    loop {
+        tick.tick().await;
+        let before = DateTime::from_system_time(SystemTime::now() - rescan);
+        if let Err(e) = scan_due(&state, &mut osv, before).await {
+            warn!("Vulnerability scan failed: {}", e);
+        }
+    }
+}
+
+async fn scan_due(state: &AppState, osv: &mut Osv, scanned_before: DateTime) -> ServerResult<()> {
+    let due = DeviceInventoryDoc::due_for_scan(&state.db, scanned_before, DEVICES_PER_TICK).await?;
+    for doc in due {
+        let result = osv.scan(&doc.inventory).await;
+        let failed = result.is_err();
+        if let Err(e) = &result {
+            warn!("Advisory scan of device {} failed: {}", doc.device_id, e);
+        }
+        doc.set_scan_result(&state.db, result).await?;
+        // OSV is unreachable or rejecting requests: retry on the next tick
+        if failed {
+            break;
+        }
+    }
+    Ok(())
+}
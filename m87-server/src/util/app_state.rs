@@ -1,10 +1,17 @@
 use std::sync::Arc;
 
-use crate::{config::AppConfig, db::Mongo, relay::relay_state::RelayState};
+use crate::{
+    config::AppConfig,
+    db::Mongo,
+    relay::relay_state::RelayState,
+    util::{events::EventBus, iot_bridge::IotBridge},
+};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: Arc<Mongo>,
     pub config: Arc<AppConfig>,
     pub relay: Arc<RelayState>,
+    pub events: EventBus,
+    pub iot: IotBridge,
 }
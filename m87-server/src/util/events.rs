@@ -0,0 +1,59 @@
+use std::sync::Arc;
+
+use m87_shared::deploy_spec::DeployReportKind;
+use tokio::sync::broadcast;
+
+use crate::models::device::DeviceDoc;
+
+/// Events buffered per subscriber before slow subscribers start missing events.
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Debug, Clone)]
+pub enum DeviceEventKind {
+    Connected,
+    Disconnected,
+    Report(DeployReportKind),
+    /// A device alert rule fired
+    Alert { rule: String, message: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct DeviceEvent {
+    /// Device the event belongs to. Subscribers use its scopes for access checks.
+    pub device: Arc<DeviceDoc>,
+    /// Unix time in milliseconds
+    pub time: u64,
+    pub kind: DeviceEventKind,
+}
+
+/// In-process fan-out of device events (used by the gRPC `StreamEvents` RPC).
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<DeviceEvent>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUFFER);
+        Self { tx }
+    }
+
+    pub fn publish(&self, device: &DeviceDoc, kind: DeviceEventKind) {
+        // no subscribers is fine
+        let _ = self.tx.send(DeviceEvent {
+            device: Arc::new(device.clone()),
+            time: mongodb::bson::DateTime::now().timestamp_millis() as u64,
+            kind,
+        });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DeviceEvent> {
+        self.tx.subscribe()
+    }
+}
@@ -0,0 +1,636 @@
+//! Republishing device presence and heartbeat telemetry to the IoT platforms
+//! organizations bridge to (`m87 org iot-bridge`).
+//!
+//! Heartbeats hand their metrics to [`IotBridge`] without waiting on it;
+//! [`run`] publishes them, at most once per device and telemetry interval,
+//! along with connects and disconnects from the event bus. Bridge changes
+//! apply within [`RELOAD_INTERVAL`], and telemetry is dropped rather than
+//! queued when a platform is slower than the fleet.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use hmac::{Hmac, Mac};
+use m87_shared::iot_bridge::{IotBridgeTarget, IotPresence, IotTelemetry};
+use m87_shared::metrics::SystemMetrics;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, mpsc};
+use tracing::warn;
+
+use crate::{
+    db::Mongo,
+    models::{device::DeviceDoc, iot_bridge::IotBridgeDoc},
+    util::{app_state::AppState, events::DeviceEventKind},
+};
+
+/// Heartbeats queued for the publisher before new ones are dropped.
+const QUEUE: usize = 1024;
+/// How long a changed bridge takes to apply.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Lifetime of the Azure SAS tokens the server signs.
+const SAS_TTL_SECS: i64 = 3600;
+/// ThingsBoard access tokens last 2.5h by default; log in again before.
+const THINGSBOARD_LOGIN_TTL: Duration = Duration::from_secs(3600);
+/// Least time between two successful publishes noted on a bridge.
+const RECORD_INTERVAL: Duration = Duration::from_secs(60);
+
+type Queued = (DeviceDoc, IotTelemetry);
+
+/// Handle heartbeats queue telemetry on.
+#[derive(Clone)]
+pub struct IotBridge {
+    tx: mpsc::Sender<Queued>,
+    /// Whether any org has a bridge, so heartbeats skip the copy otherwise
+    active: Arc<AtomicBool>,
+}
+
+impl IotBridge {
+    /// The handle and the queue to pass to [`run`].
+    pub fn new() -> (Self, mpsc::Receiver<Queued>) {
+        let (tx, rx) = mpsc::channel(QUEUE);
+        let bridge = Self {
+            tx,
+            active: Arc::new(AtomicBool::new(false)),
+        };
+        (bridge, rx)
+    }
+
+    pub fn telemetry(&self, device: &DeviceDoc, metrics: &SystemMetrics) {
+        if !self.active.load(Ordering::Relaxed) {
+            return;
+        }
+        let telemetry = IotTelemetry::from_metrics(
+            &device.short_id,
+            &device.name,
+            metrics,
+            mongodb::bson::DateTime::now().timestamp_millis() as u64,
+        );
+        // the publisher is behind; the next heartbeat brings fresh metrics
+        let _ = self.tx.try_send((device.clone(), telemetry));
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Message {
+    Presence(IotPresence),
+    Telemetry(IotTelemetry),
+}
+
+impl Message {
+    fn kind(&self) -> &'static str {
+        match self {
+            Message::Presence(_) => "presence",
+            Message::Telemetry(_) => "telemetry",
+        }
+    }
+
+    fn device_id(&self) -> &str {
+        match self {
+            Message::Presence(p) => &p.device_id,
+            Message::Telemetry(t) => &t.device_id,
+        }
+    }
+
+    fn body(&self) -> Value {
+        match self {
+            Message::Presence(p) => json!(p),
+            Message::Telemetry(t) => json!(t),
+        }
+    }
+}
+
+/// Publish presence and telemetry of devices to the bridges of their orgs,
+/// until the server stops.
+pub async fn run(state: AppState, mut telemetry: mpsc::Receiver<Queued>) {
+    let mut events = state.events.subscribe();
+    let publisher = Arc::new(Publisher::default());
+    let mut bridges: Vec<Arc<IotBridgeDoc>> = Vec::new();
+    let mut loaded_at: Option<Instant> = None;
+    // last telemetry published, by org and device
+    let mut published: HashMap<(String, String), Instant> = HashMap::new();
+    loop {
+        if loaded_at.is_none_or(|at| at.elapsed() >= RELOAD_INTERVAL) {
+            match IotBridgeDoc::all(&state.db).await {
+                Ok(docs) => {
+                    bridges = docs.into_iter().map(Arc::new).collect();
+                    state
+                        .iot
+                        .active
+                        .store(!bridges.is_empty(), Ordering::Relaxed);
+                }
+                Err(e) => warn!("Loading IoT bridges failed: {}", e),
+            }
+            loaded_at = Some(Instant::now());
+        }
+
+        let (device, message) = tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let online = match event.kind {
+                        DeviceEventKind::Connected => true,
+                        DeviceEventKind::Disconnected => false,
+                        _ => continue,
+                    };
+                    let presence = IotPresence {
+                        device_id: event.device.short_id.clone(),
+                        device_name: event.device.name.clone(),
+                        online,
+                        time: event.time,
+                    };
+                    (event.device, Message::Presence(presence))
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("IoT bridge missed {} device events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            Some((device, t)) = telemetry.recv() => (Arc::new(device), Message::Telemetry(t)),
+            _ = tokio::time::sleep(RELOAD_INTERVAL) => continue,
+        };
+
+        for bridge in bridges.iter().filter(|b| b.covers(&device)) {
+            if matches!(message, Message::Telemetry(_)) {
+                let key = (bridge.org_id.clone(), device.short_id.clone());
+                let interval = Duration::from_secs(bridge.telemetry_interval_secs);
+                if published
+                    .get(&key)
+                    .is_some_and(|at| at.elapsed() < interval)
+                {
+                    continue;
+                }
+                published.insert(key, Instant::now());
+            }
+            tokio::spawn(publisher.clone().publish(
+                state.db.clone(),
+                bridge.clone(),
+                message.clone(),
+            ));
+        }
+    }
+}
+
+#[derive(Default)]
+struct Publisher {
+    http: reqwest::Client,
+    /// ThingsBoard access tokens by url and username
+    thingsboard_logins: Mutex<HashMap<(String, String), (String, Instant)>>,
+    /// ThingsBoard device ids by url and m87 device id
+    thingsboard_devices: Mutex<HashMap<(String, String), String>>,
+    /// Outcome last noted on each org's bridge
+    recorded: Mutex<HashMap<String, (Option<String>, Instant)>>,
+}
+
+impl Publisher {
+    async fn publish(self: Arc<Self>, db: Arc<Mongo>, bridge: Arc<IotBridgeDoc>, message: Message) {
+        let result = match &bridge.target {
+            IotBridgeTarget::AwsIot {
+                endpoint,
+                access_key_id,
+                secret_access_key,
+                topic_prefix,
+            } => {
+                self.publish_aws(
+                    endpoint,
+                    access_key_id,
+                    secret_access_key,
+                    topic_prefix,
+                    &message,
+                )
+                .await
+            }
+            IotBridgeTarget::AzureIotHub {
+                host_name,
+                policy_name,
+                policy_key,
+            } => {
+                self.publish_azure(host_name, policy_name, policy_key, &message)
+                    .await
+            }
+            IotBridgeTarget::Thingsboard {
+                url,
+                username,
+                password,
+            } => {
+                self.publish_thingsboard(url, username, password, &message)
+                    .await
+            }
+        };
+        let error = result.err();
+
+        // note changes at once and successes once a minute
+        let mut recorded = self.recorded.lock().await;
+        let due = match recorded.get(&bridge.org_id) {
+            Some((last, at)) => {
+                last != &error || (error.is_none() && at.elapsed() >= RECORD_INTERVAL)
+            }
+            None => true,
+        };
+        if !due {
+            return;
+        }
+        recorded.insert(bridge.org_id.clone(), (error.clone(), Instant::now()));
+        drop(recorded);
+        if let Some(e) = &error {
+            warn!(
+                "IoT bridge of org {} failed to publish {}: {}",
+                bridge.org_id,
+                message.kind(),
+                e
+            );
+        }
+        if let Err(e) = IotBridgeDoc::record(&db, &bridge.org_id, error).await {
+            warn!(
+                "Saving the IoT bridge state of org {} failed: {}",
+                bridge.org_id, e
+            );
+        }
+    }
+
+    /// Publish to `<topic_prefix>/<device>/<kind>` through the HTTPS data
+    /// plane.
+    async fn publish_aws(
+        &self,
+        endpoint: &str,
+        access_key_id: &str,
+        secret_access_key: &str,
+        topic_prefix: &str,
+        message: &Message,
+    ) -> Result<(), String> {
+        let region = aws_region(endpoint)
+            .ok_or_else(|| format!("cannot tell the AWS region of {}", endpoint))?;
+        let topic: Vec<String> = topic_prefix
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .chain([message.device_id(), message.kind()])
+            .map(percent_encode)
+            .collect();
+        let path = format!("/topics/{}", topic.join("/"));
+        let body = message.body().to_string();
+
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        // paths are encoded twice for signing, except for S3
+        let canonical_path = path.split('/').map(percent_encode).collect::<Vec<_>>();
+        let canonical_request = format!(
+            "POST\n{}\nqos=1\nhost:{}\nx-amz-date:{}\n\nhost;x-amz-date\n{}",
+            canonical_path.join("/"),
+            endpoint,
+            amz_date,
+            hex::encode(Sha256::digest(body.as_bytes()))
+        );
+        let authorization = sigv4_authorization(
+            access_key_id,
+            secret_access_key,
+            &amz_date,
+            region,
+            "iotdata",
+            "host;x-amz-date",
+            &canonical_request,
+        );
+        self.http
+            .post(format!("https://{}{}?qos=1", endpoint, path))
+            .timeout(REQUEST_TIMEOUT)
+            .header("x-amz-date", amz_date)
+            .header(AUTHORIZATION, authorization)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("AWS IoT: {}", e))?;
+        Ok(())
+    }
+
+    /// Send a device-to-cloud message on behalf of the device, registering
+    /// it first when the hub does not know it.
+    async fn publish_azure(
+        &self,
+        host_name: &str,
+        policy_name: &str,
+        policy_key: &str,
+        message: &Message,
+    ) -> Result<(), String> {
+        let token = azure_sas_token(
+            host_name,
+            policy_name,
+            policy_key,
+            chrono::Utc::now().timestamp() + SAS_TTL_SECS,
+        )?;
+        let device_id = percent_encode(message.device_id());
+        let body = message.body().to_string();
+        let send = || {
+            self.http
+                .post(format!(
+                    "https://{}/devices/{}/messages/events?api-version=2020-03-13",
+                    host_name, device_id
+                ))
+                .timeout(REQUEST_TIMEOUT)
+                .header(AUTHORIZATION, &token)
+                .header(CONTENT_TYPE, "application/json")
+                .header("iothub-app-m87-type", message.kind())
+                .body(body.clone())
+                .send()
+        };
+        let res = send().await.map_err(|e| format!("Azure IoT Hub: {}", e))?;
+        let res = if res.status() == StatusCode::NOT_FOUND {
+            self.http
+                .put(format!(
+                    "https://{}/devices/{}?api-version=2021-04-12",
+                    host_name, device_id
+                ))
+                .timeout(REQUEST_TIMEOUT)
+                .header(AUTHORIZATION, &token)
+                .json(&json!({ "deviceId": message.device_id() }))
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| format!("Azure IoT Hub: registering the device: {}", e))?;
+            send().await.map_err(|e| format!("Azure IoT Hub: {}", e))?
+        } else {
+            res
+        };
+        res.error_for_status()
+            .map_err(|e| format!("Azure IoT Hub: {}", e))?;
+        Ok(())
+    }
+
+    /// Save telemetry as time series and presence as the `m87_online`
+    /// server attribute of the ThingsBoard device named like the m87 one.
+    async fn publish_thingsboard(
+        &self,
+        url: &str,
+        username: &str,
+        password: &str,
+        message: &Message,
+    ) -> Result<(), String> {
+        let url = url.trim_end_matches('/');
+        let mut token = self
+            .thingsboard_login(url, username, password, false)
+            .await?;
+        if !self.thingsboard_send(url, &token, message).await? {
+            // revoked or expired early
+            token = self
+                .thingsboard_login(url, username, password, true)
+                .await?;
+            if !self.thingsboard_send(url, &token, message).await? {
+                return Err("ThingsBoard rejected a fresh login".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    async fn thingsboard_login(
+        &self,
+        url: &str,
+        username: &str,
+        password: &str,
+        renew: bool,
+    ) -> Result<String, String> {
+        #[derive(Deserialize)]
+        struct Login {
+            token: String,
+        }
+
+        let key = (url.to_string(), username.to_string());
+        let mut logins = self.thingsboard_logins.lock().await;
+        if !renew
+            && let Some((token, at)) = logins.get(&key)
+            && at.elapsed() < THINGSBOARD_LOGIN_TTL
+        {
+            return Ok(token.clone());
+        }
+        let login: Login = self
+            .http
+            .post(format!("{}/api/auth/login", url))
+            .timeout(REQUEST_TIMEOUT)
+            .json(&json!({ "username": username, "password": password }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("ThingsBoard login: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid ThingsBoard login response: {}", e))?;
+        logins.insert(key, (login.token.clone(), Instant::now()));
+        Ok(login.token)
+    }
+
+    /// Ok(false) when ThingsBoard no longer accepts the token.
+    async fn thingsboard_send(
+        &self,
+        url: &str,
+        token: &str,
+        message: &Message,
+    ) -> Result<bool, String> {
+        let Some(device) = self.thingsboard_device(url, token, message).await? else {
+            return Ok(false);
+        };
+        let (path, body) = match message {
+            Message::Presence(p) => (
+                format!(
+                    "/api/plugins/telemetry/DEVICE/{}/attributes/SERVER_SCOPE",
+                    device
+                ),
+                json!({ "m87_online": p.online, "m87_presence_time": p.time }),
+            ),
+            Message::Telemetry(t) => {
+                let mut values = json!(t);
+                if let Some(values) = values.as_object_mut() {
+                    for key in ["device_id", "device_name", "time"] {
+                        values.remove(key);
+                    }
+                }
+                (
+                    format!("/api/plugins/telemetry/DEVICE/{}/timeseries/ANY", device),
+                    json!({ "ts": t.time, "values": values }),
+                )
+            }
+        };
+        let res = self
+            .http
+            .post(format!("{}{}", url, path))
+            .timeout(REQUEST_TIMEOUT)
+            .header("X-Authorization", format!("Bearer {}", token))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| format!("ThingsBoard: {}", e))?;
+        match res.status() {
+            StatusCode::UNAUTHORIZED => return Ok(false),
+            StatusCode::NOT_FOUND => {
+                // deleted on ThingsBoard; created again on the next message
+                let key = (url.to_string(), message.device_id().to_string());
+                self.thingsboard_devices.lock().await.remove(&key);
+            }
+            _ => {}
+        }
+        res.error_for_status()
+            .map_err(|e| format!("ThingsBoard: {}", e))?;
+        Ok(true)
+    }
+
+    /// Id of the ThingsBoard device of the message, created on first use;
+    /// None when the token is no longer accepted.
+    async fn thingsboard_device(
+        &self,
+        url: &str,
+        token: &str,
+        message: &Message,
+    ) -> Result<Option<String>, String> {
+        #[derive(Deserialize)]
+        struct EntityId {
+            id: String,
+        }
+        #[derive(Deserialize)]
+        struct Device {
+            id: EntityId,
+        }
+
+        let key = (url.to_string(), message.device_id().to_string());
+        if let Some(id) = self.thingsboard_devices.lock().await.get(&key) {
+            return Ok(Some(id.clone()));
+        }
+        let bearer = format!("Bearer {}", token);
+        let res = self
+            .http
+            .get(format!(
+                "{}/api/tenant/devices?deviceName={}",
+                url,
+                percent_encode(message.device_id())
+            ))
+            .timeout(REQUEST_TIMEOUT)
+            .header("X-Authorization", &bearer)
+            .send()
+            .await
+            .map_err(|e| format!("ThingsBoard: {}", e))?;
+        let res = match res.status() {
+            StatusCode::UNAUTHORIZED => return Ok(None),
+            StatusCode::NOT_FOUND => {
+                let device_name = match message {
+                    Message::Presence(p) => &p.device_name,
+                    Message::Telemetry(t) => &t.device_name,
+                };
+                self.http
+                    .post(format!("{}/api/device", url))
+                    .timeout(REQUEST_TIMEOUT)
+                    .header("X-Authorization", &bearer)
+                    .json(&json!({
+                        "name": message.device_id(),
+                        "label": device_name,
+                        "type": "m87",
+                    }))
+                    .send()
+                    .await
+                    .map_err(|e| format!("ThingsBoard: creating the device: {}", e))?
+            }
+            _ => res,
+        };
+        let device: Device = res
+            .error_for_status()
+            .map_err(|e| format!("ThingsBoard: {}", e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid ThingsBoard device: {}", e))?;
+        self.thingsboard_devices
+            .lock()
+            .await
+            .insert(key, device.id.id.clone());
+        Ok(Some(device.id.id))
+    }
+}
+
+/// Region of an AWS IoT data endpoint like
+/// `abc123-ats.iot.eu-central-1.amazonaws.com`.
+fn aws_region(endpoint: &str) -> Option<&str> {
+    let mut labels = endpoint.split('.');
+    labels.find(|label| *label == "iot")?;
+    labels.next().filter(|region| !region.is_empty())
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `Authorization` header of a request signed with AWS Signature Version 4.
+fn sigv4_authorization(
+    access_key_id: &str,
+    secret_access_key: &str,
+    amz_date: &str,
+    region: &str,
+    service: &str,
+    signed_headers: &str,
+    canonical_request: &str,
+) -> String {
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac_sha256(
+        format!("AWS4{}", secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [region, service, "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key_id,
+        scope,
+        signed_headers,
+        hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()))
+    )
+}
+
+/// Shared access signature for the whole hub, valid until `expiry` (unix
+/// seconds).
+fn azure_sas_token(
+    host_name: &str,
+    policy_name: &str,
+    policy_key: &str,
+    expiry: i64,
+) -> Result<String, String> {
+    let key = BASE64
+        .decode(policy_key)
+        .map_err(|_| "the Azure policy key is not base64".to_string())?;
+    let resource = percent_encode(&host_name.to_lowercase());
+    let signature = BASE64.encode(hmac_sha256(
+        &key,
+        format!("{}\n{}", resource, expiry).as_bytes(),
+    ));
+    Ok(format!(
+        "SharedAccessSignature sr={}&sig={}&se={}&skn={}",
+        resource,
+        percent_encode(&signature),
+        expiry,
+        percent_encode(policy_name)
+    ))
+}
+
+/// `s` with everything but RFC 3986 unreserved characters percent-encoded.
+fn percent_encode(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for b in s.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
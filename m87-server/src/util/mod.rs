@@ -1,3 +1,8 @@
+pub mod advisories;
 pub mod app_state;
+pub mod events;
+pub mod fs;
+pub mod iot_bridge;
 pub mod logging;
+pub mod net;
 pub mod pagination;
@@ -0,0 +1,45 @@
+//! Listening sockets. The server binds `[::]` with IPV6_V6ONLY off so one
+//! socket serves IPv6 and IPv4 (as mapped addresses), and falls back to
+//! `0.0.0.0` on hosts without IPv6.
+
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Wildcard address to listen on `port`, dual-stack where IPv6 is available.
+pub fn listen_addr(port: u16) -> SocketAddr {
+    if dual_stack_socket(Type::DGRAM, Protocol::UDP, 0).is_ok() {
+        SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))
+    } else {
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))
+    }
+}
+
+/// QUIC server endpoint on `port` of all addresses.
+pub fn quic_endpoint(config: quinn::ServerConfig, port: u16) -> io::Result<quinn::Endpoint> {
+    let socket = match dual_stack_socket(Type::DGRAM, Protocol::UDP, port) {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("IPv6 unavailable ({}), QUIC listens on IPv4 only", e);
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+            socket
+        }
+    };
+    socket.set_nonblocking(true)?;
+    quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(config),
+        UdpSocket::from(socket),
+        Arc::new(quinn::TokioRuntime),
+    )
+}
+
+fn dual_stack_socket(ty: Type, protocol: Protocol, port: u16) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV6, ty, Some(protocol))?;
+    socket.set_only_v6(false)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    Ok(socket)
+}
@@ -0,0 +1,18 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        // SAFETY: build scripts are single threaded
+        unsafe {
+            std::env::set_var(
+                "PROTOC",
+                protoc_bin_vendored::protoc_bin_path().expect("vendored protoc"),
+            );
+        }
+        println!("cargo:rerun-if-changed=proto");
+        tonic_prost_build::configure()
+            .build_client(true)
+            .build_server(true)
+            .compile_protos(&["proto/make87/v1/make87.proto"], &["proto"])
+            .expect("failed to compile make87 protos");
+    }
+}
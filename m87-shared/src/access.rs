@@ -0,0 +1,37 @@
+//! Time-boxed access grants: a role on one device for one user, ending on
+//! its own after a TTL, for people who should not be permanent members.
+
+use serde::{Deserialize, Serialize};
+
+use crate::roles::Role;
+
+/// Longest a grant may last
+pub const MAX_GRANT_TTL_SECS: u64 = 30 * 24 * 3600;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AccessGrant {
+    pub id: String,
+    pub email: String,
+    pub device_id: String,
+    pub device_name: String,
+    pub role: Role,
+    pub granted_by: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub created_at: String,
+    pub expires_at: String,
+    /// Whether the grant still gives access
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_by: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateAccessGrantBody {
+    pub email: String,
+    pub device_id: String,
+    pub role: Role,
+    pub ttl_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
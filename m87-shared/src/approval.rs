@@ -0,0 +1,225 @@
+//! Two-person approval of destructive operations on devices that require it.
+//!
+//! When someone opens a shell, exec, ssh, file, gui, vnc, docker, forward or
+//! serial session, changes packages or deploys on such a device, the server
+//! files an [`ApprovalRequest`] and
+//! refuses the operation. Once a second operator approved the request, the
+//! requester's next attempt at the same operation goes through, once, within
+//! [`APPROVAL_VALIDITY_MINUTES`].
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// How long a request waits for a decision
+pub const PENDING_TTL_MINUTES: u64 = 60;
+/// How long an approval can be used once given
+pub const APPROVAL_VALIDITY_MINUTES: u64 = 15;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApprovalStatus {
+    Pending,
+    Approved,
+    Rejected,
+    /// The approved operation ran
+    Used,
+    /// Nobody decided, or the approval was not used, in time
+    Expired,
+}
+
+impl ApprovalStatus {
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "pending" => Some(ApprovalStatus::Pending),
+            "approved" => Some(ApprovalStatus::Approved),
+            "rejected" => Some(ApprovalStatus::Rejected),
+            "used" => Some(ApprovalStatus::Used),
+            "expired" => Some(ApprovalStatus::Expired),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ApprovalStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ApprovalStatus::Pending => "pending",
+            ApprovalStatus::Approved => "approved",
+            ApprovalStatus::Rejected => "rejected",
+            ApprovalStatus::Used => "used",
+            ApprovalStatus::Expired => "expired",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApprovalRequest {
+    pub id: String,
+    pub device_id: String,
+    pub device_name: String,
+    /// `shell`, `exec`, `ssh`, `files`, `gui`, `vnc`, `docker`, `forward`,
+    /// `serial`, `packages` or `deploy`
+    pub operation: String,
+    /// What exactly is approved, e.g. the packages to remove
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub details: String,
+    pub requested_by: String,
+    pub status: ApprovalStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decided_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub created_at: String,
+    /// End of the wait for a decision, or of the approval's validity
+    pub expires_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DecideApprovalBody {
+    pub approve: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// Longest details shown to approvers, in characters
+pub const MAX_DETAILS_CHARS: usize = 200;
+
+/// The operation and details needing approval for the stream described by
+/// `header`, if it needs any.
+pub fn stream_operation(header: &serde_json::Value) -> Option<(&'static str, String)> {
+    let flag = |name: &str| header.get(name).and_then(|v| v.as_bool()) == Some(true);
+    let text = |name: &str| {
+        header
+            .get(name)
+            .and_then(|v| v.as_str())
+            .map(truncate_details)
+            .unwrap_or_default()
+    };
+    match header.get("type").and_then(|t| t.as_str())? {
+        // watching someone else's session; devices refuse read-only
+        // terminals that attach to nothing
+        "Terminal" if flag("read_only") && header.get("attach").is_some_and(|a| a.is_string()) => {
+            None
+        }
+        "Terminal" => Some(("shell", String::new())),
+        "Exec" => Some(("exec", text("command"))),
+        "Gui" => Some(("gui", text("command"))),
+        "Vnc" => Some((
+            "vnc",
+            header
+                .get("port")
+                .and_then(|p| p.as_u64())
+                .map(|p| format!("port {}", p))
+                .unwrap_or_default(),
+        )),
+        "Docker" => Some(("docker", String::new())),
+        "Forward" => Some((
+            "forward",
+            header
+                .get("target")
+                .map(|t| truncate_details(&t.to_string()))
+                .unwrap_or_default(),
+        )),
+        "Serial" => Some(("serial", text("name"))),
+        "Ssh" if flag("transfer") => Some(("files", String::new())),
+        "Ssh" => Some(("ssh", String::new())),
+        "Packages" if flag("dry_run") => None,
+        "Packages" => {
+            let action = header.get("action").and_then(|a| a.as_str()).unwrap_or("");
+            let packages: Vec<&str> = header
+                .get("packages")
+                .and_then(|p| p.as_array())
+                .map(|p| p.iter().filter_map(|p| p.as_str()).collect())
+                .unwrap_or_default();
+            Some((
+                "packages",
+                format!("{} {}", action, packages.join(" "))
+                    .trim()
+                    .to_string(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn truncate_details(details: &str) -> String {
+    match details.char_indices().nth(MAX_DETAILS_CHARS) {
+        Some((end, _)) => format!("{}…", &details[..end]),
+        None => details.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_stream_operation() {
+        assert_eq!(
+            stream_operation(&json!({ "type": "Exec", "token": "t", "command": "rm -rf /data" })),
+            Some(("exec", "rm -rf /data".to_string()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Terminal", "read_only": true, "attach": "ops" })),
+            None
+        );
+        // read-only without a session to watch is a plain shell
+        assert_eq!(
+            stream_operation(&json!({ "type": "Terminal", "read_only": true, "attach": null })),
+            Some(("shell", String::new()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Terminal", "attach": "ops" })),
+            Some(("shell", String::new()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Gui", "command": "xterm" })),
+            Some(("gui", "xterm".to_string()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Docker" })),
+            Some(("docker", String::new()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Serial", "name": "ttyUSB0", "baud": 9600 })),
+            Some(("serial", "ttyUSB0".to_string()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Vnc", "port": 5901 })),
+            Some(("vnc", "port 5901".to_string()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Forward", "target": { "Tcp": { "port": 22 } } })),
+            Some(("forward", r#"{"Tcp":{"port":22}}"#.to_string()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Ssh", "transfer": true })),
+            Some(("files", String::new()))
+        );
+        assert_eq!(
+            stream_operation(&json!({
+                "type": "Packages",
+                "action": "remove",
+                "packages": ["nginx", "curl"],
+            })),
+            Some(("packages", "remove nginx curl".to_string()))
+        );
+        assert_eq!(
+            stream_operation(&json!({ "type": "Packages", "action": "upgrade", "dry_run": true })),
+            None
+        );
+        assert_eq!(stream_operation(&json!({ "type": "Logs" })), None);
+    }
+
+    #[test]
+    fn test_long_commands_are_truncated() {
+        let command = "é".repeat(MAX_DETAILS_CHARS + 10);
+        let (_, details) =
+            stream_operation(&json!({ "type": "Exec", "command": command })).unwrap();
+        assert_eq!(details.chars().count(), MAX_DETAILS_CHARS + 1);
+        assert!(details.ends_with('…'));
+    }
+}
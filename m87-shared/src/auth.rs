@@ -7,6 +7,9 @@ pub struct DeviceAuthRequestBody {
     pub device_info: DeviceSystemInfo,
     pub owner_scope: String,
     pub device_id: String,
+    /// From an install command; the request is approved when it is valid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enrollment_token: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,3 +35,17 @@ pub struct DeviceAuthRequest {
     pub device_info: DeviceSystemInfo,
     pub created_at: String,
 }
+
+/// Password login against a server running in local auth mode
+#[derive(Serialize, Deserialize)]
+pub struct LocalLoginBody {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LocalLoginResponse {
+    pub access_token: String,
+    /// Unix timestamp the token expires at
+    pub expires_at: u64,
+}
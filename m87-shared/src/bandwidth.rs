@@ -0,0 +1,354 @@
+//! Traffic of a runtime per kind, so operators see which feature uses a
+//! device's data plan.
+//!
+//! The runtime counts payload bytes since it started and reports the
+//! counters with heartbeats; the server adds what grew since the previous
+//! report to the device's totals, which survive runtime restarts.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TrafficClass {
+    /// Heartbeats, config and reports on the control stream
+    Control,
+    /// Deployment revisions and deploy reports
+    Deploy,
+    Logs,
+    Metrics,
+    /// Shell, exec, ssh, serial, GUI and VNC sessions
+    Interactive,
+    /// File transfers and support bundles
+    Files,
+    /// Port forwards
+    Forward,
+    Other,
+}
+
+impl TrafficClass {
+    pub const ALL: [TrafficClass; 8] = [
+        TrafficClass::Control,
+        TrafficClass::Deploy,
+        TrafficClass::Logs,
+        TrafficClass::Metrics,
+        TrafficClass::Interactive,
+        TrafficClass::Files,
+        TrafficClass::Forward,
+        TrafficClass::Other,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrafficClass::Control => "control",
+            TrafficClass::Deploy => "deploy",
+            TrafficClass::Logs => "logs",
+            TrafficClass::Metrics => "metrics",
+            TrafficClass::Interactive => "interactive",
+            TrafficClass::Files => "files",
+            TrafficClass::Forward => "forward",
+            TrafficClass::Other => "other",
+        }
+    }
+}
+
+impl Display for TrafficClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How a runtime trades freshness for data, set per device with
+/// `m87 <device> bandwidth-profile` and sent with its config.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum BandwidthProfile {
+    #[default]
+    Normal,
+    /// For metered links: rarer heartbeats, no service logs unless asked
+    /// for, stronger compression, fewer metrics and shorter report log tails
+    Low,
+}
+
+impl BandwidthProfile {
+    pub fn is_normal(&self) -> bool {
+        *self == BandwidthProfile::Normal
+    }
+
+    /// Heartbeat interval the configured one is raised to
+    pub fn min_heartbeat_interval_secs(&self) -> u64 {
+        match self {
+            BandwidthProfile::Normal => 0,
+            BandwidthProfile::Low => 900,
+        }
+    }
+
+    /// Whether a logs stream follows service logs without the CLI asking
+    /// for them with `--follow`
+    pub fn follows_logs(&self) -> bool {
+        self.is_normal()
+    }
+
+    /// zstd level of compressed streams
+    pub fn compression_level(&self) -> i32 {
+        match self {
+            BandwidthProfile::Normal => 3,
+            BandwidthProfile::Low => 12,
+        }
+    }
+
+    /// Seconds between samples on a metrics stream
+    pub fn metrics_interval_secs(&self) -> u64 {
+        match self {
+            BandwidthProfile::Normal => 1,
+            BandwidthProfile::Low => 5,
+        }
+    }
+
+    /// Whether metrics keep per-core and per-interface detail
+    pub fn detailed_metrics(&self) -> bool {
+        self.is_normal()
+    }
+
+    /// Longest log tail a deploy report carries, in bytes
+    pub fn max_report_log_tail(&self) -> Option<usize> {
+        match self {
+            BandwidthProfile::Normal => None,
+            BandwidthProfile::Low => Some(512),
+        }
+    }
+
+    /// How long deploy reports wait to be uploaded together; 0 uploads them
+    /// as they happen. Heartbeats carry a digest of the waiting ones.
+    pub fn report_upload_interval_secs(&self) -> u64 {
+        match self {
+            BandwidthProfile::Normal => 0,
+            BandwidthProfile::Low => 3600,
+        }
+    }
+}
+
+impl Display for BandwidthProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BandwidthProfile::Normal => f.write_str("normal"),
+            BandwidthProfile::Low => f.write_str("low"),
+        }
+    }
+}
+
+impl FromStr for BandwidthProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(BandwidthProfile::Normal),
+            "low" => Ok(BandwidthProfile::Low),
+            other => Err(format!(
+                "unknown bandwidth profile '{}', expected normal or low",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrafficBytes {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl TrafficBytes {
+    pub fn total(&self) -> u64 {
+        self.sent.saturating_add(self.received)
+    }
+
+    fn add(&mut self, other: &TrafficBytes) {
+        self.sent = self.sent.saturating_add(other.sent);
+        self.received = self.received.saturating_add(other.received);
+    }
+
+    fn since(&self, earlier: &TrafficBytes) -> TrafficBytes {
+        TrafficBytes {
+            sent: self.sent.saturating_sub(earlier.sent),
+            received: self.received.saturating_sub(earlier.received),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClassTraffic {
+    pub class: TrafficClass,
+    #[serde(flatten)]
+    pub bytes: TrafficBytes,
+}
+
+/// Counters of a runtime since it started, sent with heartbeats.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BandwidthReport {
+    /// Unix time the runtime started counting; another value than in the
+    /// previous report means the counters restarted
+    pub since: u64,
+    /// Payload bytes per class, classes without traffic left out
+    #[serde(default)]
+    pub classes: Vec<ClassTraffic>,
+    /// UDP bytes of the runtime's connections to the relay, including QUIC
+    /// and TLS overhead; None from runtimes that do not count them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wire: Option<TrafficBytes>,
+}
+
+impl BandwidthReport {
+    pub fn class(&self, class: TrafficClass) -> TrafficBytes {
+        self.classes
+            .iter()
+            .find(|c| c.class == class)
+            .map(|c| c.bytes)
+            .unwrap_or_default()
+    }
+}
+
+/// Traffic of a device as kept by the server: its totals over runtime
+/// restarts and the runtime's last report. Body of
+/// `GET /device/{id}/bandwidth`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct DeviceBandwidth {
+    /// Unix time of the first report added to the totals
+    #[serde(default)]
+    pub totals_since: Option<u64>,
+    #[serde(default)]
+    pub totals: Vec<ClassTraffic>,
+    #[serde(default)]
+    pub wire_total: TrafficBytes,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_report: Option<BandwidthReport>,
+    /// Unix time of the last report
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reported_at: Option<u64>,
+}
+
+impl DeviceBandwidth {
+    /// Add what grew since the last report to the totals and keep `report`
+    /// as the last one.
+    pub fn add_report(&mut self, report: BandwidthReport, now: u64) {
+        let previous = self
+            .last_report
+            .as_ref()
+            .filter(|last| last.since == report.since);
+        for class in TrafficClass::ALL {
+            let current = report.class(class);
+            let grown = match previous {
+                Some(last) => current.since(&last.class(class)),
+                None => current,
+            };
+            if grown.total() == 0 {
+                continue;
+            }
+            match self.totals.iter_mut().find(|c| c.class == class) {
+                Some(total) => total.bytes.add(&grown),
+                None => self.totals.push(ClassTraffic {
+                    class,
+                    bytes: grown,
+                }),
+            }
+        }
+        self.totals.sort_by_key(|c| c.class);
+        if let Some(wire) = &report.wire {
+            let last_wire = previous.and_then(|last| last.wire);
+            let grown = match last_wire {
+                Some(last) => wire.since(&last),
+                None => *wire,
+            };
+            self.wire_total.add(&grown);
+        }
+
+        self.totals_since.get_or_insert(now);
+        self.last_report = Some(report);
+        self.reported_at = Some(now);
+    }
+
+    pub fn total(&self, class: TrafficClass) -> TrafficBytes {
+        self.totals
+            .iter()
+            .find(|c| c.class == class)
+            .map(|c| c.bytes)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy_spec::{DeployReportKind, RunState};
+
+    fn report(since: u64, logs: (u64, u64), wire: (u64, u64)) -> BandwidthReport {
+        BandwidthReport {
+            since,
+            classes: vec![ClassTraffic {
+                class: TrafficClass::Logs,
+                bytes: TrafficBytes {
+                    sent: logs.0,
+                    received: logs.1,
+                },
+            }],
+            wire: Some(TrafficBytes {
+                sent: wire.0,
+                received: wire.1,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_totals_survive_restarts() {
+        let mut bandwidth = DeviceBandwidth::default();
+        bandwidth.add_report(report(100, (1000, 10), (1500, 300)), 200);
+        bandwidth.add_report(report(100, (1600, 10), (2300, 350)), 300);
+        assert_eq!(
+            bandwidth.total(TrafficClass::Logs),
+            TrafficBytes {
+                sent: 1600,
+                received: 10
+            }
+        );
+
+        // the runtime restarted, its counters start over
+        bandwidth.add_report(report(400, (200, 0), (400, 100)), 500);
+        assert_eq!(bandwidth.total(TrafficClass::Logs).sent, 1800);
+        assert_eq!(bandwidth.wire_total.total(), 2300 + 350 + 500);
+        assert_eq!(bandwidth.totals_since, Some(200));
+        assert_eq!(bandwidth.reported_at, Some(500));
+        assert_eq!(bandwidth.total(TrafficClass::Deploy).total(), 0);
+    }
+
+    #[test]
+    fn test_profile_caps_report_log_tails() {
+        assert_eq!("low".parse::<BandwidthProfile>(), Ok(BandwidthProfile::Low));
+        assert!("slow".parse::<BandwidthProfile>().is_err());
+
+        let mut report = DeployReportKind::RunState(RunState {
+            run_id: "r".to_string(),
+            revision_id: "rev".to_string(),
+            healthy: Some(false),
+            alive: None,
+            report_time: 0,
+            log_tail: Some(format!("{}\nlast line ü", "x".repeat(2000))),
+        });
+        let max = BandwidthProfile::Low.max_report_log_tail().unwrap();
+        report.cap_log_tail(max);
+        let DeployReportKind::RunState(state) = report else {
+            unreachable!()
+        };
+        let tail = state.log_tail.unwrap();
+        assert!(tail.len() <= max);
+        assert!(tail.ends_with("last line ü"));
+    }
+
+    #[test]
+    fn test_report_serialization() {
+        let json = serde_json::to_value(report(1, (2, 3), (4, 5))).unwrap();
+        assert_eq!(json["classes"][0]["class"], "logs");
+        assert_eq!(json["classes"][0]["sent"], 2);
+    }
+}
@@ -2,16 +2,36 @@ use std::hash::{DefaultHasher, Hash, Hasher};
 
 use serde::{Deserialize, Serialize};
 
+use crate::bandwidth::BandwidthProfile;
+use crate::patching::PatchPolicy;
+use crate::probe::ProbeSpec;
+
 #[derive(Debug, Serialize, Deserialize, Clone, Hash)]
 pub struct DeviceClientConfig {
     #[serde(default)]
     pub heartbeat_interval_secs: Option<u32>,
+    /// Patch policies of the device's organizations; empty while the device
+    /// is in maintenance mode
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patch_policies: Vec<PatchPolicy>,
+    /// Custom redaction patterns of the device's organizations
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+    /// Reachability checks the runtime runs from the device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub probes: Vec<ProbeSpec>,
+    #[serde(default, skip_serializing_if = "BandwidthProfile::is_normal")]
+    pub bandwidth_profile: BandwidthProfile,
 }
 
 impl Default for DeviceClientConfig {
     fn default() -> Self {
         DeviceClientConfig {
             heartbeat_interval_secs: Some(30),
+            patch_policies: Vec::new(),
+            redact_patterns: Vec::new(),
+            probes: Vec::new(),
+            bandwidth_profile: BandwidthProfile::Normal,
         }
     }
 }
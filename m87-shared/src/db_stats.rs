@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Size and index use of one collection, as reported to admins by
+/// `GET /admin/db-stats`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct CollectionStats {
+    pub name: String,
+    pub documents: u64,
+    /// Uncompressed size of the documents; None where `$collStats` is not permitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index_bytes: Option<u64>,
+    pub indexes: Vec<IndexStats>,
+    /// Key patterns of indexes the server creates that the collection lacks
+    #[serde(default)]
+    pub missing_indexes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IndexStats {
+    pub name: String,
+    /// Key pattern, e.g. `device_id: 1, created_at: -1`
+    pub keys: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_after_secs: Option<u64>,
+    /// Queries that used the index since the database started; None where
+    /// `$indexStats` is not permitted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ops: Option<u64>,
+    /// False for indexes the server does not create, e.g. ones superseded by
+    /// a compound index. Unused ones can be dropped.
+    #[serde(default)]
+    pub expected: bool,
+}
@@ -4,10 +4,22 @@ use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::time::Duration;
 
+use crate::device::TimelineEventKind;
+use crate::egress::EgressPolicy;
+use crate::expr::{Expr, template_placeholders};
+use crate::log_format::LogFormat;
+use crate::log_metrics::{LogMetricSpec, LogMetrics};
+use crate::rollout::RolloutPolicy;
+
 fn sha256_hex(bytes: impl AsRef<[u8]>) -> String {
     format!("{:x}", Sha256::digest(bytes.as_ref()))
 }
 
+/// Hash a run's file is stored and compared by.
+pub fn content_hash(content: impl AsRef<[u8]>) -> String {
+    sha256_hex(content)
+}
+
 fn hash_json<T: Serialize>(v: &T) -> String {
     let data = serde_json::to_vec(v).expect("hash_json serialization must not fail");
     sha256_hex(data)
@@ -21,6 +33,51 @@ pub struct DeploymentRevision {
     pub jobs: Vec<RunSpec>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub rollback: Option<RollbackPolicy>,
+    /// Waves and failure threshold when rolled out to a group of devices.
+    /// Not part of the hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollout: Option<RolloutPolicy>,
+    /// Free-form metadata. Like `provenance`, not part of the hash.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// Where the revision came from (commit, pipeline, author).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+/// Source of a deployment revision, so operators can trace what code it was built from.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Provenance {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_sha: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repository: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipeline_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+}
+
+impl Provenance {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Label/value pairs of the fields that are set, in display order.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("commit", &self.git_sha),
+            ("ref", &self.git_ref),
+            ("repository", &self.repository),
+            ("pipeline", &self.pipeline_url),
+            ("author", &self.author),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.as_deref().map(|v| (k, v)))
+        .collect()
+    }
 }
 
 impl Display for DeploymentRevision {
@@ -36,6 +93,9 @@ impl DeploymentRevision {
             id: Some(uuid::Uuid::new_v4().to_string()),
             jobs: units,
             rollback,
+            rollout: None,
+            annotations: BTreeMap::new(),
+            provenance: None,
         };
         rev
     }
@@ -45,6 +105,9 @@ impl DeploymentRevision {
             id: Some(uuid::Uuid::new_v4().to_string()),
             jobs: Vec::new(),
             rollback: None,
+            rollout: None,
+            annotations: BTreeMap::new(),
+            provenance: None,
         }
     }
 
@@ -105,9 +168,17 @@ impl DeploymentRevision {
     pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
         serde_yaml::to_string(self)
     }
+
+    /// Expression errors of all runs, see [`RunSpec::expression_errors`].
+    pub fn expression_errors(&self) -> Vec<String> {
+        self.jobs
+            .iter()
+            .flat_map(RunSpec::expression_errors)
+            .collect()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateDeployRevisionBody {
     /// YAML string for DeploymentRevision.
     pub revision: String,
@@ -122,7 +193,7 @@ impl Display for CreateDeployRevisionBody {
     }
 }
 
-#[derive(Deserialize, Serialize, Default)]
+#[derive(Deserialize, Serialize, Default, Clone)]
 pub struct UpdateDeployRevisionBody {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub revision: Option<String>,
@@ -198,12 +269,25 @@ pub struct RunSpec {
     #[serde(rename = "type")]
     pub run_type: RunType,
     pub enabled: bool,
+    /// Condition on device facts, e.g. `facts.arch == "aarch64"`. Runs whose
+    /// condition is false are skipped by the agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
 
     // service / job only
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub workdir: Option<Workdir>,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub files: BTreeMap<String, String>,
+    /// [`content_hash`] by path of files the server stores apart from the
+    /// revision, once for all revisions using them. Put back into `files`
+    /// before a revision leaves the server.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub file_refs: BTreeMap<String, String>,
+    /// Write `files` read-only and immutable, and refuse to run the steps
+    /// once they were changed on the device until an operator overrides it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub immutable: bool,
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub env: BTreeMap<String, String>,
 
@@ -218,6 +302,17 @@ pub struct RunSpec {
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub observe: Option<ObserveSpec>,
+    /// Periodic checks that the run was not changed on the device outside
+    /// the agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drift: Option<DriftSpec>,
+    /// Ports the run serves on, opened in the device firewall when the
+    /// runtime manages it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expose: Vec<ExposedPort>,
+    /// Where the run may connect to; runs without one are not restricted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<EgressPolicy>,
 }
 
 impl RunSpec {
@@ -238,14 +333,20 @@ impl RunSpec {
             id,
             run_type,
             enabled,
+            when: None,
             workdir,
             files,
+            file_refs: BTreeMap::new(),
+            immutable: false,
             env,
             steps,
             on_failure,
             stop,
             reboot,
             observe,
+            drift: None,
+            expose: Vec::new(),
+            egress: None,
         }
     }
 
@@ -265,6 +366,38 @@ impl RunSpec {
     pub fn enable(&mut self, enabled: bool) {
         self.enabled = enabled;
     }
+
+    /// Errors in the `when` condition and in template step placeholders.
+    pub fn expression_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if let Some(when) = &self.when
+            && let Err(e) = Expr::parse(when)
+        {
+            errors.push(format!("{}: when: {}", self.id, e));
+        }
+        if let Some(logs) = self.observe.as_ref().and_then(|o| o.logs.as_ref())
+            && let Err(e) = LogMetrics::new(&logs.metrics)
+        {
+            errors.push(format!("{}: {}", self.id, e));
+        }
+        if let Some(egress) = &self.egress
+            && let Err(e) = egress.targets()
+        {
+            errors.push(format!("{}: {}", self.id, e));
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if step.uses.as_deref() != Some("template") {
+                continue;
+            }
+            if let Some(content) = step.with.get("content").and_then(|v| v.as_str())
+                && let Err(e) = template_placeholders(content)
+            {
+                let name = step.name.clone().unwrap_or_else(|| format!("#{}", i + 1));
+                errors.push(format!("{}: step {}: {}", self.id, name, e));
+            }
+        }
+        errors
+    }
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
@@ -291,10 +424,18 @@ impl RebootMode {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Step {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    /// Step executor kind (e.g. `copy_file`, `template`, `http`, `systemd_unit`).
+    /// Defaults to `command`, which executes `run`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uses: Option<String>,
+    /// Executor specific parameters
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub with: serde_json::Map<String, serde_json::Value>,
+    #[serde(default, skip_serializing_if = "CommandSpec::is_empty")]
     pub run: CommandSpec,
     #[serde(
         default,
@@ -306,6 +447,10 @@ pub struct Step {
     pub retry: Option<RetrySpec>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub undo: Option<Undo>,
+    /// Paths or globs, relative to the workdir, of files the step leaves
+    /// behind (e.g. test results). Uploaded with the step report.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -354,6 +499,21 @@ pub enum CommandSpec {
     Argv(Vec<String>),
 }
 
+impl Default for CommandSpec {
+    fn default() -> Self {
+        CommandSpec::Argv(vec![])
+    }
+}
+
+impl CommandSpec {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            CommandSpec::Sh(cmd) => cmd.trim().is_empty(),
+            CommandSpec::Argv(args) => args.is_empty(),
+        }
+    }
+}
+
 impl Display for CommandSpec {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -368,6 +528,53 @@ pub struct StopSpec {
     pub steps: Vec<Step>,
 }
 
+/// What the agent compares against the device every `every`: the run's
+/// materialized files by hash, and containers and units that must be running.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriftSpec {
+    #[serde(with = "duration_human", default = "default_drift_every")]
+    pub every: Duration,
+    /// Docker containers the run keeps running
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub containers: Vec<String>,
+    /// systemd units the run keeps active
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub units: Vec<String>,
+    /// Rewrite drifted files and run the steps again instead of only reporting
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub remediate: bool,
+}
+
+fn default_drift_every() -> Duration {
+    Duration::from_secs(300)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ExposedPort {
+    pub port: u16,
+    #[serde(default)]
+    pub protocol: PortProtocol,
+}
+
+#[derive(
+    Default, Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PortProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl PortProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PortProtocol::Tcp => "tcp",
+            PortProtocol::Udp => "udp",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ObserveSpec {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -382,6 +589,13 @@ pub struct ObserveSpec {
 pub struct LogSpec {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub follow: Option<CommandSpec>,
+    /// How followed lines are parsed into level, message and timestamp;
+    /// unset keeps them as plain lines
+    #[serde(default, alias = "log_format", skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+    /// Counters and gauges taken from the followed lines
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub metrics: Vec<LogMetricSpec>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -465,6 +679,8 @@ pub enum Outcome {
     Success,
     Failed,
     Unknown,
+    /// The run's `when` condition did not match the device
+    Skipped,
 }
 
 impl Display for Outcome {
@@ -473,6 +689,7 @@ impl Display for Outcome {
             Outcome::Success => write!(f, "success"),
             Outcome::Failed => write!(f, "failed"),
             Outcome::Unknown => write!(f, "unknown"),
+            Outcome::Skipped => write!(f, "skipped"),
         }
     }
 }
@@ -525,6 +742,171 @@ pub struct StepReport {
     /// Best-effort log tail for this step only (bounded).
     #[serde(default)]
     pub log_tail: String,
+
+    /// Files matching the step's `outputs` after it ran.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<StepArtifact>,
+}
+
+/// Largest output file uploaded with its content
+pub const MAX_ARTIFACT_BYTES: u64 = 256 * 1024;
+/// Content uploaded per step at most, over all its output files
+pub const MAX_STEP_ARTIFACT_BYTES: u64 = 1024 * 1024;
+/// Output files listed per step at most
+pub const MAX_STEP_ARTIFACTS: usize = 32;
+
+/// A file left behind by a step.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StepArtifact {
+    /// Relative to the run's workdir
+    pub path: String,
+    pub size: u64,
+    /// Base64 encoded; missing when the file was over the size limits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+}
+
+/// The artifacts of a run's step, from its latest report that has any.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StepArtifacts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>,
+    pub report_time: u64,
+    pub artifacts: Vec<StepArtifact>,
+}
+
+/// Largest compressed core dump uploaded with its content
+pub const MAX_CORE_DUMP_BYTES: u64 = 8 * 1024 * 1024;
+
+/// A process of a run that dumped core, captured through the agent's
+/// `core_pattern` handler.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CoreDumpReport {
+    pub run_id: String,
+    pub revision_id: String,
+    pub pid: u32,
+    /// Signal that killed the process
+    pub signal: i32,
+    /// Path of the crashed executable
+    pub executable: String,
+    /// Size of the core dump
+    pub size: u64,
+    /// Size of the zstd compressed core; missing when it was over
+    /// [`MAX_CORE_DUMP_BYTES`] and only the metadata was uploaded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed_size: Option<u64>,
+    /// The zstd compressed core, base64 encoded. Left out of listings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    pub report_time: u64,
+}
+
+impl CoreDumpReport {
+    /// e.g. `app (pid 1234) dumped core on SIGSEGV`
+    pub fn summary(&self) -> String {
+        let name = self
+            .executable
+            .rsplit('/')
+            .next()
+            .unwrap_or(&self.executable);
+        format!(
+            "{} (pid {}) dumped core on {}",
+            name,
+            self.pid,
+            signal_name(self.signal)
+        )
+    }
+}
+
+/// A core dump stored by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CoreDump {
+    pub id: String,
+    #[serde(flatten)]
+    pub report: CoreDumpReport,
+}
+
+/// Connections of a run its egress policy dropped since the previous report.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EgressViolationReport {
+    pub run_id: String,
+    pub revision_id: String,
+    /// Dropped packets of new connections, retries included
+    pub blocked: u64,
+    /// Addresses they were sent to, as far as the device kept them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub destinations: Vec<String>,
+    pub report_time: u64,
+}
+
+impl EgressViolationReport {
+    /// e.g. `3 packets to 203.0.113.9 blocked by the egress policy`
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} packets", self.blocked);
+        if !self.destinations.is_empty() {
+            summary.push_str(&format!(" to {}", self.destinations.join(", ")));
+        }
+        summary.push_str(" blocked by the egress policy");
+        summary
+    }
+}
+
+/// Name of the signals that dump core, the number otherwise.
+pub fn signal_name(signal: i32) -> String {
+    let name = match signal {
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        11 => "SIGSEGV",
+        24 => "SIGXCPU",
+        25 => "SIGXFSZ",
+        31 => "SIGSYS",
+        _ => return format!("signal {}", signal),
+    };
+    name.to_string()
+}
+
+/// Sent when the drift a run's checks find changes, including back to none.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DriftReport {
+    pub run_id: String,
+    pub revision_id: String,
+    /// Materialized files that were changed or removed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// Expected containers and units that were not running
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stopped: Vec<String>,
+    /// Whether the agent restored the files and ran the steps again
+    #[serde(default)]
+    pub remediated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub report_time: u64,
+}
+
+impl DriftReport {
+    pub fn is_drifted(&self) -> bool {
+        !self.files.is_empty() || !self.stopped.is_empty()
+    }
+
+    /// What drifted, e.g. `files config.yaml changed; app not running`.
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.files.is_empty() {
+            parts.push(format!("files {} changed", self.files.join(", ")));
+        }
+        if !self.stopped.is_empty() {
+            parts.push(format!("{} not running", self.stopped.join(", ")));
+        }
+        if parts.is_empty() {
+            return "no drift".to_string();
+        }
+        parts.join("; ")
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -568,6 +950,9 @@ pub enum DeployReportKind {
     StepReport(StepReport),
     RollbackReport(RollbackReport),
     RunState(RunState),
+    DriftReport(DriftReport),
+    CoreDump(CoreDumpReport),
+    EgressViolation(EgressViolationReport),
 }
 
 impl DeployReportKind {
@@ -578,6 +963,9 @@ impl DeployReportKind {
             DeployReportKind::StepReport(r) => &r.revision_id,
             DeployReportKind::RollbackReport(r) => &r.revision_id,
             DeployReportKind::RunState(r) => &r.revision_id,
+            DeployReportKind::DriftReport(r) => &r.revision_id,
+            DeployReportKind::CoreDump(r) => &r.revision_id,
+            DeployReportKind::EgressViolation(r) => &r.revision_id,
         }
     }
 
@@ -588,6 +976,120 @@ impl DeployReportKind {
             DeployReportKind::StepReport(r) => Some(r.run_id.clone()),
             DeployReportKind::RollbackReport(_) => None,
             DeployReportKind::RunState(r) => Some(r.run_id.clone()),
+            DeployReportKind::DriftReport(r) => Some(r.run_id.clone()),
+            DeployReportKind::CoreDump(r) => Some(r.run_id.clone()),
+            DeployReportKind::EgressViolation(r) => Some(r.run_id.clone()),
+        }
+    }
+
+    /// Device clock time of the report, for the kinds that carry one
+    pub fn report_time(&self) -> Option<u64> {
+        match self {
+            DeployReportKind::DeploymentRevisionReport(_) => None,
+            DeployReportKind::RunReport(r) => Some(r.report_time),
+            DeployReportKind::StepReport(r) => Some(r.report_time),
+            DeployReportKind::RollbackReport(_) => None,
+            DeployReportKind::RunState(r) => Some(r.report_time),
+            DeployReportKind::DriftReport(r) => Some(r.report_time),
+            DeployReportKind::CoreDump(r) => Some(r.report_time),
+            DeployReportKind::EgressViolation(r) => Some(r.report_time),
+        }
+    }
+
+    /// Drop the uploaded file contents (step artifacts, core dumps), for
+    /// copies of the report that only describe it.
+    pub fn strip_contents(&mut self) {
+        match self {
+            DeployReportKind::StepReport(r) => {
+                for artifact in &mut r.artifacts {
+                    artifact.content = None;
+                }
+            }
+            DeployReportKind::CoreDump(r) => r.content = None,
+            _ => {}
+        }
+    }
+
+    /// Keep at most the last `max_bytes` of the report's log tail.
+    pub fn cap_log_tail(&mut self, max_bytes: usize) {
+        let tail = match self {
+            DeployReportKind::StepReport(r) => &mut r.log_tail,
+            DeployReportKind::RunState(r) => match &mut r.log_tail {
+                Some(tail) => tail,
+                None => return,
+            },
+            _ => return,
+        };
+        if tail.len() <= max_bytes {
+            return;
+        }
+        let mut start = tail.len() - max_bytes;
+        while !tail.is_char_boundary(start) {
+            start += 1;
+        }
+        tail.drain(..start);
+    }
+
+    /// Kind and summary of the report on the device events timeline: applied
+    /// revisions, rollbacks, crashes, failed runs and steps and blocked
+    /// egress.
+    pub fn timeline_entry(&self) -> Option<(TimelineEventKind, String)> {
+        match self {
+            DeployReportKind::DeploymentRevisionReport(r) => {
+                let mut summary = format!("Revision {} applied: {}", r.revision_id, r.outcome);
+                if let Some(error) = &r.error {
+                    summary.push_str(&format!(" ({})", error));
+                }
+                Some((TimelineEventKind::Deployment, summary))
+            }
+            DeployReportKind::RollbackReport(r) => {
+                let summary = match &r.new_revision_id {
+                    Some(new) => format!("Rolled back from {} to {}", r.revision_id, new),
+                    None => format!("Rolled back revision {}", r.revision_id),
+                };
+                Some((TimelineEventKind::Rollback, summary))
+            }
+            DeployReportKind::RunReport(r) if r.outcome == Outcome::Failed => {
+                let mut summary = format!("Run {} failed", r.run_id);
+                if let Some(error) = &r.error {
+                    summary.push_str(&format!(": {}", error));
+                }
+                Some((TimelineEventKind::RunFailed, summary))
+            }
+            DeployReportKind::StepReport(r) if !r.success => {
+                let step = r.name.as_deref().unwrap_or("step");
+                let mut summary = format!(
+                    "Run {}: {} failed after {} attempt(s)",
+                    r.run_id, step, r.attempts
+                );
+                if let Some(code) = r.exit_code {
+                    summary.push_str(&format!(", exit code {}", code));
+                }
+                if let Some(error) = &r.error {
+                    summary.push_str(&format!(": {}", error));
+                }
+                Some((TimelineEventKind::StepFailed, summary))
+            }
+            DeployReportKind::RunState(r) if r.alive == Some(false) => Some((
+                TimelineEventKind::Crash,
+                format!("Run {} is no longer running", r.run_id),
+            )),
+            DeployReportKind::DriftReport(r) if r.is_drifted() => {
+                let mut summary = format!("Run {} drifted: {}", r.run_id, r.summary());
+                if r.remediated {
+                    summary.push_str(" (restored)");
+                }
+                Some((TimelineEventKind::Drift, summary))
+            }
+            DeployReportKind::CoreDump(r) => Some((
+                TimelineEventKind::Crash,
+                format!("Run {}: {}", r.run_id, r.summary()),
+            )),
+            DeployReportKind::EgressViolation(r) => Some((
+                TimelineEventKind::Egress,
+                format!("Run {}: {}", r.run_id, r.summary()),
+            )),
+            _ => None,
         }
     }
 }
@@ -601,8 +1103,12 @@ pub struct DeployReport {
     /// TTL target
     pub expires_at: Option<u64>,
 
-    /// When the report was received/created
+    /// When the server received the report
     pub created_at: u64,
+
+    /// Position in the device's report sequence, from runtimes that send one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
 }
 
 pub mod duration_human {
@@ -700,8 +1206,80 @@ pub struct DeploymentStatusSnapshot {
 
     pub rollback: Option<RollbackStatus>,
 
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+
     // Spec-ordered runs (jobs)
     pub runs: Vec<RunStatus>,
+    /// Runs matching the [`SnapshotQuery`] when `runs` holds only a page of
+    /// them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_runs: Option<usize>,
+}
+
+impl DeploymentStatusSnapshot {
+    /// Keep the runs `query` asks for. Outcomes stay those of the whole revision.
+    pub fn select(&mut self, query: &SnapshotQuery) {
+        if !query.is_partial() {
+            return;
+        }
+        if let Some(run_id) = &query.run_id {
+            self.runs.retain(|r| &r.run_id == run_id);
+        }
+        self.total_runs = Some(self.runs.len());
+        let runs = std::mem::take(&mut self.runs)
+            .into_iter()
+            .skip(query.offset);
+        self.runs = match query.limit {
+            Some(limit) => runs.take(limit).collect(),
+            None => runs.collect(),
+        };
+        if query.no_steps {
+            for run in &mut self.runs {
+                run.steps.clear();
+                run.steps_omitted = true;
+            }
+        }
+    }
+}
+
+/// Part of a [`DeploymentStatusSnapshot`] to fetch, for revisions with too
+/// many runs to load at once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotQuery {
+    /// First run to return, in spec order
+    #[serde(default)]
+    pub offset: usize,
+    /// Runs to return at most; all if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    /// Only the run with this id
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>,
+    /// Leave out the steps of the runs
+    #[serde(default)]
+    pub no_steps: bool,
+}
+
+impl SnapshotQuery {
+    pub fn page(offset: usize, limit: usize) -> Self {
+        Self {
+            offset,
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    /// No runs, only the revision's outcome and fields, e.g. to poll it.
+    pub fn outcome_only() -> Self {
+        Self::page(0, 0)
+    }
+
+    pub fn is_partial(&self) -> bool {
+        self.offset > 0 || self.limit.is_some() || self.run_id.is_some() || self.no_steps
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -720,6 +1298,12 @@ pub struct RunStatus {
 
     // Spec-ordered steps (including optional undo as a separate row)
     pub steps: Vec<StepStatus>,
+    /// Steps were left out of the snapshot (`no_steps`), not absent
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub steps_omitted: bool,
+    /// Latest drift report, while it found drift
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drift: Option<DriftReport>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
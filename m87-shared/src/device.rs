@@ -1,9 +1,15 @@
-use std::{fmt::Display, hash::Hash};
+use std::{collections::BTreeMap, fmt::Display, hash::Hash};
 
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
-use crate::{config::DeviceClientConfig, roles::Role};
+use crate::{
+    bandwidth::BandwidthProfile,
+    config::DeviceClientConfig,
+    expr::{self, Expr, Scope, Value},
+    metrics::{BatteryMetrics, ChargingState, Location},
+    roles::Role,
+};
 
 /// Compute short device ID (first 6 chars of SHA256 hash)
 /// Used for tunnel routing - must be consistent across server and client
@@ -14,6 +20,24 @@ pub fn short_device_id(device_id: &str) -> String {
     hash[..6].to_string()
 }
 
+/// Device names are used on the command line (`m87 <device> ...`) and in
+/// `<device>:<path>` arguments, so keep them to a shell- and path-safe set.
+pub fn validate_device_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > 63 {
+        return Err("device name must be 1-63 characters".to_string());
+    }
+    if name.starts_with(['-', '.']) {
+        return Err("device name must not start with '-' or '.'".to_string());
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return Err(format!("device name must not contain '{}'", c));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PublicDevice {
     pub id: String,
@@ -31,6 +55,49 @@ pub struct PublicDevice {
     pub system_info: DeviceSystemInfo,
     #[serde(default)]
     pub role: Role, // the role of the requestor
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_failed_upgrade: Option<FailedUpgrade>,
+    #[serde(default, skip_serializing_if = "DeviceAssetInfo::is_empty")]
+    pub info: DeviceAssetInfo,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_location: Option<Location>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<BatteryMetrics>,
+    #[serde(default, skip_serializing_if = "AlertRules::is_empty")]
+    pub alert_rules: AlertRules,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<TimeStatus>,
+    /// Set while the device is in maintenance mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<DeviceMaintenance>,
+    /// Destructive operations need a second operator's approval
+    #[serde(default)]
+    pub require_approval: bool,
+}
+
+/// Clock offsets above this are flagged as skewed.
+pub const CLOCK_SKEW_WARN_MS: i64 = 10_000;
+
+/// Clock state of a device.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TimeStatus {
+    /// NTP client in use (`chrony` or `systemd-timesyncd`), if one was found
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ntp_service: Option<String>,
+    /// Whether the system clock is synchronized, if the OS reports it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synchronized: Option<bool>,
+    /// Device clock minus reference clock in ms (positive when the device is
+    /// ahead). Measured by the server on heartbeats, so it includes the
+    /// network delay.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset_ms: Option<i64>,
+}
+
+impl TimeStatus {
+    pub fn is_skewed(&self) -> bool {
+        self.offset_ms.is_some_and(|o| o.abs() > CLOCK_SKEW_WARN_MS)
+    }
 }
 
 impl Display for PublicDevice {
@@ -40,6 +107,199 @@ impl Display for PublicDevice {
     }
 }
 
+/// Operator-maintained asset data for a device (location, contact, asset tag, ...).
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct DeviceAssetInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner_contact: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset_tag: Option<String>,
+    /// Free-form, `YYYY-MM-DD` by convention
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install_date: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom_fields: BTreeMap<String, String>,
+}
+
+impl DeviceAssetInfo {
+    /// Keys of the built-in fields. Any other key is a custom field.
+    pub const FIELDS: [&'static str; 5] = [
+        "location",
+        "owner_contact",
+        "asset_tag",
+        "install_date",
+        "notes",
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn field_mut(&mut self, key: &str) -> Option<&mut Option<String>> {
+        match key {
+            "location" => Some(&mut self.location),
+            "owner_contact" => Some(&mut self.owner_contact),
+            "asset_tag" => Some(&mut self.asset_tag),
+            "install_date" => Some(&mut self.install_date),
+            "notes" => Some(&mut self.notes),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match key {
+            "location" => self.location.as_deref(),
+            "owner_contact" => self.owner_contact.as_deref(),
+            "asset_tag" => self.asset_tag.as_deref(),
+            "install_date" => self.install_date.as_deref(),
+            "notes" => self.notes.as_deref(),
+            _ => self.custom_fields.get(key).map(String::as_str),
+        }
+    }
+
+    /// Set a built-in or custom field. `None` (or an empty value) removes it.
+    pub fn set(&mut self, key: &str, value: Option<String>) {
+        let value = value.filter(|v| !v.is_empty());
+        match self.field_mut(key) {
+            Some(field) => *field = value,
+            None => match value {
+                Some(v) => {
+                    self.custom_fields.insert(key.to_string(), v);
+                }
+                None => {
+                    self.custom_fields.remove(key);
+                }
+            },
+        }
+    }
+
+    /// All set fields, built-in ones first.
+    pub fn entries(&self) -> Vec<(&str, &str)> {
+        Self::FIELDS
+            .iter()
+            .filter_map(|k| self.get(k).map(|v| (*k, v)))
+            .chain(
+                self.custom_fields
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str())),
+            )
+            .collect()
+    }
+}
+
+/// Per-device alert thresholds evaluated by the server on each heartbeat.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct AlertRules {
+    /// Alert when the battery drops to this percentage while not charging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub low_battery_percent: Option<u8>,
+    /// Named conditions over `battery.*` and `location.*` (see [`crate::expr`]),
+    /// e.g. `battery.percent < 30 && battery.power_source == "battery"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub conditions: BTreeMap<String, String>,
+}
+
+impl AlertRules {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Alert message when `current` crosses the low-battery threshold. Fires
+    /// once per discharge instead of on every heartbeat below the threshold.
+    pub fn battery_alert(
+        &self,
+        previous: Option<&BatteryMetrics>,
+        current: &BatteryMetrics,
+    ) -> Option<String> {
+        let threshold = self.low_battery_percent? as f32;
+        let is_low =
+            |b: &BatteryMetrics| b.percent <= threshold && b.state != ChargingState::Charging;
+        if !is_low(current) || previous.is_some_and(is_low) {
+            return None;
+        }
+        Some(format!(
+            "Battery at {:.0}% (alert threshold {:.0}%)",
+            current.percent, threshold
+        ))
+    }
+
+    /// Names and messages of the conditions that hold for `current` but did
+    /// not for `previous`, so each fires once when it starts to hold.
+    /// Conditions that do not parse are ignored; they are rejected on update.
+    pub fn condition_alerts(
+        &self,
+        previous: &dyn Scope,
+        current: &dyn Scope,
+    ) -> Vec<(String, String)> {
+        self.conditions
+            .iter()
+            .filter_map(|(name, source)| {
+                let expr = Expr::parse(source).ok()?;
+                (expr.matches(current) && !expr.matches(previous)).then(|| {
+                    (
+                        name.clone(),
+                        format!("Condition '{}' met: {}", name, source),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    /// Parse errors of the named conditions.
+    pub fn condition_errors(&self) -> Vec<String> {
+        self.conditions
+            .iter()
+            .filter_map(|(name, source)| {
+                Expr::parse(source)
+                    .err()
+                    .map(|e| format!("{}: {}", name, e))
+            })
+            .collect()
+    }
+}
+
+/// Variables available to alert conditions.
+pub fn alert_scope(
+    battery: Option<&BatteryMetrics>,
+    location: Option<&Location>,
+) -> BTreeMap<String, Value> {
+    let mut scope = BTreeMap::new();
+    if let Some(battery) = battery.and_then(|b| serde_json::to_value(b).ok()) {
+        expr::flatten_json("battery", &battery, &mut scope);
+    }
+    if let Some(location) = location.and_then(|l| serde_json::to_value(l).ok()) {
+        expr::flatten_json("location", &location, &mut scope);
+    }
+    scope
+}
+
+/// A self-update the runtime rolled back because the new binary never
+/// established a control tunnel.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FailedUpgrade {
+    pub from_version: String,
+    pub to_version: String,
+    pub reason: String,
+    /// RFC 3339 timestamp of the revert
+    pub failed_at: String,
+}
+
+/// A use of the device's break-glass recovery code, reported once the
+/// runtime reaches the server again.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RecoveryUnlock {
+    /// Local user who ran `m87 local unlock`
+    pub user: String,
+    /// RFC 3339 timestamp of the unlock
+    pub unlocked_at: String,
+    /// RFC 3339 timestamp at which the local access key stops working
+    pub expires_at: String,
+}
+
 fn default_architecture() -> String {
     "unknown".to_string()
 }
@@ -60,6 +320,13 @@ pub struct DeviceSystemInfo {
     pub memory: Option<f64>,
     #[serde(default)]
     pub gpus: Vec<String>,
+    /// X25519 public key for end-to-end encrypted streams, base64 encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e2e_public_key: Option<String>,
+    /// SHA-256 of the certificate of direct LAN connections, hex encoded;
+    /// clients pin it with `--fingerprint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lan_cert_fingerprint: Option<String>,
 }
 
 impl Hash for DeviceSystemInfo {
@@ -77,6 +344,8 @@ impl Hash for DeviceSystemInfo {
         }
         self.cpu_name.hash(state);
         self.gpus.hash(state);
+        self.e2e_public_key.hash(state);
+        self.lan_cert_fingerprint.hash(state);
     }
 }
 
@@ -85,6 +354,30 @@ pub struct UpdateDeviceBody {
     pub system_info: Option<DeviceSystemInfo>,
     pub client_version: Option<String>,
     pub config: Option<DeviceClientConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub info: Option<DeviceAssetInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_rules: Option<AlertRules>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<DeviceMaintenance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub require_approval: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth_profile: Option<BandwidthProfile>,
+}
+
+/// Maintenance mode excludes a device from patch policies, e.g. while it is
+/// being repaired or during a demo.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct DeviceMaintenance {
+    pub enabled: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Unix millis when maintenance mode was turned on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
 }
 
 #[derive(Deserialize, Serialize, Default)]
@@ -110,6 +403,11 @@ pub struct DeviceStatus {
 
     // incidents
     pub incidents: Vec<IncidentInfo>,
+
+    /// Deploy reports the device holds back under its bandwidth profile;
+    /// `observations` already include their run states
+    #[serde(default)]
+    pub pending_reports: u32,
 }
 
 #[derive(Deserialize, Serialize, Default)]
@@ -122,6 +420,64 @@ pub struct AuditLog {
     pub device_id: Option<String>,
 }
 
+/// What a [`DeviceTimelineEvent`] is about.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineEventKind {
+    /// The runtime's control tunnel came up
+    Connected,
+    /// The control tunnel went down; no heartbeats until the next `Connected`
+    Disconnected,
+    /// The device booted since its previous tunnel
+    Reboot,
+    /// The runtime reported applying a deployment revision
+    Deployment,
+    RunFailed,
+    StepFailed,
+    /// A run's process died
+    Crash,
+    /// A run's files, containers or units were changed outside the agent
+    Drift,
+    Rollback,
+    /// A run's egress policy dropped connections
+    Egress,
+    /// Someone connected to the device (shell, ssh, terminal, ...)
+    Session,
+    /// Any other audit log entry of the device
+    Audit,
+}
+
+impl TimelineEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimelineEventKind::Connected => "connected",
+            TimelineEventKind::Disconnected => "disconnected",
+            TimelineEventKind::Reboot => "reboot",
+            TimelineEventKind::Deployment => "deployment",
+            TimelineEventKind::RunFailed => "run_failed",
+            TimelineEventKind::StepFailed => "step_failed",
+            TimelineEventKind::Crash => "crash",
+            TimelineEventKind::Drift => "drift",
+            TimelineEventKind::Rollback => "rollback",
+            TimelineEventKind::Egress => "egress",
+            TimelineEventKind::Session => "session",
+            TimelineEventKind::Audit => "audit",
+        }
+    }
+}
+
+/// One entry of `GET /device/<id>/events`, the merged history of a device.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DeviceTimelineEvent {
+    /// RFC 3339, server time
+    pub timestamp: String,
+    pub kind: TimelineEventKind,
+    pub summary: String,
+    /// User behind the event, for sessions and audit entries
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Default)]
 pub struct AddDeviceAccessBody {
     pub email_or_org_id: String,
@@ -135,4 +491,19 @@ impl Display for AddDeviceAccessBody {
     }
 }
 
+/// Adopt the history of a duplicate device (e.g. one registered under an
+/// older identity) into the device the request is made for.
+#[derive(Deserialize, Serialize, Default)]
+pub struct MergeDeviceBody {
+    /// Id of the duplicate device, which is deleted after the merge
+    pub from: String,
+}
+
+impl Display for MergeDeviceBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string_pretty(self).unwrap();
+        write!(f, "{}", json)
+    }
+}
+
 // remove and uupdate bodies
@@ -0,0 +1,111 @@
+//! Egress allowlists of runs, declared under `egress.allow`. The runtime
+//! drops connections a run opens to anything else and reports what it
+//! dropped, so a compromised workload cannot send data off the device.
+
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EgressPolicy {
+    /// IP addresses, networks like `10.0.0.0/8` and domain names, which the
+    /// device resolves. DNS lookups are always allowed.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+impl EgressPolicy {
+    /// The entries of `allow`, or the first one that is neither an address,
+    /// a network nor a domain name.
+    pub fn targets(&self) -> Result<Vec<EgressTarget>, String> {
+        self.allow.iter().map(|s| EgressTarget::parse(s)).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EgressTarget {
+    /// An address with the length of its network prefix
+    Network(IpAddr, u8),
+    Domain(String),
+}
+
+impl EgressTarget {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let invalid = || format!("invalid egress entry '{}'", s);
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        if let Ok(addr) = addr.parse::<IpAddr>() {
+            let max = if addr.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(p) => p
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|p| *p <= max)
+                    .ok_or_else(invalid)?,
+                None => max,
+            };
+            return Ok(EgressTarget::Network(addr, prefix));
+        }
+        if prefix.is_some() || !is_domain(s) {
+            return Err(invalid());
+        }
+        Ok(EgressTarget::Domain(s.trim_end_matches('.').to_lowercase()))
+    }
+}
+
+fn is_domain(s: &str) -> bool {
+    let s = s.trim_end_matches('.');
+    !s.is_empty()
+        && s.len() <= 253
+        && s.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        // all digits is a malformed address rather than a name
+        && s.chars().any(|c| c.is_ascii_alphabetic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses_networks_and_domains() {
+        let policy = EgressPolicy {
+            allow: vec![
+                "10.0.0.0/8".to_string(),
+                "192.168.1.20".to_string(),
+                "2001:db8::/32".to_string(),
+                "API.example.com.".to_string(),
+            ],
+        };
+        assert_eq!(
+            policy.targets().unwrap(),
+            vec![
+                EgressTarget::Network("10.0.0.0".parse().unwrap(), 8),
+                EgressTarget::Network("192.168.1.20".parse().unwrap(), 32),
+                EgressTarget::Network("2001:db8::".parse().unwrap(), 32),
+                EgressTarget::Domain("api.example.com".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        for entry in [
+            "10.0.0.0/33",
+            "10.0.0.300",
+            "example.com/24",
+            "*.example.com",
+            "-a.com",
+        ] {
+            assert!(EgressTarget::parse(entry).is_err(), "{}", entry);
+        }
+    }
+}
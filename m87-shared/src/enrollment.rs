@@ -0,0 +1,128 @@
+//! Install commands: one line that installs the m87 runtime on a new device
+//! and registers it. The install script detects the architecture and
+//! verifies the binary's checksum; the enrollment token it passes on lets
+//! the device join without waiting for approval.
+
+use serde::{Deserialize, Serialize};
+
+/// Install script the commands download unless the server names a mirror
+pub const DEFAULT_INSTALL_SCRIPT_URL: &str = "https://get.make87.com";
+pub const DEFAULT_ENROLLMENT_TTL_SECS: u64 = 24 * 3600;
+pub const MAX_ENROLLMENT_TTL_SECS: u64 = 30 * 24 * 3600;
+pub const MAX_ENROLLMENT_USES: u32 = 1000;
+
+/// Environment the install script registers the installed runtime with
+pub const ENROLLMENT_TOKEN_ENV: &str = "M87_ENROLLMENT_TOKEN";
+pub const ENROLLMENT_OWNER_ENV: &str = "M87_OWNER";
+pub const ENROLLMENT_SERVER_ENV: &str = "M87_RUNTIME_SERVER_URL";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CreateInstallCommandBody {
+    /// Organization the devices join; the caller's own devices when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+    /// How long the token is valid, [`DEFAULT_ENROLLMENT_TTL_SECS`] when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// How many devices may enroll with the token, one when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_uses: Option<u32>,
+}
+
+impl CreateInstallCommandBody {
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(ttl) = self.ttl_secs
+            && !(60..=MAX_ENROLLMENT_TTL_SECS).contains(&ttl)
+        {
+            return Err(format!(
+                "enrollment tokens are valid from a minute to {} days",
+                MAX_ENROLLMENT_TTL_SECS / 86400
+            ));
+        }
+        if let Some(uses) = self.max_uses
+            && !(1..=MAX_ENROLLMENT_USES).contains(&uses)
+        {
+            return Err(format!(
+                "an enrollment token enrolls 1 to {} devices",
+                MAX_ENROLLMENT_USES
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct InstallCommand {
+    /// Run on the new device as the user the runtime should run as
+    pub command: String,
+    /// Org id or email the devices register under
+    pub owner_reference: String,
+    pub server_url: String,
+    /// RFC 3339
+    pub expires_at: String,
+    pub max_uses: u32,
+}
+
+/// `curl <script> | <env> sh`, with the values quoted for a POSIX shell.
+pub fn render_install_command(
+    script_url: &str,
+    server_url: &str,
+    owner_reference: &str,
+    token: &str,
+) -> String {
+    format!(
+        "curl -fsSL {} | {}={} {}={} {}={} sh",
+        shell_quote(script_url),
+        ENROLLMENT_SERVER_ENV,
+        shell_quote(server_url),
+        ENROLLMENT_OWNER_ENV,
+        shell_quote(owner_reference),
+        ENROLLMENT_TOKEN_ENV,
+        shell_quote(token),
+    )
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_pipes_the_script_into_sh_with_the_enrollment_env() {
+        let command = render_install_command(
+            DEFAULT_INSTALL_SCRIPT_URL,
+            "https://m87.internal",
+            "acme",
+            "m87e_abc",
+        );
+        assert_eq!(
+            command,
+            "curl -fsSL 'https://get.make87.com' | M87_RUNTIME_SERVER_URL='https://m87.internal' \
+             M87_OWNER='acme' M87_ENROLLMENT_TOKEN='m87e_abc' sh"
+        );
+    }
+
+    #[test]
+    fn values_cannot_break_out_of_their_quotes() {
+        let command = render_install_command("u", "s", "o'; rm -rf ~; '", "t");
+        assert!(command.contains(r"M87_OWNER='o'\''; rm -rf ~; '\'''"));
+    }
+
+    #[test]
+    fn validate_bounds_ttl_and_uses() {
+        assert!(CreateInstallCommandBody::default().validate().is_ok());
+        let body = CreateInstallCommandBody {
+            ttl_secs: Some(MAX_ENROLLMENT_TTL_SECS + 1),
+            ..Default::default()
+        };
+        assert!(body.validate().is_err());
+        let body = CreateInstallCommandBody {
+            max_uses: Some(0),
+            ..Default::default()
+        };
+        assert!(body.validate().is_err());
+    }
+}
@@ -0,0 +1,863 @@
+//! A small, sandboxed expression language used by `when:` conditions,
+//! template placeholders (`{{ expr }}`) and alert rules.
+//!
+//! Expressions have no side effects, no loops and no access to anything but
+//! the variables of the [`Scope`] they are evaluated in, so evaluation time is
+//! bounded by the (size-limited) expression itself.
+//!
+//! # Syntax
+//!
+//! - literals: `"text"` or `'text'` (with `\"`, `\'`, `\\`, `\n` escapes),
+//!   numbers (`42`, `-1.5`), `true`, `false`, `null`
+//! - variables: dotted names such as `facts.arch`, `battery.percent` or `PORT`.
+//!   Unknown variables evaluate to `null`
+//! - operators, loosest first: `||`, `&&`, `==` `!=` `<` `<=` `>` `>=`, `!`
+//! - parentheses for grouping and function calls
+//!
+//! Strings that look like numbers compare numerically with numbers, so
+//! `facts.cpu.cores >= 4` works although facts are strings. Ordering
+//! comparisons with `null` are false. In conditions `null`, `false`, `0` and
+//! `""` are false and everything else is true.
+//!
+//! # Functions
+//!
+//! | function                  | result                                                  |
+//! |---------------------------|---------------------------------------------------------|
+//! | `default(value, fallback)`| `value`, or `fallback` if it is null or empty           |
+//! | `exists(value)`           | whether `value` is not null                             |
+//! | `contains(text, part)`    | whether `text` contains `part`                          |
+//! | `starts_with(text, p)`    | whether `text` starts with `p`                          |
+//! | `ends_with(text, s)`      | whether `text` ends with `s`                            |
+//! | `lower(text)`             | `text` in lower case                                    |
+//! | `upper(text)`             | `text` in upper case                                    |
+//! | `trim(text)`              | `text` without surrounding whitespace                   |
+//! | `len(text)`               | number of characters in `text`                          |
+//! | `number(text)`            | `text` as a number, or null                             |
+//! | `version_gte(v, min)`     | whether dotted version `v` is at least `min`            |
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+/// Longest accepted expression, in bytes.
+pub const MAX_EXPR_LEN: usize = 4096;
+/// Deepest accepted nesting of operators, calls and parentheses.
+pub const MAX_DEPTH: usize = 32;
+
+/// Functions callable from expressions, with their number of arguments.
+pub const FUNCTIONS: &[(&str, usize)] = &[
+    ("default", 2),
+    ("exists", 1),
+    ("contains", 2),
+    ("starts_with", 2),
+    ("ends_with", 2),
+    ("lower", 1),
+    ("upper", 1),
+    ("trim", 1),
+    ("len", 1),
+    ("number", 1),
+    ("version_gte", 2),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(b) => *b,
+            Value::Num(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Str(s) => s.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => Ok(()),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Num(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Num(n) => write!(f, "{}", n),
+            Value::Str(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExprError {
+    pub message: String,
+    /// Byte offset in the expression the error refers to
+    pub offset: usize,
+}
+
+impl ExprError {
+    fn new(message: impl Into<String>, offset: usize) -> Self {
+        Self {
+            message: message.into(),
+            offset,
+        }
+    }
+}
+
+impl Display for ExprError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at position {})", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ExprError {}
+
+/// Variables available to an expression.
+pub trait Scope {
+    fn get(&self, name: &str) -> Option<Value>;
+}
+
+impl Scope for BTreeMap<String, String> {
+    fn get(&self, name: &str) -> Option<Value> {
+        BTreeMap::get(self, name).map(|v| Value::Str(v.clone()))
+    }
+}
+
+impl Scope for BTreeMap<String, Value> {
+    fn get(&self, name: &str) -> Option<Value> {
+        BTreeMap::get(self, name).cloned()
+    }
+}
+
+/// Add the leaves of `json` to `out` as dotted names under `prefix`, e.g.
+/// `battery.percent`. Arrays are skipped.
+pub fn flatten_json(prefix: &str, json: &serde_json::Value, out: &mut BTreeMap<String, Value>) {
+    let value = match json {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                flatten_json(&format!("{}.{}", prefix, key), value, out);
+            }
+            return;
+        }
+        serde_json::Value::Array(_) => return,
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(*b),
+        serde_json::Value::Number(n) => n.as_f64().map(Value::Num).unwrap_or(Value::Null),
+        serde_json::Value::String(s) => Value::Str(s.clone()),
+    };
+    out.insert(prefix.to_string(), value);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Lit(Value),
+    Var(String),
+    Not(Box<Node>),
+    Bin(BinOp, Box<Node>, Box<Node>),
+    Call(&'static str, Vec<Node>),
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    /// Parse `source`, checking syntax, function names and argument counts.
+    pub fn parse(source: &str) -> Result<Self, ExprError> {
+        if source.len() > MAX_EXPR_LEN {
+            return Err(ExprError::new(
+                format!("expression longer than {} bytes", MAX_EXPR_LEN),
+                MAX_EXPR_LEN,
+            ));
+        }
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            depth: 0,
+            end: source.len(),
+        };
+        let root = parser.or()?;
+        if let Some((token, offset)) = tokens.get(parser.pos) {
+            return Err(ExprError::new(format!("unexpected {}", token), *offset));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Names of the variables the expression reads.
+    pub fn variables(&self) -> Vec<&str> {
+        fn walk<'a>(node: &'a Node, out: &mut Vec<&'a str>) {
+            match node {
+                Node::Lit(_) => {}
+                Node::Var(name) => out.push(name),
+                Node::Not(inner) => walk(inner, out),
+                Node::Bin(_, l, r) => {
+                    walk(l, out);
+                    walk(r, out);
+                }
+                Node::Call(_, args) => args.iter().for_each(|a| walk(a, out)),
+            }
+        }
+        let mut out = Vec::new();
+        walk(&self.root, &mut out);
+        out.sort();
+        out.dedup();
+        out
+    }
+
+    pub fn eval(&self, scope: &dyn Scope) -> Value {
+        eval(&self.root, scope)
+    }
+
+    /// Evaluate as a condition.
+    pub fn matches(&self, scope: &dyn Scope) -> bool {
+        self.eval(scope).is_truthy()
+    }
+}
+
+/// Parse and evaluate `source` as a condition.
+pub fn evaluate_condition(source: &str, scope: &dyn Scope) -> Result<bool, ExprError> {
+    Ok(Expr::parse(source)?.matches(scope))
+}
+
+/// The `{{ expr }}` placeholders of a template, with their byte range
+/// (including the braces).
+pub fn template_placeholders(
+    input: &str,
+) -> Result<Vec<(std::ops::Range<usize>, Expr)>, ExprError> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = input[offset..].find("{{") {
+        let start = offset + start;
+        let end = input[start + 2..]
+            .find("}}")
+            .map(|e| start + 2 + e)
+            .ok_or_else(|| ExprError::new("unterminated '{{' in template", start))?;
+        let expr = Expr::parse(&input[start + 2..end]).map_err(|e| ExprError {
+            offset: start + 2 + e.offset,
+            ..e
+        })?;
+        out.push((start..end + 2, expr));
+        offset = end + 2;
+    }
+    Ok(out)
+}
+
+/// Replace every `{{ expr }}` in `input` with its value. Placeholders that
+/// evaluate to `null` are an error, so typos do not render as empty strings;
+/// use `default(...)` for optional values.
+pub fn render_template(input: &str, scope: &dyn Scope) -> Result<String, ExprError> {
+    let mut out = String::with_capacity(input.len());
+    let mut last = 0;
+    for (range, expr) in template_placeholders(input)? {
+        out.push_str(&input[last..range.start]);
+        match expr.eval(scope) {
+            Value::Null => {
+                return Err(ExprError::new(
+                    format!("template value '{}' is not set", expr.source().trim()),
+                    range.start,
+                ));
+            }
+            value => out.push_str(&value.to_string()),
+        }
+        last = range.end;
+    }
+    out.push_str(&input[last..]);
+    Ok(out)
+}
+
+// ---------------------------------------------------------
+// Tokenizer
+// ---------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Op(BinOp),
+    Not,
+    Open,
+    Close,
+    Comma,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "'{}'", name),
+            Token::Str(s) => write!(f, "string {:?}", s),
+            Token::Num(n) => write!(f, "number {}", n),
+            Token::Op(op) => write!(f, "'{}'", op_str(*op)),
+            Token::Not => write!(f, "'!'"),
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comma => write!(f, "','"),
+        }
+    }
+}
+
+fn op_str(op: BinOp) -> &'static str {
+    match op {
+        BinOp::Or => "||",
+        BinOp::And => "&&",
+        BinOp::Eq => "==",
+        BinOp::Ne => "!=",
+        BinOp::Lt => "<",
+        BinOp::Le => "<=",
+        BinOp::Gt => ">",
+        BinOp::Ge => ">=",
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, ExprError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    while let Some(&(at, c)) = chars.peek() {
+        chars.next();
+        let next = chars.peek().map(|&(_, c)| c);
+        let token = match (c, next) {
+            (c, _) if c.is_whitespace() => continue,
+            ('(', _) => Token::Open,
+            (')', _) => Token::Close,
+            (',', _) => Token::Comma,
+            ('|', Some('|')) | ('&', Some('&')) | ('=', Some('=')) | ('!', Some('=')) => {
+                chars.next();
+                Token::Op(match c {
+                    '|' => BinOp::Or,
+                    '&' => BinOp::And,
+                    '=' => BinOp::Eq,
+                    _ => BinOp::Ne,
+                })
+            }
+            ('<' | '>', Some('=')) => {
+                chars.next();
+                Token::Op(if c == '<' { BinOp::Le } else { BinOp::Ge })
+            }
+            ('<', _) => Token::Op(BinOp::Lt),
+            ('>', _) => Token::Op(BinOp::Gt),
+            ('!', _) => Token::Not,
+            ('"' | '\'', _) => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, q)) if q == c => break,
+                        Some((i, '\\')) => match chars.next() {
+                            Some((_, 'n')) => s.push('\n'),
+                            Some((_, 't')) => s.push('\t'),
+                            Some((_, e @ ('\\' | '"' | '\''))) => s.push(e),
+                            _ => return Err(ExprError::new("invalid escape in string", i)),
+                        },
+                        Some((_, ch)) => s.push(ch),
+                        None => return Err(ExprError::new("unterminated string", at)),
+                    }
+                }
+                Token::Str(s)
+            }
+            (c, next)
+                if c.is_ascii_digit() || (c == '-' && next.is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                let mut end = at + c.len_utf8();
+                while let Some(&(i, ch)) = chars.peek() {
+                    if ch.is_ascii_digit() || ch == '.' {
+                        end = i + ch.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                let text = &input[at..end];
+                Token::Num(
+                    text.parse()
+                        .map_err(|_| ExprError::new(format!("invalid number '{}'", text), at))?,
+                )
+            }
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                let mut end = at + c.len_utf8();
+                while let Some(&(i, ch)) = chars.peek() {
+                    if ch.is_alphanumeric() || matches!(ch, '_' | '.' | '-') {
+                        end = i + ch.len_utf8();
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Ident(input[at..end].to_string())
+            }
+            (c, _) => return Err(ExprError::new(format!("unexpected '{}'", c), at)),
+        };
+        tokens.push((token, at));
+    }
+    Ok(tokens)
+}
+
+// ---------------------------------------------------------
+// Parser
+// ---------------------------------------------------------
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+    depth: usize,
+    /// Offset reported for errors at the end of input
+    end: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.pos)
+            .map(|(_, o)| *o)
+            .unwrap_or(self.end)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn descend(&mut self) -> Result<(), ExprError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(ExprError::new(
+                format!("expression nested deeper than {}", MAX_DEPTH),
+                self.offset(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[BinOp],
+        next: fn(&mut Self) -> Result<Node, ExprError>,
+    ) -> Result<Node, ExprError> {
+        let mut left = next(self)?;
+        while let Some(Token::Op(op)) = self.peek().cloned() {
+            if !ops.contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right = next(self)?;
+            left = Node::Bin(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Node, ExprError> {
+        self.descend()?;
+        let node = self.binary(&[BinOp::Or], Self::and);
+        self.depth -= 1;
+        node
+    }
+
+    fn and(&mut self) -> Result<Node, ExprError> {
+        self.binary(&[BinOp::And], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Node, ExprError> {
+        let left = self.unary()?;
+        match self.peek().cloned() {
+            Some(Token::Op(op)) if !matches!(op, BinOp::Or | BinOp::And) => {
+                self.pos += 1;
+                let right = self.unary()?;
+                if let Some(Token::Op(op)) = self.peek()
+                    && !matches!(op, BinOp::Or | BinOp::And)
+                {
+                    return Err(ExprError::new(
+                        "comparisons cannot be chained, use '&&'",
+                        self.offset(),
+                    ));
+                }
+                Ok(Node::Bin(op, Box::new(left), Box::new(right)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, ExprError> {
+        if self.eat(&Token::Not) {
+            self.descend()?;
+            let inner = self.unary()?;
+            self.depth -= 1;
+            return Ok(Node::Not(Box::new(inner)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, ExprError> {
+        let offset = self.offset();
+        let Some((token, _)) = self.tokens.get(self.pos).cloned() else {
+            return Err(ExprError::new("unexpected end of expression", offset));
+        };
+        self.pos += 1;
+        match token {
+            Token::Str(s) => Ok(Node::Lit(Value::Str(s))),
+            Token::Num(n) => Ok(Node::Lit(Value::Num(n))),
+            Token::Open => {
+                let inner = self.or()?;
+                if !self.eat(&Token::Close) {
+                    return Err(ExprError::new("missing ')'", self.offset()));
+                }
+                Ok(inner)
+            }
+            Token::Ident(name) if self.peek() == Some(&Token::Open) => {
+                self.pos += 1;
+                self.call(&name, offset)
+            }
+            Token::Ident(name) => Ok(match name.as_str() {
+                "true" => Node::Lit(Value::Bool(true)),
+                "false" => Node::Lit(Value::Bool(false)),
+                "null" => Node::Lit(Value::Null),
+                _ => Node::Var(name),
+            }),
+            other => Err(ExprError::new(format!("unexpected {}", other), offset)),
+        }
+    }
+
+    fn call(&mut self, name: &str, offset: usize) -> Result<Node, ExprError> {
+        let Some(&(function, arity)) = FUNCTIONS.iter().find(|(f, _)| *f == name) else {
+            return Err(ExprError::new(
+                format!("unknown function '{}'", name),
+                offset,
+            ));
+        };
+        let mut args = Vec::new();
+        if !self.eat(&Token::Close) {
+            loop {
+                args.push(self.or()?);
+                if self.eat(&Token::Close) {
+                    break;
+                }
+                if !self.eat(&Token::Comma) {
+                    return Err(ExprError::new("expected ',' or ')'", self.offset()));
+                }
+            }
+        }
+        if args.len() != arity {
+            return Err(ExprError::new(
+                format!(
+                    "{}() takes {} argument{}, got {}",
+                    function,
+                    arity,
+                    if arity == 1 { "" } else { "s" },
+                    args.len()
+                ),
+                offset,
+            ));
+        }
+        Ok(Node::Call(function, args))
+    }
+}
+
+// ---------------------------------------------------------
+// Evaluation
+// ---------------------------------------------------------
+
+fn eval(node: &Node, scope: &dyn Scope) -> Value {
+    match node {
+        Node::Lit(v) => v.clone(),
+        Node::Var(name) => scope.get(name).unwrap_or(Value::Null),
+        Node::Not(inner) => Value::Bool(!eval(inner, scope).is_truthy()),
+        Node::Bin(BinOp::Or, l, r) => {
+            Value::Bool(eval(l, scope).is_truthy() || eval(r, scope).is_truthy())
+        }
+        Node::Bin(BinOp::And, l, r) => {
+            Value::Bool(eval(l, scope).is_truthy() && eval(r, scope).is_truthy())
+        }
+        Node::Bin(op, l, r) => {
+            let (l, r) = (eval(l, scope), eval(r, scope));
+            Value::Bool(match op {
+                BinOp::Eq => values_equal(&l, &r),
+                BinOp::Ne => !values_equal(&l, &r),
+                _ => compare(&l, &r).is_some_and(|ord| match op {
+                    BinOp::Lt => ord == Ordering::Less,
+                    BinOp::Le => ord != Ordering::Greater,
+                    BinOp::Gt => ord == Ordering::Greater,
+                    _ => ord != Ordering::Less,
+                }),
+            })
+        }
+        Node::Call(function, args) => {
+            let args: Vec<Value> = args.iter().map(|a| eval(a, scope)).collect();
+            call(function, &args)
+        }
+    }
+}
+
+fn values_equal(l: &Value, r: &Value) -> bool {
+    match (l, r) {
+        (Value::Null, Value::Null) => true,
+        (Value::Null, _) | (_, Value::Null) => false,
+        (Value::Num(_), _) | (_, Value::Num(_)) => match (l.as_number(), r.as_number()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        },
+        _ => l.to_string() == r.to_string(),
+    }
+}
+
+fn compare(l: &Value, r: &Value) -> Option<Ordering> {
+    match (l, r) {
+        (Value::Null, _) | (_, Value::Null) => None,
+        _ => match (l.as_number(), r.as_number()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => Some(l.to_string().cmp(&r.to_string())),
+        },
+    }
+}
+
+fn call(function: &str, args: &[Value]) -> Value {
+    let text = |i: usize| args[i].to_string();
+    match function {
+        "default" => match &args[0] {
+            Value::Null => args[1].clone(),
+            Value::Str(s) if s.is_empty() => args[1].clone(),
+            v => v.clone(),
+        },
+        "exists" => Value::Bool(args[0] != Value::Null),
+        "contains" => Value::Bool(text(0).contains(&text(1))),
+        "starts_with" => Value::Bool(text(0).starts_with(&text(1))),
+        "ends_with" => Value::Bool(text(0).ends_with(&text(1))),
+        "lower" => Value::Str(text(0).to_lowercase()),
+        "upper" => Value::Str(text(0).to_uppercase()),
+        "trim" => Value::Str(text(0).trim().to_string()),
+        "len" => Value::Num(text(0).chars().count() as f64),
+        "number" => args[0].as_number().map(Value::Num).unwrap_or(Value::Null),
+        "version_gte" => Value::Bool(version_cmp(&text(0), &text(1)) != Ordering::Less),
+        _ => Value::Null,
+    }
+}
+
+/// Compare dotted versions numerically (`1.10 > 1.9`); a leading `v` and any
+/// suffix after the numeric part (`-rc1`, `+build`) are ignored.
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    fn parts(v: &str) -> Vec<u64> {
+        v.trim()
+            .trim_start_matches('v')
+            .split('.')
+            .map_while(|p| {
+                let digits: String = p.chars().take_while(|c| c.is_ascii_digit()).collect();
+                digits.parse().ok()
+            })
+            .collect()
+    }
+    let (a, b) = (parts(a), parts(b));
+    for i in 0..a.len().max(b.len()) {
+        let ord = a.get(i).unwrap_or(&0).cmp(b.get(i).unwrap_or(&0));
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope() -> BTreeMap<String, String> {
+        [
+            ("facts.arch", "aarch64"),
+            ("facts.gpu.vendor", "nvidia"),
+            ("facts.cpu.cores", "8"),
+            ("facts.docker.version", "24.0.7"),
+            ("PORT", "8080"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    fn check(source: &str) -> bool {
+        evaluate_condition(source, &scope()).unwrap()
+    }
+
+    #[test]
+    fn test_conditions() {
+        assert!(check(
+            r#"facts.arch == "aarch64" && facts.gpu.vendor == "nvidia""#
+        ));
+        assert!(check(r#"facts.arch != 'x86_64' || facts.missing"#));
+        assert!(!check("facts.missing"));
+        assert!(check("!exists(facts.missing) && exists(facts.arch)"));
+        assert!(check("facts.cpu.cores >= 4 && facts.cpu.cores < 16"));
+        assert!(check("facts.cpu.cores == 8"));
+        assert!(!check("facts.missing > 1 || facts.missing <= 1"));
+        assert!(check(r#"version_gte(facts.docker.version, "20.10")"#));
+        assert!(!check(r#"version_gte(facts.docker.version, "v24.1")"#));
+        assert!(check(r#"starts_with(lower(upper(facts.arch)), "aarch")"#));
+        assert!(check(
+            r#"!(facts.arch == "x86_64") && len(facts.arch) == 7"#
+        ));
+        assert!(check(r#"contains("a \"quoted\" text", '"quoted"')"#));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = [
+            ("facts.arch = 'x'", 11),
+            ("(facts.arch", 11),
+            ("facts.arch ==", 13),
+            ("facts.arch 'a'", 11),
+            ("'open", 0),
+            ("unknown(1)", 0),
+            ("lower(1, 2)", 0),
+            ("1 < 2 < 3", 6),
+            ("a # b", 2),
+        ];
+        for (source, offset) in cases {
+            let err = Expr::parse(source).unwrap_err();
+            assert_eq!(err.offset, offset, "{}: {}", source, err);
+        }
+        let deep = format!(
+            "{}1{}",
+            "(".repeat(MAX_DEPTH + 1),
+            ")".repeat(MAX_DEPTH + 1)
+        );
+        assert!(Expr::parse(&deep).is_err());
+        assert!(Expr::parse(&"a || ".repeat(MAX_EXPR_LEN)).is_err());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let out = render_template(
+            r#"listen {{PORT}}; gpu={{ default(facts.gpu.name, "none") }}"#,
+            &scope(),
+        )
+        .unwrap();
+        assert_eq!(out, "listen 8080; gpu=none");
+        let err = render_template("{{ MISSING }}", &scope()).unwrap_err();
+        assert!(err.message.contains("MISSING"));
+        assert!(render_template("value {{ PORT", &scope()).is_err());
+        assert_eq!(
+            Expr::parse("default(PORT, facts.arch) == 1")
+                .unwrap()
+                .variables(),
+            vec!["PORT", "facts.arch"]
+        );
+    }
+
+    /// xorshift, so the fuzz tests are reproducible without extra dependencies
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn pick<'a>(&mut self, items: &[&'a str]) -> &'a str {
+            items[(self.next() % items.len() as u64) as usize]
+        }
+    }
+
+    #[test]
+    fn fuzz_token_soup() {
+        let pieces = [
+            "facts.arch",
+            "PORT",
+            "\"x\"",
+            "'y'",
+            "1",
+            "-2.5",
+            "true",
+            "null",
+            "==",
+            "!=",
+            "<",
+            ">=",
+            "&&",
+            "||",
+            "!",
+            "(",
+            ")",
+            ",",
+            "default(",
+            "len(",
+            "version_gte(",
+            " ",
+            "{{",
+            "}}",
+            "\\",
+            "\"",
+            "1.2.3",
+            "é",
+            "&",
+            "|",
+        ];
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..20_000 {
+            let len = rng.next() % 24;
+            let source: String = (0..len).map(|_| rng.pick(&pieces)).collect();
+            match Expr::parse(&source) {
+                Ok(expr) => {
+                    let _ = expr.eval(&scope());
+                }
+                Err(e) => assert!(e.offset <= source.len(), "{:?}: {}", source, e),
+            }
+            let _ = render_template(&source, &scope());
+        }
+    }
+
+    #[test]
+    fn fuzz_random_bytes() {
+        let mut rng = Rng(42);
+        for _ in 0..20_000 {
+            let len = rng.next() % 40;
+            let bytes: Vec<u8> = (0..len).map(|_| (rng.next() % 128) as u8).collect();
+            let source = String::from_utf8_lossy(&bytes);
+            if let Ok(expr) = Expr::parse(&source) {
+                let _ = expr.matches(&scope());
+            }
+        }
+    }
+}
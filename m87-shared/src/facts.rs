@@ -0,0 +1,60 @@
+//! Device facts: environment and hardware details collected by the runtime
+//! (OS, kernel, container runtimes, language runtimes, GPU drivers, attached
+//! hardware). Keys are dotted paths such as `os.name` or `gpu.vendor`.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::expr::{self, ExprError, Scope, Value};
+
+/// Prefix under which facts are exposed to templates and conditions.
+pub const FACTS_PREFIX: &str = "facts.";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct DeviceFacts {
+    /// Unix millis when the facts were collected
+    pub collected_at: u64,
+    pub facts: BTreeMap<String, String>,
+}
+
+impl DeviceFacts {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.facts.get(key).map(String::as_str)
+    }
+
+    /// Facts whose key equals `prefix` or starts with `prefix.`.
+    pub fn matching<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.facts
+            .iter()
+            .filter(move |(k, _)| {
+                prefix.is_empty()
+                    || k.as_str() == prefix
+                    || k.strip_prefix(prefix).is_some_and(|r| r.starts_with('.'))
+            })
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Facts keyed `facts.<key>`, for template variables.
+    pub fn template_vars(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        self.facts
+            .iter()
+            .map(|(k, v)| (format!("{}{}", FACTS_PREFIX, k), v.clone()))
+    }
+}
+
+impl Scope for DeviceFacts {
+    fn get(&self, name: &str) -> Option<Value> {
+        let key = name.strip_prefix(FACTS_PREFIX)?;
+        self.facts.get(key).map(|v| Value::Str(v.clone()))
+    }
+}
+
+impl DeviceFacts {
+    /// Evaluate a `when:` condition such as
+    /// `facts.arch == "aarch64" && facts.gpu.vendor == "nvidia"`. See
+    /// [`crate::expr`] for the syntax.
+    pub fn evaluate_condition(&self, condition: &str) -> Result<bool, ExprError> {
+        expr::evaluate_condition(condition, self)
+    }
+}
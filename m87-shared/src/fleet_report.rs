@@ -0,0 +1,235 @@
+//! Inventory report of the fleet for management reporting: one row per
+//! device with its asset info, versions, health, last contact, active
+//! revision and location (`m87 ls --export csv`, `GET /device/report`).
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::{DeviceAssetInfo, PublicDevice};
+use crate::metrics::Location;
+use crate::usage::csv_field;
+
+/// Version of the report format; bumped only when a field changes meaning
+pub const FLEET_REPORT_VERSION: u32 = 1;
+
+/// Columns of the CSV export, in order
+pub const FLEET_REPORT_CSV_HEADER: &str = "device_id,name,health,online,last_seen,version,\
+     target_version,operating_system,architecture,active_revision,open_incidents,unhealthy_runs,\
+     location,latitude,longitude,asset_tag,owner_contact,install_date,labels";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceHealth {
+    #[default]
+    Healthy,
+    /// Runs of the active revision are down or failing their health checks,
+    /// or the last runtime upgrade was reverted
+    Degraded,
+    /// An incident of the device is not resolved yet
+    Incident,
+    Maintenance,
+    Offline,
+}
+
+impl DeviceHealth {
+    /// The worst that applies: offline, in maintenance, an open incident,
+    /// then degraded.
+    pub fn assess(device: &PublicDevice, open_incidents: u64, unhealthy_runs: u64) -> Self {
+        if !device.online {
+            DeviceHealth::Offline
+        } else if device.maintenance.is_some() {
+            DeviceHealth::Maintenance
+        } else if open_incidents > 0 {
+            DeviceHealth::Incident
+        } else if unhealthy_runs > 0 || device.last_failed_upgrade.is_some() {
+            DeviceHealth::Degraded
+        } else {
+            DeviceHealth::Healthy
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceHealth::Healthy => "healthy",
+            DeviceHealth::Degraded => "degraded",
+            DeviceHealth::Incident => "incident",
+            DeviceHealth::Maintenance => "maintenance",
+            DeviceHealth::Offline => "offline",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FleetReportRow {
+    /// Short id
+    pub device_id: String,
+    pub name: String,
+    pub health: DeviceHealth,
+    pub online: bool,
+    /// RFC 3339; the time of the report for online devices
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    pub version: String,
+    pub target_version: String,
+    pub operating_system: String,
+    pub architecture: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_revision: Option<String>,
+    pub open_incidents: u64,
+    /// Runs of the active revision that are down or unhealthy
+    pub unhealthy_runs: u64,
+    /// Last reported position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(default, skip_serializing_if = "DeviceAssetInfo::is_empty")]
+    pub info: DeviceAssetInfo,
+}
+
+impl FleetReportRow {
+    pub fn new(
+        device: &PublicDevice,
+        last_seen: Option<String>,
+        active_revision: Option<String>,
+        open_incidents: u64,
+        unhealthy_runs: u64,
+    ) -> Self {
+        Self {
+            device_id: device.short_id.clone(),
+            name: device.name.clone(),
+            health: DeviceHealth::assess(device, open_incidents, unhealthy_runs),
+            online: device.online,
+            last_seen,
+            version: device.version.clone(),
+            target_version: device.target_version.clone(),
+            operating_system: device.system_info.operating_system.clone(),
+            architecture: device.system_info.architecture.clone(),
+            active_revision,
+            open_incidents,
+            unhealthy_runs,
+            location: device.last_location.clone(),
+            info: device.info.clone(),
+        }
+    }
+}
+
+/// Body of `GET /device/report`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FleetReport {
+    pub version: u32,
+    /// RFC 3339
+    pub generated_at: String,
+    pub devices: Vec<FleetReportRow>,
+}
+
+/// Rows as CSV with [`FLEET_REPORT_CSV_HEADER`]. The asset location is the
+/// `location` column, the last reported position `latitude` and
+/// `longitude`; custom asset fields are joined into `labels` as
+/// `key=value; ...`.
+pub fn fleet_report_csv(rows: &[FleetReportRow]) -> String {
+    let mut out = String::from(FLEET_REPORT_CSV_HEADER);
+    out.push('\n');
+    for r in rows {
+        let labels: Vec<String> = r
+            .info
+            .custom_fields
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        let fields = [
+            csv_field(&r.device_id),
+            csv_field(&r.name),
+            r.health.as_str().to_string(),
+            r.online.to_string(),
+            r.last_seen.clone().unwrap_or_default(),
+            csv_field(&r.version),
+            csv_field(&r.target_version),
+            csv_field(&r.operating_system),
+            csv_field(&r.architecture),
+            csv_field(r.active_revision.as_deref().unwrap_or_default()),
+            r.open_incidents.to_string(),
+            r.unhealthy_runs.to_string(),
+            csv_field(r.info.location.as_deref().unwrap_or_default()),
+            r.location
+                .as_ref()
+                .map(|l| l.latitude.to_string())
+                .unwrap_or_default(),
+            r.location
+                .as_ref()
+                .map(|l| l.longitude.to_string())
+                .unwrap_or_default(),
+            csv_field(r.info.asset_tag.as_deref().unwrap_or_default()),
+            csv_field(r.info.owner_contact.as_deref().unwrap_or_default()),
+            csv_field(r.info.install_date.as_deref().unwrap_or_default()),
+            csv_field(&labels.join("; ")),
+        ];
+        out.push_str(&fields.join(","));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(online: bool, open_incidents: u64, unhealthy_runs: u64) -> FleetReportRow {
+        let device: PublicDevice = serde_json::from_value(serde_json::json!({
+            "id": "65f0c0ffee",
+            "name": "gw, hall 3",
+            "short_id": "a1b2c3",
+            "updated_at": "2026-10-01T08:00:00Z",
+            "created_at": "2026-01-01T08:00:00Z",
+            "online": online,
+            "version": "0.9.0",
+            "target_version": "0.9.0",
+            "system_info": {
+                "hostname": "gw3",
+                "username": "m87",
+                "public_ip_address": null,
+                "operating_system": "Ubuntu 24.04",
+                "architecture": "arm64",
+                "cpu_name": "Cortex-A72",
+            },
+            "info": {
+                "location": "Plant 2",
+                "asset_tag": "A-17",
+                "custom_fields": { "line": "3", "team": "ops" },
+            },
+            "last_location": { "latitude": 48.1, "longitude": 11.5, "timestamp": 0 },
+        }))
+        .unwrap();
+        FleetReportRow::new(
+            &device,
+            Some("2026-10-16T09:00:00Z".to_string()),
+            Some("rev-42".to_string()),
+            open_incidents,
+            unhealthy_runs,
+        )
+    }
+
+    #[test]
+    fn health_is_the_worst_condition() {
+        assert_eq!(row(true, 0, 0).health, DeviceHealth::Healthy);
+        assert_eq!(row(true, 0, 2).health, DeviceHealth::Degraded);
+        assert_eq!(row(true, 1, 2).health, DeviceHealth::Incident);
+        assert_eq!(row(false, 1, 2).health, DeviceHealth::Offline);
+    }
+
+    #[test]
+    fn csv_flattens_asset_info_and_position() {
+        let csv = fleet_report_csv(&[row(true, 0, 1)]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], FLEET_REPORT_CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "a1b2c3,\"gw, hall 3\",degraded,true,2026-10-16T09:00:00Z,0.9.0,0.9.0,Ubuntu 24.04,\
+             arm64,rev-42,0,1,Plant 2,48.1,11.5,A-17,,,line=3; team=ops"
+        );
+        assert_eq!(
+            lines[0].split(',').count(),
+            lines[1]
+                .replace("\"gw, hall 3\"", "name")
+                .split(',')
+                .count()
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Generated gRPC types for the `make87.v1` API plus conversions from the REST types.
+
+pub mod v1 {
+    tonic::include_proto!("make87.v1");
+}
+
+use crate::deploy_spec::{self as spec, DeployReport, DeployReportKind, DeploymentRevision};
+use crate::device::PublicDevice;
+use v1::report::Kind as ReportKind;
+
+impl From<spec::Outcome> for v1::Outcome {
+    fn from(o: spec::Outcome) -> Self {
+        match o {
+            spec::Outcome::Success => v1::Outcome::Success,
+            spec::Outcome::Failed => v1::Outcome::Failed,
+            spec::Outcome::Unknown => v1::Outcome::Unknown,
+            spec::Outcome::Skipped => v1::Outcome::Skipped,
+        }
+    }
+}
+
+impl From<PublicDevice> for v1::Device {
+    fn from(d: PublicDevice) -> Self {
+        let info = d.system_info;
+        v1::Device {
+            id: d.id,
+            name: d.name,
+            short_id: d.short_id,
+            online: d.online,
+            version: d.version,
+            target_version: d.target_version,
+            last_connection: d.last_connection,
+            created_at: d.created_at,
+            updated_at: d.updated_at,
+            role: d.role.to_string(),
+            system_info: Some(v1::SystemInfo {
+                hostname: info.hostname,
+                operating_system: info.operating_system,
+                architecture: info.architecture,
+                cores: info.cores,
+                cpu_name: info.cpu_name,
+                memory: info.memory,
+                public_ip_address: info.public_ip_address,
+            }),
+        }
+    }
+}
+
+impl v1::Deployment {
+    pub fn from_revision(device_id: &str, rev: &DeploymentRevision) -> Self {
+        v1::Deployment {
+            id: rev.id.clone().unwrap_or_default(),
+            device_id: device_id.to_string(),
+            job_ids: rev.jobs.iter().map(|j| j.id.clone()).collect(),
+            spec_yaml: rev.to_yaml().unwrap_or_default(),
+            annotations: rev.annotations.clone().into_iter().collect(),
+            provenance: rev.provenance.clone().map(Into::into),
+        }
+    }
+}
+
+impl From<spec::Provenance> for v1::Provenance {
+    fn from(p: spec::Provenance) -> Self {
+        v1::Provenance {
+            git_sha: p.git_sha,
+            git_ref: p.git_ref,
+            repository: p.repository,
+            pipeline_url: p.pipeline_url,
+            author: p.author,
+        }
+    }
+}
+
+impl From<spec::DeploymentStatusSnapshot> for v1::DeploymentStatus {
+    fn from(s: spec::DeploymentStatusSnapshot) -> Self {
+        v1::DeploymentStatus {
+            revision_id: s.revision_id,
+            outcome: v1::Outcome::from(s.outcome) as i32,
+            dirty: s.dirty,
+            error: s.error,
+            runs: s
+                .runs
+                .into_iter()
+                .map(|r| v1::RunStatus {
+                    run_id: r.run_id,
+                    enabled: r.enabled,
+                    outcome: v1::Outcome::from(r.outcome) as i32,
+                    last_update: r.last_update,
+                    error: r.error,
+                    alive: r.alive.map(|a| a.ok),
+                    healthy: r.healthy.map(|h| h.ok),
+                })
+                .collect(),
+            annotations: s.annotations.into_iter().collect(),
+            provenance: s.provenance.map(Into::into),
+        }
+    }
+}
+
+impl From<DeployReportKind> for ReportKind {
+    fn from(kind: DeployReportKind) -> Self {
+        match kind {
+            DeployReportKind::DeploymentRevisionReport(r) => {
+                ReportKind::Deployment(v1::DeploymentRevisionReport {
+                    outcome: v1::Outcome::from(r.outcome) as i32,
+                    dirty: r.dirty,
+                    error: r.error,
+                })
+            }
+            DeployReportKind::RunReport(r) => ReportKind::Run(v1::RunReport {
+                run_id: r.run_id,
+                outcome: v1::Outcome::from(r.outcome) as i32,
+                report_time: r.report_time,
+                error: r.error,
+            }),
+            DeployReportKind::StepReport(r) => ReportKind::Step(v1::StepReport {
+                run_id: r.run_id,
+                name: r.name,
+                attempts: r.attempts,
+                exit_code: r.exit_code,
+                report_time: r.report_time,
+                success: r.success,
+                is_undo: r.is_undo,
+                error: r.error,
+                log_tail: r.log_tail,
+                artifacts: r.artifacts.into_iter().map(|a| a.path).collect(),
+            }),
+            DeployReportKind::RollbackReport(r) => ReportKind::Rollback(v1::RollbackReport {
+                new_revision_id: r.new_revision_id,
+            }),
+            DeployReportKind::RunState(r) => ReportKind::RunState(v1::RunStateReport {
+                run_id: r.run_id,
+                healthy: r.healthy,
+                alive: r.alive,
+                report_time: r.report_time,
+                log_tail: r.log_tail,
+            }),
+            DeployReportKind::DriftReport(r) => ReportKind::Drift(v1::DriftReport {
+                run_id: r.run_id,
+                files: r.files,
+                stopped: r.stopped,
+                remediated: r.remediated,
+                error: r.error,
+                report_time: r.report_time,
+            }),
+            DeployReportKind::CoreDump(r) => ReportKind::CoreDump(v1::CoreDumpReport {
+                run_id: r.run_id,
+                pid: r.pid,
+                signal: r.signal,
+                executable: r.executable,
+                size: r.size,
+                report_time: r.report_time,
+            }),
+            DeployReportKind::EgressViolation(r) => {
+                ReportKind::EgressViolation(v1::EgressViolationReport {
+                    run_id: r.run_id,
+                    blocked: r.blocked,
+                    destinations: r.destinations,
+                    report_time: r.report_time,
+                })
+            }
+        }
+    }
+}
+
+impl v1::Report {
+    pub fn from_kind(device_id: &str, kind: DeployReportKind) -> Self {
+        v1::Report {
+            device_id: device_id.to_string(),
+            revision_id: kind.get_revision_id().to_string(),
+            kind: Some(kind.into()),
+        }
+    }
+}
+
+impl From<DeployReport> for v1::Report {
+    fn from(r: DeployReport) -> Self {
+        v1::Report {
+            device_id: r.device_id,
+            revision_id: r.revision_id,
+            kind: Some(r.kind.into()),
+        }
+    }
+}
@@ -1,9 +1,14 @@
 use serde::{Deserialize, Serialize};
 
+use crate::bandwidth::BandwidthReport;
 use crate::config::DeviceClientConfig;
 use crate::deploy_spec::{DeployReportKind, DeploymentRevision};
-use crate::device::DeviceSystemInfo;
-use crate::metrics::SystemMetrics;
+use crate::device::{DeviceSystemInfo, FailedUpgrade, ObserveStatus, RecoveryUnlock, TimeStatus};
+use crate::inventory::{PackageInventory, PackageOperationReport};
+use crate::metrics::{BatteryMetrics, Location, SystemMetrics};
+use crate::patching::PatchRunReport;
+use crate::probe::ProbeResult;
+use crate::revision_delta::RevisionDelta;
 
 #[derive(Serialize, Deserialize, Debug, Default)]
 pub struct HeartbeatRequest {
@@ -17,6 +22,140 @@ pub struct HeartbeatRequest {
     pub active_revision: String,
     #[serde(default)]
     pub deploy_report: Option<DeployReportKind>,
+    /// Set once after the runtime reverted a self-update to the previous binary
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failed_upgrade: Option<FailedUpgrade>,
+    /// Runtime protocol version, sent with the first heartbeat of a tunnel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    /// Latest position fix, if the device has a location source configured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    /// Battery state, on devices that have one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<BatteryMetrics>,
+    /// ULID chosen by the runtime when the event was queued. Re-sent events
+    /// carry the same key and are ingested only once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Monotonic sequence number of `deploy_report` on this device. Orders
+    /// reports when the device clock cannot be trusted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_seq: Option<u64>,
+    /// NTP state of the device; the server fills in the offset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<TimeStatus>,
+    /// Device clock (unix millis) when the heartbeat was sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<u64>,
+    /// Installed packages and kernel, sent when they changed since the last
+    /// heartbeat that carried them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inventory: Option<PackageInventory>,
+    /// Package operations finished since the last heartbeat that carried them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub package_operations: Vec<PackageOperationReport>,
+    /// Patch policy runs finished since the last heartbeat that carried them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patch_runs: Vec<PatchRunReport>,
+    /// Recovery code unlocks since the last heartbeat that carried them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_unlocks: Vec<RecoveryUnlock>,
+    /// Unix time the device booted, sent with the first heartbeat of a tunnel
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_time: Option<u64>,
+    /// Probe runs since the last heartbeat that carried them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub probe_results: Vec<ProbeResult>,
+    /// Hash of the revision the runtime holds. Lets the server answer with a
+    /// [`RevisionDelta`] instead of the full target revision; left out to get
+    /// the full one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held_revision: Option<String>,
+    /// Traffic counters of the runtime, sent with the first heartbeat of a
+    /// tunnel and then every 15 minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<BandwidthReport>,
+    /// Summary of the deploy reports waiting for upload, sent with periodic
+    /// heartbeats while the bandwidth profile defers report uploads
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_digest: Option<ReportDigest>,
+}
+
+/// What a device's deploy reports would tell the server, without their log
+/// tails and step details, for devices that upload reports in batches.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ReportDigest {
+    /// Reports queued on the device, not stored by the server yet
+    pub pending_events: u32,
+    /// Latest state of each run among the queued reports
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub runs: Vec<RunDigest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunDigest {
+    pub run_id: String,
+    pub revision_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alive: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub healthy: Option<bool>,
+    pub report_time: u64,
+}
+
+impl ReportDigest {
+    /// Digest of queued reports, in any order.
+    pub fn from_reports<'a>(reports: impl IntoIterator<Item = &'a DeployReportKind>) -> Self {
+        let mut digest = ReportDigest::default();
+        for report in reports {
+            digest.pending_events += 1;
+            let DeployReportKind::RunState(state) = report else {
+                continue;
+            };
+            let run = RunDigest {
+                run_id: state.run_id.clone(),
+                revision_id: state.revision_id.clone(),
+                alive: state.alive,
+                healthy: state.healthy,
+                report_time: state.report_time,
+            };
+            match digest.runs.iter_mut().find(|r| r.run_id == run.run_id) {
+                Some(known) if known.report_time <= run.report_time => *known = run,
+                Some(_) => {}
+                None => digest.runs.push(run),
+            }
+        }
+        digest.runs.sort_by(|a, b| a.run_id.cmp(&b.run_id));
+        digest
+    }
+
+    /// Overlay the run states of `revision_id` on observations built from
+    /// the reports the server stored; queued states are newer.
+    pub fn apply(&self, revision_id: &str, observations: &mut Vec<ObserveStatus>) {
+        for run in self.runs.iter().filter(|r| r.revision_id == revision_id) {
+            let index = match observations.iter().position(|o| o.name == run.run_id) {
+                Some(index) => index,
+                None => {
+                    observations.push(ObserveStatus {
+                        name: run.run_id.clone(),
+                        alive: false,
+                        healthy: false,
+                        crashes: 0,
+                        unhealthy_checks: 0,
+                    });
+                    observations.len() - 1
+                }
+            };
+            let observation = &mut observations[index];
+            if let Some(alive) = run.alive {
+                observation.alive = alive;
+            }
+            if let Some(healthy) = run.healthy {
+                observation.healthy = healthy;
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,4 +166,87 @@ pub struct HeartbeatResponse {
     pub instruction_hash: String,
     #[serde(default)]
     pub target_revision: Option<DeploymentRevision>,
+    /// Sent instead of `target_revision` when the runtime holds some of its
+    /// runs already
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revision_delta: Option<RevisionDelta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol_version: Option<u32>,
+    /// Set when the server refuses the runtime's protocol version
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upgrade_required: Option<String>,
+    /// Outcome of the deploy report carried by the request, if it had an
+    /// idempotency key. The runtime deletes a queued event only after this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report_ack: Option<ReportAck>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReportAck {
+    pub idempotency_key: String,
+    pub status: ReportAckStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReportAckStatus {
+    /// Stored, by this or an earlier delivery
+    Stored { report_id: String },
+    /// Invalid (e.g. for a revision the server does not know); do not resend
+    Rejected { reason: String },
+    /// Not stored because of a server error; send again later
+    Retry,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy_spec::RunState;
+
+    fn state(run_id: &str, alive: bool, report_time: u64) -> DeployReportKind {
+        DeployReportKind::RunState(RunState {
+            run_id: run_id.to_string(),
+            revision_id: "rev".to_string(),
+            healthy: None,
+            alive: Some(alive),
+            report_time,
+            log_tail: Some("log".to_string()),
+        })
+    }
+
+    #[test]
+    fn test_digest_keeps_latest_run_state() {
+        let reports = [
+            state("a", true, 20),
+            state("a", false, 10),
+            state("b", false, 5),
+        ];
+        let digest = ReportDigest::from_reports(&reports);
+        assert_eq!(digest.pending_events, 3);
+        assert_eq!(digest.runs.len(), 2);
+        assert_eq!(digest.runs[0].run_id, "a");
+        assert_eq!(digest.runs[0].alive, Some(true));
+        assert_eq!(digest.runs[1].alive, Some(false));
+    }
+
+    #[test]
+    fn test_digest_overlays_observations() {
+        let digest = ReportDigest::from_reports(&[state("a", false, 10), state("b", true, 10)]);
+        let mut observations = vec![ObserveStatus {
+            name: "a".to_string(),
+            alive: true,
+            healthy: true,
+            crashes: 1,
+            unhealthy_checks: 0,
+        }];
+        digest.apply("rev", &mut observations);
+        assert_eq!(observations.len(), 2);
+        assert!(!observations[0].alive);
+        assert!(observations[0].healthy);
+        assert!(observations[1].alive);
+
+        let mut other = Vec::new();
+        digest.apply("other", &mut other);
+        assert!(other.is_empty());
+    }
 }
@@ -0,0 +1,126 @@
+//! Host names of the relay and of the devices behind it.
+//!
+//! The relay routes QUIC connections by SNI: `<device>.<relay>` and
+//! `control-<device>.<relay>`. A relay reached by IP literal has no name to
+//! put behind the device label, and SNI cannot carry an address, so the
+//! address is encoded as a label under [`IP_DOMAIN`]: `10-0-0-5.ip.invalid`,
+//! `2001-db8--1.ip.invalid`. Clients decode it instead of resolving it.
+
+use std::net::IpAddr;
+
+/// Reserved TLD, never resolved.
+pub const IP_DOMAIN: &str = "ip.invalid";
+
+/// Split `host[:port]`, `[v6]:port` or a bare IPv6 literal; brackets are removed.
+pub fn split_host_port(s: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = s.strip_prefix('[') {
+        return match rest.split_once(']') {
+            Some((host, tail)) => (host, tail.strip_prefix(':').and_then(|p| p.parse().ok())),
+            None => (rest, None),
+        };
+    }
+    match s.rsplit_once(':') {
+        // more than one colon without brackets is an IPv6 literal
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (s, None),
+        },
+        _ => (s, None),
+    }
+}
+
+/// The name devices of the relay at `host` (without port) are addressed under.
+pub fn sni_domain(host: &str) -> String {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    match host.parse::<IpAddr>() {
+        Ok(ip) => format!("{}.{}", encode_ip(ip), IP_DOMAIN),
+        Err(_) => host.trim_end_matches('.').to_ascii_lowercase(),
+    }
+}
+
+/// `<label>.<relay>[:port]` for a relay given as `host[:port]`.
+pub fn device_host(label: &str, relay: &str) -> String {
+    let (host, port) = split_host_port(relay);
+    match port {
+        Some(port) => format!("{}.{}:{}", label, sni_domain(host), port),
+        None => format!("{}.{}", label, sni_domain(host)),
+    }
+}
+
+/// The relay address encoded in `name`, if it is under [`IP_DOMAIN`].
+pub fn decode_ip(name: &str) -> Option<IpAddr> {
+    let rest = name
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+        .strip_suffix(IP_DOMAIN)?
+        .strip_suffix('.')?
+        .to_string();
+    let label = rest.rsplit('.').next()?;
+    label
+        .replace('-', ".")
+        .parse()
+        .or_else(|_| label.replace('-', ":").parse())
+        .ok()
+}
+
+fn encode_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => v4.to_string().replace('.', "-"),
+        IpAddr::V6(v6) => {
+            let mut s = v6.to_string();
+            // DNS labels cannot start or end with a hyphen: "::1" -> "0::1"
+            if s.starts_with(':') {
+                s.insert(0, '0');
+            }
+            if s.ends_with(':') {
+                s.push('0');
+            }
+            s.replace(':', "-")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(
+            split_host_port("relay.example:8084"),
+            ("relay.example", Some(8084))
+        );
+        assert_eq!(split_host_port("relay.example"), ("relay.example", None));
+        assert_eq!(
+            split_host_port("[2001:db8::1]:443"),
+            ("2001:db8::1", Some(443))
+        );
+        assert_eq!(split_host_port("[2001:db8::1]"), ("2001:db8::1", None));
+        assert_eq!(split_host_port("2001:db8::1"), ("2001:db8::1", None));
+    }
+
+    #[test]
+    fn test_device_host_round_trip() {
+        assert_eq!(
+            device_host("abc", "relay.example:8084"),
+            "abc.relay.example:8084"
+        );
+        assert_eq!(
+            device_host("control-abc", "[2001:db8::1]:8084"),
+            "control-abc.2001-db8--1.ip.invalid:8084"
+        );
+        assert_eq!(device_host("abc", "::1"), "abc.0--1.ip.invalid");
+        assert_eq!(device_host("abc", "10.0.0.5"), "abc.10-0-0-5.ip.invalid");
+
+        assert_eq!(
+            decode_ip("control-abc.2001-db8--1.ip.invalid"),
+            "2001:db8::1".parse().ok()
+        );
+        assert_eq!(decode_ip("abc.0--1.ip.invalid"), "::1".parse().ok());
+        assert_eq!(
+            decode_ip("abc.10-0-0-5.ip.invalid"),
+            "10.0.0.5".parse().ok()
+        );
+        assert_eq!(decode_ip("abc.relay.example"), None);
+    }
+}
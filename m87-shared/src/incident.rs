@@ -0,0 +1,156 @@
+//! Incidents group what happened around a device alert.
+//!
+//! The first alert of a device opens an [`Incident`]; later alerts, crashes,
+//! failed runs, rollbacks and disconnects of the device, and what operators
+//! do on it, are attached until someone resolves it. State changes are posted
+//! to the incident webhook of the device's organizations as an
+//! [`IncidentNotification`].
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Most entries kept per incident; older ones are dropped first
+pub const MAX_INCIDENT_ENTRIES: usize = 200;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IncidentState {
+    Open,
+    /// Someone is looking into it; entries are still attached
+    Acknowledged,
+    Resolved,
+}
+
+impl IncidentState {
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "open" => Some(IncidentState::Open),
+            "acknowledged" | "ack" => Some(IncidentState::Acknowledged),
+            "resolved" => Some(IncidentState::Resolved),
+            _ => None,
+        }
+    }
+
+    /// Incidents move forward only: open, acknowledged, resolved. Resolving
+    /// needs no acknowledgement first.
+    pub fn can_become(self, next: IncidentState) -> bool {
+        matches!(
+            (self, next),
+            (IncidentState::Open, IncidentState::Acknowledged)
+                | (IncidentState::Open, IncidentState::Resolved)
+                | (IncidentState::Acknowledged, IncidentState::Resolved)
+        )
+    }
+}
+
+impl fmt::Display for IncidentState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            IncidentState::Open => "open",
+            IncidentState::Acknowledged => "acknowledged",
+            IncidentState::Resolved => "resolved",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IncidentEntryKind {
+    /// An alert rule fired
+    Alert,
+    /// Something the device reported: a crash, failure, rollback or disconnect
+    Event,
+    /// Something an operator did on the device
+    Action,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IncidentEntry {
+    /// RFC 3339, server time
+    pub timestamp: String,
+    pub kind: IncidentEntryKind,
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Incident {
+    pub id: String,
+    pub device_id: String,
+    pub device_name: String,
+    /// Alert rule that opened the incident
+    pub rule: String,
+    /// Message of that alert
+    pub title: String,
+    pub state: IncidentState,
+    pub opened_at: String,
+    pub updated_at: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acknowledged_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_by: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolved_at: Option<String>,
+    /// Oldest first, at most [`MAX_INCIDENT_ENTRIES`]
+    #[serde(default)]
+    pub entries: Vec<IncidentEntry>,
+}
+
+/// Acknowledge or resolve an incident
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateIncidentBody {
+    pub state: IncidentState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// Body of the POST to an incident webhook. With a secret set, the request
+/// carries `X-M87-Signature-256: sha256=<hex HMAC-SHA256 of the body>`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IncidentNotification {
+    /// `incident.opened`, `incident.acknowledged` or `incident.resolved`
+    pub event: String,
+    pub incident: Incident,
+}
+
+impl IncidentNotification {
+    pub fn new(incident: Incident) -> Self {
+        IncidentNotification {
+            event: format!("incident.{}", incident.state),
+            incident,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        use IncidentState::*;
+        assert!(Open.can_become(Acknowledged));
+        assert!(Open.can_become(Resolved));
+        assert!(Acknowledged.can_become(Resolved));
+        assert!(!Acknowledged.can_become(Acknowledged));
+        assert!(!Acknowledged.can_become(Open));
+        assert!(!Resolved.can_become(Open));
+        assert!(!Resolved.can_become(Resolved));
+    }
+
+    #[test]
+    fn test_from_label() {
+        assert_eq!(
+            IncidentState::from_label("ack"),
+            Some(IncidentState::Acknowledged)
+        );
+        assert_eq!(
+            IncidentState::from_label(" Resolved "),
+            Some(IncidentState::Resolved)
+        );
+        assert_eq!(IncidentState::from_label("closed"), None);
+    }
+}
@@ -0,0 +1,550 @@
+//! Installed packages and kernel of a device, and the advisories matching them.
+//!
+//! The runtime reports its [`PackageInventory`] with a heartbeat whenever it
+//! changes. The server matches it against OSV, which aggregates the Debian,
+//! Ubuntu, Alpine and RHEL-clone trackers as well as kernel CVEs from NVD,
+//! and keeps the resulting [`Vulnerability`] list per device.
+//!
+//! Packages are installed, removed and upgraded with `m87 <device> pkg`; each
+//! run is reported back as a [`PackageOperationReport`] for the audit log.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Dpkg,
+    Rpm,
+    Apk,
+}
+
+impl fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PackageManager::Dpkg => "dpkg",
+            PackageManager::Rpm => "rpm",
+            PackageManager::Apk => "apk",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    /// Source package (dpkg `Source`, apk origin, rpm source RPM name) when it
+    /// differs from `name`. Distro advisories are filed against it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Version of the source package, when it differs from `version`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_version: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct PackageInventory {
+    /// Unix millis when the inventory was collected
+    pub collected_at: u64,
+    /// `ID` from /etc/os-release, e.g. `debian` or `alpine`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_id: Option<String>,
+    /// `VERSION_ID` from /etc/os-release, e.g. `12` or `3.19.1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    /// Kernel release, e.g. `6.1.0-18-arm64`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manager: Option<PackageManager>,
+    #[serde(default)]
+    pub packages: Vec<Package>,
+}
+
+/// One package version to look up in an OSV ecosystem.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AdvisoryQuery {
+    pub ecosystem: String,
+    pub name: String,
+    pub version: String,
+}
+
+/// OSV ecosystem of the kernel.org kernel.
+pub const KERNEL_ECOSYSTEM: &str = "Linux";
+
+impl PackageInventory {
+    /// Same packages, kernel and OS, ignoring when they were collected.
+    pub fn same_contents(&self, other: &PackageInventory) -> bool {
+        self.os_id == other.os_id
+            && self.os_version == other.os_version
+            && self.kernel == other.kernel
+            && self.manager == other.manager
+            && self.packages == other.packages
+    }
+
+    /// OSV ecosystem of the distribution, e.g. `Debian:12`, `Ubuntu:22.04:LTS`
+    /// or `Alpine:v3.19`. None for distributions OSV does not track.
+    pub fn osv_ecosystem(&self) -> Option<String> {
+        let id = self.os_id.as_deref()?;
+        let version = self.os_version.as_deref()?;
+        let major = version.split('.').next()?;
+        match id {
+            "debian" | "raspbian" => Some(format!("Debian:{}", major)),
+            "ubuntu" => {
+                let lts = version.ends_with(".04")
+                    && major.parse::<u32>().is_ok_and(|m| m.is_multiple_of(2));
+                Some(format!(
+                    "Ubuntu:{}{}",
+                    version,
+                    if lts { ":LTS" } else { "" }
+                ))
+            }
+            "alpine" => {
+                let minor = version.split('.').nth(1)?;
+                Some(format!("Alpine:v{}.{}", major, minor))
+            }
+            "rocky" => Some(format!("Rocky Linux:{}", major)),
+            "almalinux" => Some(format!("AlmaLinux:{}", major)),
+            _ => None,
+        }
+    }
+
+    /// Lookups for the advisory scan, one per source package version. The
+    /// kernel is looked up in the upstream [`KERNEL_ECOSYSTEM`] only on
+    /// systems without a tracked distribution (Yocto, Buildroot, ...): distro
+    /// kernels carry backported fixes and are covered by their package.
+    pub fn advisory_queries(&self) -> Vec<AdvisoryQuery> {
+        let mut queries = BTreeSet::new();
+        if let Some(ecosystem) = self.osv_ecosystem() {
+            for package in &self.packages {
+                queries.insert(AdvisoryQuery {
+                    ecosystem: ecosystem.clone(),
+                    name: package
+                        .source
+                        .clone()
+                        .unwrap_or_else(|| package.name.clone()),
+                    version: package
+                        .source_version
+                        .clone()
+                        .unwrap_or_else(|| package.version.clone()),
+                });
+            }
+        } else if let Some(version) = self.kernel.as_deref().and_then(upstream_kernel_version) {
+            queries.insert(AdvisoryQuery {
+                ecosystem: KERNEL_ECOSYSTEM.to_string(),
+                name: "Kernel".to_string(),
+                version,
+            });
+        }
+        queries.into_iter().collect()
+    }
+}
+
+/// `6.1.0-18-arm64` -> `6.1.0`, `5.15.0-91-generic` -> `5.15.0`, `6.6.7+rpt` -> `6.6.7`.
+pub fn upstream_kernel_version(release: &str) -> Option<String> {
+    let end = release
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(release.len());
+    let version = release[..end].trim_end_matches('.');
+    (version.contains('.')).then(|| version.to_string())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// Parse a tracker rating such as `moderate`, `important` or Ubuntu's
+    /// `negligible`.
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "negligible" | "low" => Some(Severity::Low),
+            "medium" | "moderate" => Some(Severity::Medium),
+            "high" | "important" => Some(Severity::High),
+            "critical" => Some(Severity::Critical),
+            _ => None,
+        }
+    }
+
+    /// Qualitative rating of a CVSS base score; None for 0.0.
+    pub fn from_score(score: f64) -> Option<Self> {
+        match score {
+            s if s >= 9.0 => Some(Severity::Critical),
+            s if s >= 7.0 => Some(Severity::High),
+            s if s >= 4.0 => Some(Severity::Medium),
+            s if s > 0.0 => Some(Severity::Low),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Severity::Low => "low",
+            Severity::Medium => "medium",
+            Severity::High => "high",
+            Severity::Critical => "critical",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Base score of a CVSS v3.x vector such as
+/// `CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H`. Other versions are None.
+pub fn cvss3_base_score(vector: &str) -> Option<f64> {
+    let mut metrics = vector.split('/');
+    if !metrics.next()?.starts_with("CVSS:3") {
+        return None;
+    }
+    let mut m = std::collections::HashMap::new();
+    for metric in metrics {
+        let (k, v) = metric.split_once(':')?;
+        m.insert(k, v);
+    }
+    let changed = match *m.get("S")? {
+        "U" => false,
+        "C" => true,
+        _ => return None,
+    };
+    let av = match *m.get("AV")? {
+        "N" => 0.85,
+        "A" => 0.62,
+        "L" => 0.55,
+        "P" => 0.2,
+        _ => return None,
+    };
+    let ac = match *m.get("AC")? {
+        "L" => 0.77,
+        "H" => 0.44,
+        _ => return None,
+    };
+    let pr = match (*m.get("PR")?, changed) {
+        ("N", _) => 0.85,
+        ("L", false) => 0.62,
+        ("L", true) => 0.68,
+        ("H", false) => 0.27,
+        ("H", true) => 0.5,
+        _ => return None,
+    };
+    let ui = match *m.get("UI")? {
+        "N" => 0.85,
+        "R" => 0.62,
+        _ => return None,
+    };
+    let cia = |key: &str| match *m.get(key)? {
+        "H" => Some(0.56),
+        "L" => Some(0.22),
+        "N" => Some(0.0),
+        _ => None,
+    };
+    let iss = 1.0 - (1.0 - cia("C")?) * (1.0 - cia("I")?) * (1.0 - cia("A")?);
+    let impact = if changed {
+        7.52 * (iss - 0.029) - 3.25 * (iss - 0.02_f64).powi(15)
+    } else {
+        6.42 * iss
+    };
+    if impact <= 0.0 {
+        return Some(0.0);
+    }
+    let exploitability = 8.22 * av * ac * pr * ui;
+    let score = if changed {
+        1.08 * (impact + exploitability)
+    } else {
+        impact + exploitability
+    };
+    Some(round_up(score.min(10.0)))
+}
+
+/// CVSS "Roundup": smallest one-decimal number >= `x`, robust to float error.
+fn round_up(x: f64) -> f64 {
+    let int = (x * 100_000.0).round() as u64;
+    if int.is_multiple_of(10_000) {
+        int as f64 / 100_000.0
+    } else {
+        (int / 10_000 + 1) as f64 / 10.0
+    }
+}
+
+/// An advisory affecting a package installed on a device.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Vulnerability {
+    /// Advisory id, e.g. `DSA-5581-1`, `CVE-2024-6387` or `ALPINE-CVE-...`
+    pub id: String,
+    /// Other ids of the same issue, typically the CVE
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    pub package: String,
+    pub installed_version: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+impl Vulnerability {
+    /// The CVE id if the advisory has one, its own id otherwise.
+    pub fn display_id(&self) -> &str {
+        cve_id(&self.id, &self.aliases)
+    }
+}
+
+fn cve_id<'a>(id: &'a str, aliases: &'a [String]) -> &'a str {
+    if id.starts_with("CVE-") {
+        return id;
+    }
+    aliases
+        .iter()
+        .find(|a| a.starts_with("CVE-"))
+        .map_or(id, String::as_str)
+}
+
+/// Scan result of one device.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct DeviceVulnerabilities {
+    pub device_id: String,
+    pub device_name: String,
+    /// Unix millis of the inventory the scan used
+    pub inventory_collected_at: u64,
+    /// Unix millis of the last advisory scan; None while the first is pending
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scanned_at: Option<u64>,
+    /// Why the last scan failed, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scan_error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ecosystem: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    pub package_count: usize,
+    #[serde(default)]
+    pub vulnerabilities: Vec<Vulnerability>,
+}
+
+/// One advisory across the devices of an organization.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VulnerabilityReportEntry {
+    pub id: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    pub packages: Vec<String>,
+    /// Names of the affected devices
+    pub devices: Vec<String>,
+}
+
+/// Organization-wide report, most severe and widespread advisories first.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct VulnerabilityReport {
+    pub devices_scanned: usize,
+    /// Devices that have not reported an inventory or were not scanned yet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub devices_pending: Vec<String>,
+    pub vulnerabilities: Vec<VulnerabilityReportEntry>,
+}
+
+impl VulnerabilityReportEntry {
+    /// The CVE id if the advisory has one, its own id otherwise.
+    pub fn display_id(&self) -> &str {
+        cve_id(&self.id, &self.aliases)
+    }
+}
+
+impl VulnerabilityReport {
+    pub fn from_devices(devices: &[DeviceVulnerabilities]) -> Self {
+        let mut entries: Vec<VulnerabilityReportEntry> = Vec::new();
+        let mut report = VulnerabilityReport::default();
+        for device in devices {
+            if device.scanned_at.is_none() {
+                report.devices_pending.push(device.device_name.clone());
+                continue;
+            }
+            report.devices_scanned += 1;
+            for vuln in &device.vulnerabilities {
+                let entry = match entries.iter_mut().find(|e| e.id == vuln.id) {
+                    Some(entry) => entry,
+                    None => {
+                        entries.push(VulnerabilityReportEntry {
+                            id: vuln.id.clone(),
+                            aliases: vuln.aliases.clone(),
+                            severity: vuln.severity,
+                            summary: vuln.summary.clone(),
+                            packages: Vec::new(),
+                            devices: Vec::new(),
+                        });
+                        entries.last_mut().unwrap()
+                    }
+                };
+                if !entry.packages.contains(&vuln.package) {
+                    entry.packages.push(vuln.package.clone());
+                }
+                if !entry.devices.contains(&device.device_name) {
+                    entry.devices.push(device.device_name.clone());
+                }
+            }
+        }
+        entries.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then(b.devices.len().cmp(&a.devices.len()))
+                .then(a.id.cmp(&b.id))
+        });
+        report.vulnerabilities = entries;
+        report
+    }
+}
+
+/// Package manager operation of `m87 <device> pkg`.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageAction {
+    Install,
+    Remove,
+    /// Upgrade the listed packages, or all of them when none are listed
+    Upgrade,
+}
+
+impl fmt::Display for PackageAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PackageAction::Install => "install",
+            PackageAction::Remove => "remove",
+            PackageAction::Upgrade => "upgrade",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Outcome of a package operation, sent with the next heartbeat and recorded
+/// in the device's audit log.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PackageOperationReport {
+    pub action: PackageAction,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Package manager that ran it: `apt-get`, `dnf`, `yum` or `apk`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+    /// Operator who started it (email or token subject)
+    pub user: String,
+    /// Unix millis
+    pub started_at: u64,
+    /// Unix millis
+    pub report_time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    pub success: bool,
+    /// If it failed, short error text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Best-effort tail of the package manager output (bounded).
+    #[serde(default)]
+    pub log_tail: String,
+}
+
+impl PackageOperationReport {
+    /// E.g. `upgrade of all packages (dry run)` or `install of curl, jq`.
+    pub fn describe(&self) -> String {
+        let packages = if self.packages.is_empty() {
+            "all packages".to_string()
+        } else {
+            self.packages.join(", ")
+        };
+        let dry_run = if self.dry_run { " (dry run)" } else { "" };
+        format!("{} of {}{}", self.action, packages, dry_run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cvss3_base_score() {
+        let score = |v| cvss3_base_score(v).unwrap();
+        assert_eq!(score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:H/I:H/A:H"), 9.8);
+        assert_eq!(score("CVSS:3.1/AV:N/AC:L/PR:N/UI:R/S:C/C:L/I:L/A:N"), 6.1);
+        assert_eq!(score("CVSS:3.0/AV:L/AC:L/PR:L/UI:N/S:U/C:H/I:N/A:N"), 5.5);
+        assert_eq!(score("CVSS:3.1/AV:N/AC:L/PR:N/UI:N/S:U/C:N/I:N/A:N"), 0.0);
+        assert_eq!(cvss3_base_score("CVSS:4.0/AV:N/AC:L/AT:N"), None);
+        assert_eq!(Severity::from_score(9.8), Some(Severity::Critical));
+        assert_eq!(Severity::from_score(6.1), Some(Severity::Medium));
+        assert_eq!(Severity::from_label("Important"), Some(Severity::High));
+    }
+
+    #[test]
+    fn test_advisory_queries() {
+        let mut inventory = PackageInventory {
+            os_id: Some("debian".into()),
+            os_version: Some("12".into()),
+            kernel: Some("6.1.0-18-arm64".into()),
+            manager: Some(PackageManager::Dpkg),
+            packages: vec![
+                Package {
+                    name: "libssl3".into(),
+                    version: "3.0.11-1~deb12u2".into(),
+                    source: Some("openssl".into()),
+                    source_version: None,
+                },
+                Package {
+                    name: "openssl".into(),
+                    version: "3.0.11-1~deb12u2".into(),
+                    source: None,
+                    source_version: None,
+                },
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            inventory.advisory_queries(),
+            vec![AdvisoryQuery {
+                ecosystem: "Debian:12".into(),
+                name: "openssl".into(),
+                version: "3.0.11-1~deb12u2".into(),
+            }]
+        );
+
+        inventory.os_id = Some("poky".into());
+        assert_eq!(
+            inventory.advisory_queries(),
+            vec![AdvisoryQuery {
+                ecosystem: KERNEL_ECOSYSTEM.into(),
+                name: "Kernel".into(),
+                version: "6.1.0".into(),
+            }]
+        );
+
+        let os = |id: &str, version: &str| PackageInventory {
+            os_id: Some(id.into()),
+            os_version: Some(version.into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            os("ubuntu", "22.04").osv_ecosystem().as_deref(),
+            Some("Ubuntu:22.04:LTS")
+        );
+        assert_eq!(
+            os("ubuntu", "23.10").osv_ecosystem().as_deref(),
+            Some("Ubuntu:23.10")
+        );
+        assert_eq!(
+            os("alpine", "3.19.1").osv_ecosystem().as_deref(),
+            Some("Alpine:v3.19")
+        );
+    }
+}
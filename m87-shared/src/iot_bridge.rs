@@ -0,0 +1,195 @@
+//! Republishing the presence and key telemetry of an organization's devices
+//! to an external IoT platform (AWS IoT Core, Azure IoT Hub or ThingsBoard),
+//! so fleets migrating from one can run both without a second agent.
+
+use serde::{Deserialize, Serialize};
+
+use crate::metrics::SystemMetrics;
+
+pub const DEFAULT_TELEMETRY_INTERVAL_SECS: u64 = 60;
+pub const DEFAULT_AWS_TOPIC_PREFIX: &str = "m87";
+pub const DEFAULT_AZURE_POLICY_NAME: &str = "iothubowner";
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IotPlatform {
+    AwsIot,
+    AzureIotHub,
+    Thingsboard,
+}
+
+impl IotPlatform {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IotPlatform::AwsIot => "aws_iot",
+            IotPlatform::AzureIotHub => "azure_iot_hub",
+            IotPlatform::Thingsboard => "thingsboard",
+        }
+    }
+}
+
+/// Where and as whom the server publishes. Credentials are never returned.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "platform", rename_all = "snake_case")]
+pub enum IotBridgeTarget {
+    /// Publishes to `<topic_prefix>/<device>/presence` and `.../telemetry`
+    /// through the HTTPS data plane, signed with SigV4
+    AwsIot {
+        /// Data endpoint, e.g. `abc123-ats.iot.eu-central-1.amazonaws.com`
+        endpoint: String,
+        access_key_id: String,
+        secret_access_key: String,
+        #[serde(default = "default_topic_prefix")]
+        topic_prefix: String,
+    },
+    /// Sends device-to-cloud messages on behalf of each device, registering
+    /// devices the hub does not know yet
+    AzureIotHub {
+        /// e.g. `my-hub.azure-devices.net`
+        host_name: String,
+        /// Shared access policy with registry write and device connect
+        #[serde(default = "default_policy_name")]
+        policy_name: String,
+        policy_key: String,
+    },
+    /// Saves telemetry and the `m87_online` server attribute through the
+    /// REST API as a tenant user, creating devices by name
+    Thingsboard {
+        url: String,
+        username: String,
+        password: String,
+    },
+}
+
+fn default_topic_prefix() -> String {
+    DEFAULT_AWS_TOPIC_PREFIX.to_string()
+}
+
+fn default_policy_name() -> String {
+    DEFAULT_AZURE_POLICY_NAME.to_string()
+}
+
+impl IotBridgeTarget {
+    pub fn platform(&self) -> IotPlatform {
+        match self {
+            IotBridgeTarget::AwsIot { .. } => IotPlatform::AwsIot,
+            IotBridgeTarget::AzureIotHub { .. } => IotPlatform::AzureIotHub,
+            IotBridgeTarget::Thingsboard { .. } => IotPlatform::Thingsboard,
+        }
+    }
+
+    /// Host or URL published to, without credentials.
+    pub fn endpoint(&self) -> &str {
+        match self {
+            IotBridgeTarget::AwsIot { endpoint, .. } => endpoint,
+            IotBridgeTarget::AzureIotHub { host_name, .. } => host_name,
+            IotBridgeTarget::Thingsboard { url, .. } => url,
+        }
+    }
+}
+
+/// The IoT bridge of an organization, as returned by the server
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OrgIotBridge {
+    pub org_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<IotPlatform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub telemetry_interval_secs: u64,
+    /// RFC 3339, of the last successful publish
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_published_at: Option<String>,
+    /// Error of the last publish, if it failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+/// Replaces the bridge of the organization; no `target` removes it
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UpdateOrgIotBridgeBody {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<IotBridgeTarget>,
+    /// Least time between two telemetry messages of a device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telemetry_interval_secs: Option<u64>,
+}
+
+/// Published when a device connects or disconnects.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct IotPresence {
+    pub device_id: String,
+    pub device_name: String,
+    pub online: bool,
+    /// Unix millis
+    pub time: u64,
+}
+
+/// Published from device heartbeats, at most once per telemetry interval.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct IotTelemetry {
+    pub device_id: String,
+    pub device_name: String,
+    /// Unix millis
+    pub time: u64,
+    pub cpu_percent: f32,
+    pub memory_percent: f32,
+    pub disk_percent: f32,
+    pub rx_mbps: f32,
+    pub tx_mbps: f32,
+    pub uptime_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery_percent: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+}
+
+impl IotTelemetry {
+    pub fn from_metrics(
+        device_id: &str,
+        device_name: &str,
+        metrics: &SystemMetrics,
+        time: u64,
+    ) -> Self {
+        Self {
+            device_id: device_id.to_string(),
+            device_name: device_name.to_string(),
+            time,
+            cpu_percent: metrics.cpu.usage_percent,
+            memory_percent: metrics.memory.usage_percent,
+            disk_percent: metrics.disk.usage_percent,
+            rx_mbps: metrics.network.rx_mbps,
+            tx_mbps: metrics.network.tx_mbps,
+            uptime_secs: metrics.uptime_secs,
+            battery_percent: metrics.battery.as_ref().map(|b| b.percent),
+            latitude: metrics.location.as_ref().map(|l| l.latitude),
+            longitude: metrics.location.as_ref().map(|l| l.longitude),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_is_tagged_by_platform_with_defaults() {
+        let target: IotBridgeTarget = serde_json::from_str(
+            r#"{"platform":"azure_iot_hub","host_name":"hub.azure-devices.net","policy_key":"a2V5"}"#,
+        )
+        .unwrap();
+        assert_eq!(target.platform(), IotPlatform::AzureIotHub);
+        assert_eq!(target.endpoint(), "hub.azure-devices.net");
+        assert_eq!(
+            target,
+            IotBridgeTarget::AzureIotHub {
+                host_name: "hub.azure-devices.net".to_string(),
+                policy_name: DEFAULT_AZURE_POLICY_NAME.to_string(),
+                policy_key: "a2V5".to_string(),
+            }
+        );
+    }
+}
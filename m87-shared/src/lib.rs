@@ -1,10 +1,38 @@
+pub mod access;
+pub mod approval;
 pub mod auth;
+pub mod bandwidth;
 pub mod config;
+pub mod db_stats;
 pub mod deploy_spec;
 pub mod device;
+pub mod egress;
+pub mod enrollment;
+pub mod expr;
+pub mod facts;
+pub mod fleet_report;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod heartbeat;
+pub mod host;
+pub mod incident;
+pub mod inventory;
+pub mod iot_bridge;
+pub mod log_filter;
+pub mod log_format;
+pub mod log_metrics;
 pub mod metrics;
+pub mod notices;
 pub mod org;
 pub mod pagination;
+pub mod patching;
+pub mod probe;
+pub mod protocol;
+pub mod redact;
+pub mod relay;
+pub mod revision_delta;
 pub mod roles;
+pub mod rollout;
+pub mod usage;
 pub mod users;
+pub mod webhook;
@@ -0,0 +1,128 @@
+//! Filters of the logs stream (`m87 <device> logs --service --since --grep`).
+//! The CLI sends them in the stream header and the runtime applies them
+//! before lines leave the device, so busy devices only ship what matches.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Source of the runtime's own lines; service lines carry their run id.
+pub const RUNTIME_SOURCE: &str = "m87";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LogFilter {
+    /// Only lines of this run, or of the runtime itself with `m87`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// Replay lines the runtime kept from this many seconds back before
+    /// following new ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since_secs: Option<u64>,
+    /// Only lines matching this regular expression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grep: Option<String>,
+}
+
+impl LogFilter {
+    pub fn is_empty(&self) -> bool {
+        self == &LogFilter::default()
+    }
+
+    pub fn matcher(&self) -> Result<LogMatcher, String> {
+        let grep = match &self.grep {
+            Some(pattern) => {
+                Some(Regex::new(pattern).map_err(|e| format!("invalid --grep pattern: {}", e))?)
+            }
+            None => None,
+        };
+        Ok(LogMatcher {
+            service: self.service.clone(),
+            grep,
+        })
+    }
+}
+
+/// A compiled [`LogFilter`], without the time bound.
+#[derive(Debug, Clone)]
+pub struct LogMatcher {
+    service: Option<String>,
+    grep: Option<Regex>,
+}
+
+impl LogMatcher {
+    /// Whether a line of `source` with `text` passes. `text` is matched
+    /// without terminal colors.
+    pub fn matches(&self, source: &str, text: &str) -> bool {
+        if self.service.as_deref().is_some_and(|s| s != source) {
+            return false;
+        }
+        match &self.grep {
+            Some(grep) => grep.is_match(&strip_ansi(text)),
+            None => true,
+        }
+    }
+}
+
+/// `text` without ANSI escape sequences.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
+        }
+        // CSI sequences end with a letter; other escapes are two characters
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_by_service_and_pattern() {
+        let filter = LogFilter {
+            service: Some("camera".to_string()),
+            grep: Some("timeout|refused".to_string()),
+            ..Default::default()
+        };
+        let matcher = filter.matcher().unwrap();
+        assert!(matcher.matches("camera", "connect refused"));
+        assert!(!matcher.matches("camera", "frame 12 ok"));
+        assert!(!matcher.matches(RUNTIME_SOURCE, "connect refused"));
+
+        let runtime = LogFilter {
+            grep: Some("^WARN disk".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            runtime
+                .matcher()
+                .unwrap()
+                .matches(RUNTIME_SOURCE, "\x1b[33mWARN\x1b[0m disk almost full")
+        );
+    }
+
+    #[test]
+    fn invalid_pattern_is_reported() {
+        let filter = LogFilter {
+            grep: Some("(unclosed".to_string()),
+            ..Default::default()
+        };
+        assert!(
+            filter
+                .matcher()
+                .unwrap_err()
+                .contains("invalid --grep pattern")
+        );
+        assert!(LogFilter::default().is_empty());
+    }
+}
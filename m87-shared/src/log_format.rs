@@ -0,0 +1,407 @@
+//! Structured parsing of service log lines. A run picks the format of its
+//! followed logs with `observe.logs.format`: `json`, `logfmt` or
+//! `regex:<pattern>`; lines are split into level, message, timestamp and the
+//! remaining fields. Lines that do not parse are kept whole as the message.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// Keys checked, in order, for the level of a `json` or `logfmt` line.
+const LEVEL_KEYS: &[&str] = &["level", "lvl", "severity", "loglevel", "log.level"];
+/// Keys checked, in order, for the message.
+const MESSAGE_KEYS: &[&str] = &["msg", "message", "text"];
+/// Keys checked, in order, for the timestamp.
+const TS_KEYS: &[&str] = &["ts", "time", "timestamp", "@timestamp"];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum LogFormat {
+    /// One JSON object per line
+    Json,
+    /// `key=value` pairs, values with spaces in double quotes
+    Logfmt,
+    /// Named groups `level`, `message` (or `msg`) and `ts`; other named
+    /// groups become fields
+    Regex(String),
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "logfmt" => Ok(LogFormat::Logfmt),
+            _ => match s.strip_prefix("regex:") {
+                Some(pattern) => {
+                    Regex::new(pattern)
+                        .map_err(|e| format!("invalid log format pattern: {}", e))?;
+                    Ok(LogFormat::Regex(pattern.to_string()))
+                }
+                None => Err(format!(
+                    "unknown log format '{}', expected json, logfmt or regex:<pattern>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl TryFrom<String> for LogFormat {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Json => write!(f, "json"),
+            LogFormat::Logfmt => write!(f, "logfmt"),
+            LogFormat::Regex(pattern) => write!(f, "regex:{}", pattern),
+        }
+    }
+}
+
+impl From<LogFormat> for String {
+    fn from(format: LogFormat) -> Self {
+        format.to_string()
+    }
+}
+
+/// Severity of a log line, from least to most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl LogLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+            LogLevel::Fatal => "fatal",
+        }
+    }
+
+    /// The level a logger wrote as `value`: common names and abbreviations in
+    /// any case, and the numeric levels of bunyan and pino (10 to 60).
+    pub fn parse(value: &str) -> Option<Self> {
+        let level = match value.trim().to_ascii_lowercase().as_str() {
+            "trace" | "trc" | "10" => LogLevel::Trace,
+            "debug" | "dbg" | "20" => LogLevel::Debug,
+            "info" | "inf" | "information" | "notice" | "30" => LogLevel::Info,
+            "warn" | "wrn" | "warning" | "40" => LogLevel::Warn,
+            "error" | "err" | "eror" | "50" => LogLevel::Error,
+            "fatal" | "ftl" | "crit" | "critical" | "panic" | "alert" | "emerg" | "emergency"
+            | "60" => LogLevel::Fatal,
+            _ => return None,
+        };
+        Some(level)
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LogLevel::parse(s).ok_or_else(|| {
+            format!(
+                "unknown log level '{}', expected trace, debug, info, warn, error or fatal",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// One parsed log line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogRecord {
+    pub level: Option<LogLevel>,
+    pub message: String,
+    /// Timestamp as the service wrote it
+    pub ts: Option<String>,
+    /// Everything else the line carried
+    pub fields: BTreeMap<String, String>,
+}
+
+impl LogRecord {
+    fn unparsed(line: &str) -> Self {
+        LogRecord {
+            message: line.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Move the level, message and timestamp out of `fields`.
+    fn from_fields(line: &str, mut fields: BTreeMap<String, String>) -> Self {
+        let mut take = |keys: &[&str]| keys.iter().find_map(|k| fields.remove(*k));
+        let level = take(LEVEL_KEYS);
+        let message = take(MESSAGE_KEYS);
+        let ts = take(TS_KEYS);
+        LogRecord {
+            level: level.as_deref().and_then(LogLevel::parse),
+            message: message.unwrap_or_else(|| line.to_string()),
+            ts,
+            fields,
+        }
+    }
+}
+
+enum Parser {
+    Json,
+    Logfmt,
+    Regex(Regex),
+}
+
+/// Parses lines of one [`LogFormat`]; the pattern of a `regex:` format is
+/// compiled once.
+pub struct LogParser(Parser);
+
+impl LogParser {
+    pub fn new(format: &LogFormat) -> Result<Self, String> {
+        let parser = match format {
+            LogFormat::Json => Parser::Json,
+            LogFormat::Logfmt => Parser::Logfmt,
+            LogFormat::Regex(pattern) => Parser::Regex(
+                Regex::new(pattern).map_err(|e| format!("invalid log format pattern: {}", e))?,
+            ),
+        };
+        Ok(LogParser(parser))
+    }
+
+    pub fn parse(&self, line: &str) -> LogRecord {
+        match &self.0 {
+            Parser::Json => parse_json(line),
+            Parser::Logfmt => parse_logfmt(line),
+            Parser::Regex(regex) => parse_regex(regex, line),
+        }
+    }
+}
+
+fn parse_json(line: &str) -> LogRecord {
+    let Ok(object) = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(line)
+    else {
+        return LogRecord::unparsed(line);
+    };
+    let fields = object
+        .into_iter()
+        .map(|(k, v)| match v {
+            serde_json::Value::String(s) => (k, s),
+            v => (k, v.to_string()),
+        })
+        .collect();
+    LogRecord::from_fields(line, fields)
+}
+
+fn parse_logfmt(line: &str) -> LogRecord {
+    let mut fields = BTreeMap::new();
+    let mut chars = line.chars().peekable();
+    let mut pairs = false;
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            key.push(c);
+        }
+        if chars.next_if_eq(&'=').is_none() {
+            if key.is_empty() {
+                break;
+            }
+            // a bare key is a flag
+            fields.insert(key, "true".to_string());
+            continue;
+        }
+        pairs = true;
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some(c) => value.push(c),
+                        None => {}
+                    },
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+        if !key.is_empty() {
+            fields.insert(key, value);
+        }
+    }
+    if !pairs {
+        return LogRecord::unparsed(line);
+    }
+    LogRecord::from_fields(line, fields)
+}
+
+fn parse_regex(regex: &Regex, line: &str) -> LogRecord {
+    let Some(captures) = regex.captures(line) else {
+        return LogRecord::unparsed(line);
+    };
+    let mut fields = BTreeMap::new();
+    let mut message = None;
+    let mut level = None;
+    let mut ts = None;
+    for name in regex.capture_names().flatten() {
+        let Some(value) = captures.name(name) else {
+            continue;
+        };
+        let value = value.as_str().to_string();
+        match name {
+            "level" => level = LogLevel::parse(&value),
+            "message" | "msg" => message = Some(value),
+            "ts" => ts = Some(value),
+            _ => {
+                fields.insert(name.to_string(), value);
+            }
+        }
+    }
+    LogRecord {
+        level,
+        message: message.unwrap_or_else(|| line.to_string()),
+        ts,
+        fields,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(format: &str, line: &str) -> LogRecord {
+        LogParser::new(&format.parse().unwrap())
+            .unwrap()
+            .parse(line)
+    }
+
+    #[test]
+    fn test_format_from_str() {
+        assert_eq!("json".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("logfmt".parse::<LogFormat>(), Ok(LogFormat::Logfmt));
+        assert_eq!(
+            "regex:^(?P<level>\\w+) ".parse::<LogFormat>(),
+            Ok(LogFormat::Regex("^(?P<level>\\w+) ".to_string()))
+        );
+        assert!("regex:(".parse::<LogFormat>().is_err());
+        assert!("xml".parse::<LogFormat>().is_err());
+        assert_eq!(LogFormat::Regex("a+".into()).to_string(), "regex:a+");
+    }
+
+    #[test]
+    fn test_level_parse() {
+        assert_eq!(LogLevel::parse("WARNING"), Some(LogLevel::Warn));
+        assert_eq!(LogLevel::parse("err"), Some(LogLevel::Error));
+        assert_eq!(LogLevel::parse("50"), Some(LogLevel::Error));
+        assert_eq!(LogLevel::parse("critical"), Some(LogLevel::Fatal));
+        assert_eq!(LogLevel::parse("loud"), None);
+        assert!(LogLevel::Warn > LogLevel::Info);
+        assert!(LogLevel::Fatal > LogLevel::Error);
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let record = parse(
+            "json",
+            r#"{"level":"error","msg":"disk full","time":"2024-05-01T10:00:00Z","free":0}"#,
+        );
+        assert_eq!(record.level, Some(LogLevel::Error));
+        assert_eq!(record.message, "disk full");
+        assert_eq!(record.ts.as_deref(), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(record.fields.get("free").map(String::as_str), Some("0"));
+
+        let record = parse("json", r#"{"level":30,"message":"ready"}"#);
+        assert_eq!(record.level, Some(LogLevel::Info));
+        assert_eq!(record.message, "ready");
+    }
+
+    #[test]
+    fn test_parse_json_not_an_object() {
+        let record = parse("json", "plain text");
+        assert_eq!(record, LogRecord::unparsed("plain text"));
+        let record = parse("json", "[1, 2]");
+        assert_eq!(record.message, "[1, 2]");
+    }
+
+    #[test]
+    fn test_parse_logfmt() {
+        let record = parse(
+            "logfmt",
+            r#"ts=2024-05-01T10:00:00Z level=warn msg="slow \"db\" query" took=2.1s cached"#,
+        );
+        assert_eq!(record.level, Some(LogLevel::Warn));
+        assert_eq!(record.message, r#"slow "db" query"#);
+        assert_eq!(record.ts.as_deref(), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(record.fields.get("took").map(String::as_str), Some("2.1s"));
+        assert_eq!(
+            record.fields.get("cached").map(String::as_str),
+            Some("true")
+        );
+    }
+
+    #[test]
+    fn test_parse_logfmt_without_pairs() {
+        let record = parse("logfmt", "Starting server");
+        assert_eq!(record, LogRecord::unparsed("Starting server"));
+    }
+
+    #[test]
+    fn test_parse_logfmt_without_message() {
+        let record = parse("logfmt", "level=info port=8080");
+        assert_eq!(record.level, Some(LogLevel::Info));
+        assert_eq!(record.message, "level=info port=8080");
+        assert_eq!(record.fields.get("port").map(String::as_str), Some("8080"));
+    }
+
+    #[test]
+    fn test_parse_regex() {
+        let format = r"regex:^(?P<ts>\S+) \[(?P<level>\w+)\] (?P<module>\w+): (?P<message>.*)$";
+        let record = parse(format, "10:00:01 [WARN] camera: frame dropped");
+        assert_eq!(record.level, Some(LogLevel::Warn));
+        assert_eq!(record.message, "frame dropped");
+        assert_eq!(record.ts.as_deref(), Some("10:00:01"));
+        assert_eq!(
+            record.fields.get("module").map(String::as_str),
+            Some("camera")
+        );
+
+        let record = parse(format, "no match here");
+        assert_eq!(record, LogRecord::unparsed("no match here"));
+    }
+
+    #[test]
+    fn test_format_serde() {
+        let format: LogFormat = serde_json::from_str(r#""logfmt""#).unwrap();
+        assert_eq!(format, LogFormat::Logfmt);
+        assert_eq!(serde_json::to_string(&format).unwrap(), r#""logfmt""#);
+        assert!(serde_json::from_str::<LogFormat>(r#""csv""#).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Metrics derived from the followed logs of a run, declared under
+//! `observe.logs.metrics`: counters of matching lines and gauges holding the
+//! last number a pattern captured. The runtime adds them to the metrics
+//! stream.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::log_format::{LogLevel, LogRecord};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogMetricKind {
+    /// Number of matching lines
+    #[default]
+    Counter,
+    /// Last number captured from a matching line
+    Gauge,
+}
+
+impl LogMetricKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LogMetricKind::Counter => "counter",
+            LogMetricKind::Gauge => "gauge",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogMetricSpec {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: LogMetricKind,
+    /// Regex matched against the message of a line. A gauge takes its value
+    /// from the group named `value`, or else the first group.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Only lines at this level or above; needs `logs.format`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+}
+
+/// Current value of a log metric, as sent on the metrics stream.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogMetricSample {
+    pub run_id: String,
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: LogMetricKind,
+    pub value: f64,
+}
+
+struct Metric {
+    spec: LogMetricSpec,
+    pattern: Option<Regex>,
+    value: Option<f64>,
+}
+
+/// The log metrics of one run and their values so far.
+pub struct LogMetrics {
+    metrics: Vec<Metric>,
+}
+
+impl LogMetrics {
+    pub fn new(specs: &[LogMetricSpec]) -> Result<Self, String> {
+        let mut metrics: Vec<Metric> = Vec::with_capacity(specs.len());
+        for spec in specs {
+            if spec.name.trim().is_empty() {
+                return Err("log metric without a name".to_string());
+            }
+            if metrics.iter().any(|m| m.spec.name == spec.name) {
+                return Err(format!("log metric '{}' is declared twice", spec.name));
+            }
+            let pattern = spec
+                .pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .map_err(|e| format!("log metric '{}': invalid pattern: {}", spec.name, e))?;
+            match (spec.kind, &pattern) {
+                (LogMetricKind::Counter, None) if spec.level.is_none() => {
+                    return Err(format!(
+                        "log metric '{}' needs a pattern or a level",
+                        spec.name
+                    ));
+                }
+                (LogMetricKind::Gauge, None) => {
+                    return Err(format!("log metric '{}' needs a pattern", spec.name));
+                }
+                (LogMetricKind::Gauge, Some(regex)) if regex.captures_len() < 2 => {
+                    return Err(format!(
+                        "log metric '{}': the pattern of a gauge needs a group to capture the value",
+                        spec.name
+                    ));
+                }
+                _ => {}
+            }
+            let value = match spec.kind {
+                LogMetricKind::Counter => Some(0.0),
+                LogMetricKind::Gauge => None,
+            };
+            metrics.push(Metric {
+                spec: spec.clone(),
+                pattern,
+                value,
+            });
+        }
+        Ok(LogMetrics { metrics })
+    }
+
+    pub fn specs(&self) -> impl Iterator<Item = &LogMetricSpec> {
+        self.metrics.iter().map(|m| &m.spec)
+    }
+
+    /// Count or capture `record` in every metric it matches. Lines without a
+    /// level count as info.
+    pub fn observe(&mut self, record: &LogRecord) {
+        let level = record.level.unwrap_or(LogLevel::Info);
+        for metric in &mut self.metrics {
+            if metric.spec.level.is_some_and(|min| level < min) {
+                continue;
+            }
+            match (metric.spec.kind, &metric.pattern) {
+                (LogMetricKind::Counter, None) => {
+                    *metric.value.get_or_insert(0.0) += 1.0;
+                }
+                (LogMetricKind::Counter, Some(pattern)) => {
+                    if pattern.is_match(&record.message) {
+                        *metric.value.get_or_insert(0.0) += 1.0;
+                    }
+                }
+                (LogMetricKind::Gauge, Some(pattern)) => {
+                    let Some(captures) = pattern.captures(&record.message) else {
+                        continue;
+                    };
+                    let value = captures
+                        .name("value")
+                        .or_else(|| captures.get(1))
+                        .and_then(|m| m.as_str().trim().parse::<f64>().ok());
+                    if let Some(value) = value.filter(|v| v.is_finite()) {
+                        metric.value = Some(value);
+                    }
+                }
+                (LogMetricKind::Gauge, None) => {}
+            }
+        }
+    }
+
+    /// Values of the metrics of `run_id`; gauges that captured nothing yet are
+    /// left out.
+    pub fn samples(&self, run_id: &str) -> Vec<LogMetricSample> {
+        self.metrics
+            .iter()
+            .filter_map(|m| {
+                Some(LogMetricSample {
+                    run_id: run_id.to_string(),
+                    name: m.spec.name.clone(),
+                    kind: m.spec.kind,
+                    value: m.value?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(name: &str, kind: LogMetricKind, pattern: Option<&str>) -> LogMetricSpec {
+        LogMetricSpec {
+            name: name.to_string(),
+            kind,
+            pattern: pattern.map(str::to_string),
+            level: None,
+        }
+    }
+
+    fn line(level: Option<LogLevel>, message: &str) -> LogRecord {
+        LogRecord {
+            level,
+            message: message.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn value(metrics: &LogMetrics, name: &str) -> Option<f64> {
+        metrics
+            .samples("run")
+            .into_iter()
+            .find(|s| s.name == name)
+            .map(|s| s.value)
+    }
+
+    #[test]
+    fn test_counter_pattern() {
+        let mut metrics =
+            LogMetrics::new(&[spec("errors", LogMetricKind::Counter, Some("ERROR"))]).unwrap();
+        assert_eq!(value(&metrics, "errors"), Some(0.0));
+        metrics.observe(&line(None, "ERROR disk full"));
+        metrics.observe(&line(None, "all good"));
+        metrics.observe(&line(None, "ERROR again"));
+        assert_eq!(value(&metrics, "errors"), Some(2.0));
+    }
+
+    #[test]
+    fn test_counter_level() {
+        let mut warnings = spec("warnings", LogMetricKind::Counter, None);
+        warnings.level = Some(LogLevel::Warn);
+        let mut metrics = LogMetrics::new(&[warnings]).unwrap();
+        metrics.observe(&line(Some(LogLevel::Warn), "slow"));
+        metrics.observe(&line(Some(LogLevel::Error), "failed"));
+        metrics.observe(&line(Some(LogLevel::Info), "ok"));
+        metrics.observe(&line(None, "unparsed"));
+        assert_eq!(value(&metrics, "warnings"), Some(2.0));
+    }
+
+    #[test]
+    fn test_gauge() {
+        let mut metrics = LogMetrics::new(&[
+            spec(
+                "latency_ms",
+                LogMetricKind::Gauge,
+                Some(r"took (?P<value>[0-9.]+)ms"),
+            ),
+            spec("fps", LogMetricKind::Gauge, Some(r"fps=(\d+)")),
+        ])
+        .unwrap();
+        assert_eq!(value(&metrics, "latency_ms"), None);
+        metrics.observe(&line(None, "request took 12.5ms"));
+        metrics.observe(&line(None, "request took 7ms fps=30"));
+        metrics.observe(&line(None, "request took ms"));
+        assert_eq!(value(&metrics, "latency_ms"), Some(7.0));
+        assert_eq!(value(&metrics, "fps"), Some(30.0));
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        assert!(LogMetrics::new(&[spec("", LogMetricKind::Counter, Some("x"))]).is_err());
+        assert!(LogMetrics::new(&[spec("a", LogMetricKind::Counter, None)]).is_err());
+        assert!(LogMetrics::new(&[spec("a", LogMetricKind::Gauge, Some("x"))]).is_err());
+        assert!(LogMetrics::new(&[spec("a", LogMetricKind::Gauge, Some("("))]).is_err());
+        assert!(
+            LogMetrics::new(&[
+                spec("a", LogMetricKind::Counter, Some("x")),
+                spec("a", LogMetricKind::Counter, Some("y")),
+            ])
+            .is_err()
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::log_metrics::LogMetricSample;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SystemMetrics {
     pub hostname: String,
@@ -13,6 +15,29 @@ pub struct SystemMetrics {
     pub network: NetworkMetrics,
     pub gpu: Vec<GpuMetrics>,
     pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    /// None on devices without a battery
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub battery: Option<BatteryMetrics>,
+    /// Streams open on the runtime; None from runtimes that do not report them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streams: Option<StreamMetrics>,
+    /// Metrics the runtime takes from followed service logs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub log_metrics: Vec<LogMetricSample>,
+    /// Runs that moved the most bytes since the previous sample, most first;
+    /// empty unless the runtime accounts network flows
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub top_talkers: Vec<RunTraffic>,
+}
+
+impl SystemMetrics {
+    /// Drop per-core and per-interface detail, keeping the aggregates.
+    pub fn reduce_detail(&mut self) {
+        self.cpu.per_core.clear();
+        self.network.interfaces.clear();
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -63,6 +88,20 @@ pub struct NetworkInterfaceMetrics {
     pub tx_bytes: u64,
 }
 
+/// Traffic of the containers and units of a run since the previous sample.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RunTraffic {
+    pub run_id: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+impl RunTraffic {
+    pub fn total_bytes(&self) -> u64 {
+        self.rx_bytes + self.tx_bytes
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GpuMetrics {
     pub name: String,
@@ -70,3 +109,89 @@ pub struct GpuMetrics {
     pub memory_used_mb: u64,
     pub memory_total_mb: u64,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChargingState {
+    Charging,
+    Discharging,
+    Full,
+    NotCharging,
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerSource {
+    Ac,
+    Battery,
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatteryMetrics {
+    pub percent: f32,
+    #[serde(default)]
+    pub state: ChargingState,
+    #[serde(default)]
+    pub power_source: PowerSource,
+    /// Estimated time until empty while discharging
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_empty_secs: Option<u64>,
+}
+
+/// Concurrent streams on the runtime and how many its limits refused.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct StreamMetrics {
+    pub active: u32,
+    pub interactive: u32,
+    pub transfers: u32,
+    /// Refused since the runtime started
+    pub rejected: u64,
+    /// Payload of zstd compressed streams since the runtime started, both
+    /// directions
+    #[serde(default)]
+    pub compressed_payload_bytes: u64,
+    /// Bytes that payload took on the wire
+    #[serde(default)]
+    pub compressed_wire_bytes: u64,
+}
+
+/// A position fix reported by the device, e.g. from gpsd.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude_m: Option<f64>,
+    /// Estimated horizontal error
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accuracy_m: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_mps: Option<f64>,
+    /// Course over ground in degrees from true north
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading_deg: Option<f64>,
+    /// Unix millis when the fix was taken
+    pub timestamp: u64,
+}
+
+impl Location {
+    pub fn is_valid(&self) -> bool {
+        self.latitude.is_finite()
+            && self.longitude.is_finite()
+            && (-90.0..=90.0).contains(&self.latitude)
+            && (-180.0..=180.0).contains(&self.longitude)
+    }
+
+    /// GeoJSON point geometry. Note GeoJSON orders coordinates longitude first.
+    pub fn to_geojson_geometry(&self) -> serde_json::Value {
+        let mut coordinates = vec![self.longitude, self.latitude];
+        if let Some(alt) = self.altitude_m {
+            coordinates.push(alt);
+        }
+        serde_json::json!({ "type": "Point", "coordinates": coordinates })
+    }
+}
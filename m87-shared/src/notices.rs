@@ -0,0 +1,80 @@
+//! Notices a server pushes to CLIs, e.g. that an API is deprecated and when
+//! it stops working. The CLI shows each notice once per CLI version.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NoticeKind {
+    #[default]
+    Info,
+    Deprecation,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CliNotice {
+    /// Stable id; changing it shows the notice again
+    pub id: String,
+    #[serde(default)]
+    pub kind: NoticeKind,
+    pub message: String,
+    /// When the deprecated behavior goes away, e.g. `2027-03-01`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sunset: Option<String>,
+    /// First CLI version the notice no longer concerns
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fixed_in: Option<String>,
+}
+
+impl CliNotice {
+    pub fn applies_to(&self, cli_version: &str) -> bool {
+        match &self.fixed_in {
+            Some(fixed_in) => version_less(cli_version, fixed_in),
+            None => true,
+        }
+    }
+}
+
+/// Whether version `a` is below `b`. A leading `v` and pre-release or build
+/// suffixes are ignored, missing parts count as 0.
+pub fn version_less(a: &str, b: &str) -> bool {
+    let (mut a, mut b) = (version_parts(a), version_parts(b));
+    let len = a.len().max(b.len());
+    a.resize(len, 0);
+    b.resize(len, 0);
+    a < b
+}
+
+fn version_parts(version: &str) -> Vec<u64> {
+    let core = version.trim().trim_start_matches('v');
+    let core = core.split(['-', '+']).next().unwrap_or_default();
+    core.split('.').map(|p| p.parse().unwrap_or(0)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_compare_numerically() {
+        assert!(version_less("0.9.9", "0.9.10"));
+        assert!(version_less("v1.2", "1.2.1"));
+        assert!(!version_less("1.2.0", "1.2"));
+        assert!(!version_less("1.3.0-rc.1", "1.2.9"));
+    }
+
+    #[test]
+    fn notices_stop_at_their_fixed_version() {
+        let mut notice = CliNotice {
+            id: "api-v1-sunset".to_string(),
+            kind: NoticeKind::Deprecation,
+            message: "API v1 goes away".to_string(),
+            sunset: None,
+            fixed_in: None,
+        };
+        assert!(notice.applies_to("0.1.0"));
+        notice.fixed_in = Some("0.9.0".to_string());
+        assert!(notice.applies_to("0.8.3"));
+        assert!(!notice.applies_to("0.9.0"));
+    }
+}
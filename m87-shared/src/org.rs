@@ -43,3 +43,74 @@ pub struct InviteMemberBody {
 pub struct AddDeviceBody {
     pub device_id: String,
 }
+
+/// Quotas of an organization; `None` is unlimited
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct OrgLimits {
+    #[serde(default)]
+    pub max_devices: Option<u64>,
+    /// Deployment revisions kept across the org's devices
+    #[serde(default)]
+    pub max_revisions: Option<u64>,
+    /// Deploy reports stored for the org's devices, in bytes
+    #[serde(default)]
+    pub max_report_storage_bytes: Option<u64>,
+    /// Client sessions relayed to the org's devices at once
+    #[serde(default)]
+    pub max_concurrent_tunnels: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct OrgUsage {
+    pub devices: u64,
+    pub revisions: u64,
+    pub report_storage_bytes: u64,
+    pub concurrent_tunnels: u64,
+}
+
+/// Limits of an organization with what it currently uses (`GET /limits`)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OrgLimitsStatus {
+    pub org_id: String,
+    pub limits: OrgLimits,
+    pub usage: OrgUsage,
+}
+
+/// Per-org override of the instance defaults, set by instance admins. A field
+/// left out keeps the default, `0` lifts the limit.
+pub type UpdateOrgLimitsBody = OrgLimits;
+
+/// Custom redaction patterns of an organization, applied by the runtimes of
+/// its devices to log tails, observe output and streamed logs
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OrgRedaction {
+    pub org_id: String,
+    /// Regular expressions; a match, or its first capture group, is masked
+    pub patterns: Vec<String>,
+}
+
+/// Replaces all patterns of the organization; empty removes them
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UpdateOrgRedactionBody {
+    pub patterns: Vec<String>,
+}
+
+/// Where the server posts incident state changes of an organization's devices
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct OrgIncidentWebhook {
+    pub org_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Whether requests are signed; the secret itself is not returned
+    #[serde(default)]
+    pub signed: bool,
+}
+
+/// Replaces the webhook of the organization; no `url` removes it
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UpdateOrgIncidentWebhookBody {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
@@ -0,0 +1,249 @@
+//! OS patch policies: package upgrades a runtime applies on its own during a
+//! maintenance window, defined per organization on the server.
+//!
+//! The server sends the policies that apply to a device with its config
+//! (none while the device is in maintenance mode). The runtime checks them
+//! every minute and sends a [`PatchRunReport`] for each run with its next
+//! heartbeat.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::inventory::PackageOperationReport;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+const DAY_NAMES: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct PatchPolicy {
+    pub id: String,
+    pub name: String,
+    /// Packages to upgrade; every upgradable package when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// Maintenance window in device local time, e.g. `sat,sun 02:00-05:00`
+    pub window: String,
+    /// Reboot after patching when the OS reports that one is required
+    #[serde(default)]
+    pub reboot: bool,
+    /// Only apply on devices whose facts match this condition
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CreatePatchPolicyBody {
+    pub name: String,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    pub window: String,
+    #[serde(default)]
+    pub reboot: bool,
+    #[serde(default)]
+    pub when: Option<String>,
+}
+
+/// A policy as listed by the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PublicPatchPolicy {
+    #[serde(flatten)]
+    pub policy: PatchPolicy,
+    /// Scope the policy applies to, e.g. `org:acme`
+    pub scope: String,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+/// Outcome of one policy run on a device.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PatchRunReport {
+    pub policy_id: String,
+    pub policy_name: String,
+    pub operation: PackageOperationReport,
+    /// The OS asked for a reboot after the upgrade
+    #[serde(default)]
+    pub reboot_required: bool,
+    /// The runtime scheduled that reboot
+    #[serde(default)]
+    pub rebooting: bool,
+}
+
+/// A patch run as listed by the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PatchRun {
+    pub device_id: String,
+    pub received_at: String,
+    pub report: PatchRunReport,
+}
+
+/// Weekly recurring window such as `mon-fri 22:00-02:00` or `daily 03:00-04:00`.
+/// A window running past midnight belongs to the day it starts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    /// Bit 0 is Monday, bit 6 Sunday
+    days: u8,
+    /// Minutes after midnight
+    start: u32,
+    /// Length in minutes
+    length: u32,
+}
+
+impl MaintenanceWindow {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (days, times) = s.trim().split_once(char::is_whitespace).ok_or_else(|| {
+            format!(
+                "invalid window '{}', expected e.g. 'sat,sun 02:00-05:00'",
+                s
+            )
+        })?;
+        let (start, end) = times
+            .trim()
+            .split_once('-')
+            .ok_or_else(|| format!("invalid time range '{}', expected HH:MM-HH:MM", times))?;
+        let start = parse_time(start)?;
+        let end = parse_time(end)?;
+        if start == MINUTES_PER_DAY {
+            return Err("a window cannot start at 24:00".to_string());
+        }
+        let length = (end + MINUTES_PER_DAY - start) % MINUTES_PER_DAY;
+        let length = if length == 0 { MINUTES_PER_DAY } else { length };
+        Ok(Self {
+            days: parse_days(days)?,
+            start,
+            length,
+        })
+    }
+
+    pub fn length_minutes(&self) -> u32 {
+        self.length
+    }
+
+    /// Whether `minute` after midnight on `weekday` (0 is Monday) lies in the
+    /// window.
+    pub fn contains(&self, weekday: u32, minute: u32) -> bool {
+        let week = 7 * MINUTES_PER_DAY;
+        let now = weekday * MINUTES_PER_DAY + minute;
+        (0..7)
+            .filter(|day| self.days & (1 << day) != 0)
+            .any(|day| (now + week - (day * MINUTES_PER_DAY + self.start)) % week < self.length)
+    }
+}
+
+impl fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = if self.days == 0x7f {
+            "daily".to_string()
+        } else {
+            (0..7)
+                .filter(|day| self.days & (1 << day) != 0)
+                .map(|day| DAYS[day])
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let end = (self.start + self.length) % MINUTES_PER_DAY;
+        write!(
+            f,
+            "{} {:02}:{:02}-{:02}:{:02}",
+            days,
+            self.start / 60,
+            self.start % 60,
+            end / 60,
+            end % 60
+        )
+    }
+}
+
+fn parse_time(s: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time '{}', expected HH:MM", s);
+    let (h, m) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let h: u32 = h.parse().map_err(|_| invalid())?;
+    let m: u32 = m.parse().map_err(|_| invalid())?;
+    if m >= 60 || h > 24 || (h == 24 && m > 0) {
+        return Err(invalid());
+    }
+    Ok(h * 60 + m)
+}
+
+/// `daily`, or days and day ranges separated by commas, e.g. `mon-fri,sun`.
+fn parse_days(s: &str) -> Result<u8, String> {
+    let s = s.to_ascii_lowercase();
+    if s == "daily" || s == "*" {
+        return Ok(0x7f);
+    }
+    let day = |name: &str| {
+        let name = name.trim();
+        (0..7)
+            .find(|&d| DAYS[d] == name || DAY_NAMES[d] == name)
+            .ok_or_else(|| format!("invalid day '{}'", name))
+    };
+    let mut days = 0u8;
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (day(from)?, day(to)?);
+                // ranges may wrap around the week, e.g. fri-mon
+                let mut d = from;
+                loop {
+                    days |= 1 << d;
+                    if d == to {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => days |= 1 << day(part)?,
+        }
+    }
+    Ok(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance_window() {
+        let w = MaintenanceWindow::parse("sat,sun 02:00-05:00").unwrap();
+        assert!(w.contains(5, 2 * 60));
+        assert!(w.contains(6, 4 * 60 + 59));
+        assert!(!w.contains(6, 5 * 60));
+        assert!(!w.contains(0, 3 * 60));
+        assert_eq!(w.to_string(), "sat,sun 02:00-05:00");
+
+        // past midnight: the friday window ends saturday morning
+        let w = MaintenanceWindow::parse("mon-fri 22:00-02:00").unwrap();
+        assert_eq!(w.length_minutes(), 240);
+        assert!(w.contains(5, 60));
+        assert!(!w.contains(6, 60));
+        assert!(w.contains(0, 23 * 60));
+
+        let w = MaintenanceWindow::parse("fri-mon 00:00-24:00").unwrap();
+        assert!(w.contains(6, 720));
+        assert!(!w.contains(2, 720));
+        assert_eq!(
+            MaintenanceWindow::parse("daily 03:00-04:00")
+                .unwrap()
+                .to_string(),
+            "daily 03:00-04:00"
+        );
+
+        assert!(
+            MaintenanceWindow::parse("Saturday 01:00-02:00")
+                .unwrap()
+                .contains(5, 90)
+        );
+        assert!(MaintenanceWindow::parse("someday 02:00-03:00").is_err());
+        assert!(MaintenanceWindow::parse("sat 25:00-03:00").is_err());
+        assert!(MaintenanceWindow::parse("sat").is_err());
+    }
+}
@@ -0,0 +1,471 @@
+//! Probes: reachability checks a runtime runs from the device toward
+//! endpoints it depends on, e.g. its MQTT broker, a PLC or a cloud API.
+//!
+//! Probes are set per device on the server and sent with the device's config.
+//! The runtime runs each on its interval and sends the results with its next
+//! heartbeat. A probe that fails [`ProbeSpec::fail_after`] times in a row
+//! raises a `probe_failed` alert once, until it succeeds again.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Most probes per device
+pub const MAX_PROBES: usize = 20;
+pub const MIN_INTERVAL_SECS: u32 = 10;
+const MAX_INTERVAL_SECS: u32 = 24 * 60 * 60;
+const MAX_TIMEOUT_SECS: u32 = 60;
+
+fn default_interval_secs() -> u32 {
+    60
+}
+
+fn default_timeout_secs() -> u32 {
+    5
+}
+
+fn default_fail_after() -> u32 {
+    3
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProbeTarget {
+    /// One ICMP echo through the system's `ping`
+    Ping { host: String },
+    /// A TCP connect, e.g. to an MQTT broker or a Modbus PLC
+    Tcp { host: String, port: u16 },
+    /// A GET request; succeeds on `expect_status`, or any 2xx without one
+    Http {
+        url: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expect_status: Option<u16>,
+    },
+}
+
+impl ProbeTarget {
+    /// TCP target from `host:port`; IPv6 hosts go in brackets.
+    pub fn tcp(address: &str) -> Result<Self, String> {
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| format!("'{}' is not host:port", address))?;
+        let port = port
+            .parse::<u16>()
+            .map_err(|_| format!("invalid port in '{}'", address))?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        Ok(ProbeTarget::Tcp {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// Target from `ping:HOST`, `tcp:HOST:PORT` or an http(s) URL.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Ok(ProbeTarget::Http {
+                url: s.to_string(),
+                expect_status: None,
+            });
+        }
+        match s.split_once(':') {
+            Some(("ping", host)) => Ok(ProbeTarget::Ping {
+                host: host.to_string(),
+            }),
+            Some(("tcp", address)) => Self::tcp(address),
+            _ => Err(format!(
+                "'{}' is not ping:HOST, tcp:HOST:PORT or an http(s) URL",
+                s
+            )),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProbeTarget::Ping { .. } => "ping",
+            ProbeTarget::Tcp { .. } => "tcp",
+            ProbeTarget::Http { .. } => "http",
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let valid_host = |host: &str| !host.is_empty() && !host.contains(char::is_whitespace);
+        match self {
+            ProbeTarget::Ping { host } if !valid_host(host) => {
+                Err(format!("invalid host '{}'", host))
+            }
+            ProbeTarget::Tcp { host, .. } if !valid_host(host) => {
+                Err(format!("invalid host '{}'", host))
+            }
+            ProbeTarget::Tcp { port: 0, .. } => Err("port 0 cannot be probed".to_string()),
+            ProbeTarget::Http { url, .. }
+                if !url.starts_with("http://") && !url.starts_with("https://") =>
+            {
+                Err(format!("'{}' does not start with http:// or https://", url))
+            }
+            ProbeTarget::Http {
+                expect_status: Some(status),
+                ..
+            } if !(100..=599).contains(status) => Err(format!("{} is not an HTTP status", status)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ProbeTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeTarget::Ping { host } => write!(f, "ping {}", host),
+            ProbeTarget::Tcp { host, port } if host.contains(':') => {
+                write!(f, "tcp [{}]:{}", host, port)
+            }
+            ProbeTarget::Tcp { host, port } => write!(f, "tcp {}:{}", host, port),
+            ProbeTarget::Http {
+                url,
+                expect_status: Some(status),
+            } => write!(f, "http {} ({})", url, status),
+            ProbeTarget::Http { url, .. } => write!(f, "http {}", url),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct ProbeSpec {
+    /// Letters, digits, `-` and `_`; unique per device
+    pub name: String,
+    #[serde(flatten)]
+    pub target: ProbeTarget,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u32,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u32,
+    /// Failures in a row before the probe counts as down
+    #[serde(default = "default_fail_after")]
+    pub fail_after: u32,
+}
+
+impl ProbeSpec {
+    pub fn new(name: &str, target: ProbeTarget) -> Self {
+        ProbeSpec {
+            name: name.to_string(),
+            target,
+            interval_secs: default_interval_secs(),
+            timeout_secs: default_timeout_secs(),
+            fail_after: default_fail_after(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let valid_name = !self.name.is_empty()
+            && self.name.len() <= 64
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            return Err(format!(
+                "invalid probe name '{}': use up to 64 letters, digits, - and _",
+                self.name
+            ));
+        }
+        self.target
+            .validate()
+            .map_err(|e| format!("probe '{}': {}", self.name, e))?;
+        if !(MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&self.interval_secs) {
+            return Err(format!(
+                "probe '{}': the interval must be between {}s and {}s",
+                self.name, MIN_INTERVAL_SECS, MAX_INTERVAL_SECS
+            ));
+        }
+        if self.timeout_secs == 0
+            || self.timeout_secs > MAX_TIMEOUT_SECS
+            || self.timeout_secs >= self.interval_secs
+        {
+            return Err(format!(
+                "probe '{}': the timeout must be between 1s and {}s and shorter than the interval",
+                self.name, MAX_TIMEOUT_SECS
+            ));
+        }
+        if self.fail_after == 0 {
+            return Err(format!(
+                "probe '{}': fail_after must be at least 1",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the probes of one device: each valid, names unique, at most
+/// [`MAX_PROBES`].
+pub fn validate_probes(probes: &[ProbeSpec]) -> Result<(), String> {
+    if probes.len() > MAX_PROBES {
+        return Err(format!("at most {} probes per device", MAX_PROBES));
+    }
+    for (i, probe) in probes.iter().enumerate() {
+        probe.validate()?;
+        if probes[..i].iter().any(|p| p.name == probe.name) {
+            return Err(format!("probe '{}' is declared twice", probe.name));
+        }
+    }
+    Ok(())
+}
+
+/// One run of a probe on the device.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ProbeResult {
+    pub name: String,
+    pub ok: bool,
+    /// Unix millis, device clock
+    pub checked_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u64>,
+    /// HTTP status of an http probe
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Failures in a row up to and including this run; 0 when it succeeded
+    #[serde(default)]
+    pub consecutive_failures: u32,
+}
+
+/// A probe going down or coming back, found in the results of a heartbeat.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeTransition {
+    pub name: String,
+    pub down: bool,
+    pub message: String,
+}
+
+/// Probes that went down (reached `fail_after` failures in a row) or came
+/// back up in `results`, compared with the `previous` last result of each
+/// probe. Results of probes no longer in `probes` are ignored.
+pub fn probe_transitions(
+    probes: &[ProbeSpec],
+    previous: &[ProbeResult],
+    results: &[ProbeResult],
+) -> Vec<ProbeTransition> {
+    let mut transitions = Vec::new();
+    for probe in probes {
+        let mut down = previous
+            .iter()
+            .find(|r| r.name == probe.name)
+            .is_some_and(|r| r.consecutive_failures >= probe.fail_after);
+        for result in results.iter().filter(|r| r.name == probe.name) {
+            if !down && result.consecutive_failures >= probe.fail_after {
+                down = true;
+                transitions.push(ProbeTransition {
+                    name: probe.name.clone(),
+                    down,
+                    message: format!(
+                        "Probe '{}' ({}) failed {} times in a row: {}",
+                        probe.name,
+                        probe.target,
+                        result.consecutive_failures,
+                        result.error.as_deref().unwrap_or("unknown error")
+                    ),
+                });
+            } else if down && result.ok {
+                down = false;
+                transitions.push(ProbeTransition {
+                    name: probe.name.clone(),
+                    down,
+                    message: format!(
+                        "Probe '{}' ({}) is reachable again",
+                        probe.name, probe.target
+                    ),
+                });
+            }
+        }
+    }
+    transitions
+}
+
+/// The last result of each probe in `probes`, from `previous` updated with
+/// `results`.
+pub fn latest_results(
+    probes: &[ProbeSpec],
+    previous: &[ProbeResult],
+    results: &[ProbeResult],
+) -> Vec<ProbeResult> {
+    probes
+        .iter()
+        .filter_map(|probe| {
+            results
+                .iter()
+                .chain(previous.iter())
+                .filter(|r| r.name == probe.name)
+                .max_by_key(|r| r.checked_at)
+                .cloned()
+        })
+        .collect()
+}
+
+/// Replaces the probes of a device
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SetProbesBody {
+    pub probes: Vec<ProbeSpec>,
+}
+
+/// A probe of a device with its last result and the last day's checks.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProbeStatus {
+    #[serde(flatten)]
+    pub probe: ProbeSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last: Option<ProbeResult>,
+    #[serde(default)]
+    pub checks_24h: u64,
+    #[serde(default)]
+    pub ok_24h: u64,
+}
+
+impl ProbeStatus {
+    /// Share of successful checks over the last day, in percent
+    pub fn uptime_percent(&self) -> Option<f64> {
+        (self.checks_24h > 0).then(|| self.ok_24h as f64 * 100.0 / self.checks_24h as f64)
+    }
+}
+
+/// A probe result as listed by the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProbeRecord {
+    pub device_id: String,
+    pub received_at: String,
+    pub result: ProbeResult,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, ok: bool, checked_at: u64, consecutive_failures: u32) -> ProbeResult {
+        ProbeResult {
+            name: name.to_string(),
+            ok,
+            checked_at,
+            latency_ms: None,
+            status: None,
+            error: (!ok).then(|| "connection refused".to_string()),
+            consecutive_failures,
+        }
+    }
+
+    #[test]
+    fn test_tcp_target() {
+        assert_eq!(
+            ProbeTarget::tcp("broker.local:1883").unwrap(),
+            ProbeTarget::Tcp {
+                host: "broker.local".to_string(),
+                port: 1883
+            }
+        );
+        let v6 = ProbeTarget::tcp("[fd00::1]:502").unwrap();
+        assert_eq!(v6.to_string(), "tcp [fd00::1]:502");
+        assert!(ProbeTarget::tcp("broker.local").is_err());
+        assert!(ProbeTarget::tcp("broker.local:mqtt").is_err());
+    }
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            ProbeTarget::parse("ping:10.0.0.1").unwrap(),
+            ProbeTarget::Ping {
+                host: "10.0.0.1".to_string()
+            }
+        );
+        assert_eq!(
+            ProbeTarget::parse("tcp:broker.local:1883").unwrap().kind(),
+            "tcp"
+        );
+        assert_eq!(
+            ProbeTarget::parse("https://api.example.com/health")
+                .unwrap()
+                .kind(),
+            "http"
+        );
+        assert!(ProbeTarget::parse("broker.local:1883").is_err());
+        assert!(ProbeTarget::parse("tcp:broker.local").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let ok = ProbeSpec::new(
+            "broker",
+            ProbeTarget::Http {
+                url: "https://api.example.com/health".to_string(),
+                expect_status: None,
+            },
+        );
+        assert!(ok.validate().is_ok());
+
+        let mut bad = ok.clone();
+        bad.name = "my broker".to_string();
+        assert!(bad.validate().is_err());
+
+        let mut bad = ok.clone();
+        bad.target = ProbeTarget::Http {
+            url: "ftp://example.com".to_string(),
+            expect_status: None,
+        };
+        assert!(bad.validate().is_err());
+
+        let mut bad = ok.clone();
+        bad.interval_secs = 5;
+        assert!(bad.validate().is_err());
+
+        let mut bad = ok.clone();
+        bad.timeout_secs = bad.interval_secs;
+        assert!(bad.validate().is_err());
+
+        assert!(validate_probes(&[ok.clone(), ok]).is_err());
+    }
+
+    #[test]
+    fn test_transitions() {
+        let probes = [ProbeSpec::new(
+            "plc",
+            ProbeTarget::tcp("10.0.0.5:502").unwrap(),
+        )];
+
+        // 1 and 2 failures stay quiet, the 3rd raises once
+        let results = [
+            result("plc", false, 1, 1),
+            result("plc", false, 2, 2),
+            result("plc", false, 3, 3),
+            result("plc", false, 4, 4),
+        ];
+        let transitions = probe_transitions(&probes, &[], &results);
+        assert_eq!(transitions.len(), 1);
+        assert!(transitions[0].down);
+
+        // still down from an earlier heartbeat, then back
+        let previous = [result("plc", false, 4, 4)];
+        let results = [result("plc", false, 5, 5), result("plc", true, 6, 0)];
+        let transitions = probe_transitions(&probes, &previous, &results);
+        assert_eq!(transitions.len(), 1);
+        assert!(!transitions[0].down);
+
+        // results of removed probes are ignored
+        assert!(probe_transitions(&probes, &[], &[result("gone", false, 1, 9)]).is_empty());
+    }
+
+    #[test]
+    fn test_latest_results() {
+        let probes = [
+            ProbeSpec::new("plc", ProbeTarget::tcp("10.0.0.5:502").unwrap()),
+            ProbeSpec::new(
+                "gw",
+                ProbeTarget::Ping {
+                    host: "10.0.0.1".to_string(),
+                },
+            ),
+        ];
+        let previous = [result("plc", true, 1, 0), result("old", true, 1, 0)];
+        let results = [result("plc", false, 3, 1), result("plc", false, 2, 1)];
+        let latest = latest_results(&probes, &previous, &results);
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].checked_at, 3);
+    }
+}
@@ -0,0 +1,84 @@
+//! Protocol version exchanged between runtime, CLI and server.
+//!
+//! The version is bumped whenever a payload gains something an older peer
+//! would misread or drop. Each side announces its version (REST header, first
+//! heartbeat) and either refuses peers below [`MIN_PROTOCOL_VERSION`] with an
+//! upgrade hint or downgrades what it sends to what the peer understands.
+
+use std::fmt::Display;
+
+use crate::deploy_spec::DeploymentRevision;
+
+/// Protocol spoken by this build.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest peer protocol this build still talks to.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// REST header carrying the sender's protocol version. Peers that do not send
+/// it predate the negotiation and are treated as version 1.
+pub const PROTOCOL_HEADER: &str = "x-m87-protocol";
+
+/// What each protocol version added, oldest first.
+pub const PROTOCOL_HISTORY: &[(u32, &str)] = &[
+    (1, "devices, deployments, heartbeats and tunnels"),
+    (
+        2,
+        "deployment annotations and provenance, failed-upgrade reports",
+    ),
+];
+
+/// Parse a protocol header value. Missing or malformed values count as 1.
+pub fn parse_protocol(value: Option<&str>) -> u32 {
+    value
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compatibility {
+    Compatible,
+    /// The peer is older than [`MIN_PROTOCOL_VERSION`] and has to upgrade.
+    PeerTooOld,
+    /// The peer is newer; it is expected to downgrade what it sends.
+    PeerNewer,
+}
+
+pub fn check_compatibility(peer: u32) -> Compatibility {
+    if peer < MIN_PROTOCOL_VERSION {
+        Compatibility::PeerTooOld
+    } else if peer > PROTOCOL_VERSION {
+        Compatibility::PeerNewer
+    } else {
+        Compatibility::Compatible
+    }
+}
+
+/// Features a peer speaking `peer` is missing compared to this build.
+pub fn missing_features(peer: u32) -> Vec<&'static str> {
+    PROTOCOL_HISTORY
+        .iter()
+        .filter(|(v, _)| *v > peer)
+        .map(|(_, f)| *f)
+        .collect()
+}
+
+/// Error text for a peer that is too old, naming what it has to upgrade.
+pub fn upgrade_message(component: impl Display, peer: u32) -> String {
+    format!(
+        "{} speaks protocol v{} but at least v{} is required. Please upgrade it (m87 update).",
+        component, peer, MIN_PROTOCOL_VERSION
+    )
+}
+
+impl DeploymentRevision {
+    /// Drop fields a peer speaking `protocol` does not know about, so it is not
+    /// handed data it would silently lose.
+    pub fn downgrade_for(&mut self, protocol: u32) {
+        if protocol < 2 {
+            self.annotations.clear();
+            self.provenance = None;
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// Load of a relay, as reported to admins by `GET /admin/relay`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RelayStats {
+    /// Devices with an open control tunnel
+    pub tunnels: usize,
+    /// Resident memory of the server process; None where it cannot be read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rss_bytes: Option<u64>,
+}
+
+/// Resident memory of the current process (Linux only).
+pub fn resident_memory_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
@@ -0,0 +1,189 @@
+//! Revision updates sent to the runtime as the runs that changed.
+//!
+//! The runtime announces the hash of the revision it holds with each
+//! heartbeat. When the server knows which runs that revision has, it sends a
+//! [`RevisionDelta`] instead of the full target revision: every run by hash,
+//! in order, and the contents of only those the runtime does not hold. The
+//! runtime rebuilds the revision from the one it holds and checks the result
+//! against [`RevisionDelta::revision_hash`]; on any mismatch it asks for the
+//! full revision with the next heartbeat.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::deploy_spec::{DeploymentRevision, Provenance, RollbackPolicy, RunSpec};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevisionDelta {
+    /// Hash of the revision the delta was computed against
+    pub base_hash: String,
+    /// Hash of the revision after applying the delta
+    pub revision_hash: String,
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Hashes of all runs of the revision, in order
+    pub runs: Vec<String>,
+    /// Runs the base does not have, keyed by hash
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub changed: BTreeMap<String, RunSpec>,
+    /// Hashes of base runs the revision no longer has
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollback: Option<RollbackPolicy>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+}
+
+impl RevisionDelta {
+    /// Delta turning the revision hashed `base_hash`, whose runs hash to
+    /// `base_runs`, into `target`.
+    pub fn between(base_hash: &str, base_runs: &[String], target: &DeploymentRevision) -> Self {
+        let held: BTreeSet<&String> = base_runs.iter().collect();
+        let mut runs = Vec::with_capacity(target.jobs.len());
+        let mut changed = BTreeMap::new();
+        for run in &target.jobs {
+            let hash = run.get_hash();
+            if !held.contains(&hash) {
+                changed.insert(hash.clone(), run.clone());
+            }
+            runs.push(hash);
+        }
+        let kept: BTreeSet<&String> = runs.iter().collect();
+        let removed = base_runs
+            .iter()
+            .filter(|h| !kept.contains(h))
+            .cloned()
+            .collect();
+
+        Self {
+            base_hash: base_hash.to_string(),
+            revision_hash: target.get_hash(),
+            id: target.id.clone(),
+            runs,
+            changed,
+            removed,
+            rollback: target.rollback.clone(),
+            annotations: target.annotations.clone(),
+            provenance: target.provenance.clone(),
+        }
+    }
+
+    /// Whether the delta leaves out at least one run, i.e. is worth sending
+    /// instead of the full revision.
+    pub fn saves_runs(&self) -> bool {
+        self.changed.len() < self.runs.len()
+    }
+
+    /// Rebuild the revision from `base`, which must be the revision the delta
+    /// was computed against.
+    pub fn apply(&self, base: &DeploymentRevision) -> Result<DeploymentRevision, String> {
+        if base.get_hash() != self.base_hash {
+            return Err("the held revision is not the one the delta is based on".to_string());
+        }
+        let held: BTreeMap<String, &RunSpec> =
+            base.jobs.iter().map(|run| (run.get_hash(), run)).collect();
+
+        let mut jobs = Vec::with_capacity(self.runs.len());
+        for hash in &self.runs {
+            let run = self
+                .changed
+                .get(hash)
+                .or_else(|| held.get(hash).copied())
+                .ok_or_else(|| format!("run {} is neither held nor sent", hash))?;
+            jobs.push(run.clone());
+        }
+
+        let revision = DeploymentRevision {
+            id: self.id.clone(),
+            jobs,
+            rollback: self.rollback.clone(),
+            rollout: None,
+            annotations: self.annotations.clone(),
+            provenance: self.provenance.clone(),
+        };
+        if revision.get_hash() != self.revision_hash {
+            return Err("the rebuilt revision does not match the sent hash".to_string());
+        }
+        Ok(revision)
+    }
+}
+
+/// Hashes of the runs of `revision`, in order.
+pub fn run_hashes(revision: &DeploymentRevision) -> Vec<String> {
+    revision.jobs.iter().map(|run| run.get_hash()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deploy_spec::RunType;
+
+    fn run(id: &str, command: &str) -> RunSpec {
+        RunSpec {
+            id: id.to_string(),
+            run_type: RunType::Job,
+            enabled: true,
+            files: BTreeMap::from([("run.sh".to_string(), command.to_string())]),
+            ..Default::default()
+        }
+    }
+
+    fn revision(jobs: Vec<RunSpec>) -> DeploymentRevision {
+        DeploymentRevision {
+            id: Some("rev".to_string()),
+            jobs,
+            rollback: None,
+            rollout: None,
+            annotations: BTreeMap::new(),
+            provenance: None,
+        }
+    }
+
+    #[test]
+    fn test_delta_roundtrip() {
+        let base = revision(vec![
+            run("a", "echo a"),
+            run("b", "echo b"),
+            run("c", "echo c"),
+        ]);
+        let mut target = revision(vec![
+            run("c", "echo c"),
+            run("a", "echo a2"),
+            run("d", "echo d"),
+        ]);
+        target.id = Some("rev2".to_string());
+        target
+            .annotations
+            .insert("ticket".to_string(), "OPS-1".to_string());
+
+        let delta = RevisionDelta::between(&base.get_hash(), &run_hashes(&base), &target);
+        assert_eq!(delta.changed.len(), 2);
+        assert_eq!(delta.removed.len(), 2);
+        assert!(delta.saves_runs());
+        let applied = delta.apply(&base).unwrap();
+        assert_eq!(applied.get_hash(), target.get_hash());
+        assert_eq!(applied.id, target.id);
+        assert_eq!(applied.annotations, target.annotations);
+    }
+
+    #[test]
+    fn test_delta_rejects_other_base() {
+        let base = revision(vec![run("a", "echo a"), run("b", "echo b")]);
+        let target = revision(vec![run("a", "echo a"), run("b", "echo b2")]);
+        let delta = RevisionDelta::between(&base.get_hash(), &run_hashes(&base), &target);
+
+        let other = revision(vec![run("a", "echo a")]);
+        assert!(delta.apply(&other).is_err());
+
+        let mut forged = delta.clone();
+        forged.changed.clear();
+        assert!(forged.apply(&base).is_err());
+
+        let fresh = RevisionDelta::between("", &[], &target);
+        assert!(!fresh.saves_runs());
+    }
+}
@@ -51,3 +51,175 @@ impl Role {
         }
     }
 }
+
+/// Header field in which the relay records the caller's role on the device,
+/// overwriting whatever the client sent.
+pub const GRANTED_ROLE_KEY: &str = "granted_role";
+
+/// Minimum role for each stream type (the `type` tag of a stream header).
+/// Checked by the relay and again by the runtime before dispatching.
+pub const STREAM_ROLES: &[(&str, Role)] = &[
+    ("Logs", Role::Viewer),
+    ("Metrics", Role::Viewer),
+    ("Facts", Role::Viewer),
+    ("Sessions", Role::Viewer),
+    ("Time", Role::Viewer),
+    ("Firewall", Role::Viewer),
+    ("Terminal", Role::Editor),
+    ("Exec", Role::Editor),
+    ("Docker", Role::Editor),
+    ("Serial", Role::Editor),
+    ("Gui", Role::Editor),
+    ("Vnc", Role::Editor),
+    ("SupportBundle", Role::Editor),
+    ("Plan", Role::Editor),
+    ("Packages", Role::Admin),
+    ("Forward", Role::Admin),
+    ("Resolve", Role::Admin),
+    ("Ssh", Role::Admin),
+];
+
+/// Role needed to open the stream described by `header`. Killing sessions
+/// needs the role of opening one, as does forcing a time resync; package
+/// dry runs need Editor and unknown stream types need Admin.
+pub fn required_stream_role(header: &serde_json::Value) -> Role {
+    let stream_type = header.get("type").and_then(|t| t.as_str()).unwrap_or("");
+    if stream_type == "Sessions" && header.get("kill").is_some_and(|k| !k.is_null()) {
+        return Role::Editor;
+    }
+    if stream_type == "Time" && header.get("sync").and_then(|s| s.as_bool()) == Some(true) {
+        return Role::Editor;
+    }
+    if stream_type == "Packages" && header.get("dry_run").and_then(|d| d.as_bool()) == Some(true) {
+        return Role::Editor;
+    }
+    STREAM_ROLES
+        .iter()
+        .find(|(name, _)| *name == stream_type)
+        .map(|(_, role)| role.clone())
+        .unwrap_or(Role::Admin)
+}
+
+/// Role the relay granted for the stream described by `header`, if any.
+pub fn granted_stream_role(header: &serde_json::Value) -> Option<Role> {
+    header
+        .get(GRANTED_ROLE_KEY)
+        .and_then(|r| r.as_str())
+        .and_then(|r| Role::from_str(r).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_every_stream_type_needs_its_role() {
+        let cases = [
+            (json!({ "type": "Logs", "token": "t" }), Role::Viewer),
+            (json!({ "type": "Metrics", "token": "t" }), Role::Viewer),
+            (
+                json!({ "type": "Facts", "token": "t", "refresh": true }),
+                Role::Viewer,
+            ),
+            (json!({ "type": "Sessions", "token": "t" }), Role::Viewer),
+            (
+                json!({ "type": "Sessions", "token": "t", "kill": null }),
+                Role::Viewer,
+            ),
+            (
+                json!({ "type": "Sessions", "token": "t", "kill": "s1" }),
+                Role::Editor,
+            ),
+            (json!({ "type": "Time", "token": "t" }), Role::Viewer),
+            (
+                json!({ "type": "Time", "token": "t", "sync": true }),
+                Role::Editor,
+            ),
+            (json!({ "type": "Firewall", "token": "t" }), Role::Viewer),
+            (
+                json!({ "type": "Terminal", "token": "t", "read_only": true }),
+                Role::Editor,
+            ),
+            (
+                json!({ "type": "Exec", "token": "t", "command": "ls" }),
+                Role::Editor,
+            ),
+            (json!({ "type": "Docker", "token": "t" }), Role::Editor),
+            (
+                json!({ "type": "Serial", "token": "t", "name": "ttyUSB0" }),
+                Role::Editor,
+            ),
+            (
+                json!({ "type": "Gui", "token": "t", "command": "xclock" }),
+                Role::Editor,
+            ),
+            (json!({ "type": "Vnc", "token": "t" }), Role::Editor),
+            (
+                json!({ "type": "SupportBundle", "token": "t", "max_bytes": 1 }),
+                Role::Editor,
+            ),
+            (json!({ "type": "Plan", "token": "t" }), Role::Editor),
+            (
+                json!({ "type": "Packages", "token": "t", "action": "install" }),
+                Role::Admin,
+            ),
+            (
+                json!({ "type": "Packages", "token": "t", "dry_run": true }),
+                Role::Editor,
+            ),
+            (json!({ "type": "Forward", "token": "t" }), Role::Admin),
+            (
+                json!({ "type": "Resolve", "token": "t", "host": "db" }),
+                Role::Admin,
+            ),
+            (
+                json!({ "type": "Ssh", "token": "t", "transfer": true }),
+                Role::Admin,
+            ),
+        ];
+        for (header, role) in &cases {
+            assert_eq!(&required_stream_role(header), role, "{}", header);
+        }
+        // the table has no entry the cases above miss
+        for (name, _) in STREAM_ROLES {
+            assert!(
+                cases.iter().any(|(h, _)| h["type"] == *name),
+                "{} is not covered",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_stream_types_need_the_highest_stream_role() {
+        let highest = STREAM_ROLES.iter().map(|(_, r)| r.rank()).max().unwrap();
+        for header in [
+            json!({ "type": "Bogus", "token": "t" }),
+            json!({ "type": "terminal", "token": "t" }),
+            json!({ "type": 1 }),
+            json!({ "token": "t" }),
+            json!(null),
+        ] {
+            let role = required_stream_role(&header);
+            assert_eq!(role, Role::Admin, "{}", header);
+            assert!(role.rank() >= highest);
+        }
+    }
+
+    #[test]
+    fn test_granted_stream_role() {
+        let header = |role: serde_json::Value| json!({ "type": "Logs", GRANTED_ROLE_KEY: role });
+        assert_eq!(
+            granted_stream_role(&header(json!("Editor"))),
+            Some(Role::Editor)
+        );
+        assert_eq!(
+            granted_stream_role(&header(json!("owner"))),
+            Some(Role::Owner)
+        );
+        assert_eq!(granted_stream_role(&header(json!("root"))), None);
+        assert_eq!(granted_stream_role(&header(json!(3))), None);
+        assert_eq!(granted_stream_role(&json!({ "type": "Logs" })), None);
+    }
+}
@@ -0,0 +1,353 @@
+//! Staged rollouts of a deployment revision across a group of devices.
+//!
+//! The server activates the revision in waves (by default on 5%, then 25%,
+//! then all of the devices). A wave starts once every device of the previous
+//! ones reported an outcome and stayed healthy for the soak time. When more
+//! than the failure threshold of the updated devices fail, the rollout halts
+//! and, unless disabled, reactivates the previous revision on each of them.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::deploy_spec::{DeploymentStatusSnapshot, Outcome};
+
+pub const DEFAULT_WAVES: [u32; 3] = [5, 25, 100];
+pub const DEFAULT_FAILURE_THRESHOLD_PERCENT: u32 = 10;
+pub const DEFAULT_SOAK_SECS: u64 = 600;
+pub const DEFAULT_DEPLOY_TIMEOUT_SECS: u64 = 1800;
+
+/// How a revision is rolled out; set per rollout or as `rollout` in the
+/// deployment YAML.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RolloutPolicy {
+    /// Share of the devices running the revision once each wave started, in
+    /// percent, ascending and ending at 100
+    #[serde(default = "default_waves")]
+    pub waves: Vec<u32>,
+    /// Halt when more than this share of the devices updated so far failed
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold_percent: u32,
+    /// How long the devices of a wave stay healthy before the next one starts
+    #[serde(default = "default_soak")]
+    pub soak_secs: u64,
+    /// Devices reporting no outcome within this time count as failed
+    #[serde(default = "default_deploy_timeout")]
+    pub deploy_timeout_secs: u64,
+    /// Reactivate the previous revision on the updated devices when the
+    /// rollout halts on failures
+    #[serde(default = "default_true")]
+    pub auto_rollback: bool,
+}
+
+fn default_waves() -> Vec<u32> {
+    DEFAULT_WAVES.to_vec()
+}
+
+fn default_failure_threshold() -> u32 {
+    DEFAULT_FAILURE_THRESHOLD_PERCENT
+}
+
+fn default_soak() -> u64 {
+    DEFAULT_SOAK_SECS
+}
+
+fn default_deploy_timeout() -> u64 {
+    DEFAULT_DEPLOY_TIMEOUT_SECS
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for RolloutPolicy {
+    fn default() -> Self {
+        Self {
+            waves: default_waves(),
+            failure_threshold_percent: DEFAULT_FAILURE_THRESHOLD_PERCENT,
+            soak_secs: DEFAULT_SOAK_SECS,
+            deploy_timeout_secs: DEFAULT_DEPLOY_TIMEOUT_SECS,
+            auto_rollback: true,
+        }
+    }
+}
+
+impl RolloutPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.waves.last() != Some(&100) {
+            return Err("the last wave must be 100%".to_string());
+        }
+        if self.waves.contains(&0) || self.waves.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err("waves must be ascending percentages above 0".to_string());
+        }
+        if self.failure_threshold_percent > 100 {
+            return Err("the failure threshold is a percentage up to 100".to_string());
+        }
+        if self.deploy_timeout_secs == 0 {
+            return Err("the deploy timeout must be above 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// Devices updated once each wave started, for `total` devices. Every
+    /// wave adds at least one device; waves that would add none are dropped.
+    pub fn wave_ends(&self, total: usize) -> Vec<usize> {
+        let mut ends: Vec<usize> = Vec::new();
+        for &percent in &self.waves {
+            let end = (total * percent as usize).div_ceil(100).clamp(1, total);
+            if ends.last().is_none_or(|&last| end > last) {
+                ends.push(end);
+            }
+        }
+        ends
+    }
+
+    /// Whether `failed` of `updated` devices exceed the failure threshold.
+    pub fn exceeded(&self, failed: usize, updated: usize) -> bool {
+        updated > 0 && failed * 100 > self.failure_threshold_percent as usize * updated
+    }
+
+    /// New state of a device that runs the revision for `elapsed_secs`,
+    /// from its deployment snapshot, with the reason it failed. `None`
+    /// while there is nothing to decide yet.
+    pub fn assess(
+        &self,
+        snapshot: &DeploymentStatusSnapshot,
+        elapsed_secs: u64,
+    ) -> Option<(RolloutDeviceState, Option<String>)> {
+        let failed = |reason: String| Some((RolloutDeviceState::Failed, Some(reason)));
+        if snapshot.rollback.is_some() {
+            return failed("the device rolled the revision back".to_string());
+        }
+        if snapshot.outcome == Outcome::Failed {
+            return failed(
+                snapshot
+                    .error
+                    .clone()
+                    .unwrap_or_else(|| "the deployment failed".to_string()),
+            );
+        }
+        let unhealthy = snapshot.runs.iter().find(|run| {
+            run.enabled
+                && [&run.alive, &run.healthy]
+                    .into_iter()
+                    .flatten()
+                    .any(|observed| !observed.ok)
+        });
+        if let Some(run) = unhealthy {
+            return failed(format!("run {} is unhealthy", run.run_id));
+        }
+        match snapshot.outcome {
+            Outcome::Success | Outcome::Skipped if elapsed_secs >= self.soak_secs => {
+                Some((RolloutDeviceState::Healthy, None))
+            }
+            Outcome::Unknown if elapsed_secs >= self.deploy_timeout_secs => failed(format!(
+                "no outcome reported within {}s",
+                self.deploy_timeout_secs
+            )),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutState {
+    Running,
+    /// Stopped by an operator or on failures; resuming continues the waves
+    Halted,
+    /// The updated devices were given their previous revision back
+    RolledBack,
+    Completed,
+    /// Stopped for good; devices keep the revision they run
+    Cancelled,
+}
+
+impl RolloutState {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            RolloutState::RolledBack | RolloutState::Completed | RolloutState::Cancelled
+        )
+    }
+}
+
+impl fmt::Display for RolloutState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RolloutState::Running => "running",
+            RolloutState::Halted => "halted",
+            RolloutState::RolledBack => "rolled_back",
+            RolloutState::Completed => "completed",
+            RolloutState::Cancelled => "cancelled",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutDeviceState {
+    /// Its wave has not started
+    Pending,
+    /// The revision is active, no outcome yet
+    Deploying,
+    Healthy,
+    Failed,
+    /// The previous revision was reactivated
+    RolledBack,
+}
+
+impl fmt::Display for RolloutDeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            RolloutDeviceState::Pending => "pending",
+            RolloutDeviceState::Deploying => "deploying",
+            RolloutDeviceState::Healthy => "healthy",
+            RolloutDeviceState::Failed => "failed",
+            RolloutDeviceState::RolledBack => "rolled_back",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RolloutDevice {
+    pub device_id: String,
+    pub device_name: String,
+    /// Wave the device is updated in, from 0
+    pub wave: usize,
+    pub state: RolloutDeviceState,
+    /// Active revision before the rollout, reactivated on rollback
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_revision_id: Option<String>,
+    /// RFC 3339, when the revision was activated on the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activated_at: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Rollout {
+    pub id: String,
+    pub revision_id: String,
+    pub policy: RolloutPolicy,
+    pub state: RolloutState,
+    /// Wave being rolled out, from 0
+    pub current_wave: usize,
+    /// Number of waves, after dropping the ones that add no device
+    pub waves: usize,
+    pub created_by: String,
+    /// RFC 3339
+    pub created_at: String,
+    pub updated_at: String,
+    /// Why the rollout halted, rolled back or was cancelled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// In wave order
+    pub devices: Vec<RolloutDevice>,
+}
+
+impl Rollout {
+    pub fn count(&self, state: RolloutDeviceState) -> usize {
+        self.devices.iter().filter(|d| d.state == state).count()
+    }
+}
+
+/// Starts a rollout of `revision` to `device_ids`, in the given order
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateRolloutBody {
+    /// YAML of the deployment revision
+    pub revision: String,
+    pub device_ids: Vec<String>,
+    /// Defaults to the `rollout` of the revision, then [`RolloutPolicy::default`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<RolloutPolicy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutAction {
+    Halt,
+    /// Continue a halted rollout; the failures so far no longer count
+    Resume,
+    /// Reactivate the previous revision on every updated device
+    Rollback,
+    Cancel,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateRolloutBody {
+    pub action: RolloutAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(outcome: Outcome) -> DeploymentStatusSnapshot {
+        serde_json::from_value(serde_json::json!({
+            "revision_id": "rev",
+            "outcome": outcome,
+            "dirty": false,
+            "error": null,
+            "rollback": null,
+            "runs": [],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn waves_add_at_least_one_device() {
+        let policy = RolloutPolicy::default();
+        assert_eq!(policy.wave_ends(100), vec![5, 25, 100]);
+        assert_eq!(policy.wave_ends(10), vec![1, 3, 10]);
+        assert_eq!(policy.wave_ends(2), vec![1, 2]);
+        assert_eq!(policy.wave_ends(1), vec![1]);
+    }
+
+    #[test]
+    fn validate_rejects_unordered_waves() {
+        let mut policy = RolloutPolicy::default();
+        assert!(policy.validate().is_ok());
+        policy.waves = vec![25, 5, 100];
+        assert!(policy.validate().is_err());
+        policy.waves = vec![5, 50];
+        assert!(policy.validate().is_err());
+    }
+
+    #[test]
+    fn threshold_is_a_share_of_updated_devices() {
+        let policy = RolloutPolicy::default();
+        assert!(policy.exceeded(1, 1));
+        assert!(!policy.exceeded(1, 10));
+        assert!(policy.exceeded(2, 10));
+        assert!(!policy.exceeded(0, 0));
+    }
+
+    #[test]
+    fn assess_waits_for_soak_and_times_out() {
+        let policy = RolloutPolicy::default();
+        assert_eq!(policy.assess(&snapshot(Outcome::Success), 60), None);
+        assert_eq!(
+            policy.assess(&snapshot(Outcome::Success), DEFAULT_SOAK_SECS),
+            Some((RolloutDeviceState::Healthy, None))
+        );
+        assert_eq!(policy.assess(&snapshot(Outcome::Unknown), 60), None);
+        assert_eq!(
+            policy
+                .assess(&snapshot(Outcome::Unknown), DEFAULT_DEPLOY_TIMEOUT_SECS)
+                .map(|(state, _)| state),
+            Some(RolloutDeviceState::Failed)
+        );
+        assert_eq!(
+            policy
+                .assess(&snapshot(Outcome::Failed), 0)
+                .map(|(state, _)| state),
+            Some(RolloutDeviceState::Failed)
+        );
+    }
+}
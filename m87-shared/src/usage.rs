@@ -0,0 +1,163 @@
+//! Monthly usage per organization, for chargeback.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the export format; bumped only when a field changes meaning
+pub const USAGE_EXPORT_VERSION: u32 = 1;
+
+/// Columns of the CSV export, in order
+pub const USAGE_CSV_HEADER: &str =
+    "org_id,month,device_hours,relay_bytes,peak_storage_bytes,peak_devices";
+
+/// What one organization used on one server in one calendar month (UTC)
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct OrgUsageRecord {
+    pub org_id: String,
+    /// `YYYY-MM`
+    pub month: String,
+    /// Time the org's devices were connected, summed over devices
+    pub device_seconds: u64,
+    /// Client traffic relayed to and from the org's devices
+    pub relay_bytes: u64,
+    /// Most deployment revision and report storage seen in the month
+    pub peak_storage_bytes: u64,
+    /// Most devices the org had in the month
+    pub peak_devices: u64,
+}
+
+impl OrgUsageRecord {
+    pub fn device_hours(&self) -> f64 {
+        self.device_seconds as f64 / 3600.0
+    }
+}
+
+/// Body of `GET /usage`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UsageExport {
+    pub version: u32,
+    pub records: Vec<OrgUsageRecord>,
+}
+
+/// Filters of `GET /usage`; months are inclusive `YYYY-MM`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub org_id: Option<String>,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+/// Whether `s` is a month as used in usage records (`YYYY-MM`).
+pub fn is_month(s: &str) -> bool {
+    let Some((year, month)) = s.split_once('-') else {
+        return false;
+    };
+    year.len() == 4
+        && year.bytes().all(|b| b.is_ascii_digit())
+        && month.len() == 2
+        && matches!(month.parse::<u8>(), Ok(1..=12))
+}
+
+/// Add up records of the same org and month, e.g. from several servers.
+/// Peaks are summed too, as each server holds different devices.
+pub fn merge_usage(records: impl IntoIterator<Item = OrgUsageRecord>) -> Vec<OrgUsageRecord> {
+    let mut merged: BTreeMap<(String, String), OrgUsageRecord> = BTreeMap::new();
+    for record in records {
+        let entry = merged
+            .entry((record.org_id.clone(), record.month.clone()))
+            .or_insert_with(|| OrgUsageRecord {
+                org_id: record.org_id.clone(),
+                month: record.month.clone(),
+                ..Default::default()
+            });
+        entry.device_seconds += record.device_seconds;
+        entry.relay_bytes += record.relay_bytes;
+        entry.peak_storage_bytes += record.peak_storage_bytes;
+        entry.peak_devices += record.peak_devices;
+    }
+    merged.into_values().collect()
+}
+
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Records as CSV with [`USAGE_CSV_HEADER`], device time in hours.
+pub fn usage_csv(records: &[OrgUsageRecord]) -> String {
+    let mut out = String::from(USAGE_CSV_HEADER);
+    out.push('\n');
+    for r in records {
+        out.push_str(&format!(
+            "{},{},{:.3},{},{},{}\n",
+            csv_field(&r.org_id),
+            r.month,
+            r.device_hours(),
+            r.relay_bytes,
+            r.peak_storage_bytes,
+            r.peak_devices
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(org_id: &str, month: &str, device_seconds: u64) -> OrgUsageRecord {
+        OrgUsageRecord {
+            org_id: org_id.to_string(),
+            month: month.to_string(),
+            device_seconds,
+            relay_bytes: 100,
+            peak_storage_bytes: 10,
+            peak_devices: 1,
+        }
+    }
+
+    #[test]
+    fn test_is_month() {
+        assert!(is_month("2026-01"));
+        assert!(is_month("2026-12"));
+        assert!(!is_month("2026-13"));
+        assert!(!is_month("2026-00"));
+        assert!(!is_month("2026-1"));
+        assert!(!is_month("26-01"));
+        assert!(!is_month("2026/01"));
+    }
+
+    #[test]
+    fn test_merge_usage() {
+        let merged = merge_usage(vec![
+            record("b", "2026-01", 60),
+            record("a", "2026-02", 7200),
+            record("a", "2026-02", 3600),
+        ]);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].org_id, "a");
+        assert_eq!(merged[0].device_seconds, 10800);
+        assert_eq!(merged[0].relay_bytes, 200);
+        assert_eq!(merged[0].peak_devices, 2);
+        assert_eq!(merged[1].org_id, "b");
+    }
+
+    #[test]
+    fn test_usage_csv() {
+        let csv = usage_csv(&[
+            record("acme", "2026-01", 5400),
+            record("a,\"b\"", "2026-01", 0),
+        ]);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], USAGE_CSV_HEADER);
+        assert_eq!(lines[1], "acme,2026-01,1.500,100,10,1");
+        assert_eq!(lines[2], "\"a,\"\"b\"\"\",2026-01,0.000,100,10,1");
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+
+/// Sender of an inbound webhook. Decides how the request is verified and
+/// where the tag is read from in the payload.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookProvider {
+    /// `X-Hub-Signature-256` HMAC over the body, tag from `ref`/`after`.
+    Github,
+    /// `X-Gitlab-Token` shared secret, tag from `ref`/`checkout_sha`.
+    Gitlab,
+    /// Container registry push (Docker Hub, Harbor, distribution). Verified via
+    /// `X-M87-Signature-256` HMAC or an `Authorization` header holding the secret.
+    Registry,
+}
+
+impl std::fmt::Display for WebhookProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WebhookProvider::Github => write!(f, "github"),
+            WebhookProvider::Gitlab => write!(f, "gitlab"),
+            WebhookProvider::Registry => write!(f, "registry"),
+        }
+    }
+}
+
+/// What a verified delivery does.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebhookAction {
+    /// Re-apply the deployment revision `revision_id` to the listed devices and
+    /// to every device of `org_id`. If `image` is set, references to that image
+    /// in the revision are re-tagged with the tag taken from the payload.
+    ReapplyDeployment {
+        revision_id: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        device_ids: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        org_id: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        image: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateWebhookBody {
+    pub name: String,
+    pub provider: WebhookProvider,
+    pub action: WebhookAction,
+    /// Only act on pushes to this branch (git providers). Tag pushes always pass.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Owning organization. Defaults to the calling user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub org_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Webhook {
+    pub id: String,
+    pub name: String,
+    pub provider: WebhookProvider,
+    pub action: WebhookAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub owner_scope: String,
+    pub created_at: String,
+    /// Path to configure at the sender, relative to the server URL.
+    pub delivery_path: String,
+}
+
+/// Returned once on creation; the secret is not retrievable afterwards.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreatedWebhook {
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookDelivery {
+    pub id: String,
+    pub webhook_id: String,
+    pub received_at: String,
+    pub event: Option<String>,
+    pub verified: bool,
+    pub success: bool,
+    pub tag: Option<String>,
+    pub message: String,
+    #[serde(default)]
+    pub device_ids: Vec<String>,
+}
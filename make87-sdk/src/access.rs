@@ -0,0 +1,23 @@
+use anyhow::Result;
+use m87_shared::access::{AccessGrant, CreateAccessGrantBody};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Time-boxed grants on devices the token administers, newest first.
+    /// Expired and revoked grants are included only with `all`.
+    pub async fn list_access_grants(&self, all: bool) -> Result<Vec<AccessGrant>> {
+        send_json(self.get("/access-grants").query(&[("all", all)])).await
+    }
+
+    /// Give a user a role on one device until the TTL runs out. Needs the
+    /// admin role on the device and at least the role granted.
+    pub async fn create_access_grant(&self, body: &CreateAccessGrantBody) -> Result<AccessGrant> {
+        send_json(self.post("/access-grants").json(body)).await
+    }
+
+    /// End a grant before it expires.
+    pub async fn revoke_access_grant(&self, id: &str) -> Result<AccessGrant> {
+        send_json(self.delete(&format!("/access-grants/{}", id))).await
+    }
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use m87_shared::approval::{ApprovalRequest, ApprovalStatus, DecideApprovalBody};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Approval requests on devices the token has access to, newest first,
+    /// optionally only those with `status`.
+    pub async fn list_approvals(
+        &self,
+        status: Option<ApprovalStatus>,
+        limit: u32,
+    ) -> Result<Vec<ApprovalRequest>> {
+        let mut req = self.get("/approvals").query(&[("limit", limit)]);
+        if let Some(status) = status {
+            req = req.query(&[("status", status.to_string())]);
+        }
+        send_json(req).await
+    }
+
+    /// Approve or reject a pending request. Needs a user with the admin role
+    /// on the device other than the one who asked.
+    pub async fn decide_approval(
+        &self,
+        id: &str,
+        body: &DecideApprovalBody,
+    ) -> Result<ApprovalRequest> {
+        send_json(self.post(&format!("/approvals/{}", id)).json(body)).await
+    }
+}
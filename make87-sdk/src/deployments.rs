@@ -0,0 +1,144 @@
+use anyhow::Result;
+use m87_shared::deploy_spec::{
+    CoreDump, CreateDeployRevisionBody, DeploymentRevision, DeploymentStatusSnapshot,
+    SnapshotQuery, StepArtifacts, UpdateDeployRevisionBody,
+};
+
+use m87_shared::protocol::PROTOCOL_VERSION;
+
+use crate::{Make87Client, send_empty, send_json};
+
+impl Make87Client {
+    pub async fn list_deployments(
+        &self,
+        device_id: &str,
+        offset: Option<u64>,
+        limit: Option<u64>,
+    ) -> Result<Vec<DeploymentRevision>> {
+        let mut q: Vec<(&str, u64)> = vec![];
+        if let Some(o) = offset {
+            q.push(("offset", o));
+        }
+        if let Some(l) = limit {
+            q.push(("limit", l));
+        }
+        send_json(
+            self.get(&format!("/device/{}/revisions", device_id))
+                .query(&q),
+        )
+        .await
+    }
+
+    pub async fn get_deployment(
+        &self,
+        device_id: &str,
+        revision_id: &str,
+    ) -> Result<DeploymentRevision> {
+        send_json(self.get(&format!("/device/{}/revisions/{}", device_id, revision_id))).await
+    }
+
+    pub async fn create_deployment(
+        &self,
+        device_id: &str,
+        body: &CreateDeployRevisionBody,
+    ) -> Result<DeploymentRevision> {
+        let mut body = body.clone();
+        body.revision = self.downgrade_revision(&body.revision).await?;
+        send_json(
+            self.post(&format!("/device/{}/revisions", device_id))
+                .json(&body),
+        )
+        .await
+    }
+
+    pub async fn update_deployment(
+        &self,
+        device_id: &str,
+        revision_id: &str,
+        body: &UpdateDeployRevisionBody,
+    ) -> Result<()> {
+        let mut body = body.clone();
+        if let Some(revision) = &body.revision {
+            body.revision = Some(self.downgrade_revision(revision).await?);
+        }
+        send_empty(
+            self.post(&format!("/device/{}/revisions/{}", device_id, revision_id))
+                .json(&body),
+        )
+        .await
+    }
+
+    /// Strip fields from a revision YAML that an older server would drop.
+    async fn downgrade_revision(&self, yaml: &str) -> Result<String> {
+        let server = self.server_protocol().await?;
+        if server >= PROTOCOL_VERSION {
+            return Ok(yaml.to_string());
+        }
+        let mut revision = DeploymentRevision::from_yaml(yaml)?;
+        revision.downgrade_for(server);
+        Ok(revision.to_yaml()?)
+    }
+
+    pub async fn delete_deployment(&self, device_id: &str, revision_id: &str) -> Result<()> {
+        send_empty(self.delete(&format!("/device/{}/revisions/{}", device_id, revision_id))).await
+    }
+
+    /// Id of the revision currently active on the device, if any.
+    pub async fn get_active_deployment_id(&self, device_id: &str) -> Result<Option<String>> {
+        send_json(self.get(&format!("/device/{}/revisions/active", device_id))).await
+    }
+
+    pub async fn get_deployment_snapshot(
+        &self,
+        device_id: &str,
+        revision_id: &str,
+    ) -> Result<DeploymentStatusSnapshot> {
+        send_json(self.get(&format!(
+            "/device/{}/revisions/{}/snapshot",
+            device_id, revision_id
+        )))
+        .await
+    }
+
+    /// Part of the snapshot, e.g. one page of runs. Servers without support
+    /// for it return the whole snapshot, without `total_runs`.
+    pub async fn get_deployment_snapshot_part(
+        &self,
+        device_id: &str,
+        revision_id: &str,
+        query: &SnapshotQuery,
+    ) -> Result<DeploymentStatusSnapshot> {
+        send_json(
+            self.get(&format!(
+                "/device/{}/revisions/{}/snapshot",
+                device_id, revision_id
+            ))
+            .query(query),
+        )
+        .await
+    }
+
+    /// Files the steps of a run uploaded, from each step's latest report with any.
+    pub async fn list_run_artifacts(
+        &self,
+        device_id: &str,
+        revision_id: &str,
+        run_id: &str,
+    ) -> Result<Vec<StepArtifacts>> {
+        send_json(self.get(&format!(
+            "/device/{}/revisions/{}/runs/{}/artifacts",
+            device_id, revision_id, run_id
+        )))
+        .await
+    }
+
+    /// Core dumps of the device's runs, newest first, without their content.
+    pub async fn list_core_dumps(&self, device_id: &str) -> Result<Vec<CoreDump>> {
+        send_json(self.get(&format!("/device/{}/cores", device_id))).await
+    }
+
+    /// A core dump with its compressed content, if it was uploaded.
+    pub async fn get_core_dump(&self, device_id: &str, core_id: &str) -> Result<CoreDump> {
+        send_json(self.get(&format!("/device/{}/cores/{}", device_id, core_id))).await
+    }
+}
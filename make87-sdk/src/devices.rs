@@ -0,0 +1,204 @@
+use anyhow::Result;
+use m87_shared::bandwidth::DeviceBandwidth;
+use m87_shared::device::{
+    AddDeviceAccessBody, AuditLog, DeviceStatus, DeviceTimelineEvent, MergeDeviceBody,
+    PublicDevice, UpdateDeviceBody,
+};
+use m87_shared::fleet_report::FleetReport;
+use m87_shared::inventory::DeviceVulnerabilities;
+use m87_shared::patching::PatchRun;
+use m87_shared::probe::{ProbeRecord, ProbeSpec, ProbeStatus, SetProbesBody};
+use m87_shared::roles::Role;
+use m87_shared::users::User;
+
+use crate::{Make87Client, send_empty, send_json};
+
+impl Make87Client {
+    /// List all devices the token has access to.
+    pub async fn list_devices(&self) -> Result<Vec<PublicDevice>> {
+        send_json(self.get("/device")).await
+    }
+
+    /// Inventory report of all devices the token has access to: versions,
+    /// health, last contact, active revision, location and asset info.
+    pub async fn get_fleet_report(&self) -> Result<FleetReport> {
+        send_json(self.get("/device/report")).await
+    }
+
+    pub async fn get_device(&self, device_id: &str) -> Result<PublicDevice> {
+        send_json(self.get(&format!("/device/{}", device_id))).await
+    }
+
+    /// Delete a device and everything recorded about it.
+    pub async fn delete_device(&self, device_id: &str) -> Result<()> {
+        send_empty(self.delete(&format!("/device/{}", device_id))).await
+    }
+
+    pub async fn update_device(&self, device_id: &str, body: &UpdateDeviceBody) -> Result<()> {
+        send_empty(self.post(&format!("/device/{}", device_id)).json(body)).await
+    }
+
+    /// Move the history of device `from` into `device_id` and delete `from`.
+    pub async fn merge_device(&self, device_id: &str, from: &str) -> Result<PublicDevice> {
+        let body = MergeDeviceBody {
+            from: from.to_string(),
+        };
+        send_json(
+            self.post(&format!("/device/{}/merge", device_id))
+                .json(&body),
+        )
+        .await
+    }
+
+    pub async fn get_device_status(&self, device_id: &str) -> Result<DeviceStatus> {
+        send_json(self.get(&format!("/device/{}/status", device_id))).await
+    }
+
+    /// Advisories matching the packages the device reported, from the
+    /// server's last scan.
+    pub async fn get_device_vulnerabilities(
+        &self,
+        device_id: &str,
+    ) -> Result<DeviceVulnerabilities> {
+        send_json(self.get(&format!("/device/{}/vulnerabilities", device_id))).await
+    }
+
+    /// Traffic of the device's runtime per class, from its heartbeats.
+    pub async fn get_device_bandwidth(&self, device_id: &str) -> Result<DeviceBandwidth> {
+        send_json(self.get(&format!("/device/{}/bandwidth", device_id))).await
+    }
+
+    /// Patch policy runs the device reported, newest first.
+    pub async fn get_device_patch_runs(
+        &self,
+        device_id: &str,
+        limit: u32,
+    ) -> Result<Vec<PatchRun>> {
+        send_json(
+            self.get(&format!("/device/{}/patch-runs", device_id))
+                .query(&[("limit", limit)]),
+        )
+        .await
+    }
+
+    /// The device's probes with their last result.
+    pub async fn get_device_probes(&self, device_id: &str) -> Result<Vec<ProbeStatus>> {
+        send_json(self.get(&format!("/device/{}/probes", device_id))).await
+    }
+
+    /// Replace the device's probes; an empty list removes them all.
+    pub async fn set_device_probes(
+        &self,
+        device_id: &str,
+        probes: Vec<ProbeSpec>,
+    ) -> Result<Vec<ProbeStatus>> {
+        let body = SetProbesBody { probes };
+        send_json(
+            self.post(&format!("/device/{}/probes", device_id))
+                .json(&body),
+        )
+        .await
+    }
+
+    /// Probe results the device reported, newest first, of one probe if
+    /// `name` is set.
+    pub async fn get_device_probe_results(
+        &self,
+        device_id: &str,
+        name: Option<&str>,
+        limit: u32,
+    ) -> Result<Vec<ProbeRecord>> {
+        let mut q: Vec<(&str, String)> = vec![("limit", limit.to_string())];
+        if let Some(name) = name {
+            q.push(("name", name.to_string()));
+        }
+        send_json(
+            self.get(&format!("/device/{}/probes/results", device_id))
+                .query(&q),
+        )
+        .await
+    }
+
+    /// Audit log entries, newest first. `since`/`until` are RFC3339 timestamps.
+    pub async fn get_device_audit_logs(
+        &self,
+        device_id: &str,
+        limit: u32,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Vec<AuditLog>> {
+        let mut q: Vec<(&str, String)> = vec![("limit", limit.to_string())];
+        if let Some(s) = since {
+            q.push(("since", s.to_string()));
+        }
+        if let Some(u) = until {
+            q.push(("until", u.to_string()));
+        }
+        send_json(
+            self.get(&format!("/device/{}/audit_logs", device_id))
+                .query(&q),
+        )
+        .await
+    }
+
+    /// The device's events timeline, oldest first. `since`/`until` are RFC3339
+    /// timestamps.
+    pub async fn get_device_events(
+        &self,
+        device_id: &str,
+        limit: u32,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Vec<DeviceTimelineEvent>> {
+        let mut q: Vec<(&str, String)> = vec![("limit", limit.to_string())];
+        if let Some(s) = since {
+            q.push(("since", s.to_string()));
+        }
+        if let Some(u) = until {
+            q.push(("until", u.to_string()));
+        }
+        send_json(self.get(&format!("/device/{}/events", device_id)).query(&q)).await
+    }
+
+    pub async fn get_device_users(&self, device_id: &str) -> Result<Vec<User>> {
+        send_json(self.get(&format!("/device/{}/users", device_id))).await
+    }
+
+    pub async fn add_device_access(
+        &self,
+        device_id: &str,
+        email_or_org_id: &str,
+        role: Role,
+    ) -> Result<()> {
+        let body = AddDeviceAccessBody {
+            email_or_org_id: email_or_org_id.to_string(),
+            role,
+        };
+        send_empty(
+            self.post(&format!("/device/{}/access", device_id))
+                .json(&body),
+        )
+        .await
+    }
+
+    pub async fn update_device_access(
+        &self,
+        device_id: &str,
+        email_or_org_id: &str,
+        role: Role,
+    ) -> Result<()> {
+        let body = AddDeviceAccessBody {
+            email_or_org_id: email_or_org_id.to_string(),
+            role,
+        };
+        send_empty(
+            self.put(&format!("/device/{}/access/{}", device_id, email_or_org_id))
+                .json(&body),
+        )
+        .await
+    }
+
+    pub async fn remove_device_access(&self, device_id: &str, email_or_org_id: &str) -> Result<()> {
+        send_empty(self.delete(&format!("/device/{}/access/{}", device_id, email_or_org_id))).await
+    }
+}
@@ -0,0 +1,15 @@
+use anyhow::Result;
+use m87_shared::enrollment::{CreateInstallCommandBody, InstallCommand};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// A one-line install command for new devices, with a fresh enrollment
+    /// token that registers them without approval.
+    pub async fn create_install_command(
+        &self,
+        body: &CreateInstallCommandBody,
+    ) -> Result<InstallCommand> {
+        send_json(self.post("/auth/install-command").json(body)).await
+    }
+}
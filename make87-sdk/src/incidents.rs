@@ -0,0 +1,52 @@
+use anyhow::Result;
+use m87_shared::incident::{Incident, IncidentState, UpdateIncidentBody};
+use m87_shared::org::{OrgIncidentWebhook, UpdateOrgIncidentWebhookBody};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Incidents on devices the token has access to, newest first. Only
+    /// unresolved ones unless `state` is given or `all` is set.
+    pub async fn list_incidents(
+        &self,
+        state: Option<IncidentState>,
+        all: bool,
+        limit: u32,
+    ) -> Result<Vec<Incident>> {
+        let mut req = self
+            .get("/incidents")
+            .query(&[("limit", limit.to_string()), ("all", all.to_string())]);
+        if let Some(state) = state {
+            req = req.query(&[("state", state.to_string())]);
+        }
+        send_json(req).await
+    }
+
+    pub async fn get_incident(&self, id: &str) -> Result<Incident> {
+        send_json(self.get(&format!("/incidents/{}", id))).await
+    }
+
+    /// Acknowledge or resolve an incident. Needs the editor role on its
+    /// device.
+    pub async fn update_incident(&self, id: &str, body: &UpdateIncidentBody) -> Result<Incident> {
+        send_json(self.post(&format!("/incidents/{}", id)).json(body)).await
+    }
+
+    /// Where incident state changes of one org's devices are posted.
+    /// Requires the admin role in the org.
+    pub async fn get_org_incident_webhook(&self, org_id: &str) -> Result<OrgIncidentWebhook> {
+        send_json(self.get(&format!("/organization/{}/incident-webhook", org_id))).await
+    }
+
+    pub async fn set_org_incident_webhook(
+        &self,
+        org_id: &str,
+        body: &UpdateOrgIncidentWebhookBody,
+    ) -> Result<OrgIncidentWebhook> {
+        send_json(
+            self.post(&format!("/organization/{}/incident-webhook", org_id))
+                .json(body),
+        )
+        .await
+    }
+}
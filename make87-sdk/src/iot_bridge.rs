@@ -0,0 +1,25 @@
+use anyhow::Result;
+use m87_shared::iot_bridge::{OrgIotBridge, UpdateOrgIotBridgeBody};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// The IoT platform one org's devices are republished to. Requires the
+    /// admin role in the org.
+    pub async fn get_org_iot_bridge(&self, org_id: &str) -> Result<OrgIotBridge> {
+        send_json(self.get(&format!("/organization/{}/iot-bridge", org_id))).await
+    }
+
+    /// Replace or, without a target, remove the IoT bridge of one org.
+    pub async fn set_org_iot_bridge(
+        &self,
+        org_id: &str,
+        body: &UpdateOrgIotBridgeBody,
+    ) -> Result<OrgIotBridge> {
+        send_json(
+            self.post(&format!("/organization/{}/iot-bridge", org_id))
+                .json(body),
+        )
+        .await
+    }
+}
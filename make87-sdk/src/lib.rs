@@ -0,0 +1,204 @@
+//! Rust client for the make87 API.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! let client = make87_sdk::Make87Client::new("https://m87.example.com", "<token>")?;
+//! for device in client.list_devices().await? {
+//!     println!("{} {}", device.short_id, device.name);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod access;
+pub mod approvals;
+pub mod deployments;
+pub mod devices;
+pub mod enrollment;
+pub mod incidents;
+pub mod iot_bridge;
+pub mod limits;
+pub mod notices;
+pub mod proxy;
+pub mod redaction;
+pub mod reports;
+pub mod rollouts;
+pub mod streams;
+pub mod tls;
+pub mod usage;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use anyhow::{Result, anyhow, bail};
+use m87_shared::db_stats::CollectionStats;
+use m87_shared::protocol::{PROTOCOL_HEADER, PROTOCOL_VERSION, parse_protocol};
+use m87_shared::relay::RelayStats;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+
+pub use m87_shared;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Authenticated client for a single make87 server.
+#[derive(Clone)]
+pub struct Make87Client {
+    api_url: String,
+    token: String,
+    trust_invalid_server_cert: bool,
+    http: Client,
+    /// Server protocol version, 0 until the first [`Make87Client::server_protocol`] call
+    server_protocol: Arc<AtomicU32>,
+}
+
+impl Make87Client {
+    /// Create a client for `api_url` (e.g. the server URL stored in the CLI config)
+    /// authenticated with a bearer `token`.
+    pub fn new(api_url: impl Into<String>, token: impl Into<String>) -> Result<Self> {
+        Self::with_options(api_url, token, false)
+    }
+
+    /// Like [`Make87Client::new`], but optionally accepting self-signed server
+    /// certificates (local development servers).
+    pub fn with_options(
+        api_url: impl Into<String>,
+        token: impl Into<String>,
+        trust_invalid_server_cert: bool,
+    ) -> Result<Self> {
+        Self::with_client_builder(api_url, token, trust_invalid_server_cert, Client::builder())
+    }
+
+    /// Like [`Make87Client::with_options`], starting from a caller's HTTP client
+    /// builder, e.g. one with a proxy configured.
+    pub fn with_client_builder(
+        api_url: impl Into<String>,
+        token: impl Into<String>,
+        trust_invalid_server_cert: bool,
+        builder: reqwest::ClientBuilder,
+    ) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(PROTOCOL_HEADER, HeaderValue::from(PROTOCOL_VERSION));
+        let mut builder = tls::trust_extra_roots(builder)?
+            .timeout(REQUEST_TIMEOUT)
+            .default_headers(headers);
+        if trust_invalid_server_cert {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(Self {
+            api_url: api_url.into().trim_end_matches('/').to_string(),
+            token: token.into(),
+            trust_invalid_server_cert,
+            http: builder.build()?,
+            server_protocol: Arc::new(AtomicU32::new(0)),
+        })
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    pub fn trust_invalid_server_cert(&self) -> bool {
+        self.trust_invalid_server_cert
+    }
+
+    /// Protocol version the server speaks, read from the `/status` response
+    /// headers once and cached. Servers that predate the negotiation report 1.
+    pub async fn server_protocol(&self) -> Result<u32> {
+        let cached = self.server_protocol.load(Ordering::Relaxed);
+        if cached != 0 {
+            return Ok(cached);
+        }
+        let res = self.http.get(self.url("/status")).send().await?;
+        let version = protocol_of(&res);
+        self.server_protocol.store(version, Ordering::Relaxed);
+        Ok(version)
+    }
+
+    /// Tunnel count and memory of the server. Requires an admin token.
+    pub async fn relay_stats(&self) -> Result<RelayStats> {
+        send_json(self.get("/admin/relay")).await
+    }
+
+    /// Collections, indexes and index use of the server's database. Requires
+    /// an admin token.
+    pub async fn db_stats(&self) -> Result<Vec<CollectionStats>> {
+        send_json(self.get("/admin/db-stats")).await
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.api_url, path)
+    }
+
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.http.get(self.url(path)).bearer_auth(&self.token)
+    }
+
+    fn post(&self, path: &str) -> RequestBuilder {
+        self.http.post(self.url(path)).bearer_auth(&self.token)
+    }
+
+    fn put(&self, path: &str) -> RequestBuilder {
+        self.http.put(self.url(path)).bearer_auth(&self.token)
+    }
+
+    fn delete(&self, path: &str) -> RequestBuilder {
+        self.http.delete(self.url(path)).bearer_auth(&self.token)
+    }
+}
+
+fn protocol_of(res: &Response) -> u32 {
+    parse_protocol(
+        res.headers()
+            .get(PROTOCOL_HEADER)
+            .and_then(|v| v.to_str().ok()),
+    )
+}
+
+/// Like `error_for_status`, but surfaces the server's message on 426 and
+/// 403 and points at a version gap when an older server fails a request.
+async fn check_status(res: Response) -> Result<Response> {
+    let status = res.status();
+    let fallback = match status {
+        StatusCode::UPGRADE_REQUIRED => Some("this client is too old for the server"),
+        StatusCode::FORBIDDEN => Some("permission denied"),
+        _ => None,
+    };
+    if let Some(fallback) = fallback {
+        #[derive(serde::Deserialize)]
+        struct ErrorBody {
+            message: String,
+        }
+        let message = res
+            .json::<ErrorBody>()
+            .await
+            .map(|b| b.message)
+            .unwrap_or_else(|_| fallback.to_string());
+        bail!("{}", message);
+    }
+
+    let server = protocol_of(&res);
+    match res.error_for_status() {
+        Ok(r) => Ok(r),
+        Err(e) if server < PROTOCOL_VERSION => Err(anyhow!(e).context(format!(
+            "server speaks protocol v{} (client v{}); it may need an upgrade",
+            server, PROTOCOL_VERSION
+        ))),
+        Err(e) => Err(anyhow!(e)),
+    }
+}
+
+async fn send_json<T: DeserializeOwned>(req: RequestBuilder) -> Result<T> {
+    Ok(check_status(req.send().await?).await?.json().await?)
+}
+
+async fn send_empty(req: RequestBuilder) -> Result<()> {
+    check_status(req.send().await?).await?;
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use m87_shared::org::{OrgLimitsStatus, UpdateOrgLimitsBody};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Limits and current usage of every org the token belongs to.
+    pub async fn list_limits(&self) -> Result<Vec<OrgLimitsStatus>> {
+        send_json(self.get("/limits")).await
+    }
+
+    /// Limits and current usage of one org.
+    pub async fn get_org_limits(&self, org_id: &str) -> Result<OrgLimitsStatus> {
+        send_json(self.get(&format!("/organization/{}/limits", org_id))).await
+    }
+
+    /// Override the instance defaults for one org; `0` lifts a limit. Requires
+    /// an admin token.
+    pub async fn set_org_limits(
+        &self,
+        org_id: &str,
+        body: &UpdateOrgLimitsBody,
+    ) -> Result<OrgLimitsStatus> {
+        send_json(
+            self.post(&format!("/organization/{}/limits", org_id))
+                .json(body),
+        )
+        .await
+    }
+}
@@ -0,0 +1,11 @@
+use anyhow::Result;
+use m87_shared::notices::CliNotice;
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Notices the server wants CLIs to show, e.g. deprecations.
+    pub async fn list_notices(&self) -> Result<Vec<CliNotice>> {
+        send_json(self.get("/notices")).await
+    }
+}
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use m87_shared::org::{OrgRedaction, UpdateOrgRedactionBody};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Custom redaction patterns of one org.
+    pub async fn get_org_redaction(&self, org_id: &str) -> Result<OrgRedaction> {
+        send_json(self.get(&format!("/organization/{}/redaction", org_id))).await
+    }
+
+    /// Replace the custom redaction patterns of one org. Requires the admin
+    /// role in the org.
+    pub async fn set_org_redaction(
+        &self,
+        org_id: &str,
+        body: &UpdateOrgRedactionBody,
+    ) -> Result<OrgRedaction> {
+        send_json(
+            self.post(&format!("/organization/{}/redaction", org_id))
+                .json(body),
+        )
+        .await
+    }
+}
@@ -0,0 +1,19 @@
+use anyhow::Result;
+use m87_shared::deploy_spec::DeployReport;
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Step, run-state and rollback reports the device sent for a revision.
+    pub async fn get_deployment_reports(
+        &self,
+        device_id: &str,
+        revision_id: &str,
+    ) -> Result<Vec<DeployReport>> {
+        send_json(self.get(&format!(
+            "/device/{}/revisions/{}/reports",
+            device_id, revision_id
+        )))
+        .await
+    }
+}
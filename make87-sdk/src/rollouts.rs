@@ -0,0 +1,31 @@
+use anyhow::Result;
+use m87_shared::rollout::{CreateRolloutBody, Rollout, UpdateRolloutBody};
+
+use crate::{Make87Client, send_json};
+
+impl Make87Client {
+    /// Rollouts on devices the token has access to, newest first. Only
+    /// running and halted ones unless `all` is set.
+    pub async fn list_rollouts(&self, all: bool, limit: u32) -> Result<Vec<Rollout>> {
+        send_json(
+            self.get("/rollouts")
+                .query(&[("limit", limit.to_string()), ("all", all.to_string())]),
+        )
+        .await
+    }
+
+    pub async fn get_rollout(&self, id: &str) -> Result<Rollout> {
+        send_json(self.get(&format!("/rollouts/{}", id))).await
+    }
+
+    /// Start rolling a revision out in waves. Needs the editor role on every
+    /// device.
+    pub async fn create_rollout(&self, body: &CreateRolloutBody) -> Result<Rollout> {
+        send_json(self.post("/rollouts").json(body)).await
+    }
+
+    /// Halt, resume, roll back or cancel a rollout.
+    pub async fn update_rollout(&self, id: &str, body: &UpdateRolloutBody) -> Result<Rollout> {
+        send_json(self.post(&format!("/rollouts/{}", id)).json(body)).await
+    }
+}
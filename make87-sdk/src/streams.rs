@@ -0,0 +1,484 @@
+//! QUIC connections to devices through the relay.
+//!
+//! Every connection authenticates by sending the token (u16 BE length + bytes) on the
+//! first uni stream. Each bidirectional stream then starts with a u32 BE length prefixed
+//! JSON stream type that tells the device which handler to attach.
+
+use anyhow::{Context, Result, anyhow};
+use m87_shared::host;
+use quinn::{ClientConfig, Endpoint, EndpointConfig, IdleTimeout, TokioRuntime};
+use quinn_proto::crypto::rustls::QuicClientConfig;
+use rustls::client::danger::ServerCertVerifier;
+use rustls::{ClientConfig as RustlsClientConfig, RootCertStore};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+use std::{net::SocketAddr, sync::Arc};
+use std::{pin::Pin, task::Poll};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::task::JoinSet;
+use tracing::{debug, error, warn};
+
+use crate::Make87Client;
+use crate::proxy::UdpProxy;
+use crate::tls::{self, NoVerify};
+
+impl Make87Client {
+    /// QUIC host of the relay serving this client's API.
+    pub fn relay_host(&self) -> &str {
+        self.api_url
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+    }
+
+    /// Connect to a device through the relay.
+    pub async fn connect_device(
+        &self,
+        device_short_id: &str,
+    ) -> Result<(Endpoint, quinn::Connection)> {
+        let host = host::device_host(device_short_id, self.relay_host());
+        get_quic_connection(&host, &self.token, self.trust_invalid_server_cert).await
+    }
+
+    /// Connect to a device and open a single stream of `stream_type`.
+    pub async fn open_device_stream<T: Serialize>(
+        &self,
+        device_short_id: &str,
+        stream_type: &T,
+    ) -> Result<(quinn::Connection, QuicIo)> {
+        let (_endpoint, conn) = self.connect_device(device_short_id).await?;
+        let io = open_stream(&conn, stream_type).await?;
+        Ok((conn, io))
+    }
+}
+
+/// Delay before the next address is tried while earlier attempts are pending (RFC 8305).
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Whether the last connection succeeded over IPv4; that family is tried first.
+static PREFER_IPV4: AtomicBool = AtomicBool::new(false);
+
+/// All addresses of `host`, alternating between IPv6 and IPv4 and starting
+/// with the family that worked last.
+async fn resolve_host(host: &str, port: u16) -> Result<Vec<SocketAddr>> {
+    if let Some(ip) = host::decode_ip(host) {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+    for i in 0..10 {
+        if let Ok(addrs) = tokio::net::lookup_host((host, port)).await {
+            let addrs = interleave(addrs.collect(), PREFER_IPV4.load(Ordering::Relaxed));
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+        }
+
+        let backoff_ms = 200 + i * 150;
+        tokio::time::sleep(std::time::Duration::from_millis(backoff_ms)).await;
+    }
+
+    Err(anyhow!("DNS resolution failed after retries"))
+}
+
+fn interleave(addrs: Vec<SocketAddr>, ipv4_first: bool) -> Vec<SocketAddr> {
+    let (first, second): (Vec<_>, Vec<_>) =
+        addrs.into_iter().partition(|a| a.is_ipv4() == ipv4_first);
+    let mut out = Vec::with_capacity(first.len() + second.len());
+    let (mut first, mut second) = (first.into_iter(), second.into_iter());
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => return out,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Happy eyeballs: try `addrs` in order, starting the next attempt after
+/// [`CONNECTION_ATTEMPT_DELAY`] or as soon as one fails, and keep the first
+/// connection that completes.
+async fn race_connect(
+    addrs: Vec<SocketAddr>,
+    server_name: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    timeouts: QuicTimeouts,
+) -> Result<(Endpoint, quinn::Connection)> {
+    let mut pending = addrs.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        if let Some(addr) = pending.next() {
+            let server_name = server_name.to_string();
+            let token = token.to_string();
+            attempts.spawn(async move {
+                let res = connect_with_token_and_timeouts(
+                    addr,
+                    &server_name,
+                    &token,
+                    trust_invalid_server_cert,
+                    timeouts,
+                )
+                .await;
+                (addr, res)
+            });
+        }
+        let joined = if pending.len() > 0 {
+            tokio::select! {
+                joined = attempts.join_next() => joined,
+                _ = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY) => continue,
+            }
+        } else {
+            attempts.join_next().await
+        };
+        match joined {
+            Some(Ok((addr, Ok(conn)))) => {
+                PREFER_IPV4.store(addr.is_ipv4(), Ordering::Relaxed);
+                return Ok(conn);
+            }
+            Some(Ok((addr, Err(e)))) => {
+                debug!("QUIC connect to {} failed: {:#}", addr, e);
+                last_error = Some(e);
+            }
+            Some(Err(e)) => last_error = Some(e.into()),
+            None => break,
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow!("No addresses to connect to {}", server_name)))
+}
+
+/// QUIC keepalive interval and idle timeout of a connection. The keepalive
+/// keeps NAT mappings open; the idle timeout decides how quickly a dead path
+/// closes the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuicTimeouts {
+    pub keep_alive: Duration,
+    pub idle: Duration,
+}
+
+impl Default for QuicTimeouts {
+    fn default() -> Self {
+        Self {
+            keep_alive: Duration::from_secs(5),
+            idle: Duration::from_secs(180),
+        }
+    }
+}
+
+/// Connect to `host_name[:port]` (port defaults to 443) and authenticate with `token`.
+pub async fn get_quic_connection(
+    host_name: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<(Endpoint, quinn::Connection)> {
+    get_quic_connection_with_timeouts(
+        host_name,
+        token,
+        trust_invalid_server_cert,
+        QuicTimeouts::default(),
+    )
+    .await
+}
+
+/// [`get_quic_connection`] with explicit keepalive and idle timeouts.
+pub async fn get_quic_connection_with_timeouts(
+    host_name: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    timeouts: QuicTimeouts,
+) -> Result<(Endpoint, quinn::Connection)> {
+    let (port_free_host_name, port) = split_host_port(host_name);
+    let addrs = resolve_host(port_free_host_name, port).await?;
+
+    race_connect(
+        addrs,
+        port_free_host_name,
+        token,
+        trust_invalid_server_cert,
+        timeouts,
+    )
+    .await
+}
+
+/// [`get_quic_connection_with_timeouts`] with the QUIC datagrams carried by `proxy`.
+pub async fn get_quic_connection_via_proxy(
+    host_name: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    timeouts: QuicTimeouts,
+    proxy: &UdpProxy,
+) -> Result<(Endpoint, quinn::Connection)> {
+    let (port_free_host_name, port) = split_host_port(host_name);
+    // the proxy cannot resolve names encoding an IP literal
+    let target = host::decode_ip(port_free_host_name).map(|ip| ip.to_string());
+    let (socket, server_addr) = proxy
+        .bind(target.as_deref().unwrap_or(port_free_host_name), port)
+        .await?;
+    debug!("Connecting to {} through proxy {:?}", host_name, proxy);
+    let endpoint = Endpoint::new_with_abstract_socket(
+        EndpointConfig::default(),
+        None,
+        socket,
+        Arc::new(TokioRuntime),
+    )
+    .context("failed creating QUIC endpoint")?;
+    connect_endpoint(
+        endpoint,
+        server_addr,
+        port_free_host_name,
+        token,
+        trust_invalid_server_cert,
+        timeouts,
+    )
+    .await
+}
+
+/// Split `host[:port]` or `[v6]:port`; the port defaults to 443.
+fn split_host_port(host_name: &str) -> (&str, u16) {
+    let (host, port) = host::split_host_port(host_name);
+    (host, port.unwrap_or(443))
+}
+
+/// Open a QUIC connection to an already resolved address and authenticate with `token`.
+pub async fn connect_with_token(
+    server_addr: SocketAddr,
+    server_name: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+) -> Result<(Endpoint, quinn::Connection)> {
+    connect_with_token_and_timeouts(
+        server_addr,
+        server_name,
+        token,
+        trust_invalid_server_cert,
+        QuicTimeouts::default(),
+    )
+    .await
+}
+
+fn client_config(trust_invalid_server_cert: bool, timeouts: QuicTimeouts) -> Result<ClientConfig> {
+    // 2. Root store (system roots)
+    let mut root_store = RootCertStore::empty();
+    root_store
+        .roots
+        .extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    // plus private CAs of self-hosted servers
+    root_store.add_parsable_certificates(tls::extra_roots().iter().cloned());
+
+    // 3. Build rustls QUIC client config
+    debug!(
+        "Creating QUIC client config with trust_invalid_server_cert={}",
+        trust_invalid_server_cert
+    );
+
+    let tls = if trust_invalid_server_cert {
+        warn!("QUIC: trusting invalid server certificate");
+        RustlsClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerify))
+            .with_no_client_auth()
+    } else {
+        RustlsClientConfig::builder()
+            .with_root_certificates(root_store)
+            .with_no_client_auth()
+    };
+    quic_client_config(tls, timeouts)
+}
+
+fn quic_client_config(mut tls: RustlsClientConfig, timeouts: QuicTimeouts) -> Result<ClientConfig> {
+    // 4. QUIC requires ALPN
+    tls.alpn_protocols = vec![b"m87-quic".to_vec()];
+
+    // 5. Convert rustls → QUIC crypto config
+    let crypto =
+        Arc::new(QuicClientConfig::try_from(tls).context("failed converting rustls→quic config")?);
+
+    let mut client_cfg = ClientConfig::new(crypto);
+    let mut transport = quinn::TransportConfig::default();
+    transport.keep_alive_interval(Some(timeouts.keep_alive));
+    //make sure we dont black hole packets
+    transport
+        .initial_mtu(1200)
+        .min_mtu(1200)
+        .mtu_discovery_config(None)
+        .enable_segmentation_offload(false);
+    transport.max_idle_timeout(Some(
+        IdleTimeout::try_from(timeouts.idle).context("invalid QUIC idle timeout")?,
+    ));
+    client_cfg.transport_config(Arc::new(transport));
+    Ok(client_cfg)
+}
+
+/// [`connect_with_token`] with explicit keepalive and idle timeouts.
+pub async fn connect_with_token_and_timeouts(
+    server_addr: SocketAddr,
+    server_name: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    timeouts: QuicTimeouts,
+) -> Result<(Endpoint, quinn::Connection)> {
+    connect_endpoint(
+        client_endpoint(server_addr)?,
+        server_addr,
+        server_name,
+        token,
+        trust_invalid_server_cert,
+        timeouts,
+    )
+    .await
+}
+
+/// Open a QUIC connection to a server whose certificate `verifier` accepts, e.g. one
+/// pinned by fingerprint, without sending a token: the caller authenticates on the
+/// connection itself.
+pub async fn connect_verified(
+    server_addr: SocketAddr,
+    server_name: &str,
+    verifier: Arc<dyn ServerCertVerifier>,
+    timeouts: QuicTimeouts,
+) -> Result<(Endpoint, quinn::Connection)> {
+    let tls = RustlsClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(verifier)
+        .with_no_client_auth();
+    let mut endpoint = client_endpoint(server_addr)?;
+    endpoint.set_default_client_config(quic_client_config(tls, timeouts)?);
+    let conn = endpoint
+        .connect(server_addr, server_name)
+        .context("QUIC connect() failed")?
+        .await
+        .context("QUIC handshake failed")?;
+    Ok((endpoint, conn))
+}
+
+/// QUIC client endpoint on a local ephemeral port of the server's address family.
+fn client_endpoint(server_addr: SocketAddr) -> Result<Endpoint> {
+    let local_addr: SocketAddr = if server_addr.is_ipv6() {
+        "[::]:0".parse()?
+    } else {
+        "0.0.0.0:0".parse()?
+    };
+    Endpoint::client(local_addr).context("failed creating QUIC endpoint")
+}
+
+async fn connect_endpoint(
+    mut endpoint: Endpoint,
+    server_addr: SocketAddr,
+    server_name: &str,
+    token: &str,
+    trust_invalid_server_cert: bool,
+    timeouts: QuicTimeouts,
+) -> Result<(Endpoint, quinn::Connection)> {
+    endpoint.set_default_client_config(client_config(trust_invalid_server_cert, timeouts)?);
+
+    let connecting = endpoint
+        .connect(server_addr, server_name)
+        .context("QUIC connect() failed")?;
+
+    let conn = connecting
+        .await
+        .map_err(|e| {
+            error!("QUIC connect failed: {}", e);
+            e
+        })
+        .context("QUIC handshake failed")?;
+
+    let mut send = conn.open_uni().await?;
+    debug!("Connected to server");
+    debug!("Sending token");
+    let token_bytes = token.as_bytes();
+    send.write_all(&(token_bytes.len() as u16).to_be_bytes())
+        .await?;
+    send.write_all(token_bytes).await?;
+    send.finish()?;
+
+    Ok((endpoint, conn))
+}
+
+/// Bytes sent and received on the streams sharing it.
+#[derive(Debug, Default)]
+pub struct ByteCounter {
+    pub sent: AtomicU64,
+    pub received: AtomicU64,
+}
+
+pub struct QuicIo {
+    pub recv: quinn::RecvStream,
+    pub send: quinn::SendStream,
+    /// Counts the bytes read and written through the stream when set
+    pub counter: Option<Arc<ByteCounter>>,
+}
+
+impl QuicIo {
+    pub fn new(recv: quinn::RecvStream, send: quinn::SendStream) -> Self {
+        Self {
+            recv,
+            send,
+            counter: None,
+        }
+    }
+}
+
+impl AsyncRead for QuicIo {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.recv).poll_read(cx, buf);
+        if let Some(counter) = &self.counter {
+            let n = buf.filled().len() - before;
+            counter.received.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+impl AsyncWrite for QuicIo {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        data: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let res = Pin::new(&mut self.send)
+            .poll_write(cx, data)
+            .map_err(std::io::Error::from);
+        if let (Some(counter), Poll::Ready(Ok(n))) = (&self.counter, &res) {
+            counter.sent.fetch_add(*n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.send).poll_shutdown(cx)
+    }
+}
+
+/// Open a bidirectional stream and send the stream type header.
+pub async fn open_stream<T: Serialize>(
+    conn: &quinn::Connection,
+    stream_type: &T,
+) -> Result<QuicIo> {
+    debug!("Opening QUIC stream");
+    let (mut send, recv) = conn.open_bi().await?;
+
+    let json = serde_json::to_vec(stream_type)?;
+    let len = (json.len() as u32).to_be_bytes();
+
+    send.write_all(&len).await?;
+    send.write_all(&json).await?;
+    send.flush().await?;
+
+    debug!("Stream opened");
+
+    Ok(QuicIo::new(recv, send))
+}
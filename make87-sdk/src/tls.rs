@@ -0,0 +1,85 @@
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result, bail};
+use rustls::{
+    SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject},
+};
+
+static EXTRA_ROOTS: OnceLock<Vec<CertificateDer<'static>>> = OnceLock::new();
+
+/// Trust the CA certificates in `pem` in addition to the public roots, for
+/// servers with certificates from a private CA (e.g. offline deployments).
+/// Applies to QUIC connections and [`crate::Make87Client`]s created afterwards;
+/// only the first call has an effect. Returns the number of certificates.
+pub fn add_trusted_roots(pem: &[u8]) -> Result<usize> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid PEM certificate bundle")?;
+    if certs.is_empty() {
+        bail!("no certificates in bundle");
+    }
+    let count = certs.len();
+    let _ = EXTRA_ROOTS.set(certs);
+    Ok(count)
+}
+
+/// CA certificates added with [`add_trusted_roots`].
+pub fn extra_roots() -> &'static [CertificateDer<'static>] {
+    EXTRA_ROOTS.get().map(Vec::as_slice).unwrap_or(&[])
+}
+
+/// Add the [`extra_roots`] to an HTTP client.
+pub fn trust_extra_roots(mut builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+    for cert in extra_roots() {
+        builder = builder.add_root_certificate(reqwest::Certificate::from_der(cert)?);
+    }
+    Ok(builder)
+}
+
+/// Certificate verifier that accepts any server certificate. Only used when the caller
+/// explicitly trusts invalid server certificates.
+#[derive(Debug)]
+pub struct NoVerify;
+
+impl ServerCertVerifier for NoVerify {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![
+            SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignatureScheme::ED25519,
+            SignatureScheme::RSA_PSS_SHA256,
+            SignatureScheme::RSA_PKCS1_SHA256,
+        ]
+    }
+}
@@ -0,0 +1,18 @@
+use anyhow::Result;
+use m87_shared::usage::{UsageExport, UsageQuery};
+
+use crate::{Make87Client, check_status, send_json};
+
+impl Make87Client {
+    /// Monthly usage of the orgs the token administers (all orgs for instance
+    /// admins), optionally narrowed to one org and a range of months.
+    pub async fn usage(&self, query: &UsageQuery) -> Result<UsageExport> {
+        send_json(self.get("/usage").query(query)).await
+    }
+
+    /// The same records as CSV, as served for spreadsheets and billing tools.
+    pub async fn usage_csv(&self, query: &UsageQuery) -> Result<String> {
+        let res = check_status(self.get("/usage/csv").query(query).send().await?).await?;
+        Ok(res.text().await?)
+    }
+}
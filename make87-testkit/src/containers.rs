@@ -7,13 +7,14 @@ use testcontainers::{
 use tokio::time::sleep;
 use uuid::Uuid;
 
-use super::helpers::E2EError;
-use super::setup::{
+use crate::helpers::E2EError;
+use crate::setup::{
     ensure_images_built, ensure_network_created, CLIENT_IMAGE_NAME, CLIENT_IMAGE_TAG, NETWORK_NAME,
     SERVER_IMAGE_NAME, SERVER_IMAGE_TAG,
 };
 
-const ADMIN_KEY: &str = "e2e-admin-key";
+/// API key with admin rights on the test server (also used by the CLI container)
+pub const ADMIN_KEY: &str = "e2e-admin-key";
 
 /// Infrastructure for E2E tests - manages all containers
 pub struct E2EInfra {
@@ -304,6 +305,23 @@ impl E2EInfra {
         Ok(())
     }
 
+    /// Server URL reachable from the host (the server uses a self-signed certificate)
+    pub async fn server_url(&self) -> Result<String, E2EError> {
+        let port = self
+            .server
+            .get_host_port_ipv4(8084)
+            .await
+            .map_err(|e| E2EError::Setup(e.to_string()))?;
+        Ok(format!("https://localhost:{}", port))
+    }
+
+    /// SDK client for the server, authenticated with [`ADMIN_KEY`]
+    pub async fn sdk_client(&self) -> Result<make87_sdk::Make87Client, E2EError> {
+        let url = self.server_url().await?;
+        make87_sdk::Make87Client::with_options(url, ADMIN_KEY, true)
+            .map_err(|e| E2EError::Setup(e.to_string()))
+    }
+
     /// Execute a CLI command and return the output
     pub async fn cli_exec(&self, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
         let cmd = format!("m87 {} --verbose", args.join(" "));
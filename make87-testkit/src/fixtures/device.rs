@@ -1,7 +1,7 @@
 //! Device registration fixture for E2E tests
 
-use crate::e2e_containers::containers::E2EInfra;
-use crate::e2e_containers::helpers::{
+use crate::containers::E2EInfra;
+use crate::helpers::{
     extract_auth_requests, parse_devices_list, wait_for_result, E2EError, WaitConfig,
 };
 
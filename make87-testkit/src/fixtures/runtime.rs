@@ -1,7 +1,7 @@
 //! Runtime run fixture for E2E tests
 
-use crate::e2e_containers::containers::E2EInfra;
-use crate::e2e_containers::helpers::{
+use crate::containers::E2EInfra;
+use crate::helpers::{
     exec_background, log_contains, read_log, wait_for, E2EError, WaitConfig,
 };
 
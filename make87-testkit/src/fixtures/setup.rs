@@ -6,8 +6,8 @@
 //! - Setup SNI/tunneling
 //! - Start agent with control tunnel
 
-use crate::e2e_containers::containers::E2EInfra;
-use crate::e2e_containers::helpers::{exec_shell, E2EError, SniSetup};
+use crate::containers::E2EInfra;
+use crate::helpers::{exec_shell, E2EError, SniSetup};
 
 use super::{RuntimeRunner, DeviceRegistration, RegisteredDevice};
 
@@ -0,0 +1,37 @@
+//! Test fixtures for integration tests against make87.
+//!
+//! Starts an ephemeral environment in Docker (MongoDB, an `m87-server`, a
+//! runtime container and a CLI container on a private network) and drives it
+//! through the `m87` CLI, so tests can register a device, run the runtime and
+//! exercise it the way users do.
+//!
+//! ```no_run
+//! use make87_testkit::{E2EResult, TestSetup};
+//!
+//! #[tokio::test]
+//! async fn exec_on_device() -> E2EResult<()> {
+//!     // server + runtime running, device registered and connected
+//!     let setup = TestSetup::init().await?;
+//!     let out = setup.device_cmd("exec -- echo hello").await?;
+//!     assert!(out.contains("hello"));
+//!     Ok(())
+//! }
+//! ```
+//!
+//! For finer control, start the containers with [`E2EInfra::init`] and walk
+//! through [`DeviceRegistration`] and [`RuntimeRunner`] step by step.
+//! [`E2EInfra::sdk_client`] returns a [`make87_sdk::Make87Client`] for the
+//! server, authenticated as admin, to test code built on the SDK.
+//!
+//! The `m87-server:e2e` and `m87-client:e2e` images are built from the make87
+//! checkout in `M87_TESTKIT_SOURCE` (defaults to the workspace this crate is
+//! part of). Set `M87_TESTKIT_SKIP_BUILD=1` to use images built beforehand.
+
+pub mod containers;
+pub mod fixtures;
+pub mod helpers;
+pub mod setup;
+
+pub use containers::{E2EInfra, ADMIN_KEY};
+pub use fixtures::{DeviceRegistration, RegisteredDevice, RuntimeRunner, TestSetup};
+pub use helpers::{E2EError, E2EResult};
@@ -1,3 +1,4 @@
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::OnceLock;
 use tokio::process::Command;
@@ -28,11 +29,35 @@ pub async fn ensure_images_built() -> Result<(), String> {
     result.clone()
 }
 
+/// make87 checkout the images are built from: `M87_TESTKIT_SOURCE` if set,
+/// otherwise the workspace this crate lives in
+fn source_dir() -> PathBuf {
+    if let Ok(dir) = std::env::var("M87_TESTKIT_SOURCE") {
+        return PathBuf::from(dir);
+    }
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    manifest_dir
+        .parent()
+        .unwrap_or(manifest_dir)
+        .to_path_buf()
+}
+
 async fn build_images() -> Result<(), String> {
-    // Get workspace root (parent of tests/)
-    let workspace_root = std::env::current_dir()
-        .map(|p| p.parent().map(|p| p.to_path_buf()).unwrap_or(p))
-        .unwrap_or_else(|_| std::path::PathBuf::from(".."));
+    // Images built or pulled beforehand (e.g. in downstream CI)
+    if std::env::var("M87_TESTKIT_SKIP_BUILD").is_ok() {
+        tracing::info!("M87_TESTKIT_SKIP_BUILD set, using existing images");
+        return Ok(());
+    }
+
+    let workspace_root = source_dir();
+    if !workspace_root.join("m87-server/Dockerfile").exists() {
+        return Err(format!(
+            "No make87 checkout at {}: set M87_TESTKIT_SOURCE, or provide the {} and {} images and set M87_TESTKIT_SKIP_BUILD=1",
+            workspace_root.display(),
+            SERVER_IMAGE,
+            CLIENT_IMAGE
+        ));
+    }
 
     // Build server image
     tracing::info!(